@@ -3,9 +3,22 @@ extern crate cbindgen;
 use std::path::Path;
 
 fn main() {
+    #[cfg(feature = "node")]
+    napi_build::setup();
+
     let header_file = Path::new("include").join("datenlord.h");
 
     cbindgen::generate(".")
         .expect("Unable to generate bindings")
         .write_to_file(header_file);
+
+    // cgo only reads C, so the Go package gets its own C header
+    let go_header_file = Path::new("src").join("sdk").join("go").join("datenlord.h");
+
+    cbindgen::Builder::new()
+        .with_crate(".")
+        .with_language(cbindgen::Language::C)
+        .generate()
+        .expect("Unable to generate C bindings")
+        .write_to_file(go_header_file);
 }
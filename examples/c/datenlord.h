@@ -4,17 +4,67 @@
 #include <ostream>
 #include <new>
 
+/// `datenlord_timespec::nsec` setting the timestamp to now, like `UTIME_NOW`
+constexpr static const uint32_t DATENLORD_UTIME_NOW = ((1 << 30) - 1);
+
+/// `datenlord_timespec::nsec` leaving the timestamp unchanged, like `UTIME_OMIT`
+constexpr static const uint32_t DATENLORD_UTIME_OMIT = ((1 << 30) - 2);
+
+/// `rename_path` flag failing if the destination exists, like `RENAME_NOREPLACE`
+constexpr static const unsigned int DATENLORD_RENAME_NOREPLACE = 1;
+
+/// `rename_path` flag atomically swapping source and destination, like `RENAME_EXCHANGE`
+constexpr static const unsigned int DATENLORD_RENAME_EXCHANGE = 2;
+
+/// The largest record a log accepts, 16 MiB
+constexpr static const uintptr_t MAX_RECORD_SIZE = (16 << 20);
+
+/// The version of the event schema, bumped on every incompatible change
+constexpr static const uint32_t EVENT_SCHEMA_VERSION = 1;
+
 /// The node ID of the root inode
 constexpr static const uint64_t ROOT_ID = 1;
 
 /// Whether to check permission.
 /// If fuse mount with `-o default_permissions`, then we should not check
 /// permission. Otherwise, we should check permission.
-/// TODO: add a feature flag to control this
-constexpr static const bool NEED_CHECK_PERM = false;
+/// The SDKs have no kernel checking permissions for them, so the backends
+/// check them against the `RequestContext` of every operation.
+constexpr static const bool NEED_CHECK_PERM = true;
+
+/// The on-disk format version written by this build
+constexpr static const uint32_t FORMAT_VERSION = 2;
+
+/// The number of directories listed concurrently unless told otherwise
+constexpr static const uintptr_t DEFAULT_WALK_CONCURRENCY = 16;
 
+/// Alignment of every pooled buffer, suitable for `O_DIRECT` I/O
+constexpr static const uintptr_t BUFFER_ALIGNMENT = 4096;
+
+/// Chunk size used when streaming file contents through pooled buffers
+constexpr static const uintptr_t COPY_CHUNK_SIZE = (1 << 20);
+
+/// The type of a file
+enum class datenlord_file_kind {
+  DATENLORD_FILE_KIND_UNKNOWN,
+  DATENLORD_FILE_KIND_REGULAR,
+  DATENLORD_FILE_KIND_DIRECTORY,
+  DATENLORD_FILE_KIND_SYMLINK,
+  DATENLORD_FILE_KIND_FIFO,
+  DATENLORD_FILE_KIND_CHAR_DEVICE,
+  DATENLORD_FILE_KIND_BLOCK_DEVICE,
+  DATENLORD_FILE_KIND_SOCKET,
+};
+
+/// A directory listing, not `repr(C)` so C only sees a forward declaration
+struct datenlord_dir;
+
+/// Opaque SDK handle, not `repr(C)` so C only sees a forward declaration
 struct datenlord_sdk;
 
+/// Iterator over the entries of a walk or glob, not `repr(C)` so C only sees a forward declaration
+struct datenlord_walk;
+
 struct datenlord_bytes {
   const uint8_t *data;
   uintptr_t len;
@@ -28,39 +78,169 @@ struct datenlord_error {
 /// The type of i-number
 using INum = uint64_t;
 
-/// File attributes
-struct datenlord_file_stat {
+/// A point in time relative to the Unix epoch, like `struct timespec`
+struct datenlord_timespec {
+  /// Seconds, negative before the epoch
+  int64_t sec;
+  /// Nanoseconds past `sec`, below 1000000000
+  uint32_t nsec;
+};
+
+/// File attributes filled by `stat`, modelled after `struct statx`
+struct datenlord_stat {
   /// Inode number
   INum ino;
   /// Size in bytes
   uint64_t size;
-  /// Size in blocks
+  /// Number of 512-byte blocks allocated
   uint64_t blocks;
-  /// Permissions
-  uint16_t perm;
+  /// File type and permission bits, like `st_mode`
+  uint32_t mode;
+  /// File type
+  datenlord_file_kind kind;
   /// Number of hard links
   uint32_t nlink;
-  /// User id
+  /// User id of the owner
   uint32_t uid;
-  /// Group id
+  /// Group id of the owner
   uint32_t gid;
-  /// Rdev
-  uint32_t rdev;
+  /// Time of last access
+  datenlord_timespec atime;
+  /// Time of last modification
+  datenlord_timespec mtime;
+  /// Time of last status change
+  datenlord_timespec ctime;
+};
+
+/// A buffer borrowed from the SDK buffer pool
+struct datenlord_buffer {
+  /// Start of the buffer, aligned to 4096 bytes, null if the acquire failed
+  uint8_t *data;
+  /// Usable length of the buffer
+  uintptr_t len;
+  /// Opaque pool handle, must be passed back untouched to `datenlord_buffer_release`
+  void *handle;
+};
+
+/// An entry returned by `datenlord_walk_next`
+struct datenlord_walk_entry {
+  /// Path relative to the SDK root, valid until the next call on the walk,
+  /// null once the walk is over
+  const char *path;
+  /// Attributes of the entry
+  datenlord_stat stat;
+};
+
+/// An entry returned by `datenlord_readdir`
+///
+/// The pointers are valid until the next call on the listing.
+struct datenlord_dir_entry {
+  /// Name of the entry within the directory
+  const char *name;
+  /// Inode number
+  INum ino;
+  /// File type
+  datenlord_file_kind kind;
+  /// Attributes of the entry, null unless the listing was opened with `plus`
+  const datenlord_stat *stat;
+  /// Length of `name` in bytes, which need not be UTF-8
+  uintptr_t name_len;
+};
+
+/// A positional read or write issued through the asynchronous API
+struct datenlord_io_request {
+  /// Path of the file relative to the SDK root
+  const char *path;
+  /// Offset in the file
+  uint64_t offset;
+  /// Buffer to read into or write from, must stay valid until completion
+  uint8_t *buf;
+  /// Length of `buf`
+  uintptr_t len;
+  /// Time the whole operation may take from submission in milliseconds,
+  /// retries included, 0 bounds each step by the configured default timeout
+  uint64_t timeout_ms;
+};
+
+/// Callback invoked when an asynchronous operation finishes
+///
+/// `error` is null on success and owned by the callee otherwise, `result` is
+/// the number of bytes transferred. The callback runs on an SDK worker
+/// thread and must not block.
+using datenlord_completion_cb = void(*)(uint64_t op_id,
+                                        datenlord_error *error,
+                                        uintptr_t result,
+                                        void *user_data);
+
+/// A finished asynchronous operation returned by `datenlord_poll_completions`
+struct datenlord_completion {
+  /// The id returned when the operation was submitted
+  uint64_t op_id;
+  /// Null on success, must be freed by the caller otherwise
+  datenlord_error *error;
+  /// The number of bytes transferred
+  uintptr_t result;
+  /// The `user_data` passed when the operation was submitted
+  void *user_data;
 };
 
 extern "C" {
 
+/// Free an error returned by the SDK together with its message
+///
+/// Null, unknown and already freed errors are ignored.
+void datenlord_error_free(datenlord_error *err);
+
 datenlord_sdk *init(const char *config);
 
+/// Free the SDK, aborting the asynchronous operations still running
+///
+/// No call on the SDK may be running or made afterwards, see
+/// `datenlord_shutdown` to wait for them first.
 void free_sdk(datenlord_sdk *sdk);
 
+/// Shut the SDK down gracefully, waiting at most `timeout_ms` for the calls
+/// still running, or without limit when 0
+///
+/// Calls made from now on fail with the error code `ESHUTDOWN`, or return
+/// false, 0 or null. Once the running calls, the asynchronous operations
+/// and the open walks finished, the written data is synced, the background
+/// tasks stop and the runtime is taken down; `free_sdk` must still be
+/// called. If calls are still running after the timeout the SDK stays shut
+/// to new calls and `ETIMEDOUT` is returned, calling again waits again. Must
+/// not be called from a completion callback.
+datenlord_error *datenlord_shutdown(datenlord_sdk *sdk, uint64_t timeout_ms);
+
+/// Set the umask applied to the files and directories created from now on,
+/// returning the former one, like `umask(2)`
+///
+/// Starts as the `caller.umask` of the config, or the umask of the process.
+unsigned int datenlord_set_umask(datenlord_sdk *sdk, unsigned int umask);
+
 bool exists(datenlord_sdk *sdk, const char *dir_path);
 
+/// Like `exists` with the `path.len` bytes of `path` as path, which need
+/// not be UTF-8 nor NUL-terminated
+bool datenlord_exists_bytes(datenlord_sdk *sdk, datenlord_bytes path);
+
 datenlord_error *mkdir(datenlord_sdk *sdk, const char *dir_path);
 
+/// Create `dir_path` with `mode` together with its missing parents, like `mkdir -p`
+///
+/// Directories that already exist, or are created concurrently, are left
+/// as they are.
+datenlord_error *datenlord_mkdir_all(datenlord_sdk *sdk, const char *dir_path, unsigned int mode);
+
 datenlord_error *deldir(datenlord_sdk *sdk, const char *dir_path, bool recursive);
 
-datenlord_error *rename_path(datenlord_sdk *sdk, const char *src_path, const char *dest_path);
+/// Rename `src_path` to `dest_path` according to `flags`, like `renameat2`
+///
+/// With `DATENLORD_RENAME_NOREPLACE` an existing destination fails the call
+/// with the error code `EEXIST`.
+datenlord_error *rename_path(datenlord_sdk *sdk,
+                             const char *src_path,
+                             const char *dest_path,
+                             unsigned int flags);
 
 datenlord_error *copy_from_local_file(datenlord_sdk *sdk,
                                       bool overwrite,
@@ -71,14 +251,169 @@ datenlord_error *copy_to_local_file(datenlord_sdk *sdk,
                                     const char *src_file_path,
                                     const char *local_file_path);
 
-datenlord_error *create_file(datenlord_sdk *sdk, const char *file_path);
+/// Create the regular file `file_path`
+///
+/// With `ensure_parents` its missing parent directories are created first,
+/// like `datenlord_mkdir_all`.
+datenlord_error *create_file(datenlord_sdk *sdk, const char *file_path, bool ensure_parents);
+
+/// Like `create_file` with the `file_path.len` bytes of `file_path` as path,
+/// which need not be UTF-8 nor NUL-terminated
+datenlord_error *datenlord_create_file_bytes(datenlord_sdk *sdk,
+                                             datenlord_bytes file_path,
+                                             bool ensure_parents);
 
-datenlord_error *stat(datenlord_sdk *sdk,
-                      const char *file_path,
-                      datenlord_file_stat *file_metadata);
+/// Fill `file_metadata` with the attributes of `file_path`
+datenlord_error *stat(datenlord_sdk *sdk, const char *file_path, datenlord_stat *file_metadata);
+
+/// Like `stat` with the `file_path.len` bytes of `file_path` as path, which
+/// need not be UTF-8 nor NUL-terminated
+datenlord_error *datenlord_stat_bytes(datenlord_sdk *sdk,
+                                      datenlord_bytes file_path,
+                                      datenlord_stat *file_metadata);
+
+/// Fill `file_metadata[i]` with the attributes of `file_paths[i]` for the
+/// `count` paths at once, setting `codes[i]` to 0, or to the error code
+/// when the path cannot be stat'ed
+///
+/// Paths are looked up concurrently and each distinct parent directory is
+/// resolved once, which suits build tools checking many files. Only null or
+/// invalid arguments fail the whole call.
+datenlord_error *datenlord_stat_many(datenlord_sdk *sdk,
+                                     const char *const *file_paths,
+                                     uintptr_t count,
+                                     datenlord_stat *file_metadata,
+                                     unsigned int *codes);
+
+/// Set the access and modification times of `file_path` with nanosecond precision
+///
+/// Like `utimensat`, a time whose `nsec` is `DATENLORD_UTIME_NOW` is set to
+/// the current time and one whose `nsec` is `DATENLORD_UTIME_OMIT` is left
+/// unchanged.
+datenlord_error *datenlord_utimens(datenlord_sdk *sdk,
+                                   const char *file_path,
+                                   datenlord_timespec atime,
+                                   datenlord_timespec mtime);
+
+/// Tag `file_path` with `key`=`value`, replacing the former value
+///
+/// Keys are non-empty and contain neither '=' nor ','. Tags are kept with
+/// the file by `copy_to_local_file` and `copy_from_local_file`.
+datenlord_error *datenlord_set_tag(datenlord_sdk *sdk,
+                                   const char *file_path,
+                                   const char *key,
+                                   const char *value);
+
+/// Remove the tag `key` from `file_path`
+datenlord_error *datenlord_remove_tag(datenlord_sdk *sdk, const char *file_path, const char *key);
+
+/// Open `file_path` read-only ahead of time, so `read_file` of it skips the
+/// lookup and open
+///
+/// The handle is reopened whenever entries are removed or renamed. Files
+/// listed in the `warm_files` config field are opened by `init`.
+datenlord_error *datenlord_warm_file(datenlord_sdk *sdk, const char *file_path);
 
 datenlord_error *write_file(datenlord_sdk *sdk, const char *file_path, datenlord_bytes content);
 
 datenlord_error *read_file(datenlord_sdk *sdk, const char *file_path, datenlord_bytes *out_content);
 
+/// Borrow a buffer of at least `size` bytes from the SDK buffer pool
+///
+/// The returned buffer can be handed to `read_file` and `write_file` through
+/// `datenlord_bytes` and must be returned with `datenlord_buffer_release`.
+datenlord_buffer datenlord_buffer_acquire(datenlord_sdk *sdk, uintptr_t size);
+
+/// Return a buffer acquired by `datenlord_buffer_acquire` to the pool
+///
+/// The buffer may be released after the SDK handle itself has been freed.
+void datenlord_buffer_release(datenlord_buffer buffer);
+
+/// Flush every open file and all buffered state of the SDK, like `syncfs`
+///
+/// Returns only when everything written so far is durable, which makes it
+/// suitable for quiescing before taking a snapshot.
+datenlord_error *datenlord_sync_all(datenlord_sdk *sdk);
+
+/// Start walking every entry below the directory `dir_path`, null on invalid arguments
+///
+/// Directories are listed concurrently in the background and entries come
+/// in no particular order. Symbolic links are returned but not followed.
+datenlord_walk *datenlord_walk_open(datenlord_sdk *sdk, const char *dir_path);
+
+/// Start walking the entries matching `pattern`, null on invalid arguments
+///
+/// `?` matches one character and `*` any characters within a path
+/// component, `**` matches any number of whole components. Entries are
+/// returned like for `datenlord_walk_open`.
+datenlord_walk *datenlord_glob_open(datenlord_sdk *sdk, const char *pattern);
+
+/// Fill `entry` with the next entry of `walk`, blocking until one is found
+///
+/// At the end of the walk `entry->path` is set to null. A directory that
+/// fails to list returns an error and the walk can carry on. Must not be
+/// called from a completion callback.
+datenlord_error *datenlord_walk_next(datenlord_walk *walk, datenlord_walk_entry *entry);
+
+/// Stop `walk` and free it, null is ignored
+void datenlord_walk_close(datenlord_walk *walk);
+
+/// List the directory `dir_path` into `*dir`, with the attributes of every
+/// entry if `plus`
+///
+/// The whole directory is read up front, so later changes are not seen.
+/// The listing must be freed with `datenlord_closedir`.
+datenlord_error *datenlord_opendir(datenlord_sdk *sdk,
+                                   const char *dir_path,
+                                   bool plus,
+                                   datenlord_dir **dir);
+
+/// Like `datenlord_opendir` with the `dir_path.len` bytes of `dir_path` as
+/// path, which need not be UTF-8 nor NUL-terminated
+datenlord_error *datenlord_opendir_bytes(datenlord_sdk *sdk,
+                                         datenlord_bytes dir_path,
+                                         bool plus,
+                                         datenlord_dir **dir);
+
+/// Fill `entry` with the next entry of `dir`, false at the end or on invalid arguments
+bool datenlord_readdir(datenlord_dir *dir, datenlord_dir_entry *entry);
+
+/// Free a listing opened by `datenlord_opendir`, null is ignored
+void datenlord_closedir(datenlord_dir *dir);
+
+/// Read `req.len` bytes at `req.offset` of `req.path` into `req.buf` without blocking
+///
+/// Returns the operation id, or 0 if the arguments are invalid. On
+/// completion `callback` is invoked, or when it is null the completion is
+/// queued for `datenlord_poll_completions`. Operations still running when
+/// the SDK is freed are cancelled without completing.
+uint64_t datenlord_read_async(datenlord_sdk *sdk,
+                              datenlord_io_request req,
+                              datenlord_completion_cb callback,
+                              void *user_data);
+
+/// Write `req.len` bytes from `req.buf` at `req.offset` of `req.path` without blocking
+///
+/// Completion is reported the same way as for `datenlord_read_async`.
+uint64_t datenlord_write_async(datenlord_sdk *sdk,
+                               datenlord_io_request req,
+                               datenlord_completion_cb callback,
+                               void *user_data);
+
+/// Move up to `max` finished operations submitted without a callback into `out`
+///
+/// Never blocks, returns the number of completions written. Meant to be
+/// called from an event loop instead of handling callbacks on SDK threads.
+uintptr_t datenlord_poll_completions(datenlord_sdk *sdk, datenlord_completion *out, uintptr_t max);
+
+/// Cancel the asynchronous operation `op_id`
+///
+/// Returns whether the operation was still running. The operation is
+/// interrupted and completes right away, on the calling thread when it has
+/// a callback, with an `EINTR` error and no bytes transferred. Unless called
+/// from a completion callback, this waits for the operation to stop touching
+/// its buffer.
+/// Within a callback the buffer must be kept until the SDK is freed.
+bool datenlord_cancel(datenlord_sdk *sdk, uint64_t op_id);
+
 } // extern "C"
@@ -8,7 +8,7 @@
 void handle_error(datenlord_error *err) {
     if (err != NULL) {
         printf("Error code: %d, message: %.*s\n", err->code, (int)err->message.len, (const char*)err->message.data);
-        free(err);
+        datenlord_error_free(err);
     }
 }
 
@@ -33,8 +33,16 @@ int main() {
         handle_error(err);
     }
 
+    // Mkdir -p /example_dir/nested/dir
+    err = datenlord_mkdir_all(sdk, "example_dir/nested/dir", 0755);
+    if (err == NULL) {
+        printf("Nested directories created successfully\n");
+    } else {
+        handle_error(err);
+    }
+
     // Create file
-    err = create_file(sdk, "/example_dir/example_file.txt");
+    err = create_file(sdk, "/example_dir/example_file.txt", false);
     if (err == NULL) {
         printf("File created successfully\n");
     } else {
@@ -67,15 +75,56 @@ int main() {
         handle_error(err);
     }
 
+    // Read file asynchronously and poll for the completion
+    datenlord_io_request req = { file_path, 0, buffer, buffer_size, 0 };
+    uint64_t op_id = datenlord_read_async(sdk, req, NULL, NULL);
+    datenlord_completion completion;
+    while (datenlord_poll_completions(sdk, &completion, 1) == 0) {
+        // Do other work in the event loop
+    }
+    if (completion.error == NULL) {
+        printf("Async read %lu completed: %.*s\n", op_id, (int)completion.result, (const char*)buffer);
+    } else {
+        handle_error(completion.error);
+    }
+
     // Stat file
-    datenlord_file_stat file_stat;
-    err = stat(sdk, "/example_dir/renamed_file.txt", &file_stat);
+    datenlord_stat file_stat;
+    err = stat(sdk, "/example_dir/example_file.txt", &file_stat);
+    if (err == NULL) {
+        printf("File stat: ino %lu size %lu blocks %lu mode %o nlink %u uid %u gid %u mtime %ld.%09u\n",
+               file_stat.ino, file_stat.size, file_stat.blocks, file_stat.mode, file_stat.nlink,
+               file_stat.uid, file_stat.gid, file_stat.mtime.sec, file_stat.mtime.nsec);
+    } else {
+        handle_error(err);
+    }
+
+    // List /example_dir with the attributes of every entry
+    datenlord_dir* dir = NULL;
+    err = datenlord_opendir(sdk, "example_dir", true, &dir);
     if (err == NULL) {
-        printf("File stat: %ld %d %d %d %d %d %d %d %d %d %d\n", file_stat.blocks, file_stat.gid, file_stat.ino, file_stat.nlink, file_stat.perm, file_stat.rdev, file_stat.size, file_stat.uid);
+        datenlord_dir_entry dir_entry;
+        while (datenlord_readdir(dir, &dir_entry)) {
+            printf("Entry %s ino %lu kind %d size %lu\n", dir_entry.name, dir_entry.ino, dir_entry.kind, dir_entry.stat->size);
+        }
+        datenlord_closedir(dir);
+    } else {
+        handle_error(err);
+    }
+
+    // Find every .txt file below /example_dir
+    datenlord_walk* walk = datenlord_glob_open(sdk, "example_dir/**/*.txt");
+    datenlord_walk_entry entry;
+    while ((err = datenlord_walk_next(walk, &entry)) == NULL && entry.path != NULL) {
+        printf("Found %s size %lu\n", entry.path, entry.stat.size);
     }
+    if (err != NULL) {
+        handle_error(err);
+    }
+    datenlord_walk_close(walk);
 
     // Rename file
-    err = rename_path(sdk, "/example_dir/example_file.txt", "/example_dir/renamed_file.txt");
+    err = rename_path(sdk, "/example_dir/example_file.txt", "/example_dir/renamed_file.txt", DATENLORD_RENAME_NOREPLACE);
     if (err == NULL) {
         printf("File renamed successfully\n");
     } else {
@@ -90,7 +139,11 @@ int main() {
         handle_error(err);
     }
 
-    // Release sdk
+    // Wait for running calls, sync and release sdk
+    err = datenlord_shutdown(sdk, 5000);
+    if (err != NULL) {
+        handle_error(err);
+    }
     free_sdk(sdk);
     printf("SDK released successfully\n");
 
@@ -0,0 +1,34 @@
+const { DatenlordSdk } = require('./datenlord.node');
+
+async function main() {
+    // Init sdk
+    const sdk = new DatenlordSdk();
+    console.log('SDK initialized successfully');
+
+    // Check current dir is available
+    console.log(`Directory exists: ${await sdk.exists('/datenlord_sdk')}`);
+
+    // Mkdir /example_dir
+    await sdk.mkdir('/example_dir');
+    console.log('Directory created successfully');
+
+    // List root dir
+    const entries = await sdk.readdir('/');
+    console.log(`Root entries: ${entries.map((entry) => entry.name).join(', ')}`);
+
+    // Stat dir
+    const stats = await sdk.stat('/example_dir');
+    console.log(`Directory stat: ${JSON.stringify(stats)}`);
+
+    // Read file, assume /example_dir/example_file.txt is created by other sdk
+    try {
+        const file = await sdk.open('/example_dir/example_file.txt');
+        const content = await file.read(0, 1024);
+        console.log(`File read successfully: ${content.toString()}`);
+        await file.close();
+    } catch (err) {
+        console.log(`Failed to read file: ${err.message}`);
+    }
+}
+
+main();
@@ -4,24 +4,150 @@
 #include <ostream>
 #include <new>
 
+/// `datenlord_timespec::nsec` setting the timestamp to now, like `UTIME_NOW`
+constexpr static const uint32_t DATENLORD_UTIME_NOW = ((1 << 30) - 1);
+
+/// `datenlord_timespec::nsec` leaving the timestamp unchanged, like `UTIME_OMIT`
+constexpr static const uint32_t DATENLORD_UTIME_OMIT = ((1 << 30) - 2);
+
+/// `rename_path` flag failing if the destination exists, like `RENAME_NOREPLACE`
+constexpr static const unsigned int DATENLORD_RENAME_NOREPLACE = 1;
+
+/// `rename_path` flag atomically swapping source and destination, like `RENAME_EXCHANGE`
+constexpr static const unsigned int DATENLORD_RENAME_EXCHANGE = 2;
+
+/// The digest is a CRC-32C
+constexpr static const unsigned int DATENLORD_DIGEST_CRC32C = 1;
+
+/// The digest is a SHA-256
+constexpr static const unsigned int DATENLORD_DIGEST_SHA256 = 2;
+
+/// The digest is a BLAKE3
+constexpr static const unsigned int DATENLORD_DIGEST_BLAKE3 = 3;
+
+/// The size of `datenlord_digest::value`, terminating NUL included
+constexpr static const uintptr_t DATENLORD_DIGEST_SIZE = 72;
+
+/// The size of `datenlord_upload_id::id`, terminating NUL included
+constexpr static const uintptr_t DATENLORD_UPLOAD_ID_SIZE = 64;
+
+/// The size of `datenlord_upload_part_info::checksum`, terminating NUL included
+constexpr static const uintptr_t DATENLORD_CHECKSUM_SIZE = 65;
+
+/// The largest record a log accepts, 16 MiB
+constexpr static const uintptr_t MAX_RECORD_SIZE = (16 << 20);
+
+/// The locations holding the data of the inode, one per line: the local
+/// path, the paths on the secondaries, or the objects of a shared namespace
+constexpr static const uint32_t IOCTL_PLACEMENT = 1;
+
+/// The `platform::FILE_FLAG_*` flags of the local file of the inode, as a
+/// little endian `u32`, 0 on backends without flags
+constexpr static const uint32_t IOCTL_FILE_FLAGS = 2;
+
+/// The first command `register` accepts, those below are built in
+constexpr static const uint32_t FIRST_CUSTOM_COMMAND = 4096;
+
+/// The largest key a store accepts, in bytes
+constexpr static const uintptr_t MAX_KEY_SIZE = 1024;
+
+/// The version of the event schema, bumped on every incompatible change
+constexpr static const uint32_t EVENT_SCHEMA_VERSION = 1;
+
+/// The file cannot be changed, renamed or removed
+constexpr static const uint32_t FILE_FLAG_IMMUTABLE = (1 << 0);
+
+/// The file can only be appended to
+constexpr static const uint32_t FILE_FLAG_APPEND = (1 << 1);
+
+/// The file is skipped by backups
+constexpr static const uint32_t FILE_FLAG_NODUMP = (1 << 2);
+
+/// The file is hidden from the Finder, macOS only
+constexpr static const uint32_t FILE_FLAG_HIDDEN = (1 << 3);
+
 /// The node ID of the root inode
 constexpr static const uint64_t ROOT_ID = 1;
 
 /// Whether to check permission.
 /// If fuse mount with `-o default_permissions`, then we should not check
 /// permission. Otherwise, we should check permission.
-/// TODO: add a feature flag to control this
-constexpr static const bool NEED_CHECK_PERM = false;
+/// The SDKs have no kernel checking permissions for them, so the backends
+/// check them against the `RequestContext` of every operation.
+constexpr static const bool NEED_CHECK_PERM = true;
+
+/// The size of the chunks of a stream unless told otherwise, 1 MiB
+constexpr static const uintptr_t DEFAULT_CHUNK_SIZE = (1024 * 1024);
+
+/// The on-disk format version written by this build
+constexpr static const uint32_t FORMAT_VERSION = 2;
+
+/// The number of parts an upload takes at most, indexed from 0
+constexpr static const uint32_t MAX_PARTS = 100000;
+
+/// The number of directories listed concurrently unless told otherwise
+constexpr static const uintptr_t DEFAULT_WALK_CONCURRENCY = 16;
+
+/// Alignment of every pooled buffer, suitable for `O_DIRECT` I/O
+constexpr static const uintptr_t BUFFER_ALIGNMENT = 4096;
 
-template<typename T = void>
-struct Arc;
+/// Chunk size used when streaming file contents through pooled buffers
+constexpr static const uintptr_t COPY_CHUNK_SIZE = (1 << 20);
 
-struct LocalFS;
+/// The kind of a change reported to a watch
+enum class datenlord_event_kind {
+  /// A file, device, fifo or socket was created
+  DATENLORD_EVENT_CREATE,
+  /// A directory was created
+  DATENLORD_EVENT_MKDIR,
+  /// A symbolic link was created
+  DATENLORD_EVENT_SYMLINK,
+  /// An entry was removed
+  DATENLORD_EVENT_DELETE,
+  /// An entry was moved, or swapped with another
+  DATENLORD_EVENT_RENAME,
+  /// Attributes or tags were changed, including truncation
+  DATENLORD_EVENT_ATTRIB,
+  /// A file handle that was written to was released
+  DATENLORD_EVENT_CLOSE_WRITE,
+};
 
-struct datenlord_sdk {
-  Arc<Mutex<LocalFS>> localfs;
+/// The type of a file
+enum class datenlord_file_kind {
+  DATENLORD_FILE_KIND_UNKNOWN,
+  DATENLORD_FILE_KIND_REGULAR,
+  DATENLORD_FILE_KIND_DIRECTORY,
+  DATENLORD_FILE_KIND_SYMLINK,
+  DATENLORD_FILE_KIND_FIFO,
+  DATENLORD_FILE_KIND_CHAR_DEVICE,
+  DATENLORD_FILE_KIND_BLOCK_DEVICE,
+  DATENLORD_FILE_KIND_SOCKET,
 };
 
+/// A directory listing, not `repr(C)` so C only sees a forward declaration
+struct datenlord_dir;
+
+/// A key-value store opened by `datenlord_kv_open`, not `repr(C)` so C only
+/// sees a forward declaration
+struct datenlord_kv;
+
+/// A file being read by `datenlord_read_stream_open`, not `repr(C)` so C
+/// only sees a forward declaration
+struct datenlord_read_stream;
+
+/// Opaque SDK handle, not `repr(C)` so C only sees a forward declaration
+struct datenlord_sdk;
+
+/// A log followed by `datenlord_tail_open`, not `repr(C)` so C only sees a
+/// forward declaration
+struct datenlord_tail;
+
+/// Iterator over the entries of a walk or glob, not `repr(C)` so C only sees a forward declaration
+struct datenlord_walk;
+
+/// A watch registered by `datenlord_watch_open`, not `repr(C)` so C only sees a forward declaration
+struct datenlord_watch;
+
 struct datenlord_bytes {
   const uint8_t *data;
   uintptr_t len;
@@ -32,42 +158,283 @@ struct datenlord_error {
   datenlord_bytes message;
 };
 
+/// What `datenlord_copy` copied
+struct datenlord_copy_result {
+  /// The bytes copied
+  uint64_t copied;
+  /// Whether the destination shares the blocks of the source rather than
+  /// holding a copy of its data
+  bool reflinked;
+};
+
 /// The type of i-number
 using INum = uint64_t;
 
-/// File attributes
-struct datenlord_file_stat {
+/// A point in time relative to the Unix epoch, like `struct timespec`
+struct datenlord_timespec {
+  /// Seconds, negative before the epoch
+  int64_t sec;
+  /// Nanoseconds past `sec`, below 1000000000
+  uint32_t nsec;
+};
+
+/// File attributes filled by `stat`, modelled after `struct statx`
+struct datenlord_stat {
   /// Inode number
   INum ino;
   /// Size in bytes
   uint64_t size;
-  /// Size in blocks
+  /// Number of 512-byte blocks allocated
   uint64_t blocks;
-  /// Permissions
-  uint16_t perm;
+  /// File type and permission bits, like `st_mode`
+  uint32_t mode;
+  /// File type
+  datenlord_file_kind kind;
   /// Number of hard links
   uint32_t nlink;
-  /// User id
+  /// User id of the owner
   uint32_t uid;
-  /// Group id
+  /// Group id of the owner
   uint32_t gid;
-  /// Rdev
-  uint32_t rdev;
+  /// Time of last access
+  datenlord_timespec atime;
+  /// Time of last modification
+  datenlord_timespec mtime;
+  /// Time of last status change
+  datenlord_timespec ctime;
+  /// The generation of the entries of a directory, changing whenever
+  /// entries are created, removed or renamed in it, 0 for other files
+  uint64_t generation;
+};
+
+/// The space taken by a file or directory, filled by `datenlord_disk_usage`
+struct datenlord_disk_usage {
+  /// The sizes of the files and symbolic links, hard links counted once
+  uint64_t bytes;
+  /// The bytes of the blocks allocated to them
+  uint64_t allocated_bytes;
+  /// The files and symbolic links, hard links counted once
+  uint64_t files;
+  /// The directories, the one counted excluded
+  uint64_t dirs;
+};
+
+/// How a probe of `datenlord_healthcheck` went, the latencies of the steps
+/// not reached are 0
+struct datenlord_health {
+  /// Whether every step succeeded
+  bool healthy;
+  /// Microseconds reading the attributes of the root took
+  uint64_t metadata_latency_us;
+  /// Microseconds writing the probe file took
+  uint64_t write_latency_us;
+  /// Microseconds reading the probe file back took
+  uint64_t read_latency_us;
+};
+
+/// A digest of file contents, a NUL-terminated `<algorithm>:<hex>` string
+/// such as `crc32c:e3069283`
+struct datenlord_digest {
+  char value[DATENLORD_DIGEST_SIZE];
+};
+
+/// A buffer borrowed from the SDK buffer pool
+struct datenlord_buffer {
+  /// Start of the buffer, aligned to 4096 bytes, null if the acquire failed
+  uint8_t *data;
+  /// Usable length of the buffer
+  uintptr_t len;
+  /// Opaque pool handle, must be passed back untouched to `datenlord_buffer_release`
+  void *handle;
+};
+
+/// An entry returned by `datenlord_walk_next`
+struct datenlord_walk_entry {
+  /// Path relative to the SDK root, valid until the next call on the walk,
+  /// null once the walk is over
+  const char *path;
+  /// Attributes of the entry
+  datenlord_stat stat;
+};
+
+/// A change passed to a watch callback, valid during the callback only
+struct datenlord_event {
+  /// What happened
+  datenlord_event_kind kind;
+  /// Path of the entry relative to the SDK root, null when unknown
+  const char *path;
+  /// Path the entry moved to, null unless renamed
+  const char *new_path;
+  /// Inode number of the entry
+  INum ino;
+  /// Type of the entry
+  datenlord_file_kind file_kind;
+  /// Size of the entry after the change
+  uint64_t size;
+  /// When the change was observed
+  datenlord_timespec timestamp;
+};
+
+/// Callback invoked with every change under a watched path
+///
+/// The callback runs on an SDK worker thread, one change at a time in
+/// order, and must not block.
+using datenlord_watch_cb = void(*)(const datenlord_event *event, void *user_data);
+
+/// Callback invoked with every record of a followed log and its offset
+///
+/// The callback runs on an SDK worker thread, one record at a time in order,
+/// and must not block. `record` is valid for the duration of the call only.
+using datenlord_tail_cb = void(*)(uint64_t offset, datenlord_bytes record, void *user_data);
+
+/// Callback invoked with every entry found by `datenlord_kv_scan`
+///
+/// `key` and `value` are valid for the duration of the call only.
+using datenlord_kv_scan_cb = void(*)(const char *key, datenlord_bytes value, void *user_data);
+
+/// The id of a multipart upload, a NUL-terminated string
+struct datenlord_upload_id {
+  char id[DATENLORD_UPLOAD_ID_SIZE];
+};
+
+/// A part of a multipart upload
+struct datenlord_upload_part_info {
+  /// Index of the part, its data following the parts of smaller indexes
+  uint32_t index;
+  /// Size of its data in bytes
+  uint64_t size;
+  /// Lowercase hex SHA-256 of its data, NUL-terminated
+  char checksum[DATENLORD_CHECKSUM_SIZE];
+};
+
+/// An entry returned by `datenlord_readdir`
+///
+/// The pointers are valid until the next call on the listing.
+struct datenlord_dir_entry {
+  /// Name of the entry within the directory
+  const char *name;
+  /// Inode number
+  INum ino;
+  /// File type
+  datenlord_file_kind kind;
+  /// Attributes of the entry, null unless the listing was opened with `plus`
+  const datenlord_stat *stat;
+  /// Length of `name` in bytes, which need not be UTF-8
+  uintptr_t name_len;
+};
+
+/// A positional read or write issued through the asynchronous API
+struct datenlord_io_request {
+  /// Path of the file relative to the SDK root
+  const char *path;
+  /// Offset in the file
+  uint64_t offset;
+  /// Buffer to read into or write from, must stay valid until completion
+  uint8_t *buf;
+  /// Length of `buf`
+  uintptr_t len;
+  /// Time the whole operation may take from submission in milliseconds,
+  /// retries included, 0 bounds each step by the configured default timeout
+  uint64_t timeout_ms;
+};
+
+/// Callback invoked when an asynchronous operation finishes
+///
+/// `error` is null on success and owned by the callee otherwise, `result` is
+/// the number of bytes transferred. The callback runs on an SDK worker
+/// thread and must not block.
+using datenlord_completion_cb = void(*)(uint64_t op_id,
+                                        datenlord_error *error,
+                                        uintptr_t result,
+                                        void *user_data);
+
+/// A finished asynchronous operation returned by `datenlord_poll_completions`
+struct datenlord_completion {
+  /// The id returned when the operation was submitted
+  uint64_t op_id;
+  /// Null on success, must be freed by the caller otherwise
+  datenlord_error *error;
+  /// The number of bytes transferred
+  uintptr_t result;
+  /// The `user_data` passed when the operation was submitted
+  void *user_data;
 };
 
 extern "C" {
 
+/// Free an error returned by the SDK together with its message
+///
+/// Null, unknown and already freed errors are ignored.
+void datenlord_error_free(datenlord_error *err);
+
 datenlord_sdk *init(const char *config);
 
+/// Like `init`, with every path relative to the directory `prefix` of the
+/// namespace, e.g. `/tenants/alice`, which paths cannot lead out of,
+/// neither with `..` nor through symbolic links
+///
+/// Null if `prefix` is not a directory. The background tasks the config
+/// schedules still run on the whole namespace.
+datenlord_sdk *datenlord_init_scoped(const char *config, const char *prefix);
+
+/// Give back the reference of `sdk` to the SDK, freeing it with the last
+/// one and aborting the asynchronous operations still running
+///
+/// No call may be running or made afterwards through the reference given
+/// back, nor through any other once the SDK is freed, see
+/// `datenlord_shutdown` to wait for them first.
 void free_sdk(datenlord_sdk *sdk);
 
+/// Another reference to the SDK of `sdk`, to be freed by its own
+/// `free_sdk`, null if `sdk` is
+///
+/// Lets threads that may outlive each other share the SDK, which is freed
+/// once every reference was.
+datenlord_sdk *datenlord_sdk_clone(datenlord_sdk *sdk);
+
+/// Shut the SDK down gracefully, waiting at most `timeout_ms` for the calls
+/// still running, or without limit when 0
+///
+/// Calls made from now on fail with the error code `ESHUTDOWN`, or return
+/// false, 0 or null. Once the running calls, the asynchronous operations
+/// and the open walks finished, the written data is synced, the background
+/// tasks stop, the runtime is taken down and the spans ended so far are
+/// exported; `free_sdk` must still be called on every reference. If calls are still running after the timeout
+/// the SDK stays shut to new calls and `ETIMEDOUT` is returned, calling
+/// again waits again. Must not be called from a completion callback.
+datenlord_error *datenlord_shutdown(datenlord_sdk *sdk,
+                                    uint64_t timeout_ms);
+
+/// Set the umask applied to the files and directories created from now on,
+/// returning the former one, like `umask(2)`
+///
+/// Starts as the `caller.umask` of the config, or the umask of the process.
+unsigned int datenlord_set_umask(datenlord_sdk *sdk, unsigned int umask);
+
 bool exists(datenlord_sdk *sdk, const char *dir_path);
 
+/// Like `exists` with the `path.len` bytes of `path` as path, which need
+/// not be UTF-8 nor NUL-terminated
+bool datenlord_exists_bytes(datenlord_sdk *sdk, datenlord_bytes path);
+
 datenlord_error *mkdir(datenlord_sdk *sdk, const char *dir_path);
 
+/// Create `dir_path` with `mode` together with its missing parents, like `mkdir -p`
+///
+/// Directories that already exist, or are created concurrently, are left
+/// as they are.
+datenlord_error *datenlord_mkdir_all(datenlord_sdk *sdk, const char *dir_path, unsigned int mode);
+
 datenlord_error *deldir(datenlord_sdk *sdk, const char *dir_path, bool recursive);
 
-datenlord_error *rename_path(datenlord_sdk *sdk, const char *src_path, const char *dest_path);
+/// Rename `src_path` to `dest_path` according to `flags`, like `renameat2`
+///
+/// With `DATENLORD_RENAME_NOREPLACE` an existing destination fails the call
+/// with the error code `EEXIST`.
+datenlord_error *rename_path(datenlord_sdk *sdk,
+                             const char *src_path,
+                             const char *dest_path,
+                             unsigned int flags);
 
 datenlord_error *copy_from_local_file(datenlord_sdk *sdk,
                                       bool overwrite,
@@ -78,14 +445,442 @@ datenlord_error *copy_to_local_file(datenlord_sdk *sdk,
                                     const char *src_file_path,
                                     const char *local_file_path);
 
-datenlord_error *create_file(datenlord_sdk *sdk, const char *file_path);
+/// Copy the file `src_path` to `dst_path`, replacing the file at the
+/// destination, and fill `result` with what was copied
+///
+/// Backends able to share the blocks of both files, like btrfs and XFS
+/// through `FICLONE`, do so instead of copying the data.
+datenlord_error *datenlord_copy(datenlord_sdk *sdk,
+                                const char *src_path,
+                                const char *dst_path,
+                                datenlord_copy_result *result);
+
+/// Create the regular file `file_path`
+///
+/// With `ensure_parents` its missing parent directories are created first,
+/// like `datenlord_mkdir_all`.
+datenlord_error *create_file(datenlord_sdk *sdk, const char *file_path, bool ensure_parents);
+
+/// Like `create_file` with the `file_path.len` bytes of `file_path` as path,
+/// which need not be UTF-8 nor NUL-terminated
+datenlord_error *datenlord_create_file_bytes(datenlord_sdk *sdk,
+                                             datenlord_bytes file_path,
+                                             bool ensure_parents);
+
+/// Fill `file_metadata` with the attributes of `file_path`
+datenlord_error *stat(datenlord_sdk *sdk, const char *file_path, datenlord_stat *file_metadata);
+
+/// Like `stat` with the `file_path.len` bytes of `file_path` as path, which
+/// need not be UTF-8 nor NUL-terminated
+datenlord_error *datenlord_stat_bytes(datenlord_sdk *sdk,
+                                      datenlord_bytes file_path,
+                                      datenlord_stat *file_metadata);
+
+/// Fill `file_metadata[i]` with the attributes of `file_paths[i]` for the
+/// `count` paths at once, setting `codes[i]` to 0, or to the error code
+/// when the path cannot be stat'ed
+///
+/// Paths are looked up concurrently and each distinct parent directory is
+/// resolved once, which suits build tools checking many files. Only null or
+/// invalid arguments fail the whole call.
+datenlord_error *datenlord_stat_many(datenlord_sdk *sdk,
+                                     const char *const *file_paths,
+                                     uintptr_t count,
+                                     datenlord_stat *file_metadata,
+                                     unsigned int *codes);
 
-datenlord_error *stat(datenlord_sdk *sdk,
-                      const char *file_path,
-                      datenlord_file_stat *file_metadata);
+/// Fill `usage` with the space taken by `path`, everything below it when a
+/// directory, like `du`
+///
+/// Directories are listed concurrently; the first failing to list fails
+/// the call.
+datenlord_error *datenlord_disk_usage(datenlord_sdk *sdk,
+                                      const char *path,
+                                      datenlord_disk_usage *usage);
+
+/// Set the access and modification times of `file_path` with nanosecond precision
+///
+/// Like `utimensat`, a time whose `nsec` is `DATENLORD_UTIME_NOW` is set to
+/// the current time and one whose `nsec` is `DATENLORD_UTIME_OMIT` is left
+/// unchanged.
+datenlord_error *datenlord_utimens(datenlord_sdk *sdk,
+                                   const char *file_path,
+                                   datenlord_timespec atime,
+                                   datenlord_timespec mtime);
+
+/// Tag `file_path` with `key`=`value`, replacing the former value
+///
+/// Keys are non-empty and contain neither '=' nor ','. Tags are kept with
+/// the file by `copy_to_local_file` and `copy_from_local_file`.
+datenlord_error *datenlord_set_tag(datenlord_sdk *sdk,
+                                   const char *file_path,
+                                   const char *key,
+                                   const char *value);
+
+/// Remove the tag `key` from `file_path`
+datenlord_error *datenlord_remove_tag(datenlord_sdk *sdk, const char *file_path, const char *key);
+
+/// Open `file_path` read-only ahead of time, so `read_file` of it skips the
+/// lookup and open
+///
+/// The handle is reopened whenever entries are removed or renamed. Files
+/// listed in the `warm_files` config field are opened by `init`.
+datenlord_error *datenlord_warm_file(datenlord_sdk *sdk, const char *file_path);
+
+/// Run the control command `cmd` on `file_path` with the `input.len`
+/// bytes at `input.data` as argument, copying its output into the
+/// `out_output.len` bytes at `out_output.data` and setting `out_output.len`
+/// to its size
+///
+/// Commands below 0x1000 are built in, 1 listing the locations holding the
+/// data of the file one per line; the others run the handler registered in
+/// the process, failing with `ENOTSUP` without one. An output larger than
+/// the buffer fails with `ERANGE`, `out_output.len` telling the size needed.
+datenlord_error *datenlord_ioctl(datenlord_sdk *sdk,
+                                 const char *file_path,
+                                 uint32_t cmd,
+                                 datenlord_bytes input,
+                                 datenlord_bytes *out_output);
+
+/// Change the config values in the JSON object `update` while running,
+/// keeping the open files: `attr_cache_capacity`, `op_timeout_ms` (0
+/// removes the timeout), `retry` and `log_level`
+///
+/// Other fields fail with `EINVAL`, as they only take effect when the SDK
+/// is opened, and so does an unknown log level; nothing changes then.
+datenlord_error *datenlord_update_config(datenlord_sdk *sdk, const char *update);
+
+/// Copy a JSON object with the calls made through `sdk`, the bytes they
+/// moved, how the cache did and the handles and queues held now into the
+/// `out_json.len` bytes at `out_json.data`, NUL terminated, and set
+/// `out_json.len` to its size with the NUL
+///
+/// A buffer too small fails with `ERANGE`, `out_json.len` telling the size
+/// needed.
+datenlord_error *datenlord_get_stats(datenlord_sdk *sdk, datenlord_bytes *out_json);
+
+/// Probe `sdk` end to end, reading the attributes of the root, writing a few
+/// bytes to a scratch file under it and reading them back, and fill
+/// `out_health` with the outcome and the latency of each step, e.g. for the
+/// liveness and readiness probes of a service
+///
+/// The error of the step failing is returned, with `out_health` filled in
+/// as far as the probe got.
+datenlord_error *datenlord_healthcheck(datenlord_sdk *sdk, datenlord_health *out_health);
 
 datenlord_error *write_file(datenlord_sdk *sdk, const char *file_path, datenlord_bytes content);
 
 datenlord_error *read_file(datenlord_sdk *sdk, const char *file_path, datenlord_bytes *out_content);
 
+/// Replace the contents of `file_path` with `content`, creating the file if
+/// missing, so readers see either the former contents or `content`, never a
+/// part of it
+///
+/// `content` goes to a temporary file next to `file_path`, synced and
+/// renamed over it, then the parent directory is synced. An existing file
+/// keeps its permissions.
+datenlord_error *datenlord_write_file_atomic(datenlord_sdk *sdk,
+                                             const char *file_path,
+                                             datenlord_bytes content);
+
+/// Like `write_file`, also writing to `digest` the digest of `content` with
+/// `algorithm`, one of the `DATENLORD_DIGEST_*` constants
+///
+/// The digest is computed on the bytes the SDK received, so comparing it
+/// with one computed by the caller catches buffers mangled on the way.
+datenlord_error *datenlord_write_file_digest(datenlord_sdk *sdk,
+                                             const char *file_path,
+                                             datenlord_bytes content,
+                                             unsigned int algorithm,
+                                             datenlord_digest *digest);
+
+/// Write to `digest` the digest with `algorithm`, one of the
+/// `DATENLORD_DIGEST_*` constants, of the contents of the file `file_path`
+///
+/// The file is streamed rather than loaded in memory. With `cache`, the
+/// digest is kept in an extended attribute of the file until it is written
+/// to, so asking again is cheap.
+datenlord_error *datenlord_file_digest(datenlord_sdk *sdk,
+                                       const char *file_path,
+                                       unsigned int algorithm,
+                                       bool cache,
+                                       datenlord_digest *digest);
+
+/// Like `read_file`, failing with `EBADMSG` if the bytes read do not have
+/// the digest `expected`, as written by `datenlord_write_file_digest`
+///
+/// Fails with `EINVAL` if `expected` is not a digest. `out_content` must be
+/// large enough for the whole file for its digest to match.
+datenlord_error *datenlord_read_file_verify(datenlord_sdk *sdk,
+                                            const char *file_path,
+                                            datenlord_bytes *out_content,
+                                            const char *expected);
+
+/// Start reading at most `len` bytes of `file_path` from `offset`, as
+/// chunks of at most `chunk_size` bytes pulled with
+/// `datenlord_read_stream_next_chunk`, the block size of the backend when 0
+///
+/// A `len` of `UINT64_MAX` reads up to the end of the file. Only the chunk
+/// last pulled is held in memory. The stream must be freed with
+/// `datenlord_read_stream_close`.
+datenlord_error *datenlord_read_stream_open(datenlord_sdk *sdk,
+                                            const char *file_path,
+                                            uint64_t offset,
+                                            uint64_t len,
+                                            uintptr_t chunk_size,
+                                            datenlord_read_stream **stream);
+
+/// Point `chunk` at the next chunk of `stream`, blocking until it is read
+///
+/// The chunk is valid until the next call on the stream. At the end of the
+/// file `chunk->data` is set to null and `chunk->len` to 0. Must not be
+/// called from a completion callback.
+datenlord_error *datenlord_read_stream_next_chunk(datenlord_read_stream *stream,
+                                                  datenlord_bytes *chunk);
+
+/// Free `stream`, null is ignored
+void datenlord_read_stream_close(datenlord_read_stream *stream);
+
+/// Borrow a buffer of at least `size` bytes from the SDK buffer pool
+///
+/// The returned buffer can be handed to `read_file` and `write_file` through
+/// `datenlord_bytes` and must be returned with `datenlord_buffer_release`.
+datenlord_buffer datenlord_buffer_acquire(datenlord_sdk *sdk, uintptr_t size);
+
+/// Return a buffer acquired by `datenlord_buffer_acquire` to the pool
+///
+/// The buffer may be released after the SDK handle itself has been freed.
+void datenlord_buffer_release(datenlord_buffer buffer);
+
+/// Flush every open file and all buffered state of the SDK, like `syncfs`
+///
+/// Returns only when everything written so far is durable, which makes it
+/// suitable for quiescing before taking a snapshot.
+datenlord_error *datenlord_sync_all(datenlord_sdk *sdk);
+
+/// Start walking every entry below the directory `dir_path`, null on invalid arguments
+///
+/// Directories are listed concurrently in the background and entries come
+/// in no particular order. Symbolic links are returned but not followed.
+datenlord_walk *datenlord_walk_open(datenlord_sdk *sdk, const char *dir_path);
+
+/// Start walking the entries matching `pattern`, null on invalid arguments
+///
+/// `?` matches one character and `*` any characters within a path
+/// component, `**` matches any number of whole components. Entries are
+/// returned like for `datenlord_walk_open`.
+datenlord_walk *datenlord_glob_open(datenlord_sdk *sdk, const char *pattern);
+
+/// Fill `entry` with the next entry of `walk`, blocking until one is found
+///
+/// At the end of the walk `entry->path` is set to null. A directory that
+/// fails to list returns an error and the walk can carry on. Must not be
+/// called from a completion callback.
+datenlord_error *datenlord_walk_next(datenlord_walk *walk, datenlord_walk_entry *entry);
+
+/// Stop `walk` and free it, null is ignored
+void datenlord_walk_close(datenlord_walk *walk);
+
+/// Watch the changes to `path` and its children, or everything below it if
+/// `recursive`, calling `callback` with each of them and `user_data`
+///
+/// Fails when `path` does not exist. Changes are delivered until the watch
+/// is closed with `datenlord_watch_close` or the SDK shuts down; those past a
+/// full queue are dropped.
+datenlord_error *datenlord_watch_open(datenlord_sdk *sdk,
+                                      const char *path,
+                                      bool recursive,
+                                      datenlord_watch_cb callback,
+                                      void *user_data,
+                                      datenlord_watch **watch);
+
+/// Stop `watch` and free it, null is ignored
+///
+/// Unless called from the callback, a change being delivered may still be
+/// in the callback when this returns.
+void datenlord_watch_close(datenlord_watch *watch);
+
+/// Append `record` to the log at `file_path`, creating it when missing, and
+/// set `offset`, unless null, to the offset it was written at once synced
+///
+/// Appends to the same file through the SDK are atomic and ordered, whichever
+/// thread makes them. Each record is framed with its length and a CRC32
+/// checksum.
+datenlord_error *datenlord_append(datenlord_sdk *sdk,
+                                  const char *file_path,
+                                  datenlord_bytes record,
+                                  uint64_t *offset);
+
+/// Follow the log at `file_path` from `from_offset` on, 0 or an offset
+/// `datenlord_append` set, calling `callback` with each record and
+/// `user_data`
+///
+/// The records already written are delivered first, then those appended
+/// through the SDK, until the tail is closed with `datenlord_tail_close` or
+/// the SDK shuts down.
+datenlord_error *datenlord_tail_open(datenlord_sdk *sdk,
+                                     const char *file_path,
+                                     uint64_t from_offset,
+                                     datenlord_tail_cb callback,
+                                     void *user_data,
+                                     datenlord_tail **tail);
+
+/// Stop following the log of `tail` and free it, null is ignored
+///
+/// Unless called from the callback, a record being delivered may still be
+/// in the callback when this returns.
+void datenlord_tail_close(datenlord_tail *tail);
+
+/// Open the key-value store in the directory `dir_path`, creating it when
+/// missing
+///
+/// Small values are packed into segment files rather than stored as a file
+/// each. The store must be closed with `datenlord_kv_close` before the SDK
+/// shuts down to sync its last writes.
+datenlord_error *datenlord_kv_open(datenlord_sdk *sdk, const char *dir_path, datenlord_kv **kv);
+
+/// Set `key` to `value`, replacing its former value
+datenlord_error *datenlord_kv_put(datenlord_kv *kv, const char *key, datenlord_bytes value);
+
+/// Copy the value of `key` into the `len` bytes at `out_value.data`, setting
+/// `out_value.len` to its size and `found` to whether `key` is there
+///
+/// A value larger than the buffer fails with `ERANGE`, `out_value.len`
+/// telling the size needed.
+datenlord_error *datenlord_kv_get(datenlord_kv *kv,
+                                  const char *key,
+                                  datenlord_bytes *out_value,
+                                  bool *found);
+
+/// Remove `key`, setting `existed`, unless null, to whether it was there
+datenlord_error *datenlord_kv_delete(datenlord_kv *kv, const char *key, bool *existed);
+
+/// Call `callback` with every key starting with `prefix`, in key order, its
+/// value and `user_data`, on the calling thread
+datenlord_error *datenlord_kv_scan(datenlord_kv *kv,
+                                   const char *prefix,
+                                   datenlord_kv_scan_cb callback,
+                                   void *user_data);
+
+/// Rewrite the live values into new segments, reclaiming the space of the
+/// overwritten and deleted ones, and set `reclaimed`, unless null, to the
+/// bytes reclaimed
+datenlord_error *datenlord_kv_compact(datenlord_kv *kv, uint64_t *reclaimed);
+
+/// Sync and close `kv` and free it, null is ignored
+///
+/// Once the SDK shut down the store is only freed, failing with the error of
+/// a shut down SDK.
+datenlord_error *datenlord_kv_close(datenlord_kv *kv);
+
+/// Start a multipart upload to `file_path`, whose parent directory must
+/// exist, writing its id to `upload_id`
+///
+/// Parts are stored as they are uploaded, so a crashed client resumes with
+/// `datenlord_upload_find` and `datenlord_upload_parts`. The file is
+/// created or replaced by `datenlord_upload_complete`.
+datenlord_error *datenlord_upload_start(datenlord_sdk *sdk,
+                                        const char *file_path,
+                                        datenlord_upload_id *upload_id);
+
+/// Write to `upload_id` the id of the upload to `file_path` started last
+/// and still in progress, setting `found` to whether there is one
+datenlord_error *datenlord_upload_find(datenlord_sdk *sdk,
+                                       const char *file_path,
+                                       datenlord_upload_id *upload_id,
+                                       bool *found);
+
+/// Store `data` as part `index` of upload `upload_id`, replacing the part
+/// uploaded before with that index, and fill `part`, unless null, with its
+/// size and checksum
+///
+/// The same index must not be uploaded twice at once.
+datenlord_error *datenlord_upload_part(datenlord_sdk *sdk,
+                                       const char *upload_id,
+                                       uint32_t index,
+                                       datenlord_bytes data,
+                                       datenlord_upload_part_info *part);
+
+/// Fill the `capacity` slots of `parts` with the parts of upload
+/// `upload_id`, by index, setting `count` to their number
+///
+/// More parts than `capacity` fail with `ERANGE`, `count` telling the slots
+/// needed.
+datenlord_error *datenlord_upload_parts(datenlord_sdk *sdk,
+                                        const char *upload_id,
+                                        datenlord_upload_part_info *parts,
+                                        uintptr_t capacity,
+                                        uintptr_t *count);
+
+/// Concatenate the parts of upload `upload_id`, indexed from 0 with no gap,
+/// into its file, checking their checksums, and fill `file_metadata`,
+/// unless null, with the attributes of the file
+///
+/// A part no longer matching its checksum fails the call, leaving the
+/// upload in progress to upload the part again.
+datenlord_error *datenlord_upload_complete(datenlord_sdk *sdk,
+                                           const char *upload_id,
+                                           datenlord_stat *file_metadata);
+
+/// Give up upload `upload_id`, removing its parts
+datenlord_error *datenlord_upload_abort(datenlord_sdk *sdk, const char *upload_id);
+
+/// List the directory `dir_path` into `*dir`, with the attributes of every
+/// entry if `plus`
+///
+/// The whole directory is read up front, so later changes are not seen.
+/// The listing must be freed with `datenlord_closedir`.
+datenlord_error *datenlord_opendir(datenlord_sdk *sdk,
+                                   const char *dir_path,
+                                   bool plus,
+                                   datenlord_dir **dir);
+
+/// Like `datenlord_opendir` with the `dir_path.len` bytes of `dir_path` as
+/// path, which need not be UTF-8 nor NUL-terminated
+datenlord_error *datenlord_opendir_bytes(datenlord_sdk *sdk,
+                                         datenlord_bytes dir_path,
+                                         bool plus,
+                                         datenlord_dir **dir);
+
+/// Fill `entry` with the next entry of `dir`, false at the end or on invalid arguments
+bool datenlord_readdir(datenlord_dir *dir, datenlord_dir_entry *entry);
+
+/// Free a listing opened by `datenlord_opendir`, null is ignored
+void datenlord_closedir(datenlord_dir *dir);
+
+/// Read `req.len` bytes at `req.offset` of `req.path` into `req.buf` without blocking
+///
+/// Returns the operation id, or 0 if the arguments are invalid. On
+/// completion `callback` is invoked, or when it is null the completion is
+/// queued for `datenlord_poll_completions`. Operations still running when
+/// the SDK is freed are cancelled without completing.
+uint64_t datenlord_read_async(datenlord_sdk *sdk,
+                              datenlord_io_request req,
+                              datenlord_completion_cb callback,
+                              void *user_data);
+
+/// Write `req.len` bytes from `req.buf` at `req.offset` of `req.path` without blocking
+///
+/// Completion is reported the same way as for `datenlord_read_async`.
+uint64_t datenlord_write_async(datenlord_sdk *sdk,
+                               datenlord_io_request req,
+                               datenlord_completion_cb callback,
+                               void *user_data);
+
+/// Move up to `max` finished operations submitted without a callback into `out`
+///
+/// Never blocks, returns the number of completions written. Meant to be
+/// called from an event loop instead of handling callbacks on SDK threads.
+uintptr_t datenlord_poll_completions(datenlord_sdk *sdk, datenlord_completion *out, uintptr_t max);
+
+/// Cancel the asynchronous operation `op_id`
+///
+/// Returns whether the operation was still running. The operation is
+/// interrupted and completes right away, on the calling thread when it has
+/// a callback, with an `EINTR` error and no bytes transferred. Unless called
+/// from a completion callback, this waits for the operation to stop touching
+/// its buffer.
+/// Within a callback the buffer must be kept until the SDK is freed.
+bool datenlord_cancel(datenlord_sdk *sdk, uint64_t op_id);
+
 } // extern "C"
@@ -0,0 +1,289 @@
+// RAII wrappers over the C SDK declared in `datenlord.h`, requiring C++20
+//
+// Failures are thrown as `datenlord::Error`, handles are freed by the
+// destructors of their owners, buffers are passed as `std::span` and paths
+// as `std::string_view`. Include this header instead of `datenlord.h`.
+#pragma once
+
+#include <cstddef>
+#include <cstdint>
+#include <exception>
+#include <future>
+#include <optional>
+#include <span>
+#include <string>
+#include <string_view>
+#include <utility>
+
+#include "datenlord.h"
+
+namespace datenlord {
+
+/// A failure reported by the SDK
+class Error : public std::exception {
+ public:
+  Error(unsigned int code, std::string message) : code_(code), message_(std::move(message)) {}
+
+  /// Take over `err`, freeing it
+  explicit Error(datenlord_error *err)
+      : code_(err->code),
+        message_(reinterpret_cast<const char *>(err->message.data), err->message.len) {
+    datenlord_error_free(err);
+  }
+
+  /// Throw `err` unless it is null
+  static void check(datenlord_error *err) {
+    if (err != nullptr) {
+      throw Error(err);
+    }
+  }
+
+  /// The errno of the failure, 1 when none applies
+  unsigned int code() const noexcept { return code_; }
+
+  std::string_view message() const noexcept { return message_; }
+
+  const char *what() const noexcept override { return message_.c_str(); }
+
+ private:
+  unsigned int code_;
+  std::string message_;
+};
+
+namespace detail {
+
+inline datenlord_bytes bytes(std::string_view s) noexcept {
+  return {reinterpret_cast<const uint8_t *>(s.data()), s.size()};
+}
+
+/// Completion callback fulfilling the `std::promise<size_t>` of `user_data`
+inline void complete(uint64_t, datenlord_error *error, uintptr_t result, void *user_data) {
+  auto *done = static_cast<std::promise<std::size_t> *>(user_data);
+  if (error != nullptr) {
+    done->set_exception(std::make_exception_ptr(Error(error)));
+  } else {
+    done->set_value(result);
+  }
+}
+
+}  // namespace detail
+
+/// An entry of a directory listing
+struct DirEntry {
+  /// Name of the entry, which need not be UTF-8
+  std::string name;
+  INum ino;
+  datenlord_file_kind kind;
+  /// Attributes of the entry, only for listings opened with `plus`
+  std::optional<datenlord_stat> stat;
+};
+
+/// A directory listing, read up front when opened
+class Dir {
+ public:
+  explicit Dir(datenlord_dir *dir) noexcept : dir_(dir) {}
+  Dir(Dir &&other) noexcept : dir_(std::exchange(other.dir_, nullptr)) {}
+  Dir &operator=(Dir &&other) noexcept {
+    std::swap(dir_, other.dir_);
+    return *this;
+  }
+  ~Dir() { datenlord_closedir(dir_); }
+
+  /// The next entry, none at the end
+  std::optional<DirEntry> next() {
+    datenlord_dir_entry entry;
+    if (!datenlord_readdir(dir_, &entry)) {
+      return std::nullopt;
+    }
+    DirEntry result{std::string(entry.name, entry.name_len), entry.ino, entry.kind, std::nullopt};
+    if (entry.stat != nullptr) {
+      result.stat = *entry.stat;
+    }
+    return result;
+  }
+
+ private:
+  datenlord_dir *dir_;
+};
+
+/// A buffer borrowed from the buffer pool of the SDK, returned when destroyed
+class Buffer {
+ public:
+  explicit Buffer(datenlord_buffer buffer) noexcept : buffer_(buffer) {}
+  Buffer(Buffer &&other) noexcept : buffer_(std::exchange(other.buffer_, {})) {}
+  Buffer &operator=(Buffer &&other) noexcept {
+    std::swap(buffer_, other.buffer_);
+    return *this;
+  }
+  ~Buffer() {
+    if (buffer_.data != nullptr) {
+      datenlord_buffer_release(buffer_);
+    }
+  }
+
+  std::span<std::byte> span() const noexcept {
+    return {reinterpret_cast<std::byte *>(buffer_.data), buffer_.len};
+  }
+
+ private:
+  datenlord_buffer buffer_;
+};
+
+/// A file of an SDK, read and written at offsets
+///
+/// It holds no open handle, every call looks the file up by its path, and
+/// must not outlive its `Sdk`.
+class File {
+ public:
+  File(datenlord_sdk *sdk, std::string_view path) : sdk_(sdk), path_(path) {}
+
+  const std::string &path() const noexcept { return path_; }
+
+  /// Read into `buf` at `offset`, returning the number of bytes read
+  std::size_t read_at(std::span<std::byte> buf, uint64_t offset) const {
+    return io(false, buf, offset);
+  }
+
+  /// Write `buf` at `offset`, returning the number of bytes written
+  std::size_t write_at(std::span<const std::byte> buf, uint64_t offset) const {
+    return io(true, {const_cast<std::byte *>(buf.data()), buf.size()}, offset);
+  }
+
+  datenlord_stat stat() const {
+    datenlord_stat st;
+    Error::check(datenlord_stat_bytes(sdk_, detail::bytes(path_), &st));
+    return st;
+  }
+
+ private:
+  std::size_t io(bool write, std::span<std::byte> buf, uint64_t offset) const {
+    std::promise<std::size_t> done;
+    auto result = done.get_future();
+    datenlord_io_request req{path_.c_str(), offset, reinterpret_cast<uint8_t *>(buf.data()),
+                             buf.size(), 0};
+    auto submit = write ? datenlord_write_async : datenlord_read_async;
+    if (submit(sdk_, req, detail::complete, &done) == 0) {
+      throw Error(1, "Invalid arguments");
+    }
+    return result.get();
+  }
+
+  datenlord_sdk *sdk_;
+  std::string path_;
+};
+
+/// An SDK, freed when destroyed
+///
+/// Calls may be made from several threads at once.
+class Sdk {
+ public:
+  /// Start an SDK configured by the JSON `config`
+  explicit Sdk(const std::string &config) : sdk_(init(config.c_str())) {
+    if (sdk_ == nullptr) {
+      throw Error(1, "Failed to start the SDK");
+    }
+  }
+  Sdk(Sdk &&other) noexcept : sdk_(std::exchange(other.sdk_, nullptr)) {}
+  Sdk &operator=(Sdk &&other) noexcept {
+    std::swap(sdk_, other.sdk_);
+    return *this;
+  }
+  ~Sdk() {
+    if (sdk_ != nullptr) {
+      free_sdk(sdk_);
+    }
+  }
+
+  /// The underlying handle, to call the C API directly
+  datenlord_sdk *get() const noexcept { return sdk_; }
+
+  /// Wait at most `timeout_ms` for the running calls, or without limit when
+  /// 0, and sync the written data, see `datenlord_shutdown`
+  void shutdown(uint64_t timeout_ms = 0) { Error::check(datenlord_shutdown(sdk_, timeout_ms)); }
+
+  /// Set the umask, returning the former one
+  unsigned int set_umask(unsigned int umask) { return datenlord_set_umask(sdk_, umask); }
+
+  bool exists(std::string_view path) const {
+    return datenlord_exists_bytes(sdk_, detail::bytes(path));
+  }
+
+  datenlord_stat stat(std::string_view path) const { return file(path).stat(); }
+
+  /// Create the directory `path` with its missing parents, like `mkdir -p`
+  void mkdir_all(std::string_view path, unsigned int mode = 0777) {
+    Error::check(datenlord_mkdir_all(sdk_, std::string(path).c_str(), mode));
+  }
+
+  void remove_dir(std::string_view path, bool recursive = false) {
+    Error::check(deldir(sdk_, std::string(path).c_str(), recursive));
+  }
+
+  /// Rename `from` to `to` according to the `DATENLORD_RENAME_*` `flags`
+  void rename(std::string_view from, std::string_view to, unsigned int flags = 0) {
+    Error::check(
+        rename_path(sdk_, std::string(from).c_str(), std::string(to).c_str(), flags));
+  }
+
+  /// Create the regular file `path`, failing if it exists
+  File create(std::string_view path, bool ensure_parents = false) {
+    Error::check(datenlord_create_file_bytes(sdk_, detail::bytes(path), ensure_parents));
+    return file(path);
+  }
+
+  /// The file `path`, which is only looked up once used
+  File file(std::string_view path) const { return File(sdk_, path); }
+
+  /// Write `content` at the start of the existing file `path`
+  void write_file(std::string_view path, std::span<const std::byte> content) {
+    datenlord_bytes bytes{reinterpret_cast<const uint8_t *>(content.data()), content.size()};
+    Error::check(::write_file(sdk_, std::string(path).c_str(), bytes));
+  }
+
+  /// Read the start of the file `path` into `buf`, returning the number of
+  /// bytes read
+  std::size_t read_file(std::string_view path, std::span<std::byte> buf) const {
+    datenlord_bytes bytes{reinterpret_cast<const uint8_t *>(buf.data()), buf.size()};
+    Error::check(::read_file(sdk_, std::string(path).c_str(), &bytes));
+    return bytes.len;
+  }
+
+  /// List the directory `path`, with the attributes of every entry if `plus`
+  Dir list(std::string_view path, bool plus = false) const {
+    datenlord_dir *dir = nullptr;
+    Error::check(datenlord_opendir_bytes(sdk_, detail::bytes(path), plus, &dir));
+    return Dir(dir);
+  }
+
+  void set_tag(std::string_view path, std::string_view key, std::string_view value) {
+    Error::check(datenlord_set_tag(sdk_, std::string(path).c_str(), std::string(key).c_str(),
+                                   std::string(value).c_str()));
+  }
+
+  void remove_tag(std::string_view path, std::string_view key) {
+    Error::check(
+        datenlord_remove_tag(sdk_, std::string(path).c_str(), std::string(key).c_str()));
+  }
+
+  /// Open `path` read-only ahead of time, see `datenlord_warm_file`
+  void warm(std::string_view path) {
+    Error::check(datenlord_warm_file(sdk_, std::string(path).c_str()));
+  }
+
+  /// Make everything written so far durable, like `syncfs`
+  void sync_all() { Error::check(datenlord_sync_all(sdk_)); }
+
+  /// Borrow a buffer of at least `size` bytes from the buffer pool
+  Buffer acquire_buffer(std::size_t size) {
+    datenlord_buffer buffer = datenlord_buffer_acquire(sdk_, size);
+    if (buffer.data == nullptr) {
+      throw Error(1, "Failed to acquire a buffer");
+    }
+    return Buffer(buffer);
+  }
+
+ private:
+  datenlord_sdk *sdk_;
+};
+
+}  // namespace datenlord
@@ -0,0 +1,425 @@
+//! Load generator driving any `VirtualFs` with synthetic workloads
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::fs_util::{CreateParam, RequestContext, ROOT_ID};
+use crate::storage::virtualfs::{self, INum, VirtualFs};
+
+
+/// The access pattern generated by a benchmark run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    /// Sequential reads over one pre-written file per worker
+    SeqRead,
+    /// Block-aligned random reads over one pre-written file per worker
+    RandRead,
+    /// Sequential overwrites of one pre-written file per worker
+    SeqWrite,
+    /// Block-aligned random overwrites of one pre-written file per worker
+    RandWrite,
+    /// Create, write and close a new small file per operation
+    SmallFileCreate,
+    /// Create, stat, look up and remove an empty file per operation
+    MetadataStress,
+    /// Check the presence of an existing and a missing file in turn
+    Exists,
+    /// `Exists` through a lookup failing on the missing file, the path
+    /// `VirtualFs::exists` skips
+    ExistsByLookup,
+}
+
+impl Workload {
+    /// Every workload, in the order they are listed
+    pub const ALL: [Self; 8] = [
+        Self::SeqRead,
+        Self::RandRead,
+        Self::SeqWrite,
+        Self::RandWrite,
+        Self::SmallFileCreate,
+        Self::MetadataStress,
+        Self::Exists,
+        Self::ExistsByLookup,
+    ];
+
+    /// Whether the workload works on pre-written data files
+    fn needs_data_file(self) -> bool {
+        matches!(
+            self,
+            Self::SeqRead | Self::RandRead | Self::SeqWrite | Self::RandWrite
+        )
+    }
+
+    /// Whether the workload writes its data file rather than reading it
+    fn writes_data_file(self) -> bool {
+        matches!(self, Self::SeqWrite | Self::RandWrite)
+    }
+}
+
+impl FromStr for Workload {
+    type Err = DatenLordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seq-read" => Ok(Self::SeqRead),
+            "rand-read" => Ok(Self::RandRead),
+            "seq-write" => Ok(Self::SeqWrite),
+            "rand-write" => Ok(Self::RandWrite),
+            "small-file-create" => Ok(Self::SmallFileCreate),
+            "metadata-stress" => Ok(Self::MetadataStress),
+            "exists" => Ok(Self::Exists),
+            "exists-by-lookup" => Ok(Self::ExistsByLookup),
+            _ => Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "unknown workload={s}, expect one of seq-read, rand-read, \
+                     seq-write, rand-write, small-file-create, metadata-stress, exists, \
+                     exists-by-lookup"
+                )],
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::SeqRead => "seq-read",
+            Self::RandRead => "rand-read",
+            Self::SeqWrite => "seq-write",
+            Self::RandWrite => "rand-write",
+            Self::SmallFileCreate => "small-file-create",
+            Self::MetadataStress => "metadata-stress",
+            Self::Exists => "exists",
+            Self::ExistsByLookup => "exists-by-lookup",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Parameters of a benchmark run
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// The access pattern to generate
+    pub workload: Workload,
+    /// The number of concurrent workers
+    pub concurrency: usize,
+    /// How long the workers issue operations
+    pub duration: Duration,
+    /// The size of every read or write
+    pub block_size: usize,
+    /// The size of the data file each read or write worker works on
+    pub file_size: u64,
+}
+
+/// The result of a benchmark run
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    /// The workload that was run
+    pub workload: Workload,
+    /// The number of completed operations
+    pub ops: u64,
+    /// The number of bytes read or written
+    pub bytes: u64,
+    /// The wall-clock time spent issuing operations
+    pub elapsed: Duration,
+    /// The latency of every operation, sorted ascending
+    latencies: Vec<Duration>,
+}
+
+impl BenchReport {
+    /// Completed operations per second
+    pub fn ops_per_sec(&self) -> f64 {
+        self.ops as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Throughput in MiB per second
+    pub fn mib_per_sec(&self) -> f64 {
+        self.bytes as f64 / (1024.0 * 1024.0) / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// The latency at percentile `p`, in the range `0.0..=100.0`
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * (self.latencies.len() - 1) as f64).round();
+        self.latencies[rank as usize]
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "workload:   {}", self.workload)?;
+        writeln!(f, "operations: {} in {:.3}s", self.ops, self.elapsed.as_secs_f64())?;
+        writeln!(
+            f,
+            "throughput: {:.1} ops/s, {:.2} MiB/s",
+            self.ops_per_sec(),
+            self.mib_per_sec()
+        )?;
+        write!(
+            f,
+            "latency:    p50={:?} p90={:?} p99={:?} max={:?}",
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.percentile(100.0)
+        )
+    }
+}
+
+/// Per-worker counters merged into the final report
+#[derive(Debug, Default)]
+struct WorkerStats {
+    /// The number of completed operations
+    ops: u64,
+    /// The number of bytes read or written
+    bytes: u64,
+    /// The latency of every operation
+    latencies: Vec<Duration>,
+}
+
+/// A xorshift generator, good enough to spread random reads
+#[derive(Debug)]
+struct XorShift(u64);
+
+impl XorShift {
+    /// Get the next pseudo-random number
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Build the parameters to create a regular file `name` under `parent`
+fn file_param(parent: INum, name: String) -> CreateParam {
+    CreateParam {
+        parent,
+        name: name.into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    }
+}
+
+/// Create and fill the data file of a read or write worker
+async fn prepare_data_file<F: VirtualFs>(
+    fs: &F,
+    ctx: &RequestContext,
+    dir: INum,
+    name: String,
+    options: &BenchOptions,
+) -> DatenLordResult<INum> {
+    let ino = fs.mknod(ctx, file_param(dir, name)).await?.1.ino;
+    let fh = fs
+        .open(ctx, ino, OFlag::O_WRONLY.bits() as u32)
+        .await?;
+    let block = vec![0xa5_u8; options.block_size];
+    let mut offset = 0_u64;
+    while offset < options.file_size {
+        let len = (options.file_size - offset).min(block.len() as u64) as usize;
+        fs.write(ctx, ino, fh, offset as i64, &block[..len], 0).await?;
+        offset += len as u64;
+    }
+    fs.release(ctx, ino, fh, 0, 0, true).await?;
+    Ok(ino)
+}
+
+/// Issue operations of `options.workload` until `deadline`
+async fn run_worker<F: VirtualFs>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    dir: INum,
+    id: usize,
+    data_file: Option<INum>,
+    options: BenchOptions,
+    deadline: Instant,
+) -> DatenLordResult<WorkerStats> {
+    let mut stats = WorkerStats::default();
+    let flags = if options.workload.writes_data_file() {
+        OFlag::O_WRONLY
+    } else {
+        OFlag::O_RDONLY
+    };
+    let data = match data_file {
+        Some(ino) => {
+            let fh = fs.open(&ctx, ino, flags.bits() as u32).await?;
+            Some((ino, fh))
+        }
+        None => None,
+    };
+    let blocks = (options.file_size / options.block_size as u64).max(1);
+    let mut buf = vec![0_u8; options.block_size];
+    let payload = vec![0x5a_u8; options.block_size];
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15 ^ (id as u64 + 1));
+    let present = format!("present-{id}");
+    if matches!(options.workload, Workload::Exists | Workload::ExistsByLookup) {
+        fs.mknod(&ctx, file_param(dir, present.clone())).await?;
+    }
+
+    while Instant::now() < deadline {
+        let start = Instant::now();
+        let bytes = match (options.workload, data) {
+            (Workload::SeqRead, Some((ino, fh))) => {
+                let offset = stats.ops % blocks * options.block_size as u64;
+                fs.read(&ctx, ino, fh, offset, buf.len() as u32, &mut buf).await?
+            }
+            (Workload::RandRead, Some((ino, fh))) => {
+                let offset = rng.next() % blocks * options.block_size as u64;
+                fs.read(&ctx, ino, fh, offset, buf.len() as u32, &mut buf).await?
+            }
+            (Workload::SeqWrite, Some((ino, fh))) => {
+                let offset = stats.ops % blocks * options.block_size as u64;
+                fs.write(&ctx, ino, fh, offset as i64, &payload, 0).await?;
+                payload.len()
+            }
+            (Workload::RandWrite, Some((ino, fh))) => {
+                let offset = rng.next() % blocks * options.block_size as u64;
+                fs.write(&ctx, ino, fh, offset as i64, &payload, 0).await?;
+                payload.len()
+            }
+            (Workload::SmallFileCreate, _) => {
+                let name = format!("file-{id}-{}", stats.ops);
+                let ino = fs.mknod(&ctx, file_param(dir, name)).await?.1.ino;
+                let fh = fs
+                    .open(&ctx, ino, OFlag::O_WRONLY.bits() as u32)
+                    .await?;
+                fs.write(&ctx, ino, fh, 0, &payload, 0).await?;
+                fs.release(&ctx, ino, fh, 0, 0, true).await?;
+                payload.len()
+            }
+            (Workload::MetadataStress, _) => {
+                let name = format!("meta-{id}-{}", stats.ops);
+                let ino = fs.mknod(&ctx, file_param(dir, name.clone())).await?.1.ino;
+                fs.getattr(&ctx, ino).await?;
+                fs.lookup(&ctx, dir, name.as_ref()).await?;
+                fs.unlink(&ctx, dir, name.as_ref()).await?;
+                0
+            }
+            (workload @ (Workload::Exists | Workload::ExistsByLookup), _) => {
+                let name = if stats.ops % 2 == 0 {
+                    present.as_str()
+                } else {
+                    "missing"
+                };
+                if workload == Workload::Exists {
+                    fs.exists(&ctx, dir, name.as_ref()).await?;
+                } else {
+                    virtualfs::exists_by_lookup(&*fs, &ctx, dir, name.as_ref()).await?;
+                }
+                0
+            }
+            (
+                Workload::SeqRead | Workload::RandRead | Workload::SeqWrite | Workload::RandWrite,
+                None,
+            ) => unreachable!("data file prepared"),
+        };
+        stats.latencies.push(start.elapsed());
+        stats.ops += 1;
+        stats.bytes += bytes as u64;
+    }
+
+    if let Some((ino, fh)) = data {
+        // Flushing the written data is part of the run
+        fs.release(&ctx, ino, fh, 0, 0, options.workload.writes_data_file()).await?;
+    }
+    Ok(stats)
+}
+
+/// Remove the benchmark directory and everything created in it
+async fn cleanup<F: VirtualFs>(
+    fs: &F,
+    ctx: &RequestContext,
+    dir_name: &str,
+    dir: INum,
+) -> DatenLordResult<()> {
+    for entry in fs.readdir(ctx, dir, 0, 0).await? {
+        fs.unlink(ctx, dir, &entry.name).await?;
+    }
+    fs.rmdir(ctx, ROOT_ID, dir_name.as_ref()).await?;
+    Ok(())
+}
+
+/// Run a benchmark against `fs` on behalf of `ctx`
+///
+/// The workers operate in a scratch directory under the root which is
+/// removed once the run finishes. Writing the data files of the read
+/// workloads happens before the clock starts.
+pub async fn run<F: VirtualFs + 'static>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    options: &BenchOptions,
+) -> DatenLordResult<BenchReport> {
+    if options.concurrency == 0 || options.block_size == 0 {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec!["concurrency and block size must be positive".to_owned()],
+        });
+    }
+    let dir_name = format!("bench-{}", std::process::id());
+    let param = CreateParam {
+        node_type: SFlag::S_IFDIR,
+        mode: 0o755,
+        ..file_param(ROOT_ID, dir_name.clone())
+    };
+    let dir = fs.mkdir(&ctx, param).await?.1.ino;
+    let mut data_files = Vec::with_capacity(options.concurrency);
+    for id in 0..options.concurrency {
+        data_files.push(if options.workload.needs_data_file() {
+            Some(prepare_data_file(&*fs, &ctx, dir, format!("data-{id}"), options).await?)
+        } else {
+            None
+        });
+    }
+
+    let start = Instant::now();
+    let deadline = start + options.duration;
+    let workers: Vec<_> = (0..options.concurrency)
+        .zip(data_files)
+        .map(|(id, data_file)| {
+            tokio::spawn(run_worker(
+                Arc::clone(&fs),
+                ctx,
+                dir,
+                id,
+                data_file,
+                options.clone(),
+                deadline,
+            ))
+        })
+        .collect();
+
+    let mut report = BenchReport {
+        workload: options.workload,
+        ops: 0,
+        bytes: 0,
+        elapsed: Duration::ZERO,
+        latencies: Vec::new(),
+    };
+    let mut result = Ok(());
+    for worker in workers {
+        let stats = worker.await.map_err(|e| DatenLordError::Internal {
+            context: vec![format!("benchmark worker panicked: {e}")],
+        });
+        match stats.and_then(|stats| stats) {
+            Ok(stats) => {
+                report.ops += stats.ops;
+                report.bytes += stats.bytes;
+                report.latencies.extend(stats.latencies);
+            }
+            Err(e) => result = Err(e),
+        }
+    }
+    report.elapsed = start.elapsed();
+    report.latencies.sort_unstable();
+
+    cleanup(&*fs, &ctx, &dir_name, dir).await?;
+    result.map(|()| report)
+}
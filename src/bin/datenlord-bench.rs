@@ -0,0 +1,142 @@
+//! Benchmark harness running the standard workloads over a matrix of
+//! thread counts and block sizes
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use datenlord::bench::{self, BenchOptions, BenchReport, Workload};
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordResult;
+use datenlord::migrate;
+use datenlord::sdk;
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::VirtualFs;
+
+/// Run every combination of the given workloads, thread counts and block
+/// sizes and report their throughput and latency percentiles
+#[derive(Debug, Parser)]
+#[command(name = "datenlord-bench", version)]
+struct Cli {
+    /// SDK configuration as a JSON string
+    #[arg(long, default_value = "{}")]
+    config: String,
+    /// Backend to run on instead of the root of the config, `file:///path`
+    /// or a plain path
+    #[arg(long)]
+    backend: Option<String>,
+    /// Comma separated workloads among seq-read, rand-read, seq-write,
+    /// rand-write, small-file-create, metadata-stress, exists,
+    /// exists-by-lookup, all of them by default
+    #[arg(long, value_delimiter = ',')]
+    workloads: Vec<Workload>,
+    /// Comma separated numbers of concurrent workers
+    #[arg(long, value_delimiter = ',', default_value = "1,4")]
+    threads: Vec<usize>,
+    /// Comma separated sizes of every read or write, in bytes
+    #[arg(long, value_delimiter = ',', default_value = "4096")]
+    block_sizes: Vec<usize>,
+    /// How long to run each combination, in seconds
+    #[arg(long, default_value_t = 10)]
+    duration: u64,
+    /// The size of each data file of the read and write workloads, in bytes
+    #[arg(long, default_value_t = 64 << 20)]
+    file_size: u64,
+    /// Run on the bare local filesystem, without the caching and retrying
+    /// middlewares of the SDKs
+    #[arg(long)]
+    bare: bool,
+    /// Print CSV instead of a table
+    #[arg(long)]
+    csv: bool,
+}
+
+/// The fields of the row of `options`, in the order of `HEADER`
+fn fields(options: &BenchOptions, report: &BenchReport) -> [String; 9] {
+    let micros = |p| report.percentile(p).as_micros().to_string();
+    [
+        options.workload.to_string(),
+        options.concurrency.to_string(),
+        options.block_size.to_string(),
+        format!("{:.1}", report.ops_per_sec()),
+        format!("{:.2}", report.mib_per_sec()),
+        micros(50.0),
+        micros(90.0),
+        micros(99.0),
+        micros(100.0),
+    ]
+}
+
+/// The names of the fields of a row, latencies in microseconds
+const HEADER: [&str; 9] = [
+    "workload", "threads", "block", "ops/s", "MiB/s", "p50_us", "p90_us", "p99_us", "max_us",
+];
+
+/// Print `fields` as a table row, or as CSV
+fn print_row<S: AsRef<str>>(fields: &[S], csv: bool) {
+    if csv {
+        let fields: Vec<&str> = fields.iter().map(AsRef::as_ref).collect();
+        println!("{}", fields.join(","));
+    } else {
+        let widths = [18, 7, 9, 12, 10, 9, 9, 9, 9];
+        let mut line = String::new();
+        for (field, width) in fields.iter().zip(widths) {
+            line.push_str(&format!("{:>width$} ", field.as_ref()));
+        }
+        println!("{}", line.trim_end());
+    }
+}
+
+/// Run every combination of `cli` on `fs`, printing each row once done
+async fn run_matrix<F: VirtualFs + 'static>(
+    fs: Arc<F>,
+    config: &DatenLordConfig,
+    cli: &Cli,
+) -> DatenLordResult<()> {
+    let workloads = if cli.workloads.is_empty() {
+        Workload::ALL.to_vec()
+    } else {
+        cli.workloads.clone()
+    };
+    print_row(&HEADER, cli.csv);
+    for &workload in &workloads {
+        for &concurrency in &cli.threads {
+            for &block_size in &cli.block_sizes {
+                let options = BenchOptions {
+                    workload,
+                    concurrency,
+                    duration: Duration::from_secs(cli.duration),
+                    block_size,
+                    file_size: cli.file_size,
+                };
+                let ctx = config.request_context();
+                let report = bench::run(Arc::clone(&fs), ctx, &options).await?;
+                print_row(&fields(&options, &report), cli.csv);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut config = DatenLordConfig::parse(&cli.config);
+    let result = async {
+        if let Some(ref uri) = cli.backend {
+            config.root = migrate::backend_root(uri)?;
+        }
+        if cli.bare {
+            run_matrix(Arc::new(LocalFS::new(&config)?), &config, &cli).await
+        } else {
+            run_matrix(Arc::new(sdk::open_fs(&config)?), &config, &cli).await
+        }
+    };
+    match result.await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("benchmark of {:?} failed: {e:?}", config.root);
+            ExitCode::FAILURE
+        }
+    }
+}
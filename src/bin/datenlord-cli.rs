@@ -0,0 +1,1081 @@
+//! Command line tool for the `DatenLord` SDK
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use datenlord::bench::{self, BenchOptions, Workload};
+use datenlord::cachesim::{self, Policy};
+use datenlord::common::buffer_pool::COPY_CHUNK_SIZE;
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::{DatenLordError, DatenLordResult};
+use datenlord::diff::manifest::{Manifest, VerifyOptions};
+use datenlord::diff::{self, DiffOptions};
+use datenlord::lifecycle::{self, LifecycleAction};
+use datenlord::migrate::{self, MigrateOptions};
+use datenlord::sdk::rust::Client;
+use datenlord::storage::archive::ArchiveReport;
+use datenlord::storage::digest::DigestAlgorithm;
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::fs_util::{FileAttr, FileKind};
+#[cfg(feature = "search")]
+use datenlord::storage::search::{IndexSink, SearchIndex};
+use datenlord::storage::superblock::{Feature, Superblock, FORMAT_VERSION};
+#[cfg(feature = "search")]
+use datenlord::storage::tags::TagFilter;
+use datenlord::storage::upload::{self, MAX_PARTS};
+use datenlord::storage::walk;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
+#[cfg(feature = "search")]
+use tokio::runtime::Handle;
+
+/// The size of the parts `put --resumable` uploads by default
+const DEFAULT_PART_SIZE: u64 = 64 << 20;
+
+/// `DatenLord` command line tool
+#[derive(Debug, Parser)]
+#[command(name = "datenlord-cli", version)]
+struct Cli {
+    /// SDK configuration as a JSON string
+    #[arg(long, default_value = "{}")]
+    config: String,
+    /// Backend the file subcommands and `verify` run on instead of the root
+    /// of the config, `file:///path` or a plain path
+    #[arg(long)]
+    backend: Option<String>,
+    /// The subcommand to run
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Subcommands of the tool
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Operate on files through the rust client
+    #[command(flatten)]
+    File(FileCommand),
+    /// Run a synthetic workload and report throughput and latency percentiles
+    Bench {
+        /// One of seq-read, rand-read, seq-write, rand-write, small-file-create,
+        /// metadata-stress, exists, exists-by-lookup
+        #[arg(long, default_value = "seq-read")]
+        workload: Workload,
+        /// The number of concurrent workers
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// How long to run, in seconds
+        #[arg(long, default_value_t = 10)]
+        duration: u64,
+        /// The size of every read or write, in bytes
+        #[arg(long, default_value_t = 4096)]
+        block_size: usize,
+        /// The size of each read worker's data file, in bytes
+        #[arg(long, default_value_t = 64 << 20)]
+        file_size: u64,
+    },
+    /// Replay an access trace offline and report hypothetical cache hit rates
+    CacheSim {
+        /// JSON-lines trace, one `{"op","ino","offset","len"}` record per line
+        #[arg(long)]
+        trace: PathBuf,
+        /// Comma separated eviction policies among lru, fifo, lfu
+        #[arg(long, value_delimiter = ',', default_value = "lru")]
+        policy: Vec<Policy>,
+        /// Comma separated cache capacities, in bytes
+        #[arg(long, value_delimiter = ',', required = true)]
+        cache_sizes: Vec<u64>,
+        /// The cache page size, in bytes
+        #[arg(long, default_value_t = 4096)]
+        page_size: u64,
+    },
+    /// Copy a whole namespace from one backend to another
+    Migrate {
+        /// Source backend, `file:///path` or a plain path
+        src: String,
+        /// Destination backend, `file:///path` or a plain path
+        dst: String,
+        /// The number of files copied concurrently
+        #[arg(long, default_value_t = 8)]
+        parallelism: usize,
+        /// Limit of the copy bandwidth in bytes per second
+        #[arg(long)]
+        bytes_per_sec: Option<u64>,
+        /// File recording copied files, rerun with the same file to resume
+        #[arg(long)]
+        checkpoint: Option<PathBuf>,
+        /// Skip comparing both namespaces after copying
+        #[arg(long)]
+        no_verify: bool,
+    },
+    /// List the paths added, removed or modified from one directory to another,
+    /// exiting with 1 when there are any and 2 on errors
+    Diff {
+        /// The directory compared against, relative to the root of the config
+        old: String,
+        /// The compared directory, relative to the root of the config
+        new: String,
+        /// Read `new` from this backend instead, `file:///path` or a plain path
+        #[arg(long)]
+        new_backend: Option<String>,
+        /// Compare the checksums of files of the same size instead of their
+        /// modification times
+        #[arg(long)]
+        checksum: bool,
+        /// The number of directories listed, or files checksummed, at once
+        #[arg(long, default_value_t = walk::DEFAULT_WALK_CONCURRENCY)]
+        concurrency: usize,
+    },
+    /// Check a directory against a JSON manifest of the paths, sizes and
+    /// digests of its files, printing the differences as a JSON report
+    Verify {
+        /// The manifest, `{"files": [{"path", "size", "digest"}]}`
+        #[arg(long)]
+        manifest: PathBuf,
+        /// The directory, relative to the root of the config, the root by
+        /// default
+        #[arg(default_value = "")]
+        path: String,
+        /// Accept files the manifest does not list
+        #[arg(long)]
+        allow_unexpected: bool,
+        /// The number of directories listed, or files hashed, at once
+        #[arg(long, default_value_t = walk::DEFAULT_WALK_CONCURRENCY)]
+        concurrency: usize,
+    },
+    /// Evaluate the lifecycle rules of the config once
+    Lifecycle {
+        /// Only report what the rules would do
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Upgrade the on-disk format of the namespace to the one of this build
+    Upgrade,
+    /// Remove the backend data no file refers to any more
+    Gc {
+        /// Only list the garbage found
+        #[arg(long)]
+        dry_run: bool,
+        /// Keep the data changed within this many seconds, which may belong
+        /// to a file being created
+        #[arg(long, default_value_t = 3600)]
+        grace_secs: u64,
+    },
+    /// Repair the erasure coded chunks of the striping config, e.g. after
+    /// replacing a lost device with an empty directory
+    RebuildStripes,
+    /// Enable an optional feature on an existing namespace, migrating its data
+    EnableFeature {
+        /// One of compression, encryption, versioning, dedup, packing
+        feature: Feature,
+    },
+    /// Find files in the search index of the config
+    #[cfg(feature = "search")]
+    Search {
+        /// Words matching parts of names, and `path:`, `kind:`, `size:` or
+        /// `mtime:` restrictions, e.g. `report kind:file size:>1000`
+        query: String,
+        /// The maximum number of files listed
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Find files by their tags in the search index of the config
+    #[cfg(feature = "search")]
+    FindByTags {
+        /// Comma separated `key=value` terms and bare `key` terms matching
+        /// any value, e.g. `team=data,cold`
+        filter: String,
+        /// The maximum number of files listed
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Rebuild the search index of the config from the whole namespace
+    #[cfg(feature = "search")]
+    Reindex {
+        /// The number of directories listed concurrently
+        #[arg(long, default_value_t = walk::DEFAULT_WALK_CONCURRENCY)]
+        concurrency: usize,
+    },
+}
+
+/// Subcommands operating on files of the namespace, whose paths are relative
+/// to its root
+#[derive(Debug, Subcommand)]
+enum FileCommand {
+    /// List the entries of a directory
+    Ls {
+        /// The directory, the root by default
+        #[arg(default_value = "")]
+        path: String,
+        /// Also list the kind, permissions, size and modification time
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Write the contents of a file to stdout
+    Cat {
+        /// The file
+        path: String,
+    },
+    /// Upload a local file, replacing the file at the destination
+    Put {
+        /// The local file
+        local: PathBuf,
+        /// The destination
+        path: String,
+        /// Upload in parts, resuming the upload to the destination left by
+        /// a former run
+        #[arg(long)]
+        resumable: bool,
+        /// The size of the parts of a resumable upload, in bytes
+        #[arg(long, default_value_t = DEFAULT_PART_SIZE)]
+        part_size: u64,
+    },
+    /// Download a file to a local file, replacing it
+    Get {
+        /// The file
+        path: String,
+        /// The local destination
+        local: PathBuf,
+    },
+    /// Remove a file or an empty directory
+    Rm {
+        /// The file or directory
+        path: String,
+        /// Remove directories with everything under them
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Print the attributes of a file or directory
+    Stat {
+        /// The file or directory
+        path: String,
+    },
+    /// Print the bytes and the number of files and directories below a
+    /// directory, hard links counted once
+    Du {
+        /// The file or directory, the root by default
+        #[arg(default_value = "")]
+        path: String,
+        /// The number of directories listed at once
+        #[arg(long, default_value_t = walk::DEFAULT_WALK_CONCURRENCY)]
+        concurrency: usize,
+    },
+    /// Create a directory with its missing parents
+    Mkdir {
+        /// The directory
+        path: String,
+    },
+    /// Copy a file, replacing the file at the destination
+    Cp {
+        /// The file
+        src: String,
+        /// The destination
+        dst: String,
+    },
+    /// Flush everything buffered by the namespace and its backend, like
+    /// `syncfs`, e.g. before taking a snapshot
+    Sync,
+    /// Print the digest of the contents of a file, streamed through the
+    /// filesystem
+    Digest {
+        /// The file
+        path: String,
+        /// One of crc32c, sha256, blake3
+        #[arg(long, default_value = "sha256")]
+        algo: DigestAlgorithm,
+        /// Keep the digest in an extended attribute of the file until it is
+        /// written to, and reuse one kept there
+        #[arg(long)]
+        cache: bool,
+    },
+    /// Write a file or directory with everything under it to a local tar
+    /// archive, keeping modes, owners, times and symbolic links
+    ExportTar {
+        /// The file or directory
+        path: String,
+        /// The local archive, `-` for stdout
+        archive: PathBuf,
+    },
+    /// Extract a local tar archive into a directory, created if missing
+    ImportTar {
+        /// The local archive, `-` for stdin
+        archive: PathBuf,
+        /// The directory, the root by default
+        #[arg(default_value = "")]
+        path: String,
+    },
+}
+
+/// Build a closure mapping an `io::Error` into `DatenLordError::Io` with context
+fn io_error(context: String) -> impl FnOnce(io::Error) -> DatenLordError {
+    move |e| DatenLordError::Io {
+        context: vec![format!("{context}: {e}")],
+        source: Some(Box::new(e)),
+    }
+}
+
+/// The modification time of `attr` in seconds after the epoch
+fn mtime_secs(attr: &FileAttr) -> u64 {
+    attr.mtime
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |mtime| mtime.as_secs())
+}
+
+/// The name of the kind of `attr`
+fn kind_name(attr: &FileAttr) -> &'static str {
+    FileKind::from_sflag(attr.kind).map_or("unknown", FileKind::name)
+}
+
+/// The summary of what an archive export or import moved
+fn archive_summary(report: &ArchiveReport) -> String {
+    format!(
+        "{} files, {} directories, {} symbolic links, {} bytes, {} skipped",
+        report.files, report.dirs, report.symlinks, report.bytes, report.skipped
+    )
+}
+
+/// Copy the file `src` of `client` into the writer `dst`
+async fn copy_out<W>(client: &Client, src: &str, dst: &mut W) -> DatenLordResult<u64>
+where
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let file = client.open(src, OFlag::O_RDONLY).await?;
+    let mut reader = AsyncBufReader::with_capacity(COPY_CHUNK_SIZE, file);
+    let copied = tokio::io::copy_buf(&mut reader, dst)
+        .await
+        .map_err(io_error(format!("failed to copy {src}")))?;
+    dst.flush()
+        .await
+        .map_err(io_error(format!("failed to copy {src}")))?;
+    reader.into_inner().close().await?;
+    Ok(copied)
+}
+
+/// Copy the reader `src` into the file `dst` of `client`, created or
+/// truncated
+async fn copy_in<R>(client: &Client, src: &mut R, dst: &str) -> DatenLordResult<u64>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    let mut file = client.create(dst).await?;
+    let mut reader = AsyncBufReader::with_capacity(COPY_CHUNK_SIZE, src);
+    let copied = tokio::io::copy_buf(&mut reader, &mut file)
+        .await
+        .map_err(io_error(format!("failed to copy to {dst}")))?;
+    file.flush()
+        .await
+        .map_err(io_error(format!("failed to copy to {dst}")))?;
+    file.close().await?;
+    Ok(copied)
+}
+
+/// Upload the local file `local` to `dst` of `client` in parts of
+/// `part_size` bytes, returning the bytes uploaded and how many parts were
+/// already there
+///
+/// The last upload to `dst` left in progress is resumed, its parts matching
+/// the checksums of the local data being kept.
+async fn put_resumable(
+    client: &Client,
+    local: &Path,
+    dst: &str,
+    part_size: u64,
+) -> DatenLordResult<(u64, usize)> {
+    if part_size == 0 {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec!["the part size cannot be 0".to_owned()],
+        });
+    }
+    let mut src = tokio::fs::File::open(local)
+        .await
+        .map_err(io_error(format!("failed to open {local:?}")))?;
+    let size = src
+        .metadata()
+        .await
+        .map_err(io_error(format!("failed to stat {local:?}")))?
+        .len();
+    let count = u32::try_from(size.div_ceil(part_size).max(1)).unwrap_or(u32::MAX);
+    if count > MAX_PARTS {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("{local:?} takes more than {MAX_PARTS} parts, raise the part size")],
+        });
+    }
+
+    let pending = client
+        .list_uploads()
+        .await?
+        .into_iter()
+        .rev()
+        .find(|upload| upload.path == Path::new(dst));
+    let (id, mut parts) = match pending {
+        Some(upload) => {
+            let parts = client.upload_parts(&upload.id).await?;
+            if parts.iter().any(|part| part.index >= count) {
+                // Uploaded from another file or in other parts
+                client.abort_upload(&upload.id).await?;
+                (client.start_upload(dst).await?, Vec::new())
+            } else {
+                (upload.id, parts)
+            }
+        }
+        None => (client.start_upload(dst).await?, Vec::new()),
+    };
+    let mut resumed = 0;
+    let mut buf = Vec::new();
+    for index in 0..count {
+        buf.clear();
+        (&mut src)
+            .take(part_size)
+            .read_to_end(&mut buf)
+            .await
+            .map_err(io_error(format!("failed to read {local:?}")))?;
+        let uploaded = parts
+            .iter()
+            .position(|part| part.index == index)
+            .map(|position| parts.swap_remove(position));
+        if uploaded.is_some_and(|part| part.checksum == upload::checksum(&buf)) {
+            resumed += 1;
+            continue;
+        }
+        client.upload_part(&id, index, &buf).await?;
+    }
+    let attr = client.complete_upload(&id).await?;
+    Ok((attr.size, resumed))
+}
+
+/// Remove `path` and, if it is a directory, everything under it
+async fn remove_all(client: &Client, path: &str) -> DatenLordResult<()> {
+    // Directories are listed before their children and removed after them
+    let mut pending = vec![OsString::from(path)];
+    let mut removals = Vec::new();
+    while let Some(path) = pending.pop() {
+        if client.metadata(&path).await?.kind == SFlag::S_IFDIR {
+            for entry in client.read_dir(&path).await? {
+                if entry.name != "." && entry.name != ".." {
+                    pending.push(Path::new(&path).join(&entry.name).into_os_string());
+                }
+            }
+        }
+        removals.push(path);
+    }
+    for path in removals.iter().rev() {
+        client.remove(path).await?;
+    }
+    Ok(())
+}
+
+/// Run the file subcommand `command` through `client`
+async fn run_file_command(client: &Client, command: FileCommand) -> DatenLordResult<()> {
+    match command {
+        FileCommand::Ls { path, long } => {
+            let mut entries = client.read_dir(&path).await?;
+            entries.retain(|entry| entry.name != "." && entry.name != "..");
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            for entry in entries {
+                match entry.attr {
+                    Some(ref attr) if long => println!(
+                        "{:<10} {:04o} {:>14} {:>10} {}",
+                        kind_name(attr),
+                        attr.perm,
+                        attr.size,
+                        mtime_secs(attr),
+                        entry.name.to_string_lossy()
+                    ),
+                    _ => println!("{}", entry.name.to_string_lossy()),
+                }
+            }
+        }
+        FileCommand::Cat { path } => {
+            copy_out(client, &path, &mut tokio::io::stdout()).await?;
+        }
+        FileCommand::Put {
+            local,
+            path,
+            resumable: true,
+            part_size,
+        } => {
+            let (copied, resumed) = put_resumable(client, &local, &path, part_size).await?;
+            println!("uploaded {copied} bytes to {path}, {resumed} parts resumed");
+        }
+        FileCommand::Put { local, path, .. } => {
+            let mut src = tokio::fs::File::open(&local)
+                .await
+                .map_err(io_error(format!("failed to open {local:?}")))?;
+            let copied = copy_in(client, &mut src, &path).await?;
+            println!("uploaded {copied} bytes to {path}");
+        }
+        FileCommand::Get { path, local } => {
+            let mut dst = tokio::fs::File::create(&local)
+                .await
+                .map_err(io_error(format!("failed to create {local:?}")))?;
+            let copied = copy_out(client, &path, &mut dst).await?;
+            println!("downloaded {copied} bytes to {local:?}");
+        }
+        FileCommand::Rm { path, recursive } => {
+            if recursive {
+                remove_all(client, &path).await?;
+            } else {
+                client.remove(&path).await?;
+            }
+        }
+        FileCommand::Stat { path } => {
+            let attr = client.metadata(&path).await?;
+            println!("path:  {path}");
+            println!("kind:  {}", kind_name(&attr));
+            println!("ino:   {}", attr.ino);
+            println!("size:  {}", attr.size);
+            println!("perm:  {:04o}", attr.perm);
+            println!("nlink: {}", attr.nlink);
+            println!("uid:   {}", attr.uid);
+            println!("gid:   {}", attr.gid);
+            println!("mtime: {}", mtime_secs(&attr));
+            for (key, value) in client.tags(&path).await? {
+                println!("tag:   {key}={value}");
+            }
+        }
+        FileCommand::Du { path, concurrency } => {
+            let usage = client.disk_usage(&path, concurrency).await?;
+            println!("bytes:     {}", usage.bytes);
+            println!("allocated: {}", usage.allocated_bytes);
+            println!("files:     {}", usage.files);
+            println!("dirs:      {}", usage.dirs);
+        }
+        FileCommand::Mkdir { path } => client.create_dir_all(&path).await?,
+        FileCommand::Cp { src, dst } => {
+            let copy = client.copy(&src, &dst).await?;
+            let how = if copy.reflinked { ", reflinked" } else { "" };
+            println!("copied {} bytes from {src} to {dst}{how}", copy.copied);
+        }
+        FileCommand::Sync => client.sync_all().await?,
+        FileCommand::Digest { path, algo, cache } => {
+            println!("{}  {path}", client.file_digest(&path, algo, cache).await?);
+        }
+        FileCommand::ExportTar { path, archive } if archive.as_os_str() == "-" => {
+            let report = client.export_tar(&path, &mut tokio::io::stdout()).await?;
+            eprintln!("exported {}", archive_summary(&report));
+        }
+        FileCommand::ExportTar { path, archive } => {
+            let dst = tokio::fs::File::create(&archive)
+                .await
+                .map_err(io_error(format!("failed to create {archive:?}")))?;
+            let mut dst = tokio::io::BufWriter::with_capacity(COPY_CHUNK_SIZE, dst);
+            let report = client.export_tar(&path, &mut dst).await?;
+            println!("exported {} to {archive:?}", archive_summary(&report));
+        }
+        FileCommand::ImportTar { archive, path } => {
+            let report = if archive.as_os_str() == "-" {
+                let mut src = AsyncBufReader::with_capacity(COPY_CHUNK_SIZE, tokio::io::stdin());
+                client.import_tar(&mut src, &path).await?
+            } else {
+                let src = tokio::fs::File::open(&archive)
+                    .await
+                    .map_err(io_error(format!("failed to open {archive:?}")))?;
+                let mut src = AsyncBufReader::with_capacity(COPY_CHUNK_SIZE, src);
+                client.import_tar(&mut src, &path).await?
+            };
+            println!("imported {}", archive_summary(&report));
+        }
+    }
+    Ok(())
+}
+
+/// Open a client of the namespace of `config`, or of `backend` when given,
+/// and run the file subcommand `command` through it
+async fn run_file(config: &str, backend: Option<&str>, command: FileCommand) -> ExitCode {
+    let mut config = DatenLordConfig::parse(config);
+    let result = async {
+        if let Some(uri) = backend {
+            config.root = migrate::backend_root(uri)?;
+        }
+        let client = Client::new(&config)?;
+        run_file_command(&client, command).await
+    };
+    match result.await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Open the local filesystem described by `config` and run a benchmark on it
+async fn run_bench(config: &str, options: &BenchOptions) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    let localfs = match LocalFS::new(&config) {
+        Ok(localfs) => Arc::new(localfs),
+        Err(e) => {
+            eprintln!("failed to open {:?}: {e:?}", config.root);
+            return ExitCode::FAILURE;
+        }
+    };
+    match bench::run(localfs, config.request_context(), options).await {
+        Ok(report) => {
+            println!("{report}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("benchmark failed: {e:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Evaluate the lifecycle rules of `config` once, listing the actions
+async fn run_lifecycle(config: &str, dry_run: bool) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    let lifecycle = &config.lifecycle;
+    let result = match LocalFS::new(&config) {
+        Ok(localfs) => {
+            let ctx = config.request_context();
+            let audit_log = lifecycle.audit_log.as_ref();
+            let localfs = Arc::new(localfs);
+            lifecycle::evaluate(localfs, ctx, &lifecycle.rules, dry_run, audit_log).await
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(report) => {
+            for record in &report.records {
+                let action = match record.action {
+                    LifecycleAction::Delete => "delete".to_owned(),
+                    LifecycleAction::Archive { ref class } => format!("archive:{class}"),
+                };
+                let outcome = match record.error {
+                    Some(ref error) => format!(" failed: {error}"),
+                    None if dry_run => " (dry run)".to_owned(),
+                    None => String::new(),
+                };
+                println!("{:<16} {:<16} {}{outcome}", record.rule, action, record.path);
+            }
+            println!("{} files matched, {} actions", report.matched, report.records.len());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("lifecycle evaluation of {:?} failed: {e:?}", config.root);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Copy the namespace of backend `src` into backend `dst`
+async fn run_migrate(src: &str, dst: &str, options: &MigrateOptions) -> ExitCode {
+    let backends = migrate::open_backend(src).and_then(|src| Ok((src, migrate::open_backend(dst)?)));
+    let result = match backends {
+        Ok((src, dst)) => migrate::migrate(Arc::new(src), Arc::new(dst), options).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(report) => {
+            println!(
+                "migrated {} files ({} bytes) and {} directories in {:.3}s, \
+                 {} resumed, {} verified",
+                report.files,
+                report.bytes,
+                report.dirs,
+                report.elapsed.as_secs_f64(),
+                report.resumed,
+                report.verified
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("migration from {src} to {dst} failed: {e:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// List the changes from directory `old` to directory `new` of the namespace
+/// of `config`, or of backend `new_backend` for `new`
+async fn run_diff(
+    config: &str,
+    old: &str,
+    new: &str,
+    new_backend: Option<&str>,
+    options: &DiffOptions,
+) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    let ctx = config.request_context();
+    let result = async {
+        let localfs = Arc::new(LocalFS::new(&config)?);
+        let other = match new_backend {
+            Some(uri) => Arc::new(migrate::open_backend(uri)?),
+            None => Arc::clone(&localfs),
+        };
+        diff::diff(localfs, old, other, new, ctx, options).await
+    };
+    match result.await {
+        Ok(changeset) => {
+            for change in &changeset.changes {
+                let checksum = match change.content_differs {
+                    Some(true) => " (contents differ)",
+                    _ => "",
+                };
+                println!(
+                    "{:<8} {:>+14} {}{checksum}",
+                    change.kind.name(),
+                    change.size_delta(),
+                    change.path
+                );
+            }
+            if changeset.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(1)
+            }
+        }
+        Err(e) => {
+            eprintln!("diff of {old} and {new} failed: {e:?}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Check the directory `path` of the namespace of `config`, or of `backend`
+/// when given, against the manifest at `manifest`, exiting with 1 when
+/// they differ
+async fn run_verify(
+    config: &str,
+    manifest: &Path,
+    path: &str,
+    backend: Option<&str>,
+    options: &VerifyOptions,
+) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    let ctx = config.request_context();
+    let result = async {
+        let json = tokio::fs::read_to_string(manifest)
+            .await
+            .map_err(io_error(format!("failed to read {manifest:?}")))?;
+        let manifest = Manifest::parse(&json)?;
+        let fs = match backend {
+            Some(uri) => Arc::new(migrate::open_backend(uri)?),
+            None => Arc::new(LocalFS::new(&config)?),
+        };
+        diff::manifest::verify(fs, path, &manifest, ctx, options).await
+    };
+    match result.await {
+        Ok(report) => {
+            match serde_json::to_string_pretty(&report) {
+                Ok(json) => println!("{json}"),
+                Err(e) => {
+                    eprintln!("failed to print the report: {e}");
+                    return ExitCode::from(2);
+                }
+            }
+            if report.is_ok() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(1)
+            }
+        }
+        Err(e) => {
+            eprintln!("verification of {path:?} against {manifest:?} failed: {e:?}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Upgrade the namespace described by `config` to `FORMAT_VERSION`
+fn run_upgrade(config: &str) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    match Superblock::upgrade(&config.root) {
+        Ok(from) => {
+            println!("upgraded {:?} from format version {from} to {FORMAT_VERSION}", config.root);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to upgrade {:?}: {e:?}", config.root);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Collect the garbage of the namespace of `config` once, listing it
+async fn run_gc(config: &str, dry_run: bool, grace: Duration) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    let result = match Client::new(&config) {
+        Ok(client) => client.collect_garbage(grace, dry_run).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(report) => {
+            for orphan in &report.orphans {
+                println!("{orphan}");
+            }
+            let outcome = if dry_run { "found" } else { "removed" };
+            println!(
+                "{} inodes reachable, {outcome} {} orphans of {} bytes",
+                report.reachable,
+                report.orphans.len(),
+                report.bytes
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("garbage collection of {:?} failed: {e:?}", config.root);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Repair the chunks of the striping config of `config`
+async fn run_rebuild_stripes(config: &str) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    let result = match Client::new(&config) {
+        Ok(client) => client.rebuild_stripes().await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(report) => {
+            for (ino, stripe) in &report.lost {
+                println!("lost stripe {stripe} of inode {ino}");
+            }
+            println!(
+                "checked {} stripes of {} files, rewrote {} chunks, {} stripes lost",
+                report.stripes,
+                report.inodes,
+                report.repaired,
+                report.lost.len()
+            );
+            if report.lost.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("failed to rebuild the stripes of {:?}: {e:?}", config.root);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Enable `feature` on the namespace described by `config`
+fn run_enable_feature(config: &str, feature: Feature) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    let result = LocalFS::new(&config).and_then(|localfs| localfs.enable_feature(feature));
+    match result {
+        Ok(()) => {
+            println!("enabled {feature} on {:?}", config.root);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to enable {feature} on {:?}: {e:?}", config.root);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The search index directory of `config`, reporting when there is none
+#[cfg(feature = "search")]
+fn search_index_dir(config: &DatenLordConfig) -> Option<PathBuf> {
+    if config.search_index.is_none() {
+        eprintln!("the config has no search_index");
+    }
+    config.search_index.clone()
+}
+
+/// List the files matching `query` in the search index of `config`
+#[cfg(feature = "search")]
+fn run_search(config: &str, query: &str, limit: usize) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    let Some(dir) = search_index_dir(&config) else {
+        return ExitCode::FAILURE;
+    };
+    match SearchIndex::open(&dir).and_then(|index| index.search(query, limit)) {
+        Ok(hits) => {
+            for hit in hits {
+                let kind = hit.kind.map_or("unknown", FileKind::name);
+                println!("{:<10} {:>14} {}", kind, hit.size, hit.path);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("search failed: {e:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// List the files whose tags match `filter` in the search index of `config`
+#[cfg(feature = "search")]
+fn run_find_by_tags(config: &str, filter: &str, limit: usize) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    let Some(dir) = search_index_dir(&config) else {
+        return ExitCode::FAILURE;
+    };
+    let hits = filter
+        .parse::<TagFilter>()
+        .and_then(|filter| SearchIndex::open(&dir)?.find_by_tags(&filter, limit));
+    match hits {
+        Ok(hits) => {
+            for hit in hits {
+                let tags: Vec<String> =
+                    hit.tags.iter().map(|(key, value)| format!("{key}={value}")).collect();
+                println!("{} {}", hit.path, tags.join(","));
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("tag search failed: {e:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Index every file of the namespace of `config` from scratch
+#[cfg(feature = "search")]
+async fn run_reindex(config: &str, concurrency: usize) -> ExitCode {
+    let config = DatenLordConfig::parse(config);
+    let Some(dir) = search_index_dir(&config) else {
+        return ExitCode::FAILURE;
+    };
+    let result = async {
+        let localfs = Arc::new(LocalFS::new(&config)?);
+        let mut sink = IndexSink::open(&dir)?;
+        let ctx = config.request_context();
+        let walk = walk::walk(Arc::clone(&localfs), ctx, &Handle::current(), "".as_ref(), concurrency);
+        sink.rebuild(&*localfs, &ctx, walk).await
+    };
+    match result.await {
+        Ok(count) => {
+            println!("indexed {count} files of {:?}", config.root);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("failed to index {:?}: {e:?}", config.root);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Replay `trace` against every combination of `policies` and `cache_sizes`
+fn run_cache_sim(
+    trace: &Path,
+    policies: &[Policy],
+    cache_sizes: &[u64],
+    page_size: u64,
+) -> ExitCode {
+    let records = match File::open(trace) {
+        Ok(file) => cachesim::read_trace(BufReader::new(file)),
+        Err(e) => {
+            eprintln!("failed to open trace {trace:?}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let records = match records {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("failed to load trace {trace:?}: {e:?}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!(
+        "{:<6} {:>14} {:>12} {:>12} {:>9}",
+        "policy", "capacity", "hits", "misses", "hit rate"
+    );
+    for &policy in policies {
+        for &capacity in cache_sizes {
+            match cachesim::simulate(&records, policy, capacity, page_size) {
+                Ok(result) => println!("{result}"),
+                Err(e) => {
+                    eprintln!("simulation failed: {e:?}");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::File(command) => run_file(&cli.config, cli.backend.as_deref(), command).await,
+        Command::Bench {
+            workload,
+            concurrency,
+            duration,
+            block_size,
+            file_size,
+        } => {
+            let options = BenchOptions {
+                workload,
+                concurrency,
+                duration: Duration::from_secs(duration),
+                block_size,
+                file_size,
+            };
+            run_bench(&cli.config, &options).await
+        }
+        Command::CacheSim {
+            trace,
+            policy,
+            cache_sizes,
+            page_size,
+        } => run_cache_sim(&trace, &policy, &cache_sizes, page_size),
+        Command::Migrate {
+            src,
+            dst,
+            parallelism,
+            bytes_per_sec,
+            checkpoint,
+            no_verify,
+        } => {
+            let options = MigrateOptions {
+                parallelism,
+                bytes_per_sec,
+                checkpoint,
+                verify: !no_verify,
+            };
+            run_migrate(&src, &dst, &options).await
+        }
+        Command::Diff {
+            old,
+            new,
+            new_backend,
+            checksum,
+            concurrency,
+        } => {
+            let options = DiffOptions {
+                checksum,
+                concurrency,
+            };
+            run_diff(&cli.config, &old, &new, new_backend.as_deref(), &options).await
+        }
+        Command::Verify {
+            manifest,
+            path,
+            allow_unexpected,
+            concurrency,
+        } => {
+            let options = VerifyOptions {
+                allow_unexpected,
+                concurrency,
+            };
+            run_verify(&cli.config, &manifest, &path, cli.backend.as_deref(), &options).await
+        }
+        Command::Lifecycle { dry_run } => run_lifecycle(&cli.config, dry_run).await,
+        Command::Upgrade => run_upgrade(&cli.config),
+        Command::Gc {
+            dry_run,
+            grace_secs,
+        } => run_gc(&cli.config, dry_run, Duration::from_secs(grace_secs)).await,
+        Command::RebuildStripes => run_rebuild_stripes(&cli.config).await,
+        Command::EnableFeature { feature } => run_enable_feature(&cli.config, feature),
+        #[cfg(feature = "search")]
+        Command::Search { query, limit } => run_search(&cli.config, &query, limit),
+        #[cfg(feature = "search")]
+        Command::FindByTags { filter, limit } => run_find_by_tags(&cli.config, &filter, limit),
+        #[cfg(feature = "search")]
+        Command::Reindex { concurrency } => run_reindex(&cli.config, concurrency).await,
+    }
+}
@@ -0,0 +1,53 @@
+//! Consistency checker for the local root of a `DatenLord` namespace
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use datenlord::common::config::DatenLordConfig;
+use datenlord::fsck::{self, FsckOptions};
+
+/// Check a namespace for inconsistencies, exiting with `1` when some are
+/// left unrepaired and `2` when the check fails
+#[derive(Debug, Parser)]
+#[command(name = "datenlord-fsck", version)]
+struct Cli {
+    /// The root to check, the root of `--config` by default
+    root: Option<PathBuf>,
+    /// SDK configuration as a JSON string
+    #[arg(long, default_value = "{}")]
+    config: String,
+    /// Remove orphaned data and invalid tags
+    #[arg(long)]
+    repair: bool,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let root = cli
+        .root
+        .unwrap_or_else(|| DatenLordConfig::parse(&cli.config).root);
+    let options = FsckOptions { repair: cli.repair };
+    let report = match fsck::check(&root, &options) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("fsck of {root:?} failed: {e:?}");
+            return ExitCode::from(2);
+        }
+    };
+    for issue in &report.issues {
+        let repaired = if issue.repaired { " (repaired)" } else { "" };
+        let path = if issue.path.is_empty() { "/" } else { &issue.path };
+        println!("{:<7} {path}: {}{repaired}", issue.severity.name(), issue.message);
+    }
+    let unrepaired = report.unrepaired().count();
+    println!(
+        "{} entries checked, {} issues, {unrepaired} unrepaired",
+        report.entries,
+        report.issues.len()
+    );
+    if unrepaired == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
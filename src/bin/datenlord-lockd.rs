@@ -0,0 +1,27 @@
+//! Lock server sharing the POSIX file locks of a namespace between hosts
+use std::process::ExitCode;
+
+use clap::Parser;
+use datenlord::storage::filelock::server;
+
+/// Keep the file locks of the `SharedFs` instances whose `locks` config
+/// points here, releasing those of instances missing their lease
+#[derive(Debug, Parser)]
+#[command(name = "datenlord-lockd", version)]
+struct Cli {
+    /// The address to listen on
+    #[arg(long, default_value = "0.0.0.0:7070")]
+    listen: String,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match server::run(&cli.listen).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("lock server failed: {e:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! NFSv3 gateway serving a `DatenLord` namespace to unmodified clients
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use datenlord::common::config::DatenLordConfig;
+use datenlord::gateway::nfs;
+
+/// Serve the exports of the `nfs` field of the config over NFSv3 until the
+/// listener fails
+#[derive(Debug, Parser)]
+#[command(name = "datenlord-nfs", version)]
+struct Cli {
+    /// SDK configuration as a JSON string, or the file holding it when it
+    /// starts with `@`, reloaded on `SIGHUP`
+    #[arg(long, default_value = "{}")]
+    config: String,
+    /// The address to listen on, the `listen` of the `nfs` config by default
+    #[arg(long)]
+    listen: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut config = match cli.config.strip_prefix('@') {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(config) => DatenLordConfig::parse(&config),
+            Err(e) => {
+                eprintln!("failed to read the config {path:?}: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => DatenLordConfig::parse(&cli.config),
+    };
+    if let Some(listen) = cli.listen {
+        config.nfs.listen = listen;
+    }
+    if config.nfs.exports.is_empty() {
+        eprintln!("no exports, add some to the `nfs` field of --config");
+        return ExitCode::FAILURE;
+    }
+    let config_file = cli.config.strip_prefix('@').map(PathBuf::from);
+    match nfs::run(&config, config_file).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("NFS gateway of {:?} failed: {e:?}", config.root);
+            ExitCode::FAILURE
+        }
+    }
+}
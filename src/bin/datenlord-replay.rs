@@ -0,0 +1,113 @@
+//! Replays a captured operation sequence against a backend, for
+//! benchmarking and reproducing bugs
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordResult;
+use datenlord::migrate;
+use datenlord::replay::{self, ReplayOptions, ReplayReport};
+use datenlord::sdk;
+use datenlord::storage::localfs::LocalFS;
+
+/// Re-execute the operations of audit logs in order, exiting with `1` when
+/// some outcomes differ from the captured ones and `2` when the replay fails
+#[derive(Debug, Parser)]
+#[command(name = "datenlord-replay", version)]
+struct Cli {
+    /// The audit logs to replay, oldest first, e.g. `audit.jsonl.1
+    /// audit.jsonl`
+    #[arg(required = true)]
+    logs: Vec<PathBuf>,
+    /// SDK configuration as a JSON string
+    #[arg(long, default_value = "{}")]
+    config: String,
+    /// Backend to replay against instead of the root of the config,
+    /// `file:///path` or a plain path
+    #[arg(long)]
+    backend: Option<String>,
+    /// How many times faster than captured to issue the operations, `0` as
+    /// fast as possible
+    #[arg(long, default_value_t = 1.0)]
+    speed: f64,
+    /// Run every operation as its recorded user and group
+    #[arg(long)]
+    as_recorded: bool,
+    /// Replay on the bare local filesystem, without the caching and retrying
+    /// middlewares of the SDKs
+    #[arg(long)]
+    bare: bool,
+}
+
+/// Read the logs of `cli` and replay them on the backend of `config`
+async fn run(cli: &Cli, config: &DatenLordConfig) -> DatenLordResult<ReplayReport> {
+    let mut records = Vec::new();
+    for path in &cli.logs {
+        records.extend(replay::read_log(path)?);
+    }
+    let options = ReplayOptions {
+        speed: cli.speed,
+        as_recorded: cli.as_recorded,
+    };
+    let ctx = config.request_context();
+    if cli.bare {
+        replay::replay(&LocalFS::new(config)?, ctx, &records, &options).await
+    } else {
+        replay::replay(&sdk::open_fs(config)?, ctx, &records, &options).await
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut config = DatenLordConfig::parse(&cli.config);
+    if let Some(ref uri) = cli.backend {
+        match migrate::backend_root(uri) {
+            Ok(root) => config.root = root,
+            Err(e) => {
+                eprintln!("invalid backend {uri}: {e:?}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let report = match run(&cli, &config).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("replay on {:?} failed: {e:?}", config.root);
+            return ExitCode::from(2);
+        }
+    };
+    for mismatch in &report.mismatches {
+        let recorded = if mismatch.recorded_ok {
+            "succeeded"
+        } else {
+            "failed"
+        };
+        let error = mismatch.error.as_deref().unwrap_or("succeeded");
+        println!(
+            "#{} {}: recorded {recorded}, replayed {error}",
+            mismatch.index, mismatch.op
+        );
+    }
+    println!(
+        "{} operations replayed in {:.3}s ({:.1} ops/s), {} skipped, {} mismatched",
+        report.replayed,
+        report.elapsed.as_secs_f64(),
+        report.ops_per_sec(),
+        report.skipped,
+        report.mismatches.len()
+    );
+    println!(
+        "latency: p50={:?} p90={:?} p99={:?} max={:?}",
+        report.percentile(50.0),
+        report.percentile(90.0),
+        report.percentile(99.0),
+        report.percentile(100.0)
+    );
+    if report.mismatches.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
@@ -0,0 +1,47 @@
+//! S3-compatible endpoint serving a `DatenLord` namespace
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use datenlord::common::config::DatenLordConfig;
+use datenlord::gateway::s3;
+
+/// Serve the namespace of the config to S3 clients, its root directories
+/// as buckets
+#[derive(Debug, Parser)]
+#[command(name = "datenlord-s3", version)]
+struct Cli {
+    /// SDK configuration as a JSON string, or the file holding it when it
+    /// starts with `@`, reloaded on `SIGHUP`
+    #[arg(long, default_value = "{}")]
+    config: String,
+    /// The address to listen on, the `listen` of the `s3` config by default
+    #[arg(long)]
+    listen: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut config = match cli.config.strip_prefix('@') {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(config) => DatenLordConfig::parse(&config),
+            Err(e) => {
+                eprintln!("failed to read the config {path:?}: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => DatenLordConfig::parse(&cli.config),
+    };
+    if let Some(listen) = cli.listen {
+        config.s3.listen = listen;
+    }
+    let config_file = cli.config.strip_prefix('@').map(PathBuf::from);
+    match s3::run(&config, config_file).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("S3 endpoint on {:?} failed: {e:?}", config.root);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! SFTP subsystem serving a `DatenLord` namespace to `sshd` clients
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use datenlord::common::config::DatenLordConfig;
+use datenlord::gateway::sftp;
+
+/// Serve the directory of the `sftp` field of the config to the SFTP
+/// session on the standard input and output, as `sshd` runs subsystems
+#[derive(Debug, Parser)]
+#[command(name = "datenlord-sftp", version)]
+struct Cli {
+    /// SDK configuration as a JSON string, or the file holding it when it
+    /// starts with `@`, reloaded on `SIGHUP`
+    #[arg(long, default_value = "{}")]
+    config: String,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let config = match cli.config.strip_prefix('@') {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(config) => DatenLordConfig::parse(&config),
+            Err(e) => {
+                eprintln!("failed to read the config {path:?}: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => DatenLordConfig::parse(&cli.config),
+    };
+    let config_file = cli.config.strip_prefix('@').map(PathBuf::from);
+    match sftp::run(&config, config_file).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("SFTP session on {:?} failed: {e:?}", config.root);
+            ExitCode::FAILURE
+        }
+    }
+}
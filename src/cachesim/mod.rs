@@ -0,0 +1,314 @@
+//! Offline cache simulator replaying file access traces
+//!
+//! A trace is a JSON-lines file, one `TraceRecord` per line, for example
+//! `{"op":"read","ino":42,"offset":0,"len":4096}`. Every record touches the
+//! cache pages covering `offset..offset + len` of `ino`. Reads count towards
+//! the hit rate, writes only populate the cache.
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::BufRead;
+use std::str::FromStr;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::virtualfs::INum;
+
+/// The kind of a traced operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceOp {
+    /// A read, looked up in the cache
+    Read,
+    /// A write, inserted into the cache
+    Write,
+}
+
+/// One traced file access
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// The kind of the access
+    pub op: TraceOp,
+    /// The i-number of the accessed file
+    pub ino: INum,
+    /// The start offset of the access
+    pub offset: u64,
+    /// The length of the access
+    pub len: u64,
+}
+
+/// Read a JSON-lines trace
+pub fn read_trace<R: BufRead>(reader: R) -> DatenLordResult<Vec<TraceRecord>> {
+    let mut records = Vec::new();
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to read trace: {e}")],
+            source: None,
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|e| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid trace record at line {}: {e}", lineno + 1)],
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// The eviction policy of a simulated cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Evict the least recently used page
+    Lru,
+    /// Evict the earliest inserted page
+    Fifo,
+    /// Evict the least frequently used page, breaking ties by recency
+    Lfu,
+}
+
+impl FromStr for Policy {
+    type Err = DatenLordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lru" => Ok(Self::Lru),
+            "fifo" => Ok(Self::Fifo),
+            "lfu" => Ok(Self::Lfu),
+            _ => Err(DatenLordError::InvalidArgument {
+                context: vec![format!("unknown cache policy={s}, expect one of lru, fifo, lfu")],
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::Lru => "lru",
+            Self::Fifo => "fifo",
+            Self::Lfu => "lfu",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A cached page, identified by its file and page index
+type PageKey = (INum, u64);
+
+/// The bookkeeping of a simulated cache
+#[derive(Debug)]
+enum Cache {
+    /// Pages keyed by last access stamp
+    Lru {
+        /// The last access stamp of every cached page
+        stamps: HashMap<PageKey, u64>,
+        /// Cached pages ordered by last access
+        order: BTreeMap<u64, PageKey>,
+    },
+    /// Pages in insertion order
+    Fifo {
+        /// The cached pages
+        pages: HashSet<PageKey>,
+        /// Cached pages ordered by insertion
+        order: VecDeque<PageKey>,
+    },
+    /// Pages keyed by access count and last access stamp
+    Lfu {
+        /// The access count and last access stamp of every cached page
+        entries: HashMap<PageKey, (u64, u64)>,
+        /// Cached pages ordered by access count, then last access
+        order: BTreeMap<(u64, u64), PageKey>,
+    },
+}
+
+impl Cache {
+    /// New an empty cache with `policy`
+    fn new(policy: Policy) -> Self {
+        match policy {
+            Policy::Lru => Self::Lru {
+                stamps: HashMap::new(),
+                order: BTreeMap::new(),
+            },
+            Policy::Fifo => Self::Fifo {
+                pages: HashSet::new(),
+                order: VecDeque::new(),
+            },
+            Policy::Lfu => Self::Lfu {
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+            },
+        }
+    }
+
+    /// The number of cached pages
+    fn len(&self) -> usize {
+        match *self {
+            Self::Lru { ref stamps, .. } => stamps.len(),
+            Self::Fifo { ref pages, .. } => pages.len(),
+            Self::Lfu { ref entries, .. } => entries.len(),
+        }
+    }
+
+    /// Access `key` at time `now`, inserting it on a miss, return whether it hit
+    fn access(&mut self, key: PageKey, now: u64) -> bool {
+        match *self {
+            Self::Lru {
+                ref mut stamps,
+                ref mut order,
+            } => {
+                let hit = match stamps.insert(key, now) {
+                    Some(old) => order.remove(&old).is_some(),
+                    None => false,
+                };
+                order.insert(now, key);
+                hit
+            }
+            Self::Fifo {
+                ref mut pages,
+                ref mut order,
+            } => {
+                if pages.contains(&key) {
+                    true
+                } else {
+                    pages.insert(key);
+                    order.push_back(key);
+                    false
+                }
+            }
+            Self::Lfu {
+                ref mut entries,
+                ref mut order,
+            } => {
+                let (count, hit) = match entries.get(&key) {
+                    Some(&(count, stamp)) => {
+                        order.remove(&(count, stamp));
+                        (count + 1, true)
+                    }
+                    None => (1, false),
+                };
+                entries.insert(key, (count, now));
+                order.insert((count, now), key);
+                hit
+            }
+        }
+    }
+
+    /// Evict one page according to the policy
+    fn evict(&mut self) {
+        match *self {
+            Self::Lru {
+                ref mut stamps,
+                ref mut order,
+            } => {
+                if let Some((_, key)) = order.pop_first() {
+                    stamps.remove(&key);
+                }
+            }
+            Self::Fifo {
+                ref mut pages,
+                ref mut order,
+            } => {
+                if let Some(key) = order.pop_front() {
+                    pages.remove(&key);
+                }
+            }
+            Self::Lfu {
+                ref mut entries,
+                ref mut order,
+            } => {
+                if let Some((_, key)) = order.pop_first() {
+                    entries.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// The hypothetical outcome of replaying a trace against one cache configuration
+#[derive(Debug, Clone)]
+pub struct SimulationResult {
+    /// The eviction policy
+    pub policy: Policy,
+    /// The cache capacity in bytes
+    pub capacity: u64,
+    /// The number of page reads served from the cache
+    pub hits: u64,
+    /// The number of page reads missing the cache
+    pub misses: u64,
+}
+
+impl SimulationResult {
+    /// The fraction of page reads served from the cache
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+impl fmt::Display for SimulationResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<6} {:>14} {:>12} {:>12} {:>8.2}%",
+            self.policy,
+            self.capacity,
+            self.hits,
+            self.misses,
+            self.hit_rate() * 100.0
+        )
+    }
+}
+
+/// Replay `trace` against a cache of `capacity` bytes split into `page_size` pages
+pub fn simulate(
+    trace: &[TraceRecord],
+    policy: Policy,
+    capacity: u64,
+    page_size: u64,
+) -> DatenLordResult<SimulationResult> {
+    if page_size == 0 {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec!["page size must be positive".to_owned()],
+        });
+    }
+    let max_pages = usize::try_from(capacity / page_size).unwrap_or(usize::MAX);
+    let mut cache = Cache::new(policy);
+    let mut result = SimulationResult {
+        policy,
+        capacity,
+        hits: 0,
+        misses: 0,
+    };
+    let mut now = 0_u64;
+
+    for record in trace.iter().filter(|record| record.len > 0) {
+        let first = record.offset / page_size;
+        let last = (record.offset + record.len - 1) / page_size;
+        for page in first..=last {
+            now += 1;
+            if max_pages == 0 {
+                if record.op == TraceOp::Read {
+                    result.misses += 1;
+                }
+                continue;
+            }
+            let hit = cache.access((record.ino, page), now);
+            if record.op == TraceOp::Read {
+                if hit {
+                    result.hits += 1;
+                } else {
+                    result.misses += 1;
+                }
+            }
+            while cache.len() > max_pages {
+                cache.evict();
+            }
+        }
+    }
+    Ok(result)
+}
@@ -0,0 +1,244 @@
+//! Size-classed pool of aligned, reusable I/O buffers
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// Alignment of every pooled buffer, suitable for `O_DIRECT` I/O
+pub const BUFFER_ALIGNMENT: usize = 4096;
+/// Chunk size used when streaming file contents through pooled buffers
+pub const COPY_CHUNK_SIZE: usize = 1 << 20;
+/// The smallest size class, 4 KiB
+const MIN_CLASS_SHIFT: u32 = 12;
+/// The largest size class, 4 MiB, larger requests bypass the pool
+const MAX_CLASS_SHIFT: u32 = 22;
+/// Default number of idle buffers kept per size class
+const DEFAULT_MAX_CACHED_PER_CLASS: usize = 16;
+
+/// An owned, zero-initialized allocation aligned to `BUFFER_ALIGNMENT`
+///
+/// The allocation is over-sized by `BUFFER_ALIGNMENT` bytes and the buffer
+/// starts at its first aligned byte, which a boxed slice never moves.
+struct AlignedBuf {
+    /// The whole allocation
+    data: Box<[u8]>,
+    /// Offset of the first aligned byte in `data`
+    offset: usize,
+    /// Usable size of the buffer in bytes
+    capacity: usize,
+}
+
+impl AlignedBuf {
+    /// Allocate `capacity` zeroed bytes
+    fn new(capacity: usize) -> Self {
+        let data = vec![0; capacity + BUFFER_ALIGNMENT].into_boxed_slice();
+        let offset = data.as_ptr().align_offset(BUFFER_ALIGNMENT);
+        Self {
+            data,
+            offset,
+            capacity,
+        }
+    }
+
+    /// The whole buffer as a slice
+    fn as_slice(&self) -> &[u8] {
+        &self.data[self.offset..self.offset + self.capacity]
+    }
+
+    /// The whole buffer as a mutable slice
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.data[self.offset..self.offset + self.capacity]
+    }
+}
+
+/// Shared state of a `BufferPool`
+struct PoolInner {
+    /// Idle buffers, one free list per size class
+    classes: Vec<Mutex<Vec<AlignedBuf>>>,
+    /// Maximum number of idle buffers kept per size class
+    max_cached_per_class: usize,
+}
+
+impl PoolInner {
+    /// Return a buffer to its free list, dropping it if the list is full
+    fn recycle(&self, buf: AlignedBuf) {
+        if let Some(class) = class_of_capacity(buf.capacity) {
+            if let Ok(mut free) = self.classes[class].lock() {
+                if free.len() < self.max_cached_per_class {
+                    free.push(buf);
+                }
+            }
+        }
+    }
+}
+
+/// The size class index able to hold `size` bytes, `None` for oversized requests
+fn class_of_size(size: usize) -> Option<usize> {
+    let rounded = size.max(1 << MIN_CLASS_SHIFT).checked_next_power_of_two()?;
+    let shift = rounded.trailing_zeros();
+    (shift <= MAX_CLASS_SHIFT).then(|| (shift - MIN_CLASS_SHIFT) as usize)
+}
+
+/// The size class index of a buffer with exactly `capacity` bytes
+fn class_of_capacity(capacity: usize) -> Option<usize> {
+    if capacity.is_power_of_two() {
+        class_of_size(capacity).filter(|&class| class_capacity(class) == capacity)
+    } else {
+        None
+    }
+}
+
+/// The capacity of buffers in size class `class`
+fn class_capacity(class: usize) -> usize {
+    1 << (MIN_CLASS_SHIFT as usize + class)
+}
+
+/// A pool of aligned buffers grouped into power-of-two size classes
+///
+/// Cloning the pool is cheap and all clones share the same free lists.
+/// Buffers hold a reference to the pool and return to it when dropped, so
+/// they may safely outlive the handle they were acquired from.
+#[derive(Clone)]
+pub struct BufferPool {
+    /// The shared free lists
+    inner: Arc<PoolInner>,
+}
+
+impl std::fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("max_cached_per_class", &self.inner.max_cached_per_class)
+            .finish()
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::with_max_cached(DEFAULT_MAX_CACHED_PER_CLASS)
+    }
+}
+
+impl BufferPool {
+    /// New a `BufferPool` with the default per-class cache limit
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// New a `BufferPool` keeping at most `max_cached_per_class` idle buffers per size class
+    pub fn with_max_cached(max_cached_per_class: usize) -> Self {
+        let classes = (MIN_CLASS_SHIFT..=MAX_CLASS_SHIFT)
+            .map(|_| Mutex::new(Vec::new()))
+            .collect();
+        Self {
+            inner: Arc::new(PoolInner {
+                classes,
+                max_cached_per_class,
+            }),
+        }
+    }
+
+    /// Acquire a buffer of `size` bytes
+    ///
+    /// The buffer is taken from the matching size class when one is idle,
+    /// otherwise it is freshly allocated. Requests above the largest size
+    /// class are served by a dedicated allocation that is not recycled.
+    pub fn acquire(&self, size: usize) -> PooledBuffer {
+        let buf = match class_of_size(size) {
+            Some(class) => self.inner.classes[class]
+                .lock()
+                .ok()
+                .and_then(|mut free| free.pop())
+                .unwrap_or_else(|| AlignedBuf::new(class_capacity(class))),
+            None => AlignedBuf::new(size),
+        };
+        PooledBuffer {
+            buf: Some(buf),
+            len: size,
+            pool: Arc::clone(&self.inner),
+        }
+    }
+
+    /// The number of idle buffers currently cached across all size classes
+    pub fn idle_count(&self) -> usize {
+        self.inner
+            .classes
+            .iter()
+            .filter_map(|class| class.lock().ok().map(|free| free.len()))
+            .sum()
+    }
+}
+
+/// A buffer borrowed from a `BufferPool`, returned to the pool on drop
+pub struct PooledBuffer {
+    /// The underlying allocation, only `None` while being dropped
+    buf: Option<AlignedBuf>,
+    /// The number of bytes exposed through `Deref`
+    len: usize,
+    /// The pool to return the allocation to
+    pool: Arc<PoolInner>,
+}
+
+impl std::fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PooledBuffer")
+            .field("len", &self.len)
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl PooledBuffer {
+    /// The size of the underlying allocation
+    pub fn capacity(&self) -> usize {
+        self.buf.as_ref().map_or(0, |buf| buf.capacity)
+    }
+
+    /// Shrink or grow the visible length, bounded by the capacity
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.min(self.capacity());
+    }
+
+    /// Pointer to the first byte, aligned to `BUFFER_ALIGNMENT`
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.as_mut().as_mut_ptr()
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.buf {
+            Some(ref buf) => &buf.as_slice()[..self.len],
+            None => &[],
+        }
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let len = self.len;
+        match self.buf {
+            Some(ref mut buf) => &mut buf.as_mut_slice()[..len],
+            None => &mut [],
+        }
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for PooledBuffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.pool.recycle(buf);
+        }
+    }
+}
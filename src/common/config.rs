@@ -0,0 +1,281 @@
+//! SDK configuration passed to `init`
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::common::logging::TracingConfig;
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::gateway::{NfsConfig, S3Config, SftpConfig};
+use crate::lifecycle::LifecycleConfig;
+use crate::storage::faulty::FaultConfig;
+use crate::storage::filter::ListingFilter;
+use crate::storage::gc::GcConfig;
+use crate::storage::fs_util::{AtimePolicy, NameConfig, RequestContext};
+use crate::storage::idmap::IdMapConfig;
+use crate::storage::layout::BlockLayout;
+use crate::storage::mount::MountConfig;
+use crate::storage::notify::SinkConfig;
+use crate::storage::packing::PackingConfig;
+use crate::storage::replication::ReplicationConfig;
+use crate::storage::striping::StripingConfig;
+use crate::storage::retry::RetryPolicy;
+use crate::storage::superblock::Feature;
+use crate::storage::audit::AuditConfig;
+use crate::storage::backpressure::BackpressureConfig;
+use crate::storage::trash::TrashConfig;
+use crate::storage::transfer::CopyConfig;
+use crate::storage::versioning::VersioningConfig;
+use crate::storage::writeback::WritebackConfig;
+
+/// Default root directory of the local filesystem backend
+const DEFAULT_ROOT: &str = "/tmp";
+/// Default number of cached entries and attributes
+const DEFAULT_ATTR_CACHE_CAPACITY: usize = 65536;
+
+/// `DatenLord` SDK configuration, deserialized from a JSON string
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatenLordConfig {
+    /// Root directory of the local filesystem backend
+    pub root: PathBuf,
+    /// Paths, relative to `root`, whose writes are always synchronous as if
+    /// the files were opened with `O_SYNC`
+    pub sync_write_paths: Vec<PathBuf>,
+    /// Optional features to create a new namespace with, ignored when the
+    /// root already holds a namespace
+    pub features: Vec<Feature>,
+    /// Default timeout of every filesystem operation in milliseconds,
+    /// operations never time out when unset
+    pub op_timeout_ms: Option<u64>,
+    /// How operations failing with a transient error are retried
+    pub retry: RetryPolicy,
+    /// The number of entries, and of attributes, cached for the TTL the
+    /// backend returns with them, 0 disables the cache
+    pub attr_cache_capacity: usize,
+    /// The level of the log written to the standard error, e.g. `info`,
+    /// nothing is logged unless set or the application logs itself
+    pub log_level: Option<String>,
+    /// Where the spans of the filesystem operations are exported, nowhere
+    /// by default
+    pub tracing: TracingConfig,
+    /// The entries left out of directory listings and walks
+    pub listing_filter: ListingFilter,
+    /// Where the changes to the namespace are reported
+    pub notify_sinks: Vec<SinkConfig>,
+    /// The directory of the search index kept by the SDKs, requires the
+    /// `search` feature
+    pub search_index: Option<PathBuf>,
+    /// Who the SDK acts on behalf of, the calling process by default
+    pub caller: CallerConfig,
+    /// The lifecycle rules the SDKs evaluate in the background
+    pub lifecycle: LifecycleConfig,
+    /// Files, relative to `root`, the SDKs open read-only when they start so
+    /// their first read skips the lookup and open, see `CacheFs::warm`
+    pub warm_files: Vec<String>,
+    /// When the SDKs sync the data written through open files in the
+    /// background
+    pub writeback: WritebackConfig,
+    /// Whether the SDKs keep the former contents of overwritten files as
+    /// versions, and how many
+    pub versioning: VersioningConfig,
+    /// Whether the SDKs move removed entries to a trash they are restored
+    /// from, and how long they stay there
+    pub trash: TrashConfig,
+    /// Whether the SDKs remove the backend data no file refers to in the
+    /// background, and how often
+    pub gc: GcConfig,
+    /// Whether the SDKs record every mutating operation, who made it and
+    /// how it ended in an audit log, and where
+    pub audit: AuditConfig,
+    /// How many bytes the writes in flight may carry and how many writes may
+    /// wait for room, or fail right away, unlimited by default
+    pub backpressure: BackpressureConfig,
+    /// How the ids of the caller are squashed and translated before the
+    /// backend checks permissions and assigns ownership, unchanged by
+    /// default
+    pub idmap: IdMapConfig,
+    /// How many ranges of a large local file the SDKs copy at once, and
+    /// how large they are
+    pub copy: CopyConfig,
+    /// The block, stripe and alignment sizes of the local backend, which the
+    /// copies and streamed reads of the SDKs follow
+    pub layout: BlockLayout,
+    /// The secondary backends the data is mirrored to in the background,
+    /// none by default
+    pub replication: ReplicationConfig,
+    /// The directories file data is erasure coded across, none by default
+    pub striping: StripingConfig,
+    /// Which files are packed into segments in namespaces with the
+    /// `packing` feature, those of up to 4 KiB by default
+    pub packing: PackingConfig,
+    /// Failures injected into the calls to the backend, to test how
+    /// applications handle them, none by default
+    pub faults: FaultConfig,
+    /// Free bytes kept in reserve on the filesystem of `root`, writes that
+    /// would leave less fail with `DatenLordError::NoSpace`, 0 disables the
+    /// reserve
+    pub reserved_space_bytes: u64,
+    /// The names of entries the local filesystem backend accepts
+    pub names: NameConfig,
+    /// When reads update the access time of files: `noatime`, `relatime`,
+    /// the default, or `strictatime`
+    pub atime: AtimePolicy,
+    /// The backends mounted under directories of the namespace, the
+    /// others being in `root`, none by default
+    pub mounts: Vec<MountConfig>,
+    /// The exports of the NFS gateway, `datenlord-nfs`
+    pub nfs: NfsConfig,
+    /// The directory the SFTP subsystem serves, `datenlord-sftp`
+    pub sftp: SftpConfig,
+    /// The S3-compatible endpoint, `datenlord-s3`
+    pub s3: S3Config,
+}
+
+/// Overrides of the `RequestContext` the SDK passes to every operation
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CallerConfig {
+    /// User id, the effective user id of the process when unset
+    pub uid: Option<u32>,
+    /// Group id, the effective group id of the process when unset
+    pub gid: Option<u32>,
+    /// Umask applied to created files, the umask of the process when unset
+    pub umask: Option<u32>,
+}
+
+/// The config values an open SDK changes while running, see
+/// `sdk::update_config`, those left unset keep their value
+///
+/// Other fields are rejected rather than ignored, as they only take effect
+/// when the SDK is opened.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigUpdate {
+    /// The new `attr_cache_capacity`, dropping everything cached
+    pub attr_cache_capacity: Option<usize>,
+    /// The new `op_timeout_ms` of the calls starting from now, 0 removes
+    /// the timeout
+    pub op_timeout_ms: Option<u64>,
+    /// The new `retry` of the calls starting from now
+    pub retry: Option<RetryPolicy>,
+    /// The new `log_level`
+    pub log_level: Option<String>,
+}
+
+impl ConfigUpdate {
+    /// Parse the JSON object `update`, failing with
+    /// `DatenLordError::InvalidArgument` for invalid JSON and fields that
+    /// cannot change while running
+    pub fn parse(update: &str) -> DatenLordResult<Self> {
+        serde_json::from_str(update).map_err(|e| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid config update {update:?}: {e}")],
+        })
+    }
+
+    /// The update setting every value that can change while running to
+    /// its value in `config`
+    pub fn from_config(config: &DatenLordConfig) -> Self {
+        Self {
+            attr_cache_capacity: Some(config.attr_cache_capacity),
+            op_timeout_ms: Some(config.op_timeout_ms.unwrap_or(0)),
+            retry: Some(config.retry.clone()),
+            log_level: config.log_level.clone(),
+        }
+    }
+
+    /// Record the update in `config`
+    pub fn apply(&self, config: &mut DatenLordConfig) {
+        if let Some(capacity) = self.attr_cache_capacity {
+            config.attr_cache_capacity = capacity;
+        }
+        if let Some(timeout_ms) = self.op_timeout_ms {
+            config.op_timeout_ms = (timeout_ms > 0).then_some(timeout_ms);
+        }
+        if let Some(ref retry) = self.retry {
+            config.retry = retry.clone();
+        }
+        if let Some(ref level) = self.log_level {
+            config.log_level = Some(level.clone());
+        }
+    }
+}
+
+impl Default for DatenLordConfig {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from(DEFAULT_ROOT),
+            sync_write_paths: Vec::new(),
+            features: Vec::new(),
+            op_timeout_ms: None,
+            retry: RetryPolicy::default(),
+            attr_cache_capacity: DEFAULT_ATTR_CACHE_CAPACITY,
+            log_level: None,
+            tracing: TracingConfig::default(),
+            listing_filter: ListingFilter::default(),
+            notify_sinks: Vec::new(),
+            search_index: None,
+            caller: CallerConfig::default(),
+            lifecycle: LifecycleConfig::default(),
+            warm_files: Vec::new(),
+            writeback: WritebackConfig::default(),
+            versioning: VersioningConfig::default(),
+            trash: TrashConfig::default(),
+            gc: GcConfig::default(),
+            audit: AuditConfig::default(),
+            backpressure: BackpressureConfig::default(),
+            idmap: IdMapConfig::default(),
+            copy: CopyConfig::default(),
+            layout: BlockLayout::default(),
+            replication: ReplicationConfig::default(),
+            striping: StripingConfig::default(),
+            packing: PackingConfig::default(),
+            faults: FaultConfig::default(),
+            reserved_space_bytes: 0,
+            names: NameConfig::default(),
+            atime: AtimePolicy::default(),
+            mounts: Vec::new(),
+            nfs: NfsConfig::default(),
+            sftp: SftpConfig::default(),
+            s3: S3Config::default(),
+        }
+    }
+}
+
+impl DatenLordConfig {
+    /// Parse the config string handed to the SDK
+    ///
+    /// Strings that are not valid JSON fall back to the default config so
+    /// the demo programs passing a placeholder keep working.
+    pub fn parse(config: &str) -> Self {
+        serde_json::from_str(config).unwrap_or_else(|e| {
+            warn!("failed to parse config={config:?}, use default config: {e}");
+            Self::default()
+        })
+    }
+
+    /// The default timeout of every filesystem operation
+    pub fn op_timeout(&self) -> Option<Duration> {
+        self.op_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// The context operations of the SDK run with, `caller` applied before
+    /// `idmap`
+    pub fn request_context(&self) -> RequestContext {
+        let current = RequestContext::current();
+        self.idmap.map(RequestContext {
+            uid: self.caller.uid.unwrap_or(current.uid),
+            gid: self.caller.gid.unwrap_or(current.gid),
+            umask: self.caller.umask.unwrap_or(current.umask),
+            ..current
+        })
+    }
+
+    /// Whether writes to `path`, relative to `root`, must be synchronous
+    pub fn is_sync_write_path(&self, path: &Path) -> bool {
+        self.sync_write_paths
+            .iter()
+            .any(|prefix| path.starts_with(prefix))
+    }
+}
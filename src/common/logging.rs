@@ -0,0 +1,225 @@
+//! The log of the process written to the standard error, at a level
+//! changed while running, and the spans of the filesystem operations
+//! exported to an OTLP collector with the `otel` feature
+//!
+//! The subscriber is installed the first time a level is set or spans are
+//! exported, unless the application installed one of its own, which is
+//! left alone. Whether spans are exported is settled when it is installed.
+use std::sync::OnceLock;
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::Span;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::reload::{self, Handle};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, Registry};
+
+use super::{DatenLordError, DatenLordResult};
+
+/// The name the spans are reported under by default
+const DEFAULT_SERVICE_NAME: &str = "datenlord";
+
+/// The level of the subscriber installed, `None` when the application
+/// installed one first
+static LEVEL: OnceLock<Option<Handle<LevelFilter, Registry>>> = OnceLock::new();
+
+/// Where the spans of the filesystem operations are exported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TracingConfig {
+    /// The OTLP/HTTP endpoint of the collector, e.g.
+    /// `http://localhost:4318/v1/traces`, nothing is exported when unset;
+    /// requires the `otel` feature
+    pub otlp_endpoint: Option<String>,
+    /// The `service.name` of the spans
+    pub service_name: String,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: DEFAULT_SERVICE_NAME.to_owned(),
+        }
+    }
+}
+
+/// The level named `level`, one of `off`, `error`, `warn`, `info`, `debug`
+/// and `trace`
+pub fn parse_level(level: &str) -> DatenLordResult<LevelFilter> {
+    level.parse().map_err(|_| DatenLordError::InvalidArgument {
+        context: vec![format!("unknown log level {level:?}")],
+    })
+}
+
+/// Log at `level` when set and export spans as `tracing` says, installing
+/// the subscriber unless neither asks for it
+///
+/// Fails with `DatenLordError::Unimplemented` when spans cannot be exported,
+/// because the subscriber was installed without it, by the application or
+/// by an earlier call, or the `otel` feature is off.
+pub fn init(level: Option<&str>, tracing: &TracingConfig) -> DatenLordResult<()> {
+    let filter = level.map(parse_level).transpose()?;
+    if let Some(ref endpoint) = tracing.otlp_endpoint {
+        if !cfg!(feature = "otel") {
+            return Err(DatenLordError::Unimplemented {
+                context: vec![format!(
+                    "cannot export spans to {endpoint}, built without the otel feature"
+                )],
+                source: None,
+            });
+        }
+        let mut installed = false;
+        let handle = LEVEL.get_or_init(|| {
+            installed = true;
+            install(filter.unwrap_or(LevelFilter::OFF), tracing)
+        });
+        if !installed || handle.is_none() {
+            return Err(DatenLordError::Unimplemented {
+                context: vec![format!(
+                    "cannot export spans to {endpoint}, the log subscriber is installed already"
+                )],
+                source: None,
+            });
+        }
+    }
+    match level {
+        Some(level) => set_level(level),
+        None => Ok(()),
+    }
+}
+
+/// Log the events at `level` and above from now on
+///
+/// Fails with `DatenLordError::Unimplemented` when the application
+/// installed a subscriber of its own.
+pub fn set_level(level: &str) -> DatenLordResult<()> {
+    let filter = parse_level(level)?;
+    let handle = LEVEL.get_or_init(|| install(filter, &TracingConfig::default()));
+    let Some(handle) = handle else {
+        return Err(DatenLordError::Unimplemented {
+            context: vec!["the application installed a log subscriber of its own".to_owned()],
+            source: None,
+        });
+    };
+    handle.reload(filter).map_err(|e| DatenLordError::Internal {
+        context: vec![format!("failed to set the log level to {level}: {e}")],
+    })
+}
+
+/// Make `span` a child of the remote span of the W3C trace context headers
+/// `traceparent` and `tracestate`, e.g. of a request to a gateway
+///
+/// Does nothing without the `otel` feature or a valid `traceparent`.
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+pub fn continue_trace(span: &Span, traceparent: Option<&str>, tracestate: Option<&str>) {
+    #[cfg(feature = "otel")]
+    otel::continue_trace(span, traceparent, tracestate);
+}
+
+/// Export the spans ended so far, e.g. when the process is about to exit
+pub fn flush_traces() {
+    #[cfg(feature = "otel")]
+    otel::flush();
+}
+
+/// Install the subscriber logging at `filter` and exporting spans as
+/// `tracing` says, `None` when the application installed one first
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+fn install(filter: LevelFilter, tracing: &TracingConfig) -> Option<Handle<LevelFilter, Registry>> {
+    let (filter, handle) = reload::Layer::new(filter);
+    let subscriber = tracing_subscriber::registry()
+        .with(fmt::layer().with_writer(std::io::stderr).with_filter(filter));
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(otel::layer(tracing));
+    subscriber.try_init().ok().map(|()| handle)
+}
+
+/// The export of the spans to an OTLP collector
+#[cfg(feature = "otel")]
+mod otel {
+    use std::collections::HashMap;
+    use std::sync::OnceLock;
+
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+    use opentelemetry_sdk::Resource;
+    use tracing::{warn, Level, Span, Subscriber};
+    use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+    use tracing_subscriber::filter::{Filtered, Targets};
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    use super::TracingConfig;
+
+    /// The provider of the spans exported, flushed by `flush`
+    static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+    /// The layer exporting the spans of datenlord and of the object stores
+    /// as `config` says, `None` without an endpoint or when the exporter
+    /// fails to build
+    pub(super) fn layer<S>(
+        config: &TracingConfig,
+    ) -> Option<Filtered<OpenTelemetryLayer<S, SdkTracer>, Targets, S>>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let endpoint = config.otlp_endpoint.as_ref()?;
+        let exporter = match SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+        {
+            Ok(exporter) => exporter,
+            Err(e) => {
+                warn!("failed to export spans to {endpoint}: {e}");
+                return None;
+            }
+        };
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(config.service_name.clone())
+                    .build(),
+            )
+            .build();
+        let tracer = provider.tracer("datenlord");
+        let _ = PROVIDER.set(provider);
+        // The spans of the exporter itself, from its HTTP client, are left
+        // out so exporting never feeds itself
+        let targets = Targets::new()
+            .with_target("datenlord", Level::INFO)
+            .with_target("opendal", Level::DEBUG);
+        Some(tracing_opentelemetry::layer().with_tracer(tracer).with_filter(targets))
+    }
+
+    /// See `logging::continue_trace`
+    pub(super) fn continue_trace(span: &Span, traceparent: Option<&str>, tracestate: Option<&str>) {
+        let Some(traceparent) = traceparent else {
+            return;
+        };
+        let mut headers = HashMap::new();
+        headers.insert("traceparent".to_owned(), traceparent.to_owned());
+        if let Some(tracestate) = tracestate {
+            headers.insert("tracestate".to_owned(), tracestate.to_owned());
+        }
+        let parent = TraceContextPropagator::new().extract(&headers);
+        if let Err(e) = span.set_parent(parent) {
+            warn!("failed to continue trace {traceparent}: {e}");
+        }
+    }
+
+    /// See `logging::flush_traces`
+    pub(super) fn flush() {
+        if let Some(provider) = PROVIDER.get() {
+            if let Err(e) = provider.force_flush() {
+                warn!("failed to export spans: {e}");
+            }
+        }
+    }
+}
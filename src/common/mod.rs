@@ -1,23 +1,241 @@
+//! Common types shared by the storage layer and the SDKs
+#![forbid(unsafe_code)]
+
+pub mod buffer_pool;
+pub mod config;
+pub mod logging;
+
+use std::io::ErrorKind;
+
+use nix::errno::Errno;
 use thiserror::Error;
 
 /// `DatenLord` Result type
 pub type DatenLordResult<T> = Result<T, DatenLordError>;
+
+/// The error a `DatenLordError` was caused by, such as an `io::Error` or a
+/// `nix::Errno`
+pub type ErrorSource = Box<dyn std::error::Error + Send + Sync>;
+
 /// `DatenLord` error code
+///
+/// `context` lists what was being done, the outermost first, and the
+/// variants OS and backend errors map to keep that error as their `source`.
 #[derive(Error, Debug)]
 pub enum DatenLordError {
     /// Unimplemented error
     #[error("Unimplemented: {context:?}")]
-    Unimplemented { context: Vec<String> },
+    Unimplemented {
+        context: Vec<String>,
+        #[source]
+        source: Option<ErrorSource>,
+    },
     /// Invalid argument error
     #[error("Invalid argument: {context:?}")]
     InvalidArgument { context: Vec<String> },
+    /// A file name the backend does not accept
+    #[error("Invalid name: {context:?}")]
+    InvalidName { context: Vec<String> },
+    /// A path or inode that does not exist
+    #[error("Not found: {context:?}")]
+    NotFound {
+        context: Vec<String>,
+        #[source]
+        source: Option<ErrorSource>,
+    },
     /// Internal error
     #[error("Internal error: {context:?}")]
     Internal { context: Vec<String> },
     /// I/O error
     #[error("I/O error: {context:?}")]
-    Io { context: Vec<String> },
+    Io {
+        context: Vec<String>,
+        #[source]
+        source: Option<ErrorSource>,
+    },
+    /// The target of the operation already exists
+    #[error("Already exists: {context:?}")]
+    AlreadyExists {
+        context: Vec<String>,
+        #[source]
+        source: Option<ErrorSource>,
+    },
+    /// The caller lacks the permission for the operation
+    #[error("Permission denied: {context:?}")]
+    PermissionDenied {
+        context: Vec<String>,
+        #[source]
+        source: Option<ErrorSource>,
+    },
+    /// Operation timed out
+    #[error("Timeout: {context:?}")]
+    Timeout { context: Vec<String> },
+    /// The operation was interrupted before it finished
+    #[error("Interrupted: {context:?}")]
+    Interrupted { context: Vec<String> },
+    /// Backend temporarily unavailable, the operation may succeed if retried
+    #[error("Unavailable: {context:?}")]
+    Unavailable {
+        context: Vec<String>,
+        #[source]
+        source: Option<ErrorSource>,
+    },
+    /// The backend has no space left for the data
+    #[error("No space: {context:?}")]
+    NoSpace {
+        context: Vec<String>,
+        #[source]
+        source: Option<ErrorSource>,
+    },
+    /// The data does not match the digest it was expected to have
+    #[error("Corrupted: {context:?}")]
+    Corrupted { context: Vec<String> },
+    /// The SDK was shut down and takes no more calls
+    #[error("Shut down: {context:?}")]
+    ShutDown { context: Vec<String> },
     /// Other error
     #[error("Other error: {context:?}")]
     Other { context: Vec<String> },
-}
\ No newline at end of file
+}
+
+impl DatenLordError {
+    /// The error for an OS error of `kind` described by `message`
+    fn os(kind: ErrorKind, message: String, source: ErrorSource) -> Self {
+        let (context, source) = (vec![message], Some(source));
+        match kind {
+            ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ResourceBusy => Self::Unavailable { context, source },
+            ErrorKind::NotFound => Self::NotFound { context, source },
+            ErrorKind::AlreadyExists => Self::AlreadyExists { context, source },
+            ErrorKind::PermissionDenied => Self::PermissionDenied { context, source },
+            ErrorKind::StorageFull => Self::NoSpace { context, source },
+            _ => Self::Io { context, source },
+        }
+    }
+
+    /// The contexts of the error, the outermost first
+    fn context_mut(&mut self) -> &mut Vec<String> {
+        match *self {
+            Self::Unimplemented { ref mut context, .. }
+            | Self::InvalidArgument { ref mut context }
+            | Self::InvalidName { ref mut context }
+            | Self::NotFound { ref mut context, .. }
+            | Self::Internal { ref mut context }
+            | Self::Io { ref mut context, .. }
+            | Self::AlreadyExists { ref mut context, .. }
+            | Self::PermissionDenied { ref mut context, .. }
+            | Self::Timeout { ref mut context }
+            | Self::Interrupted { ref mut context }
+            | Self::Unavailable { ref mut context, .. }
+            | Self::NoSpace { ref mut context, .. }
+            | Self::Corrupted { ref mut context }
+            | Self::ShutDown { ref mut context }
+            | Self::Other { ref mut context } => context,
+        }
+    }
+
+    /// Add `context`, what was being done when the error happened, in front
+    /// of its contexts
+    #[must_use]
+    pub fn add_context(mut self, context: impl Into<String>) -> Self {
+        self.context_mut().insert(0, context.into());
+        self
+    }
+
+    /// Whether the error is transient, so retrying the operation may succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(*self, Self::Timeout { .. } | Self::Unavailable { .. })
+    }
+
+    /// The errno reporting the error to C and python callers, `None` when
+    /// no specific one applies
+    pub fn errno(&self) -> Option<Errno> {
+        match *self {
+            Self::Unimplemented { .. } => Some(Errno::ENOTSUP),
+            Self::InvalidArgument { .. } | Self::InvalidName { .. } => Some(Errno::EINVAL),
+            Self::NotFound { .. } => Some(Errno::ENOENT),
+            Self::AlreadyExists { .. } => Some(Errno::EEXIST),
+            Self::PermissionDenied { .. } => Some(Errno::EACCES),
+            Self::Timeout { .. } => Some(Errno::ETIMEDOUT),
+            Self::Interrupted { .. } => Some(Errno::EINTR),
+            Self::Unavailable { .. } => Some(Errno::EAGAIN),
+            Self::NoSpace { .. } => Some(Errno::ENOSPC),
+            Self::Corrupted { .. } => Some(Errno::EBADMSG),
+            Self::ShutDown { .. } => Some(Errno::ESHUTDOWN),
+            Self::Io { ref source, .. } => source.as_deref().and_then(os_errno),
+            Self::Internal { .. } | Self::Other { .. } => None,
+        }
+    }
+}
+
+/// The errno of `source` if it is an OS error
+fn os_errno(source: &(dyn std::error::Error + Send + Sync + 'static)) -> Option<Errno> {
+    match source.downcast_ref::<Errno>() {
+        Some(&errno) => Some(errno),
+        None => Some(Errno::from_raw(source.downcast_ref::<std::io::Error>()?.raw_os_error()?)),
+    }
+}
+
+/// Adds context to the error of a result, turned into a `DatenLordError`,
+/// so `io::Error` and `nix::Errno` results are propagated with `?`
+pub trait ResultExt<T> {
+    /// Add `context` to the error, see `DatenLordError::add_context`
+    fn add_context(self, context: impl Into<String>) -> DatenLordResult<T>;
+
+    /// Add the context `context` builds to the error, built only on errors
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> DatenLordResult<T>;
+}
+
+impl<T, E: Into<DatenLordError>> ResultExt<T> for Result<T, E> {
+    fn add_context(self, context: impl Into<String>) -> DatenLordResult<T> {
+        self.map_err(|e| e.into().add_context(context))
+    }
+
+    fn with_context<C: Into<String>>(self, context: impl FnOnce() -> C) -> DatenLordResult<T> {
+        self.map_err(|e| e.into().add_context(context()))
+    }
+}
+
+impl From<std::io::Error> for DatenLordError {
+    fn from(err: std::io::Error) -> Self {
+        // Unwrap the errors `From<DatenLordError>` wrapped
+        if err.get_ref().is_some_and(|inner| inner.is::<Self>()) {
+            if let Ok(inner) = err.into_inner().unwrap().downcast::<Self>() {
+                return *inner;
+            }
+            unreachable!("the inner error is a DatenLordError");
+        }
+        Self::os(err.kind(), err.to_string(), Box::new(err))
+    }
+}
+
+impl From<Errno> for DatenLordError {
+    fn from(errno: Errno) -> Self {
+        let kind = std::io::Error::from(errno).kind();
+        Self::os(kind, errno.to_string(), Box::new(errno))
+    }
+}
+impl From<DatenLordError> for std::io::Error {
+    fn from(err: DatenLordError) -> Self {
+        let kind = match err {
+            DatenLordError::Unimplemented { .. } => ErrorKind::Unsupported,
+            DatenLordError::InvalidArgument { .. } => ErrorKind::InvalidInput,
+            DatenLordError::InvalidName { .. } => ErrorKind::InvalidFilename,
+            DatenLordError::NotFound { .. } => ErrorKind::NotFound,
+            DatenLordError::AlreadyExists { .. } => ErrorKind::AlreadyExists,
+            DatenLordError::PermissionDenied { .. } => ErrorKind::PermissionDenied,
+            DatenLordError::Timeout { .. } => ErrorKind::TimedOut,
+            DatenLordError::Interrupted { .. } => ErrorKind::Interrupted,
+            DatenLordError::ShutDown { .. } => ErrorKind::NotConnected,
+            DatenLordError::NoSpace { .. } => ErrorKind::StorageFull,
+            DatenLordError::Corrupted { .. } => ErrorKind::InvalidData,
+            DatenLordError::Internal { .. }
+            | DatenLordError::Io { .. }
+            | DatenLordError::Unavailable { .. }
+            | DatenLordError::Other { .. } => ErrorKind::Other,
+        };
+        Self::new(kind, err)
+    }
+}
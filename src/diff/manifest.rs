@@ -0,0 +1,250 @@
+//! Verification of a tree against a manifest listing the paths, sizes and
+//! digests of the files it should hold, e.g. the one published with a data
+//! release
+//!
+//! A manifest is JSON, its digests written like those of `storage::digest`:
+//!
+//! ```json
+//! {"files": [{"path": "data/part-0.csv", "size": 1024, "digest": "sha256:9f86d0..."}]}
+//! ```
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::sync::Arc;
+
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::digest::{self, DigestAlgorithm};
+use crate::storage::fs_util::{self, RequestContext};
+use crate::storage::timeout;
+use crate::storage::virtualfs::{INum, VirtualFs};
+use crate::storage::walk::DEFAULT_WALK_CONCURRENCY;
+
+use super::list_tree;
+
+/// A file a manifest lists
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The path relative to the root of the tree
+    pub path: String,
+    /// The size in bytes, not checked when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The `<algorithm>:<hex>` digest of the contents, not checked when
+    /// missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+/// The files a tree should hold
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Every file, in any order
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// The manifest of the JSON `json`, failing with
+    /// `DatenLordError::InvalidArgument` if it is malformed, lists a path
+    /// twice or has a digest of an unknown algorithm
+    pub fn parse(json: &str) -> DatenLordResult<Self> {
+        let manifest: Self = serde_json::from_str(json).map_err(|e| DatenLordError::InvalidArgument {
+            context: vec![format!("malformed manifest: {e}")],
+        })?;
+        let mut paths = BTreeMap::new();
+        for entry in &manifest.files {
+            if let Some(ref digest) = entry.digest {
+                digest::algorithm_of(digest)?;
+            }
+            let path = fs_util::normalize(OsStr::new(&entry.path));
+            if paths.insert(path, ()).is_some() {
+                return Err(DatenLordError::InvalidArgument {
+                    context: vec![format!("manifest lists {:?} twice", entry.path)],
+                });
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// How a path of the tree differs from the manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    /// Listed but not in the tree
+    Missing,
+    /// In the tree but not listed
+    Unexpected,
+    /// Listed but not a regular file in the tree
+    NotAFile,
+    /// Of another size than listed
+    SizeMismatch,
+    /// With contents of another digest than listed
+    DigestMismatch,
+}
+
+/// A path of the tree differing from the manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Problem {
+    /// The path relative to the root of the tree
+    pub path: String,
+    /// How the path differs
+    pub kind: ProblemKind,
+    /// The size or digest listed, `None` unless one of them mismatches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    /// The size or digest found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<String>,
+}
+
+/// The outcome of a verification
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyReport {
+    /// The files of the manifest checked
+    pub checked: u64,
+    /// The bytes of the files whose digests were computed
+    pub bytes_hashed: u64,
+    /// Every path differing from the manifest, sorted by path
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    /// Whether the tree matches the manifest
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Parameters of a verification
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// Accept files of the tree the manifest does not list
+    pub allow_unexpected: bool,
+    /// The number of directories listed, or files hashed, at once
+    pub concurrency: usize,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            allow_unexpected: false,
+            concurrency: DEFAULT_WALK_CONCURRENCY,
+        }
+    }
+}
+
+/// The digests of every `(ino, algorithm)` of `files`, at most
+/// `concurrency` files at once, computed rather than taken from their cache
+async fn file_digests<F: VirtualFs + 'static>(
+    fs: &Arc<F>,
+    ctx: RequestContext,
+    files: Vec<(INum, DigestAlgorithm)>,
+    concurrency: usize,
+) -> DatenLordResult<Vec<String>> {
+    let deadline = timeout::deadline();
+    let mut digests = vec![String::new(); files.len()];
+    let mut pending = files.into_iter().enumerate();
+    let mut running = JoinSet::new();
+    loop {
+        while running.len() < concurrency.max(1) {
+            let Some((index, (ino, algorithm))) = pending.next() else {
+                break;
+            };
+            let fs = Arc::clone(fs);
+            running.spawn(async move {
+                let hash = digest::file_digest(&*fs, &ctx, ino, algorithm, false);
+                let result = match deadline {
+                    Some(deadline) => timeout::with_deadline(deadline, hash).await,
+                    None => hash.await,
+                };
+                (index, result)
+            });
+        }
+        let Some(done) = running.join_next().await else {
+            return Ok(digests);
+        };
+        let (index, result) = done.map_err(|e| DatenLordError::Internal {
+            context: vec![format!("digest task panicked: {e}")],
+        })?;
+        digests[index] = result?;
+    }
+}
+
+/// Check the tree below `path` of `fs` against `manifest` on behalf of `ctx`
+///
+/// Every listed file must be a regular file of the listed size and digest,
+/// and, unless `options.allow_unexpected`, every regular file or symbolic
+/// link of the tree must be listed; directories need not be. Digests are
+/// only computed for files of the right size. Differences are reported,
+/// errors reading the tree fail the verification.
+pub async fn verify<F: VirtualFs + 'static>(
+    fs: Arc<F>,
+    path: &str,
+    manifest: &Manifest,
+    ctx: RequestContext,
+    options: &VerifyOptions,
+) -> DatenLordResult<VerifyReport> {
+    let mut entries = list_tree(Arc::clone(&fs), ctx, path, options.concurrency).await?;
+    let problem = |path: &str, kind, expected, actual| Problem {
+        path: path.to_owned(),
+        kind,
+        expected,
+        actual,
+    };
+    let mut report = VerifyReport::default();
+    // Files of the right size, checked by digest
+    let mut hashed = Vec::new();
+    for entry in &manifest.files {
+        report.checked += 1;
+        let Some(attr) = entries.remove(&fs_util::normalize(OsStr::new(&entry.path))) else {
+            report.problems.push(problem(&entry.path, ProblemKind::Missing, None, None));
+            continue;
+        };
+        if attr.kind != SFlag::S_IFREG {
+            report.problems.push(problem(&entry.path, ProblemKind::NotAFile, None, None));
+            continue;
+        }
+        if let Some(size) = entry.size.filter(|&size| size != attr.size) {
+            report.problems.push(problem(
+                &entry.path,
+                ProblemKind::SizeMismatch,
+                Some(size.to_string()),
+                Some(attr.size.to_string()),
+            ));
+            continue;
+        }
+        if let Some(ref digest) = entry.digest {
+            hashed.push((entry, digest, attr.ino, digest::algorithm_of(digest)?));
+            report.bytes_hashed += attr.size;
+        }
+    }
+    if !options.allow_unexpected {
+        for (path, attr) in entries {
+            if attr.kind != SFlag::S_IFDIR {
+                let path = path.to_string_lossy();
+                report.problems.push(problem(&path, ProblemKind::Unexpected, None, None));
+            }
+        }
+    }
+
+    let files = hashed
+        .iter()
+        .map(|&(_, _, ino, algorithm)| (ino, algorithm))
+        .collect();
+    let digests = file_digests(&fs, ctx, files, options.concurrency).await?;
+    for ((entry, expected, _, _), actual) in hashed.into_iter().zip(digests) {
+        if !actual.eq_ignore_ascii_case(expected) {
+            report.problems.push(problem(
+                &entry.path,
+                ProblemKind::DigestMismatch,
+                Some(expected.clone()),
+                Some(actual),
+            ));
+        }
+    }
+    report.problems.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(report)
+}
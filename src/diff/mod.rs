@@ -0,0 +1,286 @@
+//! Differences between two directory trees, e.g. a dataset and the copy
+//! about to be promoted
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use sha2::{Digest, Sha256};
+use tokio::runtime::Handle;
+use tokio::task::JoinSet;
+
+use crate::common::buffer_pool::{BufferPool, COPY_CHUNK_SIZE};
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::fs_util::{self, FileAttr, RequestContext};
+use crate::storage::timeout;
+use crate::storage::virtualfs::{INum, VirtualFs};
+use crate::storage::walk::{self, DEFAULT_WALK_CONCURRENCY};
+
+pub mod manifest;
+
+/// How a path differs between the two trees
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// Only in the second tree
+    Added,
+    /// Only in the first tree
+    Removed,
+    /// In both trees, with a different type, size, modification time or
+    /// contents
+    Modified,
+}
+
+impl ChangeKind {
+    /// The name of the kind in reports
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Modified => "modified",
+        }
+    }
+}
+
+/// A path differing between the two trees
+#[derive(Debug, Clone)]
+pub struct Change {
+    /// The path relative to the roots of both trees
+    pub path: String,
+    /// How the path differs
+    pub kind: ChangeKind,
+    /// The attributes in the first tree, `None` when added
+    pub old: Option<FileAttr>,
+    /// The attributes in the second tree, `None` when removed
+    pub new: Option<FileAttr>,
+    /// Whether the contents differ, `None` unless their checksums were compared
+    pub content_differs: Option<bool>,
+}
+
+impl Change {
+    /// The growth in bytes from the first tree to the second, the whole size
+    /// of added and removed entries
+    pub fn size_delta(&self) -> i128 {
+        let size = |attr: &Option<FileAttr>| attr.as_ref().map_or(0, |attr| i128::from(attr.size));
+        size(&self.new) - size(&self.old)
+    }
+
+    /// How much later the entry was modified in the second tree than in the
+    /// first, in nanoseconds, `None` unless the path is in both
+    pub fn mtime_delta_ns(&self) -> Option<i128> {
+        let (old, new) = (self.old.as_ref()?, self.new.as_ref()?);
+        Some(timestamp_ns(new.mtime) - timestamp_ns(old.mtime))
+    }
+}
+
+/// The paths differing between two trees, sorted by path
+#[derive(Debug, Clone, Default)]
+pub struct Changeset {
+    /// Every differing path
+    pub changes: Vec<Change>,
+}
+
+impl Changeset {
+    /// Whether both trees are the same
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The changes of `kind`
+    pub fn of_kind(&self, kind: ChangeKind) -> impl Iterator<Item = &Change> {
+        self.changes.iter().filter(move |change| change.kind == kind)
+    }
+}
+
+/// Parameters of a diff
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// Compare the SHA-256 checksums of regular files of the same size
+    /// instead of their modification times
+    pub checksum: bool,
+    /// The number of directories listed, or files checksummed, at once
+    pub concurrency: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            checksum: false,
+            concurrency: DEFAULT_WALK_CONCURRENCY,
+        }
+    }
+}
+
+/// Nanoseconds since the epoch of `time`
+fn timestamp_ns(time: SystemTime) -> i128 {
+    let (sec, nsec) = fs_util::to_timespec(time);
+    i128::from(sec) * 1_000_000_000 + i128::from(nsec)
+}
+
+/// Every entry below the directory `root` of `fs`, by path relative to it
+async fn list_tree<F: VirtualFs + 'static>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    root: &str,
+    concurrency: usize,
+) -> DatenLordResult<BTreeMap<OsString, FileAttr>> {
+    let root = fs_util::normalize(OsStr::new(root));
+    let mut walk = walk::walk(fs, ctx, &Handle::current(), &root, concurrency);
+    let mut entries = BTreeMap::new();
+    while let Some(entry) = walk.next().await {
+        let entry = entry?;
+        let path = if root.is_empty() {
+            entry.path
+        } else {
+            OsStr::from_bytes(&entry.path.as_bytes()[root.len() + 1..]).to_owned()
+        };
+        entries.insert(path, entry.attr);
+    }
+    Ok(entries)
+}
+
+/// The SHA-256 checksum of the contents of file `ino`
+async fn checksum<F: VirtualFs>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+    pool: &BufferPool,
+) -> DatenLordResult<[u8; 32]> {
+    let fh = fs.open(ctx, ino, OFlag::O_RDONLY.bits() as u32).await?;
+    let mut buf = pool.acquire(COPY_CHUNK_SIZE);
+    let mut hasher = Sha256::new();
+    let mut offset = 0_u64;
+    let result = loop {
+        match fs.read(ctx, ino, fh, offset, buf.len() as u32, &mut buf).await {
+            Ok(0) => break Ok(hasher.finalize().into()),
+            Ok(size) => {
+                hasher.update(&buf[..size]);
+                offset += size as u64;
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    fs.release(ctx, ino, fh, 0, 0, false).await?;
+    result
+}
+
+/// Compare the contents of every `(old, new)` file pair of `pairs`, at most
+/// `concurrency` files at once, telling for each whether they differ
+async fn contents_differ<A: VirtualFs + 'static, B: VirtualFs + 'static>(
+    old: &Arc<A>,
+    new: &Arc<B>,
+    ctx: RequestContext,
+    pairs: Vec<(INum, INum)>,
+    concurrency: usize,
+) -> DatenLordResult<Vec<bool>> {
+    let deadline = timeout::deadline();
+    let pool = BufferPool::new();
+    let mut differ = vec![false; pairs.len()];
+    let mut pending = pairs.into_iter().enumerate();
+    let mut running = JoinSet::new();
+    loop {
+        while running.len() < concurrency.max(1) {
+            let Some((index, (old_ino, new_ino))) = pending.next() else {
+                break;
+            };
+            let (old, new, pool) = (Arc::clone(old), Arc::clone(new), pool.clone());
+            running.spawn(async move {
+                let compare = async {
+                    let old_sum = checksum(&*old, &ctx, old_ino, &pool).await?;
+                    let new_sum = checksum(&*new, &ctx, new_ino, &pool).await?;
+                    Ok::<_, DatenLordError>(old_sum != new_sum)
+                };
+                let result = match deadline {
+                    Some(deadline) => timeout::with_deadline(deadline, compare).await,
+                    None => compare.await,
+                };
+                (index, result)
+            });
+        }
+        let Some(done) = running.join_next().await else {
+            return Ok(differ);
+        };
+        let (index, result) = done.map_err(|e| DatenLordError::Internal {
+            context: vec![format!("checksum task panicked: {e}")],
+        })?;
+        differ[index] = result?;
+    }
+}
+
+/// Compare the tree below `old_path` of `old` with the one below `new_path`
+/// of `new` on behalf of `ctx`
+///
+/// Both trees can be directories of the same namespace, or a namespace and
+/// a copy of it in another backend, such as one made by `migrate`. A path
+/// in both trees is modified when its type or its size differs, or its
+/// modification time unless it is a directory; with `options.checksum`,
+/// regular files of the same size are modified when their contents differ,
+/// whatever their modification times. Symbolic links are compared, not
+/// followed.
+pub async fn diff<A, B>(
+    old: Arc<A>,
+    old_path: &str,
+    new: Arc<B>,
+    new_path: &str,
+    ctx: RequestContext,
+    options: &DiffOptions,
+) -> DatenLordResult<Changeset>
+where
+    A: VirtualFs + 'static,
+    B: VirtualFs + 'static,
+{
+    let (old_entries, mut new_entries) = tokio::try_join!(
+        list_tree(Arc::clone(&old), ctx, old_path, options.concurrency),
+        list_tree(Arc::clone(&new), ctx, new_path, options.concurrency),
+    )?;
+    let change = |path: OsString, kind, old, new| Change {
+        path: path.to_string_lossy().into_owned(),
+        kind,
+        old,
+        new,
+        content_differs: None,
+    };
+    let mut changes = Vec::new();
+    // Regular files of the same size, compared by checksum
+    let mut compared = Vec::new();
+    for (path, old_attr) in old_entries {
+        let Some(new_attr) = new_entries.remove(&path) else {
+            changes.push(change(path, ChangeKind::Removed, Some(old_attr), None));
+            continue;
+        };
+        let is_dir = old_attr.kind == SFlag::S_IFDIR;
+        let resized = !is_dir && old_attr.size != new_attr.size;
+        let modified = if old_attr.kind != new_attr.kind || resized {
+            true
+        } else if options.checksum && old_attr.kind == SFlag::S_IFREG {
+            compared.push((path, old_attr, new_attr));
+            continue;
+        } else {
+            !is_dir && old_attr.mtime != new_attr.mtime
+        };
+        if modified {
+            changes.push(change(path, ChangeKind::Modified, Some(old_attr), Some(new_attr)));
+        }
+    }
+    for (path, new_attr) in new_entries {
+        changes.push(change(path, ChangeKind::Added, None, Some(new_attr)));
+    }
+
+    let pairs = compared
+        .iter()
+        .map(|(_, old_attr, new_attr)| (old_attr.ino, new_attr.ino))
+        .collect();
+    let differ = contents_differ(&old, &new, ctx, pairs, options.concurrency).await?;
+    for ((path, old_attr, new_attr), differs) in compared.into_iter().zip(differ) {
+        if differs {
+            changes.push(Change {
+                content_differs: Some(true),
+                ..change(path, ChangeKind::Modified, Some(old_attr), Some(new_attr))
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(Changeset { changes })
+}
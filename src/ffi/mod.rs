@@ -0,0 +1,193 @@
+//! The only place raw pointers received over the C ABI, or exported by
+//! Python buffers, are dereferenced
+//!
+//! Invariant: every pointer handed to these helpers comes either from a
+//! Python buffer held for as long as it is used, or straight from a caller
+//! of an exported SDK function and is, as documented in `datenlord.h`,
+//! either null or valid for the access described by the function it was
+//! passed to, for as long as that function requires. The helpers check for
+//! null and otherwise trust that contract, which is why they are
+//! `pub(crate)` and must never be fed pointers of another origin.
+use std::ffi::{CStr, OsStr};
+use std::os::raw::c_char;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::Arc;
+
+/// A NUL-terminated string argument borrowed from a C caller
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CStrArg<'a>(&'a CStr);
+
+impl<'a> CStrArg<'a> {
+    /// Wrap the string at `ptr`, `None` if it is null
+    pub(crate) fn new(ptr: *const c_char) -> Option<Self> {
+        // SAFETY: non-null string arguments are NUL-terminated and outlive the call.
+        (!ptr.is_null()).then(|| Self(unsafe { CStr::from_ptr(ptr) }))
+    }
+
+    /// The string as UTF-8, `None` if it is not valid UTF-8
+    pub(crate) fn to_str(self) -> Option<&'a str> {
+        self.0.to_str().ok()
+    }
+
+    /// The string as raw bytes, whatever their encoding
+    pub(crate) fn to_os_str(self) -> &'a OsStr {
+        OsStr::from_bytes(self.0.to_bytes())
+    }
+}
+
+/// Borrow the UTF-8 string argument at `ptr`, `None` if it is null or not UTF-8
+pub(crate) fn str_arg<'a>(ptr: *const c_char) -> Option<&'a str> {
+    CStrArg::new(ptr).and_then(CStrArg::to_str)
+}
+
+/// Borrow the string argument at `ptr` as raw bytes, `None` if it is null
+pub(crate) fn os_str_arg<'a>(ptr: *const c_char) -> Option<&'a OsStr> {
+    CStrArg::new(ptr).map(CStrArg::to_os_str)
+}
+
+/// Borrow the `len` bytes at `ptr` as a string argument, `None` if `ptr` is
+/// null while `len` is not 0
+pub(crate) fn os_str_bytes_arg<'a>(ptr: *const u8, len: usize) -> Option<&'a OsStr> {
+    if len == 0 {
+        return Some(OsStr::new(""));
+    }
+    // SAFETY: non-null byte arguments are valid for reads of `len` bytes
+    // and outlive the call.
+    (!ptr.is_null()).then(|| OsStr::from_bytes(unsafe { std::slice::from_raw_parts(ptr, len) }))
+}
+
+/// A byte buffer owned by a C caller, described by a pointer and a length
+///
+/// The buffer is `Send` so asynchronous operations can carry it to the
+/// runtime; the C caller promises to keep it alive and untouched until the
+/// operation completes.
+#[derive(Debug)]
+pub(crate) struct CBytes {
+    /// Start of the buffer
+    ptr: *mut u8,
+    /// Length of the buffer
+    len: usize,
+}
+
+// SAFETY: the caller hands the buffer over to the SDK until the call or
+// the asynchronous operation using it completes.
+unsafe impl Send for CBytes {}
+
+impl CBytes {
+    /// Wrap `len` bytes at `ptr`, `None` if `ptr` is null while `len` is not 0
+    pub(crate) fn new(ptr: *const u8, len: usize) -> Option<Self> {
+        (!ptr.is_null() || len == 0).then_some(Self {
+            ptr: ptr.cast_mut(),
+            len,
+        })
+    }
+
+    /// The buffer contents
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: `ptr` is non-null and valid for reads of `len` bytes.
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// The buffer contents, for buffers the caller passed to be written to
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        if self.len == 0 {
+            return &mut [];
+        }
+        // SAFETY: output buffers are non-null and valid for writes of `len`
+        // bytes, and the caller does not access them until the call returns.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+/// The memory of the Python buffer `buffer` as an output buffer, `None` if
+/// it is read-only or not C-contiguous
+///
+/// The memory must only be written while `buffer` is held, and with the
+/// GIL held so no Python code touches it meanwhile.
+#[cfg(not(feature = "abi3"))]
+pub(crate) fn py_buffer_bytes(buffer: &pyo3::buffer::PyBuffer<u8>) -> Option<CBytes> {
+    (!buffer.readonly() && buffer.is_c_contiguous()).then(|| CBytes {
+        ptr: buffer.buf_ptr().cast(),
+        len: buffer.len_bytes(),
+    })
+}
+
+/// Borrow the object behind a handle or in-argument, `None` if it is null
+pub(crate) fn as_ref<'a, T>(ptr: *const T) -> Option<&'a T> {
+    // SAFETY: non-null handles come from `into_raw` or `into_raw_arc` and
+    // are not yet freed.
+    unsafe { ptr.as_ref() }
+}
+
+/// Borrow the object behind an out-argument, `None` if it is null
+pub(crate) fn as_mut<'a, T>(ptr: *mut T) -> Option<&'a mut T> {
+    // SAFETY: non-null out-arguments are valid and exclusively ours during the call.
+    unsafe { ptr.as_mut() }
+}
+
+/// Read the slot `index` of the in-array `ptr` of at least `index + 1` elements
+pub(crate) fn read_at<T: Copy>(ptr: *const T, index: usize) -> T {
+    // SAFETY: in-arrays are valid for reads of their documented length.
+    unsafe { ptr.add(index).read() }
+}
+
+/// Write `value` to the slot `index` of the out-array `ptr` of at least `index + 1` elements
+///
+/// The slot may be uninitialized, its previous contents are not dropped.
+pub(crate) fn write_at<T>(ptr: *mut T, index: usize, value: T) {
+    // SAFETY: out-arrays are valid for writes of their documented length.
+    unsafe { ptr.add(index).write(value) };
+}
+
+/// Move `value` to the heap and hand its ownership to C
+pub(crate) fn into_raw<T>(value: T) -> *mut T {
+    Box::into_raw(Box::new(value))
+}
+
+/// Take back ownership of an object created by `into_raw`, `None` if null
+pub(crate) fn from_raw<T>(ptr: *mut T) -> Option<Box<T>> {
+    // SAFETY: non-null pointers come from `into_raw` and are freed only once.
+    (!ptr.is_null()).then(|| unsafe { Box::from_raw(ptr) })
+}
+
+/// Move `value` to the heap behind a reference count and hand one
+/// reference to C, see `clone_raw_arc` and `from_raw_arc`
+pub(crate) fn into_raw_arc<T>(value: T) -> *mut T {
+    Arc::into_raw(Arc::new(value)).cast_mut()
+}
+
+/// Hand another reference to an object created by `into_raw_arc` to C,
+/// null if `ptr` is
+pub(crate) fn clone_raw_arc<T>(ptr: *mut T) -> *mut T {
+    if !ptr.is_null() {
+        // SAFETY: non-null pointers come from `into_raw_arc` and their
+        // reference is not yet given back.
+        unsafe { Arc::increment_strong_count(ptr.cast_const()) };
+    }
+    ptr
+}
+
+/// Take back a reference handed out by `into_raw_arc` or `clone_raw_arc`,
+/// `None` if null
+pub(crate) fn from_raw_arc<T>(ptr: *mut T) -> Option<Arc<T>> {
+    // SAFETY: non-null pointers come from `into_raw_arc` or `clone_raw_arc`
+    // and each reference is given back only once.
+    (!ptr.is_null()).then(|| unsafe { Arc::from_raw(ptr.cast_const()) })
+}
+
+/// Hand ownership of `bytes` to C as a pointer and a length
+pub(crate) fn into_raw_bytes(bytes: Vec<u8>) -> (*const u8, usize) {
+    let bytes = Box::leak(bytes.into_boxed_slice());
+    (bytes.as_ptr(), bytes.len())
+}
+
+/// Take back and free bytes handed out by `into_raw_bytes`
+pub(crate) fn free_raw_bytes(ptr: *const u8, len: usize) {
+    if !ptr.is_null() {
+        // SAFETY: the pointer and length come from `into_raw_bytes` and are freed only once.
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr.cast_mut(), len)) });
+    }
+}
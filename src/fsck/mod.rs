@@ -0,0 +1,383 @@
+//! Consistency checks of the local root of a namespace, repairing what can
+//! be repaired without losing data
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Component, Path};
+
+use nix::errno::Errno;
+use tracing::info;
+
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::superblock::{Superblock, SUPERBLOCK_NAME};
+use crate::storage::tags::{self, TAG_XATTR_PREFIX};
+use crate::storage::xattr;
+
+/// How bad an issue is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Suspicious, but the namespace works as it is
+    Warning,
+    /// The namespace is inconsistent
+    Error,
+}
+
+impl Severity {
+    /// The name of the severity in reports
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// An inconsistency found by `check`
+#[derive(Debug, Clone)]
+pub struct Issue {
+    /// How bad the issue is
+    pub severity: Severity,
+    /// The path relative to the root, empty for the root itself
+    pub path: String,
+    /// What is wrong
+    pub message: String,
+    /// Whether `--repair` fixed it
+    pub repaired: bool,
+}
+
+/// What to do about the issues found
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsckOptions {
+    /// Fix the issues that can be fixed without losing data
+    pub repair: bool,
+}
+
+/// The outcome of `check`
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    /// The entries checked, the root included
+    pub entries: u64,
+    /// The issues, in the order they were found
+    pub issues: Vec<Issue>,
+}
+
+impl FsckReport {
+    /// The issues left unrepaired
+    pub fn unrepaired(&self) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(|issue| !issue.repaired)
+    }
+
+    /// Whether every issue found, if any, was repaired
+    pub fn is_clean(&self) -> bool {
+        self.unrepaired().next().is_none()
+    }
+
+    fn report(&mut self, severity: Severity, path: &str, message: String, repaired: bool) {
+        self.issues.push(Issue {
+            severity,
+            path: path.to_owned(),
+            message,
+            repaired,
+        });
+    }
+}
+
+/// The links to a regular file found in the tree
+struct Links {
+    /// The link count of the file
+    nlink: u64,
+    /// The paths linking to it
+    paths: Vec<String>,
+}
+
+/// A walk of the tree under a root
+struct Checker<'a> {
+    root: &'a Path,
+    /// The device of the root, entries on others are mount points
+    dev: u64,
+    options: &'a FsckOptions,
+    report: FsckReport,
+    /// The links to every regular file with more than one, by inode
+    links: HashMap<u64, Links>,
+}
+
+/// Check the namespace rooted at `root`, repairing the issues found as
+/// `options` says
+///
+/// LocalFS passes inodes and data through to the local filesystem, so the
+/// tree is checked for what the local filesystem cannot vouch for: the
+/// superblock, entries vanishing between listing and stat, link counts,
+/// symbolic links leaving the root, data orphaned by interrupted writes and
+/// tags. The local format stores no checksums to verify. Only orphans and
+/// invalid tags are repaired, the rest is left to the operator.
+pub fn check(root: &Path, options: &FsckOptions) -> DatenLordResult<FsckReport> {
+    let metadata = fs::metadata(root).map_err(|e| DatenLordError::Io {
+        context: vec![format!("failed to stat root {root:?}: {e}")],
+        source: None,
+    })?;
+    if !metadata.is_dir() {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("root {root:?} is not a directory")],
+        });
+    }
+    let mut checker = Checker {
+        root,
+        dev: metadata.dev(),
+        options,
+        report: FsckReport::default(),
+        links: HashMap::new(),
+    };
+    checker.check_superblock();
+    checker.check_entry("", &metadata);
+    checker.check_dir(root, "", &metadata);
+    checker.check_links();
+    Ok(checker.report)
+}
+
+impl Checker<'_> {
+    /// Check the superblock and remove a replacement an interrupted store
+    /// left behind
+    fn check_superblock(&mut self) {
+        match Superblock::load(self.root) {
+            Ok(Some(superblock)) => {
+                if let Err(e) = superblock.check_compatible() {
+                    let message = format!("incompatible superblock: {e}");
+                    self.report.report(Severity::Error, SUPERBLOCK_NAME, message, false);
+                }
+            }
+            // Created with the default features when the namespace is opened
+            Ok(None) => {}
+            Err(e) => {
+                let message = format!("unreadable superblock: {e}");
+                self.report.report(Severity::Error, SUPERBLOCK_NAME, message, false);
+            }
+        }
+        let tmp_name = format!("{SUPERBLOCK_NAME}.tmp");
+        let tmp_path = self.root.join(&tmp_name);
+        if fs::symlink_metadata(&tmp_path).is_ok() {
+            let repaired = self.options.repair && self.remove_orphan(&tmp_name, &tmp_path);
+            let message = "orphaned superblock replacement".to_owned();
+            self.report.report(Severity::Warning, &tmp_name, message, repaired);
+        }
+    }
+
+    /// Remove the orphan `path`, reporting failures
+    fn remove_orphan(&mut self, name: &str, path: &Path) -> bool {
+        match fs::remove_file(path) {
+            Ok(()) => {
+                info!("removed orphan {path:?}");
+                true
+            }
+            Err(e) => {
+                let message = format!("failed to remove orphan: {e}");
+                self.report.report(Severity::Error, name, message, false);
+                false
+            }
+        }
+    }
+
+    /// Check the entries of the directory `path`, at `rel` from the root
+    fn check_dir(&mut self, path: &Path, rel: &str, metadata: &Metadata) {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.report.report(Severity::Error, rel, format!("failed to list: {e}"), false);
+                return;
+            }
+        };
+        let mut subdirs = Vec::new();
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let message = format!("failed to list: {e}");
+                    self.report.report(Severity::Error, rel, message, false);
+                    return;
+                }
+            };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if rel.is_empty() && name.starts_with(SUPERBLOCK_NAME) {
+                continue;
+            }
+            let child_rel = if rel.is_empty() { name } else { format!("{rel}/{name}") };
+            let child_path = entry.path();
+            match fs::symlink_metadata(&child_path) {
+                Ok(child) => {
+                    self.check_entry(&child_rel, &child);
+                    if child.is_dir() {
+                        subdirs.push((child_path, child_rel, child));
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    let message = "listed by its directory but missing".to_owned();
+                    self.report.report(Severity::Error, &child_rel, message, false);
+                }
+                Err(e) => {
+                    let message = format!("failed to stat: {e}");
+                    self.report.report(Severity::Error, &child_rel, message, false);
+                }
+            }
+        }
+
+        // A directory links to itself from its parent and its `.` entry and
+        // from the `..` entry of each subdirectory, filesystems not counting
+        // directory links report 1
+        let expected = 2 + subdirs.len() as u64;
+        if metadata.nlink() != 1 && metadata.nlink() != expected {
+            let message = format!(
+                "link count {} but {} subdirectories, expected {expected}",
+                metadata.nlink(),
+                subdirs.len()
+            );
+            self.report.report(Severity::Error, rel, message, false);
+        }
+        for (child_path, child_rel, child) in subdirs {
+            if child.dev() == self.dev {
+                self.check_dir(&child_path, &child_rel, &child);
+            } else {
+                let message = "mount point of another filesystem, not checked".to_owned();
+                self.report.report(Severity::Warning, &child_rel, message, false);
+            }
+        }
+    }
+
+    /// Check the entry at `rel` from the root, whatever its type
+    fn check_entry(&mut self, rel: &str, metadata: &Metadata) {
+        self.report.entries += 1;
+        let file_type = metadata.file_type();
+        if file_type.is_file() {
+            if metadata.nlink() > 1 {
+                self.links
+                    .entry(metadata.ino())
+                    .or_insert_with(|| Links {
+                        nlink: metadata.nlink(),
+                        paths: Vec::new(),
+                    })
+                    .paths
+                    .push(rel.to_owned());
+            }
+        } else if file_type.is_symlink() {
+            self.check_symlink(rel);
+        } else if file_type.is_fifo()
+            || file_type.is_socket()
+            || file_type.is_block_device()
+            || file_type.is_char_device()
+        {
+            let message = "special file, not readable through the sdk".to_owned();
+            self.report.report(Severity::Warning, rel, message, false);
+        }
+        self.check_tags(rel);
+    }
+
+    /// Check the symbolic link at `rel` stays within the root and resolves
+    fn check_symlink(&mut self, rel: &str) {
+        let path = self.root.join(rel);
+        let target = match fs::read_link(&path) {
+            Ok(target) => target,
+            Err(e) => {
+                let message = format!("failed to read link: {e}");
+                self.report.report(Severity::Error, rel, message, false);
+                return;
+            }
+        };
+        // Resolve `..` lexically against the parent, the way paths of the
+        // namespace are
+        let mut depth = rel.matches('/').count();
+        let mut escapes = target.is_absolute();
+        for component in target.components() {
+            match component {
+                Component::ParentDir if depth == 0 => escapes = true,
+                Component::ParentDir => depth -= 1,
+                Component::Normal(_) => depth += 1,
+                _ => {}
+            }
+        }
+        if escapes {
+            let message = format!("link to {target:?} leaves the root");
+            self.report.report(Severity::Warning, rel, message, false);
+        } else if fs::metadata(&path).is_err() {
+            let message = format!("dangling link to {target:?}");
+            self.report.report(Severity::Warning, rel, message, false);
+        }
+    }
+
+    /// Check the tags of the entry at `rel` have valid keys and UTF-8 values,
+    /// removing the invalid ones when repairing
+    fn check_tags(&mut self, rel: &str) {
+        let path = self.root.join(rel);
+        let names = match xattr::list(&path) {
+            Ok(names) => names,
+            Err(e) if e.raw_os_error() == Some(Errno::EOPNOTSUPP as i32) => return,
+            Err(e) => {
+                let message = format!("failed to list xattrs: {e}");
+                self.report.report(Severity::Error, rel, message, false);
+                return;
+            }
+        };
+        for name in names {
+            let Some(key) = tags::tag_key(&name) else {
+                continue;
+            };
+            let invalid = if tags::check_key(key).is_err() {
+                Some(format!("invalid tag key {key:?}"))
+            } else {
+                match xattr::get(&path, &name) {
+                    Ok(Some(value)) if std::str::from_utf8(&value).is_err() => {
+                        Some(format!("tag {key} has a value that is not UTF-8"))
+                    }
+                    Ok(_) => None,
+                    Err(e) => Some(format!("failed to read tag {key}: {e}")),
+                }
+            };
+            if let Some(message) = invalid {
+                let repaired = self.options.repair && self.remove_tag(rel, &path, &name);
+                self.report.report(Severity::Error, rel, message, repaired);
+            }
+        }
+    }
+
+    /// Remove the tag attribute `name` of `path`, reporting failures
+    fn remove_tag(&mut self, rel: &str, path: &Path, name: &str) -> bool {
+        match xattr::remove(path, name) {
+            Ok(()) => {
+                info!("removed {name} of {path:?}");
+                true
+            }
+            Err(e) => {
+                let key = name.strip_prefix(TAG_XATTR_PREFIX).unwrap_or(name);
+                let message = format!("failed to remove tag {key}: {e}");
+                self.report.report(Severity::Error, rel, message, false);
+                false
+            }
+        }
+    }
+
+    /// Check the link counts of the files linked more than once against the
+    /// links found in the tree
+    fn check_links(&mut self) {
+        let mut links: Vec<(u64, Links)> = self.links.drain().collect();
+        links.sort_by_key(|&(ino, _)| ino);
+        for (_, links) in links {
+            let found = links.paths.len() as u64;
+            let (severity, message) = match found.cmp(&links.nlink) {
+                std::cmp::Ordering::Equal => continue,
+                std::cmp::Ordering::Less => (
+                    Severity::Warning,
+                    format!(
+                        "link count {} but {found} links in the tree, the others are \
+                         outside the root",
+                        links.nlink
+                    ),
+                ),
+                std::cmp::Ordering::Greater => (
+                    Severity::Error,
+                    format!("link count {} but {found} links in the tree", links.nlink),
+                ),
+            };
+            let paths = links.paths.join(", ");
+            self.report.report(severity, &paths, message, false);
+        }
+    }
+}
+
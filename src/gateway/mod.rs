@@ -0,0 +1,149 @@
+//! Gateways serving the namespace to clients that run neither the SDKs nor
+//! FUSE
+//!
+//! Their configs are parsed whether or not the features building the
+//! gateways are enabled.
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+pub use crate::storage::idmap::Squash;
+
+#[cfg(feature = "nfs")]
+pub mod nfs;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+
+/// The address the NFS gateway listens on by default, the NFS port
+const DEFAULT_NFS_LISTEN: &str = "0.0.0.0:2049";
+/// The address the S3 gateway listens on by default
+const DEFAULT_S3_LISTEN: &str = "127.0.0.1:9000";
+/// The region the S3 gateway signs as by default
+const DEFAULT_S3_REGION: &str = "us-east-1";
+/// The user and group ids squashed callers act as by default, `nobody`
+const DEFAULT_ANON_ID: u32 = 65534;
+
+/// The NFSv3 gateway, see `gateway::nfs`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NfsConfig {
+    /// The address both the MOUNT and the NFS programs are served on
+    pub listen: String,
+    /// The directories clients may mount, none by default
+    pub exports: Vec<NfsExport>,
+}
+
+impl Default for NfsConfig {
+    fn default() -> Self {
+        Self {
+            listen: DEFAULT_NFS_LISTEN.to_owned(),
+            exports: Vec::new(),
+        }
+    }
+}
+
+/// A directory of the namespace clients may mount
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NfsExport {
+    /// The directory relative to the namespace root, which clients mount as
+    /// `/<path>`, the root itself when empty
+    pub path: String,
+    /// Fail every change made through the export with `NFS3ERR_ROFS`
+    pub read_only: bool,
+    /// Which callers act as `anon_uid` and `anon_gid`, callers without
+    /// `AUTH_UNIX` credentials always do
+    pub squash: Squash,
+    /// The user id squashed callers act as
+    pub anon_uid: u32,
+    /// The group id squashed callers act as
+    pub anon_gid: u32,
+}
+
+impl Default for NfsExport {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            read_only: false,
+            squash: Squash::default(),
+            anon_uid: DEFAULT_ANON_ID,
+            anon_gid: DEFAULT_ANON_ID,
+        }
+    }
+}
+
+/// The SFTP subsystem, see `gateway::sftp`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SftpConfig {
+    /// The directory relative to the namespace root sessions see as `/`,
+    /// the root itself when empty
+    pub path: String,
+    /// Fail every change made through the sessions with a permission error
+    pub read_only: bool,
+}
+
+/// The S3-compatible endpoint, see `gateway::s3`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct S3Config {
+    /// The address the endpoint is served on
+    pub listen: String,
+    /// The region clients sign their requests for
+    pub region: String,
+    /// The access key id clients sign their requests with, any request is
+    /// served unchecked when empty
+    pub access_key_id: String,
+    /// The secret key of `access_key_id`
+    pub secret_access_key: String,
+    /// Serve over TLS rather than plain HTTP
+    pub tls: Option<TlsConfig>,
+    /// The callers known by a token or a client certificate, acting with
+    /// their own ids rather than those of the config
+    pub principals: Vec<PrincipalConfig>,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            listen: DEFAULT_S3_LISTEN.to_owned(),
+            region: DEFAULT_S3_REGION.to_owned(),
+            access_key_id: String::new(),
+            secret_access_key: String::new(),
+            tls: None,
+            principals: Vec::new(),
+        }
+    }
+}
+
+/// The certificate a gateway serves TLS with
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// The PEM file of the certificate chain, leaf first
+    pub cert: PathBuf,
+    /// The PEM file of the private key of the certificate
+    pub key: PathBuf,
+    /// The PEM file of the CAs client certificates must be signed by, which
+    /// clients must then present, none being asked for when unset
+    pub client_ca: Option<PathBuf>,
+}
+
+/// A caller of a gateway and the ids it acts with, which are mapped by the
+/// `idmap` of the config like those of any other caller
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrincipalConfig {
+    /// The token the caller sends as `Authorization: Bearer <token>`, none
+    /// when empty
+    pub token: String,
+    /// The hex SHA-256 of the DER of the client certificate the caller
+    /// presents, none when empty
+    pub certificate_sha256: String,
+    /// The user id the caller acts as
+    pub uid: u32,
+    /// The group id the caller acts as
+    pub gid: u32,
+}
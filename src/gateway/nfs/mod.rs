@@ -0,0 +1,247 @@
+//! A user-space NFSv3 server over any `VirtualFs`, see RFC 1813
+//!
+//! Clients mount the exports of `NfsConfig` without the SDKs or FUSE. The
+//! MOUNT and NFS programs share one TCP port and no portmapper runs, so
+//! clients name the port twice, and without the NLM locking protocol they
+//! lock locally, e.g. on Linux:
+//! `mount -t nfs -o vers=3,proto=tcp,port=2049,mountport=2049,nolock host:/data /mnt`
+//!
+//! File handles hold the export and the inode number, and go stale when the
+//! backend no longer knows the inode, such as after a restart. They are not
+//! checked to lie under their export: exports choose the squashing and
+//! whether changes are allowed rather than confine the clients.
+use std::ffi::OsStr;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nix::sys::stat::SFlag;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::{debug, info};
+
+use self::mount::{MOUNT_PROGRAM, MOUNT_VERSION};
+use self::nfs3::{NFS_PROGRAM, NFS_VERSION};
+use self::rpc::{AcceptStat, Call, UnixCred, RPC_VERSION};
+use super::{NfsConfig, NfsExport, Squash};
+use crate::common::config::DatenLordConfig;
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::sdk;
+use crate::storage::fs_util::{self, RequestContext, ROOT_ID};
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+mod mount;
+mod nfs3;
+pub mod rpc;
+pub mod xdr;
+
+/// The length of the file handles handed out, the export index followed by
+/// the inode number
+const HANDLE_LEN: usize = 12;
+
+/// An export with its directory looked up
+#[derive(Debug)]
+struct Export {
+    /// The path clients mount, `/` followed by the normalized directory
+    name: String,
+    /// The inode of the directory
+    root: INum,
+    config: NfsExport,
+}
+
+impl Export {
+    /// The context the caller with `cred` acts with through the export
+    fn context(&self, cred: Option<&UnixCred>) -> RequestContext {
+        let (uid, gid) = match cred {
+            Some(cred) if self.config.squash == Squash::None => (cred.uid, cred.gid),
+            Some(cred) if self.config.squash == Squash::Root && cred.uid != 0 => {
+                (cred.uid, cred.gid)
+            }
+            _ => (self.config.anon_uid, self.config.anon_gid),
+        };
+        // Clients apply their umask to the modes they send
+        RequestContext {
+            uid,
+            gid,
+            pid: 0,
+            umask: 0,
+            root: ROOT_ID,
+        }
+    }
+}
+
+/// The NFSv3 and MOUNT server of a filesystem
+#[derive(Debug)]
+pub struct NfsServer<F> {
+    fs: Arc<F>,
+    exports: Vec<Export>,
+    /// The verifier of unstable writes, new on every start so clients send
+    /// again the writes they did not commit before a restart
+    verifier: [u8; 8],
+}
+
+impl<F: VirtualFs + 'static> NfsServer<F> {
+    /// A server of the exports of `config` on `fs`, failing if an exported
+    /// directory cannot be looked up
+    pub async fn new(fs: Arc<F>, config: &NfsConfig) -> DatenLordResult<Self> {
+        let ctx = RequestContext::current();
+        let mut exports = Vec::with_capacity(config.exports.len());
+        for export in &config.exports {
+            let path = fs_util::normalize(OsStr::new(&export.path));
+            let mut root = ROOT_ID;
+            for name in fs_util::components(&path) {
+                let (_, attr, _) = fs.lookup(&ctx, root, name).await?;
+                if attr.kind != SFlag::S_IFDIR {
+                    return Err(DatenLordError::InvalidArgument {
+                        context: vec![format!(
+                            "exported path {:?} is not a directory",
+                            export.path
+                        )],
+                    });
+                }
+                root = attr.ino;
+            }
+            exports.push(Export {
+                name: format!("/{}", path.to_string_lossy()),
+                root,
+                config: export.clone(),
+            });
+        }
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let verifier = u64::try_from(started.as_nanos())
+            .unwrap_or(u64::MAX)
+            .to_be_bytes();
+        Ok(Self {
+            fs,
+            exports,
+            verifier,
+        })
+    }
+
+    /// Serve the clients connecting to `listener` until accepting fails
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            debug!("NFS client {peer} connected");
+            let server = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(e) = server.serve_connection(stream).await {
+                    debug!("NFS connection of {peer} failed: {e}");
+                }
+            });
+        }
+    }
+
+    /// Serve the calls of one connection, concurrently since clients send
+    /// many before the first replies
+    async fn serve_connection(self: Arc<Self>, stream: TcpStream) -> io::Result<()> {
+        let (mut reader, mut writer) = stream.into_split();
+        let (replies, mut pending) = mpsc::unbounded_channel::<Vec<u8>>();
+        let sender = tokio::spawn(async move {
+            while let Some(reply) = pending.recv().await {
+                rpc::write_record(&mut writer, &reply).await?;
+            }
+            Ok::<_, io::Error>(())
+        });
+        while let Some(record) = rpc::read_record(&mut reader).await? {
+            let server = Arc::clone(&self);
+            let replies = replies.clone();
+            tokio::spawn(async move {
+                if let Some(reply) = server.dispatch(&record).await {
+                    // The connection is gone when the sender stopped
+                    let _ = replies.send(reply);
+                }
+            });
+        }
+        drop(replies);
+        sender.await.map_err(io::Error::other)?
+    }
+
+    /// The reply to the call `record`, `None` if it is no call
+    async fn dispatch(&self, record: &[u8]) -> Option<Vec<u8>> {
+        let mut call = match Call::parse(record) {
+            Ok(call) => call,
+            Err(e) => {
+                debug!("dropping an undecodable RPC message: {e}");
+                return None;
+            }
+        };
+        if call.rpc_version != RPC_VERSION {
+            return Some(rpc::rpc_mismatch(call.xid).into_inner());
+        }
+        let served = match call.program {
+            MOUNT_PROGRAM => MOUNT_VERSION,
+            NFS_PROGRAM => NFS_VERSION,
+            _ => return Some(rpc::accepted(call.xid, AcceptStat::ProgUnavail).into_inner()),
+        };
+        if call.version != served {
+            let mut reply = rpc::accepted(call.xid, AcceptStat::ProgMismatch);
+            reply.u32(served).u32(served);
+            return Some(reply.into_inner());
+        }
+        let results = if call.program == MOUNT_PROGRAM {
+            self.mount(&mut call).await
+        } else {
+            self.nfs3(&mut call).await
+        };
+        let reply = match results {
+            Ok(Some(results)) => {
+                let mut reply = rpc::accepted(call.xid, AcceptStat::Success);
+                reply.append(results);
+                reply
+            }
+            Ok(None) => rpc::accepted(call.xid, AcceptStat::ProcUnavail),
+            Err(e) => {
+                debug!("garbage arguments to procedure {}: {e}", call.procedure);
+                rpc::accepted(call.xid, AcceptStat::GarbageArgs)
+            }
+        };
+        Some(reply.into_inner())
+    }
+
+    /// The file handle of `ino` reached through the export `index`
+    fn handle(index: usize, ino: INum) -> [u8; HANDLE_LEN] {
+        let mut handle = [0; HANDLE_LEN];
+        handle[..4].copy_from_slice(&u32::try_from(index).unwrap_or(u32::MAX).to_be_bytes());
+        handle[4..].copy_from_slice(&ino.to_be_bytes());
+        handle
+    }
+
+    /// The export and the inode of the file handle `handle`, `None` if it
+    /// was not handed out by this server
+    fn resolve(&self, handle: &[u8]) -> Option<(usize, &Export, INum)> {
+        let handle: &[u8; HANDLE_LEN] = handle.try_into().ok()?;
+        let index = u32::from_be_bytes([handle[0], handle[1], handle[2], handle[3]]) as usize;
+        let mut ino = [0; 8];
+        ino.copy_from_slice(&handle[4..]);
+        let export = self.exports.get(index)?;
+        Some((index, export, u64::from_be_bytes(ino)))
+    }
+}
+
+/// Serve the exports of the `nfs` field of `config` on the filesystem
+/// stack of the SDKs until the listener fails, reloading `config_file` on
+/// `SIGHUP`, see `sdk::reload_on_hangup`
+pub async fn run(config: &DatenLordConfig, config_file: Option<PathBuf>) -> DatenLordResult<()> {
+    let fs = Arc::new(sdk::open_fs(config)?);
+    let _writeback = sdk::writeback(&fs, config)?;
+    sdk::reload_on_hangup(&fs, config_file)?;
+    let server = Arc::new(NfsServer::new(fs, &config.nfs).await?);
+    let listener = TcpListener::bind(&config.nfs.listen)
+        .await
+        .map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to listen on {}: {e}", config.nfs.listen)],
+            source: None,
+        })?;
+    info!("serving NFSv3 on {}", config.nfs.listen);
+    server
+        .serve(listener)
+        .await
+        .map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to accept NFS clients: {e}")],
+            source: None,
+        })
+}
@@ -0,0 +1,79 @@
+//! The MOUNT version 3 program of RFC 1813, handing out the file handles
+//! of the exports
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+use super::rpc::{Call, AUTH_UNIX};
+use super::xdr::XdrWriter;
+use super::NfsServer;
+use crate::common::DatenLordResult;
+use crate::storage::fs_util;
+use crate::storage::virtualfs::VirtualFs;
+
+/// The number of the MOUNT program
+pub(crate) const MOUNT_PROGRAM: u32 = 100_005;
+/// The version of the MOUNT program served
+pub(crate) const MOUNT_VERSION: u32 = 3;
+/// The longest path mounted
+const MNTPATHLEN: usize = 1024;
+
+/// `MOUNTPROC3_NULL`, doing nothing
+const NULL: u32 = 0;
+/// `MOUNTPROC3_MNT`, returning the file handle of an export
+pub(crate) const MNT: u32 = 1;
+/// `MOUNTPROC3_DUMP`, listing the mounts
+const DUMP: u32 = 2;
+/// `MOUNTPROC3_UMNT`, forgetting a mount
+const UMNT: u32 = 3;
+/// `MOUNTPROC3_UMNTALL`, forgetting the mounts of a client
+const UMNTALL: u32 = 4;
+/// `MOUNTPROC3_EXPORT`, listing the exports
+pub(crate) const EXPORT: u32 = 5;
+
+/// `MNT3_OK`
+const MNT3_OK: u32 = 0;
+/// `MNT3ERR_NOENT`, no export has the path
+const MNT3ERR_NOENT: u32 = 2;
+
+impl<F: VirtualFs + 'static> NfsServer<F> {
+    /// The results of the MOUNT call `call`, `None` if its procedure does
+    /// not exist
+    pub(super) async fn mount(&self, call: &mut Call<'_>) -> DatenLordResult<Option<XdrWriter>> {
+        let mut results = XdrWriter::new();
+        match call.procedure {
+            // No mount is remembered, so there are none to list or forget
+            NULL | UMNTALL => {}
+            UMNT => {
+                call.args.opaque(MNTPATHLEN)?;
+            }
+            DUMP => {
+                results.bool(false);
+            }
+            MNT => {
+                let path = OsStr::from_bytes(call.args.opaque(MNTPATHLEN)?);
+                let name = format!("/{}", fs_util::normalize(path).to_string_lossy());
+                match self.exports.iter().position(|export| export.name == name) {
+                    Some(index) => {
+                        let handle = Self::handle(index, self.exports[index].root);
+                        results.u32(MNT3_OK).opaque(&handle).u32(1).u32(AUTH_UNIX);
+                    }
+                    None => {
+                        results.u32(MNT3ERR_NOENT);
+                    }
+                }
+            }
+            EXPORT => {
+                for export in &self.exports {
+                    // Every client may mount every export
+                    results
+                        .bool(true)
+                        .opaque(export.name.as_bytes())
+                        .bool(false);
+                }
+                results.bool(false);
+            }
+            _ => return Ok(None),
+        }
+        Ok(Some(results))
+    }
+}
@@ -0,0 +1,1166 @@
+//! The NFS version 3 program of RFC 1813, run on the `VirtualFs` of the
+//! server
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::stat::{self, SFlag};
+
+use super::rpc::{Call, UnixCred};
+use super::xdr::{XdrReader, XdrWriter};
+use super::{Export, NfsServer};
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::fs_util::{
+    self, CreateParam, DirEntry, FileAttr, RenameParam, RequestContext, SetAttrParam,
+};
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+/// The number of the NFS program
+pub(crate) const NFS_PROGRAM: u32 = 100_003;
+/// The version of the NFS program served
+pub(crate) const NFS_VERSION: u32 = 3;
+
+/// The longest file handle, `NFS3_FHSIZE`
+const FHSIZE: usize = 64;
+/// The longest name or path accepted
+const MAX_PATH: usize = 4096;
+/// The most bytes a `READ` returns or a `WRITE` takes
+pub(crate) const MAX_IO: u32 = 1 << 20;
+/// The size `READ`, `WRITE` and `READDIR` requests should be multiples of
+const PREFERRED_MULTIPLE: u32 = 4096;
+/// The preferred size of `READDIR` requests
+const PREFERRED_READDIR: u32 = 64 << 10;
+/// The most hard links to a file reported by `PATHCONF`
+const LINK_MAX: u32 = 32000;
+/// The longest name reported when the backend reports none
+const DEFAULT_NAME_MAX: u32 = 255;
+/// The id of the filesystem, the same for every export
+const FSID: u64 = 1;
+/// The mode of files created without one
+const FILE_MODE: u32 = 0o644;
+/// The mode of directories created without one
+const DIR_MODE: u32 = 0o755;
+/// `FSF3_SYMLINK | FSF3_HOMOGENEOUS | FSF3_CANSETTIME`, hard links being
+/// out of reach of `VirtualFs::link`
+const FS_PROPERTIES: u32 = 0x2 | 0x8 | 0x10;
+
+pub(crate) const NULL: u32 = 0;
+pub(crate) const GETATTR: u32 = 1;
+pub(crate) const SETATTR: u32 = 2;
+pub(crate) const LOOKUP: u32 = 3;
+pub(crate) const ACCESS: u32 = 4;
+pub(crate) const READLINK: u32 = 5;
+pub(crate) const READ: u32 = 6;
+pub(crate) const WRITE: u32 = 7;
+pub(crate) const CREATE: u32 = 8;
+pub(crate) const MKDIR: u32 = 9;
+pub(crate) const SYMLINK: u32 = 10;
+pub(crate) const MKNOD: u32 = 11;
+pub(crate) const REMOVE: u32 = 12;
+pub(crate) const RMDIR: u32 = 13;
+pub(crate) const RENAME: u32 = 14;
+pub(crate) const LINK: u32 = 15;
+pub(crate) const READDIR: u32 = 16;
+pub(crate) const READDIRPLUS: u32 = 17;
+pub(crate) const FSSTAT: u32 = 18;
+pub(crate) const FSINFO: u32 = 19;
+pub(crate) const PATHCONF: u32 = 20;
+pub(crate) const COMMIT: u32 = 21;
+
+/// `ACCESS3_READ`
+pub(crate) const ACCESS_READ: u32 = 0x1;
+/// `ACCESS3_LOOKUP`
+pub(crate) const ACCESS_LOOKUP: u32 = 0x2;
+/// `ACCESS3_MODIFY`
+pub(crate) const ACCESS_MODIFY: u32 = 0x4;
+/// `ACCESS3_EXTEND`
+pub(crate) const ACCESS_EXTEND: u32 = 0x8;
+/// `ACCESS3_DELETE`
+pub(crate) const ACCESS_DELETE: u32 = 0x10;
+/// `ACCESS3_EXECUTE`
+pub(crate) const ACCESS_EXECUTE: u32 = 0x20;
+
+/// `stable_how` of writes the server may keep in memory until a `COMMIT`
+pub(crate) const UNSTABLE: u32 = 0;
+/// `stable_how` of writes whose data is durable when they return
+pub(crate) const DATA_SYNC: u32 = 1;
+/// `stable_how` of writes whose data and attributes are durable when they
+/// return
+pub(crate) const FILE_SYNC: u32 = 2;
+
+/// `createmode3` replacing an existing file
+pub(crate) const UNCHECKED: u32 = 0;
+/// `createmode3` failing if the file exists
+pub(crate) const GUARDED: u32 = 1;
+/// `createmode3` failing if the file exists unless created by the same
+/// call, told by a verifier
+pub(crate) const EXCLUSIVE: u32 = 2;
+
+/// `ftype3` of a regular file
+const NF3REG: u32 = 1;
+/// `ftype3` of a directory
+const NF3DIR: u32 = 2;
+/// `ftype3` of a block device
+const NF3BLK: u32 = 3;
+/// `ftype3` of a character device
+const NF3CHR: u32 = 4;
+/// `ftype3` of a symbolic link
+const NF3LNK: u32 = 5;
+/// `ftype3` of a socket
+const NF3SOCK: u32 = 6;
+/// `ftype3` of a named pipe
+const NF3FIFO: u32 = 7;
+
+/// The status of an NFS procedure, `nfsstat3`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0,
+    Perm = 1,
+    NoEnt = 2,
+    Io = 5,
+    Acces = 13,
+    Exist = 17,
+    XDev = 18,
+    NotDir = 20,
+    IsDir = 21,
+    Inval = 22,
+    FBig = 27,
+    NoSpc = 28,
+    RoFs = 30,
+    NotEmpty = 66,
+    Stale = 70,
+    BadHandle = 10001,
+    NotSync = 10002,
+    BadCookie = 10003,
+    NotSupp = 10004,
+    TooSmall = 10005,
+    ServerFault = 10006,
+    BadType = 10007,
+    Jukebox = 10008,
+}
+
+impl From<DatenLordError> for Status {
+    fn from(e: DatenLordError) -> Self {
+        match e.errno() {
+            Some(Errno::EPERM) => Self::Perm,
+            Some(Errno::ENOENT) => Self::NoEnt,
+            Some(Errno::EACCES) => Self::Acces,
+            Some(Errno::EEXIST) => Self::Exist,
+            Some(Errno::EXDEV) => Self::XDev,
+            Some(Errno::ENOTDIR) => Self::NotDir,
+            Some(Errno::EISDIR) => Self::IsDir,
+            Some(Errno::EINVAL) => Self::Inval,
+            Some(Errno::EFBIG) => Self::FBig,
+            Some(Errno::ENOTSUP) => Self::NotSupp,
+            Some(Errno::ENOSPC) => Self::NoSpc,
+            Some(Errno::EROFS) => Self::RoFs,
+            Some(Errno::ENOTEMPTY) => Self::NotEmpty,
+            // Clients retry after a while
+            Some(Errno::ETIMEDOUT | Errno::EAGAIN) => Self::Jukebox,
+            Some(Errno::ESHUTDOWN) => Self::ServerFault,
+            _ => Self::Io,
+        }
+    }
+}
+
+/// The status of a lookup failing with `e`, backends reporting missing
+/// entries without an errno
+fn not_found(e: DatenLordError) -> Status {
+    if e.errno().is_none() {
+        Status::NoEnt
+    } else {
+        e.into()
+    }
+}
+
+/// The number of `FALSE` booleans the results of `procedure` hold when it
+/// fails, leaving out every optional attribute
+fn failure_len(procedure: u32) -> usize {
+    match procedure {
+        GETATTR => 0,
+        SETATTR | WRITE | CREATE | MKDIR | SYMLINK | MKNOD | REMOVE | RMDIR | COMMIT => 2,
+        LINK => 3,
+        RENAME => 4,
+        _ => 1,
+    }
+}
+
+/// The attributes `SETATTR` sets, and those created entries get, `sattr3`
+#[derive(Debug, Clone, Copy, Default)]
+struct SetAttrs {
+    mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    size: Option<u64>,
+    atime: Option<SystemTime>,
+    mtime: Option<SystemTime>,
+}
+
+impl SetAttrs {
+    fn decode(args: &mut XdrReader<'_>) -> DatenLordResult<Self> {
+        let mode = if args.bool()? {
+            Some(args.u32()?)
+        } else {
+            None
+        };
+        let uid = if args.bool()? {
+            Some(args.u32()?)
+        } else {
+            None
+        };
+        let gid = if args.bool()? {
+            Some(args.u32()?)
+        } else {
+            None
+        };
+        let size = if args.bool()? {
+            Some(args.u64()?)
+        } else {
+            None
+        };
+        Ok(Self {
+            mode,
+            uid,
+            gid,
+            size,
+            atime: Self::decode_time(args)?,
+            mtime: Self::decode_time(args)?,
+        })
+    }
+
+    /// A `set_atime` or `set_mtime`
+    fn decode_time(args: &mut XdrReader<'_>) -> DatenLordResult<Option<SystemTime>> {
+        match args.u32()? {
+            0 => Ok(None),
+            1 => Ok(Some(SystemTime::now())),
+            2 => decode_nfstime(args).map(Some),
+            how => Err(DatenLordError::InvalidArgument {
+                context: vec![format!("invalid time_how {how}")],
+            }),
+        }
+    }
+
+    /// The attributes but the mode, given to the entry just created with it
+    fn without_mode(self) -> Self {
+        Self { mode: None, ..self }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.mode.is_none()
+            && self.uid.is_none()
+            && self.gid.is_none()
+            && self.size.is_none()
+            && self.atime.is_none()
+            && self.mtime.is_none()
+    }
+
+    fn param(self) -> SetAttrParam {
+        SetAttrParam {
+            mode: self.mode,
+            u_id: self.uid,
+            g_id: self.gid,
+            size: self.size,
+            a_time: self.atime,
+            m_time: self.mtime,
+            ..SetAttrParam::default()
+        }
+    }
+}
+
+/// How `CREATE` creates a file, `createhow3`
+#[derive(Debug)]
+enum CreateHow {
+    Unchecked(SetAttrs),
+    Guarded(SetAttrs),
+    Exclusive([u8; 8]),
+}
+
+impl CreateHow {
+    fn decode(args: &mut XdrReader<'_>) -> DatenLordResult<Self> {
+        match args.u32()? {
+            UNCHECKED => SetAttrs::decode(args).map(Self::Unchecked),
+            GUARDED => SetAttrs::decode(args).map(Self::Guarded),
+            EXCLUSIVE => {
+                let mut verifier = [0; 8];
+                verifier.copy_from_slice(args.fixed(8)?);
+                Ok(Self::Exclusive(verifier))
+            }
+            mode => Err(DatenLordError::InvalidArgument {
+                context: vec![format!("invalid createmode3 {mode}")],
+            }),
+        }
+    }
+}
+
+/// The times `CREATE` stores the verifier of an `EXCLUSIVE` create in, the
+/// seconds of the access and modification times like Linux servers do
+fn verifier_times(verifier: &[u8; 8]) -> (SystemTime, SystemTime) {
+    let secs = |bytes: &[u8]| {
+        let secs = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        UNIX_EPOCH + Duration::from_secs(secs.into())
+    };
+    (secs(&verifier[..4]), secs(&verifier[4..]))
+}
+
+/// A `nfstime3`
+fn decode_nfstime(args: &mut XdrReader<'_>) -> DatenLordResult<SystemTime> {
+    let (sec, nsec) = (args.u32()?, args.u32()?);
+    fs_util::from_timespec(sec.into(), nsec).ok_or_else(|| DatenLordError::InvalidArgument {
+        context: vec![format!("invalid nfstime3 {sec}.{nsec:09}")],
+    })
+}
+
+/// The seconds and nanoseconds of `time` as a `nfstime3` holds them, times
+/// before the epoch or after 2106 being clamped
+fn nfstime(time: SystemTime) -> (u32, u32) {
+    let (sec, nsec) = fs_util::to_timespec(time);
+    (u32::try_from(sec.max(0)).unwrap_or(u32::MAX), nsec)
+}
+
+fn encode_nfstime(out: &mut XdrWriter, time: SystemTime) {
+    let (sec, nsec) = nfstime(time);
+    out.u32(sec).u32(nsec);
+}
+
+/// The `ftype3` of files of type `kind`
+fn ftype(kind: SFlag) -> u32 {
+    match kind {
+        SFlag::S_IFDIR => NF3DIR,
+        SFlag::S_IFBLK => NF3BLK,
+        SFlag::S_IFCHR => NF3CHR,
+        SFlag::S_IFLNK => NF3LNK,
+        SFlag::S_IFSOCK => NF3SOCK,
+        SFlag::S_IFIFO => NF3FIFO,
+        _ => NF3REG,
+    }
+}
+
+/// A `fattr3`
+fn encode_fattr(out: &mut XdrWriter, attr: &FileAttr) {
+    let rdev = u64::from(attr.rdev);
+    let device = |number: u64| u32::try_from(number).unwrap_or(u32::MAX);
+    out.u32(ftype(attr.kind))
+        .u32(attr.perm.into())
+        .u32(attr.nlink)
+        .u32(attr.uid)
+        .u32(attr.gid)
+        .u64(attr.size)
+        .u64(attr.blocks.saturating_mul(512))
+        .u32(device(stat::major(rdev)))
+        .u32(device(stat::minor(rdev)))
+        .u64(FSID)
+        .u64(attr.ino);
+    encode_nfstime(out, attr.atime);
+    encode_nfstime(out, attr.mtime);
+    encode_nfstime(out, attr.ctime);
+}
+
+/// A `post_op_attr`
+fn encode_post_op(out: &mut XdrWriter, attr: Option<&FileAttr>) {
+    out.bool(attr.is_some());
+    if let Some(attr) = attr {
+        encode_fattr(out, attr);
+    }
+}
+
+/// A `wcc_data` of an inode with the attributes `before` and `after` the
+/// change
+fn encode_wcc(out: &mut XdrWriter, before: &FileAttr, after: Option<&FileAttr>) {
+    out.bool(true).u64(before.size);
+    encode_nfstime(out, before.mtime);
+    encode_nfstime(out, before.ctime);
+    encode_post_op(out, after);
+}
+
+/// The size of the name of an entry encoded in a listing
+fn encoded_len(name: &[u8]) -> usize {
+    4 + name.len().div_ceil(4) * 4
+}
+
+/// An inode a file handle names, with the caller acting on it
+struct Target<'a> {
+    /// The index of the export the handle was handed out through
+    index: usize,
+    export: &'a Export,
+    ino: INum,
+    ctx: RequestContext,
+    /// The attributes before the procedure runs
+    attr: FileAttr,
+}
+
+impl Target<'_> {
+    /// Fail with `NFS3ERR_ROFS` through read-only exports
+    fn writable(&self) -> Result<(), Status> {
+        if self.export.config.read_only {
+            Err(Status::RoFs)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fail with `NFS3ERR_NOTDIR` unless the inode is a directory
+    fn directory(&self) -> Result<(), Status> {
+        if self.attr.kind == SFlag::S_IFDIR {
+            Ok(())
+        } else {
+            Err(Status::NotDir)
+        }
+    }
+}
+
+impl<F: VirtualFs + 'static> NfsServer<F> {
+    /// The results of the NFS call `call`, `None` if its procedure does not
+    /// exist
+    pub(super) async fn nfs3(&self, call: &mut Call<'_>) -> DatenLordResult<Option<XdrWriter>> {
+        let cred = call.cred.as_ref();
+        let args = &mut call.args;
+        let result = match call.procedure {
+            NULL => return Ok(Some(XdrWriter::new())),
+            GETATTR => self.getattr(cred, args.opaque(FHSIZE)?).await,
+            SETATTR => {
+                let handle = args.opaque(FHSIZE)?;
+                let attrs = SetAttrs::decode(args)?;
+                let guard = if args.bool()? {
+                    Some(decode_nfstime(args)?)
+                } else {
+                    None
+                };
+                self.setattr(cred, handle, attrs, guard).await
+            }
+            LOOKUP => {
+                let (dir, name) = (args.opaque(FHSIZE)?, args.opaque(MAX_PATH)?);
+                self.lookup(cred, dir, name).await
+            }
+            ACCESS => {
+                let handle = args.opaque(FHSIZE)?;
+                self.access(cred, handle, args.u32()?).await
+            }
+            READLINK => self.readlink(cred, args.opaque(FHSIZE)?).await,
+            READ => {
+                let handle = args.opaque(FHSIZE)?;
+                let (offset, count) = (args.u64()?, args.u32()?);
+                self.read(cred, handle, offset, count).await
+            }
+            WRITE => {
+                let handle = args.opaque(FHSIZE)?;
+                let (offset, count, stable) = (args.u64()?, args.u32()?, args.u32()?);
+                let data = args.opaque(MAX_IO as usize)?;
+                let data = &data[..data.len().min(count as usize)];
+                self.write(cred, handle, offset, stable, data).await
+            }
+            CREATE => {
+                let (dir, name) = (args.opaque(FHSIZE)?, args.opaque(MAX_PATH)?);
+                let how = CreateHow::decode(args)?;
+                self.create(cred, dir, name, how).await
+            }
+            MKDIR => {
+                let (dir, name) = (args.opaque(FHSIZE)?, args.opaque(MAX_PATH)?);
+                let attrs = SetAttrs::decode(args)?;
+                self.mkdir(cred, dir, name, attrs).await
+            }
+            SYMLINK => {
+                let (dir, name) = (args.opaque(FHSIZE)?, args.opaque(MAX_PATH)?);
+                let attrs = SetAttrs::decode(args)?;
+                let target = args.opaque(MAX_PATH)?;
+                self.symlink(cred, dir, name, attrs, target).await
+            }
+            MKNOD => {
+                let (dir, name) = (args.opaque(FHSIZE)?, args.opaque(MAX_PATH)?);
+                let node = match args.u32()? {
+                    kind @ (NF3CHR | NF3BLK) => {
+                        let attrs = SetAttrs::decode(args)?;
+                        let (major, minor) = (args.u32()?, args.u32()?);
+                        let node_type = if kind == NF3CHR {
+                            SFlag::S_IFCHR
+                        } else {
+                            SFlag::S_IFBLK
+                        };
+                        Some((node_type, attrs, stat::makedev(major.into(), minor.into())))
+                    }
+                    NF3SOCK => Some((SFlag::S_IFSOCK, SetAttrs::decode(args)?, 0)),
+                    NF3FIFO => Some((SFlag::S_IFIFO, SetAttrs::decode(args)?, 0)),
+                    _ => None,
+                };
+                self.mknod(cred, dir, name, node).await
+            }
+            REMOVE | RMDIR => {
+                let (dir, name) = (args.opaque(FHSIZE)?, args.opaque(MAX_PATH)?);
+                self.remove(cred, dir, name, call.procedure == RMDIR).await
+            }
+            RENAME => {
+                let (from_dir, from_name) = (args.opaque(FHSIZE)?, args.opaque(MAX_PATH)?);
+                let (to_dir, to_name) = (args.opaque(FHSIZE)?, args.opaque(MAX_PATH)?);
+                self.rename(cred, (from_dir, from_name), (to_dir, to_name))
+                    .await
+            }
+            LINK => {
+                args.opaque(FHSIZE)?;
+                args.opaque(FHSIZE)?;
+                args.opaque(MAX_PATH)?;
+                Err(Status::NotSupp)
+            }
+            READDIR => {
+                let (dir, cookie) = (args.opaque(FHSIZE)?, args.u64()?);
+                args.fixed(8)?;
+                let count = args.u32()?;
+                self.readdir(cred, dir, cookie, count, None).await
+            }
+            READDIRPLUS => {
+                let (dir, cookie) = (args.opaque(FHSIZE)?, args.u64()?);
+                args.fixed(8)?;
+                let (dircount, maxcount) = (args.u32()?, args.u32()?);
+                self.readdir(cred, dir, cookie, maxcount, Some(dircount))
+                    .await
+            }
+            FSSTAT => self.fsstat(cred, args.opaque(FHSIZE)?).await,
+            FSINFO => self.fsinfo(cred, args.opaque(FHSIZE)?).await,
+            PATHCONF => self.pathconf(cred, args.opaque(FHSIZE)?).await,
+            COMMIT => {
+                let handle = args.opaque(FHSIZE)?;
+                // The whole file is synced whatever the range
+                args.u64()?;
+                args.u32()?;
+                self.commit(cred, handle).await
+            }
+            _ => return Ok(None),
+        };
+        let mut results = XdrWriter::new();
+        match result {
+            Ok(body) => {
+                results.u32(Status::Ok as u32).append(body);
+            }
+            Err(status) => {
+                results.u32(status as u32);
+                for _ in 0..failure_len(call.procedure) {
+                    results.bool(false);
+                }
+            }
+        }
+        Ok(Some(results))
+    }
+
+    /// The inode the file handle `handle` names, with its current attributes
+    async fn target(&self, cred: Option<&UnixCred>, handle: &[u8]) -> Result<Target<'_>, Status> {
+        let (index, export, ino) = self.resolve(handle).ok_or(Status::BadHandle)?;
+        let ctx = export.context(cred);
+        let (_, attr) = self
+            .fs
+            .getattr(&ctx, ino)
+            .await
+            .map_err(|_| Status::Stale)?;
+        Ok(Target {
+            index,
+            export,
+            ino,
+            ctx,
+            attr,
+        })
+    }
+
+    /// The attributes of `ino` after a change, `None` if they cannot be had
+    async fn attr_after(&self, target: &Target<'_>, ino: INum) -> Option<FileAttr> {
+        self.fs
+            .getattr(&target.ctx, ino)
+            .await
+            .ok()
+            .map(|(_, attr)| attr)
+    }
+
+    /// The entry `name` of the directory `dir`, `None` if it cannot be looked up
+    async fn entry(&self, dir: &Target<'_>, name: &OsStr) -> Option<FileAttr> {
+        self.fs
+            .lookup(&dir.ctx, dir.ino, name)
+            .await
+            .ok()
+            .map(|(_, attr, _)| attr)
+    }
+
+    /// Set `attrs` on the entry with `attr` unless there are none
+    async fn set_attrs(
+        &self,
+        ctx: &RequestContext,
+        attr: FileAttr,
+        attrs: SetAttrs,
+    ) -> Result<FileAttr, Status> {
+        if attrs.is_empty() {
+            return Ok(attr);
+        }
+        Ok(self.fs.setattr(ctx, attr.ino, attrs.param()).await?.1)
+    }
+
+    /// The results of creating the entry with `attr` in the directory `dir`
+    async fn created(&self, dir: &Target<'_>, attr: &FileAttr) -> XdrWriter {
+        let mut out = XdrWriter::new();
+        out.bool(true).opaque(&Self::handle(dir.index, attr.ino));
+        encode_post_op(&mut out, Some(attr));
+        encode_wcc(
+            &mut out,
+            &dir.attr,
+            self.attr_after(dir, dir.ino).await.as_ref(),
+        );
+        out
+    }
+
+    async fn getattr(&self, cred: Option<&UnixCred>, handle: &[u8]) -> Result<XdrWriter, Status> {
+        let target = self.target(cred, handle).await?;
+        let mut out = XdrWriter::new();
+        encode_fattr(&mut out, &target.attr);
+        Ok(out)
+    }
+
+    async fn setattr(
+        &self,
+        cred: Option<&UnixCred>,
+        handle: &[u8],
+        attrs: SetAttrs,
+        guard: Option<SystemTime>,
+    ) -> Result<XdrWriter, Status> {
+        let target = self.target(cred, handle).await?;
+        target.writable()?;
+        if guard.is_some_and(|ctime| nfstime(ctime) != nfstime(target.attr.ctime)) {
+            return Err(Status::NotSync);
+        }
+        let (_, after) = self
+            .fs
+            .setattr(&target.ctx, target.ino, attrs.param())
+            .await?;
+        let mut out = XdrWriter::new();
+        encode_wcc(&mut out, &target.attr, Some(&after));
+        Ok(out)
+    }
+
+    async fn lookup(
+        &self,
+        cred: Option<&UnixCred>,
+        dir: &[u8],
+        name: &[u8],
+    ) -> Result<XdrWriter, Status> {
+        let dir = self.target(cred, dir).await?;
+        dir.directory()?;
+        let attr = match name {
+            // Clients stay within the export they mounted
+            b"." => dir.attr,
+            b".." if dir.ino == dir.export.root => dir.attr,
+            _ => {
+                let name = OsStr::from_bytes(name);
+                self.fs
+                    .lookup(&dir.ctx, dir.ino, name)
+                    .await
+                    .map_err(not_found)?
+                    .1
+            }
+        };
+        let mut out = XdrWriter::new();
+        out.opaque(&Self::handle(dir.index, attr.ino));
+        encode_post_op(&mut out, Some(&attr));
+        encode_post_op(&mut out, Some(&dir.attr));
+        Ok(out)
+    }
+
+    async fn access(
+        &self,
+        cred: Option<&UnixCred>,
+        handle: &[u8],
+        requested: u32,
+    ) -> Result<XdrWriter, Status> {
+        let target = self.target(cred, handle).await?;
+        let checks = [
+            (ACCESS_READ, 0o4),
+            (ACCESS_LOOKUP | ACCESS_EXECUTE, 0o1),
+            (ACCESS_MODIFY | ACCESS_EXTEND | ACCESS_DELETE, 0o2),
+        ];
+        let mut granted = 0;
+        for (bits, mask) in checks {
+            let bits = requested & bits;
+            if bits == 0 || (mask == 0o2 && target.writable().is_err()) {
+                continue;
+            }
+            match self.fs.access(&target.ctx, target.ino, mask).await {
+                // Backends that do not check access check the operations
+                Ok(()) | Err(DatenLordError::Unimplemented { .. }) => granted |= bits,
+                Err(_) => {}
+            }
+        }
+        let mut out = XdrWriter::new();
+        encode_post_op(&mut out, Some(&target.attr));
+        out.u32(granted);
+        Ok(out)
+    }
+
+    async fn readlink(&self, cred: Option<&UnixCred>, handle: &[u8]) -> Result<XdrWriter, Status> {
+        let target = self.target(cred, handle).await?;
+        if target.attr.kind != SFlag::S_IFLNK {
+            return Err(Status::Inval);
+        }
+        let link = self.fs.readlink(&target.ctx, target.ino).await?;
+        let mut out = XdrWriter::new();
+        encode_post_op(&mut out, Some(&target.attr));
+        out.opaque(&link);
+        Ok(out)
+    }
+
+    async fn read(
+        &self,
+        cred: Option<&UnixCred>,
+        handle: &[u8],
+        offset: u64,
+        count: u32,
+    ) -> Result<XdrWriter, Status> {
+        let target = self.target(cred, handle).await?;
+        if target.attr.kind == SFlag::S_IFDIR {
+            return Err(Status::IsDir);
+        }
+        let (ctx, ino) = (&target.ctx, target.ino);
+        let count = count.min(MAX_IO);
+        let fh = self
+            .fs
+            .open(ctx, ino, OFlag::O_RDONLY.bits() as u32)
+            .await?;
+        let mut buf = vec![0; count as usize];
+        let read = self.fs.read(ctx, ino, fh, offset, count, &mut buf).await;
+        let released = self.fs.release(ctx, ino, fh, 0, 0, true).await;
+        let read = read?;
+        released?;
+        let eof = offset.saturating_add(read as u64) >= target.attr.size;
+        let mut out = XdrWriter::new();
+        encode_post_op(&mut out, Some(&target.attr));
+        out.u32(read as u32).bool(eof).opaque(&buf[..read]);
+        Ok(out)
+    }
+
+    async fn write(
+        &self,
+        cred: Option<&UnixCred>,
+        handle: &[u8],
+        offset: u64,
+        stable: u32,
+        data: &[u8],
+    ) -> Result<XdrWriter, Status> {
+        let target = self.target(cred, handle).await?;
+        target.writable()?;
+        if target.attr.kind == SFlag::S_IFDIR {
+            return Err(Status::IsDir);
+        }
+        let offset = i64::try_from(offset).map_err(|_| Status::FBig)?;
+        let (ctx, ino) = (&target.ctx, target.ino);
+        let fh = self
+            .fs
+            .open(ctx, ino, OFlag::O_WRONLY.bits() as u32)
+            .await?;
+        let mut written = self.fs.write(ctx, ino, fh, offset, data, 0).await;
+        if written.is_ok() && stable != UNSTABLE {
+            written = self.fs.fsync(ctx, ino, fh, stable == DATA_SYNC).await;
+        }
+        let released = self.fs.release(ctx, ino, fh, 0, 0, true).await;
+        written?;
+        released?;
+        let mut out = XdrWriter::new();
+        encode_wcc(
+            &mut out,
+            &target.attr,
+            self.attr_after(&target, ino).await.as_ref(),
+        );
+        out.u32(data.len() as u32)
+            .u32(stable.min(FILE_SYNC))
+            .fixed(&self.verifier);
+        Ok(out)
+    }
+
+    async fn create(
+        &self,
+        cred: Option<&UnixCred>,
+        dir: &[u8],
+        name: &[u8],
+        how: CreateHow,
+    ) -> Result<XdrWriter, Status> {
+        let dir = self.target(cred, dir).await?;
+        dir.writable()?;
+        dir.directory()?;
+        let name = OsStr::from_bytes(name);
+        let mode = match how {
+            CreateHow::Unchecked(attrs) | CreateHow::Guarded(attrs) => attrs.mode,
+            CreateHow::Exclusive(_) => None,
+        };
+        let param = CreateParam {
+            parent: dir.ino,
+            name: name.to_owned(),
+            mode: mode.unwrap_or(FILE_MODE),
+            rdev: 0,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        let attr = match self.fs.mknod(&dir.ctx, param).await {
+            Ok((_, attr, _)) => match how {
+                CreateHow::Unchecked(attrs) | CreateHow::Guarded(attrs) => {
+                    self.set_attrs(&dir.ctx, attr, attrs.without_mode()).await?
+                }
+                CreateHow::Exclusive(ref verifier) => {
+                    let (atime, mtime) = verifier_times(verifier);
+                    let attrs = SetAttrs {
+                        atime: Some(atime),
+                        mtime: Some(mtime),
+                        ..SetAttrs::default()
+                    };
+                    self.set_attrs(&dir.ctx, attr, attrs).await?
+                }
+            },
+            Err(DatenLordError::AlreadyExists { .. }) => {
+                let existing = self.entry(&dir, name).await.ok_or(Status::Exist)?;
+                match how {
+                    CreateHow::Unchecked(attrs) if existing.kind == SFlag::S_IFREG => {
+                        // Only the size applies to an existing file, to truncate it
+                        let attrs = SetAttrs {
+                            size: attrs.size,
+                            ..SetAttrs::default()
+                        };
+                        self.set_attrs(&dir.ctx, existing, attrs).await?
+                    }
+                    // A retransmission of the create that made the file
+                    CreateHow::Exclusive(ref verifier)
+                        if nfstime(existing.atime).0 == nfstime(verifier_times(verifier).0).0
+                            && nfstime(existing.mtime).0
+                                == nfstime(verifier_times(verifier).1).0 =>
+                    {
+                        existing
+                    }
+                    _ => return Err(Status::Exist),
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(self.created(&dir, &attr).await)
+    }
+
+    async fn mkdir(
+        &self,
+        cred: Option<&UnixCred>,
+        dir: &[u8],
+        name: &[u8],
+        attrs: SetAttrs,
+    ) -> Result<XdrWriter, Status> {
+        let dir = self.target(cred, dir).await?;
+        dir.writable()?;
+        dir.directory()?;
+        let param = CreateParam {
+            parent: dir.ino,
+            name: OsStr::from_bytes(name).to_owned(),
+            mode: attrs.mode.unwrap_or(DIR_MODE),
+            rdev: 0,
+            node_type: SFlag::S_IFDIR,
+            link: None,
+        };
+        let (_, attr, _) = self.fs.mkdir(&dir.ctx, param).await?;
+        let attr = self.set_attrs(&dir.ctx, attr, attrs.without_mode()).await?;
+        Ok(self.created(&dir, &attr).await)
+    }
+
+    async fn symlink(
+        &self,
+        cred: Option<&UnixCred>,
+        dir: &[u8],
+        name: &[u8],
+        attrs: SetAttrs,
+        link: &[u8],
+    ) -> Result<XdrWriter, Status> {
+        let dir = self.target(cred, dir).await?;
+        dir.writable()?;
+        dir.directory()?;
+        let name = OsStr::from_bytes(name);
+        let link = Path::new(OsStr::from_bytes(link));
+        let (_, attr, _) = self.fs.symlink(&dir.ctx, dir.ino, name, link).await?;
+        // The mode of a symbolic link means nothing
+        let attr = self.set_attrs(&dir.ctx, attr, attrs.without_mode()).await?;
+        Ok(self.created(&dir, &attr).await)
+    }
+
+    async fn mknod(
+        &self,
+        cred: Option<&UnixCred>,
+        dir: &[u8],
+        name: &[u8],
+        node: Option<(SFlag, SetAttrs, u64)>,
+    ) -> Result<XdrWriter, Status> {
+        let dir = self.target(cred, dir).await?;
+        dir.writable()?;
+        dir.directory()?;
+        let (node_type, attrs, rdev) = node.ok_or(Status::BadType)?;
+        let param = CreateParam {
+            parent: dir.ino,
+            name: OsStr::from_bytes(name).to_owned(),
+            mode: attrs.mode.unwrap_or(FILE_MODE),
+            rdev: u32::try_from(rdev).map_err(|_| Status::Inval)?,
+            node_type,
+            link: None,
+        };
+        let (_, attr, _) = self.fs.mknod(&dir.ctx, param).await?;
+        let attr = self.set_attrs(&dir.ctx, attr, attrs.without_mode()).await?;
+        Ok(self.created(&dir, &attr).await)
+    }
+
+    /// `REMOVE`, or `RMDIR` if `rmdir`
+    async fn remove(
+        &self,
+        cred: Option<&UnixCred>,
+        dir: &[u8],
+        name: &[u8],
+        rmdir: bool,
+    ) -> Result<XdrWriter, Status> {
+        let dir = self.target(cred, dir).await?;
+        dir.writable()?;
+        dir.directory()?;
+        let name = OsStr::from_bytes(name);
+        let removed = if rmdir {
+            self.fs.rmdir(&dir.ctx, dir.ino, name).await.map(|_| ())
+        } else {
+            self.fs.unlink(&dir.ctx, dir.ino, name).await
+        };
+        if let Err(e) = removed {
+            if e.errno().is_some() {
+                return Err(e.into());
+            }
+            // Tell apart the failures backends report without an errno
+            return Err(match self.entry(&dir, name).await {
+                None => Status::NoEnt,
+                Some(attr) if rmdir && attr.kind != SFlag::S_IFDIR => Status::NotDir,
+                Some(_) if rmdir => Status::NotEmpty,
+                Some(attr) if attr.kind == SFlag::S_IFDIR => Status::IsDir,
+                Some(_) => Status::Io,
+            });
+        }
+        let mut out = XdrWriter::new();
+        encode_wcc(
+            &mut out,
+            &dir.attr,
+            self.attr_after(&dir, dir.ino).await.as_ref(),
+        );
+        Ok(out)
+    }
+
+    async fn rename(
+        &self,
+        cred: Option<&UnixCred>,
+        (from_dir, from_name): (&[u8], &[u8]),
+        (to_dir, to_name): (&[u8], &[u8]),
+    ) -> Result<XdrWriter, Status> {
+        let from = self.target(cred, from_dir).await?;
+        let to = self.target(cred, to_dir).await?;
+        from.writable()?;
+        to.writable()?;
+        if from.index != to.index {
+            return Err(Status::XDev);
+        }
+        from.directory()?;
+        to.directory()?;
+        let (from_name, to_name) = (OsStr::from_bytes(from_name), OsStr::from_bytes(to_name));
+        let param = RenameParam {
+            old_parent: from.ino,
+            old_name: from_name.to_owned(),
+            new_parent: to.ino,
+            new_name: to_name.to_owned(),
+            flags: 0,
+        };
+        if let Err(e) = self.fs.rename(&from.ctx, param).await {
+            if e.errno().is_some() {
+                return Err(e.into());
+            }
+            let Some(source) = self.entry(&from, from_name).await else {
+                return Err(Status::NoEnt);
+            };
+            return Err(match self.entry(&to, to_name).await {
+                Some(dest) if dest.kind == SFlag::S_IFDIR && source.kind == SFlag::S_IFDIR => {
+                    Status::NotEmpty
+                }
+                Some(dest) if dest.kind == SFlag::S_IFDIR => Status::IsDir,
+                _ => Status::Io,
+            });
+        }
+        let mut out = XdrWriter::new();
+        encode_wcc(
+            &mut out,
+            &from.attr,
+            self.attr_after(&from, from.ino).await.as_ref(),
+        );
+        encode_wcc(
+            &mut out,
+            &to.attr,
+            self.attr_after(&to, to.ino).await.as_ref(),
+        );
+        Ok(out)
+    }
+
+    /// The entries of the directory `dir` from `cookie` on, with their
+    /// attributes if `plus`
+    async fn list(
+        &self,
+        dir: &Target<'_>,
+        cookie: u64,
+        plus: bool,
+    ) -> Result<Vec<(DirEntry, Option<FileAttr>)>, Status> {
+        let offset = i64::try_from(cookie).map_err(|_| Status::BadCookie)?;
+        let (ctx, ino) = (&dir.ctx, dir.ino);
+        let fh = self.fs.opendir(ctx, ino, 0).await?;
+        let listed = if plus {
+            self.fs
+                .readdirplus(ctx, ino, fh, offset)
+                .await
+                .map(|entries| {
+                    entries
+                        .into_iter()
+                        .map(|(entry, attr, _)| (entry, Some(attr)))
+                        .collect()
+                })
+        } else {
+            self.fs
+                .readdir(ctx, ino, fh, offset)
+                .await
+                .map(|entries| entries.into_iter().map(|entry| (entry, None)).collect())
+        };
+        let released = self.fs.releasedir(ctx, ino, fh, 0).await;
+        let listed = listed?;
+        released?;
+        Ok(listed)
+    }
+
+    /// `READDIR` returning at most `count` bytes, or `READDIRPLUS` when
+    /// given the `dircount` bytes the entries without their attributes and
+    /// handles may take
+    async fn readdir(
+        &self,
+        cred: Option<&UnixCred>,
+        dir: &[u8],
+        cookie: u64,
+        count: u32,
+        dircount: Option<u32>,
+    ) -> Result<XdrWriter, Status> {
+        let dir = self.target(cred, dir).await?;
+        dir.directory()?;
+        let entries = self.list(&dir, cookie, dircount.is_some()).await?;
+        let mut out = XdrWriter::new();
+        encode_post_op(&mut out, Some(&dir.attr));
+        // Listings are not versioned, so the cookie verifier is always zero
+        out.fixed(&[0; 8]);
+        // The status before the results and the end of the list after them
+        let (count, mut dir_len) = (count as usize, 0);
+        let mut eof = true;
+        for (index, (entry, attr)) in entries.iter().enumerate() {
+            let name = entry.name.as_bytes();
+            let entry_len = 4 + 8 + encoded_len(name) + 8;
+            let mut encoded = XdrWriter::new();
+            encoded
+                .bool(true)
+                .u64(entry.ino)
+                .opaque(name)
+                .u64(cookie + index as u64 + 1);
+            if dircount.is_some() {
+                encode_post_op(&mut encoded, attr.as_ref());
+                // The parents of entries are left to `LOOKUP`
+                let handle =
+                    (name != b"." && name != b"..").then(|| Self::handle(dir.index, entry.ino));
+                encoded.bool(handle.is_some());
+                if let Some(handle) = handle {
+                    encoded.opaque(&handle);
+                }
+            }
+            let full = 4 + out.len() + encoded.len() + 8 > count
+                || dircount.is_some_and(|dircount| dir_len + entry_len > dircount as usize);
+            if full {
+                if index == 0 {
+                    return Err(Status::TooSmall);
+                }
+                eof = false;
+                break;
+            }
+            dir_len += entry_len;
+            out.append(encoded);
+        }
+        out.bool(false).bool(eof);
+        Ok(out)
+    }
+
+    async fn fsstat(&self, cred: Option<&UnixCred>, handle: &[u8]) -> Result<XdrWriter, Status> {
+        let target = self.target(cred, handle).await?;
+        let stat = self.fs.statfs(&target.ctx, target.ino).await?;
+        let block = u64::from(if stat.frsize == 0 {
+            stat.bsize
+        } else {
+            stat.frsize
+        });
+        let mut out = XdrWriter::new();
+        encode_post_op(&mut out, Some(&target.attr));
+        out.u64(stat.blocks.saturating_mul(block))
+            .u64(stat.bfree.saturating_mul(block))
+            .u64(stat.bavail.saturating_mul(block))
+            .u64(stat.files)
+            .u64(stat.f_free)
+            .u64(stat.f_free)
+            // The counts may change at any time
+            .u32(0);
+        Ok(out)
+    }
+
+    async fn fsinfo(&self, cred: Option<&UnixCred>, handle: &[u8]) -> Result<XdrWriter, Status> {
+        let target = self.target(cred, handle).await?;
+        let mut out = XdrWriter::new();
+        encode_post_op(&mut out, Some(&target.attr));
+        out.u32(MAX_IO)
+            .u32(MAX_IO)
+            .u32(PREFERRED_MULTIPLE)
+            .u32(MAX_IO)
+            .u32(MAX_IO)
+            .u32(PREFERRED_MULTIPLE)
+            .u32(PREFERRED_READDIR)
+            .u64(u64::MAX)
+            // Times are kept to the nanosecond
+            .u32(0)
+            .u32(1)
+            .u32(FS_PROPERTIES);
+        Ok(out)
+    }
+
+    async fn pathconf(&self, cred: Option<&UnixCred>, handle: &[u8]) -> Result<XdrWriter, Status> {
+        let target = self.target(cred, handle).await?;
+        let stat = self.fs.statfs(&target.ctx, target.ino).await?;
+        let name_max = if stat.namelen == 0 {
+            DEFAULT_NAME_MAX
+        } else {
+            stat.namelen
+        };
+        let mut out = XdrWriter::new();
+        encode_post_op(&mut out, Some(&target.attr));
+        out.u32(LINK_MAX)
+            .u32(name_max)
+            // no_trunc, chown_restricted, case_insensitive, case_preserving
+            .bool(true)
+            .bool(true)
+            .bool(false)
+            .bool(true);
+        Ok(out)
+    }
+
+    async fn commit(&self, cred: Option<&UnixCred>, handle: &[u8]) -> Result<XdrWriter, Status> {
+        let target = self.target(cred, handle).await?;
+        // Nothing was written through read-only exports
+        if target.writable().is_ok() {
+            let (ctx, ino) = (&target.ctx, target.ino);
+            let fh = self
+                .fs
+                .open(ctx, ino, OFlag::O_WRONLY.bits() as u32)
+                .await?;
+            let synced = self.fs.fsync(ctx, ino, fh, false).await;
+            let released = self.fs.release(ctx, ino, fh, 0, 0, true).await;
+            synced?;
+            released?;
+        }
+        let mut out = XdrWriter::new();
+        encode_wcc(
+            &mut out,
+            &target.attr,
+            self.attr_after(&target, target.ino).await.as_ref(),
+        );
+        out.fixed(&self.verifier);
+        Ok(out)
+    }
+}
@@ -0,0 +1,195 @@
+//! The ONC RPC version 2 messages of RFC 5531, framed by record marking
+//! over TCP
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::xdr::{XdrReader, XdrWriter};
+use crate::common::{DatenLordError, DatenLordResult};
+
+/// The version of the RPC protocol
+pub(crate) const RPC_VERSION: u32 = 2;
+/// The `msg_type` of a call
+pub(crate) const CALL: u32 = 0;
+/// The `msg_type` of a reply
+pub(crate) const REPLY: u32 = 1;
+/// The `reply_stat` of an accepted call
+pub(crate) const MSG_ACCEPTED: u32 = 0;
+/// The `reply_stat` of a rejected call
+pub(crate) const MSG_DENIED: u32 = 1;
+/// The `reject_stat` of a call of another RPC version
+const RPC_MISMATCH: u32 = 0;
+/// The flavor of calls without credentials
+pub(crate) const AUTH_NONE: u32 = 0;
+/// The flavor of calls with the user and group ids of the caller
+pub(crate) const AUTH_UNIX: u32 = 1;
+/// The longest credential or verifier body
+const MAX_AUTH_LEN: usize = 400;
+/// The longest machine name of `AUTH_UNIX` credentials
+const MAX_MACHINE_NAME: usize = 255;
+/// The most supplementary groups of `AUTH_UNIX` credentials
+const MAX_GROUPS: u32 = 16;
+/// The bit of a record marking header flagging the last fragment
+const LAST_FRAGMENT: u32 = 1 << 31;
+/// The longest record read, in bytes, protecting against bogus lengths
+pub(crate) const MAX_RECORD_LEN: usize = 4 << 20;
+
+/// The status of an accepted call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptStat {
+    /// The call ran, its results follow
+    Success = 0,
+    /// The program is not served
+    ProgUnavail = 1,
+    /// The version of the program is not served, the lowest and highest
+    /// ones served follow
+    ProgMismatch = 2,
+    /// The procedure does not exist
+    ProcUnavail = 3,
+    /// The arguments could not be decoded
+    GarbageArgs = 4,
+}
+
+/// The `AUTH_UNIX` credentials of a caller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixCred {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary group ids
+    pub gids: Vec<u32>,
+}
+
+/// An RPC call
+#[derive(Debug)]
+pub struct Call<'a> {
+    /// The id the reply is matched with
+    pub xid: u32,
+    /// The RPC version, replies are denied unless `RPC_VERSION`
+    pub rpc_version: u32,
+    pub program: u32,
+    pub version: u32,
+    pub procedure: u32,
+    /// The credentials, `None` for other flavors than `AUTH_UNIX`
+    pub cred: Option<UnixCred>,
+    /// The arguments of the procedure
+    pub args: XdrReader<'a>,
+}
+
+impl<'a> Call<'a> {
+    /// Decode the call `record`, failing if it is no call
+    pub fn parse(record: &'a [u8]) -> DatenLordResult<Self> {
+        let mut reader = XdrReader::new(record);
+        let xid = reader.u32()?;
+        let msg_type = reader.u32()?;
+        if msg_type != CALL {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "RPC message xid={xid} of type {msg_type} is no call"
+                )],
+            });
+        }
+        let rpc_version = reader.u32()?;
+        let program = reader.u32()?;
+        let version = reader.u32()?;
+        let procedure = reader.u32()?;
+        let flavor = reader.u32()?;
+        let body = reader.opaque(MAX_AUTH_LEN)?;
+        let cred = if flavor == AUTH_UNIX {
+            Some(Self::unix_cred(body)?)
+        } else {
+            None
+        };
+        // The verifier of `AUTH_NONE` and `AUTH_UNIX` calls is ignored
+        reader.u32()?;
+        reader.opaque(MAX_AUTH_LEN)?;
+        Ok(Self {
+            xid,
+            rpc_version,
+            program,
+            version,
+            procedure,
+            cred,
+            args: reader,
+        })
+    }
+
+    /// Decode the body of `AUTH_UNIX` credentials
+    fn unix_cred(body: &[u8]) -> DatenLordResult<UnixCred> {
+        let mut reader = XdrReader::new(body);
+        let _stamp = reader.u32()?;
+        reader.opaque(MAX_MACHINE_NAME)?;
+        let uid = reader.u32()?;
+        let gid = reader.u32()?;
+        let count = reader.u32()?.min(MAX_GROUPS);
+        let gids = (0..count)
+            .map(|_| reader.u32())
+            .collect::<DatenLordResult<_>>()?;
+        Ok(UnixCred { uid, gid, gids })
+    }
+}
+
+/// Start the reply to the call `xid` accepted with `stat`, the results
+/// being written next
+pub fn accepted(xid: u32, stat: AcceptStat) -> XdrWriter {
+    let mut reply = XdrWriter::new();
+    reply
+        .u32(xid)
+        .u32(REPLY)
+        .u32(MSG_ACCEPTED)
+        .u32(AUTH_NONE)
+        .opaque(&[])
+        .u32(stat as u32);
+    reply
+}
+
+/// The reply to the call `xid` made with another RPC version
+pub fn rpc_mismatch(xid: u32) -> XdrWriter {
+    let mut reply = XdrWriter::new();
+    reply
+        .u32(xid)
+        .u32(REPLY)
+        .u32(MSG_DENIED)
+        .u32(RPC_MISMATCH)
+        .u32(RPC_VERSION)
+        .u32(RPC_VERSION);
+    reply
+}
+
+/// Read the next record of `reader`, joining its fragments, `None` when the
+/// stream ends before it
+pub async fn read_record<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut record = Vec::new();
+    loop {
+        let header = match reader.read_u32().await {
+            Ok(header) => header,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && record.is_empty() => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        let len = (header & !LAST_FRAGMENT) as usize;
+        if record.len() + len > MAX_RECORD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("RPC record longer than {MAX_RECORD_LEN} bytes"),
+            ));
+        }
+        let start = record.len();
+        record.resize(start + len, 0);
+        reader.read_exact(&mut record[start..]).await?;
+        if header & LAST_FRAGMENT != 0 {
+            return Ok(Some(record));
+        }
+    }
+}
+
+/// Write `record` to `writer` as a single fragment
+pub async fn write_record<W: AsyncWrite + Unpin>(writer: &mut W, record: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(record.len())
+        .ok()
+        .filter(|&len| len & LAST_FRAGMENT == 0)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "RPC record too long"))?;
+    writer.write_u32(len | LAST_FRAGMENT).await?;
+    writer.write_all(record).await?;
+    writer.flush().await
+}
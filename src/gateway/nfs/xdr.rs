@@ -0,0 +1,150 @@
+//! The XDR encoding of RFC 4506 the RPC messages are made of
+//!
+//! Every item is big-endian and padded to a multiple of four bytes.
+use crate::common::{DatenLordError, DatenLordResult};
+
+/// The padding after `len` bytes of opaque data
+fn padding(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+/// Appends XDR items to a buffer
+#[derive(Debug, Default)]
+pub struct XdrWriter {
+    /// The encoded items
+    buf: Vec<u8>,
+}
+
+impl XdrWriter {
+    /// An empty writer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of bytes written so far
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Whether nothing was written yet
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// The encoded items
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u32(value.into())
+    }
+
+    /// Fixed-length opaque data, whose length the protocol implies
+    pub fn fixed(&mut self, data: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(data);
+        self.buf.resize(self.buf.len() + padding(data.len()), 0);
+        self
+    }
+
+    /// Append the items written to `other`
+    pub fn append(&mut self, other: XdrWriter) -> &mut Self {
+        self.buf.extend_from_slice(&other.buf);
+        self
+    }
+
+    /// Variable-length opaque data or string, preceded by its length
+    pub fn opaque(&mut self, data: &[u8]) -> &mut Self {
+        let len = u32::try_from(data.len()).unwrap_or(u32::MAX);
+        self.u32(len).fixed(data)
+    }
+}
+
+/// Reads XDR items from a buffer
+#[derive(Debug)]
+pub struct XdrReader<'a> {
+    /// The encoded items
+    buf: &'a [u8],
+    /// The offset of the next item
+    pos: usize,
+}
+
+impl<'a> XdrReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// The bytes not read yet
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// The next `len` bytes
+    fn take(&mut self, len: usize) -> DatenLordResult<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.buf.len());
+        let Some(end) = end else {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "truncated XDR data, {len} bytes needed at offset {} of {}",
+                    self.pos,
+                    self.buf.len()
+                )],
+            });
+        };
+        let data = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(data)
+    }
+
+    pub fn u32(&mut self) -> DatenLordResult<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> DatenLordResult<u64> {
+        Ok(u64::from(self.u32()?) << 32 | u64::from(self.u32()?))
+    }
+
+    pub fn bool(&mut self) -> DatenLordResult<bool> {
+        match self.u32()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(DatenLordError::InvalidArgument {
+                context: vec![format!("invalid XDR boolean {value}")],
+            }),
+        }
+    }
+
+    /// Fixed-length opaque data of `len` bytes
+    pub fn fixed(&mut self, len: usize) -> DatenLordResult<&'a [u8]> {
+        let data = self.take(len)?;
+        self.take(padding(len))?;
+        Ok(data)
+    }
+
+    /// Variable-length opaque data or string of at most `max` bytes
+    pub fn opaque(&mut self, max: usize) -> DatenLordResult<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if len > max {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "XDR opaque of {len} bytes, at most {max} are allowed"
+                )],
+            });
+        }
+        self.fixed(len)
+    }
+}
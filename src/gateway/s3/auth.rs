@@ -0,0 +1,305 @@
+//! AWS Signature Version 4 of the requests, see
+//! <https://docs.aws.amazon.com/AmazonS3/latest/API/sig-v4-authenticating-requests.html>
+//!
+//! Requests are signed in the `Authorization` header or, for presigned
+//! URLs, in the query. The payload hash the signature covers is checked
+//! once the payload is received.
+use std::time::{Duration, SystemTime};
+
+use hmac::{Hmac, Mac};
+use hyper::header::AUTHORIZATION;
+use hyper::http::request::Parts;
+use hyper::StatusCode;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+
+use super::{hex, parse_query, xml, Error};
+
+/// The one signing algorithm
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+/// The payload hash of requests whose payload is not signed
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+/// How far the date of a request may be from the clock of the server
+const MAX_SKEW: Duration = Duration::from_secs(15 * 60);
+/// The longest validity of presigned URLs in seconds, a week
+const MAX_EXPIRES: u64 = 7 * 24 * 3600;
+
+/// The characters URI-encoded in canonical requests, all but the unreserved
+pub(super) const URI_ENCODED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// What the signature of a request covers of its payload
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Payload {
+    /// Nothing
+    Unsigned,
+    /// Its SHA-256, in hex
+    Sha256(String),
+}
+
+/// The key clients sign their requests with
+#[derive(Debug)]
+pub(super) struct Credentials {
+    pub(super) access_key_id: String,
+    pub(super) secret_access_key: String,
+    pub(super) region: String,
+}
+
+/// The signature of a request and what it covers
+#[derive(Debug)]
+struct Signature {
+    /// The access key id followed by the scope
+    credential: String,
+    /// The `;` separated names of the headers covered
+    signed_headers: String,
+    /// The hex of the signature itself
+    signature: String,
+    /// When the request was signed, as `X-Amz-Date`
+    date: String,
+    /// The payload hash covered, as sent
+    payload: String,
+}
+
+impl Credentials {
+    /// Check the signature of the request with `parts` at `now`, returning
+    /// what it covers of the payload
+    pub(super) fn verify(&self, parts: &Parts, now: SystemTime) -> Result<Payload, Error> {
+        let query = parse_query(parts.uri.query().unwrap_or(""));
+        let presigned = query.iter().any(|(name, _)| name == "X-Amz-Signature");
+        let signature = if let Some(authorization) = parts.headers.get(AUTHORIZATION) {
+            from_header(parts, authorization.to_str().unwrap_or(""), now)?
+        } else if presigned {
+            from_query(&query, now)?
+        } else {
+            return Err(Error::new(
+                StatusCode::FORBIDDEN,
+                "AccessDenied",
+                "the request is not signed",
+            ));
+        };
+
+        let (access_key_id, scope) = signature
+            .credential
+            .split_once('/')
+            .ok_or_else(|| malformed("the credential has no scope"))?;
+        if access_key_id != self.access_key_id {
+            return Err(Error::new(
+                StatusCode::FORBIDDEN,
+                "InvalidAccessKeyId",
+                format!("the access key id {access_key_id} does not exist"),
+            ));
+        }
+        let scope_parts: Vec<&str> = scope.split('/').collect();
+        let [day, region, "s3", "aws4_request"] = scope_parts[..] else {
+            return Err(malformed(format!("the scope {scope} is not of s3")));
+        };
+        if !signature.date.starts_with(day) {
+            return Err(malformed(format!(
+                "the scope is of {day} but the request was signed at {}",
+                signature.date
+            )));
+        }
+        if region != self.region {
+            return Err(malformed(format!(
+                "the region {region} is wrong, expecting {}",
+                self.region
+            )));
+        }
+
+        let canonical = canonical_request(parts, &query, presigned, &signature);
+        let string_to_sign = format!(
+            "{ALGORITHM}\n{}\n{scope}\n{}",
+            signature.date,
+            hex(&Sha256::digest(canonical.as_bytes()))
+        );
+        let mut key = format!("AWS4{}", self.secret_access_key).into_bytes();
+        for part in [day, region, "s3", "aws4_request", &string_to_sign] {
+            key = hmac(&key, part.as_bytes());
+        }
+        if hex(&key) != signature.signature {
+            return Err(Error::new(
+                StatusCode::FORBIDDEN,
+                "SignatureDoesNotMatch",
+                "the signature does not match the request",
+            ));
+        }
+        if signature.payload == UNSIGNED_PAYLOAD {
+            Ok(Payload::Unsigned)
+        } else {
+            Ok(Payload::Sha256(signature.payload.to_ascii_lowercase()))
+        }
+    }
+}
+
+/// The signature in the `Authorization` header `authorization`
+fn from_header(parts: &Parts, authorization: &str, now: SystemTime) -> Result<Signature, Error> {
+    let fields = authorization
+        .strip_prefix(ALGORITHM)
+        .ok_or_else(|| malformed(format!("only {ALGORITHM} signatures are supported")))?;
+    let (mut credential, mut signed_headers, mut signature) = (None, None, None);
+    for field in fields.split(',') {
+        match field.trim().split_once('=') {
+            Some(("Credential", value)) => credential = Some(value.to_owned()),
+            Some(("SignedHeaders", value)) => signed_headers = Some(value.to_owned()),
+            Some(("Signature", value)) => signature = Some(value.to_owned()),
+            _ => return Err(malformed(format!("unexpected field {field:?}"))),
+        }
+    }
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+    };
+    let date = header("x-amz-date").ok_or_else(|| malformed("X-Amz-Date is missing"))?;
+    check_date(&date)?;
+    if date < xml::amz_date(now - MAX_SKEW) || date > xml::amz_date(now + MAX_SKEW) {
+        return Err(Error::new(
+            StatusCode::FORBIDDEN,
+            "RequestTimeTooSkewed",
+            format!("the request was signed at {date}, too far from the server time"),
+        ));
+    }
+    let payload = header("x-amz-content-sha256").ok_or_else(|| {
+        Error::new(
+            StatusCode::BAD_REQUEST,
+            "InvalidRequest",
+            "X-Amz-Content-SHA256 is missing",
+        )
+    })?;
+    let hashed = payload.len() == 64 && payload.bytes().all(|c| c.is_ascii_hexdigit());
+    if payload != UNSIGNED_PAYLOAD && !hashed {
+        return Err(Error::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "NotImplemented",
+            format!("the payload {payload} is not supported, sign the whole payload instead"),
+        ));
+    }
+    Ok(Signature {
+        credential: credential.ok_or_else(|| malformed("Credential is missing"))?,
+        signed_headers: signed_headers.ok_or_else(|| malformed("SignedHeaders is missing"))?,
+        signature: signature.ok_or_else(|| malformed("Signature is missing"))?,
+        date,
+        payload,
+    })
+}
+
+/// The signature in the query `query` of a presigned URL
+fn from_query(query: &[(String, String)], now: SystemTime) -> Result<Signature, Error> {
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| malformed(format!("{name} is missing")))
+    };
+    if param("X-Amz-Algorithm")? != ALGORITHM {
+        return Err(malformed(format!("only {ALGORITHM} signatures are supported")));
+    }
+    let date = param("X-Amz-Date")?;
+    check_date(&date)?;
+    let expires = param("X-Amz-Expires")?
+        .parse::<u64>()
+        .ok()
+        .filter(|expires| *expires <= MAX_EXPIRES)
+        .ok_or_else(|| malformed(format!("X-Amz-Expires is not at most {MAX_EXPIRES}")))?;
+    if date > xml::amz_date(now + MAX_SKEW)
+        || date < xml::amz_date(now - Duration::from_secs(expires))
+    {
+        return Err(Error::new(
+            StatusCode::FORBIDDEN,
+            "AccessDenied",
+            "the presigned URL has expired",
+        ));
+    }
+    Ok(Signature {
+        credential: param("X-Amz-Credential")?,
+        signed_headers: param("X-Amz-SignedHeaders")?,
+        signature: param("X-Amz-Signature")?,
+        date,
+        payload: UNSIGNED_PAYLOAD.to_owned(),
+    })
+}
+
+/// The canonical request `signature` signs
+fn canonical_request(
+    parts: &Parts,
+    query: &[(String, String)],
+    presigned: bool,
+    signature: &Signature,
+) -> String {
+    let mut params: Vec<(String, String)> = query
+        .iter()
+        .filter(|(name, _)| !presigned || name != "X-Amz-Signature")
+        .map(|(name, value)| {
+            (
+                utf8_percent_encode(name, URI_ENCODED).to_string(),
+                utf8_percent_encode(value, URI_ENCODED).to_string(),
+            )
+        })
+        .collect();
+    params.sort();
+    let query = params
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join("&");
+    let mut headers = String::new();
+    for name in signature.signed_headers.split(';') {
+        let values: Vec<String> = parts
+            .headers
+            .get_all(name)
+            .iter()
+            .map(|value| {
+                String::from_utf8_lossy(value.as_bytes())
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        headers.push_str(&format!("{name}:{}\n", values.join(",")));
+    }
+    format!(
+        "{}\n{}\n{query}\n{headers}\n{}\n{}",
+        parts.method,
+        parts.uri.path(),
+        signature.signed_headers,
+        signature.payload
+    )
+}
+
+/// Fail unless `date` is a basic ISO 8601 time in UTC, which orders like
+/// the times it stands for
+fn check_date(date: &str) -> Result<(), Error> {
+    let well_formed = date.len() == 16
+        && date.bytes().enumerate().all(|(i, c)| match i {
+            8 => c == b'T',
+            15 => c == b'Z',
+            _ => c.is_ascii_digit(),
+        });
+    if well_formed {
+        Ok(())
+    } else {
+        Err(malformed(format!("the date {date} is not like 20060102T150405Z")))
+    }
+}
+
+/// The HMAC-SHA256 of `data` with `key`
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256>>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// An error of a signature not made as expected
+fn malformed(message: impl Into<String>) -> Error {
+    Error::new(
+        StatusCode::BAD_REQUEST,
+        "AuthorizationHeaderMalformed",
+        message,
+    )
+}
@@ -0,0 +1,347 @@
+//! The operations on buckets, listings included
+use std::ffi::OsStr;
+
+use hyper::{Body, Response, StatusCode};
+use nix::sys::stat::SFlag;
+use percent_encoding::{utf8_percent_encode, AsciiSet};
+
+use super::auth::URI_ENCODED;
+use super::xml::{self, Xml};
+use super::{empty_response, hex, is_bucket_name, unhex, xml_response, Call, Error, S3Server};
+use crate::common::DatenLordError;
+use crate::storage::fs_util::{CreateParam, FileAttr, ROOT_ID};
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+/// The mode of the directories of created buckets, before the umask
+const BUCKET_MODE: u32 = 0o777;
+/// The most keys a listing returns
+const MAX_KEYS: usize = 1000;
+/// The characters of the keys in listings asking for `encoding-type=url`
+const KEY_ENCODED: &AsciiSet = &URI_ENCODED.remove(b'/');
+
+/// A result of a listing
+#[derive(Debug)]
+enum Listed {
+    /// An object
+    Object { key: String, attr: FileAttr },
+    /// The keys sharing a prefix up to the delimiter
+    Prefix(String),
+}
+
+impl Listed {
+    /// The key or prefix, by which the results are ordered
+    fn name(&self) -> &str {
+        match *self {
+            Self::Object { ref key, .. } | Self::Prefix(ref key) => key,
+        }
+    }
+}
+
+/// What a listing asks for
+#[derive(Debug)]
+struct Listing<'a> {
+    prefix: &'a str,
+    delimiter: Option<&'a str>,
+    /// The results are after this key or prefix
+    after: String,
+    max_keys: usize,
+}
+
+impl<F: VirtualFs + 'static> S3Server<F> {
+    /// `ListBuckets`
+    pub(super) async fn list_buckets(&self) -> Result<Response<Body>, Error> {
+        let mut xml = Xml::new("ListAllMyBucketsResult");
+        let owner = self.ctx().uid.to_string();
+        xml.open("Owner")
+            .element("ID", &owner)
+            .element("DisplayName", &owner)
+            .close("Owner")
+            .open("Buckets");
+        for (name, attr) in self.entries(ROOT_ID, "").await? {
+            let Some(name) = name.strip_suffix('/') else {
+                continue;
+            };
+            if is_bucket_name(name) {
+                xml.open("Bucket")
+                    .element("Name", name)
+                    .element("CreationDate", &xml::iso8601(attr.ctime))
+                    .close("Bucket");
+            }
+        }
+        xml.close("Buckets");
+        Ok(xml_response(StatusCode::OK, xml))
+    }
+
+    /// `CreateBucket`, whatever the location asked for
+    pub(super) async fn create_bucket(&self, name: &str) -> Result<Response<Body>, Error> {
+        if !is_bucket_name(name) {
+            return Err(Error::new(
+                StatusCode::BAD_REQUEST,
+                "InvalidBucketName",
+                format!("{name} is not a valid bucket name"),
+            ));
+        }
+        let param = CreateParam {
+            parent: ROOT_ID,
+            name: name.into(),
+            mode: BUCKET_MODE,
+            rdev: 0,
+            node_type: SFlag::S_IFDIR,
+            link: None,
+        };
+        match self.fs.mkdir(&self.ctx(), param).await {
+            Ok(_) => {
+                let mut response = empty_response(StatusCode::OK);
+                if let Ok(location) = format!("/{name}").parse() {
+                    response.headers_mut().insert("location", location);
+                }
+                Ok(response)
+            }
+            Err(DatenLordError::AlreadyExists { .. }) => Err(Error::new(
+                StatusCode::CONFLICT,
+                "BucketAlreadyOwnedByYou",
+                format!("the bucket {name} already exists"),
+            )),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// `DeleteBucket`, only when it holds no object
+    pub(super) async fn delete_bucket(&self, name: &str) -> Result<Response<Body>, Error> {
+        let dir = self.bucket(name).await?;
+        if !self.entries(dir, "").await?.is_empty() {
+            return Err(Error::new(
+                StatusCode::CONFLICT,
+                "BucketNotEmpty",
+                format!("the bucket {name} is not empty"),
+            ));
+        }
+        self.fs.rmdir(&self.ctx(), ROOT_ID, OsStr::new(name)).await?;
+        Ok(empty_response(StatusCode::NO_CONTENT))
+    }
+
+    /// `HeadBucket`
+    pub(super) async fn head_bucket(&self, name: &str) -> Result<Response<Body>, Error> {
+        self.bucket(name).await?;
+        let mut response = empty_response(StatusCode::OK);
+        if let Ok(region) = self.region.parse() {
+            response.headers_mut().insert("x-amz-bucket-region", region);
+        }
+        Ok(response)
+    }
+
+    /// `GetBucketLocation`, the region of the endpoint
+    pub(super) async fn bucket_location(&self, name: &str) -> Result<Response<Body>, Error> {
+        self.bucket(name).await?;
+        let mut xml = Xml::new("LocationConstraint");
+        // Buckets of the default region have no constraint
+        if self.region != "us-east-1" {
+            xml.text(&self.region);
+        }
+        Ok(xml_response(StatusCode::OK, xml))
+    }
+
+    /// `ListObjectsV2`, or `ListObjects` without `list-type=2`
+    pub(super) async fn list_objects(
+        &self,
+        name: &str,
+        call: &Call,
+    ) -> Result<Response<Body>, Error> {
+        let dir = self.bucket(name).await?;
+        let v2 = call.param("list-type") == Some("2");
+        let url = match call.param("encoding-type") {
+            None => false,
+            Some("url") => true,
+            Some(other) => return Err(Error::invalid(format!("unknown encoding type {other}"))),
+        };
+        let max_keys = match call.param("max-keys") {
+            None => MAX_KEYS,
+            Some(max) => max
+                .parse::<usize>()
+                .map_err(|_| Error::invalid(format!("max-keys {max} is not a number")))?
+                .min(MAX_KEYS),
+        };
+        let token = call.param("continuation-token");
+        let after = match (v2, token) {
+            (true, Some(token)) => unhex(token)
+                .and_then(|after| String::from_utf8(after).ok())
+                .ok_or_else(|| Error::invalid("the continuation token is not valid"))?,
+            (true, None) => call.param("start-after").unwrap_or("").to_owned(),
+            (false, _) => call.param("marker").unwrap_or("").to_owned(),
+        };
+        let listing = Listing {
+            prefix: call.param("prefix").unwrap_or(""),
+            delimiter: call.param("delimiter").filter(|delimiter| !delimiter.is_empty()),
+            after,
+            max_keys,
+        };
+        let (results, truncated) = self.list(dir, &listing).await?;
+
+        let encode = |text: &str| {
+            if url {
+                utf8_percent_encode(text, KEY_ENCODED).to_string()
+            } else {
+                text.to_owned()
+            }
+        };
+        let mut xml = Xml::new("ListBucketResult");
+        xml.element("Name", name)
+            .element("Prefix", &encode(listing.prefix))
+            .element("MaxKeys", &max_keys.to_string())
+            .element("IsTruncated", &truncated.to_string());
+        if let Some(delimiter) = listing.delimiter {
+            xml.element("Delimiter", &encode(delimiter));
+        }
+        if url {
+            xml.element("EncodingType", "url");
+        }
+        let last = results.last().map(|result| result.name().to_owned());
+        if v2 {
+            xml.element("KeyCount", &results.len().to_string());
+            if let Some(token) = token {
+                xml.element("ContinuationToken", token);
+            }
+            if let Some(start_after) = call.param("start-after") {
+                xml.element("StartAfter", &encode(start_after));
+            }
+            if let (true, Some(last)) = (truncated, &last) {
+                xml.element("NextContinuationToken", &hex(last.as_bytes()));
+            }
+        } else {
+            xml.element("Marker", &encode(call.param("marker").unwrap_or("")));
+            if let (true, Some(last)) = (truncated, &last) {
+                xml.element("NextMarker", &encode(last));
+            }
+        }
+        for result in &results {
+            if let Listed::Object { ref key, ref attr } = *result {
+                xml.open("Contents")
+                    .element("Key", &encode(key))
+                    .element("LastModified", &xml::iso8601(attr.mtime))
+                    .element("ETag", &self.etag(attr).await)
+                    .element("Size", &attr.size.to_string())
+                    .element("StorageClass", "STANDARD")
+                    .close("Contents");
+            }
+        }
+        for result in &results {
+            if let Listed::Prefix(ref prefix) = *result {
+                xml.open("CommonPrefixes")
+                    .element("Prefix", &encode(prefix))
+                    .close("CommonPrefixes");
+            }
+        }
+        Ok(xml_response(StatusCode::OK, xml))
+    }
+
+    /// The results of `listing` in the bucket `bucket` in key order and
+    /// whether more are left
+    ///
+    /// Directories are walked depth first with their entries ordered by
+    /// key, the names of directories followed by `/`, which lists the keys
+    /// in order. Directories whose keys all fall before `after`, outside
+    /// the prefix or under a common prefix are skipped.
+    async fn list(&self, bucket: INum, listing: &Listing<'_>) -> Result<(Vec<Listed>, bool), Error> {
+        let Listing {
+            prefix,
+            delimiter,
+            ref after,
+            max_keys,
+        } = *listing;
+        let mut results: Vec<Listed> = Vec::new();
+        // Start from the deepest directory the prefix names
+        let start = prefix.rfind('/').map_or("", |end| &prefix[..=end]);
+        let names: Vec<&str> = start.split('/').filter(|name| !name.is_empty()).collect();
+        let Some(chain) = self.walk(bucket, &names).await? else {
+            return Ok((results, false));
+        };
+        let dir = chain[chain.len() - 1];
+        if dir.kind != SFlag::S_IFDIR {
+            return Ok((results, false));
+        }
+        let mut stack = vec![self.entries(dir.ino, start).await?.into_iter()];
+        while results.len() <= max_keys {
+            let Some(entries) = stack.last_mut() else {
+                break;
+            };
+            let Some((key, attr)) = entries.next() else {
+                stack.pop();
+                continue;
+            };
+            let is_dir = attr.kind == SFlag::S_IFDIR;
+            let under_prefix = key.starts_with(prefix) || (is_dir && prefix.starts_with(&key));
+            if !under_prefix {
+                continue;
+            }
+            if is_dir && *after >= key && !after.starts_with(&key) {
+                continue;
+            }
+            let common = match delimiter {
+                Some(delimiter) if key.starts_with(prefix) => key[prefix.len()..]
+                    .find(delimiter)
+                    .map(|end| key[..prefix.len() + end + delimiter.len()].to_owned()),
+                _ => None,
+            };
+            if let Some(common) = common {
+                let listed = results.last().map(Listed::name) == Some(common.as_str());
+                if !listed && common > *after {
+                    results.push(Listed::Prefix(common));
+                }
+                continue;
+            }
+            if is_dir {
+                let children = self.entries(attr.ino, &key).await?;
+                // Directories without entries stand for themselves
+                if children.is_empty() && key.starts_with(prefix) && key > *after {
+                    results.push(Listed::Object { key, attr });
+                } else {
+                    stack.push(children.into_iter());
+                }
+            } else if key.starts_with(prefix) && key > *after {
+                results.push(Listed::Object { key, attr });
+            }
+        }
+        let truncated = results.len() > max_keys;
+        results.truncate(max_keys);
+        Ok((results, truncated))
+    }
+
+    /// The files and directories in the directory `ino` with their keys,
+    /// `dir` followed by their names and by `/` for directories, in order
+    ///
+    /// Entries that are neither, or whose names are not UTF-8, have no key.
+    pub(super) async fn entries(
+        &self,
+        ino: INum,
+        dir: &str,
+    ) -> Result<Vec<(String, FileAttr)>, Error> {
+        let fh = self.fs.opendir(&self.ctx(), ino, 0).await?;
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        let result = loop {
+            match self.fs.readdirplus(&self.ctx(), ino, fh, offset).await {
+                Ok(batch) if batch.is_empty() => break Ok(()),
+                Ok(batch) => {
+                    offset += i64::try_from(batch.len()).unwrap_or(i64::MAX);
+                    entries.extend(batch.into_iter().map(|(entry, attr, _)| (entry.name, attr)));
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        self.fs.releasedir(&self.ctx(), ino, fh, 0).await?;
+        result?;
+        let mut keys: Vec<(String, FileAttr)> = entries
+            .into_iter()
+            .filter_map(|(name, attr)| {
+                let name = name.to_str()?;
+                match attr.kind {
+                    SFlag::S_IFDIR => Some((format!("{dir}{name}/"), attr)),
+                    SFlag::S_IFREG => Some((format!("{dir}{name}"), attr)),
+                    _ => None,
+                }
+            })
+            .collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(keys)
+    }
+}
@@ -0,0 +1,644 @@
+//! An S3-compatible endpoint over any `VirtualFs`, the inverse of the S3
+//! backends
+//!
+//! Tools speaking S3, such as `aws s3`, MLflow and DVC, read and write the
+//! namespace the SDKs use: the directories at its root are the buckets and
+//! the `/` separated keys of their objects are the paths of the files under
+//! them. Buckets are named in the path of the requests, e.g.
+//! `aws --endpoint-url http://127.0.0.1:9000 s3 cp model.bin s3://models/`.
+//!
+//! Puts and multipart uploads are staged in `.datenlord_s3_uploads` at the
+//! root and renamed into place, so readers see objects whole. Keys with
+//! empty, `.` or `..` components cannot be stored, keys ending with `/` are
+//! directories, and directories left empty by deletes are removed. ETags
+//! are the MD5 of what was put, kept in an extended attribute, and for files
+//! written otherwise are made of their size and modification time.
+//!
+//! Every request acts as the caller of the config. When `S3Config` has a
+//! key, requests must be signed with it by Signature Version 4, which SDKs
+//! also use for presigned URLs when configured with `s3v4`. Requests from
+//! the principals of an `Authenticator`, known by a bearer token or a
+//! client certificate, act with their own ids instead, see `principal`.
+//! Versioning, ACLs, tagging and user metadata are not supported.
+use std::convert::Infallible;
+use std::ffi::OsStr;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::http::request::Parts;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use nix::errno::Errno;
+use nix::sys::stat::SFlag;
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, info_span, Instrument};
+
+use self::auth::{Credentials, Payload};
+use self::principal::{Authenticator, StaticPrincipals};
+use self::xml::Xml;
+use super::{S3Config, TlsConfig};
+use crate::common::config::DatenLordConfig;
+use crate::common::logging;
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::sdk;
+use crate::storage::fs_util::{self, FileAttr, RequestContext, ROOT_ID};
+use crate::storage::idmap::IdMapConfig;
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+mod auth;
+mod bucket;
+mod multipart;
+mod object;
+pub mod principal;
+mod tls;
+mod xml;
+
+tokio::task_local! {
+    /// The context of the principal the request being served comes from
+    static PRINCIPAL_CTX: RequestContext;
+}
+
+/// The directory at the root puts and multipart uploads are staged in, not
+/// a valid bucket name so it is never listed
+const UPLOADS: &str = ".datenlord_s3_uploads";
+/// The extended attribute keeping the ETag of an object together with the
+/// size and modification time it was computed for
+const ETAG_XATTR: &str = "user.datenlord.s3.etag";
+/// The longest key, in bytes
+const MAX_KEY_LEN: usize = 1024;
+/// The query parameters of the supported operations, the others name
+/// subresources such as `acl` or `tagging`
+const KNOWN_PARAMS: [&str; 14] = [
+    "list-type",
+    "prefix",
+    "delimiter",
+    "max-keys",
+    "continuation-token",
+    "start-after",
+    "marker",
+    "encoding-type",
+    "fetch-owner",
+    "location",
+    "delete",
+    "uploads",
+    "uploadId",
+    "partNumber",
+];
+
+/// An error response
+#[derive(Debug)]
+pub(super) struct Error {
+    status: StatusCode,
+    /// The S3 error code, e.g. `NoSuchKey`
+    code: &'static str,
+    message: String,
+}
+
+impl Error {
+    pub(super) fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// An argument of the request is not valid
+    fn invalid(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "InvalidArgument", message)
+    }
+
+    /// The object `key` does not exist
+    fn no_such_key(key: &str) -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            "NoSuchKey",
+            format!("the key {key} does not exist"),
+        )
+    }
+
+    /// The response to the request for `resource`
+    fn response(&self, resource: &str) -> Response<Body> {
+        let mut xml = Xml::error();
+        xml.element("Code", self.code)
+            .element("Message", &self.message)
+            .element("Resource", resource);
+        xml_response(self.status, xml)
+    }
+}
+
+impl From<DatenLordError> for Error {
+    fn from(e: DatenLordError) -> Self {
+        let message = e.to_string();
+        match e.errno() {
+            Some(Errno::EACCES | Errno::EPERM) => {
+                Self::new(StatusCode::FORBIDDEN, "AccessDenied", message)
+            }
+            Some(Errno::EINVAL) => Self::invalid(message),
+            Some(Errno::ETIMEDOUT | Errno::EAGAIN) => {
+                Self::new(StatusCode::SERVICE_UNAVAILABLE, "SlowDown", message)
+            }
+            Some(Errno::ESHUTDOWN) => {
+                Self::new(StatusCode::SERVICE_UNAVAILABLE, "ServiceUnavailable", message)
+            }
+            Some(Errno::ENOTSUP) => Self::new(StatusCode::NOT_IMPLEMENTED, "NotImplemented", message),
+            _ => Self::new(StatusCode::INTERNAL_SERVER_ERROR, "InternalError", message),
+        }
+    }
+}
+
+/// Whether a lookup failed with `e` because the entry is missing, backends
+/// reporting missing entries without an errno
+fn is_missing(e: &DatenLordError) -> bool {
+    matches!(e.errno(), None | Some(Errno::ENOENT | Errno::ENOTDIR))
+}
+
+/// A request being served
+#[derive(Debug)]
+struct Call {
+    parts: Parts,
+    /// The decoded query parameters
+    query: Vec<(String, String)>,
+    /// What the signature covers of `body`
+    payload: Payload,
+    body: Body,
+}
+
+impl Call {
+    /// The value of the query parameter `name`
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of the header `name`, `None` unless it is visible ASCII
+    fn header(&self, name: &str) -> Option<&str> {
+        self.parts.headers.get(name)?.to_str().ok()
+    }
+}
+
+/// The S3 endpoint of a filesystem
+#[derive(Debug)]
+pub struct S3Server<F> {
+    fs: Arc<F>,
+    /// The context requests act with unless they come from a principal
+    ctx: RequestContext,
+    /// The key requests must be signed with, if any
+    credentials: Option<Credentials>,
+    region: String,
+    /// Who requests come from when not told by their signature
+    authenticator: Option<Arc<dyn Authenticator>>,
+    /// How the ids of principals are mapped
+    idmap: IdMapConfig,
+    /// The TLS the endpoint is served with, if any
+    tls: Option<Arc<ServerConfig>>,
+    /// Numbers the requests and names the staged files
+    sequence: AtomicU64,
+}
+
+impl<F: VirtualFs + 'static> S3Server<F> {
+    /// An endpoint of `fs` configured by `config` whose requests act as `ctx`
+    /// unless they come from one of its `principals`
+    ///
+    /// The endpoint serves plain HTTP until given the `tls` of the config
+    /// with `with_tls`.
+    pub fn new(fs: Arc<F>, config: &S3Config, ctx: RequestContext) -> Self {
+        let credentials = (!config.access_key_id.is_empty()).then(|| Credentials {
+            access_key_id: config.access_key_id.clone(),
+            secret_access_key: config.secret_access_key.clone(),
+            region: config.region.clone(),
+        });
+        let authenticator = (!config.principals.is_empty()).then(|| {
+            Arc::new(StaticPrincipals::new(&config.principals)) as Arc<dyn Authenticator>
+        });
+        Self {
+            fs,
+            ctx,
+            credentials,
+            region: config.region.clone(),
+            authenticator,
+            idmap: IdMapConfig::default(),
+            tls: None,
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Tell who requests come from with `authenticator` rather than the
+    /// `principals` of the config
+    #[must_use]
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Map the ids of principals with `idmap`, as those of the config are
+    #[must_use]
+    pub fn with_idmap(mut self, idmap: IdMapConfig) -> Self {
+        self.idmap = idmap;
+        self
+    }
+
+    /// Serve over the TLS of `config`
+    pub fn with_tls(mut self, config: &TlsConfig) -> DatenLordResult<Self> {
+        self.tls = Some(tls::server_config(config)?);
+        Ok(self)
+    }
+
+    /// Serve the clients connecting to `listener` until accepting fails
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            debug!("S3 client {peer} connected");
+            let server = Arc::clone(&self);
+            tokio::spawn(async move {
+                let Some(ref tls) = server.tls else {
+                    return server.serve_connection(stream, Vec::new(), peer).await;
+                };
+                match TlsAcceptor::from(Arc::clone(tls)).accept(stream).await {
+                    Ok(stream) => {
+                        let certificates = stream
+                            .get_ref()
+                            .1
+                            .peer_certificates()
+                            .map(|chain| chain.iter().map(|cert| cert.0.clone()).collect())
+                            .unwrap_or_default();
+                        server.serve_connection(stream, certificates, peer).await;
+                    }
+                    Err(e) => debug!("TLS handshake of S3 client {peer} failed: {e}"),
+                }
+            });
+        }
+    }
+
+    /// Serve the requests of the connection `stream` from `peer`, which
+    /// presented the DER `certificates`
+    async fn serve_connection<S>(
+        self: Arc<Self>,
+        stream: S,
+        certificates: Vec<Vec<u8>>,
+        peer: SocketAddr,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let certificates: Arc<[Vec<u8>]> = certificates.into();
+        let service = service_fn(move |request| {
+            Arc::clone(&self).handle(request, Arc::clone(&certificates))
+        });
+        let connection = Http::new().http1_only(true).serve_connection(stream, service);
+        if let Err(e) = connection.await {
+            debug!("S3 connection of {peer} failed: {e}");
+        }
+    }
+
+    /// The response to `request`, errors included
+    ///
+    /// The request runs in an `info` span continuing the trace of its W3C
+    /// trace context headers, see `logging::continue_trace`.
+    async fn handle(
+        self: Arc<Self>,
+        request: Request<Body>,
+        certificates: Arc<[Vec<u8>]>,
+    ) -> Result<Response<Body>, Infallible> {
+        let id = self.next_id();
+        let resource = request.uri().path().to_owned();
+        let method = request.method().clone();
+        let span = info_span!("s3", %method, resource);
+        let header = |name| request.headers().get(name).and_then(|value| value.to_str().ok());
+        logging::continue_trace(&span, header("traceparent"), header("tracestate"));
+        let mut response = match self.route(request, &certificates).instrument(span).await {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("S3 {method} {resource} failed: {e:?}");
+                e.response(&resource)
+            }
+        };
+        if let Ok(id) = HeaderValue::from_str(&format!("{id:016X}")) {
+            response.headers_mut().insert("x-amz-request-id", id);
+        }
+        Ok(response)
+    }
+
+    /// The context of the request with `parts` from a client that presented
+    /// `certificates`, and what its signature covers of the payload
+    fn authenticate(
+        &self,
+        parts: &Parts,
+        certificates: &[Vec<u8>],
+    ) -> Result<(RequestContext, Payload), Error> {
+        if let Some(ref authenticator) = self.authenticator {
+            if let Some(principal) = authenticator.authenticate(parts, certificates) {
+                let ctx = RequestContext {
+                    uid: principal.uid,
+                    gid: principal.gid,
+                    ..self.ctx
+                };
+                return Ok((self.idmap.map(ctx), Payload::Unsigned));
+            }
+            if self.credentials.is_none() {
+                return Err(Error::new(
+                    StatusCode::FORBIDDEN,
+                    "AccessDenied",
+                    "the request carries no known token or certificate",
+                ));
+            }
+        }
+        let payload = match self.credentials {
+            Some(ref credentials) => credentials.verify(parts, SystemTime::now())?,
+            None => Payload::Unsigned,
+        };
+        Ok((self.ctx, payload))
+    }
+
+    /// The context the request being served acts with
+    fn ctx(&self) -> RequestContext {
+        PRINCIPAL_CTX.try_with(|ctx| *ctx).unwrap_or(self.ctx)
+    }
+
+    /// Check and dispatch `request` to the operation it calls, acting with
+    /// the context of its principal
+    async fn route(
+        &self,
+        request: Request<Body>,
+        certificates: &[Vec<u8>],
+    ) -> Result<Response<Body>, Error> {
+        let (parts, body) = request.into_parts();
+        let (ctx, payload) = self.authenticate(&parts, certificates)?;
+        PRINCIPAL_CTX.scope(ctx, self.dispatch(parts, payload, body)).await
+    }
+
+    /// Dispatch the request with `parts` to the operation it calls
+    async fn dispatch(
+        &self,
+        parts: Parts,
+        payload: Payload,
+        body: Body,
+    ) -> Result<Response<Body>, Error> {
+        let query = parse_query(parts.uri.query().unwrap_or(""));
+        if let Some((name, _)) = query
+            .iter()
+            .find(|(name, _)| !KNOWN_PARAMS.contains(&name.as_str()) && !name.starts_with("X-Amz-"))
+        {
+            // Like `x-id`, which SDKs add to name the operation
+            if name != "x-id" {
+                return Err(Error::new(
+                    StatusCode::NOT_IMPLEMENTED,
+                    "NotImplemented",
+                    format!("the subresource {name} is not supported"),
+                ));
+            }
+        }
+        let path = percent_decode_str(parts.uri.path())
+            .decode_utf8()
+            .map_err(|_| Error::invalid("the path is not UTF-8"))?
+            .into_owned();
+        let path = path.strip_prefix('/').unwrap_or(&path);
+        let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+        let mut call = Call {
+            parts,
+            query,
+            payload,
+            body,
+        };
+        let has = |name: &str| call.param(name).is_some();
+
+        match (call.parts.method.clone(), bucket.is_empty(), key.is_empty()) {
+            (Method::GET, true, _) => self.list_buckets().await,
+            (Method::PUT, false, true) => self.create_bucket(bucket).await,
+            (Method::DELETE, false, true) => self.delete_bucket(bucket).await,
+            (Method::HEAD, false, true) => self.head_bucket(bucket).await,
+            (Method::GET, false, true) if has("location") => self.bucket_location(bucket).await,
+            (Method::GET, false, true) if !has("delete") && !has("uploads") => {
+                self.list_objects(bucket, &call).await
+            }
+            (Method::POST, false, true) if has("delete") => {
+                self.delete_objects(bucket, &mut call).await
+            }
+            (Method::GET, false, false) => self.get_object(bucket, key, &call, true).await,
+            (Method::HEAD, false, false) => self.get_object(bucket, key, &call, false).await,
+            (Method::PUT, false, false) if has("uploadId") => {
+                self.upload_part(bucket, key, &mut call).await
+            }
+            (Method::PUT, false, false) if call.header("x-amz-copy-source").is_some() => {
+                self.copy_object(bucket, key, &call).await
+            }
+            (Method::PUT, false, false) => self.put_object(bucket, key, &mut call).await,
+            (Method::DELETE, false, false) if has("uploadId") => {
+                self.abort_upload(bucket, key, &call).await
+            }
+            (Method::DELETE, false, false) => self.delete_object(bucket, key).await,
+            (Method::POST, false, false) if has("uploads") => {
+                self.create_upload(bucket, key).await
+            }
+            (Method::POST, false, false) if has("uploadId") => {
+                self.complete_upload(bucket, key, &mut call).await
+            }
+            (method, ..) => Err(Error::new(
+                StatusCode::NOT_IMPLEMENTED,
+                "NotImplemented",
+                format!("{method} {} is not supported", call.parts.uri.path()),
+            )),
+        }
+    }
+
+    /// A number no other call of the server returns
+    fn next_id(&self) -> u64 {
+        self.sequence.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// A name no other staged file or upload has, starting with `kind`
+    fn unique_name(&self, kind: &str) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        format!("{kind}-{:x}-{:x}", now.as_nanos(), self.next_id())
+    }
+
+    /// The directory of the bucket `name`
+    async fn bucket(&self, name: &str) -> Result<INum, Error> {
+        let no_such_bucket = || {
+            Error::new(
+                StatusCode::NOT_FOUND,
+                "NoSuchBucket",
+                format!("the bucket {name} does not exist"),
+            )
+        };
+        if !is_bucket_name(name) {
+            return Err(no_such_bucket());
+        }
+        match self.fs.lookup(&self.ctx(), ROOT_ID, OsStr::new(name)).await {
+            Ok((_, attr, _)) if attr.kind == SFlag::S_IFDIR => Ok(attr.ino),
+            Ok(_) => Err(no_such_bucket()),
+            Err(e) if is_missing(&e) => Err(no_such_bucket()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The entries from `dir` along the path `names`, `dir` first, or
+    /// `None` if an entry is missing
+    async fn walk(&self, dir: INum, names: &[&str]) -> Result<Option<Vec<FileAttr>>, Error> {
+        let mut chain = vec![self.fs.getattr(&self.ctx(), dir).await?.1];
+        for name in names {
+            let parent = chain[chain.len() - 1];
+            if parent.kind != SFlag::S_IFDIR {
+                return Ok(None);
+            }
+            match self.fs.lookup(&self.ctx(), parent.ino, OsStr::new(name)).await {
+                Ok((_, attr, _)) => chain.push(attr),
+                Err(e) if is_missing(&e) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(Some(chain))
+    }
+
+    /// The ETag of the object `attr`, quoted
+    async fn etag(&self, attr: &FileAttr) -> String {
+        let (secs, nanos) = fs_util::to_timespec(attr.mtime);
+        let version = format!("{}:{secs}.{nanos:09}:", attr.size);
+        if let Ok(value) = self.fs.getxattr(&self.ctx(), attr.ino, ETAG_XATTR).await {
+            if let Some(etag) = String::from_utf8_lossy(&value).strip_prefix(&version) {
+                return format!("\"{etag}\"");
+            }
+        }
+        // Written by other means, `-` tells clients it is no MD5
+        format!("\"{secs:x}{nanos:08x}-{:x}\"", attr.size)
+    }
+
+    /// Keep `etag`, unquoted, as the ETag of the file `ino` as it is now
+    async fn set_etag(&self, ino: INum, etag: &str) -> Result<(), Error> {
+        let (_, attr) = self.fs.getattr(&self.ctx(), ino).await?;
+        let (secs, nanos) = fs_util::to_timespec(attr.mtime);
+        let value = format!("{}:{secs}.{nanos:09}:{etag}", attr.size);
+        if let Err(e) = self
+            .fs
+            .setxattr(&self.ctx(), ino, ETAG_XATTR, value.as_bytes(), 0, 0)
+            .await
+        {
+            // The object is still served, with the ETag of other files
+            debug!("failed to keep the ETag of inode {ino}: {e}");
+        }
+        Ok(())
+    }
+}
+
+/// Serve the S3 endpoint of the `s3` field of `config` on the filesystem
+/// stack of the SDKs until accepting clients fails, reloading `config_file`
+/// on `SIGHUP`, see `sdk::reload_on_hangup`
+pub async fn run(config: &DatenLordConfig, config_file: Option<PathBuf>) -> DatenLordResult<()> {
+    let fs = Arc::new(sdk::open_fs(config)?);
+    let _writeback = sdk::writeback(&fs, config)?;
+    sdk::reload_on_hangup(&fs, config_file)?;
+    let mut server = S3Server::new(fs, &config.s3, config.request_context())
+        .with_idmap(config.idmap.clone());
+    if let Some(ref tls) = config.s3.tls {
+        server = server.with_tls(tls)?;
+    }
+    let server = Arc::new(server);
+    let listener = TcpListener::bind(&config.s3.listen)
+        .await
+        .map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to listen on {}: {e}", config.s3.listen)],
+            source: None,
+        })?;
+    info!("serving S3 on {}", config.s3.listen);
+    server
+        .serve(listener)
+        .await
+        .map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to accept S3 clients: {e}")],
+            source: None,
+        })
+}
+
+/// The decoded parameters of the query `query`, `+` standing for itself
+pub(super) fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, value) = param.split_once('=').unwrap_or((param, ""));
+            (
+                percent_decode_str(name).decode_utf8_lossy().into_owned(),
+                percent_decode_str(value).decode_utf8_lossy().into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// `bytes` in lowercase hex
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The bytes of the hex `text`
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Whether `name` follows the bucket naming rules: 3 to 63 lowercase
+/// letters, digits, `.` and `-`, beginning and ending with a letter or digit
+fn is_bucket_name(name: &str) -> bool {
+    let alphanumeric = |c: u8| c.is_ascii_lowercase() || c.is_ascii_digit();
+    (3..=63).contains(&name.len())
+        && name.bytes().all(|c| alphanumeric(c) || c == b'.' || c == b'-')
+        && name.bytes().next().is_some_and(alphanumeric)
+        && name.bytes().last().is_some_and(alphanumeric)
+}
+
+/// The path components of `key` and whether it names a directory, ending
+/// with `/`
+fn key_components(key: &str) -> Result<(Vec<&str>, bool), Error> {
+    if key.len() > MAX_KEY_LEN {
+        return Err(Error::new(
+            StatusCode::BAD_REQUEST,
+            "KeyTooLongError",
+            format!("keys are at most {MAX_KEY_LEN} bytes"),
+        ));
+    }
+    let (path, dir) = match key.strip_suffix('/') {
+        Some(path) => (path, true),
+        None => (key, false),
+    };
+    let names: Vec<&str> = path.split('/').collect();
+    if names.iter().any(|name| matches!(*name, "" | "." | "..")) {
+        return Err(Error::invalid(format!(
+            "the key {key} has an empty, . or .. component"
+        )));
+    }
+    Ok((names, dir))
+}
+
+/// A response of `status` without a body
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// A response of `status` holding the document `xml`
+fn xml_response(status: StatusCode, xml: Xml) -> Response<Body> {
+    let mut response = Response::new(Body::from(xml.finish()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+    response
+}
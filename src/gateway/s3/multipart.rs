@@ -0,0 +1,275 @@
+//! Multipart uploads, each staged in a directory of the staging directory
+//! until it completes
+//!
+//! The directory holds the bucket and key of the upload in `target`, and
+//! each part in a file named after its number and MD5, so the parts a
+//! client completes with are found by the ETags it lists.
+use std::ffi::OsStr;
+
+use hyper::{Body, Response, StatusCode};
+use md5::{Digest, Md5};
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use tracing::debug;
+
+use super::object::{etag_response, malformed_xml, MAX_XML_LEN, UPLOADS_MODE};
+use super::xml::{CompleteMultipartUpload, Xml};
+use super::{hex, is_missing, key_components, unhex, xml_response, Call, Error, S3Server, UPLOADS};
+use crate::storage::fs_util::{CreateParam, ROOT_ID};
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+/// The file of an upload holding its bucket and key
+const TARGET: &str = "target";
+/// The greatest part number
+const MAX_PART_NUMBER: u32 = 10_000;
+
+/// The name of the file of the part `number` whose MD5 is `etag`
+fn part_name(number: u32, etag: &str) -> String {
+    format!("{number:05}.{etag}")
+}
+
+impl<F: VirtualFs + 'static> S3Server<F> {
+    /// `CreateMultipartUpload`
+    pub(super) async fn create_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<Response<Body>, Error> {
+        self.bucket(bucket).await?;
+        if key_components(key)?.1 {
+            return Err(Error::invalid(format!("cannot upload to the directory {key}")));
+        }
+        let uploads = self.uploads().await?;
+        let id = self.unique_name("upload");
+        let param = CreateParam {
+            parent: uploads,
+            name: id.clone().into(),
+            mode: UPLOADS_MODE,
+            rdev: 0,
+            node_type: SFlag::S_IFDIR,
+            link: None,
+        };
+        let (_, dir, _) = self.fs.mkdir(&self.ctx(), param).await?;
+        let target = self.create_file(dir.ino, TARGET).await?;
+        let flags = OFlag::O_WRONLY.bits() as u32;
+        let fh = self.fs.open(&self.ctx(), target, flags).await?;
+        let written = self
+            .fs
+            .write(&self.ctx(), target, fh, 0, format!("{bucket}/{key}").as_bytes(), flags)
+            .await;
+        self.fs.release(&self.ctx(), target, fh, flags, 0, true).await?;
+        written?;
+
+        let mut xml = Xml::new("InitiateMultipartUploadResult");
+        xml.element("Bucket", bucket)
+            .element("Key", key)
+            .element("UploadId", &id);
+        Ok(xml_response(StatusCode::OK, xml))
+    }
+
+    /// `UploadPart`
+    pub(super) async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        call: &mut Call,
+    ) -> Result<Response<Body>, Error> {
+        let number = call
+            .param("partNumber")
+            .and_then(|number| number.parse::<u32>().ok())
+            .filter(|number| (1..=MAX_PART_NUMBER).contains(number))
+            .ok_or_else(|| {
+                Error::invalid(format!("part numbers are from 1 to {MAX_PART_NUMBER}"))
+            })?;
+        if call.header("x-amz-copy-source").is_some() {
+            return Err(Error::new(
+                StatusCode::NOT_IMPLEMENTED,
+                "NotImplemented",
+                "parts cannot be copied from other objects",
+            ));
+        }
+        let dir = self.upload(bucket, key, call).await?;
+        let staged = self.stage_in(dir, "part").await?;
+        let result = async {
+            let etag = hex(&self.receive(staged.ino, call).await?);
+            // Replacing the part sent before with the same data
+            let param = crate::storage::fs_util::RenameParam {
+                old_parent: dir,
+                old_name: staged.name.clone().into(),
+                new_parent: dir,
+                new_name: part_name(number, &etag).into(),
+                flags: 0,
+            };
+            self.fs.rename(&self.ctx(), param).await?;
+            Ok(etag)
+        }
+        .await;
+        match result {
+            Ok(etag) => etag_response(&format!("\"{etag}\"")),
+            Err(e) => {
+                self.discard(&staged).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// `CompleteMultipartUpload`, concatenating the parts listed
+    pub(super) async fn complete_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        call: &mut Call,
+    ) -> Result<Response<Body>, Error> {
+        let bucket_dir = self.bucket(bucket).await?;
+        let (names, _) = key_components(key)?;
+        let dir = self.upload(bucket, key, call).await?;
+        let body = self.read_body(call, MAX_XML_LEN).await?;
+        let complete: CompleteMultipartUpload =
+            serde_xml_rs::from_reader(&body[..]).map_err(malformed_xml)?;
+        if complete.parts.is_empty() {
+            return Err(Error::new(
+                StatusCode::BAD_REQUEST,
+                "MalformedXML",
+                "no part is listed",
+            ));
+        }
+        if complete
+            .parts
+            .windows(2)
+            .any(|pair| pair[0].number >= pair[1].number)
+        {
+            return Err(Error::new(
+                StatusCode::BAD_REQUEST,
+                "InvalidPartOrder",
+                "the parts are not listed in ascending order",
+            ));
+        }
+
+        let staged = self.stage_in(dir, "object").await?;
+        let result = async {
+            let flags = OFlag::O_WRONLY.bits() as u32;
+            let fh = self.fs.open(&self.ctx(), staged.ino, flags).await?;
+            let mut digests = Md5::new();
+            let appended = async {
+                let mut offset = 0;
+                for part in &complete.parts {
+                    let invalid_part = || {
+                        Error::new(
+                            StatusCode::BAD_REQUEST,
+                            "InvalidPart",
+                            format!("the part {} with the ETag {} is missing", part.number, part.etag),
+                        )
+                    };
+                    let etag = part.etag.trim_matches('"').to_ascii_lowercase();
+                    let digest = unhex(&etag)
+                        .filter(|digest| digest.len() == 16)
+                        .ok_or_else(invalid_part)?;
+                    let name = part_name(part.number, &etag);
+                    let attr = match self.fs.lookup(&self.ctx(), dir, OsStr::new(&name)).await {
+                        Ok((_, attr, _)) => attr,
+                        Err(e) if is_missing(&e) => return Err(invalid_part()),
+                        Err(e) => return Err(e.into()),
+                    };
+                    offset += self.append(attr.ino, staged.ino, fh, offset, None).await?;
+                    digests.update(&digest);
+                }
+                Ok(())
+            }
+            .await;
+            self.fs
+                .release(&self.ctx(), staged.ino, fh, flags, 0, true)
+                .await?;
+            appended?;
+            // The MD5 of the MD5s of the parts, followed by their count
+            let etag = format!("{}-{}", hex(&digests.finalize()), complete.parts.len());
+            self.set_etag(staged.ino, &etag).await?;
+            self.publish(&staged, bucket_dir, &names, key).await?;
+            Ok(etag)
+        }
+        .await;
+        let etag = match result {
+            Ok(etag) => etag,
+            Err(e) => {
+                self.discard(&staged).await;
+                return Err(e);
+            }
+        };
+        self.remove_upload(call.param("uploadId").unwrap_or(""), dir)
+            .await;
+
+        let mut xml = Xml::new("CompleteMultipartUploadResult");
+        xml.element("Location", &format!("/{bucket}/{key}"))
+            .element("Bucket", bucket)
+            .element("Key", key)
+            .element("ETag", &format!("\"{etag}\""));
+        Ok(xml_response(StatusCode::OK, xml))
+    }
+
+    /// `AbortMultipartUpload`
+    pub(super) async fn abort_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        call: &Call,
+    ) -> Result<Response<Body>, Error> {
+        let dir = self.upload(bucket, key, call).await?;
+        self.remove_upload(call.param("uploadId").unwrap_or(""), dir)
+            .await;
+        Ok(super::empty_response(StatusCode::NO_CONTENT))
+    }
+
+    /// The directory of the upload `call` names, failing unless it is an
+    /// upload to `key` of `bucket`
+    async fn upload(&self, bucket: &str, key: &str, call: &Call) -> Result<INum, Error> {
+        let id = call.param("uploadId").unwrap_or("");
+        let no_such_upload = || {
+            Error::new(
+                StatusCode::NOT_FOUND,
+                "NoSuchUpload",
+                format!("the upload {id} does not exist"),
+            )
+        };
+        if !id.starts_with("upload-") || id.contains('/') {
+            return Err(no_such_upload());
+        }
+        let Some(chain) = self.walk(ROOT_ID, &[UPLOADS, id, TARGET]).await? else {
+            return Err(no_such_upload());
+        };
+        let (dir, target) = (chain[2], chain[3]);
+        let flags = OFlag::O_RDONLY.bits() as u32;
+        let fh = self.fs.open(&self.ctx(), target.ino, flags).await?;
+        let len = usize::try_from(target.size).unwrap_or(usize::MAX).min(MAX_XML_LEN);
+        let mut buf = vec![0; len];
+        let read = self
+            .fs
+            .read(&self.ctx(), target.ino, fh, 0, len as u32, &mut buf)
+            .await;
+        self.fs
+            .release(&self.ctx(), target.ino, fh, flags, 0, false)
+            .await?;
+        buf.truncate(read?);
+        if buf != format!("{bucket}/{key}").as_bytes() {
+            return Err(no_such_upload());
+        }
+        Ok(dir.ino)
+    }
+
+    /// Remove the upload `id` whose directory is `dir` with its parts
+    async fn remove_upload(&self, id: &str, dir: INum) {
+        let result = async {
+            for (name, _) in self.entries(dir, "").await? {
+                self.fs.unlink(&self.ctx(), dir, OsStr::new(&name)).await?;
+            }
+            let (_, uploads, _) = self
+                .fs
+                .lookup(&self.ctx(), ROOT_ID, OsStr::new(UPLOADS))
+                .await?;
+            self.fs.rmdir(&self.ctx(), uploads.ino, OsStr::new(id)).await?;
+            Ok::<(), Error>(())
+        }
+        .await;
+        if let Err(e) = result {
+            debug!("failed to remove the upload {id}: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,613 @@
+//! The operations on objects and the staging of the data put
+use std::ffi::OsStr;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{
+    ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED, RANGE,
+};
+use hyper::{Body, Response, StatusCode};
+use md5::{Digest, Md5};
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use percent_encoding::percent_decode_str;
+use sha2::Sha256;
+use tracing::debug;
+
+use super::auth::Payload;
+use super::xml::{self, Xml};
+use super::{
+    empty_response, hex, key_components, xml_response, Call, Error, S3Server, UPLOADS,
+};
+use crate::common::DatenLordError;
+use crate::storage::fs_util::{CreateParam, RenameParam, ROOT_ID};
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+/// The mode of the files of objects, before the umask
+const FILE_MODE: u32 = 0o666;
+/// The mode of the directories of keys, before the umask
+pub(super) const DIR_MODE: u32 = 0o777;
+/// The mode of the staging directory and of the directories of uploads,
+/// before the umask
+pub(super) const UPLOADS_MODE: u32 = 0o700;
+/// The most bytes read or written at once
+pub(super) const CHUNK_LEN: usize = 1 << 20;
+/// The longest XML body accepted, in bytes
+pub(super) const MAX_XML_LEN: usize = 1 << 20;
+/// The ETag of directories, the MD5 of nothing
+const EMPTY_ETAG: &str = "\"d41d8cd98f00b204e9800998ecf8427e\"";
+
+impl<F: VirtualFs + 'static> S3Server<F> {
+    /// `GetObject`, or `HeadObject` without `body`
+    pub(super) async fn get_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        call: &Call,
+        body: bool,
+    ) -> Result<Response<Body>, Error> {
+        let dir = self.bucket(bucket).await?;
+        let (names, is_dir) = key_components(key)?;
+        let chain = self
+            .walk(dir, &names)
+            .await?
+            .ok_or_else(|| Error::no_such_key(key))?;
+        let attr = chain[chain.len() - 1];
+        let expected = if is_dir { SFlag::S_IFDIR } else { SFlag::S_IFREG };
+        if attr.kind != expected {
+            return Err(Error::no_such_key(key));
+        }
+        let (etag, size) = if is_dir {
+            (EMPTY_ETAG.to_owned(), 0)
+        } else {
+            (self.etag(&attr).await, attr.size)
+        };
+        let range = call
+            .parts
+            .headers
+            .get(RANGE)
+            .and_then(|range| range.to_str().ok())
+            .and_then(|range| parse_range(range, size));
+        let (start, end) = match range {
+            None => (0, size),
+            Some(Some(range)) => range,
+            Some(None) => {
+                return Err(Error::new(
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    "InvalidRange",
+                    format!("the range is not satisfiable for {size} bytes"),
+                ))
+            }
+        };
+        let mut response = Response::builder()
+            .header(CONTENT_LENGTH, end - start)
+            .header(CONTENT_TYPE, "binary/octet-stream")
+            .header(ETAG, etag)
+            .header(LAST_MODIFIED, xml::http_date(attr.mtime))
+            .header(ACCEPT_RANGES, "bytes");
+        response = if range.is_some() {
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_RANGE, format!("bytes {start}-{}/{size}", end - 1))
+        } else {
+            response.status(StatusCode::OK)
+        };
+        let body = if body && start < end {
+            self.send(attr.ino, start, end).await?
+        } else {
+            Body::empty()
+        };
+        response.body(body).map_err(internal)
+    }
+
+    /// A body streaming the bytes from `start` to `end` of the file `ino`
+    async fn send(&self, ino: INum, start: u64, end: u64) -> Result<Body, Error> {
+        let flags = OFlag::O_RDONLY.bits() as u32;
+        let fh = self.fs.open(&self.ctx(), ino, flags).await?;
+        let (mut sender, body) = Body::channel();
+        let (fs, ctx) = (Arc::clone(&self.fs), self.ctx());
+        tokio::spawn(async move {
+            let mut buf = vec![0; CHUNK_LEN];
+            let mut offset = start;
+            while offset < end {
+                let len = usize::try_from(end - offset).map_or(CHUNK_LEN, |len| len.min(CHUNK_LEN));
+                match fs.read(&ctx, ino, fh, offset, len as u32, &mut buf[..len]).await {
+                    Ok(read) if read > 0 => {
+                        let data = Bytes::copy_from_slice(&buf[..read]);
+                        if sender.send_data(data).await.is_err() {
+                            break;
+                        }
+                        offset += read as u64;
+                    }
+                    // Fail the response rather than end it early
+                    result => {
+                        debug!("failed to read inode {ino} at {offset}: {result:?}");
+                        sender.abort();
+                        break;
+                    }
+                }
+            }
+            if let Err(e) = fs.release(&ctx, ino, fh, flags, 0, false).await {
+                debug!("failed to release inode {ino}: {e}");
+            }
+        });
+        Ok(body)
+    }
+
+    /// `PutObject`, of a directory when `key` ends with `/`
+    pub(super) async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        call: &mut Call,
+    ) -> Result<Response<Body>, Error> {
+        let dir = self.bucket(bucket).await?;
+        let (names, is_dir) = key_components(key)?;
+        if is_dir {
+            self.read_body(call, 0).await?;
+            self.fs
+                .mkdir_all(&self.ctx(), dir, OsStr::new(&names.join("/")), DIR_MODE)
+                .await
+                .map_err(|e| conflict(e, key))?;
+            return etag_response(EMPTY_ETAG);
+        }
+        let staged = self.stage().await?;
+        let result = async {
+            let md5 = self.receive(staged.ino, call).await?;
+            let etag = hex(&md5);
+            self.set_etag(staged.ino, &etag).await?;
+            self.publish(&staged, dir, &names, key).await?;
+            Ok(etag)
+        }
+        .await;
+        match result {
+            Ok(etag) => etag_response(&format!("\"{etag}\"")),
+            Err(e) => {
+                self.discard(&staged).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// `CopyObject`, with the data copied
+    pub(super) async fn copy_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        call: &Call,
+    ) -> Result<Response<Body>, Error> {
+        let source = call.header("x-amz-copy-source").unwrap_or("");
+        let source = percent_decode_str(source)
+            .decode_utf8()
+            .map_err(|_| Error::invalid("the copy source is not UTF-8"))?;
+        // Without the version, which is always the current one
+        let source = source.split_once('?').map_or(&*source, |(source, _)| source);
+        let source = source.strip_prefix('/').unwrap_or(source);
+        let (source_bucket, source_key) = source
+            .split_once('/')
+            .ok_or_else(|| Error::invalid(format!("the copy source {source} has no key")))?;
+        let source_dir = self.bucket(source_bucket).await?;
+        let (source_names, _) = key_components(source_key)?;
+        let source = match self.walk(source_dir, &source_names).await? {
+            Some(chain) if chain[chain.len() - 1].kind == SFlag::S_IFREG => chain[chain.len() - 1],
+            _ => return Err(Error::no_such_key(source_key)),
+        };
+        let dir = self.bucket(bucket).await?;
+        let (names, is_dir) = key_components(key)?;
+        if is_dir {
+            return Err(Error::invalid(format!("cannot copy onto the directory {key}")));
+        }
+
+        let staged = self.stage().await?;
+        let result = async {
+            let flags = OFlag::O_WRONLY.bits() as u32;
+            let fh = self.fs.open(&self.ctx(), staged.ino, flags).await?;
+            let mut md5 = Md5::new();
+            let copied = self.append(source.ino, staged.ino, fh, 0, Some(&mut md5)).await;
+            self.fs
+                .release(&self.ctx(), staged.ino, fh, flags, 0, true)
+                .await?;
+            copied?;
+            let etag = hex(&md5.finalize());
+            self.set_etag(staged.ino, &etag).await?;
+            let (_, attr) = self.fs.getattr(&self.ctx(), staged.ino).await?;
+            self.publish(&staged, dir, &names, key).await?;
+            Ok((etag, attr))
+        }
+        .await;
+        let (etag, attr) = match result {
+            Ok(copied) => copied,
+            Err(e) => {
+                self.discard(&staged).await;
+                return Err(e);
+            }
+        };
+        let mut xml = Xml::new("CopyObjectResult");
+        xml.element("LastModified", &xml::iso8601(attr.mtime))
+            .element("ETag", &format!("\"{etag}\""));
+        Ok(xml_response(StatusCode::OK, xml))
+    }
+
+    /// `DeleteObject`, which succeeds for missing keys too
+    pub(super) async fn delete_object(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<Response<Body>, Error> {
+        let dir = self.bucket(bucket).await?;
+        self.remove(dir, key).await?;
+        Ok(empty_response(StatusCode::NO_CONTENT))
+    }
+
+    /// `DeleteObjects`
+    pub(super) async fn delete_objects(
+        &self,
+        bucket: &str,
+        call: &mut Call,
+    ) -> Result<Response<Body>, Error> {
+        let dir = self.bucket(bucket).await?;
+        let body = self.read_body(call, MAX_XML_LEN).await?;
+        let delete: xml::Delete = serde_xml_rs::from_reader(&body[..]).map_err(malformed_xml)?;
+        let mut xml = Xml::new("DeleteResult");
+        for object in &delete.objects {
+            match self.remove(dir, &object.key).await {
+                Ok(()) if delete.quiet => {}
+                Ok(()) => {
+                    xml.open("Deleted")
+                        .element("Key", &object.key)
+                        .close("Deleted");
+                }
+                Err(e) => {
+                    xml.open("Error")
+                        .element("Key", &object.key)
+                        .element("Code", e.code)
+                        .element("Message", &e.message)
+                        .close("Error");
+                }
+            }
+        }
+        Ok(xml_response(StatusCode::OK, xml))
+    }
+
+    /// Remove the object `key` of the bucket `dir` if it exists, with the
+    /// directories it leaves empty
+    async fn remove(&self, dir: INum, key: &str) -> Result<(), Error> {
+        let (names, is_dir) = key_components(key)?;
+        let Some(chain) = self.walk(dir, &names).await? else {
+            return Ok(());
+        };
+        let (parent, attr) = (chain[names.len() - 1], chain[names.len()]);
+        let name = OsStr::new(names[names.len() - 1]);
+        match (is_dir, attr.kind) {
+            (false, SFlag::S_IFREG) => self.fs.unlink(&self.ctx(), parent.ino, name).await?,
+            // Directories holding objects stay, as their prefix does
+            (true, SFlag::S_IFDIR) => {
+                if self.fs.rmdir(&self.ctx(), parent.ino, name).await.is_err() {
+                    return Ok(());
+                }
+            }
+            _ => return Ok(()),
+        }
+        for depth in (1..names.len()).rev() {
+            let name = OsStr::new(names[depth - 1]);
+            if self.fs.rmdir(&self.ctx(), chain[depth - 1].ino, name).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The staging directory, created if missing
+    pub(super) async fn uploads(&self) -> Result<INum, Error> {
+        let uploads = self
+            .fs
+            .mkdir_all(&self.ctx(), ROOT_ID, OsStr::new(UPLOADS), UPLOADS_MODE)
+            .await?;
+        Ok(uploads.ino)
+    }
+
+    /// A new empty file in the staging directory
+    pub(super) async fn stage(&self) -> Result<Staged, Error> {
+        let uploads = self.uploads().await?;
+        self.stage_in(uploads, "put").await
+    }
+
+    /// A new empty file in the directory `dir`, its name starting with `kind`
+    pub(super) async fn stage_in(&self, dir: INum, kind: &str) -> Result<Staged, Error> {
+        let name = self.unique_name(kind);
+        let ino = self.create_file(dir, &name).await?;
+        Ok(Staged { dir, name, ino })
+    }
+
+    /// Create the empty file `name` in the directory `dir`
+    pub(super) async fn create_file(&self, dir: INum, name: &str) -> Result<INum, Error> {
+        let param = CreateParam {
+            parent: dir,
+            name: name.into(),
+            mode: FILE_MODE,
+            rdev: 0,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        let (_, attr, _) = self.fs.mknod(&self.ctx(), param).await?;
+        Ok(attr.ino)
+    }
+
+    /// Remove `staged`, whose data was not published
+    pub(super) async fn discard(&self, staged: &Staged) {
+        let name = OsStr::new(&staged.name);
+        if let Err(e) = self.fs.unlink(&self.ctx(), staged.dir, name).await {
+            debug!("failed to discard the staged file {}: {e}", staged.name);
+        }
+    }
+
+    /// Move `staged` to `key`, made of `names`, in the bucket `bucket`,
+    /// replacing the object there
+    pub(super) async fn publish(
+        &self,
+        staged: &Staged,
+        bucket: INum,
+        names: &[&str],
+        key: &str,
+    ) -> Result<(), Error> {
+        let Some((name, parents)) = names.split_last() else {
+            return Err(Error::invalid("the key is empty"));
+        };
+        let parent = if parents.is_empty() {
+            bucket
+        } else {
+            self.fs
+                .mkdir_all(&self.ctx(), bucket, OsStr::new(&parents.join("/")), DIR_MODE)
+                .await
+                .map_err(|e| conflict(e, key))?
+                .ino
+        };
+        if let Ok((_, attr, _)) = self.fs.lookup(&self.ctx(), parent, OsStr::new(name)).await {
+            if attr.kind == SFlag::S_IFDIR {
+                let e = DatenLordError::AlreadyExists {
+                    context: vec![format!("{key} is a directory")],
+                    source: None,
+                };
+                return Err(conflict(e, key));
+            }
+        }
+        let param = RenameParam {
+            old_parent: staged.dir,
+            old_name: staged.name.clone().into(),
+            new_parent: parent,
+            new_name: (*name).into(),
+            flags: 0,
+        };
+        self.fs.rename(&self.ctx(), param).await?;
+        Ok(())
+    }
+
+    /// Write the body of `call` to the file `ino`, returning its MD5 once
+    /// checked against the digests the request carries
+    pub(super) async fn receive(&self, ino: INum, call: &mut Call) -> Result<[u8; 16], Error> {
+        let flags = OFlag::O_WRONLY.bits() as u32;
+        let fh = self.fs.open(&self.ctx(), ino, flags).await?;
+        let mut digests = Digests::default();
+        let mut buf = Vec::with_capacity(CHUNK_LEN);
+        let mut offset = 0;
+        let result = async {
+            while let Some(data) = call.body.data().await {
+                let data = data.map_err(incomplete)?;
+                digests.update(&data);
+                buf.extend_from_slice(&data);
+                if buf.len() >= CHUNK_LEN {
+                    self.fs
+                        .write(&self.ctx(), ino, fh, offset, &buf, flags)
+                        .await?;
+                    offset += i64::try_from(buf.len()).unwrap_or(i64::MAX);
+                    buf.clear();
+                }
+            }
+            if !buf.is_empty() {
+                self.fs
+                    .write(&self.ctx(), ino, fh, offset, &buf, flags)
+                    .await?;
+            }
+            Ok::<(), Error>(())
+        }
+        .await;
+        self.fs.release(&self.ctx(), ino, fh, flags, 0, true).await?;
+        result?;
+        digests.check(call)
+    }
+
+    /// The body of `call`, of at most `limit` bytes, once checked against
+    /// the digests the request carries
+    pub(super) async fn read_body(&self, call: &mut Call, limit: usize) -> Result<Vec<u8>, Error> {
+        let mut digests = Digests::default();
+        let mut body = Vec::new();
+        while let Some(data) = call.body.data().await {
+            let data = data.map_err(incomplete)?;
+            if body.len() + data.len() > limit {
+                return Err(Error::new(
+                    StatusCode::BAD_REQUEST,
+                    "MaxMessageLengthExceeded",
+                    format!("the body is longer than {limit} bytes"),
+                ));
+            }
+            digests.update(&data);
+            body.extend_from_slice(&data);
+        }
+        digests.check(call)?;
+        Ok(body)
+    }
+
+    /// Append the file `src` to the file `dst` opened as `fh` at `offset`,
+    /// feeding `md5` if any, returning the bytes copied
+    pub(super) async fn append(
+        &self,
+        src: INum,
+        dst: INum,
+        fh: u64,
+        mut offset: u64,
+        mut md5: Option<&mut Md5>,
+    ) -> Result<u64, Error> {
+        let flags = OFlag::O_RDONLY.bits() as u32;
+        let src_fh = self.fs.open(&self.ctx(), src, flags).await?;
+        let mut buf = vec![0; CHUNK_LEN];
+        let start = offset;
+        let result = async {
+            loop {
+                let read = self
+                    .fs
+                    .read(&self.ctx(), src, src_fh, offset - start, CHUNK_LEN as u32, &mut buf)
+                    .await?;
+                if read == 0 {
+                    return Ok(offset - start);
+                }
+                let write_offset = i64::try_from(offset).unwrap_or(i64::MAX);
+                self.fs
+                    .write(&self.ctx(), dst, fh, write_offset, &buf[..read], 0)
+                    .await?;
+                if let Some(ref mut md5) = md5 {
+                    md5.update(&buf[..read]);
+                }
+                offset += read as u64;
+            }
+        }
+        .await;
+        self.fs.release(&self.ctx(), src, src_fh, flags, 0, false).await?;
+        result
+    }
+}
+
+/// A file written before it is moved into place
+#[derive(Debug)]
+pub(super) struct Staged {
+    /// The directory holding it
+    pub(super) dir: INum,
+    pub(super) name: String,
+    pub(super) ino: INum,
+}
+
+/// The digests of a body being received, checked against those of the
+/// request when it ends
+#[derive(Default)]
+struct Digests {
+    md5: Md5,
+    sha256: Sha256,
+    crc32: crc32fast::Hasher,
+}
+
+impl Digests {
+    fn update(&mut self, data: &[u8]) {
+        self.md5.update(data);
+        self.sha256.update(data);
+        self.crc32.update(data);
+    }
+
+    /// The MD5 of the body, failing if it does not match the payload hash
+    /// signed or the `Content-MD5` and checksum headers of `call`
+    fn check(self, call: &Call) -> Result<[u8; 16], Error> {
+        let md5: [u8; 16] = self.md5.finalize().into();
+        let sha256 = self.sha256.finalize();
+        if let Payload::Sha256(ref expected) = call.payload {
+            if hex(&sha256) != *expected {
+                return Err(Error::new(
+                    StatusCode::BAD_REQUEST,
+                    "XAmzContentSHA256Mismatch",
+                    "the body does not match the signed X-Amz-Content-SHA256",
+                ));
+            }
+        }
+        let checks = [
+            ("content-md5", STANDARD.encode(md5)),
+            ("x-amz-checksum-sha256", STANDARD.encode(sha256)),
+            (
+                "x-amz-checksum-crc32",
+                STANDARD.encode(self.crc32.finalize().to_be_bytes()),
+            ),
+        ];
+        for (header, digest) in checks {
+            if call.header(header).is_some_and(|expected| expected != digest) {
+                return Err(Error::new(
+                    StatusCode::BAD_REQUEST,
+                    "BadDigest",
+                    format!("the body does not match its {header}"),
+                ));
+            }
+        }
+        Ok(md5)
+    }
+}
+
+impl std::fmt::Debug for Digests {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Digests").finish_non_exhaustive()
+    }
+}
+
+/// The bytes from the start to the end of the `Range` header `range` in
+/// an object of `size` bytes, `None` if it is not one range of bytes, which
+/// is ignored, and `Some(None)` if it is not satisfiable
+fn parse_range(range: &str, size: u64) -> Option<Option<(u64, u64)>> {
+    let (first, last) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = if first.is_empty() {
+        let len: u64 = last.parse().ok()?;
+        if len == 0 {
+            return Some(None);
+        }
+        (size.saturating_sub(len), size)
+    } else {
+        let start: u64 = first.parse().ok()?;
+        let end = if last.is_empty() {
+            size
+        } else {
+            let last: u64 = last.parse().ok()?;
+            if last < start {
+                return None;
+            }
+            last.saturating_add(1).min(size)
+        };
+        (start, end)
+    };
+    Some((start < size).then_some((start, end)))
+}
+
+/// A response holding the ETag `etag`, quoted
+pub(super) fn etag_response(etag: &str) -> Result<Response<Body>, Error> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(ETAG, etag)
+        .body(Body::empty())
+        .map_err(internal)
+}
+
+/// The error of storing `key` failing with `e`, a conflict when an object
+/// and a directory would share a path
+pub(super) fn conflict(e: DatenLordError, key: &str) -> Error {
+    match e {
+        DatenLordError::AlreadyExists { .. } => Error::new(
+            StatusCode::CONFLICT,
+            "InvalidRequest",
+            format!("the key {key} and another key are a file and a directory of one path"),
+        ),
+        e => e.into(),
+    }
+}
+
+/// An unexpected failure of the server
+pub(super) fn internal(e: impl ToString) -> Error {
+    Error::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "InternalError",
+        e.to_string(),
+    )
+}
+
+/// A body not parsed as the XML expected
+pub(super) fn malformed_xml(e: serde_xml_rs::Error) -> Error {
+    Error::new(StatusCode::BAD_REQUEST, "MalformedXML", e.to_string())
+}
+
+/// A body that could not be received
+fn incomplete(e: hyper::Error) -> Error {
+    Error::new(StatusCode::BAD_REQUEST, "IncompleteBody", e.to_string())
+}
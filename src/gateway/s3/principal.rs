@@ -0,0 +1,110 @@
+//! Who a request comes from, told by a bearer token or the certificate the
+//! client presented over TLS rather than by a signature
+//!
+//! The principal an `Authenticator` finds acts with its own ids, mapped by
+//! the `idmap` of the config. Requests it finds none for are left to the
+//! signature check, and rejected when the endpoint has no key.
+use std::collections::HashMap;
+use std::fmt;
+
+use hyper::header::AUTHORIZATION;
+use hyper::http::request::Parts;
+use sha2::{Digest, Sha256};
+
+use super::hex;
+use crate::gateway::PrincipalConfig;
+
+/// The ids a caller acts with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Principal {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Tells who a request comes from
+pub trait Authenticator: Send + Sync + fmt::Debug {
+    /// The principal of the request with `parts`, from a client that
+    /// presented the DER `certificates`, leaf first, `None` if unknown
+    fn authenticate(&self, parts: &Parts, certificates: &[Vec<u8>]) -> Option<Principal>;
+}
+
+/// The token of `Authorization: Bearer <token>`
+#[must_use]
+pub fn bearer_token(parts: &Parts) -> Option<&str> {
+    let authorization = parts.headers.get(AUTHORIZATION)?.to_str().ok()?;
+    authorization.strip_prefix("Bearer ").map(str::trim)
+}
+
+/// The principals of the config, known by their token or the SHA-256 of
+/// their certificate
+#[derive(Debug, Default)]
+pub struct StaticPrincipals {
+    /// By the SHA-256 of their token, so looking one up takes no longer for
+    /// tokens closer to a known one
+    tokens: HashMap<[u8; 32], Principal>,
+    /// By the lowercase hex SHA-256 of their certificate
+    certificates: HashMap<String, Principal>,
+}
+
+impl StaticPrincipals {
+    /// The principals of `configs`
+    #[must_use]
+    pub fn new(configs: &[PrincipalConfig]) -> Self {
+        let mut principals = Self::default();
+        for config in configs {
+            let principal = Principal {
+                uid: config.uid,
+                gid: config.gid,
+            };
+            if !config.token.is_empty() {
+                let digest = Sha256::digest(config.token.as_bytes()).into();
+                principals.tokens.insert(digest, principal);
+            }
+            if !config.certificate_sha256.is_empty() {
+                let fingerprint = config.certificate_sha256.replace(':', "").to_lowercase();
+                principals.certificates.insert(fingerprint, principal);
+            }
+        }
+        principals
+    }
+}
+
+impl Authenticator for StaticPrincipals {
+    fn authenticate(&self, parts: &Parts, certificates: &[Vec<u8>]) -> Option<Principal> {
+        if let Some(token) = bearer_token(parts) {
+            let digest: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+            if let Some(&principal) = self.tokens.get(&digest) {
+                return Some(principal);
+            }
+        }
+        let leaf = certificates.first()?;
+        self.certificates.get(&hex(&Sha256::digest(leaf))).copied()
+    }
+}
+
+/// Hands the bearer token of requests to a callback, which validates it,
+/// e.g. as a JWT against the keys of an identity provider, and returns the
+/// principal it names
+pub struct BearerAuthenticator<V> {
+    validate: V,
+}
+
+impl<V: Fn(&str) -> Option<Principal> + Send + Sync> BearerAuthenticator<V> {
+    /// An authenticator of the tokens `validate` accepts
+    pub fn new(validate: V) -> Self {
+        Self { validate }
+    }
+}
+
+impl<V> fmt::Debug for BearerAuthenticator<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BearerAuthenticator")
+            .finish_non_exhaustive()
+    }
+}
+
+impl<V: Fn(&str) -> Option<Principal> + Send + Sync> Authenticator for BearerAuthenticator<V> {
+    fn authenticate(&self, parts: &Parts, _certificates: &[Vec<u8>]) -> Option<Principal> {
+        (self.validate)(bearer_token(parts)?)
+    }
+}
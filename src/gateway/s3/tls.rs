@@ -0,0 +1,73 @@
+//! The TLS the endpoint is served with when `S3Config` has `tls`, asking
+//! clients for a certificate signed by `client_ca` when it is set
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls_pemfile::Item;
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::gateway::TlsConfig;
+
+/// A `DatenLordError::Io` for the TLS file `path`
+fn tls_error(path: &Path, e: impl std::fmt::Display) -> DatenLordError {
+    DatenLordError::Io {
+        context: vec![format!("failed to load the TLS file {path:?}: {e}")],
+        source: None,
+    }
+}
+
+/// The PEM items of the file `path`
+fn read_pem(path: &Path) -> DatenLordResult<Vec<Item>> {
+    let file = File::open(path).map_err(|e| tls_error(path, e))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file)).map_err(|e| tls_error(path, e))
+}
+
+/// The certificates of the PEM file `path`, failing if it has none
+fn read_certificates(path: &Path) -> DatenLordResult<Vec<Certificate>> {
+    let certificates: Vec<_> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certificates.is_empty() {
+        return Err(tls_error(path, "no certificate"));
+    }
+    Ok(certificates)
+}
+
+/// The server side of the TLS of `config`, speaking HTTP/1.1
+pub(super) fn server_config(config: &TlsConfig) -> DatenLordResult<Arc<ServerConfig>> {
+    let chain = read_certificates(&config.cert)?;
+    let key = read_pem(&config.key)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| tls_error(&config.key, "no private key"))?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match config.client_ca {
+        Some(ref client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in read_certificates(client_ca)? {
+                roots
+                    .add(&certificate)
+                    .map_err(|e| tls_error(client_ca, e))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server = builder
+        .with_single_cert(chain, key)
+        .map_err(|e| tls_error(&config.cert, e))?;
+    server.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(server))
+}
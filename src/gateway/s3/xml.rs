@@ -0,0 +1,158 @@
+//! The XML bodies of the requests and responses, and the dates they hold
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_derive::Deserialize;
+
+/// The namespace of the elements of S3 responses
+const NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+/// Builds a response document
+#[derive(Debug)]
+pub(super) struct Xml {
+    buf: String,
+    /// The element the document is made of
+    root: &'static str,
+}
+
+impl Xml {
+    /// A document made of the element `root` of the S3 namespace
+    pub(super) fn new(root: &'static str) -> Self {
+        Self::with_attributes(root, &format!(" xmlns=\"{NAMESPACE}\""))
+    }
+
+    /// An error document, which has no namespace
+    pub(super) fn error() -> Self {
+        Self::with_attributes("Error", "")
+    }
+
+    fn with_attributes(root: &'static str, attributes: &str) -> Self {
+        let buf = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<{root}{attributes}>");
+        Self { buf, root }
+    }
+
+    /// Start the element `name`
+    pub(super) fn open(&mut self, name: &str) -> &mut Self {
+        self.buf.push_str(&format!("<{name}>"));
+        self
+    }
+
+    /// End the element `name`
+    pub(super) fn close(&mut self, name: &str) -> &mut Self {
+        self.buf.push_str(&format!("</{name}>"));
+        self
+    }
+
+    /// The element `name` holding `text`
+    pub(super) fn element(&mut self, name: &str, text: &str) -> &mut Self {
+        self.open(name).text(text).close(name)
+    }
+
+    /// `text`, escaped
+    pub(super) fn text(&mut self, text: &str) -> &mut Self {
+        for c in text.chars() {
+            match c {
+                '<' => self.buf.push_str("&lt;"),
+                '>' => self.buf.push_str("&gt;"),
+                '&' => self.buf.push_str("&amp;"),
+                '"' => self.buf.push_str("&quot;"),
+                '\'' => self.buf.push_str("&apos;"),
+                c => self.buf.push(c),
+            }
+        }
+        self
+    }
+
+    /// The document with its root element ended
+    pub(super) fn finish(mut self) -> String {
+        let root = self.root;
+        self.close(root);
+        self.buf
+    }
+}
+
+/// The body of `CompleteMultipartUpload`
+#[derive(Debug, Deserialize)]
+pub(super) struct CompleteMultipartUpload {
+    #[serde(rename = "Part", default)]
+    pub(super) parts: Vec<CompletedPart>,
+}
+
+/// A part of `CompleteMultipartUpload`
+#[derive(Debug, Deserialize)]
+pub(super) struct CompletedPart {
+    #[serde(rename = "PartNumber")]
+    pub(super) number: u32,
+    #[serde(rename = "ETag")]
+    pub(super) etag: String,
+}
+
+/// The body of `DeleteObjects`
+#[derive(Debug, Deserialize)]
+pub(super) struct Delete {
+    #[serde(rename = "Object", default)]
+    pub(super) objects: Vec<ObjectIdentifier>,
+    /// Report only the keys that failed
+    #[serde(rename = "Quiet", default)]
+    pub(super) quiet: bool,
+}
+
+/// An object of `DeleteObjects`
+#[derive(Debug, Deserialize)]
+pub(super) struct ObjectIdentifier {
+    #[serde(rename = "Key")]
+    pub(super) key: String,
+}
+
+/// The date and time of `time` in UTC, the year, month, day, hours, minutes
+/// and seconds
+fn civil(time: SystemTime) -> (u64, u64, u64, u64, u64, u64) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // See Howard Hinnant's `civil_from_days`
+    let days = secs / 86_400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = (shifted_month + 2) % 12 + 1;
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    let (hours, minutes, seconds) = (secs % 86_400 / 3600, secs % 3600 / 60, secs % 60);
+    (year, month, day, hours, minutes, seconds)
+}
+
+/// `time` as ISO 8601, the `LastModified` of listings
+pub(super) fn iso8601(time: SystemTime) -> String {
+    let (year, month, day, hours, minutes, seconds) = civil(time);
+    format!("{year:04}-{month:02}-{day:02}T{hours:02}:{minutes:02}:{seconds:02}.000Z")
+}
+
+/// `time` as the basic ISO 8601 of `X-Amz-Date`
+pub(super) fn amz_date(time: SystemTime) -> String {
+    let (year, month, day, hours, minutes, seconds) = civil(time);
+    format!("{year:04}{month:02}{day:02}T{hours:02}{minutes:02}{seconds:02}Z")
+}
+
+/// `time` as the IMF-fixdate of RFC 9110, the `Last-Modified` header
+pub(super) fn http_date(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let weekday = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86_400
+        % 7;
+    let (year, month, day, hours, minutes, seconds) = civil(time);
+    format!(
+        "{}, {day:02} {} {year:04} {hours:02}:{minutes:02}:{seconds:02} GMT",
+        DAYS[weekday as usize],
+        MONTHS[month as usize - 1]
+    )
+}
@@ -0,0 +1,139 @@
+//! An SFTP version 3 server over any `VirtualFs`, see
+//! draft-ietf-secsh-filexfer-02
+//!
+//! The SSH transport is left to `sshd`, which runs `datenlord-sftp` as its
+//! SFTP subsystem for users it authenticated, so `sftp`, `scp` and the
+//! tools built on them move data in and out with the keys and accounts
+//! already set up:
+//! `Subsystem sftp /usr/local/bin/datenlord-sftp --config @/etc/datenlord.json`
+//!
+//! Sessions see the directory of `SftpConfig` as `/` and `..` never leaves
+//! it. Requests of a session are served in order, like `sftp-server` does,
+//! while sessions on one runtime share the server and its filesystem.
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use nix::sys::stat::SFlag;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use self::session::Session;
+use super::SftpConfig;
+use crate::common::config::DatenLordConfig;
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::sdk;
+use crate::storage::fs_util::{self, FileAttr, RequestContext, ROOT_ID};
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+mod session;
+pub mod wire;
+
+/// The SFTP server of a directory of a filesystem
+#[derive(Debug)]
+pub struct SftpServer<F> {
+    fs: Arc<F>,
+    /// The inode of the directory sessions see as `/`
+    root: INum,
+    read_only: bool,
+}
+
+impl<F: VirtualFs + 'static> SftpServer<F> {
+    /// A server of the directory of `config` on `fs`, failing if it cannot
+    /// be looked up
+    pub async fn new(fs: Arc<F>, config: &SftpConfig) -> DatenLordResult<Self> {
+        let ctx = RequestContext::current();
+        let mut root = ROOT_ID;
+        for name in fs_util::components(OsStr::new(&config.path)) {
+            let (_, attr, _) = fs.lookup(&ctx, root, name).await?;
+            if attr.kind != SFlag::S_IFDIR {
+                return Err(DatenLordError::InvalidArgument {
+                    context: vec![format!("served path {:?} is not a directory", config.path)],
+                });
+            }
+            root = attr.ino;
+        }
+        Ok(Self {
+            fs,
+            root,
+            read_only: config.read_only,
+        })
+    }
+
+    /// Serve the session of the client sending requests to `reader` and
+    /// reading the replies from `writer`, acting as `ctx`, until it ends
+    pub async fn serve<R, W>(
+        &self,
+        ctx: RequestContext,
+        mut reader: R,
+        mut writer: W,
+    ) -> io::Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut session = Session::new(self, ctx);
+        let result = async {
+            while let Some(packet) = wire::read_packet(&mut reader).await? {
+                if let Some(reply) = session.handle(&packet).await {
+                    wire::write_packet(&mut writer, &reply).await?;
+                }
+            }
+            Ok(())
+        }
+        .await;
+        // The handles the client left open
+        session.close_all().await;
+        result
+    }
+
+    /// The `/` separated `path` the client sent, resolved against `/` with
+    /// `..` lexically removing the component before it
+    fn components(path: &[u8]) -> Vec<OsString> {
+        let mut components = Vec::new();
+        for name in fs_util::components(OsStr::from_bytes(path)) {
+            if name == ".." {
+                components.pop();
+            } else {
+                components.push(name.to_owned());
+            }
+        }
+        components
+    }
+
+    /// The attributes of the entry at `components`
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        components: &[OsString],
+    ) -> DatenLordResult<FileAttr> {
+        let (_, mut attr) = self.fs.getattr(ctx, self.root).await?;
+        for name in components {
+            attr = self.fs.lookup(ctx, attr.ino, name).await?.1;
+        }
+        Ok(attr)
+    }
+}
+
+/// Serve the directory of the `sftp` field of `config` on the filesystem
+/// stack of the SDKs to the one session on the standard input and output,
+/// as `sshd` runs SFTP subsystems, reloading `config_file` on `SIGHUP`, see
+/// `sdk::reload_on_hangup`
+pub async fn run(config: &DatenLordConfig, config_file: Option<PathBuf>) -> DatenLordResult<()> {
+    let fs = Arc::new(sdk::open_fs(config)?);
+    let _writeback = sdk::writeback(&fs, config)?;
+    sdk::reload_on_hangup(&fs, config_file)?;
+    let server = SftpServer::new(fs, &config.sftp).await?;
+    server
+        .serve(
+            config.request_context(),
+            tokio::io::stdin(),
+            tokio::io::stdout(),
+        )
+        .await
+        .map_err(|e| DatenLordError::Io {
+            context: vec![format!("SFTP session failed: {e}")],
+            source: None,
+        })
+}
@@ -0,0 +1,837 @@
+//! The requests of an SFTP session and the handles it opened
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::errno::Errno;
+use nix::fcntl::{OFlag, RenameFlags};
+use nix::sys::stat::SFlag;
+use tracing::debug;
+
+use super::wire::{Reader, Writer};
+use super::SftpServer;
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::fs_util::{CreateParam, FileAttr, RenameParam, RequestContext, SetAttrParam};
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+/// The version of the protocol served
+const VERSION: u32 = 3;
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_LSTAT: u8 = 7;
+const FXP_FSTAT: u8 = 8;
+const FXP_SETSTAT: u8 = 9;
+const FXP_FSETSTAT: u8 = 10;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_REMOVE: u8 = 13;
+const FXP_MKDIR: u8 = 14;
+const FXP_RMDIR: u8 = 15;
+const FXP_REALPATH: u8 = 16;
+const FXP_STAT: u8 = 17;
+const FXP_RENAME: u8 = 18;
+const FXP_READLINK: u8 = 19;
+const FXP_SYMLINK: u8 = 20;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+const FXP_EXTENDED: u8 = 200;
+const FXP_EXTENDED_REPLY: u8 = 201;
+
+/// The attribute flags of `ATTRS`
+const ATTR_SIZE: u32 = 0x1;
+const ATTR_UIDGID: u32 = 0x2;
+const ATTR_PERMISSIONS: u32 = 0x4;
+const ATTR_ACMODTIME: u32 = 0x8;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+/// The flags of `OPEN`
+const FXF_READ: u32 = 0x1;
+const FXF_WRITE: u32 = 0x2;
+const FXF_APPEND: u32 = 0x4;
+const FXF_CREAT: u32 = 0x8;
+const FXF_TRUNC: u32 = 0x10;
+const FXF_EXCL: u32 = 0x20;
+
+/// The extensions of OpenSSH served, with their versions
+const EXTENSIONS: [(&str, &str); 3] = [
+    ("posix-rename@openssh.com", "1"),
+    ("statvfs@openssh.com", "2"),
+    ("fsync@openssh.com", "1"),
+];
+
+/// The most bytes a `READ` returns, what clients ask for at most
+const MAX_READ: u32 = 256 << 10;
+/// The most names a `READDIR` returns
+const MAX_NAMES: usize = 128;
+/// The mode of files created without one, before the umask
+const FILE_MODE: u32 = 0o666;
+/// The mode of directories created without one, before the umask
+const DIR_MODE: u32 = 0o777;
+/// The `ST_RDONLY` flag of `statvfs`
+const ST_RDONLY: u64 = 0x1;
+
+/// The status of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok = 0,
+    Eof = 1,
+    NoSuchFile = 2,
+    PermissionDenied = 3,
+    Failure = 4,
+    BadMessage = 5,
+    OpUnsupported = 8,
+}
+
+impl Status {
+    /// The message sent along, like `sftp-server` does
+    fn message(self) -> &'static str {
+        match self {
+            Self::Ok => "Success",
+            Self::Eof => "End of file",
+            Self::NoSuchFile => "No such file",
+            Self::PermissionDenied => "Permission denied",
+            Self::Failure => "Failure",
+            Self::BadMessage => "Bad message",
+            Self::OpUnsupported => "Operation unsupported",
+        }
+    }
+}
+
+impl From<DatenLordError> for Status {
+    fn from(e: DatenLordError) -> Self {
+        match e.errno() {
+            Some(Errno::EACCES | Errno::EPERM) => Self::PermissionDenied,
+            Some(Errno::ENOENT) => Self::NoSuchFile,
+            Some(Errno::ENOTSUP) => Self::OpUnsupported,
+            _ => Self::Failure,
+        }
+    }
+}
+
+/// The status of a lookup failing with `e`, backends reporting missing
+/// entries without an errno
+fn not_found(e: DatenLordError) -> Status {
+    if e.errno().is_none() {
+        Status::NoSuchFile
+    } else {
+        e.into()
+    }
+}
+
+/// The attributes a client sends, `ATTRS`
+#[derive(Debug, Default)]
+struct Attrs {
+    size: Option<u64>,
+    owner: Option<(u32, u32)>,
+    permissions: Option<u32>,
+    times: Option<(u32, u32)>,
+}
+
+impl Attrs {
+    fn decode(args: &mut Reader<'_>) -> DatenLordResult<Self> {
+        let flags = args.u32()?;
+        let mut attrs = Self::default();
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(args.u64()?);
+        }
+        if flags & ATTR_UIDGID != 0 {
+            attrs.owner = Some((args.u32()?, args.u32()?));
+        }
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(args.u32()?);
+        }
+        if flags & ATTR_ACMODTIME != 0 {
+            attrs.times = Some((args.u32()?, args.u32()?));
+        }
+        if flags & ATTR_EXTENDED != 0 {
+            // No extended attributes are understood
+            for _ in 0..args.u32()? {
+                args.string()?;
+                args.string()?;
+            }
+        }
+        Ok(attrs)
+    }
+
+    /// The mode of an entry created with the attributes, `default` when
+    /// they have none
+    fn mode(&self, default: u32) -> u32 {
+        self.permissions
+            .map_or(default, |permissions| permissions & 0o7777)
+    }
+
+    fn param(&self) -> SetAttrParam {
+        let time = |secs: u32| UNIX_EPOCH + Duration::from_secs(secs.into());
+        SetAttrParam {
+            mode: self.permissions.map(|permissions| permissions & 0o7777),
+            u_id: self.owner.map(|(uid, _)| uid),
+            g_id: self.owner.map(|(_, gid)| gid),
+            size: self.size,
+            a_time: self.times.map(|(atime, _)| time(atime)),
+            m_time: self.times.map(|(_, mtime)| time(mtime)),
+            ..SetAttrParam::default()
+        }
+    }
+}
+
+/// The seconds of `time` since the epoch as `ATTRS` holds them
+fn secs(time: SystemTime) -> u32 {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    u32::try_from(secs).unwrap_or(u32::MAX)
+}
+
+/// The `ATTRS` of `attr`, its type being part of the permissions
+fn encode_attrs(out: &mut Writer, attr: &FileAttr) {
+    out.u32(ATTR_SIZE | ATTR_UIDGID | ATTR_PERMISSIONS | ATTR_ACMODTIME)
+        .u64(attr.size)
+        .u32(attr.uid)
+        .u32(attr.gid)
+        .u32(attr.kind.bits() | u32::from(attr.perm))
+        .u32(secs(attr.atime))
+        .u32(secs(attr.mtime));
+}
+
+/// The line `ls -l` prints for the entry `name` with `attr`, which clients
+/// show as is, the time being in UTC
+fn long_name(name: &OsStr, attr: &FileAttr) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let kind = match attr.kind {
+        SFlag::S_IFDIR => 'd',
+        SFlag::S_IFLNK => 'l',
+        SFlag::S_IFCHR => 'c',
+        SFlag::S_IFBLK => 'b',
+        SFlag::S_IFIFO => 'p',
+        SFlag::S_IFSOCK => 's',
+        _ => '-',
+    };
+    let perm = u32::from(attr.perm);
+    let mut mode = String::from(kind);
+    for (shift, special, flag) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = perm >> shift;
+        mode.push(if bits & 4 == 0 { '-' } else { 'r' });
+        mode.push(if bits & 2 == 0 { '-' } else { 'w' });
+        mode.push(match (bits & 1 != 0, perm & special != 0) {
+            (true, true) => flag,
+            (false, true) => flag.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    let secs = u64::from(secs(attr.mtime));
+    // The civil date of the day, see Howard Hinnant's `civil_from_days`
+    let day_of_era = (secs / 86_400 + 719_468) % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = (shifted_month + 2) % 12;
+    format!(
+        "{mode} {:>3} {:<8} {:<8} {:>8} {} {day:>2} {:02}:{:02} {}",
+        attr.nlink,
+        attr.uid,
+        attr.gid,
+        attr.size,
+        MONTHS[month as usize],
+        secs % 86_400 / 3600,
+        secs % 3600 / 60,
+        name.to_string_lossy()
+    )
+}
+
+/// The reply of type `kind` to the request `id`
+fn reply(kind: u8, id: u32) -> Writer {
+    let mut out = Writer::new(kind);
+    out.u32(id);
+    out
+}
+
+/// The `STATUS` reply to the request `id`
+fn status(id: u32, status: Status) -> Writer {
+    let mut out = reply(FXP_STATUS, id);
+    out.u32(status as u32)
+        .string(status.message().as_bytes())
+        .string(b"");
+    out
+}
+
+/// A file or directory the client opened
+#[derive(Debug)]
+enum Handle {
+    File {
+        ino: INum,
+        fh: u64,
+        /// Whether writes go to the end of the file, whatever their offset
+        append: bool,
+    },
+    Dir {
+        ino: INum,
+        fh: u64,
+        /// The entries not returned yet, listed by the first `READDIR`
+        entries: Option<VecDeque<(OsString, FileAttr)>>,
+    },
+}
+
+/// A session of a client
+pub(super) struct Session<'a, F> {
+    server: &'a SftpServer<F>,
+    /// The caller the requests act as
+    ctx: RequestContext,
+    handles: HashMap<u32, Handle>,
+    next_handle: u32,
+}
+
+impl<'a, F: VirtualFs + 'static> Session<'a, F> {
+    pub(super) fn new(server: &'a SftpServer<F>, ctx: RequestContext) -> Self {
+        Self {
+            server,
+            ctx,
+            handles: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    /// The reply to the request `packet`, `None` if it is not even a
+    /// request
+    pub(super) async fn handle(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        let mut args = Reader::new(packet);
+        let kind = args.u8().ok()?;
+        if kind == FXP_INIT {
+            // Clients speaking later versions fall back to the one served
+            let mut out = Writer::new(FXP_VERSION);
+            out.u32(VERSION);
+            for (name, version) in EXTENSIONS {
+                out.string(name.as_bytes()).string(version.as_bytes());
+            }
+            return Some(out.into_inner());
+        }
+        let Ok(id) = args.u32() else {
+            debug!("dropping an SFTP packet of type {kind} without a request id");
+            return None;
+        };
+        let out = match self.request(kind, id, &mut args).await {
+            Ok(Ok(out)) => out,
+            Ok(Err(code)) => status(id, code),
+            Err(e) => {
+                debug!("bad SFTP request of type {kind}: {e}");
+                status(id, Status::BadMessage)
+            }
+        };
+        Some(out.into_inner())
+    }
+
+    /// Release the handles left open
+    pub(super) async fn close_all(&mut self) {
+        for (_, handle) in self.handles.drain() {
+            if let Err(e) = Self::release(self.server, &self.ctx, handle).await {
+                debug!("failed to release an SFTP handle: {e}");
+            }
+        }
+    }
+
+    /// The reply to the request `id` of type `kind`, failing if `args`
+    /// cannot be decoded
+    async fn request(
+        &mut self,
+        kind: u8,
+        id: u32,
+        args: &mut Reader<'_>,
+    ) -> DatenLordResult<Result<Writer, Status>> {
+        Ok(match kind {
+            FXP_OPEN => {
+                let (path, flags) = (args.string()?, args.u32()?);
+                let attrs = Attrs::decode(args)?;
+                self.open(id, path, flags, &attrs).await
+            }
+            FXP_CLOSE => self.close(id, args.string()?).await,
+            FXP_READ => {
+                let (handle, offset, len) = (args.string()?, args.u64()?, args.u32()?);
+                self.read(id, handle, offset, len).await
+            }
+            FXP_WRITE => {
+                let (handle, offset, data) = (args.string()?, args.u64()?, args.string()?);
+                self.write(id, handle, offset, data).await
+            }
+            // Entries are looked up without following links either way
+            FXP_LSTAT | FXP_STAT => self.stat(id, args.string()?).await,
+            FXP_FSTAT => self.fstat(id, args.string()?).await,
+            FXP_SETSTAT => {
+                let path = args.string()?;
+                let attrs = Attrs::decode(args)?;
+                self.setstat(id, path, &attrs).await
+            }
+            FXP_FSETSTAT => {
+                let handle = args.string()?;
+                let attrs = Attrs::decode(args)?;
+                self.fsetstat(id, handle, &attrs).await
+            }
+            FXP_OPENDIR => self.opendir(id, args.string()?).await,
+            FXP_READDIR => self.readdir(id, args.string()?).await,
+            FXP_REMOVE => self.remove(id, args.string()?, false).await,
+            FXP_MKDIR => {
+                let path = args.string()?;
+                let attrs = Attrs::decode(args)?;
+                self.mkdir(id, path, &attrs).await
+            }
+            FXP_RMDIR => self.remove(id, args.string()?, true).await,
+            FXP_REALPATH => Ok(Self::realpath(id, args.string()?)),
+            FXP_RENAME => {
+                let (from, to) = (args.string()?, args.string()?);
+                // Unlike `rename(2)`, existing entries are not replaced
+                let flags = RenameFlags::RENAME_NOREPLACE.bits();
+                self.rename(id, from, to, flags).await
+            }
+            FXP_READLINK => self.readlink(id, args.string()?).await,
+            FXP_SYMLINK => {
+                // OpenSSH sends the target first, against the draft, and
+                // every client follows it
+                let (target, path) = (args.string()?, args.string()?);
+                self.symlink(id, target, path).await
+            }
+            FXP_EXTENDED => match args.string()? {
+                b"posix-rename@openssh.com" => {
+                    let (from, to) = (args.string()?, args.string()?);
+                    self.rename(id, from, to, 0).await
+                }
+                b"statvfs@openssh.com" => self.statvfs(id, args.string()?).await,
+                b"fsync@openssh.com" => self.fsync(id, args.string()?).await,
+                _ => Err(Status::OpUnsupported),
+            },
+            _ => Err(Status::OpUnsupported),
+        })
+    }
+
+    /// Fail with a permission error if the server is read-only
+    fn writable(&self) -> Result<(), Status> {
+        if self.server.read_only {
+            Err(Status::PermissionDenied)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The attributes of the entry at `path`
+    async fn lookup(&self, path: &[u8]) -> Result<FileAttr, Status> {
+        let components = SftpServer::<F>::components(path);
+        self.server
+            .lookup(&self.ctx, &components)
+            .await
+            .map_err(not_found)
+    }
+
+    /// The directory holding the entry at `path` and the name of the entry
+    async fn parent(&self, path: &[u8]) -> Result<(INum, OsString), Status> {
+        let mut components = SftpServer::<F>::components(path);
+        // The root is in no directory that can be changed
+        let name = components.pop().ok_or(Status::PermissionDenied)?;
+        let dir = self
+            .server
+            .lookup(&self.ctx, &components)
+            .await
+            .map_err(not_found)?;
+        if dir.kind != SFlag::S_IFDIR {
+            return Err(Status::NoSuchFile);
+        }
+        Ok((dir.ino, name))
+    }
+
+    /// The directory holding the entry at `path`, the name and the
+    /// attributes of the entry
+    async fn entry(&self, path: &[u8]) -> Result<(INum, OsString, FileAttr), Status> {
+        let (dir, name) = self.parent(path).await?;
+        let (_, attr, _) = self
+            .server
+            .fs
+            .lookup(&self.ctx, dir, &name)
+            .await
+            .map_err(not_found)?;
+        Ok((dir, name, attr))
+    }
+
+    /// The id of the handle the client sent as `handle`
+    fn handle_id(handle: &[u8]) -> Result<u32, Status> {
+        let id = <[u8; 4]>::try_from(handle).map_err(|_| Status::Failure)?;
+        Ok(u32::from_be_bytes(id))
+    }
+
+    /// The open handle the client sent as `handle`
+    fn handle_of(&mut self, handle: &[u8]) -> Result<&mut Handle, Status> {
+        self.handles
+            .get_mut(&Self::handle_id(handle)?)
+            .ok_or(Status::Failure)
+    }
+
+    /// The inode and file handle of the open file `handle`
+    fn file_of(&mut self, handle: &[u8]) -> Result<(INum, u64, bool), Status> {
+        match *self.handle_of(handle)? {
+            Handle::File { ino, fh, append } => Ok((ino, fh, append)),
+            Handle::Dir { .. } => Err(Status::Failure),
+        }
+    }
+
+    /// The `HANDLE` reply to the request `id` for the newly opened `handle`
+    fn opened(&mut self, id: u32, handle: Handle) -> Writer {
+        let handle_id = self.next_handle;
+        self.next_handle = self.next_handle.wrapping_add(1);
+        self.handles.insert(handle_id, handle);
+        let mut out = reply(FXP_HANDLE, id);
+        out.string(&handle_id.to_be_bytes());
+        out
+    }
+
+    /// Release `handle` on the filesystem of `server`
+    async fn release(
+        server: &SftpServer<F>,
+        ctx: &RequestContext,
+        handle: Handle,
+    ) -> DatenLordResult<()> {
+        match handle {
+            Handle::File { ino, fh, .. } => server.fs.release(ctx, ino, fh, 0, 0, true).await,
+            Handle::Dir { ino, fh, .. } => server.fs.releasedir(ctx, ino, fh, 0).await,
+        }
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        path: &[u8],
+        flags: u32,
+        attrs: &Attrs,
+    ) -> Result<Writer, Status> {
+        let writes = flags & (FXF_WRITE | FXF_APPEND | FXF_CREAT | FXF_TRUNC) != 0;
+        if writes {
+            self.writable()?;
+        }
+        let ctx = self.ctx;
+        let (attr, created) = if flags & FXF_CREAT == 0 {
+            (self.lookup(path).await?, false)
+        } else {
+            let (dir, name) = self.parent(path).await?;
+            let param = CreateParam {
+                parent: dir,
+                name: name.clone(),
+                mode: attrs.mode(FILE_MODE),
+                rdev: 0,
+                node_type: SFlag::S_IFREG,
+                link: None,
+            };
+            match self.server.fs.mknod(&ctx, param).await {
+                Ok((_, attr, _)) => (attr, true),
+                Err(DatenLordError::AlreadyExists { .. }) if flags & FXF_EXCL == 0 => {
+                    let (_, attr, _) = self
+                        .server
+                        .fs
+                        .lookup(&ctx, dir, &name)
+                        .await
+                        .map_err(not_found)?;
+                    (attr, false)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        if attr.kind == SFlag::S_IFDIR {
+            return Err(Status::Failure);
+        }
+        if flags & FXF_TRUNC != 0 && !created {
+            let param = SetAttrParam {
+                size: Some(0),
+                ..SetAttrParam::default()
+            };
+            self.server.fs.setattr(&ctx, attr.ino, param).await?;
+        }
+        let mut open_flags = match (flags & FXF_READ != 0, writes) {
+            (true, true) => OFlag::O_RDWR,
+            (false, true) => OFlag::O_WRONLY,
+            _ => OFlag::O_RDONLY,
+        };
+        if flags & FXF_APPEND != 0 {
+            open_flags |= OFlag::O_APPEND;
+        }
+        let fh = self
+            .server
+            .fs
+            .open(&ctx, attr.ino, open_flags.bits() as u32)
+            .await?;
+        let handle = Handle::File {
+            ino: attr.ino,
+            fh,
+            append: flags & FXF_APPEND != 0,
+        };
+        Ok(self.opened(id, handle))
+    }
+
+    async fn close(&mut self, id: u32, handle: &[u8]) -> Result<Writer, Status> {
+        let handle = self
+            .handles
+            .remove(&Self::handle_id(handle)?)
+            .ok_or(Status::Failure)?;
+        Self::release(self.server, &self.ctx, handle).await?;
+        Ok(status(id, Status::Ok))
+    }
+
+    async fn read(
+        &mut self,
+        id: u32,
+        handle: &[u8],
+        offset: u64,
+        len: u32,
+    ) -> Result<Writer, Status> {
+        let (ino, fh, _) = self.file_of(handle)?;
+        let len = len.min(MAX_READ);
+        let mut buf = vec![0; len as usize];
+        let read = self
+            .server
+            .fs
+            .read(&self.ctx, ino, fh, offset, len, &mut buf)
+            .await?;
+        if read == 0 && len > 0 {
+            return Err(Status::Eof);
+        }
+        let mut out = reply(FXP_DATA, id);
+        out.string(&buf[..read]);
+        Ok(out)
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: &[u8],
+        offset: u64,
+        data: &[u8],
+    ) -> Result<Writer, Status> {
+        let (ino, fh, append) = self.file_of(handle)?;
+        let offset = if append {
+            self.server.fs.getattr(&self.ctx, ino).await?.1.size
+        } else {
+            offset
+        };
+        let offset = i64::try_from(offset).map_err(|_| Status::Failure)?;
+        self.server
+            .fs
+            .write(&self.ctx, ino, fh, offset, data, 0)
+            .await?;
+        Ok(status(id, Status::Ok))
+    }
+
+    /// The `ATTRS` reply to the request `id` for the entry with `attr`
+    fn attrs(id: u32, attr: &FileAttr) -> Writer {
+        let mut out = reply(FXP_ATTRS, id);
+        encode_attrs(&mut out, attr);
+        out
+    }
+
+    async fn stat(&mut self, id: u32, path: &[u8]) -> Result<Writer, Status> {
+        Ok(Self::attrs(id, &self.lookup(path).await?))
+    }
+
+    async fn fstat(&mut self, id: u32, handle: &[u8]) -> Result<Writer, Status> {
+        let ino = match *self.handle_of(handle)? {
+            Handle::File { ino, .. } | Handle::Dir { ino, .. } => ino,
+        };
+        let (_, attr) = self.server.fs.getattr(&self.ctx, ino).await?;
+        Ok(Self::attrs(id, &attr))
+    }
+
+    async fn setstat(&mut self, id: u32, path: &[u8], attrs: &Attrs) -> Result<Writer, Status> {
+        self.writable()?;
+        let attr = self.lookup(path).await?;
+        self.server
+            .fs
+            .setattr(&self.ctx, attr.ino, attrs.param())
+            .await?;
+        Ok(status(id, Status::Ok))
+    }
+
+    async fn fsetstat(&mut self, id: u32, handle: &[u8], attrs: &Attrs) -> Result<Writer, Status> {
+        self.writable()?;
+        let ino = match *self.handle_of(handle)? {
+            Handle::File { ino, .. } | Handle::Dir { ino, .. } => ino,
+        };
+        self.server
+            .fs
+            .setattr(&self.ctx, ino, attrs.param())
+            .await?;
+        Ok(status(id, Status::Ok))
+    }
+
+    async fn opendir(&mut self, id: u32, path: &[u8]) -> Result<Writer, Status> {
+        let attr = self.lookup(path).await?;
+        if attr.kind != SFlag::S_IFDIR {
+            return Err(Status::NoSuchFile);
+        }
+        let fh = self.server.fs.opendir(&self.ctx, attr.ino, 0).await?;
+        let handle = Handle::Dir {
+            ino: attr.ino,
+            fh,
+            entries: None,
+        };
+        Ok(self.opened(id, handle))
+    }
+
+    async fn readdir(&mut self, id: u32, handle: &[u8]) -> Result<Writer, Status> {
+        let (server, ctx) = (self.server, self.ctx);
+        let Handle::Dir {
+            ino,
+            fh,
+            ref mut entries,
+        } = *self.handle_of(handle)?
+        else {
+            return Err(Status::Failure);
+        };
+        if entries.is_none() {
+            let listed = server.fs.readdirplus(&ctx, ino, fh, 0).await?;
+            *entries = Some(
+                listed
+                    .into_iter()
+                    .map(|(entry, attr, _)| (entry.name, attr))
+                    .collect(),
+            );
+        }
+        let entries = entries.get_or_insert_with(VecDeque::new);
+        if entries.is_empty() {
+            return Err(Status::Eof);
+        }
+        let count = entries.len().min(MAX_NAMES);
+        let mut out = reply(FXP_NAME, id);
+        out.u32(count as u32);
+        for (name, attr) in entries.drain(..count) {
+            out.string(name.as_bytes())
+                .string(long_name(&name, &attr).as_bytes());
+            encode_attrs(&mut out, &attr);
+        }
+        Ok(out)
+    }
+
+    /// `REMOVE`, or `RMDIR` if `dir`
+    async fn remove(&mut self, id: u32, path: &[u8], dir: bool) -> Result<Writer, Status> {
+        self.writable()?;
+        let (parent, name, attr) = self.entry(path).await?;
+        if (attr.kind == SFlag::S_IFDIR) != dir {
+            return Err(Status::Failure);
+        }
+        if dir {
+            self.server.fs.rmdir(&self.ctx, parent, &name).await?;
+        } else {
+            self.server.fs.unlink(&self.ctx, parent, &name).await?;
+        }
+        Ok(status(id, Status::Ok))
+    }
+
+    async fn mkdir(&mut self, id: u32, path: &[u8], attrs: &Attrs) -> Result<Writer, Status> {
+        self.writable()?;
+        let (parent, name) = self.parent(path).await?;
+        let param = CreateParam {
+            parent,
+            name,
+            mode: attrs.mode(DIR_MODE),
+            rdev: 0,
+            node_type: SFlag::S_IFDIR,
+            link: None,
+        };
+        self.server.fs.mkdir(&self.ctx, param).await?;
+        Ok(status(id, Status::Ok))
+    }
+
+    /// The `NAME` reply holding the absolute form of `path`, whether or not
+    /// an entry is there
+    fn realpath(id: u32, path: &[u8]) -> Writer {
+        let mut absolute = Vec::with_capacity(path.len() + 1);
+        for name in SftpServer::<F>::components(path) {
+            absolute.push(b'/');
+            absolute.extend_from_slice(name.as_bytes());
+        }
+        if absolute.is_empty() {
+            absolute.push(b'/');
+        }
+        let mut out = reply(FXP_NAME, id);
+        // No attributes are known
+        out.u32(1).string(&absolute).string(&absolute).u32(0);
+        out
+    }
+
+    /// Move the entry at `from` to `to` with the `renameat2` `flags`
+    async fn rename(
+        &mut self,
+        id: u32,
+        from: &[u8],
+        to: &[u8],
+        flags: u32,
+    ) -> Result<Writer, Status> {
+        self.writable()?;
+        let (old_parent, old_name, _) = self.entry(from).await?;
+        let (new_parent, new_name) = self.parent(to).await?;
+        let param = RenameParam {
+            old_parent,
+            old_name,
+            new_parent,
+            new_name,
+            flags,
+        };
+        self.server.fs.rename(&self.ctx, param).await?;
+        Ok(status(id, Status::Ok))
+    }
+
+    async fn readlink(&mut self, id: u32, path: &[u8]) -> Result<Writer, Status> {
+        let attr = self.lookup(path).await?;
+        let target = self.server.fs.readlink(&self.ctx, attr.ino).await?;
+        let mut out = reply(FXP_NAME, id);
+        out.u32(1).string(&target).string(&target).u32(0);
+        Ok(out)
+    }
+
+    async fn symlink(&mut self, id: u32, target: &[u8], path: &[u8]) -> Result<Writer, Status> {
+        self.writable()?;
+        let (parent, name) = self.parent(path).await?;
+        let target = Path::new(OsStr::from_bytes(target));
+        self.server
+            .fs
+            .symlink(&self.ctx, parent, &name, target)
+            .await?;
+        Ok(status(id, Status::Ok))
+    }
+
+    async fn statvfs(&mut self, id: u32, path: &[u8]) -> Result<Writer, Status> {
+        let attr = self.lookup(path).await?;
+        let stat = self.server.fs.statfs(&self.ctx, attr.ino).await?;
+        let fragment = if stat.frsize == 0 {
+            stat.bsize
+        } else {
+            stat.frsize
+        };
+        let flags = if self.server.read_only { ST_RDONLY } else { 0 };
+        let mut out = reply(FXP_EXTENDED_REPLY, id);
+        out.u64(stat.bsize.into())
+            .u64(fragment.into())
+            .u64(stat.blocks)
+            .u64(stat.bfree)
+            .u64(stat.bavail)
+            .u64(stat.files)
+            .u64(stat.f_free)
+            .u64(stat.f_free)
+            // No filesystem id is kept
+            .u64(0)
+            .u64(flags)
+            .u64(stat.namelen.into());
+        Ok(out)
+    }
+
+    async fn fsync(&mut self, id: u32, handle: &[u8]) -> Result<Writer, Status> {
+        let (ino, fh, _) = self.file_of(handle)?;
+        self.server.fs.fsync(&self.ctx, ino, fh, false).await?;
+        Ok(status(id, Status::Ok))
+    }
+}
@@ -0,0 +1,132 @@
+//! The SSH encoding of RFC 4251 the SFTP packets are made of
+//!
+//! Integers are big-endian and strings are preceded by their length, with
+//! no padding.
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+/// The longest packet read, in bytes, above the 256 KiB clients send at most
+const MAX_PACKET_LEN: usize = 1 << 20;
+
+/// Appends items to a packet
+#[derive(Debug, Default)]
+pub struct Writer {
+    /// The encoded items
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    /// A packet of type `kind`
+    pub fn new(kind: u8) -> Self {
+        Self { buf: vec![kind] }
+    }
+
+    /// The encoded packet, without its length
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.buf.push(value);
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.buf.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    /// A string or binary data, preceded by its length
+    pub fn string(&mut self, data: &[u8]) -> &mut Self {
+        self.u32(u32::try_from(data.len()).unwrap_or(u32::MAX));
+        self.buf.extend_from_slice(data);
+        self
+    }
+}
+
+/// Reads items from a packet
+#[derive(Debug)]
+pub struct Reader<'a> {
+    /// The items not read yet
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// The bytes not read yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    /// The next `len` bytes
+    fn take(&mut self, len: usize) -> DatenLordResult<&'a [u8]> {
+        if len > self.buf.len() {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "truncated SFTP packet, {len} bytes needed but {} left",
+                    self.buf.len()
+                )],
+            });
+        }
+        let (data, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(data)
+    }
+
+    pub fn u8(&mut self) -> DatenLordResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> DatenLordResult<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> DatenLordResult<u64> {
+        Ok(u64::from(self.u32()?) << 32 | u64::from(self.u32()?))
+    }
+
+    /// A string or binary data preceded by its length
+    pub fn string(&mut self) -> DatenLordResult<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+/// Read the next packet of `reader`, `None` when the stream ends before it
+pub async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if len == 0 || len > MAX_PACKET_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("SFTP packet of {len} bytes, 1 to {MAX_PACKET_LEN} are allowed"),
+        ));
+    }
+    let mut packet = vec![0; len];
+    reader.read_exact(&mut packet).await?;
+    Ok(Some(packet))
+}
+
+/// Write `packet` to `writer` preceded by its length
+pub async fn write_packet<W: AsyncWrite + Unpin>(writer: &mut W, packet: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(packet.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "SFTP packet too long"))?;
+    writer.write_u32(len).await?;
+    writer.write_all(packet).await?;
+    writer.flush().await
+}
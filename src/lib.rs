@@ -1,5 +1,14 @@
 //! datenlord c buffer example
 
+pub mod bench;
+pub mod cachesim;
+pub mod diff;
+pub mod fsck;
+pub mod gateway;
+pub mod lifecycle;
+pub mod migrate;
+pub mod replay;
 pub mod sdk;
 pub mod storage;
-pub mod common;
\ No newline at end of file
+pub mod common;
+mod ffi;
@@ -0,0 +1,324 @@
+//! Lifecycle rules deleting or archiving files by age and tags
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::fs_util::{self, RequestContext, ROOT_ID};
+use crate::storage::tags::{self, TagFilter};
+use crate::storage::virtualfs::VirtualFs;
+use crate::storage::walk::{self, DEFAULT_WALK_CONCURRENCY};
+
+/// The tag recording the storage class a file was archived to
+pub const STORAGE_CLASS_TAG: &str = "storage_class";
+/// Default time between two scheduled evaluations, an hour
+const DEFAULT_INTERVAL_SECS: u64 = 3600;
+
+/// The lifecycle rules of a namespace and how they are evaluated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LifecycleConfig {
+    /// The rules, applied in order, none disables the scheduled task
+    pub rules: Vec<LifecycleRule>,
+    /// The time between two scheduled evaluations in seconds
+    pub interval_secs: u64,
+    /// Only report what the scheduled evaluations would do
+    pub dry_run: bool,
+    /// File every action is appended to as a JSON line
+    pub audit_log: Option<PathBuf>,
+}
+
+impl Default for LifecycleConfig {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            interval_secs: DEFAULT_INTERVAL_SECS,
+            dry_run: false,
+            audit_log: None,
+        }
+    }
+}
+
+/// A rule acting on the files matching all of its conditions
+///
+/// Directories are never acted on, only the files below them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LifecycleRule {
+    /// The name of the rule in reports and the audit log
+    pub name: String,
+    /// Glob of the paths the rule applies to, relative to the root, e.g.
+    /// `tmp/**`; see `walk::glob`
+    #[serde(default = "LifecycleRule::every_path")]
+    pub path: String,
+    /// Tag filter the files must match, e.g. `cold` or `team=data,cold`;
+    /// see `TagFilter`
+    #[serde(default)]
+    pub tags: Option<String>,
+    /// The time since the last modification the files must have reached,
+    /// in seconds
+    #[serde(default)]
+    pub min_age_secs: Option<u64>,
+    /// What happens to the files
+    pub action: LifecycleAction,
+}
+
+impl LifecycleRule {
+    /// The pattern matching every path
+    fn every_path() -> String {
+        "**".to_owned()
+    }
+}
+
+/// What a rule does to the files it selects
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LifecycleAction {
+    /// Remove the file
+    Delete,
+    /// Demote the file to the storage class `class`
+    ///
+    /// The local backend has a single tier, so the class is recorded as the
+    /// `STORAGE_CLASS_TAG` tag for the tiers to come. Files already in the
+    /// class are left alone.
+    Archive {
+        /// The storage class, e.g. `archive`
+        class: String,
+    },
+}
+
+/// An action a rule took, or would have taken in a dry run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LifecycleRecord {
+    /// When the action was decided, in nanoseconds since the epoch
+    pub timestamp_ns: i128,
+    /// The name of the rule
+    pub rule: String,
+    /// The path of the file relative to the root
+    pub path: String,
+    /// The action
+    pub action: LifecycleAction,
+    /// Whether the action was only reported
+    pub dry_run: bool,
+    /// Why the action failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The result of evaluating the rules once
+#[derive(Debug, Clone, Default)]
+pub struct LifecycleReport {
+    /// The number of files the path patterns matched
+    pub matched: u64,
+    /// The actions, in the order they were taken
+    pub records: Vec<LifecycleRecord>,
+}
+
+/// The rule parsed for evaluation
+struct CompiledRule<'a> {
+    /// The rule
+    rule: &'a LifecycleRule,
+    /// Its tag filter
+    tags: Option<TagFilter>,
+    /// Its minimum age
+    min_age: Option<Duration>,
+}
+
+impl<'a> CompiledRule<'a> {
+    /// Parse the conditions of `rule`
+    fn new(rule: &'a LifecycleRule) -> DatenLordResult<Self> {
+        let tags = match rule.tags {
+            Some(ref filter) => Some(filter.parse().map_err(|e: DatenLordError| {
+                DatenLordError::InvalidArgument {
+                    context: vec![format!("invalid tags of lifecycle rule {}: {e}", rule.name)],
+                }
+            })?),
+            None => None,
+        };
+        Ok(Self {
+            rule,
+            tags,
+            min_age: rule.min_age_secs.map(Duration::from_secs),
+        })
+    }
+}
+
+/// Evaluate `rules` once over the files of `fs` on behalf of `ctx`
+///
+/// Rules are applied in order, so a file deleted by a rule is not seen by
+/// the next ones. With `dry_run` nothing changes and the report tells what
+/// would have. Every record is appended to `audit_log` when given; a failed
+/// action is recorded with its error and does not stop the evaluation.
+pub async fn evaluate<F: VirtualFs + 'static>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    rules: &[LifecycleRule],
+    dry_run: bool,
+    audit_log: Option<&PathBuf>,
+) -> DatenLordResult<LifecycleReport> {
+    let compiled = rules
+        .iter()
+        .map(CompiledRule::new)
+        .collect::<DatenLordResult<Vec<_>>>()?;
+    let mut report = LifecycleReport::default();
+    for rule in compiled {
+        let mut matches = walk::glob(
+            Arc::clone(&fs),
+            ctx,
+            &Handle::current(),
+            &rule.rule.path,
+            DEFAULT_WALK_CONCURRENCY,
+        );
+        let now = SystemTime::now();
+        while let Some(entry) = matches.next().await {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("lifecycle rule {} skips a directory: {e}", rule.rule.name);
+                    continue;
+                }
+            };
+            if entry.attr.kind == SFlag::S_IFDIR {
+                continue;
+            }
+            report.matched += 1;
+            if let Some(min_age) = rule.min_age {
+                let age = now.duration_since(entry.attr.mtime).unwrap_or_default();
+                if age < min_age {
+                    continue;
+                }
+            }
+            let archive_class = match rule.rule.action {
+                LifecycleAction::Archive { ref class } => Some(class),
+                LifecycleAction::Delete => None,
+            };
+            if rule.tags.is_some() || archive_class.is_some() {
+                let file_tags = match tags::get_tags(&*fs, &ctx, entry.attr.ino).await {
+                    Ok(file_tags) => file_tags,
+                    Err(e) => {
+                        warn!("lifecycle rule {} skips {:?}: {e}", rule.rule.name, entry.path);
+                        continue;
+                    }
+                };
+                if rule.tags.as_ref().is_some_and(|filter| !filter.matches(&file_tags)) {
+                    continue;
+                }
+                if archive_class.is_some() && file_tags.get(STORAGE_CLASS_TAG) == archive_class {
+                    continue;
+                }
+            }
+
+            let error = if dry_run {
+                None
+            } else {
+                let result = match rule.rule.action {
+                    LifecycleAction::Delete => fs.unlink(&ctx, ROOT_ID, &entry.path).await,
+                    LifecycleAction::Archive { ref class } => {
+                        tags::set_tag(&*fs, &ctx, entry.attr.ino, STORAGE_CLASS_TAG, class).await
+                    }
+                };
+                result.err().map(|e| e.to_string())
+            };
+            let (sec, nsec) = fs_util::to_timespec(SystemTime::now());
+            let record = LifecycleRecord {
+                timestamp_ns: i128::from(sec) * 1_000_000_000 + i128::from(nsec),
+                rule: rule.rule.name.clone(),
+                path: entry.path.to_string_lossy().into_owned(),
+                action: rule.rule.action.clone(),
+                dry_run,
+                error,
+            };
+            if let Some(path) = audit_log {
+                append_audit(path, &record)?;
+            }
+            report.records.push(record);
+        }
+    }
+    Ok(report)
+}
+
+/// Append `record` to the audit log at `path` as a JSON line
+fn append_audit(path: &PathBuf, record: &LifecycleRecord) -> DatenLordResult<()> {
+    let io_error = |e: std::io::Error| DatenLordError::Io {
+        context: vec![format!("failed to append to lifecycle audit log {path:?}: {e}")],
+        source: None,
+    };
+    let line = serde_json::to_string(record).map_err(|e| DatenLordError::Internal {
+        context: vec![format!("failed to serialize lifecycle record {record:?}: {e}")],
+    })?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(io_error)?;
+    writeln!(file, "{line}").map_err(io_error)
+}
+
+/// The scheduled evaluation of the lifecycle rules of a namespace, stopped
+/// when dropped
+///
+/// The rules are evaluated every `interval_secs` on a thread of its own,
+/// the first time one interval after the start.
+#[derive(Debug)]
+pub struct LifecycleTask {
+    /// Dropped to stop the task
+    _stop: oneshot::Sender<()>,
+}
+
+impl LifecycleTask {
+    /// Start evaluating the rules of `config` over `fs` on behalf of `ctx`,
+    /// `None` when there are no rules
+    pub fn start<F: VirtualFs + 'static>(
+        fs: Arc<F>,
+        ctx: RequestContext,
+        config: LifecycleConfig,
+    ) -> DatenLordResult<Option<Self>> {
+        if config.rules.is_empty() {
+            return Ok(None);
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the lifecycle runtime: {e}")],
+            })?;
+        let (stop, mut stopped) = oneshot::channel();
+        let interval = Duration::from_secs(config.interval_secs.max(1));
+        std::thread::Builder::new()
+            .name("datenlord-lifecycle".to_owned())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    loop {
+                        let run = async {
+                            tokio::time::sleep(interval).await;
+                            let audit_log = config.audit_log.as_ref();
+                            evaluate(Arc::clone(&fs), ctx, &config.rules, config.dry_run, audit_log)
+                                .await
+                        };
+                        tokio::select! {
+                            _ = &mut stopped => return,
+                            report = run => match report {
+                                Ok(report) => info!(
+                                    "lifecycle rules matched {} files and took {} actions",
+                                    report.matched,
+                                    report.records.len()
+                                ),
+                                Err(e) => warn!("lifecycle evaluation failed: {e}"),
+                            },
+                        }
+                    }
+                });
+            })
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the lifecycle thread: {e}")],
+            })?;
+        Ok(Some(Self { _stop: stop }))
+    }
+}
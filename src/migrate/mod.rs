@@ -0,0 +1,423 @@
+//! Copy a whole namespace from one backend to another
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+use crate::common::buffer_pool::{BufferPool, COPY_CHUNK_SIZE};
+use crate::common::config::DatenLordConfig;
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::fs_util::{CreateParam, FileAttr, RequestContext, ROOT_ID};
+use crate::storage::localfs::LocalFS;
+use crate::storage::tags;
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+
+/// The root of the backend addressed by `uri`
+///
+/// Only the local filesystem exists so far, addressed as `file:///root` or
+/// as a plain path.
+pub fn backend_root(uri: &str) -> DatenLordResult<PathBuf> {
+    match uri.split_once("://") {
+        Some(("file", path)) => Ok(PathBuf::from(path)),
+        Some((scheme, _)) => Err(DatenLordError::Unimplemented {
+            context: vec![format!("backend scheme {scheme} of uri={uri} unimplemented")],
+            source: None,
+        }),
+        None => Ok(PathBuf::from(uri)),
+    }
+}
+
+/// Open the backend addressed by `uri`, see `backend_root`
+pub fn open_backend(uri: &str) -> DatenLordResult<LocalFS> {
+    LocalFS::new(&DatenLordConfig {
+        root: backend_root(uri)?,
+        ..DatenLordConfig::default()
+    })
+}
+
+/// Parameters of a migration
+#[derive(Debug, Clone)]
+pub struct MigrateOptions {
+    /// The number of files copied concurrently
+    pub parallelism: usize,
+    /// Limit of the copy bandwidth in bytes per second, unlimited when `None`
+    pub bytes_per_sec: Option<u64>,
+    /// File recording the copied files, a rerun skips the files listed in it
+    pub checkpoint: Option<PathBuf>,
+    /// Whether to compare every file of both backends after copying
+    pub verify: bool,
+}
+
+/// The result of a migration
+#[derive(Debug, Clone, Default)]
+pub struct MigrateReport {
+    /// The number of directories created or already present
+    pub dirs: u64,
+    /// The number of files copied
+    pub files: u64,
+    /// The number of files skipped as already copied by an earlier run
+    pub resumed: u64,
+    /// The number of bytes copied
+    pub bytes: u64,
+    /// The number of files compared by the verification pass
+    pub verified: u64,
+    /// The wall-clock time of the migration
+    pub elapsed: Duration,
+}
+
+/// A regular file to copy
+#[derive(Debug, Clone)]
+struct FileJob {
+    /// The file in the source backend
+    src_ino: INum,
+    /// The parent directory in the destination backend
+    dst_parent: INum,
+    /// The file name
+    name: OsString,
+    /// The path relative to the namespace root, used by the checkpoint, with
+    /// the bytes of names that are not UTF-8 replaced
+    rel_path: String,
+}
+
+/// Bandwidth limiter handing out byte budgets at a fixed rate
+#[derive(Debug)]
+struct Throttle {
+    /// The allowed bytes per second
+    bytes_per_sec: u64,
+    /// When the budget handed out so far is used up
+    next_free: Mutex<Instant>,
+}
+
+impl Throttle {
+    /// Wait until `bytes` more bytes may be transferred
+    async fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut next_free = self.next_free.lock().unwrap();
+            let now = Instant::now();
+            let start = (*next_free).max(now);
+            *next_free = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+            start - now
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// The list of files already copied, persisted line by line
+#[derive(Debug)]
+struct Checkpoint {
+    /// The files copied by earlier runs
+    done: HashSet<String>,
+    /// The checkpoint file opened for appending
+    file: Option<Mutex<File>>,
+}
+
+impl Checkpoint {
+    /// Load the checkpoint at `path`, an absent path disables checkpointing
+    fn open(path: Option<&Path>) -> DatenLordResult<Self> {
+        let Some(path) = path else {
+            return Ok(Self {
+                done: HashSet::new(),
+                file: None,
+            });
+        };
+        let io_error = |e: std::io::Error| DatenLordError::Io {
+            context: vec![format!("failed to open checkpoint {path:?}: {e}")],
+            source: None,
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(io_error)?;
+        let done = BufReader::new(&file)
+            .lines()
+            .collect::<Result<_, _>>()
+            .map_err(io_error)?;
+        Ok(Self {
+            done,
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    /// Record `rel_path` as copied
+    fn record(&self, rel_path: &str) -> DatenLordResult<()> {
+        if let Some(ref file) = self.file {
+            writeln!(file.lock().unwrap(), "{rel_path}").map_err(|e| DatenLordError::Io {
+                context: vec![format!("failed to update checkpoint: {e}")],
+                source: None,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Look up `name` under `parent`, `None` if it does not exist
+async fn lookup<F: VirtualFs>(
+    fs: &F,
+    ctx: &RequestContext,
+    parent: INum,
+    name: &OsStr,
+) -> Option<FileAttr> {
+    fs.lookup(ctx, parent, name)
+        .await
+        .ok()
+        .map(|(_, attr, _)| attr)
+}
+
+/// List every entry of directory `ino` with its attributes
+async fn list_dir<F: VirtualFs>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+) -> DatenLordResult<Vec<(OsString, FileAttr)>> {
+    let mut children = Vec::new();
+    loop {
+        let offset = i64::try_from(children.len()).unwrap_or(i64::MAX);
+        let entries = fs.readdirplus(ctx, ino, 0, offset).await?;
+        if entries.is_empty() {
+            return Ok(children);
+        }
+        for (entry, attr, _) in entries {
+            children.push((entry.name, attr));
+        }
+    }
+}
+
+/// Build the parameters to create `name` under `parent` like `attr`
+fn create_param(parent: INum, name: &OsStr, attr: &FileAttr) -> CreateParam {
+    CreateParam {
+        parent,
+        name: name.to_owned(),
+        mode: u32::from(attr.perm),
+        rdev: 0,
+        node_type: attr.kind,
+        link: None,
+    }
+}
+
+/// Recreate the directory tree of `src` in `dst` and collect the files to copy
+async fn copy_tree<S: VirtualFs, D: VirtualFs>(
+    src: &S,
+    dst: &D,
+    ctx: &RequestContext,
+    report: &mut MigrateReport,
+) -> DatenLordResult<Vec<FileJob>> {
+    let mut jobs = Vec::new();
+    let mut dirs = vec![(ROOT_ID, ROOT_ID, String::new())];
+    while let Some((src_dir, dst_dir, rel_dir)) = dirs.pop() {
+        for (name, attr) in list_dir(src, ctx, src_dir).await? {
+            let rel_path = format!("{rel_dir}/{}", name.to_string_lossy());
+            if attr.kind == SFlag::S_IFDIR {
+                let dst_ino = match lookup(dst, ctx, dst_dir, &name).await {
+                    Some(existing) => existing.ino,
+                    None => dst.mkdir(ctx, create_param(dst_dir, &name, &attr)).await?.1.ino,
+                };
+                tags::copy_tags(src, dst, ctx, attr.ino, dst_ino).await?;
+                report.dirs += 1;
+                dirs.push((attr.ino, dst_ino, rel_path));
+            } else if attr.kind == SFlag::S_IFREG {
+                jobs.push(FileJob {
+                    src_ino: attr.ino,
+                    dst_parent: dst_dir,
+                    name,
+                    rel_path,
+                });
+            } else {
+                warn!("skip {rel_path} of unsupported type {:?}", attr.kind);
+            }
+        }
+    }
+    Ok(jobs)
+}
+
+/// Copy one file with its tags, replacing any partial copy left by an
+/// interrupted run
+async fn copy_file<S: VirtualFs, D: VirtualFs>(
+    src: &S,
+    dst: &D,
+    ctx: &RequestContext,
+    job: &FileJob,
+    pool: &BufferPool,
+    throttle: Option<&Throttle>,
+) -> DatenLordResult<u64> {
+    let (_, attr) = src.getattr(ctx, job.src_ino).await?;
+    if lookup(dst, ctx, job.dst_parent, &job.name).await.is_some() {
+        dst.unlink(ctx, job.dst_parent, &job.name)
+            .await?;
+    }
+    let dst_ino = dst
+        .mknod(ctx, create_param(job.dst_parent, &job.name, &attr))
+        .await?
+        .1
+        .ino;
+
+    let src_fh = src
+        .open(ctx, job.src_ino, OFlag::O_RDONLY.bits() as u32)
+        .await?;
+    let dst_fh = dst
+        .open(ctx, dst_ino, OFlag::O_WRONLY.bits() as u32)
+        .await?;
+    let mut buf = pool.acquire(COPY_CHUNK_SIZE);
+    let mut offset = 0_u64;
+    let result = loop {
+        let size = match src
+            .read(ctx, job.src_ino, src_fh, offset, buf.len() as u32, &mut buf)
+            .await
+        {
+            Ok(0) => break Ok(offset),
+            Ok(size) => size,
+            Err(e) => break Err(e),
+        };
+        if let Some(throttle) = throttle {
+            throttle.acquire(size).await;
+        }
+        if let Err(e) = dst.write(ctx, dst_ino, dst_fh, offset as i64, &buf[..size], 0).await {
+            break Err(e);
+        }
+        offset += size as u64;
+    };
+    let result = match result {
+        Ok(size) => dst.fsync(ctx, dst_ino, dst_fh, false).await.map(|()| size),
+        Err(e) => Err(e),
+    };
+    src.release(ctx, job.src_ino, src_fh, 0, 0, false).await?;
+    dst.release(ctx, dst_ino, dst_fh, 0, 0, true).await?;
+    let size = result?;
+    tags::copy_tags(src, dst, ctx, job.src_ino, dst_ino).await?;
+    Ok(size)
+}
+
+/// Check the copy of one file has the same size and contents as the source
+async fn verify_file<S: VirtualFs, D: VirtualFs>(
+    src: &S,
+    dst: &D,
+    ctx: &RequestContext,
+    job: &FileJob,
+    pool: &BufferPool,
+) -> DatenLordResult<()> {
+    let mismatch = |what: &str| DatenLordError::Internal {
+        context: vec![format!("verification of {} failed: {what} differ", job.rel_path)],
+    };
+    let (_, src_attr) = src.getattr(ctx, job.src_ino).await?;
+    let dst_attr = lookup(dst, ctx, job.dst_parent, &job.name)
+        .await
+        .ok_or_else(|| mismatch("presence"))?;
+    if src_attr.size != dst_attr.size {
+        return Err(mismatch("sizes"));
+    }
+
+    let src_fh = src
+        .open(ctx, src_attr.ino, OFlag::O_RDONLY.bits() as u32)
+        .await?;
+    let dst_fh = dst
+        .open(ctx, dst_attr.ino, OFlag::O_RDONLY.bits() as u32)
+        .await?;
+    let mut src_buf = pool.acquire(COPY_CHUNK_SIZE);
+    let mut dst_buf = pool.acquire(COPY_CHUNK_SIZE);
+    let mut offset = 0_u64;
+    let result = loop {
+        let src_read = src
+            .read(ctx, src_attr.ino, src_fh, offset, src_buf.len() as u32, &mut src_buf)
+            .await;
+        let dst_read = dst
+            .read(ctx, dst_attr.ino, dst_fh, offset, dst_buf.len() as u32, &mut dst_buf)
+            .await;
+        match (src_read, dst_read) {
+            (Ok(0), Ok(0)) => break Ok(()),
+            (Ok(src_size), Ok(dst_size)) => {
+                if src_buf[..src_size] != dst_buf[..dst_size] {
+                    break Err(mismatch("contents"));
+                }
+                offset += src_size as u64;
+            }
+            (Err(e), _) | (_, Err(e)) => break Err(e),
+        }
+    };
+    src.release(ctx, src_attr.ino, src_fh, 0, 0, false).await?;
+    dst.release(ctx, dst_attr.ino, dst_fh, 0, 0, false).await?;
+    result
+}
+
+/// Copy the whole namespace of `src` into `dst`
+///
+/// Directories are recreated first, then files are copied by
+/// `options.parallelism` concurrent workers. With a checkpoint, files
+/// recorded by an earlier run are skipped, so an interrupted migration can
+/// be resumed by running it again. The verification pass compares every
+/// file, including the resumed ones. Both backends are accessed as the
+/// calling process.
+pub async fn migrate<S, D>(
+    src: Arc<S>,
+    dst: Arc<D>,
+    options: &MigrateOptions,
+) -> DatenLordResult<MigrateReport>
+where
+    S: VirtualFs + 'static,
+    D: VirtualFs + 'static,
+{
+    let start = Instant::now();
+    let ctx = RequestContext::current();
+    let mut report = MigrateReport::default();
+    let checkpoint = Arc::new(Checkpoint::open(options.checkpoint.as_deref())?);
+    let jobs = copy_tree(&*src, &*dst, &ctx, &mut report).await?;
+    info!("migrating {} files in {} directories", jobs.len(), report.dirs);
+
+    let pool = BufferPool::new();
+    let throttle = options.bytes_per_sec.map(|bytes_per_sec| {
+        Arc::new(Throttle {
+            bytes_per_sec: bytes_per_sec.max(1),
+            next_free: Mutex::new(Instant::now()),
+        })
+    });
+    let permits = Arc::new(Semaphore::new(options.parallelism.max(1)));
+    let mut workers = JoinSet::new();
+    for job in jobs.iter().cloned() {
+        if checkpoint.done.contains(&job.rel_path) {
+            report.resumed += 1;
+            continue;
+        }
+        let (src, dst, pool, throttle, checkpoint, permits) = (
+            Arc::clone(&src),
+            Arc::clone(&dst),
+            pool.clone(),
+            throttle.clone(),
+            Arc::clone(&checkpoint),
+            Arc::clone(&permits),
+        );
+        workers.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let bytes = copy_file(&*src, &*dst, &ctx, &job, &pool, throttle.as_deref()).await?;
+            checkpoint.record(&job.rel_path)?;
+            Ok::<_, DatenLordError>(bytes)
+        });
+    }
+    while let Some(result) = workers.join_next().await {
+        let bytes = result.map_err(|e| DatenLordError::Internal {
+            context: vec![format!("migration worker panicked: {e}")],
+        })??;
+        report.files += 1;
+        report.bytes += bytes;
+    }
+
+    if options.verify {
+        for job in &jobs {
+            verify_file(&*src, &*dst, &ctx, job, &pool).await?;
+            report.verified += 1;
+        }
+    }
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
@@ -0,0 +1,376 @@
+//! Replays the operations of an audit log against any `VirtualFs`
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::audit::AuditRecord;
+use crate::storage::fs_util::{CreateParam, RenameParam, RequestContext, SetAttrParam, ROOT_ID};
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+/// How a captured operation sequence is replayed
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayOptions {
+    /// How many times faster than captured the operations are issued, `0.0`
+    /// issuing each as soon as the previous one returned
+    pub speed: f64,
+    /// Run every operation on behalf of its recorded uid, gid and pid rather
+    /// than of the replaying context
+    pub as_recorded: bool,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            as_recorded: false,
+        }
+    }
+}
+
+/// An operation whose outcome differed from the captured one
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    /// The position of the record in the sequence, from 0
+    pub index: usize,
+    /// The operation, e.g. `unlink`
+    pub op: String,
+    /// Whether the captured operation succeeded
+    pub recorded_ok: bool,
+    /// The failure of the replayed operation, if it failed
+    pub error: Option<String>,
+}
+
+/// The result of a replay
+#[derive(Debug, Clone)]
+pub struct ReplayReport {
+    /// The number of operations issued
+    pub replayed: u64,
+    /// The number of records that could not be replayed, on inodes created
+    /// before the capture or of operations the log lacks the input of
+    pub skipped: u64,
+    /// The operations whose outcome differed from the captured one
+    pub mismatches: Vec<Mismatch>,
+    /// The wall-clock time of the replay
+    pub elapsed: Duration,
+    /// The latency of every issued operation, sorted ascending
+    latencies: Vec<Duration>,
+}
+
+impl ReplayReport {
+    /// Issued operations per second
+    pub fn ops_per_sec(&self) -> f64 {
+        self.replayed as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// The latency at percentile `p`, in the range `0.0..=100.0`
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * (self.latencies.len() - 1) as f64).round();
+        self.latencies[rank as usize]
+    }
+}
+
+/// Read the records of the audit log at `path`, in the order written
+pub fn read_log(path: &Path) -> DatenLordResult<Vec<AuditRecord>> {
+    let invalid = |message: String| DatenLordError::InvalidArgument {
+        context: vec![message],
+    };
+    let file = std::fs::File::open(path)
+        .map_err(|e| invalid(format!("failed to open the audit log {path:?}: {e}")))?;
+    let mut records = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line =
+            line.map_err(|e| invalid(format!("failed to read the audit log {path:?}: {e}")))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|e| {
+            invalid(format!(
+                "invalid record on line {} of {path:?}: {e}",
+                number + 1
+            ))
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// The state of a replay, mapping the captured inodes to the replayed ones
+struct Replayer<'a, F> {
+    /// The filesystem replayed against
+    fs: &'a F,
+    /// The replayed inode of each captured one
+    inodes: HashMap<INum, INum>,
+    /// The captured inode of each entry known by its captured parent and
+    /// name, to forget the inodes of removed entries
+    entries: HashMap<(INum, String), INum>,
+    /// The handle writes to each replayed inode go through
+    handles: HashMap<INum, u64>,
+}
+
+impl<F: VirtualFs> Replayer<'_, F> {
+    /// The replayed inode of the captured `ino`
+    fn inode(&self, ino: Option<INum>) -> Option<INum> {
+        ino.and_then(|ino| self.inodes.get(&ino).copied())
+    }
+
+    /// Remember the entry `name` of `parent` created as `recorded` and
+    /// replayed as `ino`
+    fn created(&mut self, record: &AuditRecord, ino: INum) {
+        if let (Some(parent), Some(name), Some(recorded)) =
+            (record.parent, record.name.clone(), record.ino)
+        {
+            self.inodes.insert(recorded, ino);
+            self.entries.insert((parent, name), recorded);
+        }
+    }
+
+    /// Forget the entry `name` of `parent`, releasing the handle of its inode
+    async fn removed(&mut self, ctx: &RequestContext, parent: INum, name: &str) {
+        let Some(recorded) = self.entries.remove(&(parent, name.to_owned())) else {
+            return;
+        };
+        if let Some(ino) = self.inodes.remove(&recorded) {
+            if let Some(fh) = self.handles.remove(&ino) {
+                let _ = self.fs.release(ctx, ino, fh, 0, 0, false).await;
+            }
+        }
+    }
+
+    /// The handle to write to `ino` through, opened on first use
+    async fn handle(&mut self, ctx: &RequestContext, ino: INum) -> DatenLordResult<u64> {
+        if let Some(&fh) = self.handles.get(&ino) {
+            return Ok(fh);
+        }
+        let fh = self
+            .fs
+            .open(ctx, ino, OFlag::O_WRONLY.bits() as u32)
+            .await?;
+        self.handles.insert(ino, fh);
+        Ok(fh)
+    }
+
+    /// Issue the operation of `record`, `None` when it cannot be replayed
+    async fn apply(
+        &mut self,
+        ctx: &RequestContext,
+        record: &AuditRecord,
+    ) -> Option<DatenLordResult<()>> {
+        let parent = self.inode(record.parent);
+        let name = record.name.as_deref().map(OsStr::new);
+        match record.op.as_str() {
+            "mknod" | "mkdir" => {
+                let mode = record.mode?;
+                let kind = mode & SFlag::S_IFMT.bits();
+                let node_type = if record.op == "mkdir" {
+                    SFlag::S_IFDIR
+                } else if kind == 0 {
+                    SFlag::S_IFREG
+                } else {
+                    SFlag::from_bits_truncate(kind)
+                };
+                let param = CreateParam {
+                    parent: parent?,
+                    name: name?.to_owned(),
+                    mode,
+                    rdev: 0,
+                    node_type,
+                    link: None,
+                };
+                let result = if record.op == "mkdir" {
+                    self.fs.mkdir(ctx, param).await
+                } else {
+                    self.fs.mknod(ctx, param).await
+                };
+                Some(result.map(|(_, attr, _)| self.created(record, attr.ino)))
+            }
+            "symlink" => {
+                let target = Path::new(record.target.as_deref()?);
+                let result = self.fs.symlink(ctx, parent?, name?, target).await;
+                Some(result.map(|(_, attr, _)| self.created(record, attr.ino)))
+            }
+            "create" => {
+                let (parent, name) = (parent?, name?);
+                let flags = OFlag::O_CREAT.bits() as u32;
+                let result = self
+                    .fs
+                    .create(ctx, 0, parent, name, record.mode?, flags)
+                    .await;
+                let result = match result {
+                    Ok(()) => self.fs.lookup(ctx, parent, name).await,
+                    Err(e) => Err(e),
+                };
+                Some(result.map(|(_, attr, _)| self.created(record, attr.ino)))
+            }
+            "unlink" | "rmdir" => {
+                let (parent, name) = (parent?, name?);
+                self.removed(ctx, record.parent?, record.name.as_deref()?)
+                    .await;
+                Some(if record.op == "unlink" {
+                    self.fs.unlink(ctx, parent, name).await
+                } else {
+                    self.fs.rmdir(ctx, parent, name).await.map(|_| ())
+                })
+            }
+            "rename" => {
+                let param = RenameParam {
+                    old_parent: parent?,
+                    old_name: name?.to_owned(),
+                    new_parent: self.inode(record.new_parent)?,
+                    new_name: OsStr::new(record.new_name.as_deref()?).to_owned(),
+                    flags: 0,
+                };
+                let result = self.fs.rename(ctx, param).await;
+                if result.is_ok() {
+                    let (old, new) = (record.parent?, record.new_parent?);
+                    let new_name = record.new_name.clone()?;
+                    self.removed(ctx, new, &new_name).await;
+                    if let Some(ino) = self.entries.remove(&(old, record.name.clone()?)) {
+                        self.entries.insert((new, new_name), ino);
+                    }
+                }
+                Some(result)
+            }
+            "write" => {
+                let ino = self.inode(record.ino)?;
+                // The log keeps the size of writes, not their data
+                let data = vec![0; usize::try_from(record.len?).ok()?];
+                let offset = record.offset?;
+                let result = match self.handle(ctx, ino).await {
+                    Ok(fh) => self.fs.write(ctx, ino, fh, offset, &data, 0).await,
+                    Err(e) => Err(e),
+                };
+                Some(result)
+            }
+            "setattr" => {
+                let ino = self.inode(record.ino)?;
+                let (u_id, g_id) = record.owner.unwrap_or_default();
+                let param = SetAttrParam {
+                    fh: self.handles.get(&ino).copied(),
+                    mode: record.mode,
+                    u_id,
+                    g_id,
+                    size: record.size,
+                    ..SetAttrParam::default()
+                };
+                Some(self.fs.setattr(ctx, ino, param).await.map(|_| ()))
+            }
+            "open" => {
+                let ino = self.inode(record.ino)?;
+                let flags = (OFlag::O_WRONLY | OFlag::O_TRUNC).bits() as u32;
+                let result = match self.fs.open(ctx, ino, flags).await {
+                    Ok(fh) => self.fs.release(ctx, ino, fh, flags, 0, false).await,
+                    Err(e) => Err(e),
+                };
+                Some(result)
+            }
+            "setxattr" => {
+                let ino = self.inode(record.ino)?;
+                // Nor the values of extended attributes
+                let name = record.xattr.as_deref()?;
+                Some(self.fs.setxattr(ctx, ino, name, b"", 0, 0).await)
+            }
+            "removexattr" => {
+                let ino = self.inode(record.ino)?;
+                Some(
+                    self.fs
+                        .removexattr(ctx, ino, record.xattr.as_deref()?)
+                        .await,
+                )
+            }
+            // Nor the entries hard links are made to
+            _ => None,
+        }
+    }
+
+    /// Release every handle opened by the replay
+    async fn close(self, ctx: &RequestContext) {
+        for (ino, fh) in self.handles {
+            let _ = self.fs.release(ctx, ino, fh, 0, 0, false).await;
+        }
+    }
+}
+
+/// Replay `records` against `fs` on behalf of `ctx`, at the pace `options`
+/// sets
+///
+/// The inodes of the captured entries are mapped to the ones the replayed
+/// operations create, starting from the root; operations on entries that
+/// existed before the capture are skipped. Writes and extended attributes
+/// carry zeroes and empty values, the log holding their sizes and names
+/// only. Operations are issued in order, one at a time.
+pub async fn replay<F: VirtualFs>(
+    fs: &F,
+    ctx: RequestContext,
+    records: &[AuditRecord],
+    options: &ReplayOptions,
+) -> DatenLordResult<ReplayReport> {
+    if !(options.speed >= 0.0 && options.speed.is_finite()) {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!(
+                "replay speed={} must be a finite non-negative number",
+                options.speed
+            )],
+        });
+    }
+    let mut replayer = Replayer {
+        fs,
+        inodes: HashMap::from([(ROOT_ID, ROOT_ID)]),
+        entries: HashMap::new(),
+        handles: HashMap::new(),
+    };
+    let first = records.first().map_or(0, |record| record.timestamp_ns);
+    let start = Instant::now();
+    let (mut replayed, mut skipped) = (0, 0);
+    let mut mismatches = Vec::new();
+    let mut latencies = Vec::with_capacity(records.len());
+    for (index, record) in records.iter().enumerate() {
+        if options.speed > 0.0 {
+            let offset = u64::try_from(record.timestamp_ns - first).unwrap_or(0);
+            let due = Duration::from_nanos(offset).div_f64(options.speed);
+            tokio::time::sleep_until((start + due).into()).await;
+        }
+        let ctx = if options.as_recorded {
+            RequestContext {
+                uid: record.uid,
+                gid: record.gid,
+                pid: record.pid,
+                ..ctx
+            }
+        } else {
+            ctx
+        };
+        let issued = Instant::now();
+        let Some(result) = replayer.apply(&ctx, record).await else {
+            skipped += 1;
+            continue;
+        };
+        latencies.push(issued.elapsed());
+        replayed += 1;
+        if result.is_ok() != record.ok {
+            mismatches.push(Mismatch {
+                index,
+                op: record.op.clone(),
+                recorded_ok: record.ok,
+                error: result.err().map(|e| e.to_string()),
+            });
+        }
+    }
+    replayer.close(&ctx).await;
+    latencies.sort_unstable();
+    Ok(ReplayReport {
+        replayed,
+        skipped,
+        mismatches,
+        elapsed: start.elapsed(),
+        latencies,
+    })
+}
@@ -1,14 +1,49 @@
-use std::ffi::CStr;
-use std::os::raw::{c_char, c_uint};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::ffi::{CString, OsStr, OsString};
+use std::io::Write;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+use std::pin::Pin;
 use std::ptr;
-use std::time::SystemTime;
-use bytes::BytesMut;
 use tokio::runtime::Runtime;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-
-use crate::storage::fs_util::{CreateParam, RenameParam};
-use crate::storage::localfs::LocalFS;
+use std::time::{Duration, SystemTime};
+use nix::errno::Errno;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use nix::fcntl::OFlag;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::common::buffer_pool::{BufferPool, PooledBuffer, COPY_CHUNK_SIZE};
+use crate::common::config::{ConfigUpdate, DatenLordConfig};
+use crate::common::logging;
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::ffi::{self, CBytes};
+use crate::lifecycle::LifecycleTask;
+use crate::storage::fs_util::{
+    self, CreateParam, DirEntry, FileAttr, FileKind, RenameParam, RequestContext, UtimeSpec, ROOT_ID,
+};
+use crate::sdk::gate::{Call, CallGate};
+use crate::sdk::{self, SdkFs};
+use crate::storage::appendlog::{LogSync, SharedLogs};
+use crate::storage::digest::{self, DigestAlgorithm};
+use crate::storage::kv::{KvOptions, KvStore};
+use crate::storage::notify::EventKind;
+use crate::storage::tags;
+use crate::storage::stream;
+use crate::storage::timeout;
+use crate::storage::gc::GcTask;
+use crate::storage::health::HealthReport;
+use crate::storage::trash::PurgeTask;
+use crate::storage::transfer::{self, CopyConfig};
+use crate::storage::upload::{self, UploadPart};
 use crate::storage::virtualfs::{INum, VirtualFs};
+use crate::storage::walk::{self, DiskUsage, Walk, DEFAULT_WALK_CONCURRENCY};
+use crate::storage::writeback::WritebackTask;
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
@@ -17,26 +52,142 @@ pub struct datenlord_error {
     pub message: datenlord_bytes,
 }
 
-/// File attributes
+/// A point in time relative to the Unix epoch, like `struct timespec`
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_timespec {
+    /// Seconds, negative before the epoch
+    pub sec: i64,
+    /// Nanoseconds past `sec`, below 1000000000
+    pub nsec: u32,
+}
+
+/// `datenlord_timespec::nsec` setting the timestamp to now, like `UTIME_NOW`
+pub const DATENLORD_UTIME_NOW: u32 = (1 << 30) - 1;
+/// `datenlord_timespec::nsec` leaving the timestamp unchanged, like `UTIME_OMIT`
+pub const DATENLORD_UTIME_OMIT: u32 = (1 << 30) - 2;
+
+impl datenlord_timespec {
+    fn new(time: SystemTime) -> Self {
+        let (sec, nsec) = fs_util::to_timespec(time);
+        Self { sec, nsec }
+    }
+
+    /// The timestamp to set, `None` if `nsec` is out of range
+    fn to_utime(self) -> Option<UtimeSpec> {
+        match self.nsec {
+            DATENLORD_UTIME_NOW => Some(UtimeSpec::Now),
+            DATENLORD_UTIME_OMIT => Some(UtimeSpec::Omit),
+            nsec => fs_util::from_timespec(self.sec, nsec).map(UtimeSpec::Time),
+        }
+    }
+}
+
+/// The type of a file
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub enum datenlord_file_kind {
+    DATENLORD_FILE_KIND_UNKNOWN,
+    DATENLORD_FILE_KIND_REGULAR,
+    DATENLORD_FILE_KIND_DIRECTORY,
+    DATENLORD_FILE_KIND_SYMLINK,
+    DATENLORD_FILE_KIND_FIFO,
+    DATENLORD_FILE_KIND_CHAR_DEVICE,
+    DATENLORD_FILE_KIND_BLOCK_DEVICE,
+    DATENLORD_FILE_KIND_SOCKET,
+}
+
+impl From<Option<FileKind>> for datenlord_file_kind {
+    fn from(kind: Option<FileKind>) -> Self {
+        match kind {
+            None => Self::DATENLORD_FILE_KIND_UNKNOWN,
+            Some(FileKind::RegularFile) => Self::DATENLORD_FILE_KIND_REGULAR,
+            Some(FileKind::Directory) => Self::DATENLORD_FILE_KIND_DIRECTORY,
+            Some(FileKind::Symlink) => Self::DATENLORD_FILE_KIND_SYMLINK,
+            Some(FileKind::NamedPipe) => Self::DATENLORD_FILE_KIND_FIFO,
+            Some(FileKind::CharDevice) => Self::DATENLORD_FILE_KIND_CHAR_DEVICE,
+            Some(FileKind::BlockDevice) => Self::DATENLORD_FILE_KIND_BLOCK_DEVICE,
+            Some(FileKind::Socket) => Self::DATENLORD_FILE_KIND_SOCKET,
+        }
+    }
+}
+
+/// File attributes filled by `stat`, modelled after `struct statx`
 #[repr(C)]
 #[allow(non_camel_case_types)]
-pub struct datenlord_file_stat {
+pub struct datenlord_stat {
     /// Inode number
     pub ino: INum,
     /// Size in bytes
     pub size: u64,
-    /// Size in blocks
+    /// Number of 512-byte blocks allocated
     pub blocks: u64,
-    /// Permissions
-    pub perm: u16,
+    /// File type and permission bits, like `st_mode`
+    pub mode: u32,
+    /// File type
+    pub kind: datenlord_file_kind,
     /// Number of hard links
     pub nlink: u32,
-    /// User id
+    /// User id of the owner
     pub uid: u32,
-    /// Group id
+    /// Group id of the owner
     pub gid: u32,
-    /// Rdev
-    pub rdev: u32,
+    /// Time of last access
+    pub atime: datenlord_timespec,
+    /// Time of last modification
+    pub mtime: datenlord_timespec,
+    /// Time of last status change
+    pub ctime: datenlord_timespec,
+    /// The generation of the entries of a directory, changing whenever
+    /// entries are created, removed or renamed in it, 0 for other files
+    pub generation: u64,
+}
+
+impl From<&FileAttr> for datenlord_stat {
+    fn from(attr: &FileAttr) -> Self {
+        Self {
+            ino: attr.ino,
+            size: attr.size,
+            blocks: attr.blocks,
+            mode: attr.mode(),
+            kind: FileKind::from_sflag(attr.kind).into(),
+            nlink: attr.nlink,
+            uid: attr.uid,
+            gid: attr.gid,
+            atime: datenlord_timespec::new(attr.atime),
+            mtime: datenlord_timespec::new(attr.mtime),
+            ctime: datenlord_timespec::new(attr.ctime),
+            generation: attr.generation,
+        }
+    }
+}
+
+/// How a probe of `datenlord_healthcheck` went, the latencies of the steps
+/// not reached are 0
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_health {
+    /// Whether every step succeeded
+    pub healthy: bool,
+    /// Microseconds reading the attributes of the root took
+    pub metadata_latency_us: u64,
+    /// Microseconds writing the probe file took
+    pub write_latency_us: u64,
+    /// Microseconds reading the probe file back took
+    pub read_latency_us: u64,
+}
+
+impl From<&HealthReport> for datenlord_health {
+    fn from(report: &HealthReport) -> Self {
+        Self {
+            healthy: report.healthy,
+            metadata_latency_us: report.metadata_latency_us.unwrap_or(0),
+            write_latency_us: report.write_latency_us.unwrap_or(0),
+            read_latency_us: report.read_latency_us.unwrap_or(0),
+        }
+    }
 }
 
 #[repr(C)]
@@ -46,168 +197,499 @@ pub struct datenlord_bytes {
     pub len: usize,
 }
 
+/// A buffer borrowed from the SDK buffer pool
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_buffer {
+    /// Start of the buffer, aligned to 4096 bytes, null if the acquire failed
+    pub data: *mut u8,
+    /// Usable length of the buffer
+    pub len: usize,
+    /// Opaque pool handle, must be passed back untouched to `datenlord_buffer_release`
+    pub handle: *mut c_void,
+}
+
+/// Addresses of the errors handed to C and not yet freed
+///
+/// Lets `datenlord_error_free` ignore pointers it did not hand out or
+/// already freed instead of corrupting the heap.
+static LIVE_ERRORS: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+
 impl datenlord_error {
     fn new(code: c_uint, message: String) -> *mut datenlord_error {
-        let message_bytes = message.into_bytes();
-        let error = Box::new(datenlord_error {
+        // The message is owned by the error and freed by `datenlord_error_free`
+        let (data, len) = ffi::into_raw_bytes(message.into_bytes());
+        let error = ffi::into_raw(datenlord_error {
             code,
-            message: datenlord_bytes {
-                data: message_bytes.as_ptr(),
-                len: message_bytes.len(),
-            },
+            message: datenlord_bytes { data, len },
         });
-        Box::into_raw(error)
+        LIVE_ERRORS.lock().unwrap().insert(error as usize);
+        error
     }
 }
 
-#[repr(C)]
+/// Opaque SDK handle, not `repr(C)` so C only sees a forward declaration
 #[allow(non_camel_case_types)]
 pub struct datenlord_sdk {
     // Do not expose the internal structure
-    localfs: Arc<Mutex<LocalFS>>,
+    localfs: Arc<SdkFs>,
+    /// The caller every operation runs on behalf of, see `ctx()`
+    ctx: RequestContext,
+    /// The umask of the caller, changed by `datenlord_set_umask`
+    umask: AtomicU32,
+    buffer_pool: BufferPool,
+    /// How `copy_from_local_file` splits large files
+    copy: CopyConfig,
+    /// Runtime running the asynchronous operations, taken down by
+    /// `datenlord_shutdown`
+    runtime: Mutex<Option<Runtime>>,
+    /// Handle of `runtime`
+    handle: Handle,
+    /// Admits calls until `datenlord_shutdown`
+    calls: Arc<CallGate>,
+    /// The id of the next asynchronous operation
+    next_op_id: AtomicU64,
+    /// Finished asynchronous operations submitted without a callback
+    completions: Arc<Mutex<VecDeque<Completion>>>,
+    /// Asynchronous operations still running, keyed by id
+    pending: Arc<Mutex<HashMap<u64, PendingOp>>>,
+    /// The scheduled evaluation of the lifecycle rules, if any, stopped by
+    /// `datenlord_shutdown`
+    lifecycle: Mutex<Option<LifecycleTask>>,
+    /// The scheduled purge of the trash, if any, stopped by
+    /// `datenlord_shutdown`
+    purge: Mutex<Option<PurgeTask>>,
+    /// The scheduled garbage collection, if any, stopped by
+    /// `datenlord_shutdown`
+    gc: Mutex<Option<GcTask>>,
+    /// The background writeback, flushing a last time on `datenlord_shutdown`
+    /// or `free_sdk`
+    writeback: Mutex<Option<WritebackTask>>,
+    /// The logs appended to by `datenlord_append`, closed by
+    /// `datenlord_shutdown`
+    logs: SharedLogs<SdkFs>,
+}
+
+// Calls on one handle from many C threads share it
+const _: fn() = || {
+    fn send_sync<T: Send + Sync>() {}
+    send_sync::<datenlord_sdk>();
+};
+
+impl datenlord_sdk {
+    /// The context of an operation starting now
+    fn ctx(&self) -> RequestContext {
+        RequestContext {
+            umask: self.umask.load(Ordering::Relaxed),
+            ..self.ctx
+        }
+    }
+}
+
+/// Callback invoked when an asynchronous operation finishes
+///
+/// `error` is null on success and owned by the callee otherwise, `result` is
+/// the number of bytes transferred. The callback runs on an SDK worker
+/// thread and must not block.
+#[allow(non_camel_case_types)]
+pub type datenlord_completion_cb =
+    Option<extern "C" fn(op_id: u64, error: *mut datenlord_error, result: usize, user_data: *mut c_void)>;
+
+/// A positional read or write issued through the asynchronous API
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_io_request {
+    /// Path of the file relative to the SDK root
+    pub path: *const c_char,
+    /// Offset in the file
+    pub offset: u64,
+    /// Buffer to read into or write from, must stay valid until completion
+    pub buf: *mut u8,
+    /// Length of `buf`
+    pub len: usize,
+    /// Time the whole operation may take from submission in milliseconds,
+    /// retries included, 0 bounds each step by the configured default timeout
+    pub timeout_ms: u64,
+}
+
+/// A finished asynchronous operation returned by `datenlord_poll_completions`
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_completion {
+    /// The id returned when the operation was submitted
+    pub op_id: u64,
+    /// Null on success, must be freed by the caller otherwise
+    pub error: *mut datenlord_error,
+    /// The number of bytes transferred
+    pub result: usize,
+    /// The `user_data` passed when the operation was submitted
+    pub user_data: *mut c_void,
+}
+
+/// A finished asynchronous operation waiting to be polled
+struct Completion {
+    /// The id of the operation
+    op_id: u64,
+    /// The number of bytes transferred, or the error code and message
+    result: Result<usize, (c_uint, String)>,
+    /// The `user_data` pointer, kept as an address so it can cross threads
+    user_data: usize,
+}
+
+/// A running asynchronous operation
+struct PendingOp {
+    /// The task running the operation
+    task: JoinHandle<()>,
+    /// The callback to report completion to, `None` to queue it instead
+    callback: datenlord_completion_cb,
+    /// The `user_data` pointer, kept as an address so it can cross threads
+    user_data: usize,
+}
+
+/// Report a finished operation through `callback`, or queue it when there is none
+fn complete(
+    completions: &Mutex<VecDeque<Completion>>,
+    callback: datenlord_completion_cb,
+    completion: Completion,
+) {
+    match callback {
+        Some(callback) => {
+            let (error, size) = match completion.result {
+                Ok(size) => (ptr::null_mut(), size),
+                Err((code, message)) => (datenlord_error::new(code, message), 0),
+            };
+            callback(completion.op_id, error, size, completion.user_data as *mut c_void);
+        }
+        None => completions.lock().unwrap().push_back(completion),
+    }
+}
+
+/// Free an error returned by the SDK together with its message
+///
+/// Null, unknown and already freed errors are ignored.
+#[no_mangle]
+pub extern "C" fn datenlord_error_free(err: *mut datenlord_error) {
+    if !LIVE_ERRORS.lock().unwrap().remove(&(err as usize)) {
+        return;
+    }
+    if let Some(error) = ffi::from_raw(err) {
+        ffi::free_raw_bytes(error.message.data, error.message.len);
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn init(config: *const c_char) -> *mut datenlord_sdk {
-    if config.is_null() {
+    let Some(config_str) = ffi::str_arg(config) else {
         return ptr::null_mut();
-    }
+    };
+    open_sdk(config_str, None)
+}
 
-    let config_str = unsafe {
-        CStr::from_ptr(config)
-            .to_str()
-            .unwrap_or("default config")
+/// Like `init`, with every path relative to the directory `prefix` of the
+/// namespace, e.g. `/tenants/alice`, which paths cannot lead out of,
+/// neither with `..` nor through symbolic links
+///
+/// Null if `prefix` is not a directory. The background tasks the config
+/// schedules still run on the whole namespace.
+#[no_mangle]
+pub extern "C" fn datenlord_init_scoped(
+    config: *const c_char,
+    prefix: *const c_char,
+) -> *mut datenlord_sdk {
+    let (Some(config_str), Some(prefix)) = (ffi::str_arg(config), ffi::os_str_arg(prefix)) else {
+        return ptr::null_mut();
     };
+    open_sdk(config_str, Some(prefix))
+}
 
-    let localfs = LocalFS::new().unwrap();
-    let sdk = Box::new(datenlord_sdk {
-        localfs: Arc::new(Mutex::new(localfs)),
-    });
+/// The SDK of the config `config_str`, scoped to `prefix` if any
+fn open_sdk(config_str: &str, prefix: Option<&OsStr>) -> *mut datenlord_sdk {
 
-    Box::into_raw(sdk)
+    let config = DatenLordConfig::parse(config_str);
+    let localfs = match sdk::open_fs(&config) {
+        Ok(localfs) => localfs,
+        Err(_) => return ptr::null_mut(),
+    };
+    let runtime = match Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(_) => return ptr::null_mut(),
+    };
+    let localfs = Arc::new(localfs);
+    let ctx = config.request_context();
+    let Ok(writeback) = sdk::writeback(&localfs, &config) else {
+        return ptr::null_mut();
+    };
+    let Ok(lifecycle) = LifecycleTask::start(Arc::clone(&localfs), ctx, config.lifecycle) else {
+        return ptr::null_mut();
+    };
+    let Ok(purge) = PurgeTask::start(Arc::clone(&localfs), ctx, config.trash) else {
+        return ptr::null_mut();
+    };
+    let Ok(gc) = GcTask::start(Arc::clone(&localfs), sdk::packed, ctx, config.gc) else {
+        return ptr::null_mut();
+    };
+    let ctx = match prefix {
+        Some(prefix) => match runtime.block_on(sdk::scope(&localfs, &ctx, prefix)) {
+            Ok(ctx) => ctx,
+            Err(_) => return ptr::null_mut(),
+        },
+        None => ctx,
+    };
+    let logs = SharedLogs::new(Arc::clone(&localfs), ctx, LogSync::Batch);
+    ffi::into_raw_arc(datenlord_sdk {
+        localfs,
+        ctx,
+        umask: AtomicU32::new(ctx.umask),
+        buffer_pool: BufferPool::new(),
+        copy: config.copy,
+        handle: runtime.handle().clone(),
+        runtime: Mutex::new(Some(runtime)),
+        calls: Arc::default(),
+        next_op_id: AtomicU64::new(1),
+        completions: Arc::new(Mutex::new(VecDeque::new())),
+        pending: Arc::new(Mutex::new(HashMap::new())),
+        lifecycle: Mutex::new(lifecycle),
+        purge: Mutex::new(purge),
+        gc: Mutex::new(gc),
+        writeback: Mutex::new(Some(writeback)),
+        logs,
+    })
 }
 
+/// Give back the reference of `sdk` to the SDK, freeing it with the last
+/// one and aborting the asynchronous operations still running
+///
+/// No call may be running or made afterwards through the reference given
+/// back, nor through any other once the SDK is freed, see
+/// `datenlord_shutdown` to wait for them first.
 #[no_mangle]
 pub extern "C" fn free_sdk(sdk: *mut datenlord_sdk) {
-    if !sdk.is_null() {
-        unsafe {
-            let _ = Box::from_raw(sdk);
-        }
-    }
+    drop(ffi::from_raw_arc(sdk));
 }
 
+/// Another reference to the SDK of `sdk`, to be freed by its own
+/// `free_sdk`, null if `sdk` is
+///
+/// Lets threads that may outlive each other share the SDK, which is freed
+/// once every reference was.
 #[no_mangle]
-pub extern "C" fn exists(sdk: *mut datenlord_sdk, dir_path: *const c_char) -> bool {
-    if sdk.is_null() || dir_path.is_null() {
-        return false;
+pub extern "C" fn datenlord_sdk_clone(sdk: *mut datenlord_sdk) -> *mut datenlord_sdk {
+    ffi::clone_raw_arc(sdk)
+}
+
+/// Shut the SDK down gracefully, waiting at most `timeout_ms` for the calls
+/// still running, or without limit when 0
+///
+/// Calls made from now on fail with the error code `ESHUTDOWN`, or return
+/// false, 0 or null. Once the running calls, the asynchronous operations
+/// and the open walks finished, the written data is synced, the background
+/// tasks stop, the runtime is taken down and the spans ended so far are
+/// exported; `free_sdk` must still be called on every reference. If calls are still running after the timeout
+/// the SDK stays shut to new calls and `ETIMEDOUT` is returned, calling
+/// again waits again. Must not be called from a completion callback.
+#[no_mangle]
+pub extern "C" fn datenlord_shutdown(
+    sdk: *mut datenlord_sdk,
+    timeout_ms: u64,
+) -> *mut datenlord_error {
+    let Some(sdk_ref) = ffi::as_ref(sdk) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let timeout = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms));
+    if !sdk_ref.calls.close(timeout) {
+        return datenlord_error::new(
+            Errno::ETIMEDOUT as c_uint,
+            "Timed out waiting for the running calls".to_string(),
+        );
     }
+    let Some(runtime) = sdk_ref.runtime.lock().unwrap().take() else {
+        return ptr::null_mut();
+    };
+    let synced = runtime.block_on(async {
+        let closed = sdk_ref.logs.close().await;
+        sdk_ref.localfs.sync_all(&sdk_ref.ctx()).await.and(closed)
+    });
+    drop(sdk_ref.lifecycle.lock().unwrap().take());
+    drop(sdk_ref.purge.lock().unwrap().take());
+    drop(sdk_ref.gc.lock().unwrap().take());
+    drop(sdk_ref.writeback.lock().unwrap().take());
+    runtime.shutdown_background();
+    logging::flush_traces();
+    match synced {
+        Ok(()) => ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to sync filesystem: {e}")),
+    }
+}
 
-    let path = unsafe { CStr::from_ptr(dir_path).to_str().unwrap_or_default() };
+/// Set the umask applied to the files and directories created from now on,
+/// returning the former one, like `umask(2)`
+///
+/// Starts as the `caller.umask` of the config, or the umask of the process.
+#[no_mangle]
+pub extern "C" fn datenlord_set_umask(sdk: *mut datenlord_sdk, umask: c_uint) -> c_uint {
+    let Some(sdk_ref) = ffi::as_ref(sdk) else {
+        return 0;
+    };
+    sdk_ref.umask.swap(umask & 0o777, Ordering::Relaxed)
+}
 
-    let sdk_ref = unsafe { &*sdk };
+#[no_mangle]
+pub extern "C" fn exists(sdk: *mut datenlord_sdk, dir_path: *const c_char) -> bool {
+    exists_at(sdk, ffi::os_str_arg(dir_path))
+}
 
-    let rt = Runtime::new().unwrap();
-    let result = rt.block_on(async {
-        let localfs = sdk_ref.localfs.lock().unwrap();
-        // demo inode info
-        localfs.lookup(1000, 1000, 1, path).await
-    });
+/// Like `exists` with the `path.len` bytes of `path` as path, which need
+/// not be UTF-8 nor NUL-terminated
+#[no_mangle]
+pub extern "C" fn datenlord_exists_bytes(sdk: *mut datenlord_sdk, path: datenlord_bytes) -> bool {
+    exists_at(sdk, ffi::os_str_bytes_arg(path.data, path.len))
+}
 
-    result.is_ok()
+/// Whether `path` exists, false on invalid arguments
+fn exists_at(sdk: *mut datenlord_sdk, path: Option<&OsStr>) -> bool {
+    let (Some(sdk_ref), Some(path)) = (ffi::as_ref(sdk), path) else {
+        return false;
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return false;
+    };
+    sdk_ref
+        .handle
+        .block_on(sdk_ref.localfs.exists(&sdk_ref.ctx(), ROOT_ID, path))
+        .unwrap_or(false)
 }
 
 #[no_mangle]
 pub extern "C" fn mkdir(sdk: *mut datenlord_sdk, dir_path: *const c_char) -> *mut datenlord_error {
-    if sdk.is_null() || dir_path.is_null() {
+    let (Some(sdk_ref), Some(path)) = (ffi::as_ref(sdk), ffi::os_str_arg(dir_path)) else {
         return datenlord_error::new(1, "Invalid arguments".to_string());
-    }
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
 
     println!("mkdir path {:?}", dir_path);
-
-    let path = unsafe { CStr::from_ptr(dir_path).to_str().unwrap_or_default() };
-
-    let sdk_ref = unsafe { &*sdk };
-
-    let rt = Runtime::new().unwrap();
-    let result = rt.block_on(async {
+    let result = sdk_ref.handle.block_on(async {
         let param = CreateParam {
-            parent: 34735213,// test inode
-            name: path.to_string(),
+            parent: ROOT_ID,
+            name: path.to_owned(),
             mode: 0o777,
             rdev: 0,
-            uid: 1000,
-            gid: 1000,
             node_type: nix::sys::stat::SFlag::S_IFDIR,
             link: None,
         };
 
-        let localfs = sdk_ref.localfs.lock().unwrap();
-        localfs.mkdir(param).await
+        let localfs = &sdk_ref.localfs;
+        localfs.mkdir(&sdk_ref.ctx(), param).await
     });
 
     match result {
         Ok(_) => std::ptr::null_mut(),
-        Err(_) => datenlord_error::new(1, "Failed to create directory".to_string()),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to create directory".to_string()),
     }
 
 }
 
+/// Create `dir_path` with `mode` together with its missing parents, like `mkdir -p`
+///
+/// Directories that already exist, or are created concurrently, are left
+/// as they are.
 #[no_mangle]
-pub extern "C" fn deldir(
+pub extern "C" fn datenlord_mkdir_all(
     sdk: *mut datenlord_sdk,
     dir_path: *const c_char,
-    recursive: bool
+    mode: c_uint,
 ) -> *mut datenlord_error {
-    if sdk.is_null() || dir_path.is_null() {
+    let (Some(sdk_ref), Some(path)) = (ffi::as_ref(sdk), ffi::os_str_arg(dir_path)) else {
         return datenlord_error::new(1, "Invalid arguments".to_string());
-    }
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        localfs.mkdir_all(&sdk_ref.ctx(), ROOT_ID, path, mode).await
+    });
 
-    let path = unsafe { CStr::from_ptr(dir_path).to_str().unwrap_or_default() };
-    let sdk_ref = unsafe { &*sdk };
+    match result {
+        Ok(_) => std::ptr::null_mut(),
+        Err(DatenLordError::AlreadyExists { .. }) => datenlord_error::new(
+            Errno::EEXIST as c_uint,
+            "Failed to create directory, a path component is not a directory".to_string(),
+        ),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to create directory".to_string()),
+    }
+}
 
-    let rt = Runtime::new().unwrap();
+#[no_mangle]
+pub extern "C" fn deldir(
+    sdk: *mut datenlord_sdk,
+    dir_path: *const c_char,
+    recursive: bool
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path)) = (ffi::as_ref(sdk), ffi::os_str_arg(dir_path)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
     // dimiss recursive now
-    let result = rt.block_on(async {
-        let localfs = sdk_ref.localfs.lock().unwrap();
-        localfs.rmdir(1000, 1000, 1, path).await
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        localfs.rmdir(&sdk_ref.ctx(), 1, path).await
     });
 
     match result {
         Ok(_) => std::ptr::null_mut(),
-        Err(_) => datenlord_error::new(1, "Failed to remove directory".to_string()),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to remove directory".to_string()),
     }
 }
 
+/// `rename_path` flag failing if the destination exists, like `RENAME_NOREPLACE`
+pub const DATENLORD_RENAME_NOREPLACE: c_uint = 1;
+/// `rename_path` flag atomically swapping source and destination, like `RENAME_EXCHANGE`
+pub const DATENLORD_RENAME_EXCHANGE: c_uint = 2;
+
+/// Rename `src_path` to `dest_path` according to `flags`, like `renameat2`
+///
+/// With `DATENLORD_RENAME_NOREPLACE` an existing destination fails the call
+/// with the error code `EEXIST`.
 #[no_mangle]
 pub extern "C" fn rename_path(
     sdk: *mut datenlord_sdk,
     src_path: *const c_char,
-    dest_path: *const c_char
+    dest_path: *const c_char,
+    flags: c_uint,
 ) -> *mut datenlord_error {
-    if sdk.is_null() || src_path.is_null() || dest_path.is_null() {
+    let (Some(sdk_ref), Some(src), Some(dest)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(src_path),
+        ffi::os_str_arg(dest_path),
+    ) else {
         return datenlord_error::new(1, "Invalid arguments".to_string());
-    }
-
-    let src = unsafe { CStr::from_ptr(src_path).to_str().unwrap_or_default() };
-    let dest = unsafe { CStr::from_ptr(dest_path).to_str().unwrap_or_default() };
-    let sdk_ref = unsafe { &*sdk };
-
-    let rt = Runtime::new().unwrap();
-    let result = rt.block_on(async {
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
         let param = RenameParam {
             old_parent: 1,
-            old_name: src.to_string(),
+            old_name: src.to_owned(),
             new_parent: 1,
-            new_name: dest.to_string(),
-            flags: 0,
+            new_name: dest.to_owned(),
+            flags,
         };
-        let localfs = sdk_ref.localfs.lock().unwrap();
-        localfs.rename(1000, 1000, param).await
+        let localfs = &sdk_ref.localfs;
+        localfs.rename(&sdk_ref.ctx(), param).await
     });
 
     match result {
         Ok(_) => std::ptr::null_mut(),
-        Err(_) => datenlord_error::new(1, "Failed to rename path".to_string()),
+        Err(DatenLordError::AlreadyExists { .. }) => datenlord_error::new(
+            Errno::EEXIST as c_uint,
+            "Failed to rename path, destination exists".to_string(),
+        ),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to rename path".to_string()),
     }
 }
 
@@ -218,31 +700,56 @@ pub extern "C" fn copy_from_local_file(
     local_file_path: *const c_char,
     dest_file_path: *const c_char
 ) -> *mut datenlord_error {
-    if sdk.is_null() || local_file_path.is_null() || dest_file_path.is_null() {
+    let (Some(sdk_ref), Some(local), Some(dest)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(local_file_path),
+        ffi::os_str_arg(dest_file_path),
+    ) else {
         return datenlord_error::new(1, "Invalid arguments".to_string());
-    }
-
-    let local = unsafe { CStr::from_ptr(local_file_path).to_str().unwrap_or_default() };
-    let dest = unsafe { CStr::from_ptr(dest_file_path).to_str().unwrap_or_default() };
-    let sdk_ref = unsafe { &*sdk };
-
-    let rt = Runtime::new().unwrap();
-    let result = rt.block_on(async {
-        let localfs = sdk_ref.localfs.lock().unwrap();
-
-        if !overwrite && localfs.lookup(1000, 1000, 1, dest).await.is_ok() {
-            return Err(());
-        }
-
-        match std::fs::read(local) {
-            Ok(content) => {
-                match localfs.write(1, 0, 0, &content, 0).await {
-                    Ok(_) => Ok(()),
-                    Err(_) => Err(()),
-                }
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+
+        let ino = match localfs.lookup(&sdk_ref.ctx(), ROOT_ID, dest).await {
+            Ok(_) if !overwrite => return Err(()),
+            Ok((_, attr, _)) => attr.ino,
+            Err(_) => {
+                let param = CreateParam {
+                    parent: ROOT_ID,
+                    name: dest.to_owned(),
+                    mode: 0o666,
+                    rdev: 0,
+                    node_type: nix::sys::stat::SFlag::S_IFREG,
+                    link: None,
+                };
+                localfs.mknod(&sdk_ref.ctx(), param).await.map_err(|_| ())?.1.ino
             }
-            Err(_) => Err(()),
+        };
+
+        let file = std::fs::File::open(local).map_err(|_| ())?;
+        let fh = localfs.open(&sdk_ref.ctx(), ino, OFlag::O_WRONLY.bits() as u32).await.map_err(|_| ())?;
+        let result = transfer::copy_from_local(
+            localfs,
+            sdk_ref.ctx(),
+            file,
+            ino,
+            fh,
+            &sdk_ref.copy,
+            &sdk_ref.buffer_pool,
+        )
+        .await
+        .map_err(|_| ());
+        localfs.release(&sdk_ref.ctx(), ino, fh, 0, 0, true).await.map_err(|_| ())?;
+        result?;
+        for (key, value) in tags::read_local_tags(Path::new(local)).map_err(|_| ())? {
+            tags::set_tag(localfs.as_ref(), &sdk_ref.ctx(), ino, &key, &value)
+                .await
+                .map_err(|_| ())?;
         }
+        Ok(())
     });
 
     match result {
@@ -257,29 +764,41 @@ pub extern "C" fn copy_to_local_file(
     src_file_path: *const c_char,
     local_file_path: *const c_char
 ) -> *mut datenlord_error {
-    if sdk.is_null() || src_file_path.is_null() || local_file_path.is_null() {
+    let (Some(sdk_ref), Some(src), Some(local)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(src_file_path),
+        ffi::os_str_arg(local_file_path),
+    ) else {
         return datenlord_error::new(1, "Invalid arguments".to_string());
-    }
-
-    let src = unsafe { CStr::from_ptr(src_file_path).to_str().unwrap_or_default() };
-    let local = unsafe { CStr::from_ptr(local_file_path).to_str().unwrap_or_default() };
-    let sdk_ref = unsafe { &*sdk };
-
-    let rt = Runtime::new().unwrap();
-    let result = rt.block_on(async {
-        let mut buf = BytesMut::new();
-        let localfs = sdk_ref.localfs.lock().unwrap();
-
-        // for demo purpose, we need to get the hole file size
-        match localfs.read(1, 0, 0, 1024, &mut buf).await {
-            Ok(size) => {
-                match std::fs::write(local, &buf[..size]) {
-                    Ok(_) => Ok(()),
-                    Err(_) => Err(()),
-                }
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        let ctx = sdk_ref.ctx();
+        let (_, attr, _) = localfs.lookup(&ctx, ROOT_ID, src).await.map_err(|_| ())?;
+        let fh = localfs.open(&ctx, attr.ino, OFlag::O_RDONLY.bits() as u32).await.map_err(|_| ())?;
+
+        let mut file = std::fs::File::create(local).map_err(|_| ())?;
+        let mut buf = sdk_ref.buffer_pool.acquire(COPY_CHUNK_SIZE);
+        let mut offset = 0;
+        let result = loop {
+            let read = localfs.read(&ctx, attr.ino, fh, offset, buf.len() as u32, &mut buf);
+            let size = match read.await {
+                Ok(0) => break Ok(()),
+                Ok(size) => size,
+                Err(_) => break Err(()),
+            };
+            if file.write_all(&buf[..size]).is_err() {
+                break Err(());
             }
-            Err(_) => Err(()),
-        }
+            offset += size as u64;
+        };
+        localfs.release(&ctx, attr.ino, fh, 0, 0, true).await.map_err(|_| ())?;
+        result?;
+        let tags = tags::get_tags(localfs.as_ref(), &ctx, attr.ino).await.map_err(|_| ())?;
+        tags::write_local_tags(Path::new(local), &tags).map_err(|_| ())
     });
 
     match result {
@@ -288,145 +807,2144 @@ pub extern "C" fn copy_to_local_file(
     }
 }
 
+/// What `datenlord_copy` copied
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_copy_result {
+    /// The bytes copied
+    pub copied: u64,
+    /// Whether the destination shares the blocks of the source rather than
+    /// holding a copy of its data
+    pub reflinked: bool,
+}
 
+/// Copy the file `src_path` to `dst_path`, replacing the file at the
+/// destination, and fill `result` with what was copied
+///
+/// Backends able to share the blocks of both files, like btrfs and XFS
+/// through `FICLONE`, do so instead of copying the data.
 #[no_mangle]
-pub extern "C" fn create_file(
+pub extern "C" fn datenlord_copy(
     sdk: *mut datenlord_sdk,
-    file_path: *const c_char
+    src_path: *const c_char,
+    dst_path: *const c_char,
+    result: *mut datenlord_copy_result,
 ) -> *mut datenlord_error {
-    if sdk.is_null() || file_path.is_null() {
+    let (Some(sdk_ref), Some(src), Some(dst), Some(result)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(src_path),
+        ffi::os_str_arg(dst_path),
+        ffi::as_mut(result),
+    ) else {
         return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let ctx = sdk_ref.ctx();
+    let copy = transfer::copy_file(sdk_ref.localfs.as_ref(), &ctx, src, dst);
+    match sdk_ref.handle.block_on(copy) {
+        Ok(copy) => {
+            *result = datenlord_copy_result {
+                copied: copy.copied,
+                reflinked: copy.reflinked,
+            };
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to copy {src:?} to {dst:?}: {e}")),
     }
+}
+
+/// Create the regular file `file_path`
+///
+/// With `ensure_parents` its missing parent directories are created first,
+/// like `datenlord_mkdir_all`.
+#[no_mangle]
+pub extern "C" fn create_file(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    ensure_parents: bool,
+) -> *mut datenlord_error {
+    create_file_at(sdk, ffi::os_str_arg(file_path), ensure_parents)
+}
+
+/// Like `create_file` with the `file_path.len` bytes of `file_path` as path,
+/// which need not be UTF-8 nor NUL-terminated
+#[no_mangle]
+pub extern "C" fn datenlord_create_file_bytes(
+    sdk: *mut datenlord_sdk,
+    file_path: datenlord_bytes,
+    ensure_parents: bool,
+) -> *mut datenlord_error {
+    create_file_at(sdk, ffi::os_str_bytes_arg(file_path.data, file_path.len), ensure_parents)
+}
 
-    let path = unsafe { CStr::from_ptr(file_path).to_str().unwrap_or_default() };
-    let sdk_ref = unsafe { &*sdk };
+/// Create the regular file `path`, see `create_file`
+fn create_file_at(
+    sdk: *mut datenlord_sdk,
+    path: Option<&OsStr>,
+    ensure_parents: bool,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path)) = (ffi::as_ref(sdk), path) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        if ensure_parents {
+            if let Some(end) = path.as_bytes().iter().rposition(|&b| b == b'/') {
+                let parents = OsStr::from_bytes(&path.as_bytes()[..end]);
+                localfs.mkdir_all(&sdk_ref.ctx(), ROOT_ID, parents, 0o777).await?;
+            }
+        }
 
-    let rt = Runtime::new().unwrap();
-    let result = rt.block_on(async {
         let param = CreateParam {
-            parent: 1,
-            name: path.to_string(),
-            mode: 0o644,
+            parent: ROOT_ID,
+            name: path.to_owned(),
+            mode: 0o666,
             rdev: 0,
-            uid: 1000,
-            gid: 1000,
             node_type: nix::sys::stat::SFlag::S_IFREG,
             link: None,
         };
-
-        let localfs = sdk_ref.localfs.lock().unwrap();
-        localfs.mknod(param).await
+        localfs.mknod(&sdk_ref.ctx(), param).await
     });
 
     match result {
         Ok(_) => std::ptr::null_mut(),
-        Err(_) => datenlord_error::new(1, "Failed to create file".to_string()),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to create file".to_string()),
     }
 }
 
+/// Fill `file_metadata` with the attributes of `file_path`
 #[no_mangle]
 pub extern "C" fn stat(
     sdk: *mut datenlord_sdk,
     file_path: *const c_char,
-    file_metadata: *mut datenlord_file_stat
+    file_metadata: *mut datenlord_stat
 ) -> *mut datenlord_error {
-    if sdk.is_null() || file_path.is_null() {
-        return datenlord_error::new(1, "Invalid arguments".to_string());
-    }
-    if file_metadata.is_null() {
-        return datenlord_error::new(1, "Invalid arguments".to_string());
-    }
+    stat_at(sdk, ffi::os_str_arg(file_path), file_metadata)
+}
 
-    let path = unsafe { CStr::from_ptr(file_path).to_str().unwrap_or_default() };
-    let sdk_ref = unsafe { &*sdk };
-    let file_metadata: &mut datenlord_file_stat = unsafe { &mut *file_metadata };
+/// Like `stat` with the `file_path.len` bytes of `file_path` as path, which
+/// need not be UTF-8 nor NUL-terminated
+#[no_mangle]
+pub extern "C" fn datenlord_stat_bytes(
+    sdk: *mut datenlord_sdk,
+    file_path: datenlord_bytes,
+    file_metadata: *mut datenlord_stat,
+) -> *mut datenlord_error {
+    stat_at(sdk, ffi::os_str_bytes_arg(file_path.data, file_path.len), file_metadata)
+}
 
-    let rt = Runtime::new().unwrap();
-    let result = rt.block_on(async {
-        let localfs = sdk_ref.localfs.lock().unwrap();
-        localfs.getattr(1).await  // 示例 inode
+/// Fill `file_metadata` with the attributes of `path`, see `stat`
+fn stat_at(
+    sdk: *mut datenlord_sdk,
+    path: Option<&OsStr>,
+    file_metadata: *mut datenlord_stat,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path)) = (ffi::as_ref(sdk), path) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let Some(file_metadata) = ffi::as_mut(file_metadata) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        localfs.lookup(&sdk_ref.ctx(), ROOT_ID, path).await
     });
 
     match result {
-        Ok(attr) => {
-            println!("File duration: {:?}, attr: {:?}", attr.0, attr.1);
-            // Convert to file metadata
-            file_metadata.size = attr.1.size;
-            file_metadata.uid = attr.1.uid;
-            file_metadata.gid = attr.1.gid;
-            file_metadata.nlink = attr.1.nlink;
-            file_metadata.rdev = attr.1.rdev;
-
+        Ok((_, attr, _)) => {
+            *file_metadata = datenlord_stat::from(&attr);
             std::ptr::null_mut()
         }
-        Err(_) => datenlord_error::new(1, "Failed to get file metadata".to_string()),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to get file metadata".to_string()),
     }
 }
 
+/// Fill `file_metadata[i]` with the attributes of `file_paths[i]` for the
+/// `count` paths at once, setting `codes[i]` to 0, or to the error code
+/// when the path cannot be stat'ed
+///
+/// Paths are looked up concurrently and each distinct parent directory is
+/// resolved once, which suits build tools checking many files. Only null or
+/// invalid arguments fail the whole call.
 #[no_mangle]
-pub extern "C" fn write_file(
+pub extern "C" fn datenlord_stat_many(
     sdk: *mut datenlord_sdk,
-    file_path: *const c_char,
-    content: datenlord_bytes,
+    file_paths: *const *const c_char,
+    count: usize,
+    file_metadata: *mut datenlord_stat,
+    codes: *mut c_uint,
 ) -> *mut datenlord_error {
-    if sdk.is_null() || file_path.is_null() {
+    let Some(sdk_ref) = ffi::as_ref(sdk) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    if count > 0 && (file_paths.is_null() || file_metadata.is_null() || codes.is_null()) {
         return datenlord_error::new(1, "Invalid arguments".to_string());
     }
+    let Some(paths) = (0..count)
+        .map(|index| ffi::os_str_arg(ffi::read_at(file_paths, index)))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+
+    let results = sdk_ref.handle.block_on(walk::stat_many(
+        Arc::clone(&sdk_ref.localfs),
+        sdk_ref.ctx(),
+        &paths,
+        DEFAULT_WALK_CONCURRENCY,
+    ));
+    for (index, result) in results.iter().enumerate() {
+        match *result {
+            Ok(ref attr) => {
+                ffi::write_at(file_metadata, index, datenlord_stat::from(attr));
+                ffi::write_at(codes, index, 0);
+            }
+            Err(ref e) => ffi::write_at(codes, index, error_code(e)),
+        }
+    }
+    std::ptr::null_mut()
+}
 
-    let path = unsafe { CStr::from_ptr(file_path).to_str().unwrap_or_default() };
-    let data = unsafe { std::slice::from_raw_parts(content.data, content.len) };
+/// The space taken by a file or directory, filled by `datenlord_disk_usage`
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_disk_usage {
+    /// The sizes of the files and symbolic links, hard links counted once
+    pub bytes: u64,
+    /// The bytes of the blocks allocated to them
+    pub allocated_bytes: u64,
+    /// The files and symbolic links, hard links counted once
+    pub files: u64,
+    /// The directories, the one counted excluded
+    pub dirs: u64,
+}
 
-    println!("Writing file: {} data size: {} data {}", path, data.len(), String::from_utf8_lossy(data));
+impl From<DiskUsage> for datenlord_disk_usage {
+    fn from(usage: DiskUsage) -> Self {
+        Self {
+            bytes: usage.bytes,
+            allocated_bytes: usage.allocated_bytes,
+            files: usage.files,
+            dirs: usage.dirs,
+        }
+    }
+}
 
-    let sdk_ref = unsafe { &*sdk };
+/// Fill `usage` with the space taken by `path`, everything below it when a
+/// directory, like `du`
+///
+/// Directories are listed concurrently; the first failing to list fails
+/// the call.
+#[no_mangle]
+pub extern "C" fn datenlord_disk_usage(
+    sdk: *mut datenlord_sdk,
+    path: *const c_char,
+    usage: *mut datenlord_disk_usage,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(usage)) =
+        (ffi::as_ref(sdk), ffi::os_str_arg(path), ffi::as_mut(usage))
+    else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(walk::disk_usage(
+        Arc::clone(&sdk_ref.localfs),
+        sdk_ref.ctx(),
+        path,
+        DEFAULT_WALK_CONCURRENCY,
+    ));
+    match result {
+        Ok(counted) => {
+            *usage = counted.into();
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to count the usage of {path:?}: {e}")),
+    }
+}
 
-    let rt = Runtime::new().unwrap();
-    let result = rt.block_on(async {
-        let localfs = sdk_ref.localfs.lock().unwrap();
-        // demo params
-        localfs.write(34734588, 0, 0, data, 0).await
+/// Set the access and modification times of `file_path` with nanosecond precision
+///
+/// Like `utimensat`, a time whose `nsec` is `DATENLORD_UTIME_NOW` is set to
+/// the current time and one whose `nsec` is `DATENLORD_UTIME_OMIT` is left
+/// unchanged.
+#[no_mangle]
+pub extern "C" fn datenlord_utimens(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    atime: datenlord_timespec,
+    mtime: datenlord_timespec,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(atime), Some(mtime)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(file_path),
+        atime.to_utime(),
+        mtime.to_utime(),
+    ) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        let (_, attr, _) = localfs.lookup(&sdk_ref.ctx(), ROOT_ID, path).await?;
+        localfs.utimens(&sdk_ref.ctx(), attr.ino, atime, mtime).await
     });
 
     match result {
         Ok(_) => std::ptr::null_mut(),
-        Err(_) => datenlord_error::new(1, "Failed to write file".to_string()),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to set file times".to_string()),
     }
 }
 
+/// Tag `file_path` with `key`=`value`, replacing the former value
+///
+/// Keys are non-empty and contain neither '=' nor ','. Tags are kept with
+/// the file by `copy_to_local_file` and `copy_from_local_file`.
 #[no_mangle]
-pub extern "C" fn read_file(
+pub extern "C" fn datenlord_set_tag(
     sdk: *mut datenlord_sdk,
     file_path: *const c_char,
-    out_content: *mut datenlord_bytes,
+    key: *const c_char,
+    value: *const c_char,
 ) -> *mut datenlord_error {
-    if sdk.is_null() || file_path.is_null() || out_content.is_null() {
+    let (Some(sdk_ref), Some(path), Some(key), Some(value)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(file_path),
+        ffi::str_arg(key),
+        ffi::str_arg(value),
+    ) else {
         return datenlord_error::new(1, "Invalid arguments".to_string());
-    }
-
-    let path = unsafe { CStr::from_ptr(file_path).to_str().unwrap_or_default() };
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        let (_, attr, _) = localfs.lookup(&sdk_ref.ctx(), ROOT_ID, path).await?;
+        tags::set_tag(localfs.as_ref(), &sdk_ref.ctx(), attr.ino, key, value).await
+    });
 
-    let sdk_ref = unsafe { &*sdk };
+    match result {
+        Ok(()) => std::ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to set tag".to_string()),
+    }
+}
 
-    let rt = Runtime::new().unwrap();
+/// Remove the tag `key` from `file_path`
+#[no_mangle]
+pub extern "C" fn datenlord_remove_tag(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    key: *const c_char,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(key)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(file_path),
+        ffi::str_arg(key),
+    ) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        let (_, attr, _) = localfs.lookup(&sdk_ref.ctx(), ROOT_ID, path).await?;
+        tags::remove_tag(localfs.as_ref(), &sdk_ref.ctx(), attr.ino, key).await
+    });
+
+    match result {
+        Ok(()) => std::ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to remove tag".to_string()),
+    }
+}
+
+/// Open `file_path` read-only ahead of time, so `read_file` of it skips the
+/// lookup and open
+///
+/// The handle is reopened whenever entries are removed or renamed. Files
+/// listed in the `warm_files` config field are opened by `init`.
+#[no_mangle]
+pub extern "C" fn datenlord_warm_file(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path)) = (ffi::as_ref(sdk), ffi::os_str_arg(file_path)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(sdk::cache(&sdk_ref.localfs).warm(&sdk_ref.ctx(), path));
+
+    match result {
+        Ok(_) => std::ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to warm file".to_string()),
+    }
+}
+
+/// Run the control command `cmd` on `file_path` with the `input.len`
+/// bytes at `input.data` as argument, copying its output into the
+/// `out_output.len` bytes at `out_output.data` and setting `out_output.len`
+/// to its size
+///
+/// Commands below 0x1000 are built in, 1 listing the locations holding the
+/// data of the file one per line; the others run the handler registered in
+/// the process, failing with `ENOTSUP` without one. An output larger than
+/// the buffer fails with `ERANGE`, `out_output.len` telling the size needed.
+#[no_mangle]
+pub extern "C" fn datenlord_ioctl(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    cmd: u32,
+    input: datenlord_bytes,
+    out_output: *mut datenlord_bytes,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(input), Some(out_output)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(file_path),
+        CBytes::new(input.data, input.len),
+        ffi::as_mut(out_output),
+    ) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Some(mut buffer) = CBytes::new(out_output.data, out_output.len) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        let ctx = sdk_ref.ctx();
+        let (_, attr, _) = localfs.lookup(&ctx, ROOT_ID, path).await?;
+        localfs.ioctl(&ctx, attr.ino, cmd, input.as_slice()).await
+    });
+
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => return datenlord_error::new(error_code(&e), format!("Failed to run ioctl {cmd:#x}: {e}")),
+    };
+    out_output.len = output.len();
+    let Some(target) = buffer.as_mut_slice().get_mut(..output.len()) else {
+        return datenlord_error::new(Errno::ERANGE as c_uint, "Output larger than the buffer".to_string());
+    };
+    target.copy_from_slice(&output);
+    ptr::null_mut()
+}
+
+/// Change the config values in the JSON object `update` while running,
+/// keeping the open files: `attr_cache_capacity`, `op_timeout_ms` (0
+/// removes the timeout), `retry` and `log_level`
+///
+/// Other fields fail with `EINVAL`, as they only take effect when the SDK
+/// is opened, and so does an unknown log level; nothing changes then.
+#[no_mangle]
+pub extern "C" fn datenlord_update_config(
+    sdk: *mut datenlord_sdk,
+    update: *const c_char,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(update)) = (ffi::as_ref(sdk), ffi::str_arg(update)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = ConfigUpdate::parse(update).and_then(|update| sdk::update_config(&sdk_ref.localfs, &update));
+
+    match result {
+        Ok(()) => ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to update the config: {e}")),
+    }
+}
+
+/// Copy a JSON object with the calls made through `sdk`, the bytes they
+/// moved, how the cache did and the handles and queues held now into the
+/// `out_json.len` bytes at `out_json.data`, NUL terminated, and set
+/// `out_json.len` to its size with the NUL
+///
+/// A buffer too small fails with `ERANGE`, `out_json.len` telling the size
+/// needed.
+#[no_mangle]
+pub extern "C" fn datenlord_get_stats(
+    sdk: *mut datenlord_sdk,
+    out_json: *mut datenlord_bytes,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(out_json)) = (ffi::as_ref(sdk), ffi::as_mut(out_json)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Some(mut buffer) = CBytes::new(out_json.data, out_json.len) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let mut json = match serde_json::to_vec(&sdk::stats(&sdk_ref.localfs)) {
+        Ok(json) => json,
+        Err(e) => return datenlord_error::new(Errno::EIO as c_uint, format!("Failed to encode the stats: {e}")),
+    };
+    json.push(0);
+    out_json.len = json.len();
+    let Some(target) = buffer.as_mut_slice().get_mut(..json.len()) else {
+        return datenlord_error::new(Errno::ERANGE as c_uint, "Stats larger than the buffer".to_string());
+    };
+    target.copy_from_slice(&json);
+    ptr::null_mut()
+}
+
+/// Probe `sdk` end to end, reading the attributes of the root, writing a few
+/// bytes to a scratch file under it and reading them back, and fill
+/// `out_health` with the outcome and the latency of each step, e.g. for the
+/// liveness and readiness probes of a service
+///
+/// The error of the step failing is returned, with `out_health` filled in
+/// as far as the probe got.
+#[no_mangle]
+pub extern "C" fn datenlord_healthcheck(
+    sdk: *mut datenlord_sdk,
+    out_health: *mut datenlord_health,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(out_health)) = (ffi::as_ref(sdk), ffi::as_mut(out_health)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    *out_health = datenlord_health::default();
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let (report, error) = sdk_ref.handle.block_on(sdk::healthcheck(&sdk_ref.localfs, &sdk_ref.ctx()));
+
+    *out_health = datenlord_health::from(&report);
+    match error {
+        None => ptr::null_mut(),
+        Some(e) => datenlord_error::new(error_code(&e), format!("Health check failed: {e}")),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn write_file(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    content: datenlord_bytes,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path)) = (ffi::as_ref(sdk), ffi::os_str_arg(file_path)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let Some(data) = CBytes::new(content.data, content.len) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let data = data.as_slice();
+
+    println!("Writing file: {:?} data size: {} data {}", path, data.len(), String::from_utf8_lossy(data));
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        let (_, attr, _) = localfs.lookup(&sdk_ref.ctx(), ROOT_ID, path).await?;
+        let fh = localfs.open(&sdk_ref.ctx(), attr.ino, OFlag::O_WRONLY.bits() as u32).await?;
+        let result = localfs.write(&sdk_ref.ctx(), attr.ino, fh, 0, data, 0).await;
+        localfs.release(&sdk_ref.ctx(), attr.ino, fh, 0, 0, true).await?;
+        result
+    });
+
+    match result {
+        Ok(_) => std::ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to write file".to_string()),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn read_file(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    out_content: *mut datenlord_bytes,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(out_content)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(file_path),
+        ffi::as_mut(out_content),
+    ) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let Some(mut out_buffer) = CBytes::new(out_content.data, out_content.len) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
     // TODO, use outside buffer
-    let result = rt.block_on(async {
-        let localfs = sdk_ref.localfs.lock().unwrap();
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+
+        let buffer = out_buffer.as_mut_slice();
 
-        // Convert buffer to c buffer
-        let out_content_data = unsafe { (*out_content).data as *mut u8 };
-        let out_content_len = unsafe { (*out_content).len };
-        let buffer: &mut [u8] = unsafe { std::slice::from_raw_parts_mut(out_content_data, out_content_len) };
+        let cache = sdk::cache(localfs);
+        if let Some(handle) = cache.take_warm(&sdk_ref.ctx(), path).await {
+            let size = buffer.len() as u32;
+            let result = localfs.read(&sdk_ref.ctx(), handle.ino, handle.fh, 0, size, buffer).await;
+            cache.put_warm(handle).await;
+            return result;
+        }
 
-        localfs.read(34734588, 0, 0, buffer.len() as u32, buffer).await
+        let (_, attr, _) = localfs.lookup(&sdk_ref.ctx(), ROOT_ID, path).await?;
+        let fh = localfs.open(&sdk_ref.ctx(), attr.ino, OFlag::O_RDONLY.bits() as u32).await?;
+        let result = localfs.read(&sdk_ref.ctx(), attr.ino, fh, 0, buffer.len() as u32, buffer).await;
+        localfs.release(&sdk_ref.ctx(), attr.ino, fh, 0, 0, true).await?;
+        result
     });
 
     match result {
         Ok(size) => {
-            unsafe {
-                (*out_content).len = size;
-            }
+            out_content.len = size;
             std::ptr::null_mut()
         }
-        Err(_) => datenlord_error::new(1, "Failed to read file".to_string()),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to read file".to_string()),
+    }
+}
+
+/// Replace the contents of `file_path` with `content`, creating the file if
+/// missing, so readers see either the former contents or `content`, never a
+/// part of it
+///
+/// `content` goes to a temporary file next to `file_path`, synced and
+/// renamed over it, then the parent directory is synced. An existing file
+/// keeps its permissions.
+#[no_mangle]
+pub extern "C" fn datenlord_write_file_atomic(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    content: datenlord_bytes,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(data)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(file_path),
+        CBytes::new(content.data, content.len),
+    ) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let written = sdk_ref.handle.block_on(upload::write_file_atomic(
+        sdk_ref.localfs.as_ref(),
+        &sdk_ref.ctx(),
+        path,
+        data.as_slice(),
+    ));
+    match written {
+        Ok(_) => ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to write file atomically: {e}")),
+    }
+}
+
+/// The digest is a CRC-32C
+pub const DATENLORD_DIGEST_CRC32C: c_uint = 1;
+/// The digest is a SHA-256
+pub const DATENLORD_DIGEST_SHA256: c_uint = 2;
+/// The digest is a BLAKE3
+pub const DATENLORD_DIGEST_BLAKE3: c_uint = 3;
+/// The size of `datenlord_digest::value`, terminating NUL included
+pub const DATENLORD_DIGEST_SIZE: usize = 72;
+
+/// A digest of file contents, a NUL-terminated `<algorithm>:<hex>` string
+/// such as `crc32c:e3069283`
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_digest {
+    pub value: [c_char; DATENLORD_DIGEST_SIZE],
+}
+
+/// The algorithm of the `DATENLORD_DIGEST_*` constant `algorithm`
+fn digest_algorithm(algorithm: c_uint) -> Option<DigestAlgorithm> {
+    match algorithm {
+        DATENLORD_DIGEST_CRC32C => Some(DigestAlgorithm::Crc32c),
+        DATENLORD_DIGEST_SHA256 => Some(DigestAlgorithm::Sha256),
+        DATENLORD_DIGEST_BLAKE3 => Some(DigestAlgorithm::Blake3),
+        _ => None,
+    }
+}
+
+/// Like `write_file`, also writing to `digest` the digest of `content` with
+/// `algorithm`, one of the `DATENLORD_DIGEST_*` constants
+///
+/// The digest is computed on the bytes the SDK received, so comparing it
+/// with one computed by the caller catches buffers mangled on the way.
+#[no_mangle]
+pub extern "C" fn datenlord_write_file_digest(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    content: datenlord_bytes,
+    algorithm: c_uint,
+    digest: *mut datenlord_digest,
+) -> *mut datenlord_error {
+    let Some(algorithm) = digest_algorithm(algorithm) else {
+        return datenlord_error::new(Errno::EINVAL as c_uint, "Unknown digest algorithm".to_string());
+    };
+    let (Some(data), Some(digest)) = (CBytes::new(content.data, content.len), ffi::as_mut(digest)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let value = algorithm.digest(data.as_slice());
+    let error = write_file(sdk, file_path, content);
+    if error.is_null() {
+        fill_c_string(&mut digest.value, &value);
+    }
+    error
+}
+
+/// Write to `digest` the digest with `algorithm`, one of the
+/// `DATENLORD_DIGEST_*` constants, of the contents of the file `file_path`
+///
+/// The file is streamed rather than loaded in memory. With `cache`, the
+/// digest is kept in an extended attribute of the file until it is written
+/// to, so asking again is cheap.
+#[no_mangle]
+pub extern "C" fn datenlord_file_digest(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    algorithm: c_uint,
+    cache: bool,
+    digest: *mut datenlord_digest,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(digest)) =
+        (ffi::as_ref(sdk), ffi::os_str_arg(file_path), ffi::as_mut(digest))
+    else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Some(algorithm) = digest_algorithm(algorithm) else {
+        return datenlord_error::new(Errno::EINVAL as c_uint, "Unknown digest algorithm".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let localfs = &sdk_ref.localfs;
+    let result = sdk_ref.handle.block_on(async {
+        let ctx = sdk_ref.ctx();
+        let (_, attr, _) = localfs.lookup(&ctx, ROOT_ID, path).await?;
+        digest::file_digest(localfs.as_ref(), &ctx, attr.ino, algorithm, cache).await
+    });
+    match result {
+        Ok(value) => {
+            fill_c_string(&mut digest.value, &value);
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to digest {path:?}: {e}")),
+    }
+}
+
+/// Like `read_file`, failing with `EBADMSG` if the bytes read do not have
+/// the digest `expected`, as written by `datenlord_write_file_digest`
+///
+/// Fails with `EINVAL` if `expected` is not a digest. `out_content` must be
+/// large enough for the whole file for its digest to match.
+#[no_mangle]
+pub extern "C" fn datenlord_read_file_verify(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    out_content: *mut datenlord_bytes,
+    expected: *const c_char,
+) -> *mut datenlord_error {
+    let Some(expected) = ffi::str_arg(expected) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    if let Err(e) = digest::algorithm_of(expected) {
+        return datenlord_error::new(error_code(&e), format!("Invalid digest: {e}"));
+    }
+    let error = read_file(sdk, file_path, out_content);
+    if !error.is_null() {
+        return error;
+    }
+    let Some(out_content) = ffi::as_ref(out_content) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Some(data) = CBytes::new(out_content.data, out_content.len) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    match digest::verify(data.as_slice(), expected) {
+        Ok(()) => std::ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to read file: {e}")),
+    }
+}
+
+/// A file being read by `datenlord_read_stream_open`, not `repr(C)` so C
+/// only sees a forward declaration
+#[allow(non_camel_case_types)]
+pub struct datenlord_read_stream {
+    /// The chunks left
+    chunks: Pin<Box<dyn Stream<Item = DatenLordResult<Bytes>> + Send>>,
+    /// The chunk last returned by `datenlord_read_stream_next_chunk`
+    current: Bytes,
+    /// Handle of the SDK runtime
+    handle: Handle,
+    /// Admits calls until the SDK shuts down
+    calls: Arc<CallGate>,
+}
+
+/// Start reading at most `len` bytes of `file_path` from `offset`, as
+/// chunks of at most `chunk_size` bytes pulled with
+/// `datenlord_read_stream_next_chunk`, the block size of the backend when 0
+///
+/// A `len` of `UINT64_MAX` reads up to the end of the file. Only the chunk
+/// last pulled is held in memory. The stream must be freed with
+/// `datenlord_read_stream_close`.
+#[no_mangle]
+pub extern "C" fn datenlord_read_stream_open(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    offset: u64,
+    len: u64,
+    chunk_size: usize,
+    stream: *mut *mut datenlord_read_stream,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(stream)) =
+        (ffi::as_ref(sdk), ffi::os_str_arg(file_path), ffi::as_mut(stream))
+    else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let chunk_size = match chunk_size {
+        0 => sdk_ref.localfs.block_layout().block_len(),
+        chunk_size => chunk_size,
+    };
+    let ctx = sdk_ref.ctx();
+    match sdk_ref.handle.block_on(sdk_ref.localfs.lookup(&ctx, ROOT_ID, path)) {
+        Ok((_, attr, _)) => {
+            let chunks = stream::read_stream(Arc::clone(&sdk_ref.localfs), ctx, attr.ino, offset, len, chunk_size);
+            *stream = ffi::into_raw(datenlord_read_stream {
+                chunks: Box::pin(chunks),
+                current: Bytes::new(),
+                handle: sdk_ref.handle.clone(),
+                calls: Arc::clone(&sdk_ref.calls),
+            });
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to open file: {e}")),
+    }
+}
+
+/// Point `chunk` at the next chunk of `stream`, blocking until it is read
+///
+/// The chunk is valid until the next call on the stream. At the end of the
+/// file `chunk->data` is set to null and `chunk->len` to 0. Must not be
+/// called from a completion callback.
+#[no_mangle]
+pub extern "C" fn datenlord_read_stream_next_chunk(
+    stream: *mut datenlord_read_stream,
+    chunk: *mut datenlord_bytes,
+) -> *mut datenlord_error {
+    let (Some(stream), Some(chunk)) = (ffi::as_mut(stream), ffi::as_mut(chunk)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = stream.calls.enter() else {
+        return shut_down();
+    };
+
+    match stream.handle.block_on(stream.chunks.next()) {
+        Some(Ok(next)) => {
+            stream.current = next;
+            *chunk = datenlord_bytes {
+                data: stream.current.as_ptr(),
+                len: stream.current.len(),
+            };
+            ptr::null_mut()
+        }
+        Some(Err(e)) => datenlord_error::new(error_code(&e), format!("Failed to read file: {e}")),
+        None => {
+            stream.current = Bytes::new();
+            *chunk = datenlord_bytes {
+                data: ptr::null(),
+                len: 0,
+            };
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Free `stream`, null is ignored
+#[no_mangle]
+pub extern "C" fn datenlord_read_stream_close(stream: *mut datenlord_read_stream) {
+    drop(ffi::from_raw(stream));
+}
+
+/// Borrow a buffer of at least `size` bytes from the SDK buffer pool
+///
+/// The returned buffer can be handed to `read_file` and `write_file` through
+/// `datenlord_bytes` and must be returned with `datenlord_buffer_release`.
+#[no_mangle]
+pub extern "C" fn datenlord_buffer_acquire(sdk: *mut datenlord_sdk, size: usize) -> datenlord_buffer {
+    let Some(sdk_ref) = ffi::as_ref(sdk) else {
+        return datenlord_buffer {
+            data: ptr::null_mut(),
+            len: 0,
+            handle: ptr::null_mut(),
+        };
+    };
+    let mut buffer = sdk_ref.buffer_pool.acquire(size);
+
+    datenlord_buffer {
+        data: buffer.as_mut_ptr(),
+        len: buffer.len(),
+        handle: ffi::into_raw(buffer).cast(),
+    }
+}
+
+/// Return a buffer acquired by `datenlord_buffer_acquire` to the pool
+///
+/// The buffer may be released after the SDK handle itself has been freed.
+#[no_mangle]
+pub extern "C" fn datenlord_buffer_release(buffer: datenlord_buffer) {
+    drop(ffi::from_raw(buffer.handle.cast::<PooledBuffer>()));
+}
+
+/// Flush every open file and all buffered state of the SDK, like `syncfs`
+///
+/// Returns only when everything written so far is durable, which makes it
+/// suitable for quiescing before taking a snapshot.
+#[no_mangle]
+pub extern "C" fn datenlord_sync_all(sdk: *mut datenlord_sdk) -> *mut datenlord_error {
+    let Some(sdk_ref) = ffi::as_ref(sdk) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        localfs.sync_all(&sdk_ref.ctx()).await
+    });
+
+    match result {
+        Ok(_) => std::ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to sync filesystem".to_string()),
+    }
+}
+
+/// Iterator over the entries of a walk or glob, not `repr(C)` so C only sees a forward declaration
+#[allow(non_camel_case_types)]
+pub struct datenlord_walk {
+    /// The running walk
+    walk: Walk,
+    /// The path of the entry last returned by `datenlord_walk_next`
+    current: CString,
+    /// Counts the walk as running until it is closed, as it lists
+    /// directories on the SDK runtime
+    _call: Call,
+}
+
+/// An entry returned by `datenlord_walk_next`
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_walk_entry {
+    /// Path relative to the SDK root, valid until the next call on the walk,
+    /// null once the walk is over
+    pub path: *const c_char,
+    /// Attributes of the entry
+    pub stat: datenlord_stat,
+}
+
+/// Start walking every entry below the directory `dir_path`, null on invalid arguments
+///
+/// Directories are listed concurrently in the background and entries come
+/// in no particular order. Symbolic links are returned but not followed.
+#[no_mangle]
+pub extern "C" fn datenlord_walk_open(sdk: *mut datenlord_sdk, dir_path: *const c_char) -> *mut datenlord_walk {
+    let (Some(sdk_ref), Some(path)) = (ffi::as_ref(sdk), ffi::os_str_arg(dir_path)) else {
+        return ptr::null_mut();
+    };
+    let Ok(call) = sdk_ref.calls.enter() else {
+        return ptr::null_mut();
+    };
+    let walk = walk::walk(
+        Arc::clone(&sdk_ref.localfs),
+        sdk_ref.ctx(),
+        &sdk_ref.handle,
+        path,
+        DEFAULT_WALK_CONCURRENCY,
+    );
+    ffi::into_raw(datenlord_walk {
+        walk,
+        current: CString::default(),
+        _call: call,
+    })
+}
+
+/// Start walking the entries matching `pattern`, null on invalid arguments
+///
+/// `?` matches one character and `*` any characters within a path
+/// component, `**` matches any number of whole components. Entries are
+/// returned like for `datenlord_walk_open`.
+#[no_mangle]
+pub extern "C" fn datenlord_glob_open(sdk: *mut datenlord_sdk, pattern: *const c_char) -> *mut datenlord_walk {
+    let (Some(sdk_ref), Some(pattern)) = (ffi::as_ref(sdk), ffi::str_arg(pattern)) else {
+        return ptr::null_mut();
+    };
+    let Ok(call) = sdk_ref.calls.enter() else {
+        return ptr::null_mut();
+    };
+    let walk = walk::glob(
+        Arc::clone(&sdk_ref.localfs),
+        sdk_ref.ctx(),
+        &sdk_ref.handle,
+        pattern,
+        DEFAULT_WALK_CONCURRENCY,
+    );
+    ffi::into_raw(datenlord_walk {
+        walk,
+        current: CString::default(),
+        _call: call,
+    })
+}
+
+/// Fill `entry` with the next entry of `walk`, blocking until one is found
+///
+/// At the end of the walk `entry->path` is set to null. A directory that
+/// fails to list returns an error and the walk can carry on. Must not be
+/// called from a completion callback.
+#[no_mangle]
+pub extern "C" fn datenlord_walk_next(
+    walk: *mut datenlord_walk,
+    entry: *mut datenlord_walk_entry,
+) -> *mut datenlord_error {
+    let (Some(walk), Some(entry)) = (ffi::as_mut(walk), ffi::as_mut(entry)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+
+    match walk.walk.blocking_next() {
+        Some(Ok(found)) => {
+            // Paths come from the filesystem and never hold a nul byte
+            walk.current = CString::new(found.path.into_vec()).unwrap_or_default();
+            *entry = datenlord_walk_entry {
+                path: walk.current.as_ptr(),
+                stat: datenlord_stat::from(&found.attr),
+            };
+            ptr::null_mut()
+        }
+        Some(Err(e)) => {
+            datenlord_error::new(error_code(&e), format!("Failed to list directory: {e}"))
+        }
+        None => {
+            entry.path = ptr::null();
+            ptr::null_mut()
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Stop `walk` and free it, null is ignored
+#[no_mangle]
+pub extern "C" fn datenlord_walk_close(walk: *mut datenlord_walk) {
+    drop(ffi::from_raw(walk));
+}
+
+/// The kind of a change reported to a watch
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub enum datenlord_event_kind {
+    /// A file, device, fifo or socket was created
+    DATENLORD_EVENT_CREATE,
+    /// A directory was created
+    DATENLORD_EVENT_MKDIR,
+    /// A symbolic link was created
+    DATENLORD_EVENT_SYMLINK,
+    /// An entry was removed
+    DATENLORD_EVENT_DELETE,
+    /// An entry was moved, or swapped with another
+    DATENLORD_EVENT_RENAME,
+    /// Attributes or tags were changed, including truncation
+    DATENLORD_EVENT_ATTRIB,
+    /// A file handle that was written to was released
+    DATENLORD_EVENT_CLOSE_WRITE,
+}
+
+impl From<EventKind> for datenlord_event_kind {
+    fn from(kind: EventKind) -> Self {
+        match kind {
+            EventKind::Create => Self::DATENLORD_EVENT_CREATE,
+            EventKind::Mkdir => Self::DATENLORD_EVENT_MKDIR,
+            EventKind::Symlink => Self::DATENLORD_EVENT_SYMLINK,
+            EventKind::Delete => Self::DATENLORD_EVENT_DELETE,
+            EventKind::Rename => Self::DATENLORD_EVENT_RENAME,
+            EventKind::Attrib => Self::DATENLORD_EVENT_ATTRIB,
+            EventKind::CloseWrite => Self::DATENLORD_EVENT_CLOSE_WRITE,
+        }
+    }
+}
+
+/// A change passed to a watch callback, valid during the callback only
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_event {
+    /// What happened
+    pub kind: datenlord_event_kind,
+    /// Path of the entry relative to the SDK root, null when unknown
+    pub path: *const c_char,
+    /// Path the entry moved to, null unless renamed
+    pub new_path: *const c_char,
+    /// Inode number of the entry
+    pub ino: INum,
+    /// Type of the entry
+    pub file_kind: datenlord_file_kind,
+    /// Size of the entry after the change
+    pub size: u64,
+    /// When the change was observed
+    pub timestamp: datenlord_timespec,
+}
+
+/// Callback invoked with every change under a watched path
+///
+/// The callback runs on an SDK worker thread, one change at a time in
+/// order, and must not block.
+#[allow(non_camel_case_types)]
+pub type datenlord_watch_cb = Option<extern "C" fn(event: *const datenlord_event, user_data: *mut c_void)>;
+
+/// A watch registered by `datenlord_watch_open`, not `repr(C)` so C only sees a forward declaration
+#[allow(non_camel_case_types)]
+pub struct datenlord_watch {
+    /// The task passing the changes to the callback
+    task: JoinHandle<()>,
+}
+
+impl Drop for datenlord_watch {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Watch the changes to `path` and its children, or everything below it if
+/// `recursive`, calling `callback` with each of them and `user_data`
+///
+/// Fails when `path` does not exist. Changes are delivered until the watch
+/// is closed with `datenlord_watch_close` or the SDK shuts down; those past a
+/// full queue are dropped.
+#[no_mangle]
+pub extern "C" fn datenlord_watch_open(
+    sdk: *mut datenlord_sdk,
+    path: *const c_char,
+    recursive: bool,
+    callback: datenlord_watch_cb,
+    user_data: *mut c_void,
+    watch: *mut *mut datenlord_watch,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(callback), Some(watch)) =
+        (ffi::as_ref(sdk), ffi::os_str_arg(path), callback, ffi::as_mut(watch))
+    else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let watched = sdk_ref.handle.block_on(sdk::notify(&sdk_ref.localfs).watch(
+        &sdk_ref.ctx(),
+        Path::new(path),
+        recursive,
+    ));
+    let mut events = match watched {
+        Ok(events) => events,
+        Err(e) => return datenlord_error::new(error_code(&e), format!("Failed to watch: {e}")),
+    };
+    // Kept as an address so it can cross threads
+    let user_data = user_data as usize;
+    let task = sdk_ref.handle.spawn(async move {
+        while let Some(event) = events.next().await {
+            // Paths come from the filesystem and never hold a nul byte
+            let path = event.path.map(|path| CString::new(path).unwrap_or_default());
+            let new_path = event.new_path.map(|path| CString::new(path).unwrap_or_default());
+            let c_event = datenlord_event {
+                kind: event.kind.into(),
+                path: path.as_ref().map_or(ptr::null(), |path| path.as_ptr()),
+                new_path: new_path.as_ref().map_or(ptr::null(), |path| path.as_ptr()),
+                ino: event.ino,
+                file_kind: event.file_kind.into(),
+                size: event.size,
+                timestamp: datenlord_timespec {
+                    sec: i64::try_from(event.timestamp_ns.div_euclid(1_000_000_000)).unwrap_or(i64::MAX),
+                    nsec: event.timestamp_ns.rem_euclid(1_000_000_000) as u32,
+                },
+            };
+            callback(&c_event, user_data as *mut c_void);
+        }
+    });
+    *watch = ffi::into_raw(datenlord_watch { task });
+    ptr::null_mut()
+}
+
+/// Stop `watch` and free it, null is ignored
+///
+/// Unless called from the callback, a change being delivered may still be
+/// in the callback when this returns.
+#[no_mangle]
+pub extern "C" fn datenlord_watch_close(watch: *mut datenlord_watch) {
+    drop(ffi::from_raw(watch));
+}
+
+/// Append `record` to the log at `file_path`, creating it when missing, and
+/// set `offset`, unless null, to the offset it was written at once synced
+///
+/// Appends to the same file through the SDK are atomic and ordered, whichever
+/// thread makes them. Each record is framed with its length and a CRC32
+/// checksum.
+#[no_mangle]
+pub extern "C" fn datenlord_append(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    record: datenlord_bytes,
+    offset: *mut u64,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(record)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(file_path),
+        CBytes::new(record.data, record.len),
+    ) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    match sdk_ref.handle.block_on(sdk_ref.logs.append(path, record.as_slice())) {
+        Ok(appended) => {
+            if let Some(offset) = ffi::as_mut(offset) {
+                *offset = appended;
+            }
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to append to log: {e}")),
+    }
+}
+
+/// Callback invoked with every record of a followed log and its offset
+///
+/// The callback runs on an SDK worker thread, one record at a time in order,
+/// and must not block. `record` is valid for the duration of the call only.
+#[allow(non_camel_case_types)]
+pub type datenlord_tail_cb =
+    Option<extern "C" fn(offset: u64, record: datenlord_bytes, user_data: *mut c_void)>;
+
+/// A log followed by `datenlord_tail_open`, not `repr(C)` so C only sees a
+/// forward declaration
+#[allow(non_camel_case_types)]
+pub struct datenlord_tail {
+    /// The task passing the records to the callback
+    task: JoinHandle<()>,
+}
+
+impl Drop for datenlord_tail {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Follow the log at `file_path` from `from_offset` on, 0 or an offset
+/// `datenlord_append` set, calling `callback` with each record and
+/// `user_data`
+///
+/// The records already written are delivered first, then those appended
+/// through the SDK, until the tail is closed with `datenlord_tail_close` or
+/// the SDK shuts down.
+#[no_mangle]
+pub extern "C" fn datenlord_tail_open(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    from_offset: u64,
+    callback: datenlord_tail_cb,
+    user_data: *mut c_void,
+    tail: *mut *mut datenlord_tail,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(callback), Some(tail)) =
+        (ffi::as_ref(sdk), ffi::os_str_arg(file_path), callback, ffi::as_mut(tail))
+    else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let mut records = match sdk_ref.handle.block_on(sdk_ref.logs.tail(path, from_offset)) {
+        Ok(records) => records,
+        Err(e) => return datenlord_error::new(error_code(&e), format!("Failed to tail log: {e}")),
+    };
+    // Kept as an address so it can cross threads
+    let user_data = user_data as usize;
+    let task = sdk_ref.handle.spawn(async move {
+        while let Ok(Some(record)) = records.next().await {
+            let data = datenlord_bytes {
+                data: record.data.as_ptr(),
+                len: record.data.len(),
+            };
+            callback(record.offset, data, user_data as *mut c_void);
+        }
+    });
+    *tail = ffi::into_raw(datenlord_tail { task });
+    ptr::null_mut()
+}
+
+/// Stop following the log of `tail` and free it, null is ignored
+///
+/// Unless called from the callback, a record being delivered may still be
+/// in the callback when this returns.
+#[no_mangle]
+pub extern "C" fn datenlord_tail_close(tail: *mut datenlord_tail) {
+    drop(ffi::from_raw(tail));
+}
+
+/// A key-value store opened by `datenlord_kv_open`, not `repr(C)` so C only
+/// sees a forward declaration
+#[allow(non_camel_case_types)]
+pub struct datenlord_kv {
+    /// The open store, its segment writers running on the SDK runtime
+    kv: KvStore<SdkFs>,
+    /// Handle of the SDK runtime
+    handle: Handle,
+    /// Admits calls until the SDK shuts down
+    calls: Arc<CallGate>,
+}
+
+/// Callback invoked with every entry found by `datenlord_kv_scan`
+///
+/// `key` and `value` are valid for the duration of the call only.
+#[allow(non_camel_case_types)]
+pub type datenlord_kv_scan_cb =
+    Option<extern "C" fn(key: *const c_char, value: datenlord_bytes, user_data: *mut c_void)>;
+
+/// Open the key-value store in the directory `dir_path`, creating it when
+/// missing
+///
+/// Small values are packed into segment files rather than stored as a file
+/// each. The store must be closed with `datenlord_kv_close` before the SDK
+/// shuts down to sync its last writes.
+#[no_mangle]
+pub extern "C" fn datenlord_kv_open(
+    sdk: *mut datenlord_sdk,
+    dir_path: *const c_char,
+    kv: *mut *mut datenlord_kv,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(kv)) =
+        (ffi::as_ref(sdk), ffi::os_str_arg(dir_path), ffi::as_mut(kv))
+    else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let opened = sdk_ref.handle.block_on(KvStore::open(
+        Arc::clone(&sdk_ref.localfs),
+        sdk_ref.ctx(),
+        Path::new(path),
+        KvOptions::default(),
+    ));
+    match opened {
+        Ok(store) => {
+            *kv = ffi::into_raw(datenlord_kv {
+                kv: store,
+                handle: sdk_ref.handle.clone(),
+                calls: Arc::clone(&sdk_ref.calls),
+            });
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to open key-value store: {e}")),
+    }
+}
+
+/// Set `key` to `value`, replacing its former value
+#[no_mangle]
+pub extern "C" fn datenlord_kv_put(
+    kv: *mut datenlord_kv,
+    key: *const c_char,
+    value: datenlord_bytes,
+) -> *mut datenlord_error {
+    let (Some(kv_ref), Some(key), Some(value)) = (
+        ffi::as_ref(kv),
+        ffi::str_arg(key),
+        CBytes::new(value.data, value.len),
+    ) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = kv_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    match kv_ref.handle.block_on(kv_ref.kv.put(key, value.as_slice())) {
+        Ok(()) => ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to put value: {e}")),
+    }
+}
+
+/// Copy the value of `key` into the `len` bytes at `out_value.data`, setting
+/// `out_value.len` to its size and `found` to whether `key` is there
+///
+/// A value larger than the buffer fails with `ERANGE`, `out_value.len`
+/// telling the size needed.
+#[no_mangle]
+pub extern "C" fn datenlord_kv_get(
+    kv: *mut datenlord_kv,
+    key: *const c_char,
+    out_value: *mut datenlord_bytes,
+    found: *mut bool,
+) -> *mut datenlord_error {
+    let (Some(kv_ref), Some(key), Some(out_value), Some(found)) = (
+        ffi::as_ref(kv),
+        ffi::str_arg(key),
+        ffi::as_mut(out_value),
+        ffi::as_mut(found),
+    ) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Some(mut buffer) = CBytes::new(out_value.data, out_value.len) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = kv_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let value = match kv_ref.handle.block_on(kv_ref.kv.get(key)) {
+        Ok(value) => value,
+        Err(e) => return datenlord_error::new(error_code(&e), format!("Failed to get value: {e}")),
+    };
+    *found = value.is_some();
+    let value = value.unwrap_or_default();
+    out_value.len = value.len();
+    let Some(target) = buffer.as_mut_slice().get_mut(..value.len()) else {
+        return datenlord_error::new(Errno::ERANGE as c_uint, "Value larger than the buffer".to_string());
+    };
+    target.copy_from_slice(&value);
+    ptr::null_mut()
+}
+
+/// Remove `key`, setting `existed`, unless null, to whether it was there
+#[no_mangle]
+pub extern "C" fn datenlord_kv_delete(
+    kv: *mut datenlord_kv,
+    key: *const c_char,
+    existed: *mut bool,
+) -> *mut datenlord_error {
+    let (Some(kv_ref), Some(key)) = (ffi::as_ref(kv), ffi::str_arg(key)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = kv_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    match kv_ref.handle.block_on(kv_ref.kv.delete(key)) {
+        Ok(deleted) => {
+            if let Some(existed) = ffi::as_mut(existed) {
+                *existed = deleted;
+            }
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to delete value: {e}")),
+    }
+}
+
+/// Call `callback` with every key starting with `prefix`, in key order, its
+/// value and `user_data`, on the calling thread
+#[no_mangle]
+pub extern "C" fn datenlord_kv_scan(
+    kv: *mut datenlord_kv,
+    prefix: *const c_char,
+    callback: datenlord_kv_scan_cb,
+    user_data: *mut c_void,
+) -> *mut datenlord_error {
+    let (Some(kv_ref), Some(prefix), Some(callback)) =
+        (ffi::as_ref(kv), ffi::str_arg(prefix), callback)
+    else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = kv_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let entries = match kv_ref.handle.block_on(kv_ref.kv.scan(prefix)) {
+        Ok(entries) => entries,
+        Err(e) => return datenlord_error::new(error_code(&e), format!("Failed to scan values: {e}")),
+    };
+    for (key, value) in entries {
+        // Keys are C strings when put and never hold a nul byte
+        let key = CString::new(key).unwrap_or_default();
+        let value = datenlord_bytes {
+            data: value.as_ptr(),
+            len: value.len(),
+        };
+        callback(key.as_ptr(), value, user_data);
+    }
+    ptr::null_mut()
+}
+
+/// Rewrite the live values into new segments, reclaiming the space of the
+/// overwritten and deleted ones, and set `reclaimed`, unless null, to the
+/// bytes reclaimed
+#[no_mangle]
+pub extern "C" fn datenlord_kv_compact(kv: *mut datenlord_kv, reclaimed: *mut u64) -> *mut datenlord_error {
+    let Some(kv_ref) = ffi::as_ref(kv) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = kv_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    match kv_ref.handle.block_on(kv_ref.kv.compact()) {
+        Ok(bytes) => {
+            if let Some(reclaimed) = ffi::as_mut(reclaimed) {
+                *reclaimed = bytes;
+            }
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to compact values: {e}")),
+    }
+}
+
+/// Sync and close `kv` and free it, null is ignored
+///
+/// Once the SDK shut down the store is only freed, failing with the error of
+/// a shut down SDK.
+#[no_mangle]
+pub extern "C" fn datenlord_kv_close(kv: *mut datenlord_kv) -> *mut datenlord_error {
+    let Some(kv) = ffi::from_raw(kv) else {
+        return ptr::null_mut();
+    };
+    let Ok(_call) = kv.calls.enter() else {
+        return shut_down();
+    };
+    let datenlord_kv { kv, handle, .. } = *kv;
+
+    match handle.block_on(kv.close()) {
+        Ok(()) => ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to close key-value store: {e}")),
+    }
+}
+
+/// The size of `datenlord_upload_id::id`, terminating NUL included
+pub const DATENLORD_UPLOAD_ID_SIZE: usize = 64;
+/// The size of `datenlord_upload_part_info::checksum`, terminating NUL included
+pub const DATENLORD_CHECKSUM_SIZE: usize = 65;
+
+/// The id of a multipart upload, a NUL-terminated string
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_upload_id {
+    pub id: [c_char; DATENLORD_UPLOAD_ID_SIZE],
+}
+
+/// A part of a multipart upload
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_upload_part_info {
+    /// Index of the part, its data following the parts of smaller indexes
+    pub index: u32,
+    /// Size of its data in bytes
+    pub size: u64,
+    /// Lowercase hex SHA-256 of its data, NUL-terminated
+    pub checksum: [c_char; DATENLORD_CHECKSUM_SIZE],
+}
+
+impl From<&UploadPart> for datenlord_upload_part_info {
+    fn from(part: &UploadPart) -> Self {
+        let mut checksum = [0; DATENLORD_CHECKSUM_SIZE];
+        fill_c_string(&mut checksum, &part.checksum);
+        Self {
+            index: part.index,
+            size: part.size,
+            checksum,
+        }
+    }
+}
+
+/// Copy `value` into `out` as a NUL-terminated string, cut to fit
+fn fill_c_string(out: &mut [c_char], value: &str) {
+    let len = value.len().min(out.len().saturating_sub(1));
+    for (slot, &byte) in out.iter_mut().zip(&value.as_bytes()[..len]) {
+        *slot = byte as c_char;
+    }
+    if let Some(end) = out.get_mut(len) {
+        *end = 0;
+    }
+}
+
+/// Start a multipart upload to `file_path`, whose parent directory must
+/// exist, writing its id to `upload_id`
+///
+/// Parts are stored as they are uploaded, so a crashed client resumes with
+/// `datenlord_upload_find` and `datenlord_upload_parts`. The file is
+/// created or replaced by `datenlord_upload_complete`.
+#[no_mangle]
+pub extern "C" fn datenlord_upload_start(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    upload_id: *mut datenlord_upload_id,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(upload_id)) =
+        (ffi::as_ref(sdk), ffi::os_str_arg(file_path), ffi::as_mut(upload_id))
+    else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let started = sdk_ref.handle.block_on(upload::start_upload(
+        sdk_ref.localfs.as_ref(),
+        &sdk_ref.ctx(),
+        Path::new(path),
+    ));
+    match started {
+        Ok(id) => {
+            fill_c_string(&mut upload_id.id, &id);
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to start upload: {e}")),
+    }
+}
+
+/// Write to `upload_id` the id of the upload to `file_path` started last
+/// and still in progress, setting `found` to whether there is one
+#[no_mangle]
+pub extern "C" fn datenlord_upload_find(
+    sdk: *mut datenlord_sdk,
+    file_path: *const c_char,
+    upload_id: *mut datenlord_upload_id,
+    found: *mut bool,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(upload_id), Some(found)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(file_path),
+        ffi::as_mut(upload_id),
+        ffi::as_mut(found),
+    ) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let uploads = sdk_ref
+        .handle
+        .block_on(upload::list_uploads(sdk_ref.localfs.as_ref(), &sdk_ref.ctx()));
+    match uploads {
+        Ok(uploads) => {
+            let last = uploads.iter().rev().find(|upload| upload.path.as_os_str() == path);
+            *found = last.is_some();
+            if let Some(last) = last {
+                fill_c_string(&mut upload_id.id, &last.id);
+            }
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to list uploads: {e}")),
+    }
+}
+
+/// Store `data` as part `index` of upload `upload_id`, replacing the part
+/// uploaded before with that index, and fill `part`, unless null, with its
+/// size and checksum
+///
+/// The same index must not be uploaded twice at once.
+#[no_mangle]
+pub extern "C" fn datenlord_upload_part(
+    sdk: *mut datenlord_sdk,
+    upload_id: *const c_char,
+    index: u32,
+    data: datenlord_bytes,
+    part: *mut datenlord_upload_part_info,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(id), Some(data)) = (
+        ffi::as_ref(sdk),
+        ffi::str_arg(upload_id),
+        CBytes::new(data.data, data.len),
+    ) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let uploaded = sdk_ref.handle.block_on(upload::upload_part(
+        sdk_ref.localfs.as_ref(),
+        &sdk_ref.ctx(),
+        id,
+        index,
+        data.as_slice(),
+    ));
+    match uploaded {
+        Ok(uploaded) => {
+            if let Some(part) = ffi::as_mut(part) {
+                *part = datenlord_upload_part_info::from(&uploaded);
+            }
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to upload part: {e}")),
+    }
+}
+
+/// Fill the `capacity` slots of `parts` with the parts of upload
+/// `upload_id`, by index, setting `count` to their number
+///
+/// More parts than `capacity` fail with `ERANGE`, `count` telling the slots
+/// needed.
+#[no_mangle]
+pub extern "C" fn datenlord_upload_parts(
+    sdk: *mut datenlord_sdk,
+    upload_id: *const c_char,
+    parts: *mut datenlord_upload_part_info,
+    capacity: usize,
+    count: *mut usize,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(id), Some(count)) =
+        (ffi::as_ref(sdk), ffi::str_arg(upload_id), ffi::as_mut(count))
+    else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    if capacity > 0 && parts.is_null() {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    }
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let listed = sdk_ref
+        .handle
+        .block_on(upload::upload_parts(sdk_ref.localfs.as_ref(), &sdk_ref.ctx(), id));
+    let listed = match listed {
+        Ok(listed) => listed,
+        Err(e) => return datenlord_error::new(error_code(&e), format!("Failed to list upload parts: {e}")),
+    };
+    *count = listed.len();
+    if listed.len() > capacity {
+        return datenlord_error::new(Errno::ERANGE as c_uint, "More parts than the capacity".to_string());
+    }
+    for (index, part) in listed.iter().enumerate() {
+        ffi::write_at(parts, index, datenlord_upload_part_info::from(part));
+    }
+    ptr::null_mut()
+}
+
+/// Concatenate the parts of upload `upload_id`, indexed from 0 with no gap,
+/// into its file, checking their checksums, and fill `file_metadata`,
+/// unless null, with the attributes of the file
+///
+/// A part no longer matching its checksum fails the call, leaving the
+/// upload in progress to upload the part again.
+#[no_mangle]
+pub extern "C" fn datenlord_upload_complete(
+    sdk: *mut datenlord_sdk,
+    upload_id: *const c_char,
+    file_metadata: *mut datenlord_stat,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(id)) = (ffi::as_ref(sdk), ffi::str_arg(upload_id)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    let completed = sdk_ref
+        .handle
+        .block_on(upload::complete_upload(sdk_ref.localfs.as_ref(), &sdk_ref.ctx(), id));
+    match completed {
+        Ok(attr) => {
+            if let Some(file_metadata) = ffi::as_mut(file_metadata) {
+                *file_metadata = datenlord_stat::from(&attr);
+            }
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to complete upload: {e}")),
+    }
+}
+
+/// Give up upload `upload_id`, removing its parts
+#[no_mangle]
+pub extern "C" fn datenlord_upload_abort(
+    sdk: *mut datenlord_sdk,
+    upload_id: *const c_char,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(id)) = (ffi::as_ref(sdk), ffi::str_arg(upload_id)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+
+    match sdk_ref
+        .handle
+        .block_on(upload::abort_upload(sdk_ref.localfs.as_ref(), &sdk_ref.ctx(), id))
+    {
+        Ok(()) => ptr::null_mut(),
+        Err(e) => datenlord_error::new(error_code(&e), format!("Failed to abort upload: {e}")),
+    }
+}
+
+/// A directory listing, not `repr(C)` so C only sees a forward declaration
+#[allow(non_camel_case_types)]
+pub struct datenlord_dir {
+    /// The entries not returned yet
+    entries: std::vec::IntoIter<DirEntry>,
+    /// The name of the entry last returned by `datenlord_readdir`
+    current_name: CString,
+    /// The attributes of the entry last returned by `datenlord_readdir`
+    current_stat: Option<datenlord_stat>,
+}
+
+/// An entry returned by `datenlord_readdir`
+///
+/// The pointers are valid until the next call on the listing.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct datenlord_dir_entry {
+    /// Name of the entry within the directory
+    pub name: *const c_char,
+    /// Inode number
+    pub ino: INum,
+    /// File type
+    pub kind: datenlord_file_kind,
+    /// Attributes of the entry, null unless the listing was opened with `plus`
+    pub stat: *const datenlord_stat,
+    /// Length of `name` in bytes, which need not be UTF-8
+    pub name_len: usize,
+}
+
+/// List the directory `dir_path` into `*dir`, with the attributes of every
+/// entry if `plus`
+///
+/// The whole directory is read up front, so later changes are not seen.
+/// The listing must be freed with `datenlord_closedir`.
+#[no_mangle]
+pub extern "C" fn datenlord_opendir(
+    sdk: *mut datenlord_sdk,
+    dir_path: *const c_char,
+    plus: bool,
+    dir: *mut *mut datenlord_dir,
+) -> *mut datenlord_error {
+    opendir_at(sdk, ffi::os_str_arg(dir_path), plus, dir)
+}
+
+/// Like `datenlord_opendir` with the `dir_path.len` bytes of `dir_path` as
+/// path, which need not be UTF-8 nor NUL-terminated
+#[no_mangle]
+pub extern "C" fn datenlord_opendir_bytes(
+    sdk: *mut datenlord_sdk,
+    dir_path: datenlord_bytes,
+    plus: bool,
+    dir: *mut *mut datenlord_dir,
+) -> *mut datenlord_error {
+    opendir_at(sdk, ffi::os_str_bytes_arg(dir_path.data, dir_path.len), plus, dir)
+}
+
+/// List the directory `path` into `*dir`, see `datenlord_opendir`
+fn opendir_at(
+    sdk: *mut datenlord_sdk,
+    path: Option<&OsStr>,
+    plus: bool,
+    dir: *mut *mut datenlord_dir,
+) -> *mut datenlord_error {
+    let (Some(sdk_ref), Some(path), Some(dir)) = (ffi::as_ref(sdk), path, ffi::as_mut(dir)) else {
+        return datenlord_error::new(1, "Invalid arguments".to_string());
+    };
+    let Ok(_call) = sdk_ref.calls.enter() else {
+        return shut_down();
+    };
+    let result = sdk_ref.handle.block_on(async {
+        let localfs = &sdk_ref.localfs;
+        let ctx = sdk_ref.ctx();
+        let (_, attr, _) = localfs.lookup(&ctx, ROOT_ID, path).await?;
+        // Pages of one open directory do not shift as entries change
+        let fh = localfs.opendir(&ctx, attr.ino, 0).await?;
+        let mut entries = Vec::new();
+        let listed = loop {
+            let offset = i64::try_from(entries.len()).unwrap_or(i64::MAX);
+            let page = if plus {
+                localfs
+                    .readdirplus(&ctx, attr.ino, fh, offset)
+                    .await
+                    .map(|detailed| detailed.into_iter().map(|(entry, _, _)| entry).collect())
+            } else {
+                localfs.readdir(&ctx, attr.ino, fh, offset).await
+            };
+            match page {
+                Ok(page) if page.is_empty() => break Ok(()),
+                Ok(page) => entries.extend(page),
+                Err(e) => break Err(e),
+            }
+        };
+        localfs.releasedir(&ctx, attr.ino, fh, 0).await?;
+        listed.map(|()| entries)
+    });
+
+    match result {
+        Ok(entries) => {
+            *dir = ffi::into_raw(datenlord_dir {
+                entries: entries.into_iter(),
+                current_name: CString::default(),
+                current_stat: None,
+            });
+            ptr::null_mut()
+        }
+        Err(e) => datenlord_error::new(error_code(&e), "Failed to read directory".to_string()),
+    }
+}
+
+/// Fill `entry` with the next entry of `dir`, false at the end or on invalid arguments
+#[no_mangle]
+pub extern "C" fn datenlord_readdir(dir: *mut datenlord_dir, entry: *mut datenlord_dir_entry) -> bool {
+    let (Some(dir), Some(entry)) = (ffi::as_mut(dir), ffi::as_mut(entry)) else {
+        return false;
+    };
+    let Some(next) = dir.entries.next() else {
+        return false;
+    };
+    // Names come from the filesystem and never hold a nul byte
+    dir.current_name = CString::new(next.name.into_vec()).unwrap_or_default();
+    dir.current_stat = next.attr.as_ref().map(datenlord_stat::from);
+    *entry = datenlord_dir_entry {
+        name: dir.current_name.as_ptr(),
+        ino: next.ino,
+        kind: Some(next.kind).into(),
+        stat: dir.current_stat.as_ref().map_or(ptr::null(), ptr::from_ref),
+        name_len: dir.current_name.as_bytes().len(),
+    };
+    true
+}
+
+/// Free a listing opened by `datenlord_opendir`, null is ignored
+#[no_mangle]
+pub extern "C" fn datenlord_closedir(dir: *mut datenlord_dir) {
+    drop(ffi::from_raw(dir));
+}
+
+/// Whether an asynchronous request reads or writes
+#[derive(Clone, Copy)]
+enum IoKind {
+    /// Read into the request buffer
+    Read,
+    /// Write from the request buffer
+    Write,
+}
+
+impl IoKind {
+    /// The name of the operation used in error messages
+    fn name(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+        }
+    }
+}
+
+/// Run a positional read or write of the whole of `buf` on `path`
+async fn positional_io<F: VirtualFs>(
+    localfs: Arc<F>,
+    ctx: RequestContext,
+    kind: IoKind,
+    path: OsString,
+    offset: u64,
+    mut buf: CBytes,
+) -> DatenLordResult<usize> {
+    let flags = match kind {
+        IoKind::Read => OFlag::O_RDONLY,
+        IoKind::Write => OFlag::O_WRONLY,
+    };
+    let (_, attr, _) = localfs.lookup(&ctx, ROOT_ID, &path).await?;
+    let fh = localfs.open(&ctx, attr.ino, flags.bits() as u32).await?;
+    let result = match kind {
+        IoKind::Read => {
+            let buffer = buf.as_mut_slice();
+            localfs.read(&ctx, attr.ino, fh, offset, buffer.len() as u32, buffer).await
+        }
+        IoKind::Write => {
+            let buffer = buf.as_slice();
+            localfs
+                .write(&ctx, attr.ino, fh, offset as i64, buffer, 0)
+                .await
+                .map(|()| buffer.len())
+        }
+    };
+    localfs.release(&ctx, attr.ino, fh, 0, 0, true).await?;
+    result
+}
+
+/// Schedule `kind` on the SDK runtime and report it through `callback` or the completion queue
+fn submit_io(
+    sdk: *mut datenlord_sdk,
+    kind: IoKind,
+    req: datenlord_io_request,
+    callback: datenlord_completion_cb,
+    user_data: *mut c_void,
+) -> u64 {
+    let (Some(sdk_ref), Some(path), Some(buf)) = (
+        ffi::as_ref(sdk),
+        ffi::os_str_arg(req.path),
+        CBytes::new(req.buf, req.len),
+    ) else {
+        return 0;
+    };
+    let Ok(call) = sdk_ref.calls.enter() else {
+        return 0;
+    };
+    let path = path.to_owned();
+    let op_id = sdk_ref.next_op_id.fetch_add(1, Ordering::Relaxed);
+    let completions = Arc::clone(&sdk_ref.completions);
+    let pending = Arc::clone(&sdk_ref.pending);
+    // Raw pointers are not `Send`, move the address of `user_data` into the task instead
+    let user_data = user_data as usize;
+    let io = positional_io(Arc::clone(&sdk_ref.localfs), sdk_ref.ctx(), kind, path, req.offset, buf);
+    // Ids come from the counter, so they are never registered already
+    let Ok(io) = sdk_ref.localfs.interruptible(op_id, io) else {
+        return 0;
+    };
+    // Count the deadline from submission so time spent queued is part of it
+    let deadline = (req.timeout_ms > 0).then(|| Instant::now() + Duration::from_millis(req.timeout_ms));
+
+    // Hold the lock while spawning so the task cannot finish before it is registered
+    let mut pending_ops = sdk_ref.pending.lock().unwrap();
+    let task = sdk_ref.handle.spawn(async move {
+        // Running until the completion is reported, see `datenlord_shutdown`
+        let _call = call;
+        let result = match deadline {
+            Some(deadline) => timeout::with_deadline(deadline, io).await,
+            None => io.await,
+        };
+        // A missing entry means `datenlord_cancel` already reported the completion
+        if pending.lock().unwrap().remove(&op_id).is_none() {
+            return;
+        }
+        let completion = Completion {
+            op_id,
+            result: result.map_err(|e| {
+                (error_code(&e), format!("Failed to {} file: {e:?}", kind.name()))
+            }),
+            user_data,
+        };
+        complete(&completions, callback, completion);
+    });
+    pending_ops.insert(
+        op_id,
+        PendingOp {
+            task,
+            callback,
+            user_data,
+        },
+    );
+
+    op_id
+}
+
+/// The error code reporting `e`, 1 unless a more specific errno applies,
+/// see `DatenLordError::errno`
+fn error_code(e: &DatenLordError) -> c_uint {
+    e.errno().map_or(1, |errno| errno as c_uint)
+}
+
+/// The error of a call made after `datenlord_shutdown`
+fn shut_down() -> *mut datenlord_error {
+    datenlord_error::new(Errno::ESHUTDOWN as c_uint, "The SDK is shut down".to_string())
+}
+
+/// Read `req.len` bytes at `req.offset` of `req.path` into `req.buf` without blocking
+///
+/// Returns the operation id, or 0 if the arguments are invalid. On
+/// completion `callback` is invoked, or when it is null the completion is
+/// queued for `datenlord_poll_completions`. Operations still running when
+/// the SDK is freed are cancelled without completing.
+#[no_mangle]
+pub extern "C" fn datenlord_read_async(
+    sdk: *mut datenlord_sdk,
+    req: datenlord_io_request,
+    callback: datenlord_completion_cb,
+    user_data: *mut c_void,
+) -> u64 {
+    submit_io(sdk, IoKind::Read, req, callback, user_data)
+}
+
+/// Write `req.len` bytes from `req.buf` at `req.offset` of `req.path` without blocking
+///
+/// Completion is reported the same way as for `datenlord_read_async`.
+#[no_mangle]
+pub extern "C" fn datenlord_write_async(
+    sdk: *mut datenlord_sdk,
+    req: datenlord_io_request,
+    callback: datenlord_completion_cb,
+    user_data: *mut c_void,
+) -> u64 {
+    submit_io(sdk, IoKind::Write, req, callback, user_data)
+}
+
+/// Move up to `max` finished operations submitted without a callback into `out`
+///
+/// Never blocks, returns the number of completions written. Meant to be
+/// called from an event loop instead of handling callbacks on SDK threads.
+#[no_mangle]
+pub extern "C" fn datenlord_poll_completions(
+    sdk: *mut datenlord_sdk,
+    out: *mut datenlord_completion,
+    max: usize,
+) -> usize {
+    let Some(sdk_ref) = ffi::as_ref(sdk) else {
+        return 0;
+    };
+    if out.is_null() {
+        return 0;
+    }
+
+    let mut completions = sdk_ref.completions.lock().unwrap();
+    let count = completions.len().min(max);
+
+    for (index, completion) in completions.drain(..count).enumerate() {
+        let (error, result) = match completion.result {
+            Ok(size) => (ptr::null_mut(), size),
+            Err((code, message)) => (datenlord_error::new(code, message), 0),
+        };
+        let completion = datenlord_completion {
+            op_id: completion.op_id,
+            error,
+            result,
+            user_data: completion.user_data as *mut c_void,
+        };
+        ffi::write_at(out, index, completion);
+    }
+    count
+}
+
+/// Cancel the asynchronous operation `op_id`
+///
+/// Returns whether the operation was still running. The operation is
+/// interrupted and completes right away, on the calling thread when it has
+/// a callback, with an `EINTR` error and no bytes transferred. Unless called
+/// from a completion callback, this waits for the operation to stop touching
+/// its buffer.
+/// Within a callback the buffer must be kept until the SDK is freed.
+#[no_mangle]
+pub extern "C" fn datenlord_cancel(sdk: *mut datenlord_sdk, op_id: u64) -> bool {
+    let Some(sdk_ref) = ffi::as_ref(sdk) else {
+        return false;
+    };
+
+    let Some(op) = sdk_ref.pending.lock().unwrap().remove(&op_id) else {
+        return false;
+    };
+    sdk_ref.localfs.cancel(op_id);
+    // Completion callbacks run on the runtime, which cannot block on itself
+    if Handle::try_current().is_err() {
+        let _ = sdk_ref.handle.block_on(op.task);
+    }
+    let completion = Completion {
+        op_id,
+        result: Err((Errno::EINTR as c_uint, "Operation interrupted".to_string())),
+        user_data: op.user_data,
+    };
+    complete(&sdk_ref.completions, op.callback, completion);
+    true
+}
@@ -0,0 +1,79 @@
+//! Admission of the calls made through an SDK, so it can shut down once the
+//! running ones finished
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+/// Whether calls are admitted and how many are running
+#[derive(Debug, Default)]
+struct GateState {
+    /// Set once the SDK shuts down
+    closed: bool,
+    /// The calls admitted and not finished yet
+    running: usize,
+}
+
+/// Admits the calls made through an SDK until it is closed, counting the
+/// running ones
+#[derive(Debug, Default)]
+pub(crate) struct CallGate {
+    /// The admission state
+    state: Mutex<GateState>,
+    /// Notified when the last running call finishes
+    idle: Condvar,
+}
+
+/// A call admitted by a `CallGate`, running until dropped
+#[derive(Debug)]
+pub(crate) struct Call {
+    /// The gate counting the call
+    gate: Arc<CallGate>,
+}
+
+impl CallGate {
+    /// Admit a call, failing with `DatenLordError::ShutDown` once closed
+    pub(crate) fn enter(self: &Arc<Self>) -> DatenLordResult<Call> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(DatenLordError::ShutDown {
+                context: vec!["the SDK is shut down".to_owned()],
+            });
+        }
+        state.running += 1;
+        Ok(Call {
+            gate: Arc::clone(self),
+        })
+    }
+
+    /// Stop admitting calls and wait for the running ones to finish, for at
+    /// most `timeout` if given, returning whether they all finished
+    pub(crate) fn close(&self, timeout: Option<Duration>) -> bool {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        while state.running > 0 {
+            state = match deadline {
+                None => self.idle.wait(state).unwrap(),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return false;
+                    }
+                    self.idle.wait_timeout(state, left).unwrap().0
+                }
+            };
+        }
+        true
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        let mut state = self.gate.state.lock().unwrap();
+        state.running -= 1;
+        if state.running == 0 {
+            self.gate.idle.notify_all();
+        }
+    }
+}
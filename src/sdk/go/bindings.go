@@ -0,0 +1,110 @@
+package datenlord
+
+/*
+#cgo CFLAGS: -I${SRCDIR}
+#cgo LDFLAGS: -L${SRCDIR}/../../../target/release -ldatenlord
+#include <stdlib.h>
+#include "datenlord.h"
+
+uint64_t datenlord_go_read(datenlord_sdk *sdk, datenlord_io_request req, uintptr_t done);
+uint64_t datenlord_go_write(datenlord_sdk *sdk, datenlord_io_request req, uintptr_t done);
+*/
+import "C"
+
+import (
+	"runtime"
+	"runtime/cgo"
+	"syscall"
+	"unsafe"
+)
+
+// codeUnknown is the error code of failures without an errno
+const codeUnknown = 1
+
+// Error is a failure reported by the SDK
+type Error struct {
+	// Code is the errno of the failure, 1 when it has none
+	Code syscall.Errno
+	// Message describes the failure
+	Message string
+}
+
+func (e *Error) Error() string {
+	if e.Code == codeUnknown {
+		return e.Message
+	}
+	return e.Message + ": " + e.Code.Error()
+}
+
+// Unwrap returns the errno, so errors.Is matches e.g. fs.ErrExist
+func (e *Error) Unwrap() error {
+	if e.Code == codeUnknown {
+		return nil
+	}
+	return e.Code
+}
+
+// takeError converts and frees err, nil stays nil
+func takeError(err *C.datenlord_error) error {
+	if err == nil {
+		return nil
+	}
+	defer C.datenlord_error_free(err)
+	message := C.GoBytes(unsafe.Pointer(err.message.data), C.int(err.message.len))
+	return &Error{Code: syscall.Errno(err.code), Message: string(message)}
+}
+
+// cBytes borrows the bytes of s for the duration of a call
+func cBytes(s string) C.datenlord_bytes {
+	return C.datenlord_bytes{
+		data: (*C.uint8_t)(unsafe.Pointer(unsafe.StringData(s))),
+		len:  C.uintptr_t(len(s)),
+	}
+}
+
+// completion is the outcome of an asynchronous operation
+type completion struct {
+	n   int
+	err error
+}
+
+//export datenlordGoComplete
+func datenlordGoComplete(_ C.uint64_t, err *C.datenlord_error, result C.uintptr_t, done C.uintptr_t) {
+	// The channel is buffered, so the SDK thread never blocks here
+	cgo.Handle(done).Value().(chan completion) <- completion{n: int(result), err: takeError(err)}
+}
+
+// positionalIO reads into or writes p at off of name through the
+// asynchronous API, waiting for the operation to complete
+func positionalIO(sdk *C.datenlord_sdk, write bool, name string, p []byte, off int64) (int, error) {
+	if len(p) == 0 {
+		return 0, nil
+	}
+	path := C.CString(name)
+	defer C.free(unsafe.Pointer(path))
+	// The SDK keeps using the buffer after the call returns
+	var pinner runtime.Pinner
+	pinner.Pin(&p[0])
+	defer pinner.Unpin()
+	done := make(chan completion, 1)
+	handle := cgo.NewHandle(done)
+	defer handle.Delete()
+
+	req := C.datenlord_io_request{
+		path:   path,
+		offset: C.uint64_t(off),
+		buf:    (*C.uint8_t)(unsafe.Pointer(&p[0])),
+		len:    C.uintptr_t(len(p)),
+	}
+	var id C.uint64_t
+	if write {
+		id = C.datenlord_go_write(sdk, req, C.uintptr_t(handle))
+	} else {
+		id = C.datenlord_go_read(sdk, req, C.uintptr_t(handle))
+	}
+	if id == 0 {
+		return 0, &Error{Code: syscall.EINVAL, Message: "Invalid arguments or closed SDK"}
+	}
+	result := <-done
+	return result.n, result.err
+}
@@ -0,0 +1,16 @@
+// Completion callbacks of the asynchronous API, handed over to Go
+#include "_cgo_export.h"
+
+// `user_data` carries the `cgo.Handle` of the channel waiting for the operation
+static void datenlord_go_complete(uint64_t op_id, datenlord_error *error, uintptr_t result,
+                                  void *user_data) {
+    datenlordGoComplete(op_id, error, result, (uintptr_t)user_data);
+}
+
+uint64_t datenlord_go_read(datenlord_sdk *sdk, datenlord_io_request req, uintptr_t done) {
+    return datenlord_read_async(sdk, req, datenlord_go_complete, (void *)done);
+}
+
+uint64_t datenlord_go_write(datenlord_sdk *sdk, datenlord_io_request req, uintptr_t done) {
+    return datenlord_write_async(sdk, req, datenlord_go_complete, (void *)done);
+}
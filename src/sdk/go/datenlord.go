@@ -0,0 +1,368 @@
+// Package datenlord is the Go SDK of datenlord, wrapping the C ABI of
+// libdatenlord
+//
+// Build the library with `cargo build --release` first, the package links
+// `target/release/libdatenlord.so`, which must be found at run time, e.g.
+// through LD_LIBRARY_PATH.
+package datenlord
+
+// #include <stdlib.h>
+// #include "datenlord.h"
+import "C"
+
+import (
+	"io"
+	"io/fs"
+	"path"
+	"sort"
+	"syscall"
+	"time"
+	"unsafe"
+)
+
+// FS is a datenlord namespace, addressing files by their path relative to
+// its root
+//
+// It implements fs.FS, fs.StatFS, fs.ReadDirFS and fs.ReadFileFS, and is
+// safe for concurrent use.
+type FS struct {
+	sdk *C.datenlord_sdk
+}
+
+// Open opens the namespace the JSON config describes, e.g. `{"root": "/data"}`
+func Open(config string) (*FS, error) {
+	cConfig := C.CString(config)
+	defer C.free(unsafe.Pointer(cConfig))
+	sdk := C.init(cConfig)
+	if sdk == nil {
+		return nil, &Error{Code: syscall.EINVAL, Message: "Failed to open the namespace"}
+	}
+	return &FS{sdk: sdk}, nil
+}
+
+// Close waits for the running calls, syncs the written data and frees the SDK
+//
+// No call may be made once it returned.
+func (f *FS) Close() error {
+	err := takeError(C.datenlord_shutdown(f.sdk, 0))
+	C.free_sdk(f.sdk)
+	f.sdk = nil
+	return err
+}
+
+// sdkPath maps a path of fs.FS to a path of the SDK, where the root is ""
+func sdkPath(name string) string {
+	if name == "." {
+		return ""
+	}
+	return name
+}
+
+// Exists reports whether name exists
+func (f *FS) Exists(name string) bool {
+	return bool(C.datenlord_exists_bytes(f.sdk, cBytes(sdkPath(name))))
+}
+
+// Stat returns the attributes of name, without following a final symbolic link
+func (f *FS) Stat(name string) (fs.FileInfo, error) {
+	if !fs.ValidPath(name) {
+		return nil, &fs.PathError{Op: "stat", Path: name, Err: fs.ErrInvalid}
+	}
+	var stat C.datenlord_stat
+	if err := takeError(C.datenlord_stat_bytes(f.sdk, cBytes(sdkPath(name)), &stat)); err != nil {
+		return nil, f.pathError("stat", name, err)
+	}
+	return newFileInfo(path.Base(name), &stat), nil
+}
+
+// pathError wraps err of op on name, failures without an errno of paths
+// that do not exist becoming fs.ErrNotExist
+func (f *FS) pathError(op, name string, err error) error {
+	if e, ok := err.(*Error); ok && e.Code == codeUnknown && !f.Exists(name) {
+		err = fs.ErrNotExist
+	}
+	return &fs.PathError{Op: op, Path: name, Err: err}
+}
+
+// MkdirAll creates the directory name with perm, less the umask, together
+// with its missing parents, like os.MkdirAll
+func (f *FS) MkdirAll(name string, perm fs.FileMode) error {
+	cName := C.CString(name)
+	defer C.free(unsafe.Pointer(cName))
+	if err := takeError(C.datenlord_mkdir_all(f.sdk, cName, C.uint(perm.Perm()))); err != nil {
+		return &fs.PathError{Op: "mkdir", Path: name, Err: err}
+	}
+	return nil
+}
+
+// RemoveDir removes the empty directory name
+func (f *FS) RemoveDir(name string) error {
+	cName := C.CString(name)
+	defer C.free(unsafe.Pointer(cName))
+	if err := takeError(C.deldir(f.sdk, cName, false)); err != nil {
+		return f.pathError("remove", name, err)
+	}
+	return nil
+}
+
+// Rename renames oldName to newName, replacing an existing file like os.Rename
+func (f *FS) Rename(oldName, newName string) error {
+	cOld := C.CString(oldName)
+	defer C.free(unsafe.Pointer(cOld))
+	cNew := C.CString(newName)
+	defer C.free(unsafe.Pointer(cNew))
+	if err := takeError(C.rename_path(f.sdk, cOld, cNew, 0)); err != nil {
+		return &fs.PathError{Op: "rename", Path: oldName, Err: err}
+	}
+	return nil
+}
+
+// Sync makes everything written so far durable, like syncfs
+func (f *FS) Sync() error {
+	return takeError(C.datenlord_sync_all(f.sdk))
+}
+
+// Create creates the empty regular file name and returns it, failing with
+// fs.ErrExist if it exists
+func (f *FS) Create(name string) (*File, error) {
+	if err := takeError(C.datenlord_create_file_bytes(f.sdk, cBytes(name), false)); err != nil {
+		return nil, &fs.PathError{Op: "create", Path: name, Err: err}
+	}
+	return f.File(name), nil
+}
+
+// File returns the file name to read and write at offsets, which is only
+// looked up once used
+func (f *FS) File(name string) *File {
+	return &File{fs: f, name: name}
+}
+
+// Open opens name for reading, implementing fs.FS
+func (f *FS) Open(name string) (fs.File, error) {
+	info, err := f.Stat(name)
+	if err != nil {
+		return nil, err
+	}
+	if !info.IsDir() {
+		return &File{fs: f, name: name}, nil
+	}
+	entries, err := f.ReadDir(name)
+	if err != nil {
+		return nil, err
+	}
+	return &dir{info: info, entries: entries}, nil
+}
+
+// ReadFile returns the content of the file name, implementing fs.ReadFileFS
+func (f *FS) ReadFile(name string) ([]byte, error) {
+	info, err := f.Stat(name)
+	if err != nil {
+		return nil, err
+	}
+	if info.IsDir() {
+		return nil, &fs.PathError{Op: "read", Path: name, Err: syscall.EISDIR}
+	}
+	content := make([]byte, info.Size())
+	n, err := f.File(name).ReadAt(content, 0)
+	if err == io.EOF {
+		err = nil
+	}
+	return content[:n], err
+}
+
+// ReadDir returns the entries of the directory name sorted by name,
+// implementing fs.ReadDirFS
+func (f *FS) ReadDir(name string) ([]fs.DirEntry, error) {
+	if !fs.ValidPath(name) {
+		return nil, &fs.PathError{Op: "readdir", Path: name, Err: fs.ErrInvalid}
+	}
+	var listing *C.datenlord_dir
+	err := takeError(C.datenlord_opendir_bytes(f.sdk, cBytes(sdkPath(name)), true, &listing))
+	if err != nil {
+		return nil, f.pathError("readdir", name, err)
+	}
+	defer C.datenlord_closedir(listing)
+
+	var entries []fs.DirEntry
+	var entry C.datenlord_dir_entry
+	for C.datenlord_readdir(listing, &entry) {
+		entryName := C.GoStringN(entry.name, C.int(entry.name_len))
+		if entryName == "." || entryName == ".." || entry.stat == nil {
+			continue
+		}
+		entries = append(entries, fs.FileInfoToDirEntry(newFileInfo(entryName, entry.stat)))
+	}
+	sort.Slice(entries, func(i, j int) bool { return entries[i].Name() < entries[j].Name() })
+	return entries, nil
+}
+
+// File is a file of an FS, read and written at offsets
+//
+// It holds no open handle, every call looks the file up by its path. Read
+// advances an offset of its own, so a File must not be read concurrently.
+type File struct {
+	fs     *FS
+	name   string
+	offset int64
+}
+
+// ReadAt reads len(p) bytes at off, implementing io.ReaderAt
+func (f *File) ReadAt(p []byte, off int64) (int, error) {
+	if off < 0 {
+		return 0, &fs.PathError{Op: "read", Path: f.name, Err: fs.ErrInvalid}
+	}
+	n, err := positionalIO(f.fs.sdk, false, f.name, p, off)
+	if err != nil {
+		return n, f.fs.pathError("read", f.name, err)
+	}
+	if n < len(p) {
+		return n, io.EOF
+	}
+	return n, nil
+}
+
+// WriteAt writes p at off, implementing io.WriterAt
+func (f *File) WriteAt(p []byte, off int64) (int, error) {
+	if off < 0 {
+		return 0, &fs.PathError{Op: "write", Path: f.name, Err: fs.ErrInvalid}
+	}
+	n, err := positionalIO(f.fs.sdk, true, f.name, p, off)
+	if err != nil {
+		return n, f.fs.pathError("write", f.name, err)
+	}
+	return n, nil
+}
+
+// Read reads from the offset of f and advances it, implementing io.Reader
+func (f *File) Read(p []byte) (int, error) {
+	n, err := f.ReadAt(p, f.offset)
+	f.offset += int64(n)
+	if err == io.EOF && n > 0 {
+		err = nil
+	}
+	return n, err
+}
+
+// Stat returns the attributes of f
+func (f *File) Stat() (fs.FileInfo, error) {
+	return f.fs.Stat(f.name)
+}
+
+// Close does nothing as f holds no open handle, implementing fs.File
+func (f *File) Close() error {
+	return nil
+}
+
+// dir is a directory opened through fs.FS, listed when opened
+type dir struct {
+	info    fs.FileInfo
+	entries []fs.DirEntry
+}
+
+func (d *dir) Stat() (fs.FileInfo, error) {
+	return d.info, nil
+}
+
+func (d *dir) Read([]byte) (int, error) {
+	return 0, &fs.PathError{Op: "read", Path: d.info.Name(), Err: syscall.EISDIR}
+}
+
+func (d *dir) Close() error {
+	return nil
+}
+
+// ReadDir returns the next n entries, or all that are left when n <= 0,
+// implementing fs.ReadDirFile
+func (d *dir) ReadDir(n int) ([]fs.DirEntry, error) {
+	if n <= 0 {
+		entries := d.entries
+		d.entries = nil
+		return entries, nil
+	}
+	if len(d.entries) == 0 {
+		return nil, io.EOF
+	}
+	n = min(n, len(d.entries))
+	entries := d.entries[:n]
+	d.entries = d.entries[n:]
+	return entries, nil
+}
+
+// Attr holds the attributes of a file fs.FileInfo leaves out, returned by
+// its Sys method
+type Attr struct {
+	Ino    uint64
+	Nlink  uint32
+	Uid    uint32
+	Gid    uint32
+	Blocks uint64
+	Atime  time.Time
+	Ctime  time.Time
+}
+
+// fileInfo implements fs.FileInfo
+type fileInfo struct {
+	name    string
+	size    int64
+	mode    fs.FileMode
+	modTime time.Time
+	attr    Attr
+}
+
+func newFileInfo(name string, stat *C.datenlord_stat) *fileInfo {
+	return &fileInfo{
+		name:    name,
+		size:    int64(stat.size),
+		mode:    fileMode(uint32(stat.mode)),
+		modTime: timeOf(stat.mtime),
+		attr: Attr{
+			Ino:    uint64(stat.ino),
+			Nlink:  uint32(stat.nlink),
+			Uid:    uint32(stat.uid),
+			Gid:    uint32(stat.gid),
+			Blocks: uint64(stat.blocks),
+			Atime:  timeOf(stat.atime),
+			Ctime:  timeOf(stat.ctime),
+		},
+	}
+}
+
+func (i *fileInfo) Name() string       { return i.name }
+func (i *fileInfo) Size() int64        { return i.size }
+func (i *fileInfo) Mode() fs.FileMode  { return i.mode }
+func (i *fileInfo) ModTime() time.Time { return i.modTime }
+func (i *fileInfo) IsDir() bool        { return i.mode.IsDir() }
+func (i *fileInfo) Sys() any           { return &i.attr }
+
+func timeOf(ts C.datenlord_timespec) time.Time {
+	return time.Unix(int64(ts.sec), int64(ts.nsec))
+}
+
+// fileMode converts an `st_mode` like os.Stat does
+func fileMode(mode uint32) fs.FileMode {
+	m := fs.FileMode(mode & 0o777)
+	switch mode & syscall.S_IFMT {
+	case syscall.S_IFDIR:
+		m |= fs.ModeDir
+	case syscall.S_IFLNK:
+		m |= fs.ModeSymlink
+	case syscall.S_IFIFO:
+		m |= fs.ModeNamedPipe
+	case syscall.S_IFSOCK:
+		m |= fs.ModeSocket
+	case syscall.S_IFCHR:
+		m |= fs.ModeDevice | fs.ModeCharDevice
+	case syscall.S_IFBLK:
+		m |= fs.ModeDevice
+	}
+	if mode&syscall.S_ISUID != 0 {
+		m |= fs.ModeSetuid
+	}
+	if mode&syscall.S_ISGID != 0 {
+		m |= fs.ModeSetgid
+	}
+	if mode&syscall.S_ISVTX != 0 {
+		m |= fs.ModeSticky
+	}
+	return m
+}
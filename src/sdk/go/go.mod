@@ -0,0 +1,3 @@
+module github.com/datenlord/datenlord/sdk/go
+
+go 1.21
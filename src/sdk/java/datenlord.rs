@@ -0,0 +1,405 @@
+use jni::objects::{JByteArray, JClass, JObjectArray, JString};
+use jni::sys::{jboolean, jint, jlong, jlongArray, jobjectArray, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use std::ffi::OsString;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::UNIX_EPOCH;
+use tokio::runtime::Runtime;
+
+use crate::common::config::DatenLordConfig;
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::ffi;
+use crate::storage::fs_util::{CreateParam, RenameParam, RequestContext, ROOT_ID};
+use crate::storage::localfs::LocalFS;
+use crate::storage::virtualfs::VirtualFs;
+
+/// The native state behind a `io.datenlord.DatenlordFS` handle
+struct JavaSdk {
+    localfs: LocalFS,
+    /// The caller every operation runs on behalf of, see `ctx()`
+    ctx: RequestContext,
+    /// The umask of the caller, changed by `setUmask`
+    umask: AtomicU32,
+    runtime: Runtime,
+}
+
+impl JavaSdk {
+    /// The context of an operation starting now
+    fn ctx(&self) -> RequestContext {
+        RequestContext {
+            umask: self.umask.load(Ordering::Relaxed),
+            ..self.ctx
+        }
+    }
+}
+
+/// Get the Java exception class matching a `DatenLordError`
+fn exception_class(err: &DatenLordError) -> &'static str {
+    match *err {
+        DatenLordError::Unimplemented { .. } => "java/lang/UnsupportedOperationException",
+        DatenLordError::InvalidArgument { .. } | DatenLordError::InvalidName { .. } => {
+            "java/lang/IllegalArgumentException"
+        }
+        DatenLordError::Io { .. }
+        | DatenLordError::Unavailable { .. }
+        | DatenLordError::NoSpace { .. }
+        | DatenLordError::Corrupted { .. } => "java/io/IOException",
+        DatenLordError::NotFound { .. } => "java/nio/file/NoSuchFileException",
+        DatenLordError::AlreadyExists { .. } => "java/nio/file/FileAlreadyExistsException",
+        DatenLordError::PermissionDenied { .. } => "java/nio/file/AccessDeniedException",
+        DatenLordError::Timeout { .. } | DatenLordError::Interrupted { .. } => {
+            "java/io/InterruptedIOException"
+        }
+        DatenLordError::ShutDown { .. } => "java/lang/IllegalStateException",
+        DatenLordError::Internal { .. } | DatenLordError::Other { .. } => {
+            "java/lang/RuntimeException"
+        }
+    }
+}
+
+/// Unwrap a result, throwing the matching Java exception and returning
+/// `default` on error
+fn unwrap_or_throw<T>(env: &mut JNIEnv, result: DatenLordResult<T>, default: T) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            // A failed throw leaves a pending JVM error, nothing more to do
+            let _ = env.throw_new(exception_class(&err), err.to_string());
+            default
+        }
+    }
+}
+
+/// Convert a Java string argument
+fn get_string(env: &mut JNIEnv, value: &JString) -> DatenLordResult<String> {
+    env.get_string(value)
+        .map(String::from)
+        .map_err(|e| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid java string: {e}")],
+        })
+}
+
+/// Convert a Java path argument
+fn get_path(env: &mut JNIEnv, value: &JString) -> DatenLordResult<OsString> {
+    get_string(env, value).map(OsString::from)
+}
+
+/// Map a JNI error into a `DatenLordError`
+fn jni_error(e: jni::errors::Error) -> DatenLordError {
+    DatenLordError::Internal {
+        context: vec![format!("jni call failed: {e}")],
+    }
+}
+
+/// Get the native state of a handle created by `init`
+fn sdk_ref<'a>(handle: jlong) -> DatenLordResult<&'a JavaSdk> {
+    ffi::as_ref(handle as *const JavaSdk).ok_or_else(|| DatenLordError::InvalidArgument {
+        context: vec!["DatenlordFS is already closed".to_owned()],
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_init(
+    mut env: JNIEnv,
+    _class: JClass,
+    config: JString,
+) -> jlong {
+    let result = (|| {
+        let config = if config.is_null() {
+            DatenLordConfig::default()
+        } else {
+            DatenLordConfig::parse(&get_string(&mut env, &config)?)
+        };
+        let runtime = Runtime::new().map_err(|e| DatenLordError::Internal {
+            context: vec![format!("failed to create runtime: {e}")],
+        })?;
+        let localfs = LocalFS::new(&config)?;
+        let ctx = config.request_context();
+        Ok(ffi::into_raw(JavaSdk {
+            localfs,
+            ctx,
+            umask: AtomicU32::new(ctx.umask),
+            runtime,
+        }) as jlong)
+    })();
+    unwrap_or_throw(&mut env, result, 0)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_free(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    drop(ffi::from_raw(handle as *mut JavaSdk));
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_setUmask(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    umask: jint,
+) -> jint {
+    let result = sdk_ref(handle).map(|sdk| {
+        let umask = umask.cast_unsigned() & 0o777;
+        sdk.umask.swap(umask, Ordering::Relaxed).cast_signed()
+    });
+    unwrap_or_throw(&mut env, result, 0)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_exists(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    path: JString,
+) -> jboolean {
+    let result = (|| {
+        let sdk = sdk_ref(handle)?;
+        let path = get_path(&mut env, &path)?;
+        Ok(sdk
+            .runtime
+            .block_on(sdk.localfs.lookup(&sdk.ctx(), ROOT_ID, &path))
+            .is_ok())
+    })();
+    if unwrap_or_throw(&mut env, result, false) {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_create(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    path: JString,
+    directory: jboolean,
+) {
+    let result = (|| {
+        let sdk = sdk_ref(handle)?;
+        let param = CreateParam {
+            parent: ROOT_ID,
+            name: get_path(&mut env, &path)?,
+            mode: if directory == JNI_TRUE { 0o777 } else { 0o666 },
+            rdev: 0,
+            node_type: if directory == JNI_TRUE {
+                SFlag::S_IFDIR
+            } else {
+                SFlag::S_IFREG
+            },
+            link: None,
+        };
+        sdk.runtime.block_on(async {
+            if directory == JNI_TRUE {
+                sdk.localfs.mkdir(&sdk.ctx(), param).await
+            } else {
+                sdk.localfs.mknod(&sdk.ctx(), param).await
+            }
+        })?;
+        Ok(())
+    })();
+    unwrap_or_throw(&mut env, result, ());
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_mkdirs(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    path: JString,
+) {
+    let result = (|| {
+        let sdk = sdk_ref(handle)?;
+        let path = get_path(&mut env, &path)?;
+        sdk.runtime
+            .block_on(sdk.localfs.mkdir_all(&sdk.ctx(), ROOT_ID, &path, 0o777))?;
+        Ok(())
+    })();
+    unwrap_or_throw(&mut env, result, ());
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_delete(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    path: JString,
+) {
+    let result = (|| {
+        let sdk = sdk_ref(handle)?;
+        let path = get_path(&mut env, &path)?;
+        sdk.runtime.block_on(async {
+            let (_, attr, _) = sdk.localfs.lookup(&sdk.ctx(), ROOT_ID, &path).await?;
+            if attr.kind == SFlag::S_IFDIR {
+                sdk.localfs.rmdir(&sdk.ctx(), ROOT_ID, &path).await.map(|_| ())
+            } else {
+                sdk.localfs.unlink(&sdk.ctx(), ROOT_ID, &path).await
+            }
+        })
+    })();
+    unwrap_or_throw(&mut env, result, ());
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_rename(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    src_path: JString,
+    dest_path: JString,
+) {
+    let result = (|| {
+        let sdk = sdk_ref(handle)?;
+        let param = RenameParam {
+            old_parent: ROOT_ID,
+            old_name: get_path(&mut env, &src_path)?,
+            new_parent: ROOT_ID,
+            new_name: get_path(&mut env, &dest_path)?,
+            flags: 0,
+        };
+        sdk.runtime
+            .block_on(sdk.localfs.rename(&sdk.ctx(), param))
+    })();
+    unwrap_or_throw(&mut env, result, ());
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_read(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    path: JString,
+    offset: jlong,
+    buffer: JByteArray,
+) -> jint {
+    let result = (|| {
+        let sdk = sdk_ref(handle)?;
+        let path = get_path(&mut env, &path)?;
+        let offset = u64::try_from(offset).map_err(|_| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid read offset={offset}")],
+        })?;
+        let len = env.get_array_length(&buffer).map_err(jni_error)?;
+        let mut buf = vec![0; len as usize];
+
+        let size = sdk.runtime.block_on(async {
+            let (_, attr, _) = sdk.localfs.lookup(&sdk.ctx(), ROOT_ID, &path).await?;
+            let fh = sdk
+                .localfs
+                .open(&sdk.ctx(), attr.ino, OFlag::O_RDONLY.bits() as u32)
+                .await?;
+            let result = sdk
+                .localfs
+                .read(&sdk.ctx(), attr.ino, fh, offset, buf.len() as u32, &mut buf)
+                .await;
+            sdk.localfs.release(&sdk.ctx(), attr.ino, fh, 0, 0, true).await?;
+            result
+        })?;
+
+        let bytes: Vec<i8> = buf[..size].iter().map(|&b| b as i8).collect();
+        env.set_byte_array_region(&buffer, 0, &bytes)
+            .map_err(jni_error)?;
+        Ok(size as jint)
+    })();
+    unwrap_or_throw(&mut env, result, -1)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_write(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    path: JString,
+    offset: jlong,
+    data: JByteArray,
+) {
+    let result = (|| {
+        let sdk = sdk_ref(handle)?;
+        let path = get_path(&mut env, &path)?;
+        let data = env.convert_byte_array(&data).map_err(jni_error)?;
+
+        sdk.runtime.block_on(async {
+            let (_, attr, _) = sdk.localfs.lookup(&sdk.ctx(), ROOT_ID, &path).await?;
+            let fh = sdk
+                .localfs
+                .open(&sdk.ctx(), attr.ino, OFlag::O_WRONLY.bits() as u32)
+                .await?;
+            let result = sdk.localfs.write(&sdk.ctx(), attr.ino, fh, offset, &data, 0).await;
+            sdk.localfs.release(&sdk.ctx(), attr.ino, fh, 0, 0, true).await?;
+            result
+        })
+    })();
+    unwrap_or_throw(&mut env, result, ());
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_stat(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    path: JString,
+) -> jlongArray {
+    let result = (|| {
+        let sdk = sdk_ref(handle)?;
+        let path = get_path(&mut env, &path)?;
+        let (_, attr, _) = sdk
+            .runtime
+            .block_on(sdk.localfs.lookup(&sdk.ctx(), ROOT_ID, &path))?;
+
+        let mtime_ms = attr
+            .mtime
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis() as jlong);
+        // Keep in sync with the field order of `DatenlordFS.Stat`
+        let fields = [
+            attr.ino as jlong,
+            attr.size as jlong,
+            attr.blocks as jlong,
+            jlong::from(attr.mode()),
+            jlong::from(attr.nlink),
+            jlong::from(attr.uid),
+            jlong::from(attr.gid),
+            mtime_ms,
+        ];
+        let array = env
+            .new_long_array(fields.len() as i32)
+            .map_err(jni_error)?;
+        env.set_long_array_region(&array, 0, &fields)
+            .map_err(jni_error)?;
+        Ok(array.into_raw())
+    })();
+    unwrap_or_throw(&mut env, result, ptr::null_mut())
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_datenlord_DatenlordFS_list(
+    mut env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+    path: JString,
+) -> jobjectArray {
+    let result = (|| {
+        let sdk = sdk_ref(handle)?;
+        let path = get_path(&mut env, &path)?;
+        let entries = sdk.runtime.block_on(async {
+            let (_, attr, _) = sdk.localfs.lookup(&sdk.ctx(), ROOT_ID, &path).await?;
+            sdk.localfs.readdir(&sdk.ctx(), attr.ino, 0, 0).await
+        })?;
+
+        let names: JObjectArray = env
+            .new_object_array(entries.len() as i32, "java/lang/String", JString::default())
+            .map_err(jni_error)?;
+        for (index, entry) in entries.iter().enumerate() {
+            let name = env.new_string(entry.name.to_string_lossy()).map_err(jni_error)?;
+            env.set_object_array_element(&names, index as i32, name)
+                .map_err(jni_error)?;
+        }
+        Ok(names.into_raw())
+    })();
+    unwrap_or_throw(&mut env, result, ptr::null_mut())
+}
@@ -0,0 +1,131 @@
+package io.datenlord;
+
+import java.io.IOException;
+
+/**
+ * Java binding of the datenlord sdk, backed by the JNI functions in
+ * `src/sdk/java/datenlord.rs`.
+ *
+ * Errors are mapped from `DatenLordError`: I/O errors raise
+ * {@link IOException}, invalid arguments raise
+ * {@link IllegalArgumentException}, unimplemented operations raise
+ * {@link UnsupportedOperationException} and internal errors raise
+ * {@link RuntimeException}.
+ */
+public class DatenlordFS implements AutoCloseable {
+    static {
+        System.loadLibrary("datenlord");
+    }
+
+    /** File status returned by {@link DatenlordFS#stat(String)} */
+    public static final class Stat {
+        public final long ino;
+        public final long size;
+        public final long blocks;
+        public final int mode;
+        public final int nlink;
+        public final int uid;
+        public final int gid;
+        public final long mtimeMillis;
+
+        private Stat(long[] fields) {
+            this.ino = fields[0];
+            this.size = fields[1];
+            this.blocks = fields[2];
+            this.mode = (int) fields[3];
+            this.nlink = (int) fields[4];
+            this.uid = (int) fields[5];
+            this.gid = (int) fields[6];
+            this.mtimeMillis = fields[7];
+        }
+
+        public boolean isDirectory() {
+            return (mode & 0170000) == 0040000;
+        }
+    }
+
+    private long handle;
+
+    public DatenlordFS(String config) throws IOException {
+        this.handle = init(config);
+    }
+
+    /** Set the umask applied to the files and directories created from now on, returning the former one */
+    public int setUmask(int umask) throws IOException {
+        return setUmask(handle, umask);
+    }
+
+    public boolean exists(String path) throws IOException {
+        return exists(handle, path);
+    }
+
+    public void mkdir(String path) throws IOException {
+        create(handle, path, true);
+    }
+
+    /** Create `path` together with its missing parent directories, existing ones are kept */
+    public void mkdirs(String path) throws IOException {
+        mkdirs(handle, path);
+    }
+
+    public void createFile(String path) throws IOException {
+        create(handle, path, false);
+    }
+
+    public void delete(String path) throws IOException {
+        delete(handle, path);
+    }
+
+    public void rename(String srcPath, String destPath) throws IOException {
+        rename(handle, srcPath, destPath);
+    }
+
+    /** Read into `buffer` from `offset`, returning the number of bytes read */
+    public int read(String path, long offset, byte[] buffer) throws IOException {
+        return read(handle, path, offset, buffer);
+    }
+
+    public void write(String path, long offset, byte[] data) throws IOException {
+        write(handle, path, offset, data);
+    }
+
+    public Stat stat(String path) throws IOException {
+        return new Stat(stat(handle, path));
+    }
+
+    public String[] list(String path) throws IOException {
+        return list(handle, path);
+    }
+
+    @Override
+    public synchronized void close() {
+        if (handle != 0) {
+            free(handle);
+            handle = 0;
+        }
+    }
+
+    private static native long init(String config) throws IOException;
+
+    private static native void free(long handle);
+
+    private static native int setUmask(long handle, int umask) throws IOException;
+
+    private static native boolean exists(long handle, String path) throws IOException;
+
+    private static native void create(long handle, String path, boolean directory) throws IOException;
+
+    private static native void mkdirs(long handle, String path) throws IOException;
+
+    private static native void delete(long handle, String path) throws IOException;
+
+    private static native void rename(long handle, String srcPath, String destPath) throws IOException;
+
+    private static native int read(long handle, String path, long offset, byte[] buffer) throws IOException;
+
+    private static native void write(long handle, String path, long offset, byte[] data) throws IOException;
+
+    private static native long[] stat(long handle, String path) throws IOException;
+
+    private static native String[] list(long handle, String path) throws IOException;
+}
@@ -0,0 +1,2 @@
+//! This mod is for the datenlord java sdk.
+pub mod datenlord;
@@ -1,3 +1,436 @@
+use std::ffi::OsStr;
+#[cfg(any(feature = "nfs", feature = "sftp", feature = "s3"))]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+#[cfg(any(feature = "nfs", feature = "sftp", feature = "s3"))]
+use tokio::signal::unix::{signal, SignalKind};
+#[cfg(any(feature = "nfs", feature = "sftp", feature = "s3"))]
+use tracing::info;
+use tracing::warn;
+
+use crate::common::config::{ConfigUpdate, DatenLordConfig};
+use crate::common::logging;
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::audit::AuditFs;
+use crate::storage::backpressure::{BackpressureFs, BackpressureStats};
+use crate::storage::cache::CacheFs;
+use crate::storage::dedup::DedupBackend;
+use crate::storage::faulty::FaultyFs;
+use crate::storage::filter::FilterFs;
+use crate::storage::fs_util::{self, DirEntry, RequestContext, ROOT_ID};
+use crate::storage::health::{self, HealthReport, HEALTH_FILE};
+use crate::storage::interrupt::InterruptFs;
+use crate::storage::localfs::LocalFS;
+use crate::storage::mount::MountTable;
+use crate::storage::notify::{EventSink, NotifyFs, SinkConfig};
+use crate::storage::packing::PackedBackend;
+use crate::storage::replication::ReplicatedBackend;
+use crate::storage::retry::RetryFs;
+use crate::storage::stats::{FsStats, StatsFs};
+use crate::storage::striping::StripedBackend;
+use crate::storage::superblock::Feature;
+use crate::storage::timeout::TimeoutFs;
+use crate::storage::trash::{TrashFs, TRASH_DIR};
+use crate::storage::upload::UPLOADS_DIR;
+use crate::storage::versioning::{VersioningFs, VERSIONS_DIR};
+use crate::storage::virtualfs::VirtualFs;
+use crate::storage::writeback::WritebackTask;
+
 pub mod c;
+pub(crate) mod gate;
+#[cfg(feature = "java")]
+pub mod java;
+#[cfg(feature = "node")]
+pub mod node;
 pub mod py;
-pub mod pybind11;
\ No newline at end of file
+pub mod pybind11;
+pub mod rust;
+
+/// The filesystem stack driven by the SDKs taking a config
+pub type SdkFs = InterruptFs<StatsFs<SdkBackpressureFs>>;
+/// The write admission middleware of `SdkFs`, holding its write queue
+pub type SdkBackpressureFs =
+    BackpressureFs<AuditFs<FilterFs<NotifyFs<TrashFs<VersioningFs<SdkCacheFs>>>>>>;
+/// The cache middleware of `SdkFs`, holding its warm handles
+pub type SdkCacheFs = CacheFs<RetryFs<TimeoutFs<SdkMountTable>>>;
+/// The mount table of `SdkFs`, its root backend being the local filesystem
+pub type SdkMountTable =
+    MountTable<FaultyFs<PackedBackend<DedupBackend<StripedBackend<ReplicatedBackend>>>>>;
+
+/// The listings `list_dir` takes of a directory changing meanwhile before
+/// giving up
+const LIST_ATTEMPTS: usize = 3;
+
+/// Open the local filesystem `config` describes behind the replication,
+/// striping, deduplication, packing, timeout, retry, cache, versioning, trash, notification, listing filter
+/// and audit middlewares it configures, with writes admitted under its in-flight limits and
+/// operations counted and interruptible by id, and the backends of its `mounts` mounted over it
+///
+/// The version store and the trash are left out of listings when on, and
+/// the uploads in progress and the file of the health probes always.
+pub fn open_fs(config: &DatenLordConfig) -> DatenLordResult<SdkFs> {
+    if let Err(e) = logging::init(config.log_level.as_deref(), &config.tracing) {
+        warn!("failed to set up the log: {e}");
+    }
+    let mut sinks = config
+        .notify_sinks
+        .iter()
+        .map(SinkConfig::build)
+        .collect::<DatenLordResult<Vec<_>>>()?;
+    if let Some(ref dir) = config.search_index {
+        sinks.push(index_sink(dir)?);
+    }
+    let localfs = LocalFS::new(config)?;
+    let dedup = localfs.features().contains(&Feature::Dedup);
+    let packing = localfs.features().contains(&Feature::Packing);
+    let replicated = ReplicatedBackend::new(localfs, &config.root, &config.replication)?;
+    let striped = StripedBackend::new(replicated, &config.striping)?;
+    let deduped = DedupBackend::new(striped, &config.root, dedup)?;
+    let localfs = PackedBackend::new(deduped, &config.root, packing, &config.packing)?;
+    let mounted = MountTable::new(FaultyFs::new(localfs, config.faults.clone()), config)?;
+    let cached = CacheFs::new(
+        RetryFs::new(
+            TimeoutFs::new(mounted, config.op_timeout()),
+            config.retry.clone(),
+        ),
+        config.attr_cache_capacity,
+    );
+    warm_files(&cached, config);
+    let versioned = VersioningFs::new(cached, config.versioning.clone());
+    let trashed = TrashFs::new(versioned, config.trash.clone());
+    let mut filter = config.listing_filter.clone();
+    filter.hidden_names.push(UPLOADS_DIR.to_owned());
+    filter.hidden_names.push(HEALTH_FILE.to_owned());
+    if config.versioning.enabled {
+        filter.hidden_names.push(VERSIONS_DIR.to_owned());
+    }
+    if config.trash.enabled {
+        filter.hidden_names.push(TRASH_DIR.to_owned());
+    }
+    let filtered = FilterFs::new(
+        NotifyFs::new(trashed, config.root.display().to_string(), sinks)?,
+        filter,
+    );
+    Ok(InterruptFs::new(StatsFs::new(BackpressureFs::new(
+        AuditFs::new(filtered, &config.audit)?,
+        &config.backpressure,
+    ))))
+}
+
+/// The write admission middleware of `fs`
+pub(crate) fn backpressure(fs: &SdkFs) -> &SdkBackpressureFs {
+    fs.inner().inner()
+}
+
+/// The notification middleware of `fs`, where watches are registered
+pub(crate) fn notify(fs: &SdkFs) -> &NotifyFs<TrashFs<VersioningFs<SdkCacheFs>>> {
+    backpressure(fs).inner().inner().inner()
+}
+
+/// The trash middleware of `fs`
+pub(crate) fn trash(fs: &SdkFs) -> &TrashFs<VersioningFs<SdkCacheFs>> {
+    notify(fs).inner()
+}
+
+/// The versioning middleware of `fs`
+pub(crate) fn versioning(fs: &SdkFs) -> &VersioningFs<SdkCacheFs> {
+    trash(fs).inner()
+}
+
+/// The cache middleware of `fs`
+pub(crate) fn cache(fs: &SdkFs) -> &SdkCacheFs {
+    versioning(fs).inner()
+}
+
+/// The mount table of `fs`
+pub(crate) fn mounts(fs: &SdkFs) -> &SdkMountTable {
+    cache(fs).inner().inner().inner()
+}
+
+/// The packing middleware of `fs`, in the root backend of its mount table
+pub(crate) fn packed(
+    fs: &SdkFs,
+) -> &PackedBackend<DedupBackend<StripedBackend<ReplicatedBackend>>> {
+    mounts(fs).root().inner()
+}
+
+/// The deduplication middleware of `fs`
+pub(crate) fn dedup(fs: &SdkFs) -> &DedupBackend<StripedBackend<ReplicatedBackend>> {
+    packed(fs).inner()
+}
+
+/// The striping middleware of `fs`
+pub(crate) fn striped(fs: &SdkFs) -> &StripedBackend<ReplicatedBackend> {
+    dedup(fs).inner()
+}
+
+/// The replication middleware of `fs`
+pub(crate) fn replicated(fs: &SdkFs) -> &ReplicatedBackend {
+    striped(fs).inner()
+}
+
+/// The local filesystem at the bottom of `fs`
+pub(crate) fn local(fs: &SdkFs) -> &LocalFS {
+    replicated(fs).primary()
+}
+
+/// A snapshot of the activity of an SDK, see `stats`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SdkStats {
+    /// The calls made through the SDK and the bytes they moved
+    #[serde(flatten)]
+    pub fs: FsStats,
+    /// The `lookup` and `getattr` calls answered by the attribute cache
+    pub cache_hits: u64,
+    /// The `lookup` and `getattr` calls missing the attribute cache
+    pub cache_misses: u64,
+    /// `cache_hits` over all the cached calls, 0 before any
+    pub cache_hit_ratio: f64,
+    /// The files open
+    pub open_files: usize,
+    /// The directories open
+    pub open_dirs: usize,
+    /// The bytes written and not synced yet, waiting for the write back
+    pub dirty_bytes: u64,
+    /// The changed paths not mirrored to the secondaries yet
+    pub replication_pending: usize,
+    /// The writes in flight and waiting for room
+    #[serde(flatten)]
+    pub writes: BackpressureStats,
+}
+
+/// What `fs` did since it was opened and what it holds now
+pub(crate) fn stats(fs: &SdkFs) -> SdkStats {
+    let (cache_hits, cache_misses) = cache(fs).hits();
+    let cached = cache_hits + cache_misses;
+    let (open_files, open_dirs) = local(fs).open_handles();
+    SdkStats {
+        fs: fs.inner().stats(),
+        cache_hits,
+        cache_misses,
+        cache_hit_ratio: if cached == 0 {
+            0.0
+        } else {
+            cache_hits as f64 / cached as f64
+        },
+        open_files,
+        open_dirs,
+        dirty_bytes: local(fs).dirty_bytes().get(),
+        replication_pending: replicated(fs)
+            .replication_status()
+            .map_or(0, |status| status.pending),
+        writes: backpressure(fs).stats(),
+    }
+}
+
+/// Probe `fs` end to end on behalf of `ctx`, see `health::probe`
+///
+/// The probe runs below the versioning, trash and notification middlewares,
+/// so it keeps no versions of the probed file and notifies no watchers.
+pub(crate) async fn healthcheck(
+    fs: &SdkFs,
+    ctx: &RequestContext,
+) -> (HealthReport, Option<DatenLordError>) {
+    health::probe(cache(fs), ctx).await
+}
+
+/// `ctx` with the directory `prefix`, relative to the root of `ctx`, as its
+/// root, failing if `prefix` is not a directory
+///
+/// The paths of the returned context are relative to `prefix` and cannot
+/// lead out of it, neither with `..` nor through symbolic links, see
+/// `safe_path`.
+pub(crate) async fn scope(
+    fs: &SdkFs,
+    ctx: &RequestContext,
+    prefix: &OsStr,
+) -> DatenLordResult<RequestContext> {
+    if fs_util::normalize(prefix).is_empty() {
+        return Ok(*ctx);
+    }
+    let (_, attr, _) = fs.lookup(ctx, ROOT_ID, prefix).await?;
+    if attr.kind != SFlag::S_IFDIR {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("prefix {prefix:?} is not a directory")],
+        });
+    }
+    Ok(RequestContext {
+        root: attr.ino,
+        ..*ctx
+    })
+}
+
+/// The entries of the directory `path`, relative to the root of `ctx`,
+/// listed with their attributes when `plus`, as a snapshot
+///
+/// A listing during which the generation of the directory changed, see
+/// `FileAttr::generation`, is taken again, failing with
+/// `DatenLordError::Unavailable` after `LIST_ATTEMPTS` listings.
+pub(crate) async fn list_dir(
+    fs: &SdkFs,
+    ctx: &RequestContext,
+    path: &OsStr,
+    plus: bool,
+) -> DatenLordResult<Vec<DirEntry>> {
+    let (_, mut dir, _) = fs.lookup(ctx, ROOT_ID, path).await?;
+    for _ in 0..LIST_ATTEMPTS {
+        let fh = fs.opendir(ctx, dir.ino, 0).await?;
+        let mut entries = Vec::new();
+        let listed = loop {
+            let offset = i64::try_from(entries.len()).unwrap_or(i64::MAX);
+            let page = if plus {
+                fs.readdirplus(ctx, dir.ino, fh, offset)
+                    .await
+                    .map(|detailed| detailed.into_iter().map(|(entry, _, _)| entry).collect())
+            } else {
+                fs.readdir(ctx, dir.ino, fh, offset).await
+            };
+            match page {
+                Ok(page) if page.is_empty() => break Ok(()),
+                Ok(page) => entries.extend(page),
+                Err(e) => break Err(e),
+            }
+        };
+        fs.releasedir(ctx, dir.ino, fh, 0).await?;
+        listed?;
+        let (_, after) = fs.getattr(ctx, dir.ino).await?;
+        if after.generation == dir.generation {
+            return Ok(entries);
+        }
+        dir = after;
+    }
+    Err(DatenLordError::Unavailable {
+        context: vec![format!(
+            "directory {path:?} changed during each of {LIST_ATTEMPTS} listings"
+        )],
+        source: None,
+    })
+}
+
+/// Change the values of `update` in `fs` while running, the open handles
+/// are kept
+///
+/// Everything is checked before anything changes, so a failing update
+/// changes nothing.
+pub(crate) fn update_config(fs: &SdkFs, update: &ConfigUpdate) -> DatenLordResult<()> {
+    if let Some(ref level) = update.log_level {
+        logging::parse_level(level)?;
+    }
+    let retrying = cache(fs).inner();
+    if let Some(capacity) = update.attr_cache_capacity {
+        cache(fs).set_capacity(capacity);
+    }
+    if let Some(timeout_ms) = update.op_timeout_ms {
+        let timeout = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms));
+        retrying.inner().set_default_timeout(timeout);
+    }
+    if let Some(ref retry) = update.retry {
+        retrying.set_policy(retry.clone());
+    }
+    if let Some(ref level) = update.log_level {
+        logging::set_level(level)?;
+    }
+    Ok(())
+}
+
+/// Apply the config in the file `path` to `fs` every time the process gets
+/// `SIGHUP`, as far as it can change while running
+///
+/// Without a file, or when it fails to read or parse, the signal is only
+/// logged, rather than ending the process as it does by default.
+#[cfg(any(feature = "nfs", feature = "sftp", feature = "s3"))]
+pub(crate) fn reload_on_hangup(fs: &Arc<SdkFs>, path: Option<PathBuf>) -> DatenLordResult<()> {
+    let mut hangups = signal(SignalKind::hangup()).map_err(|e| DatenLordError::Io {
+        context: vec![format!("failed to handle SIGHUP: {e}")],
+        source: None,
+    })?;
+    let fs = Arc::clone(fs);
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            let Some(ref path) = path else {
+                warn!("got SIGHUP without a config file to reload");
+                continue;
+            };
+            let config = match std::fs::read_to_string(path) {
+                Ok(config) => config,
+                Err(e) => {
+                    warn!("failed to reload the config {path:?}: {e}");
+                    continue;
+                }
+            };
+            // Unlike at startup, a broken config keeps the current values
+            let config: DatenLordConfig = match serde_json::from_str(&config) {
+                Ok(config) => config,
+                Err(e) => {
+                    warn!("failed to parse the config {path:?}: {e}");
+                    continue;
+                }
+            };
+            match update_config(&fs, &ConfigUpdate::from_config(&config)) {
+                Ok(()) => info!("reloaded the config {path:?}"),
+                Err(e) => warn!("failed to apply the config {path:?}: {e}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Start writing back the data written through the open files of `fs` as
+/// the `writeback` field of `config` says
+pub(crate) fn writeback(
+    fs: &Arc<SdkFs>,
+    config: &DatenLordConfig,
+) -> DatenLordResult<WritebackTask> {
+    let dirty = local(fs).dirty_bytes();
+    let fs = Arc::clone(fs);
+    WritebackTask::start(dirty, move || local(&fs).flush_dirty(), config.writeback)
+}
+
+/// Open the `warm_files` of `config` ahead of time
+///
+/// The files are opened on a runtime of their own, on a thread of its own
+/// since the SDKs may be opened inside a runtime. Files failing to open are
+/// only logged.
+fn warm_files(fs: &SdkCacheFs, config: &DatenLordConfig) {
+    if config.warm_files.is_empty() {
+        return;
+    }
+    let ctx = config.request_context();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    warn!("failed to start a runtime warming files: {e}");
+                    return;
+                }
+            };
+            runtime.block_on(async {
+                for path in &config.warm_files {
+                    if let Err(e) = fs.warm(&ctx, OsStr::new(path)).await {
+                        warn!("failed to warm {path}: {e}");
+                    }
+                }
+            });
+        });
+    });
+}
+
+/// The sink keeping the search index in `dir` current
+#[cfg(feature = "search")]
+fn index_sink(dir: &std::path::Path) -> DatenLordResult<Box<dyn EventSink>> {
+    Ok(Box::new(crate::storage::search::IndexSink::open(dir)?))
+}
+
+/// The sink keeping the search index in `dir` current
+#[cfg(not(feature = "search"))]
+fn index_sink(dir: &std::path::Path) -> DatenLordResult<Box<dyn EventSink>> {
+    Err(crate::common::DatenLordError::Unimplemented {
+        context: vec![format!("cannot keep search index {dir:?}, built without the search feature")],
+        source: None,
+    })
+}
@@ -0,0 +1,236 @@
+use std::ffi::OsStr;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use napi::bindgen_prelude::Buffer;
+use napi::{Error, Result};
+use napi_derive::napi;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+use crate::common::config::DatenLordConfig;
+use crate::common::DatenLordError;
+use crate::storage::fs_util::{CreateParam, FileAttr, RequestContext, ROOT_ID};
+use crate::storage::localfs::LocalFS;
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+/// Build a closure mapping a `DatenLordError` into a JS error with context
+fn js_error(context: &'static str) -> impl FnOnce(DatenLordError) -> Error {
+    move |e| Error::from_reason(format!("{context}: {e}"))
+}
+
+/// File status, mirroring the fields of node's `fs.Stats`
+#[napi(object)]
+pub struct Stats {
+    pub ino: i64,
+    pub size: i64,
+    pub blocks: i64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u32,
+    pub atime_ms: f64,
+    pub mtime_ms: f64,
+    pub ctime_ms: f64,
+}
+
+impl From<FileAttr> for Stats {
+    fn from(attr: FileAttr) -> Self {
+        let millis = |time: std::time::SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0.0, |duration| duration.as_secs_f64() * 1000.0)
+        };
+        Self {
+            ino: attr.ino as i64,
+            size: attr.size as i64,
+            blocks: attr.blocks as i64,
+            mode: attr.kind.bits() | u32::from(attr.perm),
+            nlink: attr.nlink,
+            uid: attr.uid,
+            gid: attr.gid,
+            rdev: attr.rdev,
+            atime_ms: millis(attr.atime),
+            mtime_ms: millis(attr.mtime),
+            ctime_ms: millis(attr.ctime),
+        }
+    }
+}
+
+/// A directory entry returned by `readdir`
+#[napi(object)]
+pub struct Dirent {
+    pub ino: i64,
+    pub name: String,
+    /// One of the names of `FileKind`
+    pub kind: String,
+    /// Only filled when listed with `withStats`
+    pub stats: Option<Stats>,
+}
+
+/// The datenlord sdk handle, every method returns a `Promise`
+#[napi]
+pub struct DatenlordSdk {
+    localfs: Arc<LocalFS>,
+    /// The caller every operation runs on behalf of
+    ctx: RequestContext,
+}
+
+#[napi]
+impl DatenlordSdk {
+    #[napi(constructor)]
+    pub fn new(config: Option<String>) -> Result<Self> {
+        let config = config
+            .as_deref()
+            .map(DatenLordConfig::parse)
+            .unwrap_or_default();
+        let localfs = LocalFS::new(&config).map_err(js_error("Failed to init sdk"))?;
+        Ok(Self {
+            localfs: Arc::new(localfs),
+            ctx: config.request_context(),
+        })
+    }
+
+    /// Set the umask applied to the files and directories created from now
+    /// on, returning the former one like `process.umask`
+    #[napi]
+    pub fn set_umask(&mut self, umask: u32) -> u32 {
+        std::mem::replace(&mut self.ctx.umask, umask & 0o777)
+    }
+
+    #[napi]
+    pub async fn exists(&self, path: String) -> bool {
+        self.localfs
+            .exists(&self.ctx, ROOT_ID, OsStr::new(&path))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Create a directory, with `recursive` creating its missing parents and
+    /// keeping existing directories like `fs.mkdir`
+    #[napi]
+    pub async fn mkdir(&self, path: String, recursive: Option<bool>) -> Result<()> {
+        if recursive.unwrap_or(false) {
+            return self
+                .localfs
+                .mkdir_all(&self.ctx, ROOT_ID, OsStr::new(&path), 0o777)
+                .await
+                .map(|_| ())
+                .map_err(js_error("Failed to create directory"));
+        }
+        let param = CreateParam {
+            parent: ROOT_ID,
+            name: path.into(),
+            mode: 0o777,
+            rdev: 0,
+            node_type: SFlag::S_IFDIR,
+            link: None,
+        };
+        self.localfs
+            .mkdir(&self.ctx, param)
+            .await
+            .map(|_| ())
+            .map_err(js_error("Failed to create directory"))
+    }
+
+    #[napi]
+    pub async fn open(&self, path: String, flags: Option<u32>) -> Result<FileHandle> {
+        let flags = flags.unwrap_or(OFlag::O_RDONLY.bits() as u32);
+        let (_, attr, _) = self
+            .localfs
+            .lookup(&self.ctx, ROOT_ID, OsStr::new(&path))
+            .await
+            .map_err(js_error("Failed to open file"))?;
+        let fh = self
+            .localfs
+            .open(&self.ctx, attr.ino, flags)
+            .await
+            .map_err(js_error("Failed to open file"))?;
+        Ok(FileHandle {
+            localfs: Arc::clone(&self.localfs),
+            ctx: self.ctx,
+            ino: attr.ino,
+            fh,
+        })
+    }
+
+    /// List a directory, `withStats` filling the attributes of every entry
+    #[napi]
+    pub async fn readdir(&self, path: String, with_stats: Option<bool>) -> Result<Vec<Dirent>> {
+        let (_, attr, _) = self
+            .localfs
+            .lookup(&self.ctx, ROOT_ID, OsStr::new(&path))
+            .await
+            .map_err(js_error("Failed to read directory"))?;
+        let entries = if with_stats.unwrap_or(false) {
+            self.localfs
+                .readdirplus(&self.ctx, attr.ino, 0, 0)
+                .await
+                .map(|detailed| detailed.into_iter().map(|(entry, _, _)| entry).collect())
+        } else {
+            self.localfs.readdir(&self.ctx, attr.ino, 0, 0).await
+        }
+        .map_err(js_error("Failed to read directory"))?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| Dirent {
+                ino: entry.ino as i64,
+                name: entry.name.to_string_lossy().into_owned(),
+                kind: entry.kind.name().to_owned(),
+                stats: entry.attr.map(Stats::from),
+            })
+            .collect())
+    }
+
+    #[napi]
+    pub async fn stat(&self, path: String) -> Result<Stats> {
+        self.localfs
+            .lookup(&self.ctx, ROOT_ID, OsStr::new(&path))
+            .await
+            .map(|(_, attr, _)| Stats::from(attr))
+            .map_err(js_error("Failed to get file metadata"))
+    }
+}
+
+/// An open file returned by `DatenlordSdk.open`
+#[napi]
+pub struct FileHandle {
+    localfs: Arc<LocalFS>,
+    ctx: RequestContext,
+    ino: INum,
+    fh: u64,
+}
+
+#[napi]
+impl FileHandle {
+    #[napi]
+    pub async fn read(&self, offset: i64, length: u32) -> Result<Buffer> {
+        let offset = u64::try_from(offset)
+            .map_err(|_| Error::from_reason(format!("Invalid read offset {offset}")))?;
+        let mut buf = vec![0; length as usize];
+        let size = self
+            .localfs
+            .read(&self.ctx, self.ino, self.fh, offset, length, &mut buf)
+            .await
+            .map_err(js_error("Failed to read file"))?;
+        buf.truncate(size);
+        Ok(buf.into())
+    }
+
+    #[napi]
+    pub async fn write(&self, offset: i64, data: Buffer) -> Result<u32> {
+        self.localfs
+            .write(&self.ctx, self.ino, self.fh, offset, &data, 0)
+            .await
+            .map_err(js_error("Failed to write file"))?;
+        Ok(data.len() as u32)
+    }
+
+    #[napi]
+    pub async fn close(&self) -> Result<()> {
+        self.localfs
+            .release(&self.ctx, self.ino, self.fh, 0, 0, true)
+            .await
+            .map_err(js_error("Failed to close file"))
+    }
+}
@@ -0,0 +1,2 @@
+//! This mod is for the datenlord node sdk.
+pub mod datenlord;
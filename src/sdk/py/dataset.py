@@ -0,0 +1,110 @@
+"""Datasets of files read through a datenlord SDK, for PyTorch and TensorFlow
+
+Samples are read many at once with `batch_read`. The SDK is opened from the
+config on first use and not pickled, so a dataset built in the parent can be
+handed to spawned DataLoader workers, forked ones reopening the SDK they
+inherit by themselves.
+"""
+import collections
+from concurrent.futures import ThreadPoolExecutor
+
+try:
+    from torch.utils.data import Dataset as _MapStyle
+    from torch.utils.data import IterableDataset as _IterableStyle
+    from torch.utils.data import get_worker_info
+except ImportError:
+    _MapStyle = _IterableStyle = object
+
+    def get_worker_info():
+        return None
+
+
+class _DatenlordFiles:
+    """The files of `paths`, or of the `manifest` file listing one path per
+    line, each sample being `transform(data)` or the bytes without one"""
+
+    def __init__(self, config=None, paths=None, manifest=None, transform=None, concurrency=16):
+        if (paths is None) == (manifest is None):
+            raise ValueError("exactly one of paths and manifest is needed")
+        self.config = config
+        self.transform = transform
+        self.concurrency = concurrency
+        self._sdk = None
+        if manifest is not None:
+            lines = self.sdk().read_file(manifest).decode().splitlines()
+            paths = [line.strip() for line in lines if line.strip()]
+        self.paths = list(paths)
+
+    def sdk(self):
+        """The SDK, opened on first use"""
+        if self._sdk is None:
+            # Set by the `datenlord` module once this one is loaded
+            self._sdk = DatenlordSDK(self.config)
+        return self._sdk
+
+    def __getstate__(self):
+        state = self.__dict__.copy()
+        state["_sdk"] = None
+        return state
+
+    def _read(self, paths):
+        contents = self.sdk().batch_read(paths, self.concurrency)
+        samples = []
+        for path, data in zip(paths, contents):
+            if data is None:
+                raise OSError(f"Failed to read {path}")
+            samples.append(data if self.transform is None else self.transform(data))
+        return samples
+
+
+class DatenlordDataset(_DatenlordFiles, _MapStyle):
+    """A map-style dataset, a DataLoader fetching each batch of indices with
+    one `batch_read`"""
+
+    def __len__(self):
+        return len(self.paths)
+
+    def __getitem__(self, index):
+        return self._read([self.paths[index]])[0]
+
+    def __getitems__(self, indices):
+        return self._read([self.paths[index] for index in indices])
+
+
+class DatenlordIterableDataset(_DatenlordFiles, _IterableStyle):
+    """An iterable dataset reading `batch_size` files at once while up to
+    `prefetch` batches are read ahead, the DataLoader workers each taking
+    their share of the paths"""
+
+    def __init__(self, config=None, paths=None, manifest=None, transform=None, concurrency=16,
+                 batch_size=64, prefetch=2):
+        super().__init__(config, paths, manifest, transform, concurrency)
+        self.batch_size = max(batch_size, 1)
+        self.prefetch = max(prefetch, 1)
+
+    def __len__(self):
+        return len(self.paths)
+
+    def __iter__(self):
+        paths = self.paths
+        worker = get_worker_info()
+        if worker is not None:
+            paths = paths[worker.id::worker.num_workers]
+        batches = (paths[i:i + self.batch_size] for i in range(0, len(paths), self.batch_size))
+        with ThreadPoolExecutor(max_workers=1) as reader:
+            pending = collections.deque()
+            for batch in batches:
+                pending.append(reader.submit(self._read, batch))
+                if len(pending) > self.prefetch:
+                    yield from pending.popleft().result()
+            while pending:
+                yield from pending.popleft().result()
+
+    def to_tensorflow(self):
+        """The samples as a `tf.data.Dataset` of byte strings, without a
+        `transform`"""
+        import tensorflow as tf
+
+        return tf.data.Dataset.from_generator(
+            self.__iter__, output_signature=tf.TensorSpec(shape=(), dtype=tf.string)
+        )
@@ -1,231 +1,2496 @@
 use pyo3::prelude::*;
+#[cfg(not(feature = "abi3"))]
+use pyo3::buffer::PyBuffer;
+use pyo3::type_object::PyTypeObject;
+use pyo3::once_cell::GILOnceCell;
+use pyo3::types::{PyBytes, PyType};
 use pyo3::wrap_pyfunction;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use tokio::runtime::Runtime;
-use bytes::BytesMut;
+use tokio::time::Instant;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
 use std::fs;
-use crate::storage::localfs::LocalFS;
-use crate::storage::fs_util::{CreateParam, RenameParam};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use std::io::Write;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use crate::common::buffer_pool::BufferPool;
+use crate::common::config::{ConfigUpdate, DatenLordConfig};
+use crate::common::logging;
+#[cfg(not(feature = "abi3"))]
+use crate::ffi;
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::diff::{self, Change, DiffOptions};
+use crate::lifecycle::LifecycleTask;
+use crate::sdk::gate::{Call, CallGate};
+use crate::sdk::{self, SdkFs};
+use crate::storage::appendlog::{AppendLog, LogSync, LogTail, SharedLogs};
+use crate::storage::archive;
+use crate::storage::digest::{self, DigestAlgorithm};
+use crate::storage::kv::{KvOptions, KvStore};
+use crate::storage::notify::{Event, Watch};
+#[cfg(feature = "search")]
+use crate::storage::search::{SearchHit, SearchIndex};
+use crate::storage::tags::{self, TagFilter};
+use crate::storage::stream;
+use crate::storage::timeout;
+use crate::storage::gc::{self, GcTask};
+use crate::storage::trash::{self, PurgeTask};
+use crate::storage::transfer::{self, CopyConfig};
+use crate::storage::upload;
+use crate::storage::walk::{self, Walk, DEFAULT_WALK_CONCURRENCY};
+use crate::storage::writeback::{self, WritebackTask};
+use crate::storage::fs_util::{
+    self, CreateParam, DirEntry, FileAttr, FileKind, RenameParam, RequestContext, SeekWhence,
+    SetAttrParam, UtimeSpec, ROOT_ID,
+};
 use crate::storage::virtualfs::{INum, VirtualFs};
+use nix::errno::Errno;
+use nix::fcntl::{OFlag, RenameFlags};
 use nix::sys::stat::SFlag;
 
+/// A secondary in `replication_status`: root, whether in sync, paths
+/// replicated, failures and the last error
+type SecondaryTuple = (OsString, bool, u64, u64, Option<String>);
+
+/// File attributes returned by `stat`, mirroring `os.stat_result`
+#[pyclass]
+struct StatResult {
+    /// File type and permission bits
+    #[pyo3(get)]
+    st_mode: u32,
+    /// Inode number
+    #[pyo3(get)]
+    st_ino: u64,
+    /// Number of hard links
+    #[pyo3(get)]
+    st_nlink: u32,
+    /// User id of the owner
+    #[pyo3(get)]
+    st_uid: u32,
+    /// Group id of the owner
+    #[pyo3(get)]
+    st_gid: u32,
+    /// Size in bytes
+    #[pyo3(get)]
+    st_size: u64,
+    /// Number of 512-byte blocks allocated
+    #[pyo3(get)]
+    st_blocks: u64,
+    /// Time of last access in nanoseconds since the epoch
+    #[pyo3(get)]
+    st_atime_ns: i128,
+    /// Time of last modification in nanoseconds since the epoch
+    #[pyo3(get)]
+    st_mtime_ns: i128,
+    /// Time of last status change in nanoseconds since the epoch
+    #[pyo3(get)]
+    st_ctime_ns: i128,
+    /// File type name, one of the names of `FileKind`, or "unknown"
+    #[pyo3(get)]
+    kind: &'static str,
+    /// The generation of the entries of a directory, changing whenever
+    /// entries are created, removed or renamed in it, 0 for other files
+    #[pyo3(get)]
+    generation: u64,
+}
+
+/// Nanoseconds since the epoch of `time`
+fn timestamp_ns(time: SystemTime) -> i128 {
+    let (sec, nsec) = fs_util::to_timespec(time);
+    i128::from(sec) * 1_000_000_000 + i128::from(nsec)
+}
+
+impl From<&FileAttr> for StatResult {
+    fn from(attr: &FileAttr) -> Self {
+        Self {
+            st_mode: attr.mode(),
+            st_ino: attr.ino,
+            st_nlink: attr.nlink,
+            st_uid: attr.uid,
+            st_gid: attr.gid,
+            st_size: attr.size,
+            st_blocks: attr.blocks,
+            st_atime_ns: timestamp_ns(attr.atime),
+            st_mtime_ns: timestamp_ns(attr.mtime),
+            st_ctime_ns: timestamp_ns(attr.ctime),
+            kind: FileKind::from_sflag(attr.kind).map_or("unknown", FileKind::name),
+            generation: attr.generation,
+        }
+    }
+}
+
+#[pymethods]
+impl StatResult {
+    /// Time of last access in seconds since the epoch
+    #[getter]
+    fn st_atime(&self) -> f64 {
+        self.st_atime_ns as f64 / 1e9
+    }
+
+    /// Time of last modification in seconds since the epoch
+    #[getter]
+    fn st_mtime(&self) -> f64 {
+        self.st_mtime_ns as f64 / 1e9
+    }
+
+    /// Time of last status change in seconds since the epoch
+    #[getter]
+    fn st_ctime(&self) -> f64 {
+        self.st_ctime_ns as f64 / 1e9
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "datenlord.StatResult(st_mode={:#o}, st_ino={}, st_nlink={}, st_uid={}, \
+             st_gid={}, st_size={}, st_blocks={}, st_atime={}, st_mtime={}, st_ctime={}, \
+             kind='{}')",
+            self.st_mode,
+            self.st_ino,
+            self.st_nlink,
+            self.st_uid,
+            self.st_gid,
+            self.st_size,
+            self.st_blocks,
+            self.st_atime(),
+            self.st_mtime(),
+            self.st_ctime(),
+            self.kind,
+        )
+    }
+}
+
+/// An entry returned by `readdir`, like `os.DirEntry`
+#[pyclass(name = "DirEntry")]
+struct PyDirEntry {
+    /// Name of the entry within the directory
+    #[pyo3(get)]
+    name: OsString,
+    /// Inode number
+    #[pyo3(get)]
+    ino: u64,
+    /// File type name, one of the names of `FileKind`
+    #[pyo3(get)]
+    kind: &'static str,
+    /// Attributes of the entry, `None` unless listed with `plus`
+    #[pyo3(get)]
+    stat: Option<Py<StatResult>>,
+}
+
+impl PyDirEntry {
+    fn new(py: Python, entry: DirEntry) -> PyResult<Self> {
+        Ok(Self {
+            name: entry.name,
+            ino: entry.ino,
+            kind: entry.kind.name(),
+            stat: entry
+                .attr
+                .map(|attr| Py::new(py, StatResult::from(&attr)))
+                .transpose()?,
+        })
+    }
+}
+
+#[pymethods]
+impl PyDirEntry {
+    fn __repr__(&self) -> String {
+        format!(
+            "datenlord.DirEntry(name='{}', ino={}, kind='{}')",
+            self.name.to_string_lossy(), self.ino, self.kind,
+        )
+    }
+}
+
+/// A path differing between the two directories compared by `diff`
+#[pyclass(name = "Change")]
+struct PyChange {
+    /// Path relative to both directories
+    #[pyo3(get)]
+    path: String,
+    /// One of "added", "removed" and "modified"
+    #[pyo3(get)]
+    kind: &'static str,
+    /// Attributes in the first directory, `None` when added
+    #[pyo3(get)]
+    old: Option<Py<StatResult>>,
+    /// Attributes in the second directory, `None` when removed
+    #[pyo3(get)]
+    new: Option<Py<StatResult>>,
+    /// Growth in bytes, the whole size of added and removed entries
+    #[pyo3(get)]
+    size_delta: i128,
+    /// How much later the entry was modified in the second directory, in
+    /// nanoseconds, `None` unless the path is in both
+    #[pyo3(get)]
+    mtime_delta_ns: Option<i128>,
+    /// Whether the contents differ, `None` unless their checksums were compared
+    #[pyo3(get)]
+    content_differs: Option<bool>,
+}
+
+impl PyChange {
+    fn new(py: Python, change: Change) -> PyResult<Self> {
+        let stat = |attr: &Option<FileAttr>| attr.as_ref().map(|attr| Py::new(py, StatResult::from(attr))).transpose();
+        Ok(Self {
+            old: stat(&change.old)?,
+            new: stat(&change.new)?,
+            size_delta: change.size_delta(),
+            mtime_delta_ns: change.mtime_delta_ns(),
+            content_differs: change.content_differs,
+            kind: change.kind.name(),
+            path: change.path,
+        })
+    }
+}
+
+#[pymethods]
+impl PyChange {
+    fn __repr__(&self) -> String {
+        format!(
+            "datenlord.Change(path='{}', kind='{}', size_delta={})",
+            self.path, self.kind, self.size_delta,
+        )
+    }
+}
+
+/// A file found by `search` or `find_by_tags`
+#[pyclass(name = "SearchHit")]
+struct PySearchHit {
+    /// Path relative to the root
+    #[pyo3(get)]
+    path: String,
+    /// Inode number
+    #[pyo3(get)]
+    ino: u64,
+    /// File type name, one of the names of `FileKind`, or "unknown"
+    #[pyo3(get)]
+    kind: &'static str,
+    /// Size in bytes
+    #[pyo3(get)]
+    size: u64,
+    /// Time of last modification in nanoseconds since the epoch
+    #[pyo3(get)]
+    mtime_ns: i64,
+    /// Tags of the file
+    #[pyo3(get)]
+    tags: BTreeMap<String, String>,
+}
+
+#[pymethods]
+impl PySearchHit {
+    fn __repr__(&self) -> String {
+        format!(
+            "datenlord.SearchHit(path='{}', kind='{}', size={})",
+            self.path, self.kind, self.size,
+        )
+    }
+}
+
+#[cfg(feature = "search")]
+impl From<SearchHit> for PySearchHit {
+    fn from(hit: SearchHit) -> Self {
+        Self {
+            path: hit.path,
+            ino: hit.ino,
+            kind: hit.kind.map_or("unknown", FileKind::name),
+            size: hit.size,
+            mtime_ns: hit.mtime_ns,
+            tags: hit.tags,
+        }
+    }
+}
+
+/// Special timestamps accepted by `utimens`, like `UTIME_NOW` and `UTIME_OMIT`
+#[pyclass]
+#[derive(Clone, Copy)]
+enum Utime {
+    /// Set the timestamp to the current time
+    Now,
+    /// Leave the timestamp unchanged
+    Omit,
+}
+
+/// A timestamp argument of `utimens`, nanoseconds since the epoch or a `Utime`
+#[derive(FromPyObject)]
+enum UtimeArg {
+    Special(Utime),
+    Nanos(i128),
+}
+
+impl UtimeArg {
+    /// The timestamp to set, `None` if it is out of range
+    fn to_utime(&self) -> Option<UtimeSpec> {
+        match *self {
+            Self::Special(Utime::Now) => Some(UtimeSpec::Now),
+            Self::Special(Utime::Omit) => Some(UtimeSpec::Omit),
+            Self::Nanos(nanos) => {
+                let sec = i64::try_from(nanos.div_euclid(1_000_000_000)).ok()?;
+                let nsec = nanos.rem_euclid(1_000_000_000) as u32;
+                fs_util::from_timespec(sec, nsec).map(UtimeSpec::Time)
+            }
+        }
+    }
+}
+
+/// Iterator over the entries of `walk` or `glob`, yielding `(path, StatResult)` tuples
+#[pyclass]
+struct WalkIter {
+    /// The running walk, dropped before the runtime it runs on
+    walk: Walk,
+    /// Runtime listing the directories
+    runtime: Runtime,
+}
+
+#[pymethods]
+impl WalkIter {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<Self>, py: Python) -> PyResult<Option<(OsString, StatResult)>> {
+        let WalkIter { walk, runtime } = &mut *slf;
+        match py.allow_threads(|| runtime.block_on(walk.next())) {
+            Some(Ok(entry)) => Ok(Some((entry.path, StatResult::from(&entry.attr)))),
+            Some(Err(e)) => Err(os_error(&e, "walk", "Failed to list directory")),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A change reported by `watch`
+#[pyclass(name = "Event")]
+struct PyEvent {
+    /// One of "create", "mkdir", "symlink", "delete", "rename", "attrib"
+    /// and "close_write"
+    #[pyo3(get)]
+    kind: &'static str,
+    /// Path of the entry relative to the root, `None` when unknown
+    #[pyo3(get)]
+    path: Option<String>,
+    /// Path the entry moved to, `None` unless renamed
+    #[pyo3(get)]
+    new_path: Option<String>,
+    /// Inode number of the entry
+    #[pyo3(get)]
+    ino: u64,
+    /// Size of the entry after the change
+    #[pyo3(get)]
+    size: u64,
+    /// When the change was observed, in nanoseconds since the epoch
+    #[pyo3(get)]
+    timestamp_ns: i128,
+}
+
+impl From<Event> for PyEvent {
+    fn from(event: Event) -> Self {
+        Self {
+            kind: event.kind.name(),
+            path: event.path,
+            new_path: event.new_path,
+            ino: event.ino,
+            size: event.size,
+            timestamp_ns: event.timestamp_ns,
+        }
+    }
+}
+
+#[pymethods]
+impl PyEvent {
+    fn __repr__(&self) -> String {
+        format!(
+            "datenlord.Event(kind='{}', path={:?}, new_path={:?})",
+            self.kind, self.path, self.new_path,
+        )
+    }
+}
+
+/// Iterator over the changes under a path watched by `watch`, blocking
+/// until the next one
+#[pyclass]
+struct WatchIter {
+    /// The watch, `None` once closed
+    watch: Option<Watch>,
+}
+
+#[pymethods]
+impl WatchIter {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<Self>, py: Python) -> PyResult<Option<PyEvent>> {
+        let Some(watch) = slf.watch.as_mut() else {
+            return Ok(None);
+        };
+        let event = py.allow_threads(|| block_on(None, watch.next()))?;
+        Ok(event.map(PyEvent::from))
+    }
+
+    /// Stop watching, ending the iteration
+    fn close(&mut self) {
+        self.watch = None;
+    }
+}
+
+/// Iterator over the records of a log returned by `tail`, as `(offset,
+/// record)` tuples, waiting for new ones until closed
+#[pyclass]
+struct TailIter {
+    /// The tail, `None` once closed
+    tail: Option<LogTail<SdkFs>>,
+}
+
+#[pymethods]
+impl TailIter {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<Self>, py: Python) -> PyResult<Option<(u64, Vec<u8>)>> {
+        let Some(tail) = slf.tail.as_mut() else {
+            return Ok(None);
+        };
+        let record = py
+            .allow_threads(|| block_on(None, tail.next()))?
+            .map_err(|e| os_error(&e, "tail", "Failed to read log"))?;
+        Ok(record.map(|record| (record.offset, record.data)))
+    }
+
+    /// Stop following the log, ending the iteration
+    fn close(&mut self) {
+        self.tail = None;
+    }
+}
+
+/// Iterator over the chunks of a file read by `read_stream`, as `bytes`
+#[pyclass]
+struct ReadStreamIter {
+    /// The chunks left, `None` once closed
+    chunks: Option<Pin<Box<dyn Stream<Item = DatenLordResult<Bytes>> + Send>>>,
+}
+
+#[pymethods]
+impl ReadStreamIter {
+    fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<Self>, py: Python) -> PyResult<Option<PyObject>> {
+        let Some(chunks) = slf.chunks.as_mut() else {
+            return Ok(None);
+        };
+        match py.allow_threads(|| block_on(None, chunks.next()))? {
+            Some(Ok(chunk)) => Ok(Some(PyBytes::new(py, &chunk).into())),
+            Some(Err(e)) => Err(os_error(&e, "read_stream", "Failed to read file")),
+            None => Ok(None),
+        }
+    }
+
+    /// Stop reading, ending the iteration
+    fn close(&mut self) {
+        self.chunks = None;
+    }
+}
+
+/// An append-only log opened by `open_log`, see `AppendLog`
+#[pyclass(name = "AppendLog")]
+struct PyAppendLog {
+    /// The open log, `None` once closed, dropped before the runtime its
+    /// writer runs on
+    log: Option<AppendLog<SdkFs>>,
+    /// Runtime running the writer of the log
+    runtime: Runtime,
+}
+
+impl PyAppendLog {
+    /// The open log, raising once closed
+    fn log(&self) -> PyResult<&AppendLog<SdkFs>> {
+        self.log
+            .as_ref()
+            .ok_or_else(|| pyo3::exceptions::PyValueError::new_err("append log is closed"))
+    }
+}
+
+#[pymethods]
+impl PyAppendLog {
+    /// The offset the next record is appended at
+    #[getter]
+    fn end(&self) -> PyResult<u64> {
+        Ok(self.log()?.end())
+    }
+
+    /// Append `record`, returning the offset it was appended at
+    fn append(&self, py: Python, record: &[u8]) -> PyResult<u64> {
+        let log = self.log()?;
+        py.allow_threads(|| self.runtime.block_on(log.append(record)))
+            .map_err(|e| os_error(&e, "AppendLog.append", "Failed to append to log"))
+    }
+
+    /// Sync the records appended so far
+    fn sync(&self, py: Python) -> PyResult<()> {
+        let log = self.log()?;
+        py.allow_threads(|| self.runtime.block_on(log.sync()))
+            .map_err(|e| os_error(&e, "AppendLog.sync", "Failed to sync log"))
+    }
+
+    /// At most `limit` records from the one appended at `offset` on, as
+    /// `(offset, record)` tuples
+    #[args(limit = "1000")]
+    fn read_from(&self, py: Python, offset: u64, limit: usize) -> PyResult<Vec<(u64, Vec<u8>)>> {
+        let log = self.log()?;
+        let records = py
+            .allow_threads(|| self.runtime.block_on(log.read_from(offset, limit)))
+            .map_err(|e| os_error(&e, "AppendLog.read_from", "Failed to read log"))?;
+        Ok(records.into_iter().map(|record| (record.offset, record.data)).collect())
+    }
+
+    /// Sync and close the log, closing a closed log does nothing
+    fn close(&mut self, py: Python) -> PyResult<()> {
+        let Some(log) = self.log.take() else {
+            return Ok(());
+        };
+        py.allow_threads(|| self.runtime.block_on(log.close()))
+            .map_err(|e| os_error(&e, "AppendLog.close", "Failed to close log"))
+    }
+}
+
+/// A key-value store opened by `open_kv`, see `KvStore`
+#[pyclass(name = "KvStore")]
+struct PyKvStore {
+    /// The open store, `None` once closed, dropped before the runtime its
+    /// segment writers run on
+    kv: Option<KvStore<SdkFs>>,
+    /// Runtime running the segment writers of the store
+    runtime: Runtime,
+}
+
+impl PyKvStore {
+    /// The open store, raising once closed
+    fn kv(&self) -> PyResult<&KvStore<SdkFs>> {
+        self.kv
+            .as_ref()
+            .ok_or_else(|| pyo3::exceptions::PyValueError::new_err("key-value store is closed"))
+    }
+}
+
+#[pymethods]
+impl PyKvStore {
+    /// Set `key` to `value`, replacing its former value
+    fn put(&self, py: Python, key: &str, value: &[u8]) -> PyResult<()> {
+        let kv = self.kv()?;
+        py.allow_threads(|| self.runtime.block_on(kv.put(key, value)))
+            .map_err(|e| os_error(&e, "KvStore.put", "Failed to put value"))
+    }
+
+    /// The value of `key`, `None` when missing
+    fn get(&self, py: Python, key: &str) -> PyResult<Option<Vec<u8>>> {
+        let kv = self.kv()?;
+        py.allow_threads(|| self.runtime.block_on(kv.get(key)))
+            .map_err(|e| os_error(&e, "KvStore.get", "Failed to get value"))
+    }
+
+    /// Remove `key`, returning whether it was there
+    fn delete(&self, py: Python, key: &str) -> PyResult<bool> {
+        let kv = self.kv()?;
+        py.allow_threads(|| self.runtime.block_on(kv.delete(key)))
+            .map_err(|e| os_error(&e, "KvStore.delete", "Failed to delete value"))
+    }
+
+    /// The keys starting with `prefix` and their values, as `(key, value)`
+    /// tuples in key order
+    #[args(prefix = "\"\"")]
+    fn scan(&self, py: Python, prefix: &str) -> PyResult<Vec<(String, Vec<u8>)>> {
+        let kv = self.kv()?;
+        py.allow_threads(|| self.runtime.block_on(kv.scan(prefix)))
+            .map_err(|e| os_error(&e, "KvStore.scan", "Failed to scan values"))
+    }
+
+    /// Rewrite the live values into new segments, returning the bytes
+    /// reclaimed
+    fn compact(&self, py: Python) -> PyResult<u64> {
+        let kv = self.kv()?;
+        py.allow_threads(|| self.runtime.block_on(kv.compact()))
+            .map_err(|e| os_error(&e, "KvStore.compact", "Failed to compact values"))
+    }
+
+    /// Sync the values written so far
+    fn sync(&self, py: Python) -> PyResult<()> {
+        let kv = self.kv()?;
+        py.allow_threads(|| self.runtime.block_on(kv.sync()))
+            .map_err(|e| os_error(&e, "KvStore.sync", "Failed to sync values"))
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        let kv = self.kv()?;
+        Ok(self.runtime.block_on(kv.len()))
+    }
+
+    /// Sync and close the store, closing a closed store does nothing
+    fn close(&mut self, py: Python) -> PyResult<()> {
+        let Some(kv) = self.kv.take() else {
+            return Ok(());
+        };
+        py.allow_threads(|| self.runtime.block_on(kv.close()))
+            .map_err(|e| os_error(&e, "KvStore.close", "Failed to close key-value store"))
+    }
+}
+
+/// A path of an SDK with the methods of `pathlib.Path`, made by
+/// `sdk.path(...)` and joined with `/`
+#[pyclass]
+struct DatenlordPath {
+    /// The SDK the path is resolved by
+    sdk: Py<DatenlordSDK>,
+    /// The path below the root of the SDK
+    path: PathBuf,
+}
+
+impl DatenlordPath {
+    fn new(sdk: Py<DatenlordSDK>, path: OsString) -> Self {
+        Self {
+            sdk,
+            path: PathBuf::from(path),
+        }
+    }
+
+    /// The path `path` of the same SDK
+    fn with_path(&self, py: Python, path: PathBuf) -> Self {
+        Self {
+            sdk: self.sdk.clone_ref(py),
+            path,
+        }
+    }
+
+    /// The kind of the entry at the path, `None` if there is none
+    fn kind(&self, py: Python) -> PyResult<Option<SFlag>> {
+        let sdk = self.sdk.borrow(py);
+        let localfs = &sdk.localfs()?;
+        let result = sdk.block_on(None, localfs.lookup(&sdk.ctx, ROOT_ID, self.path.as_os_str()))?;
+        match result {
+            Ok((_, attr, _)) => Ok(Some(attr.kind)),
+            Err(e @ DatenLordError::Timeout { .. }) => {
+                Err(exception(&e, "Failed to look up path", None, Some(self.path.as_os_str())))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[pymethods]
+impl DatenlordPath {
+    fn __str__(&self) -> OsString {
+        self.path.clone().into_os_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("datenlord.DatenlordPath('{}')", self.path.to_string_lossy())
+    }
+
+    fn __truediv__(&self, py: Python, other: OsString) -> Self {
+        self.with_path(py, self.path.join(other))
+    }
+
+    /// The last component of the path, empty for the root
+    #[getter]
+    fn name(&self) -> OsString {
+        self.path.file_name().unwrap_or_default().to_owned()
+    }
+
+    /// The directory holding the path, the root being its own parent
+    #[getter]
+    fn parent(&self, py: Python) -> Self {
+        let parent = self.path.parent().unwrap_or(&self.path).to_owned();
+        self.with_path(py, parent)
+    }
+
+    #[args(timeout = "None")]
+    fn stat(&self, py: Python, timeout: Option<f64>) -> PyResult<StatResult> {
+        self.sdk.borrow(py).stat(self.__str__(), timeout)
+    }
+
+    fn exists(&self, py: Python) -> PyResult<bool> {
+        Ok(self.kind(py)?.is_some())
+    }
+
+    fn is_dir(&self, py: Python) -> PyResult<bool> {
+        Ok(self.kind(py)? == Some(SFlag::S_IFDIR))
+    }
+
+    fn is_file(&self, py: Python) -> PyResult<bool> {
+        Ok(self.kind(py)? == Some(SFlag::S_IFREG))
+    }
+
+    /// The paths of the entries of the directory, as a list
+    #[args(timeout = "None")]
+    fn iterdir(&self, py: Python, timeout: Option<f64>) -> PyResult<Vec<Self>> {
+        let entries = self.sdk.borrow(py).list_entries("Path.iterdir", self.path.as_os_str(), false, timeout)?;
+        Ok(entries
+            .into_iter()
+            .filter(|entry| entry.name != "." && entry.name != "..")
+            .map(|entry| self.with_path(py, self.path.join(entry.name)))
+            .collect())
+    }
+
+    #[args(timeout = "None")]
+    fn read_bytes(&self, py: Python, timeout: Option<f64>) -> PyResult<PyObject> {
+        self.sdk.borrow(py).read_file(py, self.__str__(), timeout, None)
+    }
+
+    /// Replace the contents of the file by `data`, creating it if missing,
+    /// and return the number of bytes written
+    #[args(timeout = "None")]
+    fn write_bytes(&self, py: Python, data: &[u8], timeout: Option<f64>) -> PyResult<usize> {
+        self.sdk.borrow(py).replace_file(self.path.as_os_str(), data, timeout)?;
+        Ok(data.len())
+    }
+
+    /// Remove the file, raising `OSError` if missing unless `missing_ok`
+    #[args(missing_ok = "false", timeout = "None")]
+    fn unlink(&self, py: Python, missing_ok: bool, timeout: Option<f64>) -> PyResult<()> {
+        let result = self.sdk.borrow(py).remove_file(self.__str__(), timeout);
+        if result.is_err() && missing_ok && self.kind(py)?.is_none() {
+            return Ok(());
+        }
+        result
+    }
+
+    /// Create the directory, with its missing parents if `parents`
+    #[args(parents = "false", exist_ok = "false", timeout = "None")]
+    fn mkdir(&self, py: Python, parents: bool, exist_ok: bool, timeout: Option<f64>) -> PyResult<()> {
+        let sdk = self.sdk.borrow(py);
+        let result = if parents {
+            sdk.mkdir_all(self.__str__(), 0o777, timeout)
+        } else {
+            sdk.mkdir(self.__str__(), timeout)
+        };
+        match result {
+            Err(e) if exist_ok && raised::<pyo3::exceptions::PyFileExistsError>(py, &e) => {
+                if self.is_dir(py)? { Ok(()) } else { Err(e) }
+            }
+            result => result,
+        }
+    }
+
+    /// Remove the directory, which must be empty
+    #[args(timeout = "None")]
+    fn rmdir(&self, py: Python, timeout: Option<f64>) -> PyResult<()> {
+        self.sdk.borrow(py).deldir(self.__str__(), false, timeout)
+    }
+}
+
+/// The filesystem stack behind the SDK, repeated lookups are answered from
+/// the cache and each attempt of a retried call is bounded by the default
+/// timeout separately unless the call has a timeout
 #[pyclass]
 struct DatenlordSDK {
-    localfs: Arc<Mutex<LocalFS>>,
+    /// The config the SDK was opened with and updated to, reopened from in
+    /// forked children
+    config: Mutex<DatenLordConfig>,
+    /// The state of the SDK in the process that opened it, see `process`
+    process: Mutex<Arc<Process>>,
+    /// The caller every operation runs on behalf of
+    ctx: RequestContext,
+    buffer_pool: BufferPool,
+    /// How `copy_from_local_file` splits large files
+    copy: CopyConfig,
+    /// The index searched by `search`, if the config has one
+    #[cfg(feature = "search")]
+    search_index: Option<SearchIndex>,
+    /// Admits calls until `close`
+    calls: Arc<CallGate>,
+}
+
+/// The part of an SDK bound to the process that opened it: the filesystem
+/// stack, with its open files and backend connections, and the background
+/// tasks, whose threads a forked child does not inherit
+struct Process {
+    /// The id of the process
+    pid: u32,
+    localfs: Arc<SdkFs>,
+    /// The scheduled evaluation of the lifecycle rules, if any, stopped by
+    /// `close`
+    lifecycle: Mutex<Option<LifecycleTask>>,
+    /// The scheduled purge of the trash, if any, stopped by `close`
+    purge: Mutex<Option<PurgeTask>>,
+    /// The scheduled garbage collection, if any, stopped by `close`
+    gc: Mutex<Option<GcTask>>,
+    /// The background writeback, flushing a last time on `close` or when
+    /// collected
+    writeback: Mutex<Option<WritebackTask>>,
+    /// The logs appended to by `append`, closed by `close`
+    logs: SharedLogs<SdkFs>,
+    /// Runtime running the writers of `logs`
+    logs_runtime: Runtime,
+    /// Runtime of the quick calls, see `exists`
+    quick_runtime: Runtime,
+}
+
+impl Process {
+    /// Open the filesystem of `config` and start its background tasks in
+    /// the current process, on behalf of `ctx` scoped to `prefix`, see
+    /// `sdk::scope`, returning the scoped context too
+    ///
+    /// The background tasks run on the whole namespace whatever the root.
+    fn open(
+        config: &DatenLordConfig,
+        ctx: RequestContext,
+        prefix: &OsStr,
+    ) -> DatenLordResult<(Self, RequestContext)> {
+        let localfs = Arc::new(sdk::open_fs(config)?);
+        let scoped = Runtime::new()
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start a runtime: {e}")],
+            })?
+            .block_on(sdk::scope(&localfs, &ctx, prefix))?;
+        let ctx = RequestContext {
+            root: ROOT_ID,
+            ..ctx
+        };
+        let writeback = sdk::writeback(&localfs, config)?;
+        let lifecycle = LifecycleTask::start(Arc::clone(&localfs), ctx, config.lifecycle.clone())?;
+        let purge = PurgeTask::start(Arc::clone(&localfs), ctx, config.trash.clone())?;
+        let gc = GcTask::start(Arc::clone(&localfs), sdk::packed, ctx, config.gc.clone())?;
+        let logs_runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("datenlord-logs")
+            .enable_all()
+            .build()
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the log runtime: {e}")],
+            })?;
+        let quick_runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the quick runtime: {e}")],
+            })?;
+        let logs = SharedLogs::new(Arc::clone(&localfs), scoped, LogSync::Batch);
+        let process = Self {
+            pid: std::process::id(),
+            localfs,
+            lifecycle: Mutex::new(lifecycle),
+            purge: Mutex::new(purge),
+            gc: Mutex::new(gc),
+            writeback: Mutex::new(Some(writeback)),
+            logs,
+            logs_runtime,
+            quick_runtime,
+        };
+        Ok((process, scoped))
+    }
+}
+
+impl Drop for DatenlordSDK {
+    fn drop(&mut self) {
+        if let Ok(process) = self.process.get_mut() {
+            if process.pid != std::process::id() {
+                // Inherited by a forked child that never used it, dropping
+                // it would wait for threads that were not forked
+                std::mem::forget(Arc::clone(process));
+            }
+        }
+    }
+}
+
+/// How often a blocking call checks for signals such as Ctrl-C
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Run `fut` to completion on a fresh runtime
+///
+/// With a `timeout` in seconds, every filesystem call `fut` makes fails once
+/// the timeout passed, so a caller giving up, e.g. through
+/// `asyncio.wait_for`, does not leave backend requests running. A signal
+/// handler raising, like the one of Ctrl-C, drops `fut` and raises instead.
+fn block_on<F: Future>(timeout: Option<f64>, fut: F) -> PyResult<F::Output> {
+    let deadline = deadline(timeout)?;
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let fut = within(deadline, fut);
+        tokio::pin!(fut);
+        loop {
+            tokio::select! {
+                output = &mut fut => return Ok(output),
+                () = tokio::time::sleep(SIGNAL_CHECK_INTERVAL) => Python::with_gil(|py| py.check_signals())?,
+            }
+        }
+    })
+}
+
+/// The deadline of a call given `timeout` in seconds, if any
+fn deadline(timeout: Option<f64>) -> PyResult<Option<Instant>> {
+    match timeout {
+        Some(secs) => match Duration::try_from_secs_f64(secs) {
+            Ok(timeout) => Ok(Some(Instant::now() + timeout)),
+            Err(_) => Err(pyo3::exceptions::PyValueError::new_err(
+                "timeout must be a non-negative number of seconds",
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Run `fut`, failing its filesystem calls past `deadline` if any
+async fn within<F: Future>(deadline: Option<Instant>, fut: F) -> F::Output {
+    match deadline {
+        Some(deadline) => timeout::with_deadline(deadline, fut).await,
+        None => fut.await,
+    }
+}
+
+/// The sync policy named `sync` of `open_log` and `open_kv`
+fn parse_log_sync(sync: &str, sync_interval_ms: u64) -> PyResult<LogSync> {
+    match sync {
+        "none" => Ok(LogSync::None),
+        "batch" => Ok(LogSync::Batch),
+        "interval" => Ok(LogSync::Interval(Duration::from_millis(sync_interval_ms))),
+        _ => Err(pyo3::exceptions::PyValueError::new_err(
+            "sync must be \"none\", \"batch\" or \"interval\"",
+        )),
+    }
+}
+
+/// The `datenlord.errors` module, loaded with the extension
+static ERRORS: GILOnceCell<Py<PyModule>> = GILOnceCell::new();
+
+/// The exception of `datenlord.errors` for `errno`, raised by `operation` on
+/// `path` with `message`
+fn sdk_error(errno: Option<Errno>, message: String, operation: Option<&str>, path: Option<&OsStr>) -> PyErr {
+    let errno = errno.map(|errno| errno as i32);
+    Python::with_gil(|py| {
+        let errors = ERRORS.get(py).expect("the datenlord module is initialized").as_ref(py);
+        let class = errors.call_method1("_class_for", (errno,));
+        match class.and_then(|class| Ok(class.downcast::<PyType>()?)) {
+            Ok(class) => PyErr::from_type(
+                class,
+                (errno, message, path.map(OsStr::to_owned), operation.map(str::to_owned)),
+            ),
+            Err(e) => e,
+        }
+    })
+}
+
+/// The exception `operation` raises for `err`, see `path_error`
+fn os_error(err: &DatenLordError, operation: &str, message: &str) -> PyErr {
+    exception(err, message, Some(operation), None)
+}
+
+/// The exception `operation` raises for `err` on `path`, the subclass of
+/// `datenlord.errors.DatenlordError` for its errno such as `NotFound`,
+/// `PermissionDenied` or `Timeout`
+fn path_error(err: &DatenLordError, operation: &str, path: impl AsRef<OsStr>, message: &str) -> PyErr {
+    exception(err, message, Some(operation), Some(path.as_ref()))
+}
+
+/// The exception raised for `err`, with `message` telling what failed
+fn exception(err: &DatenLordError, message: &str, operation: Option<&str>, path: Option<&OsStr>) -> PyErr {
+    let message = match *err {
+        DatenLordError::Timeout { .. } => format!("{message}, timed out"),
+        DatenLordError::Interrupted { .. } => format!("{message}, interrupted"),
+        DatenLordError::PermissionDenied { .. } => format!("{message}, permission denied"),
+        DatenLordError::NoSpace { .. } => format!("{message}, no space left"),
+        DatenLordError::Corrupted { .. } => format!("{message}, digest mismatch"),
+        _ => message.to_owned(),
+    };
+    sdk_error(err.errno(), message, operation, path)
+}
+
+/// The exception raised for a tag operation on `path`, `ValueError` for
+/// invalid keys and filters, see `exception` otherwise
+fn tag_error(err: &DatenLordError, operation: &str, path: Option<&OsString>, message: &str) -> PyErr {
+    match *err {
+        DatenLordError::InvalidArgument { .. } => pyo3::exceptions::PyValueError::new_err(err.to_string()),
+        _ => exception(err, message, Some(operation), path.map(OsString::as_os_str)),
+    }
 }
 
+/// Whether `err` is an instance of `T`, such as the builtin `OSError`
+/// subclass the exceptions of `os_error` derive from
+fn raised<T: PyTypeObject>(py: Python, err: &PyErr) -> bool {
+    err.value(py).is_instance_of::<T>().unwrap_or(false)
+}
+
+/// An I/O error on a file outside the SDK
+fn local_error(err: std::io::Error) -> DatenLordError {
+    DatenLordError::Io {
+        context: vec![err.to_string()],
+        source: None,
+    }
+}
+
+/// Every method takes an optional `timeout` in seconds bounding the whole
+/// operation, retries included, raising `errors.Timeout` once it passed
+///
+/// Failures raise the `datenlord.errors.DatenlordError` subclass for their
+/// errno, with the `path` and `operation` that failed.
 #[pymethods]
 impl DatenlordSDK {
     #[new]
-    fn new() -> PyResult<Self> {
-        let localfs = LocalFS::new().unwrap();
+    fn new(config: Option<&str>, prefix: Option<OsString>) -> PyResult<Self> {
+        let config = config.map(DatenLordConfig::parse).unwrap_or_default();
+        let prefix = prefix.unwrap_or_default();
+        let (process, ctx) = Process::open(&config, config.request_context(), &prefix)
+            .map_err(|e| os_error(&e, "DatenlordSDK", "Failed to open the SDK"))?;
+        #[cfg(feature = "search")]
+        let search_index = config
+            .search_index
+            .as_deref()
+            .map(SearchIndex::open)
+            .transpose()
+            .map_err(|e| os_error(&e, "DatenlordSDK", "Failed to open the search index"))?;
         Ok(DatenlordSDK {
-            localfs: Arc::new(Mutex::new(localfs)),
+            process: Mutex::new(Arc::new(process)),
+            ctx,
+            buffer_pool: BufferPool::new(),
+            copy: config.copy,
+            #[cfg(feature = "search")]
+            search_index,
+            calls: Arc::default(),
+            config: Mutex::new(config),
         })
     }
 
-    fn exists(&self, dir_path: &str) -> PyResult<bool> {
-        let sdk_ref = &self.localfs;
-        let rt = Runtime::new().unwrap();
-        let result = rt.block_on(async {
-            let localfs = sdk_ref.lock().unwrap();
-            localfs.lookup(1000, 1000, 1, dir_path).await
-        });
-        Ok(result.is_ok())
+    /// Set the umask applied to the files and directories created from now
+    /// on, returning the former one like `os.umask`
+    fn set_umask(&mut self, umask: u32) -> u32 {
+        std::mem::replace(&mut self.ctx.umask, umask & 0o777)
+    }
+
+    #[args(timeout = "None")]
+    fn exists(&self, dir_path: OsString, timeout: Option<f64>) -> PyResult<bool> {
+        // Called in loops, so on the runtime of the process rather than a
+        // fresh one, and without the signal checks of `block_on`
+        let _call = self.enter()?;
+        let deadline = deadline(timeout)?;
+        let process = self.process()?;
+        let result = process.quick_runtime.block_on(within(
+            deadline,
+            process.localfs.exists(&self.ctx, ROOT_ID, &dir_path),
+        ));
+        match result {
+            Ok(exists) => Ok(exists),
+            Err(e @ DatenLordError::Timeout { .. }) => {
+                Err(path_error(&e, "exists", &dir_path, "Failed to look up path"))
+            }
+            Err(_) => Ok(false),
+        }
     }
 
-    fn mkdir(&self, dir_path: &str) -> PyResult<()> {
-        let sdk_ref = &self.localfs;
-        let rt = Runtime::new().unwrap();
-        let result = rt.block_on(async {
+    #[args(timeout = "None")]
+    fn mkdir(&self, dir_path: OsString, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
             let param = CreateParam {
-                parent: 34735213, // 示例 inode
-                name: dir_path.to_string(),
+                parent: ROOT_ID,
+                name: dir_path.clone(),
                 mode: 0o777,
                 rdev: 0,
-                uid: 1000,
-                gid: 1000,
                 node_type: SFlag::S_IFDIR,
                 link: None,
             };
-            let localfs = sdk_ref.lock().unwrap();
-            localfs.mkdir(param).await
-        });
+            localfs.mkdir(&self.ctx, param).await
+        })?;
 
-        if result.is_ok() {
-            Ok(())
-        } else {
-            Err(pyo3::exceptions::PyOSError::new_err("Failed to create directory"))
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(path_error(&e, "mkdir", &dir_path, "Failed to create directory")),
         }
     }
 
-    fn deldir(&self, dir_path: &str, recursive: bool) -> PyResult<()> {
-        let sdk_ref = &self.localfs;
-        let rt = Runtime::new().unwrap();
-        let result = rt.block_on(async {
-            let localfs = sdk_ref.lock().unwrap();
-            localfs.rmdir(1000, 1000, 1, dir_path).await // 示例 inode
-        });
+    /// Create `dir_path` together with its missing parents, like `os.makedirs`
+    /// with `exist_ok=True`
+    #[args(mode = "0o777", timeout = "None")]
+    fn mkdir_all(&self, dir_path: OsString, mode: u32, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            localfs.mkdir_all(&self.ctx, ROOT_ID, &dir_path, mode).await
+        })?;
 
-        if result.is_ok() {
-            Ok(())
-        } else {
-            Err(pyo3::exceptions::PyOSError::new_err("Failed to remove directory"))
+        match result {
+            Ok(_) => Ok(()),
+            Err(e @ DatenLordError::AlreadyExists { .. }) => Err(path_error(
+                &e,
+                "mkdir_all",
+                &dir_path,
+                "Failed to create directory, a path component is not a directory",
+            )),
+            Err(e) => Err(path_error(&e, "mkdir_all", &dir_path, "Failed to create directory")),
+        }
+    }
+
+    #[args(timeout = "None")]
+    fn deldir(&self, dir_path: OsString, recursive: bool, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            localfs.rmdir(&self.ctx, 1, &dir_path).await // 示例 inode
+        })?;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(path_error(&e, "deldir", &dir_path, "Failed to remove directory")),
         }
     }
 
-    fn rename_path(&self, src_path: &str, dest_path: &str) -> PyResult<()> {
-        let sdk_ref = &self.localfs;
-        let rt = Runtime::new().unwrap();
-        let result = rt.block_on(async {
+    /// Remove the file `file_path`, like `os.remove`
+    #[args(timeout = "None")]
+    fn remove_file(&self, file_path: OsString, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            localfs.unlink(&self.ctx, ROOT_ID, &file_path).await
+        })?;
+
+        result.map_err(|e| path_error(&e, "remove_file", &file_path, "Failed to remove file"))
+    }
+
+    /// `file_path` as a `DatenlordPath`
+    fn path(slf: PyRef<Self>, file_path: OsString) -> DatenlordPath {
+        DatenlordPath::new(slf.into(), file_path)
+    }
+
+    /// Rename `src_path` to `dest_path`, `flags` takes `RENAME_NOREPLACE` or
+    /// `RENAME_EXCHANGE` like `renameat2`
+    #[args(flags = "0", timeout = "None")]
+    fn rename_path(&self, src_path: OsString, dest_path: OsString, flags: u32, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
             let param = RenameParam {
                 old_parent: 1,
-                old_name: src_path.to_string(),
+                old_name: src_path.clone(),
                 new_parent: 1,
-                new_name: dest_path.to_string(),
-                flags: 0,
+                new_name: dest_path.clone(),
+                flags,
             };
-            let localfs = sdk_ref.lock().unwrap();
-            localfs.rename(1000, 1000, param).await
-        });
+            localfs.rename(&self.ctx, param).await
+        })?;
 
-        if result.is_ok() {
-            Ok(())
-        } else {
-            Err(pyo3::exceptions::PyOSError::new_err("Failed to rename path"))
+        match result {
+            Ok(()) => Ok(()),
+            Err(e @ DatenLordError::AlreadyExists { .. }) => {
+                Err(path_error(&e, "rename_path", &dest_path, "Failed to rename path, destination exists"))
+            }
+            Err(e) => Err(path_error(&e, "rename_path", &src_path, "Failed to rename path")),
         }
     }
 
-    fn copy_from_local_file(&self, local_file_path: &str, dest_file_path: &str, overwrite: bool) -> PyResult<()> {
-        let sdk_ref = &self.localfs;
-        let rt = Runtime::new().unwrap();
-        let result = rt.block_on(async {
-            let localfs = sdk_ref.lock().unwrap();
-            if !overwrite && localfs.lookup(1000, 1000, 1, dest_file_path).await.is_ok() {
-                return Err(());
-            }
+    #[args(timeout = "None")]
+    fn copy_from_local_file(
+        &self,
+        local_file_path: OsString,
+        dest_file_path: OsString,
+        overwrite: bool,
+        timeout: Option<f64>,
+    ) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let ino = match localfs.lookup(&self.ctx, ROOT_ID, &dest_file_path).await {
+                Ok(_) if !overwrite => {
+                    return Err(DatenLordError::AlreadyExists {
+                        context: vec![format!("{dest_file_path:?} already exists")],
+                        source: None,
+                    })
+                }
+                Ok((_, attr, _)) => attr.ino,
+                Err(_) => {
+                    let param = CreateParam {
+                        parent: ROOT_ID,
+                        name: dest_file_path.clone(),
+                        mode: 0o666,
+                        rdev: 0,
+                        node_type: SFlag::S_IFREG,
+                        link: None,
+                    };
+                    localfs.mknod(&self.ctx, param).await?.1.ino
+                }
+            };
 
-            match fs::read(local_file_path) {
-                Ok(content) => localfs.write(1, 0, 0, &content, 0).await.map_err(|_| ()),
-                Err(_) => Err(()),
+            let file = fs::File::open(&local_file_path).map_err(local_error)?;
+            let fh = localfs.open(&self.ctx, ino, OFlag::O_WRONLY.bits() as u32).await?;
+            let result = transfer::copy_from_local(
+                localfs,
+                self.ctx,
+                file,
+                ino,
+                fh,
+                &self.copy,
+                &self.buffer_pool,
+            )
+            .await;
+            localfs.release(&self.ctx, ino, fh, 0, 0, true).await?;
+            result?;
+            for (key, value) in tags::read_local_tags(Path::new(&local_file_path))? {
+                tags::set_tag(localfs.as_ref(), &self.ctx, ino, &key, &value).await?;
             }
-        });
-
-        if result.is_ok() {
             Ok(())
-        } else {
-            Err(pyo3::exceptions::PyOSError::new_err("Failed to copy file"))
+        })?;
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => Err(path_error(&e, "copy_from_local_file", &dest_file_path, "Failed to copy file")),
         }
     }
 
-    fn copy_to_local_file(&self, src_file_path: &str, local_file_path: &str) -> PyResult<()> {
-        let sdk_ref = &self.localfs;
-        let rt = Runtime::new().unwrap();
-        let result = rt.block_on(async {
-            let mut buf = BytesMut::new();
-            let localfs = sdk_ref.lock().unwrap();
-            localfs.read(1, 0, 0, 1024, &mut buf).await.map_err(|_| ()) // 示例 inode 和最大读取字节数
-        });
+    #[args(timeout = "None")]
+    fn copy_to_local_file(&self, src_file_path: OsString, local_file_path: OsString, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &src_file_path).await?;
+            let fh = localfs.open(&self.ctx, attr.ino, OFlag::O_RDONLY.bits() as u32).await?;
+
+            let mut file = fs::File::create(&local_file_path).map_err(local_error)?;
+            let mut buf = self.buffer_pool.acquire(localfs.block_layout().block_len());
+            let mut offset = 0;
+            let result = loop {
+                let read = localfs.read(&self.ctx, attr.ino, fh, offset, buf.len() as u32, &mut buf);
+                let size = match read.await {
+                    Ok(0) => break Ok(()),
+                    Ok(size) => size,
+                    Err(e) => break Err(e),
+                };
+                if let Err(e) = file.write_all(&buf[..size]) {
+                    break Err(local_error(e));
+                }
+                offset += size as u64;
+            };
+            localfs.release(&self.ctx, attr.ino, fh, 0, 0, true).await?;
+            result?;
+            let tags = tags::get_tags(localfs.as_ref(), &self.ctx, attr.ino).await?;
+            tags::write_local_tags(Path::new(&local_file_path), &tags)
+        })?;
 
         match result {
-            Ok(size) => {
-                Ok(())
-            }
-            Err(_) => Err(pyo3::exceptions::PyOSError::new_err("Failed to copy file to local")),
+            Ok(()) => Ok(()),
+            Err(e) => Err(path_error(&e, "copy_to_local_file", &src_file_path, "Failed to copy file to local")),
         }
     }
 
-    fn create_file(&self, file_path: &str) -> PyResult<()> {
-        let sdk_ref = &self.localfs;
-        let rt = Runtime::new().unwrap();
-        let result = rt.block_on(async {
+    /// Copy the file `src_path` to `dst_path`, replacing the file at the
+    /// destination, as a dict with the `copied` bytes and whether the copy
+    /// is `reflinked`, sharing the blocks of the source
+    #[args(timeout = "None")]
+    fn copy(
+        &self,
+        py: Python,
+        src_path: OsString,
+        dst_path: OsString,
+        timeout: Option<f64>,
+    ) -> PyResult<PyObject> {
+        let localfs = self.localfs()?;
+        let copy = transfer::copy_file(localfs.as_ref(), &self.ctx, &src_path, &dst_path);
+        let copy = self
+            .block_on(timeout, copy)?
+            .map_err(|e| path_error(&e, "copy", &src_path, "Failed to copy file"))?;
+        let copy = serde_json::to_string(&copy)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(py.import("json")?.call_method1("loads", (copy,))?.into())
+    }
+
+    /// Write `path`, everything below it when a directory, to the local tar
+    /// archive `local_file_path`, as a dict with the `files`, `dirs`,
+    /// `symlinks` and `bytes` archived and the entries `skipped`
+    #[args(timeout = "None")]
+    fn export_tar(
+        &self,
+        py: Python,
+        path: OsString,
+        local_file_path: OsString,
+        timeout: Option<f64>,
+    ) -> PyResult<PyObject> {
+        let localfs = self.localfs()?;
+        let export = async {
+            let file = tokio::fs::File::create(&local_file_path).await.map_err(local_error)?;
+            let mut writer = tokio::io::BufWriter::new(file);
+            archive::export_tar(localfs.as_ref(), &self.ctx, &path, &mut writer).await
+        };
+        let report = py
+            .allow_threads(|| self.block_on(timeout, export))?
+            .map_err(|e| path_error(&e, "export_tar", &path, "Failed to export archive"))?;
+        let report = serde_json::to_string(&report)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(py.import("json")?.call_method1("loads", (report,))?.into())
+    }
+
+    /// Extract the local tar archive `local_file_path` into the directory
+    /// `dest_path`, created if missing, as a dict like `export_tar`'s
+    #[args(timeout = "None")]
+    fn import_tar(
+        &self,
+        py: Python,
+        local_file_path: OsString,
+        dest_path: OsString,
+        timeout: Option<f64>,
+    ) -> PyResult<PyObject> {
+        let localfs = self.localfs()?;
+        let import = async {
+            let file = tokio::fs::File::open(&local_file_path).await.map_err(local_error)?;
+            let mut reader = tokio::io::BufReader::new(file);
+            archive::import_tar(localfs.as_ref(), &self.ctx, &mut reader, &dest_path).await
+        };
+        let report = py
+            .allow_threads(|| self.block_on(timeout, import))?
+            .map_err(|e| path_error(&e, "import_tar", &dest_path, "Failed to import archive"))?;
+        let report = serde_json::to_string(&report)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(py.import("json")?.call_method1("loads", (report,))?.into())
+    }
+
+    /// Create the regular file `file_path`, with `ensure_parents` creating its
+    /// missing parent directories first
+    #[args(ensure_parents = "false", timeout = "None")]
+    fn create_file(&self, file_path: OsString, ensure_parents: bool, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            if ensure_parents {
+                let parents = Path::new(&file_path).parent().map(Path::as_os_str);
+                if let Some(parents) = parents.filter(|parents| !parents.is_empty()) {
+                    localfs.mkdir_all(&self.ctx, ROOT_ID, parents, 0o777).await?;
+                }
+            }
+
             let param = CreateParam {
-                parent: 1,
-                name: file_path.to_string(),
-                mode: 0o644,
+                parent: ROOT_ID,
+                name: file_path.clone(),
+                mode: 0o666,
                 rdev: 0,
-                uid: 1000,
-                gid: 1000,
                 node_type: SFlag::S_IFREG,
                 link: None,
             };
 
-            let localfs = sdk_ref.lock().unwrap();
-            localfs.mknod(param).await
-        });
+            localfs.mknod(&self.ctx, param).await
+        })?;
 
-        if result.is_ok() {
-            Ok(())
-        } else {
-            Err(pyo3::exceptions::PyOSError::new_err("Failed to create file"))
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(path_error(&e, "create_file", &file_path, "Failed to create file")),
         }
     }
 
-    fn stat(&self, file_path: &str) -> PyResult<(u64, u32, u32, u32, u32)> {
-        let sdk_ref = &self.localfs;
-        let rt = Runtime::new().unwrap();
-        let result = rt.block_on(async {
-            let localfs = sdk_ref.lock().unwrap();
-            localfs.getattr(1).await // 示例 inode
-        });
+    /// Create the empty regular file `file_path` unless it exists, like
+    /// `open` with `O_CREAT | O_EXCL`, returning whether this call created it
+    ///
+    /// Exactly one of the callers racing to create the same path gets
+    /// `True`, so the file can serve as a lock file.
+    #[args(timeout = "None")]
+    fn create_exclusive(&self, file_path: OsString, timeout: Option<f64>) -> PyResult<bool> {
+        let localfs = &self.localfs()?;
+        let param = CreateParam {
+            parent: ROOT_ID,
+            name: file_path.clone(),
+            mode: 0o666,
+            rdev: 0,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        match self.block_on(timeout, localfs.mknod(&self.ctx, param))? {
+            Ok(_) => Ok(true),
+            Err(DatenLordError::AlreadyExists { .. }) => Ok(false),
+            Err(e) => Err(path_error(&e, "create_exclusive", &file_path, "Failed to create file")),
+        }
+    }
+
+    #[args(timeout = "None")]
+    fn stat(&self, file_path: OsString, timeout: Option<f64>) -> PyResult<StatResult> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            localfs.lookup(&self.ctx, ROOT_ID, &file_path).await
+        })?;
 
         match result {
-            Ok(attr) => {
-                let file_stat = (
-                    attr.1.size,  // 文件大小
-                    attr.1.uid,   // 用户ID
-                    attr.1.gid,   // 组ID
-                    attr.1.nlink, // 硬链接数量
-                    attr.1.rdev,  // 设备ID
-                );
-                Ok(file_stat)
+            Ok((_, attr, _)) => Ok(StatResult::from(&attr)),
+            Err(e) => Err(path_error(&e, "stat", &file_path, "Failed to get file metadata")),
+        }
+    }
+
+    /// The attributes of every path of `file_paths`, in order, `None` for the
+    /// paths that cannot be stat'ed
+    ///
+    /// Up to `concurrency` paths are looked up at once, and each distinct
+    /// parent directory is resolved once.
+    #[args(concurrency = "DEFAULT_WALK_CONCURRENCY", timeout = "None")]
+    fn stat_many(
+        &self,
+        file_paths: Vec<OsString>,
+        concurrency: usize,
+        timeout: Option<f64>,
+    ) -> PyResult<Vec<Option<StatResult>>> {
+        let localfs = self.localfs()?;
+        let stats = walk::stat_many(localfs, self.ctx, &file_paths, concurrency);
+        let results = self.block_on(timeout, stats)?;
+        Ok(results
+            .into_iter()
+            .map(|result| result.ok().map(|attr| StatResult::from(&attr)))
+            .collect())
+    }
+
+    /// The space taken by `path`, everything below it when a directory, as
+    /// a dict with the `bytes` and `allocated_bytes` of the files, hard
+    /// links counted once, and the number of `files` and `dirs`, like `du`
+    ///
+    /// Up to `concurrency` directories are listed at once.
+    #[args(concurrency = "DEFAULT_WALK_CONCURRENCY", timeout = "None")]
+    fn disk_usage(
+        &self,
+        py: Python,
+        path: OsString,
+        concurrency: usize,
+        timeout: Option<f64>,
+    ) -> PyResult<PyObject> {
+        let localfs = self.localfs()?;
+        let usage = walk::disk_usage(localfs, self.ctx, &path, concurrency);
+        let usage = self
+            .block_on(timeout, usage)?
+            .map_err(|e| path_error(&e, "disk_usage", &path, "Failed to count disk usage"))?;
+        let usage = serde_json::to_string(&usage)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(py.import("json")?.call_method1("loads", (usage,))?.into())
+    }
+
+    /// The contents of every file of `file_paths` as `bytes`, in order,
+    /// `None` for the files that cannot be read
+    ///
+    /// Up to `concurrency` files are read at once, with the GIL released so
+    /// other threads run meanwhile.
+    #[args(concurrency = "DEFAULT_WALK_CONCURRENCY", timeout = "None")]
+    fn batch_read(
+        &self,
+        py: Python,
+        file_paths: Vec<OsString>,
+        concurrency: usize,
+        timeout: Option<f64>,
+    ) -> PyResult<Vec<Option<PyObject>>> {
+        let localfs = self.localfs()?;
+        let reads = stream::read_many(localfs, self.ctx, &file_paths, concurrency);
+        let results = py.allow_threads(|| self.block_on(timeout, reads))?;
+        Ok(results
+            .into_iter()
+            .map(|result| result.ok().map(|data| PyBytes::new(py, &data).into()))
+            .collect())
+    }
+
+    /// Set the access and modification times of `file_path`
+    ///
+    /// Each time is in nanoseconds since the epoch, or `Utime.Now` or
+    /// `Utime.Omit`, which `UTIME_NOW` and `UTIME_OMIT` alias.
+    #[args(timeout = "None")]
+    fn utimens(&self, file_path: OsString, atime_ns: UtimeArg, mtime_ns: UtimeArg, timeout: Option<f64>) -> PyResult<()> {
+        let (Some(atime), Some(mtime)) = (atime_ns.to_utime(), mtime_ns.to_utime()) else {
+            return Err(pyo3::exceptions::PyOverflowError::new_err("timestamp out of range"));
+        };
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            localfs.utimens(&self.ctx, attr.ino, atime, mtime).await
+        })?;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(path_error(&e, "utimens", &file_path, "Failed to set file times")),
+        }
+    }
+
+    /// Write `content` to `file_path`, returning its digest with the
+    /// algorithm `digest`, `"crc32c"`, `"sha256"` or `"blake3"`, if given
+    #[args(timeout = "None", digest = "None")]
+    fn write_file(
+        &self,
+        file_path: OsString,
+        content: Vec<u8>,
+        timeout: Option<f64>,
+        digest: Option<&str>,
+    ) -> PyResult<Option<String>> {
+        let algorithm = digest
+            .map(str::parse::<DigestAlgorithm>)
+            .transpose()
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let digest = algorithm.map(|algorithm| algorithm.digest(&content));
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            let fh = localfs.open(&self.ctx, attr.ino, OFlag::O_WRONLY.bits() as u32).await?;
+            let result = localfs.write(&self.ctx, attr.ino, fh, 0, &content, 0).await;
+            localfs.release(&self.ctx, attr.ino, fh, 0, 0, true).await?;
+            result
+        })?;
+
+        match result {
+            Ok(()) => Ok(digest),
+            Err(e) => Err(path_error(&e, "write_file", &file_path, "Failed to write file")),
+        }
+    }
+
+    /// Replace the contents of `file_path` with `content`, creating the file
+    /// if missing, so readers see either the former contents or `content`,
+    /// never a part of it
+    ///
+    /// `content` goes to a temporary file next to `file_path`, synced and
+    /// renamed over it, then the parent directory is synced. An existing
+    /// file keeps its permissions.
+    #[args(timeout = "None")]
+    fn write_file_atomic(&self, file_path: OsString, content: Vec<u8>, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            upload::write_file_atomic(localfs.as_ref(), &self.ctx, &file_path, &content).await
+        })?;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(path_error(&e, "write_file_atomic", &file_path, "Failed to write file atomically")),
+        }
+    }
+
+    /// The digest with the algorithm `algo`, `"crc32c"`, `"sha256"` or
+    /// `"blake3"`, of the contents of `file_path`, streamed rather than
+    /// loaded in memory
+    ///
+    /// With `cache`, the digest is kept in an extended attribute of the file
+    /// until it is written to, so asking again is cheap.
+    #[args(algo = "\"sha256\"", cache = "false", timeout = "None")]
+    fn file_digest(
+        &self,
+        file_path: OsString,
+        algo: &str,
+        cache: bool,
+        timeout: Option<f64>,
+    ) -> PyResult<String> {
+        let algorithm = algo
+            .parse::<DigestAlgorithm>()
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            digest::file_digest(localfs.as_ref(), &self.ctx, attr.ino, algorithm, cache).await
+        })?;
+
+        result.map_err(|e| path_error(&e, "file_digest", &file_path, "Failed to digest file"))
+    }
+
+    /// Read `file_path` as `bytes`, raising `errors.Corruption` if its
+    /// contents do not have the digest `expected_digest` returned by
+    /// `write_file`
+    #[args(timeout = "None", expected_digest = "None")]
+    fn read_file(
+        &self,
+        py: Python,
+        file_path: OsString,
+        timeout: Option<f64>,
+        expected_digest: Option<&str>,
+    ) -> PyResult<PyObject> {
+        if let Some(Err(e)) = expected_digest.map(digest::algorithm_of) {
+            return Err(pyo3::exceptions::PyValueError::new_err(e.to_string()));
+        }
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let cache = sdk::cache(localfs);
+            if let Some(handle) = cache.take_warm(&self.ctx, &file_path).await {
+                let result = async {
+                    let (_, attr) = localfs.getattr(&self.ctx, handle.ino).await?;
+                    let mut buf = self.buffer_pool.acquire(attr.size as usize);
+                    let len = buf.len() as u32;
+                    let size = localfs.read(&self.ctx, handle.ino, handle.fh, 0, len, &mut buf).await?;
+                    Ok((buf, size))
+                }
+                .await;
+                cache.put_warm(handle).await;
+                return result;
             }
-            Err(_) => Err(pyo3::exceptions::PyOSError::new_err("Failed to get file metadata")),
+
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            let fh = localfs.open(&self.ctx, attr.ino, OFlag::O_RDONLY.bits() as u32).await?;
+            let mut buf = self.buffer_pool.acquire(attr.size as usize);
+            let result = localfs.read(&self.ctx, attr.ino, fh, 0, buf.len() as u32, &mut buf).await;
+            localfs.release(&self.ctx, attr.ino, fh, 0, 0, true).await?;
+            result.map(|size| (buf, size))
+        })?;
+
+        let checked = result.and_then(|(buf, size)| match expected_digest {
+            Some(expected) => digest::verify(&buf[..size], expected).map(|()| (buf, size)),
+            None => Ok((buf, size)),
+        });
+        match checked {
+            // Copied once, from the pooled buffer into the `bytes`
+            Ok((buf, size)) => Ok(PyBytes::new(py, &buf[..size]).into()),
+            Err(e) => Err(path_error(&e, "read_file", &file_path, "Failed to read file")),
         }
     }
 
-    fn write_file(&self, file_path: &str, content: Vec<u8>) -> PyResult<()> {
-        let sdk_ref = &self.localfs;
-        let rt = Runtime::new().unwrap();
-        let result = rt.block_on(async {
-            let localfs = sdk_ref.lock().unwrap();
-            localfs.write(34734588, 0, 0, &content, 0).await // 示例 inode
+    /// Read `file_path` from `offset` straight into `buffer`, any writable
+    /// C-contiguous object of the buffer protocol such as a `bytearray`, a
+    /// `memoryview` or a numpy array, and return the number of bytes read
+    ///
+    /// Not available in the stable ABI build, which has no buffer protocol.
+    #[cfg(not(feature = "abi3"))]
+    #[args(offset = "0", timeout = "None")]
+    fn read_into(
+        &self,
+        py: Python,
+        file_path: OsString,
+        buffer: &PyAny,
+        offset: u64,
+        timeout: Option<f64>,
+    ) -> PyResult<usize> {
+        // Viewed as bytes whatever the item type
+        let view = py.import("builtins")?.getattr("memoryview")?.call1((buffer,))?;
+        let view = PyBuffer::<u8>::get(view.call_method1("cast", ("B",))?)?;
+        let mut target = ffi::py_buffer_bytes(&view)
+            .ok_or_else(|| pyo3::exceptions::PyTypeError::new_err("buffer must be writable"))?;
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let buf = target.as_mut_slice();
+            let len = buf.len().min(u32::MAX as usize) as u32;
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            let fh = localfs.open(&self.ctx, attr.ino, OFlag::O_RDONLY.bits() as u32).await?;
+            let result = localfs.read(&self.ctx, attr.ino, fh, offset, len, buf).await;
+            localfs.release(&self.ctx, attr.ino, fh, 0, 0, true).await?;
+            result
         });
+        view.release(py);
 
-        if result.is_ok() {
-            Ok(())
-        } else {
-            Err(pyo3::exceptions::PyOSError::new_err("Failed to write file"))
+        result?.map_err(|e| path_error(&e, "read_into", &file_path, "Failed to read file"))
+    }
+
+    /// The offset of the next data or hole at or after `offset` of
+    /// `file_path`, with `whence` being `os.SEEK_DATA` or `os.SEEK_HOLE`,
+    /// `None` past the end of the file or when no data follows
+    #[args(timeout = "None")]
+    fn lseek(&self, file_path: OsString, offset: u64, whence: i32, timeout: Option<f64>) -> PyResult<Option<u64>> {
+        let whence = SeekWhence::from_raw(whence)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            let fh = localfs.open(&self.ctx, attr.ino, OFlag::O_RDONLY.bits() as u32).await?;
+            let result = localfs.lseek(&self.ctx, attr.ino, fh, offset, whence).await;
+            localfs.release(&self.ctx, attr.ino, fh, 0, 0, true).await?;
+            result
+        })?;
+
+        result.map_err(|e| path_error(&e, "lseek", &file_path, "Failed to seek file"))
+    }
+
+    /// Iterate over the bytes of `file_path` from `offset`, at most
+    /// `length` of them or up to the end without one, as `bytes` chunks of
+    /// at most `chunk_size` bytes, the block size of the backend by default
+    ///
+    /// Chunks are read one at a time as the iteration goes, so large files
+    /// are never held whole in memory.
+    #[args(offset = "0", length = "None", chunk_size = "None", timeout = "None")]
+    fn read_stream(
+        &self,
+        file_path: OsString,
+        offset: u64,
+        length: Option<u64>,
+        chunk_size: Option<usize>,
+        timeout: Option<f64>,
+    ) -> PyResult<ReadStreamIter> {
+        if chunk_size == Some(0) {
+            return Err(pyo3::exceptions::PyValueError::new_err("chunk_size must be positive"));
+        }
+        let localfs = &self.localfs()?;
+        let chunk_size = chunk_size.unwrap_or_else(|| localfs.block_layout().block_len());
+        let result = self.block_on(timeout, localfs.lookup(&self.ctx, ROOT_ID, &file_path))?;
+
+        let (_, attr, _) = result.map_err(|e| path_error(&e, "read_stream", &file_path, "Failed to read file"))?;
+        let chunks = stream::read_stream(
+            Arc::clone(localfs),
+            self.ctx,
+            attr.ino,
+            offset,
+            length.unwrap_or(u64::MAX),
+            chunk_size,
+        );
+        Ok(ReadStreamIter {
+            chunks: Some(Box::pin(chunks)),
+        })
+    }
+
+    /// Open `file_path` read-only ahead of time, so `read_file` of it skips
+    /// the lookup and open
+    #[args(timeout = "None")]
+    fn warm(&self, file_path: OsString, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = self.localfs()?;
+        let result = self.block_on(timeout, sdk::cache(&localfs).warm(&self.ctx, &file_path))?;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(path_error(&e, "warm", &file_path, "Failed to warm file")),
+        }
+    }
+
+    /// List the directory `dir_path`, with the attributes of every entry if `plus`
+    #[args(plus = "false", timeout = "None")]
+    fn readdir(&self, py: Python, dir_path: OsString, plus: bool, timeout: Option<f64>) -> PyResult<Vec<PyDirEntry>> {
+        self.list_entries("readdir", &dir_path, plus, timeout)?
+            .into_iter()
+            .map(|entry| PyDirEntry::new(py, entry))
+            .collect()
+    }
+
+    /// List the names in the directory `dir_path`, or with `detail` the
+    /// entries with their attributes, fetched in one pass like `readdir(plus=True)`
+    #[args(detail = "false", timeout = "None")]
+    fn list_dir(&self, py: Python, dir_path: OsString, detail: bool, timeout: Option<f64>) -> PyResult<PyObject> {
+        let entries = self.list_entries("list_dir", &dir_path, detail, timeout)?;
+        if !detail {
+            let names: Vec<OsString> = entries.into_iter().map(|entry| entry.name).collect();
+            return Ok(names.into_py(py));
+        }
+        let entries = entries
+            .into_iter()
+            .map(|entry| PyDirEntry::new(py, entry))
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(entries.into_py(py))
+    }
+
+    /// Iterate over every entry below `dir_path`, listing at most
+    /// `concurrency` directories at once
+    ///
+    /// Entries come in no particular order, symbolic links are yielded but
+    /// not followed.
+    #[args(concurrency = "DEFAULT_WALK_CONCURRENCY")]
+    fn walk(&self, dir_path: OsString, concurrency: usize) -> PyResult<WalkIter> {
+        let _call = self.enter()?;
+        let runtime = Runtime::new().unwrap();
+        let walk = walk::walk(self.localfs()?, self.ctx, runtime.handle(), &dir_path, concurrency);
+        Ok(WalkIter { walk, runtime })
+    }
+
+    /// Iterate over the changes to `path` and its children, or everything
+    /// below it with `recursive`, as `Event`s, like inotify
+    ///
+    /// Changes are queued from the call on, until read. Iterating blocks
+    /// until the next change and stops once the SDK is closed or `close` is
+    /// called on the iterator.
+    #[args(recursive = "false", timeout = "None")]
+    fn watch(&self, path: OsString, recursive: bool, timeout: Option<f64>) -> PyResult<WatchIter> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            sdk::notify(localfs).watch(&self.ctx, Path::new(&path), recursive).await
+        })?;
+
+        let watch = result.map_err(|e| path_error(&e, "watch", &path, "Failed to watch"))?;
+        Ok(WatchIter { watch: Some(watch) })
+    }
+
+    /// Iterate over the entries matching `pattern`, like `walk`
+    ///
+    /// `?` matches one character and `*` any characters within a path
+    /// component, `**` matches any number of whole components.
+    #[args(concurrency = "DEFAULT_WALK_CONCURRENCY")]
+    fn glob(&self, pattern: &str, concurrency: usize) -> PyResult<WalkIter> {
+        let _call = self.enter()?;
+        let runtime = Runtime::new().unwrap();
+        let walk = walk::glob(self.localfs()?, self.ctx, runtime.handle(), pattern, concurrency);
+        Ok(WalkIter { walk, runtime })
+    }
+
+    /// The paths added, removed or modified from directory `old_path` to
+    /// directory `new_path`, sorted by path
+    ///
+    /// Entries of both directories are modified when their types or sizes
+    /// differ, or their modification times unless they are directories. With
+    /// `checksum`, files of the same size are compared by the checksums of
+    /// their contents instead of their modification times.
+    #[args(checksum = "false", concurrency = "DEFAULT_WALK_CONCURRENCY", timeout = "None")]
+    fn diff(
+        &self,
+        py: Python,
+        old_path: &str,
+        new_path: &str,
+        checksum: bool,
+        concurrency: usize,
+        timeout: Option<f64>,
+    ) -> PyResult<Vec<PyChange>> {
+        let localfs = &self.localfs()?;
+        let options = DiffOptions { checksum, concurrency };
+        let result = self.block_on(timeout, async {
+            diff::diff(Arc::clone(localfs), old_path, Arc::clone(localfs), new_path, self.ctx, &options).await
+        })?;
+        match result {
+            Ok(changeset) => changeset.changes.into_iter().map(|change| PyChange::new(py, change)).collect(),
+            Err(e) => Err(path_error(&e, "diff", old_path, "Failed to compare directories")),
         }
     }
 
-    fn read_file(&self, file_path: &str) -> PyResult<Vec<u8>> {
-        let sdk_ref = &self.localfs;
-        let rt = Runtime::new().unwrap();
-        let mut buf = BytesMut::new();
-        let result = rt.block_on(async {
-            buf.reserve(1024);
-            let localfs = sdk_ref.lock().unwrap();
-            localfs.read(1, 0, 0, 1024, &mut buf).await.map_err(|_| ()) // 示例 inode
+    /// Open the append-only log at `file_path`, creating it when missing
+    ///
+    /// `sync` is `"none"`, syncing only on `sync` and `close`, `"batch"`,
+    /// syncing before appends return, or `"interval"`, syncing at most every
+    /// `sync_interval_ms` milliseconds.
+    #[args(sync = "\"batch\"", sync_interval_ms = "1000")]
+    fn open_log(&self, file_path: OsString, sync: &str, sync_interval_ms: u64) -> PyResult<PyAppendLog> {
+        let sync = parse_log_sync(sync, sync_interval_ms)?;
+        let _call = self.enter()?;
+        let runtime = Runtime::new().unwrap();
+        let log = runtime
+            .block_on(AppendLog::open(self.localfs()?, self.ctx, &file_path, sync))
+            .map_err(|e| path_error(&e, "open_log", &file_path, "Failed to open log"))?;
+        Ok(PyAppendLog { log: Some(log), runtime })
+    }
+
+    /// Append `record` to the log at `file_path`, creating it when missing,
+    /// and return the offset it was written at once synced
+    ///
+    /// Appends to the same file through this SDK are atomic and ordered,
+    /// whichever thread makes them. Records are framed as by `open_log`,
+    /// which must not append to the same file meanwhile.
+    fn append(&self, py: Python, file_path: OsString, record: &[u8]) -> PyResult<u64> {
+        let _call = self.enter()?;
+        let process = self.process()?;
+        py.allow_threads(|| process.logs_runtime.block_on(process.logs.append(&file_path, record)))
+            .map_err(|e| path_error(&e, "append", &file_path, "Failed to append to log"))
+    }
+
+    /// Iterate over the records of the log at `file_path` from `from_offset`
+    /// on as `(offset, record)` tuples, waiting for those `append` adds
+    /// until the iterator is closed
+    #[args(from_offset = "0")]
+    fn tail(&self, py: Python, file_path: OsString, from_offset: u64) -> PyResult<TailIter> {
+        let _call = self.enter()?;
+        let process = self.process()?;
+        let tail = py
+            .allow_threads(|| process.logs_runtime.block_on(process.logs.tail(&file_path, from_offset)))
+            .map_err(|e| path_error(&e, "tail", &file_path, "Failed to tail log"))?;
+        Ok(TailIter { tail: Some(tail) })
+    }
+
+    /// Open the key-value store in the directory `dir_path`, creating it
+    /// when missing
+    ///
+    /// Values are packed into segment files of about `segment_bytes` each;
+    /// `sync` is `"none"`, `"batch"` or `"interval"` as for `open_log`.
+    #[args(sync = "\"batch\"", sync_interval_ms = "1000", segment_bytes = "64 << 20")]
+    fn open_kv(&self, dir_path: OsString, sync: &str, sync_interval_ms: u64, segment_bytes: u64) -> PyResult<PyKvStore> {
+        let sync = parse_log_sync(sync, sync_interval_ms)?;
+        let options = KvOptions { segment_bytes, sync };
+        let _call = self.enter()?;
+        let runtime = Runtime::new().unwrap();
+        let kv = runtime
+            .block_on(KvStore::open(self.localfs()?, self.ctx, Path::new(&dir_path), options))
+            .map_err(|e| path_error(&e, "open_kv", &dir_path, "Failed to open key-value store"))?;
+        Ok(PyKvStore { kv: Some(kv), runtime })
+    }
+
+    /// Reopen the filesystem and restart the background tasks in a forked
+    /// child, e.g. a DataLoader worker, a no-op in the process that opened
+    /// the SDK
+    ///
+    /// The first call of a child reopens them anyway, this only moves the
+    /// cost and the errors up front. Data written by the parent but not
+    /// synced before the fork is not visible to the child.
+    fn reinit_after_fork(&self) -> PyResult<()> {
+        self.process().map(drop)
+    }
+
+    /// Close the SDK, waiting at most `timeout` seconds for the calls still
+    /// running in other threads, or without limit
+    ///
+    /// Calls made afterwards raise `BrokenPipeError`. Once the running calls
+    /// finished the written data is synced and the background tasks stop.
+    /// Raises `TimeoutError` if calls are still running after the timeout,
+    /// closing again waits again. Also called when leaving a `with` block.
+    #[args(timeout = "None")]
+    fn close(&self, py: Python, timeout: Option<f64>) -> PyResult<()> {
+        let timeout = timeout.map(Duration::try_from_secs_f64).transpose().map_err(|_| {
+            pyo3::exceptions::PyValueError::new_err("timeout must be a non-negative number of seconds")
+        })?;
+        if !py.allow_threads(|| self.calls.close(timeout)) {
+            return Err(sdk_error(
+                Some(Errno::ETIMEDOUT),
+                "Timed out waiting for the running calls".to_owned(),
+                Some("close"),
+                None,
+            ));
+        }
+        let process = self.process()?;
+        let Some(writeback) = process.writeback.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let logs = py.allow_threads(|| process.logs_runtime.block_on(process.logs.close()));
+        let localfs = &process.localfs;
+        let result = block_on(None, async { localfs.sync_all(&self.ctx).await })?;
+        logs.map_err(|e| os_error(&e, "close", "Failed to close logs"))?;
+        py.allow_threads(|| {
+            drop(process.lifecycle.lock().unwrap().take());
+            drop(process.purge.lock().unwrap().take());
+            drop(process.gc.lock().unwrap().take());
+            drop(writeback);
         });
+        result.map_err(|e| os_error(&e, "close", "Failed to sync filesystem"))
+    }
+
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __exit__(&self, py: Python, _exc_type: &PyAny, _exc_value: &PyAny, _traceback: &PyAny) -> PyResult<bool> {
+        self.close(py, None)?;
+        Ok(false)
+    }
+
+    #[args(timeout = "None")]
+    fn sync_all(&self, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            localfs.sync_all(&self.ctx).await
+        })?;
 
         match result {
-            Ok(size) =>  {
-                Ok(Vec::from(&buf[..size]))
+            Ok(()) => Ok(()),
+            Err(e) => Err(os_error(&e, "sync_all", "Failed to sync filesystem")),
+        }
+    }
+
+    /// The files matching `query` in the search index, at most `limit` of
+    /// them and best first
+    ///
+    /// Bare words match parts of names, `path:`, `kind:`, `size:` and
+    /// `mtime:` restrict the other fields, e.g. `report kind:file size:>1000`.
+    #[args(limit = "100")]
+    fn search(&self, query: &str, limit: usize) -> PyResult<Vec<PySearchHit>> {
+        self.search_hits(query, limit)
+    }
+
+    /// Tag `file_path` with `key`=`value`, replacing the former value
+    #[args(timeout = "None")]
+    fn set_tag(&self, file_path: OsString, key: &str, value: &str, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            tags::set_tag(localfs.as_ref(), &self.ctx, attr.ino, key, value).await
+        })?;
+
+        result.map_err(|e| tag_error(&e, "set_tag", Some(&file_path), "Failed to set tag"))
+    }
+
+    /// Remove the tag `key` from `file_path`
+    #[args(timeout = "None")]
+    fn remove_tag(&self, file_path: OsString, key: &str, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            tags::remove_tag(localfs.as_ref(), &self.ctx, attr.ino, key).await
+        })?;
+
+        result.map_err(|e| tag_error(&e, "remove_tag", Some(&file_path), "Failed to remove tag"))
+    }
+
+    /// The tags of `file_path` as a dict
+    #[args(timeout = "None")]
+    fn get_tags(&self, file_path: OsString, timeout: Option<f64>) -> PyResult<BTreeMap<String, String>> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            tags::get_tags(localfs.as_ref(), &self.ctx, attr.ino).await
+        })?;
+
+        result.map_err(|e| path_error(&e, "get_tags", &file_path, "Failed to get tags"))
+    }
+
+    /// The versions of `file_path`, oldest first, as `(version_id, size,
+    /// mtime_ns)` tuples, none unless `versioning` is on in the config
+    #[args(timeout = "None")]
+    fn list_versions(&self, file_path: OsString, timeout: Option<f64>) -> PyResult<Vec<(u64, u64, i128)>> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            sdk::versioning(localfs).list_versions(&self.ctx, attr.ino).await
+        })?;
+
+        let versions = result.map_err(|e| path_error(&e, "list_versions", &file_path, "Failed to list versions"))?;
+        Ok(versions
+            .into_iter()
+            .map(|version| (version.id, version.size, timestamp_ns(version.mtime)))
+            .collect())
+    }
+
+    /// The content of the version `version_id` of `file_path`
+    #[args(timeout = "None")]
+    fn read_version(&self, file_path: OsString, version_id: u64, timeout: Option<f64>) -> PyResult<Vec<u8>> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            sdk::versioning(localfs).read_version(&self.ctx, attr.ino, version_id).await
+        })?;
+
+        result.map_err(|e| path_error(&e, "read_version", &file_path, "Failed to read version"))
+    }
+
+    /// Give `file_path` the content of its version `version_id`, keeping
+    /// the content it replaces as a new version
+    #[args(timeout = "None")]
+    fn restore_version(&self, file_path: OsString, version_id: u64, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let (_, attr, _) = localfs.lookup(&self.ctx, ROOT_ID, &file_path).await?;
+            sdk::versioning(localfs).restore_version(&self.ctx, attr.ino, version_id).await
+        })?;
+
+        result.map_err(|e| path_error(&e, "restore_version", &file_path, "Failed to restore version"))
+    }
+
+    /// The entries removed to the trash, oldest removal first, as
+    /// `(path, deleted_ns, stat)` tuples, none unless `trash` is on in the
+    /// config
+    #[args(timeout = "None")]
+    fn list_trash(&self, py: Python, timeout: Option<f64>) -> PyResult<Vec<(OsString, i128, Py<StatResult>)>> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async { trash::list_trash(localfs.as_ref(), &self.ctx).await })?;
+
+        let entries = result.map_err(|e| os_error(&e, "list_trash", "Failed to list trash"))?;
+        entries
+            .into_iter()
+            .map(|entry| {
+                let stat = Py::new(py, StatResult::from(&entry.attr))?;
+                Ok((entry.path.into_os_string(), timestamp_ns(entry.deleted_at), stat))
+            })
+            .collect()
+    }
+
+    /// Move the entry last removed from `file_path` back from the trash,
+    /// creating its missing parent directories
+    #[args(timeout = "None")]
+    fn restore(&self, file_path: OsString, timeout: Option<f64>) -> PyResult<StatResult> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            trash::restore(localfs.as_ref(), &self.ctx, Path::new(&file_path)).await
+        })?;
+
+        match result {
+            Ok(attr) => Ok(StatResult::from(&attr)),
+            Err(e) => Err(path_error(&e, "restore", &file_path, "Failed to restore from trash")),
+        }
+    }
+
+    /// Remove for good the entries removed to the trash at least
+    /// `older_than` seconds ago, returning how many
+    #[args(older_than = "0.0", timeout = "None")]
+    fn purge(&self, older_than: f64, timeout: Option<f64>) -> PyResult<usize> {
+        let older_than = Duration::try_from_secs_f64(older_than)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            trash::purge(localfs.as_ref(), &self.ctx, older_than).await
+        })?;
+
+        result.map_err(|e| os_error(&e, "purge", "Failed to purge trash"))
+    }
+
+    /// Remove the backend data no file refers to any more, unless changed
+    /// within the last `grace` seconds, or only report it with `dry_run`,
+    /// returning the orphans found and the bytes they take
+    #[args(grace = "3600.0", dry_run = "false", timeout = "None")]
+    fn collect_garbage(&self, grace: f64, dry_run: bool, timeout: Option<f64>) -> PyResult<(Vec<String>, u64)> {
+        let grace = Duration::try_from_secs_f64(grace)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        let localfs = self.localfs()?;
+        let packed = sdk::packed(&localfs);
+        let result = self.block_on(timeout, gc::collect(packed, &self.ctx, grace, dry_run))?;
+
+        result
+            .map(|report| (report.orphans, report.bytes))
+            .map_err(|e| os_error(&e, "collect_garbage", "Failed to collect garbage"))
+    }
+
+    /// Start a multipart upload to `file_path`, whose parent directory must
+    /// exist, returning its id
+    ///
+    /// Parts are stored as they are uploaded, so an upload interrupted by a
+    /// crash is resumed from `list_uploads` and `upload_parts`.
+    #[args(timeout = "None")]
+    fn start_upload(&self, file_path: OsString, timeout: Option<f64>) -> PyResult<String> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            upload::start_upload(localfs.as_ref(), &self.ctx, Path::new(&file_path)).await
+        })?;
+
+        result.map_err(|e| path_error(&e, "start_upload", &file_path, "Failed to start upload"))
+    }
+
+    /// Store `data` as part `index` of upload `upload_id`, replacing the
+    /// part uploaded before with that index, and return its SHA-256 as hex
+    #[args(timeout = "None")]
+    fn upload_part(&self, upload_id: &str, index: u32, data: &[u8], timeout: Option<f64>) -> PyResult<String> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            upload::upload_part(localfs.as_ref(), &self.ctx, upload_id, index, data).await
+        })?;
+
+        match result {
+            Ok(part) => Ok(part.checksum),
+            Err(e) => Err(os_error(&e, "upload_part", "Failed to upload part")),
+        }
+    }
+
+    /// The parts of upload `upload_id` as `(index, size, checksum)` tuples,
+    /// by index
+    #[args(timeout = "None")]
+    fn upload_parts(&self, upload_id: &str, timeout: Option<f64>) -> PyResult<Vec<(u32, u64, String)>> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            upload::upload_parts(localfs.as_ref(), &self.ctx, upload_id).await
+        })?;
+
+        let parts = result.map_err(|e| os_error(&e, "upload_parts", "Failed to list upload parts"))?;
+        Ok(parts
+            .into_iter()
+            .map(|part| (part.index, part.size, part.checksum))
+            .collect())
+    }
+
+    /// Concatenate the parts of upload `upload_id`, indexed from 0 with no
+    /// gap, into its target, checking their checksums
+    #[args(timeout = "None")]
+    fn complete_upload(&self, upload_id: &str, timeout: Option<f64>) -> PyResult<StatResult> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            upload::complete_upload(localfs.as_ref(), &self.ctx, upload_id).await
+        })?;
+
+        match result {
+            Ok(attr) => Ok(StatResult::from(&attr)),
+            Err(e) => Err(os_error(&e, "complete_upload", "Failed to complete upload")),
+        }
+    }
+
+    /// Give up upload `upload_id`, removing its parts
+    #[args(timeout = "None")]
+    fn abort_upload(&self, upload_id: &str, timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            upload::abort_upload(localfs.as_ref(), &self.ctx, upload_id).await
+        })?;
+
+        result.map_err(|e| os_error(&e, "abort_upload", "Failed to abort upload"))
+    }
+
+    /// The uploads in progress, oldest first, as `(upload_id, path,
+    /// started_ns)` tuples
+    #[args(timeout = "None")]
+    fn list_uploads(&self, timeout: Option<f64>) -> PyResult<Vec<(String, OsString, i128)>> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async { upload::list_uploads(localfs.as_ref(), &self.ctx).await })?;
+
+        let uploads = result.map_err(|e| os_error(&e, "list_uploads", "Failed to list uploads"))?;
+        Ok(uploads
+            .into_iter()
+            .map(|upload| (upload.id, upload.path.into_os_string(), timestamp_ns(upload.started_at)))
+            .collect())
+    }
+
+    /// How far the secondaries of the `replication` config are behind, as
+    /// the number of changed paths not mirrored yet and a `(root, in_sync,
+    /// replicated, failures, last_error)` tuple per secondary, `None`
+    /// without secondaries
+    fn replication_status(&self) -> PyResult<Option<(usize, Vec<SecondaryTuple>)>> {
+        let localfs = self.localfs()?;
+        let Some(status) = sdk::replicated(&localfs).replication_status() else {
+            return Ok(None);
+        };
+        let secondaries = status
+            .secondaries
+            .into_iter()
+            .map(|secondary| {
+                (
+                    secondary.root.into_os_string(),
+                    secondary.in_sync,
+                    secondary.replicated,
+                    secondary.failures,
+                    secondary.last_error,
+                )
+            })
+            .collect();
+        Ok(Some((status.pending, secondaries)))
+    }
+
+    /// Repair the chunks of the `striping` config that are missing, corrupt
+    /// or stale, returning how many were rewritten and the `(inode, stripe)`
+    /// pairs lost for good
+    #[args(timeout = "None")]
+    fn rebuild_stripes(&self, timeout: Option<f64>) -> PyResult<(u64, Vec<(u64, u64)>)> {
+        let localfs = self.localfs()?;
+        let striped = sdk::striped(&localfs);
+        let result = self.block_on(timeout, striped.rebuild())?;
+
+        result
+            .map(|report| (report.repaired, report.lost))
+            .map_err(|e| os_error(&e, "rebuild_stripes", "Failed to rebuild stripes"))
+    }
+
+    /// How much the deduplicated blocks save, as `(blocks, stored_bytes,
+    /// referenced_bytes)`, `None` unless the namespace has the `dedup`
+    /// feature
+    fn dedup_stats(&self) -> PyResult<Option<(u64, u64, u64)>> {
+        let localfs = self.localfs()?;
+        let stats = sdk::dedup(&localfs).stats();
+        Ok(stats.map(|stats| (stats.blocks, stats.stored_bytes, stats.referenced_bytes)))
+    }
+
+    /// How much the segments of the packed files hold, as `(files, segments,
+    /// live_bytes, dead_bytes)`, `None` unless the namespace has the
+    /// `packing` feature
+    fn pack_stats(&self) -> PyResult<Option<(u64, u64, u64, u64)>> {
+        let localfs = self.localfs()?;
+        let stats = sdk::packed(&localfs)
+            .stats()
+            .map_err(|e| os_error(&e, "pack_stats", "Failed to read the packing stats"))?;
+        Ok(stats.map(|stats| (stats.files, stats.segments, stats.live_bytes, stats.dead_bytes)))
+    }
+
+    /// Change the config values in the JSON object `update` while running,
+    /// keeping the open files: `attr_cache_capacity`, `op_timeout_ms` (0
+    /// removes the timeout), `retry` and `log_level`
+    ///
+    /// Other fields and unknown log levels raise `ValueError`, as the
+    /// fields only take effect when the SDK is opened.
+    fn update_config(&self, update: &str) -> PyResult<()> {
+        let _call = self.enter()?;
+        let error = |e: DatenLordError| match e {
+            DatenLordError::InvalidArgument { .. } => pyo3::exceptions::PyValueError::new_err(e.to_string()),
+            _ => os_error(&e, "update_config", "Failed to update the config"),
+        };
+        let update = ConfigUpdate::parse(update).map_err(error)?;
+        let localfs = self.localfs()?;
+        sdk::update_config(&localfs, &update).map_err(error)?;
+        update.apply(&mut self.config.lock().unwrap());
+        Ok(())
+    }
+
+    /// The calls made through the SDK, the bytes they moved, how the cache
+    /// did and the handles and queues held now, as a dict, e.g.
+    /// `{"ops": {"read": {"calls": 3, "errors": 0}}, "bytes_read": 12, ...}`
+    fn get_stats(&self, py: Python) -> PyResult<PyObject> {
+        let localfs = self.localfs()?;
+        let stats = serde_json::to_string(&sdk::stats(&localfs))
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(py.import("json")?.call_method1("loads", (stats,))?.into())
+    }
+
+    /// Probe the filesystem end to end, reading the root, writing a few
+    /// bytes and reading them back, as a dict with `healthy`, the latency of
+    /// each step in microseconds and the `error` of the step failing, e.g.
+    /// for the readiness probe of a service
+    ///
+    /// With a `timeout` in seconds, a step still running then fails the
+    /// probe.
+    #[args(timeout = "None")]
+    fn healthcheck(&self, py: Python, timeout: Option<f64>) -> PyResult<PyObject> {
+        let localfs = self.localfs()?;
+        let (report, _) = self.block_on(timeout, sdk::healthcheck(&localfs, &self.ctx))?;
+        let report = serde_json::to_string(&report)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+        Ok(py.import("json")?.call_method1("loads", (report,))?.into())
+    }
+
+    /// The files of the search index matching the tag `filter`, at most
+    /// `limit` of them
+    ///
+    /// The filter is a comma separated list of `key=value` terms, matching
+    /// that value, and bare `key` terms, matching any value, e.g. `team=data,cold`.
+    #[args(limit = "100")]
+    fn find_by_tags(&self, filter: &str, limit: usize) -> PyResult<Vec<PySearchHit>> {
+        let filter: TagFilter = filter.parse().map_err(|e| tag_error(&e, "find_by_tags", None, "Invalid tag filter"))?;
+        self.tagged_hits(&filter, limit)
+    }
+}
+
+impl DatenlordSDK {
+    /// Run `fut` like the free `block_on`, as a call `close` waits for,
+    /// raising `BrokenPipeError` once closed
+    fn block_on<F: Future>(&self, timeout: Option<f64>, fut: F) -> PyResult<F::Output> {
+        let _call = self.enter()?;
+        block_on(timeout, fut)
+    }
+
+    /// Replace the contents of `file_path` by `content`, creating it if
+    /// missing
+    fn replace_file(&self, file_path: &OsStr, content: &[u8], timeout: Option<f64>) -> PyResult<()> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, async {
+            let attr = match localfs.lookup(&self.ctx, ROOT_ID, file_path).await {
+                Ok((_, attr, _)) => attr,
+                Err(DatenLordError::NotFound { .. }) => {
+                    let param = CreateParam {
+                        parent: ROOT_ID,
+                        name: file_path.to_owned(),
+                        mode: 0o666,
+                        rdev: 0,
+                        node_type: SFlag::S_IFREG,
+                        link: None,
+                    };
+                    localfs.mknod(&self.ctx, param).await?.1
+                }
+                Err(e) => return Err(e),
+            };
+            let fh = localfs.open(&self.ctx, attr.ino, OFlag::O_WRONLY.bits() as u32).await?;
+            let result = async {
+                let truncate = SetAttrParam {
+                    fh: Some(fh),
+                    size: Some(0),
+                    ..SetAttrParam::default()
+                };
+                localfs.setattr(&self.ctx, attr.ino, truncate).await?;
+                localfs.write(&self.ctx, attr.ino, fh, 0, content, 0).await
             }
-            Err(_) => Err(pyo3::exceptions::PyOSError::new_err("Failed to read file")),
+            .await;
+            localfs.release(&self.ctx, attr.ino, fh, 0, 0, true).await?;
+            result
+        })?;
+
+        result.map_err(|e| path_error(&e, "Path.write_bytes", file_path, "Failed to write file"))
+    }
+
+    /// The state of the SDK in the current process, reopened from the config
+    /// in a forked child on first use
+    fn process(&self) -> PyResult<Arc<Process>> {
+        let mut process = self.process.lock().unwrap();
+        if process.pid != std::process::id() {
+            let config = self.config.lock().unwrap().clone();
+            // The root of the context is kept, the prefix being resolved
+            let (reopened, _) = Process::open(&config, self.ctx, OsStr::new(""))
+                .map_err(|e| exception(&e, "Failed to reopen the SDK after a fork", None, None))?;
+            // Dropping the state of the parent would wait for threads that
+            // were not forked
+            std::mem::forget(std::mem::replace(&mut *process, Arc::new(reopened)));
         }
+        Ok(Arc::clone(&process))
+    }
+
+    /// The filesystem stack of the current process, see `process`
+    fn localfs(&self) -> PyResult<Arc<SdkFs>> {
+        Ok(Arc::clone(&self.process()?.localfs))
+    }
+
+    /// Admit a call `close` waits for, raising `BrokenPipeError` once closed
+    fn enter(&self) -> PyResult<Call> {
+        self.calls.enter().map_err(|e| exception(&e, "The SDK is closed", None, None))
+    }
+
+    /// Every entry of the directory `dir_path`, with attributes if `plus`, for
+    /// the method `operation`, as a snapshot of the directory, see
+    /// `sdk::list_dir`
+    fn list_entries(
+        &self,
+        operation: &str,
+        dir_path: &OsStr,
+        plus: bool,
+        timeout: Option<f64>,
+    ) -> PyResult<Vec<DirEntry>> {
+        let localfs = &self.localfs()?;
+        let result = self.block_on(timeout, sdk::list_dir(localfs, &self.ctx, dir_path, plus))?;
+
+        result.map_err(|e| path_error(&e, operation, dir_path, "Failed to read directory"))
+    }
+
+    /// Search the index of the config, see `search`
+    #[cfg(feature = "search")]
+    fn search_hits(&self, query: &str, limit: usize) -> PyResult<Vec<PySearchHit>> {
+        let Some(ref index) = self.search_index else {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "no search_index in the config",
+            ));
+        };
+        let hits = index.search(query, limit).map_err(|e| match e {
+            DatenLordError::InvalidArgument { .. } => pyo3::exceptions::PyValueError::new_err(e.to_string()),
+            _ => os_error(&e, "search", "Failed to search"),
+        })?;
+        Ok(hits.into_iter().map(PySearchHit::from).collect())
+    }
+
+    /// Search the index of the config by tags, see `find_by_tags`
+    #[cfg(feature = "search")]
+    fn tagged_hits(&self, filter: &TagFilter, limit: usize) -> PyResult<Vec<PySearchHit>> {
+        let Some(ref index) = self.search_index else {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "no search_index in the config",
+            ));
+        };
+        let hits = index
+            .find_by_tags(filter, limit)
+            .map_err(|e| os_error(&e, "find_by_tags", "Failed to search tags"))?;
+        Ok(hits.into_iter().map(PySearchHit::from).collect())
+    }
+
+    /// Search the index of the config by tags, see `find_by_tags`
+    #[cfg(not(feature = "search"))]
+    fn tagged_hits(&self, _filter: &TagFilter, _limit: usize) -> PyResult<Vec<PySearchHit>> {
+        Err(pyo3::exceptions::PyNotImplementedError::new_err(
+            "built without the search feature",
+        ))
+    }
+
+    /// Search the index of the config, see `search`
+    #[cfg(not(feature = "search"))]
+    fn search_hits(&self, _query: &str, _limit: usize) -> PyResult<Vec<PySearchHit>> {
+        Err(pyo3::exceptions::PyNotImplementedError::new_err(
+            "built without the search feature",
+        ))
     }
 }
 
 #[pyfunction]
-fn init_sdk() -> PyResult<DatenlordSDK> {
-    DatenlordSDK::new()
+fn init_sdk(config: Option<&str>, prefix: Option<OsString>) -> PyResult<DatenlordSDK> {
+    DatenlordSDK::new(config, prefix)
+}
+
+/// Sync the data written through the open files of every SDK and export the
+/// spans ended so far, also run when the interpreter exits
+#[pyfunction]
+fn flush_all(py: Python) {
+    py.allow_threads(|| {
+        writeback::flush_all();
+        logging::flush_traces();
+    });
 }
 
 #[pymodule]
 fn datenlord(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<DatenlordSDK>()?;
+    m.add_class::<StatResult>()?;
+    m.add_class::<PyDirEntry>()?;
+    m.add_class::<PySearchHit>()?;
+    m.add_class::<PyChange>()?;
+    m.add_class::<WalkIter>()?;
+    m.add_class::<PyEvent>()?;
+    m.add_class::<WatchIter>()?;
+    m.add_class::<PyAppendLog>()?;
+    m.add_class::<TailIter>()?;
+    m.add_class::<ReadStreamIter>()?;
+    m.add_class::<PyKvStore>()?;
+    m.add_class::<DatenlordPath>()?;
+    m.add_class::<Utime>()?;
+    m.add("UTIME_NOW", Utime::Now.into_py(py))?;
+    m.add("UTIME_OMIT", Utime::Omit.into_py(py))?;
+    m.add("RENAME_NOREPLACE", RenameFlags::RENAME_NOREPLACE.bits())?;
+    m.add("RENAME_EXCHANGE", RenameFlags::RENAME_EXCHANGE.bits())?;
     m.add_function(wrap_pyfunction!(init_sdk, m)?)?;
+    m.add_function(wrap_pyfunction!(flush_all, m)?)?;
+    let errors = PyModule::from_code(py, include_str!("errors.py"), "errors.py", "datenlord.errors")?;
+    // Importable as `datenlord.errors`, pickled exceptions being found there
+    py.import("sys")?.getattr("modules")?.set_item("datenlord.errors", errors)?;
+    m.add("errors", errors)?;
+    m.add("DatenlordError", errors.getattr("DatenlordError")?)?;
+    let _ = ERRORS.set(py, errors.into());
+    // PyTorch is optional, the datasets then being plain classes
+    let datasets = PyModule::from_code(py, include_str!("dataset.py"), "dataset.py", "datenlord.dataset")?;
+    datasets.setattr("DatenlordSDK", m.getattr("DatenlordSDK")?)?;
+    for name in ["DatenlordDataset", "DatenlordIterableDataset"] {
+        let dataset = datasets.getattr(name)?;
+        // Pickled for spawned DataLoader workers as `datenlord.<name>`
+        dataset.setattr("__module__", "datenlord")?;
+        m.add(name, dataset)?;
+    }
+    // Found by fsspec through the `fsspec.specs` entry point
+    if py.import("fsspec").is_ok() {
+        let adapter = PyModule::from_code(py, include_str!("fsspec.py"), "fsspec.py", "datenlord.fsspec")?;
+        adapter.setattr("DatenlordSDK", m.getattr("DatenlordSDK")?)?;
+        let filesystem = adapter.getattr("DatenlordFileSystem")?;
+        // Pickled by dask as `datenlord.DatenlordFileSystem`
+        filesystem.setattr("__module__", "datenlord")?;
+        m.add("DatenlordFileSystem", filesystem)?;
+    }
+    // SDKs still referenced at exit may never be collected
+    py.import("atexit")?.call_method1("register", (m.getattr("flush_all")?,))?;
     Ok(())
 }
@@ -0,0 +1,79 @@
+"""The exceptions raised by a datenlord SDK
+
+Every one is a `DatenlordError`, an `OSError` with the `errno` of the failure,
+or `None` when none applies, the `path` it happened on and the SDK method that
+failed as `operation`. They also derive from the builtin subclass python picks
+for their errno, e.g. `NotFound` from `FileNotFoundError`, so handlers written
+for the builtin exceptions keep catching them.
+"""
+import errno as _errno
+
+
+class DatenlordError(OSError):
+    """A failed SDK operation"""
+
+    def __init__(self, errno, message, path=None, operation=None):
+        if errno is None:
+            super().__init__(message)
+        elif path is None:
+            super().__init__(errno, message)
+        else:
+            super().__init__(errno, message, path)
+        self.path = path
+        self.operation = operation
+
+
+class NotFound(DatenlordError, FileNotFoundError):
+    """The path, or one of its parents, does not exist"""
+
+
+class PermissionDenied(DatenlordError, PermissionError):
+    """The caller may not access the path, or it leads out of the root"""
+
+
+class AlreadyExists(DatenlordError, FileExistsError):
+    """The path exists already"""
+
+
+class Timeout(DatenlordError, TimeoutError):
+    """The operation did not finish before its timeout"""
+
+
+class Corruption(DatenlordError):
+    """The data read does not have the digest expected"""
+
+
+_BY_ERRNO = {
+    _errno.ENOENT: NotFound,
+    _errno.EACCES: PermissionDenied,
+    _errno.EPERM: PermissionDenied,
+    _errno.EEXIST: AlreadyExists,
+    _errno.ETIMEDOUT: Timeout,
+    _errno.EBADMSG: Corruption,
+}
+
+
+def _class_for(errno):
+    """The exception raised for `errno`, a `DatenlordError` also deriving from
+    the builtin subclass of `OSError` for it, if any"""
+    if errno is None:
+        return DatenlordError
+    cls = _BY_ERRNO.get(errno)
+    if cls is None:
+        builtin = type(OSError(errno, ""))
+        if builtin is OSError:
+            cls = DatenlordError
+        else:
+            cls = type(builtin.__name__, (DatenlordError, builtin), {"__module__": __name__})
+        _BY_ERRNO[errno] = cls
+    return cls
+
+
+__all__ = [
+    "DatenlordError",
+    "NotFound",
+    "PermissionDenied",
+    "AlreadyExists",
+    "Timeout",
+    "Corruption",
+]
@@ -0,0 +1,140 @@
+"""An fsspec filesystem over a datenlord SDK, for `datenlord://` urls
+
+Defined by the `datenlord` module when fsspec is installed, which finds it
+through the `fsspec.specs` entry point, so pandas, pyarrow or dask open
+`datenlord://data/x.parquet` with `storage_options={"config": ...}`.
+"""
+import errno
+import os
+
+from fsspec.spec import AbstractBufferedFile, AbstractFileSystem
+
+
+class DatenlordFileSystem(AbstractFileSystem):
+    """Paths relative to the root of an SDK created from `config`, the json
+    taken by `init_sdk`, or of `sdk`"""
+
+    protocol = "datenlord"
+    root_marker = ""
+
+    def __init__(self, config=None, sdk=None, **storage_options):
+        super().__init__(**storage_options)
+        # Set by the `datenlord` module once this one is loaded
+        self.sdk = sdk if sdk is not None else DatenlordSDK(config)
+
+    @staticmethod
+    def _info(path, stat):
+        kind = {"file": "file", "directory": "directory"}.get(stat.kind, "other")
+        return {
+            "name": path,
+            "size": stat.st_size,
+            "type": kind,
+            "mode": stat.st_mode,
+            "mtime": stat.st_mtime_ns / 1e9,
+        }
+
+    def info(self, path, **kwargs):
+        path = self._strip_protocol(path)
+        try:
+            stat = self.sdk.stat(path)
+        except OSError as e:
+            # A file in the way of a parent directory leaves the path missing
+            if e.errno in (errno.ENOENT, errno.ENOTDIR):
+                raise FileNotFoundError(errno.ENOENT, os.strerror(errno.ENOENT), path) from e
+            raise
+        return self._info(path, stat)
+
+    def ls(self, path, detail=True, **kwargs):
+        path = self._strip_protocol(path)
+        if self.info(path)["type"] != "directory":
+            return [self.info(path)] if detail else [path]
+        prefix = path + "/" if path else ""
+        entries = [
+            self._info(prefix + entry.name, entry.stat)
+            for entry in self.sdk.readdir(path, plus=True)
+            if entry.name not in (".", "..")
+        ]
+        return entries if detail else [entry["name"] for entry in entries]
+
+    def exists(self, path, **kwargs):
+        return self.sdk.exists(self._strip_protocol(path))
+
+    def mkdir(self, path, create_parents=True, **kwargs):
+        path = self._strip_protocol(path)
+        if create_parents:
+            self.sdk.mkdir_all(path)
+        else:
+            self.sdk.mkdir(path)
+
+    def makedirs(self, path, exist_ok=False):
+        path = self._strip_protocol(path)
+        if not exist_ok and self.exists(path):
+            raise FileExistsError(errno.EEXIST, os.strerror(errno.EEXIST), path)
+        self.sdk.mkdir_all(path)
+
+    def rmdir(self, path):
+        self.sdk.deldir(self._strip_protocol(path), False)
+
+    def rm_file(self, path):
+        self.sdk.remove_file(self._strip_protocol(path))
+
+    def _rm(self, path):
+        if self.isdir(path):
+            self.rmdir(path)
+        else:
+            self.rm_file(path)
+
+    def mv(self, path1, path2, recursive=False, maxdepth=None, **kwargs):
+        self.sdk.rename_path(self._strip_protocol(path1), self._strip_protocol(path2))
+
+    def cat_file(self, path, start=None, end=None, **kwargs):
+        path = self._strip_protocol(path)
+        if start is None and end is None:
+            return self.sdk.read_file(path)
+        size = self.size(path)
+        start = 0 if start is None else start if start >= 0 else max(size + start, 0)
+        end = size if end is None else end if end >= 0 else max(size + end, 0)
+        return b"".join(self.sdk.read_stream(path, offset=start, length=max(end - start, 0)))
+
+    def pipe_file(self, path, value, **kwargs):
+        self.sdk.path(self._strip_protocol(path)).write_bytes(value)
+
+    def _open(self, path, mode="rb", block_size="default", autocommit=True, cache_options=None, **kwargs):
+        return DatenlordFile(
+            self,
+            self._strip_protocol(path),
+            mode,
+            block_size=block_size,
+            autocommit=autocommit,
+            cache_options=cache_options,
+            **kwargs,
+        )
+
+
+class DatenlordFile(AbstractBufferedFile):
+    """A file of a `DatenlordFileSystem`, read by ranges and written as the
+    parts of a multipart upload completed on close"""
+
+    def _fetch_range(self, start, end):
+        sdk = self.fs.sdk
+        return b"".join(sdk.read_stream(self.path, offset=start, length=max(end - start, 0)))
+
+    def _initiate_upload(self):
+        self.upload_id = self.fs.sdk.start_upload(self.path)
+        self.parts = 0
+
+    def _upload_chunk(self, final=False):
+        data = self.buffer.getvalue()
+        # An upload needs a part, even an empty one
+        if data or self.parts == 0:
+            self.fs.sdk.upload_part(self.upload_id, self.parts, data)
+            self.parts += 1
+        if final and self.autocommit:
+            self.commit()
+        return True
+
+    def commit(self):
+        self.fs.sdk.complete_upload(self.upload_id)
+
+    def discard(self):
+        self.fs.sdk.abort_upload(self.upload_id)
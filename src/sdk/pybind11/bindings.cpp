@@ -12,10 +12,48 @@ std::string handle_error(datenlord_error *err) {
         return "Success";
     }
     std::string message((const char *)err->message.data, err->message.len);
-    free(err);
+    datenlord::datenlord_error_free(err);
     return message;
 }
 
+py::dict stat_dict(const datenlord_stat &stat) {
+    return py::dict(
+        "ino"_a = stat.ino,
+        "size"_a = stat.size,
+        "blocks"_a = stat.blocks,
+        "mode"_a = stat.mode,
+        "kind"_a = static_cast<int>(stat.kind),
+        "nlink"_a = stat.nlink,
+        "uid"_a = stat.uid,
+        "gid"_a = stat.gid,
+        "atime"_a = py::make_tuple(stat.atime.sec, stat.atime.nsec),
+        "mtime"_a = py::make_tuple(stat.mtime.sec, stat.mtime.nsec),
+        "ctime"_a = py::make_tuple(stat.ctime.sec, stat.ctime.nsec)
+    );
+}
+
+// Drain `walk` into a list of (path, stat) tuples and close it
+py::list collect_walk(datenlord_walk *walk) {
+    if (walk == nullptr) {
+        throw std::invalid_argument("Invalid arguments");
+    }
+    py::list entries;
+    datenlord_walk_entry entry;
+    while (true) {
+        datenlord_error *err = datenlord::datenlord_walk_next(walk, &entry);
+        if (err != nullptr) {
+            datenlord::datenlord_walk_close(walk);
+            throw std::runtime_error(handle_error(err));
+        }
+        if (entry.path == nullptr) {
+            break;
+        }
+        entries.append(py::make_tuple(std::string(entry.path), stat_dict(entry.stat)));
+    }
+    datenlord::datenlord_walk_close(walk);
+    return entries;
+}
+
 PYBIND11_MODULE(datenlord, m) {
     m.doc() = "Python bindings for datenlord SDK";
 
@@ -34,6 +72,15 @@ PYBIND11_MODULE(datenlord, m) {
         free_sdk(sdk);
     });
 
+    m.def("shutdown", [](datenlord_sdk *sdk, uint64_t timeout_ms) -> std::string {
+        datenlord_error *err = datenlord::datenlord_shutdown(sdk, timeout_ms);
+        return handle_error(err);
+    }, "sdk"_a, "timeout_ms"_a = 0);
+
+    m.def("set_umask", [](datenlord_sdk *sdk, unsigned int umask) -> unsigned int {
+        return datenlord::datenlord_set_umask(sdk, umask);
+    });
+
     m.def("exists", [](datenlord_sdk *sdk, const std::string &dir_path) -> bool {
         return exists(sdk, dir_path.c_str());
     });
@@ -43,15 +90,23 @@ PYBIND11_MODULE(datenlord, m) {
         return handle_error(err);
     });
 
+    m.def("mkdir_all", [](datenlord_sdk *sdk, const std::string &dir_path, unsigned int mode) -> std::string {
+        datenlord_error *err = datenlord::datenlord_mkdir_all(sdk, dir_path.c_str(), mode);
+        return handle_error(err);
+    }, "sdk"_a, "dir_path"_a, "mode"_a = 0777);
+
     m.def("deldir", [](datenlord_sdk *sdk, const std::string &dir_path, bool recursive) -> std::string {
         datenlord_error *err = datenlord::deldir(sdk, dir_path.c_str(), recursive);
         return handle_error(err);
     });
 
-    m.def("rename_path", [](datenlord_sdk *sdk, const std::string &src_path, const std::string &dest_path) -> std::string {
-        datenlord_error *err = datenlord::rename_path(sdk, src_path.c_str(), dest_path.c_str());
+    m.attr("RENAME_NOREPLACE") = DATENLORD_RENAME_NOREPLACE;
+    m.attr("RENAME_EXCHANGE") = DATENLORD_RENAME_EXCHANGE;
+
+    m.def("rename_path", [](datenlord_sdk *sdk, const std::string &src_path, const std::string &dest_path, unsigned int flags) -> std::string {
+        datenlord_error *err = datenlord::rename_path(sdk, src_path.c_str(), dest_path.c_str(), flags);
         return handle_error(err);
-    });
+    }, "sdk"_a, "src_path"_a, "dest_path"_a, "flags"_a = 0);
 
     m.def("copy_from_local_file", [](datenlord_sdk *sdk, bool overwrite, const std::string &local_file_path, const std::string &dest_file_path) -> std::string {
         datenlord_error *err = datenlord::copy_from_local_file(sdk, overwrite, local_file_path.c_str(), dest_file_path.c_str());
@@ -63,27 +118,65 @@ PYBIND11_MODULE(datenlord, m) {
         return handle_error(err);
     });
 
-    m.def("create_file", [](datenlord_sdk *sdk, const std::string &file_path) -> std::string {
-        datenlord_error *err = datenlord::create_file(sdk, file_path.c_str());
+    m.def("create_file", [](datenlord_sdk *sdk, const std::string &file_path, bool ensure_parents) -> std::string {
+        datenlord_error *err = datenlord::create_file(sdk, file_path.c_str(), ensure_parents);
         return handle_error(err);
-    });
+    }, "sdk"_a, "file_path"_a, "ensure_parents"_a = false);
 
     m.def("stat", [](datenlord_sdk *sdk, const std::string &file_path) -> py::dict {
-        datenlord_file_stat stat;
+        datenlord_stat stat;
         datenlord_error *err = datenlord::stat(sdk, file_path.c_str(), &stat);
         if (err != nullptr) {
             throw std::runtime_error(handle_error(err));
         }
-        return py::dict(
-            "ino"_a = stat.ino,
-            "size"_a = stat.size,
-            "blocks"_a = stat.blocks,
-            "perm"_a = stat.perm,
-            "nlink"_a = stat.nlink,
-            "uid"_a = stat.uid,
-            "gid"_a = stat.gid,
-            "rdev"_a = stat.rdev
-        );
+        return stat_dict(stat);
+    });
+
+    // A stat dict for every path, None for the paths that cannot be stat'ed
+    m.def("stat_many", [](datenlord_sdk *sdk, const std::vector<std::string> &file_paths) -> py::list {
+        std::vector<const char *> paths;
+        for (const std::string &path : file_paths) {
+            paths.push_back(path.c_str());
+        }
+        std::vector<datenlord_stat> stats(paths.size());
+        std::vector<unsigned int> codes(paths.size());
+        datenlord_error *err = datenlord::datenlord_stat_many(sdk, paths.data(), paths.size(), stats.data(), codes.data());
+        if (err != nullptr) {
+            throw std::runtime_error(handle_error(err));
+        }
+        py::list results;
+        for (size_t i = 0; i < paths.size(); i++) {
+            if (codes[i] == 0) {
+                results.append(stat_dict(stats[i]));
+            } else {
+                results.append(py::none());
+            }
+        }
+        return results;
+    });
+
+    m.attr("UTIME_NOW") = DATENLORD_UTIME_NOW;
+    m.attr("UTIME_OMIT") = DATENLORD_UTIME_OMIT;
+
+    // Times are (sec, nsec) pairs, an nsec of UTIME_NOW or UTIME_OMIT works like for utimensat
+    m.def("utimens", [](datenlord_sdk *sdk, const std::string &file_path, std::pair<int64_t, uint32_t> atime, std::pair<int64_t, uint32_t> mtime) -> std::string {
+        datenlord_error *err = datenlord::datenlord_utimens(sdk, file_path.c_str(), { atime.first, atime.second }, { mtime.first, mtime.second });
+        return handle_error(err);
+    });
+
+    m.def("set_tag", [](datenlord_sdk *sdk, const std::string &file_path, const std::string &key, const std::string &value) -> std::string {
+        datenlord_error *err = datenlord::datenlord_set_tag(sdk, file_path.c_str(), key.c_str(), value.c_str());
+        return handle_error(err);
+    });
+
+    m.def("remove_tag", [](datenlord_sdk *sdk, const std::string &file_path, const std::string &key) -> std::string {
+        datenlord_error *err = datenlord::datenlord_remove_tag(sdk, file_path.c_str(), key.c_str());
+        return handle_error(err);
+    });
+
+    m.def("warm_file", [](datenlord_sdk *sdk, const std::string &file_path) -> std::string {
+        datenlord_error *err = datenlord::datenlord_warm_file(sdk, file_path.c_str());
+        return handle_error(err);
     });
 
     m.def("write_file", [](datenlord_sdk *sdk, const std::string &file_path, const std::string &content) -> std::string {
@@ -93,7 +186,7 @@ PYBIND11_MODULE(datenlord, m) {
     });
 
     m.def("read_file", [](datenlord_sdk *sdk, const std::string &file_path) -> py::memoryview {
-        datenlord_file_stat stat;
+        datenlord_stat stat;
         datenlord_error *err = datenlord::stat(sdk, file_path.c_str(), &stat);
         if (err != nullptr) {
             throw std::runtime_error(handle_error(err));
@@ -113,4 +206,32 @@ PYBIND11_MODULE(datenlord, m) {
 
         return py::memoryview(out_content);
     });
+
+    m.def("walk", [](datenlord_sdk *sdk, const std::string &dir_path) -> py::list {
+        return collect_walk(datenlord::datenlord_walk_open(sdk, dir_path.c_str()));
+    });
+
+    m.def("glob", [](datenlord_sdk *sdk, const std::string &pattern) -> py::list {
+        return collect_walk(datenlord::datenlord_glob_open(sdk, pattern.c_str()));
+    });
+
+    // Entries are dicts of name, ino and kind, plus stat when `plus` is set
+    m.def("readdir", [](datenlord_sdk *sdk, const std::string &dir_path, bool plus) -> py::list {
+        datenlord_dir *dir = nullptr;
+        datenlord_error *err = datenlord::datenlord_opendir(sdk, dir_path.c_str(), plus, &dir);
+        if (err != nullptr) {
+            throw std::runtime_error(handle_error(err));
+        }
+        py::list entries;
+        datenlord_dir_entry entry;
+        while (datenlord::datenlord_readdir(dir, &entry)) {
+            py::dict item("name"_a = std::string(entry.name), "ino"_a = entry.ino, "kind"_a = static_cast<int>(entry.kind));
+            if (entry.stat != nullptr) {
+                item["stat"] = stat_dict(*entry.stat);
+            }
+            entries.append(item);
+        }
+        datenlord::datenlord_closedir(dir);
+        return entries;
+    }, "sdk"_a, "dir_path"_a, "plus"_a = false);
 }
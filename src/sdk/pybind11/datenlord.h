@@ -4,6 +4,18 @@
 #include <ostream>
 #include <new>
 
+/// `datenlord_timespec::nsec` setting the timestamp to now, like `UTIME_NOW`
+constexpr static const uint32_t DATENLORD_UTIME_NOW = ((1 << 30) - 1);
+
+/// `datenlord_timespec::nsec` leaving the timestamp unchanged, like `UTIME_OMIT`
+constexpr static const uint32_t DATENLORD_UTIME_OMIT = ((1 << 30) - 2);
+
+/// `rename_path` flag failing if the destination exists, like `RENAME_NOREPLACE`
+constexpr static const unsigned int DATENLORD_RENAME_NOREPLACE = 1;
+
+/// `rename_path` flag atomically swapping source and destination, like `RENAME_EXCHANGE`
+constexpr static const unsigned int DATENLORD_RENAME_EXCHANGE = 2;
+
 /// The node ID of the root inode
 constexpr static const uint64_t ROOT_ID = 1;
 
@@ -11,13 +23,19 @@ constexpr static const uint64_t ROOT_ID = 1;
 /// If fuse mount with `-o default_permissions`, then we should not check
 /// permission. Otherwise, we should check permission.
 /// TODO: add a feature flag to control this
-constexpr static const bool NEED_CHECK_PERM = false;
+constexpr static const bool NEED_CHECK_PERM = true;
 
 
 struct LocalFS;
 
 struct datenlord_sdk {};
 
+/// Iterator over the entries of a walk or glob
+struct datenlord_walk {};
+
+/// A directory listing
+struct datenlord_dir {};
+
 struct datenlord_bytes {
   const uint8_t *data;
   uintptr_t len;
@@ -31,41 +49,126 @@ struct datenlord_error {
 /// The type of i-number
 using INum = uint64_t;
 
-/// File attributes
-struct datenlord_file_stat {
+/// The type of a file
+enum class datenlord_file_kind {
+  DATENLORD_FILE_KIND_UNKNOWN,
+  DATENLORD_FILE_KIND_REGULAR,
+  DATENLORD_FILE_KIND_DIRECTORY,
+  DATENLORD_FILE_KIND_SYMLINK,
+  DATENLORD_FILE_KIND_FIFO,
+  DATENLORD_FILE_KIND_CHAR_DEVICE,
+  DATENLORD_FILE_KIND_BLOCK_DEVICE,
+  DATENLORD_FILE_KIND_SOCKET,
+};
+
+/// A point in time relative to the Unix epoch, like `struct timespec`
+struct datenlord_timespec {
+  /// Seconds, negative before the epoch
+  int64_t sec;
+  /// Nanoseconds past `sec`, below 1000000000
+  uint32_t nsec;
+};
+
+/// File attributes filled by `stat`, modelled after `struct statx`
+struct datenlord_stat {
   /// Inode number
   INum ino;
   /// Size in bytes
   uint64_t size;
-  /// Size in blocks
+  /// Number of 512-byte blocks allocated
   uint64_t blocks;
-  /// Permissions
-  uint16_t perm;
+  /// File type and permission bits, like `st_mode`
+  uint32_t mode;
+  /// File type
+  datenlord_file_kind kind;
   /// Number of hard links
   uint32_t nlink;
-  /// User id
+  /// User id of the owner
   uint32_t uid;
-  /// Group id
+  /// Group id of the owner
   uint32_t gid;
-  /// Rdev
-  uint32_t rdev;
+  /// Time of last access
+  datenlord_timespec atime;
+  /// Time of last modification
+  datenlord_timespec mtime;
+  /// Time of last status change
+  datenlord_timespec ctime;
+};
+
+/// An entry returned by `datenlord_walk_next`
+struct datenlord_walk_entry {
+  /// Path relative to the SDK root, valid until the next call on the walk,
+  /// null once the walk is over
+  const char *path;
+  /// Attributes of the entry
+  datenlord_stat stat;
+};
+
+/// An entry returned by `datenlord_readdir`
+///
+/// The pointers are valid until the next call on the listing.
+struct datenlord_dir_entry {
+  /// Name of the entry within the directory
+  const char *name;
+  /// Inode number
+  INum ino;
+  /// File type
+  datenlord_file_kind kind;
+  /// Attributes of the entry, null unless the listing was opened with `plus`
+  const datenlord_stat *stat;
 };
 
 namespace datenlord {
 
 extern "C" {
 
+/// Free an error returned by the SDK together with its message
+///
+/// Null, unknown and already freed errors are ignored.
+void datenlord_error_free(datenlord_error *err);
+
 datenlord_sdk *init(const char *config);
 
+/// Free the SDK, aborting the asynchronous operations still running
+///
+/// No call on the SDK may be running or made afterwards, see
+/// `datenlord_shutdown` to wait for them first.
 void free_sdk(datenlord_sdk *sdk);
 
+/// Shut the SDK down gracefully, waiting at most `timeout_ms` for the calls
+/// still running, or without limit when 0
+///
+/// Calls made from now on fail with the error code `ESHUTDOWN`, or return
+/// false, 0 or null. Once the running calls, the asynchronous operations
+/// and the open walks finished, the written data is synced, the background
+/// tasks stop and the runtime is taken down; `free_sdk` must still be
+/// called. If calls are still running after the timeout the SDK stays shut
+/// to new calls and `ETIMEDOUT` is returned, calling again waits again. Must
+/// not be called from a completion callback.
+datenlord_error *datenlord_shutdown(datenlord_sdk *sdk, uint64_t timeout_ms);
+
+/// Set the umask applied to the files and directories created from now on,
+/// returning the former one, like `umask(2)`
+///
+/// Starts as the `caller.umask` of the config, or the umask of the process.
+unsigned int datenlord_set_umask(datenlord_sdk *sdk, unsigned int umask);
+
 bool exists(datenlord_sdk *sdk, const char *dir_path);
 
 datenlord_error *mkdir(datenlord_sdk *sdk, const char *dir_path);
 
+datenlord_error *datenlord_mkdir_all(datenlord_sdk *sdk, const char *dir_path, unsigned int mode);
+
 datenlord_error *deldir(datenlord_sdk *sdk, const char *dir_path, bool recursive);
 
-datenlord_error *rename_path(datenlord_sdk *sdk, const char *src_path, const char *dest_path);
+/// Rename `src_path` to `dest_path` according to `flags`, like `renameat2`
+///
+/// With `DATENLORD_RENAME_NOREPLACE` an existing destination fails the call
+/// with the error code `EEXIST`.
+datenlord_error *rename_path(datenlord_sdk *sdk,
+                             const char *src_path,
+                             const char *dest_path,
+                             unsigned int flags);
 
 datenlord_error *copy_from_local_file(datenlord_sdk *sdk,
                                       bool overwrite,
@@ -76,16 +179,83 @@ datenlord_error *copy_to_local_file(datenlord_sdk *sdk,
                                     const char *src_file_path,
                                     const char *local_file_path);
 
-datenlord_error *create_file(datenlord_sdk *sdk, const char *file_path);
+datenlord_error *create_file(datenlord_sdk *sdk, const char *file_path, bool ensure_parents);
 
 datenlord_error *stat(datenlord_sdk *sdk,
                       const char *file_path,
-                      datenlord_file_stat *file_metadata);
+                      datenlord_stat *file_metadata);
+
+/// Fill `file_metadata[i]` with the attributes of `file_paths[i]` for the
+/// `count` paths at once, setting `codes[i]` to 0, or to the error code
+/// when the path cannot be stat'ed
+///
+/// Paths are looked up concurrently and each distinct parent directory is
+/// resolved once, which suits build tools checking many files. Only null or
+/// invalid arguments fail the whole call.
+datenlord_error *datenlord_stat_many(datenlord_sdk *sdk,
+                                     const char *const *file_paths,
+                                     uintptr_t count,
+                                     datenlord_stat *file_metadata,
+                                     unsigned int *codes);
+
+/// Set the access and modification times of `file_path` with nanosecond precision
+///
+/// Like `utimensat`, a time whose `nsec` is `DATENLORD_UTIME_NOW` is set to
+/// the current time and one whose `nsec` is `DATENLORD_UTIME_OMIT` is left
+/// unchanged.
+datenlord_error *datenlord_utimens(datenlord_sdk *sdk,
+                                   const char *file_path,
+                                   datenlord_timespec atime,
+                                   datenlord_timespec mtime);
+
+/// Tag `file_path` with `key`=`value`, replacing the former value
+///
+/// Keys are non-empty and contain neither '=' nor ','. Tags are kept with
+/// the file by `copy_to_local_file` and `copy_from_local_file`.
+datenlord_error *datenlord_set_tag(datenlord_sdk *sdk,
+                                   const char *file_path,
+                                   const char *key,
+                                   const char *value);
+
+/// Remove the tag `key` from `file_path`
+datenlord_error *datenlord_remove_tag(datenlord_sdk *sdk, const char *file_path, const char *key);
+
+/// Open `file_path` read-only ahead of time, so `read_file` of it skips the
+/// lookup and open
+///
+/// The handle is reopened whenever entries are removed or renamed. Files
+/// listed in the `warm_files` config field are opened by `init`.
+datenlord_error *datenlord_warm_file(datenlord_sdk *sdk, const char *file_path);
 
 datenlord_error *write_file(datenlord_sdk *sdk, const char *file_path, datenlord_bytes content);
 
 datenlord_error *read_file(datenlord_sdk *sdk, const char *file_path, datenlord_bytes *out_content);
 
+/// Start walking every entry below the directory `dir_path`, null on invalid arguments
+datenlord_walk *datenlord_walk_open(datenlord_sdk *sdk, const char *dir_path);
+
+/// Start walking the entries matching `pattern`, null on invalid arguments
+datenlord_walk *datenlord_glob_open(datenlord_sdk *sdk, const char *pattern);
+
+/// Fill `entry` with the next entry of `walk`, `entry->path` is null at the end
+datenlord_error *datenlord_walk_next(datenlord_walk *walk, datenlord_walk_entry *entry);
+
+/// Stop `walk` and free it, null is ignored
+void datenlord_walk_close(datenlord_walk *walk);
+
+/// List the directory `dir_path` into `*dir`, with the attributes of every
+/// entry if `plus`
+datenlord_error *datenlord_opendir(datenlord_sdk *sdk,
+                                   const char *dir_path,
+                                   bool plus,
+                                   datenlord_dir **dir);
+
+/// Fill `entry` with the next entry of `dir`, false at the end or on invalid arguments
+bool datenlord_readdir(datenlord_dir *dir, datenlord_dir_entry *entry);
+
+/// Free a listing opened by `datenlord_opendir`, null is ignored
+void datenlord_closedir(datenlord_dir *dir);
+
 } // extern "C"
 
 }
\ No newline at end of file
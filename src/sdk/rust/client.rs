@@ -0,0 +1,789 @@
+//! A path based asynchronous client for Rust applications
+use std::ffi::OsStr;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::Stream;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use tokio::runtime::Handle;
+
+use crate::common::config::{ConfigUpdate, DatenLordConfig};
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::lifecycle::LifecycleTask;
+use crate::sdk::{self, SdkFs, SdkStats};
+use crate::storage::appendlog::{AppendLog, LogSync, LogTail, SharedLogs};
+use crate::storage::archive::{self, ArchiveReport};
+use crate::storage::cache::WarmHandle;
+use crate::storage::dedup::DedupStats;
+use crate::storage::digest::{self, DigestAlgorithm};
+use crate::storage::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, RequestContext, SeekWhence,
+    StatFsParam, ROOT_ID,
+};
+use crate::storage::gc::{self, GcReport, GcTask};
+use crate::storage::health::HealthReport;
+use crate::storage::kv::{KvOptions, KvStore};
+use crate::storage::layout::BlockLayout;
+use crate::storage::notify::Watch;
+use crate::storage::packing::PackStats;
+use crate::storage::replication::ReplicationStatus;
+use crate::storage::stream;
+use crate::storage::striping::RebuildReport;
+use crate::storage::tags::{self, Tags};
+use crate::storage::transfer;
+use crate::storage::trash::{self, PurgeTask, TrashEntry};
+use crate::storage::upload::{self, Upload, UploadPart};
+use crate::storage::virtualfs::VirtualFs;
+use crate::storage::walk::{self, DiskUsage};
+use crate::storage::writeback::WritebackTask;
+
+/// The mode of the directories created by `Client::create_dir_all`, before
+/// the umask of the caller
+const DIR_MODE: u32 = 0o777;
+/// The mode of the files created by `Client::open` with `O_CREAT`, before
+/// the umask of the caller
+const FILE_MODE: u32 = 0o666;
+
+/// A client of a datenlord namespace, addressing files by their path
+/// relative to the namespace root
+///
+/// Every method is asynchronous and must run inside a tokio runtime. The
+/// client is cheap to clone and clones share the same caches. Operations
+/// run on behalf of the caller the `caller` field of the config names.
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// The filesystem stack the config describes
+    fs: Arc<SdkFs>,
+    /// The caller every operation runs on behalf of
+    ctx: RequestContext,
+    /// The scheduled evaluation of the lifecycle rules, stopped with the
+    /// last clone
+    _lifecycle: Option<Arc<LifecycleTask>>,
+    /// The scheduled purge of the trash, stopped with the last clone
+    _purge: Option<Arc<PurgeTask>>,
+    /// The scheduled garbage collection, stopped with the last clone
+    _gc: Option<Arc<GcTask>>,
+    /// The background writeback, flushing a last time with the last clone
+    _writeback: Arc<WritebackTask>,
+    /// The logs appended to by path, shared by the clones
+    logs: Arc<SharedLogs<SdkFs>>,
+}
+
+impl Client {
+    /// Open the namespace `config` describes
+    pub fn new(config: &DatenLordConfig) -> DatenLordResult<Self> {
+        let fs = Arc::new(sdk::open_fs(config)?);
+        let ctx = config.request_context();
+        let lifecycle = LifecycleTask::start(Arc::clone(&fs), ctx, config.lifecycle.clone())?;
+        let purge = PurgeTask::start(Arc::clone(&fs), ctx, config.trash.clone())?;
+        let gc = GcTask::start(Arc::clone(&fs), sdk::packed, ctx, config.gc.clone())?;
+        let writeback = sdk::writeback(&fs, config)?;
+        Ok(Self {
+            fs: Arc::clone(&fs),
+            ctx,
+            _lifecycle: lifecycle.map(Arc::new),
+            _purge: purge.map(Arc::new),
+            _gc: gc.map(Arc::new),
+            _writeback: Arc::new(writeback),
+            logs: Arc::new(SharedLogs::new(Arc::clone(&fs), ctx, LogSync::Batch)),
+        })
+    }
+
+    /// A client of the directory `prefix` of this one, sharing its caches
+    /// and background tasks, failing if `prefix` is not a directory
+    ///
+    /// Paths of the returned client are relative to `prefix` and cannot lead
+    /// out of it, neither with `..` nor through symbolic links. Its trash,
+    /// versions and watched paths are those below `prefix` too.
+    pub async fn scoped(&self, prefix: impl AsRef<OsStr>) -> DatenLordResult<Self> {
+        let ctx = sdk::scope(&self.fs, &self.ctx, prefix.as_ref()).await?;
+        Ok(Self {
+            ctx,
+            logs: Arc::new(SharedLogs::new(Arc::clone(&self.fs), ctx, LogSync::Batch)),
+            ..self.clone()
+        })
+    }
+
+    /// Set the umask applied to the files and directories this client
+    /// creates from now on, returning the former one, like `umask(2)`
+    ///
+    /// Clones made earlier keep their umask.
+    pub fn set_umask(&mut self, umask: u32) -> u32 {
+        std::mem::replace(&mut self.ctx.umask, umask & 0o777)
+    }
+
+    /// The attributes of `path`, without following a final symbolic link
+    pub async fn metadata(&self, path: impl AsRef<OsStr>) -> DatenLordResult<FileAttr> {
+        let path = path.as_ref();
+        let (_, attr, _) = self.fs.lookup(&self.ctx, ROOT_ID, path).await?;
+        Ok(attr)
+    }
+
+    /// The statistics of the filesystem holding `path`, like `statvfs(3)`,
+    /// those of the backend mounted there under a mount point
+    pub async fn statfs(&self, path: impl AsRef<OsStr>) -> DatenLordResult<StatFsParam> {
+        let attr = self.metadata(path).await?;
+        self.fs.statfs(&self.ctx, attr.ino).await
+    }
+
+    /// The attributes of every path of `paths`, in order, with at most
+    /// `concurrency` lookups at once, see `walk::stat_many`
+    pub async fn metadata_many(
+        &self,
+        paths: &[impl AsRef<OsStr>],
+        concurrency: usize,
+    ) -> Vec<DatenLordResult<FileAttr>> {
+        walk::stat_many(Arc::clone(&self.fs), self.ctx, paths, concurrency).await
+    }
+
+    /// The space taken by `path`, everything below it when a directory, with
+    /// at most `concurrency` directories listed at once, see
+    /// `walk::disk_usage`
+    pub async fn disk_usage(
+        &self,
+        path: impl AsRef<OsStr>,
+        concurrency: usize,
+    ) -> DatenLordResult<DiskUsage> {
+        walk::disk_usage(Arc::clone(&self.fs), self.ctx, path.as_ref(), concurrency).await
+    }
+
+    /// Read at most `len` bytes of `path` from `offset` as a stream of
+    /// chunks of at most `chunk_size` bytes, see `stream::read_stream`
+    pub async fn read_stream(
+        &self,
+        path: impl AsRef<OsStr>,
+        offset: u64,
+        len: u64,
+        chunk_size: usize,
+    ) -> DatenLordResult<impl Stream<Item = DatenLordResult<Bytes>> + Send + 'static> {
+        let attr = self.metadata(path).await?;
+        Ok(stream::read_stream(Arc::clone(&self.fs), self.ctx, attr.ino, offset, len, chunk_size))
+    }
+
+    /// The contents of every file of `paths`, in order, with at most
+    /// `concurrency` reads at once, see `stream::read_many`
+    pub async fn read_many(
+        &self,
+        paths: &[impl AsRef<OsStr>],
+        concurrency: usize,
+    ) -> Vec<DatenLordResult<Vec<u8>>> {
+        stream::read_many(Arc::clone(&self.fs), self.ctx, paths, concurrency).await
+    }
+
+    /// Whether `path` exists, false on errors, see `VirtualFs::exists`
+    pub async fn exists(&self, path: impl AsRef<OsStr>) -> bool {
+        self.fs
+            .exists(&self.ctx, ROOT_ID, path.as_ref())
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Create the directory `path` together with its missing parents, like `mkdir -p`
+    pub async fn create_dir_all(&self, path: impl AsRef<OsStr>) -> DatenLordResult<()> {
+        let path = path.as_ref();
+        self.fs
+            .mkdir_all(&self.ctx, ROOT_ID, path, DIR_MODE)
+            .await
+            .map(|_| ())
+    }
+
+    /// Every entry of the directory `path` with its attributes, as a
+    /// snapshot of the directory, see `sdk::list_dir`
+    pub async fn read_dir(&self, path: impl AsRef<OsStr>) -> DatenLordResult<Vec<DirEntry>> {
+        sdk::list_dir(&self.fs, &self.ctx, path.as_ref(), true).await
+    }
+
+    /// Remove the file or empty directory `path`
+    pub async fn remove(&self, path: impl AsRef<OsStr>) -> DatenLordResult<()> {
+        let path = path.as_ref();
+        if self.metadata(path).await?.kind == SFlag::S_IFDIR {
+            self.fs
+                .rmdir(&self.ctx, ROOT_ID, path)
+                .await
+                .map(|_| ())
+        } else {
+            self.fs.unlink(&self.ctx, ROOT_ID, path).await
+        }
+    }
+
+    /// Copy the file `src` to `dst`, replacing the file at the destination,
+    /// returning the bytes copied and whether `dst` shares the blocks of
+    /// `src`, see `transfer::copy_file`
+    pub async fn copy(
+        &self,
+        src: impl AsRef<OsStr>,
+        dst: impl AsRef<OsStr>,
+    ) -> DatenLordResult<CopyRangeResult> {
+        transfer::copy_file(self.fs.as_ref(), &self.ctx, src.as_ref(), dst.as_ref()).await
+    }
+
+    /// Replace the contents of the file `path` with `data`, creating it if
+    /// missing, so readers never see a part of `data`, see
+    /// `upload::write_file_atomic`
+    pub async fn write_file_atomic(
+        &self,
+        path: impl AsRef<OsStr>,
+        data: &[u8],
+    ) -> DatenLordResult<FileAttr> {
+        upload::write_file_atomic(self.fs.as_ref(), &self.ctx, path.as_ref(), data).await
+    }
+
+    /// Write `path`, everything below it when a directory, to `writer` as a
+    /// tar archive, see `archive::export_tar`
+    pub async fn export_tar<W: AsyncWrite + Unpin + ?Sized>(
+        &self,
+        path: impl AsRef<OsStr>,
+        writer: &mut W,
+    ) -> DatenLordResult<ArchiveReport> {
+        archive::export_tar(self.fs.as_ref(), &self.ctx, path.as_ref(), writer).await
+    }
+
+    /// Extract the tar archive read from `reader` into the directory `dest`,
+    /// see `archive::import_tar`
+    pub async fn import_tar<R: AsyncRead + Unpin + ?Sized>(
+        &self,
+        reader: &mut R,
+        dest: impl AsRef<OsStr>,
+    ) -> DatenLordResult<ArchiveReport> {
+        archive::import_tar(self.fs.as_ref(), &self.ctx, reader, dest.as_ref()).await
+    }
+
+    /// The digest with `algorithm` of the contents of the file `path`,
+    /// cached in its extended attributes with `cache`, see
+    /// `digest::file_digest`
+    pub async fn file_digest(
+        &self,
+        path: impl AsRef<OsStr>,
+        algorithm: DigestAlgorithm,
+        cache: bool,
+    ) -> DatenLordResult<String> {
+        let attr = self.metadata(path).await?;
+        digest::file_digest(self.fs.as_ref(), &self.ctx, attr.ino, algorithm, cache).await
+    }
+
+    /// Run the control command `cmd` on `path` with the argument `input`,
+    /// returning its output, see `storage::ioctl`
+    pub async fn ioctl(
+        &self,
+        path: impl AsRef<OsStr>,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        let attr = self.metadata(path).await?;
+        self.fs.ioctl(&self.ctx, attr.ino, cmd, input).await
+    }
+
+    /// The tags of `path`
+    pub async fn tags(&self, path: impl AsRef<OsStr>) -> DatenLordResult<Tags> {
+        let path = path.as_ref();
+        let attr = self.metadata(path).await?;
+        tags::get_tags(self.fs.as_ref(), &self.ctx, attr.ino).await
+    }
+
+    /// Tag `path` with `key`=`value`, replacing the former value
+    pub async fn set_tag(
+        &self,
+        path: impl AsRef<OsStr>,
+        key: &str,
+        value: &str,
+    ) -> DatenLordResult<()> {
+        let path = path.as_ref();
+        let attr = self.metadata(path).await?;
+        tags::set_tag(self.fs.as_ref(), &self.ctx, attr.ino, key, value).await
+    }
+
+    /// Remove the tag `key` from `path`
+    pub async fn remove_tag(&self, path: impl AsRef<OsStr>, key: &str) -> DatenLordResult<()> {
+        let path = path.as_ref();
+        let attr = self.metadata(path).await?;
+        tags::remove_tag(self.fs.as_ref(), &self.ctx, attr.ino, key).await
+    }
+
+    /// The entries removed to the trash, oldest removal first, none unless
+    /// `trash` is on in the config
+    pub async fn list_trash(&self) -> DatenLordResult<Vec<TrashEntry>> {
+        trash::list_trash(self.fs.as_ref(), &self.ctx).await
+    }
+
+    /// Move the entry last removed from `path` back from the trash, see
+    /// `trash::restore`
+    pub async fn restore(&self, path: impl AsRef<Path>) -> DatenLordResult<FileAttr> {
+        trash::restore(self.fs.as_ref(), &self.ctx, path.as_ref()).await
+    }
+
+    /// Remove for good the entries removed to the trash at least
+    /// `older_than` ago, returning how many
+    pub async fn purge(&self, older_than: Duration) -> DatenLordResult<usize> {
+        trash::purge(self.fs.as_ref(), &self.ctx, older_than).await
+    }
+
+    /// Flush every open file and all state buffered by the namespace and
+    /// its backend, like `syncfs`, see `VirtualFs::sync_all`
+    pub async fn sync_all(&self) -> DatenLordResult<()> {
+        self.fs.sync_all(&self.ctx).await
+    }
+
+    /// Remove the backend data no file refers to any more, unless changed
+    /// within `grace`, or only report it with `dry_run`, see `gc::collect`
+    pub async fn collect_garbage(&self, grace: Duration, dry_run: bool) -> DatenLordResult<GcReport> {
+        gc::collect(sdk::packed(&self.fs), &self.ctx, grace, dry_run).await
+    }
+
+    /// How far the secondaries of the `replication` config are behind,
+    /// `None` without secondaries
+    pub fn replication_status(&self) -> Option<ReplicationStatus> {
+        sdk::replicated(&self.fs).replication_status()
+    }
+
+    /// Repair the chunks of the `striping` config that are missing, corrupt
+    /// or stale, see `StripedBackend::rebuild`
+    pub async fn rebuild_stripes(&self) -> DatenLordResult<RebuildReport> {
+        sdk::striped(&self.fs).rebuild().await
+    }
+
+    /// How much the deduplicated blocks save, `None` unless the namespace
+    /// has the `dedup` feature
+    pub fn dedup_stats(&self) -> Option<DedupStats> {
+        sdk::dedup(&self.fs).stats()
+    }
+
+    /// How much the segments of the packed files hold, `None` unless the
+    /// namespace has the `packing` feature
+    pub fn pack_stats(&self) -> DatenLordResult<Option<PackStats>> {
+        sdk::packed(&self.fs).stats()
+    }
+
+    /// Change the values of `update` while running, for every clone and
+    /// keeping the open files, see `ConfigUpdate`
+    pub fn update_config(&self, update: &ConfigUpdate) -> DatenLordResult<()> {
+        sdk::update_config(&self.fs, update)
+    }
+
+    /// The calls made through the client, the bytes they moved, how the
+    /// cache did and the handles and queues held now
+    pub fn stats(&self) -> SdkStats {
+        sdk::stats(&self.fs)
+    }
+
+    /// The sizes the backend reads and writes best, see
+    /// `VirtualFs::block_layout`
+    pub fn block_layout(&self) -> BlockLayout {
+        self.fs.block_layout()
+    }
+
+    /// Probe the filesystem end to end, reading the root, writing a few bytes
+    /// and reading them back, e.g. for a readiness probe, see `health::probe`
+    pub async fn healthcheck(&self) -> HealthReport {
+        sdk::healthcheck(&self.fs, &self.ctx).await.0
+    }
+
+    /// Watch the changes to `path` and its children, or everything below it
+    /// with `recursive`, see `NotifyFs::watch`
+    pub async fn watch(&self, path: impl AsRef<Path>, recursive: bool) -> DatenLordResult<Watch> {
+        sdk::notify(&self.fs)
+            .watch(&self.ctx, path.as_ref(), recursive)
+            .await
+    }
+
+    /// Open the append-only log at `path`, creating it when missing, with
+    /// its records synced as `sync` says, see `AppendLog`
+    pub async fn open_log(
+        &self,
+        path: impl AsRef<OsStr>,
+        sync: LogSync,
+    ) -> DatenLordResult<AppendLog<SdkFs>> {
+        let path = path.as_ref();
+        AppendLog::open(Arc::clone(&self.fs), self.ctx, path, sync).await
+    }
+
+    /// Append `record` to the log at `path`, creating it when missing, and
+    /// return the offset it was written at once synced
+    ///
+    /// Appends to the same file through this client and its clones go
+    /// through a single writer, so concurrent ones are atomic and ordered.
+    pub async fn append(&self, path: impl AsRef<OsStr>, record: &[u8]) -> DatenLordResult<u64> {
+        self.logs.append(path.as_ref(), record).await
+    }
+
+    /// Follow the records of the log at `path` from `offset` on, waiting for
+    /// those appended later through this client and its clones
+    pub async fn tail(&self, path: impl AsRef<OsStr>, offset: u64) -> DatenLordResult<LogTail<SdkFs>> {
+        self.logs.tail(path.as_ref(), offset).await
+    }
+
+    /// Open the key-value store in the directory `path`, creating it when
+    /// missing, see `KvStore`
+    pub async fn open_kv(
+        &self,
+        path: impl AsRef<Path>,
+        options: KvOptions,
+    ) -> DatenLordResult<KvStore<SdkFs>> {
+        KvStore::open(Arc::clone(&self.fs), self.ctx, path.as_ref(), options).await
+    }
+
+    /// Start a multipart upload to `path`, returning its id, see
+    /// `upload::start_upload`
+    pub async fn start_upload(&self, path: impl AsRef<Path>) -> DatenLordResult<String> {
+        upload::start_upload(self.fs.as_ref(), &self.ctx, path.as_ref()).await
+    }
+
+    /// Store `data` as part `index` of upload `id`, returning its checksum
+    pub async fn upload_part(&self, id: &str, index: u32, data: &[u8]) -> DatenLordResult<UploadPart> {
+        upload::upload_part(self.fs.as_ref(), &self.ctx, id, index, data).await
+    }
+
+    /// The parts uploaded to upload `id`, by index, to resume it from
+    pub async fn upload_parts(&self, id: &str) -> DatenLordResult<Vec<UploadPart>> {
+        upload::upload_parts(self.fs.as_ref(), &self.ctx, id).await
+    }
+
+    /// Concatenate the parts of upload `id` into its target, returning the
+    /// attributes of the file
+    pub async fn complete_upload(&self, id: &str) -> DatenLordResult<FileAttr> {
+        upload::complete_upload(self.fs.as_ref(), &self.ctx, id).await
+    }
+
+    /// Give up upload `id`, removing its parts
+    pub async fn abort_upload(&self, id: &str) -> DatenLordResult<()> {
+        upload::abort_upload(self.fs.as_ref(), &self.ctx, id).await
+    }
+
+    /// The uploads in progress, oldest first
+    pub async fn list_uploads(&self) -> DatenLordResult<Vec<Upload>> {
+        upload::list_uploads(self.fs.as_ref(), &self.ctx).await
+    }
+
+    /// Open the file `path` read-only ahead of time, so opening it with
+    /// `O_RDONLY` hands out the open handle, see `CacheFs::warm`
+    pub async fn warm(&self, path: impl AsRef<OsStr>) -> DatenLordResult<()> {
+        let path = path.as_ref();
+        sdk::cache(&self.fs).warm(&self.ctx, path).await.map(|_| ())
+    }
+
+    /// Open the file `path` with `flags`, like `open(2)`
+    ///
+    /// `O_CREAT` creates a missing file with mode 0o666 less the umask, and
+    /// fails with `EEXIST` if it exists along with `O_EXCL`. `O_TRUNC` cuts
+    /// a regular file to zero length, updating its times, and `O_NOFOLLOW`
+    /// fails with `ELOOP` on a symbolic link.
+    pub async fn open(&self, path: impl AsRef<OsStr>, flags: OFlag) -> DatenLordResult<File> {
+        let path = path.as_ref();
+        if flags.contains(OFlag::O_CREAT) {
+            let param = CreateParam {
+                parent: ROOT_ID,
+                name: path.to_owned(),
+                mode: FILE_MODE,
+                rdev: 0,
+                node_type: SFlag::S_IFREG,
+                link: None,
+            };
+            match self.fs.mknod(&self.ctx, param).await {
+                Err(DatenLordError::AlreadyExists { .. }) if !flags.contains(OFlag::O_EXCL) => {}
+                result => result.map(|_| ())?,
+            }
+        }
+        if flags == OFlag::O_RDONLY {
+            if let Some(handle) = sdk::cache(&self.fs).take_warm(&self.ctx, path).await {
+                return Ok(File {
+                    fs: Arc::clone(&self.fs),
+                    ctx: self.ctx,
+                    ino: handle.ino,
+                    fh: handle.fh,
+                    warm: Some(handle),
+                    closed: false,
+                    position: 0,
+                    pending: Pending::Idle,
+                });
+            }
+        }
+        let attr = self.metadata(path).await?;
+        if attr.kind == SFlag::S_IFDIR {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("{path:?} is a directory")],
+            });
+        }
+        let fh = self
+            .fs
+            .open(&self.ctx, attr.ino, flags.bits() as u32)
+            .await?;
+        Ok(File {
+            fs: Arc::clone(&self.fs),
+            ctx: self.ctx,
+            ino: attr.ino,
+            fh,
+            warm: None,
+            closed: false,
+            position: 0,
+            pending: Pending::Idle,
+        })
+    }
+
+    /// Create the file `path`, or truncate it if it exists, and open it for
+    /// writing, like `File::create`
+    pub async fn create(&self, path: impl AsRef<OsStr>) -> DatenLordResult<File> {
+        self.open(path, OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC)
+            .await
+    }
+}
+
+/// A boxed future run by the `tokio::io` traits of `File`
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// The operation a `File` is running for the `tokio::io` traits
+enum Pending {
+    /// None
+    Idle,
+    /// A read returning the bytes read
+    Read(BoxFuture<io::Result<Vec<u8>>>),
+    /// A write returning the number of bytes written
+    Write(BoxFuture<io::Result<usize>>),
+    /// A seek relative to the end, returning the new position
+    Seek(BoxFuture<io::Result<u64>>),
+}
+
+impl std::fmt::Debug for Pending {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match *self {
+            Self::Idle => "Idle",
+            Self::Read(_) => "Read",
+            Self::Write(_) => "Write",
+            Self::Seek(_) => "Seek",
+        })
+    }
+}
+
+/// A file opened by `Client::open` or `Client::create`
+///
+/// `read_at` and `write_at` take explicit offsets, while the `AsyncRead`,
+/// `AsyncWrite` and `AsyncSeek` implementations work from a cursor starting
+/// at 0, so the file can be used with `tokio::io::copy` and friends. Like
+/// for other `AsyncWrite`s, a write that returned `Pending` must be retried
+/// with the same data. Close it with `close` to see release errors;
+/// dropping it inside a tokio runtime releases it in the background.
+#[derive(Debug)]
+pub struct File {
+    /// The filesystem the file belongs to
+    fs: Arc<SdkFs>,
+    /// The caller the file was opened by
+    ctx: RequestContext,
+    /// The inode of the file
+    ino: u64,
+    /// The handle the file is open as
+    fh: u64,
+    /// The warm handle `fh` is, given back instead of released
+    warm: Option<WarmHandle>,
+    /// Whether `close` already released the handle
+    closed: bool,
+    /// The cursor of the `tokio::io` traits
+    position: u64,
+    /// The operation the `tokio::io` traits are running
+    pending: Pending,
+}
+
+impl File {
+    /// Read into `buf` from `offset`, returning the number of bytes read,
+    /// 0 at the end of the file
+    pub async fn read_at(&self, buf: &mut [u8], offset: u64) -> DatenLordResult<usize> {
+        let size = u32::try_from(buf.len()).unwrap_or(u32::MAX);
+        self.fs.read(&self.ctx, self.ino, self.fh, offset, size, buf).await
+    }
+
+    /// Write all of `data` at `offset`
+    pub async fn write_at(&self, data: &[u8], offset: u64) -> DatenLordResult<()> {
+        let offset = i64::try_from(offset).map_err(|_| DatenLordError::InvalidArgument {
+            context: vec![format!("offset {offset} out of range")],
+        })?;
+        self.fs.write(&self.ctx, self.ino, self.fh, offset, data, 0).await
+    }
+
+    /// Copy at most `len` bytes from `offset` to the file `dst` at
+    /// `dst_offset`, like `copy_file_range(2)`, returning the bytes copied,
+    /// 0 at the end of the file, and whether `dst` shares their blocks
+    pub async fn copy_range(
+        &self,
+        offset: u64,
+        dst: &File,
+        dst_offset: u64,
+        len: u64,
+    ) -> DatenLordResult<CopyRangeResult> {
+        let param = CopyRangeParam {
+            ino_in: self.ino,
+            fh_in: self.fh,
+            offset_in: offset,
+            ino_out: dst.ino,
+            fh_out: dst.fh,
+            offset_out: dst_offset,
+            len,
+        };
+        self.fs.copy_file_range(&self.ctx, param).await
+    }
+
+    /// The offset of the next data or hole at or after `offset`, like
+    /// `lseek` with `SEEK_DATA` or `SEEK_HOLE`, `None` past the end of the
+    /// file or when no data follows
+    pub async fn lseek(&self, offset: u64, whence: SeekWhence) -> DatenLordResult<Option<u64>> {
+        self.fs.lseek(&self.ctx, self.ino, self.fh, offset, whence).await
+    }
+
+    /// The current attributes of the file
+    pub async fn metadata(&self) -> DatenLordResult<FileAttr> {
+        let (_, attr) = self.fs.getattr(&self.ctx, self.ino).await?;
+        Ok(attr)
+    }
+
+    /// Flush the file data and metadata to the backend, like `fsync`
+    pub async fn sync_all(&self) -> DatenLordResult<()> {
+        self.fs.fsync(&self.ctx, self.ino, self.fh, false).await
+    }
+
+    /// Release the file
+    pub async fn close(mut self) -> DatenLordResult<()> {
+        self.closed = true;
+        if let Some(handle) = self.warm.take() {
+            sdk::cache(&self.fs).put_warm(handle).await;
+            return Ok(());
+        }
+        self.fs.release(&self.ctx, self.ino, self.fh, 0, 0, true).await
+    }
+}
+
+impl File {
+    /// Wait for the running write, if any, to finish
+    fn poll_write_done(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Pending::Write(ref mut write) = self.pending {
+            let result = ready!(write.as_mut().poll(cx));
+            self.pending = Pending::Idle;
+            self.position += result? as u64;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for File {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_done(cx))?;
+        if !matches!(this.pending, Pending::Read(_)) {
+            let (fs, ctx, ino, fh) = (Arc::clone(&this.fs), this.ctx, this.ino, this.fh);
+            let offset = this.position;
+            let mut data = vec![0; buf.remaining()];
+            this.pending = Pending::Read(Box::pin(async move {
+                let size = u32::try_from(data.len()).unwrap_or(u32::MAX);
+                let read = fs.read(&ctx, ino, fh, offset, size, &mut data).await?;
+                data.truncate(read);
+                Ok(data)
+            }));
+        }
+        let Pending::Read(ref mut read) = this.pending else {
+            unreachable!("a read was just started");
+        };
+        let result = ready!(read.as_mut().poll(cx));
+        this.pending = Pending::Idle;
+        let data = result?;
+        // The caller may offer a smaller buffer when polling again
+        let len = data.len().min(buf.remaining());
+        buf.put_slice(&data[..len]);
+        this.position += len as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for File {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !matches!(this.pending, Pending::Write(_)) {
+            let (fs, ctx, ino, fh) = (Arc::clone(&this.fs), this.ctx, this.ino, this.fh);
+            let offset = i64::try_from(this.position)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "position out of range"))?;
+            let data = data.to_vec();
+            this.pending = Pending::Write(Box::pin(async move {
+                fs.write(&ctx, ino, fh, offset, &data, 0).await?;
+                Ok(data.len())
+            }));
+        }
+        let Pending::Write(ref mut write) = this.pending else {
+            unreachable!("a write was just started");
+        };
+        let result = ready!(write.as_mut().poll(cx));
+        this.pending = Pending::Idle;
+        let written = result?;
+        this.position += written as u64;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Writes reach the backend as soon as they complete
+        self.get_mut().poll_write_done(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_write_done(cx)
+    }
+}
+
+impl AsyncSeek for File {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        if !matches!(this.pending, Pending::Idle) {
+            return Err(io::Error::other("another operation is in progress"));
+        }
+        let out_of_range = || io::Error::new(io::ErrorKind::InvalidInput, "seek out of range");
+        match position {
+            SeekFrom::Start(offset) => this.position = offset,
+            SeekFrom::Current(delta) => {
+                this.position = this.position.checked_add_signed(delta).ok_or_else(out_of_range)?;
+            }
+            SeekFrom::End(delta) => {
+                let (fs, ctx, ino) = (Arc::clone(&this.fs), this.ctx, this.ino);
+                this.pending = Pending::Seek(Box::pin(async move {
+                    let (_, attr) = fs.getattr(&ctx, ino).await?;
+                    attr.size.checked_add_signed(delta).ok_or_else(out_of_range)
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_done(cx))?;
+        if let Pending::Seek(ref mut seek) = this.pending {
+            let result = ready!(seek.as_mut().poll(cx));
+            this.pending = Pending::Idle;
+            this.position = result?;
+        }
+        Poll::Ready(Ok(this.position))
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Ok(runtime) = Handle::try_current() {
+            let (fs, ctx, ino, fh) = (Arc::clone(&self.fs), self.ctx, self.ino, self.fh);
+            let warm = self.warm.take();
+            runtime.spawn(async move {
+                match warm {
+                    Some(handle) => sdk::cache(&fs).put_warm(handle).await,
+                    None => {
+                        let _ = fs.release(&ctx, ino, fh, 0, 0, true).await;
+                    }
+                }
+            });
+        }
+    }
+}
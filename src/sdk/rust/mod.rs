@@ -0,0 +1,4 @@
+//! This module contains the datenlord rust sdk
+mod client;
+
+pub use client::{Client, File};
@@ -0,0 +1,607 @@
+//! Append-only logs of records on top of `VirtualFs`, appended in a
+//! guaranteed order by a single writer task per log
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{CreateParam, RequestContext, SetAttrParam, ROOT_ID};
+use super::virtualfs::{INum, VirtualFs};
+
+/// The size of the frame header, the record length and its CRC-32
+pub(crate) const HEADER_SIZE: usize = 8;
+/// The largest record a log accepts, 16 MiB
+pub const MAX_RECORD_SIZE: usize = 16 << 20;
+/// The most bytes written by one batch
+const MAX_BATCH_BYTES: usize = 4 << 20;
+/// The appends and syncs queued ahead of the writer
+const QUEUE_DEPTH: usize = 1024;
+/// The mode of created logs, less the umask
+const LOG_MODE: u32 = 0o666;
+/// The records a tail reads at once
+const TAIL_BATCH: usize = 256;
+
+/// When the records appended to a log are synced to the backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSync {
+    /// Only by `AppendLog::sync` and on close, leaving the rest to the
+    /// page cache
+    None,
+    /// Before the appends of every batch return
+    Batch,
+    /// At most once per interval, appends returning before their sync
+    Interval(Duration),
+}
+
+/// A record read back from a log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// The offset the record was appended at
+    pub offset: u64,
+    /// The record
+    pub data: Vec<u8>,
+}
+
+/// A request to the writer of a log
+enum Request {
+    /// Append a framed record, replying with its offset
+    Append(Vec<u8>, oneshot::Sender<DatenLordResult<u64>>),
+    /// Sync the records appended so far
+    Sync(oneshot::Sender<DatenLordResult<()>>),
+}
+
+/// An append-only log file of length-prefixed, checksummed records
+///
+/// Appends are queued to a writer task, which writes the queued records in
+/// batches of one write each, in the order of the appends, and syncs them
+/// as the `LogSync` policy says. A log has a single writer: concurrent
+/// appends are ordered by the log instead of racing to write at the end of
+/// the file, but two logs opened on the same file must not both append.
+/// Opening a log drops a partly written record left at its end by a crash.
+#[derive(Debug)]
+pub struct AppendLog<F: VirtualFs + 'static> {
+    /// The filesystem holding the log
+    fs: Arc<F>,
+    /// The caller the log is read on behalf of
+    ctx: RequestContext,
+    /// The inode of the log file
+    ino: INum,
+    /// The handle of the log file, shared with the writer
+    fh: u64,
+    /// The end of the records written so far
+    end: Arc<AtomicU64>,
+    /// The end of the records written so far, published to the tails
+    appended: watch::Receiver<u64>,
+    /// Queues requests to the writer, dropped to stop it
+    requests: Option<mpsc::Sender<Request>>,
+    /// The writer, syncing and closing the file once stopped
+    writer: Option<JoinHandle<()>>,
+}
+
+/// The frame of `record`, its length and CRC-32 followed by the record
+fn frame(record: &[u8]) -> DatenLordResult<Vec<u8>> {
+    if record.len() > MAX_RECORD_SIZE {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!(
+                "log record of {} bytes exceeds {MAX_RECORD_SIZE} bytes",
+                record.len()
+            )],
+        });
+    }
+    let mut frame = Vec::with_capacity(HEADER_SIZE + record.len());
+    frame.extend_from_slice(&(record.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32fast::hash(record).to_le_bytes());
+    frame.extend_from_slice(record);
+    Ok(frame)
+}
+
+/// Read exactly `len` bytes of file `ino` open as `fh` at `offset`, `None`
+/// when the file ends first
+async fn read_exact<F: VirtualFs>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+    fh: u64,
+    offset: u64,
+    len: usize,
+) -> DatenLordResult<Option<Vec<u8>>> {
+    let mut buf = vec![0; len];
+    let mut filled = 0;
+    while filled < len {
+        let size = (len - filled).min(u32::MAX as usize) as u32;
+        let read = fs
+            .read(ctx, ino, fh, offset + filled as u64, size, &mut buf[filled..])
+            .await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        filled += read;
+    }
+    Ok(Some(buf))
+}
+
+/// The record framed at `offset`, with the offset of the next one, `None`
+/// unless a whole valid frame starts there
+async fn read_record<F: VirtualFs>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+    fh: u64,
+    offset: u64,
+) -> DatenLordResult<Option<(Vec<u8>, u64)>> {
+    let Some(header) = read_exact(fs, ctx, ino, fh, offset, HEADER_SIZE).await? else {
+        return Ok(None);
+    };
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if len > MAX_RECORD_SIZE {
+        return Ok(None);
+    }
+    let data_offset = offset + HEADER_SIZE as u64;
+    let Some(data) = read_exact(fs, ctx, ino, fh, data_offset, len).await? else {
+        return Ok(None);
+    };
+    if crc32fast::hash(&data) != crc {
+        return Ok(None);
+    }
+    Ok(Some((data, data_offset + len as u64)))
+}
+
+impl<F: VirtualFs + 'static> AppendLog<F> {
+    /// Open the log at `path`, relative to the root of `fs`, on behalf of
+    /// `ctx`, creating it when missing
+    ///
+    /// The records are scanned to find the end of the log, and a partly
+    /// written record at the end is cut off. The writer runs on the current
+    /// runtime.
+    pub async fn open(
+        fs: Arc<F>,
+        ctx: RequestContext,
+        path: &OsStr,
+        sync: LogSync,
+    ) -> DatenLordResult<Self> {
+        let param = CreateParam {
+            parent: ROOT_ID,
+            name: path.to_owned(),
+            mode: LOG_MODE,
+            rdev: 0,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        let ino = match fs.mknod(&ctx, param).await {
+            Ok((_, attr, _)) => attr.ino,
+            Err(DatenLordError::AlreadyExists { .. }) => {
+                fs.lookup(&ctx, ROOT_ID, path).await?.1.ino
+            }
+            Err(e) => return Err(e),
+        };
+        let (_, attr) = fs.getattr(&ctx, ino).await?;
+        if attr.kind != SFlag::S_IFREG {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("log {path:?} is not a regular file")],
+            });
+        }
+        let fh = fs.open(&ctx, ino, OFlag::O_RDWR.bits() as u32).await?;
+        let end = match Self::recover(&fs, &ctx, ino, fh, attr.size).await {
+            Ok(end) => end,
+            Err(e) => {
+                let _ = fs.release(&ctx, ino, fh, 0, 0, false).await;
+                return Err(e);
+            }
+        };
+
+        let (published, appended) = watch::channel(end);
+        let end = Arc::new(AtomicU64::new(end));
+        let (requests, queue) = mpsc::channel(QUEUE_DEPTH);
+        let writer = Writer {
+            fs: Arc::clone(&fs),
+            ctx,
+            ino,
+            fh,
+            end: Arc::clone(&end),
+            appended: published,
+            sync,
+            last_sync: Instant::now(),
+            unsynced: false,
+            failed: None,
+        };
+        let writer = tokio::spawn(writer.run(queue));
+        Ok(Self {
+            fs,
+            ctx,
+            ino,
+            fh,
+            end,
+            appended,
+            requests: Some(requests),
+            writer: Some(writer),
+        })
+    }
+
+    /// Find the end of the valid records of the file of `size` bytes, and
+    /// cut off what follows
+    async fn recover(
+        fs: &F,
+        ctx: &RequestContext,
+        ino: INum,
+        fh: u64,
+        size: u64,
+    ) -> DatenLordResult<u64> {
+        let mut end = 0;
+        while end < size {
+            match read_record(fs, ctx, ino, fh, end).await? {
+                Some((_, next)) => end = next,
+                None => break,
+            }
+        }
+        if end < size {
+            let param = SetAttrParam {
+                fh: Some(fh),
+                size: Some(end),
+                ..SetAttrParam::default()
+            };
+            fs.setattr(ctx, ino, param).await?;
+        }
+        Ok(end)
+    }
+
+    /// The end of the records written so far, where the next record is
+    /// appended unless appends are queued
+    pub fn end(&self) -> u64 {
+        self.end.load(Ordering::Acquire)
+    }
+
+    /// Send `request` to the writer
+    async fn send(&self, request: Request) -> DatenLordResult<()> {
+        let stopped = || DatenLordError::Internal {
+            context: vec!["log writer stopped".to_owned()],
+        };
+        let requests = self.requests.as_ref().ok_or_else(stopped)?;
+        requests.send(request).await.map_err(|_| stopped())
+    }
+
+    /// Append `record`, returning the offset it was written at once written,
+    /// and synced with `LogSync::Batch`
+    ///
+    /// Records are written in the order the appends are called in. A failed
+    /// write fails the appends queued after it too, until the log is opened
+    /// again, so the log never has holes.
+    pub async fn append(&self, record: &[u8]) -> DatenLordResult<u64> {
+        let frame = frame(record)?;
+        let (reply, offset) = oneshot::channel();
+        self.send(Request::Append(frame, reply)).await?;
+        offset.await.map_err(|_| DatenLordError::Internal {
+            context: vec!["log writer stopped".to_owned()],
+        })?
+    }
+
+    /// Sync the records appended so far to the backend
+    pub async fn sync(&self) -> DatenLordResult<()> {
+        let (reply, synced) = oneshot::channel();
+        self.send(Request::Sync(reply)).await?;
+        synced.await.map_err(|_| DatenLordError::Internal {
+            context: vec!["log writer stopped".to_owned()],
+        })?
+    }
+
+    /// The records from `offset` on, at most `limit` of them
+    ///
+    /// `offset` is 0 or an offset returned by `append`, and reading from
+    /// `end()` returns no records. The records are read as written so far,
+    /// whether synced or not.
+    pub async fn read_from(&self, offset: u64, limit: usize) -> DatenLordResult<Vec<LogRecord>> {
+        let end = self.end();
+        if offset > end {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("log offset {offset} is past the end {end}")],
+            });
+        }
+        let mut records = Vec::new();
+        let mut next = offset;
+        while next < end && records.len() < limit {
+            let record = read_record(&*self.fs, &self.ctx, self.ino, self.fh, next).await?;
+            let Some((data, after)) = record else {
+                return Err(DatenLordError::InvalidArgument {
+                    context: vec![format!("no log record at offset {next}")],
+                });
+            };
+            records.push(LogRecord { offset: next, data });
+            next = after;
+        }
+        Ok(records)
+    }
+
+    /// Follow the records from `offset` on, those appended later included
+    ///
+    /// `offset` is 0 or an offset returned by `append`. Only the records
+    /// appended through this log are waited for, not those other processes
+    /// write to the file.
+    pub fn tail(self: &Arc<Self>, offset: u64) -> LogTail<F> {
+        LogTail {
+            log: Arc::clone(self),
+            offset,
+            appended: self.appended.clone(),
+            records: VecDeque::new(),
+        }
+    }
+
+    /// Write and sync the queued records and close the log
+    pub async fn close(mut self) -> DatenLordResult<()> {
+        drop(self.requests.take());
+        if let Some(writer) = self.writer.take() {
+            writer.await.map_err(|e| DatenLordError::Internal {
+                context: vec![format!("log writer panicked: {e}")],
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The task writing the records of a log in order
+struct Writer<F: VirtualFs> {
+    /// The filesystem holding the log
+    fs: Arc<F>,
+    /// The caller the log is written on behalf of
+    ctx: RequestContext,
+    /// The inode of the log file
+    ino: INum,
+    /// The handle of the log file, released once stopped
+    fh: u64,
+    /// The end of the records written so far
+    end: Arc<AtomicU64>,
+    /// Publishes the end to the tails once records are written
+    appended: watch::Sender<u64>,
+    /// When the records are synced
+    sync: LogSync,
+    /// When the records were last synced
+    last_sync: Instant,
+    /// Whether records were written since the last sync
+    unsynced: bool,
+    /// Why an earlier write failed, failing the appends since
+    failed: Option<String>,
+}
+
+impl<F: VirtualFs> Writer<F> {
+    /// Serve `queue` until every sender is gone, then sync and release the file
+    async fn run(mut self, mut queue: mpsc::Receiver<Request>) {
+        loop {
+            let deadline = match self.sync {
+                LogSync::Interval(interval) if self.unsynced => Some(self.last_sync + interval),
+                LogSync::None | LogSync::Batch | LogSync::Interval(_) => None,
+            };
+            let request = match deadline {
+                Some(deadline) => tokio::select! {
+                    request = queue.recv() => request,
+                    () = tokio::time::sleep_until(deadline) => {
+                        if self.sync_file().await.is_err() {
+                            // Retried one interval later
+                            self.last_sync = Instant::now();
+                        }
+                        continue;
+                    }
+                },
+                None => queue.recv().await,
+            };
+            let Some(request) = request else {
+                break;
+            };
+            let mut batch = Vec::new();
+            let mut pending = Some(request);
+            let mut bytes = 0;
+            while let Some(request) = pending.take() {
+                match request {
+                    Request::Append(frame, reply) => {
+                        bytes += frame.len();
+                        batch.push((frame, reply));
+                        if bytes < MAX_BATCH_BYTES {
+                            pending = queue.try_recv().ok();
+                        }
+                    }
+                    Request::Sync(reply) => {
+                        self.write_batch(std::mem::take(&mut batch)).await;
+                        let _ = reply.send(self.sync_file().await);
+                    }
+                }
+            }
+            self.write_batch(batch).await;
+        }
+        if self.unsynced {
+            let _ = self.sync_file().await;
+        }
+        let _ = self.fs.release(&self.ctx, self.ino, self.fh, 0, 0, true).await;
+    }
+
+    /// Write the frames of `batch` in one write and reply to their appends
+    async fn write_batch(&mut self, batch: Vec<(Vec<u8>, oneshot::Sender<DatenLordResult<u64>>)>) {
+        if batch.is_empty() {
+            return;
+        }
+        let start = self.end.load(Ordering::Acquire);
+        let result = match self.failed {
+            Some(ref failed) => Err(failed.clone()),
+            None => {
+                let data: Vec<u8> = batch.iter().flat_map(|(frame, _)| frame).copied().collect();
+                let mut result = self
+                    .fs
+                    .write(&self.ctx, self.ino, self.fh, start as i64, &data, 0)
+                    .await
+                    .map(|()| data.len() as u64);
+                if result.is_ok() {
+                    self.unsynced = true;
+                    let due = match self.sync {
+                        LogSync::None => false,
+                        LogSync::Batch => true,
+                        LogSync::Interval(interval) => self.last_sync.elapsed() >= interval,
+                    };
+                    if due {
+                        result = self.sync_file().await.map(|()| data.len() as u64);
+                    }
+                }
+                result.map_err(|e| {
+                    let failed = e.to_string();
+                    self.failed = Some(failed.clone());
+                    failed
+                })
+            }
+        };
+        match result {
+            Ok(len) => {
+                self.end.store(start + len, Ordering::Release);
+                self.appended.send_replace(start + len);
+                let mut offset = start;
+                for (frame, reply) in batch {
+                    let _ = reply.send(Ok(offset));
+                    offset += frame.len() as u64;
+                }
+            }
+            Err(failed) => {
+                for (_, reply) in batch {
+                    let _ = reply.send(Err(DatenLordError::Io {
+                        context: vec![format!("failed to append to log: {failed}")],
+                        source: None,
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Sync the data of the log file
+    async fn sync_file(&mut self) -> DatenLordResult<()> {
+        self.fs.fsync(&self.ctx, self.ino, self.fh, true).await?;
+        self.last_sync = Instant::now();
+        self.unsynced = false;
+        Ok(())
+    }
+}
+
+/// The records of a log from an offset on, waiting for new ones once the
+/// written ones are read, see `AppendLog::tail`
+#[derive(Debug)]
+pub struct LogTail<F: VirtualFs + 'static> {
+    /// The log followed, kept open by the tail
+    log: Arc<AppendLog<F>>,
+    /// The offset of the next record not read yet
+    offset: u64,
+    /// The end of the records written so far
+    appended: watch::Receiver<u64>,
+    /// The records read but not returned yet
+    records: VecDeque<LogRecord>,
+}
+
+impl<F: VirtualFs + 'static> LogTail<F> {
+    /// The next record, waiting until one is appended, `None` once the
+    /// writer of the log stopped
+    pub async fn next(&mut self) -> DatenLordResult<Option<LogRecord>> {
+        loop {
+            if let Some(record) = self.records.pop_front() {
+                return Ok(Some(record));
+            }
+            let end = *self.appended.borrow_and_update();
+            if self.offset < end {
+                let records = self.log.read_from(self.offset, TAIL_BATCH).await?;
+                if let Some(last) = records.last() {
+                    self.offset = last.offset + (HEADER_SIZE + last.data.len()) as u64;
+                }
+                self.records.extend(records);
+                continue;
+            }
+            if self.appended.changed().await.is_err() {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// The offset of the next record not returned yet
+    pub fn offset(&self) -> u64 {
+        self.records.front().map_or(self.offset, |record| record.offset)
+    }
+}
+
+/// The logs appended to by path, with a single writer per log file shared
+/// by every caller
+///
+/// Appends through the same `SharedLogs` to the same file, by any path
+/// naming it, are atomic and ordered whichever task or thread makes them.
+/// Logs are opened on first use, on the current runtime, and stay open
+/// until `close`.
+#[derive(Debug)]
+pub struct SharedLogs<F: VirtualFs + 'static> {
+    /// The filesystem holding the logs
+    fs: Arc<F>,
+    /// The caller the logs are used on behalf of
+    ctx: RequestContext,
+    /// When the logs are synced
+    sync: LogSync,
+    /// The open logs by inode
+    logs: Mutex<HashMap<INum, Arc<AppendLog<F>>>>,
+}
+
+impl<F: VirtualFs + 'static> SharedLogs<F> {
+    /// The logs of `fs`, used on behalf of `ctx` and synced as `sync` says
+    pub fn new(fs: Arc<F>, ctx: RequestContext, sync: LogSync) -> Self {
+        Self {
+            fs,
+            ctx,
+            sync,
+            logs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The log at `path`, relative to the root, opened and created when
+    /// missing
+    pub async fn log(&self, path: &OsStr) -> DatenLordResult<Arc<AppendLog<F>>> {
+        let mut logs = self.logs.lock().await;
+        if let Ok((_, attr, _)) = self.fs.lookup(&self.ctx, ROOT_ID, path).await {
+            if let Some(log) = logs.get(&attr.ino) {
+                return Ok(Arc::clone(log));
+            }
+        }
+        let log = AppendLog::open(Arc::clone(&self.fs), self.ctx, path, self.sync).await?;
+        let log = Arc::new(log);
+        logs.insert(log.ino, Arc::clone(&log));
+        Ok(log)
+    }
+
+    /// Append `record` to the log at `path`, returning the offset it was
+    /// written at, see `AppendLog::append`
+    pub async fn append(&self, path: &OsStr, record: &[u8]) -> DatenLordResult<u64> {
+        self.log(path).await?.append(record).await
+    }
+
+    /// Follow the records of the log at `path` from `offset` on, see
+    /// `AppendLog::tail`
+    pub async fn tail(&self, path: &OsStr, offset: u64) -> DatenLordResult<LogTail<F>> {
+        let log = self.log(path).await?;
+        let end = log.end();
+        if offset > end {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("log offset {offset} is past the end {end}")],
+            });
+        }
+        Ok(log.tail(offset))
+    }
+
+    /// Sync and close the open logs, those still followed by a tail being
+    /// synced and closed once the tail is dropped
+    pub async fn close(&self) -> DatenLordResult<()> {
+        let logs = std::mem::take(&mut *self.logs.lock().await);
+        let mut result = Ok(());
+        for (_, log) in logs {
+            let closed = match Arc::try_unwrap(log) {
+                Ok(log) => log.close().await,
+                Err(log) => log.sync().await,
+            };
+            result = result.and(closed);
+        }
+        result
+    }
+}
@@ -0,0 +1,845 @@
+//! Export of a directory tree of a filesystem to a tar archive and import of
+//! one, streamed between the filesystem and the archive without a copy on
+//! the local disk
+//!
+//! Archives are POSIX pax: ustar headers, extended by pax headers for paths
+//! and link targets longer than the ustar fields, sizes and ids too large
+//! for them, sub-second modification times and the extended attributes,
+//! stored as `SCHILY.xattr.<name>` like GNU tar does. GNU long names are
+//! read too. Regular files, directories and symbolic links are archived
+//! with their mode, owner and modification time; hard links are archived
+//! as separate files and other kinds are skipped.
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult, ResultExt};
+
+use super::fs_util::{self, CreateParam, FileAttr, RequestContext, SetAttrParam, ROOT_ID};
+use super::virtualfs::{INum, VirtualFs};
+
+/// The size of the blocks of a tar archive
+const BLOCK_SIZE: usize = 512;
+/// The mode of the directories `import_tar` creates before setting theirs
+const DIR_MODE: u32 = 0o777;
+/// The prefix of the pax records holding extended attributes
+const XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// The type flags of the tar entries this module reads or writes
+mod kind {
+    pub(super) const REGULAR: u8 = b'0';
+    pub(super) const OLD_REGULAR: u8 = b'\0';
+    pub(super) const CONTIGUOUS: u8 = b'7';
+    pub(super) const HARD_LINK: u8 = b'1';
+    pub(super) const SYMLINK: u8 = b'2';
+    pub(super) const DIRECTORY: u8 = b'5';
+    pub(super) const PAX: u8 = b'x';
+    pub(super) const PAX_GLOBAL: u8 = b'g';
+    pub(super) const GNU_LONG_NAME: u8 = b'L';
+    pub(super) const GNU_LONG_LINK: u8 = b'K';
+}
+
+/// What `export_tar` or `import_tar` moved
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveReport {
+    /// The regular files
+    pub files: u64,
+    /// The directories, the one exported or imported into excluded
+    pub dirs: u64,
+    /// The symbolic links
+    pub symlinks: u64,
+    /// The bytes of the regular files
+    pub bytes: u64,
+    /// The entries left out, of another kind or with a path leading out of
+    /// the destination
+    pub skipped: u64,
+}
+
+/// A `DatenLordError::InvalidArgument` for a malformed archive
+fn malformed(reason: impl std::fmt::Display) -> DatenLordError {
+    DatenLordError::InvalidArgument {
+        context: vec![format!("malformed tar archive: {reason}")],
+    }
+}
+
+/// An archived entry
+#[derive(Debug)]
+struct Member {
+    /// The path relative to the directory archived
+    path: Vec<u8>,
+    /// The type flag
+    kind: u8,
+    /// The permission bits
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    /// The bytes of data following the header
+    size: u64,
+    mtime: SystemTime,
+    /// The target of a link
+    link: Vec<u8>,
+    /// The extended attributes
+    xattrs: Vec<(String, Vec<u8>)>,
+}
+
+impl Default for Member {
+    fn default() -> Self {
+        Self {
+            path: Vec::new(),
+            kind: kind::REGULAR,
+            mode: 0,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            mtime: UNIX_EPOCH,
+            link: Vec::new(),
+            xattrs: Vec::new(),
+        }
+    }
+}
+
+/// Write `value` as a NUL-terminated octal number filling `field`, false if
+/// it does not fit
+fn put_octal(field: &mut [u8], value: u64) -> bool {
+    let digits = field.len() - 1;
+    let text = format!("{value:0digits$o}");
+    if text.len() > digits {
+        return false;
+    }
+    field[..digits].copy_from_slice(text.as_bytes());
+    field[digits] = 0;
+    true
+}
+
+/// The number of the numeric header field `field`, octal or base-256
+fn get_number(field: &[u8]) -> DatenLordResult<u64> {
+    if field.first().is_some_and(|&byte| byte & 0x80 != 0) {
+        return Ok(field[1..]
+            .iter()
+            .fold(u64::from(field[0] & 0x7f), |n, &byte| (n << 8) | u64::from(byte)));
+    }
+    let text = field
+        .iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| char::from(byte))
+        .collect::<String>();
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| malformed(format!("bad number {text:?}")))
+}
+
+/// The bytes of the text header field `field` up to its first NUL
+fn get_text(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    &field[..end]
+}
+
+/// The pax record of `key` and `value`, led by its own length
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let body = key.len() + value.len() + 3;
+    let mut len = body + body.to_string().len();
+    if len.to_string().len() + body > len {
+        len += 1;
+    }
+    let mut record = format!("{len} {key}=").into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/// The seconds and nanoseconds of `time` after the epoch, 0 before it
+fn epoch_time(time: SystemTime) -> (u64, u32) {
+    time.duration_since(UNIX_EPOCH)
+        .map_or((0, 0), |since| (since.as_secs(), since.subsec_nanos()))
+}
+
+/// The ustar header of `member`, led by a pax header for what does not fit
+fn encode_header(member: &Member) -> Vec<u8> {
+    let mut header = [0_u8; BLOCK_SIZE];
+    let mut pax = Vec::new();
+    let (secs, nanos) = epoch_time(member.mtime);
+
+    let name_len = member.path.len().min(100);
+    header[..name_len].copy_from_slice(&member.path[..name_len]);
+    if member.path.len() > 100 {
+        pax.extend(pax_record("path", &member.path));
+    }
+    put_octal(&mut header[100..108], u64::from(member.mode & 0o7777));
+    if !put_octal(&mut header[108..116], u64::from(member.uid)) {
+        pax.extend(pax_record("uid", member.uid.to_string().as_bytes()));
+    }
+    if !put_octal(&mut header[116..124], u64::from(member.gid)) {
+        pax.extend(pax_record("gid", member.gid.to_string().as_bytes()));
+    }
+    if !put_octal(&mut header[124..136], member.size) {
+        pax.extend(pax_record("size", member.size.to_string().as_bytes()));
+    }
+    put_octal(&mut header[136..148], secs);
+    if nanos != 0 {
+        pax.extend(pax_record("mtime", format!("{secs}.{nanos:09}").as_bytes()));
+    }
+    header[156] = member.kind;
+    let link_len = member.link.len().min(100);
+    header[157..157 + link_len].copy_from_slice(&member.link[..link_len]);
+    if member.link.len() > 100 {
+        pax.extend(pax_record("linkpath", &member.link));
+    }
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    for (name, value) in &member.xattrs {
+        pax.extend(pax_record(&format!("{XATTR_PREFIX}{name}"), value));
+    }
+    seal(&mut header);
+
+    let mut encoded = Vec::with_capacity(BLOCK_SIZE);
+    if !pax.is_empty() {
+        let mut pax_header = [0_u8; BLOCK_SIZE];
+        pax_header[..100].copy_from_slice(&header[..100]);
+        pax_header[100..108].copy_from_slice(b"0000644\0");
+        pax_header[108..124].copy_from_slice(&header[108..124]);
+        put_octal(&mut pax_header[124..136], pax.len() as u64);
+        pax_header[136..148].copy_from_slice(&header[136..148]);
+        pax_header[156] = kind::PAX;
+        pax_header[257..265].copy_from_slice(&header[257..265]);
+        seal(&mut pax_header);
+        encoded.extend_from_slice(&pax_header);
+        let padded = pax.len().next_multiple_of(BLOCK_SIZE);
+        pax.resize(padded, 0);
+        encoded.extend(pax);
+    }
+    encoded.extend_from_slice(&header);
+    encoded
+}
+
+/// Fill in the checksum of `header`
+fn seal(header: &mut [u8; BLOCK_SIZE]) {
+    header[148..156].fill(b' ');
+    let sum: u64 = header.iter().map(|&byte| u64::from(byte)).sum();
+    put_octal(&mut header[148..155], sum);
+    header[155] = b' ';
+}
+
+/// Whether the checksum of `header` is right
+fn checksum_matches(header: &[u8; BLOCK_SIZE]) -> DatenLordResult<bool> {
+    let expected = get_number(&header[148..156])?;
+    let unsigned: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if (148..156).contains(&i) { 32 } else { u64::from(byte) })
+        .sum();
+    // Some old writers summed signed bytes
+    let signed: i64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &byte)| if (148..156).contains(&i) { 32 } else { i64::from(byte as i8) })
+        .sum();
+    Ok(expected == unsigned || i64::try_from(expected).is_ok_and(|expected| expected == signed))
+}
+
+/// Write `data` followed by the zeros padding it to a whole block
+async fn write_padded<W: AsyncWrite + Unpin + ?Sized>(
+    writer: &mut W,
+    data: &[u8],
+) -> DatenLordResult<()> {
+    writer
+        .write_all(data)
+        .await
+        .add_context("failed to write the archive")?;
+    let padding = data.len().next_multiple_of(BLOCK_SIZE) - data.len();
+    writer
+        .write_all(&[0; BLOCK_SIZE][..padding])
+        .await
+        .add_context("failed to write the archive")
+}
+
+/// The entries of the directory `ino` with their attributes
+async fn list_dir<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+) -> DatenLordResult<Vec<(OsString, FileAttr)>> {
+    let fh = fs.opendir(ctx, ino, 0).await?;
+    let mut entries = Vec::new();
+    let listed = loop {
+        let offset = i64::try_from(entries.len()).unwrap_or(i64::MAX);
+        match fs.readdirplus(ctx, ino, fh, offset).await {
+            Ok(page) if page.is_empty() => break Ok(()),
+            Ok(page) => entries.extend(page.into_iter().map(|(entry, attr, _)| (entry.name, attr))),
+            Err(e) => break Err(e),
+        }
+    };
+    fs.releasedir(ctx, ino, fh, 0).await?;
+    listed?;
+    entries.retain(|(name, _)| name != "." && name != "..");
+    Ok(entries)
+}
+
+/// The extended attributes of `ino`, none if the filesystem has none
+async fn read_xattrs<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+) -> DatenLordResult<Vec<(String, Vec<u8>)>> {
+    let names = match fs.listxattr(ctx, ino).await {
+        Ok(names) => names,
+        Err(DatenLordError::Unimplemented { .. }) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut xattrs = Vec::with_capacity(names.len());
+    for name in names {
+        let value = fs.getxattr(ctx, ino, &name).await?;
+        xattrs.push((name, value));
+    }
+    Ok(xattrs)
+}
+
+/// Write the header and the data of the entry `attr` at `path` in the
+/// archive
+async fn export_entry<F, W>(
+    fs: &F,
+    ctx: &RequestContext,
+    writer: &mut W,
+    path: &Path,
+    attr: &FileAttr,
+    report: &mut ArchiveReport,
+) -> DatenLordResult<()>
+where
+    F: VirtualFs + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut member = Member {
+        path: path.as_os_str().as_bytes().to_vec(),
+        mode: u32::from(attr.perm),
+        uid: attr.uid,
+        gid: attr.gid,
+        mtime: attr.mtime,
+        ..Member::default()
+    };
+    match attr.kind {
+        SFlag::S_IFDIR => {
+            member.kind = kind::DIRECTORY;
+            member.path.push(b'/');
+            member.xattrs = read_xattrs(fs, ctx, attr.ino).await?;
+            report.dirs += 1;
+        }
+        SFlag::S_IFLNK => {
+            member.kind = kind::SYMLINK;
+            member.link = fs.readlink(ctx, attr.ino).await?;
+            report.symlinks += 1;
+        }
+        SFlag::S_IFREG => {
+            member.kind = kind::REGULAR;
+            member.size = attr.size;
+            member.xattrs = read_xattrs(fs, ctx, attr.ino).await?;
+        }
+        _ => {
+            warn!("{path:?} is neither a file, a directory nor a symbolic link, it is not archived");
+            report.skipped += 1;
+            return Ok(());
+        }
+    }
+    writer
+        .write_all(&encode_header(&member))
+        .await
+        .add_context("failed to write the archive")?;
+    if member.kind == kind::REGULAR {
+        export_data(fs, ctx, writer, path, attr.ino, member.size).await?;
+        report.files += 1;
+        report.bytes += member.size;
+    }
+    Ok(())
+}
+
+/// Write the first `size` bytes of the file `ino`, padded to a whole block
+///
+/// The header already holds the size, so a file shrinking meanwhile is
+/// padded with zeros and one growing is cut.
+async fn export_data<F, W>(
+    fs: &F,
+    ctx: &RequestContext,
+    writer: &mut W,
+    path: &Path,
+    ino: INum,
+    size: u64,
+) -> DatenLordResult<()>
+where
+    F: VirtualFs + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let flags = OFlag::O_RDONLY.bits() as u32;
+    let fh = fs.open(ctx, ino, flags).await?;
+    let mut buf = vec![0; fs.block_layout().block_len().next_multiple_of(BLOCK_SIZE)];
+    let mut offset = 0;
+    let copied = async {
+        while offset < size {
+            let want = usize::try_from(size - offset).map_or(buf.len(), |left| left.min(buf.len()));
+            let read = fs
+                .read(ctx, ino, fh, offset, u32::try_from(want).unwrap_or(u32::MAX), &mut buf[..want])
+                .await?;
+            let chunk = if read == 0 {
+                warn!("{path:?} shrank while archived, padding it with zeros");
+                buf[..want].fill(0);
+                want
+            } else {
+                read
+            };
+            writer
+                .write_all(&buf[..chunk])
+                .await
+                .add_context("failed to write the archive")?;
+            offset += chunk as u64;
+        }
+        Ok::<_, DatenLordError>(())
+    }
+    .await;
+    fs.release(ctx, ino, fh, flags, 0, false).await?;
+    copied?;
+    let padding = usize::try_from(size % BLOCK_SIZE as u64).map_or(0, |tail| {
+        if tail == 0 {
+            0
+        } else {
+            BLOCK_SIZE - tail
+        }
+    });
+    writer
+        .write_all(&[0; BLOCK_SIZE][..padding])
+        .await
+        .add_context("failed to write the archive")
+}
+
+/// Write the entry at `path`, relative to the root of `fs`, and everything
+/// below it when a directory, to `writer` as a tar archive on behalf of
+/// `ctx`
+///
+/// Entries are named relative to `path`, a file by its own name, and the
+/// directories come before their entries, which come sorted by name. The
+/// archive ends with the two zero blocks of the format, the writer is
+/// flushed but not shut down.
+pub async fn export_tar<F, W>(
+    fs: &F,
+    ctx: &RequestContext,
+    path: &OsStr,
+    writer: &mut W,
+) -> DatenLordResult<ArchiveReport>
+where
+    F: VirtualFs + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let path = fs_util::normalize(path);
+    let attr = if path.is_empty() {
+        fs.getattr(ctx, ROOT_ID).await?.1
+    } else {
+        fs.lookup(ctx, ROOT_ID, &path).await?.1
+    };
+    let mut report = ArchiveReport::default();
+    if attr.kind == SFlag::S_IFDIR {
+        let mut pending = vec![(PathBuf::new(), attr.ino)];
+        while let Some((dir, ino)) = pending.pop() {
+            let mut entries = list_dir(fs, ctx, ino).await?;
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut subdirs = Vec::new();
+            for (name, attr) in entries {
+                let path = dir.join(&name);
+                export_entry(fs, ctx, writer, &path, &attr, &mut report).await?;
+                if attr.kind == SFlag::S_IFDIR {
+                    subdirs.push((path, attr.ino));
+                }
+            }
+            pending.extend(subdirs.into_iter().rev());
+        }
+    } else {
+        let name = Path::new(&path).file_name().map_or_else(PathBuf::new, PathBuf::from);
+        export_entry(fs, ctx, writer, &name, &attr, &mut report).await?;
+    }
+    write_padded(writer, &[0; 2 * BLOCK_SIZE]).await?;
+    writer
+        .flush()
+        .await
+        .add_context("failed to write the archive")?;
+    Ok(report)
+}
+
+/// Read the next block into `block`, false at the end of the stream
+async fn read_block<R: AsyncRead + Unpin + ?Sized>(
+    reader: &mut R,
+    block: &mut [u8; BLOCK_SIZE],
+) -> DatenLordResult<bool> {
+    let mut filled = 0;
+    while filled < BLOCK_SIZE {
+        let read = reader
+            .read(&mut block[filled..])
+            .await
+            .add_context("failed to read the archive")?;
+        if read == 0 {
+            if filled == 0 {
+                return Ok(false);
+            }
+            return Err(malformed("truncated block"));
+        }
+        filled += read;
+    }
+    Ok(true)
+}
+
+/// Read the `size` bytes of data following a header and their padding
+async fn read_data<R: AsyncRead + Unpin + ?Sized>(
+    reader: &mut R,
+    size: u64,
+) -> DatenLordResult<Vec<u8>> {
+    let len = usize::try_from(size).map_err(|_| malformed("oversized extended header"))?;
+    let mut data = vec![0; len.next_multiple_of(BLOCK_SIZE)];
+    reader
+        .read_exact(&mut data)
+        .await
+        .add_context("failed to read the archive")?;
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Skip the `size` bytes of data following a header and their padding
+async fn skip_data<R: AsyncRead + Unpin + ?Sized>(reader: &mut R, size: u64) -> DatenLordResult<()> {
+    let padded = size.next_multiple_of(BLOCK_SIZE as u64);
+    let skipped = tokio::io::copy(&mut (&mut *reader).take(padded), &mut tokio::io::sink())
+        .await
+        .add_context("failed to read the archive")?;
+    if skipped < padded {
+        return Err(malformed("truncated entry"));
+    }
+    Ok(())
+}
+
+/// Apply the pax records of `data` to `member`
+fn apply_pax(member: &mut Member, data: &[u8]) -> DatenLordResult<()> {
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest
+            .iter()
+            .position(|&byte| byte == b' ')
+            .ok_or_else(|| malformed("bad pax record"))?;
+        let len: usize = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .filter(|&len| len > space && len <= rest.len())
+            .ok_or_else(|| malformed("bad pax record length"))?;
+        let record = &rest[space + 1..len];
+        rest = &rest[len..];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        let Some(eq) = record.iter().position(|&byte| byte == b'=') else {
+            return Err(malformed("bad pax record"));
+        };
+        let (key, value) = (String::from_utf8_lossy(&record[..eq]), &record[eq + 1..]);
+        let number = || {
+            std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| malformed(format!("bad pax {key}")))
+        };
+        match &*key {
+            "path" => member.path = value.to_vec(),
+            "linkpath" => member.link = value.to_vec(),
+            "size" => member.size = number()?,
+            "uid" => member.uid = u32::try_from(number()?).map_err(|_| malformed("bad pax uid"))?,
+            "gid" => member.gid = u32::try_from(number()?).map_err(|_| malformed("bad pax gid"))?,
+            "mtime" => {
+                let text = String::from_utf8_lossy(value);
+                let (secs, fraction) = text.split_once('.').unwrap_or((&text, ""));
+                let secs: u64 = secs.parse().map_err(|_| malformed("bad pax mtime"))?;
+                let nanos = format!("{fraction:0<9}")
+                    .get(..9)
+                    .and_then(|nanos| nanos.parse::<u32>().ok())
+                    .unwrap_or(0);
+                member.mtime = UNIX_EPOCH + Duration::new(secs, nanos);
+            }
+            _ => {
+                if let Some(name) = key.strip_prefix(XATTR_PREFIX) {
+                    member.xattrs.push((name.to_owned(), value.to_vec()));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The next member of the archive with the extended headers before it
+/// applied, `None` at its end
+async fn next_member<R: AsyncRead + Unpin + ?Sized>(
+    reader: &mut R,
+) -> DatenLordResult<Option<Member>> {
+    let mut extended = Member::default();
+    let (mut long_name, mut long_link) = (None, None);
+    let mut block = [0_u8; BLOCK_SIZE];
+    loop {
+        if !read_block(reader, &mut block).await? || block.iter().all(|&byte| byte == 0) {
+            return Ok(None);
+        }
+        if !checksum_matches(&block)? {
+            return Err(malformed("bad header checksum"));
+        }
+        let size = get_number(&block[124..136])?;
+        match block[156] {
+            kind::PAX => {
+                let data = read_data(reader, size).await?;
+                apply_pax(&mut extended, &data)?;
+            }
+            kind::PAX_GLOBAL => skip_data(reader, size).await?,
+            kind::GNU_LONG_NAME => {
+                long_name = Some(get_text(&read_data(reader, size).await?).to_vec());
+            }
+            kind::GNU_LONG_LINK => {
+                long_link = Some(get_text(&read_data(reader, size).await?).to_vec());
+            }
+            entry_kind => {
+                let mut path = get_text(&block[345..500]).to_vec();
+                if !path.is_empty() {
+                    path.push(b'/');
+                }
+                path.extend_from_slice(get_text(&block[..100]));
+                let mut member = Member {
+                    path: long_name.unwrap_or(path),
+                    kind: entry_kind,
+                    mode: u32::try_from(get_number(&block[100..108])? & 0o7777).unwrap_or(0),
+                    uid: u32::try_from(get_number(&block[108..116])?).unwrap_or(0),
+                    gid: u32::try_from(get_number(&block[116..124])?).unwrap_or(0),
+                    size,
+                    mtime: UNIX_EPOCH + Duration::from_secs(get_number(&block[136..148])?),
+                    link: long_link.unwrap_or_else(|| get_text(&block[157..257]).to_vec()),
+                    xattrs: Vec::new(),
+                };
+                if !extended.path.is_empty() {
+                    member.path = extended.path;
+                }
+                if !extended.link.is_empty() {
+                    member.link = extended.link;
+                }
+                if extended.size != 0 {
+                    member.size = extended.size;
+                }
+                if extended.uid != 0 {
+                    member.uid = extended.uid;
+                }
+                if extended.gid != 0 {
+                    member.gid = extended.gid;
+                }
+                if extended.mtime != UNIX_EPOCH {
+                    member.mtime = extended.mtime;
+                }
+                member.xattrs = extended.xattrs;
+                return Ok(Some(member));
+            }
+        }
+    }
+}
+
+/// The path of a member relative to the destination, `None` if it is the
+/// destination itself or leads out of it
+fn member_path(raw: &[u8]) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in raw.split(|&byte| byte == b'/') {
+        match component {
+            b"" | b"." => {}
+            b".." => return None,
+            name => path.push(OsStr::from_bytes(name)),
+        }
+    }
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+/// Set the extended attributes of `member` on `ino`, warning about those
+/// the filesystem rejects
+async fn write_xattrs<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+    path: &Path,
+    member: &Member,
+) {
+    for (name, value) in &member.xattrs {
+        if let Err(e) = fs.setxattr(ctx, ino, name, value, 0, 0).await {
+            warn!("failed to restore xattr {name} of {path:?}: {e}");
+        }
+    }
+}
+
+/// The mode, owner and modification time of `member`, the owner only when
+/// `ctx` is the superuser
+fn member_attrs(ctx: &RequestContext, member: &Member) -> SetAttrParam {
+    SetAttrParam {
+        mode: Some(member.mode),
+        u_id: ctx.is_root().then_some(member.uid),
+        g_id: ctx.is_root().then_some(member.gid),
+        m_time: Some(member.mtime),
+        ..SetAttrParam::default()
+    }
+}
+
+/// Create the entry of `name` in `parent` with `create`, replacing a file
+/// or a symbolic link already there
+async fn create_replacing<F, C, Fut>(
+    fs: &F,
+    ctx: &RequestContext,
+    parent: INum,
+    name: &OsStr,
+    create: C,
+) -> DatenLordResult<FileAttr>
+where
+    F: VirtualFs + ?Sized,
+    C: Fn() -> Fut,
+    Fut: std::future::Future<Output = DatenLordResult<(Duration, FileAttr, u64)>>,
+{
+    match create().await {
+        Err(DatenLordError::AlreadyExists { .. }) => {
+            let (_, existing, _) = fs.lookup(ctx, parent, name).await?;
+            if existing.kind == SFlag::S_IFDIR {
+                return Err(DatenLordError::AlreadyExists {
+                    context: vec![format!("{name:?} is a directory")],
+                    source: None,
+                });
+            }
+            fs.unlink(ctx, parent, name).await?;
+            create().await.map(|(_, attr, _)| attr)
+        }
+        result => result.map(|(_, attr, _)| attr),
+    }
+}
+
+/// Write the `size` bytes of data following a header and their padding to
+/// the new file `ino`
+async fn import_data<F, R>(
+    fs: &F,
+    ctx: &RequestContext,
+    reader: &mut R,
+    ino: INum,
+    size: u64,
+) -> DatenLordResult<()>
+where
+    F: VirtualFs + ?Sized,
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let flags = OFlag::O_WRONLY.bits() as u32;
+    let fh = fs.open(ctx, ino, flags).await?;
+    let mut buf = vec![0; fs.block_layout().block_len().next_multiple_of(BLOCK_SIZE)];
+    let mut offset = 0;
+    let copied = async {
+        while offset < size {
+            let want = usize::try_from(size - offset).map_or(buf.len(), |left| left.min(buf.len()));
+            reader
+                .read_exact(&mut buf[..want])
+                .await
+                .map_err(|_| malformed("truncated entry"))?;
+            let at = i64::try_from(offset).map_err(|_| malformed("oversized entry"))?;
+            fs.write(ctx, ino, fh, at, &buf[..want], 0).await?;
+            offset += want as u64;
+        }
+        Ok::<_, DatenLordError>(())
+    }
+    .await;
+    let released = fs.release(ctx, ino, fh, flags, 0, true).await;
+    copied?;
+    released?;
+    let padding = usize::try_from(size.next_multiple_of(BLOCK_SIZE as u64) - size).unwrap_or(0);
+    reader
+        .read_exact(&mut [0; BLOCK_SIZE][..padding])
+        .await
+        .map_err(|_| malformed("truncated entry"))?;
+    Ok(())
+}
+
+/// Extract the tar archive read from `reader` into the directory `dest`,
+/// relative to the root of `fs`, on behalf of `ctx`, creating it and the
+/// missing parents of the entries
+///
+/// Files and symbolic links already there are replaced, directories are
+/// kept and take the attributes of the archive. Modes and modification
+/// times are restored, owners only for the superuser, and the extended
+/// attributes the filesystem rejects are left out with a warning. Entries
+/// whose path leads out of `dest` with `..`, hard links and other kinds
+/// are skipped.
+pub async fn import_tar<F, R>(
+    fs: &F,
+    ctx: &RequestContext,
+    reader: &mut R,
+    dest: &OsStr,
+) -> DatenLordResult<ArchiveReport>
+where
+    F: VirtualFs + ?Sized,
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let dest = fs.mkdir_all(ctx, ROOT_ID, dest, DIR_MODE).await?;
+    let mut report = ArchiveReport::default();
+    // Set once their entries are in, which changes their modification time
+    // and may need the write permission the archive takes away
+    let mut dirs = Vec::new();
+    while let Some(member) = next_member(reader).await? {
+        let Some(path) = member_path(&member.path) else {
+            if member.path.split(|&byte| byte == b'/').any(|part| part == b"..") {
+                warn!("{:?} leads out of the destination, it is skipped", OsStr::from_bytes(&member.path));
+                report.skipped += 1;
+            }
+            skip_data(reader, member.size).await?;
+            continue;
+        };
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                fs.mkdir_all(ctx, dest.ino, parent.as_os_str(), DIR_MODE).await?.ino
+            }
+            _ => dest.ino,
+        };
+        let name = path.file_name().unwrap_or_default();
+        match member.kind {
+            kind::DIRECTORY => {
+                let attr = fs.mkdir_all(ctx, parent, name, DIR_MODE).await?;
+                write_xattrs(fs, ctx, attr.ino, &path, &member).await;
+                dirs.push((attr.ino, member_attrs(ctx, &member)));
+                skip_data(reader, member.size).await?;
+                report.dirs += 1;
+            }
+            kind::SYMLINK => {
+                let target = PathBuf::from(OsString::from_vec(member.link.clone()));
+                create_replacing(fs, ctx, parent, name, || fs.symlink(ctx, parent, name, &target))
+                    .await?;
+                skip_data(reader, member.size).await?;
+                report.symlinks += 1;
+            }
+            kind::REGULAR | kind::OLD_REGULAR | kind::CONTIGUOUS => {
+                let create = || {
+                    fs.mknod(
+                        ctx,
+                        CreateParam {
+                            parent,
+                            name: name.to_owned(),
+                            mode: member.mode | 0o600,
+                            rdev: 0,
+                            node_type: SFlag::S_IFREG,
+                            link: None,
+                        },
+                    )
+                };
+                let attr = create_replacing(fs, ctx, parent, name, create).await?;
+                import_data(fs, ctx, reader, attr.ino, member.size).await?;
+                write_xattrs(fs, ctx, attr.ino, &path, &member).await;
+                fs.setattr(ctx, attr.ino, member_attrs(ctx, &member)).await?;
+                report.files += 1;
+                report.bytes += member.size;
+            }
+            other => {
+                let what = if other == kind::HARD_LINK { "a hard link" } else { "of an unsupported kind" };
+                warn!("{path:?} is {what}, it is skipped");
+                skip_data(reader, member.size).await?;
+                report.skipped += 1;
+            }
+        }
+    }
+    for (ino, param) in dirs.into_iter().rev() {
+        fs.setattr(ctx, ino, param).await?;
+    }
+    Ok(report)
+}
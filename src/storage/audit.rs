@@ -0,0 +1,749 @@
+//! An audit log recording who changed what in a namespace
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use nix::fcntl::OFlag;
+use serde_derive::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{
+    self, CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// Default size past which the audit log is rotated, 64 MiB
+const DEFAULT_MAX_BYTES: u64 = 64 << 20;
+/// Default number of rotated audit logs kept
+const DEFAULT_KEEP: usize = 5;
+
+/// Whether and where mutating operations are recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Record every mutating operation, off by default
+    pub enabled: bool,
+    /// The file records are appended to as JSON lines, created when missing
+    pub path: PathBuf,
+    /// The size past which the log is rotated, 0 never rotates
+    pub max_bytes: u64,
+    /// The number of rotated logs kept as `<path>.1`, the most recent, to
+    /// `<path>.<keep>`
+    pub keep: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::new(),
+            max_bytes: DEFAULT_MAX_BYTES,
+            keep: DEFAULT_KEEP,
+        }
+    }
+}
+
+/// A mutating operation, serialized as one JSON line of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// When the operation finished, in nanoseconds since the epoch
+    pub timestamp_ns: i128,
+    /// The effective user id of the caller
+    pub uid: u32,
+    /// The effective group id of the caller
+    pub gid: u32,
+    /// The process id of the caller
+    pub pid: u32,
+    /// The `VirtualFs` method called, e.g. `unlink` or `setattr`
+    pub op: String,
+    /// The inode operated on, for operations on an open or known inode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ino: Option<INum>,
+    /// The directory holding the entry, for namespace changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<INum>,
+    /// The name of the entry in `parent`; the SDKs address everything from
+    /// the root, so it is usually the path in the namespace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The directory the entry moved to, for renames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_parent: Option<INum>,
+    /// The name the entry moved to, for renames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_name: Option<String>,
+    /// The offset written at, for writes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    /// The number of bytes written, for writes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub len: Option<u64>,
+    /// The mode set or created with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// The size truncated or extended to, for `setattr` and opens with
+    /// `O_TRUNC`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The owner set, for `setattr`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<(Option<u32>, Option<u32>)>,
+    /// The target of the link, for symlinks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// The extended attribute set or removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xattr: Option<String>,
+    /// Whether the operation succeeded
+    pub ok: bool,
+    /// The errno of the failure, when one applies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno: Option<i32>,
+    /// The failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    /// A record of `op` called by `ctx`, without a result yet
+    fn new(ctx: &RequestContext, op: &str) -> Self {
+        Self {
+            timestamp_ns: 0,
+            uid: ctx.uid,
+            gid: ctx.gid,
+            pid: ctx.pid,
+            op: op.to_owned(),
+            ino: None,
+            parent: None,
+            name: None,
+            new_parent: None,
+            new_name: None,
+            offset: None,
+            len: None,
+            mode: None,
+            size: None,
+            owner: None,
+            target: None,
+            xattr: None,
+            ok: true,
+            errno: None,
+            error: None,
+        }
+    }
+
+    /// The record about the inode `ino`
+    fn on(self, ino: INum) -> Self {
+        Self {
+            ino: Some(ino),
+            ..self
+        }
+    }
+
+    /// The record about the entry `name` in `parent`, with the bytes of
+    /// names that are not UTF-8 replaced
+    fn at(self, parent: INum, name: &OsStr) -> Self {
+        Self {
+            parent: Some(parent),
+            name: Some(name.to_string_lossy().into_owned()),
+            ..self
+        }
+    }
+
+    /// The record about an entry moved to `name` in `parent`
+    fn to(self, parent: INum, name: &OsStr) -> Self {
+        Self {
+            new_parent: Some(parent),
+            new_name: Some(name.to_string_lossy().into_owned()),
+            ..self
+        }
+    }
+
+    /// The record of the operation having finished with `result`
+    fn finished<T>(self, result: &DatenLordResult<T>) -> Self {
+        let (sec, nsec) = fs_util::to_timespec(SystemTime::now());
+        let timestamp_ns = i128::from(sec) * 1_000_000_000 + i128::from(nsec);
+        match *result {
+            Ok(_) => Self {
+                timestamp_ns,
+                ..self
+            },
+            Err(ref e) => Self {
+                timestamp_ns,
+                ok: false,
+                errno: e.errno().map(|errno| errno as i32),
+                error: Some(e.to_string()),
+                ..self
+            },
+        }
+    }
+}
+
+/// The open audit log
+#[derive(Debug)]
+struct AuditLog {
+    /// Where records are appended
+    path: PathBuf,
+    /// The size past which the log is rotated, 0 never rotates
+    max_bytes: u64,
+    /// The number of rotated logs kept
+    keep: usize,
+    /// The open log and its size
+    file: Mutex<(File, u64)>,
+}
+
+/// Open `path` for appending, with its current size
+fn open_log(path: &Path) -> std::io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+/// `path` with `.<index>` appended
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+impl AuditLog {
+    /// Open the log `config` describes
+    fn open(config: &AuditConfig) -> DatenLordResult<Self> {
+        if config.path.as_os_str().is_empty() {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec!["the audit log needs a path".to_owned()],
+            });
+        }
+        let file = open_log(&config.path).map_err(|e| DatenLordError::InvalidArgument {
+            context: vec![format!("failed to open the audit log {:?}: {e}", config.path)],
+        })?;
+        Ok(Self {
+            path: config.path.clone(),
+            max_bytes: config.max_bytes,
+            keep: config.keep,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append `record`, rotating the log first when it is full
+    fn append(&self, record: &AuditRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        if self.max_bytes > 0 && file.1 > 0 && file.1 + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+            *file = open_log(&self.path)?;
+        }
+        file.0.write_all(&line)?;
+        file.1 += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shift the rotated logs by one, dropping the oldest, and move the
+    /// current one to `<path>.1`
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+        for index in (1..self.keep).rev() {
+            match std::fs::rename(rotated(&self.path, index), rotated(&self.path, index + 1)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        std::fs::rename(&self.path, rotated(&self.path, 1))
+    }
+}
+
+/// A `VirtualFs` appending a record of every mutating call to an audit
+/// log, when enabled
+///
+/// Records are written once the inner call returned, failed calls
+/// included, in the order the calls finished. A record that cannot be
+/// written is logged and dropped; the call it records is not failed.
+#[derive(Debug)]
+pub struct AuditFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// The audit log, `None` when disabled
+    log: Option<AuditLog>,
+}
+
+impl<F: VirtualFs> AuditFs<F> {
+    /// Wrap `inner`, recording its mutating calls as `config` says
+    pub fn new(inner: F, config: &AuditConfig) -> DatenLordResult<Self> {
+        let log = config.enabled.then(|| AuditLog::open(config)).transpose()?;
+        Ok(Self { inner, log })
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Append `record` of the call that finished with `result`
+    fn record<T>(&self, record: impl FnOnce() -> AuditRecord, result: &DatenLordResult<T>) {
+        let Some(ref log) = self.log else {
+            return;
+        };
+        let record = record().finished(result);
+        if let Err(e) = log.append(&record) {
+            warn!("failed to write the audit record {record:?}: {e}");
+        }
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for AuditFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.lookup(ctx, parent, name).await
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.inner.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let (mode, size) = (param.mode, param.size);
+        let owner = (param.u_id.is_some() || param.g_id.is_some()).then_some((param.u_id, param.g_id));
+        let result = self.inner.setattr(ctx, ino, param).await;
+        self.record(
+            || AuditRecord {
+                mode,
+                size,
+                owner,
+                ..AuditRecord::new(ctx, "setattr").on(ino)
+            },
+            &result,
+        );
+        result
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (parent, name, mode) = (param.parent, param.name.clone(), param.mode);
+        let result = self.inner.mknod(ctx, param).await;
+        self.record(
+            || AuditRecord {
+                mode: Some(mode),
+                ino: result.as_ref().ok().map(|(_, attr, _)| attr.ino),
+                ..AuditRecord::new(ctx, "mknod").at(parent, &name)
+            },
+            &result,
+        );
+        result
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (parent, name, mode) = (param.parent, param.name.clone(), param.mode);
+        let result = self.inner.mkdir(ctx, param).await;
+        self.record(
+            || AuditRecord {
+                mode: Some(mode),
+                ino: result.as_ref().ok().map(|(_, attr, _)| attr.ino),
+                ..AuditRecord::new(ctx, "mkdir").at(parent, &name)
+            },
+            &result,
+        );
+        result
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let result = self.inner.unlink(ctx, parent, name).await;
+        self.record(|| AuditRecord::new(ctx, "unlink").at(parent, name), &result);
+        result
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        let result = self.inner.rmdir(ctx, parent, dir_name).await;
+        self.record(
+            || AuditRecord {
+                ino: result.as_ref().ok().copied().flatten(),
+                ..AuditRecord::new(ctx, "rmdir").at(parent, dir_name)
+            },
+            &result,
+        );
+        result
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let result = self.inner.symlink(ctx, parent, name, target_path).await;
+        self.record(
+            || AuditRecord {
+                ino: result.as_ref().ok().map(|(_, attr, _)| attr.ino),
+                target: Some(target_path.to_string_lossy().into_owned()),
+                ..AuditRecord::new(ctx, "symlink").at(parent, name)
+            },
+            &result,
+        );
+        result
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let record = AuditRecord::new(ctx, "rename")
+            .at(param.old_parent, &param.old_name)
+            .to(param.new_parent, &param.new_name);
+        let result = self.inner.rename(ctx, param).await;
+        self.record(|| record, &result);
+        result
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        let result = self.inner.link(ctx, newparent, newname).await;
+        self.record(|| AuditRecord::new(ctx, "link").to(newparent, newname), &result);
+        result
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let result = self.inner.open(ctx, ino, flags).await;
+        // Only opens truncating the file change it
+        if OFlag::from_bits_truncate(flags as i32).contains(OFlag::O_TRUNC) {
+            self.record(
+                || AuditRecord {
+                    size: Some(0),
+                    ..AuditRecord::new(ctx, "open").on(ino)
+                },
+                &result,
+            );
+        }
+        result
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        self.inner.read(ctx, ino, fh, offset, size, buf).await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let result = self.inner.write(ctx, ino, fh, offset, data, flags).await;
+        self.record(
+            || AuditRecord {
+                offset: Some(offset),
+                len: Some(data.len() as u64),
+                ..AuditRecord::new(ctx, "write").on(ino)
+            },
+            &result,
+        );
+        result
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.release(ctx, ino, fh, flags, lock_owner, flush).await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        self.inner.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        let result = self.inner.copy_file_range(ctx, param).await;
+        self.record(
+            || AuditRecord {
+                offset: i64::try_from(param.offset_out).ok(),
+                len: Some(result.as_ref().map_or(param.len, |copy| copy.copied)),
+                ..AuditRecord::new(ctx, "copy_file_range").on(param.ino_out)
+            },
+            &result,
+        );
+        result
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.inner.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.inner.readdirplus(ctx, ino, fh, offset).await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        let result = self
+            .inner
+            .setxattr(ctx, ino, name, value, flags, position)
+            .await;
+        self.record(
+            || AuditRecord {
+                xattr: Some(name.to_owned()),
+                ..AuditRecord::new(ctx, "setxattr").on(ino)
+            },
+            &result,
+        );
+        result
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        let result = self.inner.removexattr(ctx, ino, name).await;
+        self.record(
+            || AuditRecord {
+                xattr: Some(name.to_owned()),
+                ..AuditRecord::new(ctx, "removexattr").on(ino)
+            },
+            &result,
+        );
+        result
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let result = self.inner.create(ctx, ino, parent, name, mode, flags).await;
+        self.record(
+            || AuditRecord {
+                mode: Some(mode),
+                ..AuditRecord::new(ctx, "create").on(ino).at(parent, name)
+            },
+            &result,
+        );
+        result
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
@@ -0,0 +1,522 @@
+//! Middleware bounding the writes in flight, so writers on many threads
+//! cannot pile up more data in the layers below than they can take
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam, RenameParam,
+    RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// How much write data may be in flight and how many writes may wait for room
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackpressureConfig {
+    /// The bytes the writes running at once may carry, no limit when 0; a
+    /// single larger write runs alone, limits above 4 GiB count as 4 GiB
+    pub max_in_flight_bytes: u64,
+    /// The writes that may wait for room at once, no limit when 0; further
+    /// ones fail with `DatenLordError::Unavailable`
+    pub max_queued_writes: usize,
+    /// Fail the writes finding no room with `DatenLordError::Unavailable`,
+    /// `EAGAIN`, instead of waiting
+    pub fail_fast: bool,
+}
+
+/// The state of the write queue of a `BackpressureFs`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackpressureStats {
+    /// The bytes of the writes running in the inner filesystem
+    pub in_flight_write_bytes: u64,
+    /// The writes waiting for room
+    pub queued_writes: usize,
+    /// The most writes that waited at once
+    pub peak_queued_writes: usize,
+    /// The writes that waited for room before running
+    pub delayed_writes: u64,
+    /// The writes failed for lack of room
+    pub rejected_writes: u64,
+}
+
+/// A `VirtualFs` admitting writes only while the bytes in flight stay under
+/// a limit
+///
+/// Writes beyond the limit wait in a queue until earlier ones finish, or
+/// fail right away with `DatenLordError::Unavailable` when the config says
+/// so or the queue is full. Other operations pass through.
+#[derive(Debug)]
+pub struct BackpressureFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// Whether to fail instead of waiting
+    fail_fast: bool,
+    /// The writes that may wait at once, no limit when 0
+    max_queued: usize,
+    /// One permit per byte that may be in flight, `None` without limit
+    room: Option<Semaphore>,
+    /// The permits of `room`, the bytes a single write holds at most
+    limit: u32,
+    /// The bytes of the writes running
+    in_flight: AtomicU64,
+    /// The writes waiting
+    queued: AtomicUsize,
+    /// The most writes that waited at once
+    peak_queued: AtomicUsize,
+    /// The writes that waited
+    delayed: AtomicU64,
+    /// The writes rejected
+    rejected: AtomicU64,
+}
+
+/// A write counted as waiting until dropped, even when cancelled
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// An admitted write, holding its room until dropped
+struct Admitted<'a> {
+    /// The room taken, `None` without limit
+    _permit: Option<SemaphorePermit<'a>>,
+    /// The counter of the bytes in flight
+    in_flight: &'a AtomicU64,
+    /// The bytes of the write
+    len: u64,
+}
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(self.len, Ordering::Relaxed);
+    }
+}
+
+impl<F: VirtualFs> BackpressureFs<F> {
+    /// Wrap `inner`, limiting its writes as `config` says
+    pub fn new(inner: F, config: &BackpressureConfig) -> Self {
+        let limit = u32::try_from(config.max_in_flight_bytes).unwrap_or(u32::MAX);
+        Self {
+            inner,
+            fail_fast: config.fail_fast,
+            max_queued: config.max_queued_writes,
+            room: (limit > 0).then(|| Semaphore::new(limit as usize)),
+            limit,
+            in_flight: AtomicU64::new(0),
+            queued: AtomicUsize::new(0),
+            peak_queued: AtomicUsize::new(0),
+            delayed: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// The state of the write queue
+    pub fn stats(&self) -> BackpressureStats {
+        BackpressureStats {
+            in_flight_write_bytes: self.in_flight.load(Ordering::Relaxed),
+            queued_writes: self.queued.load(Ordering::Relaxed),
+            peak_queued_writes: self.peak_queued.load(Ordering::Relaxed),
+            delayed_writes: self.delayed.load(Ordering::Relaxed),
+            rejected_writes: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Count a write rejected because of `reason`
+    fn reject(&self, len: usize, reason: &str) -> DatenLordError {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        DatenLordError::Unavailable {
+            context: vec![format!("write of {len} bytes rejected, {reason}")],
+            source: None,
+        }
+    }
+
+    /// Wait for room for a write of `len` bytes, failing instead when there
+    /// is none and the write may not wait
+    async fn admit(&self, len: usize) -> DatenLordResult<Admitted<'_>> {
+        let permit = match self.room {
+            None => None,
+            Some(ref room) => {
+                let permits = u32::try_from(len).unwrap_or(u32::MAX).min(self.limit);
+                match room.try_acquire_many(permits) {
+                    Ok(permit) => Some(permit),
+                    Err(_) if self.fail_fast => {
+                        return Err(self.reject(len, "too many bytes in flight"));
+                    }
+                    Err(_) => {
+                        let queued = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+                        let _queued = Queued(&self.queued);
+                        if self.max_queued != 0 && queued > self.max_queued {
+                            return Err(self.reject(len, "too many writes waiting"));
+                        }
+                        self.peak_queued.fetch_max(queued, Ordering::Relaxed);
+                        self.delayed.fetch_add(1, Ordering::Relaxed);
+                        // the room is never closed, so waiting always succeeds
+                        room.acquire_many(permits).await.ok()
+                    }
+                }
+            }
+        };
+        let len = len as u64;
+        self.in_flight.fetch_add(len, Ordering::Relaxed);
+        Ok(Admitted {
+            _permit: permit,
+            in_flight: &self.in_flight,
+            len,
+        })
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for BackpressureFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.lookup(ctx, parent, name).await
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.inner.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.setattr(ctx, ino, param).await
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mknod(ctx, param).await
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mkdir(ctx, param).await
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.unlink(ctx, parent, name).await
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        self.inner.rmdir(ctx, parent, dir_name).await
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.symlink(ctx, parent, name, target_path).await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        self.inner.rename(ctx, param).await
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.link(ctx, newparent, newname).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.open(ctx, ino, flags).await
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        self.inner.read(ctx, ino, fh, offset, size, buf).await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let _admitted = self.admit(data.len()).await?;
+        self.inner.write(ctx, ino, fh, offset, data, flags).await
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        self.inner.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        self.inner.copy_file_range(ctx, param).await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.inner.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.inner.readdirplus(ctx, ino, fh, offset).await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .setxattr(ctx, ino, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.create(ctx, ino, parent, name, mode, flags).await
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
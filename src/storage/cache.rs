@@ -0,0 +1,907 @@
+//! Middleware caching entries and attributes for the TTL the inner filesystem returns
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{
+    self, CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam, ROOT_ID,
+};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// How long a name `exists` found missing is remembered, the shortest TTL
+/// of the backends
+const MISSING_TTL: Duration = Duration::from_millis(100);
+
+/// A cached value valid until `expires`
+#[derive(Debug, Clone, Copy)]
+struct Cached<T> {
+    /// The cached value
+    value: T,
+    /// When the TTL the value came with runs out
+    expires: Instant,
+}
+
+impl<T: Copy> Cached<T> {
+    /// The value and its remaining TTL, `None` once expired
+    fn get(&self, now: Instant) -> Option<(Duration, T)> {
+        (self.expires > now).then(|| (self.expires - now, self.value))
+    }
+}
+
+/// A TTL map holding at most `capacity` values
+#[derive(Debug)]
+struct TtlMap<K, V> {
+    /// The cached values
+    map: RwLock<HashMap<K, Cached<V>>>,
+    /// The number of values kept before expired ones are dropped
+    capacity: AtomicUsize,
+}
+
+impl<K: std::hash::Hash + Eq, V: Copy> TtlMap<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            map: RwLock::new(HashMap::new()),
+            capacity: AtomicUsize::new(capacity),
+        }
+    }
+
+    /// Keep at most `capacity` values from now on, dropping every value
+    /// cached so far
+    fn set_capacity(&self, capacity: usize) {
+        let mut map = self.map.write().unwrap();
+        self.capacity.store(capacity, Ordering::Relaxed);
+        map.clear();
+        map.shrink_to_fit();
+    }
+
+    /// The value of `key` and its remaining TTL, if cached and not expired
+    fn get(&self, key: &K) -> Option<(Duration, V)> {
+        self.map.read().unwrap().get(key)?.get(Instant::now())
+    }
+
+    /// Cache `value` for `ttl`
+    ///
+    /// A full map first drops its expired values, and everything when none
+    /// expired, which keeps inserts cheap while bounding the memory.
+    fn insert(&self, key: K, value: V, ttl: Duration) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if ttl.is_zero() || capacity == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut map = self.map.write().unwrap();
+        if map.len() >= capacity {
+            map.retain(|_, cached| cached.expires > now);
+            if map.len() >= capacity {
+                map.clear();
+            }
+        }
+        map.insert(
+            key,
+            Cached {
+                value,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    /// The value of `key`, expired or not
+    fn get_expired(&self, key: &K) -> Option<V> {
+        self.map.read().unwrap().get(key).map(|cached| cached.value)
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        self.map.write().unwrap().remove(key).map(|cached| cached.value)
+    }
+
+    fn clear(&self) {
+        self.map.write().unwrap().clear();
+    }
+}
+
+/// The key of a cached entry, names are normalized so `/a//b/` and `a/b` share one
+///
+/// Entries are kept apart by the root of the caller, as `ROOT_ID` and the
+/// paths under it stand for another directory for every root.
+fn entry_key(ctx: &RequestContext, parent: INum, name: &OsStr) -> (INum, INum, OsString) {
+    (ctx.root, parent, fs_util::normalize(name))
+}
+
+/// Whether the normalized `name` has one component, naming an entry of the
+/// directory it is looked up in
+fn is_single(name: &OsStr) -> bool {
+    !name.is_empty() && name != ".." && !name.as_bytes().contains(&b'/')
+}
+
+/// A cached entry
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    /// The inode the entry resolves to
+    ino: INum,
+    /// The generation the inner filesystem returned with it
+    generation: u64,
+    /// The generation of the directory holding the entry before it was
+    /// looked up, if known and the name has one component
+    dir_generation: Option<u64>,
+}
+
+/// A read-only handle opened ahead of time by `CacheFs::warm`
+#[derive(Debug, Clone)]
+pub struct WarmHandle {
+    /// The inode of the file
+    pub ino: INum,
+    /// The handle the file is open as
+    pub fh: u64,
+    /// The normalized path the handle was opened for
+    path: OsString,
+    /// The caller the handle was opened by
+    ctx: RequestContext,
+    /// `WarmSet::generation` when the handle was opened
+    generation: u64,
+}
+
+/// The warm handles of a `CacheFs`
+#[derive(Debug, Default)]
+struct WarmSet {
+    /// The caller each warm path is opened by and its handle, `None` while
+    /// taken or when the path no longer resolves
+    handles: HashMap<OsString, (RequestContext, Option<WarmHandle>)>,
+    /// Bumped whenever entries change, handles opened before are released
+    /// instead of being handed out again
+    generation: u64,
+}
+
+/// A `VirtualFs` answering repeated `lookup` and `getattr` calls from a cache
+///
+/// Entries, keyed by parent and name, and attributes, keyed by inode, are
+/// kept for the TTL the inner filesystem returned with them. Only
+/// attributes of non-directories are cached since every change to a
+/// directory's entries would invalidate them. Writes, truncation and
+/// `setattr` drop the attributes of the inode, and removing, renaming or
+/// linking entries drops every cached entry, since a name may resolve
+/// through the changed directories.
+///
+/// An expired entry whose name has one component is still valid as long as
+/// the generation of its directory did not change, see
+/// `FileAttr::generation`. The generations of directories are kept for
+/// their TTL too, so the expired entries of a directory are revalidated
+/// with at most one `getattr` of the directory per TTL rather than a
+/// `lookup` each, only the attributes of their inodes being fetched again.
+///
+/// Files can also be opened read-only ahead of time with `warm`, so the
+/// first read of a latency-critical file skips its lookup and open. The
+/// warm handles are reopened whenever entries are removed, renamed or
+/// linked, as the paths may name other files since.
+///
+/// The names `exists` finds missing are remembered for `MISSING_TTL`, and
+/// forgotten whenever entries are created, renamed or linked.
+#[derive(Debug)]
+pub struct CacheFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// The entry of `(parent, name)`
+    entries: TtlMap<(INum, INum, OsString), Entry>,
+    /// The attributes of non-directory inodes
+    attrs: TtlMap<INum, FileAttr>,
+    /// The generations of directories, by inode
+    dirs: TtlMap<INum, u64>,
+    /// The keys of the entries `exists` found missing
+    missing: TtlMap<(INum, INum, OsString), ()>,
+    /// The handles opened by `warm`
+    warm: Mutex<WarmSet>,
+    /// The `lookup`, `getattr` and `exists` calls answered from the cache
+    hits: AtomicU64,
+    /// The `lookup`, `getattr` and `exists` calls passed to the inner
+    /// filesystem
+    misses: AtomicU64,
+}
+
+impl<F: VirtualFs> CacheFs<F> {
+    /// Wrap `inner`, caching at most `capacity` entries and as many
+    /// attributes, 0 disables the cache
+    pub fn new(inner: F, capacity: usize) -> Self {
+        Self {
+            inner,
+            entries: TtlMap::new(capacity),
+            attrs: TtlMap::new(capacity),
+            dirs: TtlMap::new(capacity),
+            missing: TtlMap::new(capacity),
+            warm: Mutex::new(WarmSet::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Cache at most `capacity` entries and as many attributes from now on,
+    /// 0 disables the cache, dropping everything cached so far
+    pub fn set_capacity(&self, capacity: usize) {
+        self.entries.set_capacity(capacity);
+        self.attrs.set_capacity(capacity);
+        self.dirs.set_capacity(capacity);
+        self.missing.set_capacity(capacity);
+    }
+
+    /// The `lookup`, `getattr` and `exists` calls answered from the cache
+    /// and those passed to the inner filesystem so far, as `(hits, misses)`
+    pub fn hits(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Cache `attr` of the inode `ino` seen by `ctx` for `ttl`, only the
+    /// generation of a directory
+    fn cache_attr(&self, ctx: &RequestContext, ino: INum, attr: &FileAttr, ttl: Duration) {
+        if attr.kind == SFlag::S_IFDIR {
+            self.dirs.insert(ctx.scope(ino), attr.generation, ttl);
+        } else {
+            self.attrs.insert(attr.ino, *attr, ttl);
+        }
+    }
+
+    /// Drop the entry of `name` under `parent` and the attributes it points to
+    fn forget_entry(&self, ctx: &RequestContext, parent: INum, name: &OsStr) {
+        if let Some(entry) = self.entries.remove(&entry_key(ctx, parent, name)) {
+            self.attrs.remove(&entry.ino);
+        }
+    }
+
+    /// Drop every cached entry and generation of a directory
+    fn forget_entries(&self) {
+        self.entries.clear();
+        self.dirs.clear();
+    }
+
+    /// Drop every name found missing, as a created entry may be on its path
+    fn forget_missing(&self) {
+        self.missing.clear();
+    }
+
+    /// Cache the result of looking up `name` under `parent`, whose
+    /// generation was `dir_generation` before
+    fn cache_entry(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        entry: &(Duration, FileAttr, u64),
+        dir_generation: Option<u64>,
+    ) {
+        let (ttl, attr, generation) = *entry;
+        let cached = Entry {
+            ino: attr.ino,
+            generation,
+            dir_generation,
+        };
+        let key = entry_key(ctx, parent, name);
+        self.missing.remove(&key);
+        self.entries.insert(key, cached, ttl);
+        self.cache_attr(ctx, attr.ino, &attr, ttl);
+    }
+
+    /// The generation of the directory `dir`, cached or from the inner
+    /// filesystem
+    async fn dir_generation(&self, ctx: &RequestContext, dir: INum) -> DatenLordResult<u64> {
+        if let Some((_, generation)) = self.dirs.get(&ctx.scope(dir)) {
+            return Ok(generation);
+        }
+        let (ttl, attr) = self.inner.getattr(ctx, dir).await?;
+        self.cache_attr(ctx, dir, &attr, ttl);
+        Ok(attr.generation)
+    }
+
+    /// The expired entry `key` of the directory `parent` with the current
+    /// attributes of its inode, if the generation of `parent` did not change
+    /// since the entry was looked up
+    async fn revalidate(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        key: &(INum, INum, OsString),
+    ) -> Option<(Duration, FileAttr, u64)> {
+        let entry = self.entries.get_expired(key)?;
+        let dir_generation = entry.dir_generation?;
+        if self.dir_generation(ctx, parent).await.ok()? != dir_generation {
+            return None;
+        }
+        let (ttl, attr) = match self.attrs.get(&entry.ino) {
+            Some(cached) => cached,
+            None => self.inner.getattr(ctx, entry.ino).await.ok()?,
+        };
+        self.entries.insert(key.clone(), entry, ttl);
+        self.cache_attr(ctx, entry.ino, &attr, ttl);
+        Some((ttl, attr, entry.generation))
+    }
+
+    /// Open `path`, relative to the root, read-only on behalf of `ctx` and
+    /// keep the handle for `take_warm`, replacing the former one
+    ///
+    /// The path stays warm for good, a failed reopen only leaves it without
+    /// a handle until entries change again.
+    pub async fn warm(&self, ctx: &RequestContext, path: &OsStr) -> DatenLordResult<FileAttr> {
+        let path = fs_util::normalize(path);
+        let (handle, attr) = self.open_warm(ctx, &path).await?;
+        let replaced = self
+            .warm
+            .lock()
+            .unwrap()
+            .handles
+            .insert(path, (*ctx, Some(handle)));
+        if let Some((_, Some(replaced))) = replaced {
+            self.release_warm(replaced).await;
+        }
+        Ok(attr)
+    }
+
+    /// Take the warm handle of `path` if `warm` opened it for the same user,
+    /// group and root as `ctx`, to give back with `put_warm` once done reading
+    pub async fn take_warm(&self, ctx: &RequestContext, path: &OsStr) -> Option<WarmHandle> {
+        let path = fs_util::normalize(path);
+        let stale = {
+            let mut set = self.warm.lock().unwrap();
+            let generation = set.generation;
+            let &mut (owner, ref mut slot) = set.handles.get_mut(&path)?;
+            if (owner.uid, owner.gid, owner.root) != (ctx.uid, ctx.gid, ctx.root) {
+                return None;
+            }
+            let handle = slot.take()?;
+            if handle.generation == generation {
+                return Some(handle);
+            }
+            handle
+        };
+        self.release_warm(stale).await;
+        None
+    }
+
+    /// Give back a handle `take_warm` returned, released instead when
+    /// entries changed since it was opened
+    pub async fn put_warm(&self, handle: WarmHandle) {
+        let rejected = {
+            let mut set = self.warm.lock().unwrap();
+            let generation = set.generation;
+            let current = handle.generation == generation;
+            match set.handles.get_mut(&handle.path) {
+                Some(&mut (_, ref mut slot)) if current && slot.is_none() => {
+                    *slot = Some(handle);
+                    None
+                }
+                _ => Some(handle),
+            }
+        };
+        if let Some(handle) = rejected {
+            self.release_warm(handle).await;
+        }
+    }
+
+    /// Open the normalized `path` read-only for `warm`
+    async fn open_warm(
+        &self,
+        ctx: &RequestContext,
+        path: &OsStr,
+    ) -> DatenLordResult<(WarmHandle, FileAttr)> {
+        // Read first, so a change racing with the open makes the handle stale
+        let generation = self.warm.lock().unwrap().generation;
+        let (_, attr, _) = self.lookup(ctx, ROOT_ID, path).await?;
+        if attr.kind == SFlag::S_IFDIR {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("cannot warm directory {path:?}")],
+            });
+        }
+        let fh = self
+            .inner
+            .open(ctx, attr.ino, OFlag::O_RDONLY.bits() as u32)
+            .await?;
+        let handle = WarmHandle {
+            ino: attr.ino,
+            fh,
+            path: path.to_owned(),
+            ctx: *ctx,
+            generation,
+        };
+        Ok((handle, attr))
+    }
+
+    /// Release a warm handle
+    async fn release_warm(&self, handle: WarmHandle) {
+        if let Err(e) = self
+            .inner
+            .release(&handle.ctx, handle.ino, handle.fh, 0, 0, false)
+            .await
+        {
+            warn!("failed to release the warm handle of {:?}: {e}", handle.path);
+        }
+    }
+
+    /// Reopen every warm path after entries changed
+    async fn refresh_warm(&self) {
+        let stale: Vec<_> = {
+            let mut set = self.warm.lock().unwrap();
+            if set.handles.is_empty() {
+                return;
+            }
+            set.generation += 1;
+            set.handles
+                .iter_mut()
+                .map(|(path, &mut (ctx, ref mut slot))| (path.clone(), ctx, slot.take()))
+                .collect()
+        };
+        for (path, ctx, handle) in stale {
+            if let Some(handle) = handle {
+                self.release_warm(handle).await;
+            }
+            match self.open_warm(&ctx, &path).await {
+                Ok((handle, _)) => self.put_warm(handle).await,
+                Err(e) => warn!("failed to reopen warm file {path:?}: {e}"),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for CacheFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let key = entry_key(ctx, parent, name);
+        if let Some((entry_ttl, entry)) = self.entries.get(&key) {
+            if let Some((attr_ttl, attr)) = self.attrs.get(&entry.ino) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok((entry_ttl.min(attr_ttl), attr, entry.generation));
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let single = is_single(&key.2);
+        if single {
+            if let Some(entry) = self.revalidate(ctx, parent, &key).await {
+                return Ok(entry);
+            }
+        }
+        // Read before the lookup, so a change racing with it fails the
+        // revalidation
+        let dir_generation = single
+            .then(|| self.dirs.get(&ctx.scope(parent)))
+            .flatten()
+            .map(|(_, generation)| generation);
+        let entry = self.inner.lookup(ctx, parent, name).await?;
+        self.cache_entry(ctx, parent, name, &entry, dir_generation);
+        Ok(entry)
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        // A fresh entry is enough, its attributes are not needed
+        let key = entry_key(ctx, parent, name);
+        if self.entries.get(&key).is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(true);
+        }
+        if self.missing.get(&key).is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(false);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let exists = self.inner.exists(ctx, parent, name).await?;
+        if !exists {
+            self.missing.insert(key, (), MISSING_TTL);
+        }
+        Ok(exists)
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        // Any inode number may be asked for, so a caller with a root of its
+        // own only gets the attributes of those the inner filesystem lets it
+        if ctx.root == ROOT_ID {
+            if let Some(cached) = self.attrs.get(&ino) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(cached);
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let (ttl, attr) = self.inner.getattr(ctx, ino).await?;
+        self.cache_attr(ctx, ino, &attr, ttl);
+        Ok((ttl, attr))
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let result = self.inner.setattr(ctx, ino, param).await;
+        self.attrs.remove(&ino);
+        let (ttl, attr) = result?;
+        self.cache_attr(ctx, ino, &attr, ttl);
+        Ok((ttl, attr))
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (parent, name) = (param.parent, param.name.clone());
+        let entry = self.inner.mknod(ctx, param).await?;
+        self.forget_missing();
+        self.cache_entry(ctx, parent, &name, &entry, None);
+        Ok(entry)
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (parent, name) = (param.parent, param.name.clone());
+        let entry = self.inner.mkdir(ctx, param).await?;
+        self.forget_missing();
+        self.cache_entry(ctx, parent, &name, &entry, None);
+        Ok(entry)
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let result = self.inner.unlink(ctx, parent, name).await;
+        self.forget_entry(ctx, parent, name);
+        // Other names of the file, e.g. through a path with more components, may still be cached
+        self.forget_entries();
+        if result.is_ok() {
+            self.refresh_warm().await;
+        }
+        result
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        let result = self.inner.rmdir(ctx, parent, dir_name).await;
+        self.forget_entries();
+        if result.is_ok() {
+            self.refresh_warm().await;
+        }
+        result
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let entry = self
+            .inner
+            .symlink(ctx, parent, name, target_path)
+            .await?;
+        self.forget_missing();
+        Ok(entry)
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let (old_parent, old_name) = (param.old_parent, param.old_name.clone());
+        let (new_parent, new_name) = (param.new_parent, param.new_name.clone());
+        let result = self.inner.rename(ctx, param).await;
+        // Both sides change, and with them the names resolving through them
+        self.forget_entry(ctx, old_parent, &old_name);
+        self.forget_entry(ctx, new_parent, &new_name);
+        self.forget_entries();
+        self.forget_missing();
+        if result.is_ok() {
+            self.refresh_warm().await;
+        }
+        result
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        let result = self.inner.link(ctx, newparent, newname).await;
+        self.forget_entries();
+        self.forget_missing();
+        if result.is_ok() {
+            self.refresh_warm().await;
+        }
+        result
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let result = self.inner.open(ctx, ino, flags).await;
+        if OFlag::from_bits_truncate(flags as i32).contains(OFlag::O_TRUNC) {
+            self.attrs.remove(&ino);
+        }
+        result
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        self.inner.read(ctx, ino, fh, offset, size, buf).await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let result = self.inner.write(ctx, ino, fh, offset, data, flags).await;
+        self.attrs.remove(&ino);
+        result
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        self.inner.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        let result = self.inner.copy_file_range(ctx, param).await;
+        self.attrs.remove(&param.ino_out);
+        result
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.inner.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        let entries = self.inner.readdirplus(ctx, ino, fh, offset).await?;
+        for &(_, ref attr, ttl) in &entries {
+            self.cache_attr(ctx, attr.ino, attr, ttl);
+        }
+        Ok(entries)
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .setxattr(ctx, ino, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let result = self
+            .inner
+            .create(ctx, ino, parent, name, mode, flags)
+            .await;
+        self.forget_missing();
+        result
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
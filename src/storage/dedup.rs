@@ -0,0 +1,1014 @@
+//! Content-addressed deduplication of file data, for namespaces with the
+//! `dedup` feature
+//!
+//! The data of a file is cut into blocks of `BLOCK_SIZE` bytes, each kept
+//! once under the SHA-256 of its content in the store next to the
+//! superblock, as `<store>/blocks/<first 2 hex digits>/<hex>`. Block `n` of
+//! inode `ino` is a hard link to its block at `<store>/files/<ino>/<n>`, so
+//! the link count of a block is its reference count, shared by every process
+//! opening the namespace, and a block name removed while a file still links
+//! to it loses no data. Inode numbers come from the inode table and are
+//! never reused, so a block map is never taken over by a later file. Blocks
+//! of zeros are left out as holes.
+//!
+//! A file is deduplicated once its inode has a block map, made when it is
+//! created or truncated to nothing. The wrapped filesystem keeps the
+//! namespace and the attributes, its files being left sparse at their size,
+//! and serves the files holding data from before the feature was enabled.
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use nix::fcntl::RenameFlags;
+use nix::libc;
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::digest::hex;
+use super::fs_util::{
+    seek_extents, CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::gc::{GcReport, Sweep};
+use super::striping::{create_dir, owner_context};
+use super::superblock::SUPERBLOCK_NAME;
+use super::layout::BlockLayout;
+use super::virtualfs::{copy_range_through, INum, VirtualFs};
+
+/// The bytes of a block, 128 KiB
+const BLOCK_SIZE: usize = 128 * 1024;
+/// The locks serializing the changes to the block map of an inode, picked
+/// by inode number
+const LOCK_COUNT: usize = 64;
+/// The attempts at linking a block removed as unreferenced in between
+const LINK_ATTEMPTS: usize = 3;
+
+/// Tells apart the temporary files of the blocks written by a process
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// How much the store saves
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DedupStats {
+    /// The blocks kept
+    pub blocks: u64,
+    /// The bytes the blocks take
+    pub stored_bytes: u64,
+    /// The bytes of the blocks the files refer to, each reference counted
+    pub referenced_bytes: u64,
+}
+
+/// The blocks and the block maps of the inodes
+#[derive(Debug)]
+struct Store {
+    /// The directory of the blocks
+    blocks: PathBuf,
+    /// The directory of the block maps
+    files: PathBuf,
+    /// The locks serializing changes to the block map of an inode
+    locks: Vec<Mutex<()>>,
+}
+
+/// Turn an `io::Error` on `what` into a `DatenLordError`
+fn io_error(what: String) -> impl FnOnce(io::Error) -> DatenLordError {
+    move |e| DatenLordError::Io {
+        context: vec![format!("{what}: {e}")],
+        source: Some(Box::new(e)),
+    }
+}
+
+/// Whether `a` and `b` are links of the same file
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+        _ => false,
+    }
+}
+
+impl Store {
+    /// Open the store of the namespace rooted at `root`, created when missing
+    fn open(root: &Path) -> DatenLordResult<Self> {
+        let dir = root.join(format!("{SUPERBLOCK_NAME}.dedup"));
+        let store = Self {
+            blocks: dir.join("blocks"),
+            files: dir.join("files"),
+            locks: (0..LOCK_COUNT).map(|_| Mutex::new(())).collect(),
+        };
+        for path in [&dir, &store.blocks, &store.files] {
+            match create_dir(path) {
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                    return Err(io_error(format!("failed to create dedup store {path:?}"))(
+                        e,
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(store)
+    }
+
+    /// Lock the block map of `ino`
+    async fn lock(&self, ino: INum) -> MutexGuard<'_, ()> {
+        self.locks[(ino % LOCK_COUNT as u64) as usize].lock().await
+    }
+
+    /// The block map of `ino`
+    fn map_dir(&self, ino: INum) -> PathBuf {
+        self.files.join(ino.to_string())
+    }
+
+    /// The entry of block `n` in the block map of `ino`
+    fn entry(&self, ino: INum, n: u64) -> PathBuf {
+        self.map_dir(ino).join(n.to_string())
+    }
+
+    /// The block holding data of SHA-256 `hash`
+    fn block_path(&self, hash: &str) -> PathBuf {
+        self.blocks.join(&hash[..2]).join(hash)
+    }
+
+    /// The block holding `data`
+    fn block_of(&self, data: &[u8]) -> PathBuf {
+        self.block_path(&hex(&Sha256::digest(data)))
+    }
+
+    /// Whether the data of `ino` is deduplicated
+    fn is_deduped(&self, ino: INum) -> bool {
+        self.map_dir(ino).is_dir()
+    }
+
+    /// Make `ino` deduplicated, without data yet
+    fn reset(&self, ino: INum) -> DatenLordResult<()> {
+        self.remove(ino);
+        create_dir(&self.map_dir(ino)).map_err(io_error(format!(
+            "failed to make the block map of inode {ino}"
+        )))
+    }
+
+    /// Remove the block map of `ino`, and the blocks only it referred to
+    fn remove(&self, ino: INum) {
+        for n in self.entries(ino) {
+            if let Err(e) = self.unlink_entry(ino, n) {
+                warn!("failed to remove block {n} of inode {ino}: {e}");
+            }
+        }
+        let dir = self.map_dir(ino);
+        match fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                warn!("failed to remove the block map {dir:?}: {e}");
+            }
+            _ => {}
+        }
+    }
+
+    /// The indexes of the blocks `ino` maps, sorted
+    fn entries(&self, ino: INum) -> Vec<u64> {
+        let Ok(entries) = fs::read_dir(self.map_dir(ino)) else {
+            return Vec::new();
+        };
+        let mut indexes = entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<u64>().ok())
+            .collect::<Vec<_>>();
+        indexes.sort_unstable();
+        indexes
+    }
+
+    /// The inodes with a block map, sorted
+    fn inodes(&self) -> Vec<INum> {
+        let mut inodes = match fs::read_dir(&self.files) {
+            Ok(entries) => entries
+                .flatten()
+                .filter_map(|entry| entry.file_name().to_str()?.parse::<INum>().ok())
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                warn!("failed to list the block maps {:?}: {e}", self.files);
+                Vec::new()
+            }
+        };
+        inodes.sort_unstable();
+        inodes
+    }
+
+    /// When the block map of `ino` last changed
+    fn changed(&self, ino: INum) -> Option<SystemTime> {
+        fs::metadata(self.map_dir(ino))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// The block the map entry `path` is the last reference to, and its
+    /// bytes
+    fn last_reference(&self, path: &Path) -> Option<(PathBuf, u64)> {
+        let metadata = fs::metadata(path).ok()?;
+        if metadata.nlink() != 2 {
+            return None;
+        }
+        let data = fs::read(path).ok()?;
+        Some((self.block_of(&data), metadata.len()))
+    }
+
+    /// Remove the block `path` unless a block map links to it
+    fn drop_unreferenced(path: &Path) {
+        let Ok(metadata) = fs::metadata(path) else {
+            return;
+        };
+        if metadata.nlink() == 1 {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    warn!("failed to remove the unreferenced block {path:?}: {e}");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Drop block `n` of `ino`, and the block if it was its last reference
+    fn unlink_entry(&self, ino: INum, n: u64) -> io::Result<()> {
+        let path = self.entry(ino, n);
+        let last = self.last_reference(&path);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            result => result?,
+        }
+        if let Some((block, _)) = last {
+            Self::drop_unreferenced(&block);
+        }
+        Ok(())
+    }
+
+    /// Write the block `path` holding `data` unless it exists
+    fn put(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        if path.exists() {
+            return Ok(());
+        }
+        if let Some(dir) = path.parent() {
+            match create_dir(dir) {
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+                _ => {}
+            }
+        }
+        let temp = self.blocks.join(format!(
+            ".{}.{}.tmp",
+            std::process::id(),
+            NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temp, data)?;
+        // Linking rather than renaming keeps the block another writer put
+        // first, which files may already link to
+        let result = match fs::hard_link(&temp, path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+            result => result,
+        };
+        let _ = fs::remove_file(&temp);
+        result
+    }
+
+    /// Block `n` of `ino`, `None` for a hole
+    fn load_block(&self, ino: INum, n: u64) -> DatenLordResult<Option<Vec<u8>>> {
+        match fs::read(self.entry(ino, n)) {
+            Ok(data) if data.len() == BLOCK_SIZE => Ok(Some(data)),
+            Ok(data) => Err(DatenLordError::Io {
+                context: vec![format!(
+                    "block {n} of inode {ino} has {} bytes instead of {BLOCK_SIZE}",
+                    data.len()
+                )],
+                source: None,
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_error(format!("failed to read block {n} of inode {ino}"))(e)),
+        }
+    }
+
+    /// Map `data`, a whole block, as block `n` of `ino`, keeping it once
+    fn store_block(&self, ino: INum, n: u64, data: &[u8]) -> io::Result<()> {
+        if data.iter().all(|&byte| byte == 0) {
+            return self.unlink_entry(ino, n);
+        }
+        let block = self.block_of(data);
+        let entry = self.entry(ino, n);
+        if same_file(&entry, &block) {
+            return Ok(());
+        }
+        let last = self.last_reference(&entry);
+        let temp = self.map_dir(ino).join(format!(".{n}.tmp"));
+        let mut attempts = 0;
+        loop {
+            self.put(&block, data)?;
+            let _ = fs::remove_file(&temp);
+            match fs::hard_link(&block, &temp) {
+                Ok(()) => break,
+                // Removed as unreferenced since it was put
+                Err(e) if e.kind() == io::ErrorKind::NotFound && attempts < LINK_ATTEMPTS => {
+                    attempts += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        fs::rename(&temp, &entry)?;
+        if let Some((old, _)) = last {
+            Self::drop_unreferenced(&old);
+        }
+        Ok(())
+    }
+
+    /// Read `buf.len()` bytes of `ino` from `offset`, holes reading as zeros
+    fn read(&self, ino: INum, offset: u64, buf: &mut [u8]) -> DatenLordResult<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let (n, start) = (
+                position / BLOCK_SIZE as u64,
+                (position % BLOCK_SIZE as u64) as usize,
+            );
+            let len = (BLOCK_SIZE - start).min(buf.len() - done);
+            let out = &mut buf[done..done + len];
+            match self.load_block(ino, n)? {
+                Some(data) => out.copy_from_slice(&data[start..start + len]),
+                None => out.fill(0),
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Write `data` to `ino` at `offset`, mapping the blocks it touches anew
+    fn write(&self, ino: INum, offset: u64, data: &[u8]) -> DatenLordResult<()> {
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let (n, start) = (
+                position / BLOCK_SIZE as u64,
+                (position % BLOCK_SIZE as u64) as usize,
+            );
+            let len = (BLOCK_SIZE - start).min(data.len() - done);
+            let piece = &data[done..done + len];
+            let result = if len == BLOCK_SIZE {
+                self.store_block(ino, n, piece)
+            } else {
+                let mut block = self
+                    .load_block(ino, n)?
+                    .unwrap_or_else(|| vec![0; BLOCK_SIZE]);
+                block[start..start + len].copy_from_slice(piece);
+                self.store_block(ino, n, &block)
+            };
+            result.map_err(io_error(format!(
+                "failed to write block {n} of inode {ino}"
+            )))?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Drop the data of `ino` past `size`
+    fn truncate(&self, ino: INum, size: u64) -> DatenLordResult<()> {
+        let kept = size.div_ceil(BLOCK_SIZE as u64);
+        for n in self.entries(ino).into_iter().filter(|&n| n >= kept) {
+            self.unlink_entry(ino, n)
+                .map_err(io_error(format!("failed to drop block {n} of inode {ino}")))?;
+        }
+        let end = (size % BLOCK_SIZE as u64) as usize;
+        if end != 0 {
+            let n = size / BLOCK_SIZE as u64;
+            if let Some(mut block) = self.load_block(ino, n)? {
+                block[end..].fill(0);
+                self.store_block(ino, n, &block).map_err(io_error(format!(
+                    "failed to truncate block {n} of inode {ino}"
+                )))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sync the blocks of `ino` and its block map to their device
+    fn sync(&self, ino: INum) -> DatenLordResult<()> {
+        let dir = self.map_dir(ino);
+        for n in self.entries(ino) {
+            let path = self.entry(ino, n);
+            fs::File::open(&path)
+                .and_then(|file| file.sync_all())
+                .map_err(io_error(format!("failed to sync block {path:?}")))?;
+        }
+        fs::File::open(&dir)
+            .and_then(|file| file.sync_all())
+            .map_err(io_error(format!("failed to sync block map {dir:?}")))
+    }
+
+    /// Call `f` with the path and metadata of every file under the blocks,
+    /// the temporary ones included
+    fn each_block(&self, mut f: impl FnMut(PathBuf, &fs::Metadata)) {
+        let Ok(entries) = fs::read_dir(&self.blocks) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_dir() {
+                f(entry.path(), &metadata);
+                continue;
+            }
+            for block in fs::read_dir(entry.path()).into_iter().flatten().flatten() {
+                if let Ok(metadata) = block.metadata() {
+                    f(block.path(), &metadata);
+                }
+            }
+        }
+    }
+}
+
+/// A `VirtualFs` keeping the data of its files as deduplicated blocks when
+/// the namespace has the `dedup` feature, and the rest in the wrapped
+/// filesystem
+#[derive(Debug)]
+pub struct DedupBackend<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// The store, `None` without the feature
+    store: Option<Store>,
+}
+
+impl<F: VirtualFs> DedupBackend<F> {
+    /// Deduplicate the data of `inner` in the store of the namespace rooted
+    /// at `root` if `enabled`
+    pub fn new(inner: F, root: &Path, enabled: bool) -> DatenLordResult<Self> {
+        let store = if enabled {
+            Some(Store::open(root)?)
+        } else {
+            None
+        };
+        Ok(Self { inner, store })
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// How much the store saves, `None` without the feature
+    #[must_use]
+    pub fn stats(&self) -> Option<DedupStats> {
+        let store = self.store.as_ref()?;
+        let mut stats = DedupStats::default();
+        store.each_block(|path, metadata| {
+            if path
+                .file_name()
+                .is_some_and(|name| !name.to_string_lossy().starts_with('.'))
+            {
+                stats.blocks += 1;
+                stats.stored_bytes += metadata.len();
+                stats.referenced_bytes += metadata.len() * metadata.nlink().saturating_sub(1);
+            }
+        });
+        Some(stats)
+    }
+
+    /// The store if `ino` is deduplicated
+    fn deduped(&self, ino: INum) -> Option<&Store> {
+        self.store.as_ref().filter(|store| store.is_deduped(ino))
+    }
+
+    /// Make `ino` deduplicated if the wrapped filesystem holds no data of it
+    async fn reset_if_empty(&self, ctx: &RequestContext, ino: INum) -> DatenLordResult<()> {
+        let Some(ref store) = self.store else {
+            return Ok(());
+        };
+        let _guard = store.lock(ino).await;
+        let (_, attr) = self.inner.getattr(ctx, ino).await?;
+        if attr.kind == SFlag::S_IFREG && attr.size == 0 {
+            store.reset(ino)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the block map of the file `attr` describes if it was its last
+    /// link
+    fn removed(&self, attr: &FileAttr) {
+        if let Some(ref store) = self.store {
+            if attr.kind == SFlag::S_IFREG && attr.nlink <= 1 {
+                store.remove(attr.ino);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<F: Sweep> Sweep for DedupBackend<F> {
+    async fn sweep(
+        &self,
+        ctx: &RequestContext,
+        reachable: &HashSet<INum>,
+        grace: Duration,
+        dry_run: bool,
+    ) -> DatenLordResult<GcReport> {
+        let mut report = GcReport::default();
+        if let Some(ref store) = self.store {
+            let recent = |changed: Option<SystemTime>| {
+                changed.is_some_and(|changed| changed.elapsed().unwrap_or_default() < grace)
+            };
+            // The bytes freed with entry `n` of `ino`
+            let freed = |ino: INum, n: u64| {
+                store
+                    .last_reference(&store.entry(ino, n))
+                    .map_or(0, |(_, bytes)| bytes)
+            };
+            for ino in store.inodes() {
+                let _guard = store.lock(ino).await;
+                if !reachable.contains(&ino) {
+                    if !recent(store.changed(ino)) {
+                        let bytes = store.entries(ino).into_iter().map(|n| freed(ino, n)).sum();
+                        report.found(format!("block map of inode {ino}"), bytes, dry_run);
+                        if !dry_run {
+                            store.remove(ino);
+                        }
+                    }
+                    continue;
+                }
+                // Gone since it was marked
+                let Ok((_, attr)) = self.inner.getattr(ctx, ino).await else {
+                    continue;
+                };
+                let kept = attr.size.div_ceil(BLOCK_SIZE as u64);
+                for n in store.entries(ino).into_iter().filter(|&n| n >= kept) {
+                    report.found(format!("block {n} of inode {ino}"), freed(ino, n), dry_run);
+                    if !dry_run {
+                        if let Err(e) = store.unlink_entry(ino, n) {
+                            warn!("failed to drop block {n} of inode {ino}: {e}");
+                        }
+                    }
+                }
+            }
+            // Blocks and temporary files no block map links to, those
+            // changed within the grace being linked by now
+            let mut unreferenced = Vec::new();
+            store.each_block(|path, metadata| {
+                if metadata.nlink() == 1 && !recent(metadata.modified().ok()) {
+                    unreferenced.push((path, metadata.len()));
+                }
+            });
+            for (path, bytes) in unreferenced {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                report.found(format!("block {name}"), bytes, dry_run);
+                if !dry_run {
+                    Store::drop_unreferenced(&path);
+                }
+            }
+        }
+        report.absorb(self.inner.sweep(ctx, reachable, grace, dry_run).await?);
+        Ok(report)
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for DedupBackend<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.lookup(ctx, parent, name).await
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.inner.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let Some(size) = param.size else {
+            return self.inner.setattr(ctx, ino, param).await;
+        };
+        let Some(store) = self.deduped(ino) else {
+            let result = self.inner.setattr(ctx, ino, param).await?;
+            if size == 0 {
+                self.reset_if_empty(ctx, ino).await?;
+            }
+            return Ok(result);
+        };
+        let _guard = store.lock(ino).await;
+        let result = self.inner.setattr(ctx, ino, param).await?;
+        store.truncate(ino, size)?;
+        Ok(result)
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let result = self.inner.mknod(ctx, param).await?;
+        self.reset_if_empty(ctx, result.1.ino).await?;
+        Ok(result)
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mkdir(ctx, param).await
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let removed = match self.store {
+            Some(_) => self.inner.lookup(ctx, parent, name).await.ok(),
+            None => None,
+        };
+        self.inner.unlink(ctx, parent, name).await?;
+        if let Some((_, ref attr, _)) = removed {
+            self.removed(attr);
+        }
+        Ok(())
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        self.inner.rmdir(ctx, parent, dir_name).await
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.symlink(ctx, parent, name, target_path).await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let exchange = param.flags & RenameFlags::RENAME_EXCHANGE.bits() != 0;
+        // The file a rename replaces loses a link
+        let replaced = if self.store.is_some() && !exchange {
+            let source = self
+                .inner
+                .lookup(ctx, param.old_parent, &param.old_name)
+                .await;
+            let target = self
+                .inner
+                .lookup(ctx, param.new_parent, &param.new_name)
+                .await;
+            match (source, target) {
+                (Ok((_, source, _)), Ok((_, target, _))) if source.ino != target.ino => {
+                    Some(target)
+                }
+                _ => None,
+            }
+        } else {
+            None
+        };
+        self.inner.rename(ctx, param).await?;
+        if let Some(ref attr) = replaced {
+            self.removed(attr);
+        }
+        Ok(())
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.link(ctx, newparent, newname).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let fh = self.inner.open(ctx, ino, flags).await?;
+        if flags as i32 & libc::O_TRUNC != 0 {
+            if let Err(e) = self.reset_if_empty(ctx, ino).await {
+                let _ = self.inner.release(ctx, ino, fh, flags, 0, false).await;
+                return Err(e);
+            }
+        }
+        Ok(fh)
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        let Some(store) = self.deduped(ino) else {
+            return self.inner.read(ctx, ino, fh, offset, size, buf).await;
+        };
+        let (_, attr) = self.inner.getattr(ctx, ino).await?;
+        let len = attr
+            .size
+            .saturating_sub(offset)
+            .min(buf.len().min(size as usize) as u64) as usize;
+        store.read(ino, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let Some(store) = self.deduped(ino) else {
+            return self.inner.write(ctx, ino, fh, offset, data, flags).await;
+        };
+        let offset = u64::try_from(offset).map_err(|_| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid write offset={offset}")],
+        })?;
+        let _guard = store.lock(ino).await;
+        let (_, attr) = self.inner.getattr(ctx, ino).await?;
+        store.write(ino, offset, data)?;
+        let end = offset + data.len() as u64;
+        let param = SetAttrParam {
+            fh: Some(fh),
+            size: (end > attr.size).then_some(end),
+            m_time: Some(SystemTime::now()),
+            ..SetAttrParam::default()
+        };
+        self.inner.setattr(&owner_context(ctx), ino, param).await?;
+        Ok(())
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        if let Some(store) = self.deduped(ino) {
+            store.sync(ino)?;
+        }
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        let Some(store) = self.deduped(ino) else {
+            return self.inner.lseek(ctx, ino, fh, offset, whence).await;
+        };
+        let _guard = store.lock(ino).await;
+        let (_, attr) = self.inner.getattr(ctx, ino).await?;
+        let block = BLOCK_SIZE as u64;
+        let extents: Vec<_> = store
+            .entries(ino)
+            .into_iter()
+            .map(|n| (n * block, (n + 1) * block))
+            .collect();
+        Ok(seek_extents(&extents, attr.size, offset, whence))
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        // Deduplicated data never reaches the inner files, copy it block by
+        // block
+        if self.deduped(param.ino_in).is_some() || self.deduped(param.ino_out).is_some() {
+            return copy_range_through(self, ctx, param).await;
+        }
+        self.inner.copy_file_range(ctx, param).await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.inner.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.inner.readdirplus(ctx, ino, fh, offset).await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .setxattr(ctx, ino, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .create(ctx, ino, parent, name, mode, flags)
+            .await?;
+        if self.store.is_some() {
+            let (_, attr, _) = self.inner.lookup(ctx, parent, name).await?;
+            self.reset_if_empty(ctx, attr.ino).await?;
+        }
+        Ok(())
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
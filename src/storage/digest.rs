@@ -0,0 +1,272 @@
+//! Digests of the data written and read through the SDKs, so callers check
+//! it end to end across the FFI boundary
+//!
+//! A digest is written `<algorithm>:<lowercase hex>`, e.g.
+//! `crc32c:e3069283`, so whoever verifies it needs nothing else.
+//!
+//! `file_digest` computes the digest of a whole file, streaming it through
+//! the filesystem, and can cache it in the `DIGEST_XATTR` extended
+//! attribute of the file, which writes through `LocalFS` remove.
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::common::buffer_pool::COPY_CHUNK_SIZE;
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{FileAttr, RequestContext};
+use super::virtualfs::{INum, VirtualFs};
+
+/// The extended attribute caching the digests of a file computed by
+/// `file_digest`
+pub const DIGEST_XATTR: &str = "user.datenlord.digest";
+
+/// The algorithms digests are computed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    /// CRC-32C, cheap enough for every write
+    Crc32c,
+    /// SHA-256
+    Sha256,
+    /// BLAKE3, as strong as SHA-256 and faster in software
+    Blake3,
+}
+
+impl DigestAlgorithm {
+    /// The name of the algorithm, the prefix of its digests
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Crc32c => "crc32c",
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    /// The digest of `data`, prefixed with the name of the algorithm
+    #[must_use]
+    pub fn digest(self, data: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    /// A hasher computing the digest of data fed in pieces
+    #[must_use]
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Crc32c => Hasher(State::Crc32c(0)),
+            Self::Sha256 => Hasher(State::Sha256(Sha256::new())),
+            Self::Blake3 => Hasher(State::Blake3(Box::default())),
+        }
+    }
+}
+
+/// The digest of data fed in pieces, the same as `DigestAlgorithm::digest`
+/// of the pieces concatenated
+#[derive(Debug, Clone)]
+pub struct Hasher(State);
+
+/// The state of a `Hasher`
+#[derive(Debug, Clone)]
+enum State {
+    /// The CRC-32C of the data so far
+    Crc32c(u32),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    /// Feed the next piece `data`
+    pub fn update(&mut self, data: &[u8]) {
+        match self.0 {
+            State::Crc32c(ref mut crc) => *crc = crc32c::crc32c_append(*crc, data),
+            State::Sha256(ref mut sha) => sha.update(data),
+            State::Blake3(ref mut blake) => {
+                blake.update(data);
+            }
+        }
+    }
+
+    /// The digest of the data fed, prefixed with the name of the algorithm
+    #[must_use]
+    pub fn finish(self) -> String {
+        let (algorithm, value) = match self.0 {
+            State::Crc32c(crc) => (DigestAlgorithm::Crc32c, hex(&crc.to_be_bytes())),
+            State::Sha256(sha) => (DigestAlgorithm::Sha256, hex(&sha.finalize())),
+            State::Blake3(blake) => (DigestAlgorithm::Blake3, hex(blake.finalize().as_bytes())),
+        };
+        format!("{}:{value}", algorithm.name())
+    }
+}
+
+impl FromStr for DigestAlgorithm {
+    type Err = DatenLordError;
+
+    fn from_str(name: &str) -> DatenLordResult<Self> {
+        match name {
+            "crc32c" => Ok(Self::Crc32c),
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            _ => Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "unknown digest algorithm {name:?}, expected \"crc32c\", \"sha256\" or \"blake3\""
+                )],
+            }),
+        }
+    }
+}
+
+/// The algorithm of `digest`, failing with `DatenLordError::InvalidArgument`
+/// if it is not a digest
+pub fn algorithm_of(digest: &str) -> DatenLordResult<DigestAlgorithm> {
+    match digest.split_once(':') {
+        Some((name, value))
+            if !value.is_empty() && value.bytes().all(|b| b.is_ascii_hexdigit()) =>
+        {
+            name.parse()
+        }
+        _ => Err(DatenLordError::InvalidArgument {
+            context: vec![format!("{digest:?} is not an <algorithm>:<hex> digest")],
+        }),
+    }
+}
+
+/// Check that `data` has the digest `expected`, failing with
+/// `DatenLordError::Corrupted` if it does not and with
+/// `DatenLordError::InvalidArgument` if `expected` is not a digest
+pub fn verify(data: &[u8], expected: &str) -> DatenLordResult<()> {
+    let actual = algorithm_of(expected)?.digest(data);
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(DatenLordError::Corrupted {
+            context: vec![format!("expected digest {expected}, got {actual}")],
+        })
+    }
+}
+
+/// The digests of a file cached in its `DIGEST_XATTR`, valid while its
+/// size and modification time are those it had when they were computed
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedDigests {
+    size: u64,
+    mtime: SystemTime,
+    /// The digests by algorithm name
+    digests: BTreeMap<String, String>,
+}
+
+impl CachedDigests {
+    /// The cache of the file of `attr`, empty
+    fn new(attr: &FileAttr) -> Self {
+        Self {
+            size: attr.size,
+            mtime: attr.mtime,
+            digests: BTreeMap::new(),
+        }
+    }
+
+    /// Whether the digests are those of the file of `attr`
+    fn is_valid_for(&self, attr: &FileAttr) -> bool {
+        self.size == attr.size && self.mtime == attr.mtime
+    }
+}
+
+/// The digests cached for the file of `attr`, `None` when there are none
+/// or they are stale
+async fn cached_digests<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    attr: &FileAttr,
+) -> Option<CachedDigests> {
+    let value = fs.getxattr(ctx, attr.ino, DIGEST_XATTR).await.ok()?;
+    serde_json::from_slice::<CachedDigests>(&value)
+        .ok()
+        .filter(|cached| cached.is_valid_for(attr))
+}
+
+/// The digest with `algorithm` of the contents of file `ino`, read through
+/// `fs` in chunks
+async fn hash_file<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+    algorithm: DigestAlgorithm,
+) -> DatenLordResult<String> {
+    let fh = fs.open(ctx, ino, OFlag::O_RDONLY.bits() as u32).await?;
+    let mut buf = vec![0; COPY_CHUNK_SIZE];
+    let mut hasher = algorithm.hasher();
+    let mut offset = 0_u64;
+    let result = loop {
+        match fs.read(ctx, ino, fh, offset, buf.len() as u32, &mut buf).await {
+            Ok(0) => break Ok(()),
+            Ok(size) => {
+                hasher.update(&buf[..size]);
+                offset += size as u64;
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    fs.release(ctx, ino, fh, 0, 0, false).await?;
+    result.map(|()| hasher.finish())
+}
+
+/// The digest with `algorithm` of the contents of the regular file `ino`,
+/// streamed through `fs` rather than loaded in memory
+///
+/// With `cache`, a digest cached in the `DIGEST_XATTR` of the file is
+/// returned while the size and modification time of the file are unchanged,
+/// and a computed one is cached there when they did not change meanwhile.
+/// Failing to cache it, e.g. for lack of write permission, is not an error.
+pub async fn file_digest<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+    algorithm: DigestAlgorithm,
+    cache: bool,
+) -> DatenLordResult<String> {
+    let (_, attr) = fs.getattr(ctx, ino).await?;
+    if attr.kind != SFlag::S_IFREG {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("inode={ino} is not a regular file")],
+        });
+    }
+    let cached = if cache {
+        cached_digests(fs, ctx, &attr).await
+    } else {
+        None
+    };
+    if let Some(digest) = cached.as_ref().and_then(|cached| cached.digests.get(algorithm.name())) {
+        return Ok(digest.clone());
+    }
+
+    let digest = hash_file(fs, ctx, ino, algorithm).await?;
+    if cache {
+        let (_, after) = fs.getattr(ctx, ino).await?;
+        if after.size == attr.size && after.mtime == attr.mtime {
+            let mut cached = cached.unwrap_or_else(|| CachedDigests::new(&attr));
+            cached.digests.insert(algorithm.name().to_owned(), digest.clone());
+            let stored = match serde_json::to_vec(&cached) {
+                Ok(value) => fs.setxattr(ctx, ino, DIGEST_XATTR, &value, 0, 0).await,
+                Err(e) => Err(DatenLordError::Internal {
+                    context: vec![e.to_string()],
+                }),
+            };
+            if let Err(e) = stored {
+                debug!("failed to cache the {} digest of inode={ino}: {e}", algorithm.name());
+            }
+        }
+    }
+    Ok(digest)
+}
+
+/// The lowercase hex digits of `bytes`
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,99 @@
+//! Open directories, each holding the listing its `readdir` offsets index,
+//! so entries created or removed meanwhile do not shift them
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::common::DatenLordResult;
+
+use super::fs_util::DirEntry;
+use super::virtualfs::INum;
+
+/// A directory opened through `VirtualFs::opendir`
+#[derive(Debug)]
+struct OpenDir {
+    /// The directory
+    ino: INum,
+    /// The entries listed by the first read, `None` before it
+    entries: Option<Arc<Vec<DirEntry>>>,
+}
+
+/// The open directories of a filesystem, by handle
+#[derive(Debug)]
+pub(crate) struct DirHandles {
+    /// The next handle to allocate, 0 is never one
+    next_fh: AtomicU64,
+    /// The open directories
+    open: Mutex<HashMap<u64, OpenDir>>,
+}
+
+impl Default for DirHandles {
+    fn default() -> Self {
+        Self {
+            next_fh: AtomicU64::new(1),
+            open: Mutex::default(),
+        }
+    }
+}
+
+impl DirHandles {
+    /// Open the directory `ino`, returning its handle
+    pub(crate) fn open(&self, ino: INum) -> u64 {
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.open
+            .lock()
+            .unwrap()
+            .insert(fh, OpenDir { ino, entries: None });
+        fh
+    }
+
+    /// The number of open directories
+    pub(crate) fn len(&self) -> usize {
+        self.open.lock().unwrap().len()
+    }
+
+    /// Close the handle `fh`, unknown handles are ignored
+    pub(crate) fn release(&self, fh: u64) {
+        self.open.lock().unwrap().remove(&fh);
+    }
+
+    /// The entries of the directory `ino` from `offset` on, `list` listing
+    /// all of them
+    ///
+    /// The first read of the handle `fh` lists the directory and every read
+    /// of it indexes that listing until it is released. Without an open
+    /// handle of `ino`, e.g. with handle 0, every read lists anew.
+    pub(crate) async fn read<Fut>(
+        &self,
+        ino: INum,
+        fh: u64,
+        offset: i64,
+        list: impl FnOnce() -> Fut,
+    ) -> DatenLordResult<Vec<DirEntry>>
+    where
+        Fut: Future<Output = DatenLordResult<Vec<DirEntry>>>,
+    {
+        let skip = usize::try_from(offset).unwrap_or(0);
+        let (opened, listed) = match self.open.lock().unwrap().get(&fh) {
+            Some(dir) if dir.ino == ino => (true, dir.entries.clone()),
+            _ => (false, None),
+        };
+        let entries = match listed {
+            Some(entries) => entries,
+            None => {
+                let entries = Arc::new(list().await?);
+                if !opened {
+                    return Ok(entries.iter().skip(skip).cloned().collect());
+                }
+                // A concurrent first read may have listed it already
+                let mut open = self.open.lock().unwrap();
+                match open.get_mut(&fh) {
+                    Some(dir) => Arc::clone(dir.entries.get_or_insert(entries)),
+                    None => entries,
+                }
+            }
+        };
+        Ok(entries.iter().skip(skip).cloned().collect())
+    }
+}
@@ -0,0 +1,523 @@
+//! Middleware injecting failures into `VirtualFs` calls, to exercise error
+//! paths deterministically
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam, RenameParam,
+    RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// The error injected calls fail with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultKind {
+    /// `DatenLordError::Io`, reported as `EIO` by the backend
+    #[default]
+    Io,
+    /// `DatenLordError::Unavailable`, retried by `RetryFs`
+    Unavailable,
+    /// `DatenLordError::Timeout`, retried by `RetryFs`
+    Timeout,
+}
+
+impl FaultKind {
+    /// The injected error of call `op`
+    fn error(self, op: &str) -> DatenLordError {
+        let context = vec![format!("injected {self:?} fault on {op}")];
+        match self {
+            Self::Io => DatenLordError::Io { context, source: None },
+            Self::Unavailable => DatenLordError::Unavailable { context, source: None },
+            Self::Timeout => DatenLordError::Timeout { context },
+        }
+    }
+}
+
+/// The failures `FaultyFs` injects, none by default
+///
+/// Calls are counted from 1 in the order they reach the middleware, so a
+/// sequence of calls fails the same way on every run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaultConfig {
+    /// The calls faults are injected into, e.g. `["read", "write"]`, all
+    /// of them when empty; releasing handles and forgetting inodes never fail
+    pub ops: Vec<String>,
+    /// Fail every nth call with `error`
+    pub fail_every: Option<u64>,
+    /// The error injected calls fail with
+    pub error: FaultKind,
+    /// Delay every nth call by `delay_ms`
+    pub delay_every: Option<u64>,
+    /// The delay of the delayed calls in milliseconds
+    pub delay_ms: u64,
+    /// Write only the first half of every nth write and fail it with `error`
+    pub partial_write_every: Option<u64>,
+    /// Fail writes with `DatenLordError::NoSpace` once this many bytes were
+    /// written, after writing what still fits
+    pub space_limit: Option<u64>,
+}
+
+/// Whether `n` is a multiple of `every`
+fn nth(every: Option<u64>, n: u64) -> bool {
+    every.is_some_and(|every| n.is_multiple_of(every))
+}
+
+/// Run the inner call `$call` of operation `$op` unless a fault is injected
+/// into it
+macro_rules! fault {
+    ($self:ident, $op:literal, $call:expr) => {{
+        match $self.inject($op).await {
+            Some(e) => Err(e),
+            None => $call.await,
+        }
+    }};
+}
+
+/// A `VirtualFs` failing, delaying and cutting short calls to the inner
+/// filesystem as its `FaultConfig` says
+#[derive(Debug)]
+pub struct FaultyFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// The faults to inject
+    config: FaultConfig,
+    /// The calls faults may be injected into so far
+    calls: AtomicU64,
+    /// The writes so far
+    writes: AtomicU64,
+    /// The bytes written so far
+    written: AtomicU64,
+}
+
+impl<F: VirtualFs> FaultyFs<F> {
+    /// Wrap `inner`, injecting the faults of `config`
+    pub fn new(inner: F, config: FaultConfig) -> Self {
+        Self {
+            inner,
+            config,
+            calls: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+        }
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Whether faults are injected into calls `op`
+    fn covers(&self, op: &str) -> bool {
+        self.config.ops.is_empty() || self.config.ops.iter().any(|covered| covered == op)
+    }
+
+    /// Count the call `op`, delaying it if its turn came, and the error it
+    /// fails with if any
+    async fn inject(&self, op: &str) -> Option<DatenLordError> {
+        if !self.covers(op) {
+            return None;
+        }
+        let n = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        if nth(self.config.delay_every, n) {
+            debug!("delaying call {n}, {op}, by {}ms", self.config.delay_ms);
+            tokio::time::sleep(Duration::from_millis(self.config.delay_ms)).await;
+        }
+        nth(self.config.fail_every, n).then(|| {
+            debug!("failing call {n}, {op}");
+            self.config.error.error(op)
+        })
+    }
+
+    /// Write `data`, cutting it short when a partial write or a full
+    /// backend is due
+    async fn write_faulty(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        if let Some(e) = self.inject("write").await {
+            return Err(e);
+        }
+        if !self.covers("write") {
+            return self.inner.write(ctx, ino, fh, offset, data, flags).await;
+        }
+        let n = self.writes.fetch_add(1, Ordering::Relaxed) + 1;
+        let len = data.len() as u64;
+        let (allowed, error) = if nth(self.config.partial_write_every, n) {
+            (len / 2, Some(self.config.error.error("write")))
+        } else {
+            (len, None)
+        };
+        let (allowed, error) = match self.config.space_limit {
+            Some(limit) => {
+                let written = self.written.fetch_add(allowed, Ordering::Relaxed);
+                let room = limit.saturating_sub(written);
+                if room < allowed {
+                    self.written.fetch_sub(allowed - room, Ordering::Relaxed);
+                    let context = vec![format!("injected full backend after {limit} bytes")];
+                    (room, Some(DatenLordError::NoSpace { context, source: None }))
+                } else {
+                    (allowed, error)
+                }
+            }
+            None => (allowed, error),
+        };
+        if allowed > 0 || error.is_none() {
+            self.inner
+                .write(ctx, ino, fh, offset, &data[..allowed as usize], flags)
+                .await?;
+        }
+        error.map_or(Ok(()), Err)
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for FaultyFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        fault!(self, "lookup", self.inner.lookup(ctx, parent, name))
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        fault!(self, "exists", self.inner.exists(ctx, parent, name))
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        fault!(self, "getattr", self.inner.getattr(ctx, ino))
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        fault!(self, "setattr", self.inner.setattr(ctx, ino, param))
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        fault!(self, "readlink", self.inner.readlink(ctx, ino))
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        fault!(self, "mknod", self.inner.mknod(ctx, param))
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        fault!(self, "mkdir", self.inner.mkdir(ctx, param))
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        fault!(self, "unlink", self.inner.unlink(ctx, parent, name))
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        fault!(self, "rmdir", self.inner.rmdir(ctx, parent, dir_name))
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        fault!(self, "symlink", self.inner.symlink(ctx, parent, name, target_path))
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        fault!(self, "rename", self.inner.rename(ctx, param))
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        fault!(self, "link", self.inner.link(ctx, newparent, newname))
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        fault!(self, "open", self.inner.open(ctx, ino, flags))
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        fault!(self, "read", self.inner.read(ctx, ino, fh, offset, size, buf))
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.write_faulty(ctx, ino, fh, offset, data, flags).await
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        fault!(self, "flush", self.inner.flush(ctx, ino, fh, lock_owner))
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        fault!(self, "fsync", self.inner.fsync(ctx, ino, fh, datasync))
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        fault!(self, "lseek", self.inner.lseek(ctx, ino, fh, offset, whence))
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        fault!(self, "copy_file_range", self.inner.copy_file_range(ctx, param))
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        fault!(self, "opendir", self.inner.opendir(ctx, ino, flags))
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        fault!(self, "readdir", self.inner.readdir(ctx, ino, fh, offset))
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        fault!(self, "readdirplus", self.inner.readdirplus(ctx, ino, fh, offset))
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        fault!(self, "fsyncdir", self.inner.fsyncdir(ctx, ino, fh, datasync))
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        fault!(self, "sync_all", self.inner.sync_all(ctx))
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        fault!(self, "statfs", self.inner.statfs(ctx, ino))
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        fault!(self, "setxattr", self.inner.setxattr(ctx, ino, name, value, flags, position))
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        fault!(self, "getxattr", self.inner.getxattr(ctx, ino, name))
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        fault!(self, "listxattr", self.inner.listxattr(ctx, ino))
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        fault!(self, "removexattr", self.inner.removexattr(ctx, ino, name))
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        fault!(self, "access", self.inner.access(ctx, ino, mask))
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        fault!(self, "create", self.inner.create(ctx, ino, parent, name, mode, flags))
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        fault!(self, "getlk", self.inner.getlk(ctx, ino, lk_param))
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        fault!(self, "setlk", self.inner.setlk(ctx, ino, lk_param, sleep))
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        fault!(self, "bmap", self.inner.bmap(ctx, ino, blocksize, idx))
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        fault!(self, "ioctl", self.inner.ioctl(ctx, ino, cmd, input))
+    }
+}
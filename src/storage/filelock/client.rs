@@ -0,0 +1,149 @@
+//! The client of a `LockServer`
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::super::virtualfs::INum;
+use super::server::{Request, Response};
+use super::{LockService, LockStats, RangeLock};
+
+/// The locks of a namespace kept by a `LockServer`
+#[derive(Debug)]
+pub struct RemoteLocks {
+    /// `host:port` of the server
+    address: String,
+    /// The connection, opened on first use and again after a failure
+    connection: Mutex<Option<BufStream<TcpStream>>>,
+}
+
+impl RemoteLocks {
+    /// A client of the server at `address`, connected to on first use
+    #[must_use]
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_owned(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn round_trip(
+        stream: &mut BufStream<TcpStream>,
+        request: &Request,
+    ) -> std::io::Result<Response> {
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        stream.write_all(&line).await?;
+        stream.flush().await?;
+        let mut reply = String::new();
+        if stream.read_line(&mut reply).await? == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(serde_json::from_str(&reply)?)
+    }
+
+    /// Send `request`, failing with `DatenLordError::Unavailable` when the
+    /// server cannot be reached
+    ///
+    /// A request is not sent again once the connection fails; the next one
+    /// reconnects.
+    async fn call(&self, request: Request) -> DatenLordResult<Response> {
+        let unavailable = |e: std::io::Error| DatenLordError::Unavailable {
+            context: vec![format!("lock server {} failed: {e}", self.address)],
+            source: None,
+        };
+        let mut connection = self.connection.lock().await;
+        let stream = match *connection {
+            Some(ref mut stream) => stream,
+            None => {
+                let stream = TcpStream::connect(&self.address)
+                    .await
+                    .map_err(unavailable)?;
+                connection.insert(BufStream::new(stream))
+            }
+        };
+        match Self::round_trip(stream, &request).await {
+            Ok(Response::Error(e)) => Err(DatenLordError::Io {
+                context: vec![format!("lock server {}: {e}", self.address)],
+                source: None,
+            }),
+            Ok(response) => Ok(response),
+            Err(e) => {
+                *connection = None;
+                Err(unavailable(e))
+            }
+        }
+    }
+
+    fn unexpected(&self, response: &Response) -> DatenLordError {
+        DatenLordError::Corrupted {
+            context: vec![format!(
+                "unexpected reply {response:?} from lock server {}",
+                self.address
+            )],
+        }
+    }
+
+    async fn lock(&self, request: Request) -> DatenLordResult<Option<RangeLock>> {
+        match self.call(request).await? {
+            Response::Conflict(held) => Ok(held),
+            response => Err(self.unexpected(&response)),
+        }
+    }
+
+    async fn done(&self, request: Request) -> DatenLordResult<()> {
+        match self.call(request).await? {
+            Response::Done => Ok(()),
+            response => Err(self.unexpected(&response)),
+        }
+    }
+}
+
+#[async_trait]
+impl LockService for RemoteLocks {
+    async fn keepalive(&self, session: &str, lease: Duration) -> DatenLordResult<bool> {
+        let request = Request::Keepalive {
+            session: session.to_owned(),
+            lease_ms: u64::try_from(lease.as_millis()).unwrap_or(u64::MAX),
+        };
+        match self.call(request).await? {
+            Response::Renewed(alive) => Ok(alive),
+            response => Err(self.unexpected(&response)),
+        }
+    }
+
+    async fn end(&self, session: &str) -> DatenLordResult<()> {
+        self.done(Request::End {
+            session: session.to_owned(),
+        })
+        .await
+    }
+
+    async fn test(&self, lock: &RangeLock) -> DatenLordResult<Option<RangeLock>> {
+        self.lock(Request::Test { lock: lock.clone() }).await
+    }
+
+    async fn try_set(&self, lock: &RangeLock) -> DatenLordResult<Option<RangeLock>> {
+        self.lock(Request::Set { lock: lock.clone() }).await
+    }
+
+    async fn release(&self, session: &str, ino: INum, owner: u64) -> DatenLordResult<()> {
+        self.done(Request::Release {
+            session: session.to_owned(),
+            ino,
+            owner,
+        })
+        .await
+    }
+
+    async fn stats(&self) -> DatenLordResult<LockStats> {
+        match self.call(Request::Stats).await? {
+            Response::Stats(stats) => Ok(stats),
+            response => Err(self.unexpected(&response)),
+        }
+    }
+}
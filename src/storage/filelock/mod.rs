@@ -0,0 +1,432 @@
+//! POSIX byte range locks shared by the `SharedFs` instances of a namespace
+//!
+//! Every instance opens a session with the `LockService`, kept alive by a
+//! heartbeat. When a session misses its lease, e.g. because its host died,
+//! its locks are released, stolen by the next owner asking for them.
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use nix::libc;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::virtualfs::INum;
+
+pub mod client;
+pub mod server;
+
+pub use self::client::RemoteLocks;
+pub use self::server::LockServer;
+
+/// The kind of a lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockType {
+    /// Shared with other readers, `F_RDLCK`
+    Read,
+    /// Exclusive, `F_WRLCK`
+    Write,
+    /// Releases the range, `F_UNLCK`
+    Unlock,
+}
+
+impl LockType {
+    /// The kind of the `typ` of a `FileLockParam`
+    pub fn from_raw(typ: u32) -> DatenLordResult<Self> {
+        match i32::try_from(typ) {
+            Ok(libc::F_RDLCK) => Ok(Self::Read),
+            Ok(libc::F_WRLCK) => Ok(Self::Write),
+            Ok(libc::F_UNLCK) => Ok(Self::Unlock),
+            _ => Err(DatenLordError::InvalidArgument {
+                context: vec![format!("invalid lock type {typ}")],
+            }),
+        }
+    }
+}
+
+/// A lock on the bytes `start..=end` of inode `ino`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RangeLock {
+    /// The locked inode
+    pub ino: INum,
+    /// The session of the instance taking the lock
+    pub session: String,
+    /// The lock owner within the session
+    pub owner: u64,
+    /// The process taking the lock, only reported
+    pub pid: u32,
+    /// The first locked byte
+    pub start: u64,
+    /// The last locked byte, `u64::MAX` up to any end of file
+    pub end: u64,
+    /// The kind of the lock
+    pub typ: LockType,
+}
+
+impl RangeLock {
+    fn same_owner(&self, other: &Self) -> bool {
+        self.session == other.session && self.owner == other.owner
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        self.ino == other.ino && self.start <= other.end && other.start <= self.end
+    }
+
+    fn conflicts(&self, other: &Self) -> bool {
+        !self.same_owner(other)
+            && self.overlaps(other)
+            && (self.typ == LockType::Write || other.typ == LockType::Write)
+    }
+}
+
+/// Counters on the locks of a service, and on the waits of an instance
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockStats {
+    /// The sessions alive
+    pub sessions: u64,
+    /// The locks held
+    pub held: u64,
+    /// The locks granted so far
+    pub acquired: u64,
+    /// The attempts denied by a conflicting lock
+    pub conflicts: u64,
+    /// The locks released because their session missed its lease
+    pub stolen: u64,
+    /// The waits of this instance for a conflicting lock to go
+    pub waits: u64,
+    /// The time this instance spent waiting, in milliseconds
+    pub waited_ms: u64,
+}
+
+/// Where the locks of a namespace are kept
+#[async_trait]
+pub trait LockService: Debug + Send + Sync {
+    /// Open `session`, or keep it alive, for another `lease`, returning
+    /// whether it was alive
+    async fn keepalive(&self, session: &str, lease: Duration) -> DatenLordResult<bool>;
+
+    /// Close `session`, releasing its locks
+    async fn end(&self, session: &str) -> DatenLordResult<()>;
+
+    /// The lock of another owner conflicting with `lock`, if any
+    async fn test(&self, lock: &RangeLock) -> DatenLordResult<Option<RangeLock>>;
+
+    /// Take, change or with `LockType::Unlock` release the range of `lock`,
+    /// unless another owner holds a conflicting lock, returned instead
+    async fn try_set(&self, lock: &RangeLock) -> DatenLordResult<Option<RangeLock>>;
+
+    /// Release the locks of `owner` of `session` on `ino`
+    async fn release(&self, session: &str, ino: INum, owner: u64) -> DatenLordResult<()>;
+
+    /// The counters of the service, without the waits
+    async fn stats(&self) -> DatenLordResult<LockStats>;
+}
+
+/// A lock service configured in `SharedConfig::locks`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LockConfig {
+    /// Kept in the instance, so only its own owners exclude each other
+    #[default]
+    Local,
+    /// Kept by a `datenlord-lockd` server
+    Server {
+        /// `host:port` of the server
+        address: String,
+    },
+}
+
+impl LockConfig {
+    /// Build the service
+    pub fn build(&self) -> Arc<dyn LockService> {
+        match *self {
+            Self::Local => Arc::new(LockManager::default()),
+            Self::Server { ref address } => Arc::new(RemoteLocks::new(address)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LockState {
+    /// The locks held on every inode
+    locks: HashMap<INum, Vec<RangeLock>>,
+    /// When the lease of every session ends
+    sessions: HashMap<String, Instant>,
+    stats: LockStats,
+}
+
+impl LockState {
+    /// Release the locks of the sessions whose lease ended
+    fn reap(&mut self, now: Instant) {
+        let expired: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|&(_, &expires)| expires <= now)
+            .map(|(session, _)| session.clone())
+            .collect();
+        for session in expired {
+            self.sessions.remove(&session);
+            let released = self.release(|lock| lock.session == session);
+            if released > 0 {
+                warn!("lock session {session} missed its lease, releasing its {released} locks");
+                self.stats.stolen += released;
+            }
+        }
+    }
+
+    /// Release the locks matching `released`, returning how many
+    fn release(&mut self, released: impl Fn(&RangeLock) -> bool) -> u64 {
+        let mut count = 0;
+        self.locks.retain(|_, locks| {
+            let before = locks.len();
+            locks.retain(|lock| !released(lock));
+            count += before - locks.len();
+            !locks.is_empty()
+        });
+        count as u64
+    }
+
+    fn conflict(&self, lock: &RangeLock) -> Option<RangeLock> {
+        if lock.typ == LockType::Unlock {
+            return None;
+        }
+        self.locks
+            .get(&lock.ino)?
+            .iter()
+            .find(|held| held.conflicts(lock))
+            .cloned()
+    }
+
+    fn try_set(&mut self, lock: &RangeLock) -> DatenLordResult<Option<RangeLock>> {
+        if lock.start > lock.end {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("invalid lock range {}..={}", lock.start, lock.end)],
+            });
+        }
+        if !self.sessions.contains_key(&lock.session) {
+            return Err(DatenLordError::Io {
+                context: vec![format!(
+                    "lock session {} missed its lease, its locks were released",
+                    lock.session
+                )],
+                source: None,
+            });
+        }
+        if let Some(held) = self.conflict(lock) {
+            self.stats.conflicts += 1;
+            return Ok(Some(held));
+        }
+        // The new lock replaces whatever the owner held in its range
+        let held = self.locks.remove(&lock.ino).unwrap_or_default();
+        let mut locks = Vec::with_capacity(held.len() + 1);
+        for held in held {
+            if !held.same_owner(lock) || !held.overlaps(lock) {
+                locks.push(held);
+                continue;
+            }
+            if held.start < lock.start {
+                locks.push(RangeLock {
+                    end: lock.start - 1,
+                    ..held.clone()
+                });
+            }
+            if held.end > lock.end {
+                locks.push(RangeLock {
+                    start: lock.end + 1,
+                    ..held
+                });
+            }
+        }
+        if lock.typ != LockType::Unlock {
+            locks.push(lock.clone());
+            self.stats.acquired += 1;
+        }
+        if !locks.is_empty() {
+            self.locks.insert(lock.ino, locks);
+        }
+        Ok(None)
+    }
+}
+
+/// The locks of a namespace kept in memory, by one instance or by a
+/// `LockServer` for all of them
+#[derive(Debug, Default)]
+pub struct LockManager {
+    state: Mutex<LockState>,
+}
+
+impl LockManager {
+    fn state(&self) -> std::sync::MutexGuard<'_, LockState> {
+        let mut state = self.state.lock().unwrap();
+        state.reap(Instant::now());
+        state
+    }
+}
+
+#[async_trait]
+impl LockService for LockManager {
+    async fn keepalive(&self, session: &str, lease: Duration) -> DatenLordResult<bool> {
+        let mut state = self.state();
+        Ok(state
+            .sessions
+            .insert(session.to_owned(), Instant::now() + lease)
+            .is_some())
+    }
+
+    async fn end(&self, session: &str) -> DatenLordResult<()> {
+        let mut state = self.state();
+        state.sessions.remove(session);
+        state.release(|lock| lock.session == session);
+        Ok(())
+    }
+
+    async fn test(&self, lock: &RangeLock) -> DatenLordResult<Option<RangeLock>> {
+        Ok(self.state().conflict(lock))
+    }
+
+    async fn try_set(&self, lock: &RangeLock) -> DatenLordResult<Option<RangeLock>> {
+        self.state().try_set(lock)
+    }
+
+    async fn release(&self, session: &str, ino: INum, owner: u64) -> DatenLordResult<()> {
+        self.state()
+            .release(|lock| lock.ino == ino && lock.session == session && lock.owner == owner);
+        Ok(())
+    }
+
+    async fn stats(&self) -> DatenLordResult<LockStats> {
+        let state = self.state();
+        Ok(LockStats {
+            sessions: state.sessions.len() as u64,
+            held: state.locks.values().map(|locks| locks.len() as u64).sum(),
+            ..state.stats
+        })
+    }
+}
+
+/// The session of an instance with its lock service, kept alive in the
+/// background and ended when dropped
+#[derive(Debug)]
+pub(crate) struct LockSession {
+    service: Arc<dyn LockService>,
+    id: String,
+    waits: AtomicU64,
+    waited_ms: AtomicU64,
+    heartbeat: JoinHandle<()>,
+}
+
+impl LockSession {
+    /// Open the session `id`, renewing its `lease` three times per lease
+    pub(crate) async fn open(
+        service: Arc<dyn LockService>,
+        id: String,
+        lease: Duration,
+    ) -> DatenLordResult<Self> {
+        service.keepalive(&id, lease).await?;
+        let heartbeat = {
+            let service = Arc::clone(&service);
+            let id = id.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(lease / 3).await;
+                    match service.keepalive(&id, lease).await {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            warn!("lock session {id} missed its lease, its locks were released");
+                        }
+                        Err(e) => warn!("failed to renew lock session {id}: {e}"),
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            service,
+            id,
+            waits: AtomicU64::new(0),
+            waited_ms: AtomicU64::new(0),
+            heartbeat,
+        })
+    }
+
+    /// The session id, to put in the locks taken
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The lock of another owner conflicting with `lock`, if any
+    pub(crate) async fn test(&self, lock: &RangeLock) -> DatenLordResult<Option<RangeLock>> {
+        self.service.test(lock).await
+    }
+
+    /// Set `lock`, waiting for the conflicting locks to go if `wait` or
+    /// failing with `DatenLordError::Unavailable` otherwise
+    pub(crate) async fn set(&self, lock: &RangeLock, wait: bool) -> DatenLordResult<()> {
+        let mut waiting: Option<Instant> = None;
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            match self.service.try_set(lock).await? {
+                None => break,
+                Some(held) if !wait => return Err(conflict(&held)),
+                Some(_) => {
+                    waiting.get_or_insert_with(Instant::now);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_millis(50));
+                }
+            }
+        }
+        if let Some(started) = waiting {
+            let waited = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+            self.waits.fetch_add(1, Ordering::Relaxed);
+            self.waited_ms.fetch_add(waited, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Release the locks of `owner` on `ino`
+    pub(crate) async fn release(&self, ino: INum, owner: u64) -> DatenLordResult<()> {
+        self.service.release(&self.id, ino, owner).await
+    }
+
+    /// The counters of the service and the waits of the session
+    pub(crate) async fn stats(&self) -> DatenLordResult<LockStats> {
+        Ok(LockStats {
+            waits: self.waits.load(Ordering::Relaxed),
+            waited_ms: self.waited_ms.load(Ordering::Relaxed),
+            ..self.service.stats().await?
+        })
+    }
+}
+
+impl Drop for LockSession {
+    fn drop(&mut self) {
+        self.heartbeat.abort();
+        // Left to expire with its lease without a runtime to end it on
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let service = Arc::clone(&self.service);
+            let id = self.id.clone();
+            runtime.spawn(async move {
+                if let Err(e) = service.end(&id).await {
+                    warn!("failed to end lock session {id}, it expires with its lease: {e}");
+                }
+            });
+        }
+    }
+}
+
+/// The error of a lock denied by `held`
+pub(crate) fn conflict(held: &RangeLock) -> DatenLordError {
+    DatenLordError::Unavailable {
+        context: vec![format!(
+            "bytes {}..={} of inode={} are locked by pid {}",
+            held.start, held.end, held.ino, held.pid
+        )],
+        source: None,
+    }
+}
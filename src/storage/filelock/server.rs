@@ -0,0 +1,143 @@
+//! A server keeping the locks of a namespace for the `RemoteLocks` clients
+//! of its instances
+//!
+//! Clients send a request as a JSON line and read the reply as one, over as
+//! many requests as they like per connection.
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufStream};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::super::virtualfs::INum;
+use super::{LockManager, LockService, LockStats, RangeLock};
+
+/// A request of a client, one per `LockService` method
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum Request {
+    Keepalive {
+        session: String,
+        lease_ms: u64,
+    },
+    End {
+        session: String,
+    },
+    Test {
+        lock: RangeLock,
+    },
+    Set {
+        lock: RangeLock,
+    },
+    Release {
+        session: String,
+        ino: INum,
+        owner: u64,
+    },
+    Stats,
+}
+
+/// The reply to a request
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Response {
+    Done,
+    Renewed(bool),
+    Conflict(Option<RangeLock>),
+    Stats(LockStats),
+    Error(String),
+}
+
+/// A lock server, keeping the sessions and the locks in memory
+#[derive(Debug, Default)]
+pub struct LockServer {
+    locks: LockManager,
+}
+
+impl LockServer {
+    /// The counters of the locks kept
+    pub async fn stats(&self) -> DatenLordResult<LockStats> {
+        self.locks.stats().await
+    }
+
+    /// Serve the clients connecting to `listener` until accepting fails
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            debug!("lock client {peer} connected");
+            let server = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(e) = server.serve_connection(stream).await {
+                    debug!("lock connection of {peer} failed: {e}");
+                }
+            });
+        }
+    }
+
+    /// Serve the requests of one connection in order
+    async fn serve_connection(&self, stream: TcpStream) -> io::Result<()> {
+        let mut stream = BufStream::new(stream);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await? == 0 {
+                return Ok(());
+            }
+            let response = match serde_json::from_str(&line) {
+                Ok(request) => self.handle(request).await,
+                Err(e) => Response::Error(format!("invalid request: {e}")),
+            };
+            let mut reply = serde_json::to_vec(&response)?;
+            reply.push(b'\n');
+            stream.write_all(&reply).await?;
+            stream.flush().await?;
+        }
+    }
+
+    async fn handle(&self, request: Request) -> Response {
+        let result = match request {
+            Request::Keepalive { session, lease_ms } => self
+                .locks
+                .keepalive(&session, Duration::from_millis(lease_ms))
+                .await
+                .map(Response::Renewed),
+            Request::End { session } => self.locks.end(&session).await.map(|()| Response::Done),
+            Request::Test { lock } => self.locks.test(&lock).await.map(Response::Conflict),
+            Request::Set { lock } => self.locks.try_set(&lock).await.map(Response::Conflict),
+            Request::Release {
+                session,
+                ino,
+                owner,
+            } => self
+                .locks
+                .release(&session, ino, owner)
+                .await
+                .map(|()| Response::Done),
+            Request::Stats => self.locks.stats().await.map(Response::Stats),
+        };
+        result.unwrap_or_else(|e| Response::Error(e.to_string()))
+    }
+}
+
+/// Serve the locks of a namespace on `listen` until the listener fails
+pub async fn run(listen: &str) -> DatenLordResult<()> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to listen on {listen}: {e}")],
+            source: None,
+        })?;
+    info!("serving locks on {listen}");
+    Arc::new(LockServer::default())
+        .serve(listener)
+        .await
+        .map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to accept lock clients: {e}")],
+            source: None,
+        })
+}
@@ -0,0 +1,455 @@
+//! Middleware hiding entries from directory listings
+use std::ffi::OsStr;
+use std::future::Future;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common::DatenLordResult;
+
+use super::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam, RenameParam,
+    RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+use super::walk;
+
+/// Which entries are left out of directory listings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ListingFilter {
+    /// Hide names starting with a dot
+    pub hide_dotfiles: bool,
+    /// Hide names matching any of these patterns, where `?` matches one
+    /// character and `*` any characters, e.g. `.trash` or `.snapshot*`
+    pub hidden_names: Vec<String>,
+}
+
+impl ListingFilter {
+    /// Whether the filter hides nothing
+    pub fn is_empty(&self) -> bool {
+        !self.hide_dotfiles && self.hidden_names.is_empty()
+    }
+
+    /// Whether `name` is left out of listings, patterns matching names
+    /// that are not UTF-8 with their invalid bytes replaced
+    pub fn hides(&self, name: &OsStr) -> bool {
+        if self.hide_dotfiles && name.as_bytes().starts_with(b".") {
+            return true;
+        }
+        let name = name.to_string_lossy();
+        self.hidden_names
+            .iter()
+            .any(|pattern| walk::match_component(pattern, &name))
+    }
+}
+
+/// A `VirtualFs` leaving the entries its `ListingFilter` hides out of
+/// `readdir` and `readdirplus`
+///
+/// Hidden entries are only absent from listings, and from the walks built
+/// on them; looking them up by name still works. Offsets count visible
+/// entries, so every listing reads the directory from its start.
+#[derive(Debug)]
+pub struct FilterFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// The entries to hide
+    filter: ListingFilter,
+}
+
+impl<F: VirtualFs> FilterFs<F> {
+    /// Wrap `inner`, hiding the entries `filter` matches
+    pub fn new(inner: F, filter: ListingFilter) -> Self {
+        Self { inner, filter }
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// The visible entries from the `offset`th on, reading the pages
+    /// `list` returns for inner offsets until the directory is exhausted
+    async fn visible<T, L, Fut>(
+        &self,
+        offset: i64,
+        mut list: L,
+        name: impl Fn(&T) -> &OsStr,
+    ) -> DatenLordResult<Vec<T>>
+    where
+        L: FnMut(i64) -> Fut,
+        Fut: Future<Output = DatenLordResult<Vec<T>>>,
+    {
+        if self.filter.is_empty() {
+            return list(offset).await;
+        }
+        let mut skip = usize::try_from(offset).unwrap_or(0);
+        let (mut inner_offset, mut visible) = (0, Vec::new());
+        loop {
+            let page = list(inner_offset).await?;
+            if page.is_empty() {
+                return Ok(visible);
+            }
+            inner_offset += i64::try_from(page.len()).unwrap_or(i64::MAX);
+            for entry in page {
+                if self.filter.hides(name(&entry)) {
+                    continue;
+                }
+                if skip > 0 {
+                    skip -= 1;
+                } else {
+                    visible.push(entry);
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for FilterFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.lookup(ctx, parent, name).await
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.inner.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.setattr(ctx, ino, param).await
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mknod(ctx, param).await
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mkdir(ctx, param).await
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.unlink(ctx, parent, name).await
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        self.inner.rmdir(ctx, parent, dir_name).await
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.symlink(ctx, parent, name, target_path).await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        self.inner.rename(ctx, param).await
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.link(ctx, newparent, newname).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.open(ctx, ino, flags).await
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        self.inner.read(ctx, ino, fh, offset, size, buf).await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.write(ctx, ino, fh, offset, data, flags).await
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.release(ctx, ino, fh, flags, lock_owner, flush).await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        self.inner.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        self.inner.copy_file_range(ctx, param).await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.visible(
+            offset,
+            |offset| self.inner.readdir(ctx, ino, fh, offset),
+            |entry| &entry.name,
+        )
+        .await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.visible(
+            offset,
+            |offset| self.inner.readdirplus(ctx, ino, fh, offset),
+            |(entry, _, _)| &entry.name,
+        )
+        .await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.setxattr(ctx, ino, name, value, flags, position).await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.create(ctx, ino, parent, name, mode, flags).await
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
@@ -1,6 +1,10 @@
 //! The implementation of filesystem related utilities
+use std::ffi::{OsStr, OsString};
+use std::fs::FileType;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
 use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::Context;
 use clippy_utilities::Cast;
@@ -16,19 +20,98 @@ use crate::common::{DatenLordError, DatenLordResult};
 /// # Errors
 ///
 /// Return the built `Err(anyhow::Error(..))`
-pub fn build_error_result_from_errno<T>(_error_code: Errno, err_msg: String) -> DatenLordResult<T> {
-    Err(DatenLordError::Internal {
-        context: vec![err_msg],
+pub fn build_error_result_from_errno<T>(error_code: Errno, err_msg: String) -> DatenLordResult<T> {
+    let context = vec![err_msg];
+    Err(match error_code {
+        Errno::EACCES | Errno::EPERM => DatenLordError::PermissionDenied { context, source: None },
+        _ => DatenLordError::Internal { context },
     })
 }
 
 use super::virtualfs::INum;
 
+/// The components of the `/` separated `path`, leaving out empty ones and `.`
+pub fn components(path: &OsStr) -> impl Iterator<Item = &OsStr> {
+    path.as_bytes()
+        .split(|&c| c == b'/')
+        .map(OsStr::from_bytes)
+        .filter(|component| !component.is_empty() && *component != ".")
+}
+
+/// `path` with its `components` joined by single `/`
+pub fn normalize(path: &OsStr) -> OsString {
+    let mut normalized = OsString::with_capacity(path.len());
+    for component in components(path) {
+        if !normalized.is_empty() {
+            normalized.push("/");
+        }
+        normalized.push(component);
+    }
+    normalized
+}
+
+/// The longest name of an entry by default, like `NAME_MAX`
+const DEFAULT_NAME_MAX: usize = 255;
+/// How old an access time `relatime` updates on every read is, like Linux
+const RELATIME_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The names of entries a backend accepts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NameConfig {
+    /// The longest name of an entry in bytes, reported as `namelen` by `statfs`
+    pub max_len: usize,
+    /// Reject names that are not valid UTF-8, given to the backend or found
+    /// listing a directory, for applications handling names as strings
+    pub require_utf8: bool,
+}
+
+impl Default for NameConfig {
+    fn default() -> Self {
+        Self {
+            max_len: DEFAULT_NAME_MAX,
+            require_utf8: false,
+        }
+    }
+}
+
+impl NameConfig {
+    /// Check no `/` separated component of `name` holds a NUL byte or is
+    /// longer than `max_len`, failing with `DatenLordError::InvalidName`
+    pub fn check(&self, name: &OsStr) -> DatenLordResult<()> {
+        let invalid = |reason: String| DatenLordError::InvalidName {
+            context: vec![format!("invalid name {name:?}: {reason}")],
+        };
+        let bytes = name.as_bytes();
+        if bytes.contains(&0) {
+            return Err(invalid("embedded NUL byte".to_owned()));
+        }
+        if let Some(component) = bytes.split(|&c| c == b'/').find(|c| c.len() > self.max_len) {
+            return Err(invalid(format!(
+                "{} bytes in a component, at most {} are allowed",
+                component.len(),
+                self.max_len
+            )));
+        }
+        self.check_listed(name)
+    }
+
+    /// Check the name of an entry found listing a directory, see `require_utf8`
+    pub fn check_listed(&self, name: &OsStr) -> DatenLordResult<()> {
+        if self.require_utf8 && name.to_str().is_none() {
+            return Err(DatenLordError::InvalidName {
+                context: vec![format!("name {name:?} is not valid UTF-8")],
+            });
+        }
+        Ok(())
+    }
+}
+
 /// The node ID of the root inode
 pub const ROOT_ID: u64 = 1;
 
 /// POSIX statvfs parameters
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct StatFsParam {
     /// The number of blocks in the filesystem
     pub blocks: u64,
@@ -64,7 +147,7 @@ impl Default for StatFsParam {
 }
 
 /// Set attribute parameters
-#[derive(Debug)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct SetAttrParam {
     /// FUSE set attribute bit mask
     pub valid: u32,
@@ -96,15 +179,11 @@ pub struct CreateParam {
     /// Parent directory i-number
     pub parent: INum,
     /// File name
-    pub name: String,
+    pub name: OsString,
     /// File mode
     pub mode: u32,
     /// File flags
     pub rdev: u32,
-    /// User ID
-    pub uid: u32,
-    /// Group ID
-    pub gid: u32,
     /// Type
     pub node_type: SFlag,
     /// For symlink
@@ -117,12 +196,12 @@ pub struct RenameParam {
     /// Old parent directory i-number
     pub old_parent: INum,
     /// Old name
-    pub old_name: String,
+    pub old_name: OsString,
     /// New parent directory i-number
     pub new_parent: INum,
     /// New name
-    pub new_name: String,
-    /// Rename flags
+    pub new_name: OsString,
+    /// Rename flags, `RENAME_NOREPLACE` or `RENAME_EXCHANGE` of `renameat2`
     pub flags: u32,
 }
 
@@ -144,7 +223,7 @@ pub struct FileLockParam {
 }
 
 /// File attributes
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileAttr {
     /// Inode number
     pub ino: INum,
@@ -159,6 +238,7 @@ pub struct FileAttr {
     /// Time of last change
     pub ctime: SystemTime,
     /// Kind of file (directory, file, pipe, etc)
+    #[serde(with = "sflag_bits")]
     pub kind: SFlag,
     /// Permissions
     pub perm: u16,
@@ -170,15 +250,366 @@ pub struct FileAttr {
     pub gid: u32,
     /// Rdev
     pub rdev: u32,
+    /// The generation of the entries of a directory, changing whenever an
+    /// entry is created, removed or renamed in or out of it, 0 for other
+    /// files
+    ///
+    /// Comparing it before and after listing a directory tells whether the
+    /// listing is a snapshot, and a cached entry is still valid as long as
+    /// the generation of its directory did not change.
+    #[serde(default)]
+    pub generation: u64,
 }
 
 /// Whether to check permission.
 /// If fuse mount with `-o default_permissions`, then we should not check
 /// permission. Otherwise, we should check permission.
-/// TODO: add a feature flag to control this
-pub const NEED_CHECK_PERM: bool = false;
+/// The SDKs have no kernel checking permissions for them, so the backends
+/// check them against the `RequestContext` of every operation.
+pub const NEED_CHECK_PERM: bool = true;
+
+/// The caller an operation runs on behalf of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestContext {
+    /// Effective user id
+    pub uid: u32,
+    /// Effective group id
+    pub gid: u32,
+    /// Process id
+    pub pid: u32,
+    /// Permission bits cleared from the mode of created files
+    pub umask: u32,
+    /// The directory the caller sees as the root, `ROOT_ID` for the root of
+    /// the filesystem, which paths of the caller cannot lead out of
+    #[serde(default = "root_id")]
+    pub root: INum,
+}
+
+/// The default `RequestContext::root`
+const fn root_id() -> INum {
+    ROOT_ID
+}
+
+impl RequestContext {
+    /// The context of the calling process
+    pub fn current() -> Self {
+        Self {
+            uid: nix::unistd::geteuid().as_raw(),
+            gid: nix::unistd::getegid().as_raw(),
+            pid: nix::unistd::getpid().as_raw().cast(),
+            umask: current_umask(),
+            root: ROOT_ID,
+        }
+    }
+
+    /// The inode `ino` stands for, `ROOT_ID` being the root of the caller
+    pub fn scope(&self, ino: INum) -> INum {
+        if ino == ROOT_ID {
+            self.root
+        } else {
+            ino
+        }
+    }
+
+    /// Whether the caller is the superuser
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    /// The mode of a file created with `mode`, with the umask applied
+    pub fn create_mode(&self, mode: u32) -> u32 {
+        mode & !self.umask & 0o7777
+    }
+}
+
+/// The umask of the process, read from `/proc` since `umask(2)` can only
+/// be queried by changing it
+fn current_umask() -> u32 {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("Umask:"))
+                .and_then(|umask| u32::from_str_radix(umask.trim(), 8).ok())
+        })
+        .unwrap_or(0o022)
+}
+
+/// (De)serialize file type bits as the raw `st_mode` bits
+mod sflag_bits {
+    use nix::sys::stat::SFlag;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(kind: &SFlag, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(kind.bits())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SFlag, D::Error> {
+        u32::deserialize(deserializer).map(SFlag::from_bits_truncate)
+    }
+}
+
+/// The type of a file, as reported to the SDKs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileKind {
+    /// Regular file
+    #[serde(rename = "file")]
+    RegularFile,
+    /// Directory
+    #[serde(rename = "directory")]
+    Directory,
+    /// Symbolic link
+    #[serde(rename = "symlink")]
+    Symlink,
+    /// Named pipe
+    #[serde(rename = "fifo")]
+    NamedPipe,
+    /// Character device
+    #[serde(rename = "char_device")]
+    CharDevice,
+    /// Block device
+    #[serde(rename = "block_device")]
+    BlockDevice,
+    /// Unix domain socket
+    #[serde(rename = "socket")]
+    Socket,
+}
+
+impl FileKind {
+    /// The kind of a file with type bits `kind`, `None` for unknown bits
+    pub fn from_sflag(kind: SFlag) -> Option<Self> {
+        match kind {
+            SFlag::S_IFREG => Some(Self::RegularFile),
+            SFlag::S_IFDIR => Some(Self::Directory),
+            SFlag::S_IFLNK => Some(Self::Symlink),
+            SFlag::S_IFIFO => Some(Self::NamedPipe),
+            SFlag::S_IFCHR => Some(Self::CharDevice),
+            SFlag::S_IFBLK => Some(Self::BlockDevice),
+            SFlag::S_IFSOCK => Some(Self::Socket),
+            _ => None,
+        }
+    }
+
+    /// The kind of a file of type `file_type`
+    pub fn from_file_type(file_type: FileType) -> Self {
+        if file_type.is_dir() {
+            Self::Directory
+        } else if file_type.is_symlink() {
+            Self::Symlink
+        } else if file_type.is_fifo() {
+            Self::NamedPipe
+        } else if file_type.is_char_device() {
+            Self::CharDevice
+        } else if file_type.is_block_device() {
+            Self::BlockDevice
+        } else if file_type.is_socket() {
+            Self::Socket
+        } else {
+            Self::RegularFile
+        }
+    }
+
+    /// The lowercase name of the kind
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::RegularFile => "file",
+            Self::Directory => "directory",
+            Self::Symlink => "symlink",
+            Self::NamedPipe => "fifo",
+            Self::CharDevice => "char_device",
+            Self::BlockDevice => "block_device",
+            Self::Socket => "socket",
+        }
+    }
+
+    /// The kind named `name`, see `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::RegularFile,
+            Self::Directory,
+            Self::Symlink,
+            Self::NamedPipe,
+            Self::CharDevice,
+            Self::BlockDevice,
+            Self::Socket,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    }
+}
+
+/// An entry of a directory listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirEntry {
+    /// The name of the child, as on disk so it need not be UTF-8
+    pub name: OsString,
+    /// The inode number of the child
+    pub ino: INum,
+    /// The type of the child
+    pub kind: FileKind,
+    /// The attributes of the child, only filled by `VirtualFs::readdirplus`
+    pub attr: Option<FileAttr>,
+}
+
+/// Split `time` into seconds and nanoseconds relative to the Unix epoch
+///
+/// Times before the epoch have negative seconds and the nanoseconds still
+/// count forward, like `struct timespec`.
+pub fn to_timespec(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (after.as_secs().cast(), after.subsec_nanos()),
+        Err(e) => {
+            let before = e.duration();
+            let secs: i64 = before.as_secs().cast();
+            match before.subsec_nanos() {
+                0 => (-secs, 0),
+                nanos => (-secs - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+/// The inverse of `to_timespec`, `None` if `nsec` is not below one second
+pub fn from_timespec(sec: i64, nsec: u32) -> Option<SystemTime> {
+    if nsec >= 1_000_000_000 {
+        return None;
+    }
+    let since_epoch = Duration::new(sec.unsigned_abs(), 0);
+    let whole = if sec >= 0 {
+        UNIX_EPOCH.checked_add(since_epoch)?
+    } else {
+        UNIX_EPOCH.checked_sub(since_epoch)?
+    };
+    whole.checked_add(Duration::from_nanos(nsec.into()))
+}
+
+/// What `VirtualFs::lseek` looks for from an offset, like `SEEK_DATA` and
+/// `SEEK_HOLE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekWhence {
+    /// The next byte holding data
+    Data,
+    /// The next byte in a hole, the end of the file counting as one
+    Hole,
+}
+
+impl SeekWhence {
+    /// The whence `SEEK_DATA` or `SEEK_HOLE` of `lseek(2)` stands for
+    pub fn from_raw(whence: i32) -> DatenLordResult<Self> {
+        match whence {
+            nix::libc::SEEK_DATA => Ok(Self::Data),
+            nix::libc::SEEK_HOLE => Ok(Self::Hole),
+            _ => Err(DatenLordError::InvalidArgument {
+                context: vec![format!("unsupported whence={whence}")],
+            }),
+        }
+    }
+}
+
+/// The ranges of two open files `VirtualFs::copy_file_range` copies between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyRangeParam {
+    /// The i-number of the source
+    pub ino_in: INum,
+    /// The handle the source is open as
+    pub fh_in: u64,
+    /// The offset in the source the copy starts at
+    pub offset_in: u64,
+    /// The i-number of the destination
+    pub ino_out: INum,
+    /// The handle the destination is open for writing as
+    pub fh_out: u64,
+    /// The offset in the destination the copy starts at
+    pub offset_out: u64,
+    /// The bytes to copy, the copy stops at the end of the source
+    pub len: u64,
+}
+
+/// What a `VirtualFs::copy_file_range` copied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CopyRangeResult {
+    /// The bytes copied, 0 at the end of the source
+    pub copied: u64,
+    /// Whether the destination shares the blocks of the source rather than
+    /// holding a copy of its data, like after `FICLONE`
+    pub reflinked: bool,
+}
+
+/// Where `whence` lands from `offset` in a file of `size` bytes whose data
+/// is the sorted, disjoint `(start, end)` ranges of `extents`, `None` when
+/// `offset` is past the end or no data follows it
+pub fn seek_extents(
+    extents: &[(u64, u64)],
+    size: u64,
+    offset: u64,
+    whence: SeekWhence,
+) -> Option<u64> {
+    if offset >= size {
+        return None;
+    }
+    match whence {
+        SeekWhence::Data => extents
+            .iter()
+            .find(|&&(_, end)| end > offset)
+            .map(|&(start, _)| start.max(offset))
+            .filter(|&pos| pos < size),
+        SeekWhence::Hole => {
+            let mut pos = offset;
+            for &(start, end) in extents {
+                if start > pos {
+                    break;
+                }
+                pos = pos.max(end);
+            }
+            Some(pos.min(size))
+        }
+    }
+}
+
+/// A timestamp to set through `VirtualFs::utimens`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UtimeSpec {
+    /// Set the timestamp to the current time, like `UTIME_NOW`
+    Now,
+    /// Leave the timestamp unchanged, like `UTIME_OMIT`
+    Omit,
+    /// Set the timestamp to the given time
+    Time(SystemTime),
+}
+
+impl UtimeSpec {
+    /// The time to pass to `setattr`, `now` standing for the current time
+    pub fn resolve(self, now: SystemTime) -> Option<SystemTime> {
+        match self {
+            Self::Now => Some(now),
+            Self::Omit => None,
+            Self::Time(time) => Some(time),
+        }
+    }
+}
+
+/// When reads update the access time of files, like the `noatime`,
+/// `relatime` and `strictatime` mount options
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AtimePolicy {
+    /// Never
+    NoAtime,
+    /// When the access time is not later than the modification or change
+    /// time, or is a day old
+    #[default]
+    Relatime,
+    /// On every read, as POSIX requires
+    StrictAtime,
+}
 
 impl FileAttr {
+    /// The file type and permission bits, like `st_mode`
+    pub fn mode(&self) -> u32 {
+        self.kind.bits() | u32::from(self.perm)
+    }
+
     /// New a `FileAttr`
     pub(crate) fn now() -> Self {
         let now = SystemTime::now();
@@ -195,6 +626,7 @@ impl FileAttr {
             uid: 0,
             gid: 0,
             rdev: 0,
+            generation: 0,
         }
     }
 
@@ -202,9 +634,9 @@ impl FileAttr {
     pub(crate) fn setattr_precheck(
         &self,
         param: &SetAttrParam,
-        context_uid: u32,
-        context_gid: u32,
+        ctx: &RequestContext,
     ) -> DatenLordResult<Option<FileAttr>> {
+        let context_uid = ctx.uid;
         let cur_attr = *self;
         let mut dirty_attr = cur_attr;
 
@@ -220,7 +652,7 @@ impl FileAttr {
                         "setattr() cannot change atime".to_owned(),
                     );
                 }
-                cur_attr.check_perm(context_uid, context_gid, 2)?;
+                cur_attr.check_perm(ctx, 2)?;
                 if context_uid != cur_attr.uid {
                     return build_error_result_from_errno(
                         Errno::EACCES,
@@ -315,7 +747,25 @@ impl FileAttr {
         Ok(attr_changed.then_some(dirty_attr))
     }
 
-    /// ```
+    /// Whether a read at `now` updates the access time under `policy`
+    ///
+    /// Unlike the `atime` of `setattr`, which always applies, the update
+    /// leaves `ctime` unchanged.
+    pub fn read_updates_atime(&self, policy: AtimePolicy, now: SystemTime) -> bool {
+        match policy {
+            AtimePolicy::NoAtime => false,
+            AtimePolicy::Relatime => {
+                self.atime <= self.mtime
+                    || self.atime <= self.ctime
+                    || now
+                        .duration_since(self.atime)
+                        .is_ok_and(|age| age >= RELATIME_MAX_AGE)
+            }
+            AtimePolicy::StrictAtime => true,
+        }
+    }
+
+    /// ```text
     /// File permissions in Unix/Linux systems are represented as a 12-bit structure,
     /// laid out as follows:
     /// ┌───────────────┬─────────┬─────────┬─────────┐
@@ -333,14 +783,54 @@ impl FileAttr {
     /// When Sticky Bit set on a directory, files in that directory may only be unlinked or -
     /// renamed by root or the directory owner or the file owner.
     /// ```
-    pub fn check_perm(&self, uid: u32, gid: u32, access_mode: u8) -> DatenLordResult<()> {
+    pub fn check_perm(&self, ctx: &RequestContext, access_mode: u8) -> DatenLordResult<()> {
         if NEED_CHECK_PERM {
-            self.check_perm_inner(uid, gid, access_mode)
+            self.check_perm_inner(ctx.uid, ctx.gid, access_mode)
         } else {
             Ok(())
         }
     }
 
+    /// Check that the caller may remove or replace `child`, an entry of the
+    /// directory `self`
+    ///
+    /// Once the sticky bit of the directory is set, only root, the owner of
+    /// the directory and the owner of the entry may.
+    pub fn check_sticky(&self, ctx: &RequestContext, child: &FileAttr) -> DatenLordResult<()> {
+        let sticky = u32::from(self.perm) & Mode::S_ISVTX.bits() != 0;
+        if !NEED_CHECK_PERM || !sticky || ctx.is_root() {
+            return Ok(());
+        }
+        if ctx.uid == self.uid || ctx.uid == child.uid {
+            return Ok(());
+        }
+        build_error_result_from_errno(
+            Errno::EPERM,
+            format!(
+                "check_sticky() denied uid={} an entry of uid={} in a directory of uid={}",
+                ctx.uid, child.uid, self.uid
+            ),
+        )
+    }
+
+    /// The permission bits left once the caller wrote to the file or changed
+    /// its owner, `None` when no set-user-ID or set-group-ID bit drops
+    ///
+    /// Like Linux, only regular files lose the bits and root keeps them.
+    /// Set-group-ID without group execute marks mandatory locking rather
+    /// than privileges and stays.
+    pub fn setid_cleared_perm(&self, ctx: &RequestContext) -> Option<u16> {
+        if ctx.is_root() || self.kind != SFlag::S_IFREG {
+            return None;
+        }
+        let mut perm = u32::from(self.perm) & !Mode::S_ISUID.bits();
+        if perm & Mode::S_IXGRP.bits() != 0 {
+            perm &= !Mode::S_ISGID.bits();
+        }
+        let perm: u16 = perm.cast();
+        (perm != self.perm).then_some(perm)
+    }
+
     /// If `NEED_CHECK_PERM` is true, then check permission by ourselves not
     /// rely on kernel.
     #[inline]
@@ -368,6 +858,9 @@ impl FileAttr {
     }
 
     /// For given uid and gid, get the access mode of the file
+    ///
+    /// The set-user-ID, set-group-ID and sticky bits grant nothing here, they
+    /// act on writes, ownership and the entries of directories instead.
     #[allow(clippy::default_numeric_fallback)]
     #[allow(clippy::arithmetic_side_effects)]
     fn get_access_mode(&self, uid: u32, gid: u32) -> u8 {
@@ -398,6 +891,7 @@ impl Default for FileAttr {
             uid: 0,
             gid: 0,
             rdev: 0,
+            generation: 0,
         }
     }
 }
@@ -420,18 +914,15 @@ pub fn parse_mode(mode: u32) -> Mode {
         "helper_parse_mode() found mode={mode} overflow, larger than u16::MAX",
     );
 
-    #[cfg(target_os = "linux")]
-    let file_mode = Mode::from_bits_truncate(mode);
+    // `mode_t` is 16 bits wide on macOS
+    let file_mode = Mode::from_bits_truncate(mode.cast::<nix::libc::mode_t>());
     debug!("parse_mode() read mode={:?}", file_mode);
     file_mode
 }
 
 /// Parse file mode bits
 pub fn parse_mode_bits(mode: u32) -> u16 {
-    #[cfg(target_os = "linux")]
-    let bits = parse_mode(mode).bits().cast();
-
-    bits
+    parse_mode(mode).bits().cast()
 }
 
 /// Convert system time to timestamp in seconds and nano-seconds
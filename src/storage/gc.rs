@@ -0,0 +1,206 @@
+//! Mark-and-sweep collection of the backend data no inode of the tree
+//! refers to any more
+//!
+//! Removing a file drops its data right away, but a crash in between, a
+//! write racing with the removal or a change made behind the SDKs leave data
+//! behind. `collect` marks every inode reachable from the root, the versions
+//! and the trash included, then has the backend sweep the data of the
+//! others. A directory failing to list stops the collection before anything
+//! is swept, since its children would look unreachable.
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{RequestContext, ROOT_ID};
+use super::virtualfs::{INum, VirtualFs};
+
+/// Default age of the data a collection leaves alone, an hour
+const DEFAULT_GRACE_SECS: u64 = 3600;
+
+/// When the SDKs collect garbage in the background, never by default
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GcConfig {
+    /// The time between two collections in seconds, 0 disabling them
+    pub interval_secs: u64,
+    /// The age in seconds below which unreachable data is kept, as it may
+    /// belong to a file being created
+    pub grace_secs: u64,
+    /// Only report the garbage found
+    pub dry_run: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 0,
+            grace_secs: DEFAULT_GRACE_SECS,
+            dry_run: false,
+        }
+    }
+}
+
+/// What a collection found and removed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcReport {
+    /// The inodes reachable from the root
+    pub reachable: u64,
+    /// The unreferenced data found, e.g. `block 12.0`
+    pub orphans: Vec<String>,
+    /// The orphans removed, none on a dry run
+    pub removed: u64,
+    /// The bytes the orphans take
+    pub bytes: u64,
+}
+
+impl GcReport {
+    /// Record the orphan `name` of `bytes` bytes, removed unless `dry_run`
+    pub(crate) fn found(&mut self, name: String, bytes: u64, dry_run: bool) {
+        self.orphans.push(name);
+        self.bytes += bytes;
+        if !dry_run {
+            self.removed += 1;
+        }
+    }
+
+    /// Add what a collection of another store found
+    pub(crate) fn absorb(&mut self, other: Self) {
+        self.orphans.extend(other.orphans);
+        self.removed += other.removed;
+        self.bytes += other.bytes;
+    }
+}
+
+/// A filesystem keeping data by inode number, which it can sweep
+#[async_trait]
+pub trait Sweep: VirtualFs {
+    /// Remove the data of the inodes missing from `reachable` and the data
+    /// of the others past their size, leaving alone what changed within
+    /// `grace`, or only report it with `dry_run`
+    async fn sweep(
+        &self,
+        ctx: &RequestContext,
+        reachable: &HashSet<INum>,
+        grace: Duration,
+        dry_run: bool,
+    ) -> DatenLordResult<GcReport>;
+}
+
+/// Every inode reachable from the root of `fs` on behalf of `ctx`
+pub async fn mark<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+) -> DatenLordResult<HashSet<INum>> {
+    let mut reachable = HashSet::from([ROOT_ID]);
+    let mut dirs = vec![ROOT_ID];
+    while let Some(dir) = dirs.pop() {
+        let fh = fs.opendir(ctx, dir, 0).await?;
+        let mut listed = 0;
+        let result = loop {
+            let offset = i64::try_from(listed).unwrap_or(i64::MAX);
+            match fs.readdirplus(ctx, dir, fh, offset).await {
+                Ok(page) if page.is_empty() => break Ok(()),
+                Ok(page) => {
+                    listed += page.len();
+                    for (_, attr, _) in page {
+                        if reachable.insert(attr.ino) && attr.kind == SFlag::S_IFDIR {
+                            dirs.push(attr.ino);
+                        }
+                    }
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        fs.releasedir(ctx, dir, fh, 0).await?;
+        result?;
+    }
+    Ok(reachable)
+}
+
+/// Mark the inodes reachable in `fs` and sweep the data of the others
+pub async fn collect<S: Sweep + ?Sized>(
+    fs: &S,
+    ctx: &RequestContext,
+    grace: Duration,
+    dry_run: bool,
+) -> DatenLordResult<GcReport> {
+    let reachable = mark(fs, ctx).await?;
+    let mut report = fs.sweep(ctx, &reachable, grace, dry_run).await?;
+    report.reachable = reachable.len() as u64;
+    Ok(report)
+}
+
+/// A background task collecting garbage as a `GcConfig` says, stopped when
+/// dropped
+#[derive(Debug)]
+pub struct GcTask {
+    /// Dropped to stop the task
+    _stop: oneshot::Sender<()>,
+}
+
+impl GcTask {
+    /// Start collecting the garbage of the filesystem `store` finds in `fs`
+    /// on behalf of `ctx`, `None` when `config` has no interval
+    pub fn start<F, S>(
+        fs: Arc<F>,
+        store: fn(&F) -> &S,
+        ctx: RequestContext,
+        config: GcConfig,
+    ) -> DatenLordResult<Option<Self>>
+    where
+        F: Send + Sync + 'static,
+        S: Sweep + ?Sized + 'static,
+    {
+        if config.interval_secs == 0 {
+            return Ok(None);
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!(
+                    "failed to start the garbage collection runtime: {e}"
+                )],
+            })?;
+        let (stop, mut stopped) = oneshot::channel();
+        let interval = Duration::from_secs(config.interval_secs);
+        let grace = Duration::from_secs(config.grace_secs);
+        std::thread::Builder::new()
+            .name("datenlord-gc".to_owned())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    loop {
+                        tokio::select! {
+                            _ = &mut stopped => return,
+                            () = tokio::time::sleep(interval) => {}
+                        }
+                        tokio::select! {
+                            _ = &mut stopped => return,
+                            collected = collect(store(&fs), &ctx, grace, config.dry_run) => match collected {
+                                Ok(report) if report.orphans.is_empty() => {}
+                                Ok(report) => info!(
+                                    "found {} orphans of {} bytes, removed {}",
+                                    report.orphans.len(),
+                                    report.bytes,
+                                    report.removed
+                                ),
+                                Err(e) => warn!("garbage collection failed: {e}"),
+                            },
+                        }
+                    }
+                });
+            })
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the garbage collection thread: {e}")],
+            })?;
+        Ok(Some(Self { _stop: stop }))
+    }
+}
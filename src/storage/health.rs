@@ -0,0 +1,117 @@
+//! A cheap end-to-end probe of a filesystem, for the liveness and readiness
+//! probes of the services embedding the SDKs
+//!
+//! The probe reads the attributes of the root, then writes a few bytes to
+//! `HEALTH_FILE` under the root and reads them back, timing each step. Every
+//! probe writes the same bytes, so probes running at once, in this process
+//! or another one sharing the root, do not fail each other.
+use std::ffi::OsStr;
+use std::time::Instant;
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{CreateParam, RequestContext, ROOT_ID};
+use super::trash::read_file;
+use super::virtualfs::{INum, VirtualFs};
+
+/// The file under the root the probes write and read
+pub const HEALTH_FILE: &str = ".datenlord_health";
+/// What the probes write
+const PAYLOAD: &[u8] = b"datenlord health probe\n";
+/// The mode of `HEALTH_FILE`
+const HEALTH_FILE_MODE: u32 = 0o600;
+
+/// How a probe went, the latencies of the steps not reached are `None`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthReport {
+    /// Whether every step succeeded
+    pub healthy: bool,
+    /// How long reading the attributes of the root took, in microseconds
+    pub metadata_latency_us: Option<u64>,
+    /// How long writing `HEALTH_FILE` took, in microseconds
+    pub write_latency_us: Option<u64>,
+    /// How long reading `HEALTH_FILE` back took, in microseconds
+    pub read_latency_us: Option<u64>,
+    /// The error of the step failing
+    pub error: Option<String>,
+}
+
+/// Microseconds since `start`
+fn micros_since(start: Instant) -> u64 {
+    u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX)
+}
+
+/// Probe `fs` on behalf of `ctx`, returning the report and the error of
+/// the step failing
+pub async fn probe<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+) -> (HealthReport, Option<DatenLordError>) {
+    let mut report = HealthReport::default();
+    let result = run(fs, ctx, &mut report).await;
+    report.healthy = result.is_ok();
+    let error = result.err();
+    report.error = error.as_ref().map(ToString::to_string);
+    (report, error)
+}
+
+/// Run the steps of a probe, filling in their latencies
+async fn run<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    report: &mut HealthReport,
+) -> DatenLordResult<()> {
+    let start = Instant::now();
+    fs.getattr(ctx, ROOT_ID).await?;
+    report.metadata_latency_us = Some(micros_since(start));
+
+    let start = Instant::now();
+    let ino = write_payload(fs, ctx).await?;
+    report.write_latency_us = Some(micros_since(start));
+
+    let start = Instant::now();
+    let content = read_file(fs, ctx, ino, PAYLOAD.len() as u64).await?;
+    report.read_latency_us = Some(micros_since(start));
+    if content != PAYLOAD {
+        return Err(DatenLordError::Io {
+            context: vec![format!(
+                "read back {} bytes of {HEALTH_FILE} differing from the {} written",
+                content.len(),
+                PAYLOAD.len()
+            )],
+            source: None,
+        });
+    }
+    Ok(())
+}
+
+/// Write `PAYLOAD` at the start of `HEALTH_FILE`, creating it if missing,
+/// returning its inode
+async fn write_payload<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+) -> DatenLordResult<INum> {
+    let ino = match fs.lookup(ctx, ROOT_ID, OsStr::new(HEALTH_FILE)).await {
+        Ok((_, attr, _)) => attr.ino,
+        Err(_) => {
+            let param = CreateParam {
+                parent: ROOT_ID,
+                name: HEALTH_FILE.into(),
+                mode: HEALTH_FILE_MODE,
+                rdev: 0,
+                node_type: SFlag::S_IFREG,
+                link: None,
+            };
+            fs.mknod(ctx, param).await?.1.ino
+        }
+    };
+    let flags = OFlag::O_WRONLY.bits() as u32;
+    let fh = fs.open(ctx, ino, flags).await?;
+    let written = fs.write(ctx, ino, fh, 0, PAYLOAD, flags).await;
+    fs.release(ctx, ino, fh, flags, 0, true).await?;
+    written.map(|()| ino)
+}
@@ -0,0 +1,113 @@
+//! Translation of the ids of callers before they reach the backend, so
+//! containers with different uid namespaces share a namespace
+//!
+//! The ids of the caller are squashed first, then translated by the first
+//! range holding them; ids no range holds are kept as they are.
+use serde_derive::{Deserialize, Serialize};
+
+use super::fs_util::RequestContext;
+
+/// The user and group ids squashed callers act as by default, `nobody`
+const DEFAULT_ANON_ID: u32 = 65534;
+
+/// Which callers act as the anonymous user, like the `root_squash` options
+/// of `exports(5)`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Squash {
+    /// Every caller acts as itself
+    None,
+    /// The superuser
+    #[default]
+    Root,
+    /// Every caller
+    All,
+}
+
+/// `count` consecutive ids from `from` on, translated to as many from `to`
+/// on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdRange {
+    /// The first id of the caller translated
+    pub from: u32,
+    /// The id `from` is translated to
+    pub to: u32,
+    /// The number of ids translated, 1 when unset
+    #[serde(default = "one")]
+    pub count: u32,
+}
+
+/// The `count` of ranges that leave it unset
+fn one() -> u32 {
+    1
+}
+
+impl IdRange {
+    /// The id `id` is translated to, `None` if the range does not hold it
+    #[must_use]
+    pub fn translate(&self, id: u32) -> Option<u32> {
+        let offset = id.checked_sub(self.from)?;
+        (offset < self.count).then(|| self.to.wrapping_add(offset))
+    }
+}
+
+/// How the ids of the callers of an SDK handle are squashed and translated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdMapConfig {
+    /// The translated ranges of user ids
+    pub uids: Vec<IdRange>,
+    /// The translated ranges of group ids
+    pub gids: Vec<IdRange>,
+    /// Which callers act as `anon_uid` and `anon_gid`, none by default
+    pub squash: Squash,
+    /// The user id squashed callers act as
+    pub anon_uid: u32,
+    /// The group id squashed callers act as
+    pub anon_gid: u32,
+}
+
+impl Default for IdMapConfig {
+    fn default() -> Self {
+        Self {
+            uids: Vec::new(),
+            gids: Vec::new(),
+            squash: Squash::None,
+            anon_uid: DEFAULT_ANON_ID,
+            anon_gid: DEFAULT_ANON_ID,
+        }
+    }
+}
+
+impl IdMapConfig {
+    /// `ctx` with its ids squashed and translated, the context the backend
+    /// checks permissions against and assigns ownership from
+    #[must_use]
+    pub fn map(&self, ctx: RequestContext) -> RequestContext {
+        let squashed = match self.squash {
+            Squash::None => false,
+            Squash::Root => ctx.is_root(),
+            Squash::All => true,
+        };
+        if squashed {
+            return RequestContext {
+                uid: self.anon_uid,
+                gid: self.anon_gid,
+                ..ctx
+            };
+        }
+        RequestContext {
+            uid: translate(&self.uids, ctx.uid),
+            gid: translate(&self.gids, ctx.gid),
+            ..ctx
+        }
+    }
+}
+
+/// `id` translated by the first of `ranges` holding it, unchanged if none
+fn translate(ranges: &[IdRange], id: u32) -> u32 {
+    ranges
+        .iter()
+        .find_map(|range| range.translate(id))
+        .unwrap_or(id)
+}
@@ -0,0 +1,874 @@
+//! The persistent inode table of `LocalFS`, mapping the inode numbers it
+//! allocates to the paths, relative to the root, of their links
+//!
+//! Inode numbers are never reused, so an inode number kept by a client
+//! across a restart either still names the same file or fails to resolve.
+//! The table is a log of changes in the root, hidden from listings like the
+//! superblock, rewritten once most of its records are obsolete. Every
+//! instance sharing a root takes a lock on the log before changing it and
+//! first applies the changes the others appended, so they agree on the
+//! numbers. Each entry also records the local inode of the file, telling a
+//! path replaced behind the table's back from the file it was registered as.
+//!
+//! Reading a long log makes opening a large namespace slow, so the table is
+//! also saved as a snapshot of its entries on `save_snapshot`, tagged with
+//! the position in the log it was taken at. Opening loads the snapshot, if
+//! the log still holds that position, and the records appended since, in
+//! the background; the first call needing the table waits for it. Once
+//! loaded, the links are checked against the root in the background, those
+//! whose path no longer holds their file being dropped.
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+use std::thread;
+
+use rustix::fs::{flock, FlockOperation};
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::ROOT_ID;
+use super::superblock::SUPERBLOCK_NAME;
+use super::virtualfs::INum;
+
+/// The size of the length and CRC-32 before every record
+const HEADER_SIZE: usize = 8;
+/// The inode numbers reserved at once, the log being synced for each
+/// reservation so numbers handed out before a crash are not handed out again
+const RESERVE_BLOCK: INum = 1024;
+/// The records the log holds at least before it is rewritten
+const COMPACT_MIN_RECORDS: usize = 4096;
+/// The first bytes of a snapshot, ending with its format version
+const SNAPSHOT_MAGIC: &[u8; 8] = b"DLINOS01";
+/// The size of the magic, the position in the log and the allocator state
+/// heading a snapshot, before the CRC-32 of the latter
+const SNAPSHOT_HEADER_SIZE: usize = 8 + 8 + 8 + 4 + 8 + 8 + 8;
+/// The links checked against the root at once by the revalidation
+const REVALIDATE_BATCH: usize = 1024;
+
+/// A change to the table
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Record {
+    /// Inode numbers below this one may have been handed out
+    Reserve(INum),
+    /// `path` is a link of inode `ino`, the local inode `local`
+    Add {
+        ino: INum,
+        local: u64,
+        path: PathBuf,
+    },
+    /// `path` is no longer a link, its inode being dropped once it has no
+    /// link left unless `keep`
+    Remove { path: PathBuf, keep: bool },
+    /// The entry at `from` moved to `to`, and the one at `to` to `from` if
+    /// `exchange`, the one at `to` being replaced otherwise
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        exchange: bool,
+    },
+}
+
+/// Append `path` to `buf`, preceded by its length
+fn put_path(buf: &mut Vec<u8>, path: &Path) {
+    let bytes = path.as_os_str().as_bytes();
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Reads the fields of a record
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn path(&mut self) -> Option<PathBuf> {
+        let len = u32::from_le_bytes(self.take(4)?.try_into().ok()?) as usize;
+        Some(PathBuf::from(OsStr::from_bytes(self.take(len)?)))
+    }
+}
+
+impl Record {
+    /// The record framed by its length and CRC-32
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match *self {
+            Self::Reserve(next) => {
+                data.push(0);
+                data.extend_from_slice(&next.to_le_bytes());
+            }
+            Self::Add {
+                ino,
+                local,
+                ref path,
+            } => {
+                data.push(1);
+                data.extend_from_slice(&ino.to_le_bytes());
+                data.extend_from_slice(&local.to_le_bytes());
+                put_path(&mut data, path);
+            }
+            Self::Remove { ref path, keep } => {
+                data.push(2);
+                data.push(u8::from(keep));
+                put_path(&mut data, path);
+            }
+            Self::Rename {
+                ref from,
+                ref to,
+                exchange,
+            } => {
+                data.push(3);
+                data.push(u8::from(exchange));
+                put_path(&mut data, from);
+                put_path(&mut data, to);
+            }
+        }
+        let mut frame = Vec::with_capacity(HEADER_SIZE + data.len());
+        frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+        frame.extend_from_slice(&data);
+        frame
+    }
+
+    /// The record framed at the start of `buf` with the size of its frame,
+    /// `None` unless a whole valid frame starts there
+    fn decode(buf: &[u8]) -> Option<(Self, usize)> {
+        let mut header = Fields(buf.get(..HEADER_SIZE)?);
+        let len = u32::from_le_bytes(header.take(4)?.try_into().ok()?) as usize;
+        let crc = u32::from_le_bytes(header.take(4)?.try_into().ok()?);
+        let data = buf.get(HEADER_SIZE..HEADER_SIZE.checked_add(len)?)?;
+        if crc32fast::hash(data) != crc {
+            return None;
+        }
+        let mut fields = Fields(data);
+        let record = match fields.u8()? {
+            0 => Self::Reserve(fields.u64()?),
+            1 => Self::Add {
+                ino: fields.u64()?,
+                local: fields.u64()?,
+                path: fields.path()?,
+            },
+            2 => Self::Remove {
+                keep: fields.u8()? != 0,
+                path: fields.path()?,
+            },
+            3 => Self::Rename {
+                exchange: fields.u8()? != 0,
+                from: fields.path()?,
+                to: fields.path()?,
+            },
+            _ => return None,
+        };
+        Some((record, HEADER_SIZE + len))
+    }
+}
+
+/// The length and CRC-32 of the record framed at the start of `frame`
+fn frame_header(frame: &[u8]) -> (u32, u32) {
+    let mut header = Fields(frame);
+    (header.u32().unwrap_or_default(), header.u32().unwrap_or_default())
+}
+
+/// An inode of the table
+#[derive(Debug)]
+struct Inode {
+    /// The local inode of the file
+    local: u64,
+    /// The paths of its links
+    paths: BTreeSet<PathBuf>,
+}
+
+/// The path of the entry at `relative` below `base`
+fn rebase(base: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        base.to_owned()
+    } else {
+        base.join(relative)
+    }
+}
+
+/// The table in memory and the log it was read from
+#[derive(Debug)]
+struct State {
+    inodes: HashMap<INum, Inode>,
+    by_path: HashMap<PathBuf, INum>,
+    by_local: HashMap<u64, INum>,
+    /// The next inode number to allocate
+    next: INum,
+    /// The inode numbers reserved by the log
+    reserved: INum,
+    /// The log, opened for appending
+    log: File,
+    /// The local inode of the log, changed when another instance rewrote it
+    log_ino: u64,
+    /// The end of the records read or written
+    offset: u64,
+    /// The records in the log
+    records: usize,
+    /// Where the last record read or written starts, and its CRC-32, which
+    /// tell the log a snapshot was taken from
+    last: (u64, u32),
+    /// The position in the log of the last snapshot saved
+    saved: Option<(u64, u64)>,
+}
+
+impl State {
+    /// Apply `record` to the table
+    fn apply(&mut self, record: &Record) {
+        match *record {
+            Record::Reserve(next) => self.reserved = self.reserved.max(next),
+            Record::Add {
+                ino,
+                local,
+                ref path,
+            } => {
+                if self.by_path.contains_key(path) {
+                    self.remove(path, false);
+                }
+                let inode = self.inodes.entry(ino).or_insert_with(|| Inode {
+                    local,
+                    paths: BTreeSet::new(),
+                });
+                inode.paths.insert(path.clone());
+                self.by_path.insert(path.clone(), ino);
+                self.by_local.insert(local, ino);
+                self.next = self.next.max(ino + 1);
+            }
+            Record::Remove { ref path, keep } => self.remove(path, keep),
+            Record::Rename {
+                ref from,
+                ref to,
+                exchange,
+            } => self.rename(from, to, exchange),
+        }
+    }
+
+    /// Drop the link at `path`, and its inode once it has none left unless
+    /// `keep`
+    fn remove(&mut self, path: &Path, keep: bool) {
+        let Some(ino) = self.by_path.remove(path) else {
+            return;
+        };
+        let Some(inode) = self.inodes.get_mut(&ino) else {
+            return;
+        };
+        inode.paths.remove(path);
+        if inode.paths.is_empty() && !keep {
+            let local = inode.local;
+            self.inodes.remove(&ino);
+            if self.by_local.get(&local) == Some(&ino) {
+                self.by_local.remove(&local);
+            }
+        }
+    }
+
+    /// Move the links under `from` to `to`, and those under `to` to `from`
+    /// if `exchange`, the links under `to` being dropped otherwise
+    fn rename(&mut self, from: &Path, to: &Path, exchange: bool) {
+        if !exchange {
+            let replaced: Vec<_> = self
+                .by_path
+                .keys()
+                .filter(|path| path.starts_with(to))
+                .cloned()
+                .collect();
+            for path in replaced {
+                self.remove(&path, false);
+            }
+        }
+        let mut moved = Vec::new();
+        for (path, &ino) in &self.by_path {
+            if let Ok(relative) = path.strip_prefix(from) {
+                moved.push((path.clone(), rebase(to, relative), ino));
+            } else if exchange {
+                if let Ok(relative) = path.strip_prefix(to) {
+                    moved.push((path.clone(), rebase(from, relative), ino));
+                }
+            }
+        }
+        for (old, _, ino) in &moved {
+            self.by_path.remove(old);
+            if let Some(inode) = self.inodes.get_mut(ino) {
+                inode.paths.remove(old);
+            }
+        }
+        for (_, new, ino) in moved {
+            if let Some(inode) = self.inodes.get_mut(&ino) {
+                inode.paths.insert(new.clone());
+            }
+            self.by_path.insert(new, ino);
+        }
+    }
+
+    /// The inode of the link at `path` if it is still the local inode
+    /// `local`
+    fn find(&self, path: &Path, local: u64) -> Option<INum> {
+        let ino = *self.by_path.get(path)?;
+        (self.inodes.get(&ino)?.local == local).then_some(ino)
+    }
+}
+
+/// The inode table of the namespace under a root
+#[derive(Debug)]
+pub(crate) struct InodeTable {
+    /// The root of the namespace
+    root: PathBuf,
+    /// The path of the log
+    path: PathBuf,
+    /// The path of the snapshot
+    snapshot: PathBuf,
+    /// The root directory, locked by the instance changing the log
+    lock: File,
+    /// The table once loaded, or why it failed to
+    state: OnceLock<Result<Mutex<State>, String>>,
+}
+
+/// Map an `io::Error` on the table at `path` to `DatenLordError::Io`
+fn table_error(path: &Path) -> impl FnOnce(std::io::Error) -> DatenLordError + '_ {
+    move |e| DatenLordError::Io {
+        context: vec![format!("failed to access the inode table {path:?}: {e}")],
+        source: None,
+    }
+}
+
+/// Open the log at `path` for appending
+fn open_log(path: &Path) -> DatenLordResult<(File, u64)> {
+    let log = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(table_error(path))?;
+    let log_ino = log.metadata().map_err(table_error(path))?.ino();
+    Ok((log, log_ino))
+}
+
+/// Holds the lock on the log until dropped
+struct Locked<'a>(&'a File);
+
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        let _ = flock(self.0, FlockOperation::Unlock);
+    }
+}
+
+impl InodeTable {
+    /// Open the table of the namespace rooted at `root`, creating it when
+    /// missing, and load it in the background
+    pub(crate) fn open(root: &Path) -> DatenLordResult<Arc<Self>> {
+        let path = root.join(format!("{SUPERBLOCK_NAME}.inodes"));
+        // The log is replaced when rewritten, so the root is locked instead
+        let lock = File::open(root).map_err(table_error(root))?;
+        open_log(&path)?;
+        let table = Arc::new(Self {
+            root: root.to_owned(),
+            snapshot: path.with_extension("inodes.snapshot"),
+            path,
+            lock,
+            state: OnceLock::new(),
+        });
+        let weak = Arc::downgrade(&table);
+        let loader = thread::Builder::new()
+            .name("datenlord-inodes".to_owned())
+            .spawn(move || Self::load_and_revalidate(&weak));
+        if let Err(e) = loader {
+            warn!("failed to load the inode table in the background: {e}");
+        }
+        Ok(table)
+    }
+
+    /// The table, loaded first unless it is already
+    fn state(&self) -> DatenLordResult<MutexGuard<'_, State>> {
+        let loaded = self
+            .state
+            .get_or_init(|| self.load().map(Mutex::new).map_err(|e| e.to_string()));
+        match *loaded {
+            Ok(ref state) => Ok(state.lock().unwrap()),
+            Err(ref e) => Err(DatenLordError::Io {
+                context: vec![format!("failed to load the inode table {:?}: {e}", self.path)],
+                source: None,
+            }),
+        }
+    }
+
+    /// Read the table from the snapshot, when it still matches the log, and
+    /// from the records of the log after it
+    fn load(&self) -> DatenLordResult<State> {
+        let (log, log_ino) = open_log(&self.path)?;
+        let mut state = State {
+            inodes: HashMap::new(),
+            by_path: HashMap::new(),
+            by_local: HashMap::new(),
+            next: ROOT_ID + 1,
+            reserved: 0,
+            log,
+            log_ino,
+            offset: 0,
+            records: 0,
+            last: (0, 0),
+            saved: None,
+        };
+        let _locked = self.lock_log()?;
+        if let Err(e) = self.read_snapshot(&mut state) {
+            warn!("ignoring the snapshot {:?} of the inode table: {e}", self.snapshot);
+        }
+        self.catch_up(&mut state)?;
+        // Numbers reserved before a crash may have been handed out
+        state.next = state.next.max(state.reserved);
+        Ok(state)
+    }
+
+    /// Load the snapshot into the empty `state` if it was taken from the
+    /// log `state` has open, leaving `state` empty otherwise
+    ///
+    /// The lock on the log must be held.
+    fn read_snapshot(&self, state: &mut State) -> std::io::Result<()> {
+        let invalid = |what: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, what);
+        let data = match fs::read(&self.snapshot) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let header = data
+            .get(..SNAPSHOT_HEADER_SIZE + 4)
+            .ok_or_else(|| invalid("truncated header"))?;
+        let (fields, crc) = header.split_at(SNAPSHOT_HEADER_SIZE);
+        let (magic, fields) = fields.split_at(SNAPSHOT_MAGIC.len());
+        if magic != SNAPSHOT_MAGIC {
+            return Err(invalid("unknown format"));
+        }
+        if crc32fast::hash(fields).to_le_bytes() != crc {
+            return Err(invalid("corrupt header"));
+        }
+        let mut fields = Fields(fields);
+        let mut parse = || {
+            let position = (fields.u64()?, fields.u64()?, fields.u64()?, fields.u32()?);
+            Some((position, fields.u64()?, fields.u64()?, fields.u64()?))
+        };
+        let ((log_ino, offset, last_start, last_crc), reserved, next, records) =
+            parse().ok_or_else(|| invalid("truncated header"))?;
+
+        // The log rewritten since, possibly reusing the local inode, no
+        // longer holds the last record the snapshot saw where it saw it
+        if log_ino != state.log_ino || state.log.metadata()?.len() < offset {
+            return Ok(());
+        }
+        if offset > 0 {
+            let mut frame = [0; HEADER_SIZE];
+            state.log.read_exact_at(&mut frame, last_start)?;
+            let (len, crc) = frame_header(&frame);
+            if last_start + HEADER_SIZE as u64 + u64::from(len) != offset || crc != last_crc {
+                return Ok(());
+            }
+        }
+
+        let mut entries = Vec::new();
+        let mut parsed = SNAPSHOT_HEADER_SIZE + 4;
+        while parsed < data.len() {
+            let (record, size) =
+                Record::decode(&data[parsed..]).ok_or_else(|| invalid("corrupt entry"))?;
+            if !matches!(record, Record::Add { .. }) {
+                return Err(invalid("unexpected record"));
+            }
+            entries.push(record);
+            parsed += size;
+        }
+        for record in &entries {
+            state.apply(record);
+        }
+        state.reserved = reserved;
+        state.next = state.next.max(next);
+        state.offset = offset;
+        state.records = usize::try_from(records).unwrap_or(usize::MAX);
+        state.last = (last_start, last_crc);
+        state.saved = Some((log_ino, offset));
+        Ok(())
+    }
+
+    /// Save the table as a snapshot, unless the last one saved is up to date
+    pub(crate) fn save_snapshot(&self) -> DatenLordResult<()> {
+        let mut state = self.state()?;
+        let _locked = self.lock_log()?;
+        self.catch_up(&mut state)?;
+        if state.saved == Some((state.log_ino, state.offset)) {
+            return Ok(());
+        }
+        let mut fields = Vec::with_capacity(SNAPSHOT_HEADER_SIZE - 8);
+        fields.extend_from_slice(&state.log_ino.to_le_bytes());
+        fields.extend_from_slice(&state.offset.to_le_bytes());
+        fields.extend_from_slice(&state.last.0.to_le_bytes());
+        fields.extend_from_slice(&state.last.1.to_le_bytes());
+        fields.extend_from_slice(&state.reserved.to_le_bytes());
+        fields.extend_from_slice(&state.next.to_le_bytes());
+        fields.extend_from_slice(&(state.records as u64).to_le_bytes());
+        let mut data = SNAPSHOT_MAGIC.to_vec();
+        data.extend_from_slice(&fields);
+        data.extend_from_slice(&crc32fast::hash(&fields).to_le_bytes());
+        for (&ino, inode) in &state.inodes {
+            for path in &inode.paths {
+                let record = Record::Add {
+                    ino,
+                    local: inode.local,
+                    path: path.clone(),
+                };
+                data.extend_from_slice(&record.encode());
+            }
+        }
+        let tmp = self.snapshot.with_extension("snapshot.tmp");
+        let mut file = File::create(&tmp).map_err(table_error(&tmp))?;
+        file.write_all(&data).map_err(table_error(&tmp))?;
+        file.sync_all().map_err(table_error(&tmp))?;
+        fs::rename(&tmp, &self.snapshot).map_err(table_error(&self.snapshot))?;
+        state.saved = Some((state.log_ino, state.offset));
+        Ok(())
+    }
+
+    /// Load the table of `table`, then drop the links whose path no longer
+    /// holds the file they were registered as, a batch at a time, stopping
+    /// once the table is dropped
+    fn load_and_revalidate(table: &Weak<Self>) {
+        let links: Vec<(INum, u64, PathBuf)> = {
+            let Some(table) = table.upgrade() else {
+                return;
+            };
+            let Ok(state) = table.state() else {
+                return;
+            };
+            state
+                .inodes
+                .iter()
+                .flat_map(|(&ino, inode)| {
+                    inode.paths.iter().map(move |path| (ino, inode.local, path.clone()))
+                })
+                .collect()
+        };
+        for batch in links.chunks(REVALIDATE_BATCH) {
+            let Some(table) = table.upgrade() else {
+                return;
+            };
+            let stale: Vec<_> = batch
+                .iter()
+                .filter(|&&(_, local, ref path)| table.is_stale(path, local))
+                .collect();
+            if stale.is_empty() {
+                continue;
+            }
+            let dropped = table.change(|table, state, records| {
+                for &&(ino, local, ref path) in &stale {
+                    // Changed meanwhile, e.g. renamed by this instance
+                    if state.find(path, local) != Some(ino) || !table.is_stale(path, local) {
+                        continue;
+                    }
+                    let path = path.clone();
+                    Self::log(state, records, Record::Remove { path, keep: false });
+                }
+            });
+            if let Err(e) = dropped {
+                warn!("failed to drop the stale links of the inode table: {e}");
+                return;
+            }
+        }
+    }
+
+    /// Whether the link at `path`, relative to the root, no longer holds
+    /// the local inode `local`, errors other than a missing path keeping it
+    fn is_stale(&self, path: &Path, local: u64) -> bool {
+        match fs::symlink_metadata(self.root.join(path)) {
+            Ok(metadata) => metadata.ino() != local,
+            Err(e) => e.kind() == std::io::ErrorKind::NotFound,
+        }
+    }
+
+    /// Take the lock on the log, released when the guard is dropped
+    fn lock_log(&self) -> DatenLordResult<Locked<'_>> {
+        flock(&self.lock, FlockOperation::LockExclusive).map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to lock the inode table {:?}: {e}", self.path)],
+            source: None,
+        })?;
+        Ok(Locked(&self.lock))
+    }
+
+    /// Apply the records other instances appended to the log, reading it
+    /// again from the start if one of them rewrote it, and cut off a
+    /// partly written record at its end
+    ///
+    /// The lock on the log must be held.
+    fn catch_up(&self, state: &mut State) -> DatenLordResult<()> {
+        let current = fs::metadata(&self.path).map_err(table_error(&self.path))?;
+        if current.ino() != state.log_ino {
+            let (log, log_ino) = open_log(&self.path)?;
+            state.inodes.clear();
+            state.by_path.clear();
+            state.by_local.clear();
+            state.log = log;
+            state.log_ino = log_ino;
+            state.offset = 0;
+            state.records = 0;
+            state.last = (0, 0);
+        }
+        let len = state.log.metadata().map_err(table_error(&self.path))?.len();
+        if len <= state.offset {
+            return Ok(());
+        }
+        let mut buf = vec![0; (len - state.offset) as usize];
+        state
+            .log
+            .read_exact_at(&mut buf, state.offset)
+            .map_err(table_error(&self.path))?;
+        let mut parsed = 0;
+        while let Some((record, size)) = Record::decode(&buf[parsed..]) {
+            state.apply(&record);
+            state.records += 1;
+            state.last = (state.offset + parsed as u64, frame_header(&buf[parsed..]).1);
+            parsed += size;
+        }
+        state.offset += parsed as u64;
+        if parsed < buf.len() {
+            warn!(
+                "cutting off {} bytes of a partly written record at the end of {:?}",
+                buf.len() - parsed,
+                self.path
+            );
+            state
+                .log
+                .set_len(state.offset)
+                .map_err(table_error(&self.path))?;
+        }
+        Ok(())
+    }
+
+    /// Run `change` on the table brought up to date with the log, then
+    /// append the records it applied
+    fn change<T>(
+        &self,
+        change: impl FnOnce(&Self, &mut State, &mut Vec<Record>) -> T,
+    ) -> DatenLordResult<T> {
+        let mut state = self.state()?;
+        let _locked = self.lock_log()?;
+        self.catch_up(&mut state)?;
+        let mut records = Vec::new();
+        let result = change(self, &mut state, &mut records);
+        if records.is_empty() {
+            return Ok(result);
+        }
+        let reserved = records
+            .iter()
+            .any(|record| matches!(*record, Record::Reserve(_)));
+        let mut data = Vec::new();
+        let mut last_start = 0;
+        for record in &records {
+            last_start = data.len();
+            data.extend_from_slice(&record.encode());
+        }
+        state
+            .log
+            .write_all(&data)
+            .map_err(table_error(&self.path))?;
+        if reserved {
+            state.log.sync_data().map_err(table_error(&self.path))?;
+        }
+        state.last = (state.offset + last_start as u64, frame_header(&data[last_start..]).1);
+        state.offset += data.len() as u64;
+        state.records += records.len();
+        if state.records >= COMPACT_MIN_RECORDS && state.records > 4 * state.by_path.len() {
+            if let Err(e) = self.compact(&mut state) {
+                warn!("failed to rewrite the inode table: {e}");
+            }
+        }
+        Ok(result)
+    }
+
+    /// Rewrite the log with the records of the table as it is
+    ///
+    /// The lock on the log must be held.
+    fn compact(&self, state: &mut State) -> DatenLordResult<()> {
+        let tmp = self.path.with_extension("inodes.tmp");
+        let mut data = Record::Reserve(state.reserved.max(state.next)).encode();
+        let mut records = 1;
+        let mut last_start = 0;
+        for (&ino, inode) in &state.inodes {
+            for path in &inode.paths {
+                let record = Record::Add {
+                    ino,
+                    local: inode.local,
+                    path: path.clone(),
+                };
+                last_start = data.len();
+                data.extend_from_slice(&record.encode());
+                records += 1;
+            }
+        }
+        let mut file = File::create(&tmp).map_err(table_error(&tmp))?;
+        file.write_all(&data).map_err(table_error(&tmp))?;
+        file.sync_all().map_err(table_error(&tmp))?;
+        fs::rename(&tmp, &self.path).map_err(table_error(&self.path))?;
+        let (log, log_ino) = open_log(&self.path)?;
+        state.log = log;
+        state.log_ino = log_ino;
+        state.offset = data.len() as u64;
+        state.records = records;
+        state.last = (last_start as u64, frame_header(&data[last_start..]).1);
+        Ok(())
+    }
+
+    /// Apply `record` to `state` and queue it for the log
+    fn log(state: &mut State, records: &mut Vec<Record>, record: Record) {
+        state.apply(&record);
+        records.push(record);
+    }
+
+    /// The inode number of the link at `path`, relative to the root, of the
+    /// local inode `local`, allocating one unless the path or another link
+    /// of the same local inode already has one
+    pub(crate) fn register(&self, path: &Path, local: u64) -> DatenLordResult<INum> {
+        if path.as_os_str().is_empty() {
+            return Ok(ROOT_ID);
+        }
+        if let Some(ino) = self.state()?.find(path, local) {
+            return Ok(ino);
+        }
+        self.change(|table, state, records| table.register_locked(state, records, path, local))
+    }
+
+    /// Like `register` for every `(path, local)` of `entries`, taking the
+    /// lock on the log once
+    pub(crate) fn register_all(&self, entries: &[(PathBuf, u64)]) -> DatenLordResult<Vec<INum>> {
+        {
+            let state = self.state()?;
+            let found: Option<Vec<_>> = entries
+                .iter()
+                .map(|(path, local)| state.find(path, *local))
+                .collect();
+            if let Some(found) = found {
+                return Ok(found);
+            }
+        }
+        self.change(|table, state, records| {
+            entries
+                .iter()
+                .map(|(path, local)| table.register_locked(state, records, path, *local))
+                .collect()
+        })
+    }
+
+    fn register_locked(
+        &self,
+        state: &mut State,
+        records: &mut Vec<Record>,
+        path: &Path,
+        local: u64,
+    ) -> INum {
+        if let Some(ino) = state.find(path, local) {
+            return ino;
+        }
+        // Another link of the same file has an inode number, unless the
+        // local inode was reused by another file since
+        if let Some(&ino) = state.by_local.get(&local) {
+            let linked = state.inodes.get(&ino).is_some_and(|inode| {
+                inode.paths.iter().any(|other| {
+                    fs::symlink_metadata(self.root.join(other))
+                        .is_ok_and(|metadata| metadata.ino() == local)
+                })
+            });
+            if linked {
+                let path = path.to_owned();
+                Self::log(state, records, Record::Add { ino, local, path });
+                return ino;
+            }
+            let stale: Vec<_> = state
+                .inodes
+                .get(&ino)
+                .map(|inode| inode.paths.iter().cloned().collect())
+                .unwrap_or_default();
+            for path in stale {
+                Self::log(state, records, Record::Remove { path, keep: false });
+            }
+            state.by_local.remove(&local);
+        }
+        let ino = state.next;
+        state.next += 1;
+        if state.next > state.reserved {
+            let reserve = state.next.saturating_add(RESERVE_BLOCK);
+            Self::log(state, records, Record::Reserve(reserve));
+        }
+        let path = path.to_owned();
+        Self::log(state, records, Record::Add { ino, local, path });
+        ino
+    }
+
+    /// The path, relative to the root, of a link of inode `ino`
+    pub(crate) fn path(&self, ino: INum) -> DatenLordResult<PathBuf> {
+        if ino == ROOT_ID {
+            return Ok(PathBuf::new());
+        }
+        let first_path = |state: &MutexGuard<'_, State>| {
+            state
+                .inodes
+                .get(&ino)
+                .and_then(|inode| inode.paths.first().cloned())
+        };
+        let mut state = self.state()?;
+        if let Some(path) = first_path(&state) {
+            return Ok(path);
+        }
+        // Another instance may have allocated it
+        {
+            let _locked = self.lock_log()?;
+            self.catch_up(&mut state)?;
+        }
+        first_path(&state).ok_or_else(|| DatenLordError::InvalidArgument {
+            context: vec![format!("unknown inode={ino}")],
+        })
+    }
+
+    /// Drop the link at `path`, relative to the root, and its inode once it
+    /// has no link left unless `keep`, returning its inode number
+    pub(crate) fn remove(&self, path: &Path, keep: bool) -> DatenLordResult<Option<INum>> {
+        self.change(|_, state, records| {
+            let ino = state.by_path.get(path).copied();
+            if ino.is_some() {
+                let path = path.to_owned();
+                Self::log(state, records, Record::Remove { path, keep });
+            }
+            ino
+        })
+    }
+
+    /// Move the links under `from` to `to`, both relative to the root, and
+    /// those under `to` to `from` if `exchange`, the links under `to` being
+    /// dropped otherwise
+    pub(crate) fn rename(&self, from: &Path, to: &Path, exchange: bool) -> DatenLordResult<()> {
+        self.change(|_, state, records| {
+            let record = Record::Rename {
+                from: from.to_owned(),
+                to: to.to_owned(),
+                exchange,
+            };
+            Self::log(state, records, record);
+        })
+    }
+}
@@ -0,0 +1,445 @@
+//! Middleware letting running operations be interrupted by id
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::oneshot;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam, RenameParam,
+    RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// The running interruptible operations, keyed by id
+type OpTable = Arc<Mutex<HashMap<u64, oneshot::Sender<()>>>>;
+
+/// A `VirtualFs` keeping a table of the running operations, so they can be
+/// interrupted by id like FUSE `INTERRUPT` requests do
+///
+/// An operation wrapped by `interruptible` and then interrupted, through
+/// `cancel` or `VirtualFs::interrupt`, is dropped at its next await point and
+/// returns `DatenLordError::Interrupted`. Interrupting an unknown or finished
+/// operation does nothing.
+#[derive(Debug)]
+pub struct InterruptFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// The registered operations
+    ops: OpTable,
+}
+
+/// Removes an operation from the table once it finished or was dropped
+struct Registration {
+    /// The table the operation is registered in
+    ops: OpTable,
+    /// The id of the operation
+    op_id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.ops.lock().unwrap().remove(&self.op_id);
+    }
+}
+
+impl<F: VirtualFs> InterruptFs<F> {
+    /// Wrap `inner`
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner,
+            ops: Arc::default(),
+        }
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Register `fut` as operation `op_id`, returning it made interruptible
+    ///
+    /// The operation is registered right away, so it can be interrupted
+    /// before it is first polled. Fails if `op_id` is already running.
+    pub fn interruptible<T>(
+        &self,
+        op_id: u64,
+        fut: impl Future<Output = DatenLordResult<T>>,
+    ) -> DatenLordResult<impl Future<Output = DatenLordResult<T>>> {
+        let (interrupt, interrupted) = oneshot::channel();
+        match self.ops.lock().unwrap().entry(op_id) {
+            Entry::Occupied(_) => {
+                return Err(DatenLordError::InvalidArgument {
+                    context: vec![format!("operation {op_id} is already running")],
+                })
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(interrupt);
+            }
+        }
+        let registration = Registration {
+            ops: Arc::clone(&self.ops),
+            op_id,
+        };
+        Ok(async move {
+            let _registration = registration;
+            tokio::select! {
+                biased;
+                _ = interrupted => Err(DatenLordError::Interrupted {
+                    context: vec![format!("operation {op_id} was interrupted")],
+                }),
+                result = fut => result,
+            }
+        })
+    }
+
+    /// Interrupt operation `op_id`, returning whether it was running
+    pub fn cancel(&self, op_id: u64) -> bool {
+        let interrupt = self.ops.lock().unwrap().remove(&op_id);
+        interrupt.is_some_and(|interrupt| interrupt.send(()).is_ok())
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for InterruptFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        if !self.cancel(unique) {
+            self.inner.interrupt(unique).await;
+        }
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.lookup(ctx, parent, name).await
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.inner.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.setattr(ctx, ino, param).await
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mknod(ctx, param).await
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mkdir(ctx, param).await
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.unlink(ctx, parent, name).await
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        self.inner.rmdir(ctx, parent, dir_name).await
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.symlink(ctx, parent, name, target_path).await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        self.inner.rename(ctx, param).await
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.link(ctx, newparent, newname).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.open(ctx, ino, flags).await
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        self.inner.read(ctx, ino, fh, offset, size, buf).await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.write(ctx, ino, fh, offset, data, flags).await
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.release(ctx, ino, fh, flags, lock_owner, flush).await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        self.inner.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        self.inner.copy_file_range(ctx, param).await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.inner.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.inner.readdirplus(ctx, ino, fh, offset).await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.setxattr(ctx, ino, name, value, flags, position).await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.create(ctx, ino, parent, name, mode, flags).await
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
@@ -0,0 +1,79 @@
+//! Control commands run on an inode through `VirtualFs::ioctl`, so backend
+//! specific controls need no trait method of their own
+//!
+//! Every layer answers the built-in commands it knows and passes the others
+//! down; a command reaching the bottom of the stack runs the handler
+//! registered for it with `register`.
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::RequestContext;
+use super::virtualfs::INum;
+
+/// The locations holding the data of the inode, one per line: the local
+/// path, the paths on the secondaries, or the objects of a shared namespace
+pub const IOCTL_PLACEMENT: u32 = 1;
+
+/// The `platform::FILE_FLAG_*` flags of the local file of the inode, as a
+/// little endian `u32`, 0 on backends without flags
+pub const IOCTL_FILE_FLAGS: u32 = 2;
+
+/// The first command `register` accepts, those below are built in
+pub const FIRST_CUSTOM_COMMAND: u32 = 0x1000;
+
+/// The output of a command, or its error
+pub type IoctlFuture = Pin<Box<dyn Future<Output = DatenLordResult<Vec<u8>>> + Send>>;
+
+/// Runs a custom command on behalf of the caller on an inode with an input
+pub type IoctlHandler = Arc<dyn Fn(RequestContext, INum, Vec<u8>) -> IoctlFuture + Send + Sync>;
+
+/// The handlers of the custom commands of the process
+static HANDLERS: RwLock<BTreeMap<u32, IoctlHandler>> = RwLock::new(BTreeMap::new());
+
+/// Run `handler` for the command `cmd` on every filesystem of the process
+///
+/// Fails with `DatenLordError::InvalidArgument` for a built-in command and
+/// with `DatenLordError::AlreadyExists` if `cmd` has a handler already.
+pub fn register(cmd: u32, handler: IoctlHandler) -> DatenLordResult<()> {
+    if cmd < FIRST_CUSTOM_COMMAND {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("ioctl command {cmd:#x} is built in")],
+        });
+    }
+    let mut handlers = HANDLERS.write().unwrap();
+    if handlers.contains_key(&cmd) {
+        return Err(DatenLordError::AlreadyExists {
+            context: vec![format!("ioctl command {cmd:#x} is registered already")],
+            source: None,
+        });
+    }
+    handlers.insert(cmd, handler);
+    Ok(())
+}
+
+/// Remove the handler of `cmd`, returning whether it had one
+pub fn unregister(cmd: u32) -> bool {
+    HANDLERS.write().unwrap().remove(&cmd).is_some()
+}
+
+/// Run the handler registered for `cmd`, failing with
+/// `DatenLordError::Unimplemented` without one
+pub async fn dispatch(
+    ctx: &RequestContext,
+    ino: INum,
+    cmd: u32,
+    input: &[u8],
+) -> DatenLordResult<Vec<u8>> {
+    let handler = HANDLERS.read().unwrap().get(&cmd).cloned();
+    match handler {
+        Some(handler) => handler(*ctx, ino, input.to_vec()).await,
+        None => Err(DatenLordError::Unimplemented {
+            context: vec![format!("ioctl command {cmd:#x} unimplemented")],
+            source: None,
+        }),
+    }
+}
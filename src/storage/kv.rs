@@ -0,0 +1,448 @@
+//! A key-value store of small objects packed into append-only segment
+//! files, sparing every object an inode of its own
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::appendlog::{AppendLog, LogSync, HEADER_SIZE, MAX_RECORD_SIZE};
+use super::fs_util::{RequestContext, ROOT_ID};
+use super::virtualfs::{INum, VirtualFs};
+
+/// The largest key a store accepts, in bytes
+pub const MAX_KEY_SIZE: usize = 1024;
+/// Default size past which a new segment is started, 64 MiB
+const DEFAULT_SEGMENT_BYTES: u64 = 64 << 20;
+/// The prefix of the names of the segment files
+const SEGMENT_PREFIX: &str = "segment-";
+/// The mode of the store directory, less the umask
+const DIR_MODE: u32 = 0o777;
+/// The records read at once when loading a segment
+const LOAD_BATCH: usize = 1024;
+/// The tag of a record setting a value
+const PUT: u8 = 0;
+/// The tag of a record removing a key
+const DELETE: u8 = 1;
+/// The size of the tag and the key length ahead of the key of a record
+const RECORD_HEADER: usize = 5;
+
+/// How a store writes its segments
+#[derive(Debug, Clone, Copy)]
+pub struct KvOptions {
+    /// The size past which a new segment is started
+    pub segment_bytes: u64,
+    /// When the segments are synced to the backend
+    pub sync: LogSync,
+}
+
+impl Default for KvOptions {
+    fn default() -> Self {
+        Self {
+            segment_bytes: DEFAULT_SEGMENT_BYTES,
+            sync: LogSync::Batch,
+        }
+    }
+}
+
+/// Where the current value of a key is
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    /// The number of the segment holding it
+    segment: u64,
+    /// The offset of its record in the segment
+    offset: u64,
+    /// The size of its record
+    len: u64,
+}
+
+/// The open segments and the index of the keys
+#[derive(Debug)]
+struct State<F: VirtualFs + 'static> {
+    /// The open segments by number, appends going to the last one
+    segments: BTreeMap<u64, AppendLog<F>>,
+    /// The location of the value of every key
+    index: BTreeMap<String, Location>,
+    /// The bytes of records overwritten, deleted or deleting, reclaimed by
+    /// `compact`
+    garbage: u64,
+}
+
+/// The record setting `key` to `value`, or deleting it without one
+fn encode(key: &str, value: Option<&[u8]>) -> Vec<u8> {
+    let (tag, value) = match value {
+        Some(value) => (PUT, value),
+        None => (DELETE, &[][..]),
+    };
+    let mut record = Vec::with_capacity(RECORD_HEADER + key.len() + value.len());
+    record.push(tag);
+    record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    record.extend_from_slice(key.as_bytes());
+    record.extend_from_slice(value);
+    record
+}
+
+/// The key of `record` and its value, `None` for a deletion
+fn decode(record: &[u8]) -> DatenLordResult<(&str, Option<&[u8]>)> {
+    let invalid = || DatenLordError::InvalidArgument {
+        context: vec!["invalid key-value record".to_owned()],
+    };
+    let header = record.get(..RECORD_HEADER).ok_or_else(invalid)?;
+    let key_len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    let key = record
+        .get(RECORD_HEADER..RECORD_HEADER + key_len)
+        .ok_or_else(invalid)?;
+    let key = std::str::from_utf8(key).map_err(|_| invalid())?;
+    let value = &record[RECORD_HEADER + key_len..];
+    match header[0] {
+        PUT => Ok((key, Some(value))),
+        DELETE => Ok((key, None)),
+        _ => Err(invalid()),
+    }
+}
+
+/// The size a record of `len` bytes takes in a segment
+fn framed(len: usize) -> u64 {
+    (HEADER_SIZE + len) as u64
+}
+
+/// A store of small values under string keys, kept in the directory of a
+/// namespace as append-only segment files
+///
+/// Puts and deletes append a record to the last segment, starting a new
+/// one once it is full, and the location of the value of every key is
+/// indexed in memory, rebuilt from the segments on open. Overwritten and
+/// deleted values take space until `compact` rewrites the live ones. A
+/// store has a single writer: it must not be opened twice at once.
+#[derive(Debug)]
+pub struct KvStore<F: VirtualFs + 'static> {
+    /// The filesystem holding the store
+    fs: Arc<F>,
+    /// The caller the store is used on behalf of
+    ctx: RequestContext,
+    /// The directory of the segments, relative to the root
+    dir: PathBuf,
+    /// How segments are written
+    options: KvOptions,
+    /// The segments and the index
+    state: RwLock<State<F>>,
+}
+
+impl<F: VirtualFs + 'static> KvStore<F> {
+    /// Open the store in `dir`, relative to the root of `fs`, on behalf of
+    /// `ctx`, creating it when missing
+    ///
+    /// The segment writers run on the current runtime.
+    pub async fn open(
+        fs: Arc<F>,
+        ctx: RequestContext,
+        dir: &Path,
+        options: KvOptions,
+    ) -> DatenLordResult<Self> {
+        let attr = fs
+            .mkdir_all(&ctx, ROOT_ID, dir.as_os_str(), DIR_MODE)
+            .await?;
+        let mut numbers = segment_numbers(fs.as_ref(), &ctx, attr.ino).await?;
+        numbers.sort_unstable();
+        let store = Self {
+            fs,
+            ctx,
+            dir: dir.to_owned(),
+            options,
+            state: RwLock::new(State {
+                segments: BTreeMap::new(),
+                index: BTreeMap::new(),
+                garbage: 0,
+            }),
+        };
+        {
+            let mut state = store.state.write().await;
+            for number in numbers {
+                let segment = store.open_segment(number).await?;
+                state.segments.insert(number, segment);
+                store.load(&mut state, number).await?;
+            }
+            if state.segments.is_empty() {
+                state.segments.insert(1, store.open_segment(1).await?);
+            }
+        }
+        Ok(store)
+    }
+
+    /// The path of segment `number`, relative to the root
+    fn segment_path(&self, number: u64) -> PathBuf {
+        self.dir.join(format!("{SEGMENT_PREFIX}{number:08}"))
+    }
+
+    /// Open segment `number`, creating it when missing
+    async fn open_segment(&self, number: u64) -> DatenLordResult<AppendLog<F>> {
+        let path = self.segment_path(number);
+        AppendLog::open(
+            Arc::clone(&self.fs),
+            self.ctx,
+            path.as_os_str(),
+            self.options.sync,
+        )
+        .await
+    }
+
+    /// Index the records of segment `number`, later ones overriding the
+    /// earlier ones
+    async fn load(&self, state: &mut State<F>, number: u64) -> DatenLordResult<()> {
+        let mut offset = 0;
+        loop {
+            let records = state.segments[&number]
+                .read_from(offset, LOAD_BATCH)
+                .await?;
+            let Some(last) = records.last() else {
+                return Ok(());
+            };
+            offset = last.offset + framed(last.data.len());
+            for record in records {
+                let len = framed(record.data.len());
+                let (key, value) = decode(&record.data)?;
+                let former = if value.is_some() {
+                    let location = Location {
+                        segment: number,
+                        offset: record.offset,
+                        len,
+                    };
+                    state.index.insert(key.to_owned(), location)
+                } else {
+                    state.garbage += len;
+                    state.index.remove(key)
+                };
+                if let Some(former) = former {
+                    state.garbage += former.len;
+                }
+            }
+        }
+    }
+
+    /// Append `record` to the last segment, starting a new one first when it
+    /// is full, returning its location
+    async fn append(&self, state: &mut State<F>, record: &[u8]) -> DatenLordResult<Location> {
+        let (&last, segment) =
+            state
+                .segments
+                .last_key_value()
+                .ok_or_else(|| DatenLordError::Internal {
+                    context: vec!["key-value store has no segment".to_owned()],
+                })?;
+        let number = if segment.end() > 0 && segment.end() >= self.options.segment_bytes {
+            let segment = self.open_segment(last + 1).await?;
+            state.segments.insert(last + 1, segment);
+            last + 1
+        } else {
+            last
+        };
+        let offset = state.segments[&number].append(record).await?;
+        Ok(Location {
+            segment: number,
+            offset,
+            len: framed(record.len()),
+        })
+    }
+
+    /// Read the value at `location`
+    async fn read(&self, state: &State<F>, location: Location) -> DatenLordResult<Vec<u8>> {
+        let segment =
+            state
+                .segments
+                .get(&location.segment)
+                .ok_or_else(|| DatenLordError::Internal {
+                    context: vec![format!("missing key-value segment {}", location.segment)],
+                })?;
+        let records = segment.read_from(location.offset, 1).await?;
+        let record = records.first().ok_or_else(|| DatenLordError::Internal {
+            context: vec![format!("no key-value record at {location:?}")],
+        })?;
+        match decode(&record.data)? {
+            (_, Some(value)) => Ok(value.to_vec()),
+            (_, None) => Err(DatenLordError::Internal {
+                context: vec![format!("deletion indexed at {location:?}")],
+            }),
+        }
+    }
+
+    /// Set `key` to `value`, replacing its former value
+    pub async fn put(&self, key: &str, value: &[u8]) -> DatenLordResult<()> {
+        check_key(key)?;
+        let record = encode(key, Some(value));
+        if record.len() > MAX_RECORD_SIZE {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "value of {} bytes is too large for key {key:?}",
+                    value.len()
+                )],
+            });
+        }
+        let mut state = self.state.write().await;
+        let location = self.append(&mut state, &record).await?;
+        if let Some(former) = state.index.insert(key.to_owned(), location) {
+            state.garbage += former.len;
+        }
+        Ok(())
+    }
+
+    /// The value of `key`, `None` when missing
+    pub async fn get(&self, key: &str) -> DatenLordResult<Option<Vec<u8>>> {
+        let state = self.state.read().await;
+        match state.index.get(key) {
+            Some(&location) => self.read(&state, location).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove `key`, returning whether it was there
+    pub async fn delete(&self, key: &str) -> DatenLordResult<bool> {
+        let mut state = self.state.write().await;
+        if !state.index.contains_key(key) {
+            return Ok(false);
+        }
+        let location = self.append(&mut state, &encode(key, None)).await?;
+        if let Some(former) = state.index.remove(key) {
+            state.garbage += former.len + location.len;
+        }
+        Ok(true)
+    }
+
+    /// The keys starting with `prefix` and their values, in key order
+    pub async fn scan(&self, prefix: &str) -> DatenLordResult<Vec<(String, Vec<u8>)>> {
+        let state = self.state.read().await;
+        let mut entries = Vec::new();
+        for (key, &location) in state
+            .index
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+        {
+            if !key.starts_with(prefix) {
+                break;
+            }
+            entries.push((key.clone(), self.read(&state, location).await?));
+        }
+        Ok(entries)
+    }
+
+    /// The number of keys
+    pub async fn len(&self) -> usize {
+        self.state.read().await.index.len()
+    }
+
+    /// Whether the store has no key
+    pub async fn is_empty(&self) -> bool {
+        self.state.read().await.index.is_empty()
+    }
+
+    /// The bytes taken by overwritten and deleted values, reclaimed by
+    /// `compact`
+    pub async fn garbage_bytes(&self) -> u64 {
+        self.state.read().await.garbage
+    }
+
+    /// Sync the records written so far
+    pub async fn sync(&self) -> DatenLordResult<()> {
+        let state = self.state.read().await;
+        if let Some((_, segment)) = state.segments.last_key_value() {
+            segment.sync().await?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the live values into new segments and remove the former ones,
+    /// returning the bytes reclaimed
+    ///
+    /// The new segments are synced before the former ones are removed, and
+    /// loading the segments in order yields the same values whether a crash
+    /// leaves the former ones or not.
+    pub async fn compact(&self) -> DatenLordResult<u64> {
+        let mut state = self.state.write().await;
+        let garbage = state.garbage;
+        let first = state
+            .segments
+            .last_key_value()
+            .map_or(1, |(&last, _)| last + 1);
+        let former: Vec<u64> = state.segments.keys().copied().collect();
+        state
+            .segments
+            .insert(first, self.open_segment(first).await?);
+        let live: Vec<(String, Location)> = state
+            .index
+            .iter()
+            .map(|(key, &location)| (key.clone(), location))
+            .collect();
+        let mut index = BTreeMap::new();
+        for (key, location) in live {
+            let value = self.read(&state, location).await?;
+            let location = self.append(&mut state, &encode(&key, Some(&value))).await?;
+            index.insert(key, location);
+        }
+        for (_, segment) in state.segments.range(first..) {
+            segment.sync().await?;
+        }
+        state.index = index;
+        state.garbage = 0;
+        for number in former {
+            if let Some(segment) = state.segments.remove(&number) {
+                segment.close().await?;
+            }
+            let path = self.segment_path(number);
+            self.fs.unlink(&self.ctx, ROOT_ID, path.as_os_str()).await?;
+        }
+        Ok(garbage)
+    }
+
+    /// Sync and close the segments
+    pub async fn close(self) -> DatenLordResult<()> {
+        let state = self.state.into_inner();
+        for (_, segment) in state.segments {
+            segment.close().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that `key` can be stored
+fn check_key(key: &str) -> DatenLordResult<()> {
+    if key.is_empty() || key.len() > MAX_KEY_SIZE {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!(
+                "key of {} bytes, expect 1 to {MAX_KEY_SIZE} bytes",
+                key.len()
+            )],
+        });
+    }
+    Ok(())
+}
+
+/// The numbers of the segments in the directory `dir`
+async fn segment_numbers<F: VirtualFs>(
+    fs: &F,
+    ctx: &RequestContext,
+    dir: INum,
+) -> DatenLordResult<Vec<u64>> {
+    let fh = fs.opendir(ctx, dir, 0).await?;
+    let mut names = Vec::new();
+    let listed = loop {
+        let offset = i64::try_from(names.len()).unwrap_or(i64::MAX);
+        match fs.readdir(ctx, dir, fh, offset).await {
+            Ok(page) if page.is_empty() => break Ok(()),
+            Ok(page) => names.extend(page.into_iter().map(|entry| entry.name)),
+            Err(e) => break Err(e),
+        }
+    };
+    fs.releasedir(ctx, dir, fh, 0).await?;
+    listed?;
+    Ok(names
+        .iter()
+        .filter_map(|name| segment_number(name))
+        .collect())
+}
+
+/// The number of the segment file `name`, `None` for other files
+fn segment_number(name: &OsStr) -> Option<u64> {
+    name.to_str()?.strip_prefix(SEGMENT_PREFIX)?.parse().ok()
+}
@@ -0,0 +1,72 @@
+//! The sizes a backend wants its data read and written in
+use serde::{Deserialize, Serialize};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+/// The default size of a block, 1 MiB
+const DEFAULT_BLOCK_SIZE: u64 = 1 << 20;
+/// The default size of a stripe, 8 MiB
+const DEFAULT_STRIPE_SIZE: u64 = 8 << 20;
+/// The default alignment, 4 KiB
+const DEFAULT_ALIGNMENT: u64 = 4096;
+
+/// How a backend lays out file data, so the layers above read and write it
+/// in pieces it handles well, see `VirtualFs::block_layout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockLayout {
+    /// The bytes of the buffers data is read and written through, a
+    /// multiple of `alignment`
+    pub block_size: u64,
+    /// The bytes of the ranges transferred concurrently, a multiple of
+    /// `block_size`
+    pub stripe_size: u64,
+    /// The offsets and sizes the backend prefers, a power of two
+    pub alignment: u64,
+}
+
+impl Default for BlockLayout {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            stripe_size: DEFAULT_STRIPE_SIZE,
+            alignment: DEFAULT_ALIGNMENT,
+        }
+    }
+}
+
+impl BlockLayout {
+    /// Fail with `DatenLordError::InvalidArgument` unless the sizes are
+    /// nested as documented
+    pub fn validate(&self) -> DatenLordResult<()> {
+        let invalid = |reason: &str| {
+            Err(DatenLordError::InvalidArgument {
+                context: vec![format!("invalid block layout {self:?}: {reason}")],
+            })
+        };
+        if !self.alignment.is_power_of_two() {
+            return invalid("the alignment is not a power of two");
+        }
+        if self.block_size == 0 || !self.block_size.is_multiple_of(self.alignment) {
+            return invalid("the block size is not a multiple of the alignment");
+        }
+        if self.stripe_size == 0 || !self.stripe_size.is_multiple_of(self.block_size) {
+            return invalid("the stripe size is not a multiple of the block size");
+        }
+        if self.block_size > u64::from(u32::MAX) {
+            return invalid("the block size does not fit a single read");
+        }
+        Ok(())
+    }
+
+    /// The block size as a buffer length, at least 1
+    pub fn block_len(&self) -> usize {
+        usize::try_from(self.block_size.max(1)).unwrap_or(usize::MAX)
+    }
+
+    /// `len` rounded up to a multiple of the block size
+    pub fn round_up_to_block(&self, len: u64) -> u64 {
+        len.div_ceil(self.block_size.max(1))
+            .saturating_mul(self.block_size.max(1))
+    }
+}
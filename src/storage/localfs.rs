@@ -1,26 +1,631 @@
 use async_trait::async_trait;
-use bytes::BytesMut;
+use nix::errno::Errno;
+use nix::fcntl::{renameat2, OFlag, RenameFlags};
+use nix::sys::stat::{Mode, SFlag, UtimensatFlags};
+use nix::sys::statvfs::statvfs;
+use nix::sys::time::TimeSpec;
 use opendal::services::Fs;
 use opendal::Operator;
+use std::collections::{HashMap, HashSet};
+use rustix::fs::SeekFrom;
+use std::ffi::OsStr;
 use std::fs;
-use std::os::unix::fs::MetadataExt;
-use std::time::{Duration, SystemTime};
-use std::path::Path;
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::{DirEntryExt, FileExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+use crate::common::config::DatenLordConfig;
+use crate::common::{DatenLordError, DatenLordResult, ResultExt};
+use super::digest::DIGEST_XATTR;
+use super::dir_handle::DirHandles;
+use super::inode_table::InodeTable;
+use super::ioctl;
+use super::layout::BlockLayout;
+use super::platform;
+use super::fs_util::{
+    self, parse_oflag, AtimePolicy, CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileKind,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam, ROOT_ID,
+};
+use super::safe_path;
+use super::superblock::{Feature, Superblock, SUPERBLOCK_NAME};
+use super::virtualfs::{copy_range_through, INum, VirtualFs};
+use super::writeback::DirtyBytes;
+use super::xattr;
+
+/// The TTL of attributes returned by `LocalFS`
+const ATTR_TTL: Duration = Duration::from_secs(1);
+
+/// Permission bits checked by `check_perm`
+const ACCESS_READ: u8 = 0o4;
+const ACCESS_WRITE: u8 = 0o2;
+const ACCESS_EXEC: u8 = 0o1;
+
+/// How writes through a file handle are made durable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SyncMode {
+    /// Writes are left to the page cache
+    None,
+    /// Each write syncs file data, like `O_DSYNC`
+    Data,
+    /// Each write syncs file data and metadata, like `O_SYNC`
+    All,
+}
+
+impl SyncMode {
+    /// Get the sync mode requested by the open flags
+    fn from_flags(flags: OFlag) -> Self {
+        // `O_SYNC` includes the `O_DSYNC` bit on Linux, so check it first
+        if flags.contains(OFlag::O_SYNC) {
+            Self::All
+        } else if flags.contains(OFlag::O_DSYNC) {
+            Self::Data
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// A file opened through `VirtualFs::open`
+#[derive(Debug)]
+struct OpenFile {
+    /// The underlying local file
+    file: fs::File,
+    /// The durability required for every write
+    sync_mode: SyncMode,
+    /// The bytes written through the handle and not synced yet
+    dirty: AtomicU64,
+    /// Whether a write through the handle removed the cached digests
+    digests_dropped: AtomicBool,
+}
+
+impl OpenFile {
+    /// Forget the unsynced bytes of the handle, returning how many there were
+    fn take_dirty(&self, dirty: &DirtyBytes) -> u64 {
+        let bytes = self.dirty.swap(0, Ordering::Relaxed);
+        dirty.sub(bytes);
+        bytes
+    }
+}
+
+/// Map an `io::Error` of an extended attribute call like `?` does, but a
+/// local file system without extended attributes to
+/// `DatenLordError::Unimplemented`
+fn xattr_error(context: String) -> impl FnOnce(std::io::Error) -> DatenLordError {
+    move |e| {
+        if e.raw_os_error() == Some(Errno::EOPNOTSUPP as i32) {
+            DatenLordError::Unimplemented {
+                context: vec![context, e.to_string()],
+                source: Some(Box::new(e)),
+            }
+        } else {
+            DatenLordError::from(e).add_context(context)
+        }
+    }
+}
 
-use crate::common::DatenLordResult;
-use super::fs_util::{CreateParam, FileAttr, RenameParam, SetAttrParam, StatFsParam};
-use super::virtualfs::{DirEntry, INum, VirtualFs};
 #[derive(Debug)]
 pub struct LocalFS {
     operator: Operator,
+    /// The SDK configuration
+    config: DatenLordConfig,
+    /// The paths of the inodes, persisted under the root
+    inodes: Arc<InodeTable>,
+    /// The open file handles
+    handles: RwLock<HashMap<u64, Arc<OpenFile>>>,
+    /// The next file handle to allocate
+    next_fh: AtomicU64,
+    /// The superblock of the namespace under the root
+    superblock: RwLock<Superblock>,
+    /// The bytes written through all the handles and not synced yet
+    dirty: Arc<DirtyBytes>,
+    /// Whether the last write was rejected to keep the reserved space free
+    low_space: AtomicBool,
+    /// The open directories
+    dirs: DirHandles,
+    /// The inodes whose digests were cached since the handles writing them
+    /// removed the cached ones
+    digested: Mutex<HashSet<INum>>,
+    /// The changes made through this instance to the entries of each
+    /// directory, by local inode
+    ///
+    /// The generation of a directory is its change time in nanoseconds plus
+    /// these changes, so changes by other processes move it too, and those
+    /// made within one tick of the clock of the local file system are still
+    /// told apart.
+    changes: Mutex<HashMap<u64, u64>>,
 }
 
 impl LocalFS {
-    pub fn new() -> DatenLordResult<Self> {
+    pub fn new(config: &DatenLordConfig) -> DatenLordResult<Self> {
+        config.layout.validate()?;
+        if !config.root.is_dir() {
+            Self::create_dir(&config.root, 0o755)?;
+        }
+        let superblock = Superblock::open_or_create(&config.root, &config.features)?;
+        let inodes = InodeTable::open(&config.root)?;
         let mut builder = Fs::default();
-        builder.root("/tmp");
+        builder.root(&config.root.to_string_lossy());
         let op = Operator::new(builder).unwrap().finish();
-        Ok(Self { operator: op })
+        Ok(Self {
+            operator: op,
+            config: config.clone(),
+            inodes,
+            handles: RwLock::new(HashMap::new()),
+            next_fh: AtomicU64::new(1),
+            superblock: RwLock::new(superblock),
+            dirty: Arc::new(DirtyBytes::new(config.writeback.dirty_high_watermark)),
+            low_space: AtomicBool::new(false),
+            dirs: DirHandles::default(),
+            digested: Mutex::new(HashSet::new()),
+            changes: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Fail with `DatenLordError::NoSpace` when writing `len` bytes would leave
+    /// less than `reserved_space_bytes` free on the filesystem of the root
+    ///
+    /// Logs a warning when writes start being rejected, and again once they
+    /// are accepted.
+    fn check_reserved_space(&self, len: u64) -> DatenLordResult<()> {
+        let reserved = self.config.reserved_space_bytes;
+        if reserved == 0 {
+            return Ok(());
+        }
+        let root = &self.config.root;
+        let stat =
+            statvfs(root).with_context(|| format!("failed to stat filesystem of {root:?}"))?;
+        let free = stat.blocks_available().saturating_mul(stat.fragment_size());
+        let low = free < reserved.saturating_add(len);
+        if self.low_space.swap(low, Ordering::Relaxed) != low {
+            if low {
+                warn!(
+                    "{free} bytes free on the filesystem of {root:?}, \
+                     rejecting writes to keep {reserved} bytes free"
+                );
+            } else {
+                info!("{free} bytes free on the filesystem of {root:?}, accepting writes again");
+            }
+        }
+        if low {
+            return Err(DatenLordError::NoSpace {
+                context: vec![format!(
+                    "writing {len} bytes would leave less than the {reserved} reserved bytes free \
+                     on the filesystem of {root:?}, {free} bytes are free"
+                )],
+                source: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// The bytes written through the open handles and not synced yet
+    pub fn dirty_bytes(&self) -> Arc<DirtyBytes> {
+        Arc::clone(&self.dirty)
+    }
+
+    /// The numbers of open files and open directories
+    pub fn open_handles(&self) -> (usize, usize) {
+        (self.handles.read().unwrap().len(), self.dirs.len())
+    }
+
+    /// Sync the data and metadata of every handle written through since its
+    /// last sync, returning the bytes synced
+    pub fn flush_dirty(&self) -> DatenLordResult<u64> {
+        let handles: Vec<_> = self.handles.read().unwrap().values().cloned().collect();
+        let mut flushed = 0;
+        for handle in handles {
+            let bytes = handle.dirty.swap(0, Ordering::Relaxed);
+            if bytes == 0 {
+                continue;
+            }
+            if let Err(e) = handle.file.sync_all() {
+                handle.dirty.fetch_add(bytes, Ordering::Relaxed);
+                return Err(DatenLordError::from(e).add_context("failed to write back open file"));
+            }
+            self.dirty.sub(bytes);
+            flushed += bytes;
+        }
+        Ok(flushed)
+    }
+
+    /// The optional features the namespace was created with
+    pub fn features(&self) -> Vec<Feature> {
+        self.superblock.read().unwrap().features.iter().copied().collect()
+    }
+
+    /// Enable `feature` on the namespace, migrating the existing data
+    pub fn enable_feature(&self, feature: Feature) -> DatenLordResult<()> {
+        self.superblock
+            .write()
+            .unwrap()
+            .enable_feature(&self.config.root, feature)
+    }
+
+    /// The local path of the directory `ctx` sees as the root
+    fn scope_path(&self, ctx: &RequestContext) -> DatenLordResult<PathBuf> {
+        if ctx.root == ROOT_ID {
+            return Ok(self.config.root.clone());
+        }
+        Ok(self.config.root.join(self.inodes.path(ctx.root)?))
+    }
+
+    /// Get the local path of an inode, which must be under the root of `ctx`,
+    /// `ROOT_ID` standing for that root
+    fn inode_path(&self, ctx: &RequestContext, ino: INum) -> DatenLordResult<PathBuf> {
+        let root = self.scope_path(ctx)?;
+        if ino == ROOT_ID {
+            return Ok(root);
+        }
+        let path = self.config.root.join(self.inodes.path(ino)?);
+        if !path.starts_with(&root) {
+            return Err(DatenLordError::PermissionDenied {
+                context: vec![format!("inode={ino} is outside of the root {root:?}")],
+                source: None,
+            });
+        }
+        Ok(path)
+    }
+
+    /// The path of an inode relative to the root
+    pub(crate) fn relative_path(&self, ino: INum) -> DatenLordResult<PathBuf> {
+        self.inodes.path(ino)
+    }
+
+    /// The path of the local path `path` relative to the root
+    fn relative<'a>(&self, path: &'a Path) -> DatenLordResult<&'a Path> {
+        path.strip_prefix(&self.config.root)
+            .map_err(|_| DatenLordError::Internal {
+                context: vec![format!("{path:?} is not under the root {:?}", self.config.root)],
+            })
+    }
+
+    /// Get the local path of the child `name` under `parent`, which must be a
+    /// valid name not leading out of the root of `ctx`, see
+    /// `NameConfig::check` and `safe_path::resolve`
+    fn child_path(&self, ctx: &RequestContext, parent: INum, name: &OsStr) -> DatenLordResult<PathBuf> {
+        self.config.names.check(name)?;
+        let dir = self.inode_path(ctx, parent)?;
+        safe_path::resolve(&self.scope_path(ctx)?, &dir, name, false)
+    }
+
+    /// The local path `path` points to when it is a symbolic link, which
+    /// must not lead out of the root of `ctx` either
+    fn follow(&self, ctx: &RequestContext, path: &Path) -> DatenLordResult<PathBuf> {
+        let is_link = fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink());
+        match (is_link, path.parent(), path.file_name()) {
+            (true, Some(dir), Some(name)) => {
+                safe_path::resolve(&self.scope_path(ctx)?, dir, name, true)
+            }
+            _ => Ok(path.to_owned()),
+        }
+    }
+
+    /// Stat a local path and register it in the inode table
+    fn register(&self, path: PathBuf) -> DatenLordResult<FileAttr> {
+        let metadata = fs::symlink_metadata(&path)
+            .with_context(|| format!("failed to stat {path:?}"))?;
+        let ino = self.inodes.register(self.relative(&path)?, metadata.ino())?;
+        Ok(self.local_attr(metadata, ino))
+    }
+
+    /// Drop the link at the local path `path` from the inode table, and its
+    /// inode unless `keep`, returning its inode number
+    ///
+    /// The entry is already gone, so a failure to record it is only logged:
+    /// the table tells the path apart from a file created there later.
+    fn unregister(&self, path: &Path, keep: bool) -> Option<INum> {
+        let result = self
+            .relative(path)
+            .and_then(|relative| self.inodes.remove(relative, keep));
+        result.unwrap_or_else(|e| {
+            warn!("failed to drop {path:?} from the inode table: {e}");
+            None
+        })
+    }
+
+    /// Create a local directory
+    ///
+    /// The C SDK exports a `mkdir` symbol which interposes the libc one used
+    /// by `std::fs`, so directories are always created through `mkdirat`.
+    pub(crate) fn create_dir(path: &Path, mode: u32) -> DatenLordResult<()> {
+        nix::sys::stat::mkdirat(None, path, Mode::from_bits_truncate(mode))
+            .with_context(|| format!("failed to create directory {path:?}"))
+    }
+
+    /// Check that the caller is granted `access_mode` on the local `path`
+    fn check_access(ctx: &RequestContext, path: &Path, access_mode: u8) -> DatenLordResult<()> {
+        if ctx.is_root() {
+            return Ok(());
+        }
+        let metadata = fs::metadata(path).with_context(|| format!("failed to stat {path:?}"))?;
+        Self::fileattr_from_local_metadata(metadata, 0)
+            .check_perm(ctx, access_mode)
+            .map_err(|_| DatenLordError::PermissionDenied {
+                context: vec![format!(
+                    "{path:?} denies access mode {access_mode:o} to uid={} gid={}",
+                    ctx.uid, ctx.gid
+                )],
+                source: None,
+            })
+    }
+
+    /// Check that the caller may add or remove entries in the directory
+    /// holding the local `path`
+    fn check_parent_access(ctx: &RequestContext, path: &Path) -> DatenLordResult<()> {
+        match path.parent() {
+            Some(parent) => Self::check_access(ctx, parent, ACCESS_WRITE | ACCESS_EXEC),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that the caller may remove or replace the entry at the local
+    /// `path`, whose metadata is `metadata`, past the sticky bit of the
+    /// directory holding it
+    fn check_sticky(
+        ctx: &RequestContext,
+        path: &Path,
+        metadata: &fs::Metadata,
+    ) -> DatenLordResult<()> {
+        let Some(parent) = path.parent() else {
+            return Ok(());
+        };
+        if ctx.is_root() {
+            return Ok(());
+        }
+        let dir = fs::metadata(parent).with_context(|| format!("failed to stat {parent:?}"))?;
+        let child = Self::fileattr_from_local_metadata(metadata.clone(), 0);
+        Self::fileattr_from_local_metadata(dir, 0)
+            .check_sticky(ctx, &child)
+            .map_err(|_| DatenLordError::PermissionDenied {
+                context: vec![format!(
+                    "sticky directory {parent:?} denies removing {path:?} to uid={}",
+                    ctx.uid
+                )],
+                source: None,
+            })
+    }
+
+    /// Check that the caller may move the local entry `from` to `to`, the
+    /// entry at `to` being replaced, or moved to `from` if `exchange`
+    ///
+    /// Like `rename(2)`, both entries are checked against the sticky bit of
+    /// their directory, and a directory moving to another parent needs write
+    /// access to update its `..` entry.
+    fn check_rename_entries(
+        ctx: &RequestContext,
+        from: &Path,
+        to: &Path,
+        exchange: bool,
+    ) -> DatenLordResult<()> {
+        if ctx.is_root() {
+            return Ok(());
+        }
+        let other_parent = from.parent() != to.parent();
+        let moved = fs::symlink_metadata(from)
+            .with_context(|| format!("failed to stat {from:?}"))?;
+        Self::check_sticky(ctx, from, &moved)?;
+        if moved.is_dir() && other_parent {
+            Self::check_access(ctx, from, ACCESS_WRITE)?;
+        }
+        if let Ok(target) = fs::symlink_metadata(to) {
+            Self::check_sticky(ctx, to, &target)?;
+            if exchange && target.is_dir() && other_parent {
+                Self::check_access(ctx, to, ACCESS_WRITE)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Give an entry created for the caller its mode, with the umask of the
+    /// caller applied, and its owner, removing the entry again on failure
+    ///
+    /// Like Linux, entries created in a set-group-ID directory belong to the
+    /// group of the directory and directories inherit the bit, while
+    /// executable files lose it unless the caller is in that group.
+    fn set_created_owner(
+        ctx: &RequestContext,
+        path: &Path,
+        mode: u32,
+        is_dir: bool,
+    ) -> DatenLordResult<()> {
+        let setgid = Mode::S_ISGID.bits();
+        let group_dir = path
+            .parent()
+            .and_then(|parent| fs::metadata(parent).ok())
+            .filter(|dir| dir.mode() & setgid != 0);
+        let gid = group_dir.as_ref().map_or(ctx.gid, MetadataExt::gid);
+        let mut mode = ctx.create_mode(mode);
+        if group_dir.is_some() && is_dir {
+            mode |= setgid;
+        } else if gid != ctx.gid && !ctx.is_root() && mode & Mode::S_IXGRP.bits() != 0 {
+            mode &= !setgid;
+        }
+
+        let chown =
+            ctx.uid != nix::unistd::geteuid().as_raw() || gid != nix::unistd::getegid().as_raw();
+        // The mode comes last, changing the owner drops the set-ID bits
+        let result = if chown {
+            std::os::unix::fs::lchown(path, Some(ctx.uid), Some(gid))
+        } else {
+            Ok(())
+        }
+        .and_then(|()| fs::set_permissions(path, fs::Permissions::from_mode(mode)));
+        result.map_err(|e| {
+            let removed = if is_dir { fs::remove_dir(path) } else { fs::remove_file(path) };
+            if let Err(remove_err) = removed {
+                warn!("failed to remove {path:?} after failing to set its owner: {remove_err}");
+            }
+            DatenLordError::from(e).add_context(format!("failed to set the owner of {path:?}"))
+        })
+    }
+
+    /// Get an open file handle
+    fn handle(&self, fh: u64) -> DatenLordResult<Arc<OpenFile>> {
+        self.handles
+            .read()
+            .unwrap()
+            .get(&fh)
+            .cloned()
+            .ok_or_else(|| DatenLordError::InvalidArgument {
+                context: vec![format!("unknown file handle={fh}")],
+            })
+    }
+
+    /// Account for the `len` bytes just written to the file `ino` open as
+    /// `fh`, clearing its set-id bits and syncing it as its handle requires
+    fn written(
+        &self,
+        ctx: &RequestContext,
+        ino: INum,
+        fh: u64,
+        handle: &OpenFile,
+        len: u64,
+    ) -> DatenLordResult<()> {
+        // Once per handle for the digests cached before it was opened, and
+        // again for those cached meanwhile
+        let cached_since = self.digested.lock().unwrap().remove(&ino);
+        if !handle.digests_dropped.swap(true, Ordering::Relaxed) || cached_since {
+            xattr::fremove_if_any(&handle.file, DIGEST_XATTR)
+                .with_context(|| format!("failed to drop the digests of file handle={fh}"))?;
+        }
+        if !ctx.is_root() {
+            let metadata = handle
+                .file
+                .metadata()
+                .with_context(|| format!("failed to stat file handle={fh}"))?;
+            let attr = Self::fileattr_from_local_metadata(metadata, ino);
+            if let Some(perm) = attr.setid_cleared_perm(ctx) {
+                handle
+                    .file
+                    .set_permissions(fs::Permissions::from_mode(perm.into()))
+                    .with_context(|| format!("failed to chmod file handle={fh}"))?;
+            }
+        }
+
+        match handle.sync_mode {
+            SyncMode::None => {
+                handle.dirty.fetch_add(len, Ordering::Relaxed);
+                self.dirty.add(len);
+                Ok(())
+            }
+            SyncMode::Data => handle.file.sync_data(),
+            SyncMode::All => handle.file.sync_all(),
+        }
+        .with_context(|| format!("failed to sync file handle={fh}"))
+    }
+
+    /// Update the access time of the file open as `fh` after a read, as the
+    /// atime policy says
+    ///
+    /// Files are read through `O_NOATIME` handles when possible, so the
+    /// policy rather than the mount options of `root` decides. For
+    /// `relatime` the kernel updates the access time through another
+    /// descriptor, leaving `ctime` unchanged, as long as `root` is not
+    /// mounted `noatime`; for `strictatime` it is set, changing `ctime`.
+    fn accessed(&self, fh: u64, handle: &OpenFile) -> DatenLordResult<()> {
+        let policy = self.config.atime;
+        if policy == AtimePolicy::NoAtime {
+            return Ok(());
+        }
+        let metadata = handle
+            .file
+            .metadata()
+            .with_context(|| format!("failed to stat file handle={fh}"))?;
+        let attr = Self::fileattr_from_local_metadata(metadata, 0);
+        if !attr.read_updates_atime(policy, SystemTime::now()) {
+            return Ok(());
+        }
+        // Readers not owning the file read through handles without
+        // `O_NOATIME`, whose access time the kernel updates instead
+        let updated = if policy == AtimePolicy::StrictAtime {
+            nix::sys::stat::futimens(
+                handle.file.as_raw_fd(),
+                &TimeSpec::UTIME_NOW,
+                &TimeSpec::UTIME_OMIT,
+            )
+            .map_err(std::io::Error::from)
+        } else {
+            let fd = format!("/proc/self/fd/{}", handle.file.as_raw_fd());
+            fs::File::open(fd).and_then(|file| file.read_at(&mut [0], 0).map(|_| ()))
+        };
+        if let Err(e) = updated {
+            debug!("failed to update the access time of file handle={fh}: {e}");
+        }
+        Ok(())
+    }
+
+    /// Whether the config forces synchronous writes for a local path
+    fn is_sync_write_path(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.config.root)
+            .is_ok_and(|relative| self.config.is_sync_write_path(relative))
+    }
+
+    /// The entries of directory `ino` from `offset` on, with their
+    /// attributes if `with_attr`
+    fn list_dir(
+        &self,
+        ctx: &RequestContext,
+        ino: INum,
+        offset: i64,
+        with_attr: bool,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        let path = self.follow(ctx, &self.inode_path(ctx, ino)?)?;
+        let entries = fs::read_dir(&path)
+            .with_context(|| format!("failed to read directory {path:?}"))?;
+
+        // The superblock is internal metadata and never listed, the root
+        // being looked up under its local inode too
+        let entries = entries.filter(|entry| {
+            path != self.config.root
+                || entry.as_ref().map_or(true, |entry| {
+                    !entry.file_name().to_string_lossy().starts_with(SUPERBLOCK_NAME)
+                })
+        });
+
+        let relative = self.relative(&path)?;
+        let mut dir_entries = Vec::new();
+        let mut links = Vec::new();
+        for entry in entries.skip(usize::try_from(offset).unwrap_or(0)) {
+            let entry = entry.with_context(|| format!("failed to read directory {path:?}"))?;
+            let name = entry.file_name();
+            self.config.names.check_listed(&name)?;
+            let child_ino = entry.ino();
+            let file_type = entry
+                .file_type()
+                .with_context(|| format!("failed to stat {:?}", entry.path()))?;
+            // `DirEntry::metadata` is an `fstatat` relative to the open
+            // directory without following symbolic links, so no path is
+            // resolved again
+            let attr = if with_attr {
+                let metadata = entry
+                    .metadata()
+                    .with_context(|| format!("failed to stat {:?}", entry.path()))?;
+                Some(self.local_attr(metadata, child_ino))
+            } else {
+                None
+            };
+            links.push((relative.join(&name), child_ino));
+            dir_entries.push(DirEntry {
+                name,
+                ino: child_ino,
+                kind: FileKind::from_file_type(file_type),
+                attr,
+            });
+        }
+        let inos = self.inodes.register_all(&links)?;
+        for (entry, ino) in dir_entries.iter_mut().zip(inos) {
+            entry.ino = ino;
+            if let Some(attr) = entry.attr.as_mut() {
+                attr.ino = ino;
+            }
+        }
+        Ok(dir_entries)
     }
 
     fn fileattr_from_metadata(metadata: opendal::Metadata, ino: u64) -> FileAttr {
@@ -48,205 +653,770 @@ impl LocalFS {
             uid: 1000,
             gid: 1000,
             rdev: 0,
+            generation: 0,
         }
     }
 
     fn fileattr_from_local_metadata(metadata: fs::Metadata, ino: u64) -> FileAttr {
-        let kind = if metadata.is_file() {
-            nix::sys::stat::SFlag::S_IFREG
-        } else if metadata.is_dir() {
-            nix::sys::stat::SFlag::S_IFDIR
-        } else {
-            nix::sys::stat::SFlag::S_IFLNK
+        let to_system_time = |secs: i64, nsecs: i64| {
+            fs_util::from_timespec(secs, nsecs as u32).unwrap_or(UNIX_EPOCH)
         };
-        let size = metadata.len();
-        let atime = metadata.modified().unwrap_or(SystemTime::now().into()).into();
 
-        return FileAttr {
+        FileAttr {
             ino: ino as INum,
-            size,
-            blocks: size / 512,
-            atime,
-            mtime: atime,
-            ctime: atime,
-            kind,
-            perm: 0o775,
-            nlink: 1,
-            uid: 1000,
-            gid: 1000,
-            rdev: 0,
+            size: metadata.len(),
+            blocks: metadata.blocks(),
+            atime: to_system_time(metadata.atime(), metadata.atime_nsec()),
+            mtime: to_system_time(metadata.mtime(), metadata.mtime_nsec()),
+            ctime: to_system_time(metadata.ctime(), metadata.ctime_nsec()),
+            kind: SFlag::from_bits_truncate(metadata.mode() & SFlag::S_IFMT.bits()),
+            perm: (metadata.mode() & 0o7777) as u16,
+            nlink: metadata.nlink() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            rdev: metadata.rdev() as u32,
+            generation: if metadata.is_dir() {
+                (metadata.ctime() as u64)
+                    .wrapping_mul(1_000_000_000)
+                    .wrapping_add(metadata.ctime_nsec() as u64)
+            } else {
+                0
+            },
+        }
+    }
+
+    /// The attributes of the inode `ino` of local metadata `metadata`, the
+    /// generation of a directory counting the changes made through `self`
+    fn local_attr(&self, metadata: fs::Metadata, ino: u64) -> FileAttr {
+        let changes = if metadata.is_dir() {
+            self.changes.lock().unwrap().get(&metadata.ino()).copied()
+        } else {
+            None
         };
+        let mut attr = Self::fileattr_from_local_metadata(metadata, ino);
+        attr.generation = attr.generation.wrapping_add(changes.unwrap_or(0));
+        attr
+    }
+
+    /// Count a change to the entries of the directory holding the local
+    /// `path`
+    fn changed(&self, path: &Path) {
+        let Some(dir) = path.parent() else {
+            return;
+        };
+        match fs::symlink_metadata(dir) {
+            Ok(metadata) => {
+                let mut changes = self.changes.lock().unwrap();
+                *changes.entry(metadata.ino()).or_default() += 1;
+            }
+            Err(e) => warn!("failed to stat {dir:?} to count a change of its entries: {e}"),
+        }
     }
 }
 
 #[async_trait]
 impl VirtualFs for LocalFS {
+    fn block_layout(&self) -> BlockLayout {
+        self.config.layout
+    }
+
     async fn lookup(
         &self,
-        _uid: u32,
-        _gid: u32,
-        _parent: INum,
-        name: &str,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
     ) -> DatenLordResult<(Duration, FileAttr, u64)> {
-        let path = format!("/tmp/{}", name);
-        let local_metadata = fs::metadata(path).unwrap();
-        let ino = local_metadata.ino();
-        let metadata = Self::fileattr_from_local_metadata(local_metadata, ino);
-        Ok((Duration::from_secs(1), metadata, 0))
+        let path = self.child_path(ctx, parent, name)?;
+        if let Some(dir) = path.parent() {
+            Self::check_access(ctx, dir, ACCESS_EXEC)?;
+        }
+        let attr = self.register(path)?;
+        Ok((ATTR_TTL, attr, 0))
     }
 
-    async fn getattr(&self, ino: u64) -> DatenLordResult<(Duration, FileAttr)> {
-        Ok((Duration::from_secs(1), FileAttr::default()))
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        // Stat without registering the entry in the inode table
+        let path = match self.child_path(ctx, parent, name) {
+            Ok(path) => path,
+            Err(DatenLordError::NotFound { .. }) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if let Some(dir) = path.parent() {
+            match Self::check_access(ctx, dir, ACCESS_EXEC) {
+                Err(DatenLordError::NotFound { .. }) => return Ok(false),
+                result => result?,
+            }
+        }
+        match fs::symlink_metadata(&path) {
+            Ok(_) => Ok(true),
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                Ok(false)
+            }
+            Err(e) => Err(e).with_context(|| format!("failed to stat {path:?}")),
+        }
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let path = self.inode_path(ctx, ino)?;
+        let metadata = fs::symlink_metadata(&path)
+            .with_context(|| format!("failed to stat {path:?}"))?;
+        Ok((ATTR_TTL, self.local_attr(metadata, ino)))
     }
 
     async fn setattr(
         &self,
-        _uid: u32,
-        _gid: u32,
+        ctx: &RequestContext,
         ino: u64,
         param: SetAttrParam,
     ) -> DatenLordResult<(Duration, FileAttr)> {
-        Ok((Duration::from_secs(1), FileAttr::default()))
+        let path = self.inode_path(ctx, ino)?;
+        // Modes and sizes are those of the file a symbolic link points to
+        let target = self.follow(ctx, &path)?;
+        let (_, attr) = self.getattr(ctx, ino).await?;
+        attr.setattr_precheck(&param, ctx)?;
+        if param.size.is_some() {
+            Self::check_access(ctx, &target, ACCESS_WRITE)?;
+        }
+        if let Some(mode) = param.mode {
+            fs::set_permissions(&target, fs::Permissions::from_mode(mode & 0o7777))
+                .with_context(|| format!("failed to chmod {target:?}"))?;
+        }
+        if param.u_id.is_some() || param.g_id.is_some() {
+            std::os::unix::fs::lchown(&path, param.u_id, param.g_id)
+                .with_context(|| format!("failed to chown {path:?}"))?;
+            let metadata = fs::symlink_metadata(&path)
+                .with_context(|| format!("failed to stat {path:?}"))?;
+            let attr = Self::fileattr_from_local_metadata(metadata, ino);
+            if let Some(perm) = attr.setid_cleared_perm(ctx) {
+                fs::set_permissions(&path, fs::Permissions::from_mode(perm.into()))
+                    .with_context(|| format!("failed to chmod {path:?}"))?;
+            }
+        }
+        if let Some(size) = param.size {
+            fs::OpenOptions::new()
+                .write(true)
+                .open(&target)
+                .and_then(|file| file.set_len(size))
+                .with_context(|| format!("failed to truncate {target:?}"))?;
+            xattr::remove_if_any(&target, DIGEST_XATTR)
+                .with_context(|| format!("failed to drop the digests of {target:?}"))?;
+        }
+        if param.a_time.is_some() || param.m_time.is_some() {
+            let to_timespec = |time: Option<SystemTime>| {
+                time.map_or(TimeSpec::UTIME_OMIT, |time| {
+                    let (sec, nsec) = fs_util::to_timespec(time);
+                    TimeSpec::new(sec, nsec.into())
+                })
+            };
+            nix::sys::stat::utimensat(
+                None,
+                &path,
+                &to_timespec(param.a_time),
+                &to_timespec(param.m_time),
+                UtimensatFlags::NoFollowSymlink,
+            )
+            .with_context(|| format!("failed to set times of {path:?}"))?;
+        }
+        self.getattr(ctx, ino).await
     }
 
-    async fn readlink(&self, ino: u64) -> DatenLordResult<Vec<u8>> {
-        Ok(Vec::new())
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        let path = self.inode_path(ctx, ino)?;
+        let target = fs::read_link(&path)
+            .with_context(|| format!("failed to read symbolic link {path:?}"))?;
+        Ok(target.into_os_string().into_vec())
     }
 
-    async fn open(&self, _uid: u32, _gid: u32, ino: u64, _flags: u32) -> DatenLordResult<u64> {
-        Ok(0)
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let oflags = parse_oflag(flags);
+        let path = self.inode_path(ctx, ino)?;
+        if oflags.contains(OFlag::O_NOFOLLOW)
+            && fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_symlink())
+        {
+            return Err(DatenLordError::from(Errno::ELOOP)
+                .add_context(format!("{path:?} is a symbolic link")));
+        }
+        let path = self.follow(ctx, &path)?;
+        let access_mode = oflags & OFlag::O_ACCMODE;
+        let mut required = 0;
+        if access_mode != OFlag::O_WRONLY {
+            required |= ACCESS_READ;
+        }
+        if access_mode != OFlag::O_RDONLY || oflags.contains(OFlag::O_TRUNC) {
+            required |= ACCESS_WRITE;
+        }
+        Self::check_access(ctx, &path, required)?;
+
+        let mut options = fs::OpenOptions::new();
+        options
+            .read(access_mode != OFlag::O_WRONLY)
+            .write(access_mode != OFlag::O_RDONLY)
+            .append(oflags.contains(OFlag::O_APPEND));
+        // Reads update the access time as the atime policy says, owners
+        // alone may open with `O_NOATIME`
+        let file = if access_mode == OFlag::O_WRONLY {
+            options.open(&path)
+        } else {
+            match options.clone().custom_flags(OFlag::O_NOATIME.bits()).open(&path) {
+                Err(e) if e.raw_os_error() == Some(Errno::EPERM as i32) => options.open(&path),
+                opened => opened,
+            }
+        }
+        .with_context(|| format!("failed to open {path:?}"))?;
+        // Truncating bumps the times even of an empty file, like `open(2)`
+        let regular = file
+            .metadata()
+            .with_context(|| format!("failed to stat {path:?}"))?
+            .is_file();
+        if oflags.contains(OFlag::O_TRUNC) && regular {
+            let param = SetAttrParam {
+                size: Some(0),
+                ..SetAttrParam::default()
+            };
+            self.setattr(ctx, ino, param).await?;
+        }
+
+        // Sync writes are made durable by an explicit sync after each write
+        // rather than by passing the flags down, so the same semantics hold
+        // for the per-path config override.
+        let sync_mode = match SyncMode::from_flags(oflags) {
+            SyncMode::None if self.is_sync_write_path(&path) => SyncMode::All,
+            sync_mode => sync_mode,
+        };
+
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.handles
+            .write()
+            .unwrap()
+            .insert(fh, Arc::new(OpenFile {
+                file,
+                sync_mode,
+                dirty: AtomicU64::new(0),
+                digests_dropped: AtomicBool::new(false),
+            }));
+        Ok(fh)
     }
 
     async fn read(
         &self,
-        ino: u64,
+        _ctx: &RequestContext,
+        _ino: u64,
         fh: u64,
         offset: u64,
         size: u32,
         buf: &mut [u8],
     ) -> DatenLordResult<usize> {
-        // fill buf with size bytes from data
-        let data = vec![0; size as usize];
-        buf.copy_from_slice(&data);
-        Ok(data.len())
+        let handle = self.handle(fh)?;
+        let len = buf.len().min(size as usize);
+        let mut read = 0;
+        while read < len {
+            let n = handle
+                .file
+                .read_at(&mut buf[read..len], offset + read as u64)
+                .with_context(|| format!("failed to read file handle={fh}"))?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+        }
+        self.accessed(fh, &handle)?;
+        Ok(read)
     }
 
     async fn write(
         &self,
+        ctx: &RequestContext,
         ino: u64,
         fh: u64,
         offset: i64,
         data: &[u8],
         _flags: u32,
     ) -> DatenLordResult<()> {
-        Ok(())
+        let handle = self.handle(fh)?;
+        let offset = u64::try_from(offset).map_err(|_| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid write offset={offset}")],
+        })?;
+        self.check_reserved_space(data.len() as u64)?;
+        handle
+            .file
+            .write_all_at(data, offset)
+            .with_context(|| format!("failed to write file handle={fh}"))?;
+        self.written(ctx, ino, fh, &handle, data.len() as u64)
     }
 
-    async fn unlink(&self, _uid: u32, _gid: u32, _parent: INum, name: &str) -> DatenLordResult<()> {
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        let (src, dst) = (self.handle(param.fh_in)?, self.handle(param.fh_out)?);
+        let stat = |handle: &OpenFile, fh: u64| {
+            handle
+                .file
+                .metadata()
+                .with_context(|| format!("failed to stat file handle={fh}"))
+        };
+        let src_size = stat(&src, param.fh_in)?.len();
+        let len = param.len.min(src_size.saturating_sub(param.offset_in));
+        if len == 0 {
+            return Ok(CopyRangeResult::default());
+        }
+        let copy_failed = || format!("failed to copy file handle={} to {}", param.fh_in, param.fh_out);
+        // All of a file copied over the start of a file no larger leaves
+        // both with the same data, which their blocks can hold for both
+        let whole = param.offset_in == 0
+            && param.offset_out == 0
+            && len == src_size
+            && stat(&dst, param.fh_out)?.len() <= src_size;
+        if whole && platform::reflink(&src.file, &dst.file).with_context(copy_failed)? {
+            self.written(ctx, param.ino_out, param.fh_out, &dst, len)?;
+            self.accessed(param.fh_in, &src)?;
+            return Ok(CopyRangeResult {
+                copied: len,
+                reflinked: true,
+            });
+        }
+        self.check_reserved_space(len)?;
+        let copied = platform::copy_range(&src.file, param.offset_in, &dst.file, param.offset_out, len)
+            .with_context(copy_failed)?;
+        let Some(copied) = copied else {
+            return copy_range_through(self, ctx, param).await;
+        };
+        self.written(ctx, param.ino_out, param.fh_out, &dst, copied)?;
+        self.accessed(param.fh_in, &src)?;
+        Ok(CopyRangeResult {
+            copied,
+            reflinked: false,
+        })
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let path = self.child_path(ctx, parent, name)?;
+        Self::check_parent_access(ctx, &path)?;
+        let metadata = fs::symlink_metadata(&path)
+            .with_context(|| format!("failed to stat {path:?}"))?;
+        Self::check_sticky(ctx, &path, &metadata)?;
+        fs::remove_file(&path).with_context(|| format!("failed to remove {path:?}"))?;
+        self.changed(&path);
+        self.unregister(&path, metadata.nlink() > 1);
         Ok(())
     }
 
-    async fn mkdir(&self, param: CreateParam) -> DatenLordResult<(Duration, FileAttr, u64)> {
-        let path = format!("/tmp/{}", param.name);
-        // self.operator.create_dir(&path).await.unwrap();
-
-        let attr = FileAttr {
-            ino: param.parent,
-            size: 0,
-            blocks: 0,
-            atime: SystemTime::now(),
-            mtime: SystemTime::now(),
-            ctime: SystemTime::now(),
-            kind: param.node_type,
-            perm: param.mode as u16,
-            nlink: 2,
-            uid: param.uid,
-            gid: param.gid,
-            rdev: 0,
-        };
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let path = self.child_path(ctx, param.parent, &param.name)?;
+        Self::check_parent_access(ctx, &path)?;
+        Self::create_dir(&path, param.mode)?;
+        self.changed(&path);
+        Self::set_created_owner(ctx, &path, param.mode, true)?;
 
-        Ok((Duration::from_secs(1), attr, param.parent))
+        let attr = self.register(path)?;
+        Ok((ATTR_TTL, attr, 0))
     }
 
-    async fn rename(&self, _uid: u32, _gid: u32, param: RenameParam) -> DatenLordResult<()> {
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let flags = RenameFlags::from_bits(param.flags).ok_or_else(|| {
+            DatenLordError::InvalidArgument {
+                context: vec![format!("unsupported rename flags={:#x}", param.flags)],
+            }
+        })?;
+        let old_path = self.child_path(ctx, param.old_parent, &param.old_name)?;
+        let new_path = self.child_path(ctx, param.new_parent, &param.new_name)?;
+        Self::check_parent_access(ctx, &old_path)?;
+        Self::check_parent_access(ctx, &new_path)?;
+        let exchange = flags.contains(RenameFlags::RENAME_EXCHANGE);
+        Self::check_rename_entries(ctx, &old_path, &new_path, exchange)?;
+        renameat2(None, &old_path, None, &new_path, flags).with_context(|| {
+            format!("failed to rename {old_path:?} to {new_path:?}")
+        })?;
+        self.changed(&old_path);
+        if old_path.parent() != new_path.parent() {
+            self.changed(&new_path);
+        }
+        let renamed = self.relative(&old_path).and_then(|old| {
+            let new = self.relative(&new_path)?;
+            self.inodes.rename(old, new, exchange)
+        });
+        if let Err(e) = renamed {
+            warn!("failed to record the rename of {old_path:?} in the inode table: {e}");
+        }
         Ok(())
     }
 
     async fn release(
         &self,
-        ino: u64,
+        _ctx: &RequestContext,
+        _ino: u64,
         fh: u64,
         _flags: u32,
         _lock_owner: u64,
         _flush: bool,
     ) -> DatenLordResult<()> {
+        // The data still reaches the disk through the page cache
+        if let Some(handle) = self.handles.write().unwrap().remove(&fh) {
+            handle.take_dirty(&self.dirty);
+        }
         Ok(())
     }
 
-    async fn statfs(&self, _uid: u32, _gid: u32, ino: u64) -> DatenLordResult<StatFsParam> {
-        Ok(StatFsParam::default())
+    // The space of the filesystem holding the root
+    async fn statfs(&self, _ctx: &RequestContext, _ino: u64) -> DatenLordResult<StatFsParam> {
+        let root = &self.config.root;
+        let stat =
+            statvfs(root).with_context(|| format!("failed to stat filesystem of {root:?}"))?;
+        Ok(StatFsParam {
+            blocks: stat.blocks(),
+            bfree: stat.blocks_free(),
+            bavail: stat.blocks_available(),
+            files: stat.files(),
+            f_free: stat.files_free(),
+            bsize: u32::try_from(stat.block_size()).unwrap_or(u32::MAX),
+            namelen: u32::try_from(self.config.names.max_len).unwrap_or(u32::MAX),
+            frsize: u32::try_from(stat.fragment_size()).unwrap_or(u32::MAX),
+        })
     }
 
-    async fn fsync(&self, _ino: u64, _fh: u64, _datasync: bool) -> DatenLordResult<()> {
+    async fn fsync(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        let handle = self.handle(fh)?;
+        if datasync {
+            handle.file.sync_data()
+        } else {
+            handle.file.sync_all()
+        }
+        .with_context(|| format!("failed to sync file handle={fh}"))?;
+        handle.take_dirty(&self.dirty);
         Ok(())
     }
 
-    async fn flush(&self, _ino: u64, _fh: u64, _lock_owner: u64) -> DatenLordResult<()> {
+    async fn lseek(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        let handle = self.handle(fh)?;
+        let offset = i64::try_from(offset).map_err(|_| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid seek offset={offset}")],
+        })?;
+        let position = match whence {
+            SeekWhence::Data => SeekFrom::Data(offset),
+            SeekWhence::Hole => SeekFrom::Hole(offset),
+        };
+        match rustix::fs::seek(&handle.file, position) {
+            Ok(pos) => Ok(Some(pos)),
+            Err(rustix::io::Errno::NXIO) => Ok(None),
+            Err(e) => Err(std::io::Error::from(e))
+                .with_context(|| format!("failed to seek file handle={fh}")),
+        }
+    }
+
+    async fn sync_all(&self, _ctx: &RequestContext) -> DatenLordResult<()> {
+        let handles: Vec<_> = self.handles.read().unwrap().values().cloned().collect();
+        for handle in handles {
+            handle
+                .file
+                .sync_all()
+                .add_context("failed to sync open file")?;
+            handle.take_dirty(&self.dirty);
+        }
+        if let Err(e) = self.inodes.save_snapshot() {
+            warn!("failed to save the snapshot of the inode table: {e}");
+        }
+
+        let root = fs::File::open(&self.config.root)
+            .with_context(|| format!("failed to open root {:?}", self.config.root))?;
+        nix::unistd::syncfs(root.as_raw_fd())
+            .with_context(|| format!("failed to sync filesystem of {:?}", self.config.root))
+    }
+
+    async fn flush(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        _fh: u64,
+        _lock_owner: u64,
+    ) -> DatenLordResult<()> {
         Ok(())
     }
 
     async fn symlink(
         &self,
-        _uid: u32,
-        _gid: u32,
-        _parent: INum,
-        _name: &str,
-        _target_path: &Path,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
     ) -> DatenLordResult<(Duration, FileAttr, u64)> {
-        Ok((Duration::from_secs(1), FileAttr::default(), 0))
+        let path = self.child_path(ctx, parent, name)?;
+        Self::check_parent_access(ctx, &path)?;
+        // The target is kept as given, following it is confined to the root
+        std::os::unix::fs::symlink(target_path, &path)
+            .with_context(|| format!("failed to create symbolic link {path:?}"))?;
+        self.changed(&path);
+        let chown =
+            ctx.uid != nix::unistd::geteuid().as_raw() || ctx.gid != nix::unistd::getegid().as_raw();
+        if chown {
+            if let Err(e) = std::os::unix::fs::lchown(&path, Some(ctx.uid), Some(ctx.gid)) {
+                if let Err(remove_err) = fs::remove_file(&path) {
+                    warn!("failed to remove {path:?} after failing to set its owner: {remove_err}");
+                }
+                return Err(DatenLordError::from(e)
+                    .add_context(format!("failed to set the owner of {path:?}")));
+            }
+        }
+        let attr = self.register(path)?;
+        Ok((ATTR_TTL, attr, 0))
     }
 
     async fn readdir(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         ino: u64,
         fh: u64,
         offset: i64,
     ) -> DatenLordResult<Vec<DirEntry>> {
-        Ok(Vec::new())
+        Self::check_access(ctx, &self.inode_path(ctx, ino)?, ACCESS_READ)?;
+        self.dirs
+            .read(ino, fh, offset, || async { self.list_dir(ctx, ino, 0, false) })
+            .await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        Self::check_access(ctx, &self.inode_path(ctx, ino)?, ACCESS_READ)?;
+        let entries = self
+            .dirs
+            .read(ino, fh, offset, || async { self.list_dir(ctx, ino, 0, true) })
+            .await?;
+        let mut detailed = Vec::with_capacity(entries.len());
+        for mut entry in entries {
+            // Listed by a `readdir` of the handle, entries removed since
+            // are left out
+            let attr = match entry.attr {
+                Some(attr) => attr,
+                None => match self.getattr(ctx, entry.ino).await {
+                    Ok((_, attr)) => attr,
+                    Err(_) => continue,
+                },
+            };
+            entry.attr = Some(attr);
+            detailed.push((entry, attr, ATTR_TTL));
+        }
+        Ok(detailed)
     }
 
     async fn rmdir(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         parent: INum,
-        dir_name: &str,
+        dir_name: &OsStr,
     ) -> DatenLordResult<Option<INum>> {
-        Ok(None)
+        let path = self.child_path(ctx, parent, dir_name)?;
+        Self::check_parent_access(ctx, &path)?;
+        let metadata = fs::symlink_metadata(&path)
+            .with_context(|| format!("failed to stat {path:?}"))?;
+        Self::check_sticky(ctx, &path, &metadata)?;
+        fs::remove_dir(&path).with_context(|| format!("failed to remove directory {path:?}"))?;
+        self.changed(&path);
+        Ok(self.unregister(&path, false))
     }
 
-    async fn link(&self, _newparent: u64, _newname: &str) -> DatenLordResult<()> {
+    async fn link(
+        &self,
+        _ctx: &RequestContext,
+        _newparent: u64,
+        _newname: &OsStr,
+    ) -> DatenLordResult<()> {
         Ok(())
     }
 
     async fn forget(&self, _ino: u64, _nlookup: u64) {
     }
 
-    async fn mknod(&self, _param: CreateParam) -> DatenLordResult<(Duration, FileAttr, u64)> {
-        Ok((Duration::from_secs(1), FileAttr::default(), 0))
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let path = self.child_path(ctx, param.parent, &param.name)?;
+        Self::check_parent_access(ctx, &path)?;
+        nix::sys::stat::mknod(
+            &path,
+            param.node_type,
+            Mode::from_bits_truncate(param.mode),
+            param.rdev.into(),
+        )
+        .with_context(|| format!("failed to create node {path:?}"))?;
+        self.changed(&path);
+        Self::set_created_owner(ctx, &path, param.mode, false)?;
+
+        let attr = self.register(path)?;
+        Ok((ATTR_TTL, attr, 0))
     }
 
-    async fn opendir(&self, _uid: u32, _gid: u32, ino: u64, _flags: u32) -> DatenLordResult<u64> {
-        Ok(0)
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let param = CreateParam {
+            parent,
+            name: name.to_owned(),
+            mode,
+            rdev: 0,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        match self.mknod(ctx, param).await {
+            Err(DatenLordError::AlreadyExists { .. })
+                if !parse_oflag(flags).contains(OFlag::O_EXCL) =>
+            {
+                Ok(())
+            }
+            result => result.map(|_| ()),
+        }
     }
 
-    async fn releasedir(&self, _ino: u64, _fh: u64, _flags: u32) -> DatenLordResult<()> {
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        let path = self.inode_path(ctx, ino)?;
+        let access_mode = (mask & 0o7) as u8;
+        if access_mode == 0 {
+            return fs::symlink_metadata(&path)
+                .map(|_| ())
+                .with_context(|| format!("failed to stat {path:?}"));
+        }
+        Self::check_access(ctx, &path, access_mode)
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        _position: u32,
+    ) -> DatenLordResult<()> {
+        let path = self.inode_path(ctx, ino)?;
+        Self::check_access(ctx, &path, ACCESS_WRITE)?;
+        xattr::set(&path, name, value, flags)
+            .map_err(xattr_error(format!("failed to set xattr {name} of {path:?}")))?;
+        if name == DIGEST_XATTR {
+            self.digested.lock().unwrap().insert(ino);
+        }
         Ok(())
     }
 
-    async fn fsyncdir(&self, _ino: u64, _fh: u64, _datasync: bool) -> DatenLordResult<()> {
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        let path = self.inode_path(ctx, ino)?;
+        Self::check_access(ctx, &path, ACCESS_READ)?;
+        xattr::get(&path, name)
+            .map_err(xattr_error(format!("failed to get xattr {name} of {path:?}")))?
+            .ok_or_else(|| DatenLordError::InvalidArgument {
+                context: vec![format!("{path:?} has no xattr {name}")],
+            })
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        let path = self.inode_path(ctx, ino)?;
+        Self::check_access(ctx, &path, ACCESS_READ)?;
+        xattr::list(&path).map_err(xattr_error(format!("failed to list xattrs of {path:?}")))
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        let path = self.inode_path(ctx, ino)?;
+        Self::check_access(ctx, &path, ACCESS_WRITE)?;
+        xattr::remove(&path, name)
+            .map_err(xattr_error(format!("failed to remove xattr {name} of {path:?}")))
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, _flags: u32) -> DatenLordResult<u64> {
+        Self::check_access(ctx, &self.inode_path(ctx, ino)?, ACCESS_READ)?;
+        Ok(self.dirs.open(ino))
+    }
+
+    async fn releasedir(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        _flags: u32,
+    ) -> DatenLordResult<()> {
+        self.dirs.release(fh);
         Ok(())
     }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        _fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        let path = self.inode_path(ctx, ino)?;
+        fs::File::open(&path)
+            .and_then(|dir| if datasync { dir.sync_data() } else { dir.sync_all() })
+            .with_context(|| format!("failed to sync directory {path:?}"))
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        match cmd {
+            ioctl::IOCTL_PLACEMENT => {
+                let mut placement = self.inode_path(ctx, ino)?.into_os_string().into_vec();
+                placement.push(b'\n');
+                Ok(placement)
+            }
+            ioctl::IOCTL_FILE_FLAGS => {
+                let path = self.inode_path(ctx, ino)?;
+                let flags = platform::file_flags(&path)
+                    .with_context(|| format!("failed to read the flags of {path:?}"))?;
+                Ok(flags.to_le_bytes().to_vec())
+            }
+            _ => ioctl::dispatch(ctx, ino, cmd, input).await,
+        }
+    }
 }
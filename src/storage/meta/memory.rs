@@ -0,0 +1,131 @@
+//! A metadata store kept in memory, shared by the `SharedFs` instances of a
+//! process given the same store and lost with it
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use crate::common::DatenLordResult;
+
+use super::super::fs_util::{FileAttr, ROOT_ID};
+use super::super::virtualfs::INum;
+use super::MetaStore;
+
+#[derive(Debug, Default)]
+struct State {
+    last_ino: INum,
+    attrs: HashMap<INum, FileAttr>,
+    dirs: HashMap<INum, BTreeMap<OsString, INum>>,
+    /// The owner of every lock and when its lease ends
+    locks: HashMap<String, (String, Instant)>,
+}
+
+/// The metadata of a namespace kept in memory
+#[derive(Debug, Default)]
+pub struct MemoryMeta {
+    state: Mutex<State>,
+}
+
+#[async_trait]
+impl MetaStore for MemoryMeta {
+    async fn next_ino(&self) -> DatenLordResult<INum> {
+        let mut state = self.state.lock().unwrap();
+        state.last_ino = state.last_ino.max(ROOT_ID) + 1;
+        Ok(state.last_ino)
+    }
+
+    async fn get_attr(&self, ino: INum) -> DatenLordResult<Option<FileAttr>> {
+        Ok(self.state.lock().unwrap().attrs.get(&ino).copied())
+    }
+
+    async fn set_attr(&self, attr: &FileAttr) -> DatenLordResult<()> {
+        self.state.lock().unwrap().attrs.insert(attr.ino, *attr);
+        Ok(())
+    }
+
+    async fn insert_attr(&self, attr: &FileAttr) -> DatenLordResult<bool> {
+        let mut state = self.state.lock().unwrap();
+        if state.attrs.contains_key(&attr.ino) {
+            return Ok(false);
+        }
+        state.attrs.insert(attr.ino, *attr);
+        Ok(true)
+    }
+
+    async fn remove_attr(&self, ino: INum) -> DatenLordResult<()> {
+        let mut state = self.state.lock().unwrap();
+        state.attrs.remove(&ino);
+        state.dirs.remove(&ino);
+        Ok(())
+    }
+
+    async fn get_entry(&self, parent: INum, name: &OsStr) -> DatenLordResult<Option<INum>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .dirs
+            .get(&parent)
+            .and_then(|entries| entries.get(name))
+            .copied())
+    }
+
+    async fn set_entry(
+        &self,
+        parent: INum,
+        name: &OsStr,
+        ino: INum,
+        exclusive: bool,
+    ) -> DatenLordResult<bool> {
+        let mut state = self.state.lock().unwrap();
+        let entries = state.dirs.entry(parent).or_default();
+        if exclusive && entries.contains_key(name) {
+            return Ok(false);
+        }
+        entries.insert(name.to_owned(), ino);
+        Ok(true)
+    }
+
+    async fn remove_entry(&self, parent: INum, name: &OsStr) -> DatenLordResult<()> {
+        if let Some(entries) = self.state.lock().unwrap().dirs.get_mut(&parent) {
+            entries.remove(name);
+        }
+        Ok(())
+    }
+
+    async fn entries(&self, parent: INum) -> DatenLordResult<Vec<(OsString, INum)>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.dirs.get(&parent).map_or_else(Vec::new, |entries| {
+            entries
+                .iter()
+                .map(|(name, &ino)| (name.clone(), ino))
+                .collect()
+        }))
+    }
+
+    async fn try_lock(&self, key: &str, owner: &str, lease: Duration) -> DatenLordResult<bool> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if let Some(&(_, expires)) = state.locks.get(key) {
+            if expires > now {
+                return Ok(false);
+            }
+        }
+        state
+            .locks
+            .insert(key.to_owned(), (owner.to_owned(), now + lease));
+        Ok(true)
+    }
+
+    async fn unlock(&self, key: &str, owner: &str) -> DatenLordResult<()> {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .locks
+            .get(key)
+            .is_some_and(|(holder, _)| holder == owner)
+        {
+            state.locks.remove(key);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! Metadata backends holding the directory tree and the attributes of a
+//! namespace, shared by `SharedFs` instances on several hosts
+//!
+//! A store only offers single-key operations; `SharedFs` serializes the
+//! changes spanning several keys, like a rename, with the lease locks of
+//! the store, which expire if their holder dies.
+use std::ffi::{OsStr, OsString};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::FileAttr;
+use super::virtualfs::INum;
+
+pub mod memory;
+pub mod redis;
+
+pub use self::memory::MemoryMeta;
+pub use self::redis::RedisMeta;
+
+/// The directory tree and the attributes of a namespace
+#[async_trait]
+pub trait MetaStore: Debug + Send + Sync {
+    /// A new inode number, never handed out before, above `ROOT_ID`
+    async fn next_ino(&self) -> DatenLordResult<INum>;
+
+    /// The attributes of inode `ino`, `None` when it does not exist
+    async fn get_attr(&self, ino: INum) -> DatenLordResult<Option<FileAttr>>;
+
+    /// Store the attributes of inode `attr.ino`
+    async fn set_attr(&self, attr: &FileAttr) -> DatenLordResult<()>;
+
+    /// Store the attributes of inode `attr.ino` unless it exists, returning
+    /// whether they were stored
+    async fn insert_attr(&self, attr: &FileAttr) -> DatenLordResult<bool>;
+
+    /// Drop the attributes of inode `ino`, and its entries if a directory
+    async fn remove_attr(&self, ino: INum) -> DatenLordResult<()>;
+
+    /// The inode of the entry `name` of directory `parent`
+    async fn get_entry(&self, parent: INum, name: &OsStr) -> DatenLordResult<Option<INum>>;
+
+    /// Point the entry `name` of directory `parent` at `ino`, replacing the
+    /// former one unless `exclusive`, returning whether it was set
+    async fn set_entry(
+        &self,
+        parent: INum,
+        name: &OsStr,
+        ino: INum,
+        exclusive: bool,
+    ) -> DatenLordResult<bool>;
+
+    /// Drop the entry `name` of directory `parent`
+    async fn remove_entry(&self, parent: INum, name: &OsStr) -> DatenLordResult<()>;
+
+    /// The entries of directory `parent`, in no particular order
+    async fn entries(&self, parent: INum) -> DatenLordResult<Vec<(OsString, INum)>>;
+
+    /// Take the lock `key` for `owner` unless another owner holds it,
+    /// releasing it after `lease` anyway, returning whether it was taken
+    async fn try_lock(&self, key: &str, owner: &str, lease: Duration) -> DatenLordResult<bool>;
+
+    /// Release the lock `key` if `owner` still holds it
+    async fn unlock(&self, key: &str, owner: &str) -> DatenLordResult<()>;
+}
+
+/// A metadata backend configured in `SharedConfig::meta`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MetaConfig {
+    /// Kept in memory, only shared by the instances of the process
+    #[default]
+    Memory,
+    /// Kept in a redis server
+    Redis {
+        /// `host:port` of the server
+        address: String,
+        /// The prefix of every key, so namespaces share a server
+        #[serde(default = "default_prefix")]
+        prefix: String,
+        /// The password to `AUTH` with
+        #[serde(default)]
+        password: Option<String>,
+    },
+}
+
+fn default_prefix() -> String {
+    "datenlord".to_owned()
+}
+
+impl MetaConfig {
+    /// Build the store
+    pub fn build(&self) -> Arc<dyn MetaStore> {
+        match *self {
+            Self::Memory => Arc::new(MemoryMeta::default()),
+            Self::Redis {
+                ref address,
+                ref prefix,
+                ref password,
+            } => Arc::new(RedisMeta::new(address, prefix, password.clone())),
+        }
+    }
+}
+
+/// A name telling the lock holders of every instance apart
+pub(crate) fn lock_owner() -> String {
+    static INSTANCES: AtomicU64 = AtomicU64::new(0);
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let instance = INSTANCES.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{started}-{instance}", std::process::id())
+}
+
+/// The lease locks held by an operation, released with `release`
+#[derive(Debug)]
+pub(crate) struct MetaLock {
+    store: Arc<dyn MetaStore>,
+    owner: String,
+    keys: Vec<String>,
+}
+
+impl MetaLock {
+    /// Take the locks `keys` for `owner` in order, so operations taking
+    /// several never deadlock, waiting up to twice the lease for each
+    ///
+    /// Fails with `DatenLordError::Unavailable` when a lock stays taken.
+    pub(crate) async fn acquire(
+        store: &Arc<dyn MetaStore>,
+        owner: &str,
+        mut keys: Vec<String>,
+        lease: Duration,
+    ) -> DatenLordResult<Self> {
+        keys.sort();
+        keys.dedup();
+        let mut lock = Self {
+            store: Arc::clone(store),
+            owner: owner.to_owned(),
+            keys: Vec::with_capacity(keys.len()),
+        };
+        for key in keys {
+            let deadline = Instant::now() + lease * 2;
+            let mut backoff = Duration::from_millis(1);
+            loop {
+                match store.try_lock(&key, owner, lease).await {
+                    Ok(true) => break,
+                    Ok(false) if Instant::now() < deadline => {
+                        tokio::time::sleep(backoff).await;
+                        backoff = (backoff * 2).min(Duration::from_millis(50));
+                    }
+                    Ok(false) => {
+                        lock.release().await;
+                        return Err(DatenLordError::Unavailable {
+                            context: vec![format!("lock {key} is still held by another owner")],
+                            source: None,
+                        });
+                    }
+                    Err(e) => {
+                        lock.release().await;
+                        return Err(e);
+                    }
+                }
+            }
+            lock.keys.push(key);
+        }
+        Ok(lock)
+    }
+
+    /// Release the locks, left to expire when the store fails
+    pub(crate) async fn release(self) {
+        for key in self.keys.iter().rev() {
+            if let Err(e) = self.store.unlock(key, &self.owner).await {
+                warn!("failed to release lock {key}, it expires with its lease: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! A metadata store kept in a redis server, spoken to over RESP
+//!
+//! Under the prefix of the namespace, `<prefix>:next` counts the inode
+//! numbers handed out, `<prefix>:attr:<ino>` holds the attributes of an
+//! inode as JSON, `<prefix>:dir:<ino>` the entries of a directory as a hash
+//! of names to inode numbers and `<prefix>:lock:<key>` the owner of a lock,
+//! expiring with its lease.
+use std::ffi::{OsStr, OsString};
+use std::future::Future;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::pin::Pin;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::super::fs_util::{FileAttr, ROOT_ID};
+use super::super::virtualfs::INum;
+use super::MetaStore;
+
+/// Deletes a lock only while its owner holds it
+const UNLOCK_SCRIPT: &str =
+    "if redis.call('get', KEYS[1]) == ARGV[1] then return redis.call('del', KEYS[1]) end return 0";
+
+/// A reply of the server
+#[derive(Debug)]
+enum Reply {
+    Status,
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+/// Read a reply from `stream`
+fn read_reply(
+    stream: &mut BufStream<TcpStream>,
+) -> Pin<Box<dyn Future<Output = std::io::Result<Reply>> + Send + '_>> {
+    Box::pin(async move {
+        let mut line = Vec::new();
+        stream.read_until(b'\n', &mut line).await?;
+        if !line.ends_with(b"\r\n") {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        let text = String::from_utf8_lossy(&line[1..line.len() - 2]).into_owned();
+        let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid reply");
+        let len = || text.parse::<i64>().map_err(|_| invalid());
+        Ok(match line[0] {
+            b'+' => Reply::Status,
+            b'-' => Reply::Error(text),
+            b':' => Reply::Integer(len()?),
+            b'$' => match usize::try_from(len()?) {
+                Ok(len) => {
+                    let mut data = vec![0; len + 2];
+                    stream.read_exact(&mut data).await?;
+                    data.truncate(len);
+                    Reply::Bulk(Some(data))
+                }
+                Err(_) => Reply::Bulk(None),
+            },
+            b'*' => match usize::try_from(len()?) {
+                Ok(len) => {
+                    let mut items = Vec::with_capacity(len.min(1024));
+                    for _ in 0..len {
+                        items.push(read_reply(stream).await?);
+                    }
+                    Reply::Array(Some(items))
+                }
+                Err(_) => Reply::Array(None),
+            },
+            _ => return Err(invalid()),
+        })
+    })
+}
+
+/// The metadata of a namespace kept in a redis server
+#[derive(Debug)]
+pub struct RedisMeta {
+    /// `host:port` of the server
+    address: String,
+    /// The prefix of every key
+    prefix: String,
+    /// The password to `AUTH` with
+    password: Option<String>,
+    /// The connection, opened on first use and again after a failure
+    connection: Mutex<Option<BufStream<TcpStream>>>,
+}
+
+impl RedisMeta {
+    /// A store keeping its keys under `prefix` in the server at `address`,
+    /// connected to on first use
+    #[must_use]
+    pub fn new(address: &str, prefix: &str, password: Option<String>) -> Self {
+        Self {
+            address: address.to_owned(),
+            prefix: prefix.to_owned(),
+            password,
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn key(&self, kind: &str, id: impl std::fmt::Display) -> String {
+        format!("{}:{kind}:{id}", self.prefix)
+    }
+
+    async fn connect(&self) -> std::io::Result<BufStream<TcpStream>> {
+        let mut stream = BufStream::new(TcpStream::connect(&self.address).await?);
+        if let Some(ref password) = self.password {
+            let args: [&[u8]; 2] = [b"AUTH", password.as_bytes()];
+            if let Reply::Error(e) = Self::round_trip(&mut stream, &args).await? {
+                return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, e));
+            }
+        }
+        Ok(stream)
+    }
+
+    async fn round_trip(
+        stream: &mut BufStream<TcpStream>,
+        args: &[&[u8]],
+    ) -> std::io::Result<Reply> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg);
+            request.extend_from_slice(b"\r\n");
+        }
+        stream.write_all(&request).await?;
+        stream.flush().await?;
+        read_reply(stream).await
+    }
+
+    /// Run the command `args`, failing with `DatenLordError::Unavailable`
+    /// when the server cannot be reached
+    ///
+    /// A command is not sent again once the connection fails, since it may
+    /// have run; the next one reconnects.
+    async fn call(&self, args: &[&[u8]]) -> DatenLordResult<Reply> {
+        let command = String::from_utf8_lossy(args[0]);
+        let unavailable = |e: std::io::Error| DatenLordError::Unavailable {
+            context: vec![format!("redis {} failed {command}: {e}", self.address)],
+            source: None,
+        };
+        let mut connection = self.connection.lock().await;
+        let stream = match *connection {
+            Some(ref mut stream) => stream,
+            None => connection.insert(self.connect().await.map_err(unavailable)?),
+        };
+        match Self::round_trip(stream, args).await {
+            Ok(Reply::Error(e)) => Err(DatenLordError::Io {
+                context: vec![format!("redis {} failed {command}: {e}", self.address)],
+                source: None,
+            }),
+            Ok(reply) => Ok(reply),
+            Err(e) => {
+                *connection = None;
+                Err(unavailable(e))
+            }
+        }
+    }
+
+    fn unexpected(&self, reply: &Reply) -> DatenLordError {
+        DatenLordError::Corrupted {
+            context: vec![format!(
+                "unexpected reply {reply:?} from redis {}",
+                self.address
+            )],
+        }
+    }
+
+    fn parse_ino(&self, data: &[u8]) -> DatenLordResult<INum> {
+        std::str::from_utf8(data)
+            .ok()
+            .and_then(|text| text.parse().ok())
+            .ok_or_else(|| DatenLordError::Corrupted {
+                context: vec![format!(
+                    "invalid inode number {:?} in redis {}",
+                    String::from_utf8_lossy(data),
+                    self.address
+                )],
+            })
+    }
+}
+
+#[async_trait]
+impl MetaStore for RedisMeta {
+    async fn next_ino(&self) -> DatenLordResult<INum> {
+        let key = format!("{}:next", self.prefix);
+        match self.call(&[b"INCR", key.as_bytes()]).await? {
+            Reply::Integer(n) => Ok(ROOT_ID + n.unsigned_abs()),
+            reply => Err(self.unexpected(&reply)),
+        }
+    }
+
+    async fn get_attr(&self, ino: INum) -> DatenLordResult<Option<FileAttr>> {
+        let key = self.key("attr", ino);
+        match self.call(&[b"GET", key.as_bytes()]).await? {
+            Reply::Bulk(None) => Ok(None),
+            Reply::Bulk(Some(json)) => {
+                serde_json::from_slice(&json)
+                    .map(Some)
+                    .map_err(|e| DatenLordError::Corrupted {
+                        context: vec![format!("invalid attributes of inode={ino} in redis: {e}")],
+                    })
+            }
+            reply => Err(self.unexpected(&reply)),
+        }
+    }
+
+    async fn set_attr(&self, attr: &FileAttr) -> DatenLordResult<()> {
+        let key = self.key("attr", attr.ino);
+        let json = serde_json::to_vec(attr).unwrap();
+        self.call(&[b"SET", key.as_bytes(), &json]).await?;
+        Ok(())
+    }
+
+    async fn insert_attr(&self, attr: &FileAttr) -> DatenLordResult<bool> {
+        let key = self.key("attr", attr.ino);
+        let json = serde_json::to_vec(attr).unwrap();
+        match self.call(&[b"SET", key.as_bytes(), &json, b"NX"]).await? {
+            Reply::Status => Ok(true),
+            Reply::Bulk(None) => Ok(false),
+            reply => Err(self.unexpected(&reply)),
+        }
+    }
+
+    async fn remove_attr(&self, ino: INum) -> DatenLordResult<()> {
+        let attr = self.key("attr", ino);
+        let dir = self.key("dir", ino);
+        self.call(&[b"DEL", attr.as_bytes(), dir.as_bytes()])
+            .await?;
+        Ok(())
+    }
+
+    async fn get_entry(&self, parent: INum, name: &OsStr) -> DatenLordResult<Option<INum>> {
+        let key = self.key("dir", parent);
+        match self
+            .call(&[b"HGET", key.as_bytes(), name.as_bytes()])
+            .await?
+        {
+            Reply::Bulk(None) => Ok(None),
+            Reply::Bulk(Some(ino)) => self.parse_ino(&ino).map(Some),
+            reply => Err(self.unexpected(&reply)),
+        }
+    }
+
+    async fn set_entry(
+        &self,
+        parent: INum,
+        name: &OsStr,
+        ino: INum,
+        exclusive: bool,
+    ) -> DatenLordResult<bool> {
+        let key = self.key("dir", parent);
+        let command: &[u8] = if exclusive { b"HSETNX" } else { b"HSET" };
+        let ino = ino.to_string();
+        match self
+            .call(&[command, key.as_bytes(), name.as_bytes(), ino.as_bytes()])
+            .await?
+        {
+            Reply::Integer(set) => Ok(!exclusive || set == 1),
+            reply => Err(self.unexpected(&reply)),
+        }
+    }
+
+    async fn remove_entry(&self, parent: INum, name: &OsStr) -> DatenLordResult<()> {
+        let key = self.key("dir", parent);
+        self.call(&[b"HDEL", key.as_bytes(), name.as_bytes()])
+            .await?;
+        Ok(())
+    }
+
+    async fn entries(&self, parent: INum) -> DatenLordResult<Vec<(OsString, INum)>> {
+        let key = self.key("dir", parent);
+        let items = match self.call(&[b"HGETALL", key.as_bytes()]).await? {
+            Reply::Array(items) => items.unwrap_or_default(),
+            reply => return Err(self.unexpected(&reply)),
+        };
+        let mut entries = Vec::with_capacity(items.len() / 2);
+        let mut items = items.into_iter();
+        while let (Some(name), Some(ino)) = (items.next(), items.next()) {
+            match (name, ino) {
+                (Reply::Bulk(Some(name)), Reply::Bulk(Some(ino))) => {
+                    entries.push((OsString::from_vec(name), self.parse_ino(&ino)?));
+                }
+                (reply, _) => return Err(self.unexpected(&reply)),
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn try_lock(&self, key: &str, owner: &str, lease: Duration) -> DatenLordResult<bool> {
+        let key = self.key("lock", key);
+        let lease = lease.as_millis().max(1).to_string();
+        let args: [&[u8]; 6] = [
+            b"SET",
+            key.as_bytes(),
+            owner.as_bytes(),
+            b"NX",
+            b"PX",
+            lease.as_bytes(),
+        ];
+        match self.call(&args).await? {
+            Reply::Status => Ok(true),
+            Reply::Bulk(None) => Ok(false),
+            reply => Err(self.unexpected(&reply)),
+        }
+    }
+
+    async fn unlock(&self, key: &str, owner: &str) -> DatenLordResult<()> {
+        let key = self.key("lock", key);
+        let args: [&[u8]; 5] = [
+            b"EVAL",
+            UNLOCK_SCRIPT.as_bytes(),
+            b"1",
+            key.as_bytes(),
+            owner.as_bytes(),
+        ];
+        self.call(&args).await?;
+        Ok(())
+    }
+}
@@ -1,5 +1,51 @@
 //! The storage implementation.
+#![forbid(unsafe_code)]
 
 pub mod virtualfs;
+pub mod appendlog;
+pub mod archive;
+pub mod audit;
+pub mod backpressure;
+pub mod cache;
+pub mod dedup;
+pub mod digest;
+pub(crate) mod dir_handle;
+pub mod faulty;
+pub mod idmap;
+pub(crate) mod inode_table;
+pub mod filelock;
+pub mod filter;
+pub mod gc;
+pub mod health;
+pub mod interrupt;
+pub mod ioctl;
+pub mod kv;
+pub mod layout;
 pub mod localfs;
-pub mod fs_util;
\ No newline at end of file
+pub mod meta;
+pub mod mount;
+pub mod notify;
+pub mod overlay;
+pub mod packing;
+pub mod platform;
+pub mod fs_util;
+pub mod replication;
+pub mod retry;
+pub(crate) mod safe_path;
+#[cfg(feature = "search")]
+pub mod search;
+pub mod sharedfs;
+pub mod stats;
+pub mod stream;
+pub mod striping;
+pub mod superblock;
+pub mod tags;
+pub mod timeout;
+pub mod trash;
+pub mod transfer;
+pub mod upload;
+pub mod versioning;
+pub mod walk;
+pub mod wire;
+pub mod writeback;
+pub(crate) mod xattr;
\ No newline at end of file
@@ -0,0 +1,1175 @@
+//! A namespace composed of several backends, each mounted under a directory
+//! of the root backend, e.g. `hot` on a local NVMe drive and `archive` in S3
+//!
+//! Like a mount of the kernel, a mount point is a directory of the root
+//! backend, created when missing, whose entries the mounted backend hides;
+//! it cannot be removed or renamed, nor can the directories holding it.
+//! Mounts do not nest, and symbolic links are followed by the backend
+//! holding them, so they do not lead into other mounts.
+//!
+//! The inode numbers of a mounted backend are those it returns with the
+//! index of the mount in their upper 16 bits, so they stay valid as long as
+//! the backend's own, across restarts for `LocalFS`; the root backend keeps
+//! its numbers. Backends must number their inodes below 2^48. File handles
+//! are those of the backends, every call with a handle also naming the
+//! inode it is open on.
+//!
+//! Renaming an entry to another mount copies it, everything below it for a
+//! directory, to a temporary name next to the destination, renames the copy
+//! into place and removes the source, like `mv` across filesystems; a
+//! failure before the removal leaves the source as it was.
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use nix::errno::Errno;
+use nix::fcntl::RenameFlags;
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+use crate::common::config::DatenLordConfig;
+use crate::common::{DatenLordError, DatenLordResult, ResultExt};
+
+use super::fs_util::{
+    self, CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam, UtimeSpec, ROOT_ID,
+};
+use super::layout::BlockLayout;
+use super::localfs::LocalFS;
+use super::overlay::{copy_attr, copy_data, copy_xattrs};
+use super::sharedfs::{SharedConfig, SharedFs};
+use super::trash::{list_dir, remove_all};
+use super::virtualfs::{self, INum, VirtualFs};
+
+/// The bits of an inode number left to the backend holding it
+const MOUNT_SHIFT: u32 = 48;
+/// The most mounts a table holds, index 0 being the root backend
+const MAX_MOUNTS: usize = (1 << (64 - MOUNT_SHIFT)) - 1;
+/// The prefix of the names entries moved from another mount are copied to
+const MOVING_PREFIX: &str = ".datenlord_moving.";
+/// The TTL of the attributes of mount points and parents of mount roots,
+/// whose backends differ from their neighbours'
+const ATTR_TTL: Duration = Duration::from_secs(1);
+
+/// Permission bits checked with `FileAttr::check_perm`
+const ACCESS_WRITE: u8 = 0o2;
+const ACCESS_EXEC: u8 = 0o1;
+
+/// A backend mounted under a directory of the namespace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountConfig {
+    /// The mount point, relative to the root
+    pub path: String,
+    /// The backend mounted there
+    pub backend: MountBackend,
+}
+
+/// The backends a `MountTable` mounts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MountBackend {
+    /// A local directory, like the root of the namespace
+    Local {
+        /// The root of the backend, created when missing
+        root: PathBuf,
+    },
+    /// A `SharedFs`, its data in any opendal service like S3, connected on
+    /// first use so an unreachable store only fails the calls under its
+    /// mount
+    Shared(SharedConfig),
+}
+
+/// A mounted backend
+struct Mount {
+    /// The mount point, relative to the root
+    path: PathBuf,
+    fs: OnceCell<Box<dyn VirtualFs>>,
+    /// The namespace connected to on first use, `None` for the backends
+    /// given built
+    shared: Option<SharedConfig>,
+}
+
+impl fmt::Debug for Mount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mount")
+            .field("path", &self.path)
+            .field("connected", &self.fs.initialized())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where the mount points are in the root backend, found on first use
+#[derive(Debug, Default)]
+struct Points {
+    /// The mount index of each entry `(dir, name)` of the root backend
+    /// covered by a mount
+    by_entry: HashMap<(INum, OsString), usize>,
+    /// The directory of the root backend holding each mount point, by mount
+    /// index less one
+    parents: Vec<INum>,
+    /// The directories of the root backend on the way to a mount point, the
+    /// mount points included
+    busy: HashSet<INum>,
+}
+
+/// A filesystem routing the paths under its mount points to the backends
+/// mounted there and the others to its root backend, see the module
+/// documentation
+#[derive(Debug)]
+pub struct MountTable<F> {
+    root: F,
+    /// The mounts, mount index `i` being `mounts[i - 1]`
+    mounts: Vec<Mount>,
+    points: OnceCell<Points>,
+    /// The context the mount points are created with
+    mounter: RequestContext,
+    /// The number of the next temporary name of a move across mounts
+    next_move: AtomicU64,
+}
+
+/// `ctx` for the backend of mount `index`, with the root of the caller when
+/// it is in that backend so the backend confines the caller too, else with
+/// the root of the backend
+fn layer_ctx(ctx: &RequestContext, index: usize) -> RequestContext {
+    let (root_index, root) = decode(ctx.root);
+    RequestContext {
+        root: if root_index == index { root } else { ROOT_ID },
+        ..*ctx
+    }
+}
+
+/// The inode number of the table for inode `ino` of the backend of mount
+/// `index`
+fn encode(index: usize, ino: INum) -> DatenLordResult<INum> {
+    if ino >> MOUNT_SHIFT != 0 {
+        return Err(DatenLordError::Internal {
+            context: vec![format!(
+                "inode={ino} of mount {index} does not fit in {MOUNT_SHIFT} bits"
+            )],
+        });
+    }
+    Ok(((index as INum) << MOUNT_SHIFT) | ino)
+}
+
+/// The mount index and the inode number in its backend of inode `ino`
+fn decode(ino: INum) -> (usize, INum) {
+    (
+        (ino >> MOUNT_SHIFT) as usize,
+        ino & ((1 << MOUNT_SHIFT) - 1),
+    )
+}
+
+/// `attr` of the backend of mount `index` as attributes of the table
+fn outer(index: usize, mut attr: FileAttr) -> DatenLordResult<FileAttr> {
+    attr.ino = encode(index, attr.ino)?;
+    Ok(attr)
+}
+
+/// The error `errno` with `context`
+fn errno(errno: Errno, context: String) -> DatenLordError {
+    DatenLordError::from(errno).add_context(context)
+}
+
+/// The error of removing or renaming a mount point, or a directory on the
+/// way to one, not transient unlike other `EBUSY` errors
+fn busy(name: &OsStr) -> DatenLordError {
+    DatenLordError::Io {
+        context: vec![format!("{name:?} is or holds a mount point")],
+        source: Some(Box::new(Errno::EBUSY)),
+    }
+}
+
+/// The mount points `paths` normalized, failing if one is the root, leaves
+/// it or nests in another
+fn check_paths<'a>(paths: impl Iterator<Item = &'a OsStr>) -> DatenLordResult<Vec<PathBuf>> {
+    let mut checked: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        let normalized = PathBuf::from(fs_util::normalize(path));
+        if normalized.as_os_str().is_empty() || normalized.iter().any(|c| c == "..") {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("invalid mount point {path:?}")],
+            });
+        }
+        if let Some(other) = checked
+            .iter()
+            .find(|other| other.starts_with(&normalized) || normalized.starts_with(other))
+        {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("mount points {other:?} and {path:?} nest")],
+            });
+        }
+        checked.push(normalized);
+    }
+    if checked.len() > MAX_MOUNTS {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("more than {MAX_MOUNTS} mounts")],
+        });
+    }
+    Ok(checked)
+}
+
+/// Copy the entry `src` of `from`, everything below it for a directory, to
+/// the entry `name` of the directory `parent` of `to`
+async fn copy_tree<F: VirtualFs + ?Sized, G: VirtualFs + ?Sized>(
+    from: &F,
+    ctx: &RequestContext,
+    src: INum,
+    to: &G,
+    parent: INum,
+    name: &OsStr,
+) -> DatenLordResult<()> {
+    let (_, attr) = from.getattr(ctx, src).await?;
+    let mut pending = vec![(attr, parent, name.to_owned())];
+    let mut dirs = Vec::new();
+    while let Some((attr, parent, name)) = pending.pop() {
+        let param = CreateParam {
+            parent,
+            name: name.clone(),
+            mode: u32::from(attr.perm),
+            rdev: attr.rdev,
+            node_type: attr.kind,
+            link: None,
+        };
+        let (_, copy, _) = match attr.kind {
+            // Writable by the caller until its entries are copied
+            SFlag::S_IFDIR => {
+                let param = CreateParam {
+                    mode: param.mode | 0o700,
+                    ..param
+                };
+                to.mkdir(ctx, param).await?
+            }
+            SFlag::S_IFLNK => {
+                let target = from.readlink(ctx, attr.ino).await?;
+                to.symlink(ctx, parent, &name, Path::new(OsStr::from_bytes(&target)))
+                    .await?
+            }
+            _ => to.mknod(ctx, param).await?,
+        };
+        match attr.kind {
+            SFlag::S_IFDIR => {
+                for (child, child_attr) in list_dir(from, ctx, attr.ino).await? {
+                    pending.push((child_attr, copy.ino, child));
+                }
+                dirs.push((copy.ino, attr));
+            }
+            SFlag::S_IFLNK => {}
+            kind => {
+                if kind == SFlag::S_IFREG {
+                    copy_data(from, ctx, attr.ino, to, copy.ino).await?;
+                }
+                copy_attr(to, ctx, copy.ino, &attr).await?;
+            }
+        }
+        copy_xattrs(from, ctx, attr.ino, to, copy.ino).await;
+    }
+    // Last, copying their entries changed their times
+    for (ino, attr) in dirs.iter().rev() {
+        copy_attr(to, ctx, *ino, attr).await?;
+    }
+    Ok(())
+}
+
+impl<F: VirtualFs> MountTable<F> {
+    /// Mount the backends of the `mounts` of `config` over `root`, with the
+    /// `names` and `atime` of `config`
+    pub fn new(root: F, config: &DatenLordConfig) -> DatenLordResult<Self> {
+        let mounts = &config.mounts;
+        let paths = check_paths(mounts.iter().map(|mount| OsStr::new(&mount.path)))?;
+        let mut built = Vec::with_capacity(mounts.len());
+        for (path, mount) in paths.into_iter().zip(mounts) {
+            let (fs, shared) = match mount.backend {
+                MountBackend::Local { ref root } => {
+                    let config = DatenLordConfig {
+                        root: root.clone(),
+                        names: config.names.clone(),
+                        atime: config.atime,
+                        ..DatenLordConfig::default()
+                    };
+                    let fs = LocalFS::new(&config)
+                        .with_context(|| format!("failed to mount {root:?} on {path:?}"))?;
+                    let fs: Box<dyn VirtualFs> = Box::new(fs);
+                    (OnceCell::from(fs), None)
+                }
+                MountBackend::Shared(ref shared) => {
+                    let config = SharedConfig {
+                        names: config.names.clone(),
+                        atime: config.atime,
+                        ..shared.clone()
+                    };
+                    (OnceCell::new(), Some(config))
+                }
+            };
+            built.push(Mount { path, fs, shared });
+        }
+        Ok(Self::build(root, built))
+    }
+
+    /// Like `new`, with the backends `mounts` given built, by mount point
+    pub fn with_backends(
+        root: F,
+        mounts: Vec<(PathBuf, Box<dyn VirtualFs>)>,
+    ) -> DatenLordResult<Self> {
+        let paths = check_paths(mounts.iter().map(|(path, _)| path.as_os_str()))?;
+        let mounts = paths
+            .into_iter()
+            .zip(mounts)
+            .map(|(path, (_, fs))| Mount {
+                path,
+                fs: OnceCell::from(fs),
+                shared: None,
+            })
+            .collect();
+        Ok(Self::build(root, mounts))
+    }
+
+    fn build(root: F, mounts: Vec<Mount>) -> Self {
+        Self {
+            root,
+            mounts,
+            points: OnceCell::new(),
+            mounter: RequestContext::current(),
+            next_move: AtomicU64::new(0),
+        }
+    }
+
+    /// The root backend
+    pub fn root(&self) -> &F {
+        &self.root
+    }
+
+    /// The mount points, relative to the root
+    pub fn mount_points(&self) -> impl Iterator<Item = &Path> {
+        self.mounts.iter().map(|mount| mount.path.as_path())
+    }
+
+    /// The backend of mount `index`, connected if not yet
+    async fn backend(&self, index: usize) -> DatenLordResult<&dyn VirtualFs> {
+        if index == 0 {
+            return Ok(&self.root);
+        }
+        let mount = self
+            .mounts
+            .get(index - 1)
+            .ok_or_else(|| DatenLordError::NotFound {
+                context: vec![format!("no mount {index}")],
+                source: None,
+            })?;
+        let fs = mount
+            .fs
+            .get_or_try_init(|| async {
+                let config = mount
+                    .shared
+                    .as_ref()
+                    .ok_or_else(|| DatenLordError::Internal {
+                        context: vec![format!("mount {:?} has no backend", mount.path)],
+                    })?;
+                let fs = SharedFs::new(config)
+                    .await
+                    .with_context(|| format!("failed to connect the mount {:?}", mount.path))?;
+                Ok::<_, DatenLordError>(Box::new(fs) as Box<dyn VirtualFs>)
+            })
+            .await?;
+        Ok(&**fs)
+    }
+
+    /// The backends connected, the root backend first
+    fn connected(&self) -> impl Iterator<Item = &dyn VirtualFs> {
+        let mounts = self.mounts.iter().filter_map(|mount| mount.fs.get());
+        std::iter::once(&self.root as &dyn VirtualFs).chain(mounts.map(|fs| &**fs))
+    }
+
+    /// The mount points, created in the root backend on first use
+    async fn points(&self) -> DatenLordResult<&Points> {
+        self.points
+            .get_or_try_init(|| async {
+                let mut points = Points::default();
+                for (i, mount) in self.mounts.iter().enumerate() {
+                    let (mut parent, mut dir) = (ROOT_ID, ROOT_ID);
+                    for component in &mount.path {
+                        let attr = self
+                            .root
+                            .mkdir_all(&self.mounter, dir, component, 0o755)
+                            .await
+                            .with_context(|| {
+                                format!("failed to create the mount point {:?}", mount.path)
+                            })?;
+                        points.busy.insert(attr.ino);
+                        (parent, dir) = (dir, attr.ino);
+                    }
+                    let name = mount.path.file_name().unwrap_or_default().to_owned();
+                    points.by_entry.insert((parent, name), i + 1);
+                    points.parents.push(parent);
+                }
+                Ok::<_, DatenLordError>(points)
+            })
+            .await
+    }
+
+    /// The backend holding inode `ino` on behalf of `ctx` and its inode
+    /// number there
+    async fn locate(
+        &self,
+        ctx: &RequestContext,
+        ino: INum,
+    ) -> DatenLordResult<(&dyn VirtualFs, usize, INum)> {
+        let (index, ino) = decode(ctx.scope(ino));
+        Ok((self.backend(index).await?, index, ino))
+    }
+
+    /// Whether inode `ino` is a mount point or a directory holding one
+    async fn is_busy(&self, ino: INum) -> DatenLordResult<bool> {
+        match decode(ino) {
+            (0, ino) => Ok(self.points().await?.busy.contains(&ino)),
+            (_, ino) => Ok(ino == ROOT_ID),
+        }
+    }
+
+    /// The attributes of the entry `name` of the directory `dir`, `..`
+    /// being its parent
+    async fn child(
+        &self,
+        ctx: &RequestContext,
+        dir: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<FileAttr> {
+        let dir = ctx.scope(dir);
+        let (index, inner) = decode(dir);
+        if name == ".." {
+            // Like for `LocalFS`, callers cannot leave their root
+            if dir == ctx.scope(ROOT_ID) {
+                return Err(DatenLordError::PermissionDenied {
+                    context: vec![format!("\"..\" leaves the root inode={dir}")],
+                    source: None,
+                });
+            }
+            if index != 0 && inner == ROOT_ID {
+                let parent = self.points().await?.parents[index - 1];
+                let (_, attr) = self.root.getattr(&layer_ctx(ctx, 0), parent).await?;
+                return Ok(attr);
+            }
+        } else if index == 0 {
+            let points = self.points().await?;
+            if let Some(&mount) = points.by_entry.get(&(inner, name.to_owned())) {
+                let lctx = layer_ctx(ctx, mount);
+                let (_, attr) = self.backend(mount).await?.getattr(&lctx, ROOT_ID).await?;
+                return outer(mount, attr);
+            }
+        }
+        let (_, attr, _) = self
+            .backend(index)
+            .await?
+            .lookup(&layer_ctx(ctx, index), inner, name)
+            .await?;
+        outer(index, attr)
+    }
+
+    /// The directory holding the last component of `name`, a `/` separated
+    /// path under `parent` like the SDKs pass, and that component, or the
+    /// rest of `name` from the first component that is not a directory for
+    /// the backend to resolve
+    async fn resolve(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(INum, OsString)> {
+        let mut components: Vec<_> = fs_util::components(name).collect();
+        let last = components
+            .pop()
+            .ok_or_else(|| DatenLordError::InvalidName {
+                context: vec![format!("invalid name {name:?}: no component")],
+            })?;
+        let mut dir = ctx.scope(parent);
+        let mut components = components.into_iter();
+        while let Some(component) = components.next() {
+            match self.child(ctx, dir, component).await {
+                Ok(attr) if attr.kind == SFlag::S_IFDIR => dir = attr.ino,
+                // The backend resolves the rest, following a link inside it
+                // or failing as it does on its own
+                Ok(_) | Err(DatenLordError::NotFound { .. }) => {
+                    let rest: PathBuf = std::iter::once(component)
+                        .chain(components)
+                        .chain(std::iter::once(last))
+                        .collect();
+                    return Ok((dir, rest.into_os_string()));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((dir, last.to_owned()))
+    }
+
+    /// The entries `entries` of the directory `dir` of mount `index` as
+    /// entries of the table, the mount points as the roots of their mounts
+    async fn entries(
+        &self,
+        ctx: &RequestContext,
+        index: usize,
+        dir: INum,
+        entries: Vec<DirEntry>,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        let points = if index == 0 {
+            Some(self.points().await?)
+        } else {
+            None
+        };
+        let mut outer_entries = Vec::with_capacity(entries.len());
+        for mut entry in entries {
+            let key = (dir, entry.name.clone());
+            match points.and_then(|points| points.by_entry.get(&key)) {
+                Some(&mount) => {
+                    entry.ino = encode(mount, ROOT_ID)?;
+                    if let Some(ref mut attr) = entry.attr {
+                        *attr = self.mount_root(ctx, mount, *attr).await?;
+                    }
+                }
+                None => {
+                    entry.ino = encode(index, entry.ino)?;
+                    entry.attr = entry.attr.map(|attr| outer(index, attr)).transpose()?;
+                }
+            }
+            outer_entries.push(entry);
+        }
+        Ok(outer_entries)
+    }
+
+    /// The attributes of the root of mount `index`, those of its mount point
+    /// `covered` when the backend is unreachable, so the directory holding
+    /// it still lists
+    async fn mount_root(
+        &self,
+        ctx: &RequestContext,
+        index: usize,
+        covered: FileAttr,
+    ) -> DatenLordResult<FileAttr> {
+        let root = match self.backend(index).await {
+            Ok(fs) => fs.getattr(&layer_ctx(ctx, index), ROOT_ID).await,
+            Err(e) => Err(e),
+        };
+        match root {
+            Ok((_, attr)) => outer(index, attr),
+            Err(e) => {
+                warn!("failed to stat the root of mount {index}: {e}");
+                Ok(FileAttr {
+                    ino: encode(index, ROOT_ID)?,
+                    ..covered
+                })
+            }
+        }
+    }
+
+    /// Move the entry `src`, of attributes `attr`, of the directory
+    /// `src_dir` to `dst_name` in the directory `dst_dir` of another mount
+    async fn move_across(
+        &self,
+        ctx: &RequestContext,
+        (src_dir, src_name, attr): (INum, &OsStr, &FileAttr),
+        (dst_dir, dst_name): (INum, &OsStr),
+        flags: RenameFlags,
+    ) -> DatenLordResult<()> {
+        if flags.contains(RenameFlags::RENAME_EXCHANGE) {
+            return Err(errno(
+                Errno::EXDEV,
+                format!("cannot exchange {src_name:?} with {dst_name:?} of another mount"),
+            ));
+        }
+        let (from, src_index, src_parent) = self.locate(ctx, src_dir).await?;
+        let (to, dst_index, dst_parent) = self.locate(ctx, dst_dir).await?;
+        let (from_ctx, to_ctx) = (layer_ctx(ctx, src_index), layer_ctx(ctx, dst_index));
+        let dst = match to.lookup(&to_ctx, dst_parent, dst_name).await {
+            Ok((_, dst, _)) => Some(dst),
+            Err(DatenLordError::NotFound { .. }) => None,
+            Err(e) => return Err(e),
+        };
+        // Checked first, the copy being left otherwise
+        let (_, src_dir_attr) = from.getattr(&from_ctx, src_parent).await?;
+        src_dir_attr.check_perm(ctx, ACCESS_WRITE | ACCESS_EXEC)?;
+        src_dir_attr.check_sticky(ctx, attr)?;
+        let (_, dst_dir_attr) = to.getattr(&to_ctx, dst_parent).await?;
+        dst_dir_attr.check_perm(ctx, ACCESS_WRITE | ACCESS_EXEC)?;
+        if let Some(ref dst) = dst {
+            dst_dir_attr.check_sticky(ctx, dst)?;
+            if flags.contains(RenameFlags::RENAME_NOREPLACE) {
+                return Err(DatenLordError::AlreadyExists {
+                    context: vec![format!("{dst_name:?} exists")],
+                    source: None,
+                });
+            }
+            match (attr.kind == SFlag::S_IFDIR, dst.kind == SFlag::S_IFDIR) {
+                (true, false) => {
+                    return Err(errno(
+                        Errno::ENOTDIR,
+                        format!("{dst_name:?} is not a directory"),
+                    ));
+                }
+                (false, true) => {
+                    return Err(errno(Errno::EISDIR, format!("{dst_name:?} is a directory")));
+                }
+                (true, true) if !list_dir(to, &to_ctx, dst.ino).await?.is_empty() => {
+                    return Err(errno(
+                        Errno::ENOTEMPTY,
+                        format!("directory {dst_name:?} is not empty"),
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        let n = self.next_move.fetch_add(1, Ordering::Relaxed);
+        let temp = OsString::from(format!("{MOVING_PREFIX}{}.{n}", std::process::id()));
+        let (_, src_ino) = decode(attr.ino);
+        // Inodes only, already resolved under the root of the caller
+        let copy_ctx = RequestContext {
+            root: ROOT_ID,
+            ..*ctx
+        };
+        let copied = match copy_tree(from, &copy_ctx, src_ino, to, dst_parent, &temp).await {
+            Ok(()) => {
+                let param = RenameParam {
+                    old_parent: dst_parent,
+                    old_name: temp.clone(),
+                    new_parent: dst_parent,
+                    new_name: dst_name.to_owned(),
+                    flags: 0,
+                };
+                to.rename(&to_ctx, param).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = copied {
+            if let Err(e) = remove_all(to, &to_ctx, dst_parent, &temp).await {
+                warn!("failed to remove the partial copy {temp:?} of {src_name:?}: {e}");
+            }
+            return Err(e.add_context(format!("failed to copy {src_name:?} to another mount")));
+        }
+        remove_all(from, &from_ctx, src_parent, src_name)
+            .await
+            .with_context(|| format!("copied {src_name:?} to another mount, failed to remove it"))
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for MountTable<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.connected().try_for_each(VirtualFs::init)
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.root.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        for fs in self.connected() {
+            fs.destroy().await?;
+        }
+        Ok(())
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        for fs in self.connected() {
+            fs.interrupt(unique).await;
+        }
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        // An empty path names the directory itself, like for `LocalFS`
+        if fs_util::components(name).next().is_none() {
+            let (ttl, attr) = self.getattr(ctx, parent).await?;
+            return Ok((ttl, attr, 0));
+        }
+        let (dir, name) = self.resolve(ctx, parent, name).await?;
+        let attr = self.child(ctx, dir, &name).await?;
+        Ok((ATTR_TTL, attr, 0))
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        // Without mounts every path is in the root backend
+        if self.mounts.is_empty() {
+            let (_, inner) = decode(ctx.scope(parent));
+            return self.root.exists(&layer_ctx(ctx, 0), inner, name).await;
+        }
+        virtualfs::exists_by_lookup(self, ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        let (index, ino) = decode(ino);
+        let fs = match index {
+            0 => Some(&self.root as &dyn VirtualFs),
+            index => self
+                .mounts
+                .get(index - 1)
+                .and_then(|mount| mount.fs.get())
+                .map(|fs| &**fs),
+        };
+        if let Some(fs) = fs {
+            fs.forget(ino, nlookup).await;
+        }
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        let (ttl, attr) = fs.getattr(&layer_ctx(ctx, index), ino).await?;
+        Ok((ttl, outer(index, attr)?))
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        let (ttl, attr) = fs.setattr(&layer_ctx(ctx, index), ino, param).await?;
+        Ok((ttl, outer(index, attr)?))
+    }
+
+    async fn utimens(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        atime: UtimeSpec,
+        mtime: UtimeSpec,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        let (ttl, attr) = fs
+            .utimens(&layer_ctx(ctx, index), ino, atime, mtime)
+            .await?;
+        Ok((ttl, outer(index, attr)?))
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.readlink(&layer_ctx(ctx, index), ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (dir, name) = self.resolve(ctx, param.parent, &param.name).await?;
+        let (fs, index, parent) = self.locate(ctx, dir).await?;
+        let param = CreateParam {
+            parent,
+            name,
+            ..param
+        };
+        let (ttl, attr, generation) = fs.mknod(&layer_ctx(ctx, index), param).await?;
+        Ok((ttl, outer(index, attr)?, generation))
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (dir, name) = self.resolve(ctx, param.parent, &param.name).await?;
+        let (fs, index, parent) = self.locate(ctx, dir).await?;
+        let param = CreateParam {
+            parent,
+            name,
+            ..param
+        };
+        let (ttl, attr, generation) = fs.mkdir(&layer_ctx(ctx, index), param).await?;
+        Ok((ttl, outer(index, attr)?, generation))
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let (dir, name) = self.resolve(ctx, parent, name).await?;
+        let attr = self.child(ctx, dir, &name).await?;
+        if self.is_busy(attr.ino).await? {
+            return Err(busy(&name));
+        }
+        let (fs, index, parent) = self.locate(ctx, dir).await?;
+        fs.unlink(&layer_ctx(ctx, index), parent, &name).await
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        let (dir, name) = self.resolve(ctx, parent, dir_name).await?;
+        let attr = self.child(ctx, dir, &name).await?;
+        if self.is_busy(attr.ino).await? {
+            return Err(busy(&name));
+        }
+        let (fs, index, parent) = self.locate(ctx, dir).await?;
+        let removed = fs.rmdir(&layer_ctx(ctx, index), parent, &name).await?;
+        removed.map(|ino| encode(index, ino)).transpose()
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (dir, name) = self.resolve(ctx, parent, name).await?;
+        let (fs, index, parent) = self.locate(ctx, dir).await?;
+        let (ttl, attr, generation) = fs
+            .symlink(&layer_ctx(ctx, index), parent, &name, target_path)
+            .await?;
+        Ok((ttl, outer(index, attr)?, generation))
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let (src_dir, src_name) = self.resolve(ctx, param.old_parent, &param.old_name).await?;
+        let (dst_dir, dst_name) = self.resolve(ctx, param.new_parent, &param.new_name).await?;
+        let src = self.child(ctx, src_dir, &src_name).await?;
+        if self.is_busy(src.ino).await? {
+            return Err(busy(&src_name));
+        }
+        match self.child(ctx, dst_dir, &dst_name).await {
+            Ok(dst) if self.is_busy(dst.ino).await? => return Err(busy(&dst_name)),
+            Ok(_) | Err(DatenLordError::NotFound { .. }) => {}
+            Err(e) => return Err(e),
+        }
+        let (src_index, old_parent) = decode(src_dir);
+        let (dst_index, new_parent) = decode(dst_dir);
+        if src_index != dst_index {
+            let flags = RenameFlags::from_bits_truncate(param.flags);
+            let src = (src_dir, src_name.as_os_str(), &src);
+            return self
+                .move_across(ctx, src, (dst_dir, &dst_name), flags)
+                .await;
+        }
+        let param = RenameParam {
+            old_parent,
+            old_name: src_name,
+            new_parent,
+            new_name: dst_name,
+            flags: param.flags,
+        };
+        self.backend(src_index)
+            .await?
+            .rename(&layer_ctx(ctx, src_index), param)
+            .await
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        let (dir, name) = self.resolve(ctx, newparent, newname).await?;
+        let (fs, index, parent) = self.locate(ctx, dir).await?;
+        fs.link(&layer_ctx(ctx, index), parent, &name).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.open(&layer_ctx(ctx, index), ino, flags).await
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.read(&layer_ctx(ctx, index), ino, fh, offset, size, buf)
+            .await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.write(&layer_ctx(ctx, index), ino, fh, offset, data, flags)
+            .await
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.flush(&layer_ctx(ctx, index), ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.release(&layer_ctx(ctx, index), ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.fsync(&layer_ctx(ctx, index), ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.lseek(&layer_ctx(ctx, index), ino, fh, offset, whence)
+            .await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        let (index_in, ino_in) = decode(ctx.scope(param.ino_in));
+        let (index_out, ino_out) = decode(ctx.scope(param.ino_out));
+        if index_in != index_out {
+            return virtualfs::copy_range_through(self, ctx, param).await;
+        }
+        let param = CopyRangeParam {
+            ino_in,
+            ino_out,
+            ..param
+        };
+        self.backend(index_in)
+            .await?
+            .copy_file_range(&layer_ctx(ctx, index_in), param)
+            .await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.opendir(&layer_ctx(ctx, index), ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        let entries = fs.readdir(&layer_ctx(ctx, index), ino, fh, offset).await?;
+        self.entries(ctx, index, ino, entries).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        let detailed = fs
+            .readdirplus(&layer_ctx(ctx, index), ino, fh, offset)
+            .await?;
+        let ttls: Vec<_> = detailed.iter().map(|&(_, _, ttl)| ttl).collect();
+        let entries = detailed
+            .into_iter()
+            .map(|(entry, attr, _)| DirEntry {
+                attr: Some(attr),
+                ..entry
+            })
+            .collect();
+        let entries = self.entries(ctx, index, ino, entries).await?;
+        Ok(entries
+            .into_iter()
+            .zip(ttls)
+            .filter_map(|(entry, ttl)| Some((entry.attr?, entry, ttl)))
+            .map(|(attr, entry, ttl)| (entry, attr, ttl))
+            .collect())
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.releasedir(&layer_ctx(ctx, index), ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.fsyncdir(&layer_ctx(ctx, index), ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        let ctx = RequestContext {
+            root: ROOT_ID,
+            ..*ctx
+        };
+        for fs in self.connected() {
+            fs.sync_all(&ctx).await?;
+        }
+        Ok(())
+    }
+
+    // The statistics of the backend holding `ino`, so each mount reports its
+    // own space
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.statfs(&layer_ctx(ctx, index), ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.setxattr(&layer_ctx(ctx, index), ino, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.getxattr(&layer_ctx(ctx, index), ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.listxattr(&layer_ctx(ctx, index), ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.removexattr(&layer_ctx(ctx, index), ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.access(&layer_ctx(ctx, index), ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let (dir, name) = self.resolve(ctx, parent, name).await?;
+        let (fs, index, parent) = self.locate(ctx, dir).await?;
+        let (_, ino) = decode(ino);
+        fs.create(&layer_ctx(ctx, index), ino, parent, &name, mode, flags)
+            .await
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.getlk(&layer_ctx(ctx, index), ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.setlk(&layer_ctx(ctx, index), ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.bmap(&layer_ctx(ctx, index), ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        let (fs, index, ino) = self.locate(ctx, ino).await?;
+        fs.ioctl(&layer_ctx(ctx, index), ino, cmd, input).await
+    }
+}
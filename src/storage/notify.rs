@@ -0,0 +1,1137 @@
+//! Notifications of namespace changes, delivered to pluggable sinks and
+//! in-process watches
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use nix::fcntl::{OFlag, RenameFlags};
+use serde_derive::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{
+    self, CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileKind, FileLockParam,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam, ROOT_ID,
+};
+use super::tags::{self, Tags};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// The version of the event schema, bumped on every incompatible change
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+/// The number of events queued for a sink before new ones are dropped
+const SINK_QUEUE: usize = 4096;
+/// The number of events queued for a watch before new ones are dropped
+const WATCH_QUEUE: usize = 4096;
+/// The number of inode paths remembered while watched, past which the
+/// paths of further inodes are not learned
+const WATCHED_PATHS: usize = 1 << 16;
+
+/// What happened to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// A file, device, fifo or socket was created
+    Create,
+    /// A directory was created
+    Mkdir,
+    /// A symbolic link was created
+    Symlink,
+    /// An entry was removed
+    Delete,
+    /// An entry was moved, or swapped with `RENAME_EXCHANGE`
+    Rename,
+    /// Attributes were changed by `setattr`, including truncation, a file
+    /// was opened with `O_TRUNC`, or tags were set or removed
+    Attrib,
+    /// A file handle that was written to was released, like `IN_CLOSE_WRITE`
+    CloseWrite,
+}
+
+impl EventKind {
+    /// The name of the kind, as serialized
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Mkdir => "mkdir",
+            Self::Symlink => "symlink",
+            Self::Delete => "delete",
+            Self::Rename => "rename",
+            Self::Attrib => "attrib",
+            Self::CloseWrite => "close_write",
+        }
+    }
+}
+
+/// A change to a namespace, serialized as one JSON object
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// `EVENT_SCHEMA_VERSION` of the producer
+    pub version: u32,
+    /// The namespace the change happened in, its root path
+    pub namespace: String,
+    /// What happened
+    pub kind: EventKind,
+    /// The inode of the file
+    pub ino: INum,
+    /// The type of the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_kind: Option<FileKind>,
+    /// The directory holding the entry, for namespace changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<INum>,
+    /// The name of the entry in `parent`; the SDKs address everything from
+    /// the root, so it is usually the path in the namespace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The directory the entry moved to, for renames
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_parent: Option<INum>,
+    /// The name the entry moved to, for renames
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_name: Option<String>,
+    /// The path of the entry from the root, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// The path of the entry from the root after a rename, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_path: Option<String>,
+    /// The size of the file after the change
+    pub size: u64,
+    /// When the change was observed, in nanoseconds since the epoch
+    pub timestamp_ns: i128,
+    /// The tags of the file after a tag was set or removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Tags>,
+}
+
+impl Event {
+    /// A `kind` event about the file with `attr`
+    fn new(kind: EventKind, attr: &FileAttr) -> Self {
+        let (sec, nsec) = fs_util::to_timespec(SystemTime::now());
+        Self {
+            version: EVENT_SCHEMA_VERSION,
+            namespace: String::new(),
+            kind,
+            ino: attr.ino,
+            file_kind: FileKind::from_sflag(attr.kind),
+            parent: None,
+            name: None,
+            new_parent: None,
+            new_name: None,
+            path: None,
+            new_path: None,
+            size: attr.size,
+            timestamp_ns: i128::from(sec) * 1_000_000_000 + i128::from(nsec),
+            tags: None,
+        }
+    }
+
+    /// The event about the entry `name` in `parent`, with the bytes of names
+    /// that are not UTF-8 replaced
+    fn at(self, parent: INum, name: &OsStr) -> Self {
+        Self {
+            parent: Some(parent),
+            name: Some(name.to_string_lossy().into_owned()),
+            ..self
+        }
+    }
+
+    /// The event about an entry moved to `name` in `parent`
+    fn to(self, parent: INum, name: &OsStr) -> Self {
+        Self {
+            new_parent: Some(parent),
+            new_name: Some(name.to_string_lossy().into_owned()),
+            ..self
+        }
+    }
+}
+
+/// A destination of events
+///
+/// Each sink is driven by its own task, one event at a time. A failed
+/// delivery is retried once, after which the event is dropped, so sinks
+/// should drop broken connections and reconnect on the next call.
+#[async_trait]
+pub trait EventSink: Send {
+    /// A short description for logs
+    fn describe(&self) -> String;
+
+    /// Deliver `payload`, one event serialized as JSON
+    async fn send(&mut self, payload: &str) -> std::io::Result<()>;
+
+    /// Finish delivering buffered events, called whenever the queue drains
+    async fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A sink configured in `DatenLordConfig::notify_sinks`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// POST every event to an `http://` URL
+    Webhook {
+        /// The URL to post to
+        url: String,
+    },
+    /// Publish every event to a NATS subject
+    Nats {
+        /// `host:port` of the NATS server
+        address: String,
+        /// The subject to publish to
+        subject: String,
+    },
+    /// Write every event as a JSON line to a Unix stream socket
+    UnixSocket {
+        /// The path of the listening socket
+        path: PathBuf,
+    },
+}
+
+impl SinkConfig {
+    /// Build the sink, failing on invalid settings
+    pub fn build(&self) -> DatenLordResult<Box<dyn EventSink>> {
+        Ok(match *self {
+            Self::Webhook { ref url } => Box::new(WebhookSink::new(url)?),
+            Self::Nats {
+                ref address,
+                ref subject,
+            } => {
+                if subject.is_empty() || subject.contains(char::is_whitespace) {
+                    return Err(DatenLordError::InvalidArgument {
+                        context: vec![format!("invalid NATS subject {subject:?}")],
+                    });
+                }
+                Box::new(NatsSink {
+                    address: address.clone(),
+                    subject: subject.clone(),
+                    connection: None,
+                })
+            }
+            Self::UnixSocket { ref path } => Box::new(UnixSocketSink {
+                path: path.clone(),
+                connection: None,
+            }),
+        })
+    }
+}
+
+/// Posts events to a plain HTTP endpoint, one connection per event
+#[derive(Debug)]
+struct WebhookSink {
+    /// `host:port` to connect to
+    address: String,
+    /// The `Host` header
+    host: String,
+    /// The request target
+    target: String,
+}
+
+impl WebhookSink {
+    /// Parse `url`, only `http://` is supported
+    fn new(url: &str) -> DatenLordResult<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("webhook url {url:?} is not an http:// url")],
+            });
+        };
+        let (host, target) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        if host.is_empty() {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("webhook url {url:?} has no host")],
+            });
+        }
+        let address = if host.contains(':') {
+            host.to_owned()
+        } else {
+            format!("{host}:80")
+        };
+        Ok(Self {
+            address,
+            host: host.to_owned(),
+            target: target.to_owned(),
+        })
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    fn describe(&self) -> String {
+        format!("webhook http://{}{}", self.host, self.target)
+    }
+
+    async fn send(&mut self, payload: &str) -> std::io::Result<()> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{payload}",
+            self.target,
+            self.host,
+            payload.len(),
+        );
+        stream.write_all(request.as_bytes()).await?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status).await?;
+        // `HTTP/1.1 204 No Content`
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(std::io::Error::other(format!(
+                "unexpected response {:?}",
+                status.trim_end()
+            ))),
+        }
+    }
+}
+
+/// Publishes events to a NATS subject over the core NATS protocol
+#[derive(Debug)]
+struct NatsSink {
+    /// `host:port` of the server
+    address: String,
+    /// The subject to publish to
+    subject: String,
+    /// The connection, opened on first use
+    connection: Option<TcpStream>,
+}
+
+impl NatsSink {
+    /// Connect to the server and say hello
+    async fn connect(&self) -> std::io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(&self.address).await?;
+        // The server greets with an `INFO {...}` line
+        let mut info = Vec::new();
+        while !info.ends_with(b"\r\n") {
+            info.push(stream.read_u8().await?);
+        }
+        stream
+            .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false}\r\n")
+            .await?;
+        Ok(stream)
+    }
+
+    /// Publish `payload` on `stream`, first answering the keep-alive pings
+    /// the server sent since the last event
+    async fn publish(&self, stream: &mut TcpStream, payload: &str) -> std::io::Result<()> {
+        let mut pending = [0; 1024];
+        loop {
+            match stream.try_read(&mut pending) {
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => {
+                    let pings = pending[..read].windows(4).filter(|w| *w == b"PING").count();
+                    for _ in 0..pings {
+                        stream.write_all(b"PONG\r\n").await?;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        let message = format!("PUB {} {}\r\n{payload}\r\n", self.subject, payload.len());
+        stream.write_all(message.as_bytes()).await
+    }
+}
+
+#[async_trait]
+impl EventSink for NatsSink {
+    fn describe(&self) -> String {
+        format!("nats {} subject {}", self.address, self.subject)
+    }
+
+    async fn send(&mut self, payload: &str) -> std::io::Result<()> {
+        let mut stream = match self.connection.take() {
+            Some(stream) => stream,
+            None => self.connect().await?,
+        };
+        self.publish(&mut stream, payload).await?;
+        // Broken connections are dropped and reopened by the next call
+        self.connection = Some(stream);
+        Ok(())
+    }
+}
+
+/// Writes events as JSON lines to a Unix stream socket
+#[derive(Debug)]
+struct UnixSocketSink {
+    /// The path of the socket
+    path: PathBuf,
+    /// The connection, opened on first use
+    connection: Option<UnixStream>,
+}
+
+#[async_trait]
+impl EventSink for UnixSocketSink {
+    fn describe(&self) -> String {
+        format!("unix socket {}", self.path.display())
+    }
+
+    async fn send(&mut self, payload: &str) -> std::io::Result<()> {
+        let mut stream = match self.connection.take() {
+            Some(stream) => stream,
+            None => UnixStream::connect(&self.path).await?,
+        };
+        stream.write_all(format!("{payload}\n").as_bytes()).await?;
+        self.connection = Some(stream);
+        Ok(())
+    }
+}
+
+/// Fans events out to the sinks, each driven on a background thread
+#[derive(Debug)]
+struct Notifier {
+    /// The namespace stamped on every event
+    namespace: String,
+    /// The queue of every sink
+    queues: Vec<mpsc::Sender<Arc<str>>>,
+}
+
+impl Notifier {
+    /// Start delivering to `sinks` on a dedicated thread, so delivery never
+    /// depends on the runtime of the callers
+    fn start(namespace: String, sinks: Vec<Box<dyn EventSink>>) -> DatenLordResult<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the notification runtime: {e}")],
+            })?;
+        let mut queues = Vec::with_capacity(sinks.len());
+        let mut deliveries = Vec::with_capacity(sinks.len());
+        for sink in sinks {
+            let (queue, events) = mpsc::channel(SINK_QUEUE);
+            queues.push(queue);
+            deliveries.push(deliver(sink, events));
+        }
+        std::thread::Builder::new()
+            .name("datenlord-notify".to_owned())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let tasks: Vec<_> = deliveries.into_iter().map(tokio::spawn).collect();
+                    for task in tasks {
+                        let _ = task.await;
+                    }
+                });
+            })
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the notification thread: {e}")],
+            })?;
+        Ok(Self { namespace, queues })
+    }
+
+    /// Queue `event` for every sink, dropping it for sinks that fell behind
+    fn emit(&self, mut event: Event) {
+        event.namespace.clone_from(&self.namespace);
+        let payload: Arc<str> = match serde_json::to_string(&event) {
+            Ok(payload) => payload.into(),
+            Err(e) => {
+                warn!("failed to serialize event {event:?}: {e}");
+                return;
+            }
+        };
+        for queue in &self.queues {
+            if queue.try_send(Arc::clone(&payload)).is_err() {
+                warn!("notification sink is behind, event dropped");
+            }
+        }
+    }
+}
+
+/// Deliver the events of `events` to `sink` until every sender is gone
+async fn deliver(mut sink: Box<dyn EventSink>, mut events: mpsc::Receiver<Arc<str>>) {
+    while let Some(payload) = events.recv().await {
+        if let Err(first) = sink.send(&payload).await {
+            if let Err(e) = sink.send(&payload).await {
+                warn!(
+                    "failed to notify {}, event dropped: {first}, then {e}",
+                    sink.describe()
+                );
+            }
+        }
+        if events.is_empty() {
+            if let Err(e) = sink.flush().await {
+                warn!("failed to flush {}: {e}", sink.describe());
+            }
+        }
+    }
+}
+
+/// `path` relative to the root, without its `.` and root components
+fn relative(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+/// A watch registered by `NotifyFs::watch`
+#[derive(Debug)]
+struct Watcher {
+    /// The watched path relative to the root
+    path: PathBuf,
+    /// Whether changes below the children of `path` are watched too
+    recursive: bool,
+    /// The queue of the `Watch`
+    events: mpsc::Sender<Event>,
+}
+
+impl Watcher {
+    /// Whether a change to `path` is watched: the path itself, its
+    /// children, and with `recursive` everything below it
+    fn matches(&self, path: &Path) -> bool {
+        path == self.path
+            || path.parent() == Some(self.path.as_path())
+            || (self.recursive && path.starts_with(&self.path))
+    }
+}
+
+/// The changes under a watched path, see `NotifyFs::watch`
+#[derive(Debug)]
+pub struct Watch {
+    /// The events matching the watch
+    events: mpsc::Receiver<Event>,
+}
+
+impl Watch {
+    /// The next change, `None` once the filesystem is gone
+    pub async fn next(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+}
+
+/// A `VirtualFs` reporting changes to the namespace as `Event`s
+///
+/// Events are emitted once the inner operation succeeded and delivered in
+/// the background, in order per sink and watch and at most once. Without
+/// sinks or watches it only forwards. Removals and renames look the entry up
+/// first to report what they affected.
+///
+/// Paths are those of entries addressed from the root, which is how the SDKs
+/// address everything, and, while watched, those of the inodes looked up or
+/// created through the layer.
+#[derive(Debug)]
+pub struct NotifyFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// Where events go, `None` without sinks
+    notifier: Option<Notifier>,
+    /// The registered watches, dropped ones are removed on the next change
+    watchers: Mutex<Vec<Watcher>>,
+    /// The paths of the inodes seen while watched, relative to the root
+    paths: Mutex<HashMap<INum, PathBuf>>,
+    /// The handles written to since they were opened
+    written: Mutex<HashSet<u64>>,
+}
+
+impl<F: VirtualFs> NotifyFs<F> {
+    /// Wrap `inner`, reporting the changes in `namespace` to `sinks`
+    pub fn new(inner: F, namespace: String, sinks: Vec<Box<dyn EventSink>>) -> DatenLordResult<Self> {
+        let notifier = if sinks.is_empty() {
+            None
+        } else {
+            Some(Notifier::start(namespace, sinks)?)
+        };
+        Ok(Self {
+            inner,
+            notifier,
+            watchers: Mutex::default(),
+            paths: Mutex::default(),
+            written: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Watch the changes to `path`, relative to the root, and to its
+    /// children, or everything below it with `recursive`
+    ///
+    /// Fails when `path` does not exist. Changes are queued until read, those
+    /// past a full queue are dropped, and the watch ends when dropped.
+    pub async fn watch(
+        &self,
+        ctx: &RequestContext,
+        path: &Path,
+        recursive: bool,
+    ) -> DatenLordResult<Watch> {
+        let path = relative(path);
+        if path.as_os_str().is_empty() {
+            self.inner.getattr(ctx, ROOT_ID).await?;
+        } else {
+            let (_, attr, _) = self.inner.lookup(ctx, ROOT_ID, path.as_os_str()).await?;
+            self.paths.lock().unwrap().insert(attr.ino, path.clone());
+        }
+        let (events, receiver) = mpsc::channel(WATCH_QUEUE);
+        self.watchers.lock().unwrap().push(Watcher {
+            path,
+            recursive,
+            events,
+        });
+        Ok(Watch { events: receiver })
+    }
+
+    /// Whether changes are reported, to sinks or watches
+    fn observed(&self) -> bool {
+        self.notifier.is_some() || self.watched()
+    }
+
+    /// Whether a watch is registered, forgetting the inode paths once the
+    /// last one is dropped
+    fn watched(&self) -> bool {
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.retain(|watcher| !watcher.events.is_closed());
+        if watchers.is_empty() {
+            drop(watchers);
+            self.paths.lock().unwrap().clear();
+            return false;
+        }
+        true
+    }
+
+    /// The path of the entry `name` in `parent`, if the path of `parent` is
+    /// known
+    fn entry_path(&self, parent: INum, name: &OsStr) -> Option<PathBuf> {
+        let name = relative(Path::new(name));
+        if parent == ROOT_ID {
+            return Some(name);
+        }
+        self.paths
+            .lock()
+            .unwrap()
+            .get(&parent)
+            .map(|dir| dir.join(name))
+    }
+
+    /// Remember the path of `ino`, the entry `name` in `parent`, while
+    /// watched
+    fn remember(&self, parent: INum, name: &OsStr, ino: INum) {
+        if !self.watched() {
+            return;
+        }
+        let Some(path) = self.entry_path(parent, name) else {
+            return;
+        };
+        let mut paths = self.paths.lock().unwrap();
+        if paths.len() < WATCHED_PATHS || paths.contains_key(&ino) {
+            paths.insert(ino, path);
+        }
+    }
+
+    /// Forget the remembered paths at and below `removed`
+    fn forget_paths(&self, removed: &Path) {
+        self.paths
+            .lock()
+            .unwrap()
+            .retain(|_, path| !path.starts_with(removed));
+    }
+
+    /// Move the remembered paths at and below `old` to `new`, forgetting
+    /// those of the entry replaced at `new`
+    fn rebase(&self, old: &Path, new: &Path) {
+        let mut paths = self.paths.lock().unwrap();
+        paths.retain(|_, path| !path.starts_with(new) || path.starts_with(old));
+        for path in paths.values_mut() {
+            if let Ok(rest) = path.strip_prefix(old) {
+                *path = new.join(rest);
+            }
+        }
+    }
+
+    /// Queue `event` for the sinks and the matching watches, with the paths
+    /// it is about when known
+    fn emit(&self, mut event: Event) {
+        let path = match (event.parent, &event.name) {
+            (Some(parent), Some(name)) => self.entry_path(parent, OsStr::new(name)),
+            _ => self.paths.lock().unwrap().get(&event.ino).cloned(),
+        };
+        let new_path = match (event.new_parent, &event.new_name) {
+            (Some(parent), Some(name)) => self.entry_path(parent, OsStr::new(name)),
+            _ => None,
+        };
+        event.path = path.as_deref().map(|path| path.to_string_lossy().into_owned());
+        event.new_path = new_path
+            .as_deref()
+            .map(|path| path.to_string_lossy().into_owned());
+        {
+            let mut watchers = self.watchers.lock().unwrap();
+            watchers.retain(|watcher| !watcher.events.is_closed());
+            for watcher in watchers.iter() {
+                let matches = [&path, &new_path]
+                    .into_iter()
+                    .flatten()
+                    .any(|path| watcher.matches(path));
+                if matches && watcher.events.try_send(event.clone()).is_err() {
+                    warn!("watch of {:?} is behind, event dropped", watcher.path);
+                }
+            }
+        }
+        if let Some(ref notifier) = self.notifier {
+            notifier.emit(event);
+        }
+    }
+
+    /// Report the tags of `ino` after its extended attribute `name` changed,
+    /// when there are sinks and the attribute holds a tag
+    async fn emit_tags(&self, ctx: &RequestContext, ino: INum, name: &str) {
+        if tags::tag_key(name).is_none() || !self.observed() {
+            return;
+        }
+        let (Ok((_, attr)), Ok(tags)) = (
+            self.inner.getattr(ctx, ino).await,
+            tags::get_tags(&self.inner, ctx, ino).await,
+        ) else {
+            warn!("failed to read the tags of inode {ino}, event dropped");
+            return;
+        };
+        self.emit(Event {
+            tags: Some(tags),
+            ..Event::new(EventKind::Attrib, &attr)
+        });
+    }
+
+    /// The attributes of `name` in `parent` when there are sinks to tell
+    /// about them, `None` otherwise or if the lookup fails
+    async fn lookup_attr(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> Option<FileAttr> {
+        if !self.observed() {
+            return None;
+        }
+        self.inner
+            .lookup(ctx, parent, name)
+            .await
+            .ok()
+            .map(|(_, attr, _)| attr)
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for NotifyFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let entry = self.inner.lookup(ctx, parent, name).await?;
+        self.remember(parent, name, entry.1.ino);
+        Ok(entry)
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.inner.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let (ttl, attr) = self.inner.setattr(ctx, ino, param).await?;
+        self.emit(Event::new(EventKind::Attrib, &attr));
+        Ok((ttl, attr))
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (parent, name) = (param.parent, param.name.clone());
+        let entry = self.inner.mknod(ctx, param).await?;
+        self.remember(parent, &name, entry.1.ino);
+        self.emit(Event::new(EventKind::Create, &entry.1).at(parent, &name));
+        Ok(entry)
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (parent, name) = (param.parent, param.name.clone());
+        let entry = self.inner.mkdir(ctx, param).await?;
+        self.remember(parent, &name, entry.1.ino);
+        self.emit(Event::new(EventKind::Mkdir, &entry.1).at(parent, &name));
+        Ok(entry)
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let attr = self.lookup_attr(ctx, parent, name).await;
+        self.inner.unlink(ctx, parent, name).await?;
+        if let Some(path) = self.entry_path(parent, name) {
+            self.forget_paths(&path);
+        }
+        if let Some(attr) = attr {
+            self.emit(Event::new(EventKind::Delete, &attr).at(parent, name));
+        }
+        Ok(())
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        let attr = self.lookup_attr(ctx, parent, dir_name).await;
+        let removed = self.inner.rmdir(ctx, parent, dir_name).await?;
+        if let Some(path) = self.entry_path(parent, dir_name) {
+            self.forget_paths(&path);
+        }
+        if let Some(attr) = attr {
+            self.emit(Event::new(EventKind::Delete, &attr).at(parent, dir_name));
+        }
+        Ok(removed)
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let entry = self
+            .inner
+            .symlink(ctx, parent, name, target_path)
+            .await?;
+        self.remember(parent, name, entry.1.ino);
+        self.emit(Event::new(EventKind::Symlink, &entry.1).at(parent, name));
+        Ok(entry)
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let attr = self
+            .lookup_attr(ctx, param.old_parent, &param.old_name)
+            .await;
+        let event = attr.map(|attr| {
+            Event::new(EventKind::Rename, &attr)
+                .at(param.old_parent, &param.old_name)
+                .to(param.new_parent, &param.new_name)
+        });
+        let exchange = param.flags & RenameFlags::RENAME_EXCHANGE.bits() != 0;
+        let paths = (
+            self.entry_path(param.old_parent, &param.old_name),
+            self.entry_path(param.new_parent, &param.new_name),
+        );
+        self.inner.rename(ctx, param).await?;
+        match paths {
+            (Some(old), Some(new)) if !exchange => self.rebase(&old, &new),
+            (old, new) => {
+                for path in [old, new].into_iter().flatten() {
+                    self.forget_paths(&path);
+                }
+            }
+        }
+        if let Some(event) = event {
+            self.emit(event);
+        }
+        Ok(())
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.link(ctx, newparent, newname).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let fh = self.inner.open(ctx, ino, flags).await?;
+        if OFlag::from_bits_truncate(flags as i32).contains(OFlag::O_TRUNC) {
+            if let Ok((_, attr)) = self.inner.getattr(ctx, ino).await {
+                self.emit(Event::new(EventKind::Attrib, &attr));
+            }
+        }
+        Ok(fh)
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        self.inner.read(ctx, ino, fh, offset, size, buf).await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.write(ctx, ino, fh, offset, data, flags).await?;
+        if self.observed() {
+            self.written.lock().unwrap().insert(fh);
+        }
+        Ok(())
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        let result = self
+            .inner
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await;
+        if self.written.lock().unwrap().remove(&fh) {
+            if let Ok((_, attr)) = self.inner.getattr(ctx, ino).await {
+                self.emit(Event::new(EventKind::CloseWrite, &attr));
+            }
+        }
+        result
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        self.inner.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        let copy = self.inner.copy_file_range(ctx, param).await?;
+        if self.observed() {
+            self.written.lock().unwrap().insert(param.fh_out);
+        }
+        Ok(copy)
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.inner.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.inner.readdirplus(ctx, ino, fh, offset).await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.setxattr(ctx, ino, name, value, flags, position).await?;
+        self.emit_tags(ctx, ino, name).await;
+        Ok(())
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await?;
+        self.emit_tags(ctx, ino, name).await;
+        Ok(())
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.create(ctx, ino, parent, name, mode, flags).await
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
@@ -0,0 +1,1434 @@
+//! Union of directory trees: read-only lower layers overlaid with a writable
+//! upper layer, so scratch changes land on top of an immutable dataset
+//!
+//! An entry of the overlay is the entry of the same path in the topmost
+//! layer holding it, a directory merging the entries of every layer down to
+//! the first one holding something else under its name. Files of a lower
+//! layer are copied up to the upper layer, with their parent directories,
+//! before they are changed. Removing an entry a lower layer holds leaves a
+//! whiteout in the upper layer, an empty file named `WHITEOUT_PREFIX`
+//! followed by the name, hiding it from the layers below; a directory made
+//! where a removed one was holds an `OPAQUE_MARKER` file, so nothing of the
+//! layers below shows through it. These are the conventions of AUFS, so the
+//! layers need no special files, and lower layers left by another overlay
+//! are read the same way.
+//!
+//! Copy-ups, whiteouts and opaque markers are made with the credentials of
+//! the process, like those of the mounter of a kernel overlay, once the
+//! caller's permissions were checked on the overlay; everything else is
+//! done on behalf of the caller. Like a kernel overlay without
+//! `redirect_dir`, renaming a directory a lower layer holds fails with
+//! `EXDEV`, so callers fall back to copying it. Hard links are not
+//! supported.
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use nix::errno::Errno;
+use nix::fcntl::{OFlag, RenameFlags};
+use nix::sys::stat::SFlag;
+use tracing::{debug, warn};
+
+use crate::common::buffer_pool::COPY_CHUNK_SIZE;
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::dir_handle::DirHandles;
+use super::fs_util::{
+    self, CreateParam, DirEntry, FileAttr, FileKind, FileLockParam, RenameParam, RequestContext,
+    SeekWhence, SetAttrParam, StatFsParam, ROOT_ID,
+};
+use super::layout::BlockLayout;
+use super::trash::list_dir;
+use super::virtualfs::{INum, VirtualFs};
+
+/// The prefix of the name of a whiteout, hiding the entry named by the rest
+/// from the layers below
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+/// The file marking a directory opaque, hiding the layers below
+pub const OPAQUE_MARKER: &str = ".wh..wh..opq";
+/// The mode of whiteouts and opaque markers
+const MARKER_MODE: u32 = 0o600;
+/// The TTL of the attributes returned, short since the layers may change
+/// behind the overlay
+const ATTR_TTL: Duration = Duration::from_secs(1);
+/// The index of the upper layer in `Node::layers`
+const UPPER: usize = 0;
+
+/// Permission bits checked with `FileAttr::check_perm`
+const ACCESS_READ: u8 = 0o4;
+const ACCESS_WRITE: u8 = 0o2;
+const ACCESS_EXEC: u8 = 0o1;
+
+/// An entry of the overlay
+#[derive(Debug, Clone)]
+struct Node {
+    ino: INum,
+    /// The path relative to the root of the overlay
+    path: PathBuf,
+    kind: SFlag,
+    /// The layers holding the entry, the topmost first, with its inode in
+    /// each; layer `UPPER` is the upper layer and layer `i` the lower layer
+    /// `i - 1`. Only directories are in more than one.
+    layers: Vec<(usize, INum)>,
+}
+
+impl Node {
+    /// The topmost layer holding the entry and its inode there
+    fn top(&self) -> (usize, INum) {
+        self.layers[0]
+    }
+
+    fn in_upper(&self) -> bool {
+        self.top().0 == UPPER
+    }
+
+    /// The lower layers holding the directory
+    fn lower(&self) -> &[(usize, INum)] {
+        &self.layers[usize::from(self.in_upper())..]
+    }
+}
+
+/// The entries known to the overlay, by inode and by path
+#[derive(Debug)]
+struct Nodes {
+    next_ino: INum,
+    by_ino: HashMap<INum, Node>,
+    by_path: HashMap<PathBuf, INum>,
+}
+
+/// A file opened through the overlay, in the topmost layer holding it then
+#[derive(Debug, Clone, Copy)]
+struct OpenFile {
+    layer: usize,
+    ino: INum,
+    fh: u64,
+}
+
+/// An entry found in the layers
+#[derive(Debug)]
+struct Found {
+    /// The attributes in the topmost layer holding it
+    attr: FileAttr,
+    layers: Vec<(usize, INum)>,
+}
+
+/// A filesystem showing the lower layers `lowers` under the writable layer
+/// `upper`, see the module documentation
+///
+/// Inode numbers are the overlay's own, an entry keeping its number while
+/// its path exists.
+#[derive(Debug)]
+pub struct OverlayFs<F> {
+    upper: F,
+    /// The read-only layers, the topmost first
+    lowers: Vec<F>,
+    /// The context copy-ups, whiteouts and opaque markers are made with
+    mounter: RequestContext,
+    nodes: Mutex<Nodes>,
+    /// The next file handle to allocate, 0 is never one
+    next_fh: AtomicU64,
+    files: Mutex<HashMap<u64, OpenFile>>,
+    dirs: DirHandles,
+    /// Serializes the changes to the namespace
+    changes: tokio::sync::Mutex<()>,
+}
+
+/// The name of the whiteout of `name`
+fn whiteout_name(name: &OsStr) -> OsString {
+    let mut whiteout = OsString::from(WHITEOUT_PREFIX);
+    whiteout.push(name);
+    whiteout
+}
+
+/// Whether `name` is that of a whiteout or an opaque marker, never an entry
+/// of the overlay
+fn is_internal(name: &OsStr) -> bool {
+    name.as_bytes().starts_with(WHITEOUT_PREFIX.as_bytes())
+}
+
+/// Check that an entry may be named `name`
+fn check_name(name: &OsStr) -> DatenLordResult<()> {
+    if name == ".." || is_internal(name) {
+        return Err(DatenLordError::InvalidName {
+            context: vec![format!("{name:?} cannot name an entry of the overlay")],
+        });
+    }
+    Ok(())
+}
+
+/// The error `errno` with `context`
+fn errno(errno: Errno, context: String) -> DatenLordError {
+    DatenLordError::from(errno).add_context(context)
+}
+
+/// `ctx` for the layers, whose roots are their own
+fn layer_ctx(ctx: &RequestContext) -> RequestContext {
+    RequestContext {
+        root: ROOT_ID,
+        ..*ctx
+    }
+}
+
+/// `attr` as the attributes of the entry `ino` of the overlay
+fn with_ino(mut attr: FileAttr, ino: INum) -> FileAttr {
+    attr.ino = ino;
+    attr
+}
+
+/// Whether the directory `dir` of `fs` holds `name`
+async fn exists<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    dir: INum,
+    name: &OsStr,
+) -> DatenLordResult<bool> {
+    match fs.lookup(ctx, dir, name).await {
+        Ok(_) => Ok(true),
+        Err(DatenLordError::NotFound { .. }) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Copy the contents of the file `src` of `from` to the empty file `dst` of
+/// `to`
+pub(super) async fn copy_data<F: VirtualFs + ?Sized, G: VirtualFs + ?Sized>(
+    from: &F,
+    ctx: &RequestContext,
+    src: INum,
+    to: &G,
+    dst: INum,
+) -> DatenLordResult<()> {
+    let fh_in = from.open(ctx, src, OFlag::O_RDONLY.bits() as u32).await?;
+    let fh_out = match to.open(ctx, dst, OFlag::O_WRONLY.bits() as u32).await {
+        Ok(fh) => fh,
+        Err(e) => {
+            let _ = from.release(ctx, src, fh_in, 0, 0, false).await;
+            return Err(e);
+        }
+    };
+    let mut buf = vec![0; COPY_CHUNK_SIZE];
+    let mut offset = 0_u64;
+    let copied = loop {
+        let read = match from
+            .read(ctx, src, fh_in, offset, buf.len() as u32, &mut buf)
+            .await
+        {
+            Ok(0) => break Ok(()),
+            Ok(read) => read,
+            Err(e) => break Err(e),
+        };
+        let at = i64::try_from(offset).unwrap_or(i64::MAX);
+        if let Err(e) = to.write(ctx, dst, fh_out, at, &buf[..read], 0).await {
+            break Err(e);
+        }
+        offset += read as u64;
+    };
+    let released = to.release(ctx, dst, fh_out, 0, 0, true).await;
+    from.release(ctx, src, fh_in, 0, 0, false).await?;
+    copied.and(released)
+}
+
+/// Give the copy `ino` of `fs` the mode, owner and times of `attr`, the
+/// owner only if the process may change it
+pub(super) async fn copy_attr<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+    attr: &FileAttr,
+) -> DatenLordResult<()> {
+    // Before the mode, since changing the owner clears the set-id bits
+    if (attr.uid, attr.gid) != (ctx.uid, ctx.gid) {
+        let owner = SetAttrParam {
+            u_id: Some(attr.uid),
+            g_id: Some(attr.gid),
+            ..SetAttrParam::default()
+        };
+        if let Err(e) = fs.setattr(ctx, ino, owner).await {
+            debug!("failed to keep the owner of the copy inode={ino}: {e}");
+        }
+    }
+    let param = SetAttrParam {
+        mode: Some(u32::from(attr.perm)),
+        a_time: Some(attr.atime),
+        m_time: Some(attr.mtime),
+        ..SetAttrParam::default()
+    };
+    fs.setattr(ctx, ino, param).await.map(|_| ())
+}
+
+/// Copy the extended attributes of `src` of `from` to `dst` of `to`, those
+/// failing to be copied being left out
+pub(super) async fn copy_xattrs<F: VirtualFs + ?Sized, G: VirtualFs + ?Sized>(
+    from: &F,
+    ctx: &RequestContext,
+    src: INum,
+    to: &G,
+    dst: INum,
+) {
+    let Ok(names) = from.listxattr(ctx, src).await else {
+        return;
+    };
+    for name in names {
+        let copied = match from.getxattr(ctx, src, &name).await {
+            Ok(value) => to.setxattr(ctx, dst, &name, &value, 0, 0).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = copied {
+            debug!("failed to copy the extended attribute {name} of inode={src}: {e}");
+        }
+    }
+}
+
+impl<F: VirtualFs> OverlayFs<F> {
+    /// Overlay the read-only layers `lowers`, the topmost first, with the
+    /// writable layer `upper`
+    pub fn new(upper: F, lowers: Vec<F>) -> Self {
+        let root = Node {
+            ino: ROOT_ID,
+            path: PathBuf::new(),
+            kind: SFlag::S_IFDIR,
+            layers: (0..=lowers.len()).map(|layer| (layer, ROOT_ID)).collect(),
+        };
+        let nodes = Nodes {
+            next_ino: ROOT_ID + 1,
+            by_ino: HashMap::from([(ROOT_ID, root)]),
+            by_path: HashMap::from([(PathBuf::new(), ROOT_ID)]),
+        };
+        Self {
+            upper,
+            lowers,
+            mounter: RequestContext::current(),
+            nodes: Mutex::new(nodes),
+            next_fh: AtomicU64::new(1),
+            files: Mutex::default(),
+            dirs: DirHandles::default(),
+            changes: tokio::sync::Mutex::default(),
+        }
+    }
+
+    /// The writable layer
+    pub fn upper(&self) -> &F {
+        &self.upper
+    }
+
+    /// The read-only layers, the topmost first
+    pub fn lowers(&self) -> &[F] {
+        &self.lowers
+    }
+
+    fn layer(&self, layer: usize) -> &F {
+        match layer {
+            UPPER => &self.upper,
+            lower => &self.lowers[lower - 1],
+        }
+    }
+
+    /// The entry `ino`, not scoped by a context
+    fn known(&self, ino: INum) -> DatenLordResult<Node> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .by_ino
+            .get(&ino)
+            .cloned()
+            .ok_or_else(|| DatenLordError::NotFound {
+                context: vec![format!("no inode={ino} in the overlay")],
+                source: None,
+            })
+    }
+
+    /// The entry `ino` stands for on behalf of `ctx`
+    fn node(&self, ctx: &RequestContext, ino: INum) -> DatenLordResult<Node> {
+        self.known(ctx.scope(ino))
+    }
+
+    /// The open file `fh`
+    fn file(&self, fh: u64) -> DatenLordResult<OpenFile> {
+        self.files.lock().unwrap().get(&fh).copied().ok_or_else(|| {
+            DatenLordError::InvalidArgument {
+                context: vec![format!("unknown file handle={fh}")],
+            }
+        })
+    }
+
+    /// The entry at `path`, held by the layers of `found` from now on
+    fn register(&self, path: PathBuf, found: &Found) -> Node {
+        let mut nodes = self.nodes.lock().unwrap();
+        let ino = match nodes.by_path.get(&path) {
+            Some(&ino) => ino,
+            None => {
+                let ino = nodes.next_ino;
+                nodes.next_ino += 1;
+                nodes.by_path.insert(path.clone(), ino);
+                ino
+            }
+        };
+        let node = Node {
+            ino,
+            path,
+            kind: found.attr.kind,
+            layers: found.layers.clone(),
+        };
+        match nodes.by_ino.get(&ino) {
+            // A lookup racing with a copy-up must not hide the copy
+            Some(known) if known.in_upper() && !node.in_upper() => known.clone(),
+            _ => {
+                nodes.by_ino.insert(ino, node.clone());
+                node
+            }
+        }
+    }
+
+    /// Forget the entry at `path` and everything under it
+    fn unregister(&self, path: &Path) {
+        let mut nodes = self.nodes.lock().unwrap();
+        let Nodes {
+            ref mut by_ino,
+            ref mut by_path,
+            ..
+        } = *nodes;
+        by_path.retain(|known, ino| {
+            let gone = known.starts_with(path);
+            if gone {
+                by_ino.remove(ino);
+            }
+            !gone
+        });
+    }
+
+    /// Move the entry at `from` and everything under it to `to`, replacing
+    /// the entries there
+    fn move_nodes(&self, from: &Path, to: &Path) {
+        self.unregister(to);
+        let mut nodes = self.nodes.lock().unwrap();
+        let Nodes {
+            ref mut by_ino,
+            ref mut by_path,
+            ..
+        } = *nodes;
+        let moved: Vec<_> = by_path
+            .iter()
+            .filter(|&(path, _)| path.starts_with(from))
+            .map(|(path, &ino)| (path.clone(), ino))
+            .collect();
+        for (path, ino) in moved {
+            by_path.remove(&path);
+            let path = match path.strip_prefix(from) {
+                Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+                _ => to.to_owned(),
+            };
+            if let Some(node) = by_ino.get_mut(&ino) {
+                node.path.clone_from(&path);
+            }
+            by_path.insert(path, ino);
+        }
+    }
+
+    /// The attributes of `node` in its topmost layer
+    async fn attr(&self, ctx: &RequestContext, node: &Node) -> DatenLordResult<FileAttr> {
+        let (layer, ino) = node.top();
+        let (_, attr) = self.layer(layer).getattr(&layer_ctx(ctx), ino).await?;
+        Ok(with_ino(attr, node.ino))
+    }
+
+    /// The entry `name` of the directory held by `layers`, the topmost
+    /// first, `None` when none holds it or a whiteout hides it
+    ///
+    /// `ctx` is already the context of the layers.
+    async fn find(
+        &self,
+        ctx: &RequestContext,
+        layers: &[(usize, INum)],
+        name: &OsStr,
+    ) -> DatenLordResult<Option<Found>> {
+        let mut found: Option<Found> = None;
+        for &(layer, dir) in layers {
+            let fs = self.layer(layer);
+            let attr = match fs.lookup(ctx, dir, name).await {
+                Ok((_, attr, _)) => attr,
+                Err(DatenLordError::NotFound { .. }) => {
+                    if exists(fs, ctx, dir, &whiteout_name(name)).await? {
+                        break;
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            match found {
+                None => {
+                    found = Some(Found {
+                        attr,
+                        layers: vec![(layer, attr.ino)],
+                    });
+                }
+                // Directories merge, anything else hides the layers below
+                Some(ref mut entry)
+                    if entry.attr.kind == SFlag::S_IFDIR && attr.kind == SFlag::S_IFDIR =>
+                {
+                    entry.layers.push((layer, attr.ino));
+                }
+                Some(_) => break,
+            }
+            if attr.kind != SFlag::S_IFDIR
+                || exists(fs, ctx, attr.ino, OsStr::new(OPAQUE_MARKER)).await?
+            {
+                break;
+            }
+        }
+        Ok(found)
+    }
+
+    /// The entry `name` of the directory `dir` and its attributes
+    async fn child(
+        &self,
+        ctx: &RequestContext,
+        dir: &Node,
+        name: &OsStr,
+    ) -> DatenLordResult<(Node, FileAttr)> {
+        if dir.kind != SFlag::S_IFDIR {
+            return Err(errno(
+                Errno::ENOTDIR,
+                format!("{:?} of the overlay is not a directory", dir.path),
+            ));
+        }
+        let not_found = || DatenLordError::NotFound {
+            context: vec![format!(
+                "no entry {name:?} in {:?} of the overlay",
+                dir.path
+            )],
+            source: None,
+        };
+        if is_internal(name) {
+            return Err(not_found());
+        }
+        let found = self
+            .find(&layer_ctx(ctx), &dir.layers, name)
+            .await?
+            .ok_or_else(not_found)?;
+        let node = self.register(dir.path.join(name), &found);
+        if node.top() == found.layers[0] {
+            Ok((node.clone(), with_ino(found.attr, node.ino)))
+        } else {
+            let attr = self.attr(ctx, &node).await?;
+            Ok((node, attr))
+        }
+    }
+
+    /// The entry at `path`, looked up from the root unless known
+    async fn node_at(&self, ctx: &RequestContext, path: &Path) -> DatenLordResult<Node> {
+        let known = {
+            let nodes = self.nodes.lock().unwrap();
+            let ino = nodes.by_path.get(path);
+            ino.and_then(|ino| nodes.by_ino.get(ino)).cloned()
+        };
+        if let Some(node) = known {
+            return Ok(node);
+        }
+        let mut node = self.known(ROOT_ID)?;
+        for name in path {
+            node = self.child(ctx, &node, name).await?.0;
+        }
+        Ok(node)
+    }
+
+    /// The entry `name` of the directory `dir`, `..` being its parent, and
+    /// its attributes
+    async fn walk(
+        &self,
+        ctx: &RequestContext,
+        dir: &Node,
+        name: &OsStr,
+    ) -> DatenLordResult<(Node, FileAttr)> {
+        if name != ".." {
+            return self.child(ctx, dir, name).await;
+        }
+        // The root of the caller is its own parent
+        let parent = match dir.path.parent() {
+            Some(parent) if dir.ino != ctx.scope(ROOT_ID) => self.node_at(ctx, parent).await?,
+            _ => dir.clone(),
+        };
+        let attr = self.attr(ctx, &parent).await?;
+        Ok((parent, attr))
+    }
+
+    /// The directory holding the last component of `name`, a `/` separated
+    /// path under `parent` like the SDKs pass, and that component
+    async fn resolve(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Node, OsString)> {
+        let mut components: Vec<_> = fs_util::components(name).collect();
+        let last = components
+            .pop()
+            .ok_or_else(|| DatenLordError::InvalidName {
+                context: vec![format!("invalid name {name:?}: no component")],
+            })?;
+        let mut dir = self.node(ctx, parent)?;
+        for component in components {
+            dir = self.walk(ctx, &dir, component).await?.0;
+        }
+        if dir.kind != SFlag::S_IFDIR {
+            return Err(errno(
+                Errno::ENOTDIR,
+                format!("{:?} of the overlay is not a directory", dir.path),
+            ));
+        }
+        Ok((dir, last.to_owned()))
+    }
+
+    /// The entries of the directory `node` merged over its layers, with
+    /// their attributes and the topmost layer holding them
+    async fn merged(
+        &self,
+        ctx: &RequestContext,
+        node: &Node,
+    ) -> DatenLordResult<Vec<(OsString, FileAttr, usize)>> {
+        let ctx = layer_ctx(ctx);
+        let mut entries = Vec::new();
+        let mut seen = HashSet::new();
+        for &(layer, dir) in &node.layers {
+            let mut whiteouts = Vec::new();
+            for (name, attr) in list_dir(self.layer(layer), &ctx, dir).await? {
+                if let Some(hidden) = name.as_bytes().strip_prefix(WHITEOUT_PREFIX.as_bytes()) {
+                    whiteouts.push(OsStr::from_bytes(hidden).to_owned());
+                } else if seen.insert(name.clone()) {
+                    entries.push((name, attr, layer));
+                }
+            }
+            // A whiteout only hides the layers below its own
+            seen.extend(whiteouts);
+        }
+        Ok(entries)
+    }
+
+    /// The entries of the directory `node`, with their attributes
+    async fn list(&self, ctx: &RequestContext, node: &Node) -> DatenLordResult<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        for (name, attr, layer) in self.merged(ctx, node).await? {
+            let child = if attr.kind == SFlag::S_IFDIR {
+                // Only a lookup tells which layers the directory merges
+                match self.child(ctx, node, &name).await {
+                    Ok((child, _)) => child,
+                    Err(DatenLordError::NotFound { .. }) => continue,
+                    Err(e) => return Err(e),
+                }
+            } else {
+                let found = Found {
+                    attr,
+                    layers: vec![(layer, attr.ino)],
+                };
+                self.register(node.path.join(&name), &found)
+            };
+            entries.push(DirEntry {
+                name,
+                ino: child.ino,
+                kind: FileKind::from_sflag(attr.kind).unwrap_or(FileKind::RegularFile),
+                attr: Some(with_ino(attr, child.ino)),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Create the file `name` of `MARKER_MODE` in the directory `dir` of the
+    /// upper layer, if missing
+    async fn mark(&self, dir: INum, name: OsString) -> DatenLordResult<()> {
+        let param = CreateParam {
+            parent: dir,
+            name,
+            mode: MARKER_MODE,
+            rdev: 0,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        match self.upper.mknod(&self.mounter, param).await {
+            Ok(_) | Err(DatenLordError::AlreadyExists { .. }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Remove the whiteouts and the opaque marker of the directory `dir` of
+    /// the upper layer
+    async fn clear_markers(&self, dir: INum) -> DatenLordResult<()> {
+        for (name, _) in list_dir(&self.upper, &self.mounter, dir).await? {
+            if is_internal(&name) {
+                self.upper.unlink(&self.mounter, dir, &name).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `node` once in the upper layer, copying it up together with its
+    /// parents missing there
+    ///
+    /// The caller holds `changes`.
+    async fn copy_up(&self, node: &Node) -> DatenLordResult<Node> {
+        if node.in_upper() {
+            return Ok(node.clone());
+        }
+        let mut dir = self.known(ROOT_ID)?;
+        for name in &node.path {
+            // Looked up anew, another change may have copied it up
+            let (child, _) = self.child(&self.mounter, &dir, name).await?;
+            dir = if child.in_upper() {
+                child
+            } else {
+                self.copy_up_one(&dir, &child).await?
+            };
+        }
+        Ok(dir)
+    }
+
+    /// Copy `node` up into its parent `dir`, already in the upper layer,
+    /// with its contents, attributes and extended attributes
+    async fn copy_up_one(&self, dir: &Node, node: &Node) -> DatenLordResult<Node> {
+        let ctx = &self.mounter;
+        let (layer, ino) = node.top();
+        let lower = self.layer(layer);
+        let (_, attr) = lower.getattr(ctx, ino).await?;
+        let (_, parent) = dir.top();
+        let name = node.path.file_name().unwrap_or_default().to_owned();
+        // Writable by the process until the copy is complete
+        let param = CreateParam {
+            parent,
+            name: name.clone(),
+            mode: u32::from(attr.perm) | 0o700,
+            rdev: attr.rdev,
+            node_type: attr.kind,
+            link: None,
+        };
+        let (_, copy, _) = match attr.kind {
+            SFlag::S_IFDIR => self.upper.mkdir(ctx, param).await?,
+            SFlag::S_IFLNK => {
+                let target = lower.readlink(ctx, ino).await?;
+                let target = Path::new(OsStr::from_bytes(&target));
+                self.upper.symlink(ctx, parent, &name, target).await?
+            }
+            _ => self.upper.mknod(ctx, param).await?,
+        };
+        let copied = async {
+            if attr.kind == SFlag::S_IFREG {
+                copy_data(lower, ctx, ino, &self.upper, copy.ino).await?;
+            }
+            if attr.kind != SFlag::S_IFLNK {
+                copy_attr(&self.upper, ctx, copy.ino, &attr).await?;
+            }
+            copy_xattrs(lower, ctx, ino, &self.upper, copy.ino).await;
+            Ok::<(), DatenLordError>(())
+        };
+        if let Err(e) = copied.await {
+            // A partial copy would hide the complete one below
+            let removed = if attr.kind == SFlag::S_IFDIR {
+                self.upper.rmdir(ctx, parent, &name).await.map(|_| ())
+            } else {
+                self.upper.unlink(ctx, parent, &name).await
+            };
+            if let Err(e) = removed {
+                warn!("failed to remove the partial copy of {:?}: {e}", node.path);
+            }
+            return Err(e.add_context(format!("failed to copy up {:?}", node.path)));
+        }
+        let layers = if attr.kind == SFlag::S_IFDIR {
+            std::iter::once((UPPER, copy.ino))
+                .chain(node.layers.iter().copied())
+                .collect()
+        } else {
+            vec![(UPPER, copy.ino)]
+        };
+        Ok(self.register(node.path.clone(), &Found { attr: copy, layers }))
+    }
+
+    /// The entry `node` once in the upper layer, taking `changes`
+    async fn copied_up(&self, node: Node) -> DatenLordResult<Node> {
+        if node.in_upper() {
+            return Ok(node);
+        }
+        let _changes = self.changes.lock().await;
+        self.copy_up(&node).await
+    }
+
+    /// Create the entry `param` describes in the upper layer, a symbolic
+    /// link to `param.link` if of that kind, replacing a whiteout of its name
+    async fn create_entry(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let _changes = self.changes.lock().await;
+        let (dir, name) = self.resolve(ctx, param.parent, &param.name).await?;
+        check_name(&name)?;
+        self.attr(ctx, &dir)
+            .await?
+            .check_perm(ctx, ACCESS_WRITE | ACCESS_EXEC)?;
+        let lctx = layer_ctx(ctx);
+        if self.find(&lctx, &dir.layers, &name).await?.is_some() {
+            return Err(DatenLordError::AlreadyExists {
+                context: vec![format!("{:?} exists in the overlay", dir.path.join(&name))],
+                source: None,
+            });
+        }
+        let dir = self.copy_up(&dir).await?;
+        let (_, upper_dir) = dir.top();
+        let whiteout = whiteout_name(&name);
+        let whited_out = exists(&self.upper, &self.mounter, upper_dir, &whiteout).await?;
+        let kind = param.node_type;
+        let param = CreateParam {
+            parent: upper_dir,
+            name: name.clone(),
+            ..param
+        };
+        let (_, attr, _) = match kind {
+            SFlag::S_IFDIR => self.upper.mkdir(&lctx, param).await?,
+            SFlag::S_IFLNK => {
+                let target = param.link.unwrap_or_default();
+                self.upper.symlink(&lctx, upper_dir, &name, &target).await?
+            }
+            _ => self.upper.mknod(&lctx, param).await?,
+        };
+        // Nothing of the removed directory below shows through the new one
+        if kind == SFlag::S_IFDIR && whited_out {
+            self.mark(attr.ino, OPAQUE_MARKER.into()).await?;
+        }
+        if whited_out {
+            self.upper
+                .unlink(&self.mounter, upper_dir, &whiteout)
+                .await?;
+        }
+        let found = Found {
+            attr,
+            layers: vec![(UPPER, attr.ino)],
+        };
+        let node = self.register(dir.path.join(&name), &found);
+        Ok((ATTR_TTL, with_ino(attr, node.ino), 0))
+    }
+
+    /// Remove `node` of attributes `attr` from the directory `dir`, leaving
+    /// a whiteout if a lower layer holds it
+    ///
+    /// The caller holds `changes`.
+    async fn remove(
+        &self,
+        ctx: &RequestContext,
+        dir: &Node,
+        node: &Node,
+        attr: &FileAttr,
+    ) -> DatenLordResult<()> {
+        let dir_attr = self.attr(ctx, dir).await?;
+        dir_attr.check_perm(ctx, ACCESS_WRITE | ACCESS_EXEC)?;
+        dir_attr.check_sticky(ctx, attr)?;
+        let name = node.path.file_name().unwrap_or_default();
+        let lctx = layer_ctx(ctx);
+        let in_lower = self.find(&lctx, dir.lower(), name).await?.is_some();
+        let dir = if in_lower {
+            self.copy_up(dir).await?
+        } else {
+            dir.clone()
+        };
+        let (_, upper_dir) = dir.top();
+        if node.in_upper() {
+            if attr.kind == SFlag::S_IFDIR {
+                self.clear_markers(node.top().1).await?;
+                self.upper.rmdir(&lctx, upper_dir, name).await?;
+            } else {
+                self.upper.unlink(&lctx, upper_dir, name).await?;
+            }
+        }
+        if in_lower {
+            self.mark(upper_dir, whiteout_name(name)).await?;
+        }
+        self.unregister(&node.path);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for OverlayFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.upper.init()?;
+        self.lowers.iter().try_for_each(VirtualFs::init)
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.upper.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.upper.destroy().await?;
+        for lower in &self.lowers {
+            lower.destroy().await?;
+        }
+        Ok(())
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.upper.interrupt(unique).await;
+        for lower in &self.lowers {
+            lower.interrupt(unique).await;
+        }
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        // An empty path names the directory itself, like for `LocalFS`
+        if fs_util::components(name).next().is_none() {
+            let node = self.node(ctx, parent)?;
+            return Ok((ATTR_TTL, self.attr(ctx, &node).await?, 0));
+        }
+        let (dir, name) = self.resolve(ctx, parent, name).await?;
+        let (_, attr) = self.walk(ctx, &dir, &name).await?;
+        Ok((ATTR_TTL, attr, 0))
+    }
+
+    // Entries are kept while their path exists
+    async fn forget(&self, _ino: u64, _nlookup: u64) {}
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let node = self.node(ctx, ino)?;
+        Ok((ATTR_TTL, self.attr(ctx, &node).await?))
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let node = self.copied_up(self.node(ctx, ino)?).await?;
+        // Handles opened before the copy-up are of the lower layer
+        let fh = param
+            .fh
+            .and_then(|fh| self.file(fh).ok())
+            .filter(|file| file.layer == UPPER)
+            .map(|file| file.fh);
+        let param = SetAttrParam { fh, ..param };
+        let (_, attr) = self
+            .upper
+            .setattr(&layer_ctx(ctx), node.top().1, param)
+            .await?;
+        Ok((ATTR_TTL, with_ino(attr, node.ino)))
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        let (layer, ino) = self.node(ctx, ino)?.top();
+        self.layer(layer).readlink(&layer_ctx(ctx), ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.create_entry(ctx, param).await
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let param = CreateParam {
+            node_type: SFlag::S_IFDIR,
+            ..param
+        };
+        self.create_entry(ctx, param).await
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let _changes = self.changes.lock().await;
+        let (dir, name) = self.resolve(ctx, parent, name).await?;
+        check_name(&name)?;
+        let (node, attr) = self.child(ctx, &dir, &name).await?;
+        if attr.kind == SFlag::S_IFDIR {
+            return Err(errno(
+                Errno::EISDIR,
+                format!("{:?} of the overlay is a directory", node.path),
+            ));
+        }
+        self.remove(ctx, &dir, &node, &attr).await
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        let _changes = self.changes.lock().await;
+        let (dir, name) = self.resolve(ctx, parent, dir_name).await?;
+        check_name(&name)?;
+        let (node, attr) = self.child(ctx, &dir, &name).await?;
+        if attr.kind != SFlag::S_IFDIR {
+            return Err(errno(
+                Errno::ENOTDIR,
+                format!("{:?} of the overlay is not a directory", node.path),
+            ));
+        }
+        if !self.merged(ctx, &node).await?.is_empty() {
+            return Err(errno(
+                Errno::ENOTEMPTY,
+                format!("directory {:?} of the overlay is not empty", node.path),
+            ));
+        }
+        self.remove(ctx, &dir, &node, &attr).await?;
+        Ok(Some(node.ino))
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let param = CreateParam {
+            parent,
+            name: name.to_owned(),
+            mode: 0o777,
+            rdev: 0,
+            node_type: SFlag::S_IFLNK,
+            link: Some(target_path.to_owned()),
+        };
+        self.create_entry(ctx, param).await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let flags = RenameFlags::from_bits_truncate(param.flags);
+        if flags.contains(RenameFlags::RENAME_EXCHANGE) {
+            return Err(DatenLordError::Unimplemented {
+                context: vec!["exchanging entries of the overlay is not supported".to_owned()],
+                source: None,
+            });
+        }
+        let _changes = self.changes.lock().await;
+        let (src_dir, src_name) = self.resolve(ctx, param.old_parent, &param.old_name).await?;
+        let (dst_dir, dst_name) = self.resolve(ctx, param.new_parent, &param.new_name).await?;
+        check_name(&src_name)?;
+        check_name(&dst_name)?;
+        let (src, src_attr) = self.child(ctx, &src_dir, &src_name).await?;
+        let src_is_dir = src_attr.kind == SFlag::S_IFDIR;
+        if src_is_dir && src.layers.iter().any(|&(layer, _)| layer != UPPER) {
+            return Err(errno(
+                Errno::EXDEV,
+                format!(
+                    "directory {:?} of a lower layer cannot be renamed",
+                    src.path
+                ),
+            ));
+        }
+        let dst_path = dst_dir.path.join(&dst_name);
+        if dst_path == src.path {
+            return Ok(());
+        }
+        if dst_path.starts_with(&src.path) {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("cannot move {:?} under itself", src.path)],
+            });
+        }
+        let dst = match self.child(ctx, &dst_dir, &dst_name).await {
+            Ok(dst) => Some(dst),
+            Err(DatenLordError::NotFound { .. }) => None,
+            Err(e) => return Err(e),
+        };
+        for (dir, entry) in [
+            (&src_dir, Some(&src_attr)),
+            (&dst_dir, dst.as_ref().map(|dst| &dst.1)),
+        ] {
+            let dir_attr = self.attr(ctx, dir).await?;
+            dir_attr.check_perm(ctx, ACCESS_WRITE | ACCESS_EXEC)?;
+            if let Some(entry) = entry {
+                dir_attr.check_sticky(ctx, entry)?;
+            }
+        }
+        if let Some((ref dst, ref dst_attr)) = dst {
+            if flags.contains(RenameFlags::RENAME_NOREPLACE) {
+                return Err(DatenLordError::AlreadyExists {
+                    context: vec![format!("{dst_path:?} exists in the overlay")],
+                    source: None,
+                });
+            }
+            match (src_is_dir, dst_attr.kind == SFlag::S_IFDIR) {
+                (true, false) => {
+                    return Err(errno(
+                        Errno::ENOTDIR,
+                        format!("{dst_path:?} is not a directory"),
+                    ));
+                }
+                (false, true) => {
+                    return Err(errno(Errno::EISDIR, format!("{dst_path:?} is a directory")));
+                }
+                (true, true) if !self.merged(ctx, dst).await?.is_empty() => {
+                    return Err(errno(
+                        Errno::ENOTEMPTY,
+                        format!("directory {dst_path:?} is not empty"),
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        let lctx = layer_ctx(ctx);
+        let src_in_lower = self
+            .find(&lctx, src_dir.lower(), &src_name)
+            .await?
+            .is_some();
+        let dst_in_lower = self
+            .find(&lctx, dst_dir.lower(), &dst_name)
+            .await?
+            .is_some();
+        let src = self.copy_up(&src).await?;
+        let dst_dir = self.copy_up(&dst_dir).await?;
+        // Copied up with the source
+        let src_dir = self.node_at(&self.mounter, &src_dir.path).await?;
+        if let Some((ref dst, ref dst_attr)) = dst {
+            if dst_attr.kind == SFlag::S_IFDIR && dst.in_upper() {
+                self.clear_markers(dst.top().1).await?;
+            }
+        }
+        let whiteout = whiteout_name(&dst_name);
+        let whited_out = exists(&self.upper, &self.mounter, dst_dir.top().1, &whiteout).await?;
+        if src_is_dir && (dst_in_lower || whited_out) {
+            self.mark(src.top().1, OPAQUE_MARKER.into()).await?;
+        }
+        let param = RenameParam {
+            old_parent: src_dir.top().1,
+            old_name: src_name.clone(),
+            new_parent: dst_dir.top().1,
+            new_name: dst_name,
+            flags: 0,
+        };
+        self.upper.rename(&lctx, param).await?;
+        if whited_out {
+            self.upper
+                .unlink(&self.mounter, dst_dir.top().1, &whiteout)
+                .await?;
+        }
+        if src_in_lower {
+            self.mark(src_dir.top().1, whiteout_name(&src_name)).await?;
+        }
+        self.move_nodes(&src.path, &dst_path);
+        Ok(())
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let mut node = self.node(ctx, ino)?;
+        let writes = fs_util::parse_oflag(flags)
+            .intersects(OFlag::O_WRONLY | OFlag::O_RDWR | OFlag::O_TRUNC);
+        if writes && !node.in_upper() {
+            // Checked first, so a denied open copies nothing up
+            self.attr(ctx, &node).await?.check_perm(ctx, ACCESS_WRITE)?;
+            node = self.copied_up(node).await?;
+        }
+        let (layer, ino) = node.top();
+        let fh = self.layer(layer).open(&layer_ctx(ctx), ino, flags).await?;
+        let file = OpenFile { layer, ino, fh };
+        let overlay_fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.files.lock().unwrap().insert(overlay_fh, file);
+        Ok(overlay_fh)
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        let file = self.file(fh)?;
+        self.layer(file.layer)
+            .read(&layer_ctx(ctx), file.ino, file.fh, offset, size, buf)
+            .await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let file = self.file(fh)?;
+        self.layer(file.layer)
+            .write(&layer_ctx(ctx), file.ino, file.fh, offset, data, flags)
+            .await
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        let file = self.file(fh)?;
+        self.layer(file.layer)
+            .flush(&layer_ctx(ctx), file.ino, file.fh, lock_owner)
+            .await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        let file = self.file(fh)?;
+        self.files.lock().unwrap().remove(&fh);
+        self.layer(file.layer)
+            .release(&layer_ctx(ctx), file.ino, file.fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        let file = self.file(fh)?;
+        self.layer(file.layer)
+            .fsync(&layer_ctx(ctx), file.ino, file.fh, datasync)
+            .await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        let file = self.file(fh)?;
+        self.layer(file.layer)
+            .lseek(&layer_ctx(ctx), file.ino, file.fh, offset, whence)
+            .await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, _flags: u32) -> DatenLordResult<u64> {
+        let node = self.node(ctx, ino)?;
+        if node.kind != SFlag::S_IFDIR {
+            return Err(errno(
+                Errno::ENOTDIR,
+                format!("{:?} of the overlay is not a directory", node.path),
+            ));
+        }
+        self.attr(ctx, &node).await?.check_perm(ctx, ACCESS_READ)?;
+        Ok(self.dirs.open(node.ino))
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        let node = self.node(ctx, ino)?;
+        let entries = self
+            .dirs
+            .read(node.ino, fh, offset, || self.list(ctx, &node))
+            .await?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| DirEntry {
+                attr: None,
+                ..entry
+            })
+            .collect())
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        let node = self.node(ctx, ino)?;
+        let entries = self
+            .dirs
+            .read(node.ino, fh, offset, || self.list(ctx, &node))
+            .await?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|entry| Some((entry.attr?, entry)))
+            .map(|(attr, entry)| (entry, attr, ATTR_TTL))
+            .collect())
+    }
+
+    async fn releasedir(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        _flags: u32,
+    ) -> DatenLordResult<()> {
+        self.dirs.release(fh);
+        Ok(())
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        _fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        // The lower layers do not change
+        match self.node(ctx, ino)?.top() {
+            (UPPER, ino) => self.upper.fsyncdir(&layer_ctx(ctx), ino, 0, datasync).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.upper.sync_all(&layer_ctx(ctx)).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, _ino: u64) -> DatenLordResult<StatFsParam> {
+        self.upper.statfs(&layer_ctx(ctx), ROOT_ID).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        let node = self.copied_up(self.node(ctx, ino)?).await?;
+        self.upper
+            .setxattr(&layer_ctx(ctx), node.top().1, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        let (layer, ino) = self.node(ctx, ino)?.top();
+        self.layer(layer).getxattr(&layer_ctx(ctx), ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        let (layer, ino) = self.node(ctx, ino)?.top();
+        self.layer(layer).listxattr(&layer_ctx(ctx), ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        let node = self.copied_up(self.node(ctx, ino)?).await?;
+        self.upper
+            .removexattr(&layer_ctx(ctx), node.top().1, name)
+            .await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        // Writing a file of a lower layer copies it up, so its mode decides
+        let (layer, ino) = self.node(ctx, ino)?.top();
+        self.layer(layer).access(&layer_ctx(ctx), ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let param = CreateParam {
+            parent,
+            name: name.to_owned(),
+            mode,
+            rdev: 0,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        match self.create_entry(ctx, param).await {
+            Err(DatenLordError::AlreadyExists { .. })
+                if !fs_util::parse_oflag(flags).contains(OFlag::O_EXCL) =>
+            {
+                Ok(())
+            }
+            result => result.map(|_| ()),
+        }
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        let file = self.file(lk_param.fh)?;
+        let lk_param = FileLockParam {
+            fh: file.fh,
+            ..lk_param
+        };
+        self.layer(file.layer)
+            .getlk(&layer_ctx(ctx), file.ino, lk_param)
+            .await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        let file = self.file(lk_param.fh)?;
+        let lk_param = FileLockParam {
+            fh: file.fh,
+            ..lk_param
+        };
+        self.layer(file.layer)
+            .setlk(&layer_ctx(ctx), file.ino, lk_param, sleep)
+            .await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        let (layer, ino) = self.node(ctx, ino)?.top();
+        self.layer(layer)
+            .ioctl(&layer_ctx(ctx), ino, cmd, input)
+            .await
+    }
+}
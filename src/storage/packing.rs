@@ -0,0 +1,1188 @@
+//! Packing of small files into append-only segment files, for namespaces
+//! with the `packing` feature
+//!
+//! The data of a file of at most `max_file_size` bytes is appended to the
+//! newest segment of the store next to the superblock, and an index log
+//! records where it lives. Changing a packed file appends its data again,
+//! leaving the old copy as dead space. Once a segment reaches
+//! `segment_size` a new one is started, and a segment left at least half
+//! dead is compacted: its live data is appended to the newest segment and
+//! the segment removed. The wrapped filesystem keeps the namespace and the
+//! attributes, its files being left sparse at their size.
+//!
+//! Files are packed when written while empty, and unpacked to the wrapped
+//! filesystem once they grow past `max_file_size`. Like the inode table,
+//! the index is shared by every instance opening the namespace, each taking
+//! a lock on the store before using it and first applying the records the
+//! others appended.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use nix::fcntl::{OFlag, RenameFlags};
+use nix::libc;
+use nix::sys::stat::SFlag;
+use rustix::fs::{flock, FlockOperation};
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{
+    seek_extents, CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::gc::{GcReport, Sweep};
+use super::striping::{create_dir, owner_context};
+use super::superblock::SUPERBLOCK_NAME;
+use super::layout::BlockLayout;
+use super::virtualfs::{copy_range_through, INum, VirtualFs};
+
+/// Default size of the largest packed file, 4 KiB
+const DEFAULT_MAX_FILE_SIZE: u64 = 4096;
+/// Default size of a segment past which a new one is started, 64 MiB
+const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+/// The size of the length and CRC-32 before every index record
+const HEADER_SIZE: usize = 8;
+/// The records the index holds at least before it is rewritten
+const COMPACT_MIN_RECORDS: usize = 4096;
+/// The locks serializing the changes to a packed file, picked by inode
+/// number
+const LOCK_COUNT: usize = 64;
+
+/// Which files are packed and how large segments grow
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackingConfig {
+    /// The size of the largest file packed
+    pub max_file_size: u64,
+    /// The size of a segment past which a new one is started
+    pub segment_size: u64,
+}
+
+impl Default for PackingConfig {
+    fn default() -> Self {
+        Self {
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+        }
+    }
+}
+
+/// How much the segments hold
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackStats {
+    /// The packed files
+    pub files: u64,
+    /// The segments holding them
+    pub segments: u64,
+    /// The bytes of the packed files
+    pub live_bytes: u64,
+    /// The bytes of the segments no file refers to any more
+    pub dead_bytes: u64,
+}
+
+/// Where the data of a packed file lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Extent {
+    segment: u64,
+    offset: u64,
+    len: u32,
+    /// The CRC-32C of the data
+    crc: u32,
+}
+
+/// A change to the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Record {
+    /// The data of `ino` is at `extent`
+    Put { ino: INum, extent: Extent },
+    /// `ino` is not packed any more
+    Drop(INum),
+}
+
+/// Reads the fields of a record
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+impl Record {
+    /// The record framed by its length and CRC-32
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match *self {
+            Self::Put { ino, extent } => {
+                data.push(0);
+                data.extend_from_slice(&ino.to_le_bytes());
+                data.extend_from_slice(&extent.segment.to_le_bytes());
+                data.extend_from_slice(&extent.offset.to_le_bytes());
+                data.extend_from_slice(&extent.len.to_le_bytes());
+                data.extend_from_slice(&extent.crc.to_le_bytes());
+            }
+            Self::Drop(ino) => {
+                data.push(1);
+                data.extend_from_slice(&ino.to_le_bytes());
+            }
+        }
+        let mut frame = Vec::with_capacity(HEADER_SIZE + data.len());
+        frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+        frame.extend_from_slice(&data);
+        frame
+    }
+
+    /// The record framed at the start of `buf` with the size of its frame,
+    /// `None` unless a whole valid frame starts there
+    fn decode(buf: &[u8]) -> Option<(Self, usize)> {
+        let mut header = Fields(buf.get(..HEADER_SIZE)?);
+        let len = header.u32()? as usize;
+        let crc = header.u32()?;
+        let data = buf.get(HEADER_SIZE..HEADER_SIZE.checked_add(len)?)?;
+        if crc32fast::hash(data) != crc {
+            return None;
+        }
+        let (&kind, data) = data.split_first()?;
+        let mut fields = Fields(data);
+        let record = match kind {
+            0 => Self::Put {
+                ino: fields.u64()?,
+                extent: Extent {
+                    segment: fields.u64()?,
+                    offset: fields.u64()?,
+                    len: fields.u32()?,
+                    crc: fields.u32()?,
+                },
+            },
+            1 => Self::Drop(fields.u64()?),
+            _ => return None,
+        };
+        Some((record, HEADER_SIZE + len))
+    }
+}
+
+/// The bytes of a segment the index knows of
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    /// The bytes of the packed files
+    live: u64,
+    /// The end of the data written
+    end: u64,
+}
+
+impl Usage {
+    fn dead(self) -> u64 {
+        self.end.saturating_sub(self.live)
+    }
+}
+
+/// The index in memory and the log it was read from
+#[derive(Debug)]
+struct State {
+    files: HashMap<INum, Extent>,
+    segments: BTreeMap<u64, Usage>,
+    /// The segment appended to
+    active: u64,
+    /// The log, opened for appending
+    log: File,
+    /// The local inode of the log, changed when another instance rewrote it
+    log_ino: u64,
+    /// The end of the records read or written
+    offset: u64,
+    /// The records in the log
+    records: usize,
+}
+
+impl State {
+    /// Apply `record` to the index
+    fn apply(&mut self, record: &Record) {
+        match *record {
+            Record::Put { ino, extent } => {
+                self.release(ino);
+                let usage = self.segments.entry(extent.segment).or_default();
+                usage.live += u64::from(extent.len);
+                usage.end = usage.end.max(extent.offset + u64::from(extent.len));
+                self.active = self.active.max(extent.segment);
+                self.files.insert(ino, extent);
+            }
+            Record::Drop(ino) => self.release(ino),
+        }
+    }
+
+    /// Forget where the data of `ino` lives
+    fn release(&mut self, ino: INum) {
+        if let Some(old) = self.files.remove(&ino) {
+            if let Some(usage) = self.segments.get_mut(&old.segment) {
+                usage.live -= u64::from(old.len);
+            }
+        }
+    }
+}
+
+/// Map an `io::Error` on the store at `path` to `DatenLordError::Io`
+fn pack_error(path: &Path) -> impl FnOnce(io::Error) -> DatenLordError + '_ {
+    move |e| DatenLordError::Io {
+        context: vec![format!("failed to access the packed files {path:?}: {e}")],
+        source: None,
+    }
+}
+
+/// Open the log at `path` for appending
+fn open_log(path: &Path) -> DatenLordResult<(File, u64)> {
+    let log = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(pack_error(path))?;
+    let log_ino = log.metadata().map_err(pack_error(path))?.ino();
+    Ok((log, log_ino))
+}
+
+/// Holds the lock on the store until dropped
+struct Locked<'a>(&'a File);
+
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        let _ = flock(self.0, FlockOperation::Unlock);
+    }
+}
+
+/// The segments and their index
+#[derive(Debug)]
+struct Packs {
+    /// The directory of the segments and the index
+    dir: PathBuf,
+    /// The path of the index
+    index: PathBuf,
+    /// The directory, locked by the instance using the index
+    lock: File,
+    config: PackingConfig,
+    state: StdMutex<State>,
+    /// The locks serializing changes to a packed file
+    locks: Vec<Mutex<()>>,
+}
+
+impl Packs {
+    /// Open the store of the namespace rooted at `root`, created when missing
+    fn open(root: &Path, config: &PackingConfig) -> DatenLordResult<Self> {
+        let dir = root.join(format!("{SUPERBLOCK_NAME}.packs"));
+        match create_dir(&dir) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                return Err(pack_error(&dir)(e));
+            }
+            _ => {}
+        }
+        let lock = File::open(&dir).map_err(pack_error(&dir))?;
+        let index = dir.join("index");
+        let (log, log_ino) = open_log(&index)?;
+        // Data appended by an instance that crashed before indexing it
+        // still takes the newest segment
+        let active = fs::read_dir(&dir)
+            .map_err(pack_error(&dir))?
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0);
+        let packs = Self {
+            dir,
+            index,
+            lock,
+            config: config.clone(),
+            state: StdMutex::new(State {
+                files: HashMap::new(),
+                segments: BTreeMap::new(),
+                active,
+                log,
+                log_ino,
+                offset: 0,
+                records: 0,
+            }),
+            locks: (0..LOCK_COUNT).map(|_| Mutex::new(())).collect(),
+        };
+        packs.change(|_, _, _| Ok(()))?;
+        Ok(packs)
+    }
+
+    /// Lock the packed file `ino`
+    async fn lock(&self, ino: INum) -> MutexGuard<'_, ()> {
+        self.locks[(ino % LOCK_COUNT as u64) as usize].lock().await
+    }
+
+    /// The path of segment `id`
+    fn segment(&self, id: u64) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    /// Take the lock on the store, released when the guard is dropped
+    fn lock_index(&self) -> DatenLordResult<Locked<'_>> {
+        flock(&self.lock, FlockOperation::LockExclusive).map_err(|e| DatenLordError::Io {
+            context: vec![format!(
+                "failed to lock the packed files {:?}: {e}",
+                self.dir
+            )],
+            source: None,
+        })?;
+        Ok(Locked(&self.lock))
+    }
+
+    /// Apply the records other instances appended to the index, reading it
+    /// again from the start if one of them rewrote it, and cut off a partly
+    /// written record at its end
+    ///
+    /// The lock on the store must be held.
+    fn catch_up(&self, state: &mut State) -> DatenLordResult<()> {
+        let current = fs::metadata(&self.index).map_err(pack_error(&self.index))?;
+        if current.ino() != state.log_ino {
+            let (log, log_ino) = open_log(&self.index)?;
+            state.files.clear();
+            state.segments.clear();
+            state.log = log;
+            state.log_ino = log_ino;
+            state.offset = 0;
+            state.records = 0;
+        }
+        let len = state.log.metadata().map_err(pack_error(&self.index))?.len();
+        if len <= state.offset {
+            return Ok(());
+        }
+        let mut buf = vec![0; (len - state.offset) as usize];
+        state
+            .log
+            .read_exact_at(&mut buf, state.offset)
+            .map_err(pack_error(&self.index))?;
+        let mut parsed = 0;
+        while let Some((record, size)) = Record::decode(&buf[parsed..]) {
+            state.apply(&record);
+            state.records += 1;
+            parsed += size;
+        }
+        state.offset += parsed as u64;
+        if parsed < buf.len() {
+            warn!(
+                "cutting off {} bytes of a partly written record at the end of {:?}",
+                buf.len() - parsed,
+                self.index
+            );
+            state
+                .log
+                .set_len(state.offset)
+                .map_err(pack_error(&self.index))?;
+        }
+        Ok(())
+    }
+
+    /// Run `change` on the index brought up to date with the log, append
+    /// the records it applied, then remove the segments left empty
+    fn change<T>(
+        &self,
+        change: impl FnOnce(&Self, &mut State, &mut Vec<Record>) -> DatenLordResult<T>,
+    ) -> DatenLordResult<T> {
+        let mut state = self.state.lock().unwrap();
+        let _locked = self.lock_index()?;
+        self.catch_up(&mut state)?;
+        let mut records = Vec::new();
+        let result = change(self, &mut state, &mut records);
+        if !records.is_empty() {
+            let data: Vec<u8> = records.iter().flat_map(Record::encode).collect();
+            state
+                .log
+                .write_all(&data)
+                .map_err(pack_error(&self.index))?;
+            state.offset += data.len() as u64;
+            state.records += records.len();
+        }
+        let active = state.active;
+        let empty: Vec<u64> = state
+            .segments
+            .iter()
+            .filter(|&(&id, usage)| id != active && usage.live == 0)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in empty {
+            match fs::remove_file(self.segment(id)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    warn!("failed to remove the empty segment {id}: {e}");
+                }
+                _ => {
+                    state.segments.remove(&id);
+                }
+            }
+        }
+        if state.records >= COMPACT_MIN_RECORDS && state.records > 4 * state.files.len() {
+            if let Err(e) = self.rewrite(&mut state) {
+                warn!("failed to rewrite the index of the packed files: {e}");
+            }
+        }
+        result
+    }
+
+    /// Rewrite the index with the records of the files as they are
+    ///
+    /// The lock on the store must be held.
+    fn rewrite(&self, state: &mut State) -> DatenLordResult<()> {
+        let tmp = self.dir.join("index.tmp");
+        let data: Vec<u8> = state
+            .files
+            .iter()
+            .flat_map(|(&ino, &extent)| Record::Put { ino, extent }.encode())
+            .collect();
+        let mut file = File::create(&tmp).map_err(pack_error(&tmp))?;
+        file.write_all(&data).map_err(pack_error(&tmp))?;
+        file.sync_all().map_err(pack_error(&tmp))?;
+        fs::rename(&tmp, &self.index).map_err(pack_error(&self.index))?;
+        let (log, log_ino) = open_log(&self.index)?;
+        state.log = log;
+        state.log_ino = log_ino;
+        state.offset = data.len() as u64;
+        state.records = state.files.len();
+        Ok(())
+    }
+
+    /// Apply `record` to `state` and queue it for the log
+    fn log(state: &mut State, records: &mut Vec<Record>, record: Record) {
+        state.apply(&record);
+        records.push(record);
+    }
+
+    /// Read the data at `extent`
+    fn read_extent(&self, ino: INum, extent: Extent) -> DatenLordResult<Vec<u8>> {
+        let path = self.segment(extent.segment);
+        let mut data = vec![0; extent.len as usize];
+        File::open(&path)
+            .and_then(|file| file.read_exact_at(&mut data, extent.offset))
+            .map_err(pack_error(&path))?;
+        if crc32c::crc32c(&data) != extent.crc {
+            return Err(DatenLordError::Io {
+                context: vec![format!(
+                    "packed inode {ino} does not match its checksum in segment {}",
+                    extent.segment
+                )],
+                source: None,
+            });
+        }
+        Ok(data)
+    }
+
+    /// Append `data` to the newest segment as the data of `ino`, starting a
+    /// new segment when it is full
+    fn append(
+        &self,
+        state: &mut State,
+        records: &mut Vec<Record>,
+        ino: INum,
+        data: &[u8],
+    ) -> DatenLordResult<()> {
+        let mut path = self.segment(state.active);
+        let mut offset = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+        if offset >= self.config.segment_size {
+            state.active += 1;
+            path = self.segment(state.active);
+            offset = 0;
+        }
+        let mut segment = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(pack_error(&path))?;
+        segment.write_all(data).map_err(pack_error(&path))?;
+        let extent = Extent {
+            segment: state.active,
+            offset,
+            len: data.len() as u32,
+            crc: crc32c::crc32c(data),
+        };
+        Self::log(state, records, Record::Put { ino, extent });
+        Ok(())
+    }
+
+    /// Move the live data of segment `id` to the newest one if at least
+    /// half of it is dead and it is not the newest
+    fn compact(
+        &self,
+        state: &mut State,
+        records: &mut Vec<Record>,
+        id: u64,
+    ) -> DatenLordResult<()> {
+        let Some(&usage) = state.segments.get(&id) else {
+            return Ok(());
+        };
+        if id == state.active || usage.dead() < usage.live {
+            return Ok(());
+        }
+        let moved: Vec<(INum, Extent)> = state
+            .files
+            .iter()
+            .filter(|&(_, extent)| extent.segment == id)
+            .map(|(&ino, &extent)| (ino, extent))
+            .collect();
+        for (ino, extent) in moved {
+            let data = self.read_extent(ino, extent)?;
+            self.append(state, records, ino, &data)?;
+        }
+        Ok(())
+    }
+
+    /// The data of `ino` if packed
+    fn get(&self, ino: INum) -> DatenLordResult<Option<Vec<u8>>> {
+        self.change(|packs, state, _| match state.files.get(&ino) {
+            Some(&extent) => packs.read_extent(ino, extent).map(Some),
+            None => Ok(None),
+        })
+    }
+
+    /// Whether `ino` is packed
+    fn contains(&self, ino: INum) -> DatenLordResult<bool> {
+        self.change(|_, state, _| Ok(state.files.contains_key(&ino)))
+    }
+
+    /// Pack `data` as the data of `ino`
+    fn put(&self, ino: INum, data: &[u8]) -> DatenLordResult<()> {
+        self.change(|packs, state, records| {
+            let old = state.files.get(&ino).map(|extent| extent.segment);
+            packs.append(state, records, ino, data)?;
+            match old {
+                Some(id) => packs.compact(state, records, id),
+                None => Ok(()),
+            }
+        })
+    }
+
+    /// Unpack `ino`, dropping its data
+    fn remove(&self, ino: INum) -> DatenLordResult<()> {
+        self.change(|packs, state, records| {
+            let Some(old) = state.files.get(&ino).map(|extent| extent.segment) else {
+                return Ok(());
+            };
+            Self::log(state, records, Record::Drop(ino));
+            packs.compact(state, records, old)
+        })
+    }
+
+    /// Sync the segment holding `ino` and the index to their device
+    fn sync(&self, ino: INum) -> DatenLordResult<()> {
+        self.change(|packs, state, _| {
+            if let Some(extent) = state.files.get(&ino) {
+                let path = packs.segment(extent.segment);
+                File::open(&path)
+                    .and_then(|file| file.sync_data())
+                    .map_err(pack_error(&path))?;
+            }
+            state.log.sync_data().map_err(pack_error(&packs.index))
+        })
+    }
+
+    /// The packed inodes and their bytes, sorted
+    fn files(&self) -> DatenLordResult<Vec<(INum, u64)>> {
+        self.change(|_, state, _| {
+            let mut files: Vec<_> = state
+                .files
+                .iter()
+                .map(|(&ino, extent)| (ino, u64::from(extent.len)))
+                .collect();
+            files.sort_unstable();
+            Ok(files)
+        })
+    }
+
+    /// How much the segments hold
+    fn stats(&self) -> DatenLordResult<PackStats> {
+        self.change(|_, state, _| {
+            let mut stats = PackStats {
+                files: state.files.len() as u64,
+                segments: state.segments.len() as u64,
+                ..PackStats::default()
+            };
+            for usage in state.segments.values() {
+                stats.live_bytes += usage.live;
+                stats.dead_bytes += usage.dead();
+            }
+            Ok(stats)
+        })
+    }
+}
+
+/// A `VirtualFs` packing the small files into segments when the namespace
+/// has the `packing` feature, and keeping the rest in the wrapped
+/// filesystem
+#[derive(Debug)]
+pub struct PackedBackend<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// The segments, `None` without the feature
+    packs: Option<Packs>,
+}
+
+impl<F: VirtualFs> PackedBackend<F> {
+    /// Pack the small files of `inner` in the store of the namespace rooted
+    /// at `root` as `config` says if `enabled`
+    pub fn new(
+        inner: F,
+        root: &Path,
+        enabled: bool,
+        config: &PackingConfig,
+    ) -> DatenLordResult<Self> {
+        let packs = if enabled {
+            if config.segment_size == 0 {
+                return Err(DatenLordError::InvalidArgument {
+                    context: vec!["segment size 0".to_owned()],
+                });
+            }
+            Some(Packs::open(root, config)?)
+        } else {
+            None
+        };
+        Ok(Self { inner, packs })
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// How much the segments hold, `None` without the feature
+    pub fn stats(&self) -> DatenLordResult<Option<PackStats>> {
+        self.packs.as_ref().map(Packs::stats).transpose()
+    }
+
+    /// Write `data`, the whole data of the packed file `ino`, to the wrapped
+    /// filesystem and unpack it
+    async fn unpack(&self, ctx: &RequestContext, ino: INum, data: &[u8]) -> DatenLordResult<()> {
+        let Some(ref packs) = self.packs else {
+            return Ok(());
+        };
+        let ctx = owner_context(ctx);
+        let fh = self
+            .inner
+            .open(&ctx, ino, OFlag::O_WRONLY.bits() as u32)
+            .await?;
+        let written = self.inner.write(&ctx, ino, fh, 0, data, 0).await;
+        let released = self.inner.release(&ctx, ino, fh, 0, 0, true).await;
+        written?;
+        released?;
+        packs.remove(ino)
+    }
+
+    /// Unpack the file `attr` describes if it was its last link
+    fn removed(&self, attr: &FileAttr) {
+        if let Some(ref packs) = self.packs {
+            if attr.kind == SFlag::S_IFREG && attr.nlink <= 1 {
+                if let Err(e) = packs.remove(attr.ino) {
+                    warn!("failed to drop packed inode {}: {e}", attr.ino);
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<F: Sweep> Sweep for PackedBackend<F> {
+    async fn sweep(
+        &self,
+        ctx: &RequestContext,
+        reachable: &HashSet<INum>,
+        grace: Duration,
+        dry_run: bool,
+    ) -> DatenLordResult<GcReport> {
+        let mut report = GcReport::default();
+        if let Some(ref packs) = self.packs {
+            for (ino, bytes) in packs.files()? {
+                if reachable.contains(&ino) {
+                    continue;
+                }
+                let _guard = packs.lock(ino).await;
+                // Created since it was marked
+                if self.inner.getattr(ctx, ino).await.is_ok() {
+                    continue;
+                }
+                report.found(format!("packed inode {ino}"), bytes, dry_run);
+                if !dry_run {
+                    packs.remove(ino)?;
+                }
+            }
+        }
+        report.absorb(self.inner.sweep(ctx, reachable, grace, dry_run).await?);
+        Ok(report)
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for PackedBackend<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.lookup(ctx, parent, name).await
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.inner.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let (Some(size), Some(packs)) = (param.size, self.packs.as_ref()) else {
+            return self.inner.setattr(ctx, ino, param).await;
+        };
+        let _guard = packs.lock(ino).await;
+        let Some(mut data) = packs.get(ino)? else {
+            return self.inner.setattr(ctx, ino, param).await;
+        };
+        let result = self.inner.setattr(ctx, ino, param).await?;
+        if size > packs.config.max_file_size {
+            self.unpack(ctx, ino, &data).await?;
+        } else {
+            data.resize(size as usize, 0);
+            packs.put(ino, &data)?;
+        }
+        Ok(result)
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mknod(ctx, param).await
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mkdir(ctx, param).await
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let removed = match self.packs {
+            Some(_) => self.inner.lookup(ctx, parent, name).await.ok(),
+            None => None,
+        };
+        self.inner.unlink(ctx, parent, name).await?;
+        if let Some((_, ref attr, _)) = removed {
+            self.removed(attr);
+        }
+        Ok(())
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        self.inner.rmdir(ctx, parent, dir_name).await
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.symlink(ctx, parent, name, target_path).await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let exchange = param.flags & RenameFlags::RENAME_EXCHANGE.bits() != 0;
+        // The file a rename replaces loses a link
+        let replaced = if self.packs.is_some() && !exchange {
+            let source = self
+                .inner
+                .lookup(ctx, param.old_parent, &param.old_name)
+                .await;
+            let target = self
+                .inner
+                .lookup(ctx, param.new_parent, &param.new_name)
+                .await;
+            match (source, target) {
+                (Ok((_, source, _)), Ok((_, target, _))) if source.ino != target.ino => {
+                    Some(target)
+                }
+                _ => None,
+            }
+        } else {
+            None
+        };
+        self.inner.rename(ctx, param).await?;
+        if let Some(ref attr) = replaced {
+            self.removed(attr);
+        }
+        Ok(())
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.link(ctx, newparent, newname).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let fh = self.inner.open(ctx, ino, flags).await?;
+        if let Some(ref packs) = self.packs {
+            if flags as i32 & libc::O_TRUNC != 0 {
+                let _guard = packs.lock(ino).await;
+                if let Err(e) = packs.remove(ino) {
+                    let _ = self.inner.release(ctx, ino, fh, flags, 0, false).await;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(fh)
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        let Some(ref packs) = self.packs else {
+            return self.inner.read(ctx, ino, fh, offset, size, buf).await;
+        };
+        let Some(data) = packs.get(ino)? else {
+            return self.inner.read(ctx, ino, fh, offset, size, buf).await;
+        };
+        let start = (offset as usize).min(data.len());
+        let len = (data.len() - start).min(buf.len().min(size as usize));
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let Some(ref packs) = self.packs else {
+            return self.inner.write(ctx, ino, fh, offset, data, flags).await;
+        };
+        let start = u64::try_from(offset).map_err(|_| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid write offset={offset}")],
+        })?;
+        let _guard = packs.lock(ino).await;
+        let (_, attr) = self.inner.getattr(ctx, ino).await?;
+        let packed = packs.get(ino)?;
+        if packed.is_none() && (attr.kind != SFlag::S_IFREG || attr.size != 0) {
+            return self.inner.write(ctx, ino, fh, offset, data, flags).await;
+        }
+        let mut content = packed.unwrap_or_default();
+        let end = start + data.len() as u64;
+        if end.max(content.len() as u64) > packs.config.max_file_size {
+            if !content.is_empty() {
+                self.unpack(ctx, ino, &content).await?;
+            } else {
+                packs.remove(ino)?;
+            }
+            return self.inner.write(ctx, ino, fh, offset, data, flags).await;
+        }
+        if content.len() < end as usize {
+            content.resize(end as usize, 0);
+        }
+        content[start as usize..end as usize].copy_from_slice(data);
+        packs.put(ino, &content)?;
+        let param = SetAttrParam {
+            fh: Some(fh),
+            size: (end > attr.size).then_some(end),
+            m_time: Some(SystemTime::now()),
+            ..SetAttrParam::default()
+        };
+        self.inner.setattr(&owner_context(ctx), ino, param).await?;
+        Ok(())
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        if let Some(ref packs) = self.packs {
+            packs.sync(ino)?;
+        }
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        if let Some(ref packs) = self.packs {
+            if packs.contains(ino)? {
+                let (_, attr) = self.inner.getattr(ctx, ino).await?;
+                return Ok(seek_extents(&[(0, attr.size)], attr.size, offset, whence));
+            }
+        }
+        self.inner.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        let Some(ref packs) = self.packs else {
+            return self.inner.copy_file_range(ctx, param).await;
+        };
+        // Packed files are copied through the segments holding them
+        if packs.get(param.ino_in)?.is_some() {
+            return copy_range_through(self, ctx, param).await;
+        }
+        let guard = packs.lock(param.ino_out).await;
+        if packs.get(param.ino_out)?.is_some() {
+            drop(guard);
+            return copy_range_through(self, ctx, param).await;
+        }
+        self.inner.copy_file_range(ctx, param).await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.inner.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.inner.readdirplus(ctx, ino, fh, offset).await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .setxattr(ctx, ino, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.create(ctx, ino, parent, name, mode, flags).await
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
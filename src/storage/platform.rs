@@ -0,0 +1,194 @@
+//! What the local backend does differently on Linux and macOS: the error of
+//! a missing extended attribute, the flags of files and how files are copied
+//!
+//! Open files share their blocks through `FICLONE` on Linux, on btrfs and
+//! XFS, and are copied by the kernel otherwise; macOS clones by path only, so
+//! its open files are copied through user space.
+//!
+//! Watches need nothing of the platform, `NotifyFs` reports the changes made
+//! through the filesystem itself rather than asking inotify or FSEvents.
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use rustix::io::Errno;
+
+/// The file cannot be changed, renamed or removed
+pub const FILE_FLAG_IMMUTABLE: u32 = 1 << 0;
+/// The file can only be appended to
+pub const FILE_FLAG_APPEND: u32 = 1 << 1;
+/// The file is skipped by backups
+pub const FILE_FLAG_NODUMP: u32 = 1 << 2;
+/// The file is hidden from the Finder, macOS only
+pub const FILE_FLAG_HIDDEN: u32 = 1 << 3;
+
+/// The error of reading an extended attribute the file does not have
+#[cfg(target_os = "macos")]
+pub(crate) const NO_XATTR: Errno = Errno::NOATTR;
+/// The error of reading an extended attribute the file does not have
+#[cfg(not(target_os = "macos"))]
+pub(crate) const NO_XATTR: Errno = Errno::NODATA;
+
+/// The `FILE_FLAG_*` flags of `path`, never following a final symbolic link
+///
+/// Files on filesystems without flags, like tmpfs, and entries other than
+/// regular files and directories have none.
+pub fn file_flags(path: &Path) -> io::Result<u32> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_file() && !metadata.is_dir() {
+        return Ok(0);
+    }
+    native_flags(path, &metadata)
+}
+
+/// The flags of the user and system flag words of `st_flags`
+#[cfg(target_os = "macos")]
+fn native_flags(_path: &Path, metadata: &fs::Metadata) -> io::Result<u32> {
+    use std::os::macos::fs::MetadataExt;
+
+    use nix::libc;
+
+    let native = metadata.st_flags();
+    let mut flags = 0;
+    if native & (libc::UF_IMMUTABLE | libc::SF_IMMUTABLE) != 0 {
+        flags |= FILE_FLAG_IMMUTABLE;
+    }
+    if native & (libc::UF_APPEND | libc::SF_APPEND) != 0 {
+        flags |= FILE_FLAG_APPEND;
+    }
+    if native & libc::UF_NODUMP != 0 {
+        flags |= FILE_FLAG_NODUMP;
+    }
+    if native & libc::UF_HIDDEN != 0 {
+        flags |= FILE_FLAG_HIDDEN;
+    }
+    Ok(flags)
+}
+
+/// The flags of `FS_IOC_GETFLAGS`
+#[cfg(target_os = "linux")]
+fn native_flags(path: &Path, _metadata: &fs::Metadata) -> io::Result<u32> {
+    use rustix::fs::{IFlags, Mode, OFlags};
+
+    let file = rustix::fs::open(
+        path,
+        OFlags::RDONLY | OFlags::NONBLOCK | OFlags::NOFOLLOW | OFlags::CLOEXEC,
+        Mode::empty(),
+    )?;
+    let native = match rustix::fs::ioctl_getflags(&file) {
+        Ok(native) => native,
+        Err(Errno::NOTTY | Errno::NOTSUP | Errno::INVAL) => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut flags = 0;
+    if native.contains(IFlags::IMMUTABLE) {
+        flags |= FILE_FLAG_IMMUTABLE;
+    }
+    if native.contains(IFlags::APPEND) {
+        flags |= FILE_FLAG_APPEND;
+    }
+    if native.contains(IFlags::NODUMP) {
+        flags |= FILE_FLAG_NODUMP;
+    }
+    Ok(flags)
+}
+
+/// No flags elsewhere
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn native_flags(_path: &Path, _metadata: &fs::Metadata) -> io::Result<u32> {
+    Ok(0)
+}
+
+/// Copy the file `src` to `dst`, which must not exist, sharing their blocks
+/// when the filesystem can, never following a final symbolic link of `src`
+///
+/// On macOS the copy is a `clonefile(2)` on APFS, keeping the mode, flags
+/// and extended attributes, and an `fs::copy` elsewhere.
+pub fn clone_file(src: &Path, dst: &Path) -> io::Result<()> {
+    #[cfg(target_os = "macos")]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        use nix::libc;
+
+        let to_c = |path: &Path| {
+            CString::new(path.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+        };
+        let (c_src, c_dst) = (to_c(src)?, to_c(dst)?);
+        // SAFETY: both paths are NUL terminated and outlive the call
+        let ret = unsafe { libc::clonefile(c_src.as_ptr(), c_dst.as_ptr(), libc::CLONE_NOFOLLOW) };
+        if ret == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if !matches!(err.raw_os_error(), Some(libc::ENOTSUP | libc::EXDEV)) {
+            return Err(err);
+        }
+    }
+    fs::copy(src, dst).map(drop)
+}
+
+/// Make `dst` share the blocks of the whole of `src`, replacing its data,
+/// `false` when the filesystem cannot, e.g. across filesystems
+pub(crate) fn reflink(src: &File, dst: &File) -> io::Result<bool> {
+    #[cfg(target_os = "linux")]
+    match rustix::fs::ioctl_ficlone(dst, src) {
+        Ok(()) => Ok(true),
+        Err(Errno::NOTSUP | Errno::XDEV | Errno::INVAL | Errno::NOTTY | Errno::PERM) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (src, dst);
+        Ok(false)
+    }
+}
+
+/// The most bytes asked of one `copy_file_range(2)`, which copies at most
+/// that much at once anyway
+#[cfg(target_os = "linux")]
+const MAX_KERNEL_COPY: usize = 1 << 30;
+
+/// Copy at most `len` bytes of `src` from `offset_in` to `dst` at
+/// `offset_out` inside the kernel, returning the bytes copied, `None` when
+/// the kernel cannot and nothing was copied
+pub(crate) fn copy_range(
+    src: &File,
+    mut offset_in: u64,
+    dst: &File,
+    mut offset_out: u64,
+    len: u64,
+) -> io::Result<Option<u64>> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut copied = 0;
+        while copied < len {
+            let want = usize::try_from(len - copied)
+                .unwrap_or(usize::MAX)
+                .min(MAX_KERNEL_COPY);
+            match rustix::fs::copy_file_range(
+                src,
+                Some(&mut offset_in),
+                dst,
+                Some(&mut offset_out),
+                want,
+            ) {
+                Ok(0) => break,
+                Ok(n) => copied += n as u64,
+                Err(Errno::INTR) => {}
+                Err(Errno::XDEV | Errno::NOSYS | Errno::INVAL | Errno::NOTSUP) if copied == 0 => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(Some(copied))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (src, &mut offset_in, dst, &mut offset_out, len);
+        Ok(None)
+    }
+}
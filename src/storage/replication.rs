@@ -0,0 +1,1067 @@
+//! Asynchronous mirroring of a local backend to secondary backends, so the
+//! data written through the SDKs survives the loss of the primary disk
+//!
+//! Every change to the primary queues the paths it touched, and a background
+//! thread copies them from the primary root to the root of every secondary.
+//! The queue keeps each path once and holds at most `queue_capacity` of
+//! them; a secondary that misses changes, because the queue overflowed or
+//! copying to it failed, is compared with the whole primary and brought back
+//! in sync. Secondaries are mirrored by path: hard links become copies, and
+//! devices, fifos and sockets are left out.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsStr;
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+
+use async_trait::async_trait;
+use nix::fcntl::OFlag;
+use nix::sys::stat::{utimensat, Mode, UtimensatFlags};
+use nix::sys::time::TimeSpec;
+use serde_derive::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::migrate;
+
+use super::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam, RenameParam,
+    RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::ioctl;
+use super::layout::BlockLayout;
+use super::localfs::LocalFS;
+use super::platform;
+use super::superblock::SUPERBLOCK_NAME;
+use super::virtualfs::{INum, VirtualFs};
+use super::xattr;
+
+/// Default number of changed paths queued before secondaries are resynced
+const DEFAULT_QUEUE_CAPACITY: usize = 65536;
+/// Default time before a diverged secondary is resynced, five seconds
+const DEFAULT_RETRY_INTERVAL_MS: u64 = 5000;
+
+/// The secondary backends a `ReplicatedBackend` mirrors to, none by default
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicationConfig {
+    /// The secondaries, `file:///path` or plain paths, created when missing
+    pub secondaries: Vec<String>,
+    /// The changed paths waiting to be mirrored, past which the secondaries
+    /// are resynced in full
+    pub queue_capacity: usize,
+    /// The time before a secondary that missed changes is resynced, in
+    /// milliseconds, also between the attempts while it stays unreachable
+    pub retry_interval_ms: u64,
+    /// The time between two comparisons of every secondary with the whole
+    /// primary, catching changes made behind the SDKs, in seconds; 0
+    /// disables them
+    pub resync_interval_secs: u64,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            secondaries: Vec::new(),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            retry_interval_ms: DEFAULT_RETRY_INTERVAL_MS,
+            resync_interval_secs: 0,
+        }
+    }
+}
+
+/// How far a secondary is behind the primary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecondaryStatus {
+    /// The root of the secondary
+    pub root: PathBuf,
+    /// Whether every change mirrored so far reached it, false until the
+    /// first full resync ends
+    pub in_sync: bool,
+    /// The changed paths mirrored to it
+    pub replicated: u64,
+    /// The full resyncs it went through
+    pub resyncs: u64,
+    /// The failures to mirror to it
+    pub failures: u64,
+    /// The last failure, cleared by the next successful resync
+    pub last_error: Option<String>,
+    /// When the last full resync ended
+    pub last_resync: Option<SystemTime>,
+}
+
+/// The state of the replication of a backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicationStatus {
+    /// The changed paths not mirrored yet
+    pub pending: usize,
+    /// The changed paths dropped because the queue was full
+    pub overflows: u64,
+    /// Every secondary, in the order of the config
+    pub secondaries: Vec<SecondaryStatus>,
+}
+
+impl ReplicationStatus {
+    /// Whether every secondary holds every change made so far
+    #[must_use]
+    pub fn synced(&self) -> bool {
+        self.pending == 0 && self.secondaries.iter().all(|secondary| secondary.in_sync)
+    }
+}
+
+/// A secondary and when it is resynced next
+#[derive(Debug)]
+struct Secondary {
+    status: SecondaryStatus,
+    /// When to resync it, `None` while in sync and without periodic resyncs
+    resync_at: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The changed paths in order, and whether everything below them changed
+    queue: VecDeque<PathBuf>,
+    queued: HashMap<PathBuf, bool>,
+    /// Whether the worker is mirroring a path or resyncing
+    busy: bool,
+    overflows: u64,
+    stopping: bool,
+    secondaries: Vec<Secondary>,
+}
+
+/// What the worker does next
+enum Job {
+    /// Mirror a changed path, with everything below it if the flag is set
+    Path(PathBuf, bool),
+    /// Compare a secondary with the whole primary
+    Resync(usize),
+}
+
+/// The state shared with the worker
+#[derive(Debug)]
+struct Shared {
+    primary: PathBuf,
+    config: ReplicationConfig,
+    state: Mutex<State>,
+    /// Wakes the worker up when a path is queued or it must stop
+    wakeup: Condvar,
+}
+
+impl Shared {
+    /// Queue `path`, marking every secondary for a resync if the queue is
+    /// full
+    fn push(&self, path: PathBuf, recursive: bool) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(queued) = state.queued.get_mut(&path) {
+            *queued |= recursive;
+            return;
+        }
+        if state.queue.len() >= self.config.queue_capacity {
+            if state.overflows == 0 {
+                warn!("replication queue is full, resyncing the secondaries");
+            }
+            state.overflows += 1;
+            self.diverge(&mut state, "the replication queue overflowed");
+            return;
+        }
+        state.queue.push_back(path.clone());
+        state.queued.insert(path, recursive);
+        drop(state);
+        self.wakeup.notify_one();
+    }
+
+    /// Mark every secondary for a resync, e.g. when a change cannot be
+    /// attributed to a path
+    fn diverge(&self, state: &mut State, reason: &str) {
+        let now = Instant::now();
+        for secondary in &mut state.secondaries {
+            secondary.status.in_sync = false;
+            secondary.status.last_error = Some(reason.to_owned());
+            secondary.resync_at = Some(now);
+        }
+        self.wakeup.notify_one();
+    }
+
+    /// The next job, `None` once stopping with an empty queue
+    fn next_job(&self) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        state.busy = false;
+        loop {
+            if let Some(path) = state.queue.pop_front() {
+                let recursive = state.queued.remove(&path).unwrap_or(false);
+                state.busy = true;
+                return Some(Job::Path(path, recursive));
+            }
+            if state.stopping {
+                return None;
+            }
+            let now = Instant::now();
+            let due = state
+                .secondaries
+                .iter()
+                .position(|secondary| secondary.resync_at.is_some_and(|at| at <= now));
+            if let Some(index) = due {
+                state.busy = true;
+                return Some(Job::Resync(index));
+            }
+            let next = state
+                .secondaries
+                .iter()
+                .filter_map(|secondary| secondary.resync_at)
+                .min();
+            state = match next {
+                Some(next) => {
+                    self.wakeup
+                        .wait_timeout(state, next.saturating_duration_since(now))
+                        .unwrap()
+                        .0
+                }
+                None => self.wakeup.wait(state).unwrap(),
+            };
+        }
+    }
+
+    /// The secondaries in sync, those that missed changes only get them
+    /// from their next resync
+    fn targets(&self) -> Vec<(usize, PathBuf)> {
+        let state = self.state.lock().unwrap();
+        state
+            .secondaries
+            .iter()
+            .enumerate()
+            .filter(|(_, secondary)| secondary.status.in_sync)
+            .map(|(index, secondary)| (index, secondary.status.root.clone()))
+            .collect()
+    }
+
+    /// Record that mirroring to secondary `index` failed with `e`
+    fn failed(&self, index: usize, e: &io::Error) {
+        let retry = Duration::from_millis(self.config.retry_interval_ms);
+        let mut state = self.state.lock().unwrap();
+        let secondary = &mut state.secondaries[index];
+        warn!(
+            "failed to mirror to {:?}, resyncing it in {retry:?}: {e}",
+            secondary.status.root
+        );
+        secondary.status.in_sync = false;
+        secondary.status.failures += 1;
+        secondary.status.last_error = Some(e.to_string());
+        secondary.resync_at = Some(Instant::now() + retry);
+    }
+
+    fn run(&self) {
+        while let Some(job) = self.next_job() {
+            match job {
+                Job::Path(path, recursive) => {
+                    for (index, root) in self.targets() {
+                        match sync(&self.primary, &root, &path, recursive) {
+                            Ok(()) => {
+                                let mut state = self.state.lock().unwrap();
+                                state.secondaries[index].status.replicated += 1;
+                            }
+                            Err(e) => self.failed(index, &e),
+                        }
+                    }
+                }
+                Job::Resync(index) => {
+                    let root = {
+                        let mut state = self.state.lock().unwrap();
+                        state.secondaries[index].resync_at = None;
+                        state.secondaries[index].status.root.clone()
+                    };
+                    let started = Instant::now();
+                    match sync(&self.primary, &root, Path::new(""), true) {
+                        Ok(()) => {
+                            info!("resynced {root:?} in {:?}", started.elapsed());
+                            let mut state = self.state.lock().unwrap();
+                            let secondary = &mut state.secondaries[index];
+                            // Unless it missed a change while resyncing
+                            if secondary.resync_at.is_none() {
+                                secondary.status.in_sync = true;
+                                secondary.status.last_error = None;
+                                secondary.resync_at =
+                                    (self.config.resync_interval_secs > 0).then(|| {
+                                        Instant::now()
+                                            + Duration::from_secs(self.config.resync_interval_secs)
+                                    });
+                            }
+                            secondary.status.resyncs += 1;
+                            secondary.status.last_resync = Some(SystemTime::now());
+                        }
+                        Err(e) => self.failed(index, &e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `path` without its root, `.` and `..` components
+fn relative(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+/// Whether `name` is a file of the namespace itself rather than of its
+/// users, like the inode table, which is never mirrored
+fn internal(name: &OsStr) -> bool {
+    name.to_string_lossy()
+        .strip_prefix(SUPERBLOCK_NAME)
+        .is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// Treat a file vanishing from under the copy as done, the change that
+/// removed it is queued too
+fn vanished(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Create the directory `path`, through `mkdirat` since the C SDK exports
+/// a `mkdir` symbol
+fn create_dir(path: &Path, mode: u32) -> io::Result<()> {
+    nix::sys::stat::mkdirat(None, path, Mode::from_bits_truncate(mode))?;
+    Ok(())
+}
+
+/// Remove `path`, with everything below it if a directory
+fn remove(path: &Path) -> io::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    vanished(result)
+}
+
+/// Mirror the entry `path`, relative to the roots, from `primary` to
+/// `secondary`, with everything below it if `recursive`
+fn sync(primary: &Path, secondary: &Path, path: &Path, recursive: bool) -> io::Result<()> {
+    let src = primary.join(path);
+    let dst = secondary.join(path);
+    let metadata = match fs::symlink_metadata(&src) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return remove(&dst),
+        Err(e) => return Err(e),
+    };
+    // The parents may have been missed with an overflow
+    let mut dir = PathBuf::new();
+    for component in path.parent().into_iter().flat_map(Path::components) {
+        dir.push(component);
+        match fs::symlink_metadata(secondary.join(&dir)) {
+            Ok(existing) if existing.is_dir() => continue,
+            Ok(_) => remove(&secondary.join(&dir))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let mode = fs::metadata(primary.join(&dir))?.mode();
+        create_dir(&secondary.join(&dir), mode)?;
+    }
+    vanished(mirror(&src, &dst, &metadata, recursive))
+}
+
+/// Make `dst` a copy of `src`, whose metadata is `metadata`
+fn mirror(src: &Path, dst: &Path, metadata: &Metadata, recursive: bool) -> io::Result<()> {
+    let mut existing = match fs::symlink_metadata(dst) {
+        Ok(existing) => Some(existing),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let kind = metadata.file_type();
+    if existing
+        .as_ref()
+        .is_some_and(|existing| existing.file_type() != kind)
+    {
+        remove(dst)?;
+        existing = None;
+    }
+    if kind.is_dir() {
+        if existing.is_none() {
+            create_dir(dst, metadata.mode())?;
+        }
+        if recursive {
+            let mut names = HashSet::new();
+            for entry in fs::read_dir(src)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                if internal(&name) {
+                    continue;
+                }
+                let child =
+                    vanished(entry.metadata().and_then(|metadata| {
+                        mirror(&entry.path(), &dst.join(&name), &metadata, true)
+                    }));
+                child?;
+                names.insert(name);
+            }
+            for entry in fs::read_dir(dst)? {
+                let name = entry?.file_name();
+                if !names.contains(&name) && !internal(&name) {
+                    remove(&dst.join(name))?;
+                }
+            }
+        }
+    } else if kind.is_symlink() {
+        let target = fs::read_link(src)?;
+        if existing.is_some() {
+            if fs::read_link(dst)? == target {
+                return Ok(());
+            }
+            remove(dst)?;
+        }
+        return std::os::unix::fs::symlink(target, dst);
+    } else if kind.is_file() {
+        let unchanged = existing.is_some_and(|existing| {
+            existing.len() == metadata.len()
+                && existing.mtime() == metadata.mtime()
+                && existing.mtime_nsec() == metadata.mtime_nsec()
+        });
+        if !unchanged {
+            copy_file(src, dst, metadata)?;
+        }
+    } else {
+        return Ok(());
+    }
+    fs::set_permissions(dst, fs::Permissions::from_mode(metadata.mode()))?;
+    copy_xattrs(src, dst)
+}
+
+/// Copy the file `src` over `dst` through a temporary file, so `dst` is
+/// always whole, keeping its modification time
+fn copy_file(src: &Path, dst: &Path, metadata: &Metadata) -> io::Result<()> {
+    let name = dst.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = dst.with_file_name(format!(".{name}.datenlord-replica"));
+    // A copy left by an interrupted sync is replaced
+    match fs::remove_file(&tmp) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    platform::clone_file(src, &tmp)?;
+    let times = utimensat(
+        None,
+        &tmp,
+        &TimeSpec::new(metadata.atime(), metadata.atime_nsec()),
+        &TimeSpec::new(metadata.mtime(), metadata.mtime_nsec()),
+        UtimensatFlags::NoFollowSymlink,
+    );
+    if let Err(e) = times
+        .map_err(io::Error::from)
+        .and_then(|()| fs::rename(&tmp, dst))
+    {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Copy the `user.` extended attributes, like tags, of `src` to `dst`
+fn copy_xattrs(src: &Path, dst: &Path) -> io::Result<()> {
+    let unsupported = |e: &io::Error| e.raw_os_error() == Some(nix::libc::EOPNOTSUPP);
+    let names = match xattr::list(src) {
+        Ok(names) => names,
+        Err(e) if unsupported(&e) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let names: Vec<String> = names
+        .into_iter()
+        .filter(|name| name.starts_with("user."))
+        .collect();
+    for name in &names {
+        let Some(value) = xattr::get(src, name)? else {
+            continue;
+        };
+        if xattr::get(dst, name)?.as_deref() != Some(&value[..]) {
+            xattr::set(dst, name, &value, 0)?;
+        }
+    }
+    let stale = match xattr::list(dst) {
+        Ok(stale) => stale,
+        Err(e) if unsupported(&e) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for name in stale {
+        if name.starts_with("user.") && !names.contains(&name) {
+            xattr::remove(dst, &name)?;
+        }
+    }
+    Ok(())
+}
+
+/// The replication thread, draining the queue and stopping when dropped
+#[derive(Debug)]
+struct Replicator {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Replicator {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stopping = true;
+        self.shared.wakeup.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A `LocalFS` whose changes are mirrored to the secondaries of its
+/// `ReplicationConfig` in the background
+#[derive(Debug)]
+pub struct ReplicatedBackend {
+    /// The wrapped primary
+    primary: LocalFS,
+    /// The mirroring, `None` without secondaries
+    replicator: Option<Replicator>,
+    /// The handles written to since they were last flushed
+    written: Mutex<HashSet<u64>>,
+}
+
+impl ReplicatedBackend {
+    /// Mirror `primary`, rooted at `root`, to the secondaries of `config`,
+    /// each resynced in full first
+    pub fn new(primary: LocalFS, root: &Path, config: &ReplicationConfig) -> DatenLordResult<Self> {
+        if config.secondaries.is_empty() {
+            return Ok(Self {
+                primary,
+                replicator: None,
+                written: Mutex::new(HashSet::new()),
+            });
+        }
+        let now = Instant::now();
+        let mut secondaries = Vec::with_capacity(config.secondaries.len());
+        for uri in &config.secondaries {
+            let secondary = migrate::backend_root(uri)?;
+            if secondary == root {
+                return Err(DatenLordError::InvalidArgument {
+                    context: vec![format!("secondary {uri} is the primary")],
+                });
+            }
+            match create_dir(&secondary, 0o755) {
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                    return Err(DatenLordError::Io {
+                        context: vec![format!("failed to create secondary {secondary:?}: {e}")],
+                        source: None,
+                    });
+                }
+                _ => {}
+            }
+            secondaries.push(Secondary {
+                status: SecondaryStatus {
+                    root: secondary,
+                    in_sync: false,
+                    replicated: 0,
+                    resyncs: 0,
+                    failures: 0,
+                    last_error: None,
+                    last_resync: None,
+                },
+                resync_at: Some(now),
+            });
+        }
+        let shared = Arc::new(Shared {
+            primary: root.to_owned(),
+            config: config.clone(),
+            state: Mutex::new(State {
+                secondaries,
+                ..State::default()
+            }),
+            wakeup: Condvar::new(),
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name("datenlord-replication".to_owned())
+                .spawn(move || shared.run())
+                .map_err(|e| DatenLordError::Internal {
+                    context: vec![format!("failed to start the replication thread: {e}")],
+                })?
+        };
+        Ok(Self {
+            primary,
+            replicator: Some(Replicator {
+                shared,
+                thread: Some(thread),
+            }),
+            written: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// The wrapped primary
+    pub fn primary(&self) -> &LocalFS {
+        &self.primary
+    }
+
+    /// The state of the mirroring, `None` without secondaries
+    pub fn replication_status(&self) -> Option<ReplicationStatus> {
+        let replicator = self.replicator.as_ref()?;
+        let state = replicator.shared.state.lock().unwrap();
+        Some(ReplicationStatus {
+            pending: state.queue.len() + usize::from(state.busy),
+            overflows: state.overflows,
+            secondaries: state
+                .secondaries
+                .iter()
+                .map(|secondary| secondary.status.clone())
+                .collect(),
+        })
+    }
+
+    /// Queue `path`, relative to the root, when mirroring
+    fn push(&self, path: PathBuf, recursive: bool) {
+        if let Some(ref replicator) = self.replicator {
+            replicator.shared.push(path, recursive);
+        }
+    }
+
+    /// Queue the path of `ino`, resyncing if it has none
+    fn changed(&self, ino: INum) {
+        let Some(ref replicator) = self.replicator else {
+            return;
+        };
+        match self.primary.relative_path(ino) {
+            Ok(path) => replicator.shared.push(path, false),
+            Err(e) => {
+                warn!("changed inode {ino} has no path, resyncing the secondaries: {e}");
+                let mut state = replicator.shared.state.lock().unwrap();
+                replicator
+                    .shared
+                    .diverge(&mut state, "a change had no path");
+            }
+        }
+    }
+
+    /// Queue the path of the entry `name` in `parent`, with everything below
+    /// it if `recursive`
+    fn changed_entry(&self, parent: INum, name: &OsStr, recursive: bool) {
+        if self.replicator.is_none() {
+            return;
+        }
+        match self.primary.relative_path(parent) {
+            Ok(dir) => self.push(relative(&dir.join(name)), recursive),
+            // The parent is known by its child then
+            Err(_) => self.changed(parent),
+        }
+    }
+
+    /// Queue the file written through `fh` since its last flush
+    fn flushed(&self, ino: INum, fh: u64, release: bool) {
+        let mut written = self.written.lock().unwrap();
+        let was_written = if release {
+            written.remove(&fh)
+        } else {
+            written.contains(&fh)
+        };
+        drop(written);
+        if was_written {
+            self.changed(ino);
+        }
+    }
+}
+
+#[async_trait]
+impl VirtualFs for ReplicatedBackend {
+    fn init(&self) -> DatenLordResult<()> {
+        self.primary.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.primary.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.primary.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.primary.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.primary.lookup(ctx, parent, name).await
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.primary.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.primary.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.primary.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let result = self.primary.setattr(ctx, ino, param).await?;
+        self.changed(ctx.scope(ino));
+        Ok(result)
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.primary.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (parent, name) = (param.parent, param.name.clone());
+        let result = self.primary.mknod(ctx, param).await?;
+        self.changed_entry(ctx.scope(parent), &name, false);
+        Ok(result)
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (parent, name) = (param.parent, param.name.clone());
+        let result = self.primary.mkdir(ctx, param).await?;
+        self.changed_entry(ctx.scope(parent), &name, false);
+        Ok(result)
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.primary.unlink(ctx, parent, name).await?;
+        self.changed_entry(ctx.scope(parent), name, false);
+        Ok(())
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        let result = self.primary.rmdir(ctx, parent, dir_name).await?;
+        self.changed_entry(ctx.scope(parent), dir_name, false);
+        Ok(result)
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let result = self.primary.symlink(ctx, parent, name, target_path).await?;
+        self.changed_entry(ctx.scope(parent), name, false);
+        Ok(result)
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let old = (param.old_parent, param.old_name.clone());
+        let new = (param.new_parent, param.new_name.clone());
+        self.primary.rename(ctx, param).await?;
+        // A moved directory takes everything below it along
+        self.changed_entry(ctx.scope(old.0), &old.1, true);
+        self.changed_entry(ctx.scope(new.0), &new.1, true);
+        Ok(())
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.primary.link(ctx, newparent, newname).await?;
+        self.changed_entry(ctx.scope(newparent), newname, false);
+        Ok(())
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let fh = self.primary.open(ctx, ino, flags).await?;
+        if OFlag::from_bits_truncate(flags as i32).contains(OFlag::O_TRUNC) {
+            self.changed(ctx.scope(ino));
+        }
+        Ok(fh)
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        self.primary.read(ctx, ino, fh, offset, size, buf).await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let result = self.primary.write(ctx, ino, fh, offset, data, flags).await;
+        // Mirrored once flushed, rather than after every write
+        if self.replicator.is_some() {
+            self.written.lock().unwrap().insert(fh);
+        }
+        result
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.primary.flush(ctx, ino, fh, lock_owner).await?;
+        self.flushed(ino, fh, false);
+        Ok(())
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        let result = self
+            .primary
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await;
+        self.flushed(ino, fh, true);
+        result
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.primary.fsync(ctx, ino, fh, datasync).await?;
+        self.flushed(ino, fh, false);
+        Ok(())
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        self.primary.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        let result = self.primary.copy_file_range(ctx, param).await;
+        if self.replicator.is_some() {
+            self.written.lock().unwrap().insert(param.fh_out);
+        }
+        result
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.primary.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.primary.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.primary.readdirplus(ctx, ino, fh, offset).await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.primary.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.primary.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.primary.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.primary.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.primary
+            .setxattr(ctx, ino, name, value, flags, position)
+            .await?;
+        self.changed(ctx.scope(ino));
+        Ok(())
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.primary.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.primary.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.primary.removexattr(ctx, ino, name).await?;
+        self.changed(ctx.scope(ino));
+        Ok(())
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.primary.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.primary
+            .create(ctx, ino, parent, name, mode, flags)
+            .await?;
+        self.changed_entry(ctx.scope(parent), name, false);
+        Ok(())
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.primary.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.primary.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.primary.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        let mut output = self.primary.ioctl(ctx, ino, cmd, input).await?;
+        if cmd == ioctl::IOCTL_PLACEMENT {
+            if let Some(status) = self.replication_status() {
+                let relative = self.primary.relative_path(ino)?;
+                for secondary in status.secondaries {
+                    let path = secondary.root.join(&relative).into_os_string();
+                    output.extend_from_slice(path.as_bytes());
+                    output.push(b'\n');
+                }
+            }
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,468 @@
+//! Middleware retrying transient `VirtualFs` failures with exponential backoff
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::common::DatenLordResult;
+
+use super::timeout;
+use super::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam, RenameParam,
+    RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// How transient failures are retried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// The number of retries after the first attempt, 0 disables retrying
+    pub max_retries: u32,
+    /// The delay before the first retry in milliseconds
+    pub initial_backoff_ms: u64,
+    /// The upper bound of the delay between retries in milliseconds
+    pub max_backoff_ms: u64,
+    /// The factor the delay grows by after every retry
+    pub multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff_ms: 10,
+            max_backoff_ms: 1000,
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry`, counting from 0
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.initial_backoff_ms as f64 * self.multiplier.max(1.0).powf(f64::from(retry));
+        Duration::from_millis(backoff.min(self.max_backoff_ms as f64) as u64)
+    }
+}
+
+/// Whether a retry after `backoff` would start before the deadline of the
+/// current operation, see `timeout::with_deadline`
+fn within_deadline(backoff: Duration) -> bool {
+    timeout::deadline().is_none_or(|deadline| Instant::now() + backoff < deadline)
+}
+
+/// Run the inner call `$call` of operation `$op`, retrying it while it fails
+/// with a transient error, retries are left and the deadline of the
+/// operation leaves room for the backoff
+///
+/// A macro rather than a closure so calls mutably borrowing a buffer can be
+/// re-issued.
+macro_rules! retry {
+    ($self:ident, $op:literal, $call:expr) => {{
+        let policy = $self.policy();
+        let mut retry = 0;
+        loop {
+            match $call.await {
+                Err(e)
+                    if e.is_transient()
+                        && retry < policy.max_retries
+                        && within_deadline(policy.backoff(retry)) =>
+                {
+                    let backoff = policy.backoff(retry);
+                    debug!("{} failed with transient error {e:?}, retry in {backoff:?}", $op);
+                    tokio::time::sleep(backoff).await;
+                    retry += 1;
+                }
+                result => break result,
+            }
+        }
+    }};
+}
+
+/// A `VirtualFs` retrying idempotent calls that fail with a transient error
+///
+/// Only operations which can safely run twice are retried, since a failed
+/// attempt may still have taken effect. Creating, removing and renaming
+/// entries and releasing handles are passed through once.
+#[derive(Debug)]
+pub struct RetryFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// How transient failures are retried
+    policy: RwLock<RetryPolicy>,
+}
+
+impl<F: VirtualFs> RetryFs<F> {
+    /// Wrap `inner`, retrying its transient failures according to `policy`
+    pub fn new(inner: F, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy: RwLock::new(policy),
+        }
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// How transient failures are retried now
+    pub fn policy(&self) -> RetryPolicy {
+        self.policy.read().unwrap().clone()
+    }
+
+    /// Retry the calls starting from now according to `policy`
+    pub fn set_policy(&self, policy: RetryPolicy) {
+        *self.policy.write().unwrap() = policy;
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for RetryFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        retry!(self, "lookup", self.inner.lookup(ctx, parent, name))
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        retry!(self, "exists", self.inner.exists(ctx, parent, name))
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        retry!(self, "getattr", self.inner.getattr(ctx, ino))
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.setattr(ctx, ino, param).await
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        retry!(self, "readlink", self.inner.readlink(ctx, ino))
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mknod(ctx, param).await
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mkdir(ctx, param).await
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.unlink(ctx, parent, name).await
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        self.inner.rmdir(ctx, parent, dir_name).await
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner
+            .symlink(ctx, parent, name, target_path)
+            .await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        self.inner.rename(ctx, param).await
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.link(ctx, newparent, newname).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        retry!(self, "open", self.inner.open(ctx, ino, flags))
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        retry!(self, "read", self.inner.read(ctx, ino, fh, offset, size, buf))
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        retry!(self, "write", self.inner.write(ctx, ino, fh, offset, data, flags))
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        retry!(self, "flush", self.inner.flush(ctx, ino, fh, lock_owner))
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        retry!(self, "fsync", self.inner.fsync(ctx, ino, fh, datasync))
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        retry!(self, "lseek", self.inner.lseek(ctx, ino, fh, offset, whence))
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        retry!(self, "copy_file_range", self.inner.copy_file_range(ctx, param))
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        retry!(self, "opendir", self.inner.opendir(ctx, ino, flags))
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        retry!(self, "readdir", self.inner.readdir(ctx, ino, fh, offset))
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        retry!(self, "readdirplus", self.inner.readdirplus(ctx, ino, fh, offset))
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        retry!(self, "fsyncdir", self.inner.fsyncdir(ctx, ino, fh, datasync))
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        retry!(self, "sync_all", self.inner.sync_all(ctx))
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        retry!(self, "statfs", self.inner.statfs(ctx, ino))
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .setxattr(ctx, ino, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        retry!(self, "getxattr", self.inner.getxattr(ctx, ino, name))
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        retry!(self, "listxattr", self.inner.listxattr(ctx, ino))
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        retry!(self, "access", self.inner.access(ctx, ino, mask))
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .create(ctx, ino, parent, name, mode, flags)
+            .await
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        retry!(self, "bmap", self.inner.bmap(ctx, ino, blocksize, idx))
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        // Not retried, custom commands need not be idempotent
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
@@ -0,0 +1,99 @@
+//! Resolution of the names handed to `LocalFS` into local paths confined to
+//! its root
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+/// The most symbolic links followed resolving one name, like `MAXSYMLINKS`
+const MAX_SYMLINKS: usize = 40;
+
+/// A step of the resolution
+enum Step {
+    /// Go up to the parent directory
+    Up,
+    /// Go down into the entry of that name
+    Down(OsString),
+}
+
+/// The steps of `path`, where a leading `/` is relative like the rest
+fn steps(path: &Path) -> impl Iterator<Item = Step> + '_ {
+    path.components().filter_map(|component| match component {
+        Component::ParentDir => Some(Step::Up),
+        Component::Normal(name) => Some(Step::Down(name.to_owned())),
+        Component::CurDir | Component::RootDir | Component::Prefix(_) => None,
+    })
+}
+
+/// The error for `name` leaving `root`
+fn escapes(root: &Path, name: &Path) -> DatenLordError {
+    DatenLordError::PermissionDenied {
+        context: vec![format!("{name:?} resolves outside of the root {root:?}")],
+        source: None,
+    }
+}
+
+/// Resolve `name`, relative to the directory `dir` under `root`, into a local
+/// path under `root` no symbolic link leads out of
+///
+/// `.` and `..` are resolved and the symbolic links on the way followed, the
+/// one the final component names only if `follow`. Names going above `root`,
+/// and links to absolute paths or going above `root`, fail with
+/// `DatenLordError::PermissionDenied`. Entries that do not exist are left to
+/// the operation using the path to report.
+pub(crate) fn resolve(
+    root: &Path,
+    dir: &Path,
+    name: &OsStr,
+    follow: bool,
+) -> DatenLordResult<PathBuf> {
+    let local = |resolved: &[OsString]| {
+        resolved
+            .iter()
+            .fold(root.to_owned(), |path, entry| path.join(entry))
+    };
+    let relative = dir.strip_prefix(root).map_err(|_| escapes(root, dir))?;
+    let mut resolved: Vec<OsString> = steps(relative)
+        .map(|step| match step {
+            Step::Down(name) => Ok(name),
+            Step::Up => Err(escapes(root, dir)),
+        })
+        .collect::<DatenLordResult<_>>()?;
+    let mut pending: VecDeque<Step> = steps(Path::new(name)).collect();
+    let mut links = 0;
+    while let Some(step) = pending.pop_front() {
+        let entry = match step {
+            Step::Up => {
+                resolved.pop().ok_or_else(|| escapes(root, Path::new(name)))?;
+                continue;
+            }
+            Step::Down(entry) => entry,
+        };
+        let path = local(&resolved).join(&entry);
+        let is_link = (follow || !pending.is_empty())
+            && fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_symlink());
+        if !is_link {
+            resolved.push(entry);
+            continue;
+        }
+        links += 1;
+        if links > MAX_SYMLINKS {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("too many symbolic links resolving {name:?}")],
+            });
+        }
+        let target = fs::read_link(&path).map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to read link {path:?}: {e}")],
+            source: None,
+        })?;
+        if target.is_absolute() {
+            return Err(escapes(root, &path));
+        }
+        for step in steps(&target).collect::<Vec<_>>().into_iter().rev() {
+            pending.push_front(step);
+        }
+    }
+    Ok(local(&resolved))
+}
@@ -0,0 +1,502 @@
+//! A searchable index of the namespace, kept current by the change events
+use std::collections::HashMap;
+use std::path::Path;
+
+use async_trait::async_trait;
+use serde_derive::Serialize;
+use tantivy::collector::{DocSetCollector, TopDocs};
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, TermQuery};
+use tantivy::schema::{
+    DateOptions, DateTimePrecision, Field, IndexRecordOption, Schema, TantivyDocument, Value, FAST,
+    INDEXED, STORED, STRING, TEXT,
+};
+use tantivy::{DateTime, Index, IndexReader, IndexWriter, ReloadPolicy, Searcher, Term};
+use tracing::debug;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{FileKind, RequestContext, ROOT_ID};
+use super::notify::{Event, EventKind, EventSink, EVENT_SCHEMA_VERSION};
+use super::tags::{self, TagFilter, Tags};
+use super::virtualfs::{INum, VirtualFs};
+use super::walk::Walk;
+
+/// The memory the index writer buffers documents in before flushing them
+const WRITER_MEMORY: usize = 64 << 20;
+
+/// The fields of the document indexed for every file
+#[derive(Debug, Clone, Copy)]
+struct Fields {
+    /// The inode number, the key of the document
+    ino: Field,
+    /// The path relative to the root, matched exactly
+    path: Field,
+    /// The paths of the directories holding the file
+    ancestors: Field,
+    /// The last path component, tokenized so parts of names match
+    name: Field,
+    /// The name of the `FileKind`
+    kind: Field,
+    /// The size in bytes
+    size: Field,
+    /// The modification time
+    mtime: Field,
+    /// The tags as `key=value`
+    tags: Field,
+    /// The keys of the tags
+    tag_keys: Field,
+}
+
+impl Fields {
+    /// The schema of the index and its fields
+    fn schema() -> (Schema, Self) {
+        let mut builder = Schema::builder();
+        let fields = Self {
+            ino: builder.add_u64_field("ino", INDEXED | STORED | FAST),
+            path: builder.add_text_field("path", STRING | STORED),
+            ancestors: builder.add_text_field("ancestors", STRING),
+            name: builder.add_text_field("name", TEXT),
+            kind: builder.add_text_field("kind", STRING | STORED),
+            size: builder.add_u64_field("size", INDEXED | STORED | FAST),
+            mtime: builder.add_date_field(
+                "mtime",
+                DateOptions::from(INDEXED | STORED | FAST).set_precision(DateTimePrecision::Nanoseconds),
+            ),
+            tags: builder.add_text_field("tags", STRING | STORED),
+            tag_keys: builder.add_text_field("tag_keys", STRING),
+        };
+        (builder.build(), fields)
+    }
+}
+
+/// A file found by `SearchIndex::search`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SearchHit {
+    /// The path relative to the root, names that are not UTF-8 converted lossily
+    pub path: String,
+    /// The inode number
+    pub ino: INum,
+    /// The type of the file
+    pub kind: Option<FileKind>,
+    /// The size in bytes
+    pub size: u64,
+    /// The modification time in nanoseconds since the epoch
+    pub mtime_ns: i64,
+    /// The tags of the file
+    pub tags: Tags,
+}
+
+/// The paths of the directories holding `path`, outermost first
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(end, _)| &path[..end])
+}
+
+/// A failed index operation
+fn index_error(context: String) -> impl FnOnce(tantivy::TantivyError) -> DatenLordError {
+    move |e| DatenLordError::Io {
+        context: vec![format!("{context}: {e}")],
+        source: None,
+    }
+}
+
+/// An index of the paths, names, types, sizes, modification times and tags
+/// of the files of a namespace
+///
+/// Queries use the tantivy syntax: bare words match parts of names, and
+/// `path:`, `kind:`, `size:` and `mtime:` restrict the other fields, e.g.
+/// `report kind:file size:>1000000 mtime:>2024-01-01T00:00:00Z`.
+pub struct SearchIndex {
+    /// The index
+    index: Index,
+    /// Searches the last commit
+    reader: IndexReader,
+    /// The fields of the documents
+    fields: Fields,
+}
+
+impl std::fmt::Debug for SearchIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SearchIndex").finish_non_exhaustive()
+    }
+}
+
+impl SearchIndex {
+    /// Open the index in the directory `dir`, creating both if missing
+    ///
+    /// The parent of `dir` must exist. Searches see the changes committed by
+    /// an `IndexSink` shortly after the commit.
+    pub fn open(dir: &Path) -> DatenLordResult<Self> {
+        Self::open_with(dir, ReloadPolicy::OnCommitWithDelay)
+    }
+
+    /// Open the index in `dir`, reloading searches as `policy` says
+    fn open_with(dir: &Path, policy: ReloadPolicy) -> DatenLordResult<Self> {
+        match super::localfs::LocalFS::create_dir(dir, 0o755) {
+            Ok(()) | Err(DatenLordError::AlreadyExists { .. }) => {}
+            Err(e) => return Err(e),
+        }
+        let directory = MmapDirectory::open(dir).map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to open search index {dir:?}: {e}")],
+            source: None,
+        })?;
+        let (schema, fields) = Fields::schema();
+        let index = Index::open_or_create(directory, schema)
+            .map_err(index_error(format!("failed to open search index {dir:?}")))?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(policy)
+            .try_into()
+            .map_err(index_error(format!("failed to read search index {dir:?}")))?;
+        Ok(Self { index, reader, fields })
+    }
+
+    /// The files matching `query`, at most `limit` of them and best first
+    pub fn search(&self, query: &str, limit: usize) -> DatenLordResult<Vec<SearchHit>> {
+        let mut parser = QueryParser::for_index(&self.index, vec![self.fields.name]);
+        parser.set_conjunction_by_default();
+        let query = parser.parse_query(query).map_err(|e| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid search query {query:?}: {e}")],
+        })?;
+        let searcher = self.reader.searcher();
+        let top = searcher
+            .search(&query, &TopDocs::with_limit(limit.max(1)))
+            .map_err(index_error("search failed".to_owned()))?;
+        let mut hits = Vec::with_capacity(top.len());
+        for (_, address) in top.into_iter().take(limit) {
+            let doc = searcher
+                .doc(address)
+                .map_err(index_error("failed to load a search hit".to_owned()))?;
+            hits.extend(self.decode(&doc));
+        }
+        Ok(hits)
+    }
+
+    /// The files matching every term of `filter`, at most `limit` of them
+    pub fn find_by_tags(
+        &self,
+        filter: &TagFilter,
+        limit: usize,
+    ) -> DatenLordResult<Vec<SearchHit>> {
+        let terms = filter
+            .terms()
+            .iter()
+            .map(|(key, value)| {
+                let term = match *value {
+                    Some(ref value) => {
+                        Term::from_field_text(self.fields.tags, &format!("{key}={value}"))
+                    }
+                    None => Term::from_field_text(self.fields.tag_keys, key),
+                };
+                let query: Box<dyn Query> =
+                    Box::new(TermQuery::new(term, IndexRecordOption::Basic));
+                (Occur::Must, query)
+            })
+            .collect();
+        let searcher = self.reader.searcher();
+        let top = searcher
+            .search(&BooleanQuery::new(terms), &TopDocs::with_limit(limit.max(1)))
+            .map_err(index_error("tag search failed".to_owned()))?;
+        let mut hits = Vec::with_capacity(top.len());
+        for (_, address) in top.into_iter().take(limit) {
+            let doc = searcher
+                .doc(address)
+                .map_err(index_error("failed to load a search hit".to_owned()))?;
+            hits.extend(self.decode(&doc));
+        }
+        Ok(hits)
+    }
+
+    /// The committed files with `value` in the string field `field`
+    fn find(&self, searcher: &Searcher, field: Field, value: &str) -> DatenLordResult<Vec<SearchHit>> {
+        let query = TermQuery::new(Term::from_field_text(field, value), IndexRecordOption::Basic);
+        self.collect(searcher, &query)
+    }
+
+    /// The committed file with inode `ino`
+    fn get(&self, ino: INum) -> DatenLordResult<Option<SearchHit>> {
+        let query = TermQuery::new(Term::from_field_u64(self.fields.ino, ino), IndexRecordOption::Basic);
+        Ok(self.collect(&self.reader.searcher(), &query)?.pop())
+    }
+
+    /// Every committed file matching `query`
+    fn collect(&self, searcher: &Searcher, query: &dyn Query) -> DatenLordResult<Vec<SearchHit>> {
+        let addresses = searcher
+            .search(query, &DocSetCollector)
+            .map_err(index_error("index lookup failed".to_owned()))?;
+        let mut hits = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            let doc = searcher
+                .doc(address)
+                .map_err(index_error("failed to load an indexed file".to_owned()))?;
+            hits.extend(self.decode(&doc));
+        }
+        Ok(hits)
+    }
+
+    /// The file a document describes
+    fn decode(&self, doc: &TantivyDocument) -> Option<SearchHit> {
+        let kind = doc.get_first(self.fields.kind).and_then(|value| value.as_str());
+        let tags = doc
+            .get_all(self.fields.tags)
+            .filter_map(|value| value.as_str()?.split_once('='))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect();
+        Some(SearchHit {
+            path: doc.get_first(self.fields.path)?.as_str()?.to_owned(),
+            ino: doc.get_first(self.fields.ino)?.as_u64()?,
+            kind: kind.and_then(FileKind::from_name),
+            size: doc.get_first(self.fields.size)?.as_u64()?,
+            mtime_ns: doc.get_first(self.fields.mtime)?.as_datetime()?.into_timestamp_nanos(),
+            tags,
+        })
+    }
+
+    /// The document describing `hit`
+    fn encode(&self, hit: &SearchHit) -> TantivyDocument {
+        let fields = self.fields;
+        let mut doc = TantivyDocument::new();
+        doc.add_u64(fields.ino, hit.ino);
+        doc.add_text(fields.path, &hit.path);
+        for ancestor in ancestors(&hit.path) {
+            doc.add_text(fields.ancestors, ancestor);
+        }
+        doc.add_text(fields.name, hit.path.rsplit('/').next().unwrap_or_default());
+        doc.add_text(fields.kind, hit.kind.map_or("unknown", FileKind::name));
+        doc.add_u64(fields.size, hit.size);
+        doc.add_date(fields.mtime, DateTime::from_timestamp_nanos(hit.mtime_ns));
+        for (key, value) in &hit.tags {
+            doc.add_text(fields.tags, format!("{key}={value}"));
+            doc.add_text(fields.tag_keys, key);
+        }
+        doc
+    }
+}
+
+/// An `EventSink` keeping a `SearchIndex` current
+///
+/// Changes are committed whenever the event queue drains. Only one sink can
+/// write to an index at a time. Files existing before the sink was added
+/// are indexed by `rebuild`.
+pub struct IndexSink {
+    /// The index, searched for the files events refer to
+    index: SearchIndex,
+    /// Adds and removes documents
+    writer: IndexWriter,
+    /// The files changed since the last commit, `None` once removed
+    pending: HashMap<INum, Option<SearchHit>>,
+}
+
+impl std::fmt::Debug for IndexSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IndexSink")
+            .field("pending", &self.pending.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl IndexSink {
+    /// Open the index in the directory `dir` for writing, see `SearchIndex::open`
+    pub fn open(dir: &Path) -> DatenLordResult<Self> {
+        let index = SearchIndex::open_with(dir, ReloadPolicy::Manual)?;
+        let writer = index
+            .index
+            .writer(WRITER_MEMORY)
+            .map_err(index_error(format!("failed to lock search index {dir:?}")))?;
+        Ok(Self {
+            index,
+            writer,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Replace the content of the index with the files `walk` yields, their
+    /// tags read from `fs` as `ctx`, returning their number
+    pub async fn rebuild<F: VirtualFs + ?Sized>(
+        &mut self,
+        fs: &F,
+        ctx: &RequestContext,
+        mut walk: Walk,
+    ) -> DatenLordResult<u64> {
+        self.writer
+            .delete_all_documents()
+            .map_err(index_error("failed to clear the search index".to_owned()))?;
+        self.pending.clear();
+        let mut count = 0;
+        while let Some(entry) = walk.next().await {
+            let entry = entry?;
+            let (sec, nsec) = super::fs_util::to_timespec(entry.attr.mtime);
+            let hit = SearchHit {
+                // Lossy like the names of the events updating the index
+                path: entry.path.to_string_lossy().into_owned(),
+                ino: entry.attr.ino,
+                kind: FileKind::from_sflag(entry.attr.kind),
+                size: entry.attr.size,
+                mtime_ns: sec.saturating_mul(1_000_000_000).saturating_add(i64::from(nsec)),
+                tags: tags::get_tags(fs, ctx, entry.attr.ino).await?,
+            };
+            // Nothing is pending, so the documents can skip the pending files
+            self.writer
+                .add_document(self.index.encode(&hit))
+                .map_err(index_error(format!("failed to index {:?}", hit.path)))?;
+            count += 1;
+        }
+        self.commit()?;
+        Ok(count)
+    }
+
+    /// Update the index with `event`
+    pub fn apply(&mut self, event: &Event) -> DatenLordResult<()> {
+        let mtime_ns = i64::try_from(event.timestamp_ns).unwrap_or(i64::MAX);
+        match event.kind {
+            EventKind::Create | EventKind::Mkdir | EventKind::Symlink => {
+                if let Some(path) = self.path_in(event.parent, event.name.as_deref())? {
+                    self.put(SearchHit {
+                        path,
+                        ino: event.ino,
+                        kind: event.file_kind,
+                        size: event.size,
+                        mtime_ns,
+                        tags: Tags::new(),
+                    })?;
+                }
+            }
+            EventKind::Delete => self.remove(event.ino),
+            EventKind::Rename => self.rename(event)?,
+            EventKind::Attrib | EventKind::CloseWrite => {
+                if let Some(mut hit) = self.get(event.ino)? {
+                    // Attribute changes only modify the file when resizing it
+                    if event.kind == EventKind::CloseWrite || hit.size != event.size {
+                        hit.mtime_ns = mtime_ns;
+                    }
+                    hit.size = event.size;
+                    if let Some(ref tags) = event.tags {
+                        hit.tags.clone_from(tags);
+                    }
+                    self.put(hit)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the file of a rename event, together with the files below it
+    ///
+    /// Whatever the file replaced is removed, so the other side of a
+    /// `RENAME_EXCHANGE` drops out of the index until it changes again.
+    fn rename(&mut self, event: &Event) -> DatenLordResult<()> {
+        let Some(new_path) = self.path_in(event.new_parent, event.new_name.as_deref())? else {
+            self.remove(event.ino);
+            return Ok(());
+        };
+        for replaced in self.find(self.index.fields.path, &new_path)? {
+            if replaced.ino != event.ino {
+                self.remove(replaced.ino);
+            }
+        }
+        let Some(mut hit) = self.get(event.ino)? else {
+            return Ok(());
+        };
+        let old_path = std::mem::replace(&mut hit.path, new_path.clone());
+        self.put(hit)?;
+        for mut below in self.find(self.index.fields.ancestors, &old_path)? {
+            below.path = format!("{new_path}{}", &below.path[old_path.len()..]);
+            self.put(below)?;
+        }
+        Ok(())
+    }
+
+    /// The path of the entry `name` in `parent`, `None` if the parent is not indexed
+    fn path_in(&self, parent: Option<INum>, name: Option<&str>) -> DatenLordResult<Option<String>> {
+        let (Some(parent), Some(name)) = (parent, name) else {
+            return Ok(None);
+        };
+        let name = name.trim_matches('/');
+        if parent == ROOT_ID {
+            return Ok(Some(name.to_owned()));
+        }
+        Ok(self.get(parent)?.map(|dir| format!("{}/{name}", dir.path)))
+    }
+
+    /// The current state of the file `ino`
+    fn get(&self, ino: INum) -> DatenLordResult<Option<SearchHit>> {
+        match self.pending.get(&ino) {
+            Some(hit) => Ok(hit.clone()),
+            None => self.index.get(ino),
+        }
+    }
+
+    /// The current files with `value` in `field`, the path or the ancestors
+    fn find(&self, field: Field, value: &str) -> DatenLordResult<Vec<SearchHit>> {
+        let searcher = self.index.reader.searcher();
+        let mut hits: Vec<_> = self
+            .index
+            .find(&searcher, field, value)?
+            .into_iter()
+            .filter(|hit| !self.pending.contains_key(&hit.ino))
+            .collect();
+        let matches = |hit: &SearchHit| {
+            if field == self.index.fields.path {
+                hit.path == value
+            } else {
+                ancestors(&hit.path).any(|ancestor| ancestor == value)
+            }
+        };
+        hits.extend(self.pending.values().flatten().filter(|hit| matches(hit)).cloned());
+        Ok(hits)
+    }
+
+    /// Index `hit`, replacing the former document of the file
+    fn put(&mut self, hit: SearchHit) -> DatenLordResult<()> {
+        self.writer
+            .delete_term(Term::from_field_u64(self.index.fields.ino, hit.ino));
+        self.writer
+            .add_document(self.index.encode(&hit))
+            .map_err(index_error(format!("failed to index {:?}", hit.path)))?;
+        self.pending.insert(hit.ino, Some(hit));
+        Ok(())
+    }
+
+    /// Drop the file `ino` from the index
+    fn remove(&mut self, ino: INum) {
+        self.writer
+            .delete_term(Term::from_field_u64(self.index.fields.ino, ino));
+        self.pending.insert(ino, None);
+    }
+
+    /// Make the changes visible to searches
+    fn commit(&mut self) -> DatenLordResult<()> {
+        self.writer
+            .commit()
+            .map_err(index_error("failed to commit the search index".to_owned()))?;
+        self.index
+            .reader
+            .reload()
+            .map_err(index_error("failed to reload the search index".to_owned()))?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSink for IndexSink {
+    fn describe(&self) -> String {
+        "search index".to_owned()
+    }
+
+    async fn send(&mut self, payload: &str) -> std::io::Result<()> {
+        let event: Event = serde_json::from_str(payload)?;
+        if event.version != EVENT_SCHEMA_VERSION {
+            debug!("skipping event of schema version {}", event.version);
+            return Ok(());
+        }
+        Ok(self.apply(&event)?)
+    }
+
+    async fn flush(&mut self) -> std::io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        Ok(self.commit()?)
+    }
+}
@@ -0,0 +1,1287 @@
+//! A filesystem whose directory tree and attributes live in a `MetaStore`
+//! and whose data lives in an object store, so instances on several hosts
+//! given the same stores see one consistent namespace
+//!
+//! The data of inode `ino` is kept as the objects `<ino>.<index>` of
+//! `block_size` bytes, missing blocks reading as zeros, and the target of a
+//! symbolic link as its block 0. Every change to a directory holds the lease
+//! lock of the directory, and every write or truncate that of the file, so
+//! the instances never interleave them; moving a directory to another
+//! parent also holds a namespace-wide lock, so two moves cannot form a loop.
+//!
+//! POSIX file locks are kept by the `LockService` of `SharedConfig::locks`.
+//! Since `getlk` cannot return the conflicting lock, it fails with
+//! `DatenLordError::Unavailable` describing it instead.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::future::Future;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use nix::errno::Errno;
+use nix::fcntl::{OFlag, RenameFlags};
+use nix::sys::stat::SFlag;
+use opendal::{ErrorKind, Operator, Scheme};
+use serde::{Deserialize, Serialize};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::dir_handle::DirHandles;
+use super::filelock::{self, LockConfig, LockSession, LockStats, LockType, RangeLock};
+use super::gc::{GcReport, Sweep};
+use super::ioctl;
+use super::layout::BlockLayout;
+use super::fs_util::{
+    self, parse_oflag, AtimePolicy, CreateParam, DirEntry, FileAttr, FileKind, FileLockParam,
+    NameConfig,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam, ROOT_ID,
+};
+use super::meta::{self, MetaConfig, MetaLock, MetaStore};
+use super::virtualfs::{INum, VirtualFs};
+
+/// The TTL of attributes returned by `SharedFs`, short since other hosts
+/// change them
+const ATTR_TTL: Duration = Duration::from_millis(100);
+
+/// Permission bits checked with `FileAttr::check_perm`
+const ACCESS_READ: u8 = 0o4;
+const ACCESS_WRITE: u8 = 0o2;
+const ACCESS_EXEC: u8 = 0o1;
+
+/// The entry of every directory pointing at its parent, never listed
+const PARENT_ENTRY: &str = "..";
+
+/// Where a `SharedFs` keeps its metadata and its data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SharedConfig {
+    /// The store of the directory tree and the attributes
+    pub meta: MetaConfig,
+    /// The opendal service keeping the data, like `fs` or `s3`
+    pub data_scheme: String,
+    /// The options of the data service, like its `root` or `bucket`
+    pub data_options: HashMap<String, String>,
+    /// The size of the objects file data is split into
+    pub block_size: u64,
+    /// How long a lock outlives an instance dying while holding it, in
+    /// milliseconds
+    pub lock_lease_ms: u64,
+    /// The service keeping the POSIX file locks
+    pub locks: LockConfig,
+    /// The names of entries accepted
+    pub names: NameConfig,
+    /// When reads update the access time of files
+    pub atime: AtimePolicy,
+}
+
+impl Default for SharedConfig {
+    fn default() -> Self {
+        Self {
+            meta: MetaConfig::default(),
+            data_scheme: "fs".to_owned(),
+            data_options: HashMap::new(),
+            block_size: 4 << 20,
+            lock_lease_ms: 10_000,
+            locks: LockConfig::default(),
+            names: NameConfig::default(),
+            atime: AtimePolicy::default(),
+        }
+    }
+}
+
+/// A file opened through `VirtualFs::open`
+#[derive(Debug, Clone, Copy)]
+struct OpenFile {
+    ino: INum,
+    /// Whether every write goes to the end of the file
+    append: bool,
+}
+
+/// The 512-byte sectors `len` bytes of data take, as counted by
+/// `FileAttr::blocks`
+fn sectors(len: u64) -> u64 {
+    len.div_ceil(512)
+}
+
+/// Map an error of the data service, the temporary ones to
+/// `DatenLordError::Unavailable`
+fn data_error(context: String) -> impl FnOnce(opendal::Error) -> DatenLordError {
+    move |e| {
+        let context = vec![format!("{context}: {e}")];
+        let temporary = e.is_temporary();
+        let source = Some(e.into());
+        if temporary {
+            DatenLordError::Unavailable { context, source }
+        } else {
+            DatenLordError::Io { context, source }
+        }
+    }
+}
+
+fn no_entry(parent: INum, name: &OsStr) -> DatenLordError {
+    DatenLordError::NotFound {
+        context: vec![format!("no entry {name:?} in directory inode={parent}")],
+        source: None,
+    }
+}
+
+fn lock_key(kind: &str, ino: INum) -> String {
+    format!("{kind}:{ino}")
+}
+
+/// A filesystem shared through a metadata store and an object store
+#[derive(Debug)]
+pub struct SharedFs {
+    meta: Arc<dyn MetaStore>,
+    data: Operator,
+    block_size: u64,
+    lease: Duration,
+    names: NameConfig,
+    atime: AtimePolicy,
+    /// The holder of the locks this instance takes
+    owner: String,
+    /// The session holding the POSIX file locks of this instance
+    locks: LockSession,
+    handles: RwLock<HashMap<u64, OpenFile>>,
+    next_fh: AtomicU64,
+    /// The open directories
+    dirs: DirHandles,
+}
+
+impl SharedFs {
+    /// Connect to the stores `config` describes, creating the root of the
+    /// namespace unless another instance did
+    pub async fn new(config: &SharedConfig) -> DatenLordResult<Self> {
+        let scheme: Scheme =
+            config
+                .data_scheme
+                .parse()
+                .map_err(|e| DatenLordError::InvalidArgument {
+                    context: vec![format!("invalid data scheme {:?}: {e}", config.data_scheme)],
+                })?;
+        let data = Operator::via_map(scheme, config.data_options.clone()).map_err(|e| {
+            DatenLordError::InvalidArgument {
+                context: vec![format!("invalid data service {scheme}: {e}")],
+            }
+        })?;
+        // Every call to the object store in a span of its own, exported
+        // under the spans of the operations
+        #[cfg(feature = "otel")]
+        let data = data.layer(opendal::layers::TracingLayer);
+        Self::with_stores(config, config.meta.build(), data).await
+    }
+
+    /// Like `new`, with the metadata kept in `meta` and the data in `data`
+    /// rather than the stores of `config`, e.g. a `MemoryMeta` and an
+    /// in-memory operator shared by the instances of a process
+    pub async fn with_stores(
+        config: &SharedConfig,
+        meta: Arc<dyn MetaStore>,
+        data: Operator,
+    ) -> DatenLordResult<Self> {
+        if config.block_size == 0 {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec!["the block size must not be 0".to_owned()],
+            });
+        }
+        let root = FileAttr {
+            ino: ROOT_ID,
+            size: 0,
+            blocks: 0,
+            kind: SFlag::S_IFDIR,
+            perm: 0o755,
+            nlink: 2,
+            ..FileAttr::now()
+        };
+        if meta.insert_attr(&root).await? {
+            meta.set_entry(ROOT_ID, OsStr::new(PARENT_ENTRY), ROOT_ID, false)
+                .await?;
+        }
+        let owner = meta::lock_owner();
+        let lease = Duration::from_millis(config.lock_lease_ms);
+        let locks = LockSession::open(config.locks.build(), owner.clone(), lease).await?;
+        Ok(Self {
+            meta,
+            data,
+            block_size: config.block_size,
+            lease,
+            names: config.names.clone(),
+            atime: config.atime,
+            owner,
+            locks,
+            handles: RwLock::new(HashMap::new()),
+            next_fh: AtomicU64::new(1),
+            dirs: DirHandles::default(),
+        })
+    }
+
+    /// The counters of the file locks of the namespace, and the waits of
+    /// this instance for them
+    pub async fn lock_stats(&self) -> DatenLordResult<LockStats> {
+        self.locks.stats().await
+    }
+
+    fn range_lock(&self, ino: INum, param: &FileLockParam) -> DatenLordResult<RangeLock> {
+        Ok(RangeLock {
+            ino,
+            session: self.locks.id().to_owned(),
+            owner: param.lock_owner,
+            pid: param.pid,
+            start: param.start,
+            end: param.end,
+            typ: LockType::from_raw(param.typ)?,
+        })
+    }
+
+    /// Run `op` holding the locks `keys`
+    async fn locked<T>(
+        &self,
+        keys: Vec<String>,
+        op: impl Future<Output = DatenLordResult<T>> + Send,
+    ) -> DatenLordResult<T> {
+        let lock = MetaLock::acquire(&self.meta, &self.owner, keys, self.lease).await?;
+        let result = op.await;
+        lock.release().await;
+        result
+    }
+
+    async fn attr(&self, ino: INum) -> DatenLordResult<FileAttr> {
+        self.meta
+            .get_attr(ino)
+            .await?
+            .ok_or_else(|| DatenLordError::Io {
+                context: vec![format!("no inode={ino}")],
+                source: None,
+            })
+    }
+
+    /// The attributes of the directory `ino`, checking the caller has the
+    /// `access` permissions on it
+    async fn dir_attr(
+        &self,
+        ctx: &RequestContext,
+        ino: INum,
+        access: u8,
+    ) -> DatenLordResult<FileAttr> {
+        let attr = self.attr(ino).await?;
+        if attr.kind != SFlag::S_IFDIR {
+            return Err(DatenLordError::Io {
+                context: vec![format!("inode={ino} is not a directory")],
+                source: None,
+            });
+        }
+        attr.check_perm(ctx, access)?;
+        Ok(attr)
+    }
+
+    /// The directory holding the last component of `name`, a `/` separated
+    /// path under `parent` like the SDKs pass, and that component
+    async fn resolve(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(INum, OsString)> {
+        self.names.check(name)?;
+        let mut components: Vec<_> = fs_util::components(name).collect();
+        let last = components
+            .pop()
+            .ok_or_else(|| DatenLordError::InvalidName {
+                context: vec![format!("invalid name {name:?}: no component")],
+            })?;
+        let mut dir = parent;
+        for component in components {
+            self.dir_attr(ctx, dir, ACCESS_EXEC).await?;
+            dir = self.child(dir, component).await?;
+        }
+        Ok((dir, last.to_owned()))
+    }
+
+    /// The inode of the entry `name` of the directory `parent`
+    async fn child(&self, parent: INum, name: &OsStr) -> DatenLordResult<INum> {
+        if name == "." {
+            return Ok(parent);
+        }
+        self.meta
+            .get_entry(parent, name)
+            .await?
+            .ok_or_else(|| no_entry(parent, name))
+    }
+
+    /// Update the change and modification times and the generation of the
+    /// directory `attr`, adding `links` to its link count
+    async fn touch_dir(&self, mut attr: FileAttr, links: i32) -> DatenLordResult<()> {
+        let now = SystemTime::now();
+        attr.mtime = now;
+        attr.ctime = now;
+        attr.generation = attr.generation.wrapping_add(1);
+        attr.nlink = attr.nlink.saturating_add_signed(links);
+        self.meta.set_attr(&attr).await
+    }
+
+    /// Create the entry `param.name` of `param.parent`
+    async fn create_node(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<FileAttr> {
+        let (parent, name) = self.resolve(ctx, param.parent, &param.name).await?;
+        let kind = param.node_type;
+        let op = async {
+            let dir = self
+                .dir_attr(ctx, parent, ACCESS_WRITE | ACCESS_EXEC)
+                .await?;
+            if name == "."
+                || name == PARENT_ENTRY
+                || self.meta.get_entry(parent, &name).await?.is_some()
+            {
+                return Err(DatenLordError::AlreadyExists {
+                    context: vec![format!("{name:?} exists in directory inode={parent}")],
+                    source: None,
+                });
+            }
+            let ino = self.meta.next_ino().await?;
+            let setgid = u32::from(dir.perm) & nix::sys::stat::Mode::S_ISGID.bits() != 0;
+            let mut perm = ctx.create_mode(param.mode);
+            if kind == SFlag::S_IFDIR && setgid {
+                perm |= nix::sys::stat::Mode::S_ISGID.bits();
+            }
+            let attr = FileAttr {
+                ino,
+                size: param
+                    .link
+                    .as_ref()
+                    .map_or(0, |link| link.as_os_str().len() as u64),
+                blocks: 0,
+                kind,
+                perm: perm as u16,
+                nlink: if kind == SFlag::S_IFDIR { 2 } else { 1 },
+                uid: ctx.uid,
+                gid: if setgid { dir.gid } else { ctx.gid },
+                rdev: param.rdev,
+                ..FileAttr::now()
+            };
+            if let Some(ref link) = param.link {
+                self.write_block(ino, 0, link.as_os_str().as_bytes().to_vec())
+                    .await?;
+            }
+            if kind == SFlag::S_IFDIR {
+                self.meta
+                    .set_entry(ino, OsStr::new(PARENT_ENTRY), parent, false)
+                    .await?;
+            }
+            self.meta.set_attr(&attr).await?;
+            self.meta.set_entry(parent, &name, ino, true).await?;
+            self.touch_dir(dir, i32::from(kind == SFlag::S_IFDIR))
+                .await?;
+            Ok(attr)
+        };
+        self.locked(vec![lock_key("dir", parent)], op).await
+    }
+
+    /// Drop a link of `attr`, and the inode once it has none left
+    async fn drop_link(&self, mut attr: FileAttr) -> DatenLordResult<()> {
+        attr.nlink = if attr.kind == SFlag::S_IFDIR {
+            0
+        } else {
+            attr.nlink.saturating_sub(1)
+        };
+        if attr.nlink > 0 {
+            attr.ctime = SystemTime::now();
+            return self.meta.set_attr(&attr).await;
+        }
+        self.meta.remove_attr(attr.ino).await?;
+        self.truncate_blocks(attr.ino, attr.size, 0).await?;
+        Ok(())
+    }
+
+    /// Check the directory `dir` is not `moved` nor under it
+    async fn check_not_under(&self, moved: INum, mut dir: INum) -> DatenLordResult<()> {
+        loop {
+            if dir == moved {
+                return Err(DatenLordError::InvalidArgument {
+                    context: vec![format!("cannot move directory inode={moved} under itself")],
+                });
+            }
+            if dir == ROOT_ID {
+                return Ok(());
+            }
+            dir = self.child(dir, OsStr::new(PARENT_ENTRY)).await?;
+        }
+    }
+
+    /// Whether the directory `ino` has no entry
+    async fn is_empty(&self, ino: INum) -> DatenLordResult<bool> {
+        Ok(self
+            .meta
+            .entries(ino)
+            .await?
+            .iter()
+            .all(|(name, _)| name == PARENT_ENTRY))
+    }
+
+    fn block_path(ino: INum, index: u64) -> String {
+        format!("{ino}.{index}")
+    }
+
+    /// Block `index` of inode `ino`, empty when missing
+    async fn read_block(&self, ino: INum, index: u64) -> DatenLordResult<Vec<u8>> {
+        let path = Self::block_path(ino, index);
+        match self.data.read(&path).await {
+            Ok(block) => Ok(block),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(data_error(format!("failed to read block {path}"))(e)),
+        }
+    }
+
+    async fn write_block(&self, ino: INum, index: u64, block: Vec<u8>) -> DatenLordResult<()> {
+        let path = Self::block_path(ino, index);
+        self.data
+            .write(&path, block)
+            .await
+            .map_err(data_error(format!("failed to write block {path}")))
+    }
+
+    /// The bytes of block `index` of inode `ino`, 0 when missing
+    async fn block_len(&self, ino: INum, index: u64) -> DatenLordResult<u64> {
+        let path = Self::block_path(ino, index);
+        match self.data.stat(&path).await {
+            Ok(metadata) => Ok(metadata.content_length()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(data_error(format!("failed to stat block {path}"))(e)),
+        }
+    }
+
+    /// Drop the data of inode `ino` past `size`, which was `old_size` long,
+    /// returning the 512-byte sectors freed
+    async fn truncate_blocks(&self, ino: INum, old_size: u64, size: u64) -> DatenLordResult<u64> {
+        let block_size = self.block_size;
+        let mut freed = 0;
+        for index in size.div_ceil(block_size)..old_size.div_ceil(block_size) {
+            // Holes have nothing to remove
+            let len = self.block_len(ino, index).await?;
+            if len == 0 {
+                continue;
+            }
+            let path = Self::block_path(ino, index);
+            match self.data.delete(&path).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    return Err(data_error(format!("failed to remove block {path}"))(e));
+                }
+                _ => freed += sectors(len),
+            }
+        }
+        let tail = (size % block_size) as usize;
+        if tail != 0 {
+            let index = size / block_size;
+            let mut block = self.read_block(ino, index).await?;
+            if block.len() > tail {
+                freed += sectors(block.len() as u64) - sectors(tail as u64);
+                block.truncate(tail);
+                self.write_block(ino, index, block).await?;
+            }
+        }
+        Ok(freed)
+    }
+
+    fn handle(&self, fh: u64) -> DatenLordResult<OpenFile> {
+        self.handles
+            .read()
+            .unwrap()
+            .get(&fh)
+            .copied()
+            .ok_or_else(|| DatenLordError::InvalidArgument {
+                context: vec![format!("unknown file handle={fh}")],
+            })
+    }
+
+    /// The entries of directory `ino` from `offset` on with their attributes,
+    /// read through the open directory `fh`, see `DirHandles::read`
+    async fn listed(&self, ino: INum, fh: u64, offset: i64) -> DatenLordResult<Vec<DirEntry>> {
+        self.dirs
+            .read(ino, fh, offset, || async {
+                let listed = self.list(ino).await?;
+                Ok(listed
+                    .into_iter()
+                    .map(|(entry, attr)| DirEntry {
+                        attr: Some(attr),
+                        ..entry
+                    })
+                    .collect())
+            })
+            .await
+    }
+
+    /// The entries of the directory `ino` sorted by name, so offsets read
+    /// without an open directory stay valid between calls unless it changes
+    async fn list(&self, ino: INum) -> DatenLordResult<Vec<(DirEntry, FileAttr)>> {
+        let mut entries = self.meta.entries(ino).await?;
+        entries.retain(|(name, _)| name != PARENT_ENTRY);
+        entries.sort();
+        let mut listed = Vec::with_capacity(entries.len());
+        for (name, child) in entries {
+            // Removed by another instance since
+            let Some(attr) = self.meta.get_attr(child).await? else {
+                continue;
+            };
+            let kind = FileKind::from_sflag(attr.kind).unwrap_or(FileKind::RegularFile);
+            listed.push((
+                DirEntry {
+                    name,
+                    ino: child,
+                    kind,
+                    attr: None,
+                },
+                attr,
+            ));
+        }
+        Ok(listed)
+    }
+}
+
+#[async_trait]
+impl VirtualFs for SharedFs {
+    fn block_layout(&self) -> BlockLayout {
+        // Blocks are the objects, and copies move whole objects
+        let default = BlockLayout::default();
+        let block_size = self.block_size;
+        BlockLayout {
+            block_size,
+            stripe_size: default.stripe_size.div_ceil(block_size) * block_size,
+            alignment: default.alignment.min(1 << block_size.trailing_zeros()),
+        }
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (parent, name) = self.resolve(ctx, parent, name).await?;
+        self.dir_attr(ctx, parent, ACCESS_EXEC).await?;
+        let ino = self.child(parent, &name).await?;
+        Ok((ATTR_TTL, self.attr(ino).await?, 0))
+    }
+
+    async fn forget(&self, _ino: u64, _nlookup: u64) {}
+
+    async fn getattr(
+        &self,
+        _ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        Ok((ATTR_TTL, self.attr(ino).await?))
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let op = async {
+            let attr = self.attr(ino).await?;
+            let Some(mut changed) = attr.setattr_precheck(&param, ctx)? else {
+                return Ok(attr);
+            };
+            if let Some(size) = param.size {
+                if attr.kind == SFlag::S_IFDIR {
+                    return Err(DatenLordError::Io {
+                        context: vec![format!("cannot truncate directory inode={ino}")],
+                        source: None,
+                    });
+                }
+                attr.check_perm(ctx, ACCESS_WRITE)?;
+                // Growing a file leaves a hole
+                if size < attr.size {
+                    let freed = self.truncate_blocks(ino, attr.size, size).await?;
+                    changed.blocks = attr.blocks.saturating_sub(freed);
+                }
+            }
+            if param.u_id.is_some() || param.g_id.is_some() {
+                if let Some(perm) = changed.setid_cleared_perm(ctx) {
+                    changed.perm = perm;
+                }
+            }
+            self.meta.set_attr(&changed).await?;
+            Ok(changed)
+        };
+        let attr = self.locked(vec![lock_key("file", ino)], op).await?;
+        Ok((ATTR_TTL, attr))
+    }
+
+    async fn readlink(&self, _ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        let attr = self.attr(ino).await?;
+        if attr.kind != SFlag::S_IFLNK {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("inode={ino} is not a symbolic link")],
+            });
+        }
+        self.read_block(ino, 0).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let attr = self.create_node(ctx, param).await?;
+        Ok((ATTR_TTL, attr, 0))
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let param = CreateParam {
+            node_type: SFlag::S_IFDIR,
+            ..param
+        };
+        let attr = self.create_node(ctx, param).await?;
+        Ok((ATTR_TTL, attr, 0))
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let (parent, name) = self.resolve(ctx, parent, name).await?;
+        let op = async {
+            let dir = self
+                .dir_attr(ctx, parent, ACCESS_WRITE | ACCESS_EXEC)
+                .await?;
+            let attr = self.attr(self.child(parent, &name).await?).await?;
+            if attr.kind == SFlag::S_IFDIR {
+                return Err(DatenLordError::Io {
+                    context: vec![format!(
+                        "{name:?} in directory inode={parent} is a directory"
+                    )],
+                    source: None,
+                });
+            }
+            dir.check_sticky(ctx, &attr)?;
+            self.meta.remove_entry(parent, &name).await?;
+            self.drop_link(attr).await?;
+            self.touch_dir(dir, 0).await
+        };
+        self.locked(vec![lock_key("dir", parent)], op).await
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        let (parent, name) = self.resolve(ctx, parent, dir_name).await?;
+        let ino = self.child(parent, &name).await?;
+        // Entries are added to the directory holding its own lock
+        let op = async {
+            let dir = self
+                .dir_attr(ctx, parent, ACCESS_WRITE | ACCESS_EXEC)
+                .await?;
+            if self.meta.get_entry(parent, &name).await? != Some(ino) {
+                return Err(no_entry(parent, &name));
+            }
+            let attr = self.attr(ino).await?;
+            if attr.kind != SFlag::S_IFDIR {
+                return Err(DatenLordError::Io {
+                    context: vec![format!(
+                        "{name:?} in directory inode={parent} is not a directory"
+                    )],
+                    source: None,
+                });
+            }
+            dir.check_sticky(ctx, &attr)?;
+            if !self.is_empty(ino).await? {
+                return Err(DatenLordError::Io {
+                    context: vec![format!("directory {name:?} in inode={parent} is not empty")],
+                    source: None,
+                });
+            }
+            self.meta.remove_entry(parent, &name).await?;
+            self.drop_link(attr).await?;
+            self.touch_dir(dir, -1).await
+        };
+        let keys = vec![lock_key("dir", parent), lock_key("dir", ino)];
+        self.locked(keys, op).await?;
+        Ok(Some(ino))
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let attr = self
+            .create_node(
+                ctx,
+                CreateParam {
+                    parent,
+                    name: name.to_owned(),
+                    mode: 0o777,
+                    rdev: 0,
+                    node_type: SFlag::S_IFLNK,
+                    link: Some(target_path.to_owned()),
+                },
+            )
+            .await?;
+        Ok((ATTR_TTL, attr, 0))
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let flags =
+            RenameFlags::from_bits(param.flags).ok_or_else(|| DatenLordError::InvalidArgument {
+                context: vec![format!("unsupported rename flags={:#x}", param.flags)],
+            })?;
+        let exchange = flags.contains(RenameFlags::RENAME_EXCHANGE);
+        let (old_parent, old_name) = self.resolve(ctx, param.old_parent, &param.old_name).await?;
+        let (new_parent, new_name) = self.resolve(ctx, param.new_parent, &param.new_name).await?;
+        let mut keys = vec![lock_key("dir", old_parent), lock_key("dir", new_parent)];
+        if old_parent != new_parent {
+            // Only one directory moves between parents at a time, so the
+            // ancestors checked below do not change meanwhile
+            keys.push("rename".to_owned());
+        }
+        let op = async {
+            let old_dir = self
+                .dir_attr(ctx, old_parent, ACCESS_WRITE | ACCESS_EXEC)
+                .await?;
+            let new_dir = self
+                .dir_attr(ctx, new_parent, ACCESS_WRITE | ACCESS_EXEC)
+                .await?;
+            let src = self.attr(self.child(old_parent, &old_name).await?).await?;
+            old_dir.check_sticky(ctx, &src)?;
+            let dst = match self.meta.get_entry(new_parent, &new_name).await? {
+                Some(ino) => Some(self.attr(ino).await?),
+                None => None,
+            };
+            if let Some(ref dst) = dst {
+                new_dir.check_sticky(ctx, dst)?;
+            }
+            if dst.is_some_and(|dst| dst.ino == src.ino) {
+                return Ok(());
+            }
+            // Neither directory may move under itself
+            if old_parent != new_parent {
+                if src.kind == SFlag::S_IFDIR {
+                    self.check_not_under(src.ino, new_parent).await?;
+                }
+                if let Some(dst) = dst.filter(|dst| exchange && dst.kind == SFlag::S_IFDIR) {
+                    self.check_not_under(dst.ino, old_parent).await?;
+                }
+            }
+            let src_is_dir = src.kind == SFlag::S_IFDIR;
+            let mut links = (0, 0);
+            match dst {
+                Some(dst) if exchange => {
+                    let dst_is_dir = dst.kind == SFlag::S_IFDIR;
+                    self.meta
+                        .set_entry(old_parent, &old_name, dst.ino, false)
+                        .await?;
+                    self.meta
+                        .set_entry(new_parent, &new_name, src.ino, false)
+                        .await?;
+                    if old_parent != new_parent {
+                        if src_is_dir {
+                            self.meta
+                                .set_entry(src.ino, OsStr::new(PARENT_ENTRY), new_parent, false)
+                                .await?;
+                        }
+                        if dst_is_dir {
+                            self.meta
+                                .set_entry(dst.ino, OsStr::new(PARENT_ENTRY), old_parent, false)
+                                .await?;
+                        }
+                        let moved = i32::from(src_is_dir) - i32::from(dst_is_dir);
+                        links = (-moved, moved);
+                    }
+                }
+                None if exchange => return Err(no_entry(new_parent, &new_name)),
+                Some(_) if flags.contains(RenameFlags::RENAME_NOREPLACE) => {
+                    return Err(DatenLordError::AlreadyExists {
+                        context: vec![format!(
+                            "{new_name:?} exists in directory inode={new_parent}"
+                        )],
+                        source: None,
+                    });
+                }
+                dst => {
+                    if let Some(dst) = dst {
+                        match (src_is_dir, dst.kind == SFlag::S_IFDIR) {
+                            (true, false) => {
+                                return Err(DatenLordError::Io {
+                                    context: vec![format!("{new_name:?} is not a directory")],
+                                    source: None,
+                                });
+                            }
+                            (false, true) => {
+                                return Err(DatenLordError::Io {
+                                    context: vec![format!("{new_name:?} is a directory")],
+                                    source: None,
+                                });
+                            }
+                            (true, true) if !self.is_empty(dst.ino).await? => {
+                                return Err(DatenLordError::Io {
+                                    context: vec![format!("directory {new_name:?} is not empty")],
+                                    source: None,
+                                });
+                            }
+                            _ => {}
+                        }
+                        self.drop_link(dst).await?;
+                        links.1 -= i32::from(src_is_dir);
+                    }
+                    self.meta
+                        .set_entry(new_parent, &new_name, src.ino, false)
+                        .await?;
+                    self.meta.remove_entry(old_parent, &old_name).await?;
+                    if src_is_dir && old_parent != new_parent {
+                        self.meta
+                            .set_entry(src.ino, OsStr::new(PARENT_ENTRY), new_parent, false)
+                            .await?;
+                        links.0 -= 1;
+                        links.1 += 1;
+                    }
+                }
+            }
+            let mut src = src;
+            src.ctime = SystemTime::now();
+            self.meta.set_attr(&src).await?;
+            if old_parent == new_parent {
+                self.touch_dir(old_dir, links.0 + links.1).await
+            } else {
+                self.touch_dir(old_dir, links.0).await?;
+                self.touch_dir(new_dir, links.1).await
+            }
+        };
+        self.locked(keys, op).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let attr = self.attr(ino).await?;
+        let oflags = parse_oflag(flags);
+        let access_mode = oflags & OFlag::O_ACCMODE;
+        let mut required = 0;
+        if access_mode != OFlag::O_WRONLY {
+            required |= ACCESS_READ;
+        }
+        if access_mode != OFlag::O_RDONLY || oflags.contains(OFlag::O_TRUNC) {
+            required |= ACCESS_WRITE;
+        }
+        attr.check_perm(ctx, required)?;
+        if oflags.contains(OFlag::O_NOFOLLOW) && attr.kind == SFlag::S_IFLNK {
+            return Err(DatenLordError::from(Errno::ELOOP)
+                .add_context(format!("inode={ino} is a symbolic link")));
+        }
+        // Truncating bumps the times even of an empty file, like `open(2)`
+        if oflags.contains(OFlag::O_TRUNC) && attr.kind == SFlag::S_IFREG {
+            let param = SetAttrParam {
+                size: Some(0),
+                ..SetAttrParam::default()
+            };
+            self.setattr(ctx, ino, param).await?;
+        }
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.handles.write().unwrap().insert(
+            fh,
+            OpenFile {
+                ino,
+                append: oflags.contains(OFlag::O_APPEND),
+            },
+        );
+        Ok(fh)
+    }
+
+    async fn read(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        let handle = self.handle(fh)?;
+        let attr = self.attr(handle.ino).await?;
+        let len = buf.len().min(size as usize) as u64;
+        let end = attr.size.min(offset.saturating_add(len));
+        let mut pos = offset;
+        while pos < end {
+            let (index, start) = (pos / self.block_size, (pos % self.block_size) as usize);
+            let n = (self.block_size - start as u64).min(end - pos) as usize;
+            let block = self.read_block(handle.ino, index).await?;
+            let out = &mut buf[(pos - offset) as usize..][..n];
+            let available = block.len().saturating_sub(start).min(n);
+            out[..available].copy_from_slice(&block[start..start + available]);
+            out[available..].fill(0);
+            pos += n as u64;
+        }
+        // A write to the store only when the policy says so, rarely for
+        // `relatime`
+        let now = SystemTime::now();
+        if attr.read_updates_atime(self.atime, now) {
+            let op = async {
+                let attr = self.attr(handle.ino).await?;
+                if attr.read_updates_atime(self.atime, now) {
+                    self.meta.set_attr(&FileAttr { atime: now, ..attr }).await?;
+                }
+                Ok(())
+            };
+            self.locked(vec![lock_key("file", handle.ino)], op).await?;
+        }
+        Ok(end.saturating_sub(offset) as usize)
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _flags: u32,
+    ) -> DatenLordResult<()> {
+        let handle = self.handle(fh)?;
+        let offset = u64::try_from(offset).map_err(|_| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid write offset={offset}")],
+        })?;
+        let op = async {
+            let mut attr = self.attr(handle.ino).await?;
+            let offset = if handle.append { attr.size } else { offset };
+            let mut written = 0;
+            while written < data.len() {
+                let pos = offset + written as u64;
+                let (index, start) = (pos / self.block_size, (pos % self.block_size) as usize);
+                let n = (self.block_size as usize - start).min(data.len() - written);
+                // A block written whole need not be read first
+                let (mut block, old_len) = if start == 0 && n as u64 == self.block_size {
+                    (Vec::new(), self.block_len(handle.ino, index).await?)
+                } else {
+                    let block = self.read_block(handle.ino, index).await?;
+                    let len = block.len() as u64;
+                    (block, len)
+                };
+                if block.len() < start + n {
+                    block.resize(start + n, 0);
+                }
+                block[start..start + n].copy_from_slice(&data[written..written + n]);
+                attr.blocks = (attr.blocks + sectors(block.len() as u64))
+                    .saturating_sub(sectors(old_len));
+                self.write_block(handle.ino, index, block).await?;
+                written += n;
+            }
+            let now = SystemTime::now();
+            attr.size = attr.size.max(offset + data.len() as u64);
+            attr.mtime = now;
+            attr.ctime = now;
+            if let Some(perm) = attr.setid_cleared_perm(ctx) {
+                attr.perm = perm;
+            }
+            self.meta.set_attr(&attr).await
+        };
+        self.locked(vec![lock_key("file", handle.ino)], op).await
+    }
+
+    async fn flush(
+        &self,
+        _ctx: &RequestContext,
+        ino: u64,
+        _fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        // Closing any descriptor drops the POSIX locks of its owner
+        self.locks.release(ino, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+    ) -> DatenLordResult<()> {
+        self.handles.write().unwrap().remove(&fh);
+        Ok(())
+    }
+
+    async fn fsync(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        _datasync: bool,
+    ) -> DatenLordResult<()> {
+        // Every write reaches both stores before it returns
+        self.handle(fh).map(|_| ())
+    }
+
+    async fn lseek(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        let handle = self.handle(fh)?;
+        let attr = self.attr(handle.ino).await?;
+        let block_size = self.block_size;
+        let mut extents = Vec::new();
+        for index in offset / block_size..attr.size.div_ceil(block_size) {
+            let len = self.block_len(handle.ino, index).await?;
+            let start = index * block_size;
+            if len > 0 {
+                extents.push((start, start + len));
+            }
+            // Missing blocks and the ends of short ones read as zeros
+            match whence {
+                SeekWhence::Data if len > 0 && start + len > offset => break,
+                SeekWhence::Hole if len < block_size => break,
+                _ => {}
+            }
+        }
+        Ok(fs_util::seek_extents(&extents, attr.size, offset, whence))
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        match cmd {
+            ioctl::IOCTL_PLACEMENT => {}
+            // Objects have no flags
+            ioctl::IOCTL_FILE_FLAGS => {
+                self.attr(ino).await?;
+                return Ok(0_u32.to_le_bytes().to_vec());
+            }
+            _ => return ioctl::dispatch(ctx, ino, cmd, input).await,
+        }
+        let attr = self.attr(ino).await?;
+        let mut placement = Vec::new();
+        for index in 0..attr.size.div_ceil(self.block_size) {
+            // Holes have no object
+            if self.block_len(ino, index).await? > 0 {
+                placement.extend_from_slice(Self::block_path(ino, index).as_bytes());
+                placement.push(b'\n');
+            }
+        }
+        Ok(placement)
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, _flags: u32) -> DatenLordResult<u64> {
+        self.dir_attr(ctx, ino, ACCESS_READ).await?;
+        Ok(self.dirs.open(ino))
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.dir_attr(ctx, ino, ACCESS_READ).await?;
+        let entries = self.listed(ino, fh, offset).await?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| DirEntry { attr: None, ..entry })
+            .collect())
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.dir_attr(ctx, ino, ACCESS_READ).await?;
+        let entries = self.listed(ino, fh, offset).await?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|entry| {
+                let attr = entry.attr?;
+                Some((entry, attr, ATTR_TTL))
+            })
+            .collect())
+    }
+
+    async fn releasedir(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        fh: u64,
+        _flags: u32,
+    ) -> DatenLordResult<()> {
+        self.dirs.release(fh);
+        Ok(())
+    }
+
+    async fn fsyncdir(
+        &self,
+        _ctx: &RequestContext,
+        _ino: u64,
+        _fh: u64,
+        _datasync: bool,
+    ) -> DatenLordResult<()> {
+        Ok(())
+    }
+
+    async fn statfs(&self, _ctx: &RequestContext, _ino: u64) -> DatenLordResult<StatFsParam> {
+        Ok(StatFsParam {
+            namelen: u32::try_from(self.names.max_len).unwrap_or(u32::MAX),
+            ..StatFsParam::default()
+        })
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        _ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let param = CreateParam {
+            parent,
+            name: name.to_owned(),
+            mode,
+            rdev: 0,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        match self.create_node(ctx, param).await {
+            Err(DatenLordError::AlreadyExists { .. })
+                if !parse_oflag(flags).contains(OFlag::O_EXCL) =>
+            {
+                Ok(())
+            }
+            result => result.map(|_| ()),
+        }
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        let attr = self.attr(ino).await?;
+        let access_mode = (mask & 0o7) as u8;
+        if access_mode == 0 {
+            return Ok(());
+        }
+        attr.check_perm(ctx, access_mode)
+    }
+
+    async fn getlk(
+        &self,
+        _ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        let lock = self.range_lock(ino, &lk_param)?;
+        match self.locks.test(&lock).await? {
+            Some(held) => Err(filelock::conflict(&held)),
+            None => Ok(()),
+        }
+    }
+
+    async fn setlk(
+        &self,
+        _ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        let lock = self.range_lock(ino, &lk_param)?;
+        self.locks.set(&lock, sleep).await
+    }
+}
+
+#[async_trait]
+impl Sweep for SharedFs {
+    /// Remove the blocks of inodes with no attributes, those past the size
+    /// of their inode, and the inodes no directory links to any more that
+    /// hold blocks
+    async fn sweep(
+        &self,
+        _ctx: &RequestContext,
+        reachable: &HashSet<INum>,
+        grace: Duration,
+        dry_run: bool,
+    ) -> DatenLordResult<GcReport> {
+        let mut blocks: BTreeMap<INum, Vec<(u64, String)>> = BTreeMap::new();
+        for entry in self
+            .data
+            .list("/")
+            .await
+            .map_err(data_error("failed to list the blocks".to_owned()))?
+        {
+            let Some((ino, index)) = entry.name().split_once('.') else {
+                continue;
+            };
+            if let (Ok(ino), Ok(index)) = (ino.parse(), index.parse()) {
+                blocks
+                    .entry(ino)
+                    .or_default()
+                    .push((index, entry.path().to_owned()));
+            }
+        }
+        let mut report = GcReport::default();
+        for (ino, blocks) in blocks {
+            let op = async {
+                let kept = match self.meta.get_attr(ino).await? {
+                    None => 0,
+                    // Unlinked by an instance that died before dropping it,
+                    // rather than being created
+                    Some(attr)
+                        if !reachable.contains(&ino)
+                            && attr.ctime.elapsed().unwrap_or_default() >= grace =>
+                    {
+                        report.found(format!("inode {ino}"), 0, dry_run);
+                        if !dry_run {
+                            self.meta.remove_attr(ino).await?;
+                        }
+                        0
+                    }
+                    Some(attr) => attr.size.div_ceil(self.block_size),
+                };
+                for (index, path) in blocks {
+                    if index < kept {
+                        continue;
+                    }
+                    let bytes = match self.data.stat(&path).await {
+                        Ok(metadata) => metadata.content_length(),
+                        Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                        Err(e) => return Err(data_error(format!("failed to stat block {path}"))(e)),
+                    };
+                    report.found(format!("block {path}"), bytes, dry_run);
+                    if !dry_run {
+                        match self.data.delete(&path).await {
+                            Err(e) if e.kind() != ErrorKind::NotFound => {
+                                return Err(data_error(format!("failed to remove block {path}"))(e));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Ok(())
+            };
+            self.locked(vec![lock_key("file", ino)], op).await?;
+        }
+        Ok(report)
+    }
+}
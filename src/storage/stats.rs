@@ -0,0 +1,580 @@
+//! Middleware counting the calls to a `VirtualFs`, their failures and the
+//! bytes they move, so the SDKs can report them while running
+//!
+//! Every call also runs in an `info` span named `vfs` with the operation in
+//! its `op` field, which the `otel` feature exports, see `common::logging`.
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+use tracing::{info_span, Instrument};
+
+use crate::common::DatenLordResult;
+
+use super::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam, RenameParam,
+    RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// The operations counted, every `VirtualFs` call returning a result
+const OPS: [&str; 36] = [
+    "lookup",
+    "exists",
+    "getattr",
+    "setattr",
+    "readlink",
+    "mknod",
+    "mkdir",
+    "unlink",
+    "rmdir",
+    "symlink",
+    "rename",
+    "link",
+    "open",
+    "read",
+    "write",
+    "flush",
+    "release",
+    "fsync",
+    "lseek",
+    "opendir",
+    "readdir",
+    "readdirplus",
+    "releasedir",
+    "fsyncdir",
+    "sync_all",
+    "statfs",
+    "setxattr",
+    "getxattr",
+    "listxattr",
+    "removexattr",
+    "access",
+    "create",
+    "getlk",
+    "setlk",
+    "bmap",
+    "ioctl",
+];
+
+/// Run the inner call `$call` of operation `$op` in a span of its own,
+/// counting it
+macro_rules! count {
+    ($self:ident, $op:literal, $call:expr) => {{
+        let _running = $self.enter($op);
+        let span = info_span!("vfs", op = $op, otel.name = concat!("vfs.", $op));
+        let result = $call.instrument(span).await;
+        if result.is_err() {
+            $self.failed($op);
+        }
+        result
+    }};
+}
+
+/// The calls of one operation so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpStats {
+    /// The calls made, finished or not
+    pub calls: u64,
+    /// The calls failed
+    pub errors: u64,
+}
+
+/// What a `StatsFs` counted so far
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FsStats {
+    /// The calls of every operation called at least once, by name
+    pub ops: BTreeMap<String, OpStats>,
+    /// The bytes returned by reads
+    pub bytes_read: u64,
+    /// The bytes written by successful writes
+    pub bytes_written: u64,
+    /// The calls running now
+    pub in_flight: u64,
+}
+
+/// The counters of one operation
+#[derive(Debug, Default)]
+struct OpCounters {
+    /// The calls made
+    calls: AtomicU64,
+    /// The calls failed
+    errors: AtomicU64,
+}
+
+/// A call counted as running until dropped, even when cancelled
+#[derive(Debug)]
+struct Running<'a>(&'a AtomicU64);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A `VirtualFs` counting the calls to the inner filesystem
+///
+/// Counting takes a few relaxed atomic increments per call and no lock.
+#[derive(Debug)]
+pub struct StatsFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// The counters of every operation in `OPS`
+    ops: HashMap<&'static str, OpCounters>,
+    /// The bytes returned by reads
+    bytes_read: AtomicU64,
+    /// The bytes written by successful writes
+    bytes_written: AtomicU64,
+    /// The calls running now
+    in_flight: AtomicU64,
+}
+
+impl<F: VirtualFs> StatsFs<F> {
+    /// Wrap `inner`, counting from zero
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner,
+            ops: OPS.iter().map(|&op| (op, OpCounters::default())).collect(),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+        }
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// What was counted so far
+    pub fn stats(&self) -> FsStats {
+        let ops = self
+            .ops
+            .iter()
+            .filter_map(|(&op, counters)| {
+                let calls = counters.calls.load(Ordering::Relaxed);
+                let errors = counters.errors.load(Ordering::Relaxed);
+                (calls > 0).then(|| (op.to_owned(), OpStats { calls, errors }))
+            })
+            .collect();
+        FsStats {
+            ops,
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Count a call `op` starting, running until the guard returned drops
+    fn enter(&self, op: &str) -> Running<'_> {
+        if let Some(counters) = self.ops.get(op) {
+            counters.calls.fetch_add(1, Ordering::Relaxed);
+        }
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        Running(&self.in_flight)
+    }
+
+    /// Count a call `op` failing
+    fn failed(&self, op: &str) {
+        if let Some(counters) = self.ops.get(op) {
+            counters.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for StatsFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        count!(self, "lookup", self.inner.lookup(ctx, parent, name))
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        count!(self, "exists", self.inner.exists(ctx, parent, name))
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        count!(self, "getattr", self.inner.getattr(ctx, ino))
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        count!(self, "setattr", self.inner.setattr(ctx, ino, param))
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        count!(self, "readlink", self.inner.readlink(ctx, ino))
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        count!(self, "mknod", self.inner.mknod(ctx, param))
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        count!(self, "mkdir", self.inner.mkdir(ctx, param))
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        count!(self, "unlink", self.inner.unlink(ctx, parent, name))
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        count!(self, "rmdir", self.inner.rmdir(ctx, parent, dir_name))
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        count!(
+            self,
+            "symlink",
+            self.inner.symlink(ctx, parent, name, target_path)
+        )
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        count!(self, "rename", self.inner.rename(ctx, param))
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        count!(self, "link", self.inner.link(ctx, newparent, newname))
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        count!(self, "open", self.inner.open(ctx, ino, flags))
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        let result = count!(
+            self,
+            "read",
+            self.inner.read(ctx, ino, fh, offset, size, buf)
+        );
+        if let Ok(n) = result {
+            self.bytes_read.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let result = count!(
+            self,
+            "write",
+            self.inner.write(ctx, ino, fh, offset, data, flags)
+        );
+        if result.is_ok() {
+            self.bytes_written
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        count!(self, "flush", self.inner.flush(ctx, ino, fh, lock_owner))
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        count!(
+            self,
+            "release",
+            self.inner.release(ctx, ino, fh, flags, lock_owner, flush)
+        )
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        count!(self, "fsync", self.inner.fsync(ctx, ino, fh, datasync))
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        count!(
+            self,
+            "lseek",
+            self.inner.lseek(ctx, ino, fh, offset, whence)
+        )
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        let result = count!(
+            self,
+            "copy_file_range",
+            self.inner.copy_file_range(ctx, param)
+        );
+        if let Ok(ref copy) = result {
+            self.bytes_read.fetch_add(copy.copied, Ordering::Relaxed);
+            self.bytes_written.fetch_add(copy.copied, Ordering::Relaxed);
+        }
+        result
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        count!(self, "opendir", self.inner.opendir(ctx, ino, flags))
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        count!(self, "readdir", self.inner.readdir(ctx, ino, fh, offset))
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        count!(
+            self,
+            "readdirplus",
+            self.inner.readdirplus(ctx, ino, fh, offset)
+        )
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        count!(
+            self,
+            "releasedir",
+            self.inner.releasedir(ctx, ino, fh, flags)
+        )
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        count!(
+            self,
+            "fsyncdir",
+            self.inner.fsyncdir(ctx, ino, fh, datasync)
+        )
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        count!(self, "sync_all", self.inner.sync_all(ctx))
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        count!(self, "statfs", self.inner.statfs(ctx, ino))
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        count!(
+            self,
+            "setxattr",
+            self.inner.setxattr(ctx, ino, name, value, flags, position)
+        )
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        count!(self, "getxattr", self.inner.getxattr(ctx, ino, name))
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        count!(self, "listxattr", self.inner.listxattr(ctx, ino))
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        count!(self, "removexattr", self.inner.removexattr(ctx, ino, name))
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        count!(self, "access", self.inner.access(ctx, ino, mask))
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        count!(
+            self,
+            "create",
+            self.inner.create(ctx, ino, parent, name, mode, flags)
+        )
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        count!(self, "getlk", self.inner.getlk(ctx, ino, lk_param))
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        count!(self, "setlk", self.inner.setlk(ctx, ino, lk_param, sleep))
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        count!(self, "bmap", self.inner.bmap(ctx, ino, blocksize, idx))
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        count!(self, "ioctl", self.inner.ioctl(ctx, ino, cmd, input))
+    }
+}
@@ -0,0 +1,114 @@
+//! Reading files as a stream of chunks, so large files can be consumed
+//! incrementally, or many small files at once
+use std::ffi::OsStr;
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use futures::stream::{self, Stream, StreamExt};
+use nix::fcntl::OFlag;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{FileAttr, RequestContext};
+use super::virtualfs::{INum, VirtualFs};
+use super::walk;
+
+/// The size of the chunks of a stream unless told otherwise, 1 MiB
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Read at most `len` bytes of the file `ino` of `fs` from `offset` on
+/// behalf of `ctx`, as chunks of at most `chunk_size` bytes
+///
+/// Chunks are read as the stream is polled, one at a time, and the stream
+/// ends early at the end of the file. The file is opened for every chunk
+/// and released right after, so a stream dropped midway leaves nothing
+/// open. The first error ends the stream.
+pub fn read_stream<F: VirtualFs + ?Sized + 'static>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    ino: INum,
+    offset: u64,
+    len: u64,
+    chunk_size: usize,
+) -> impl Stream<Item = DatenLordResult<Bytes>> + Send + 'static {
+    stream::try_unfold((offset, len), move |(offset, remaining)| {
+        let fs = Arc::clone(&fs);
+        async move {
+            if chunk_size == 0 {
+                return Err(DatenLordError::InvalidArgument {
+                    context: vec!["chunk size 0".to_owned()],
+                });
+            }
+            if remaining == 0 {
+                return Ok(None);
+            }
+            let size = remaining.min(chunk_size.min(u32::MAX as usize) as u64) as usize;
+            let mut chunk = BytesMut::zeroed(size);
+            let fh = fs.open(&ctx, ino, OFlag::O_RDONLY.bits() as u32).await?;
+            let read = fs
+                .read(&ctx, ino, fh, offset, size as u32, &mut chunk)
+                .await;
+            fs.release(&ctx, ino, fh, 0, 0, false).await?;
+            let read = read?;
+            if read == 0 {
+                return Ok(None);
+            }
+            chunk.truncate(read);
+            let next = (offset + read as u64, remaining - read as u64);
+            Ok(Some((chunk.freeze(), next)))
+        }
+    })
+}
+
+/// Read every file of `paths`, relative to the root, whole on behalf of
+/// `ctx`, with at most `concurrency` reads at once on the current task
+///
+/// The paths are stat'ed together first, see `walk::stat_many`. The results
+/// come in the order of `paths`, and the deadline of the caller, if any,
+/// bounds every read.
+pub async fn read_many<F: VirtualFs + 'static, P: AsRef<OsStr>>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    paths: &[P],
+    concurrency: usize,
+) -> Vec<DatenLordResult<Vec<u8>>> {
+    let attrs = walk::stat_many(Arc::clone(&fs), ctx, paths, concurrency).await;
+    stream::iter(attrs)
+        .map(|attr| {
+            let fs = Arc::clone(&fs);
+            async move { read_whole(fs.as_ref(), &ctx, &attr?).await }
+        })
+        .buffered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Read the file of `attr` up to the end
+async fn read_whole<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    attr: &FileAttr,
+) -> DatenLordResult<Vec<u8>> {
+    let mut data = vec![0; attr.size as usize];
+    let fh = fs
+        .open(ctx, attr.ino, OFlag::O_RDONLY.bits() as u32)
+        .await?;
+    let read = async {
+        let mut filled = 0;
+        while filled < data.len() {
+            let len = (data.len() - filled).min(u32::MAX as usize) as u32;
+            let read = fs
+                .read(ctx, attr.ino, fh, filled as u64, len, &mut data[filled..])
+                .await?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        DatenLordResult::Ok(filled)
+    }
+    .await;
+    fs.release(ctx, attr.ino, fh, 0, 0, false).await?;
+    data.truncate(read?);
+    Ok(data)
+}
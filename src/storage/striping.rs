@@ -0,0 +1,1161 @@
+//! Erasure-coded striping of file data across several directories, e.g. on
+//! separate devices, so files survive losing some of them
+//!
+//! The data of a file is cut into stripes of `K * chunk_size` bytes, each
+//! split into K data chunks and encoded into M parity chunks with
+//! Reed-Solomon, M being `parity_shards` and K the remaining `paths`. Chunk
+//! `j` of stripe `n` of inode `ino` is kept as `<paths[j]>/<ino>/<n>`,
+//! followed by the version of the stripe and a checksum, so any K chunks of
+//! its latest version give a stripe back. The wrapped filesystem keeps the
+//! namespace and the attributes, its files being left sparse at their size.
+//!
+//! A file is striped once its inode has a directory in one of the paths,
+//! made when it is created or truncated to nothing. Files holding data from
+//! before striping was configured are served by the wrapped filesystem
+//! until then.
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use nix::fcntl::RenameFlags;
+use nix::libc;
+use nix::sys::stat::{Mode, SFlag};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::migrate;
+
+use super::fs_util::{
+    seek_extents, CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::gc::{GcReport, Sweep};
+use super::layout::BlockLayout;
+use super::virtualfs::{copy_range_through, INum, VirtualFs};
+
+/// Default size of a chunk, 64 KiB
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+/// The bytes following the data of a chunk: its version and checksum
+const TRAILER_LEN: usize = 12;
+/// The locks serializing the changes to the stripes of an inode, picked by
+/// inode number
+const LOCK_COUNT: usize = 64;
+
+/// The directories a `StripedBackend` spreads file data over, none by
+/// default
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StripingConfig {
+    /// One directory per chunk of a stripe, `file:///path` or plain paths,
+    /// created when missing
+    pub paths: Vec<String>,
+    /// The parity chunks of a stripe, as many paths as can be lost
+    pub parity_shards: usize,
+    /// The bytes of a chunk
+    pub chunk_size: usize,
+}
+
+impl Default for StripingConfig {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            parity_shards: 1,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+/// What `StripedBackend::rebuild` found and repaired
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RebuildReport {
+    /// The striped inodes
+    pub inodes: u64,
+    /// The stripes checked
+    pub stripes: u64,
+    /// The chunks missing, corrupt or stale and written again
+    pub repaired: u64,
+    /// The stripes with less than K chunks left, by inode and index
+    pub lost: Vec<(INum, u64)>,
+}
+
+/// A chunk as read from its path
+enum Chunk {
+    /// No such file
+    Missing,
+    /// A file that failed to read or to match its checksum
+    Unreadable,
+    /// The data of a version of the stripe
+    Valid(u64, Vec<u8>),
+}
+
+/// The chunks of a stripe at its latest version held by K of them
+struct Stripe {
+    /// The version of the stripe
+    version: u64,
+    /// The chunks of that version, `None` for the others
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+/// Where and how the chunks are kept
+#[derive(Debug)]
+struct Layout {
+    /// The directory of every chunk index
+    paths: Vec<PathBuf>,
+    /// The data chunks of a stripe, K
+    data_shards: usize,
+    /// The bytes of a chunk
+    chunk_size: usize,
+    /// The Reed-Solomon code of K data and M parity chunks
+    codec: ReedSolomon,
+    /// The locks serializing changes to the stripes of an inode
+    locks: Vec<Mutex<()>>,
+}
+
+impl Layout {
+    /// The bytes of data of a stripe
+    fn stripe_size(&self) -> u64 {
+        (self.data_shards * self.chunk_size) as u64
+    }
+
+    /// Lock the stripes of `ino`
+    async fn lock(&self, ino: INum) -> MutexGuard<'_, ()> {
+        self.locks[(ino % LOCK_COUNT as u64) as usize].lock().await
+    }
+
+    /// The directory of chunk index `j` of `ino`
+    fn inode_dir(&self, j: usize, ino: INum) -> PathBuf {
+        self.paths[j].join(ino.to_string())
+    }
+
+    /// Whether the data of `ino` is striped
+    fn is_striped(&self, ino: INum) -> bool {
+        (0..self.paths.len()).any(|j| self.inode_dir(j, ino).is_dir())
+    }
+
+    /// Make `ino` striped, without data yet
+    fn reset(&self, ino: INum) -> DatenLordResult<()> {
+        self.remove(ino);
+        let mut made = 0;
+        for j in 0..self.paths.len() {
+            match create_dir(&self.inode_dir(j, ino)) {
+                Ok(()) => made += 1,
+                Err(e) => warn!("failed to stripe inode {ino} to {:?}: {e}", self.paths[j]),
+            }
+        }
+        if made == 0 {
+            return Err(DatenLordError::Io {
+                context: vec![format!("failed to stripe inode {ino} to any path")],
+                source: None,
+            });
+        }
+        Ok(())
+    }
+
+    /// Remove the stripes of `ino`
+    fn remove(&self, ino: INum) {
+        for j in 0..self.paths.len() {
+            let dir = self.inode_dir(j, ino);
+            match fs::remove_dir_all(&dir) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    warn!("failed to remove the stripes {dir:?}: {e}");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The indexes of the stripes of `ino` any path holds a chunk of
+    fn stripes(&self, ino: INum) -> Vec<u64> {
+        let mut stripes = Vec::new();
+        for j in 0..self.paths.len() {
+            let Ok(entries) = fs::read_dir(self.inode_dir(j, ino)) else {
+                continue;
+            };
+            stripes.extend(
+                entries
+                    .flatten()
+                    .filter_map(|entry| entry.file_name().to_str()?.parse::<u64>().ok()),
+            );
+        }
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes
+    }
+
+    /// Read chunk `j` of stripe `n` of `ino`
+    fn load_chunk(&self, j: usize, ino: INum, n: u64) -> Chunk {
+        let path = self.inode_dir(j, ino).join(n.to_string());
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Chunk::Missing,
+            Err(e) => {
+                warn!("failed to read chunk {path:?}: {e}");
+                return Chunk::Unreadable;
+            }
+        };
+        if bytes.len() != self.chunk_size + TRAILER_LEN {
+            warn!("chunk {path:?} has {} bytes", bytes.len());
+            return Chunk::Unreadable;
+        }
+        let (body, checksum) = bytes.split_at(self.chunk_size + 8);
+        if crc32c::crc32c(body).to_le_bytes() != checksum {
+            warn!("chunk {path:?} does not match its checksum");
+            return Chunk::Unreadable;
+        }
+        let (data, version) = body.split_at(self.chunk_size);
+        let version = u64::from_le_bytes(version.try_into().unwrap_or_default());
+        Chunk::Valid(version, data.to_vec())
+    }
+
+    /// Write chunk `j` of version `version` of stripe `n` of `ino`
+    fn store_chunk(
+        &self,
+        j: usize,
+        ino: INum,
+        n: u64,
+        version: u64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let dir = self.inode_dir(j, ino);
+        match create_dir(&dir) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+        let mut bytes = Vec::with_capacity(data.len() + TRAILER_LEN);
+        bytes.extend_from_slice(data);
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend_from_slice(&crc32c::crc32c(&bytes).to_le_bytes());
+        // Readers see either version of the chunk, never a torn one
+        let temp = dir.join(format!(".{n}.tmp"));
+        fs::write(&temp, bytes)?;
+        fs::rename(temp, dir.join(n.to_string()))
+    }
+
+    /// The chunks of stripe `n` of `ino`, `None` for a hole
+    fn load_stripe(&self, ino: INum, n: u64) -> DatenLordResult<Option<Stripe>> {
+        let chunks = (0..self.paths.len())
+            .map(|j| self.load_chunk(j, ino, n))
+            .collect::<Vec<_>>();
+        if chunks.iter().all(|chunk| matches!(chunk, Chunk::Missing)) {
+            return Ok(None);
+        }
+        let mut versions = chunks
+            .iter()
+            .filter_map(|chunk| match *chunk {
+                Chunk::Valid(version, _) => Some(version),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        versions.sort_unstable();
+        // A version held by less than K chunks is being written, or was
+        // never written in full
+        let version = versions
+            .iter()
+            .rev()
+            .find(|&&version| {
+                versions.iter().filter(|&&v| v == version).count() >= self.data_shards
+            })
+            .copied()
+            .ok_or_else(|| DatenLordError::Io {
+                context: vec![format!(
+                    "stripe {n} of inode {ino} is lost, less than {} of its chunks are left",
+                    self.data_shards
+                )],
+                source: None,
+            })?;
+        let chunks = chunks
+            .into_iter()
+            .map(|chunk| match chunk {
+                Chunk::Valid(v, data) if v == version => Some(data),
+                _ => None,
+            })
+            .collect();
+        Ok(Some(Stripe { version, chunks }))
+    }
+
+    /// The data of stripe `n` of `ino` and its version, reconstructed when
+    /// data chunks are missing, `None` for a hole
+    fn read_stripe(&self, ino: INum, n: u64) -> DatenLordResult<Option<(u64, Vec<u8>)>> {
+        let Some(mut stripe) = self.load_stripe(ino, n)? else {
+            return Ok(None);
+        };
+        if stripe.chunks[..self.data_shards]
+            .iter()
+            .any(Option::is_none)
+        {
+            self.codec
+                .reconstruct_data(&mut stripe.chunks)
+                .map_err(|e| DatenLordError::Io {
+                    context: vec![format!(
+                        "failed to reconstruct stripe {n} of inode {ino}: {e}"
+                    )],
+                    source: None,
+                })?;
+        }
+        let data = stripe.chunks[..self.data_shards]
+            .iter()
+            .flatten()
+            .flatten()
+            .copied()
+            .collect();
+        Ok(Some((stripe.version, data)))
+    }
+
+    /// Encode `data`, a whole stripe, and write it as version `version` of
+    /// stripe `n` of `ino`, failing unless K chunks were written
+    fn write_stripe(&self, ino: INum, n: u64, version: u64, data: &[u8]) -> DatenLordResult<()> {
+        let mut chunks = data
+            .chunks(self.chunk_size)
+            .map(<[u8]>::to_vec)
+            .chain((self.data_shards..self.paths.len()).map(|_| vec![0; self.chunk_size]))
+            .collect::<Vec<_>>();
+        self.codec
+            .encode(&mut chunks)
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to encode stripe {n} of inode {ino}: {e}")],
+            })?;
+        let mut errors = Vec::new();
+        for (j, chunk) in chunks.iter().enumerate() {
+            if let Err(e) = self.store_chunk(j, ino, n, version, chunk) {
+                warn!("failed to write chunk {j} of stripe {n} of inode {ino}: {e}");
+                errors.push(format!("{:?}: {e}", self.paths[j]));
+            }
+        }
+        if chunks.len() - errors.len() < self.data_shards {
+            errors.insert(0, format!("failed to write stripe {n} of inode {ino}"));
+            return Err(DatenLordError::Io { context: errors, source: None });
+        }
+        Ok(())
+    }
+
+    /// Read `buf.len()` bytes of `ino` from `offset`, holes reading as zeros
+    fn read(&self, ino: INum, offset: u64, buf: &mut [u8]) -> DatenLordResult<()> {
+        let stripe_size = self.stripe_size();
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let (n, start) = (position / stripe_size, (position % stripe_size) as usize);
+            let len = (stripe_size as usize - start).min(buf.len() - done);
+            let out = &mut buf[done..done + len];
+            match self.read_stripe(ino, n)? {
+                Some((_, data)) => out.copy_from_slice(&data[start..start + len]),
+                None => out.fill(0),
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Write `data` to `ino` at `offset`, rewriting the stripes it touches
+    fn write(&self, ino: INum, offset: u64, data: &[u8]) -> DatenLordResult<()> {
+        let stripe_size = self.stripe_size();
+        let mut done = 0;
+        while done < data.len() {
+            let position = offset + done as u64;
+            let (n, start) = (position / stripe_size, (position % stripe_size) as usize);
+            let len = (stripe_size as usize - start).min(data.len() - done);
+            let (version, mut stripe) = self
+                .read_stripe(ino, n)?
+                .unwrap_or_else(|| (0, vec![0; stripe_size as usize]));
+            stripe[start..start + len].copy_from_slice(&data[done..done + len]);
+            self.write_stripe(ino, n, version + 1, &stripe)?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Drop the data of `ino` past `size`
+    fn truncate(&self, ino: INum, size: u64) -> DatenLordResult<()> {
+        let stripe_size = self.stripe_size();
+        let kept = size.div_ceil(stripe_size);
+        for n in self.stripes(ino).into_iter().filter(|&n| n >= kept) {
+            self.remove_stripe(ino, n);
+        }
+        let end = (size % stripe_size) as usize;
+        if end != 0 {
+            let n = size / stripe_size;
+            if let Some((version, mut stripe)) = self.read_stripe(ino, n)? {
+                stripe[end..].fill(0);
+                self.write_stripe(ino, n, version + 1, &stripe)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the chunks of stripe `n` of `ino`
+    fn remove_stripe(&self, ino: INum, n: u64) {
+        for j in 0..self.paths.len() {
+            let path = self.inode_dir(j, ino).join(n.to_string());
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    warn!("failed to remove chunk {path:?}: {e}");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The striped inodes, those of paths failing to list left out
+    fn inodes(&self) -> Vec<INum> {
+        let mut inodes = Vec::new();
+        for path in &self.paths {
+            match fs::read_dir(path) {
+                Ok(entries) => inodes.extend(
+                    entries
+                        .flatten()
+                        .filter_map(|entry| entry.file_name().to_str()?.parse::<INum>().ok()),
+                ),
+                Err(e) => warn!("failed to list stripe path {path:?}: {e}"),
+            }
+        }
+        inodes.sort_unstable();
+        inodes.dedup();
+        inodes
+    }
+
+    /// The bytes the chunks of `ino` take, only those of stripe `n` if
+    /// given, and when they last changed
+    fn usage(&self, ino: INum, n: Option<u64>) -> (u64, Option<SystemTime>) {
+        let (mut bytes, mut changed) = (0, None);
+        for j in 0..self.paths.len() {
+            let dir = self.inode_dir(j, ino);
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            let dir_changed = fs::metadata(&dir).and_then(|metadata| metadata.modified()).ok();
+            changed = changed.max(dir_changed);
+            for entry in entries.flatten() {
+                if n.is_some_and(|n| entry.file_name().to_str() != Some(&n.to_string())) {
+                    continue;
+                }
+                if let Ok(metadata) = entry.metadata() {
+                    bytes += metadata.len();
+                    changed = changed.max(metadata.modified().ok());
+                }
+            }
+        }
+        (bytes, changed)
+    }
+
+    /// Sync the chunks of `ino` to their devices
+    fn sync(&self, ino: INum) -> DatenLordResult<()> {
+        for j in 0..self.paths.len() {
+            let dir = self.inode_dir(j, ino);
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                fs::File::open(entry.path())
+                    .and_then(|file| file.sync_all())
+                    .map_err(|e| DatenLordError::Io {
+                        context: vec![format!("failed to sync chunk {:?}: {e}", entry.path())],
+                        source: None,
+                    })?;
+            }
+            fs::File::open(&dir)
+                .and_then(|file| file.sync_all())
+                .map_err(|e| DatenLordError::Io {
+                    context: vec![format!("failed to sync stripes {dir:?}: {e}")],
+                    source: None,
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Write the chunks of stripe `n` of `ino` that are not at its latest
+    /// version again, returning how many
+    fn repair(&self, ino: INum, n: u64) -> DatenLordResult<u64> {
+        let Some(mut stripe) = self.load_stripe(ino, n)? else {
+            return Ok(0);
+        };
+        let stale = stripe
+            .chunks
+            .iter()
+            .enumerate()
+            .filter_map(|(j, chunk)| chunk.is_none().then_some(j))
+            .collect::<Vec<_>>();
+        if stale.is_empty() {
+            return Ok(0);
+        }
+        self.codec
+            .reconstruct(&mut stripe.chunks)
+            .map_err(|e| DatenLordError::Io {
+                context: vec![format!(
+                    "failed to reconstruct stripe {n} of inode {ino}: {e}"
+                )],
+                source: None,
+            })?;
+        for &j in &stale {
+            let chunk = stripe.chunks[j].as_deref().unwrap_or_default();
+            self.store_chunk(j, ino, n, stripe.version, chunk)
+                .map_err(|e| DatenLordError::Io {
+                    context: vec![format!(
+                        "failed to rewrite chunk {j} of stripe {n} of inode {ino}: {e}"
+                    )],
+                    source: None,
+                })?;
+        }
+        Ok(stale.len() as u64)
+    }
+}
+
+/// Make the directory `path`
+pub(super) fn create_dir(path: &Path) -> io::Result<()> {
+    nix::sys::stat::mkdirat(None, path, Mode::from_bits_truncate(0o755))?;
+    Ok(())
+}
+
+/// A `VirtualFs` keeping the data of its files erasure coded across the
+/// paths of its `StripingConfig`, and the rest in the wrapped filesystem
+#[derive(Debug)]
+pub struct StripedBackend<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// The striping, `None` without paths
+    layout: Option<Layout>,
+}
+
+impl<F: VirtualFs> StripedBackend<F> {
+    /// Stripe the data of `inner` as `config` says
+    pub fn new(inner: F, config: &StripingConfig) -> DatenLordResult<Self> {
+        if config.paths.is_empty() {
+            return Ok(Self {
+                inner,
+                layout: None,
+            });
+        }
+        let invalid = |reason: String| DatenLordError::InvalidArgument {
+            context: vec![reason],
+        };
+        if config.parity_shards == 0 || config.parity_shards >= config.paths.len() {
+            return Err(invalid(format!(
+                "{} parity shards need between 1 and {} paths besides them",
+                config.parity_shards,
+                config.paths.len().saturating_sub(1)
+            )));
+        }
+        if config.chunk_size == 0 {
+            return Err(invalid("chunk size 0".to_owned()));
+        }
+        let data_shards = config.paths.len() - config.parity_shards;
+        let codec = ReedSolomon::new(data_shards, config.parity_shards).map_err(|e| {
+            invalid(format!(
+                "invalid striping of {data_shards}+{}: {e}",
+                config.parity_shards
+            ))
+        })?;
+        let mut paths = Vec::with_capacity(config.paths.len());
+        for uri in &config.paths {
+            let path = migrate::backend_root(uri)?;
+            if paths.contains(&path) {
+                return Err(invalid(format!("path {uri} is given twice")));
+            }
+            match create_dir(&path) {
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                    return Err(DatenLordError::Io {
+                        context: vec![format!("failed to create stripe path {path:?}: {e}")],
+                        source: None,
+                    });
+                }
+                _ => {}
+            }
+            paths.push(path);
+        }
+        Ok(Self {
+            inner,
+            layout: Some(Layout {
+                paths,
+                data_shards,
+                chunk_size: config.chunk_size,
+                codec,
+                locks: (0..LOCK_COUNT).map(|_| Mutex::new(())).collect(),
+            }),
+        })
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Bring every chunk of every striped inode to the latest version of
+    /// its stripe, e.g. after replacing a lost path with an empty one
+    pub async fn rebuild(&self) -> DatenLordResult<RebuildReport> {
+        let mut report = RebuildReport::default();
+        let Some(ref layout) = self.layout else {
+            return Ok(report);
+        };
+        for (j, path) in layout.paths.iter().enumerate() {
+            match create_dir(path) {
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                    return Err(DatenLordError::Io {
+                        context: vec![format!("failed to create stripe path {j} {path:?}: {e}")],
+                        source: None,
+                    });
+                }
+                _ => {}
+            }
+        }
+        for ino in layout.inodes() {
+            let _guard = layout.lock(ino).await;
+            report.inodes += 1;
+            for j in 0..layout.paths.len() {
+                match create_dir(&layout.inode_dir(j, ino)) {
+                    Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+                        warn!(
+                            "failed to restripe inode {ino} to {:?}: {e}",
+                            layout.paths[j]
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            for n in layout.stripes(ino) {
+                report.stripes += 1;
+                match layout.repair(ino, n) {
+                    Ok(repaired) => report.repaired += repaired,
+                    Err(e) => {
+                        warn!("failed to repair stripe {n} of inode {ino}: {e}");
+                        report.lost.push((ino, n));
+                    }
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// The layout if `ino` is striped
+    fn striped(&self, ino: INum) -> Option<&Layout> {
+        self.layout.as_ref().filter(|layout| layout.is_striped(ino))
+    }
+
+    /// Make `ino` striped if the wrapped filesystem holds no data of it
+    async fn reset_if_empty(&self, ctx: &RequestContext, ino: INum) -> DatenLordResult<()> {
+        let Some(ref layout) = self.layout else {
+            return Ok(());
+        };
+        let _guard = layout.lock(ino).await;
+        let (_, attr) = self.inner.getattr(ctx, ino).await?;
+        if attr.kind == SFlag::S_IFREG && attr.size == 0 {
+            layout.reset(ino)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the stripes of the file `attr` describes if it was its last
+    /// link
+    fn removed(&self, attr: &FileAttr) {
+        if let Some(ref layout) = self.layout {
+            if attr.kind == SFlag::S_IFREG && attr.nlink <= 1 {
+                layout.remove(attr.ino);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> Sweep for StripedBackend<F> {
+    async fn sweep(
+        &self,
+        ctx: &RequestContext,
+        reachable: &HashSet<INum>,
+        grace: Duration,
+        dry_run: bool,
+    ) -> DatenLordResult<GcReport> {
+        let mut report = GcReport::default();
+        let Some(ref layout) = self.layout else {
+            return Ok(report);
+        };
+        let recent = |changed: Option<SystemTime>| {
+            changed.is_some_and(|changed| changed.elapsed().unwrap_or_default() < grace)
+        };
+        for ino in layout.inodes() {
+            let _guard = layout.lock(ino).await;
+            if !reachable.contains(&ino) {
+                let (bytes, changed) = layout.usage(ino, None);
+                if !recent(changed) {
+                    report.found(format!("stripes of inode {ino}"), bytes, dry_run);
+                    if !dry_run {
+                        layout.remove(ino);
+                    }
+                }
+                continue;
+            }
+            // Gone since it was marked, or not striped any more
+            let Ok((_, attr)) = self.inner.getattr(ctx, ino).await else {
+                continue;
+            };
+            let kept = attr.size.div_ceil(layout.stripe_size());
+            for n in layout.stripes(ino).into_iter().filter(|&n| n >= kept) {
+                let (bytes, _) = layout.usage(ino, Some(n));
+                report.found(format!("stripe {n} of inode {ino}"), bytes, dry_run);
+                if !dry_run {
+                    layout.remove_stripe(ino, n);
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// The context the sizes and times of striped files are kept up with,
+/// those being changes the caller was allowed to make
+pub(super) fn owner_context(ctx: &RequestContext) -> RequestContext {
+    RequestContext {
+        uid: 0,
+        gid: 0,
+        ..*ctx
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for StripedBackend<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        let inner = self.inner.block_layout();
+        match self.layout {
+            Some(ref layout) => BlockLayout {
+                block_size: layout.chunk_size as u64,
+                stripe_size: layout.stripe_size(),
+                ..inner
+            },
+            None => inner,
+        }
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.lookup(ctx, parent, name).await
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.inner.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let Some(size) = param.size else {
+            return self.inner.setattr(ctx, ino, param).await;
+        };
+        let Some(layout) = self.striped(ino) else {
+            let result = self.inner.setattr(ctx, ino, param).await?;
+            if size == 0 {
+                self.reset_if_empty(ctx, ino).await?;
+            }
+            return Ok(result);
+        };
+        let _guard = layout.lock(ino).await;
+        let result = self.inner.setattr(ctx, ino, param).await?;
+        layout.truncate(ino, size)?;
+        Ok(result)
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let result = self.inner.mknod(ctx, param).await?;
+        self.reset_if_empty(ctx, result.1.ino).await?;
+        Ok(result)
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mkdir(ctx, param).await
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let removed = match self.layout {
+            Some(_) => self.inner.lookup(ctx, parent, name).await.ok(),
+            None => None,
+        };
+        self.inner.unlink(ctx, parent, name).await?;
+        if let Some((_, ref attr, _)) = removed {
+            self.removed(attr);
+        }
+        Ok(())
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        self.inner.rmdir(ctx, parent, dir_name).await
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.symlink(ctx, parent, name, target_path).await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let exchange = param.flags & RenameFlags::RENAME_EXCHANGE.bits() != 0;
+        // The file a rename replaces loses a link
+        let replaced = if self.layout.is_some() && !exchange {
+            let source = self
+                .inner
+                .lookup(ctx, param.old_parent, &param.old_name)
+                .await;
+            let target = self
+                .inner
+                .lookup(ctx, param.new_parent, &param.new_name)
+                .await;
+            match (source, target) {
+                (Ok((_, source, _)), Ok((_, target, _))) if source.ino != target.ino => {
+                    Some(target)
+                }
+                _ => None,
+            }
+        } else {
+            None
+        };
+        self.inner.rename(ctx, param).await?;
+        if let Some(ref attr) = replaced {
+            self.removed(attr);
+        }
+        Ok(())
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.link(ctx, newparent, newname).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let fh = self.inner.open(ctx, ino, flags).await?;
+        if flags as i32 & libc::O_TRUNC != 0 {
+            if let Err(e) = self.reset_if_empty(ctx, ino).await {
+                let _ = self.inner.release(ctx, ino, fh, flags, 0, false).await;
+                return Err(e);
+            }
+        }
+        Ok(fh)
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        let Some(layout) = self.striped(ino) else {
+            return self.inner.read(ctx, ino, fh, offset, size, buf).await;
+        };
+        let (_, attr) = self.inner.getattr(ctx, ino).await?;
+        let len = attr
+            .size
+            .saturating_sub(offset)
+            .min(buf.len().min(size as usize) as u64) as usize;
+        layout.read(ino, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        let Some(layout) = self.striped(ino) else {
+            return self.inner.write(ctx, ino, fh, offset, data, flags).await;
+        };
+        let offset = u64::try_from(offset).map_err(|_| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid write offset={offset}")],
+        })?;
+        let _guard = layout.lock(ino).await;
+        let (_, attr) = self.inner.getattr(ctx, ino).await?;
+        layout.write(ino, offset, data)?;
+        let end = offset + data.len() as u64;
+        let param = SetAttrParam {
+            fh: Some(fh),
+            size: (end > attr.size).then_some(end),
+            m_time: Some(SystemTime::now()),
+            ..SetAttrParam::default()
+        };
+        self.inner.setattr(&owner_context(ctx), ino, param).await?;
+        Ok(())
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        if let Some(layout) = self.striped(ino) {
+            layout.sync(ino)?;
+        }
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        // The chunks of a striped file hold its holes as zeros
+        if self.striped(ino).is_some() {
+            let (_, attr) = self.inner.getattr(ctx, ino).await?;
+            return Ok(seek_extents(&[(0, attr.size)], attr.size, offset, whence));
+        }
+        self.inner.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        // Striped data never reaches the inner files, copy it block by block
+        if self.striped(param.ino_in).is_some() || self.striped(param.ino_out).is_some() {
+            return copy_range_through(self, ctx, param).await;
+        }
+        self.inner.copy_file_range(ctx, param).await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.inner.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.inner.readdirplus(ctx, ino, fh, offset).await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .setxattr(ctx, ino, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .create(ctx, ino, parent, name, mode, flags)
+            .await?;
+        if self.layout.is_some() {
+            let (_, attr, _) = self.inner.lookup(ctx, parent, name).await?;
+            self.reset_if_empty(ctx, attr.ino).await?;
+        }
+        Ok(())
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
@@ -0,0 +1,277 @@
+//! Per-namespace superblock recording the on-disk format version and the
+//! optional features a namespace uses
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde_derive::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+/// The name of the superblock file in the namespace root, hidden from listings
+pub const SUPERBLOCK_NAME: &str = ".datenlord_fs_info";
+
+/// Optional features a namespace can be created with
+///
+/// Once recorded in the superblock, a feature changes how data is laid out,
+/// so a build not supporting it must refuse to open the namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// File data is stored compressed
+    Compression,
+    /// File data is stored encrypted
+    Encryption,
+    /// Overwritten file contents are kept as versions
+    Versioning,
+    /// File data is stored as deduplicated content-addressed blocks
+    Dedup,
+    /// Small files are packed into segment files
+    Packing,
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Self::Compression => "compression",
+            Self::Encryption => "encryption",
+            Self::Versioning => "versioning",
+            Self::Dedup => "dedup",
+            Self::Packing => "packing",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Feature {
+    type Err = DatenLordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compression" => Ok(Self::Compression),
+            "encryption" => Ok(Self::Encryption),
+            "versioning" => Ok(Self::Versioning),
+            "dedup" => Ok(Self::Dedup),
+            "packing" => Ok(Self::Packing),
+            _ => Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "unknown feature={s}, expect one of compression, encryption, \
+                     versioning, dedup, packing"
+                )],
+            }),
+        }
+    }
+}
+
+/// The features this build knows how to read and write
+pub const SUPPORTED_FEATURES: &[Feature] = &[Feature::Dedup, Feature::Packing];
+
+/// The on-disk format version written by this build
+pub const FORMAT_VERSION: u32 = 2;
+
+/// A step upgrading a namespace from one format version to the next
+type UpgradeStep = fn(&Path, &mut Superblock) -> DatenLordResult<()>;
+
+/// Upgrade steps, the step at index `i` upgrades version `i + 1` to `i + 2`
+const UPGRADE_STEPS: &[UpgradeStep] = &[upgrade_v1_to_v2];
+
+/// Version 1 superblocks predate the version field, nothing but the
+/// superblock itself needs rewriting
+fn upgrade_v1_to_v2(_root: &Path, _superblock: &mut Superblock) -> DatenLordResult<()> {
+    Ok(())
+}
+
+/// The version of superblocks written before the version field existed
+fn legacy_format_version() -> u32 {
+    1
+}
+
+/// The persisted superblock of a namespace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Superblock {
+    /// The on-disk format version of the namespace
+    #[serde(default = "legacy_format_version")]
+    pub format_version: u32,
+    /// The optional features the namespace data depends on
+    pub features: BTreeSet<Feature>,
+}
+
+impl Default for Superblock {
+    fn default() -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            features: BTreeSet::new(),
+        }
+    }
+}
+
+impl Superblock {
+    /// The superblock path of the namespace rooted at `root`
+    pub fn path(root: &Path) -> PathBuf {
+        root.join(SUPERBLOCK_NAME)
+    }
+
+    /// Load the superblock of `root`, `None` if the namespace has none yet
+    pub fn load(root: &Path) -> DatenLordResult<Option<Self>> {
+        let path = Self::path(root);
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(DatenLordError::Io {
+                    context: vec![format!("failed to read superblock {path:?}: {e}")],
+                    source: None,
+                })
+            }
+        };
+        serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("corrupted superblock {path:?}: {e}")],
+            })
+    }
+
+    /// Persist the superblock of `root`, atomically replacing the old one
+    pub fn store(&self, root: &Path) -> DatenLordResult<()> {
+        let path = Self::path(root);
+        let tmp_path = root.join(format!("{SUPERBLOCK_NAME}.tmp"));
+        let content = serde_json::to_vec_pretty(self).map_err(|e| DatenLordError::Internal {
+            context: vec![format!("failed to serialize superblock: {e}")],
+        })?;
+        fs::write(&tmp_path, content)
+            .and_then(|()| fs::File::open(&tmp_path)?.sync_all())
+            .and_then(|()| fs::rename(&tmp_path, &path))
+            .map_err(|e| DatenLordError::Io {
+                context: vec![format!("failed to write superblock {path:?}: {e}")],
+                source: None,
+            })
+    }
+
+    /// Fail if the namespace has another format version or uses a feature
+    /// this build does not support
+    pub fn check_compatible(&self) -> DatenLordResult<()> {
+        if self.format_version > FORMAT_VERSION {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "namespace format version {} is newer than {FORMAT_VERSION} supported by this build",
+                    self.format_version
+                )],
+            });
+        }
+        if self.format_version < FORMAT_VERSION {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "namespace format version {} is older than {FORMAT_VERSION}, \
+                     upgrade it with `datenlord-cli upgrade` first",
+                    self.format_version
+                )],
+            });
+        }
+        let unsupported: Vec<String> = self
+            .features
+            .iter()
+            .filter(|feature| !SUPPORTED_FEATURES.contains(feature))
+            .map(ToString::to_string)
+            .collect();
+        if unsupported.is_empty() {
+            Ok(())
+        } else {
+            Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "namespace requires features unsupported by this build: {}",
+                    unsupported.join(", ")
+                )],
+            })
+        }
+    }
+
+    /// Open the superblock of `root`, creating it with `features` for a new namespace
+    ///
+    /// The features of an existing namespace come from its superblock and
+    /// `features` is ignored, use `enable_feature` to add more.
+    pub fn open_or_create(root: &Path, features: &[Feature]) -> DatenLordResult<Self> {
+        if let Some(superblock) = Self::load(root)? {
+            superblock.check_compatible()?;
+            return Ok(superblock);
+        }
+        let superblock = Self {
+            features: features.iter().copied().collect(),
+            ..Self::default()
+        };
+        superblock.check_compatible()?;
+        superblock.store(root)?;
+        Ok(superblock)
+    }
+
+    /// Enable `feature` on the existing namespace rooted at `root`
+    ///
+    /// This is the migration entry point: the existing data is converted to
+    /// the layout `feature` requires before the superblock records it, so an
+    /// interrupted migration leaves the namespace readable without it.
+    pub fn enable_feature(&mut self, root: &Path, feature: Feature) -> DatenLordResult<()> {
+        if self.features.contains(&feature) {
+            return Ok(());
+        }
+        if !SUPPORTED_FEATURES.contains(&feature) {
+            return Err(DatenLordError::Unimplemented {
+                context: vec![format!("feature {feature} is not supported by this build")],
+                source: None,
+            });
+        }
+        migrate(root, feature)?;
+        self.features.insert(feature);
+        self.store(root)
+    }
+
+    /// Upgrade the namespace rooted at `root` to `FORMAT_VERSION`
+    ///
+    /// The upgrade runs one version step at a time. Before each step the
+    /// current superblock is backed up next to it as
+    /// `<SUPERBLOCK_NAME>.v<version>.bak`, and after each step the superblock
+    /// is stored with the bumped version, so an interrupted upgrade resumes
+    /// from the last completed step. Returns the version upgraded from.
+    pub fn upgrade(root: &Path) -> DatenLordResult<u32> {
+        let mut superblock = Self::load(root)?.ok_or_else(|| DatenLordError::InvalidArgument {
+            context: vec![format!("no namespace to upgrade under {root:?}")],
+        })?;
+        let from = superblock.format_version;
+        if from == 0 || from > FORMAT_VERSION {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "cannot upgrade namespace format version {from} to {FORMAT_VERSION}"
+                )],
+            });
+        }
+        while superblock.format_version < FORMAT_VERSION {
+            let version = superblock.format_version;
+            let path = Self::path(root);
+            let backup = root.join(format!("{SUPERBLOCK_NAME}.v{version}.bak"));
+            fs::copy(&path, &backup).map_err(|e| DatenLordError::Io {
+                context: vec![format!("failed to back up superblock {path:?} to {backup:?}: {e}")],
+                source: None,
+            })?;
+            let step = UPGRADE_STEPS[version as usize - 1];
+            step(root, &mut superblock)?;
+            superblock.format_version = version + 1;
+            superblock.store(root)?;
+            info!("upgraded namespace under {root:?} from format version {version} to {}", version + 1);
+        }
+        Ok(from)
+    }
+}
+
+/// Convert the existing data under `root` to the layout required by `feature`
+fn migrate(_root: &Path, feature: Feature) -> DatenLordResult<()> {
+    match feature {
+        // Existing files keep their data until truncated or replaced
+        Feature::Dedup | Feature::Packing => Ok(()),
+        _ => Err(DatenLordError::Unimplemented {
+            context: vec![format!("migration to enable {feature} unimplemented")],
+            source: None,
+        }),
+    }
+}
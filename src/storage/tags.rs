@@ -0,0 +1,190 @@
+//! Key/value tags on files, kept as extended attributes
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use nix::errno::Errno;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::RequestContext;
+use super::virtualfs::{INum, VirtualFs};
+use super::xattr;
+
+/// The prefix of the extended attributes holding tags, followed by the key
+pub const TAG_XATTR_PREFIX: &str = "user.datenlord.tag.";
+
+/// The tags of a file, by key
+pub type Tags = BTreeMap<String, String>;
+
+/// The key of the tag an extended attribute holds, `None` for other attributes
+#[must_use]
+pub fn tag_key(xattr_name: &str) -> Option<&str> {
+    xattr_name.strip_prefix(TAG_XATTR_PREFIX)
+}
+
+/// The extended attribute holding the tag `key`
+fn xattr_name(key: &str) -> String {
+    format!("{TAG_XATTR_PREFIX}{key}")
+}
+
+/// Check that `key` can name a tag and appear in a `TagFilter`
+pub fn check_key(key: &str) -> DatenLordResult<()> {
+    if key.is_empty() || key.contains(['=', ',', '\0']) {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!(
+                "invalid tag key {key:?}, keys are non-empty without '=' or ','"
+            )],
+        });
+    }
+    Ok(())
+}
+
+/// The tags of the file `ino`, none if `fs` has no extended attributes
+pub async fn get_tags<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+) -> DatenLordResult<Tags> {
+    let names = match fs.listxattr(ctx, ino).await {
+        Ok(names) => names,
+        Err(DatenLordError::Unimplemented { .. }) => return Ok(Tags::new()),
+        Err(e) => return Err(e),
+    };
+    let mut tags = Tags::new();
+    for name in names {
+        if let Some(key) = tag_key(&name) {
+            let value = fs.getxattr(ctx, ino, &name).await?;
+            tags.insert(key.to_owned(), String::from_utf8_lossy(&value).into_owned());
+        }
+    }
+    Ok(tags)
+}
+
+/// Tag the file `ino` with `key`=`value`, replacing the former value
+pub async fn set_tag<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+    key: &str,
+    value: &str,
+) -> DatenLordResult<()> {
+    check_key(key)?;
+    fs.setxattr(ctx, ino, &xattr_name(key), value.as_bytes(), 0, 0).await
+}
+
+/// Remove the tag `key` from the file `ino`
+pub async fn remove_tag<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+    key: &str,
+) -> DatenLordResult<()> {
+    check_key(key)?;
+    fs.removexattr(ctx, ino, &xattr_name(key)).await
+}
+
+/// Give the file `dst_ino` of `dst` the tags of the file `src_ino` of `src`
+pub async fn copy_tags<S: VirtualFs + ?Sized, D: VirtualFs + ?Sized>(
+    src: &S,
+    dst: &D,
+    ctx: &RequestContext,
+    src_ino: INum,
+    dst_ino: INum,
+) -> DatenLordResult<()> {
+    for (key, value) in get_tags(src, ctx, src_ino).await? {
+        set_tag(dst, ctx, dst_ino, &key, &value).await?;
+    }
+    Ok(())
+}
+
+/// The tags of the local file `path`, none if its filesystem has no user
+/// extended attributes
+pub fn read_local_tags(path: &Path) -> DatenLordResult<Tags> {
+    let names = match xattr::list(path) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(Errno::EOPNOTSUPP as i32) => return Ok(Tags::new()),
+        Err(e) => {
+            return Err(DatenLordError::Io {
+                context: vec![format!("failed to list xattrs of {path:?}: {e}")],
+                source: None,
+            })
+        }
+    };
+    let mut tags = Tags::new();
+    for name in names {
+        let Some(key) = tag_key(&name) else {
+            continue;
+        };
+        let value = xattr::get(path, &name).map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to get xattr {name} of {path:?}: {e}")],
+            source: None,
+        })?;
+        if let Some(value) = value {
+            tags.insert(key.to_owned(), String::from_utf8_lossy(&value).into_owned());
+        }
+    }
+    Ok(tags)
+}
+
+/// Tag the local file `path` with `tags`
+pub fn write_local_tags(path: &Path, tags: &Tags) -> DatenLordResult<()> {
+    for (key, value) in tags {
+        check_key(key)?;
+        xattr::set(path, &xattr_name(key), value.as_bytes(), 0).map_err(|e| DatenLordError::Io {
+            context: vec![format!("failed to tag {path:?} with {key}: {e}")],
+            source: None,
+        })?;
+    }
+    Ok(())
+}
+
+/// A conjunction of tag conditions, such as `team=data,cold`
+///
+/// A `key=value` term matches files tagged `key` with exactly `value`, and a
+/// bare `key` matches files tagged `key` with any value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    /// The keys required, with their values if constrained
+    terms: Vec<(String, Option<String>)>,
+}
+
+impl TagFilter {
+    /// The keys required, with their values if constrained
+    #[must_use]
+    pub fn terms(&self) -> &[(String, Option<String>)] {
+        &self.terms
+    }
+
+    /// Whether a file with `tags` matches every term
+    #[must_use]
+    pub fn matches(&self, tags: &Tags) -> bool {
+        self.terms.iter().all(|(key, value)| match (tags.get(key), value) {
+            (Some(tagged), Some(value)) => tagged == value,
+            (Some(_), None) => true,
+            (None, _) => false,
+        })
+    }
+}
+
+impl FromStr for TagFilter {
+    type Err = DatenLordError;
+
+    fn from_str(filter: &str) -> DatenLordResult<Self> {
+        let mut terms = Vec::new();
+        for term in filter.split(',').map(str::trim).filter(|term| !term.is_empty()) {
+            let (key, value) = match term.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(value.trim().to_owned())),
+                None => (term, None),
+            };
+            check_key(key)?;
+            terms.push((key.to_owned(), value));
+        }
+        if terms.is_empty() {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("empty tag filter {filter:?}")],
+            });
+        }
+        Ok(Self { terms })
+    }
+}
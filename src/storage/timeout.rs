@@ -0,0 +1,495 @@
+//! Middleware bounding every `VirtualFs` call with a deadline
+use std::ffi::OsStr;
+use std::future::Future;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam, RenameParam,
+    RequestContext, SeekWhence, SetAttrParam, StatFsParam,
+};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+tokio::task_local! {
+    /// The deadline of the operation the calls made in the current scope belong to
+    static OP_DEADLINE: Instant;
+}
+
+/// Run `fut` with every `TimeoutFs` call it makes failing once `deadline`
+/// passed, instead of being bounded by the default timeout
+///
+/// The deadline is shared by all the calls, including retries, so a caller
+/// giving up on the whole operation does not leave backend requests running.
+/// A nested scope never extends the deadline of the enclosing one.
+pub async fn with_deadline<F: Future>(deadline: Instant, fut: F) -> F::Output {
+    let deadline = self::deadline().map_or(deadline, |outer| outer.min(deadline));
+    OP_DEADLINE.scope(deadline, fut).await
+}
+
+/// Run `fut` with a deadline `timeout` from now, see `with_deadline`
+pub async fn with_timeout<F: Future>(timeout: Duration, fut: F) -> F::Output {
+    with_deadline(Instant::now() + timeout, fut).await
+}
+
+/// The deadline of the innermost `with_deadline` scope, `None` outside any scope
+pub fn deadline() -> Option<Instant> {
+    OP_DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// A `VirtualFs` failing calls to the inner filesystem that exceed a deadline
+///
+/// The deadline is the one set by the innermost `with_deadline` scope, or the
+/// default timeout given at construction counted from the start of each call.
+/// A timed out call is dropped at its next await point and returns
+/// `DatenLordError::Timeout`, a call made after the deadline fails without
+/// reaching the inner filesystem. Releasing handles and forgetting inodes
+/// only free resources nobody waits for, so they ignore the deadline.
+#[derive(Debug)]
+pub struct TimeoutFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// The timeout of calls made outside any `with_deadline` scope
+    default_timeout: RwLock<Option<Duration>>,
+}
+
+impl<F: VirtualFs> TimeoutFs<F> {
+    /// Wrap `inner`, bounding calls by `default_timeout` unless overridden
+    pub fn new(inner: F, default_timeout: Option<Duration>) -> Self {
+        Self {
+            inner,
+            default_timeout: RwLock::new(default_timeout),
+        }
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Bound the calls starting from now by `default_timeout` unless
+    /// overridden, `None` leaves them unbounded
+    pub fn set_default_timeout(&self, default_timeout: Option<Duration>) {
+        *self.default_timeout.write().unwrap() = default_timeout;
+    }
+
+    /// Run the inner call `fut` of operation `op` under the effective deadline
+    async fn guard<T>(
+        &self,
+        op: &str,
+        fut: impl Future<Output = DatenLordResult<T>> + Send,
+    ) -> DatenLordResult<T> {
+        match deadline() {
+            Some(deadline) => Self::bound(op, deadline, fut).await,
+            None => self.guard_default(op, fut).await,
+        }
+    }
+
+    /// Run the inner call `fut` of operation `op` under the default timeout only
+    async fn guard_default<T>(
+        &self,
+        op: &str,
+        fut: impl Future<Output = DatenLordResult<T>> + Send,
+    ) -> DatenLordResult<T> {
+        let default_timeout = *self.default_timeout.read().unwrap();
+        match default_timeout {
+            Some(timeout) => Self::bound(op, Instant::now() + timeout, fut).await,
+            None => fut.await,
+        }
+    }
+
+    /// Run `fut` of operation `op`, failing it once `deadline` passed
+    async fn bound<T>(
+        op: &str,
+        deadline: Instant,
+        fut: impl Future<Output = DatenLordResult<T>> + Send,
+    ) -> DatenLordResult<T> {
+        let timed_out = || DatenLordError::Timeout {
+            context: vec![format!("{op} exceeded its deadline")],
+        };
+        // `timeout_at` polls `fut` once even when the deadline already passed
+        if Instant::now() >= deadline {
+            return Err(timed_out());
+        }
+        tokio::time::timeout_at(deadline, fut)
+            .await
+            .unwrap_or_else(|_| Err(timed_out()))
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for TimeoutFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.guard("destroy", self.inner.destroy()).await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.guard("lookup", self.inner.lookup(ctx, parent, name))
+            .await
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.guard("exists", self.inner.exists(ctx, parent, name))
+            .await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.guard("getattr", self.inner.getattr(ctx, ino)).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.guard("setattr", self.inner.setattr(ctx, ino, param))
+            .await
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.guard("readlink", self.inner.readlink(ctx, ino)).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.guard("mknod", self.inner.mknod(ctx, param)).await
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.guard("mkdir", self.inner.mkdir(ctx, param)).await
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.guard("unlink", self.inner.unlink(ctx, parent, name))
+            .await
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        self.guard("rmdir", self.inner.rmdir(ctx, parent, dir_name))
+            .await
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.guard(
+            "symlink",
+            self.inner.symlink(ctx, parent, name, target_path),
+        )
+        .await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        self.guard("rename", self.inner.rename(ctx, param)).await
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.guard("link", self.inner.link(ctx, newparent, newname)).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.guard("open", self.inner.open(ctx, ino, flags)).await
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        self.guard("read", self.inner.read(ctx, ino, fh, offset, size, buf))
+            .await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.guard("write", self.inner.write(ctx, ino, fh, offset, data, flags))
+            .await
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.guard("flush", self.inner.flush(ctx, ino, fh, lock_owner)).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.guard_default(
+            "release",
+            self.inner.release(ctx, ino, fh, flags, lock_owner, flush),
+        )
+        .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.guard("fsync", self.inner.fsync(ctx, ino, fh, datasync)).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        self.guard("lseek", self.inner.lseek(ctx, ino, fh, offset, whence))
+            .await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        self.guard("copy_file_range", self.inner.copy_file_range(ctx, param))
+            .await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.guard("opendir", self.inner.opendir(ctx, ino, flags))
+            .await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.guard("readdir", self.inner.readdir(ctx, ino, fh, offset))
+            .await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.guard("readdirplus", self.inner.readdirplus(ctx, ino, fh, offset))
+            .await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.guard_default("releasedir", self.inner.releasedir(ctx, ino, fh, flags))
+            .await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.guard("fsyncdir", self.inner.fsyncdir(ctx, ino, fh, datasync))
+            .await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.guard("sync_all", self.inner.sync_all(ctx)).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.guard("statfs", self.inner.statfs(ctx, ino)).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.guard(
+            "setxattr",
+            self.inner.setxattr(ctx, ino, name, value, flags, position),
+        )
+        .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.guard("getxattr", self.inner.getxattr(ctx, ino, name))
+            .await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.guard("listxattr", self.inner.listxattr(ctx, ino)).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.guard("removexattr", self.inner.removexattr(ctx, ino, name))
+            .await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.guard("access", self.inner.access(ctx, ino, mask))
+            .await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.guard(
+            "create",
+            self.inner.create(ctx, ino, parent, name, mode, flags),
+        )
+        .await
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.guard("getlk", self.inner.getlk(ctx, ino, lk_param))
+            .await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.guard("setlk", self.inner.setlk(ctx, ino, lk_param, sleep))
+            .await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.guard("bmap", self.inner.bmap(ctx, ino, blocksize, idx))
+            .await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.guard("ioctl", self.inner.ioctl(ctx, ino, cmd, input))
+            .await
+    }
+}
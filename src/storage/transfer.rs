@@ -0,0 +1,226 @@
+//! Copies of local files into a namespace, splitting large files into ranges
+//! copied concurrently, and of files within a namespace
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+
+use serde_derive::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+use crate::common::buffer_pool::BufferPool;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+use crate::common::{DatenLordError, DatenLordResult};
+use crate::storage::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, RequestContext, SetAttrParam, ROOT_ID,
+};
+use crate::storage::timeout;
+use crate::storage::virtualfs::{INum, VirtualFs};
+
+/// Default number of ranges copied at once
+const DEFAULT_THREADS: usize = 4;
+/// The mode of the destinations `copy_file` creates, before the umask
+const FILE_MODE: u32 = 0o666;
+
+/// How the SDKs copy local files into the namespace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CopyConfig {
+    /// The ranges of a file copied at once, 0 or 1 copies sequentially
+    pub threads: usize,
+    /// The size of the ranges in bytes, rounded up to whole blocks of the
+    /// backend, its stripe size when 0; files no larger than one range are
+    /// copied sequentially
+    pub chunk_size: u64,
+}
+
+impl Default for CopyConfig {
+    fn default() -> Self {
+        Self {
+            threads: DEFAULT_THREADS,
+            chunk_size: 0,
+        }
+    }
+}
+
+/// Copy the local file `file` into the file `ino` of `fs` open for writing
+/// as `fh` on behalf of `ctx`, returning the bytes copied
+///
+/// Files larger than `config.chunk_size` are split into ranges of that size,
+/// at most `config.threads` of them copied at once, each by a task of the
+/// current runtime; a multi-threaded runtime is needed for them to run in
+/// parallel. The data goes through buffers of the block size of `fs`, see
+/// `VirtualFs::block_layout`. The deadline of the caller bounds every task.
+pub async fn copy_from_local<F: VirtualFs + 'static>(
+    fs: &Arc<F>,
+    ctx: RequestContext,
+    file: File,
+    ino: INum,
+    fh: u64,
+    config: &CopyConfig,
+    pool: &BufferPool,
+) -> DatenLordResult<u64> {
+    let size = file.metadata().map_err(local_error)?.len();
+    let layout = fs.block_layout();
+    let chunk_size = match config.chunk_size {
+        0 => layout.stripe_size.max(1),
+        chunk_size => layout.round_up_to_block(chunk_size),
+    };
+    let block_len = layout.block_len();
+    if config.threads <= 1 || size <= chunk_size {
+        return copy_range(&**fs, &ctx, &file, ino, fh, 0, u64::MAX, pool, block_len).await;
+    }
+
+    let deadline = timeout::deadline();
+    let file = Arc::new(file);
+    let mut pending = (0..size).step_by(usize::try_from(chunk_size).unwrap_or(usize::MAX));
+    let mut running = JoinSet::new();
+    let mut copied = 0;
+    loop {
+        while running.len() < config.threads {
+            let Some(start) = pending.next() else {
+                break;
+            };
+            // The last range runs to the end of the file, even if it grew
+            let len = if start.saturating_add(chunk_size) >= size {
+                u64::MAX
+            } else {
+                chunk_size
+            };
+            let (fs, file, pool) = (Arc::clone(fs), Arc::clone(&file), pool.clone());
+            running.spawn(async move {
+                let copy = copy_range(&*fs, &ctx, &file, ino, fh, start, len, &pool, block_len);
+                match deadline {
+                    Some(deadline) => timeout::with_deadline(deadline, copy).await,
+                    None => copy.await,
+                }
+            });
+        }
+        let Some(done) = running.join_next().await else {
+            return Ok(copied);
+        };
+        copied += done.map_err(|e| DatenLordError::Internal {
+            context: vec![format!("copy task panicked: {e}")],
+        })??;
+    }
+}
+
+/// Copy the file `src` of `fs` to `dst`, relative to the root, replacing
+/// the file at the destination, on behalf of `ctx`
+///
+/// The copy goes through `VirtualFs::copy_file_range`, so backends able to
+/// share the blocks of both files do, which the result reports as
+/// `reflinked`; the others copy the data.
+pub async fn copy_file<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    src: &OsStr,
+    dst: &OsStr,
+) -> DatenLordResult<CopyRangeResult> {
+    let (_, src_attr, _) = fs.lookup(ctx, ROOT_ID, src).await?;
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: dst.to_owned(),
+        mode: FILE_MODE,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let dst_ino = match fs.mknod(ctx, param).await {
+        Ok((_, attr, _)) => attr.ino,
+        Err(DatenLordError::AlreadyExists { .. }) => fs.lookup(ctx, ROOT_ID, dst).await?.1.ino,
+        Err(e) => return Err(e),
+    };
+    // Truncating the destination would lose the source
+    if dst_ino == src_attr.ino {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("{src:?} and {dst:?} are the same file")],
+        });
+    }
+    let fh_in = fs.open(ctx, src_attr.ino, OFlag::O_RDONLY.bits() as u32).await?;
+    let fh_out = match fs.open(ctx, dst_ino, OFlag::O_WRONLY.bits() as u32).await {
+        Ok(fh) => fh,
+        Err(e) => {
+            let _ = fs.release(ctx, src_attr.ino, fh_in, 0, 0, false).await;
+            return Err(e);
+        }
+    };
+    let copy = async {
+        let truncate = SetAttrParam {
+            fh: Some(fh_out),
+            size: Some(0),
+            ..SetAttrParam::default()
+        };
+        fs.setattr(ctx, dst_ino, truncate).await?;
+        let mut total = CopyRangeResult::default();
+        loop {
+            let param = CopyRangeParam {
+                ino_in: src_attr.ino,
+                fh_in,
+                offset_in: total.copied,
+                ino_out: dst_ino,
+                fh_out,
+                offset_out: total.copied,
+                len: u64::MAX - total.copied,
+            };
+            let copy = fs.copy_file_range(ctx, param).await?;
+            if copy.copied == 0 {
+                return Ok(total);
+            }
+            total.copied += copy.copied;
+            total.reflinked |= copy.reflinked;
+        }
+    };
+    let copied = copy.await;
+    let released = fs.release(ctx, dst_ino, fh_out, 0, 0, true).await;
+    fs.release(ctx, src_attr.ino, fh_in, 0, 0, false).await?;
+    copied.and_then(|copy| released.map(|()| copy))
+}
+
+/// Copy at most `len` bytes of `file` from `start` on, stopping at its end,
+/// into `ino` at the same offsets, `block_len` bytes at a time
+#[allow(clippy::too_many_arguments)]
+async fn copy_range<F: VirtualFs>(
+    fs: &F,
+    ctx: &RequestContext,
+    file: &File,
+    ino: INum,
+    fh: u64,
+    start: u64,
+    len: u64,
+    pool: &BufferPool,
+    block_len: usize,
+) -> DatenLordResult<u64> {
+    let mut buf = pool.acquire(block_len);
+    let mut copied = 0;
+    while copied < len {
+        let offset = start + copied;
+        let want = usize::try_from(len - copied)
+            .unwrap_or(usize::MAX)
+            .min(buf.len());
+        let read = match file.read_at(&mut buf[..want], offset) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(local_error(e)),
+        };
+        let offset = i64::try_from(offset).map_err(|_| DatenLordError::Io {
+            context: vec![format!("offset {offset} out of range")],
+            source: None,
+        })?;
+        fs.write(ctx, ino, fh, offset, &buf[..read], 0).await?;
+        copied += read as u64;
+    }
+    Ok(copied)
+}
+
+/// A failure reading the local file
+fn local_error(err: std::io::Error) -> DatenLordError {
+    DatenLordError::Io {
+        context: vec![format!("failed to read the local file: {err}")],
+        source: None,
+    }
+}
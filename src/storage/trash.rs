@@ -0,0 +1,936 @@
+//! Soft deletion, moving removed entries into a trash they are restored
+//! from or purged later
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use nix::fcntl::{OFlag, RenameFlags};
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use tracing::{debug, info, warn};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{
+    self, CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam, ROOT_ID,
+};
+use super::upload::UPLOADS_DIR;
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// The directory under the root holding the trash, one directory per
+/// removed entry
+pub const TRASH_DIR: &str = ".trash";
+/// The file of a trash directory recording the deletion
+const INFO: &str = "info";
+/// The name of the removed entry in its trash directory
+const ENTRY: &str = "entry";
+/// The mode of the trash and the other directories shared by every user at
+/// the root, writable by everyone like `/tmp`
+const SHARED_DIR_MODE: u32 = 0o1777;
+/// The mode of the directory of a removed entry
+const ENTRY_DIR_MODE: u32 = 0o700;
+/// The mode of the info file of a removed entry
+const INFO_MODE: u32 = 0o600;
+/// The mode of the directories created to restore an entry into
+const RESTORE_DIR_MODE: u32 = 0o777;
+/// Default time removed entries stay in the trash, a week
+const DEFAULT_RETENTION_SECS: u64 = 7 * 24 * 3600;
+/// Default time between two scheduled purges, an hour
+const DEFAULT_PURGE_INTERVAL_SECS: u64 = 3600;
+
+/// Whether removed entries go to the trash and how long they stay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    /// Move the entries `unlink` and `rmdir` remove to the trash, off by
+    /// default
+    pub enabled: bool,
+    /// The time removed entries stay in the trash before the scheduled
+    /// purge removes them, 0 keeps them until purged explicitly
+    pub retention_secs: u64,
+    /// The time between two scheduled purges in seconds
+    pub purge_interval_secs: u64,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_secs: DEFAULT_RETENTION_SECS,
+            purge_interval_secs: DEFAULT_PURGE_INTERVAL_SECS,
+        }
+    }
+}
+
+/// An entry removed to the trash
+#[derive(Debug, Clone)]
+pub struct TrashEntry {
+    /// The name of its directory in the trash
+    pub id: String,
+    /// Where it was removed from, relative to the root
+    pub path: PathBuf,
+    /// When it was removed
+    pub deleted_at: SystemTime,
+    /// Its attributes
+    pub attr: FileAttr,
+}
+
+/// Whether `path`, relative to the root, is in the trash or staged by an
+/// upload, where removals are final
+fn in_trash(path: &Path) -> bool {
+    match path.components().next() {
+        Some(Component::Normal(first)) => first == TRASH_DIR || first == UPLOADS_DIR,
+        _ => false,
+    }
+}
+
+/// A `VirtualFs` moving the entries `unlink` and `rmdir` remove into the
+/// trash instead, when enabled
+///
+/// Each removed entry is renamed, as the caller, into a directory of its own
+/// under `TRASH_DIR` next to a file recording its path and when it was
+/// removed, so the permissions of a removal still apply and the versions of
+/// removed files are kept. Paths are those of the directories looked up or
+/// listed through the layer; entries removed from directories it never saw
+/// are recorded under the root. Removals inside the trash and the staging
+/// directory of uploads are final.
+#[derive(Debug)]
+pub struct TrashFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// Whether removals go to the trash
+    config: TrashConfig,
+    /// The paths of the directories looked up or listed so far, checked
+    /// before use
+    dirs: RwLock<HashMap<INum, PathBuf>>,
+    /// Tells apart the trash directories created within a nanosecond
+    sequence: AtomicU64,
+}
+
+impl<F: VirtualFs> TrashFs<F> {
+    /// Wrap `inner`, moving removed entries to the trash as `config` says
+    pub fn new(inner: F, config: TrashConfig) -> Self {
+        Self {
+            inner,
+            config,
+            dirs: RwLock::default(),
+            sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// The path of the directory `ino` relative to the root, if it was
+    /// looked up or listed through the layer and is still there
+    async fn dir_path(&self, ctx: &RequestContext, ino: INum) -> Option<PathBuf> {
+        if ino == ROOT_ID {
+            return Some(PathBuf::new());
+        }
+        let path = self.dirs.read().unwrap().get(&ino).cloned()?;
+        match self.inner.lookup(ctx, ROOT_ID, path.as_os_str()).await {
+            Ok((_, attr, _)) if attr.ino == ino => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Remember the path of `attr`, the entry `name` in `parent`, if it is
+    /// a directory
+    fn remember(&self, parent: INum, name: &OsStr, attr: &FileAttr) {
+        if !self.config.enabled || attr.kind != SFlag::S_IFDIR {
+            return;
+        }
+        let mut dirs = self.dirs.write().unwrap();
+        let parent_path = if parent == ROOT_ID {
+            PathBuf::new()
+        } else {
+            match dirs.get(&parent) {
+                Some(path) => path.clone(),
+                None => return,
+            }
+        };
+        dirs.insert(attr.ino, parent_path.join(name));
+    }
+
+    /// The path of the entry `name` in `parent`, or of `name` under the root
+    /// if the path of `parent` is unknown
+    async fn entry_path(&self, ctx: &RequestContext, parent: INum, name: &OsStr) -> PathBuf {
+        match self.dir_path(ctx, parent).await {
+            Some(dir) => dir.join(name),
+            None => {
+                warn!("the path of directory inode={parent} is unknown, {name:?} is recorded under the root");
+                PathBuf::from(name)
+            }
+        }
+    }
+
+    /// Move the entry `name` in `parent`, with `attr`, to the trash
+    async fn discard(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        path: &Path,
+    ) -> DatenLordResult<()> {
+        let trash = shared_dir(&self.inner, ctx, TRASH_DIR).await?;
+        let (sec, nsec) = fs_util::to_timespec(SystemTime::now());
+        let deleted_ns = i128::from(sec) * 1_000_000_000 + i128::from(nsec);
+        let id = format!(
+            "{deleted_ns:x}-{:x}-{:x}",
+            std::process::id(),
+            self.sequence.fetch_add(1, Ordering::Relaxed)
+        );
+        let param = CreateParam {
+            parent: trash,
+            name: id.clone().into(),
+            mode: ENTRY_DIR_MODE,
+            rdev: 0,
+            node_type: SFlag::S_IFDIR,
+            link: None,
+        };
+        let (_, dir, _) = self.inner.mkdir(ctx, param).await?;
+        let mut info = format!("{deleted_ns}\n").into_bytes();
+        info.extend_from_slice(path.as_os_str().as_bytes());
+        let moved = async {
+            write_file(&self.inner, ctx, dir.ino, INFO, &info).await?;
+            let rename = RenameParam {
+                old_parent: parent,
+                old_name: name.to_owned(),
+                new_parent: dir.ino,
+                new_name: ENTRY.into(),
+                flags: 0,
+            };
+            self.inner.rename(ctx, rename).await
+        }
+        .await;
+        if let Err(e) = moved {
+            if let Err(remove_err) = remove_all(&self.inner, ctx, trash, OsStr::new(&id)).await {
+                warn!("failed to remove the trash directory {id}: {remove_err}");
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The path of the entry `name` in `parent` if removing it moves it to
+    /// the trash, with its attributes
+    async fn to_discard(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<Option<(PathBuf, FileAttr)>> {
+        if !self.config.enabled {
+            return Ok(None);
+        }
+        let path = self.entry_path(ctx, parent, name).await;
+        if in_trash(&path) {
+            return Ok(None);
+        }
+        let (_, attr, _) = self.inner.lookup(ctx, parent, name).await?;
+        Ok(Some((path, attr)))
+    }
+}
+
+/// The directory `name` under the root of `fs`, such as the trash, created
+/// writable by everyone when missing
+pub(super) async fn shared_dir<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    name: &str,
+) -> DatenLordResult<INum> {
+    if let Ok((_, attr, _)) = fs.lookup(ctx, ROOT_ID, OsStr::new(name)).await {
+        return Ok(attr.ino);
+    }
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: name.into(),
+        mode: SHARED_DIR_MODE,
+        rdev: 0,
+        node_type: SFlag::S_IFDIR,
+        link: None,
+    };
+    match fs.mkdir(ctx, param).await {
+        Ok((_, attr, _)) => {
+            // The umask of the caller does not apply to shared directories
+            let mode = SetAttrParam {
+                mode: Some(SHARED_DIR_MODE),
+                ..SetAttrParam::default()
+            };
+            fs.setattr(ctx, attr.ino, mode).await?;
+            Ok(attr.ino)
+        }
+        Err(DatenLordError::AlreadyExists { .. }) => {
+            Ok(fs.lookup(ctx, ROOT_ID, OsStr::new(name)).await?.1.ino)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Create the file `name` in `dir` holding `content`
+pub(super) async fn write_file<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    dir: INum,
+    name: &str,
+    content: &[u8],
+) -> DatenLordResult<()> {
+    let param = CreateParam {
+        parent: dir,
+        name: name.into(),
+        mode: INFO_MODE,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let (_, attr, _) = fs.mknod(ctx, param).await?;
+    let flags = OFlag::O_WRONLY.bits() as u32;
+    let fh = fs.open(ctx, attr.ino, flags).await?;
+    let written = fs.write(ctx, attr.ino, fh, 0, content, flags).await;
+    fs.release(ctx, attr.ino, fh, flags, 0, true).await?;
+    written
+}
+
+/// The content of the file `ino` with `size` bytes
+pub(super) async fn read_file<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+    size: u64,
+) -> DatenLordResult<Vec<u8>> {
+    let flags = OFlag::O_RDONLY.bits() as u32;
+    let fh = fs.open(ctx, ino, flags).await?;
+    let mut content = vec![0; usize::try_from(size).unwrap_or(usize::MAX)];
+    let mut read = 0;
+    let result = loop {
+        if read == content.len() {
+            break Ok(());
+        }
+        let len = u32::try_from(content.len() - read).unwrap_or(u32::MAX);
+        match fs
+            .read(ctx, ino, fh, read as u64, len, &mut content[read..])
+            .await
+        {
+            Ok(0) => break Ok(()),
+            Ok(n) => read += n,
+            Err(e) => break Err(e),
+        }
+    };
+    fs.release(ctx, ino, fh, flags, 0, false).await?;
+    result?;
+    content.truncate(read);
+    Ok(content)
+}
+
+/// Every entry of the directory `dir` with its attributes
+pub(super) async fn list_dir<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    dir: INum,
+) -> DatenLordResult<Vec<(OsString, FileAttr)>> {
+    let fh = fs.opendir(ctx, dir, 0).await?;
+    let mut entries = Vec::new();
+    let listed = loop {
+        let offset = i64::try_from(entries.len()).unwrap_or(i64::MAX);
+        match fs.readdirplus(ctx, dir, fh, offset).await {
+            Ok(page) if page.is_empty() => break Ok(()),
+            Ok(page) => entries.extend(page.into_iter().map(|(entry, attr, _)| (entry.name, attr))),
+            Err(e) => break Err(e),
+        }
+    };
+    fs.releasedir(ctx, dir, fh, 0).await?;
+    listed?;
+    Ok(entries)
+}
+
+/// Remove the entry `name` in `parent` and everything under it
+pub(super) async fn remove_all<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    parent: INum,
+    name: &OsStr,
+) -> DatenLordResult<()> {
+    // Directories are listed before their children and removed after them
+    let (_, attr, _) = fs.lookup(ctx, parent, name).await?;
+    let mut pending = vec![(parent, name.to_owned(), attr)];
+    let mut removals = Vec::new();
+    while let Some((parent, name, attr)) = pending.pop() {
+        if attr.kind == SFlag::S_IFDIR {
+            for (child, child_attr) in list_dir(fs, ctx, attr.ino).await? {
+                pending.push((attr.ino, child, child_attr));
+            }
+        }
+        removals.push((parent, name, attr.kind));
+    }
+    for (parent, name, kind) in removals.iter().rev() {
+        if *kind == SFlag::S_IFDIR {
+            fs.rmdir(ctx, *parent, name).await?;
+        } else {
+            fs.unlink(ctx, *parent, name).await?;
+        }
+    }
+    Ok(())
+}
+
+/// The entry in the trash directory `dir` named `id`, `None` if it is not
+/// a complete trash directory
+async fn read_entry<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    id: &OsStr,
+    dir: INum,
+) -> DatenLordResult<Option<TrashEntry>> {
+    let (Some(id), Ok((_, info, _)), Ok((_, attr, _))) = (
+        id.to_str(),
+        fs.lookup(ctx, dir, OsStr::new(INFO)).await,
+        fs.lookup(ctx, dir, OsStr::new(ENTRY)).await,
+    ) else {
+        return Ok(None);
+    };
+    let info = read_file(fs, ctx, info.ino, info.size).await?;
+    let Some(newline) = info.iter().position(|&byte| byte == b'\n') else {
+        return Ok(None);
+    };
+    let Some(deleted_ns) = std::str::from_utf8(&info[..newline])
+        .ok()
+        .and_then(|ns| ns.parse::<i128>().ok())
+    else {
+        return Ok(None);
+    };
+    let sec = i64::try_from(deleted_ns.div_euclid(1_000_000_000)).unwrap_or(i64::MAX);
+    let nsec = deleted_ns.rem_euclid(1_000_000_000) as u32;
+    let Some(deleted_at) = fs_util::from_timespec(sec, nsec) else {
+        return Ok(None);
+    };
+    Ok(Some(TrashEntry {
+        id: id.to_owned(),
+        path: PathBuf::from(OsString::from_vec(info[newline + 1..].to_vec())),
+        deleted_at,
+        attr,
+    }))
+}
+
+/// The entries in the trash of `fs` that `ctx` can read, oldest removal
+/// first
+pub async fn list_trash<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+) -> DatenLordResult<Vec<TrashEntry>> {
+    let Ok((_, trash, _)) = fs.lookup(ctx, ROOT_ID, OsStr::new(TRASH_DIR)).await else {
+        return Ok(Vec::new());
+    };
+    let mut entries = Vec::new();
+    for (id, attr) in list_dir(fs, ctx, trash.ino).await? {
+        if attr.kind != SFlag::S_IFDIR {
+            continue;
+        }
+        match read_entry(fs, ctx, &id, attr.ino).await {
+            Ok(Some(entry)) => entries.push(entry),
+            Ok(None) => debug!("skipping the incomplete trash directory {id:?}"),
+            Err(e) => debug!("skipping the trash directory {id:?}: {e}"),
+        }
+    }
+    entries.sort_by(|a, b| {
+        a.deleted_at
+            .cmp(&b.deleted_at)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    Ok(entries)
+}
+
+/// Move the entry last removed from `path` back there, creating its
+/// missing parent directories, returning its attributes
+///
+/// Fails with `DatenLordError::AlreadyExists` when `path` exists again.
+pub async fn restore<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    path: &Path,
+) -> DatenLordResult<FileAttr> {
+    let Some(entry) = list_trash(fs, ctx)
+        .await?
+        .into_iter()
+        .rev()
+        .find(|entry| entry.path == path)
+    else {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("nothing removed from {path:?} is in the trash")],
+        });
+    };
+    let (Some(name), parent) = (path.file_name(), path.parent().unwrap_or(Path::new(""))) else {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("cannot restore to {path:?}")],
+        });
+    };
+    let parent = fs
+        .mkdir_all(ctx, ROOT_ID, parent.as_os_str(), RESTORE_DIR_MODE)
+        .await?;
+    let (_, trash, _) = fs.lookup(ctx, ROOT_ID, OsStr::new(TRASH_DIR)).await?;
+    let (_, dir, _) = fs.lookup(ctx, trash.ino, OsStr::new(&entry.id)).await?;
+    let rename = RenameParam {
+        old_parent: dir.ino,
+        old_name: ENTRY.into(),
+        new_parent: parent.ino,
+        new_name: name.to_owned(),
+        flags: RenameFlags::RENAME_NOREPLACE.bits(),
+    };
+    fs.rename(ctx, rename).await?;
+    if let Err(e) = remove_all(fs, ctx, trash.ino, OsStr::new(&entry.id)).await {
+        warn!("failed to remove the trash directory {}: {e}", entry.id);
+    }
+    Ok(fs.lookup(ctx, parent.ino, name).await?.1)
+}
+
+/// Remove for good the entries in the trash of `fs` removed at least
+/// `older_than` ago, returning how many
+pub async fn purge<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    older_than: Duration,
+) -> DatenLordResult<usize> {
+    let now = SystemTime::now();
+    let entries = list_trash(fs, ctx).await?;
+    let Ok((_, trash, _)) = fs.lookup(ctx, ROOT_ID, OsStr::new(TRASH_DIR)).await else {
+        return Ok(0);
+    };
+    let mut purged = 0;
+    for entry in entries {
+        let age = now.duration_since(entry.deleted_at).unwrap_or_default();
+        if age < older_than {
+            continue;
+        }
+        remove_all(fs, ctx, trash.ino, OsStr::new(&entry.id)).await?;
+        purged += 1;
+    }
+    Ok(purged)
+}
+
+/// The scheduled purge of the trash of a namespace, stopped when dropped
+///
+/// Entries older than `retention_secs` are purged every
+/// `purge_interval_secs` on a thread of its own, the first time right
+/// after the start.
+#[derive(Debug)]
+pub struct PurgeTask {
+    /// Dropped to stop the task
+    _stop: oneshot::Sender<()>,
+}
+
+impl PurgeTask {
+    /// Start purging the trash of `fs` on behalf of `ctx` as `config` says,
+    /// `None` when the trash is off or kept until purged explicitly
+    pub fn start<F: VirtualFs + 'static>(
+        fs: Arc<F>,
+        ctx: RequestContext,
+        config: TrashConfig,
+    ) -> DatenLordResult<Option<Self>> {
+        if !config.enabled || config.retention_secs == 0 {
+            return Ok(None);
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the trash purge runtime: {e}")],
+            })?;
+        let (stop, mut stopped) = oneshot::channel();
+        let retention = Duration::from_secs(config.retention_secs);
+        let interval = Duration::from_secs(config.purge_interval_secs.max(1));
+        std::thread::Builder::new()
+            .name("datenlord-trash".to_owned())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    loop {
+                        tokio::select! {
+                            _ = &mut stopped => return,
+                            purged = purge(fs.as_ref(), &ctx, retention) => match purged {
+                                Ok(0) => {}
+                                Ok(purged) => info!("purged {purged} entries from the trash"),
+                                Err(e) => warn!("trash purge failed: {e}"),
+                            },
+                        }
+                        tokio::select! {
+                            _ = &mut stopped => return,
+                            () = tokio::time::sleep(interval) => {}
+                        }
+                    }
+                });
+            })
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the trash purge thread: {e}")],
+            })?;
+        Ok(Some(Self { _stop: stop }))
+    }
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for TrashFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let looked_up = self.inner.lookup(ctx, parent, name).await?;
+        self.remember(parent, name, &looked_up.1);
+        Ok(looked_up)
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.inner.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.setattr(ctx, ino, param).await
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mknod(ctx, param).await
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        let (parent, name) = (param.parent, param.name.clone());
+        let created = self.inner.mkdir(ctx, param).await?;
+        self.remember(parent, &name, &created.1);
+        Ok(created)
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        match self.to_discard(ctx, parent, name).await? {
+            Some((path, attr)) if attr.kind != SFlag::S_IFDIR => {
+                self.discard(ctx, parent, name, &path).await
+            }
+            _ => self.inner.unlink(ctx, parent, name).await,
+        }
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        // Directories that are not empty fail to be removed as usual
+        match self.to_discard(ctx, parent, dir_name).await? {
+            Some((path, attr))
+                if attr.kind == SFlag::S_IFDIR
+                    && list_dir(&self.inner, ctx, attr.ino).await?.is_empty() =>
+            {
+                self.discard(ctx, parent, dir_name, &path).await?;
+                Ok(Some(attr.ino))
+            }
+            _ => self.inner.rmdir(ctx, parent, dir_name).await,
+        }
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.symlink(ctx, parent, name, target_path).await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        self.inner.rename(ctx, param).await
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.link(ctx, newparent, newname).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.open(ctx, ino, flags).await
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        self.inner.read(ctx, ino, fh, offset, size, buf).await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.write(ctx, ino, fh, offset, data, flags).await
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        self.inner.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        self.inner.copy_file_range(ctx, param).await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.inner.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        let entries = self.inner.readdirplus(ctx, ino, fh, offset).await?;
+        for (entry, attr, _) in &entries {
+            self.remember(ino, &entry.name, attr);
+        }
+        Ok(entries)
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .setxattr(ctx, ino, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.create(ctx, ino, parent, name, mode, flags).await
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
@@ -0,0 +1,542 @@
+//! Multipart uploads of large files, resumable after a crash of the
+//! uploading client
+//!
+//! Each upload is staged in a directory of its own under `UPLOADS_DIR`,
+//! holding a file recording its target path and when it started, and each
+//! part in a file named after its index and the SHA-256 of its data. Parts
+//! are written to a temporary file renamed into place, so the parts listed
+//! are whole and a client resumes by sending those missing. Completing
+//! concatenates the parts in order, checking their checksums, and renames
+//! the result over the target.
+//!
+//! Small files are written in one call with `write_file_atomic`, through a
+//! temporary file renamed over the target the same way.
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::digest::hex;
+use super::fs_util::{
+    self, CreateParam, FileAttr, RenameParam, RequestContext, SetAttrParam, ROOT_ID,
+};
+use super::trash::{list_dir, read_file, remove_all, shared_dir, write_file};
+use super::virtualfs::{INum, VirtualFs};
+
+/// The directory under the root staging the uploads, one directory per
+/// upload
+pub const UPLOADS_DIR: &str = ".datenlord_uploads";
+/// The number of parts an upload takes at most, indexed from 0
+pub const MAX_PARTS: u32 = 100_000;
+/// The file of an upload directory recording its target
+const TARGET: &str = "target";
+/// The file of an upload directory the parts are concatenated into
+const ASSEMBLED: &str = "assembled";
+/// The prefix of the temporary files parts are written to
+const PARTIAL_PREFIX: &str = "partial-";
+/// The prefix of the temporary files of `write_file_atomic`, next to their
+/// target
+const ATOMIC_PREFIX: &str = ".datenlord_atomic-";
+/// The prefix of upload ids
+const ID_PREFIX: &str = "upload-";
+/// The mode of the directory of an upload
+const UPLOAD_DIR_MODE: u32 = 0o700;
+/// The mode of the completed files, less the umask of the caller
+const FILE_MODE: u32 = 0o666;
+
+/// Numbers the ids and temporary files of the process
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// An upload in progress
+#[derive(Debug, Clone)]
+pub struct Upload {
+    /// The id parts are uploaded to
+    pub id: String,
+    /// The file it completes into, relative to the root
+    pub path: PathBuf,
+    /// When it started
+    pub started_at: SystemTime,
+}
+
+/// A part uploaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadPart {
+    /// Its index, its data following the parts of smaller indexes
+    pub index: u32,
+    /// The size of its data
+    pub size: u64,
+    /// The lowercase hex SHA-256 of its data
+    pub checksum: String,
+}
+
+/// The lowercase hex SHA-256 of `data`, the checksum of a part
+#[must_use]
+pub fn checksum(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// A name no other upload or temporary file has, starting with `prefix`
+fn unique_name(prefix: &str) -> String {
+    let (sec, nsec) = fs_util::to_timespec(SystemTime::now());
+    let ns = i128::from(sec) * 1_000_000_000 + i128::from(nsec);
+    format!(
+        "{prefix}{ns:x}-{:x}-{:x}",
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// The file name of part `index` whose data has `checksum`
+fn part_name(index: u32, checksum: &str) -> String {
+    format!("{index:05}.{checksum}")
+}
+
+/// The part stored in the file `name` of `size` bytes, `None` for other
+/// files
+fn parse_part(name: &OsStr, size: u64) -> Option<UploadPart> {
+    let (index, checksum) = name.to_str()?.split_once('.')?;
+    let index = index.parse().ok().filter(|&index| index < MAX_PARTS)?;
+    let is_checksum = checksum.len() == 64
+        && checksum
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'));
+    is_checksum.then(|| UploadPart {
+        index,
+        size,
+        checksum: checksum.to_owned(),
+    })
+}
+
+/// An error for the upload `id` that cannot be found
+fn no_such_upload(id: &str) -> DatenLordError {
+    DatenLordError::InvalidArgument {
+        context: vec![format!("no upload {id:?} is in progress")],
+    }
+}
+
+/// The staging directory of uploads and the directory of upload `id` in it
+async fn upload_dir<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    id: &str,
+) -> DatenLordResult<(INum, INum)> {
+    if !id.starts_with(ID_PREFIX) || id.contains('/') {
+        return Err(no_such_upload(id));
+    }
+    let Ok((_, uploads, _)) = fs.lookup(ctx, ROOT_ID, OsStr::new(UPLOADS_DIR)).await else {
+        return Err(no_such_upload(id));
+    };
+    match fs.lookup(ctx, uploads.ino, OsStr::new(id)).await {
+        Ok((_, dir, _)) if dir.kind == SFlag::S_IFDIR => Ok((uploads.ino, dir.ino)),
+        _ => Err(no_such_upload(id)),
+    }
+}
+
+/// The upload in the directory `dir` named `id`, `None` if it has no valid
+/// target file
+async fn read_upload<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    id: &OsStr,
+    dir: INum,
+) -> DatenLordResult<Option<Upload>> {
+    let (Some(id), Ok((_, target, _))) =
+        (id.to_str(), fs.lookup(ctx, dir, OsStr::new(TARGET)).await)
+    else {
+        return Ok(None);
+    };
+    let target = read_file(fs, ctx, target.ino, target.size).await?;
+    let Some(newline) = target.iter().position(|&byte| byte == b'\n') else {
+        return Ok(None);
+    };
+    let Some(started_ns) = std::str::from_utf8(&target[..newline])
+        .ok()
+        .and_then(|ns| ns.parse::<i128>().ok())
+    else {
+        return Ok(None);
+    };
+    let sec = i64::try_from(started_ns.div_euclid(1_000_000_000)).unwrap_or(i64::MAX);
+    let nsec = started_ns.rem_euclid(1_000_000_000) as u32;
+    let Some(started_at) = fs_util::from_timespec(sec, nsec) else {
+        return Ok(None);
+    };
+    Ok(Some(Upload {
+        id: id.to_owned(),
+        path: PathBuf::from(OsString::from_vec(target[newline + 1..].to_vec())),
+        started_at,
+    }))
+}
+
+/// Start an upload to `path`, relative to the root, returning its id
+///
+/// The parent directory of `path` must exist. The file is created or
+/// replaced once the upload completes.
+pub async fn start_upload<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    path: &Path,
+) -> DatenLordResult<String> {
+    let valid = path.file_name().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !valid {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("cannot upload to {path:?}")],
+        });
+    }
+    let parent = path.parent().unwrap_or(Path::new(""));
+    if !parent.as_os_str().is_empty() {
+        let (_, attr, _) = fs.lookup(ctx, ROOT_ID, parent.as_os_str()).await?;
+        if attr.kind != SFlag::S_IFDIR {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!("the parent of {path:?} is not a directory")],
+            });
+        }
+    }
+
+    let uploads = shared_dir(fs, ctx, UPLOADS_DIR).await?;
+    let id = unique_name(ID_PREFIX);
+    let param = CreateParam {
+        parent: uploads,
+        name: id.clone().into(),
+        mode: UPLOAD_DIR_MODE,
+        rdev: 0,
+        node_type: SFlag::S_IFDIR,
+        link: None,
+    };
+    let (_, dir, _) = fs.mkdir(ctx, param).await?;
+    let (sec, nsec) = fs_util::to_timespec(SystemTime::now());
+    let started_ns = i128::from(sec) * 1_000_000_000 + i128::from(nsec);
+    let mut target = format!("{started_ns}\n").into_bytes();
+    target.extend_from_slice(path.as_os_str().as_bytes());
+    if let Err(e) = write_file(fs, ctx, dir.ino, TARGET, &target).await {
+        if let Err(remove_err) = remove_all(fs, ctx, uploads, OsStr::new(&id)).await {
+            warn!("failed to remove the upload directory {id}: {remove_err}");
+        }
+        return Err(e);
+    }
+    Ok(id)
+}
+
+/// Store `data` as part `index` of upload `id`, replacing the part formerly
+/// uploaded with that index
+///
+/// The same index must not be uploaded twice at once.
+pub async fn upload_part<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    id: &str,
+    index: u32,
+    data: &[u8],
+) -> DatenLordResult<UploadPart> {
+    if index >= MAX_PARTS {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("part indexes are below {MAX_PARTS}, got {index}")],
+        });
+    }
+    let (_, dir) = upload_dir(fs, ctx, id).await?;
+    let part = UploadPart {
+        index,
+        size: data.len() as u64,
+        checksum: checksum(data),
+    };
+    let name = part_name(index, &part.checksum);
+    let partial = unique_name(PARTIAL_PREFIX);
+    let stored = async {
+        write_file(fs, ctx, dir, &partial, data).await?;
+        let rename = RenameParam {
+            old_parent: dir,
+            old_name: partial.clone().into(),
+            new_parent: dir,
+            new_name: name.clone().into(),
+            flags: 0,
+        };
+        fs.rename(ctx, rename).await
+    }
+    .await;
+    if let Err(e) = stored {
+        if let Err(unlink_err) = fs.unlink(ctx, dir, OsStr::new(&partial)).await {
+            debug!("failed to remove the partial part {partial} of {id}: {unlink_err}");
+        }
+        return Err(e);
+    }
+    for (other, attr) in list_dir(fs, ctx, dir).await? {
+        let replaced = parse_part(&other, attr.size).is_some_and(|other_part| {
+            other_part.index == index && other_part.checksum != part.checksum
+        });
+        if replaced {
+            fs.unlink(ctx, dir, &other).await?;
+        }
+    }
+    Ok(part)
+}
+
+/// The parts uploaded to upload `id`, by index
+pub async fn upload_parts<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    id: &str,
+) -> DatenLordResult<Vec<UploadPart>> {
+    let (_, dir) = upload_dir(fs, ctx, id).await?;
+    let mut parts: Vec<_> = list_dir(fs, ctx, dir)
+        .await?
+        .into_iter()
+        .filter_map(|(name, attr)| parse_part(&name, attr.size))
+        .collect();
+    parts.sort_by_key(|part| part.index);
+    Ok(parts)
+}
+
+/// The uploads in progress that `ctx` can read, oldest first
+pub async fn list_uploads<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+) -> DatenLordResult<Vec<Upload>> {
+    let Ok((_, uploads, _)) = fs.lookup(ctx, ROOT_ID, OsStr::new(UPLOADS_DIR)).await else {
+        return Ok(Vec::new());
+    };
+    let mut found = Vec::new();
+    for (id, attr) in list_dir(fs, ctx, uploads.ino).await? {
+        if attr.kind != SFlag::S_IFDIR {
+            continue;
+        }
+        match read_upload(fs, ctx, &id, attr.ino).await {
+            Ok(Some(upload)) => found.push(upload),
+            Ok(None) => debug!("skipping the incomplete upload directory {id:?}"),
+            Err(e) => debug!("skipping the upload directory {id:?}: {e}"),
+        }
+    }
+    found.sort_by(|a, b| {
+        a.started_at
+            .cmp(&b.started_at)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    Ok(found)
+}
+
+/// Append the data of `part`, stored in the file `ino`, to the file
+/// `assembled` open as `fh` at `offset`, returning the bytes appended
+///
+/// Fails when the data does not match the checksum of the part.
+#[allow(clippy::too_many_arguments)]
+async fn append_part<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    id: &str,
+    part: &UploadPart,
+    ino: INum,
+    assembled: INum,
+    fh: u64,
+    offset: u64,
+) -> DatenLordResult<u64> {
+    let flags = OFlag::O_RDONLY.bits() as u32;
+    let part_fh = fs.open(ctx, ino, flags).await?;
+    let mut buf = vec![0; fs.block_layout().block_len()];
+    let mut hasher = Sha256::new();
+    let mut copied = 0_u64;
+    let result = loop {
+        match fs
+            .read(ctx, ino, part_fh, copied, buf.len() as u32, &mut buf)
+            .await
+        {
+            Ok(0) => break Ok(()),
+            Ok(read) => {
+                hasher.update(&buf[..read]);
+                let at = (offset + copied) as i64;
+                if let Err(e) = fs.write(ctx, assembled, fh, at, &buf[..read], 0).await {
+                    break Err(e);
+                }
+                copied += read as u64;
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    fs.release(ctx, ino, part_fh, flags, 0, false).await?;
+    result?;
+    if hex(&hasher.finalize()) != part.checksum {
+        return Err(DatenLordError::Io {
+            context: vec![format!(
+                "part {} of upload {id} does not match its checksum, upload it again",
+                part.index
+            )],
+            source: None,
+        });
+    }
+    Ok(copied)
+}
+
+/// Concatenate the parts of upload `id` into its target, created or
+/// replaced, and remove the upload, returning the attributes of the target
+///
+/// The parts must be indexed from 0 with no gap. A part whose data no
+/// longer matches its checksum fails the completion, leaving the upload in
+/// progress.
+pub async fn complete_upload<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    id: &str,
+) -> DatenLordResult<FileAttr> {
+    let (uploads, dir) = upload_dir(fs, ctx, id).await?;
+    let Some(upload) = read_upload(fs, ctx, OsStr::new(id), dir).await? else {
+        return Err(no_such_upload(id));
+    };
+    let mut parts: Vec<_> = list_dir(fs, ctx, dir)
+        .await?
+        .into_iter()
+        .filter_map(|(name, attr)| Some((parse_part(&name, attr.size)?, attr.ino)))
+        .collect();
+    parts.sort_by_key(|(part, _)| part.index);
+    if parts.is_empty() {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("upload {id} has no part")],
+        });
+    }
+    if let Some(missing) = (0..)
+        .zip(&parts)
+        .find(|&(index, (part, _))| part.index != index)
+    {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("part {} of upload {id} is missing", missing.0)],
+        });
+    }
+
+    // Left over by a completion that failed
+    if fs.lookup(ctx, dir, OsStr::new(ASSEMBLED)).await.is_ok() {
+        fs.unlink(ctx, dir, OsStr::new(ASSEMBLED)).await?;
+    }
+    let param = CreateParam {
+        parent: dir,
+        name: ASSEMBLED.into(),
+        mode: FILE_MODE,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let (_, assembled, _) = fs.mknod(ctx, param).await?;
+    let flags = OFlag::O_WRONLY.bits() as u32;
+    let fh = fs.open(ctx, assembled.ino, flags).await?;
+    let appended = async {
+        let mut offset = 0;
+        for &(ref part, ino) in &parts {
+            offset += append_part(fs, ctx, id, part, ino, assembled.ino, fh, offset).await?;
+        }
+        fs.fsync(ctx, assembled.ino, fh, false).await
+    }
+    .await;
+    fs.release(ctx, assembled.ino, fh, flags, 0, true).await?;
+    appended?;
+    let rename = RenameParam {
+        old_parent: dir,
+        old_name: ASSEMBLED.into(),
+        new_parent: ROOT_ID,
+        new_name: upload.path.clone().into_os_string(),
+        flags: 0,
+    };
+    fs.rename(ctx, rename).await?;
+    if let Err(e) = remove_all(fs, ctx, uploads, OsStr::new(id)).await {
+        warn!("failed to remove the upload directory {id}: {e}");
+    }
+    Ok(fs.lookup(ctx, ROOT_ID, upload.path.as_os_str()).await?.1)
+}
+
+/// Give up upload `id`, removing its parts
+pub async fn abort_upload<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    id: &str,
+) -> DatenLordResult<()> {
+    let (uploads, _) = upload_dir(fs, ctx, id).await?;
+    remove_all(fs, ctx, uploads, OsStr::new(id)).await
+}
+
+/// Replace the contents of the file `path`, relative to the root, with
+/// `data`, creating it if missing, so readers see either the former contents
+/// or `data`, never a part of it
+///
+/// `data` is written to a temporary file next to `path`, synced and renamed
+/// over `path`, then the directory holding `path` is synced so the rename
+/// survives a crash. An existing file keeps its permissions, not its owner
+/// nor its extended attributes.
+pub async fn write_file_atomic<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    path: &OsStr,
+    data: &[u8],
+) -> DatenLordResult<FileAttr> {
+    let path = Path::new(path);
+    let (Some(dir), Some(_)) = (path.parent(), path.file_name()) else {
+        return Err(DatenLordError::InvalidArgument {
+            context: vec![format!("{path:?} does not name a file")],
+        });
+    };
+    let perm = match fs.lookup(ctx, ROOT_ID, path.as_os_str()).await {
+        Ok((_, attr, _)) => Some(attr.perm),
+        Err(DatenLordError::NotFound { .. }) => None,
+        Err(e) => return Err(e),
+    };
+    let dir_ino = if dir.as_os_str().is_empty() {
+        ROOT_ID
+    } else {
+        fs.lookup(ctx, ROOT_ID, dir.as_os_str()).await?.1.ino
+    };
+    let temp = dir.join(unique_name(ATOMIC_PREFIX)).into_os_string();
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: temp.clone(),
+        mode: FILE_MODE,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let (_, attr, _) = fs.mknod(ctx, param).await?;
+    let written = async {
+        if let Some(perm) = perm {
+            let param = SetAttrParam {
+                mode: Some(u32::from(perm)),
+                ..SetAttrParam::default()
+            };
+            fs.setattr(ctx, attr.ino, param).await?;
+        }
+        let flags = OFlag::O_WRONLY.bits() as u32;
+        let fh = fs.open(ctx, attr.ino, flags).await?;
+        let written = async {
+            fs.write(ctx, attr.ino, fh, 0, data, 0).await?;
+            fs.fsync(ctx, attr.ino, fh, false).await
+        }
+        .await;
+        fs.release(ctx, attr.ino, fh, flags, 0, true).await?;
+        written?;
+        let rename = RenameParam {
+            old_parent: ROOT_ID,
+            old_name: temp.clone(),
+            new_parent: ROOT_ID,
+            new_name: path.as_os_str().to_owned(),
+            flags: 0,
+        };
+        fs.rename(ctx, rename).await
+    }
+    .await;
+    if let Err(e) = written {
+        if let Err(e) = fs.unlink(ctx, ROOT_ID, &temp).await {
+            warn!("failed to remove the temporary file {temp:?}: {e}");
+        }
+        return Err(e);
+    }
+
+    let fh = fs
+        .opendir(ctx, dir_ino, OFlag::O_RDONLY.bits() as u32)
+        .await?;
+    let synced = fs.fsyncdir(ctx, dir_ino, fh, false).await;
+    fs.releasedir(ctx, dir_ino, fh, 0).await?;
+    synced?;
+    Ok(fs.lookup(ctx, ROOT_ID, path.as_os_str()).await?.1)
+}
@@ -0,0 +1,793 @@
+//! Middleware keeping the former contents of overwritten files as versions
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use nix::unistd::AccessFlags;
+use serde_derive::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{
+    CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam, RenameParam,
+    RequestContext, SeekWhence, SetAttrParam, StatFsParam, ROOT_ID,
+};
+use super::layout::BlockLayout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// The directory under the root holding the versions, one directory per
+/// file named after its inode
+pub const VERSIONS_DIR: &str = ".datenlord_versions";
+/// The mode of the directories of the version store
+const STORE_MODE: u32 = 0o700;
+/// The mode of the files holding versions
+const VERSION_MODE: u32 = 0o600;
+/// The bytes copied at a time into and out of versions
+const CHUNK_LEN: usize = 1 << 20;
+/// The versions kept of every file by default
+const DEFAULT_RETENTION: usize = 10;
+
+/// Whether and how many versions of files are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VersioningConfig {
+    /// Keep versions of overwritten files, off by default
+    pub enabled: bool,
+    /// The versions kept of every file, the oldest removed first
+    pub retention: usize,
+}
+
+impl Default for VersioningConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention: DEFAULT_RETENTION,
+        }
+    }
+}
+
+/// A former content of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Version {
+    /// The id of the version, greater for later versions of the file
+    pub id: u64,
+    /// The size of the content
+    pub size: u64,
+    /// When the content was last modified before it was replaced
+    pub mtime: SystemTime,
+}
+
+/// A `VirtualFs` saving the content of a regular file as a version before
+/// it is overwritten
+///
+/// The first write through a handle, opening a file with `O_TRUNC` and
+/// every `setattr` shrinking a file copy its content into the store, so
+/// one open, write and release makes one version; empty files have no
+/// content to keep. The store lives in `VERSIONS_DIR` of the inner
+/// filesystem and is written as the process, while reading and restoring
+/// versions takes the access to the file itself. Versions follow a file
+/// across renames and go with its last link.
+#[derive(Debug)]
+pub struct VersioningFs<F> {
+    /// The wrapped filesystem
+    inner: F,
+    /// Whether and how many versions are kept
+    config: VersioningConfig,
+    /// The context the store is written with
+    store_ctx: RequestContext,
+    /// The handles that already saved a version, by a write, a truncation
+    /// or opening with `O_TRUNC`
+    saved: Mutex<HashSet<u64>>,
+    /// Serializes the changes to the store
+    store: tokio::sync::Mutex<()>,
+}
+
+impl<F: VirtualFs> VersioningFs<F> {
+    /// Wrap `inner`, keeping versions as `config` says
+    pub fn new(inner: F, config: VersioningConfig) -> Self {
+        Self {
+            inner,
+            config,
+            store_ctx: RequestContext::current(),
+            saved: Mutex::default(),
+            store: tokio::sync::Mutex::default(),
+        }
+    }
+
+    /// The wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// The versions of the file `ino`, oldest first
+    pub async fn list_versions(
+        &self,
+        ctx: &RequestContext,
+        ino: INum,
+    ) -> DatenLordResult<Vec<Version>> {
+        self.check_file(ctx, ino, AccessFlags::R_OK).await?;
+        let versions = self.versions(ino).await?;
+        Ok(versions
+            .into_iter()
+            .map(|(id, attr)| Version {
+                id,
+                size: attr.size,
+                mtime: attr.mtime,
+            })
+            .collect())
+    }
+
+    /// The content of the version `id` of the file `ino`
+    pub async fn read_version(
+        &self,
+        ctx: &RequestContext,
+        ino: INum,
+        id: u64,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.check_file(ctx, ino, AccessFlags::R_OK).await?;
+        let version = self.version(ino, id).await?;
+        let mut content = Vec::new();
+        self.copy(version.ino, Sink::Buffer(&mut content)).await?;
+        Ok(content)
+    }
+
+    /// Give the file `ino` the content of its version `id`, saving the
+    /// content it replaces as a version first
+    pub async fn restore_version(
+        &self,
+        ctx: &RequestContext,
+        ino: INum,
+        id: u64,
+    ) -> DatenLordResult<()> {
+        self.check_file(ctx, ino, AccessFlags::W_OK).await?;
+        let _store = self.store.lock().await;
+        let version = self.version(ino, id).await?;
+        // The version restored is not pruned by saving the replaced one
+        self.save(ino, Some(id)).await?;
+        let truncate = SetAttrParam {
+            size: Some(0),
+            ..SetAttrParam::default()
+        };
+        self.inner.setattr(ctx, ino, truncate).await?;
+        let flags = OFlag::O_WRONLY.bits() as u32;
+        let fh = self.inner.open(ctx, ino, flags).await?;
+        let copied = self.copy(version.ino, Sink::File { ctx, ino, fh }).await;
+        self.inner.release(ctx, ino, fh, flags, 0, true).await?;
+        copied
+    }
+
+    /// Check that `ctx` has `access` to the regular file `ino`
+    async fn check_file(
+        &self,
+        ctx: &RequestContext,
+        ino: INum,
+        access: AccessFlags,
+    ) -> DatenLordResult<()> {
+        let (_, attr) = self.inner.getattr(ctx, ino).await?;
+        if attr.kind != SFlag::S_IFREG {
+            return Err(DatenLordError::InvalidArgument {
+                context: vec![format!(
+                    "inode={ino} is not a regular file and has no versions"
+                )],
+            });
+        }
+        self.inner.access(ctx, ino, access.bits() as u32).await
+    }
+
+    /// The directory of the versions of the file `ino`, `None` if it has none
+    async fn version_dir(&self, ino: INum) -> Option<INum> {
+        let path = Path::new(VERSIONS_DIR).join(ino.to_string());
+        let (_, attr, _) = self
+            .inner
+            .lookup(&self.store_ctx, ROOT_ID, path.as_os_str())
+            .await
+            .ok()?;
+        Some(attr.ino)
+    }
+
+    /// The versions of the file `ino` with the attributes of the files
+    /// holding them, oldest first
+    async fn versions(&self, ino: INum) -> DatenLordResult<Vec<(u64, FileAttr)>> {
+        let Some(dir) = self.version_dir(ino).await else {
+            return Ok(Vec::new());
+        };
+        let ctx = &self.store_ctx;
+        let fh = self.inner.opendir(ctx, dir, 0).await?;
+        let mut versions = Vec::new();
+        let mut offset = 0;
+        let listed = loop {
+            match self.inner.readdirplus(ctx, dir, fh, offset).await {
+                Ok(page) if page.is_empty() => break Ok(()),
+                Ok(page) => {
+                    offset += i64::try_from(page.len()).unwrap_or(i64::MAX);
+                    versions.extend(page.into_iter().filter_map(|(entry, attr, _)| {
+                        let id = entry.name.to_str()?.parse().ok()?;
+                        Some((id, attr))
+                    }));
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        self.inner.releasedir(ctx, dir, fh, 0).await?;
+        listed?;
+        versions.sort_unstable_by_key(|&(id, _)| id);
+        Ok(versions)
+    }
+
+    /// The file holding the version `id` of the file `ino`
+    async fn version(&self, ino: INum, id: u64) -> DatenLordResult<FileAttr> {
+        self.versions(ino)
+            .await?
+            .into_iter()
+            .find(|&(version, _)| version == id)
+            .map(|(_, attr)| attr)
+            .ok_or_else(|| DatenLordError::InvalidArgument {
+                context: vec![format!("inode={ino} has no version {id}")],
+            })
+    }
+
+    /// Save the content of the regular file `ino` as its next version, then
+    /// remove the oldest ones past the retention but `pinned`
+    ///
+    /// The caller holds the `store` lock.
+    async fn save(&self, ino: INum, pinned: Option<u64>) -> DatenLordResult<()> {
+        let ctx = &self.store_ctx;
+        let (_, attr) = self.inner.getattr(ctx, ino).await?;
+        if attr.kind != SFlag::S_IFREG || attr.size == 0 {
+            return Ok(());
+        }
+        let path = Path::new(VERSIONS_DIR).join(ino.to_string());
+        let dir = self
+            .inner
+            .mkdir_all(ctx, ROOT_ID, path.as_os_str(), STORE_MODE)
+            .await?;
+        let versions = self.versions(ino).await?;
+        let id = versions.last().map_or(1, |&(id, _)| id + 1);
+        let name = format!("{id:020}");
+        let param = CreateParam {
+            parent: dir.ino,
+            name: name.clone().into(),
+            mode: VERSION_MODE,
+            rdev: 0,
+            node_type: SFlag::S_IFREG,
+            link: None,
+        };
+        let (_, version, _) = self.inner.mknod(ctx, param).await?;
+        let flags = OFlag::O_WRONLY.bits() as u32;
+        let saved = async {
+            let fh = self.inner.open(ctx, version.ino, flags).await?;
+            let sink = Sink::File {
+                ctx,
+                ino: version.ino,
+                fh,
+            };
+            let copied = self.copy(ino, sink).await;
+            self.inner
+                .release(ctx, version.ino, fh, flags, 0, true)
+                .await?;
+            copied?;
+            let times = SetAttrParam {
+                m_time: Some(attr.mtime),
+                ..SetAttrParam::default()
+            };
+            self.inner
+                .setattr(ctx, version.ino, times)
+                .await
+                .map(|_| ())
+        }
+        .await;
+        if let Err(e) = saved {
+            if let Err(unlink_err) = self.inner.unlink(ctx, dir.ino, OsStr::new(&name)).await {
+                warn!("failed to remove the partial version {name} of inode={ino}: {unlink_err}");
+            }
+            return Err(e);
+        }
+
+        let retention = self.config.retention.max(1);
+        let mut expired = (versions.len() + 1).saturating_sub(retention);
+        for (old, _) in versions {
+            if expired == 0 {
+                break;
+            }
+            if Some(old) == pinned {
+                continue;
+            }
+            let name = format!("{old:020}");
+            if let Err(e) = self.inner.unlink(ctx, dir.ino, OsStr::new(&name)).await {
+                warn!("failed to remove the expired version {old} of inode={ino}: {e}");
+            }
+            expired -= 1;
+        }
+        Ok(())
+    }
+
+    /// Save a version of the file `ino` before the first write through `fh`
+    async fn save_once(&self, ino: INum, fh: u64) -> DatenLordResult<()> {
+        if !self.config.enabled || self.saved.lock().unwrap().contains(&fh) {
+            return Ok(());
+        }
+        let _store = self.store.lock().await;
+        if !self.saved.lock().unwrap().insert(fh) {
+            return Ok(());
+        }
+        let saved = self.save(ino, None).await;
+        if saved.is_err() {
+            self.saved.lock().unwrap().remove(&fh);
+        }
+        saved
+    }
+
+    /// Remove the versions of the file `ino`, which lost its last link
+    async fn forget_versions(&self, ino: INum) {
+        let Some(dir) = self.version_dir(ino).await else {
+            return;
+        };
+        let ctx = &self.store_ctx;
+        let _store = self.store.lock().await;
+        let removed = async {
+            for (id, _) in self.versions(ino).await? {
+                self.inner
+                    .unlink(ctx, dir, OsStr::new(&format!("{id:020}")))
+                    .await?;
+            }
+            let (_, store, _) = self
+                .inner
+                .lookup(ctx, ROOT_ID, OsStr::new(VERSIONS_DIR))
+                .await?;
+            self.inner
+                .rmdir(ctx, store.ino, OsStr::new(&ino.to_string()))
+                .await
+                .map(|_| ())
+        }
+        .await;
+        if let Err(e) = removed {
+            warn!("failed to remove the versions of inode={ino}: {e}");
+        }
+    }
+
+    /// The regular file `name` under `parent` if removing it drops its last
+    /// link, and versioning is on
+    async fn last_link(&self, ctx: &RequestContext, parent: INum, name: &OsStr) -> Option<INum> {
+        if !self.config.enabled {
+            return None;
+        }
+        let (_, attr, _) = self.inner.lookup(ctx, parent, name).await.ok()?;
+        (attr.kind == SFlag::S_IFREG && attr.nlink <= 1).then_some(attr.ino)
+    }
+
+    /// Copy the content of the file `ino` into `sink` a chunk at a time
+    async fn copy(&self, ino: INum, mut sink: Sink<'_>) -> DatenLordResult<()> {
+        let ctx = &self.store_ctx;
+        let flags = OFlag::O_RDONLY.bits() as u32;
+        let fh = self.inner.open(ctx, ino, flags).await?;
+        let mut buf = vec![0; CHUNK_LEN];
+        let mut offset = 0;
+        let copied = async {
+            loop {
+                let read = self
+                    .inner
+                    .read(ctx, ino, fh, offset, CHUNK_LEN as u32, &mut buf)
+                    .await?;
+                if read == 0 {
+                    return Ok(());
+                }
+                match sink {
+                    Sink::File { ctx, ino, fh } => {
+                        let at = i64::try_from(offset).unwrap_or(i64::MAX);
+                        self.inner.write(ctx, ino, fh, at, &buf[..read], 0).await?;
+                    }
+                    Sink::Buffer(ref mut content) => content.extend_from_slice(&buf[..read]),
+                }
+                offset += read as u64;
+            }
+        }
+        .await;
+        self.inner.release(ctx, ino, fh, flags, 0, false).await?;
+        copied
+    }
+}
+
+/// Where `VersioningFs::copy` copies to
+enum Sink<'a> {
+    /// The file `ino` open as `fh`, written as `ctx`
+    File {
+        ctx: &'a RequestContext,
+        ino: INum,
+        fh: u64,
+    },
+    /// Memory
+    Buffer(&'a mut Vec<u8>),
+}
+
+#[async_trait]
+impl<F: VirtualFs> VirtualFs for VersioningFs<F> {
+    fn init(&self) -> DatenLordResult<()> {
+        self.inner.init()
+    }
+
+    fn block_layout(&self) -> BlockLayout {
+        self.inner.block_layout()
+    }
+
+    async fn destroy(&self) -> DatenLordResult<()> {
+        self.inner.destroy().await
+    }
+
+    async fn interrupt(&self, unique: u64) {
+        self.inner.interrupt(unique).await;
+    }
+
+    async fn lookup(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.lookup(ctx, parent, name).await
+    }
+
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        self.inner.exists(ctx, parent, name).await
+    }
+
+    async fn forget(&self, ino: u64, nlookup: u64) {
+        self.inner.forget(ino, nlookup).await;
+    }
+
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        self.inner.getattr(ctx, ino).await
+    }
+
+    async fn setattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        param: SetAttrParam,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        if let (true, Some(size)) = (self.config.enabled, param.size) {
+            let (_, attr) = self.inner.getattr(ctx, ino).await?;
+            let saved = param
+                .fh
+                .is_some_and(|fh| self.saved.lock().unwrap().contains(&fh));
+            if size < attr.size && !saved {
+                self.inner
+                    .access(ctx, ino, AccessFlags::W_OK.bits() as u32)
+                    .await?;
+                let _store = self.store.lock().await;
+                self.save(ino, None).await?;
+                if let Some(fh) = param.fh {
+                    self.saved.lock().unwrap().insert(fh);
+                }
+            }
+        }
+        self.inner.setattr(ctx, ino, param).await
+    }
+
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>> {
+        self.inner.readlink(ctx, ino).await
+    }
+
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mknod(ctx, param).await
+    }
+
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.mkdir(ctx, param).await
+    }
+
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()> {
+        let last = self.last_link(ctx, parent, name).await;
+        self.inner.unlink(ctx, parent, name).await?;
+        if let Some(ino) = last {
+            self.forget_versions(ino).await;
+        }
+        Ok(())
+    }
+
+    async fn rmdir(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        dir_name: &OsStr,
+    ) -> DatenLordResult<Option<INum>> {
+        self.inner.rmdir(ctx, parent, dir_name).await
+    }
+
+    async fn symlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+        target_path: &Path,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)> {
+        self.inner.symlink(ctx, parent, name, target_path).await
+    }
+
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()> {
+        let exchange = param.flags & nix::fcntl::RenameFlags::RENAME_EXCHANGE.bits() != 0;
+        let replaced = if exchange {
+            None
+        } else {
+            self.last_link(ctx, param.new_parent, &param.new_name).await
+        };
+        self.inner.rename(ctx, param).await?;
+        if let Some(ino) = replaced {
+            self.forget_versions(ino).await;
+        }
+        Ok(())
+    }
+
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
+        self.inner.link(ctx, newparent, newname).await
+    }
+
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        let truncating =
+            self.config.enabled && OFlag::from_bits_truncate(flags as i32).contains(OFlag::O_TRUNC);
+        // Saved before the wrapped filesystem truncates the file, which
+        // writes through the handle then leave alone
+        if truncating {
+            self.inner
+                .access(ctx, ino, AccessFlags::W_OK.bits() as u32)
+                .await?;
+            let _store = self.store.lock().await;
+            self.save(ino, None).await?;
+        }
+        let fh = self.inner.open(ctx, ino, flags).await?;
+        if truncating {
+            self.saved.lock().unwrap().insert(fh);
+        }
+        Ok(fh)
+    }
+
+    async fn read(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        buf: &mut [u8],
+    ) -> DatenLordResult<usize> {
+        self.inner.read(ctx, ino, fh, offset, size, buf).await
+    }
+
+    async fn write(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.save_once(ino, fh).await?;
+        self.inner.write(ctx, ino, fh, offset, data, flags).await
+    }
+
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.flush(ctx, ino, fh, lock_owner).await
+    }
+
+    async fn release(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+        lock_owner: u64,
+        flush: bool,
+    ) -> DatenLordResult<()> {
+        self.saved.lock().unwrap().remove(&fh);
+        self.inner
+            .release(ctx, ino, fh, flags, lock_owner, flush)
+            .await
+    }
+
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsync(ctx, ino, fh, datasync).await
+    }
+
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        self.inner.lseek(ctx, ino, fh, offset, whence).await
+    }
+
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        self.save_once(param.ino_out, param.fh_out).await?;
+        self.inner.copy_file_range(ctx, param).await
+    }
+
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64> {
+        self.inner.opendir(ctx, ino, flags).await
+    }
+
+    async fn readdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<DirEntry>> {
+        self.inner.readdir(ctx, ino, fh, offset).await
+    }
+
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        self.inner.readdirplus(ctx, ino, fh, offset).await
+    }
+
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.releasedir(ctx, ino, fh, flags).await
+    }
+
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.fsyncdir(ctx, ino, fh, datasync).await
+    }
+
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        self.inner.sync_all(ctx).await
+    }
+
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam> {
+        self.inner.statfs(ctx, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: u32,
+        position: u32,
+    ) -> DatenLordResult<()> {
+        self.inner
+            .setxattr(ctx, ino, name, value, flags, position)
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.getxattr(ctx, ino, name).await
+    }
+
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
+        self.inner.listxattr(ctx, ino).await
+    }
+
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
+        self.inner.removexattr(ctx, ino, name).await
+    }
+
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
+        self.inner.access(ctx, ino, mask).await
+    }
+
+    async fn create(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> DatenLordResult<()> {
+        self.inner.create(ctx, ino, parent, name, mode, flags).await
+    }
+
+    async fn getlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+    ) -> DatenLordResult<()> {
+        self.inner.getlk(ctx, ino, lk_param).await
+    }
+
+    async fn setlk(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        lk_param: FileLockParam,
+        sleep: bool,
+    ) -> DatenLordResult<()> {
+        self.inner.setlk(ctx, ino, lk_param, sleep).await
+    }
+
+    async fn bmap(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        blocksize: u32,
+        idx: u64,
+    ) -> DatenLordResult<()> {
+        self.inner.bmap(ctx, ino, blocksize, idx).await
+    }
+
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        self.inner.ioctl(ctx, ino, cmd, input).await
+    }
+}
@@ -1,41 +1,49 @@
 //! The `FileSystem` trait
-use std::{path::Path, time::Duration};
+use std::ffi::OsStr;
+use std::{path::Path, time::{Duration, SystemTime}};
 
 use async_trait::async_trait;
 use bytes::BytesMut;
-use serde_derive::{Serialize, Deserialize};
+use nix::sys::stat::SFlag;
 use tracing::warn;
 
 use crate::common::{DatenLordError, DatenLordResult};
 
-use super::fs_util::{CreateParam, FileAttr, FileLockParam, RenameParam, SetAttrParam, StatFsParam};
+use super::ioctl;
+use super::layout::BlockLayout;
+use super::fs_util::{
+    self, CopyRangeParam, CopyRangeResult, CreateParam, DirEntry, FileAttr, FileLockParam,
+    RenameParam, RequestContext, SeekWhence, SetAttrParam, StatFsParam, UtimeSpec,
+};
 
 /// The type of i-number
 pub type INum = u64;
 
-/// Represents a directory entry in a filesystem.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
-pub struct DirEntry {
-    /// The inode number of the child
-    ino: INum,
-    /// The name of the child
-    name: String,
-}
-
 /// Virtual filesystem trait
+///
+/// Every operation made on behalf of a caller takes its `RequestContext`,
+/// which permission checks and the ownership of created files follow.
 #[async_trait]
 pub trait VirtualFs: Sync + Send {
     /// Initialize filesystem
     fn init(&self) -> DatenLordResult<()> {
         Err(DatenLordError::Unimplemented {
             context: vec!["init unimplemented".to_owned()],
+            source: None,
         })
     }
 
+    /// The sizes the data is best read and written in, the default layout
+    /// unless the backend configures another
+    fn block_layout(&self) -> BlockLayout {
+        BlockLayout::default()
+    }
+
     /// Clean up filesystem
     async fn destroy(&self) -> DatenLordResult<()> {
         Err(DatenLordError::Unimplemented {
             context: vec!["destroy unimplemented".to_owned()],
+            source: None,
         })
     }
 
@@ -51,75 +59,171 @@ pub trait VirtualFs: Sync + Send {
     /// Look up a directory entry by name and get its attributes.
     async fn lookup(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         parent: INum,
-        name: &str,
+        name: &OsStr,
     ) -> DatenLordResult<(Duration, FileAttr, u64)>;
 
+    /// Whether the entry `name` exists under `parent`, a missing entry being
+    /// no error
+    ///
+    /// Layers answer from what they cache and backends may test the entry
+    /// without registering it, by default it is looked up.
+    async fn exists(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<bool> {
+        exists_by_lookup(self, ctx, parent, name).await
+    }
+
     /// Forget about an inode
     async fn forget(&self, ino: u64, nlookup: u64);
 
     /// Get file attributes.
-    async fn getattr(&self, ino: u64) -> DatenLordResult<(Duration, FileAttr)>;
+    async fn getattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+    ) -> DatenLordResult<(Duration, FileAttr)>;
 
     /// Set file attributes.
     async fn setattr(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         ino: u64,
         param: SetAttrParam,
     ) -> DatenLordResult<(Duration, FileAttr)>;
 
+    /// Set the access and modification times with nanosecond precision,
+    /// like `utimensat`, on top of `setattr`
+    async fn utimens(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        atime: UtimeSpec,
+        mtime: UtimeSpec,
+    ) -> DatenLordResult<(Duration, FileAttr)> {
+        let now = SystemTime::now();
+        let param = SetAttrParam {
+            a_time: atime.resolve(now),
+            m_time: mtime.resolve(now),
+            ..SetAttrParam::default()
+        };
+        self.setattr(ctx, ino, param).await
+    }
+
     /// Read symbolic link.
-    async fn readlink(&self, ino: u64) -> DatenLordResult<Vec<u8>>;
+    async fn readlink(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<u8>>;
 
     /// Create file node.
-    async fn mknod(&self, param: CreateParam) -> DatenLordResult<(Duration, FileAttr, u64)>;
+    async fn mknod(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)>;
 
     /// Create a directory
-    async fn mkdir(&self, param: CreateParam) -> DatenLordResult<(Duration, FileAttr, u64)>;
+    async fn mkdir(
+        &self,
+        ctx: &RequestContext,
+        param: CreateParam,
+    ) -> DatenLordResult<(Duration, FileAttr, u64)>;
+
+    /// Create the directory `path` under `parent` together with its missing
+    /// ancestors, like `mkdir -p`, on top of `lookup` and `mkdir`
+    ///
+    /// A component created concurrently by another caller is used as is, a
+    /// component that exists but is not a directory fails the call.
+    async fn mkdir_all(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        path: &OsStr,
+        mode: u32,
+    ) -> DatenLordResult<FileAttr> {
+        let mut attr = self.getattr(ctx, parent).await?.1;
+        for name in fs_util::components(path) {
+            attr = match self.lookup(ctx, attr.ino, name).await {
+                Ok((_, child, _)) => child,
+                Err(_) => {
+                    let param = CreateParam {
+                        parent: attr.ino,
+                        name: name.to_owned(),
+                        mode,
+                        rdev: 0,
+                        node_type: SFlag::S_IFDIR,
+                        link: None,
+                    };
+                    match self.mkdir(ctx, param).await {
+                        Ok((_, child, _)) => child,
+                        Err(DatenLordError::AlreadyExists { .. }) => {
+                            self.lookup(ctx, attr.ino, name).await?.1
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            };
+            if attr.kind != SFlag::S_IFDIR {
+                return Err(DatenLordError::AlreadyExists {
+                    context: vec![format!("{name:?} in {path:?} exists and is not a directory")],
+                    source: None,
+                });
+            }
+        }
+        Ok(attr)
+    }
 
     /// Remove a file
-    async fn unlink(&self, uid: u32, gid: u32, parent: INum, name: &str) -> DatenLordResult<()>;
+    async fn unlink(
+        &self,
+        ctx: &RequestContext,
+        parent: INum,
+        name: &OsStr,
+    ) -> DatenLordResult<()>;
 
     /// Remove a directory
     async fn rmdir(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         parent: INum,
-        dir_name: &str,
+        dir_name: &OsStr,
     ) -> DatenLordResult<Option<INum>>;
 
     /// Create a symbolic link
     async fn symlink(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         parent: INum,
-        name: &str,
+        name: &OsStr,
         target_path: &Path,
     ) -> DatenLordResult<(Duration, FileAttr, u64)>;
 
     /// Rename a file
-    async fn rename(&self, uid: u32, gid: u32, param: RenameParam) -> DatenLordResult<()>;
+    async fn rename(&self, ctx: &RequestContext, param: RenameParam) -> DatenLordResult<()>;
 
     /// Create a hard link
     #[allow(unused_variables)]
-    async fn link(&self, newparent: u64, newname: &str) -> DatenLordResult<()> {
+    async fn link(
+        &self,
+        ctx: &RequestContext,
+        newparent: u64,
+        newname: &OsStr,
+    ) -> DatenLordResult<()> {
         Err(DatenLordError::Unimplemented {
             context: vec!["link unimplemented".to_owned()],
+            source: None,
         })
     }
 
     /// Open a file
-    async fn open(&self, uid: u32, gid: u32, ino: u64, flags: u32) -> DatenLordResult<u64>;
+    async fn open(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64>;
 
     /// Read data with the given buffer, return current offset and the number of bytes read
     async fn read(
         &self,
+        ctx: &RequestContext,
         ino: u64,
         fh: u64,
         offset: u64,
@@ -131,6 +235,7 @@ pub trait VirtualFs: Sync + Send {
     /// Write data
     async fn write(
         &self,
+        ctx: &RequestContext,
         ino: u64,
         fh: u64,
         offset: i64,
@@ -139,11 +244,18 @@ pub trait VirtualFs: Sync + Send {
     ) -> DatenLordResult<()>;
 
     /// Flush method
-    async fn flush(&self, ino: u64, fh: u64, lock_owner: u64) -> DatenLordResult<()>;
+    async fn flush(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+    ) -> DatenLordResult<()>;
 
     /// Release an open file
     async fn release(
         &self,
+        ctx: &RequestContext,
         ino: u64,
         fh: u64,
         flags: u32, // same as the open flags
@@ -152,34 +264,122 @@ pub trait VirtualFs: Sync + Send {
     ) -> DatenLordResult<()>;
 
     /// Synchronize file contents
-    async fn fsync(&self, ino: u64, fh: u64, datasync: bool) -> DatenLordResult<()>;
+    async fn fsync(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()>;
+
+    /// The offset of the next data or hole at or after `offset` of an open
+    /// file, like `lseek` with `SEEK_DATA` or `SEEK_HOLE`, `None` when
+    /// `offset` is past the end or no data follows it
+    ///
+    /// The default treats the whole file as data.
+    async fn lseek(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: u64,
+        whence: SeekWhence,
+    ) -> DatenLordResult<Option<u64>> {
+        let _ = fh;
+        let (_, attr) = self.getattr(ctx, ino).await?;
+        Ok(fs_util::seek_extents(&[(0, attr.size)], attr.size, offset, whence))
+    }
+
+    /// Copy `param.len` bytes between two open files, like
+    /// `copy_file_range(2)`, stopping at the end of the source
+    ///
+    /// The default reads and writes through the filesystem, see
+    /// `copy_range_through`; backends able to share the blocks of both files
+    /// do so and report the copy `reflinked`.
+    async fn copy_file_range(
+        &self,
+        ctx: &RequestContext,
+        param: CopyRangeParam,
+    ) -> DatenLordResult<CopyRangeResult> {
+        copy_range_through(self, ctx, param).await
+    }
 
     /// Open a directory
-    async fn opendir(&self, uid: u32, gid: u32, ino: u64, flags: u32) -> DatenLordResult<u64>;
+    ///
+    /// The `readdir` and `readdirplus` offsets of the handle returned index
+    /// one listing of the directory until `releasedir`, so entries created
+    /// or removed meanwhile do not shift them; handle 0 lists it anew.
+    async fn opendir(&self, ctx: &RequestContext, ino: u64, flags: u32) -> DatenLordResult<u64>;
 
     /// Read directory
     async fn readdir(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         ino: u64,
         fh: u64,
         offset: i64,
     ) -> DatenLordResult<Vec<DirEntry>>;
 
+    /// Read directory entries together with their attributes and the TTL
+    /// of the attributes, like `READDIRPLUS`
+    ///
+    /// `DirEntry::attr` of every entry is filled as well. By default every
+    /// entry `readdir` returns is looked up.
+    async fn readdirplus(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> DatenLordResult<Vec<(DirEntry, FileAttr, Duration)>> {
+        let entries = self.readdir(ctx, ino, fh, offset).await?;
+        let mut detailed = Vec::with_capacity(entries.len());
+        for mut entry in entries {
+            let (ttl, attr, _) = self.lookup(ctx, ino, &entry.name).await?;
+            entry.attr = Some(attr);
+            detailed.push((entry, attr, ttl));
+        }
+        Ok(detailed)
+    }
+
     /// Release an open directory
-    async fn releasedir(&self, ino: u64, fh: u64, flags: u32) -> DatenLordResult<()>;
+    async fn releasedir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        flags: u32,
+    ) -> DatenLordResult<()>;
 
     /// Synchronize directory contents
-    async fn fsyncdir(&self, ino: u64, fh: u64, datasync: bool) -> DatenLordResult<()>;
+    async fn fsyncdir(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+    ) -> DatenLordResult<()>;
+
+    /// Synchronize the whole filesystem, like `syncfs`
+    ///
+    /// Returns only when every open file and all state buffered by the
+    /// filesystem and its backend is durable.
+    #[allow(unused_variables)]
+    async fn sync_all(&self, ctx: &RequestContext) -> DatenLordResult<()> {
+        Err(DatenLordError::Unimplemented {
+            context: vec!["sync_all unimplemented".to_owned()],
+            source: None,
+        })
+    }
 
     /// Get file system statistics
-    async fn statfs(&self, uid: u32, gid: u32, ino: u64) -> DatenLordResult<StatFsParam>;
+    async fn statfs(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<StatFsParam>;
 
     /// Set an extended attribute
     #[allow(unused_variables)]
     async fn setxattr(
         &self,
+        ctx: &RequestContext,
         ino: u64,
         name: &str,
         value: &[u8],
@@ -188,30 +388,39 @@ pub trait VirtualFs: Sync + Send {
     ) -> DatenLordResult<()> {
         Err(DatenLordError::Unimplemented {
             context: vec!["setxattr unimplemented".to_owned()],
+            source: None,
         })
     }
 
-    /// Get an extended attribute
+    /// Get the value of an extended attribute
     #[allow(unused_variables)]
-    async fn getxattr(&self, ino: u64, name: &str, size: u32) -> DatenLordResult<()> {
+    async fn getxattr(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> DatenLordResult<Vec<u8>> {
         Err(DatenLordError::Unimplemented {
             context: vec!["getxattr unimplemented".to_owned()],
+            source: None,
         })
     }
 
-    /// Get an extended attribute
+    /// List the names of the extended attributes
     #[allow(unused_variables)]
-    async fn listxattr(&self, ino: u64, size: u32) -> DatenLordResult<()> {
+    async fn listxattr(&self, ctx: &RequestContext, ino: u64) -> DatenLordResult<Vec<String>> {
         Err(DatenLordError::Unimplemented {
             context: vec!["listxattr unimplemented".to_owned()],
+            source: None,
         })
     }
 
     /// Remove an extended attribute
     #[allow(unused_variables)]
-    async fn removexattr(&self, ino: u64, name: &str) -> DatenLordResult<()> {
+    async fn removexattr(&self, ctx: &RequestContext, ino: u64, name: &str) -> DatenLordResult<()> {
         Err(DatenLordError::Unimplemented {
             context: vec!["removexattr unimplemented".to_owned()],
+            source: None,
         })
     }
 
@@ -222,9 +431,10 @@ pub trait VirtualFs: Sync + Send {
     /// `default_permissions` mount option is given, self method is not
     /// called. This method is not called under Linux kernel versions 2.4.x
     #[allow(unused_variables)]
-    async fn access(&self, uid: u32, gid: u32, ino: u64, mask: u32) -> DatenLordResult<()> {
+    async fn access(&self, ctx: &RequestContext, ino: u64, mask: u32) -> DatenLordResult<()> {
         Err(DatenLordError::Unimplemented {
             context: vec!["access unimplemented".to_owned()],
+            source: None,
         })
     }
 
@@ -244,16 +454,16 @@ pub trait VirtualFs: Sync + Send {
     #[allow(unused_variables)]
     async fn create(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         ino: u64,
         parent: u64,
-        name: &str,
+        name: &OsStr,
         mode: u32,
         flags: u32,
     ) -> DatenLordResult<()> {
         Err(DatenLordError::Unimplemented {
             context: vec!["create unimplemented".to_owned()],
+            source: None,
         })
     }
 
@@ -261,13 +471,13 @@ pub trait VirtualFs: Sync + Send {
     #[allow(unused_variables)]
     async fn getlk(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         ino: u64,
         lk_param: FileLockParam,
     ) -> DatenLordResult<()> {
         Err(DatenLordError::Unimplemented {
             context: vec!["getlk unimplemented".to_owned()],
+            source: None,
         })
     }
 
@@ -284,14 +494,14 @@ pub trait VirtualFs: Sync + Send {
     #[allow(unused_variables)]
     async fn setlk(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         ino: u64,
         lk_param: FileLockParam,
         sleep: bool,
     ) -> DatenLordResult<()> {
         Err(DatenLordError::Unimplemented {
             context: vec!["setlk unimplemented".to_owned()],
+            source: None,
         })
     }
 
@@ -301,14 +511,83 @@ pub trait VirtualFs: Sync + Send {
     #[allow(unused_variables)]
     async fn bmap(
         &self,
-        uid: u32,
-        gid: u32,
+        ctx: &RequestContext,
         ino: u64,
         blocksize: u32,
         idx: u64,
     ) -> DatenLordResult<()> {
         Err(DatenLordError::Unimplemented {
             context: vec!["bmap unimplemented".to_owned()],
+            source: None,
         })
     }
+
+    /// Run the control command `cmd` on `ino` with the argument `input`,
+    /// returning its output, see the `ioctl` module
+    ///
+    /// Layers answer the built-in commands they know and pass the others
+    /// down, by default to the handler registered for the command.
+    async fn ioctl(
+        &self,
+        ctx: &RequestContext,
+        ino: u64,
+        cmd: u32,
+        input: &[u8],
+    ) -> DatenLordResult<Vec<u8>> {
+        ioctl::dispatch(ctx, ino, cmd, input).await
+    }
+}
+
+/// Whether the entry `name` exists under `parent`, looking it up in `fs`
+pub async fn exists_by_lookup<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    parent: INum,
+    name: &OsStr,
+) -> DatenLordResult<bool> {
+    match fs.lookup(ctx, parent, name).await {
+        Ok(_) => Ok(true),
+        Err(DatenLordError::NotFound { .. }) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Copy the ranges of `param` by reading the source and writing the
+/// destination through `fs`, a block of `fs` at a time
+pub async fn copy_range_through<F: VirtualFs + ?Sized>(
+    fs: &F,
+    ctx: &RequestContext,
+    param: CopyRangeParam,
+) -> DatenLordResult<CopyRangeResult> {
+    let mut buf = vec![0; fs.block_layout().block_len()];
+    let mut copied = 0;
+    while copied < param.len {
+        let want = usize::try_from(param.len - copied)
+            .unwrap_or(usize::MAX)
+            .min(buf.len());
+        let read = fs
+            .read(
+                ctx,
+                param.ino_in,
+                param.fh_in,
+                param.offset_in + copied,
+                u32::try_from(want).unwrap_or(u32::MAX),
+                &mut buf[..want],
+            )
+            .await?;
+        if read == 0 {
+            break;
+        }
+        let offset = param.offset_out + copied;
+        let offset = i64::try_from(offset).map_err(|_| DatenLordError::InvalidArgument {
+            context: vec![format!("invalid write offset={offset}")],
+        })?;
+        fs.write(ctx, param.ino_out, param.fh_out, offset, &buf[..read], 0)
+            .await?;
+        copied += read as u64;
+    }
+    Ok(CopyRangeResult {
+        copied,
+        reflinked: false,
+    })
 }
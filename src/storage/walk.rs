@@ -0,0 +1,505 @@
+//! Recursive directory walks and glob matching on top of `VirtualFs::readdirplus`,
+//! bulk stats and disk usage
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::sync::Arc;
+
+use nix::sys::stat::SFlag;
+use serde_derive::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{self, FileAttr, RequestContext, ROOT_ID};
+use super::timeout;
+use super::virtualfs::{INum, VirtualFs};
+
+/// The number of directories listed concurrently unless told otherwise
+pub const DEFAULT_WALK_CONCURRENCY: usize = 16;
+/// The number of entries buffered ahead of the consumer
+const WALK_BUFFER: usize = 1024;
+
+/// An entry found by a walk
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    /// The path of the entry relative to the root of the filesystem
+    pub path: OsString,
+    /// The attributes of the entry
+    pub attr: FileAttr,
+}
+
+/// The space taken by a file, or by everything below a directory, see
+/// `disk_usage`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskUsage {
+    /// The sizes of the files and symbolic links, a file with several hard
+    /// links counted once
+    pub bytes: u64,
+    /// The bytes of the blocks allocated to them, fewer than `bytes` for
+    /// sparse files
+    pub allocated_bytes: u64,
+    /// The files and symbolic links, hard links counted once
+    pub files: u64,
+    /// The directories, the one walked excluded
+    pub dirs: u64,
+}
+
+impl DiskUsage {
+    /// Count the entry of `attr`
+    fn add(&mut self, attr: &FileAttr) {
+        if attr.kind == SFlag::S_IFDIR {
+            self.dirs += 1;
+        } else {
+            self.bytes += attr.size;
+            self.allocated_bytes += attr.blocks * 512;
+            self.files += 1;
+        }
+    }
+}
+
+/// A running walk yielding entries as directories are listed
+///
+/// Entries come in no particular order. Listing stops when the consumer
+/// falls `WALK_BUFFER` entries behind, and the walk is cancelled when
+/// dropped. A directory that fails to list yields an error and the walk
+/// carries on with the others.
+#[derive(Debug)]
+pub struct Walk {
+    /// The entries found so far
+    entries: mpsc::Receiver<DatenLordResult<WalkEntry>>,
+    /// The task listing the directories
+    task: JoinHandle<()>,
+}
+
+impl Walk {
+    /// The next entry, `None` once the walk is over
+    pub async fn next(&mut self) -> Option<DatenLordResult<WalkEntry>> {
+        self.entries.recv().await
+    }
+
+    /// The next entry, blocking the current thread
+    ///
+    /// Must not be called from inside the runtime running the walk.
+    pub fn blocking_next(&mut self) -> Option<DatenLordResult<WalkEntry>> {
+        self.entries.blocking_recv()
+    }
+}
+
+impl Drop for Walk {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Which entries a walk yields and which directories it descends into
+#[derive(Debug, Clone)]
+enum Filter {
+    /// Every entry below the root
+    All,
+    /// The entries matching a glob pattern, split into path components
+    Glob(Vec<String>),
+}
+
+impl Filter {
+    /// Whether the entry at `path` is yielded
+    fn matches(&self, path: &OsStr) -> bool {
+        match *self {
+            Self::All => true,
+            Self::Glob(ref pattern) => glob_states(pattern, path).contains(&pattern.len()),
+        }
+    }
+
+    /// Whether entries below the directory at `path` may be yielded
+    fn descends(&self, path: &OsStr) -> bool {
+        match *self {
+            Self::All => true,
+            Self::Glob(ref pattern) => glob_states(pattern, path)
+                .iter()
+                .any(|&state| state < pattern.len()),
+        }
+    }
+}
+
+/// Walk every entry below the directory `path` on `runtime` on behalf of
+/// `ctx`, listing at most `concurrency` directories at once
+///
+/// Symbolic links are yielded but not followed.
+pub fn walk<F: VirtualFs + 'static>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    runtime: &Handle,
+    path: &OsStr,
+    concurrency: usize,
+) -> Walk {
+    start(fs, ctx, runtime, fs_util::normalize(path), Filter::All, concurrency)
+}
+
+/// Walk the entries matching `pattern` on `runtime` on behalf of `ctx`,
+/// listing at most `concurrency` directories at once
+///
+/// `?` matches one character and `*` any characters within a path
+/// component, `**` matches any number of whole components. Only the
+/// directories that may hold matches are listed, starting from the longest
+/// leading part of the pattern without wildcards.
+pub fn glob<F: VirtualFs + 'static>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    runtime: &Handle,
+    pattern: &str,
+    concurrency: usize,
+) -> Walk {
+    let components: Vec<String> = pattern
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .map(str::to_owned)
+        .collect();
+    let literal = components
+        .iter()
+        .take_while(|component| !component.contains(['*', '?']))
+        .count()
+        // The last component is kept so a pattern without wildcards still
+        // matches the entry it names
+        .min(components.len().saturating_sub(1));
+    let root = components[..literal].join("/");
+    start(fs, ctx, runtime, root.into(), Filter::Glob(components), concurrency)
+}
+
+/// Stat every path of `paths`, relative to the root, on behalf of `ctx`,
+/// running at most `concurrency` lookups at once on the current runtime
+///
+/// Every distinct parent directory is resolved once and the names looked up
+/// in it, so files sharing a few directories skip resolving the shared
+/// components again. The results come in the order of `paths`, and the
+/// deadline of the caller, if any, bounds every lookup.
+pub async fn stat_many<F: VirtualFs + 'static, P: AsRef<OsStr>>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    paths: &[P],
+    concurrency: usize,
+) -> Vec<DatenLordResult<FileAttr>> {
+    let split: Vec<(OsString, OsString)> = paths
+        .iter()
+        .map(|path| {
+            let components: Vec<&OsStr> = fs_util::components(path.as_ref()).collect();
+            match components.split_last() {
+                Some((name, parents)) => (parents.join(OsStr::new("/")), (*name).to_owned()),
+                None => (OsString::new(), OsString::new()),
+            }
+        })
+        .collect();
+    let parents: Vec<OsString> = split
+        .iter()
+        .map(|(parent, _)| parent)
+        .filter(|parent| !parent.is_empty())
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let parent_lookups = parents.iter().map(|parent| (ROOT_ID, parent.clone())).collect();
+    let resolved = lookup_all(&fs, ctx, parent_lookups, concurrency).await;
+    let dirs: HashMap<&OsStr, INum> = parents
+        .iter()
+        .zip(resolved)
+        .filter_map(|(parent, attr)| Some((parent.as_os_str(), attr.ok()?.ino)))
+        .collect();
+
+    let lookups = split
+        .into_iter()
+        .zip(paths)
+        .map(|((parent, name), path)| {
+            if parent.is_empty() {
+                return (ROOT_ID, name);
+            }
+            match dirs.get(parent.as_os_str()) {
+                Some(&ino) => (ino, name),
+                // Looking the whole path up fails again, telling why for this path
+                None => (ROOT_ID, path.as_ref().to_owned()),
+            }
+        })
+        .collect();
+    lookup_all(&fs, ctx, lookups, concurrency).await
+}
+
+/// The space taken by `path` on behalf of `ctx`, everything below it when a
+/// directory, listing at most `concurrency` directories at once on the
+/// current runtime
+///
+/// There is no count kept up to date as files change, so directories are
+/// walked; the first one failing to list fails the whole count.
+pub async fn disk_usage<F: VirtualFs + 'static>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    path: &OsStr,
+    concurrency: usize,
+) -> DatenLordResult<DiskUsage> {
+    let path = fs_util::normalize(path);
+    let mut usage = DiskUsage::default();
+    if !path.is_empty() {
+        let (_, attr, _) = fs.lookup(&ctx, ROOT_ID, &path).await?;
+        if attr.kind != SFlag::S_IFDIR {
+            usage.add(&attr);
+            return Ok(usage);
+        }
+    }
+    let mut walk = walk(fs, ctx, &Handle::current(), &path, concurrency);
+    let mut linked = HashSet::new();
+    while let Some(entry) = walk.next().await {
+        let attr = entry?.attr;
+        if attr.kind == SFlag::S_IFDIR || attr.nlink <= 1 || linked.insert(attr.ino) {
+            usage.add(&attr);
+        }
+    }
+    Ok(usage)
+}
+
+/// Look up every `(parent, name)` of `lookups`, at most `concurrency` at
+/// once, an empty name standing for the parent itself
+async fn lookup_all<F: VirtualFs + 'static>(
+    fs: &Arc<F>,
+    ctx: RequestContext,
+    lookups: Vec<(INum, OsString)>,
+    concurrency: usize,
+) -> Vec<DatenLordResult<FileAttr>> {
+    let deadline = timeout::deadline();
+    let mut results: Vec<Option<DatenLordResult<FileAttr>>> =
+        std::iter::repeat_with(|| None).take(lookups.len()).collect();
+    let mut pending = lookups.into_iter().enumerate();
+    let mut running = JoinSet::new();
+    loop {
+        while running.len() < concurrency.max(1) {
+            let Some((index, (parent, name))) = pending.next() else {
+                break;
+            };
+            let fs = Arc::clone(fs);
+            running.spawn(async move {
+                let lookup = async {
+                    if name.is_empty() {
+                        fs.getattr(&ctx, parent).await.map(|(_, attr)| attr)
+                    } else {
+                        fs.lookup(&ctx, parent, &name).await.map(|(_, attr, _)| attr)
+                    }
+                };
+                let attr = match deadline {
+                    Some(deadline) => timeout::with_deadline(deadline, lookup).await,
+                    None => lookup.await,
+                };
+                (index, attr)
+            });
+        }
+        match running.join_next().await {
+            Some(Ok((index, attr))) => results[index] = Some(attr),
+            // The lookup is reported below as never finished
+            Some(Err(_)) => {}
+            None => break,
+        }
+    }
+    results
+        .into_iter()
+        .map(|attr| {
+            attr.unwrap_or_else(|| {
+                Err(DatenLordError::Internal {
+                    context: vec!["lookup panicked".to_owned()],
+                })
+            })
+        })
+        .collect()
+}
+
+/// Spawn the task walking below `root` and yielding the entries `filter` admits
+fn start<F: VirtualFs + 'static>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    runtime: &Handle,
+    root: OsString,
+    filter: Filter,
+    concurrency: usize,
+) -> Walk {
+    let (sender, entries) = mpsc::channel(WALK_BUFFER);
+    let task = runtime.spawn(async move {
+        let ino = if root.is_empty() {
+            ROOT_ID
+        } else {
+            match fs.lookup(&ctx, ROOT_ID, &root).await {
+                Ok((_, attr, _)) if attr.kind == SFlag::S_IFDIR => attr.ino,
+                // A glob whose literal prefix does not exist has no matches
+                Ok(_) | Err(_) if matches!(filter, Filter::Glob(_)) => return,
+                Ok(_) => {
+                    let _ = sender
+                        .send(Err(DatenLordError::InvalidArgument {
+                            context: vec![format!("{root:?} is not a directory")],
+                        }))
+                        .await;
+                    return;
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e)).await;
+                    return;
+                }
+            }
+        };
+        run(fs, ctx, sender, ino, root, filter, concurrency.max(1)).await;
+    });
+    Walk { entries, task }
+}
+
+/// List the directories breadth first, at most `concurrency` at once, and
+/// send the admitted entries
+async fn run<F: VirtualFs + 'static>(
+    fs: Arc<F>,
+    ctx: RequestContext,
+    sender: mpsc::Sender<DatenLordResult<WalkEntry>>,
+    root_ino: INum,
+    root: OsString,
+    filter: Filter,
+    concurrency: usize,
+) {
+    let mut pending = VecDeque::from([(root_ino, root)]);
+    let mut listings = JoinSet::new();
+    loop {
+        while listings.len() < concurrency {
+            let Some((ino, dir)) = pending.pop_front() else {
+                break;
+            };
+            let fs = Arc::clone(&fs);
+            listings.spawn(async move {
+                let children = list_dir(&*fs, &ctx, ino).await;
+                (dir, children)
+            });
+        }
+        let Some(listing) = listings.join_next().await else {
+            return;
+        };
+        let (dir, children) = match listing {
+            Ok(listing) => listing,
+            Err(e) => {
+                let _ = sender
+                    .send(Err(DatenLordError::Internal {
+                        context: vec![format!("directory listing panicked: {e}")],
+                    }))
+                    .await;
+                continue;
+            }
+        };
+        let children = match children {
+            Ok(children) => children,
+            Err(e) => {
+                if sender.send(Err(e)).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+        };
+        for (name, attr) in children {
+            let path = if dir.is_empty() {
+                name
+            } else {
+                let mut path = dir.clone();
+                path.push("/");
+                path.push(name);
+                path
+            };
+            if attr.kind == SFlag::S_IFDIR && filter.descends(&path) {
+                pending.push_back((attr.ino, path.clone()));
+            }
+            if filter.matches(&path) && sender.send(Ok(WalkEntry { path, attr })).await.is_err() {
+                // The consumer is gone
+                return;
+            }
+        }
+    }
+}
+
+/// List every entry of directory `ino` with its attributes
+async fn list_dir<F: VirtualFs>(
+    fs: &F,
+    ctx: &RequestContext,
+    ino: INum,
+) -> DatenLordResult<Vec<(OsString, FileAttr)>> {
+    let fh = fs.opendir(ctx, ino, 0).await?;
+    let mut children = Vec::new();
+    let listed = loop {
+        let offset = i64::try_from(children.len()).unwrap_or(i64::MAX);
+        match fs.readdirplus(ctx, ino, fh, offset).await {
+            Ok(entries) if entries.is_empty() => break Ok(()),
+            Ok(entries) => {
+                children.extend(entries.into_iter().map(|(entry, attr, _)| (entry.name, attr)));
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    fs.releasedir(ctx, ino, fh, 0).await?;
+    listed.map(|()| children)
+}
+
+/// The positions in `pattern` reachable after matching the components of
+/// `path`, `pattern.len()` meaning the whole pattern matched
+///
+/// Components that are not UTF-8 are matched with their invalid bytes replaced.
+fn glob_states(pattern: &[String], path: &OsStr) -> Vec<usize> {
+    let mut states = skip_globstars(pattern, vec![0]);
+    for component in fs_util::components(path) {
+        let component = component.to_string_lossy();
+        let component = component.as_ref();
+        let mut next = Vec::new();
+        for &state in &states {
+            match pattern.get(state).map(String::as_str) {
+                // `**` consumes the component and stays
+                Some("**") => next.push(state),
+                Some(segment) if match_component(segment, component) => next.push(state + 1),
+                _ => {}
+            }
+        }
+        states = skip_globstars(pattern, next);
+        if states.is_empty() {
+            break;
+        }
+    }
+    states
+}
+
+/// Add the positions after the `**` components `states` point to, since
+/// `**` also matches no component at all
+fn skip_globstars(pattern: &[String], mut states: Vec<usize>) -> Vec<usize> {
+    let mut i = 0;
+    while let Some(&state) = states.get(i) {
+        if pattern.get(state).is_some_and(|segment| segment == "**") && !states.contains(&(state + 1)) {
+            states.push(state + 1);
+        }
+        i += 1;
+    }
+    states.sort_unstable();
+    states.dedup();
+    states
+}
+
+/// Whether the path component `name` matches `segment` with `*` and `?` wildcards
+pub(crate) fn match_component(segment: &str, name: &str) -> bool {
+    let (segment, name): (Vec<char>, Vec<char>) = (segment.chars().collect(), name.chars().collect());
+    let (mut s, mut n) = (0, 0);
+    // The position of the last `*` and the name position it was tried at
+    let mut backtrack = None;
+    while n < name.len() {
+        match segment.get(s) {
+            Some('*') => {
+                backtrack = Some((s, n));
+                s += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                s += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character
+                Some((star, tried)) => {
+                    backtrack = Some((star, tried + 1));
+                    s = star + 1;
+                    n = tried + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    segment[s..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,321 @@
+//! Wire representations of attributes, entries and parameters
+//!
+//! Every message is a protobuf message through `prost` and serializable with
+//! `serde`, for gateways and persistent caches. Times are seconds and
+//! nanoseconds relative to the Unix epoch, see `fs_util::to_timespec`, so
+//! times before the epoch survive the trip.
+use std::ffi::OsString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::time::SystemTime;
+
+use nix::sys::stat::SFlag;
+use prost::Message;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+use super::fs_util::{self, DirEntry, FileAttr, FileKind, SetAttrParam, StatFsParam};
+
+/// A point in time
+#[derive(Clone, Copy, PartialEq, Eq, Message, Serialize, Deserialize)]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch, negative before it
+    #[prost(int64, tag = "1")]
+    pub sec: i64,
+    /// Nanoseconds counting forward from `sec`, below one second
+    #[prost(uint32, tag = "2")]
+    pub nsec: u32,
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        let (sec, nsec) = fs_util::to_timespec(time);
+        Self { sec, nsec }
+    }
+}
+
+impl TryFrom<Timestamp> for SystemTime {
+    type Error = DatenLordError;
+
+    fn try_from(time: Timestamp) -> DatenLordResult<Self> {
+        fs_util::from_timespec(time.sec, time.nsec)
+            .ok_or_else(|| corrupted(format!("invalid time sec={} nsec={}", time.sec, time.nsec)))
+    }
+}
+
+/// The attributes of a file, see `FileAttr`
+#[derive(Clone, PartialEq, Eq, Message, Serialize, Deserialize)]
+pub struct Attr {
+    /// Inode number
+    #[prost(uint64, tag = "1")]
+    pub ino: u64,
+    /// Size in bytes
+    #[prost(uint64, tag = "2")]
+    pub size: u64,
+    /// Size in blocks
+    #[prost(uint64, tag = "3")]
+    pub blocks: u64,
+    /// Time of last access
+    #[prost(message, optional, tag = "4")]
+    pub atime: Option<Timestamp>,
+    /// Time of last modification
+    #[prost(message, optional, tag = "5")]
+    pub mtime: Option<Timestamp>,
+    /// Time of last change
+    #[prost(message, optional, tag = "6")]
+    pub ctime: Option<Timestamp>,
+    /// The file type bits of `st_mode`
+    #[prost(uint32, tag = "7")]
+    pub kind: u32,
+    /// Permissions
+    #[prost(uint32, tag = "8")]
+    pub perm: u32,
+    /// Number of hard links
+    #[prost(uint32, tag = "9")]
+    pub nlink: u32,
+    /// User id
+    #[prost(uint32, tag = "10")]
+    pub uid: u32,
+    /// Group id
+    #[prost(uint32, tag = "11")]
+    pub gid: u32,
+    /// Rdev
+    #[prost(uint32, tag = "12")]
+    pub rdev: u32,
+    /// The generation of the entries of a directory, 0 for other files
+    #[prost(uint64, tag = "13")]
+    pub generation: u64,
+}
+
+impl From<&FileAttr> for Attr {
+    fn from(attr: &FileAttr) -> Self {
+        Self {
+            ino: attr.ino,
+            size: attr.size,
+            blocks: attr.blocks,
+            atime: Some(attr.atime.into()),
+            mtime: Some(attr.mtime.into()),
+            ctime: Some(attr.ctime.into()),
+            kind: attr.kind.bits(),
+            perm: attr.perm.into(),
+            nlink: attr.nlink,
+            uid: attr.uid,
+            gid: attr.gid,
+            rdev: attr.rdev,
+            generation: attr.generation,
+        }
+    }
+}
+
+impl TryFrom<Attr> for FileAttr {
+    type Error = DatenLordError;
+
+    fn try_from(attr: Attr) -> DatenLordResult<Self> {
+        let kind = SFlag::from_bits(attr.kind)
+            .filter(|kind| FileKind::from_sflag(*kind).is_some())
+            .ok_or_else(|| corrupted(format!("invalid file type bits {:o}", attr.kind)))?;
+        let perm = u16::try_from(attr.perm)
+            .ok()
+            .filter(|perm| perm & !0o7777 == 0)
+            .ok_or_else(|| corrupted(format!("invalid permissions {:o}", attr.perm)))?;
+        Ok(Self {
+            ino: attr.ino,
+            size: attr.size,
+            blocks: attr.blocks,
+            atime: time(attr.atime, "atime")?,
+            mtime: time(attr.mtime, "mtime")?,
+            ctime: time(attr.ctime, "ctime")?,
+            kind,
+            perm,
+            nlink: attr.nlink,
+            uid: attr.uid,
+            gid: attr.gid,
+            rdev: attr.rdev,
+            generation: attr.generation,
+        })
+    }
+}
+
+/// An entry of a directory listing, see `DirEntry`
+#[derive(Clone, PartialEq, Eq, Message, Serialize, Deserialize)]
+pub struct Entry {
+    /// The name as on disk, which need not be UTF-8
+    #[prost(bytes = "vec", tag = "1")]
+    pub name: Vec<u8>,
+    /// The inode number of the child
+    #[prost(uint64, tag = "2")]
+    pub ino: u64,
+    /// The name of the kind, see `FileKind::name`
+    #[prost(string, tag = "3")]
+    pub kind: String,
+    /// The attributes of the child, if listed with them
+    #[prost(message, optional, tag = "4")]
+    pub attr: Option<Attr>,
+}
+
+impl From<&DirEntry> for Entry {
+    fn from(entry: &DirEntry) -> Self {
+        Self {
+            name: entry.name.as_bytes().to_vec(),
+            ino: entry.ino,
+            kind: entry.kind.name().to_owned(),
+            attr: entry.attr.as_ref().map(Attr::from),
+        }
+    }
+}
+
+impl TryFrom<Entry> for DirEntry {
+    type Error = DatenLordError;
+
+    fn try_from(entry: Entry) -> DatenLordResult<Self> {
+        let kind = FileKind::from_name(&entry.kind)
+            .ok_or_else(|| corrupted(format!("invalid file kind {:?}", entry.kind)))?;
+        Ok(Self {
+            name: OsString::from_vec(entry.name),
+            ino: entry.ino,
+            kind,
+            attr: entry.attr.map(FileAttr::try_from).transpose()?,
+        })
+    }
+}
+
+/// The attributes to set, see `SetAttrParam`
+#[derive(Clone, PartialEq, Eq, Message, Serialize, Deserialize)]
+pub struct SetAttr {
+    /// FUSE set attribute bit mask
+    #[prost(uint32, tag = "1")]
+    pub valid: u32,
+    /// File handler
+    #[prost(uint64, optional, tag = "2")]
+    pub fh: Option<u64>,
+    /// File mode
+    #[prost(uint32, optional, tag = "3")]
+    pub mode: Option<u32>,
+    /// User ID
+    #[prost(uint32, optional, tag = "4")]
+    pub uid: Option<u32>,
+    /// Group ID
+    #[prost(uint32, optional, tag = "5")]
+    pub gid: Option<u32>,
+    /// File size
+    #[prost(uint64, optional, tag = "6")]
+    pub size: Option<u64>,
+    /// Access time
+    #[prost(message, optional, tag = "7")]
+    pub atime: Option<Timestamp>,
+    /// Content modified time
+    #[prost(message, optional, tag = "8")]
+    pub mtime: Option<Timestamp>,
+}
+
+impl From<&SetAttrParam> for SetAttr {
+    fn from(param: &SetAttrParam) -> Self {
+        Self {
+            valid: param.valid,
+            fh: param.fh,
+            mode: param.mode,
+            uid: param.u_id,
+            gid: param.g_id,
+            size: param.size,
+            atime: param.a_time.map(Timestamp::from),
+            mtime: param.m_time.map(Timestamp::from),
+        }
+    }
+}
+
+impl TryFrom<SetAttr> for SetAttrParam {
+    type Error = DatenLordError;
+
+    // The update fills the fields of the `abi-7-*` features
+    #[allow(clippy::needless_update)]
+    fn try_from(param: SetAttr) -> DatenLordResult<Self> {
+        Ok(Self {
+            valid: param.valid,
+            fh: param.fh,
+            mode: param.mode,
+            u_id: param.uid,
+            g_id: param.gid,
+            size: param.size,
+            a_time: param.atime.map(SystemTime::try_from).transpose()?,
+            m_time: param.mtime.map(SystemTime::try_from).transpose()?,
+            ..Self::default()
+        })
+    }
+}
+
+/// The usage of a filesystem, see `StatFsParam`
+#[derive(Clone, Copy, PartialEq, Eq, Message, Serialize, Deserialize)]
+pub struct StatFs {
+    /// The number of blocks in the filesystem
+    #[prost(uint64, tag = "1")]
+    pub blocks: u64,
+    /// The number of free blocks
+    #[prost(uint64, tag = "2")]
+    pub bfree: u64,
+    /// The number of free blocks for non-privilege users
+    #[prost(uint64, tag = "3")]
+    pub bavail: u64,
+    /// The number of inodes
+    #[prost(uint64, tag = "4")]
+    pub files: u64,
+    /// The number of free inodes
+    #[prost(uint64, tag = "5")]
+    pub f_free: u64,
+    /// Block size
+    #[prost(uint32, tag = "6")]
+    pub bsize: u32,
+    /// Maximum file name length
+    #[prost(uint32, tag = "7")]
+    pub namelen: u32,
+    /// Fragment size
+    #[prost(uint32, tag = "8")]
+    pub frsize: u32,
+}
+
+impl From<&StatFsParam> for StatFs {
+    fn from(param: &StatFsParam) -> Self {
+        Self {
+            blocks: param.blocks,
+            bfree: param.bfree,
+            bavail: param.bavail,
+            files: param.files,
+            f_free: param.f_free,
+            bsize: param.bsize,
+            namelen: param.namelen,
+            frsize: param.frsize,
+        }
+    }
+}
+
+impl From<StatFs> for StatFsParam {
+    fn from(stat: StatFs) -> Self {
+        Self {
+            blocks: stat.blocks,
+            bfree: stat.bfree,
+            bavail: stat.bavail,
+            files: stat.files,
+            f_free: stat.f_free,
+            bsize: stat.bsize,
+            namelen: stat.namelen,
+            frsize: stat.frsize,
+        }
+    }
+}
+
+/// Decode the message `M` from `bytes`
+pub fn decode<M: Message + Default>(bytes: &[u8]) -> DatenLordResult<M> {
+    M::decode(bytes).map_err(|e| corrupted(format!("failed to decode message: {e}")))
+}
+
+/// The time of the required field `field`
+fn time(time: Option<Timestamp>, field: &str) -> DatenLordResult<SystemTime> {
+    time.ok_or_else(|| corrupted(format!("missing {field}")))?
+        .try_into()
+}
+
+/// The error of a malformed message
+fn corrupted(context: String) -> DatenLordError {
+    DatenLordError::Corrupted {
+        context: vec![context],
+    }
+}
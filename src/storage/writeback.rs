@@ -0,0 +1,203 @@
+//! Background writeback of the data written through open file handles
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+use tokio::sync::{oneshot, Notify};
+use tracing::{debug, warn};
+
+use crate::common::{DatenLordError, DatenLordResult};
+
+/// Default time between two periodic writebacks, five seconds
+const DEFAULT_INTERVAL_MS: u64 = 5000;
+/// Default amount of unsynced data triggering a writeback, 64 MiB
+const DEFAULT_DIRTY_HIGH_WATERMARK: u64 = 64 << 20;
+
+/// When the data written through open file handles is synced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WritebackConfig {
+    /// The time between two periodic writebacks in milliseconds, 0 disables
+    /// them
+    pub interval_ms: u64,
+    /// The bytes written but not synced yet that trigger a writeback right
+    /// away, 0 disables the trigger
+    pub dirty_high_watermark: u64,
+}
+
+impl Default for WritebackConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: DEFAULT_INTERVAL_MS,
+            dirty_high_watermark: DEFAULT_DIRTY_HIGH_WATERMARK,
+        }
+    }
+}
+
+/// The bytes written through the open file handles of a filesystem and not
+/// synced yet
+#[derive(Debug)]
+pub struct DirtyBytes {
+    /// The unsynced bytes
+    bytes: AtomicU64,
+    /// The bytes waking the writeback up, 0 for never
+    high_watermark: u64,
+    /// Wakes the writeback up
+    wakeup: Notify,
+}
+
+impl DirtyBytes {
+    /// Track unsynced bytes, waking the writeback up past `high_watermark`
+    pub fn new(high_watermark: u64) -> Self {
+        Self {
+            bytes: AtomicU64::new(0),
+            high_watermark,
+            wakeup: Notify::new(),
+        }
+    }
+
+    /// The unsynced bytes
+    pub fn get(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Record `bytes` more unsynced bytes
+    pub fn add(&self, bytes: u64) {
+        let total = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if self.high_watermark != 0 && total >= self.high_watermark {
+            self.wakeup.notify_one();
+        }
+    }
+
+    /// Record `bytes` unsynced bytes as synced or forgotten
+    pub fn sub(&self, bytes: u64) {
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// A flush of the unsynced data of a filesystem, returning the bytes synced
+type Flush = dyn Fn() -> DatenLordResult<u64> + Send + Sync;
+
+/// The flushes of every running writeback task with the id of the process
+/// that started it, see `flush_all`
+static TASKS: Mutex<Vec<(u32, Weak<Flush>)>> = Mutex::new(Vec::new());
+
+/// Flush the unsynced data of every filesystem with a writeback task, e.g.
+/// when the process is about to exit
+///
+/// Tasks a forked child inherited are skipped, their data is the parent's
+/// to write back.
+pub fn flush_all() {
+    let pid = std::process::id();
+    let flushes: Vec<Arc<Flush>> = {
+        let mut tasks = TASKS.lock().unwrap();
+        tasks.retain(|(_, flush)| flush.strong_count() > 0);
+        tasks
+            .iter()
+            .filter(|(started_by, _)| *started_by == pid)
+            .filter_map(|(_, flush)| flush.upgrade())
+            .collect()
+    };
+    for flush in flushes {
+        if let Err(e) = flush() {
+            warn!("writeback failed: {e}");
+        }
+    }
+}
+
+/// The background writeback of a filesystem, flushing a last time when
+/// dropped
+///
+/// The unsynced data is flushed every `interval_ms`, and as soon as it
+/// reaches `dirty_high_watermark`, on a thread of its own.
+pub struct WritebackTask {
+    /// The flush of the filesystem
+    flush: Arc<Flush>,
+    /// Dropped to stop the thread
+    stop: Option<oneshot::Sender<()>>,
+    /// The thread flushing in the background, if enabled
+    thread: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for WritebackTask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WritebackTask")
+            .field("running", &self.thread.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl WritebackTask {
+    /// Start flushing the data `dirty` counts with `flush` as `config` says
+    pub fn start(
+        dirty: Arc<DirtyBytes>,
+        flush: impl Fn() -> DatenLordResult<u64> + Send + Sync + 'static,
+        config: WritebackConfig,
+    ) -> DatenLordResult<Self> {
+        let flush: Arc<Flush> = Arc::new(flush);
+        TASKS
+            .lock()
+            .unwrap()
+            .push((std::process::id(), Arc::downgrade(&flush)));
+        let mut task = Self {
+            flush: Arc::clone(&flush),
+            stop: None,
+            thread: None,
+        };
+        if config.interval_ms == 0 && config.dirty_high_watermark == 0 {
+            return Ok(task);
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the writeback runtime: {e}")],
+            })?;
+        let (stop, mut stopped) = oneshot::channel();
+        let interval = (config.interval_ms != 0).then(|| Duration::from_millis(config.interval_ms));
+        let thread = std::thread::Builder::new()
+            .name("datenlord-writeback".to_owned())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    loop {
+                        let tick = async {
+                            match interval {
+                                Some(interval) => tokio::time::sleep(interval).await,
+                                None => std::future::pending().await,
+                            }
+                        };
+                        tokio::select! {
+                            _ = &mut stopped => return,
+                            () = tick => {}
+                            () = dirty.wakeup.notified() => {}
+                        }
+                        match flush() {
+                            Ok(0) => {}
+                            Ok(bytes) => debug!("wrote back {bytes} bytes"),
+                            Err(e) => warn!("writeback failed: {e}"),
+                        }
+                    }
+                });
+            })
+            .map_err(|e| DatenLordError::Internal {
+                context: vec![format!("failed to start the writeback thread: {e}")],
+            })?;
+        task.stop = Some(stop);
+        task.thread = Some(thread);
+        Ok(task)
+    }
+}
+
+impl Drop for WritebackTask {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Err(e) = (self.flush)() {
+            warn!("final writeback failed: {e}");
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Extended attributes of local files, never following a final symbolic link
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use rustix::fs::XattrFlags;
+use rustix::io::Errno;
+
+use super::platform::NO_XATTR;
+
+/// The value of the attribute `name` of `path`, `None` if it has none
+pub(crate) fn get(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    loop {
+        let len = match rustix::fs::lgetxattr(path, name, &mut []) {
+            Ok(len) => len,
+            Err(NO_XATTR) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut value = vec![0; len];
+        match rustix::fs::lgetxattr(path, name, &mut value) {
+            Ok(len) => {
+                value.truncate(len);
+                return Ok(Some(value));
+            }
+            // The value grew in between, ask for its size again
+            Err(Errno::RANGE) => {}
+            Err(NO_XATTR) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// The names of the attributes of `path`
+pub(crate) fn list(path: &Path) -> io::Result<Vec<String>> {
+    loop {
+        let len = rustix::fs::llistxattr(path, &mut [])?;
+        let mut names = vec![0; len];
+        match rustix::fs::llistxattr(path, &mut names) {
+            Ok(len) => {
+                names.truncate(len);
+                return Ok(names
+                    .split(|&c| c == 0)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| String::from_utf8_lossy(name).into_owned())
+                    .collect());
+            }
+            Err(Errno::RANGE) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Set the attribute `name` of `path` to `value`, `flags` being
+/// `XATTR_CREATE` or `XATTR_REPLACE` of `setxattr(2)`
+pub(crate) fn set(path: &Path, name: &str, value: &[u8], flags: u32) -> io::Result<()> {
+    rustix::fs::lsetxattr(path, name, value, XattrFlags::from_bits_retain(flags))?;
+    Ok(())
+}
+
+/// Remove the attribute `name` of `path`
+pub(crate) fn remove(path: &Path, name: &str) -> io::Result<()> {
+    rustix::fs::lremovexattr(path, name)?;
+    Ok(())
+}
+
+/// Remove the attribute `name` of `path` if it has one, extended attributes
+/// being unsupported counting as not having it
+pub(crate) fn remove_if_any(path: &Path, name: &str) -> io::Result<()> {
+    match rustix::fs::lremovexattr(path, name) {
+        Ok(()) | Err(NO_XATTR | Errno::OPNOTSUPP) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Like `remove_if_any`, for the open file `file`
+pub(crate) fn fremove_if_any(file: &File, name: &str) -> io::Result<()> {
+    match rustix::fs::fremovexattr(file, name) {
+        Ok(()) | Err(NO_XATTR | Errno::OPNOTSUPP) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
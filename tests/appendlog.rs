@@ -0,0 +1,224 @@
+//! Appends records to logs in order and reads them back
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::appendlog::{AppendLog, LogRecord, LogSync, SharedLogs, MAX_RECORD_SIZE};
+use datenlord::storage::fs_util::{CreateParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::sys::stat::SFlag;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("datenlord-appendlog-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    /// The local filesystem of the root
+    fn open(&self) -> Arc<LocalFS> {
+        let config = DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        };
+        Arc::new(LocalFS::new(&config).unwrap())
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_appends_get_distinct_ordered_offsets() {
+    let root = Root::new("order");
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    let log = AppendLog::open(Arc::clone(&fs), ctx, OsStr::new("events.log"), LogSync::Batch)
+        .await
+        .unwrap();
+    let log = Arc::new(log);
+    assert_eq!(log.end(), 0);
+
+    let mut tasks = Vec::new();
+    for task in 0..8_u8 {
+        let log = Arc::clone(&log);
+        tasks.push(tokio::spawn(async move {
+            let mut offsets = Vec::new();
+            for i in 0..50_u8 {
+                offsets.push(log.append(&[task, i]).await.unwrap());
+            }
+            offsets
+        }));
+    }
+    let mut offsets = Vec::new();
+    for task in tasks {
+        let appended = task.await.unwrap();
+        // The appends of one task land in the order they were made
+        assert!(appended.windows(2).all(|pair| pair[0] < pair[1]));
+        offsets.extend(appended);
+    }
+    offsets.sort_unstable();
+    offsets.dedup();
+    assert_eq!(offsets.len(), 400);
+    assert_eq!(log.end(), 400 * 10);
+
+    let records = log.read_from(0, usize::MAX).await.unwrap();
+    assert_eq!(records.len(), 400);
+    assert_eq!(records.iter().map(|record| record.offset).collect::<Vec<_>>(), offsets);
+    let tail = log.read_from(offsets[397], 10).await.unwrap();
+    assert_eq!(tail.len(), 3);
+    assert!(log.read_from(log.end(), 10).await.unwrap().is_empty());
+    assert!(matches!(log.read_from(1, 1).await, Err(DatenLordError::InvalidArgument { .. })));
+    let past_end = log.read_from(log.end() + 1, 1).await;
+    assert!(matches!(past_end, Err(DatenLordError::InvalidArgument { .. })));
+
+    let oversized = vec![0; MAX_RECORD_SIZE + 1];
+    assert!(log.append(&oversized).await.is_err());
+    Arc::into_inner(log).unwrap().close().await.unwrap();
+}
+
+#[tokio::test]
+async fn reopening_drops_a_torn_record() {
+    let root = Root::new("torn");
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    let log = AppendLog::open(Arc::clone(&fs), ctx, OsStr::new("wal"), LogSync::None)
+        .await
+        .unwrap();
+    assert_eq!(log.append(b"first").await.unwrap(), 0);
+    assert_eq!(log.append(b"second").await.unwrap(), 13);
+    log.close().await.unwrap();
+
+    // A crash half way through a third record
+    let path = root.0.join("wal");
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes.extend_from_slice(&[5, 0, 0, 0, 1, 2]);
+    std::fs::write(&path, &bytes).unwrap();
+
+    let log = AppendLog::open(Arc::clone(&fs), ctx, OsStr::new("wal"), LogSync::Batch)
+        .await
+        .unwrap();
+    assert_eq!(log.end(), 27);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 27);
+    assert_eq!(log.append(b"third").await.unwrap(), 27);
+    let records = log.read_from(0, 10).await.unwrap();
+    assert_eq!(
+        records,
+        vec![
+            LogRecord { offset: 0, data: b"first".to_vec() },
+            LogRecord { offset: 13, data: b"second".to_vec() },
+            LogRecord { offset: 27, data: b"third".to_vec() },
+        ]
+    );
+    log.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn interval_sync_and_explicit_sync() {
+    let root = Root::new("interval");
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    let sync = LogSync::Interval(Duration::from_millis(20));
+    let log = AppendLog::open(Arc::clone(&fs), ctx, OsStr::new("metrics"), sync).await.unwrap();
+    for i in 0..10_u32 {
+        log.append(&i.to_le_bytes()).await.unwrap();
+    }
+    log.sync().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(log.read_from(0, 100).await.unwrap().len(), 10);
+    log.close().await.unwrap();
+
+    // Opening a directory as a log fails
+    let dir = CreateParam {
+        parent: ROOT_ID,
+        name: "dir".into(),
+        mode: 0o777,
+        rdev: 0,
+        node_type: SFlag::S_IFDIR,
+        link: None,
+    };
+    fs.mkdir(&ctx, dir).await.unwrap();
+    assert!(AppendLog::open(fs, ctx, OsStr::new("dir"), LogSync::None).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shared_logs_order_appends_by_path_and_tail_them() {
+    let root = Root::new("shared");
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    let logs = Arc::new(SharedLogs::new(Arc::clone(&fs), ctx, LogSync::Batch));
+    let mut tail = logs.tail(OsStr::new("events.log"), 0).await.unwrap();
+    assert!(logs.tail(OsStr::new("events.log"), 1).await.is_err());
+
+    let mut tasks = Vec::new();
+    for task in 0..4_u8 {
+        let logs = Arc::clone(&logs);
+        tasks.push(tokio::spawn(async move {
+            for i in 0..25_u8 {
+                logs.append(OsStr::new("events.log"), &[task, i]).await.unwrap();
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    // The tail sees every append once, in the order of the offsets
+    let mut seen = Vec::new();
+    while seen.len() < 100 {
+        let record = tokio::time::timeout(Duration::from_secs(5), tail.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.data.len(), 2);
+        seen.push(record);
+    }
+    assert!(seen.windows(2).all(|pair| pair[0].offset < pair[1].offset));
+    for task in 0..4_u8 {
+        let order: Vec<u8> = seen.iter().filter(|record| record.data[0] == task).map(|record| record.data[1]).collect();
+        assert_eq!(order, (0..25).collect::<Vec<_>>());
+    }
+    assert_eq!(tail.offset(), logs.log(OsStr::new("events.log")).await.unwrap().end());
+
+    // Records appended later wake the tail up
+    let waiting = tokio::spawn(async move { tail.next().await.unwrap().unwrap().data });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    logs.append(OsStr::new("events.log"), b"late").await.unwrap();
+    assert_eq!(waiting.await.unwrap(), b"late");
+    logs.close().await.unwrap();
+
+    // Reopened, the log starts from the records written
+    let reopened = SharedLogs::new(fs, ctx, LogSync::None);
+    let mut tail = reopened.tail(OsStr::new("events.log"), 0).await.unwrap();
+    assert_eq!(tail.next().await.unwrap().unwrap().data.len(), 2);
+    reopened.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn client_appends_and_tails_by_path() {
+    let root = Root::new("client");
+    let config = DatenLordConfig {
+        root: root.0.clone(),
+        ..DatenLordConfig::default()
+    };
+    let client = Client::new(&config).unwrap();
+    let first = client.append("journal", b"one").await.unwrap();
+    let second = client.clone().append("journal", b"two").await.unwrap();
+    assert_eq!(first, 0);
+    let mut tail = client.tail("journal", second).await.unwrap();
+    let record = tail.next().await.unwrap().unwrap();
+    assert_eq!((record.offset, record.data), (second, b"two".to_vec()));
+    assert!(client.tail("missing-dir/journal", 0).await.is_err());
+}
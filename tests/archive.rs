@@ -0,0 +1,182 @@
+//! Exports directory trees to tar archives and imports them back
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::storage::archive::{self, ArchiveReport};
+use datenlord::storage::fs_util::{CreateParam, RequestContext, SetAttrParam, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::{INum, VirtualFs};
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> (Self, LocalFS) {
+        let root =
+            std::env::temp_dir().join(format!("datenlord-archive-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let config = DatenLordConfig {
+            root: root.clone(),
+            ..DatenLordConfig::default()
+        };
+        let fs = LocalFS::new(&config).unwrap();
+        (Self(root), fs)
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Create the file `path` holding `data` with the mode `mode`
+async fn write_file(fs: &LocalFS, ctx: &RequestContext, path: &str, data: &[u8], mode: u32) -> INum {
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: path.into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let ino = fs.mknod(ctx, param).await.unwrap().1.ino;
+    let fh = fs.open(ctx, ino, OFlag::O_WRONLY.bits() as u32).await.unwrap();
+    fs.write(ctx, ino, fh, 0, data, 0).await.unwrap();
+    fs.release(ctx, ino, fh, 0, 0, true).await.unwrap();
+    let param = SetAttrParam {
+        mode: Some(mode),
+        ..SetAttrParam::default()
+    };
+    fs.setattr(ctx, ino, param).await.unwrap();
+    ino
+}
+
+/// The contents of the file `path`
+async fn read_file(fs: &LocalFS, ctx: &RequestContext, path: &str) -> Vec<u8> {
+    let (_, attr, _) = fs.lookup(ctx, ROOT_ID, OsStr::new(path)).await.unwrap();
+    let fh = fs.open(ctx, attr.ino, OFlag::O_RDONLY.bits() as u32).await.unwrap();
+    let mut buf = vec![0; usize::try_from(attr.size).unwrap() + 1];
+    let read = fs
+        .read(ctx, attr.ino, fh, 0, u32::try_from(buf.len()).unwrap(), &mut buf)
+        .await
+        .unwrap();
+    fs.release(ctx, attr.ino, fh, 0, 0, true).await.unwrap();
+    buf.truncate(read);
+    buf
+}
+
+#[tokio::test]
+async fn trees_round_trip_with_their_attributes() {
+    let (_root, fs) = Root::new("round-trip");
+    let ctx = RequestContext::current();
+    let long_name = format!("nested/{}", "n".repeat(150));
+    let mtime = UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789);
+
+    fs.mkdir_all(&ctx, ROOT_ID, OsStr::new("src/nested/empty"), 0o755).await.unwrap();
+    write_file(&fs, &ctx, "src/hello.txt", b"hello archive", 0o640).await;
+    let script = write_file(&fs, &ctx, "src/nested/run.sh", b"#!/bin/sh\n", 0o755).await;
+    let param = SetAttrParam {
+        m_time: Some(mtime),
+        ..SetAttrParam::default()
+    };
+    fs.setattr(&ctx, script, param).await.unwrap();
+    let big: Vec<u8> = (0..200_000_u32).map(|i| (i % 251) as u8).collect();
+    write_file(&fs, &ctx, &format!("src/{long_name}"), &big, 0o600).await;
+    let (_, nested, _) = fs.lookup(&ctx, ROOT_ID, OsStr::new("src/nested")).await.unwrap();
+    fs.symlink(&ctx, nested.ino, OsStr::new("link"), Path::new("../hello.txt"))
+        .await
+        .unwrap();
+
+    let mut tar = Vec::new();
+    let exported = archive::export_tar(&fs, &ctx, OsStr::new("src"), &mut tar).await.unwrap();
+    let expected = ArchiveReport {
+        files: 3,
+        dirs: 2,
+        symlinks: 1,
+        bytes: 13 + 10 + 200_000,
+        skipped: 0,
+    };
+    assert_eq!(exported, expected);
+    assert_eq!(tar.len() % 512, 0);
+
+    let imported = archive::import_tar(&fs, &ctx, &mut tar.as_slice(), OsStr::new("dst/copy"))
+        .await
+        .unwrap();
+    assert_eq!(imported, expected);
+
+    assert_eq!(read_file(&fs, &ctx, "dst/copy/hello.txt").await, b"hello archive");
+    assert_eq!(read_file(&fs, &ctx, &format!("dst/copy/{long_name}")).await, big);
+    let (_, run, _) = fs.lookup(&ctx, ROOT_ID, OsStr::new("dst/copy/nested/run.sh")).await.unwrap();
+    assert_eq!(run.perm, 0o755);
+    assert_eq!(run.mtime, mtime);
+    let (_, hello, _) = fs.lookup(&ctx, ROOT_ID, OsStr::new("dst/copy/hello.txt")).await.unwrap();
+    assert_eq!(hello.perm, 0o640);
+    let (_, empty, _) = fs.lookup(&ctx, ROOT_ID, OsStr::new("dst/copy/nested/empty")).await.unwrap();
+    assert_eq!(empty.kind, SFlag::S_IFDIR);
+    assert_eq!(empty.perm, 0o755);
+    let (_, link, _) = fs.lookup(&ctx, ROOT_ID, OsStr::new("dst/copy/nested/link")).await.unwrap();
+    assert_eq!(link.kind, SFlag::S_IFLNK);
+    assert_eq!(fs.readlink(&ctx, link.ino).await.unwrap(), b"../hello.txt");
+
+    // Importing again replaces the files
+    write_file(&fs, &ctx, "src/hello.txt.new", b"", 0o644).await;
+    let again = archive::import_tar(&fs, &ctx, &mut tar.as_slice(), OsStr::new("dst/copy")).await;
+    assert_eq!(again.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn a_single_file_is_archived_by_its_name() {
+    let (_root, fs) = Root::new("single");
+    let ctx = RequestContext::current();
+    fs.mkdir_all(&ctx, ROOT_ID, OsStr::new("a/b"), 0o755).await.unwrap();
+    write_file(&fs, &ctx, "a/b/data.bin", b"payload", 0o644).await;
+
+    let mut tar = Vec::new();
+    archive::export_tar(&fs, &ctx, OsStr::new("a/b/data.bin"), &mut tar).await.unwrap();
+    assert_eq!(&tar[..9], b"data.bin\0");
+    archive::import_tar(&fs, &ctx, &mut tar.as_slice(), OsStr::new("")).await.unwrap();
+    assert_eq!(read_file(&fs, &ctx, "data.bin").await, b"payload");
+}
+
+#[tokio::test]
+async fn entries_leading_out_of_the_destination_are_skipped() {
+    let (_root, fs) = Root::new("escape");
+    let ctx = RequestContext::current();
+    let evil = write_file(&fs, &ctx, "evil", b"gotcha", 0o644).await;
+    write_file(&fs, &ctx, "fine", b"ok", 0o644).await;
+    // Whole seconds leave out the pax header
+    let param = SetAttrParam {
+        m_time: Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+        ..SetAttrParam::default()
+    };
+    fs.setattr(&ctx, evil, param).await.unwrap();
+    let mut tar = Vec::new();
+    archive::export_tar(&fs, &ctx, OsStr::new(""), &mut tar).await.unwrap();
+
+    // Rename the first entry to ../evil, fixing the checksum
+    let header = &mut tar[..512];
+    assert_eq!((&header[..5], header[156]), (&b"evil\0"[..], b'0'));
+    header[..8].copy_from_slice(b"../evil\0");
+    header[148..156].fill(b' ');
+    let sum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+
+    fs.mkdir_all(&ctx, ROOT_ID, OsStr::new("jail"), 0o755).await.unwrap();
+    let report = archive::import_tar(&fs, &ctx, &mut tar.as_slice(), OsStr::new("jail/inner"))
+        .await
+        .unwrap();
+    assert_eq!((report.files, report.skipped), (1, 1));
+    assert_eq!(read_file(&fs, &ctx, "jail/inner/fine").await, b"ok");
+    assert!(fs.lookup(&ctx, ROOT_ID, OsStr::new("jail/evil")).await.is_err());
+
+    // A corrupted header is rejected
+    tar[0] = b'X';
+    let result = archive::import_tar(&fs, &ctx, &mut tar.as_slice(), OsStr::new("jail")).await;
+    assert!(matches!(result, Err(DatenLordError::InvalidArgument { .. })));
+}
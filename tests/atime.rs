@@ -0,0 +1,144 @@
+//! The access times reads leave under each atime policy
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::storage::fs_util::{
+    AtimePolicy, CreateParam, RequestContext, SetAttrParam, ROOT_ID,
+};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::sharedfs::{SharedConfig, SharedFs};
+use datenlord::storage::virtualfs::{INum, VirtualFs};
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+/// A `LocalFS` over an empty temporary directory named after `name`
+fn local_fs(name: &str, atime: AtimePolicy) -> (LocalFS, PathBuf) {
+    let root = std::env::temp_dir().join(format!("datenlord-atime-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let config = DatenLordConfig {
+        root: root.clone(),
+        atime,
+        ..DatenLordConfig::default()
+    };
+    (LocalFS::new(&config).unwrap(), root)
+}
+
+async fn shared_fs(atime: AtimePolicy) -> SharedFs {
+    let config = SharedConfig {
+        data_scheme: "memory".to_owned(),
+        atime,
+        ..SharedConfig::default()
+    };
+    SharedFs::new(&config).await.unwrap()
+}
+
+/// A file named `name` holding a few bytes
+async fn file<F: VirtualFs>(fs: &F, ctx: &RequestContext, name: &str) -> INum {
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: name.into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let ino = fs.mknod(ctx, param).await.unwrap().1.ino;
+    let fh = fs
+        .open(ctx, ino, OFlag::O_WRONLY.bits() as u32)
+        .await
+        .unwrap();
+    fs.write(ctx, ino, fh, 0, b"data", 0).await.unwrap();
+    fs.release(ctx, ino, fh, 0, 0, true).await.unwrap();
+    ino
+}
+
+/// Whether reading `ino` updates its access time
+async fn read_updates_atime<F: VirtualFs>(fs: &F, ino: INum) -> bool {
+    let ctx = RequestContext::current();
+    let (_, before) = fs.getattr(&ctx, ino).await.unwrap();
+    // Past the granularity of the timestamps of the local filesystem
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let fh = fs
+        .open(&ctx, ino, OFlag::O_RDONLY.bits() as u32)
+        .await
+        .unwrap();
+    let mut buf = [0; 4];
+    fs.read(&ctx, ino, fh, 0, 4, &mut buf).await.unwrap();
+    fs.release(&ctx, ino, fh, 0, 0, false).await.unwrap();
+    let (_, after) = fs.getattr(&ctx, ino).await.unwrap();
+    assert_eq!(after.mtime, before.mtime);
+    after.atime != before.atime
+}
+
+/// Check the policy `atime` on `fs` with a new file `name`
+async fn check<F: VirtualFs>(fs: &F, name: &str, atime: AtimePolicy) {
+    let ctx = RequestContext::current();
+    let ino = file(fs, &ctx, name).await;
+    let now = SystemTime::now();
+    let param = SetAttrParam {
+        a_time: Some(now - 2 * HOUR),
+        m_time: Some(now - HOUR),
+        ..SetAttrParam::default()
+    };
+    let (_, set) = fs.setattr(&ctx, ino, param).await.unwrap();
+    let changed = read_updates_atime(fs, ino).await;
+    // Now accessed since its last change
+    let accessed = read_updates_atime(fs, ino).await;
+    if atime != AtimePolicy::StrictAtime {
+        let (_, read) = fs.getattr(&ctx, ino).await.unwrap();
+        assert_eq!(read.ctime, set.ctime);
+    }
+    let expected = match atime {
+        AtimePolicy::NoAtime => (false, false),
+        AtimePolicy::Relatime => (true, false),
+        AtimePolicy::StrictAtime => (true, true),
+    };
+    assert_eq!((changed, accessed), expected, "{atime:?}");
+
+    // Set explicitly whatever the policy
+    let param = SetAttrParam {
+        a_time: Some(SystemTime::UNIX_EPOCH + HOUR),
+        ..SetAttrParam::default()
+    };
+    let (_, attr) = fs.setattr(&ctx, ino, param).await.unwrap();
+    assert_eq!(attr.atime, SystemTime::UNIX_EPOCH + HOUR);
+}
+
+#[tokio::test]
+async fn local_reads_follow_the_atime_policy() {
+    for (name, atime) in [
+        ("noatime", AtimePolicy::NoAtime),
+        ("relatime", AtimePolicy::Relatime),
+        ("strictatime", AtimePolicy::StrictAtime),
+    ] {
+        let (fs, root) = local_fs(name, atime);
+        check(&fs, "file", atime).await;
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
+
+#[tokio::test]
+async fn shared_reads_follow_the_atime_policy() {
+    for (name, atime) in [
+        ("noatime", AtimePolicy::NoAtime),
+        ("relatime", AtimePolicy::Relatime),
+        ("strictatime", AtimePolicy::StrictAtime),
+    ] {
+        let name = format!("atime-{name}-{}", std::process::id());
+        check(&shared_fs(atime).await, &name, atime).await;
+    }
+}
+
+#[test]
+fn the_policy_is_read_from_the_config() {
+    let parse = |json: &str| DatenLordConfig::parse(json).atime;
+    assert_eq!(parse("{}"), AtimePolicy::Relatime);
+    assert_eq!(parse(r#"{"atime": "noatime"}"#), AtimePolicy::NoAtime);
+    assert_eq!(
+        parse(r#"{"atime": "strictatime"}"#),
+        AtimePolicy::StrictAtime
+    );
+}
@@ -0,0 +1,199 @@
+//! Records the mutating operations on a local namespace in an audit log
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::audit::{AuditConfig, AuditFs, AuditRecord};
+use datenlord::storage::fs_util::{CreateParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::sys::stat::SFlag;
+
+/// A fresh root and audit log path, both removed on drop
+struct Root {
+    root: PathBuf,
+    log: PathBuf,
+}
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let tmp = std::env::temp_dir();
+        let root = tmp.join(format!("datenlord-audit-{name}-{}", std::process::id()));
+        let log = tmp.join(format!("datenlord-audit-{name}-{}.log", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let root = Self { root, log };
+        root.remove_logs();
+        root
+    }
+
+    fn config(&self, audit: AuditConfig) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.root.clone(),
+            audit,
+            ..DatenLordConfig::default()
+        }
+    }
+
+    /// The audit config writing to the log of the root
+    fn audit(&self) -> AuditConfig {
+        AuditConfig {
+            enabled: true,
+            path: self.log.clone(),
+            ..AuditConfig::default()
+        }
+    }
+
+    /// The log rotated `index` times, the current one for 0
+    fn log_path(&self, index: usize) -> PathBuf {
+        if index == 0 {
+            return self.log.clone();
+        }
+        PathBuf::from(format!("{}.{index}", self.log.display()))
+    }
+
+    fn remove_logs(&self) {
+        for index in 0..8 {
+            let _ = std::fs::remove_file(self.log_path(index));
+        }
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+        self.remove_logs();
+    }
+}
+
+/// The records of the log at `path`
+fn records(path: &Path) -> Vec<AuditRecord> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn mutations_are_recorded_with_their_caller_and_outcome() {
+    let root = Root::new("client");
+    let client = Client::new(&root.config(root.audit())).unwrap();
+    client.create_dir_all("dir").await.unwrap();
+    let file = client.create("dir/a.txt").await.unwrap();
+    file.write_at(b"hello", 3).await.unwrap();
+    file.close().await.unwrap();
+    let _ = client.metadata("dir/a.txt").await.unwrap();
+    client.remove("dir/a.txt").await.unwrap();
+    assert!(client.remove("dir/a.txt").await.is_err());
+
+    let records = records(&root.log);
+    let ctx = RequestContext::current();
+    assert!(records
+        .iter()
+        .all(|record| (record.uid, record.gid, record.pid) == (ctx.uid, ctx.gid, ctx.pid)));
+    assert!(records
+        .windows(2)
+        .all(|pair| pair[0].timestamp_ns <= pair[1].timestamp_ns));
+    let ops: Vec<_> = records
+        .iter()
+        .map(|record| (record.op.as_str(), record.ok))
+        .collect();
+    assert_eq!(
+        ops,
+        [
+            ("mkdir", true),
+            ("mknod", true),
+            ("open", true),
+            ("write", true),
+            ("unlink", true)
+        ]
+    );
+    assert_eq!(records[1].name.as_deref(), Some("dir/a.txt"));
+    assert_eq!((records[2].ino, records[2].size), (records[1].ino, Some(0)));
+    assert_eq!((records[3].offset, records[3].len), (Some(3), Some(5)));
+    assert_eq!(records[3].ino, records[1].ino);
+    assert_eq!(records[4].name.as_deref(), Some("dir/a.txt"));
+}
+
+#[tokio::test]
+async fn failures_are_recorded_with_their_errno() {
+    let root = Root::new("failures");
+    let config = root.config(root.audit());
+    let fs = AuditFs::new(LocalFS::new(&config).unwrap(), &config.audit).unwrap();
+    let ctx = RequestContext::current();
+    let mkdir = || CreateParam {
+        parent: ROOT_ID,
+        name: "dir".into(),
+        mode: 0o755,
+        rdev: 0,
+        node_type: SFlag::S_IFDIR,
+        link: None,
+    };
+    fs.mkdir(&ctx, mkdir()).await.unwrap();
+    assert!(fs.mkdir(&ctx, mkdir()).await.is_err());
+    fs.rmdir(&ctx, ROOT_ID, OsStr::new("dir")).await.unwrap();
+
+    let records = records(&root.log);
+    assert_eq!(records.len(), 3);
+    assert_eq!((records[0].ok, records[0].mode), (true, Some(0o755)));
+    assert!(!records[1].ok);
+    assert_eq!(records[1].errno, Some(nix::errno::Errno::EEXIST as i32));
+    assert!(records[1].error.is_some());
+    assert_eq!(records[2].op, "rmdir");
+    assert_eq!(records[2].ino, records[0].ino);
+}
+
+#[tokio::test]
+async fn full_logs_are_rotated() {
+    let root = Root::new("rotate");
+    let audit = AuditConfig {
+        max_bytes: 512,
+        keep: 2,
+        ..root.audit()
+    };
+    let config = root.config(audit);
+    let fs = AuditFs::new(LocalFS::new(&config).unwrap(), &config.audit).unwrap();
+    let ctx = RequestContext::current();
+    for index in 0..40 {
+        let param = CreateParam {
+            parent: ROOT_ID,
+            name: format!("dir{index}").into(),
+            mode: 0o755,
+            rdev: 0,
+            node_type: SFlag::S_IFDIR,
+            link: None,
+        };
+        fs.mkdir(&ctx, param).await.unwrap();
+    }
+
+    let mut names = Vec::new();
+    for index in (0..=2).rev() {
+        let path = root.log_path(index);
+        assert!(std::fs::metadata(&path).unwrap().len() <= 512);
+        names.extend(
+            records(&path)
+                .into_iter()
+                .map(|record| record.name.unwrap()),
+        );
+    }
+    assert!(!root.log_path(3).exists());
+    // The kept logs hold the latest records, in order
+    let last = 40 - names.len();
+    let expected: Vec<_> = (last..40).map(|index| format!("dir{index}")).collect();
+    assert_eq!(names, expected);
+}
+
+#[test]
+fn audit_logs_need_a_path() {
+    let root = Root::new("config");
+    let disabled = sdk::open_fs(&root.config(AuditConfig::default()));
+    assert!(disabled.is_ok());
+    assert!(!root.log.exists());
+    let pathless = AuditConfig {
+        enabled: true,
+        ..AuditConfig::default()
+    };
+    assert!(sdk::open_fs(&root.config(pathless)).is_err());
+}
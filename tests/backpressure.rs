@@ -0,0 +1,170 @@
+//! Limits the writes in flight below the SDKs
+use std::path::PathBuf;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::backpressure::{BackpressureConfig, BackpressureFs};
+use datenlord::storage::faulty::{FaultConfig, FaultyFs};
+use datenlord::storage::fs_util::{CreateParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir = format!("datenlord-backpressure-{name}-{}", std::process::id());
+        let root = std::env::temp_dir().join(dir);
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    fn config(&self) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        }
+    }
+
+    /// A filesystem whose writes take 100ms, behind `limits`
+    fn slow(&self, limits: BackpressureConfig) -> BackpressureFs<FaultyFs<LocalFS>> {
+        let faults = FaultConfig {
+            ops: vec!["write".to_owned()],
+            delay_every: Some(1),
+            delay_ms: 100,
+            ..FaultConfig::default()
+        };
+        let local = LocalFS::new(&self.config()).unwrap();
+        BackpressureFs::new(FaultyFs::new(local, faults), &limits)
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn ctx() -> RequestContext {
+    DatenLordConfig::default().request_context()
+}
+
+/// Create the file `name` in the root of `fs` and open it for writing
+async fn create<F: VirtualFs>(fs: &F, name: &str) -> (u64, u64) {
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: name.into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let ino = fs.mknod(&ctx(), param).await.unwrap().1.ino;
+    let fh = fs
+        .open(&ctx(), ino, OFlag::O_WRONLY.bits() as u32)
+        .await
+        .unwrap();
+    (ino, fh)
+}
+
+#[tokio::test]
+async fn writes_over_the_limit_wait_for_room() {
+    let root = Root::new("wait");
+    let fs = root.slow(BackpressureConfig {
+        max_in_flight_bytes: 1024,
+        ..BackpressureConfig::default()
+    });
+    let (ino, fh) = create(&fs, "file").await;
+    let data = [7_u8; 1024];
+    let ctx = ctx();
+    let write = |offset: i64| fs.write(&ctx, ino, fh, offset, &data, 0);
+    let watch = async {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        fs.stats()
+    };
+    let (first, second, third, during) = tokio::join!(write(0), write(1024), write(2048), watch);
+    first.unwrap();
+    second.unwrap();
+    third.unwrap();
+    assert_eq!(during.in_flight_write_bytes, 1024);
+    assert_eq!(during.queued_writes, 2);
+
+    let after = fs.stats();
+    assert_eq!(after.in_flight_write_bytes, 0);
+    assert_eq!(after.queued_writes, 0);
+    assert_eq!(after.peak_queued_writes, 2);
+    assert_eq!(after.delayed_writes, 2);
+    assert_eq!(after.rejected_writes, 0);
+    // A write larger than the limit runs alone instead of waiting forever
+    fs.write(&ctx, ino, fh, 0, &[0; 4096], 0).await.unwrap();
+}
+
+#[tokio::test]
+async fn fail_fast_writes_fail_with_eagain() {
+    let root = Root::new("fail-fast");
+    let fs = root.slow(BackpressureConfig {
+        max_in_flight_bytes: 1024,
+        fail_fast: true,
+        ..BackpressureConfig::default()
+    });
+    let (ino, fh) = create(&fs, "file").await;
+    let data = [7_u8; 600];
+    let ctx = ctx();
+    let (first, second) = tokio::join!(
+        fs.write(&ctx, ino, fh, 0, &data, 0),
+        fs.write(&ctx, ino, fh, 600, &data, 0),
+    );
+    first.unwrap();
+    let err = second.unwrap_err();
+    assert!(matches!(err, DatenLordError::Unavailable { .. }), "{err:?}");
+    assert_eq!(err.errno(), Some(Errno::EAGAIN));
+    assert_eq!(fs.stats().rejected_writes, 1);
+    assert_eq!(fs.stats().delayed_writes, 0);
+}
+
+#[tokio::test]
+async fn writes_beyond_the_queue_limit_are_rejected() {
+    let root = Root::new("queue");
+    let fs = root.slow(BackpressureConfig {
+        max_in_flight_bytes: 100,
+        max_queued_writes: 1,
+        ..BackpressureConfig::default()
+    });
+    let (ino, fh) = create(&fs, "file").await;
+    let data = [7_u8; 100];
+    let ctx = ctx();
+    let write = |offset: i64| fs.write(&ctx, ino, fh, offset, &data, 0);
+    let (first, second, third) = tokio::join!(write(0), write(100), write(200));
+    first.unwrap();
+    second.unwrap();
+    let err = third.unwrap_err();
+    assert_eq!(err.errno(), Some(Errno::EAGAIN), "{err:?}");
+    let stats = fs.stats();
+    assert_eq!((stats.peak_queued_writes, stats.rejected_writes), (1, 1));
+}
+
+#[tokio::test]
+async fn sdk_stats_report_the_write_queue() {
+    let root = Root::new("sdk");
+    let client = Client::new(&DatenLordConfig {
+        backpressure: BackpressureConfig {
+            max_in_flight_bytes: 1 << 20,
+            ..BackpressureConfig::default()
+        },
+        ..root.config()
+    })
+    .unwrap();
+    let file = client.create("file").await.unwrap();
+    file.write_at(b"data", 0).await.unwrap();
+    file.close().await.unwrap();
+    let stats = client.stats();
+    assert_eq!(stats.writes.in_flight_write_bytes, 0);
+    assert_eq!(stats.writes.rejected_writes, 0);
+    let json = serde_json::to_value(&stats).unwrap();
+    assert_eq!(json["queued_writes"], 0);
+}
@@ -0,0 +1,43 @@
+//! Runs every benchmark workload briefly against a local namespace
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::bench::{self, BenchOptions, Workload};
+use datenlord::common::config::DatenLordConfig;
+use datenlord::storage::localfs::LocalFS;
+
+#[tokio::test]
+async fn every_workload_completes_operations_and_cleans_up() {
+    let root = std::env::temp_dir().join(format!("datenlord-bench-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let config = DatenLordConfig {
+        root: root.clone(),
+        ..DatenLordConfig::default()
+    };
+    let fs = Arc::new(LocalFS::new(&config).unwrap());
+    for workload in Workload::ALL {
+        let options = BenchOptions {
+            workload,
+            concurrency: 2,
+            duration: Duration::from_millis(50),
+            block_size: 512,
+            file_size: 4096,
+        };
+        let report = bench::run(Arc::clone(&fs), config.request_context(), &options)
+            .await
+            .unwrap();
+        assert_eq!(report.workload, workload);
+        assert!(report.ops > 0, "{workload} completed no operation");
+        assert!(report.percentile(50.0) <= report.percentile(100.0));
+        if !matches!(
+            workload,
+            Workload::MetadataStress | Workload::Exists | Workload::ExistsByLookup
+        ) {
+            assert!(report.bytes >= 512 * report.ops, "{workload} moved too few bytes");
+        }
+        assert_eq!(workload.to_string().parse::<Workload>().unwrap(), workload);
+    }
+    // Only the superblock and the inode table are left behind
+    assert_eq!(std::fs::read_dir(&root).unwrap().count(), 2);
+    std::fs::remove_dir_all(&root).unwrap();
+}
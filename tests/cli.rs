@@ -0,0 +1,108 @@
+//! Runs the file subcommands of `datenlord-cli` against a local backend
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// A backend in a fresh directory, removed on drop
+struct Backend(PathBuf);
+
+impl Backend {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("datenlord-cli-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    /// Run `datenlord-cli` with `args` on the backend
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_datenlord-cli"))
+            .arg("--backend")
+            .arg(format!("file://{}", self.0.display()))
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// The stdout of `datenlord-cli` run with `args`, which must succeed
+    fn stdout(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "{args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn file_subcommands_round_trip() {
+    let backend = Backend::new("files");
+    let local = std::env::temp_dir().join(format!("datenlord-cli-local-{}", std::process::id()));
+    std::fs::write(&local, b"hello datenlord\n").unwrap();
+
+    backend.stdout(&["mkdir", "data/raw"]);
+    backend.stdout(&["put", local.to_str().unwrap(), "data/raw/hello.txt"]);
+    backend.stdout(&["cp", "data/raw/hello.txt", "data/hello.txt"]);
+    assert_eq!(backend.stdout(&["sync"]), "");
+    assert_eq!(backend.stdout(&["cat", "data/hello.txt"]), "hello datenlord\n");
+    assert_eq!(backend.stdout(&["ls"]), "data\n");
+    assert_eq!(backend.stdout(&["ls", "data"]), "hello.txt\nraw\n");
+    let long = backend.stdout(&["ls", "-l", "data"]);
+    assert!(long.lines().next().unwrap().starts_with("file"), "{long}");
+    let stat = backend.stdout(&["stat", "data/hello.txt"]);
+    assert!(stat.contains("size:  16\n"), "{stat}");
+    let du = backend.stdout(&["du", "data"]);
+    assert!(du.contains("bytes:     32\n") && du.contains("files:     2\n"), "{du}");
+    assert!(du.contains("dirs:      1\n"), "{du}");
+
+    std::fs::remove_file(&local).unwrap();
+    backend.stdout(&["get", "data/raw/hello.txt", local.to_str().unwrap()]);
+    assert_eq!(std::fs::read(&local).unwrap(), b"hello datenlord\n");
+    std::fs::remove_file(&local).unwrap();
+
+    // Directories are only removed with everything under them when asked
+    assert!(!backend.run(&["rm", "data"]).status.success());
+    backend.stdout(&["rm", "data/hello.txt"]);
+    backend.stdout(&["rm", "-r", "data"]);
+    assert_eq!(backend.stdout(&["ls"]), "");
+    assert!(!backend.run(&["stat", "data"]).status.success());
+}
+
+#[test]
+fn resumable_puts_resume_the_upload_left() {
+    let backend = Backend::new("resumable");
+    let local = std::env::temp_dir().join(format!("datenlord-cli-parts-{}", std::process::id()));
+    std::fs::write(&local, b"0123456789").unwrap();
+
+    // A run interrupted after uploading a part, another one being stale
+    let config = datenlord::common::config::DatenLordConfig {
+        root: backend.0.clone(),
+        ..datenlord::common::config::DatenLordConfig::default()
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let client = datenlord::sdk::rust::Client::new(&config).unwrap();
+        let id = client.start_upload("parts.bin").await.unwrap();
+        client.upload_part(&id, 0, b"0123").await.unwrap();
+        client.upload_part(&id, 1, b"stale").await.unwrap();
+    });
+
+    let args = ["put", "--resumable", "--part-size", "4", local.to_str().unwrap(), "parts.bin"];
+    let stdout = backend.stdout(&args);
+    assert_eq!(stdout, "uploaded 10 bytes to parts.bin, 1 parts resumed\n");
+    assert_eq!(backend.stdout(&["cat", "parts.bin"]), "0123456789");
+    assert_eq!(backend.stdout(&["ls"]), "parts.bin\n");
+
+    std::fs::write(&local, b"").unwrap();
+    let stdout = backend.stdout(&args);
+    assert_eq!(stdout, "uploaded 0 bytes to parts.bin, 0 parts resumed\n");
+    let zero = ["put", "--resumable", "--part-size", "0", local.to_str().unwrap(), "x"];
+    assert!(!backend.run(&zero).status.success());
+    std::fs::remove_file(&local).unwrap();
+}
@@ -0,0 +1,758 @@
+//! Drives the Rust client the way applications depending on the crate do
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use datenlord::common::config::{CallerConfig, DatenLordConfig};
+use datenlord::common::{DatenLordError, DatenLordResult};
+use datenlord::sdk::rust::Client;
+use datenlord::storage::cache::CacheFs;
+use datenlord::storage::fs_util::{
+    CreateParam, DirEntry, FileKind, NameConfig, RenameParam, RequestContext, SetAttrParam, ROOT_ID,
+};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::stats::StatsFs;
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// A client rooted in a fresh directory, removed on drop
+struct Namespace {
+    client: Client,
+    root: PathBuf,
+}
+
+impl Namespace {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("datenlord-client-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let config = DatenLordConfig {
+            root: root.clone(),
+            ..DatenLordConfig::default()
+        };
+        let client = Client::new(&config).unwrap();
+        Self { client, root }
+    }
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+#[tokio::test]
+async fn files_are_written_read_and_removed_by_path() {
+    let ns = Namespace::new("files");
+    let client = &ns.client;
+    client.create_dir_all("a/b").await.unwrap();
+
+    let file = client.create("a/b/c.txt").await.unwrap();
+    file.write_at(b"hello world", 0).await.unwrap();
+    file.write_at(b"W", 6).await.unwrap();
+    assert_eq!(file.metadata().await.unwrap().size, 11);
+    file.close().await.unwrap();
+
+    let file = client.open("a/b/c.txt", OFlag::O_RDONLY).await.unwrap();
+    let mut buf = [0; 32];
+    let read = file.read_at(&mut buf, 0).await.unwrap();
+    assert_eq!(&buf[..read], b"hello World");
+    assert_eq!(file.read_at(&mut buf, 11).await.unwrap(), 0);
+    drop(file);
+
+    // Creating an existing file truncates it
+    client.create("a/b/c.txt").await.unwrap().close().await.unwrap();
+    assert_eq!(client.metadata("a/b/c.txt").await.unwrap().size, 0);
+
+    let entries = client.read_dir("a").await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, "b");
+    assert_eq!(entries[0].kind, FileKind::Directory);
+    assert!(entries[0].attr.is_some());
+    // The superblock of the namespace is not listed
+    let root = client.read_dir("").await.unwrap();
+    assert_eq!(root.iter().map(|entry| entry.name.as_os_str()).collect::<Vec<_>>(), ["a"]);
+
+    assert!(client.remove("a/b").await.is_err());
+    client.remove("a/b/c.txt").await.unwrap();
+    client.remove("a/b").await.unwrap();
+    assert!(!client.exists("a/b").await);
+    assert!(client.open("a", OFlag::O_RDONLY).await.is_err());
+}
+
+#[tokio::test]
+async fn metadata_of_many_paths_is_returned_in_order() {
+    let ns = Namespace::new("metadata-many");
+    let client = &ns.client;
+    client.create_dir_all("a/b").await.unwrap();
+    for (name, content) in [("a/b/x", &b"x"[..]), ("a/b/yy", b"yy"), ("a/z", b"")] {
+        let file = client.create(name).await.unwrap();
+        file.write_at(content, 0).await.unwrap();
+        file.close().await.unwrap();
+    }
+    let paths: Vec<String> = ["a/b/yy", "a/b/x", "nope/x", "a/z", "a/b/none", "a", ""]
+        .iter()
+        .map(|path| (*path).to_owned())
+        .collect();
+    let attrs = client.metadata_many(&paths, 2).await;
+    assert_eq!(attrs.len(), paths.len());
+    let sizes: Vec<Option<u64>> = attrs
+        .iter()
+        .map(|attr| attr.as_ref().ok().map(|attr| attr.size))
+        .collect();
+    assert_eq!(sizes[..5], [Some(2), Some(1), None, Some(0), None]);
+    assert_eq!(attrs[5].as_ref().unwrap().kind, SFlag::S_IFDIR);
+    assert_eq!(attrs[6].as_ref().unwrap().kind, SFlag::S_IFDIR);
+    assert!(client.metadata_many(&[] as &[&str], 0).await.is_empty());
+}
+
+#[tokio::test]
+async fn disk_usage_counts_hard_links_once() {
+    let ns = Namespace::new("disk-usage");
+    let client = &ns.client;
+    client.create_dir_all("a/b/c").await.unwrap();
+    for (name, content) in [("a/x", &b"xxx"[..]), ("a/b/y", b"yyyyy"), ("a/b/c/z", b"")] {
+        let file = client.create(name).await.unwrap();
+        file.write_at(content, 0).await.unwrap();
+        file.close().await.unwrap();
+    }
+    std::fs::hard_link(ns.root.join("a/x"), ns.root.join("a/b/c/x")).unwrap();
+
+    let usage = client.disk_usage("a", 2).await.unwrap();
+    assert_eq!((usage.bytes, usage.files, usage.dirs), (8, 3, 2));
+    let usage = client.disk_usage("a/b", 2).await.unwrap();
+    assert_eq!((usage.bytes, usage.files, usage.dirs), (8, 3, 1));
+    let usage = client.disk_usage("a/b/y", 2).await.unwrap();
+    assert_eq!((usage.bytes, usage.files, usage.dirs), (5, 1, 0));
+    assert_eq!(client.disk_usage("", 2).await.unwrap().dirs, 3);
+    assert!(matches!(
+        client.disk_usage("a/none", 2).await,
+        Err(DatenLordError::NotFound { .. })
+    ));
+}
+
+#[tokio::test]
+async fn files_work_with_tokio_io() {
+    let ns = Namespace::new("tokio-io");
+    let client = &ns.client;
+
+    let mut file = client.create("data.bin").await.unwrap();
+    let content: Vec<u8> = (0..100_000_u32).map(|i| (i % 251) as u8).collect();
+    let copied = tokio::io::copy(&mut content.as_slice(), &mut file).await.unwrap();
+    assert_eq!(copied, 100_000);
+    file.write_all(b"tail").await.unwrap();
+    file.shutdown().await.unwrap();
+    file.close().await.unwrap();
+
+    let mut file = client.open("data.bin", OFlag::O_RDONLY).await.unwrap();
+    let mut read = Vec::new();
+    file.read_to_end(&mut read).await.unwrap();
+    assert_eq!(&read[..100_000], content.as_slice());
+    assert_eq!(&read[100_000..], b"tail");
+
+    assert_eq!(file.seek(std::io::SeekFrom::End(-4)).await.unwrap(), 100_000);
+    let mut tail = String::new();
+    file.read_to_string(&mut tail).await.unwrap();
+    assert_eq!(tail, "tail");
+    assert_eq!(file.seek(std::io::SeekFrom::Current(-2)).await.unwrap(), 100_002);
+    assert_eq!(file.read_u8().await.unwrap(), b'i');
+    assert!(file.seek(std::io::SeekFrom::Current(-200_000)).await.is_err());
+}
+
+#[tokio::test]
+async fn operations_run_as_the_configured_caller() {
+    // Creating files owned by other users needs root
+    if !nix::unistd::geteuid().is_root() {
+        return;
+    }
+    let ns = Namespace::new("caller");
+    std::fs::set_permissions(&ns.root, std::fs::Permissions::from_mode(0o777)).unwrap();
+    let client_as = |uid| {
+        Client::new(&DatenLordConfig {
+            root: ns.root.clone(),
+            caller: CallerConfig {
+                uid: Some(uid),
+                gid: Some(uid),
+                umask: Some(0o027),
+            },
+            ..DatenLordConfig::default()
+        })
+        .unwrap()
+    };
+    let (owner, other) = (client_as(1000), client_as(2000));
+
+    owner.create_dir_all("private").await.unwrap();
+    owner.create("private/notes.txt").await.unwrap().close().await.unwrap();
+    let attr = owner.metadata("private/notes.txt").await.unwrap();
+    assert_eq!((attr.uid, attr.gid, attr.perm), (1000, 1000, 0o640));
+    assert_eq!(owner.metadata("private").await.unwrap().perm, 0o750);
+
+    assert!(matches!(
+        other.metadata("private/notes.txt").await,
+        Err(DatenLordError::PermissionDenied { .. })
+    ));
+    assert!(matches!(
+        other.create_dir_all("private/sub").await,
+        Err(DatenLordError::PermissionDenied { .. })
+    ));
+    // The superuser is not restricted
+    ns.client.remove("private/notes.txt").await.unwrap();
+}
+
+#[tokio::test]
+async fn files_carry_tags() {
+    let ns = Namespace::new("tags");
+    ns.client.create("table.parquet").await.unwrap().close().await.unwrap();
+    assert!(ns.client.tags("table.parquet").await.unwrap().is_empty());
+
+    ns.client.set_tag("table.parquet", "team", "data").await.unwrap();
+    ns.client.set_tag("table.parquet", "tier", "hot").await.unwrap();
+    ns.client.set_tag("table.parquet", "tier", "cold").await.unwrap();
+    let tags = ns.client.tags("table.parquet").await.unwrap();
+    assert_eq!(
+        tags.into_iter().collect::<Vec<_>>(),
+        [("team".to_owned(), "data".to_owned()), ("tier".to_owned(), "cold".to_owned())]
+    );
+
+    ns.client.remove_tag("table.parquet", "tier").await.unwrap();
+    assert_eq!(ns.client.tags("table.parquet").await.unwrap().len(), 1);
+    assert!(matches!(
+        ns.client.set_tag("table.parquet", "a=b", "c").await,
+        Err(DatenLordError::InvalidArgument { .. })
+    ));
+}
+
+#[tokio::test]
+async fn created_entries_follow_the_umask() {
+    let mut ns = Namespace::new("umask");
+    ns.client.set_umask(0o077);
+    ns.client.create("private").await.unwrap().close().await.unwrap();
+    ns.client.create_dir_all("private_dir").await.unwrap();
+    assert_eq!(ns.client.metadata("private").await.unwrap().perm, 0o600);
+    assert_eq!(ns.client.metadata("private_dir").await.unwrap().perm, 0o700);
+
+    assert_eq!(ns.client.set_umask(0o002), 0o077);
+    ns.client.create("shared").await.unwrap().close().await.unwrap();
+    assert_eq!(ns.client.metadata("shared").await.unwrap().perm, 0o664);
+
+    // `create` honours the umask and `O_EXCL` like `open(2)` with `O_CREAT`
+    let localfs = LocalFS::new(&DatenLordConfig {
+        root: ns.root.clone(),
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    let ctx = RequestContext {
+        umask: 0o027,
+        ..RequestContext::current()
+    };
+    let flags = (OFlag::O_CREAT | OFlag::O_WRONLY).bits() as u32;
+    localfs.create(&ctx, 0, ROOT_ID, OsStr::new("created"), 0o666, flags).await.unwrap();
+    localfs.create(&ctx, 0, ROOT_ID, OsStr::new("created"), 0o666, flags).await.unwrap();
+    assert_eq!(ns.client.metadata("created").await.unwrap().perm, 0o640);
+    let exclusive = flags | OFlag::O_EXCL.bits() as u32;
+    assert!(matches!(
+        localfs.create(&ctx, 0, ROOT_ID, OsStr::new("created"), 0o666, exclusive).await,
+        Err(DatenLordError::AlreadyExists { .. })
+    ));
+}
+
+#[tokio::test]
+async fn opens_follow_their_flags() {
+    let ns = Namespace::new("open-flags");
+    let client = &ns.client;
+    let exclusive = OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL;
+    let file = client.open("lock", exclusive).await.unwrap();
+    file.write_at(b"owner", 0).await.unwrap();
+    file.close().await.unwrap();
+    assert!(matches!(
+        client.open("lock", exclusive).await,
+        Err(DatenLordError::AlreadyExists { .. })
+    ));
+    // Without `O_EXCL` the existing file is opened as it is
+    let file = client
+        .open("lock", OFlag::O_RDWR | OFlag::O_CREAT)
+        .await
+        .unwrap();
+    assert_eq!(file.metadata().await.unwrap().size, 5);
+    file.close().await.unwrap();
+
+    // Truncating updates the times, even of an empty file
+    let truncate = OFlag::O_WRONLY | OFlag::O_TRUNC;
+    let mut before = client.metadata("lock").await.unwrap();
+    for _ in 0..2 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let file = client.open("lock", truncate).await.unwrap();
+        let truncated = file.metadata().await.unwrap();
+        file.close().await.unwrap();
+        assert_eq!(truncated.size, 0);
+        assert!(truncated.mtime > before.mtime);
+        assert!(truncated.ctime > before.ctime);
+        before = truncated;
+    }
+
+    std::os::unix::fs::symlink("lock", ns.root.join("link")).unwrap();
+    let nofollow = OFlag::O_RDONLY | OFlag::O_NOFOLLOW;
+    let result = client.open("link", nofollow).await;
+    assert!(matches!(result, Err(ref e) if e.errno() == Some(Errno::ELOOP)));
+    let file = client.open("link", OFlag::O_RDONLY).await.unwrap();
+    file.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn sticky_directories_keep_entries_to_their_owners() {
+    // Entries owned by other users need root
+    if !nix::unistd::geteuid().is_root() {
+        return;
+    }
+    fn denied<T>(result: DatenLordResult<T>) -> bool {
+        matches!(result, Err(DatenLordError::PermissionDenied { .. }))
+    }
+    let ns = Namespace::new("sticky");
+    std::fs::set_permissions(&ns.root, std::fs::Permissions::from_mode(0o1777)).unwrap();
+    let localfs = LocalFS::new(&DatenLordConfig {
+        root: ns.root.clone(),
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    let as_user = |uid| RequestContext {
+        uid,
+        gid: uid,
+        umask: 0,
+        ..RequestContext::current()
+    };
+    let (owner, other, dir_owner, root) = (as_user(1000), as_user(2000), as_user(3000), as_user(0));
+    let flags = (OFlag::O_CREAT | OFlag::O_WRONLY).bits() as u32;
+    let mkdir = |parent, name: &str, mode| CreateParam {
+        parent,
+        name: name.into(),
+        mode,
+        rdev: 0,
+        node_type: SFlag::S_IFDIR,
+        link: None,
+    };
+    let rename = |from: &str, new_parent, to: &str| RenameParam {
+        old_parent: ROOT_ID,
+        old_name: from.into(),
+        new_parent,
+        new_name: to.into(),
+        flags: 0,
+    };
+    for name in ["notes", "draft", "kept"] {
+        localfs.create(&owner, 0, ROOT_ID, OsStr::new(name), 0o666, flags).await.unwrap();
+    }
+    localfs.create(&other, 0, ROOT_ID, OsStr::new("theirs"), 0o666, flags).await.unwrap();
+    localfs.mkdir(&owner, mkdir(ROOT_ID, "dir", 0o777)).await.unwrap();
+
+    // Other users can neither remove, move nor replace the entries of the owner
+    assert!(denied(localfs.unlink(&other, ROOT_ID, OsStr::new("notes")).await));
+    assert!(denied(localfs.rmdir(&other, ROOT_ID, OsStr::new("dir")).await));
+    assert!(denied(localfs.rename(&other, rename("notes", ROOT_ID, "moved")).await));
+    assert!(denied(localfs.rename(&other, rename("theirs", ROOT_ID, "notes")).await));
+    // The owner of the entry and root can
+    localfs.rename(&owner, rename("notes", ROOT_ID, "moved")).await.unwrap();
+    localfs.unlink(&owner, ROOT_ID, OsStr::new("moved")).await.unwrap();
+    localfs.rmdir(&owner, ROOT_ID, OsStr::new("dir")).await.unwrap();
+    localfs.unlink(&root, ROOT_ID, OsStr::new("draft")).await.unwrap();
+
+    // So can the owner of the directory
+    let (_, shared, _) = localfs.mkdir(&dir_owner, mkdir(ROOT_ID, "shared", 0o1777)).await.unwrap();
+    for name in ["a", "b"] {
+        localfs.create(&owner, 0, shared.ino, OsStr::new(name), 0o666, flags).await.unwrap();
+    }
+    localfs.unlink(&dir_owner, shared.ino, OsStr::new("a")).await.unwrap();
+    assert!(denied(localfs.unlink(&other, shared.ino, OsStr::new("b")).await));
+
+    // A directory moving to another parent needs write access to itself
+    localfs.mkdir(&owner, mkdir(ROOT_ID, "read_only", 0o555)).await.unwrap();
+    assert!(denied(localfs.rename(&owner, rename("read_only", shared.ino, "read_only")).await));
+    localfs.rename(&owner, rename("read_only", ROOT_ID, "renamed")).await.unwrap();
+
+    // Without the sticky bit, write and search access to the directory suffice
+    std::fs::set_permissions(&ns.root, std::fs::Permissions::from_mode(0o777)).unwrap();
+    localfs.unlink(&other, ROOT_ID, OsStr::new("kept")).await.unwrap();
+    std::fs::set_permissions(&ns.root, std::fs::Permissions::from_mode(0o755)).unwrap();
+    assert!(denied(localfs.unlink(&owner, ROOT_ID, OsStr::new("theirs")).await));
+}
+
+#[tokio::test]
+async fn writes_keep_the_reserved_space_free() {
+    let ns = Namespace::new("reserve");
+    let client_keeping = |reserved_space_bytes| {
+        Client::new(&DatenLordConfig {
+            root: ns.root.clone(),
+            reserved_space_bytes,
+            ..DatenLordConfig::default()
+        })
+        .unwrap()
+    };
+    let file = client_keeping(1).create("small").await.unwrap();
+    file.write_at(b"fits", 0).await.unwrap();
+    file.close().await.unwrap();
+
+    // No filesystem has that much room, yet entries are still created
+    let full = client_keeping(u64::MAX);
+    let file = full.create("large").await.unwrap();
+    let err = file.write_at(b"rejected", 0).await.unwrap_err();
+    assert!(matches!(err, DatenLordError::NoSpace { .. }), "{err:?}");
+    assert_eq!(err.errno(), Some(nix::errno::Errno::ENOSPC));
+    file.close().await.unwrap();
+    assert_eq!(full.metadata("large").await.unwrap().size, 0);
+    assert_eq!(std::fs::read(ns.root.join("small")).unwrap(), b"fits");
+}
+
+#[tokio::test]
+async fn missing_paths_are_not_found() {
+    let ns = Namespace::new("missing");
+    let client = &ns.client;
+    let err = client.metadata("missing").await.unwrap_err();
+    assert!(matches!(err, DatenLordError::NotFound { .. }), "{err:?}");
+    assert_eq!(err.errno(), Some(nix::errno::Errno::ENOENT));
+    let err = client.create("missing/file").await.unwrap_err();
+    assert!(matches!(err, DatenLordError::NotFound { .. }), "{err:?}");
+}
+
+#[tokio::test]
+async fn presence_is_checked_without_lookups() {
+    let ns = Namespace::new("presence");
+    let client = &ns.client;
+    client.create_dir_all("a").await.unwrap();
+    client.create("a/f").await.unwrap().close().await.unwrap();
+    let config = DatenLordConfig {
+        root: ns.root.clone(),
+        ..DatenLordConfig::default()
+    };
+    let fs = CacheFs::new(StatsFs::new(LocalFS::new(&config).unwrap()), 1024);
+    let ctx = RequestContext::current();
+    let exists = |path: &'static str| fs.exists(&ctx, ROOT_ID, OsStr::new(path));
+    assert!(exists("a/f").await.unwrap());
+    assert!(exists("a").await.unwrap());
+    // Missing entries, through a missing directory or a file too
+    for missing in ["a/g", "b/f", "a/f/g"] {
+        assert!(!exists(missing).await.unwrap(), "{missing}");
+    }
+    let calls = |op| fs.inner().stats().ops.get(op).map_or(0, |op| op.calls);
+    assert_eq!((calls("lookup"), calls("exists")), (0, 5));
+
+    // Answered from the entries the cache holds and the names found missing
+    fs.lookup(&ctx, ROOT_ID, OsStr::new("a/f")).await.unwrap();
+    assert!(exists("a/f").await.unwrap());
+    assert!(!exists("a/g").await.unwrap());
+    assert_eq!((calls("lookup"), calls("exists")), (1, 5));
+    // Until an entry is created
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: "a/g".into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    fs.mknod(&ctx, param).await.unwrap();
+    assert!(exists("a/g").await.unwrap());
+    assert!(!exists("b/f").await.unwrap());
+    assert_eq!(calls("exists"), 6);
+    assert!(client.exists("a/f").await);
+    assert!(!client.exists("a/h").await);
+}
+
+#[tokio::test]
+async fn paths_stay_under_the_root() {
+    let ns = Namespace::new("confined");
+    let client = &ns.client;
+    let secret = format!("datenlord-client-secret-{}", std::process::id());
+    let secret_path = std::env::temp_dir().join(&secret);
+    std::fs::write(&secret_path, b"secret").unwrap();
+    let escapes =
+        |res: DatenLordResult<()>| matches!(res, Err(DatenLordError::PermissionDenied { .. }));
+
+    assert!(escapes(client.metadata(&format!("../{secret}")).await.map(|_| ())));
+    assert!(escapes(client.create("a/../../stray").await.map(|_| ())));
+    client.create_dir_all("a").await.unwrap();
+    let file = client.create("/a/./../b.txt").await.unwrap();
+    file.write_at(b"inside", 0).await.unwrap();
+    file.close().await.unwrap();
+    assert_eq!(std::fs::read(ns.root.join("b.txt")).unwrap(), b"inside");
+
+    // Links are followed as long as they stay under the root
+    std::os::unix::fs::symlink("a", ns.root.join("inner")).unwrap();
+    std::os::unix::fs::symlink("inner/../b.txt", ns.root.join("b.link")).unwrap();
+    assert_eq!(client.metadata("inner/../b.txt").await.unwrap().size, 6);
+    let mut file = client.open("b.link", OFlag::O_RDONLY).await.unwrap();
+    let mut content = String::new();
+    file.read_to_string(&mut content).await.unwrap();
+    assert_eq!(content, "inside");
+    assert!(client.read_dir("inner").await.unwrap().is_empty());
+
+    std::os::unix::fs::symlink("..", ns.root.join("up")).unwrap();
+    std::os::unix::fs::symlink(&secret_path, ns.root.join("absolute")).unwrap();
+    std::os::unix::fs::symlink(format!("../{secret}"), ns.root.join("relative")).unwrap();
+    // The links themselves are entries of the namespace
+    assert_eq!(client.metadata("up").await.unwrap().kind, SFlag::S_IFLNK);
+    assert!(escapes(client.metadata(&format!("up/{secret}")).await.map(|_| ())));
+    assert!(escapes(client.read_dir("up").await.map(|_| ())));
+    for link in ["absolute", "relative"] {
+        assert!(escapes(client.open(link, OFlag::O_RDONLY).await.map(|_| ())));
+        assert!(escapes(client.open(link, OFlag::O_WRONLY | OFlag::O_TRUNC).await.map(|_| ())));
+    }
+    assert_eq!(std::fs::read(&secret_path).unwrap(), b"secret");
+    std::fs::remove_file(&secret_path).unwrap();
+}
+
+#[tokio::test]
+async fn scoped_clients_stay_under_their_prefix() {
+    let ns = Namespace::new("scoped");
+    let client = &ns.client;
+    client.create_dir_all("tenants/alice/docs").await.unwrap();
+    client.create_dir_all("tenants/bob").await.unwrap();
+    let file = client.create("tenants/bob/secret").await.unwrap();
+    file.write_at(b"bob", 0).await.unwrap();
+    file.close().await.unwrap();
+    client.create("tenants/alice/secret").await.unwrap().close().await.unwrap();
+    assert!(client.scoped("tenants/bob/secret").await.is_err());
+    assert!(client.scoped("tenants/carol").await.is_err());
+
+    let alice = client.scoped("/tenants/alice").await.unwrap();
+    let file = alice.create("docs/a.txt").await.unwrap();
+    file.write_at(b"alice", 0).await.unwrap();
+    file.close().await.unwrap();
+    assert_eq!(std::fs::read(ns.root.join("tenants/alice/docs/a.txt")).unwrap(), b"alice");
+    let names = |entries: Vec<DirEntry>| {
+        let mut names: Vec<_> = entries.into_iter().map(|entry| entry.name).collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names(alice.read_dir("").await.unwrap()), ["docs", "secret"]);
+    assert_eq!(alice.metadata("docs/../secret").await.unwrap().size, 0);
+    // The same path names another file for each client, cached or not
+    assert!(client.metadata("secret").await.is_err());
+    assert_eq!(alice.metadata("secret").await.unwrap().size, 0);
+    assert_eq!(client.metadata("tenants/bob/secret").await.unwrap().size, 3);
+
+    let escapes =
+        |res: DatenLordResult<()>| matches!(res, Err(DatenLordError::PermissionDenied { .. }));
+    assert!(escapes(alice.metadata("../bob/secret").await.map(|_| ())));
+    assert!(escapes(alice.create("docs/../../bob/secret").await.map(|_| ())));
+    std::os::unix::fs::symlink("../bob", ns.root.join("tenants/alice/bob")).unwrap();
+    std::os::unix::fs::symlink("/tenants/bob/secret", ns.root.join("tenants/alice/abs")).unwrap();
+    assert!(escapes(alice.read_dir("bob").await.map(|_| ())));
+    assert!(escapes(alice.open("abs", OFlag::O_RDONLY).await.map(|_| ())));
+    // Nor do inodes looked up by a client with a wider root
+    let bob = client.metadata("tenants/bob/secret").await.unwrap();
+    let ctx = RequestContext {
+        root: client.metadata("tenants/alice").await.unwrap().ino,
+        ..RequestContext::current()
+    };
+    let localfs = LocalFS::new(&DatenLordConfig {
+        root: ns.root.clone(),
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    assert!(matches!(
+        localfs.getattr(&ctx, bob.ino).await,
+        Err(DatenLordError::PermissionDenied { .. })
+    ));
+
+    // Scopes nest, relative to the root of the client they come from
+    let docs = alice.scoped("docs").await.unwrap();
+    assert_eq!(docs.metadata("a.txt").await.unwrap().size, 5);
+    assert!(escapes(docs.metadata("../secret").await.map(|_| ())));
+    assert_eq!(std::fs::read(ns.root.join("tenants/bob/secret")).unwrap(), b"bob");
+}
+
+#[tokio::test]
+async fn invalid_names_are_rejected() {
+    let ns = Namespace::new("names");
+    let client = &ns.client;
+    let invalid =
+        |res: DatenLordResult<()>| matches!(res, Err(DatenLordError::InvalidName { .. }));
+    assert!(invalid(client.create("nul\0byte").await.map(|_| ())));
+    let longest = "x".repeat(255);
+    client.create(&longest).await.unwrap().close().await.unwrap();
+    assert!(invalid(client.create(&format!("{longest}x")).await.map(|_| ())));
+    assert!(invalid(client.metadata(&format!("{longest}x")).await.map(|_| ())));
+
+    let short = Client::new(&DatenLordConfig {
+        root: ns.root.clone(),
+        names: NameConfig {
+            max_len: 8,
+            ..NameConfig::default()
+        },
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    // The limit applies to every component of a path
+    short.create_dir_all("dir/12345678").await.unwrap();
+    let err = short.create_dir_all("dir/123456789").await.unwrap_err();
+    assert_eq!(err.errno(), Some(nix::errno::Errno::EINVAL));
+    assert!(invalid(Err(err)));
+
+    // Names written by other programs need not be UTF-8
+    std::fs::write(ns.root.join(OsStr::from_bytes(b"caf\xe9")), b"").unwrap();
+    let names: Vec<_> = client
+        .read_dir("")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    assert!(names.iter().any(|name| name.as_bytes() == b"caf\xe9"), "{names:?}");
+    let strict = Client::new(&DatenLordConfig {
+        root: ns.root.clone(),
+        names: NameConfig {
+            require_utf8: true,
+            ..NameConfig::default()
+        },
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    assert!(invalid(strict.read_dir("").await.map(|_| ())));
+    assert!(strict.read_dir("dir").await.is_ok());
+}
+
+#[tokio::test]
+async fn names_need_not_be_utf8() {
+    let ns = Namespace::new("bytes");
+    let client = &ns.client;
+    let latin1 = OsStr::from_bytes(b"caf\xe9");
+    client.create_dir_all(OsStr::from_bytes(b"d\xefr")).await.unwrap();
+    let path = OsStr::from_bytes(b"d\xefr/caf\xe9");
+    let file = client.create(path).await.unwrap();
+    file.write_at(b"latin-1", 0).await.unwrap();
+    file.close().await.unwrap();
+    assert_eq!(std::fs::read(ns.root.join(path)).unwrap(), b"latin-1");
+
+    let entries = client.read_dir(OsStr::from_bytes(b"d\xefr")).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, latin1);
+    let mut file = client.open(path, OFlag::O_RDONLY).await.unwrap();
+    let mut content = Vec::new();
+    file.read_to_end(&mut content).await.unwrap();
+    assert_eq!(content, b"latin-1");
+    // A UTF-8 name with the same characters is another entry
+    assert!(!client.exists("d\u{ef}r/caf\u{e9}").await);
+    client.remove(path).await.unwrap();
+    assert!(!ns.root.join(path).exists());
+}
+
+#[tokio::test]
+async fn warm_files_are_reopened_when_entries_change() {
+    let ns = Namespace::new("warm");
+    let write = |name: &'static str, content: &'static [u8]| {
+        let client = ns.client.clone();
+        async move {
+            let file = client.create(name).await.unwrap();
+            file.write_at(content, 0).await.unwrap();
+            file.close().await.unwrap();
+        }
+    };
+    let read = |client: Client| async move {
+        let mut file = client.open("settings.json", OFlag::O_RDONLY).await.unwrap();
+        let mut content = String::new();
+        file.read_to_string(&mut content).await.unwrap();
+        file.close().await.unwrap();
+        content
+    };
+    write("settings.json", b"{}").await;
+    // Listed in the config, the file is open once the client is
+    let warmed = Client::new(&DatenLordConfig {
+        root: ns.root.clone(),
+        warm_files: vec!["settings.json".to_owned()],
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    assert_eq!(read(warmed.clone()).await, "{}");
+    assert_eq!(read(warmed.clone()).await, "{}");
+
+    // A removed file is not read through its former handle
+    ns.client.warm("settings.json").await.unwrap();
+    ns.client.remove("settings.json").await.unwrap();
+    assert!(ns.client.open("settings.json", OFlag::O_RDONLY).await.is_err());
+    write("settings.json", b"{\"v\": 2}").await;
+    assert_eq!(read(ns.client.clone()).await, "{\"v\": 2}");
+    ns.client.create_dir_all("dir").await.unwrap();
+    assert!(matches!(
+        ns.client.warm("dir").await,
+        Err(DatenLordError::InvalidArgument { .. })
+    ));
+}
+
+#[tokio::test]
+async fn setid_bits_follow_groups_writes_and_owners() {
+    // Entries owned by other users need root
+    if !nix::unistd::geteuid().is_root() {
+        return;
+    }
+    let ns = Namespace::new("setid");
+    std::fs::set_permissions(&ns.root, std::fs::Permissions::from_mode(0o777)).unwrap();
+    let localfs = LocalFS::new(&DatenLordConfig {
+        root: ns.root.clone(),
+        attr_cache_capacity: 0,
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    let as_user = |uid, gid| RequestContext {
+        uid,
+        gid,
+        umask: 0,
+        ..RequestContext::current()
+    };
+    let (user, team, root) = (as_user(2000, 2000), as_user(1000, 4000), as_user(0, 0));
+    let flags = (OFlag::O_CREAT | OFlag::O_WRONLY).bits() as u32;
+    let create = |ctx, parent, name: &'static str, mode| {
+        let localfs = &localfs;
+        async move {
+            localfs.create(&ctx, 0, parent, OsStr::new(name), mode, flags).await.unwrap();
+            localfs.lookup(&ctx, parent, OsStr::new(name)).await.unwrap().1
+        }
+    };
+    let write = |ctx, ino| {
+        let localfs = &localfs;
+        async move {
+            let fh = localfs.open(&ctx, ino, OFlag::O_WRONLY.bits() as u32).await.unwrap();
+            localfs.write(&ctx, ino, fh, 0, b"patched", 0).await.unwrap();
+            localfs.release(&ctx, ino, fh, 0, 0, false).await.unwrap();
+            localfs.getattr(&ctx, ino).await.unwrap().1.perm
+        }
+    };
+
+    // Entries of a set-group-ID directory take its group, directories the bit too
+    let mkdir = |parent, name: &str, mode| CreateParam {
+        parent,
+        name: name.into(),
+        mode,
+        rdev: 0,
+        node_type: SFlag::S_IFDIR,
+        link: None,
+    };
+    let (_, shared, _) = localfs.mkdir(&team, mkdir(ROOT_ID, "shared", 0o2777)).await.unwrap();
+    assert_eq!((shared.gid, shared.perm), (4000, 0o2777));
+    let (_, sub, _) = localfs.mkdir(&user, mkdir(shared.ino, "sub", 0o755)).await.unwrap();
+    assert_eq!((sub.uid, sub.gid, sub.perm), (2000, 4000, 0o2755));
+    // Outside the group, the bit is dropped from executables
+    let tool = create(user, shared.ino, "tool", 0o2755).await;
+    assert_eq!((tool.gid, tool.perm), (4000, 0o755));
+    let plain = create(user, ROOT_ID, "plain", 0o644).await;
+    assert_eq!(plain.gid, 2000);
+
+    // Writes by other callers than root drop the bits, but not a locking marker
+    let setid = create(user, ROOT_ID, "setid", 0o6755).await;
+    assert_eq!(setid.perm, 0o6755);
+    assert_eq!(write(user, setid.ino).await, 0o755);
+    let locked = create(user, ROOT_ID, "locked", 0o6744).await;
+    assert_eq!(write(user, locked.ino).await, 0o2744);
+    let privileged = create(root, ROOT_ID, "privileged", 0o4755).await;
+    assert_eq!(write(root, privileged.ino).await, 0o4755);
+
+    // So does changing the owner
+    let chowned = create(user, ROOT_ID, "chowned", 0o6755).await;
+    let param = SetAttrParam {
+        g_id: Some(4000),
+        ..SetAttrParam::default()
+    };
+    let (_, attr) = localfs.setattr(&user, chowned.ino, param).await.unwrap();
+    assert_eq!((attr.gid, attr.perm), (4000, 0o755));
+}
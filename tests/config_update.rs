@@ -0,0 +1,107 @@
+//! Changes the config of an open SDK while running
+use std::path::PathBuf;
+
+use datenlord::common::config::{ConfigUpdate, DatenLordConfig};
+use datenlord::common::DatenLordError;
+use datenlord::sdk::rust::Client;
+
+/// A root removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "datenlord-config-update-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+
+    fn config(&self) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        }
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The cache hits of looking `path` up twice
+async fn hits(client: &Client, path: &str) -> u64 {
+    let before = client.stats().cache_hits;
+    client.metadata(path).await.unwrap();
+    client.metadata(path).await.unwrap();
+    client.stats().cache_hits - before
+}
+
+#[tokio::test]
+async fn updates_apply_to_open_clients_and_files() {
+    let root = Root::new("apply");
+    let client = Client::new(&root.config()).unwrap();
+    let file = client.create("data").await.unwrap();
+    assert!(hits(&client, "data").await > 0);
+
+    let update = ConfigUpdate::parse(
+        r#"{"attr_cache_capacity": 0, "op_timeout_ms": 5000, "log_level": "warn"}"#,
+    )
+    .unwrap();
+    client.update_config(&update).unwrap();
+    assert_eq!(hits(&client, "data").await, 0);
+    // Handles opened before keep working
+    file.write_at(b"data", 0).await.unwrap();
+    file.close().await.unwrap();
+
+    client
+        .update_config(&ConfigUpdate::parse(r#"{"attr_cache_capacity": 16}"#).unwrap())
+        .unwrap();
+    assert!(hits(&client, "data").await > 0);
+}
+
+#[tokio::test]
+async fn failing_updates_change_nothing() {
+    let root = Root::new("failing");
+    let client = Client::new(&root.config()).unwrap();
+    client.create("data").await.unwrap().close().await.unwrap();
+
+    assert!(matches!(
+        ConfigUpdate::parse(r#"{"root": "/elsewhere"}"#),
+        Err(DatenLordError::InvalidArgument { .. })
+    ));
+    let update = ConfigUpdate::parse(r#"{"attr_cache_capacity": 0, "log_level": "loud"}"#).unwrap();
+    assert!(matches!(
+        client.update_config(&update),
+        Err(DatenLordError::InvalidArgument { .. })
+    ));
+    assert!(hits(&client, "data").await > 0);
+}
+
+#[test]
+fn updates_record_in_the_config() {
+    let mut config = DatenLordConfig {
+        op_timeout_ms: Some(100),
+        ..DatenLordConfig::default()
+    };
+    let update =
+        ConfigUpdate::parse(r#"{"op_timeout_ms": 0, "retry": {"max_retries": 7}}"#).unwrap();
+    update.apply(&mut config);
+    assert_eq!(config.op_timeout_ms, None);
+    assert_eq!(config.retry.max_retries, 7);
+    assert_eq!(
+        config.attr_cache_capacity,
+        DatenLordConfig::default().attr_cache_capacity
+    );
+
+    let reloaded = ConfigUpdate::from_config(&config);
+    assert_eq!(reloaded.op_timeout_ms, Some(0));
+    assert_eq!(
+        reloaded.attr_cache_capacity,
+        Some(config.attr_cache_capacity)
+    );
+    assert!(reloaded.log_level.is_none());
+}
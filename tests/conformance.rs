@@ -0,0 +1,289 @@
+//! Runs the scenarios of `tests/conformance/scenarios` through the rust
+//! client, the C ABI and the python bindings and checks every operation has
+//! the same outcome and error code on each
+//!
+//! A scenario has one operation per line, its fields separated by single
+//! spaces, and `#` comments. Each surface runs it in a fresh root and writes
+//! one outcome per operation: `ok`, `ok <value>` or `err <code>`, where the
+//! code is the errno of the error or 1 when none applies. The C runner, and
+//! the C++ one going through `include/datenlord.hpp`, are compiled with
+//! `$CXX`, `c++` by default, and the python one runs on `$PYTHON`, `python3`
+//! by default; they are skipped when missing.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordResult;
+use datenlord::sdk::rust::Client;
+use nix::fcntl::OFlag;
+
+/// The directory of the runners and scenarios
+fn conformance_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance")
+}
+
+/// The scenario files, sorted by name
+fn scenarios() -> Vec<PathBuf> {
+    let mut scenarios: Vec<PathBuf> = std::fs::read_dir(conformance_dir().join("scenarios"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    scenarios.sort();
+    scenarios
+}
+
+/// The operations of `scenario`, without blank and comment lines
+fn operations(scenario: &Path) -> Vec<String> {
+    std::fs::read_to_string(scenario)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// A fresh root for `surface` running `scenario`, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(surface: &str, scenario: &Path) -> Self {
+        let name = scenario.file_stem().unwrap().to_string_lossy();
+        let root = std::env::temp_dir().join(format!(
+            "datenlord-conformance-{surface}-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    /// The config of an sdk rooted here, as JSON
+    fn config(&self) -> String {
+        format!(r#"{{"root": {:?}}}"#, self.0)
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The outcome line of `result`
+fn outcome(result: DatenLordResult<Option<String>>) -> String {
+    match result {
+        Ok(None) => "ok".to_owned(),
+        Ok(Some(value)) => format!("ok {value}"),
+        Err(e) => format!("err {}", e.errno().map_or(1, |errno| errno as i32)),
+    }
+}
+
+/// Run the operation `line` through `client`, returning its value if any
+async fn run_rust_operation(client: &Client, line: &str) -> DatenLordResult<Option<String>> {
+    let mut fields = line.splitn(3, ' ');
+    let (op, path) = (fields.next().unwrap(), fields.next().unwrap());
+    let rest = fields.next().unwrap_or_default();
+    match op {
+        "mkdir_all" => client.create_dir_all(path).await?,
+        "create" => client.create(path).await?.close().await?,
+        "write" => {
+            let file = client.open(path, OFlag::O_WRONLY).await?;
+            file.write_at(rest.as_bytes(), 0).await?;
+            file.close().await?;
+        }
+        "read" => {
+            let file = client.open(path, OFlag::O_RDONLY).await?;
+            let mut content = Vec::new();
+            let mut buf = [0; 4096];
+            loop {
+                let read = file.read_at(&mut buf, content.len() as u64).await?;
+                if read == 0 {
+                    break;
+                }
+                content.extend_from_slice(&buf[..read]);
+            }
+            file.close().await?;
+            return Ok(Some(String::from_utf8(content).unwrap()));
+        }
+        "stat" => {
+            let attr = client.metadata(path).await?;
+            let mode = attr.kind.bits() | u32::from(attr.perm);
+            return Ok(Some(format!("{mode:o} {}", attr.size)));
+        }
+        "exists" => return Ok(Some(client.exists(path).await.to_string())),
+        "list" => {
+            let mut names: Vec<String> = client
+                .read_dir(path)
+                .await?
+                .into_iter()
+                .map(|entry| entry.name.to_string_lossy().into_owned())
+                .filter(|name| name != "." && name != "..")
+                .collect();
+            names.sort();
+            return Ok(Some(names.join(",")));
+        }
+        // The scenarios only remove directories, which `remove` does like
+        // `rmdir`
+        "rmdir" => client.remove(path).await?,
+        "set_tag" => {
+            let (key, value) = rest.split_once(' ').unwrap();
+            client.set_tag(path, key, value).await?;
+        }
+        "remove_tag" => client.remove_tag(path, rest).await?,
+        _ => panic!("unknown operation {op}"),
+    }
+    Ok(None)
+}
+
+/// The outcomes of `scenario` run through the rust client
+fn run_rust(scenario: &Path) -> Vec<String> {
+    let root = Root::new("rust", scenario);
+    let config = DatenLordConfig {
+        root: root.0.clone(),
+        ..DatenLordConfig::default()
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let client = Client::new(&config).unwrap();
+        let mut outcomes = Vec::new();
+        for line in operations(scenario) {
+            outcomes.push(outcome(run_rust_operation(&client, &line).await));
+        }
+        outcomes
+    })
+}
+
+/// Run `command`, panicking with its output if it fails
+fn check(command: &mut Command) {
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{command:?} failed:\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// The directory holding the libraries cargo built for the tests
+fn target_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().parent().unwrap().to_owned()
+}
+
+/// Build the shared library the C and python runners load, which cargo
+/// does not build for integration tests
+fn build_shared_library() {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned());
+    let mut build = Command::new(cargo);
+    build.args(["build", "--lib"]).current_dir(env!("CARGO_MANIFEST_DIR"));
+    if target_dir().ends_with("release") {
+        build.arg("--release");
+    }
+    check(&mut build);
+}
+
+/// The python interpreter and its library directory and version, `None`
+/// when there is no interpreter
+fn python() -> Option<(String, String, String)> {
+    let python = std::env::var("PYTHON").unwrap_or_else(|_| "python3".to_owned());
+    let config = "import sysconfig; print(sysconfig.get_config_var('LIBDIR')); \
+                  print(sysconfig.get_config_var('LDVERSION'))";
+    let output = Command::new(&python).args(["-c", config]).output().ok()?;
+    let output = String::from_utf8(output.stdout).ok()?;
+    let mut lines = output.lines();
+    let (libdir, version) = (lines.next()?.to_owned(), lines.next()?.to_owned());
+    Some((python, libdir, version))
+}
+
+/// Compile the runner `source` of `tests/conformance` with `flags`, `None`
+/// without a compiler
+///
+/// The library leaves the python symbols of its bindings to the
+/// interpreter loading it, so the runner links libpython to load it.
+fn compile_runner(source: &str, flags: &[&str], libdir: &str, version: &str) -> Option<PathBuf> {
+    let compiler = std::env::var("CXX").unwrap_or_else(|_| "c++".to_owned());
+    Command::new(&compiler).arg("--version").output().ok()?;
+    let runner = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("conformance-{source}"));
+    let target = target_dir();
+    check(
+        Command::new(&compiler)
+            .args(flags)
+            .arg(conformance_dir().join(source))
+            .arg("-o")
+            .arg(&runner)
+            .arg(format!("-I{}", Path::new(env!("CARGO_MANIFEST_DIR")).join("include").display()))
+            .arg(format!("-L{}", target.display()))
+            .arg(format!("-Wl,-rpath,{}", target.display()))
+            .arg("-ldatenlord")
+            .arg(format!("-L{libdir}"))
+            .arg(format!("-Wl,-rpath,{libdir}"))
+            .arg(format!("-lpython{version}")),
+    );
+    Some(runner)
+}
+
+/// The outcomes of `scenario` run by a runner invoked as `command`, which
+/// takes the config, the scenario and the output file as its last arguments
+fn run_external(surface: &str, command: &mut Command, scenario: &Path) -> Vec<String> {
+    let root = Root::new(surface, scenario);
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("conformance-{surface}.out"));
+    check(command.arg(root.config()).arg(scenario).arg(&output));
+    std::fs::read_to_string(output)
+        .unwrap()
+        .lines()
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Panic listing the operations of `scenario` whose outcomes on `surface`
+/// differ from the rust ones
+fn compare(scenario: &Path, surface: &str, rust: &[String], other: &[String]) {
+    let operations = operations(scenario);
+    let ran = other.len();
+    assert_eq!(ran, operations.len(), "{surface} did not run every operation of {scenario:?}");
+    let mismatches: Vec<String> = operations
+        .iter()
+        .zip(rust.iter().zip(other))
+        .filter(|(_, (rust, other))| rust != other)
+        .map(|(op, (rust, other))| format!("  {op}: rust `{rust}`, {surface} `{other}`"))
+        .collect();
+    assert!(
+        mismatches.is_empty(),
+        "{scenario:?} differs between rust and {surface}:\n{}",
+        mismatches.join("\n")
+    );
+}
+
+#[test]
+fn sdks_agree_on_every_scenario() {
+    let scenarios = scenarios();
+    assert!(!scenarios.is_empty());
+    let Some((python, libdir, version)) = python() else {
+        eprintln!("skipping the C and python runners, no python interpreter found");
+        return;
+    };
+
+    build_shared_library();
+    // The python module is the library under the module name
+    let module_dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    std::fs::copy(target_dir().join("libdatenlord.so"), module_dir.join("datenlord.so")).unwrap();
+    let c_runner = compile_runner("runner.c", &[], &libdir, &version);
+    let cpp_runner = compile_runner("runner.cpp", &["-std=c++20"], &libdir, &version);
+    if c_runner.is_none() {
+        eprintln!("skipping the C and C++ runners, no C++ compiler found");
+    }
+
+    for scenario in &scenarios {
+        let rust = run_rust(scenario);
+        let mut py = Command::new(&python);
+        py.arg(conformance_dir().join("runner.py")).arg(module_dir);
+        compare(scenario, "python", &rust, &run_external("python", &mut py, scenario));
+        if let Some(ref c_runner) = c_runner {
+            let c = run_external("c", &mut Command::new(c_runner), scenario);
+            compare(scenario, "c", &rust, &c);
+        }
+        if let Some(ref cpp_runner) = cpp_runner {
+            let cpp = run_external("cpp", &mut Command::new(cpp_runner), scenario);
+            compare(scenario, "cpp", &rust, &cpp);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+// Runs a conformance scenario through the C SDK, see `tests/conformance.rs`
+//
+// Usage: runner <config> <scenario> <output>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include "datenlord.h"
+
+#define MAX_LINE 4096
+#define MAX_CONTENT (1 << 16)
+#define MAX_ENTRIES 1024
+
+static FILE *out;
+
+// Report the outcome of a call without a value, freeing `err`
+static void report(datenlord_error *err) {
+    if (err == NULL) {
+        fprintf(out, "ok\n");
+    } else {
+        fprintf(out, "err %u\n", err->code);
+        datenlord_error_free(err);
+    }
+}
+
+static int compare_names(const void *a, const void *b) {
+    return strcmp(*(char *const *)a, *(char *const *)b);
+}
+
+static void run_read(datenlord_sdk *sdk, const char *path) {
+    static uint8_t content[MAX_CONTENT];
+    datenlord_bytes bytes = { content, sizeof(content) };
+    datenlord_error *err = read_file(sdk, path, &bytes);
+    if (err != NULL) {
+        report(err);
+        return;
+    }
+    fprintf(out, "ok %.*s\n", (int)bytes.len, (const char *)content);
+}
+
+static void run_stat(datenlord_sdk *sdk, const char *path) {
+    datenlord_stat st;
+    datenlord_error *err = stat(sdk, path, &st);
+    if (err != NULL) {
+        report(err);
+        return;
+    }
+    fprintf(out, "ok %o %llu\n", st.mode, (unsigned long long)st.size);
+}
+
+static void run_list(datenlord_sdk *sdk, const char *path) {
+    datenlord_dir *dir = NULL;
+    datenlord_error *err = datenlord_opendir(sdk, path, false, &dir);
+    if (err != NULL) {
+        report(err);
+        return;
+    }
+    char *names[MAX_ENTRIES];
+    size_t count = 0;
+    datenlord_dir_entry entry;
+    while (count < MAX_ENTRIES && datenlord_readdir(dir, &entry)) {
+        if (strcmp(entry.name, ".") != 0 && strcmp(entry.name, "..") != 0) {
+            names[count++] = strdup(entry.name);
+        }
+    }
+    datenlord_closedir(dir);
+    qsort(names, count, sizeof(char *), compare_names);
+    fprintf(out, "ok ");
+    for (size_t i = 0; i < count; i++) {
+        fprintf(out, i == 0 ? "%s" : ",%s", names[i]);
+        free(names[i]);
+    }
+    fprintf(out, "\n");
+}
+
+// Run the operation of `line`, whose fields are separated by single spaces
+static void run(datenlord_sdk *sdk, char *line) {
+    char *op = strtok(line, " ");
+    char *path = strtok(NULL, " ");
+    char *rest = strtok(NULL, "");
+    if (strcmp(op, "mkdir_all") == 0) {
+        report(datenlord_mkdir_all(sdk, path, 0777));
+    } else if (strcmp(op, "create") == 0) {
+        report(create_file(sdk, path, false));
+    } else if (strcmp(op, "write") == 0) {
+        datenlord_bytes content = { (const uint8_t *)rest, strlen(rest) };
+        report(write_file(sdk, path, content));
+    } else if (strcmp(op, "read") == 0) {
+        run_read(sdk, path);
+    } else if (strcmp(op, "stat") == 0) {
+        run_stat(sdk, path);
+    } else if (strcmp(op, "exists") == 0) {
+        fprintf(out, "ok %s\n", exists(sdk, path) ? "true" : "false");
+    } else if (strcmp(op, "list") == 0) {
+        run_list(sdk, path);
+    } else if (strcmp(op, "rmdir") == 0) {
+        report(deldir(sdk, path, false));
+    } else if (strcmp(op, "set_tag") == 0) {
+        char *key = strtok(rest, " ");
+        char *value = strtok(NULL, "");
+        report(datenlord_set_tag(sdk, path, key, value));
+    } else if (strcmp(op, "remove_tag") == 0) {
+        report(datenlord_remove_tag(sdk, path, rest));
+    } else {
+        fprintf(stderr, "unknown operation %s\n", op);
+        exit(2);
+    }
+}
+
+int main(int argc, char **argv) {
+    if (argc != 4) {
+        fprintf(stderr, "usage: %s <config> <scenario> <output>\n", argv[0]);
+        return 2;
+    }
+    datenlord_sdk *sdk = init(argv[1]);
+    FILE *scenario = fopen(argv[2], "r");
+    out = fopen(argv[3], "w");
+    if (sdk == NULL || scenario == NULL || out == NULL) {
+        fprintf(stderr, "failed to start the scenario\n");
+        return 2;
+    }
+    char line[MAX_LINE];
+    while (fgets(line, sizeof(line), scenario) != NULL) {
+        line[strcspn(line, "\n")] = '\0';
+        if (line[0] == '\0' || line[0] == '#') {
+            continue;
+        }
+        run(sdk, line);
+    }
+    fclose(scenario);
+    fclose(out);
+    free_sdk(sdk);
+    return 0;
+}
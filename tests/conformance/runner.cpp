@@ -0,0 +1,101 @@
+// Runs a conformance scenario through the C++ wrappers of `datenlord.hpp`,
+// see `tests/conformance.rs`
+//
+// Usage: runner-cpp <config> <scenario> <output>
+#include <algorithm>
+#include <array>
+#include <cstdlib>
+#include <fstream>
+#include <iostream>
+#include <sstream>
+#include <string>
+#include <vector>
+
+#include "datenlord.hpp"
+
+static std::ofstream out;
+
+static void run_list(const datenlord::Sdk &sdk, const std::string &path) {
+    datenlord::Dir dir = sdk.list(path);
+    std::vector<std::string> names;
+    while (auto entry = dir.next()) {
+        if (entry->name != "." && entry->name != "..") {
+            names.push_back(std::move(entry->name));
+        }
+    }
+    std::sort(names.begin(), names.end());
+    out << "ok ";
+    for (size_t i = 0; i < names.size(); i++) {
+        out << (i == 0 ? "" : ",") << names[i];
+    }
+    out << "\n";
+}
+
+// Run the operation of `line`, whose fields are separated by single spaces
+static void run(datenlord::Sdk &sdk, const std::string &line) {
+    std::istringstream fields(line);
+    std::string op, path, rest;
+    std::getline(fields, op, ' ');
+    std::getline(fields, path, ' ');
+    std::getline(fields, rest);
+    if (op == "mkdir_all") {
+        sdk.mkdir_all(path);
+    } else if (op == "create") {
+        sdk.create(path);
+    } else if (op == "write") {
+        sdk.file(path).write_at(std::as_bytes(std::span(rest)), 0);
+    } else if (op == "read") {
+        std::string content;
+        std::array<std::byte, 4096> buf;
+        datenlord::File file = sdk.file(path);
+        while (size_t read = file.read_at(buf, content.size())) {
+            content.append(reinterpret_cast<const char *>(buf.data()), read);
+        }
+        out << "ok " << content << "\n";
+        return;
+    } else if (op == "stat") {
+        datenlord_stat st = sdk.stat(path);
+        out << "ok " << std::oct << st.mode << std::dec << " " << st.size << "\n";
+        return;
+    } else if (op == "exists") {
+        out << "ok " << (sdk.exists(path) ? "true" : "false") << "\n";
+        return;
+    } else if (op == "list") {
+        run_list(sdk, path);
+        return;
+    } else if (op == "rmdir") {
+        sdk.remove_dir(path);
+    } else if (op == "set_tag") {
+        size_t space = rest.find(' ');
+        sdk.set_tag(path, rest.substr(0, space), rest.substr(space + 1));
+    } else if (op == "remove_tag") {
+        sdk.remove_tag(path, rest);
+    } else {
+        std::cerr << "unknown operation " << op << "\n";
+        std::exit(2);
+    }
+    out << "ok\n";
+}
+
+int main(int argc, char **argv) {
+    if (argc != 4) {
+        std::cerr << "usage: " << argv[0] << " <config> <scenario> <output>\n";
+        return 2;
+    }
+    datenlord::Sdk sdk(argv[1]);
+    std::ifstream scenario(argv[2]);
+    out.open(argv[3]);
+    std::string line;
+    while (std::getline(scenario, line)) {
+        if (line.empty() || line[0] == '#') {
+            continue;
+        }
+        try {
+            run(sdk, line);
+        } catch (const datenlord::Error &e) {
+            out << "err " << e.code() << "\n";
+        }
+    }
+    sdk.shutdown();
+    return 0;
+}
@@ -0,0 +1,68 @@
+"""Runs a conformance scenario through the python SDK, see `tests/conformance.rs`
+
+Usage: runner.py <module dir> <config> <scenario> <output>
+"""
+import errno
+import sys
+
+sys.path.insert(0, sys.argv[1])
+import datenlord  # noqa: E402
+
+
+def run(sdk, line):
+    """The outcome of the operation of `line`, whose fields are separated by single spaces"""
+    op, path, *rest = line.split(" ", 2)
+    rest = rest[0] if rest else ""
+    if op == "mkdir_all":
+        sdk.mkdir_all(path)
+    elif op == "create":
+        sdk.create_file(path)
+    elif op == "write":
+        sdk.write_file(path, rest.encode())
+    elif op == "read":
+        return "ok " + bytes(sdk.read_file(path)).decode()
+    elif op == "stat":
+        st = sdk.stat(path)
+        return f"ok {st.st_mode:o} {st.st_size}"
+    elif op == "exists":
+        return "ok " + ("true" if sdk.exists(path) else "false")
+    elif op == "list":
+        names = [name for name in sdk.list_dir(path) if name not in (".", "..")]
+        return "ok " + ",".join(sorted(names))
+    elif op == "rmdir":
+        sdk.deldir(path, False)
+    elif op == "set_tag":
+        key, value = rest.split(" ", 1)
+        sdk.set_tag(path, key, value)
+    elif op == "remove_tag":
+        sdk.remove_tag(path, rest)
+    else:
+        sys.exit(f"unknown operation {op}")
+    return "ok"
+
+
+def main():
+    _, _, config, scenario, output = sys.argv
+    sdk = datenlord.init_sdk(config)
+    with open(scenario) as lines, open(output, "w") as out:
+        for line in lines:
+            line = line.rstrip("\n")
+            if not line or line.startswith("#"):
+                continue
+            try:
+                outcome = run(sdk, line)
+            except datenlord.errors.DatenlordError as e:
+                # The C SDK reports errors without a specific errno as 1
+                outcome = f"err {e.errno or 1}"
+                # Every failure names its path, and ENOENT is `NotFound`
+                path = line.split(" ")[1]
+                if e.path != path or (e.errno == errno.ENOENT) != isinstance(e, datenlord.errors.NotFound):
+                    outcome += f" raised {type(e).__name__} on {e.path!r}"
+            except ValueError:
+                # Invalid arguments, such as tag keys, raise `ValueError`
+                outcome = f"err {errno.EINVAL}"
+            out.write(outcome + "\n")
+
+
+if __name__ == "__main__":
+    main()
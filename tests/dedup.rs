@@ -0,0 +1,176 @@
+//! Keeps identical blocks of file data once in namespaces with the `dedup`
+//! feature
+use std::path::PathBuf;
+use std::time::Duration;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::superblock::Feature;
+use nix::fcntl::OFlag;
+
+const BLOCK: usize = 128 * 1024;
+
+/// A root removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("datenlord-dedup-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+
+    fn config(&self, features: Vec<Feature>) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            features,
+            ..DatenLordConfig::default()
+        }
+    }
+
+    fn client(&self) -> Client {
+        Client::new(&self.config(vec![Feature::Dedup])).unwrap()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// `blocks` blocks, block `i` filled with `seed + i`
+fn checkpoint(seed: u8, blocks: usize) -> Vec<u8> {
+    (0..blocks * BLOCK)
+        .map(|i| seed.wrapping_add((i / BLOCK) as u8))
+        .collect()
+}
+
+async fn write(client: &Client, path: &str, data: &[u8]) {
+    let file = client.create(path).await.unwrap();
+    file.write_at(data, 0).await.unwrap();
+    file.close().await.unwrap();
+}
+
+async fn read(client: &Client, path: &str) -> Vec<u8> {
+    let file = client.open(path, OFlag::O_RDONLY).await.unwrap();
+    let mut data = vec![0; file.metadata().await.unwrap().size as usize];
+    let n = file.read_at(&mut data, 0).await.unwrap();
+    data.truncate(n);
+    file.close().await.unwrap();
+    data
+}
+
+#[tokio::test]
+async fn identical_blocks_are_stored_once() {
+    let root = Root::new("share");
+    let client = root.client();
+    let first = checkpoint(1, 4);
+    let mut second = first.clone();
+    second[2 * BLOCK + 10] = 0xff;
+    write(&client, "ckpt-1", &first).await;
+    write(&client, "ckpt-2", &second).await;
+    write(&client, "ckpt-3", &first).await;
+    assert_eq!(read(&client, "ckpt-1").await, first);
+    assert_eq!(read(&client, "ckpt-2").await, second);
+    assert_eq!(read(&client, "ckpt-3").await, first);
+
+    let stats = client.dedup_stats().unwrap();
+    assert_eq!(stats.blocks, 5);
+    assert_eq!(stats.stored_bytes, 5 * BLOCK as u64);
+    assert_eq!(stats.referenced_bytes, 12 * BLOCK as u64);
+    // The root only keeps the sizes
+    assert!(std::fs::read(root.0.join("ckpt-1"))
+        .unwrap()
+        .iter()
+        .all(|&b| b == 0));
+
+    // Blocks go with their last reference
+    client.remove("ckpt-1").await.unwrap();
+    assert_eq!(client.dedup_stats().unwrap().blocks, 5);
+    client.remove("ckpt-3").await.unwrap();
+    assert_eq!(client.dedup_stats().unwrap().blocks, 4);
+    assert_eq!(read(&client, "ckpt-2").await, second);
+    client.remove("ckpt-2").await.unwrap();
+    assert_eq!(client.dedup_stats().unwrap().blocks, 0);
+}
+
+#[tokio::test]
+async fn partial_writes_survive_reopening() {
+    let root = Root::new("partial");
+    let client = root.client();
+    let mut expected = vec![0; 3 * BLOCK + 100];
+    expected[BLOCK - 5..BLOCK + 5].fill(7);
+    expected[3 * BLOCK..].fill(9);
+    let file = client.create("f").await.unwrap();
+    file.write_at(&expected[BLOCK - 5..BLOCK + 5], (BLOCK - 5) as u64)
+        .await
+        .unwrap();
+    file.write_at(&expected[3 * BLOCK..], 3 * BLOCK as u64)
+        .await
+        .unwrap();
+    file.close().await.unwrap();
+    assert_eq!(read(&client, "f").await, expected);
+    // Block 2 is a hole
+    assert_eq!(client.dedup_stats().unwrap().blocks, 3);
+
+    drop(client);
+
+    let client = root.client();
+    assert_eq!(read(&client, "f").await, expected);
+    // Truncating drops the blocks
+    let file = client
+        .open("f", OFlag::O_WRONLY | OFlag::O_TRUNC)
+        .await
+        .unwrap();
+    file.close().await.unwrap();
+    assert!(read(&client, "f").await.is_empty());
+    assert_eq!(client.dedup_stats().unwrap().blocks, 0);
+}
+
+#[tokio::test]
+async fn garbage_collection_drops_lost_block_maps() {
+    let root = Root::new("gc");
+    let client = root.client();
+    write(&client, "kept", &checkpoint(1, 2)).await;
+    write(&client, "lost", &checkpoint(3, 2)).await;
+    let lost = client.metadata("lost").await.unwrap().ino;
+    // Removed behind the SDK
+    std::fs::remove_file(root.0.join("lost")).unwrap();
+
+    let report = client.collect_garbage(Duration::ZERO, true).await.unwrap();
+    assert_eq!(report.orphans, [format!("block map of inode {lost}")]);
+    assert_eq!((report.removed, report.bytes), (0, 2 * BLOCK as u64));
+    assert_eq!(client.dedup_stats().unwrap().blocks, 4);
+
+    let report = client.collect_garbage(Duration::ZERO, false).await.unwrap();
+    assert_eq!(report.removed, 1);
+    assert_eq!(client.dedup_stats().unwrap().blocks, 2);
+    assert_eq!(read(&client, "kept").await, checkpoint(1, 2));
+    let report = client.collect_garbage(Duration::ZERO, false).await.unwrap();
+    assert!(report.orphans.is_empty(), "{report:?}");
+}
+
+#[tokio::test]
+async fn enabling_the_feature_keeps_existing_files() {
+    let root = Root::new("enable");
+    let client = Client::new(&root.config(Vec::new())).unwrap();
+    write(&client, "old", b"written before").await;
+    assert!(client.dedup_stats().is_none());
+    drop(client);
+
+    LocalFS::new(&root.config(Vec::new()))
+        .unwrap()
+        .enable_feature(Feature::Dedup)
+        .unwrap();
+    let client = Client::new(&root.config(Vec::new())).unwrap();
+    assert_eq!(read(&client, "old").await, b"written before");
+    assert_eq!(client.dedup_stats().unwrap().blocks, 0);
+
+    // Replacing the file deduplicates it
+    write(&client, "old", b"written after").await;
+    assert_eq!(read(&client, "old").await, b"written after");
+    assert_eq!(client.dedup_stats().unwrap().blocks, 1);
+}
@@ -0,0 +1,237 @@
+//! Compares directory trees of local namespaces with each other and with
+//! manifests
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::diff::manifest::{self, Manifest, ManifestEntry, ProblemKind, VerifyOptions};
+use datenlord::diff::{self, ChangeKind, Changeset, DiffOptions};
+use datenlord::sdk::rust::Client;
+use datenlord::storage::digest::DigestAlgorithm;
+use datenlord::storage::localfs::LocalFS;
+
+/// A client rooted in a fresh directory, removed on drop
+///
+/// The attribute cache is off, modification times are changed behind the
+/// back of the client.
+struct Namespace {
+    config: DatenLordConfig,
+    client: Client,
+}
+
+impl Namespace {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("datenlord-diff-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let config = DatenLordConfig {
+            root,
+            attr_cache_capacity: 0,
+            ..DatenLordConfig::default()
+        };
+        let client = Client::new(&config).unwrap();
+        Self { config, client }
+    }
+
+    /// Write `content` to a new file at `path`, last modified at `mtime_secs`
+    /// after the epoch
+    async fn write(&self, path: &str, content: &[u8], mtime_secs: u64) {
+        let file = self.client.create(path).await.unwrap();
+        file.write_at(content, 0).await.unwrap();
+        file.close().await.unwrap();
+        let file = std::fs::File::options()
+            .write(true)
+            .open(self.config.root.join(path))
+            .unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime_secs))
+            .unwrap();
+    }
+
+    fn fs(&self) -> Arc<LocalFS> {
+        Arc::new(LocalFS::new(&self.config).unwrap())
+    }
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.config.root);
+    }
+}
+
+/// The path and kind of every change of `changeset`
+fn summary(changeset: &Changeset) -> Vec<(&str, ChangeKind)> {
+    changeset
+        .changes
+        .iter()
+        .map(|change| (change.path.as_str(), change.kind))
+        .collect()
+}
+
+#[tokio::test]
+async fn directories_differ_by_added_removed_and_modified_paths() {
+    let ns = Namespace::new("paths");
+    let client = &ns.client;
+    for dir in ["old/data", "old/logs", "new/data", "new/logs/2024"] {
+        client.create_dir_all(dir).await.unwrap();
+    }
+    ns.write("old/data/same.csv", b"a,b", 1000).await;
+    ns.write("new/data/same.csv", b"a,b", 1000).await;
+    ns.write("old/data/grown.csv", b"a", 1000).await;
+    ns.write("new/data/grown.csv", b"a,b,c", 1000).await;
+    ns.write("old/data/touched.csv", b"a", 1000).await;
+    ns.write("new/data/touched.csv", b"a", 1060).await;
+    ns.write("old/data/dropped.csv", b"abcd", 1000).await;
+    ns.write("new/data/fresh.csv", b"ab", 1000).await;
+    ns.write("old/logs/2024", b"", 1000).await;
+    ns.write("new/logs/2024/jan.log", b"x", 1000).await;
+
+    let fs = ns.fs();
+    let ctx = ns.config.request_context();
+    let options = DiffOptions::default();
+    let changeset = diff::diff(Arc::clone(&fs), "old", Arc::clone(&fs), "/new/", ctx, &options)
+        .await
+        .unwrap();
+    assert_eq!(
+        summary(&changeset),
+        [
+            ("data/dropped.csv", ChangeKind::Removed),
+            ("data/fresh.csv", ChangeKind::Added),
+            ("data/grown.csv", ChangeKind::Modified),
+            ("data/touched.csv", ChangeKind::Modified),
+            // A file replaced by a directory, whose entries are added
+            ("logs/2024", ChangeKind::Modified),
+            ("logs/2024/jan.log", ChangeKind::Added),
+        ]
+    );
+    let deltas: Vec<_> = changeset
+        .changes
+        .iter()
+        .map(|change| (change.size_delta(), change.mtime_delta_ns()))
+        .collect();
+    assert_eq!(deltas[..4], [(-4, None), (2, None), (4, Some(0)), (0, Some(60_000_000_000))]);
+    assert!(changeset.changes.iter().all(|change| change.content_differs.is_none()));
+    assert_eq!(changeset.of_kind(ChangeKind::Added).count(), 2);
+
+    let same = diff::diff(Arc::clone(&fs), "old", Arc::clone(&fs), "old", ctx, &options)
+        .await
+        .unwrap();
+    assert!(same.is_empty());
+    assert!(diff::diff(Arc::clone(&fs), "old", fs, "missing", ctx, &options)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn checksums_compare_contents_across_backends() {
+    let (ns, copy) = (Namespace::new("source"), Namespace::new("copy"));
+    for ns in [&ns, &copy] {
+        ns.client.create_dir_all("set").await.unwrap();
+    }
+    ns.write("set/retouched", b"same", 1000).await;
+    copy.write("set/retouched", b"same", 2000).await;
+    ns.write("set/corrupted", b"good", 1000).await;
+    copy.write("set/corrupted", b"bad!", 1000).await;
+    ns.write("set/truncated", b"long", 1000).await;
+    copy.write("set/truncated", b"lo", 1000).await;
+
+    let ctx = ns.config.request_context();
+    let options = DiffOptions {
+        checksum: true,
+        concurrency: 1,
+    };
+    let changeset = diff::diff(ns.fs(), "", copy.fs(), "", ctx, &options).await.unwrap();
+    assert_eq!(
+        summary(&changeset),
+        [
+            ("set/corrupted", ChangeKind::Modified),
+            ("set/truncated", ChangeKind::Modified),
+        ]
+    );
+    assert_eq!(changeset.changes[0].content_differs, Some(true));
+    assert_eq!(changeset.changes[1].content_differs, None);
+
+    // Without checksums files differ by their modification times instead
+    let changeset = diff::diff(ns.fs(), "set", copy.fs(), "set", ctx, &DiffOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        summary(&changeset),
+        [
+            ("retouched", ChangeKind::Modified),
+            ("truncated", ChangeKind::Modified),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn trees_are_verified_against_a_manifest() {
+    let ns = Namespace::new("manifest");
+    ns.client.create_dir_all("release/data").await.unwrap();
+    ns.write("release/data/a.csv", b"a,b", 1000).await;
+    ns.write("release/data/b.csv", b"corrupt", 1000).await;
+    ns.write("release/data/c.csv", b"short", 1000).await;
+    ns.write("release/README", b"read me", 1000).await;
+    ns.write("release/stray.tmp", b"", 1000).await;
+    ns.client.create_dir_all("release/data/d.csv").await.unwrap();
+
+    let sha = DigestAlgorithm::Sha256.digest(b"a,b");
+    let crc = DigestAlgorithm::Crc32c.digest(b"correct");
+    let json = format!(
+        r#"{{"files": [
+            {{"path": "data/a.csv", "size": 3, "digest": "{sha}"}},
+            {{"path": "/data/b.csv", "size": 7, "digest": "{crc}"}},
+            {{"path": "data/c.csv", "size": 6}},
+            {{"path": "data/d.csv"}},
+            {{"path": "data/e.csv", "size": 1}},
+            {{"path": "README"}}
+        ]}}"#
+    );
+    let manifest = Manifest::parse(&json).unwrap();
+    let ctx = ns.config.request_context();
+    let report = manifest::verify(ns.fs(), "release", &manifest, ctx, &VerifyOptions::default())
+        .await
+        .unwrap();
+    assert!(!report.is_ok());
+    assert_eq!((report.checked, report.bytes_hashed), (6, 10));
+    let problems: Vec<_> = report
+        .problems
+        .iter()
+        .map(|problem| (problem.path.as_str(), problem.kind))
+        .collect();
+    assert_eq!(
+        problems,
+        [
+            ("/data/b.csv", ProblemKind::DigestMismatch),
+            ("data/c.csv", ProblemKind::SizeMismatch),
+            ("data/d.csv", ProblemKind::NotAFile),
+            ("data/e.csv", ProblemKind::Missing),
+            ("stray.tmp", ProblemKind::Unexpected),
+        ]
+    );
+    assert_eq!(report.problems[0].expected.as_deref(), Some(crc.as_str()));
+    assert_eq!(
+        report.problems[0].actual,
+        Some(DigestAlgorithm::Crc32c.digest(b"corrupt"))
+    );
+    let json = serde_json::to_value(&report.problems[1]).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({"path": "data/c.csv", "kind": "size_mismatch", "expected": "6", "actual": "5"})
+    );
+
+    let options = VerifyOptions {
+        allow_unexpected: true,
+        concurrency: 1,
+    };
+    let listed = Manifest {
+        files: vec![ManifestEntry {
+            path: "data/a.csv".into(),
+            size: Some(3),
+            digest: Some(sha),
+        }],
+    };
+    let report = manifest::verify(ns.fs(), "release", &listed, ctx, &options).await.unwrap();
+    assert!(report.is_ok());
+
+    assert!(Manifest::parse(r#"{"files": [{"path": "a", "digest": "md5:00"}]}"#).is_err());
+    assert!(Manifest::parse(r#"{"files": [{"path": "a"}, {"path": "./a"}]}"#).is_err());
+}
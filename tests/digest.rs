@@ -0,0 +1,145 @@
+//! Digests of file contents, checked end to end
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::storage::digest::{self, DigestAlgorithm, DIGEST_XATTR};
+use datenlord::storage::fs_util::{CreateParam, RequestContext, SetAttrParam, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::{INum, VirtualFs};
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+#[test]
+fn digests_name_their_algorithm() {
+    assert_eq!(
+        DigestAlgorithm::Crc32c.digest(b"123456789"),
+        "crc32c:e3069283"
+    );
+    assert_eq!(
+        DigestAlgorithm::Sha256.digest(b""),
+        "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        "sha256".parse::<DigestAlgorithm>().unwrap(),
+        DigestAlgorithm::Sha256
+    );
+    assert!("md5".parse::<DigestAlgorithm>().is_err());
+}
+
+#[test]
+fn verify_tells_corrupted_data_from_bad_digests() {
+    let data = b"payload";
+    for algorithm in [DigestAlgorithm::Crc32c, DigestAlgorithm::Sha256] {
+        let expected = algorithm.digest(data);
+        digest::verify(data, &expected).unwrap();
+        // Hex digits match whatever their case
+        let (name, value) = expected.split_once(':').unwrap();
+        digest::verify(data, &format!("{name}:{}", value.to_uppercase())).unwrap();
+        assert!(matches!(
+            digest::verify(b"payloaD", &expected),
+            Err(DatenLordError::Corrupted { .. })
+        ));
+    }
+    for bad in ["e3069283", "crc32c:", "crc32c:xyz", "md5:abcd"] {
+        assert!(
+            matches!(
+                digest::verify(data, bad),
+                Err(DatenLordError::InvalidArgument { .. })
+            ),
+            "{bad}"
+        );
+    }
+}
+
+#[test]
+fn blake3_matches_the_reference_vectors() {
+    assert_eq!(
+        DigestAlgorithm::Blake3.digest(b""),
+        "blake3:af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+    );
+    assert_eq!(
+        DigestAlgorithm::Blake3.digest(b"abc"),
+        "blake3:6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+    );
+    // The official test vectors, over the bytes 0, 1, ..., 250, 0, 1, ...
+    let input: Vec<u8> = (0..31744_u32).map(|i| (i % 251) as u8).collect();
+    for (len, expected) in [
+        (1024, "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+        (1025, "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+        (31744, "62b6960e1a44bcc1eb1a611a8d6235b6b4b78f32e7abc4fb4c6cdcce94895c47"),
+    ] {
+        assert_eq!(DigestAlgorithm::Blake3.digest(&input[..len]), format!("blake3:{expected}"));
+        // Fed in uneven pieces, crossing blocks and chunks
+        let mut hasher = DigestAlgorithm::Blake3.hasher();
+        for piece in input[..len].chunks(100) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finish(), format!("blake3:{expected}"));
+    }
+}
+
+/// Create the file `name` holding `data`
+async fn write_file(fs: &LocalFS, ctx: &RequestContext, name: &str, data: &[u8]) -> INum {
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: name.into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let ino = fs.mknod(ctx, param).await.unwrap().1.ino;
+    overwrite(fs, ctx, ino, data).await;
+    ino
+}
+
+/// Write `data` at the start of the file `ino`
+async fn overwrite(fs: &LocalFS, ctx: &RequestContext, ino: INum, data: &[u8]) {
+    let fh = fs.open(ctx, ino, OFlag::O_WRONLY.bits() as u32).await.unwrap();
+    fs.write(ctx, ino, fh, 0, data, 0).await.unwrap();
+    fs.release(ctx, ino, fh, 0, 0, true).await.unwrap();
+}
+
+#[tokio::test]
+async fn file_digests_are_cached_until_the_file_changes() {
+    let root = std::env::temp_dir().join(format!("datenlord-digest-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let config = DatenLordConfig {
+        root: root.clone(),
+        ..DatenLordConfig::default()
+    };
+    let fs = LocalFS::new(&config).unwrap();
+    let ctx = RequestContext::current();
+    let data: Vec<u8> = (0..1_000_000_u32).map(|i| (i % 251) as u8).collect();
+    let ino = write_file(&fs, &ctx, "data.bin", &data).await;
+
+    for algorithm in [DigestAlgorithm::Crc32c, DigestAlgorithm::Sha256, DigestAlgorithm::Blake3] {
+        let streamed = digest::file_digest(&fs, &ctx, ino, algorithm, true).await.unwrap();
+        assert_eq!(streamed, algorithm.digest(&data));
+    }
+    assert!(fs.getxattr(&ctx, ino, DIGEST_XATTR).await.is_ok());
+    let cached = digest::file_digest(&fs, &ctx, ino, DigestAlgorithm::Blake3, true).await;
+    assert_eq!(cached.unwrap(), DigestAlgorithm::Blake3.digest(&data));
+
+    // A write drops the cache, of the same size or not
+    overwrite(&fs, &ctx, ino, b"changed").await;
+    assert!(fs.getxattr(&ctx, ino, DIGEST_XATTR).await.is_err());
+    let mut changed = data.clone();
+    changed[..7].copy_from_slice(b"changed");
+    let sha = digest::file_digest(&fs, &ctx, ino, DigestAlgorithm::Sha256, true).await;
+    assert_eq!(sha.unwrap(), DigestAlgorithm::Sha256.digest(&changed));
+
+    // So does a truncation
+    let param = SetAttrParam {
+        size: Some(7),
+        ..SetAttrParam::default()
+    };
+    fs.setattr(&ctx, ino, param).await.unwrap();
+    assert!(fs.getxattr(&ctx, ino, DIGEST_XATTR).await.is_err());
+    let sha = digest::file_digest(&fs, &ctx, ino, DigestAlgorithm::Sha256, false).await;
+    assert_eq!(sha.unwrap(), DigestAlgorithm::Sha256.digest(b"changed"));
+
+    // Only regular files have digests
+    let result = digest::file_digest(&fs, &ctx, ROOT_ID, DigestAlgorithm::Sha256, false).await;
+    assert!(matches!(result, Err(DatenLordError::InvalidArgument { .. })));
+    let _ = std::fs::remove_dir_all(&root);
+}
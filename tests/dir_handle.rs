@@ -0,0 +1,122 @@
+//! Reads open directories at offsets that stay put while entries are
+//! created and removed
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::storage::fs_util::{CreateParam, DirEntry, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::meta::{MemoryMeta, MetaStore};
+use datenlord::storage::sharedfs::{SharedConfig, SharedFs};
+use datenlord::storage::virtualfs::{INum, VirtualFs};
+use nix::sys::stat::SFlag;
+use opendal::{Operator, Scheme};
+
+fn ctx() -> RequestContext {
+    RequestContext {
+        uid: 0,
+        gid: 0,
+        pid: 1,
+        umask: 0o022,
+        root: ROOT_ID,
+    }
+}
+
+fn param(parent: INum, name: &str, node_type: SFlag) -> CreateParam {
+    CreateParam {
+        parent,
+        name: name.into(),
+        mode: 0o755,
+        rdev: 0,
+        node_type,
+        link: None,
+    }
+}
+
+fn names(entries: Vec<DirEntry>) -> Vec<OsString> {
+    entries.into_iter().map(|entry| entry.name).collect()
+}
+
+/// A root removed on drop
+struct Root(PathBuf);
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Fill a directory, then read it through a handle while it changes
+async fn offsets_survive_changes<F: VirtualFs>(fs: &F) {
+    let ctx = ctx();
+    let dir = fs
+        .mkdir(&ctx, param(ROOT_ID, "dir", SFlag::S_IFDIR))
+        .await
+        .unwrap()
+        .1
+        .ino;
+    for i in 0..10 {
+        let name = format!("file-{i}");
+        fs.mknod(&ctx, param(dir, &name, SFlag::S_IFREG))
+            .await
+            .unwrap();
+    }
+
+    let fh = fs.opendir(&ctx, dir, 0).await.unwrap();
+    let first = names(fs.readdir(&ctx, dir, fh, 0).await.unwrap());
+    assert_eq!(first.len(), 10);
+
+    // Consumed the first four, then the directory changes
+    fs.unlink(&ctx, dir, &first[0]).await.unwrap();
+    fs.unlink(&ctx, dir, &first[1]).await.unwrap();
+    fs.mknod(&ctx, param(dir, "new", SFlag::S_IFREG))
+        .await
+        .unwrap();
+    let rest = names(fs.readdir(&ctx, dir, fh, 4).await.unwrap());
+    assert_eq!(rest, first[4..]);
+    let detailed = fs.readdirplus(&ctx, dir, fh, 4).await.unwrap();
+    let detailed: Vec<_> = detailed
+        .into_iter()
+        .map(|(entry, attr, _)| {
+            assert_eq!(entry.ino, attr.ino);
+            entry.name
+        })
+        .collect();
+    assert_eq!(detailed, first[4..]);
+    fs.releasedir(&ctx, dir, fh, 0).await.unwrap();
+
+    // Opened again it lists the changes
+    let fh = fs.opendir(&ctx, dir, 0).await.unwrap();
+    let listed = names(fs.readdir(&ctx, dir, fh, 0).await.unwrap());
+    fs.releasedir(&ctx, dir, fh, 0).await.unwrap();
+    assert_eq!(listed.len(), 9);
+    assert!(listed.contains(&"new".into()));
+    assert!(!listed.contains(&first[0]));
+}
+
+#[tokio::test]
+async fn local_directories_keep_their_listing() {
+    let root =
+        Root(std::env::temp_dir().join(format!("datenlord-dir-handle-{}", std::process::id())));
+    let _ = std::fs::remove_dir_all(&root.0);
+    let fs = LocalFS::new(&DatenLordConfig {
+        root: root.0.clone(),
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    offsets_survive_changes(&fs).await;
+}
+
+#[tokio::test]
+async fn shared_directories_keep_their_listing() {
+    let config = SharedConfig {
+        data_scheme: "memory".to_owned(),
+        ..SharedConfig::default()
+    };
+    let meta: Arc<dyn MetaStore> = Arc::new(MemoryMeta::default());
+    let data = Operator::via_map(Scheme::Memory, HashMap::new()).unwrap();
+    let fs = SharedFs::with_stores(&config, meta, data).await.unwrap();
+    offsets_survive_changes(&fs).await;
+}
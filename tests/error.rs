@@ -0,0 +1,76 @@
+//! Converts OS errors into `DatenLordError`, keeping them as the source
+use std::error::Error;
+use std::io;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::{DatenLordError, DatenLordResult, ResultExt};
+use datenlord::sdk::rust::Client;
+use nix::errno::Errno;
+
+#[test]
+fn io_errors_keep_their_source_and_kind() {
+    let result: io::Result<()> = Err(io::Error::from(io::ErrorKind::NotFound));
+    let err = result.add_context("failed to open \"a\"").unwrap_err();
+    assert!(matches!(err, DatenLordError::NotFound { .. }), "{err:?}");
+    assert_eq!(err.errno(), Some(Errno::ENOENT));
+    let source = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
+    assert_eq!(source.kind(), io::ErrorKind::NotFound);
+
+    let err = err.add_context("failed to copy \"a\"");
+    let DatenLordError::NotFound { ref context, .. } = err else {
+        panic!("{err:?}");
+    };
+    assert_eq!(
+        context[..2],
+        ["failed to copy \"a\"", "failed to open \"a\""]
+    );
+}
+
+#[test]
+fn errnos_without_a_variant_stay_io_errors_with_their_errno() {
+    let err = DatenLordError::from(Errno::ENOTDIR);
+    assert!(matches!(err, DatenLordError::Io { .. }), "{err:?}");
+    assert_eq!(err.errno(), Some(Errno::ENOTDIR));
+    assert_eq!(
+        err.source().unwrap().downcast_ref::<Errno>(),
+        Some(&Errno::ENOTDIR)
+    );
+
+    let err = DatenLordError::from(io::Error::from_raw_os_error(Errno::EISDIR as i32));
+    assert_eq!(err.errno(), Some(Errno::EISDIR));
+    let err: DatenLordResult<()> = Err(Errno::EACCES).with_context(|| "failed to chmod");
+    assert!(matches!(err, Err(DatenLordError::PermissionDenied { .. })));
+}
+
+#[test]
+fn errors_survive_a_round_trip_through_io_errors() {
+    let err = io::Error::from(DatenLordError::ShutDown {
+        context: vec!["closed".to_owned()],
+    });
+    assert!(matches!(
+        DatenLordError::from(err),
+        DatenLordError::ShutDown { .. }
+    ));
+}
+
+#[tokio::test]
+async fn local_failures_chain_to_the_os_error() {
+    let root = std::env::temp_dir().join(format!("datenlord-error-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let client = Client::new(&DatenLordConfig {
+        root: root.clone(),
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    client.create("file").await.unwrap().close().await.unwrap();
+    let err = client.create("file/child").await.unwrap_err();
+    assert_eq!(err.errno(), Some(Errno::ENOTDIR), "{err:?}");
+    let os_error = std::iter::successors(err.source(), |&source| source.source()).any(|source| {
+        source.downcast_ref::<Errno>() == Some(&Errno::ENOTDIR)
+            || source
+                .downcast_ref::<io::Error>()
+                .is_some_and(|io| io.raw_os_error() == Some(Errno::ENOTDIR as i32))
+    });
+    assert!(os_error, "{err:?}");
+    let _ = std::fs::remove_dir_all(&root);
+}
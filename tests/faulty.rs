@@ -0,0 +1,170 @@
+//! Injects failures under the middlewares and the rust client
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::faulty::{FaultConfig, FaultKind, FaultyFs};
+use datenlord::storage::fs_util::{CreateParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::retry::{RetryFs, RetryPolicy};
+use datenlord::storage::timeout::TimeoutFs;
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir = format!("datenlord-faulty-{name}-{}", std::process::id());
+        let root = std::env::temp_dir().join(dir);
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    fn config(&self) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        }
+    }
+
+    fn faulty(&self, faults: FaultConfig) -> FaultyFs<LocalFS> {
+        FaultyFs::new(LocalFS::new(&self.config()).unwrap(), faults)
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn ctx() -> RequestContext {
+    DatenLordConfig::default().request_context()
+}
+
+/// Create the file `name` in the root of `fs` and open it for writing
+async fn create<F: VirtualFs>(fs: &F, name: &str) -> (u64, u64) {
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: name.into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let ino = fs.mknod(&ctx(), param).await.unwrap().1.ino;
+    let fh = fs.open(&ctx(), ino, OFlag::O_WRONLY.bits() as u32).await.unwrap();
+    (ino, fh)
+}
+
+#[tokio::test]
+async fn every_nth_covered_call_fails() {
+    let root = Root::new("nth");
+    let fs = root.faulty(FaultConfig {
+        ops: vec!["getattr".to_owned()],
+        fail_every: Some(3),
+        ..FaultConfig::default()
+    });
+    let mut outcomes = Vec::new();
+    for _ in 0..6 {
+        outcomes.push(fs.getattr(&ctx(), ROOT_ID).await.is_ok());
+        // Calls the faults do not cover are neither counted nor failed
+        fs.lookup(&ctx(), ROOT_ID, OsStr::new(".")).await.unwrap();
+    }
+    assert_eq!(outcomes, [true, true, false, true, true, false]);
+}
+
+#[tokio::test]
+async fn retries_recover_from_transient_faults_only() {
+    let root = Root::new("retry");
+    let policy = RetryPolicy {
+        initial_backoff_ms: 1,
+        ..RetryPolicy::default()
+    };
+    let faults = FaultConfig {
+        fail_every: Some(2),
+        error: FaultKind::Unavailable,
+        ..FaultConfig::default()
+    };
+    let fs = RetryFs::new(root.faulty(faults.clone()), policy.clone());
+    for _ in 0..4 {
+        fs.getattr(&ctx(), ROOT_ID).await.unwrap();
+    }
+
+    let faults = FaultConfig {
+        error: FaultKind::Io,
+        ..faults
+    };
+    let fs = RetryFs::new(root.faulty(faults), policy);
+    fs.getattr(&ctx(), ROOT_ID).await.unwrap();
+    let err = fs.getattr(&ctx(), ROOT_ID).await.unwrap_err();
+    assert!(matches!(err, DatenLordError::Io { .. }), "{err:?}");
+}
+
+#[tokio::test]
+async fn delayed_calls_time_out() {
+    let root = Root::new("delay");
+    let faults = FaultConfig {
+        delay_every: Some(2),
+        delay_ms: 200,
+        ..FaultConfig::default()
+    };
+    let fs = TimeoutFs::new(root.faulty(faults), Some(Duration::from_millis(50)));
+    fs.getattr(&ctx(), ROOT_ID).await.unwrap();
+    let err = fs.getattr(&ctx(), ROOT_ID).await.unwrap_err();
+    assert!(matches!(err, DatenLordError::Timeout { .. }), "{err:?}");
+}
+
+#[tokio::test]
+async fn partial_writes_keep_the_first_half() {
+    let root = Root::new("partial");
+    let fs = root.faulty(FaultConfig {
+        partial_write_every: Some(2),
+        ..FaultConfig::default()
+    });
+    let (ino, fh) = create(&fs, "data").await;
+    fs.write(&ctx(), ino, fh, 0, b"abcd", 0).await.unwrap();
+    let err = fs.write(&ctx(), ino, fh, 4, b"efghij", 0).await.unwrap_err();
+    assert!(matches!(err, DatenLordError::Io { .. }), "{err:?}");
+    fs.release(&ctx(), ino, fh, 0, 0, true).await.unwrap();
+    assert_eq!(std::fs::read(root.0.join("data")).unwrap(), b"abcdefg");
+}
+
+#[tokio::test]
+async fn writes_past_the_space_limit_fail_with_enospc() {
+    let root = Root::new("space");
+    let fs = root.faulty(FaultConfig {
+        space_limit: Some(10),
+        ..FaultConfig::default()
+    });
+    let (ino, fh) = create(&fs, "data").await;
+    fs.write(&ctx(), ino, fh, 0, b"012345", 0).await.unwrap();
+    let err = fs.write(&ctx(), ino, fh, 6, b"6789ab", 0).await.unwrap_err();
+    assert_eq!(err.errno(), Some(Errno::ENOSPC));
+    let err = fs.write(&ctx(), ino, fh, 10, b"c", 0).await.unwrap_err();
+    assert!(matches!(err, DatenLordError::NoSpace { .. }), "{err:?}");
+    fs.release(&ctx(), ino, fh, 0, 0, true).await.unwrap();
+    assert_eq!(std::fs::read(root.0.join("data")).unwrap(), b"0123456789");
+}
+
+#[tokio::test]
+async fn clients_inject_the_faults_of_their_config() {
+    let root = Root::new("client");
+    let config = format!(
+        r#"{{"root": {:?}, "faults": {{"ops": ["write"], "space_limit": 4}}}}"#,
+        root.0
+    );
+    let client = Client::new(&DatenLordConfig::parse(&config)).unwrap();
+    let file = client.create("data").await.unwrap();
+    let err = file.write_at(b"too long", 0).await.unwrap_err();
+    assert_eq!(err.errno(), Some(Errno::ENOSPC));
+    file.close().await.unwrap();
+    assert_eq!(std::fs::read(root.0.join("data")).unwrap(), b"too ");
+}
@@ -0,0 +1,1122 @@
+//! Drives the C ABI the way C callers do, including common misuse, so
+//! memory-safety regressions in the FFI layer show up under AddressSanitizer
+//!
+//! Run under ASAN with
+//! `RUSTFLAGS=-Zsanitizer=address cargo +nightly test --target x86_64-unknown-linux-gnu --test ffi`.
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+
+use datenlord::sdk::c::datenlord::*;
+
+/// An SDK instance rooted in a fresh directory, removed on drop
+struct Sdk {
+    sdk: *mut datenlord_sdk,
+    root: PathBuf,
+}
+
+impl Sdk {
+    fn new(name: &str) -> Self {
+        Self::with_config(name, "")
+    }
+
+    /// An instance whose config has the JSON members `extra`, each preceded by a comma
+    fn with_config(name: &str, extra: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("datenlord-ffi-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let config = CString::new(format!(r#"{{"root": {:?}{extra}}}"#, root)).unwrap();
+        let sdk = init(config.as_ptr());
+        assert!(!sdk.is_null(), "init failed for {root:?}");
+        Self { sdk, root }
+    }
+
+    /// Create `name` holding `content`
+    fn create(&self, name: &str, content: &[u8]) {
+        let path = c_path(name);
+        expect_ok(create_file(self.sdk, path.as_ptr(), false));
+        let bytes = datenlord_bytes {
+            data: content.as_ptr(),
+            len: content.len(),
+        };
+        expect_ok(write_file(self.sdk, path.as_ptr(), bytes));
+    }
+}
+
+impl Drop for Sdk {
+    fn drop(&mut self) {
+        free_sdk(self.sdk);
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+fn c_path(name: &str) -> CString {
+    CString::new(name).unwrap()
+}
+
+/// The message of `err`, which is freed
+fn take_message(err: *mut datenlord_error) -> String {
+    assert!(!err.is_null(), "expected an error");
+    let message = unsafe {
+        let message = &(*err).message;
+        String::from_utf8_lossy(std::slice::from_raw_parts(message.data, message.len)).into_owned()
+    };
+    datenlord_error_free(err);
+    message
+}
+
+fn expect_ok(err: *mut datenlord_error) {
+    if !err.is_null() {
+        panic!("unexpected error: {}", take_message(err));
+    }
+}
+
+/// Poll until `count` completions arrived, at most `max` per call
+fn poll_all(sdk: *mut datenlord_sdk, count: usize, max: usize) -> Vec<datenlord_completion> {
+    let mut done = Vec::with_capacity(count);
+    for _ in 0..500 {
+        let mut out = Vec::with_capacity(max);
+        let polled = datenlord_poll_completions(sdk, out.as_mut_ptr(), max);
+        assert!(polled <= max);
+        unsafe { out.set_len(polled) };
+        done.extend(out);
+        if done.len() == count {
+            return done;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("only {} of {count} operations completed", done.len());
+}
+
+#[test]
+fn null_arguments_are_rejected() {
+    assert!(init(ptr::null()).is_null());
+    free_sdk(ptr::null_mut());
+    datenlord_error_free(ptr::null_mut());
+
+    let path = c_path("file");
+    assert!(!exists(ptr::null_mut(), path.as_ptr()));
+    take_message(mkdir(ptr::null_mut(), path.as_ptr()));
+    take_message(create_file(ptr::null_mut(), path.as_ptr(), false));
+    take_message(datenlord_mkdir_all(ptr::null_mut(), path.as_ptr(), 0o755));
+    take_message(datenlord_sync_all(ptr::null_mut()));
+    assert_eq!(datenlord_poll_completions(ptr::null_mut(), ptr::null_mut(), 4), 0);
+    assert!(!datenlord_cancel(ptr::null_mut(), 1));
+
+    let sdk = Sdk::new("null-args");
+    assert!(!exists(sdk.sdk, ptr::null()));
+    take_message(create_file(sdk.sdk, ptr::null(), true));
+    take_message(stat(sdk.sdk, path.as_ptr(), ptr::null_mut()));
+    take_message(read_file(sdk.sdk, path.as_ptr(), ptr::null_mut()));
+    assert_eq!(datenlord_poll_completions(sdk.sdk, ptr::null_mut(), 4), 0);
+
+    let req = datenlord_io_request {
+        path: ptr::null(),
+        offset: 0,
+        buf: ptr::null_mut(),
+        len: 0,
+        timeout_ms: 0,
+    };
+    assert_eq!(datenlord_read_async(sdk.sdk, req, None, ptr::null_mut()), 0);
+    let req = datenlord_io_request {
+        path: path.as_ptr(),
+        offset: 0,
+        buf: ptr::null_mut(),
+        len: 16,
+        timeout_ms: 0,
+    };
+    assert_eq!(datenlord_write_async(sdk.sdk, req, None, ptr::null_mut()), 0);
+}
+
+#[test]
+fn double_free_of_error_is_ignored() {
+    let path = c_path("missing");
+    let err = mkdir(ptr::null_mut(), path.as_ptr());
+    assert!(!err.is_null());
+    datenlord_error_free(err);
+    datenlord_error_free(err);
+
+    // A pointer the SDK never handed out is ignored as well
+    let mut bogus = 0u64;
+    datenlord_error_free(ptr::addr_of_mut!(bogus).cast());
+    assert_eq!(bogus, 0);
+}
+
+#[test]
+fn error_outlives_sdk() {
+    let sdk = Sdk::new("error-outlives");
+    let path = c_path("missing");
+    let mut attr = std::mem::MaybeUninit::<datenlord_stat>::uninit();
+    let err = stat(sdk.sdk, path.as_ptr(), attr.as_mut_ptr());
+    drop(sdk);
+    assert!(!take_message(err).is_empty());
+}
+
+#[test]
+fn short_read_buffer_is_not_overrun() {
+    const CANARY: u8 = 0xa5;
+    let sdk = Sdk::new("short-read");
+    let content: Vec<u8> = (0..64).collect();
+    sdk.create("file", &content);
+
+    let mut buffer = [CANARY; 32];
+    let mut out = datenlord_bytes {
+        data: buffer.as_mut_ptr(),
+        len: 16,
+    };
+    let path = c_path("file");
+    expect_ok(read_file(sdk.sdk, path.as_ptr(), &mut out));
+    assert_eq!(out.len, 16);
+    assert_eq!(buffer[..16], content[..16]);
+    assert!(buffer[16..].iter().all(|&b| b == CANARY));
+
+    let mut empty = datenlord_bytes {
+        data: ptr::null(),
+        len: 0,
+    };
+    expect_ok(read_file(sdk.sdk, path.as_ptr(), &mut empty));
+    assert_eq!(empty.len, 0);
+}
+
+#[test]
+fn zero_length_write_accepts_null_data() {
+    let sdk = Sdk::new("zero-write");
+    sdk.create("file", &[]);
+    let path = c_path("file");
+    let mut attr = std::mem::MaybeUninit::<datenlord_stat>::uninit();
+    expect_ok(stat(sdk.sdk, path.as_ptr(), attr.as_mut_ptr()));
+    assert_eq!(unsafe { attr.assume_init() }.size, 0);
+
+    let null_data = datenlord_bytes {
+        data: ptr::null(),
+        len: 8,
+    };
+    take_message(write_file(sdk.sdk, path.as_ptr(), null_data));
+}
+
+#[test]
+fn async_reads_drain_through_small_poll_batches() {
+    let sdk = Sdk::new("async-poll");
+    let content = b"0123456789abcdef";
+    sdk.create("file", content);
+
+    let path = c_path("file");
+    let mut buffers = [[0u8; 4]; 3];
+    let mut op_ids = Vec::new();
+    for (i, buffer) in buffers.iter_mut().enumerate() {
+        let req = datenlord_io_request {
+            path: path.as_ptr(),
+            offset: (i * 4) as u64,
+            buf: buffer.as_mut_ptr(),
+            len: buffer.len(),
+            timeout_ms: 0,
+        };
+        let op_id = datenlord_read_async(sdk.sdk, req, None, ptr::null_mut());
+        assert_ne!(op_id, 0);
+        op_ids.push(op_id);
+    }
+    assert_eq!(datenlord_poll_completions(sdk.sdk, ptr::NonNull::dangling().as_ptr(), 0), 0);
+
+    let mut completions = poll_all(sdk.sdk, op_ids.len(), 1);
+    completions.sort_by_key(|completion| completion.op_id);
+    for (i, completion) in completions.iter().enumerate() {
+        assert_eq!(completion.op_id, op_ids[i]);
+        assert!(completion.error.is_null());
+        assert_eq!(completion.result, 4);
+        assert_eq!(buffers[i], content[i * 4..i * 4 + 4]);
+    }
+    // Finished operations can no longer be cancelled
+    assert!(!datenlord_cancel(sdk.sdk, op_ids[0]));
+    assert!(!datenlord_cancel(sdk.sdk, u64::MAX));
+}
+
+#[test]
+fn failed_async_read_reports_an_error() {
+    let sdk = Sdk::new("async-error");
+    let path = c_path("missing");
+    let mut buffer = [0u8; 8];
+    let req = datenlord_io_request {
+        path: path.as_ptr(),
+        offset: 0,
+        buf: buffer.as_mut_ptr(),
+        len: buffer.len(),
+        timeout_ms: 0,
+    };
+    let op_id = datenlord_read_async(sdk.sdk, req, None, ptr::null_mut());
+    let completions = poll_all(sdk.sdk, 1, 4);
+    assert_eq!(completions[0].op_id, op_id);
+    assert_eq!(completions[0].result, 0);
+    assert!(!take_message(completions[0].error).is_empty());
+}
+
+#[test]
+fn pooled_buffer_outlives_sdk() {
+    let sdk = Sdk::new("buffer-outlives");
+    let buffer = datenlord_buffer_acquire(sdk.sdk, 100);
+    assert!(!buffer.data.is_null());
+    assert!(buffer.len >= 100);
+    drop(sdk);
+    unsafe { buffer.data.write_bytes(0xff, buffer.len) };
+    datenlord_buffer_release(buffer);
+
+    let empty = datenlord_buffer_acquire(ptr::null_mut(), 100);
+    assert!(empty.data.is_null());
+    datenlord_buffer_release(empty);
+}
+
+#[test]
+fn invalid_utf8_path_is_rejected() {
+    let sdk = Sdk::new("bad-utf8");
+    let path = [0xffu8, 0xfe, 0];
+    assert!(!exists(sdk.sdk, path.as_ptr().cast::<c_char>()));
+}
+
+#[test]
+fn utimens_sets_nanosecond_times_and_honors_omit() {
+    let sdk = Sdk::new("utimens");
+    sdk.create("file", b"data");
+    let path = c_path("file");
+    let stat_file = || {
+        let mut attr = std::mem::MaybeUninit::<datenlord_stat>::uninit();
+        expect_ok(stat(sdk.sdk, path.as_ptr(), attr.as_mut_ptr()));
+        unsafe { attr.assume_init() }
+    };
+
+    let atime = datenlord_timespec {
+        sec: 1_500_000_000,
+        nsec: 123_456_789,
+    };
+    let omit = datenlord_timespec {
+        sec: 0,
+        nsec: DATENLORD_UTIME_OMIT,
+    };
+    let before = stat_file();
+    expect_ok(datenlord_utimens(sdk.sdk, path.as_ptr(), atime, omit));
+    let after = stat_file();
+    assert_eq!((after.atime.sec, after.atime.nsec), (1_500_000_000, 123_456_789));
+    assert_eq!((after.mtime.sec, after.mtime.nsec), (before.mtime.sec, before.mtime.nsec));
+
+    let pre_epoch = datenlord_timespec {
+        sec: -2,
+        nsec: 999_999_999,
+    };
+    expect_ok(datenlord_utimens(sdk.sdk, path.as_ptr(), omit, pre_epoch));
+    let after = stat_file();
+    assert_eq!((after.atime.sec, after.atime.nsec), (1_500_000_000, 123_456_789));
+    assert_eq!((after.mtime.sec, after.mtime.nsec), (-2, 999_999_999));
+
+    let now = datenlord_timespec {
+        sec: 0,
+        nsec: DATENLORD_UTIME_NOW,
+    };
+    expect_ok(datenlord_utimens(sdk.sdk, path.as_ptr(), now, now));
+    assert!(stat_file().mtime.sec > 1_500_000_000);
+
+    let invalid = datenlord_timespec {
+        sec: 0,
+        nsec: 1_000_000_000,
+    };
+    take_message(datenlord_utimens(sdk.sdk, path.as_ptr(), invalid, omit));
+}
+
+#[test]
+fn rename_honors_noreplace_and_exchange() {
+    let sdk = Sdk::new("rename");
+    sdk.create("a", b"first");
+    sdk.create("b", b"second");
+    let (a, b, c) = (c_path("a"), c_path("b"), c_path("c"));
+    let read = |path: &CString| {
+        let mut buffer = [0u8; 16];
+        let mut out = datenlord_bytes {
+            data: buffer.as_mut_ptr(),
+            len: buffer.len(),
+        };
+        expect_ok(read_file(sdk.sdk, path.as_ptr(), &mut out));
+        buffer[..out.len].to_vec()
+    };
+
+    let err = rename_path(sdk.sdk, a.as_ptr(), b.as_ptr(), DATENLORD_RENAME_NOREPLACE);
+    assert!(!err.is_null());
+    assert_eq!(unsafe { (*err).code }, 17, "expected EEXIST");
+    take_message(err);
+    assert_eq!(read(&b), b"second");
+
+    expect_ok(rename_path(sdk.sdk, a.as_ptr(), b.as_ptr(), DATENLORD_RENAME_EXCHANGE));
+    assert_eq!(read(&a), b"second");
+    assert_eq!(read(&b), b"first");
+
+    expect_ok(rename_path(sdk.sdk, a.as_ptr(), c.as_ptr(), DATENLORD_RENAME_NOREPLACE));
+    assert!(!exists(sdk.sdk, a.as_ptr()));
+    assert_eq!(read(&c), b"second");
+
+    take_message(rename_path(sdk.sdk, b.as_ptr(), c.as_ptr(), 0x80));
+}
+
+#[test]
+fn mkdir_all_creates_missing_parents() {
+    let sdk = Sdk::new("mkdir-all");
+    let nested = c_path("a/b/c");
+    expect_ok(datenlord_mkdir_all(sdk.sdk, nested.as_ptr(), 0o755));
+    assert!(exists(sdk.sdk, nested.as_ptr()));
+    // Existing directories are kept
+    expect_ok(datenlord_mkdir_all(sdk.sdk, c_path("a/b/c/d/").as_ptr(), 0o755));
+    expect_ok(datenlord_mkdir_all(sdk.sdk, nested.as_ptr(), 0o755));
+
+    let file = c_path("x/y/file");
+    take_message(create_file(sdk.sdk, file.as_ptr(), false));
+    expect_ok(create_file(sdk.sdk, file.as_ptr(), true));
+    assert!(exists(sdk.sdk, file.as_ptr()));
+
+    let err = datenlord_mkdir_all(sdk.sdk, c_path("x/y/file/z").as_ptr(), 0o755);
+    assert!(!err.is_null());
+    assert_eq!(unsafe { (*err).code }, 17, "expected EEXIST");
+    take_message(err);
+}
+
+#[test]
+fn created_entries_follow_the_umask() {
+    let sdk = Sdk::with_config("umask", r#", "caller": {"umask": 18}"#);
+    let perm = |name: &str| {
+        let mut attr = std::mem::MaybeUninit::<datenlord_stat>::uninit();
+        expect_ok(stat(sdk.sdk, c_path(name).as_ptr(), attr.as_mut_ptr()));
+        unsafe { attr.assume_init() }.mode & 0o7777
+    };
+    expect_ok(create_file(sdk.sdk, c_path("default").as_ptr(), false));
+    assert_eq!(perm("default"), 0o644);
+
+    assert_eq!(datenlord_set_umask(sdk.sdk, 0o077), 0o022);
+    expect_ok(create_file(sdk.sdk, c_path("private").as_ptr(), false));
+    expect_ok(mkdir(sdk.sdk, c_path("private_dir").as_ptr()));
+    assert_eq!(perm("private"), 0o600);
+    assert_eq!(perm("private_dir"), 0o700);
+    assert_eq!(datenlord_set_umask(ptr::null_mut(), 0), 0);
+}
+
+#[test]
+fn warm_files_follow_renames() {
+    // Listed files missing at init are skipped
+    let sdk = Sdk::with_config("warm", r#", "warm_files": ["settings"]"#);
+    sdk.create("settings", b"first");
+    sdk.create("next", b"second");
+    let (settings, next) = (c_path("settings"), c_path("next"));
+    let read = || {
+        let mut buffer = [0u8; 16];
+        let mut out = datenlord_bytes {
+            data: buffer.as_mut_ptr(),
+            len: buffer.len(),
+        };
+        expect_ok(read_file(sdk.sdk, settings.as_ptr(), &mut out));
+        buffer[..out.len].to_vec()
+    };
+
+    expect_ok(datenlord_warm_file(sdk.sdk, settings.as_ptr()));
+    assert_eq!(read(), b"first");
+    assert_eq!(read(), b"first");
+    // The handle is reopened on the file renamed over the warm path
+    expect_ok(rename_path(sdk.sdk, next.as_ptr(), settings.as_ptr(), 0));
+    assert_eq!(read(), b"second");
+
+    take_message(datenlord_warm_file(sdk.sdk, next.as_ptr()));
+    take_message(datenlord_warm_file(sdk.sdk, ptr::null()));
+    take_message(datenlord_warm_file(ptr::null_mut(), settings.as_ptr()));
+}
+
+#[test]
+fn stat_many_reports_every_path() {
+    let sdk = Sdk::new("stat-many");
+    expect_ok(datenlord_mkdir_all(sdk.sdk, c_path("src/lib").as_ptr(), 0o755));
+    sdk.create("src/lib/a.rs", b"a");
+    sdk.create("src/lib/b.rs", b"bb");
+    sdk.create("BUILD", b"");
+    let names = ["src/lib/a.rs", "missing/x.rs", "src/lib/b.rs", "", "BUILD", "src/lib/none.rs"];
+    let paths: Vec<CString> = names.iter().map(|name| c_path(name)).collect();
+    let path_ptrs: Vec<*const c_char> = paths.iter().map(|path| path.as_ptr()).collect();
+    let mut stats: Vec<datenlord_stat> = (0..names.len()).map(|_| unsafe { std::mem::zeroed() }).collect();
+    let mut codes = vec![u32::MAX; names.len()];
+
+    let count = names.len();
+    expect_ok(datenlord_stat_many(sdk.sdk, path_ptrs.as_ptr(), count, stats.as_mut_ptr(), codes.as_mut_ptr()));
+    // ENOENT for the missing paths
+    assert_eq!(codes, [0, 2, 0, 0, 0, 2]);
+    assert_eq!((stats[0].size, stats[2].size), (1, 2));
+    assert!(matches!(stats[3].kind, datenlord_file_kind::DATENLORD_FILE_KIND_DIRECTORY));
+
+    expect_ok(datenlord_stat_many(sdk.sdk, ptr::null(), 0, ptr::null_mut(), ptr::null_mut()));
+    let invalid = [ptr::null::<c_char>()];
+    take_message(datenlord_stat_many(sdk.sdk, invalid.as_ptr(), 1, stats.as_mut_ptr(), codes.as_mut_ptr()));
+    take_message(datenlord_stat_many(sdk.sdk, path_ptrs.as_ptr(), count, ptr::null_mut(), codes.as_mut_ptr()));
+    take_message(datenlord_stat_many(ptr::null_mut(), path_ptrs.as_ptr(), count, stats.as_mut_ptr(), codes.as_mut_ptr()));
+}
+
+/// Drain `walk` into the sorted paths it returns and close it
+fn walk_paths(walk: *mut datenlord_walk) -> Vec<String> {
+    assert!(!walk.is_null());
+    let mut paths = Vec::new();
+    let mut entry = datenlord_walk_entry {
+        path: ptr::null(),
+        stat: unsafe { std::mem::zeroed() },
+    };
+    loop {
+        expect_ok(datenlord_walk_next(walk, &mut entry));
+        if entry.path.is_null() {
+            break;
+        }
+        let path = unsafe { std::ffi::CStr::from_ptr(entry.path) };
+        paths.push(path.to_str().unwrap().to_owned());
+    }
+    // Exhausted walks keep reporting the end
+    expect_ok(datenlord_walk_next(walk, &mut entry));
+    assert!(entry.path.is_null());
+    datenlord_walk_close(walk);
+    paths.sort();
+    paths
+}
+
+#[test]
+fn walk_and_glob_list_the_tree() {
+    let sdk = Sdk::new("walk");
+    for name in ["a/b/c.txt", "a/b/d.py", "a/e.txt", "top.txt"] {
+        expect_ok(create_file(sdk.sdk, c_path(name).as_ptr(), true));
+    }
+
+    let root = c_path("");
+    assert_eq!(
+        walk_paths(datenlord_walk_open(sdk.sdk, root.as_ptr())),
+        ["a", "a/b", "a/b/c.txt", "a/b/d.py", "a/e.txt", "top.txt"]
+    );
+    assert_eq!(
+        walk_paths(datenlord_walk_open(sdk.sdk, c_path("a/b").as_ptr())),
+        ["a/b/c.txt", "a/b/d.py"]
+    );
+    assert_eq!(
+        walk_paths(datenlord_glob_open(sdk.sdk, c_path("**/*.txt").as_ptr())),
+        ["a/b/c.txt", "a/e.txt", "top.txt"]
+    );
+    assert_eq!(
+        walk_paths(datenlord_glob_open(sdk.sdk, c_path("a/?/*.p*").as_ptr())),
+        ["a/b/d.py"]
+    );
+    assert!(walk_paths(datenlord_glob_open(sdk.sdk, c_path("missing/*").as_ptr())).is_empty());
+
+    // A walk closed early, or outliving the sdk, is cancelled
+    let walk = datenlord_walk_open(sdk.sdk, root.as_ptr());
+    datenlord_walk_close(walk);
+    let walk = datenlord_walk_open(sdk.sdk, root.as_ptr());
+    let missing = datenlord_walk_open(sdk.sdk, c_path("missing").as_ptr());
+    take_message(datenlord_walk_next(missing, ptr::null_mut()));
+    let mut entry = datenlord_walk_entry {
+        path: ptr::null(),
+        stat: unsafe { std::mem::zeroed() },
+    };
+    take_message(datenlord_walk_next(missing, &mut entry));
+    datenlord_walk_close(missing);
+    // Entries buffered before the sdk is freed are still returned, then the walk ends
+    drop(sdk);
+    loop {
+        let err = datenlord_walk_next(walk, &mut entry);
+        if err.is_null() && entry.path.is_null() {
+            break;
+        }
+        datenlord_error_free(err);
+    }
+    datenlord_walk_close(walk);
+
+    assert!(datenlord_walk_open(ptr::null_mut(), root.as_ptr()).is_null());
+    datenlord_walk_close(ptr::null_mut());
+}
+
+#[test]
+fn readdir_lists_kinds_and_stats_with_plus() {
+    let sdk = Sdk::new("readdir");
+    expect_ok(datenlord_mkdir_all(sdk.sdk, c_path("dir/sub").as_ptr(), 0o755));
+    sdk.create("dir/file.txt", b"hello");
+
+    for plus in [false, true] {
+        let mut dir = ptr::null_mut();
+        expect_ok(datenlord_opendir(sdk.sdk, c_path("dir").as_ptr(), plus, &mut dir));
+        let mut entry = datenlord_dir_entry {
+            name: ptr::null(),
+            ino: 0,
+            kind: datenlord_file_kind::DATENLORD_FILE_KIND_UNKNOWN,
+            stat: ptr::null(),
+            name_len: 0,
+        };
+        let mut entries = Vec::new();
+        while datenlord_readdir(dir, &mut entry) {
+            let name = unsafe { std::ffi::CStr::from_ptr(entry.name) };
+            let stat = unsafe { entry.stat.as_ref() };
+            assert_eq!(stat.is_some(), plus);
+            if let Some(stat) = stat {
+                assert_eq!(stat.ino, entry.ino);
+            }
+            let is_dir = matches!(entry.kind, datenlord_file_kind::DATENLORD_FILE_KIND_DIRECTORY);
+            let size = stat.filter(|_| !is_dir).map(|stat| stat.size);
+            entries.push((name.to_str().unwrap().to_owned(), is_dir, size));
+        }
+        // Exhausted listings keep reporting the end
+        assert!(!datenlord_readdir(dir, &mut entry));
+        datenlord_closedir(dir);
+        entries.sort();
+        let size = plus.then_some(5);
+        assert_eq!(
+            entries,
+            [("file.txt".to_owned(), false, size), ("sub".to_owned(), true, None)]
+        );
+    }
+
+    let mut dir = ptr::null_mut();
+    take_message(datenlord_opendir(sdk.sdk, c_path("missing").as_ptr(), false, &mut dir));
+    assert!(dir.is_null());
+    take_message(datenlord_opendir(sdk.sdk, c_path("dir").as_ptr(), false, ptr::null_mut()));
+    assert!(!datenlord_readdir(ptr::null_mut(), ptr::null_mut()));
+    datenlord_closedir(ptr::null_mut());
+}
+
+#[test]
+fn listing_filter_hides_matching_names() {
+    let sdk = Sdk::with_config(
+        "filter",
+        r#", "listing_filter": {"hide_dotfiles": true, "hidden_names": ["*.tmp"]}"#,
+    );
+    for name in ["dir/.hidden", "dir/a.tmp", "dir/b.txt", "dir/c.txt", "dir/.trash/d.txt"] {
+        expect_ok(create_file(sdk.sdk, c_path(name).as_ptr(), true));
+    }
+
+    let mut dir = ptr::null_mut();
+    expect_ok(datenlord_opendir(sdk.sdk, c_path("dir").as_ptr(), true, &mut dir));
+    let mut entry = datenlord_dir_entry {
+        name: ptr::null(),
+        ino: 0,
+        kind: datenlord_file_kind::DATENLORD_FILE_KIND_UNKNOWN,
+        stat: ptr::null(),
+        name_len: 0,
+    };
+    let mut names = Vec::new();
+    while datenlord_readdir(dir, &mut entry) {
+        let name = unsafe { std::ffi::CStr::from_ptr(entry.name) };
+        names.push(name.to_str().unwrap().to_owned());
+    }
+    datenlord_closedir(dir);
+    names.sort();
+    assert_eq!(names, ["b.txt", "c.txt"]);
+
+    // Walks skip hidden directories, which stay reachable by name
+    assert_eq!(
+        walk_paths(datenlord_walk_open(sdk.sdk, c_path("dir").as_ptr())),
+        ["dir/b.txt", "dir/c.txt"]
+    );
+    assert!(exists(sdk.sdk, c_path("dir/.trash/d.txt").as_ptr()));
+}
+
+#[test]
+fn shutdown_waits_for_running_calls_then_refuses_new_ones() {
+    let sdk = Sdk::new("shutdown");
+    sdk.create("file", b"0123456789");
+    let path = c_path("file");
+
+    // An open walk counts as running until closed
+    let walk = datenlord_walk_open(sdk.sdk, c_path("").as_ptr());
+    assert!(!walk.is_null());
+    let err = datenlord_shutdown(sdk.sdk, 50);
+    assert_eq!(unsafe { (*err).code }, 110, "expected ETIMEDOUT");
+    datenlord_error_free(err);
+    assert!(!exists(sdk.sdk, path.as_ptr()), "no call is admitted once shutting down");
+    datenlord_walk_close(walk);
+
+    let sdk = Sdk::new("shutdown-async");
+    sdk.create("file", b"0123456789");
+    let mut buffer = [0u8; 4];
+    let req = datenlord_io_request {
+        path: path.as_ptr(),
+        offset: 2,
+        buf: buffer.as_mut_ptr(),
+        len: buffer.len(),
+        timeout_ms: 0,
+    };
+    let op_id = datenlord_read_async(sdk.sdk, req, None, ptr::null_mut());
+    assert_ne!(op_id, 0);
+    expect_ok(datenlord_shutdown(sdk.sdk, 0));
+    // The read finished before the shutdown returned
+    let completions = poll_all(sdk.sdk, 1, 4);
+    assert_eq!(completions[0].op_id, op_id);
+    assert!(completions[0].error.is_null());
+    assert_eq!(&buffer, b"2345");
+
+    let err = create_file(sdk.sdk, c_path("other").as_ptr(), false);
+    assert_eq!(unsafe { (*err).code }, 108, "expected ESHUTDOWN");
+    datenlord_error_free(err);
+    let req = datenlord_io_request {
+        path: path.as_ptr(),
+        offset: 0,
+        buf: buffer.as_mut_ptr(),
+        len: buffer.len(),
+        timeout_ms: 0,
+    };
+    assert_eq!(datenlord_read_async(sdk.sdk, req, None, ptr::null_mut()), 0);
+    assert!(datenlord_walk_open(sdk.sdk, c_path("").as_ptr()).is_null());
+    expect_ok(datenlord_shutdown(sdk.sdk, 0));
+    take_message(datenlord_shutdown(ptr::null_mut(), 0));
+}
+
+#[test]
+fn length_delimited_paths_take_any_bytes() {
+    let sdk = Sdk::new("bytes");
+    let bytes = |path: &'static [u8]| datenlord_bytes {
+        data: path.as_ptr(),
+        len: path.len(),
+    };
+    // Only the first `len` bytes are the path, no NUL is needed
+    let dir = b"d\xefr/caf\xe9 and more";
+    expect_ok(datenlord_create_file_bytes(sdk.sdk, bytes(&dir[..8]), true));
+    assert!(datenlord_exists_bytes(sdk.sdk, bytes(b"d\xefr/caf\xe9")));
+    assert!(!datenlord_exists_bytes(sdk.sdk, bytes("d\u{ef}r/caf\u{e9}".as_bytes())));
+    let mut attr = std::mem::MaybeUninit::<datenlord_stat>::uninit();
+    expect_ok(datenlord_stat_bytes(sdk.sdk, bytes(b"d\xefr/caf\xe9"), attr.as_mut_ptr()));
+    assert_eq!(unsafe { attr.assume_init() }.size, 0);
+    // NUL-terminated paths are not required to be UTF-8 either
+    assert!(exists(sdk.sdk, c"d\xefr/caf\xe9".as_ptr()));
+
+    let mut listing = ptr::null_mut();
+    expect_ok(datenlord_opendir_bytes(sdk.sdk, bytes(b"d\xefr"), false, &mut listing));
+    let mut entry = datenlord_dir_entry {
+        name: ptr::null(),
+        ino: 0,
+        kind: datenlord_file_kind::DATENLORD_FILE_KIND_UNKNOWN,
+        stat: ptr::null(),
+        name_len: 0,
+    };
+    assert!(datenlord_readdir(listing, &mut entry));
+    let name = unsafe { std::slice::from_raw_parts(entry.name.cast::<u8>(), entry.name_len) };
+    assert_eq!(name, b"caf\xe9");
+    assert!(!datenlord_readdir(listing, &mut entry));
+    datenlord_closedir(listing);
+
+    let mut attr = std::mem::MaybeUninit::<datenlord_stat>::uninit();
+    let err = datenlord_stat_bytes(sdk.sdk, bytes(b"missing"), attr.as_mut_ptr());
+    assert!(!take_message(err).is_empty());
+}
+
+/// Records the kind and path of every change into the `Mutex<Vec<_>>` at
+/// `user_data`
+extern "C" fn record_change(event: *const datenlord_event, user_data: *mut std::os::raw::c_void) {
+    let event = unsafe { &*event };
+    let changes = unsafe { &*(user_data as *const std::sync::Mutex<Vec<(bool, String)>>) };
+    let path = unsafe { std::ffi::CStr::from_ptr(event.path) };
+    let renamed = matches!(event.kind, datenlord_event_kind::DATENLORD_EVENT_RENAME);
+    changes
+        .lock()
+        .unwrap()
+        .push((renamed, path.to_string_lossy().into_owned()));
+}
+
+#[test]
+fn watch_callback_receives_changes_until_closed() {
+    let sdk = Sdk::new("watch");
+    let changes = std::sync::Mutex::new(Vec::new());
+    let user_data = &changes as *const _ as *mut std::os::raw::c_void;
+    let mut watch = ptr::null_mut();
+    let missing = c_path("missing");
+    let err = datenlord_watch_open(sdk.sdk, missing.as_ptr(), false, Some(record_change), user_data, &mut watch);
+    assert!(take_message(err).contains("Failed to watch"));
+    assert!(watch.is_null());
+
+    let root = c_path("");
+    expect_ok(datenlord_watch_open(sdk.sdk, root.as_ptr(), true, Some(record_change), user_data, &mut watch));
+    assert!(!watch.is_null());
+    sdk.create("a.txt", b"data");
+    expect_ok(rename_path(sdk.sdk, c_path("a.txt").as_ptr(), c_path("b.txt").as_ptr(), 0));
+    for _ in 0..100 {
+        if changes.lock().unwrap().iter().any(|(renamed, _)| *renamed) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let seen = changes.lock().unwrap().clone();
+    assert!(seen.iter().all(|(_, path)| path == "a.txt"), "{seen:?}");
+    assert_eq!(seen.last(), Some(&(true, "a.txt".to_owned())));
+
+    datenlord_watch_close(watch);
+    datenlord_watch_close(ptr::null_mut());
+    let count = changes.lock().unwrap().len();
+    sdk.create("c.txt", b"data");
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(changes.lock().unwrap().len(), count);
+}
+
+/// Records every key and value found by a scan into the `Vec<_>` at `user_data`
+extern "C" fn record_entry(key: *const c_char, value: datenlord_bytes, user_data: *mut std::os::raw::c_void) {
+    let entries = unsafe { &mut *(user_data as *mut Vec<(String, Vec<u8>)>) };
+    let key = unsafe { std::ffi::CStr::from_ptr(key) };
+    let value = unsafe { std::slice::from_raw_parts(value.data, value.len) };
+    entries.push((key.to_string_lossy().into_owned(), value.to_vec()));
+}
+
+#[test]
+fn kv_store_puts_gets_and_scans_values() {
+    let sdk = Sdk::new("kv");
+    let mut kv = ptr::null_mut();
+    expect_ok(datenlord_kv_open(sdk.sdk, c_path("store").as_ptr(), &mut kv));
+    assert!(!kv.is_null());
+    for (key, value) in [("user/1", &b"alice"[..]), ("user/2", b"bob"), ("team/1", b"data")] {
+        let value = datenlord_bytes { data: value.as_ptr(), len: value.len() };
+        expect_ok(datenlord_kv_put(kv, c_path(key).as_ptr(), value));
+    }
+
+    let mut buffer = [0u8; 8];
+    let mut out = datenlord_bytes { data: buffer.as_mut_ptr(), len: buffer.len() };
+    let mut found = false;
+    expect_ok(datenlord_kv_get(kv, c_path("user/1").as_ptr(), &mut out, &mut found));
+    assert!(found);
+    assert_eq!(&buffer[..out.len], b"alice");
+    let mut small = [0u8; 2];
+    let mut out = datenlord_bytes { data: small.as_mut_ptr(), len: small.len() };
+    let err = datenlord_kv_get(kv, c_path("user/1").as_ptr(), &mut out, &mut found);
+    assert!(take_message(err).contains("larger than the buffer"));
+    assert_eq!(out.len, 5);
+
+    let mut existed = false;
+    expect_ok(datenlord_kv_delete(kv, c_path("user/2").as_ptr(), &mut existed));
+    assert!(existed);
+    expect_ok(datenlord_kv_delete(kv, c_path("user/2").as_ptr(), &mut existed));
+    assert!(!existed);
+    let mut out = datenlord_bytes { data: buffer.as_mut_ptr(), len: buffer.len() };
+    expect_ok(datenlord_kv_get(kv, c_path("user/2").as_ptr(), &mut out, &mut found));
+    assert!(!found);
+
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+    let user_data = &mut entries as *mut _ as *mut std::os::raw::c_void;
+    expect_ok(datenlord_kv_scan(kv, c_path("user/").as_ptr(), Some(record_entry), user_data));
+    assert_eq!(entries, [("user/1".to_owned(), b"alice".to_vec())]);
+    let mut reclaimed = 0;
+    expect_ok(datenlord_kv_compact(kv, &mut reclaimed));
+    assert!(reclaimed > 0);
+    expect_ok(datenlord_kv_close(kv));
+    expect_ok(datenlord_kv_close(ptr::null_mut()));
+
+    // The values outlive the store handle
+    expect_ok(datenlord_kv_open(sdk.sdk, c_path("store").as_ptr(), &mut kv));
+    let mut out = datenlord_bytes { data: buffer.as_mut_ptr(), len: buffer.len() };
+    expect_ok(datenlord_kv_get(kv, c_path("team/1").as_ptr(), &mut out, &mut found));
+    assert_eq!(&buffer[..out.len], b"data");
+    expect_ok(datenlord_kv_close(kv));
+}
+
+/// Records the offset and content of every record into the `Mutex<Vec<_>>`
+/// at `user_data`
+extern "C" fn record_tail(offset: u64, record: datenlord_bytes, user_data: *mut std::os::raw::c_void) {
+    let records = unsafe { &*(user_data as *const std::sync::Mutex<Vec<(u64, Vec<u8>)>>) };
+    let data = unsafe { std::slice::from_raw_parts(record.data, record.len) };
+    records.lock().unwrap().push((offset, data.to_vec()));
+}
+
+#[test]
+fn appended_records_reach_the_tail_until_closed() {
+    let sdk = Sdk::new("tail");
+    let path = c_path("events.log");
+    let mut offsets = Vec::new();
+    for record in [&b"first"[..], b"second"] {
+        let mut offset = u64::MAX;
+        let bytes = datenlord_bytes { data: record.as_ptr(), len: record.len() };
+        expect_ok(datenlord_append(sdk.sdk, path.as_ptr(), bytes, &mut offset));
+        offsets.push(offset);
+    }
+    assert_eq!(offsets[0], 0);
+
+    let records = std::sync::Mutex::new(Vec::<(u64, Vec<u8>)>::new());
+    let user_data = &records as *const _ as *mut std::os::raw::c_void;
+    let mut tail = ptr::null_mut();
+    let err = datenlord_tail_open(sdk.sdk, path.as_ptr(), u64::MAX, Some(record_tail), user_data, &mut tail);
+    assert!(take_message(err).contains("Failed to tail log"));
+    expect_ok(datenlord_tail_open(sdk.sdk, path.as_ptr(), offsets[1], Some(record_tail), user_data, &mut tail));
+    let third = b"third";
+    let bytes = datenlord_bytes { data: third.as_ptr(), len: third.len() };
+    expect_ok(datenlord_append(sdk.sdk, path.as_ptr(), bytes, ptr::null_mut()));
+    for _ in 0..100 {
+        if records.lock().unwrap().len() == 2 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let seen = records.lock().unwrap().clone();
+    assert_eq!(seen[0], (offsets[1], b"second".to_vec()));
+    assert_eq!(seen[1].1, b"third");
+
+    datenlord_tail_close(tail);
+    datenlord_tail_close(ptr::null_mut());
+    let bytes = datenlord_bytes { data: third.as_ptr(), len: third.len() };
+    expect_ok(datenlord_append(sdk.sdk, path.as_ptr(), bytes, ptr::null_mut()));
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(records.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn multipart_uploads_resume_and_complete() {
+    let sdk = Sdk::new("upload");
+    let path = c_path("upload.bin");
+    let mut upload_id = datenlord_upload_id { id: [0; DATENLORD_UPLOAD_ID_SIZE] };
+    expect_ok(datenlord_upload_start(sdk.sdk, path.as_ptr(), &mut upload_id));
+    let id = upload_id.id.as_ptr();
+    let mut part = datenlord_upload_part_info { index: 0, size: 0, checksum: [0; DATENLORD_CHECKSUM_SIZE] };
+    for (index, data) in [(1, &b"world"[..]), (0, b"hello ")] {
+        let bytes = datenlord_bytes { data: data.as_ptr(), len: data.len() };
+        expect_ok(datenlord_upload_part(sdk.sdk, id, index, bytes, &mut part));
+    }
+    assert_eq!((part.index, part.size), (0, 6));
+    let checksum = unsafe { std::ffi::CStr::from_ptr(part.checksum.as_ptr()) };
+    assert_eq!(checksum.to_bytes().len(), 64);
+
+    // A client resuming finds the upload and its parts
+    let mut found_id = datenlord_upload_id { id: [0; DATENLORD_UPLOAD_ID_SIZE] };
+    let mut found = false;
+    expect_ok(datenlord_upload_find(sdk.sdk, path.as_ptr(), &mut found_id, &mut found));
+    assert!(found);
+    assert_eq!(found_id.id, upload_id.id);
+    let mut count = 0;
+    let err = datenlord_upload_parts(sdk.sdk, id, ptr::null_mut(), 0, &mut count);
+    assert_eq!(unsafe { (*err).code }, 34, "expected ERANGE");
+    datenlord_error_free(err);
+    assert_eq!(count, 2);
+    let mut parts = Vec::with_capacity(count);
+    expect_ok(datenlord_upload_parts(sdk.sdk, id, parts.as_mut_ptr(), count, &mut count));
+    unsafe { parts.set_len(count) };
+    assert_eq!((parts[0].index, parts[1].index, parts[1].size), (0, 1, 5));
+
+    let mut stat = unsafe { std::mem::zeroed::<datenlord_stat>() };
+    expect_ok(datenlord_upload_complete(sdk.sdk, id, &mut stat));
+    assert_eq!(stat.size, 11);
+    expect_ok(datenlord_upload_find(sdk.sdk, path.as_ptr(), &mut found_id, &mut found));
+    assert!(!found);
+    assert!(take_message(datenlord_upload_abort(sdk.sdk, id)).contains("Failed to abort upload"));
+}
+
+#[test]
+fn atomic_writes_replace_files() {
+    let sdk = Sdk::new("atomic");
+    let path = c_path("config.json");
+    for content in [&b"{}"[..], b"{\"replaced\": true}"] {
+        let bytes = datenlord_bytes { data: content.as_ptr(), len: content.len() };
+        expect_ok(datenlord_write_file_atomic(sdk.sdk, path.as_ptr(), bytes));
+        assert_eq!(std::fs::read(sdk.root.join("config.json")).unwrap(), content);
+    }
+    let missing = c_path("missing/config.json");
+    let bytes = datenlord_bytes { data: ptr::null(), len: 0 };
+    assert!(take_message(datenlord_write_file_atomic(sdk.sdk, missing.as_ptr(), bytes))
+        .contains("Failed to write file atomically"));
+}
+
+#[test]
+fn digests_catch_changed_contents() {
+    let sdk = Sdk::new("digest");
+    let path = c_path("file");
+    expect_ok(create_file(sdk.sdk, path.as_ptr(), false));
+    let content = b"123456789";
+    let bytes = || datenlord_bytes {
+        data: content.as_ptr(),
+        len: content.len(),
+    };
+    let mut digest = datenlord_digest {
+        value: [0; DATENLORD_DIGEST_SIZE],
+    };
+    expect_ok(datenlord_write_file_digest(
+        sdk.sdk,
+        path.as_ptr(),
+        bytes(),
+        DATENLORD_DIGEST_CRC32C,
+        &mut digest,
+    ));
+    let value = unsafe { std::ffi::CStr::from_ptr(digest.value.as_ptr()) };
+    assert_eq!(value.to_str().unwrap(), "crc32c:e3069283");
+    let err = datenlord_write_file_digest(sdk.sdk, path.as_ptr(), bytes(), 9, &mut digest);
+    assert_eq!(unsafe { (*err).code }, 22, "expected EINVAL");
+    take_message(err);
+
+    let mut buffer = [0; 16];
+    let mut out = datenlord_bytes {
+        data: buffer.as_mut_ptr(),
+        len: buffer.len(),
+    };
+    expect_ok(datenlord_read_file_verify(
+        sdk.sdk,
+        path.as_ptr(),
+        &mut out,
+        digest.value.as_ptr(),
+    ));
+    assert_eq!(&buffer[..out.len], content);
+
+    // Contents changed behind the SDK no longer match
+    std::fs::write(sdk.root.join("file"), b"123456780").unwrap();
+    out.len = buffer.len();
+    let err = datenlord_read_file_verify(sdk.sdk, path.as_ptr(), &mut out, digest.value.as_ptr());
+    assert_eq!(unsafe { (*err).code }, 74, "expected EBADMSG");
+    take_message(err);
+    let not_a_digest = c_path("md5:abc");
+    let err = datenlord_read_file_verify(sdk.sdk, path.as_ptr(), &mut out, not_a_digest.as_ptr());
+    assert_eq!(unsafe { (*err).code }, 22, "expected EINVAL");
+    take_message(err);
+}
+
+#[test]
+fn one_handle_serves_many_threads() {
+    const THREADS: usize = 32;
+    const ROUNDS: usize = 20;
+    let sdk = Sdk::new("threads");
+    assert!(datenlord_sdk_clone(ptr::null_mut()).is_null());
+
+    let workers: Vec<_> = (0..THREADS)
+        .map(|t| {
+            // Each thread holds its own reference, freed before it exits
+            let handle = datenlord_sdk_clone(sdk.sdk) as usize;
+            thread::spawn(move || {
+                let sdk = handle as *mut datenlord_sdk;
+                let log = c_path("log");
+                let mut offsets = Vec::with_capacity(ROUNDS);
+                for round in 0..ROUNDS {
+                    let path = c_path(&format!("file-{t}-{round}"));
+                    let content = format!("{t}:{round}");
+                    expect_ok(create_file(sdk, path.as_ptr(), false));
+                    let bytes = datenlord_bytes {
+                        data: content.as_ptr(),
+                        len: content.len(),
+                    };
+                    expect_ok(write_file(sdk, path.as_ptr(), bytes));
+
+                    let mut buffer = [0u8; 16];
+                    let mut out = datenlord_bytes {
+                        data: buffer.as_mut_ptr(),
+                        len: buffer.len(),
+                    };
+                    expect_ok(read_file(sdk, path.as_ptr(), &mut out));
+                    assert_eq!(&buffer[..out.len], content.as_bytes());
+                    assert!(exists(sdk, path.as_ptr()));
+                    expect_ok(datenlord_mkdir_all(sdk, c_path("shared/dir").as_ptr(), 0o755));
+
+                    let record = datenlord_bytes {
+                        data: content.as_ptr(),
+                        len: content.len(),
+                    };
+                    let mut offset = 0;
+                    expect_ok(datenlord_append(sdk, log.as_ptr(), record, &mut offset));
+                    offsets.push(offset);
+                }
+                free_sdk(sdk);
+                offsets
+            })
+        })
+        .collect();
+    let mut offsets: Vec<u64> = workers
+        .into_iter()
+        .flat_map(|worker| worker.join().unwrap())
+        .collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+    assert_eq!(offsets.len(), THREADS * ROUNDS, "every append got its own offset");
+
+    // The SDK outlived the references of the threads
+    let mut attr = std::mem::MaybeUninit::<datenlord_stat>::uninit();
+    expect_ok(stat(sdk.sdk, c_path("file-31-19").as_ptr(), attr.as_mut_ptr()));
+    assert_eq!(unsafe { attr.assume_init() }.size, 5);
+}
+
+#[test]
+fn ioctl_output_reports_the_size_it_needs() {
+    let sdk = Sdk::new("ioctl");
+    sdk.create("file", b"data");
+    let path = c_path("file");
+    let expected = format!("{}\n", sdk.root.join("file").display());
+    let input = datenlord_bytes {
+        data: ptr::null(),
+        len: 0,
+    };
+
+    let mut small = [0u8; 4];
+    let mut out = datenlord_bytes {
+        data: small.as_mut_ptr(),
+        len: small.len(),
+    };
+    let err = datenlord_ioctl(sdk.sdk, path.as_ptr(), 1, input, &mut out);
+    assert_eq!(unsafe { (*err).code }, 34, "expected ERANGE");
+    datenlord_error_free(err);
+    assert_eq!(out.len, expected.len());
+
+    let mut buffer = vec![0u8; out.len];
+    let mut out = datenlord_bytes {
+        data: buffer.as_mut_ptr(),
+        len: buffer.len(),
+    };
+    let input = datenlord_bytes {
+        data: ptr::null(),
+        len: 0,
+    };
+    expect_ok(datenlord_ioctl(sdk.sdk, path.as_ptr(), 1, input, &mut out));
+    assert_eq!(buffer, expected.as_bytes());
+
+    let input = datenlord_bytes {
+        data: ptr::null(),
+        len: 0,
+    };
+    let err = datenlord_ioctl(sdk.sdk, path.as_ptr(), 0x7fff_0000, input, &mut out);
+    assert_eq!(unsafe { (*err).code }, 95, "expected ENOTSUP");
+    datenlord_error_free(err);
+}
+
+#[test]
+fn stats_are_a_nul_terminated_json_object() {
+    let sdk = Sdk::new("stats");
+    sdk.create("file", b"data");
+
+    let mut small = [0u8; 4];
+    let mut out = datenlord_bytes {
+        data: small.as_mut_ptr(),
+        len: small.len(),
+    };
+    let err = datenlord_get_stats(sdk.sdk, &mut out);
+    assert_eq!(unsafe { (*err).code }, 34, "expected ERANGE");
+    datenlord_error_free(err);
+
+    // Leave room for the calls made meanwhile
+    let mut buffer = vec![0u8; out.len * 2];
+    let mut out = datenlord_bytes {
+        data: buffer.as_mut_ptr(),
+        len: buffer.len(),
+    };
+    expect_ok(datenlord_get_stats(sdk.sdk, &mut out));
+    assert_eq!(buffer[out.len - 1], 0);
+    let stats: serde_json::Value = serde_json::from_slice(&buffer[..out.len - 1]).unwrap();
+    assert_eq!(stats["ops"]["write"]["calls"], 1);
+    assert_eq!(stats["bytes_written"], 4);
+    assert_eq!(stats["open_files"], 0);
+}
+
+#[test]
+fn healthcheck_times_each_step() {
+    let sdk = Sdk::new("health");
+    let mut health = datenlord_health::default();
+    expect_ok(datenlord_healthcheck(sdk.sdk, &mut health));
+    assert!(health.healthy);
+    assert!(sdk.root.join(".datenlord_health").is_file());
+
+    // Probing again reuses the scratch file
+    expect_ok(datenlord_healthcheck(sdk.sdk, &mut health));
+    assert!(health.healthy);
+
+    let err = datenlord_healthcheck(sdk.sdk, ptr::null_mut());
+    assert!(!err.is_null());
+    datenlord_error_free(err);
+}
+
+#[test]
+fn config_updates_reject_fields_fixed_at_init() {
+    let sdk = Sdk::new("update-config");
+    let update = CString::new(r#"{"op_timeout_ms": 1000, "attr_cache_capacity": 8}"#).unwrap();
+    expect_ok(datenlord_update_config(sdk.sdk, update.as_ptr()));
+
+    for update in [r#"{"root": "/elsewhere"}"#, "not json", r#"{"log_level": "loud"}"#] {
+        let update = CString::new(update).unwrap();
+        let err = datenlord_update_config(sdk.sdk, update.as_ptr());
+        assert_eq!(unsafe { (*err).code }, 22, "expected EINVAL");
+        datenlord_error_free(err);
+    }
+    sdk.create("file", b"data");
+}
@@ -0,0 +1,236 @@
+//! Shares POSIX file locks between instances through a lock service
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::common::DatenLordError;
+use datenlord::storage::filelock::{
+    LockConfig, LockManager, LockServer, LockService, LockType, RangeLock,
+};
+use datenlord::storage::fs_util::{FileLockParam, RequestContext, ROOT_ID};
+use datenlord::storage::meta::{MemoryMeta, MetaStore};
+use datenlord::storage::sharedfs::{SharedConfig, SharedFs};
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::libc;
+use opendal::{Operator, Scheme};
+use tokio::net::TcpListener;
+
+fn ctx() -> RequestContext {
+    RequestContext {
+        uid: 0,
+        gid: 0,
+        pid: 1,
+        umask: 0o022,
+        root: ROOT_ID,
+    }
+}
+
+fn lock(session: &str, start: u64, end: u64, typ: LockType) -> RangeLock {
+    RangeLock {
+        ino: 2,
+        session: session.to_owned(),
+        owner: 1,
+        pid: 1,
+        start,
+        end,
+        typ,
+    }
+}
+
+fn param(owner: u64, start: u64, end: u64, typ: i32) -> FileLockParam {
+    FileLockParam {
+        fh: 0,
+        lock_owner: owner,
+        start,
+        end,
+        typ: typ as u32,
+        pid: 1,
+    }
+}
+
+#[tokio::test]
+async fn ranges_split_and_conflict() {
+    let locks = LockManager::default();
+    let lease = Duration::from_secs(60);
+    assert!(!locks.keepalive("a", lease).await.unwrap());
+    assert!(!locks.keepalive("b", lease).await.unwrap());
+    assert!(locks.keepalive("a", lease).await.unwrap());
+
+    assert_eq!(
+        locks
+            .try_set(&lock("a", 0, 99, LockType::Write))
+            .await
+            .unwrap(),
+        None
+    );
+    let held = locks
+        .try_set(&lock("b", 50, 60, LockType::Read))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((held.session.as_str(), held.start, held.end), ("a", 0, 99));
+
+    // Unlocking the middle leaves both ends locked
+    locks
+        .try_set(&lock("a", 40, 69, LockType::Unlock))
+        .await
+        .unwrap();
+    assert_eq!(
+        locks
+            .try_set(&lock("b", 50, 60, LockType::Read))
+            .await
+            .unwrap(),
+        None
+    );
+    let held = locks
+        .test(&lock("b", 30, 45, LockType::Write))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((held.start, held.end), (0, 39));
+    let held = locks
+        .test(&lock("b", 65, 75, LockType::Write))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((held.start, held.end), (70, 99));
+    // Readers share, and an owner never conflicts with itself
+    assert_eq!(
+        locks
+            .test(&lock("a", 50, 60, LockType::Read))
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        locks
+            .try_set(&lock("b", 0, 0, LockType::Write))
+            .await
+            .unwrap()
+            .map(|held| held.end),
+        Some(39)
+    );
+
+    let stats = locks.stats().await.unwrap();
+    assert_eq!(
+        (stats.sessions, stats.held, stats.acquired, stats.conflicts),
+        (2, 3, 2, 2)
+    );
+    locks.end("a").await.unwrap();
+    assert_eq!(locks.stats().await.unwrap().held, 1);
+}
+
+#[tokio::test]
+async fn locks_of_dead_sessions_are_stolen() {
+    let locks = LockManager::default();
+    locks
+        .keepalive("dead", Duration::from_millis(100))
+        .await
+        .unwrap();
+    locks
+        .keepalive("alive", Duration::from_secs(60))
+        .await
+        .unwrap();
+    locks
+        .try_set(&lock("dead", 0, u64::MAX, LockType::Write))
+        .await
+        .unwrap();
+    assert!(locks
+        .try_set(&lock("alive", 0, 0, LockType::Write))
+        .await
+        .unwrap()
+        .is_some());
+
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert_eq!(
+        locks
+            .try_set(&lock("alive", 0, 0, LockType::Write))
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(locks.stats().await.unwrap().stolen, 1);
+    // The dead session learns it lost its locks
+    assert!(matches!(
+        locks.try_set(&lock("dead", 0, 0, LockType::Read)).await,
+        Err(DatenLordError::Io { .. })
+    ));
+    assert!(!locks
+        .keepalive("dead", Duration::from_secs(60))
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn instances_lock_through_the_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let server = Arc::new(LockServer::default());
+    tokio::spawn(Arc::clone(&server).serve(listener));
+
+    let config = SharedConfig {
+        data_scheme: "memory".to_owned(),
+        locks: LockConfig::Server { address },
+        ..SharedConfig::default()
+    };
+    let meta: Arc<dyn MetaStore> = Arc::new(MemoryMeta::default());
+    let data = Operator::via_map(Scheme::Memory, HashMap::new()).unwrap();
+    let a = SharedFs::with_stores(&config, Arc::clone(&meta), data.clone())
+        .await
+        .unwrap();
+    let b = Arc::new(SharedFs::with_stores(&config, meta, data).await.unwrap());
+    a.create(&ctx(), 0, ROOT_ID, OsStr::new("f"), 0o644, 0)
+        .await
+        .unwrap();
+    let ino = a
+        .lookup(&ctx(), ROOT_ID, OsStr::new("f"))
+        .await
+        .unwrap()
+        .1
+        .ino;
+
+    a.setlk(&ctx(), ino, param(7, 0, u64::MAX, libc::F_WRLCK), false)
+        .await
+        .unwrap();
+    assert!(matches!(
+        b.getlk(&ctx(), ino, param(7, 10, 20, libc::F_RDLCK)).await,
+        Err(DatenLordError::Unavailable { .. })
+    ));
+    assert!(matches!(
+        b.setlk(&ctx(), ino, param(7, 10, 20, libc::F_RDLCK), false)
+            .await,
+        Err(DatenLordError::Unavailable { .. })
+    ));
+
+    let waiter = {
+        let b = Arc::clone(&b);
+        tokio::spawn(async move {
+            b.setlk(&ctx(), ino, param(7, 10, 20, libc::F_RDLCK), true)
+                .await
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!waiter.is_finished());
+    // Closing the file drops the locks of its owner
+    a.flush(&ctx(), ino, 0, 7).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), waiter)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    a.getlk(&ctx(), ino, param(8, 10, 20, libc::F_RDLCK))
+        .await
+        .unwrap();
+
+    let stats = b.lock_stats().await.unwrap();
+    assert_eq!((stats.sessions, stats.held, stats.waits), (2, 1, 1));
+    assert!(stats.conflicts >= 2);
+    assert_eq!(a.lock_stats().await.unwrap().waits, 0);
+
+    // A closed instance releases its locks right away
+    drop(b);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let stats = server.stats().await.unwrap();
+    assert_eq!((stats.sessions, stats.held, stats.stolen), (1, 0, 0));
+}
@@ -0,0 +1,124 @@
+//! Checks and repairs local namespaces
+use std::path::PathBuf;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::fsck::{self, FsckOptions, FsckReport, Severity};
+use datenlord::sdk::rust::Client;
+use datenlord::storage::tags::TAG_XATTR_PREFIX;
+
+/// A namespace in a fresh directory, removed on drop
+struct Namespace {
+    root: PathBuf,
+    client: Client,
+}
+
+impl Namespace {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("datenlord-fsck-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let config = DatenLordConfig {
+            root: root.clone(),
+            ..DatenLordConfig::default()
+        };
+        let client = Client::new(&config).unwrap();
+        Self { root, client }
+    }
+
+    async fn write(&self, path: &str, content: &[u8]) {
+        let file = self.client.create(path).await.unwrap();
+        file.write_at(content, 0).await.unwrap();
+        file.close().await.unwrap();
+    }
+
+    fn check(&self, repair: bool) -> FsckReport {
+        fsck::check(&self.root, &FsckOptions { repair }).unwrap()
+    }
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// The path, severity and repair of every issue of `report`
+fn summary(report: &FsckReport) -> Vec<(&str, Severity, bool)> {
+    report
+        .issues
+        .iter()
+        .map(|issue| (issue.path.as_str(), issue.severity, issue.repaired))
+        .collect()
+}
+
+#[tokio::test]
+async fn consistent_namespace_has_no_issues() {
+    let ns = Namespace::new("consistent");
+    ns.client.create_dir_all("data/raw").await.unwrap();
+    ns.client.create_dir_all("data/clean").await.unwrap();
+    ns.write("data/raw/a.csv", b"a,b\n1,2\n").await;
+    ns.client.set_tag("data/raw/a.csv", "team", "ml").await.unwrap();
+    std::fs::hard_link(ns.root.join("data/raw/a.csv"), ns.root.join("data/clean/a.csv")).unwrap();
+    std::os::unix::fs::symlink("../raw/a.csv", ns.root.join("data/clean/link.csv")).unwrap();
+
+    let report = ns.check(false);
+    assert!(report.issues.is_empty(), "{:?}", report.issues);
+    assert!(report.is_clean());
+    // The root, two directories under `data`, and the two links and symlink
+    assert_eq!(report.entries, 7);
+}
+
+#[tokio::test]
+async fn repair_removes_orphans_and_invalid_tags() {
+    let ns = Namespace::new("repair");
+    ns.write("table.parquet", b"rows").await;
+    std::fs::write(ns.root.join(".datenlord_fs_info.tmp"), b"{").unwrap();
+    let path = ns.root.join("table.parquet");
+    let bad_key = format!("{TAG_XATTR_PREFIX}a=b");
+    let bad_value = format!("{TAG_XATTR_PREFIX}team");
+    rustix::fs::lsetxattr(&path, &bad_key, b"c", rustix::fs::XattrFlags::empty()).unwrap();
+    rustix::fs::lsetxattr(&path, &bad_value, &[0xff], rustix::fs::XattrFlags::empty()).unwrap();
+
+    let report = ns.check(false);
+    assert!(!report.is_clean());
+    let mut issues = summary(&report);
+    issues.sort();
+    assert_eq!(
+        issues,
+        [
+            (".datenlord_fs_info.tmp", Severity::Warning, false),
+            ("table.parquet", Severity::Error, false),
+            ("table.parquet", Severity::Error, false),
+        ]
+    );
+
+    let report = ns.check(true);
+    assert_eq!(report.issues.len(), 3);
+    assert!(report.is_clean());
+    assert!(!ns.root.join(".datenlord_fs_info.tmp").exists());
+    assert!(ns.check(false).issues.is_empty());
+}
+
+#[tokio::test]
+async fn links_leaving_the_root_are_reported() {
+    let ns = Namespace::new("links");
+    let outside = Namespace::new("links-outside");
+    ns.write("escape", b"").await;
+    outside.write("elsewhere", b"data").await;
+    std::os::unix::fs::symlink("../../etc/passwd", ns.root.join("up")).unwrap();
+    std::os::unix::fs::symlink("missing", ns.root.join("dangling")).unwrap();
+    std::fs::hard_link(outside.root.join("elsewhere"), ns.root.join("linked")).unwrap();
+
+    let report = ns.check(true);
+    let mut issues = summary(&report);
+    issues.sort();
+    assert_eq!(
+        issues,
+        [
+            ("dangling", Severity::Warning, false),
+            ("linked", Severity::Warning, false),
+            ("up", Severity::Warning, false),
+        ]
+    );
+    assert!(!report.is_clean());
+    assert!(ns.root.join("dangling").symlink_metadata().is_ok());
+}
@@ -0,0 +1,228 @@
+//! Collects the backend data no inode of the tree refers to any more
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::fs_util::{CreateParam, FileAttr, RequestContext, ROOT_ID};
+use datenlord::storage::gc;
+use datenlord::storage::meta::{MemoryMeta, MetaStore};
+use datenlord::storage::sharedfs::{SharedConfig, SharedFs};
+use datenlord::storage::striping::StripingConfig;
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::fcntl::OFlag;
+use nix::sys::stat::{SFlag, UtimensatFlags};
+use nix::sys::time::TimeSpec;
+use opendal::{Operator, Scheme};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+fn ctx() -> RequestContext {
+    RequestContext {
+        uid: 0,
+        gid: 0,
+        pid: 1,
+        umask: 0o022,
+        root: ROOT_ID,
+    }
+}
+
+/// The names of the objects of `data`, sorted
+async fn objects(data: &Operator) -> Vec<String> {
+    let mut names = data
+        .list("/")
+        .await
+        .unwrap()
+        .iter()
+        .map(|entry| entry.name().to_owned())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn file_attr(ino: u64, size: u64, ctime: SystemTime) -> FileAttr {
+    FileAttr {
+        ino,
+        size,
+        blocks: 1,
+        atime: ctime,
+        mtime: ctime,
+        ctime,
+        kind: SFlag::S_IFREG,
+        perm: 0o644,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        generation: 0,
+    }
+}
+
+#[tokio::test]
+async fn shared_blocks_of_lost_inodes_are_swept() {
+    let config = SharedConfig {
+        data_scheme: "memory".to_owned(),
+        block_size: 4,
+        ..SharedConfig::default()
+    };
+    let meta: Arc<dyn MetaStore> = Arc::new(MemoryMeta::default());
+    let data = Operator::via_map(Scheme::Memory, HashMap::new()).unwrap();
+    let fs = SharedFs::with_stores(&config, Arc::clone(&meta), data.clone())
+        .await
+        .unwrap();
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: "f".into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let ino = fs.mknod(&ctx(), param).await.unwrap().1.ino;
+    let fh = fs
+        .open(&ctx(), ino, OFlag::O_WRONLY.bits() as u32)
+        .await
+        .unwrap();
+    fs.write(&ctx(), ino, fh, 0, b"0123456", 0).await.unwrap();
+    fs.release(&ctx(), ino, fh, 0, 0, false).await.unwrap();
+
+    // A block past the end, the blocks of an inode without attributes, and
+    // inodes no directory links to, one of them being created
+    data.write(&format!("{ino}.5"), b"past".to_vec())
+        .await
+        .unwrap();
+    data.write("900.0", b"lost".to_vec()).await.unwrap();
+    let old = SystemTime::now() - 2 * HOUR;
+    meta.insert_attr(&file_attr(901, 4, old)).await.unwrap();
+    data.write("901.0", b"leak".to_vec()).await.unwrap();
+    meta.insert_attr(&file_attr(902, 4, SystemTime::now()))
+        .await
+        .unwrap();
+    data.write("902.0", b"new!".to_vec()).await.unwrap();
+
+    let report = gc::collect(&fs, &ctx(), HOUR, true).await.unwrap();
+    assert_eq!(report.reachable, 2);
+    assert_eq!(
+        report.orphans,
+        [
+            format!("block {ino}.5"),
+            "block 900.0".to_owned(),
+            "inode 901".to_owned(),
+            "block 901.0".to_owned()
+        ]
+    );
+    assert_eq!((report.removed, report.bytes), (0, 12));
+    assert_eq!(objects(&data).await.len(), 6);
+
+    let report = gc::collect(&fs, &ctx(), HOUR, false).await.unwrap();
+    assert_eq!(report.removed, 4);
+    assert_eq!(
+        objects(&data).await,
+        [format!("{ino}.0"), format!("{ino}.1"), "902.0".to_owned()]
+    );
+    assert!(meta.get_attr(901).await.unwrap().is_none());
+    assert!(meta.get_attr(902).await.unwrap().is_some());
+    assert!(gc::collect(&fs, &ctx(), HOUR, false)
+        .await
+        .unwrap()
+        .orphans
+        .is_empty());
+}
+
+/// A root and stripe paths, removed on drop
+struct Roots(Vec<PathBuf>);
+
+impl Drop for Roots {
+    fn drop(&mut self) {
+        for dir in &self.0 {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Set the modification time of `path` to two hours ago
+fn age(path: &Path) {
+    let since_epoch = (SystemTime::now() - 2 * HOUR)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    let old = TimeSpec::from(since_epoch);
+    nix::sys::stat::utimensat(None, path, &old, &old, UtimensatFlags::NoFollowSymlink).unwrap();
+}
+
+#[tokio::test]
+async fn stripes_of_removed_files_are_swept() {
+    let roots = Roots(
+        ["root", "0", "1"]
+            .iter()
+            .map(|name| {
+                let dir = std::env::temp_dir()
+                    .join(format!("datenlord-gc-{name}-{}", std::process::id()));
+                let _ = std::fs::remove_dir_all(&dir);
+                dir
+            })
+            .collect(),
+    );
+    let client = Client::new(&DatenLordConfig {
+        root: roots.0[0].clone(),
+        striping: StripingConfig {
+            paths: roots.0[1..]
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+            parity_shards: 1,
+            chunk_size: 4096,
+        },
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    let mut inos = Vec::new();
+    for name in ["kept", "removed", "aged"] {
+        let file = client.create(name).await.unwrap();
+        file.write_at(&[7; 10_000], 0).await.unwrap();
+        file.close().await.unwrap();
+        inos.push(client.metadata(name).await.unwrap().ino);
+    }
+    let (kept, removed, aged) = (inos[0], inos[1], inos[2]);
+
+    // Changed behind the SDK: truncated, and removed twice
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(roots.0[0].join("kept"))
+        .unwrap()
+        .set_len(100)
+        .unwrap();
+    std::fs::remove_file(roots.0[0].join("removed")).unwrap();
+    std::fs::remove_file(roots.0[0].join("aged")).unwrap();
+    for path in &roots.0[1..] {
+        let dir = path.join(aged.to_string());
+        for chunk in std::fs::read_dir(&dir).unwrap() {
+            age(&chunk.unwrap().path());
+        }
+        age(&dir);
+    }
+
+    let report = client.collect_garbage(HOUR, false).await.unwrap();
+    assert_eq!(
+        report.orphans,
+        [
+            format!("stripe 1 of inode {kept}"),
+            format!("stripe 2 of inode {kept}"),
+            format!("stripes of inode {aged}")
+        ]
+    );
+    assert_eq!(report.removed, 3);
+    let file = client.open("kept", OFlag::O_RDONLY).await.unwrap();
+    let mut buf = vec![0; 200];
+    assert_eq!(file.read_at(&mut buf, 0).await.unwrap(), 100);
+    assert_eq!(&buf[..100], &[7; 100][..]);
+    file.close().await.unwrap();
+    assert!(!roots.0[1].join(aged.to_string()).exists());
+
+    // Without grace the recent stripes go too
+    let report = client.collect_garbage(Duration::ZERO, false).await.unwrap();
+    assert_eq!(report.orphans, [format!("stripes of inode {removed}")]);
+    assert!(!roots.0[1].join(removed.to_string()).exists());
+    assert!(roots.0[1].join(kept.to_string()).exists());
+}
@@ -0,0 +1,166 @@
+//! The generations of directories, changing with their entries
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::storage::cache::CacheFs;
+use datenlord::storage::fs_util::{CreateParam, RenameParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::sharedfs::{SharedConfig, SharedFs};
+use datenlord::storage::stats::StatsFs;
+use datenlord::storage::virtualfs::{INum, VirtualFs};
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+/// Past the 100ms TTL of the attributes of `SharedFs`
+const PAST_TTL: Duration = Duration::from_millis(150);
+
+fn create(parent: INum, name: &str, node_type: SFlag) -> CreateParam {
+    CreateParam {
+        parent,
+        name: name.into(),
+        mode: 0o755,
+        rdev: 0,
+        node_type,
+        link: None,
+    }
+}
+
+fn rename(parent: INum, old: &str, new: &str) -> RenameParam {
+    RenameParam {
+        old_parent: parent,
+        old_name: old.into(),
+        new_parent: parent,
+        new_name: new.into(),
+        flags: 0,
+    }
+}
+
+async fn shared_fs() -> SharedFs {
+    let config = SharedConfig {
+        data_scheme: "memory".to_owned(),
+        ..SharedConfig::default()
+    };
+    SharedFs::new(&config).await.unwrap()
+}
+
+async fn generation<F: VirtualFs>(fs: &F, ino: INum) -> u64 {
+    let ctx = RequestContext::current();
+    fs.getattr(&ctx, ino).await.unwrap().1.generation
+}
+
+/// Check that entries created, renamed and removed in the new directory
+/// `name` change its generation, and writes to its files do not
+async fn check<F: VirtualFs>(fs: &F, name: &str) {
+    let ctx = RequestContext::current();
+    let (_, dir, _) = fs
+        .mkdir(&ctx, create(ROOT_ID, name, SFlag::S_IFDIR))
+        .await
+        .unwrap();
+
+    let before = generation(fs, dir.ino).await;
+    let (_, file, _) = fs
+        .mknod(&ctx, create(dir.ino, "file", SFlag::S_IFREG))
+        .await
+        .unwrap();
+    assert_eq!(file.generation, 0);
+    let created = generation(fs, dir.ino).await;
+    assert_ne!(created, before);
+
+    let fh = fs
+        .open(&ctx, file.ino, OFlag::O_WRONLY.bits() as u32)
+        .await
+        .unwrap();
+    fs.write(&ctx, file.ino, fh, 0, b"data", 0).await.unwrap();
+    fs.release(&ctx, file.ino, fh, 0, 0, true).await.unwrap();
+    assert_eq!(generation(fs, dir.ino).await, created);
+
+    fs.rename(&ctx, rename(dir.ino, "file", "renamed"))
+        .await
+        .unwrap();
+    let renamed = generation(fs, dir.ino).await;
+    assert_ne!(renamed, created);
+    fs.unlink(&ctx, dir.ino, OsStr::new("renamed"))
+        .await
+        .unwrap();
+    assert_ne!(generation(fs, dir.ino).await, renamed);
+}
+
+#[tokio::test]
+async fn local_directories_change_generation_with_their_entries() {
+    let root: PathBuf =
+        std::env::temp_dir().join(format!("datenlord-generation-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let config = DatenLordConfig {
+        root: root.clone(),
+        ..DatenLordConfig::default()
+    };
+    check(&LocalFS::new(&config).unwrap(), "dir").await;
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[tokio::test]
+async fn shared_directories_change_generation_with_their_entries() {
+    let name = format!("generation-{}", std::process::id());
+    check(&shared_fs().await, &name).await;
+}
+
+#[tokio::test]
+async fn expired_entries_are_revalidated_by_the_generation_of_their_directory() {
+    let fs = CacheFs::new(StatsFs::new(shared_fs().await), 1024);
+    let ctx = RequestContext::current();
+    let name = format!("revalidated-{}", std::process::id());
+    let (_, dir, _) = fs
+        .mkdir(&ctx, create(ROOT_ID, &name, SFlag::S_IFDIR))
+        .await
+        .unwrap();
+    // Created behind the back of the cache, so looking them up fills it
+    for file in ["a", "b"] {
+        fs.inner()
+            .mknod(&ctx, create(dir.ino, file, SFlag::S_IFREG))
+            .await
+            .unwrap();
+    }
+    let lookups = || {
+        fs.inner()
+            .stats()
+            .ops
+            .get("lookup")
+            .map_or(0, |op| op.calls)
+    };
+    let getattrs = || {
+        fs.inner()
+            .stats()
+            .ops
+            .get("getattr")
+            .map_or(0, |op| op.calls)
+    };
+
+    // Looked up once the generation of the directory is known
+    fs.getattr(&ctx, dir.ino).await.unwrap();
+    for file in ["a", "b"] {
+        fs.lookup(&ctx, dir.ino, OsStr::new(file)).await.unwrap();
+    }
+    tokio::time::sleep(PAST_TTL).await;
+    let (lookups_before, getattrs_before) = (lookups(), getattrs());
+    for file in ["a", "b"] {
+        fs.lookup(&ctx, dir.ino, OsStr::new(file)).await.unwrap();
+    }
+    assert_eq!(lookups(), lookups_before);
+    // The directory once, then each file
+    assert_eq!(getattrs(), getattrs_before + 3);
+
+    // Renamed behind the back of the cache
+    fs.inner()
+        .rename(&ctx, rename(dir.ino, "a", "c"))
+        .await
+        .unwrap();
+    tokio::time::sleep(PAST_TTL).await;
+    assert!(matches!(
+        fs.lookup(&ctx, dir.ino, OsStr::new("a")).await,
+        Err(DatenLordError::NotFound { .. })
+    ));
+    assert_eq!(lookups(), lookups_before + 1);
+}
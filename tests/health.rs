@@ -0,0 +1,78 @@
+//! Probes the SDK end to end, as the readiness probe of a service would
+use std::path::PathBuf;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::faulty::{FaultConfig, FaultyFs};
+use datenlord::storage::fs_util::{RequestContext, ROOT_ID};
+use datenlord::storage::health::{self, HEALTH_FILE};
+use datenlord::storage::localfs::LocalFS;
+
+/// A root removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("datenlord-health-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+
+    fn config(&self) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        }
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn a_working_sdk_is_healthy_and_hides_the_probe_file() {
+    let root = Root::new("ok");
+    let client = Client::new(&root.config()).unwrap();
+    for _ in 0..2 {
+        let report = client.healthcheck().await;
+        assert!(report.healthy, "{report:?}");
+        assert!(report.error.is_none());
+        assert!(report.metadata_latency_us.is_some());
+        assert!(report.write_latency_us.is_some());
+        assert!(report.read_latency_us.is_some());
+    }
+    assert!(root.0.join(HEALTH_FILE).is_file());
+    assert!(client.read_dir("").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn a_failing_write_stops_the_probe() {
+    let root = Root::new("failing");
+    let fs = FaultyFs::new(
+        LocalFS::new(&root.config()).unwrap(),
+        FaultConfig {
+            ops: vec!["write".to_owned()],
+            fail_every: Some(1),
+            ..FaultConfig::default()
+        },
+    );
+    let ctx = RequestContext {
+        uid: 0,
+        gid: 0,
+        pid: 1,
+        umask: 0o022,
+        root: ROOT_ID,
+    };
+    let (report, error) = health::probe(&fs, &ctx).await;
+    assert!(!report.healthy);
+    assert!(report.metadata_latency_us.is_some());
+    assert!(report.write_latency_us.is_none());
+    assert!(report.read_latency_us.is_none());
+    assert!(matches!(error, Some(DatenLordError::Io { .. })));
+    assert!(report.error.unwrap().contains("injected"));
+}
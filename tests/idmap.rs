@@ -0,0 +1,174 @@
+//! Squashes and translates the ids of callers sharing a namespace
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use datenlord::common::config::{CallerConfig, DatenLordConfig};
+use datenlord::common::DatenLordError;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::fs_util::{RequestContext, ROOT_ID};
+use datenlord::storage::idmap::{IdMapConfig, IdRange, Squash};
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("datenlord-idmap-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    /// A client acting as `uid` and `gid` of its namespace, mapped by
+    /// `idmap`
+    fn client(&self, uid: u32, gid: u32, idmap: IdMapConfig) -> Client {
+        Client::new(&DatenLordConfig {
+            root: self.0.clone(),
+            caller: CallerConfig {
+                uid: Some(uid),
+                gid: Some(gid),
+                umask: Some(0o022),
+            },
+            idmap,
+            ..DatenLordConfig::default()
+        })
+        .unwrap()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The ids of a container whose 65536 ids start at `base` on the host
+fn container(base: u32) -> IdMapConfig {
+    let range = IdRange {
+        from: 0,
+        to: base,
+        count: 65536,
+    };
+    IdMapConfig {
+        uids: vec![range],
+        gids: vec![range],
+        ..IdMapConfig::default()
+    }
+}
+
+fn ctx(uid: u32, gid: u32) -> RequestContext {
+    RequestContext {
+        uid,
+        gid,
+        pid: 1,
+        umask: 0o022,
+        root: ROOT_ID,
+    }
+}
+
+#[test]
+fn ids_are_squashed_then_translated_by_the_first_range() {
+    let idmap = IdMapConfig {
+        uids: vec![
+            IdRange {
+                from: 1000,
+                to: 5000,
+                count: 1,
+            },
+            IdRange {
+                from: 0,
+                to: 100_000,
+                count: 65536,
+            },
+        ],
+        ..IdMapConfig::default()
+    };
+    assert_eq!(idmap.map(ctx(1000, 1000)), ctx(5000, 1000));
+    assert_eq!(idmap.map(ctx(1001, 7)), ctx(101_001, 7));
+    assert_eq!(idmap.map(ctx(70000, 0)), ctx(70000, 0));
+    assert_eq!(IdMapConfig::default().map(ctx(0, 0)), ctx(0, 0));
+
+    let root_squash = IdMapConfig {
+        squash: Squash::Root,
+        ..idmap.clone()
+    };
+    assert_eq!(root_squash.map(ctx(0, 0)), ctx(65534, 65534));
+    assert_eq!(root_squash.map(ctx(1000, 1000)), ctx(5000, 1000));
+    let all_squash = IdMapConfig {
+        squash: Squash::All,
+        anon_uid: 99,
+        anon_gid: 98,
+        ..idmap
+    };
+    assert_eq!(all_squash.map(ctx(1000, 1000)), ctx(99, 98));
+
+    let parsed: IdMapConfig = serde_json::from_str(
+        r#"{"uids": [{"from": 0, "to": 100000, "count": 65536}, {"from": 70000, "to": 1}],
+            "squash": "root"}"#,
+    )
+    .unwrap();
+    assert_eq!(parsed.uids[1].count, 1);
+    assert_eq!(parsed.squash, Squash::Root);
+    assert!(parsed.gids.is_empty());
+}
+
+#[tokio::test]
+async fn containers_share_a_namespace_through_their_maps() {
+    // Creating files owned by other users needs root
+    if !nix::unistd::geteuid().is_root() {
+        return;
+    }
+    let root = Root::new("containers");
+    let admin = root.client(0, 0, IdMapConfig::default());
+    admin.create_dir_all("shared").await.unwrap();
+    std::fs::set_permissions(
+        root.0.join("shared"),
+        std::fs::Permissions::from_mode(0o1777),
+    )
+    .unwrap();
+
+    // The superusers of two containers own their files as their host ids
+    let first = root.client(0, 0, container(100_000));
+    let second = root.client(0, 0, container(200_000));
+    first
+        .create("shared/first")
+        .await
+        .unwrap()
+        .close()
+        .await
+        .unwrap();
+    let attr = first.metadata("shared/first").await.unwrap();
+    assert_eq!((attr.uid, attr.gid), (100_000, 100_000));
+    second.create_dir_all("shared/second").await.unwrap();
+    let attr = second.metadata("shared/second").await.unwrap();
+    assert_eq!((attr.uid, attr.gid), (200_000, 200_000));
+
+    // Neither is the superuser of the host, nor of the other container
+    assert!(matches!(
+        second.remove("shared/first").await,
+        Err(DatenLordError::PermissionDenied { .. })
+    ));
+    assert!(matches!(
+        first.create_dir_all("private").await,
+        Err(DatenLordError::PermissionDenied { .. })
+    ));
+
+    // A squashed superuser acts as nobody
+    let squashed = root.client(
+        0,
+        0,
+        IdMapConfig {
+            squash: Squash::Root,
+            ..IdMapConfig::default()
+        },
+    );
+    squashed
+        .create("shared/anon")
+        .await
+        .unwrap()
+        .close()
+        .await
+        .unwrap();
+    let attr = squashed.metadata("shared/anon").await.unwrap();
+    assert_eq!((attr.uid, attr.gid), (65534, 65534));
+}
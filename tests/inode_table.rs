@@ -0,0 +1,154 @@
+//! Keeps the inode numbers of `LocalFS` stable across restarts and instances
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::storage::fs_util::{CreateParam, RenameParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::{INum, VirtualFs};
+use nix::sys::stat::SFlag;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir = format!("datenlord-inodes-{name}-{}", std::process::id());
+        let root = std::env::temp_dir().join(dir);
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    fn fs(&self) -> LocalFS {
+        LocalFS::new(&DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        })
+        .unwrap()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn ctx() -> RequestContext {
+    DatenLordConfig::default().request_context()
+}
+
+/// Create the file `name` under `parent`, returning its inode number
+async fn create(fs: &LocalFS, parent: INum, name: &str) -> INum {
+    let param = CreateParam {
+        parent,
+        name: name.into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    fs.mknod(&ctx(), param).await.unwrap().1.ino
+}
+
+async fn lookup(fs: &LocalFS, parent: INum, name: &str) -> INum {
+    fs.lookup(&ctx(), parent, OsStr::new(name))
+        .await
+        .unwrap()
+        .1
+        .ino
+}
+
+#[tokio::test]
+async fn inode_numbers_survive_restarts_and_renames() {
+    let root = Root::new("restart");
+    let (dir, file, gone) = {
+        let fs = root.fs();
+        let dir = fs
+            .mkdir_all(&ctx(), ROOT_ID, OsStr::new("dir"), 0o755)
+            .await
+            .unwrap()
+            .ino;
+        let file = create(&fs, dir, "file").await;
+        let gone = create(&fs, ROOT_ID, "gone").await;
+        fs.unlink(&ctx(), ROOT_ID, OsStr::new("gone"))
+            .await
+            .unwrap();
+        // Renaming the directory moves the file with it
+        let param = RenameParam {
+            old_parent: ROOT_ID,
+            old_name: "dir".into(),
+            new_parent: ROOT_ID,
+            new_name: "moved".into(),
+            flags: 0,
+        };
+        fs.rename(&ctx(), param).await.unwrap();
+        (dir, file, gone)
+    };
+
+    let fs = root.fs();
+    assert_eq!(fs.getattr(&ctx(), file).await.unwrap().1.ino, file);
+    assert_eq!(lookup(&fs, ROOT_ID, "moved").await, dir);
+    assert_eq!(lookup(&fs, dir, "file").await, file);
+    // The number of a removed file is neither resolved nor handed out again
+    assert!(fs.getattr(&ctx(), gone).await.is_err());
+    let again = create(&fs, ROOT_ID, "gone").await;
+    assert!(again > gone);
+}
+
+#[tokio::test]
+async fn files_replaced_behind_the_table_get_new_numbers() {
+    let root = Root::new("replaced");
+    let fs = root.fs();
+    let before = create(&fs, ROOT_ID, "file").await;
+    drop(fs);
+    // The old file is kept aside so its local inode is not reused
+    std::fs::rename(root.0.join("file"), root.0.join("old")).unwrap();
+    std::fs::write(root.0.join("file"), b"new").unwrap();
+
+    let fs = root.fs();
+    let after = lookup(&fs, ROOT_ID, "file").await;
+    assert_ne!(after, before);
+    assert_eq!(fs.getattr(&ctx(), after).await.unwrap().1.size, 3);
+}
+
+#[tokio::test]
+async fn instances_sharing_a_root_agree_on_numbers() {
+    let root = Root::new("shared");
+    let first = root.fs();
+    let second = root.fs();
+    let a = create(&first, ROOT_ID, "a").await;
+    let b = create(&second, ROOT_ID, "b").await;
+    assert_ne!(a, b);
+    // Each resolves the numbers the other allocated
+    assert_eq!(second.getattr(&ctx(), a).await.unwrap().1.ino, a);
+    assert_eq!(lookup(&first, ROOT_ID, "b").await, b);
+}
+
+#[tokio::test]
+async fn restarts_read_the_snapshot_and_the_changes_since() {
+    let root = Root::new("snapshot");
+    let (a, b) = {
+        let fs = root.fs();
+        let a = create(&fs, ROOT_ID, "a").await;
+        fs.sync_all(&ctx()).await.unwrap();
+        // Only in the log past the snapshot
+        let b = create(&fs, ROOT_ID, "b").await;
+        (a, b)
+    };
+    let snapshot = root.0.join(".datenlord_fs_info.inodes.snapshot");
+    assert!(snapshot.exists());
+
+    let fs = root.fs();
+    assert_eq!(fs.getattr(&ctx(), a).await.unwrap().1.ino, a);
+    assert_eq!(fs.getattr(&ctx(), b).await.unwrap().1.ino, b);
+    let c = create(&fs, ROOT_ID, "c").await;
+    assert!(c > b);
+    drop(fs);
+
+    // A corrupt snapshot is ignored for the log
+    std::fs::write(&snapshot, b"garbage").unwrap();
+    let fs = root.fs();
+    assert_eq!(lookup(&fs, ROOT_ID, "a").await, a);
+    assert_eq!(lookup(&fs, ROOT_ID, "c").await, c);
+}
@@ -0,0 +1,48 @@
+//! Interrupts operations through the table of `InterruptFs`
+use std::sync::Arc;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::{DatenLordError, DatenLordResult};
+use datenlord::storage::interrupt::InterruptFs;
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::VirtualFs;
+
+fn open(name: &str) -> (InterruptFs<LocalFS>, std::path::PathBuf) {
+    let root = std::env::temp_dir().join(format!("datenlord-interrupt-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let config = DatenLordConfig {
+        root: root.clone(),
+        ..DatenLordConfig::default()
+    };
+    (InterruptFs::new(LocalFS::new(&config).unwrap()), root)
+}
+
+#[tokio::test]
+async fn interrupted_operations_fail_with_interrupted() {
+    let (fs, root) = open("running");
+    let fs = Arc::new(fs);
+
+    let stuck = fs
+        .interruptible(7, std::future::pending::<DatenLordResult<()>>())
+        .unwrap();
+    assert!(matches!(
+        fs.interruptible(7, async { Ok(()) }),
+        Err(DatenLordError::InvalidArgument { .. })
+    ));
+    let task = tokio::spawn(stuck);
+    tokio::task::yield_now().await;
+    fs.interrupt(7).await;
+    assert!(matches!(task.await.unwrap(), Err(DatenLordError::Interrupted { .. })));
+    // The id is free again once the operation stopped
+    assert!(!fs.cancel(7));
+
+    // Operations can be interrupted before they are first polled
+    let pending = fs.interruptible(8, async { Ok(()) }).unwrap();
+    assert!(fs.cancel(8));
+    assert!(matches!(pending.await, Err(DatenLordError::Interrupted { .. })));
+
+    let done = fs.interruptible(9, async { Ok(42) }).unwrap();
+    assert_eq!(done.await.unwrap(), 42);
+    assert!(!fs.cancel(9));
+    let _ = std::fs::remove_dir_all(root);
+}
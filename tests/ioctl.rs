@@ -0,0 +1,148 @@
+//! Runs built-in and registered control commands through the layers
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::fs_util::{CreateParam, RequestContext, ROOT_ID};
+use datenlord::storage::ioctl::{
+    self, IoctlHandler, FIRST_CUSTOM_COMMAND, IOCTL_FILE_FLAGS, IOCTL_PLACEMENT,
+};
+use datenlord::storage::platform::{self, FILE_FLAG_IMMUTABLE};
+use datenlord::storage::meta::{MemoryMeta, MetaStore};
+use datenlord::storage::sharedfs::{SharedConfig, SharedFs};
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use opendal::{Operator, Scheme};
+
+fn ctx() -> RequestContext {
+    RequestContext {
+        uid: 0,
+        gid: 0,
+        pid: 1,
+        umask: 0o022,
+        root: ROOT_ID,
+    }
+}
+
+/// A root removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("datenlord-ioctl-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+
+    fn client(&self) -> Client {
+        Client::new(&DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        })
+        .unwrap()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn local_placement_is_the_path_under_the_root() {
+    let root = Root::new("local");
+    let client = root.client();
+    client.create("data").await.unwrap().close().await.unwrap();
+    let placement = client.ioctl("data", IOCTL_PLACEMENT, &[]).await.unwrap();
+    let expected = format!("{}\n", root.0.join("data").display());
+    assert_eq!(String::from_utf8(placement).unwrap(), expected);
+}
+
+#[tokio::test]
+async fn local_file_flags_are_those_of_the_platform() {
+    let root = Root::new("flags");
+    let client = root.client();
+    client.create("data").await.unwrap().close().await.unwrap();
+    let output = client.ioctl("data", IOCTL_FILE_FLAGS, &[]).await.unwrap();
+    let flags = u32::from_le_bytes(output.try_into().unwrap());
+    assert_eq!(flags, platform::file_flags(&root.0.join("data")).unwrap());
+    assert_eq!(flags & FILE_FLAG_IMMUTABLE, 0);
+}
+
+#[tokio::test]
+async fn registered_commands_reach_the_bottom_of_the_stack() {
+    const REVERSE: u32 = FIRST_CUSTOM_COMMAND + 1;
+    let root = Root::new("custom");
+    let client = root.client();
+    client.create("data").await.unwrap().close().await.unwrap();
+    let ino = client.metadata("data").await.unwrap().ino;
+
+    let handler: IoctlHandler = Arc::new(|_, ino, mut input| {
+        Box::pin(async move {
+            input.reverse();
+            input.extend_from_slice(&ino.to_le_bytes());
+            Ok(input)
+        })
+    });
+    ioctl::register(REVERSE, Arc::clone(&handler)).unwrap();
+    assert!(matches!(
+        ioctl::register(REVERSE, Arc::clone(&handler)),
+        Err(DatenLordError::AlreadyExists { .. })
+    ));
+    assert!(matches!(
+        ioctl::register(IOCTL_PLACEMENT, handler),
+        Err(DatenLordError::InvalidArgument { .. })
+    ));
+
+    let output = client.ioctl("data", REVERSE, b"abc").await.unwrap();
+    assert_eq!(output[..3], *b"cba");
+    assert_eq!(output[3..], ino.to_le_bytes());
+
+    assert!(ioctl::unregister(REVERSE));
+    assert!(matches!(
+        client.ioctl("data", REVERSE, b"abc").await,
+        Err(DatenLordError::Unimplemented { .. })
+    ));
+}
+
+#[tokio::test]
+async fn shared_placement_lists_the_blocks_stored() {
+    let config = SharedConfig {
+        data_scheme: "memory".to_owned(),
+        block_size: 4,
+        ..SharedConfig::default()
+    };
+    let meta: Arc<dyn MetaStore> = Arc::new(MemoryMeta::default());
+    let data = Operator::via_map(Scheme::Memory, HashMap::new()).unwrap();
+    let fs = SharedFs::with_stores(&config, meta, data).await.unwrap();
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: "f".into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let ino = fs.mknod(&ctx(), param).await.unwrap().1.ino;
+    let fh = fs
+        .open(&ctx(), ino, OFlag::O_RDWR.bits() as u32)
+        .await
+        .unwrap();
+    fs.write(&ctx(), ino, fh, 0, b"12345", 0).await.unwrap();
+    fs.write(&ctx(), ino, fh, 12, b"x", 0).await.unwrap();
+    fs.release(&ctx(), ino, fh, 0, 0, true).await.unwrap();
+
+    let placement = fs.ioctl(&ctx(), ino, IOCTL_PLACEMENT, &[]).await.unwrap();
+    assert_eq!(
+        String::from_utf8(placement).unwrap(),
+        format!("{ino}.0\n{ino}.1\n{ino}.3\n")
+    );
+    let flags = fs.ioctl(&ctx(), ino, IOCTL_FILE_FLAGS, &[]).await.unwrap();
+    assert_eq!(flags, 0_u32.to_le_bytes());
+}
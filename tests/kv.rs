@@ -0,0 +1,164 @@
+//! Stores small values under keys, packed into segment files
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::appendlog::LogSync;
+use datenlord::storage::fs_util::RequestContext;
+use datenlord::storage::kv::{KvOptions, KvStore, MAX_KEY_SIZE};
+use datenlord::storage::localfs::LocalFS;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("datenlord-kv-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    fn config(&self) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        }
+    }
+
+    /// The store in `store` of the root, with segments of `segment_bytes`
+    async fn open(&self, segment_bytes: u64) -> KvStore<LocalFS> {
+        let fs = Arc::new(LocalFS::new(&self.config()).unwrap());
+        let options = KvOptions {
+            segment_bytes,
+            sync: LogSync::None,
+        };
+        KvStore::open(fs, RequestContext::current(), Path::new("store"), options)
+            .await
+            .unwrap()
+    }
+
+    /// The names of the files of the store, sorted
+    fn segments(&self) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(self.0.join("store"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn values_are_put_got_deleted_and_scanned() {
+    let root = Root::new("basic");
+    let kv = root.open(1 << 20).await;
+    assert!(kv.is_empty().await);
+    kv.put("user/1", b"alice").await.unwrap();
+    kv.put("user/2", b"bob").await.unwrap();
+    kv.put("team/1", b"").await.unwrap();
+    kv.put("user/1", b"carol").await.unwrap();
+    assert_eq!(
+        kv.get("user/1").await.unwrap().as_deref(),
+        Some(&b"carol"[..])
+    );
+    assert_eq!(kv.get("team/1").await.unwrap().as_deref(), Some(&b""[..]));
+    assert_eq!(kv.get("missing").await.unwrap(), None);
+    assert_eq!(kv.len().await, 3);
+
+    assert!(kv.delete("user/2").await.unwrap());
+    assert!(!kv.delete("user/2").await.unwrap());
+    assert_eq!(kv.get("user/2").await.unwrap(), None);
+    kv.put("user/10", b"dave").await.unwrap();
+    let scanned = kv.scan("user/").await.unwrap();
+    assert_eq!(
+        scanned,
+        [
+            ("user/1".to_owned(), b"carol".to_vec()),
+            ("user/10".to_owned(), b"dave".to_vec())
+        ]
+    );
+    assert_eq!(kv.scan("").await.unwrap().len(), 3);
+    assert!(kv.scan("users").await.unwrap().is_empty());
+
+    assert!(kv.put("", b"value").await.is_err());
+    assert!(kv
+        .put(&"k".repeat(MAX_KEY_SIZE + 1), b"value")
+        .await
+        .is_err());
+    kv.close().await.unwrap();
+    assert_eq!(root.segments(), ["segment-00000001"]);
+}
+
+#[tokio::test]
+async fn values_are_packed_into_segments_and_reloaded() {
+    let root = Root::new("segments");
+    let kv = root.open(4096).await;
+    for i in 0..100 {
+        kv.put(&format!("blob/{i:03}"), &[i as u8; 200])
+            .await
+            .unwrap();
+    }
+    for i in 0..50 {
+        assert!(kv.delete(&format!("blob/{i:03}")).await.unwrap());
+    }
+    kv.put("blob/099", b"last").await.unwrap();
+    kv.close().await.unwrap();
+    let segments = root.segments();
+    assert!(segments.len() > 3, "{segments:?}");
+
+    // Reopening indexes the segments in order, later records winning
+    let kv = root.open(4096).await;
+    assert_eq!(kv.len().await, 50);
+    assert_eq!(kv.get("blob/010").await.unwrap(), None);
+    assert_eq!(kv.get("blob/060").await.unwrap(), Some(vec![60; 200]));
+    assert_eq!(
+        kv.get("blob/099").await.unwrap().as_deref(),
+        Some(&b"last"[..])
+    );
+    assert!(kv.garbage_bytes().await > 50 * 200);
+
+    let reclaimed = kv.compact().await.unwrap();
+    assert!(reclaimed > 50 * 200);
+    assert_eq!(kv.garbage_bytes().await, 0);
+    let compacted = root.segments();
+    assert!(compacted.len() < segments.len(), "{compacted:?}");
+    assert!(compacted.iter().all(|name| !segments.contains(name)));
+    kv.put("blob/100", b"after").await.unwrap();
+    kv.close().await.unwrap();
+
+    let kv = root.open(4096).await;
+    assert_eq!(kv.len().await, 51);
+    assert_eq!(kv.get("blob/060").await.unwrap(), Some(vec![60; 200]));
+    assert_eq!(
+        kv.get("blob/100").await.unwrap().as_deref(),
+        Some(&b"after"[..])
+    );
+    kv.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn client_stores_values_through_the_sdk_stack() {
+    let root = Root::new("client");
+    let client = Client::new(&root.config()).unwrap();
+    let kv = client
+        .open_kv("a/b/store", KvOptions::default())
+        .await
+        .unwrap();
+    kv.put("key", b"value").await.unwrap();
+    kv.sync().await.unwrap();
+    kv.close().await.unwrap();
+    let kv = client
+        .open_kv("a/b/store", KvOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(kv.get("key").await.unwrap().as_deref(), Some(&b"value"[..]));
+    kv.close().await.unwrap();
+    assert!(client.metadata("a/b/store/segment-00000001").await.is_ok());
+}
@@ -0,0 +1,150 @@
+//! Reports the block layout of the backends and follows it in copies
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use datenlord::common::buffer_pool::BufferPool;
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::fs_util::{CreateParam, RequestContext, ROOT_ID};
+use datenlord::storage::layout::BlockLayout;
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::striping::{StripedBackend, StripingConfig};
+use datenlord::storage::transfer::{self, CopyConfig};
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir = format!("datenlord-layout-{name}-{}", std::process::id());
+        let root = std::env::temp_dir().join(dir);
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    fn config(&self, layout: BlockLayout) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            layout,
+            ..DatenLordConfig::default()
+        }
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+        let _ = std::fs::remove_file(self.0.with_extension("local"));
+        for i in 0..3 {
+            let _ = std::fs::remove_dir_all(self.0.with_extension(i.to_string()));
+        }
+    }
+}
+
+/// 256 KiB blocks in 4 MiB stripes
+const NVME: BlockLayout = BlockLayout {
+    block_size: 256 << 10,
+    stripe_size: 4 << 20,
+    alignment: 4096,
+};
+
+#[test]
+fn layouts_must_nest() {
+    BlockLayout::default().validate().unwrap();
+    NVME.validate().unwrap();
+    let root = Root::new("invalid");
+    for layout in [
+        BlockLayout {
+            alignment: 3000,
+            ..NVME
+        },
+        BlockLayout {
+            block_size: 6000,
+            ..NVME
+        },
+        BlockLayout {
+            stripe_size: NVME.block_size * 3 / 2,
+            ..NVME
+        },
+        BlockLayout {
+            block_size: 8 << 30,
+            stripe_size: 8 << 30,
+            ..NVME
+        },
+    ] {
+        let err = Client::new(&root.config(layout)).unwrap_err();
+        assert!(
+            matches!(err, DatenLordError::InvalidArgument { .. }),
+            "{layout:?}: {err:?}"
+        );
+    }
+}
+
+#[test]
+fn backends_report_their_layout() {
+    let root = Root::new("report");
+    let config = root.config(NVME);
+    let local = LocalFS::new(&config).unwrap();
+    assert_eq!(local.block_layout(), NVME);
+
+    let striping = StripingConfig {
+        paths: (0..3)
+            .map(|i| root.0.with_extension(i.to_string()).display().to_string())
+            .collect(),
+        parity_shards: 1,
+        chunk_size: 64 << 10,
+    };
+    let striped = StripedBackend::new(local, &striping).unwrap();
+    let layout = striped.block_layout();
+    assert_eq!(layout.block_size, 64 << 10);
+    assert_eq!(layout.stripe_size, 2 * (64 << 10));
+    assert_eq!(layout.alignment, NVME.alignment);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn copies_follow_the_layout() {
+    let root = Root::new("copy");
+    let layout = BlockLayout {
+        block_size: 4096,
+        stripe_size: 16384,
+        alignment: 4096,
+    };
+    let fs = Arc::new(LocalFS::new(&root.config(layout)).unwrap());
+    let data: Vec<u8> = (0..100_000_u32).map(|i| (i % 251) as u8).collect();
+    let local = root.0.with_extension("local");
+    std::fs::write(&local, &data).unwrap();
+
+    let ctx = RequestContext::current();
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: "copy".into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let ino = fs.mknod(&ctx, param).await.unwrap().1.ino;
+    let fh = fs
+        .open(&ctx, ino, OFlag::O_WRONLY.bits() as u32)
+        .await
+        .unwrap();
+    let file = std::fs::File::open(&local).unwrap();
+    let pool = BufferPool::new();
+    let copied = transfer::copy_from_local(&fs, ctx, file, ino, fh, &CopyConfig::default(), &pool)
+        .await
+        .unwrap();
+    fs.release(&ctx, ino, fh, 0, 0, true).await.unwrap();
+    assert_eq!(copied, data.len() as u64);
+    assert_eq!(std::fs::read(root.0.join("copy")).unwrap(), data);
+}
+
+#[test]
+fn clients_report_the_layout_of_the_backend() {
+    let root = Root::new("client");
+    let client = Client::new(&root.config(NVME)).unwrap();
+    assert_eq!(client.block_layout(), NVME);
+}
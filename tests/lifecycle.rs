@@ -0,0 +1,177 @@
+//! Evaluates lifecycle rules over a local namespace
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::lifecycle::{
+    self, LifecycleAction, LifecycleConfig, LifecycleRule, STORAGE_CLASS_TAG,
+};
+use datenlord::sdk::rust::Client;
+use datenlord::storage::localfs::LocalFS;
+
+/// A fresh root and audit log, removed on drop
+///
+/// The attribute cache is off, rules act behind the back of the clients.
+struct Namespace {
+    config: DatenLordConfig,
+    audit_log: PathBuf,
+}
+
+impl Namespace {
+    fn new(name: &str) -> Self {
+        let base = format!("datenlord-lifecycle-{name}-{}", std::process::id());
+        let root = std::env::temp_dir().join(&base);
+        let audit_log = std::env::temp_dir().join(format!("{base}.audit"));
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_file(&audit_log);
+        let config = DatenLordConfig {
+            root,
+            attr_cache_capacity: 0,
+            ..DatenLordConfig::default()
+        };
+        Self { config, audit_log }
+    }
+
+    /// Make the file at `path` look last modified `days` ago
+    fn age(&self, path: &str, days: u64) {
+        let file = std::fs::File::options()
+            .write(true)
+            .open(self.config.root.join(path))
+            .unwrap();
+        let mtime = SystemTime::now() - Duration::from_secs(days * 24 * 3600);
+        file.set_modified(mtime).unwrap();
+    }
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.config.root);
+        let _ = std::fs::remove_file(&self.audit_log);
+    }
+}
+
+/// Delete `tmp/**` older than a week, archive files tagged `cold`
+fn rules() -> Vec<LifecycleRule> {
+    vec![
+        LifecycleRule {
+            name: "expire-tmp".to_owned(),
+            path: "tmp/**".to_owned(),
+            tags: None,
+            min_age_secs: Some(7 * 24 * 3600),
+            action: LifecycleAction::Delete,
+        },
+        LifecycleRule {
+            name: "archive-cold".to_owned(),
+            path: "**".to_owned(),
+            tags: Some("cold".to_owned()),
+            min_age_secs: None,
+            action: LifecycleAction::Archive {
+                class: "archive".to_owned(),
+            },
+        },
+    ]
+}
+
+/// The paths of the audit log at `path`, with their dry run flag
+fn audited(path: &Path) -> Vec<(String, bool)> {
+    let log = std::fs::read_to_string(path).unwrap_or_default();
+    log.lines()
+        .map(|line| {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            let path = record["path"].as_str().unwrap().to_owned();
+            (path, record["dry_run"].as_bool().unwrap())
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn rules_delete_and_archive_with_a_dry_run_first() {
+    let ns = Namespace::new("evaluate");
+    let client = Client::new(&ns.config).unwrap();
+    client.create_dir_all("tmp/build").await.unwrap();
+    client.create_dir_all("data").await.unwrap();
+    for path in ["tmp/build/old.o", "tmp/fresh.log", "data/old.csv", "data/table.parquet"] {
+        client.create(path).await.unwrap().close().await.unwrap();
+    }
+    ns.age("tmp/build/old.o", 8);
+    ns.age("data/old.csv", 30);
+    client.set_tag("data/old.csv", "cold", "").await.unwrap();
+
+    let fs = Arc::new(LocalFS::new(&ns.config).unwrap());
+    let ctx = ns.config.request_context();
+    let report = lifecycle::evaluate(Arc::clone(&fs), ctx, &rules(), true, Some(&ns.audit_log))
+        .await
+        .unwrap();
+    let planned: Vec<_> = report.records.iter().map(|record| record.path.as_str()).collect();
+    assert_eq!(planned, ["tmp/build/old.o", "data/old.csv"]);
+    assert!(client.exists("tmp/build/old.o").await);
+    assert!(!client.tags("data/old.csv").await.unwrap().contains_key(STORAGE_CLASS_TAG));
+
+    let report = lifecycle::evaluate(Arc::clone(&fs), ctx, &rules(), false, Some(&ns.audit_log))
+        .await
+        .unwrap();
+    assert_eq!(report.records.len(), 2);
+    assert!(report.records.iter().all(|record| record.error.is_none()));
+    assert!(!client.exists("tmp/build/old.o").await);
+    assert!(client.exists("tmp/fresh.log").await);
+    assert!(client.exists("tmp/build").await);
+    let tags = client.tags("data/old.csv").await.unwrap();
+    assert_eq!(tags.get(STORAGE_CLASS_TAG).map(String::as_str), Some("archive"));
+    assert_eq!(
+        audited(&ns.audit_log),
+        [
+            ("tmp/build/old.o".to_owned(), true),
+            ("data/old.csv".to_owned(), true),
+            ("tmp/build/old.o".to_owned(), false),
+            ("data/old.csv".to_owned(), false),
+        ]
+    );
+
+    // Archived files are left alone from then on
+    let report = lifecycle::evaluate(fs, ctx, &rules(), false, None).await.unwrap();
+    assert!(report.records.is_empty());
+}
+
+#[tokio::test]
+async fn clients_evaluate_the_rules_on_schedule() {
+    let ns = Namespace::new("schedule");
+    let setup = Client::new(&ns.config).unwrap();
+    setup.create_dir_all("tmp").await.unwrap();
+    setup.create("tmp/stale").await.unwrap().close().await.unwrap();
+    ns.age("tmp/stale", 10);
+
+    let client = Client::new(&DatenLordConfig {
+        lifecycle: LifecycleConfig {
+            rules: rules(),
+            interval_secs: 1,
+            dry_run: false,
+            audit_log: Some(ns.audit_log.clone()),
+        },
+        ..ns.config.clone()
+    })
+    .unwrap();
+    for _ in 0..50 {
+        if !client.exists("tmp/stale").await {
+            assert_eq!(audited(&ns.audit_log), [("tmp/stale".to_owned(), false)]);
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("the scheduled evaluation did not remove tmp/stale");
+}
+
+#[test]
+fn rules_parse_from_the_config() {
+    let config = DatenLordConfig::parse(
+        r#"{"lifecycle": {"rules": [
+            {"name": "expire", "path": "tmp/**", "min_age_secs": 604800,
+             "action": {"type": "delete"}},
+            {"name": "cold", "tags": "cold", "action": {"type": "archive", "class": "archive"}}
+        ], "dry_run": true}}"#,
+    );
+    assert_eq!(config.lifecycle.rules.len(), 2);
+    assert_eq!(config.lifecycle.rules[1].path, "**");
+    assert!(config.lifecycle.dry_run);
+    assert_eq!(config.lifecycle.interval_secs, 3600);
+}
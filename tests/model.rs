@@ -0,0 +1,479 @@
+//! Runs random operation sequences against `VirtualFs` implementations and
+//! a reference model of a POSIX namespace, checking they observe the same
+//!
+//! Every operation addresses its entry by its path from the root. After
+//! each one the outcome, and the data or names it returned, must match the
+//! model, and once the sequence is done the whole tree must too. The
+//! concurrent cases run one sequence per task, each in its own directory,
+//! with every task writing its own blocks of a shared file, so whatever
+//! the interleaving the outcome of each task and the final tree are known.
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordResult;
+use datenlord::sdk;
+use datenlord::storage::fs_util::{
+    CreateParam, RenameParam, RequestContext, SetAttrParam, ROOT_ID,
+};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use proptest::prelude::*;
+use tokio::runtime::Runtime;
+
+/// An operation on the entry at a path
+#[derive(Debug, Clone)]
+enum Op {
+    Mkdir(String),
+    Create(String),
+    Write(String, u64, Vec<u8>),
+    Truncate(String, u64),
+    Read(String),
+    Unlink(String),
+    Rmdir(String),
+    Rename(String, String),
+    List(String),
+}
+
+impl Op {
+    /// The operation with every path under `dir`
+    fn under(self, dir: &str) -> Self {
+        let at = |path: String| format!("{dir}/{path}");
+        match self {
+            Self::Mkdir(path) => Self::Mkdir(at(path)),
+            Self::Create(path) => Self::Create(at(path)),
+            Self::Write(path, offset, data) => Self::Write(at(path), offset, data),
+            Self::Truncate(path, size) => Self::Truncate(at(path), size),
+            Self::Read(path) => Self::Read(at(path)),
+            Self::Unlink(path) => Self::Unlink(at(path)),
+            Self::Rmdir(path) => Self::Rmdir(at(path)),
+            Self::Rename(from, to) => Self::Rename(at(from), at(to)),
+            Self::List(path) => Self::List(at(path)),
+        }
+    }
+}
+
+/// What an operation returned, `Err` when it failed
+type Outcome = Result<Option<Observed>, ()>;
+
+/// The data or names an operation returned
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Observed {
+    Data(Vec<u8>),
+    Names(Vec<String>),
+}
+
+/// An entry of the model
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Dir,
+    File(Vec<u8>),
+}
+
+/// The namespace as POSIX defines it, entries by path, the root implied
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Model(BTreeMap<String, Node>);
+
+/// The parent of `path`, empty for the root
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+impl Model {
+    fn is_dir(&self, path: &str) -> bool {
+        path.is_empty() || self.0.get(path) == Some(&Node::Dir)
+    }
+
+    /// The names of the children of the directory `path`, sorted
+    fn children(&self, path: &str) -> Vec<String> {
+        self.0
+            .keys()
+            .filter(|child| !child.is_empty() && parent(child) == path && *child != path)
+            .map(|child| child.rsplit('/').next().unwrap().to_owned())
+            .collect()
+    }
+
+    /// Apply `op`, returning what a POSIX namespace returns
+    fn apply(&mut self, op: &Op) -> Outcome {
+        match *op {
+            Op::Mkdir(ref path) | Op::Create(ref path) => {
+                if !self.is_dir(parent(path)) || self.0.contains_key(path) {
+                    return Err(());
+                }
+                let node = match *op {
+                    Op::Mkdir(_) => Node::Dir,
+                    _ => Node::File(Vec::new()),
+                };
+                self.0.insert(path.clone(), node);
+            }
+            Op::Write(ref path, offset, ref data) => {
+                let Some(&mut Node::File(ref mut content)) = self.0.get_mut(path) else {
+                    return Err(());
+                };
+                // Writing nothing past the end does not extend the file
+                if !data.is_empty() {
+                    let (start, end) = (offset as usize, offset as usize + data.len());
+                    if content.len() < end {
+                        content.resize(end, 0);
+                    }
+                    content[start..end].copy_from_slice(data);
+                }
+            }
+            Op::Truncate(ref path, size) => {
+                let Some(&mut Node::File(ref mut content)) = self.0.get_mut(path) else {
+                    return Err(());
+                };
+                content.resize(size as usize, 0);
+            }
+            Op::Read(ref path) => match self.0.get(path) {
+                Some(Node::File(content)) => return Ok(Some(Observed::Data(content.clone()))),
+                _ => return Err(()),
+            },
+            Op::Unlink(ref path) => {
+                if !matches!(self.0.get(path), Some(Node::File(_))) {
+                    return Err(());
+                }
+                self.0.remove(path);
+            }
+            Op::Rmdir(ref path) => {
+                if self.0.get(path) != Some(&Node::Dir) || !self.children(path).is_empty() {
+                    return Err(());
+                }
+                self.0.remove(path);
+            }
+            Op::Rename(ref from, ref to) => return self.rename(from, to),
+            Op::List(ref path) => {
+                if !self.is_dir(path) {
+                    return Err(());
+                }
+                return Ok(Some(Observed::Names(self.children(path))));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Rename `from` to `to` as `rename(2)` does
+    fn rename(&mut self, from: &str, to: &str) -> Outcome {
+        let Some(node) = self.0.get(from).cloned() else {
+            return Err(());
+        };
+        if !self.is_dir(parent(to)) || to.starts_with(&format!("{from}/")) {
+            return Err(());
+        }
+        if from == to {
+            return Ok(None);
+        }
+        match (&node, self.0.get(to)) {
+            (_, None) | (Node::File(_), Some(Node::File(_))) => {}
+            (Node::Dir, Some(Node::Dir)) if self.children(to).is_empty() => {}
+            _ => return Err(()),
+        }
+        self.0.remove(to);
+        let prefix = format!("{from}/");
+        let moved: Vec<String> = self
+            .0
+            .keys()
+            .filter(|path| *path == from || path.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for path in moved {
+            let node = self.0.remove(&path).unwrap();
+            self.0.insert(format!("{to}{}", &path[from.len()..]), node);
+        }
+        Ok(None)
+    }
+}
+
+/// The caller of every operation
+fn ctx() -> RequestContext {
+    DatenLordConfig::default().request_context()
+}
+
+fn create_param(path: &str, node_type: SFlag) -> CreateParam {
+    CreateParam {
+        parent: ROOT_ID,
+        name: path.into(),
+        mode: if node_type == SFlag::S_IFDIR { 0o755 } else { 0o644 },
+        rdev: 0,
+        node_type,
+        link: None,
+    }
+}
+
+/// The whole contents of the file `ino`
+async fn read_all<F: VirtualFs>(fs: &F, ino: u64) -> DatenLordResult<Vec<u8>> {
+    let ctx = ctx();
+    let fh = fs.open(&ctx, ino, OFlag::O_RDONLY.bits() as u32).await?;
+    let mut content = Vec::new();
+    let mut buf = vec![0; 4096];
+    let result = loop {
+        match fs.read(&ctx, ino, fh, content.len() as u64, 4096, &mut buf).await {
+            Ok(0) => break Ok(content),
+            Ok(read) => content.extend_from_slice(&buf[..read]),
+            Err(e) => break Err(e),
+        }
+    };
+    fs.release(&ctx, ino, fh, 0, 0, false).await?;
+    result
+}
+
+/// The sorted names of the entries of the directory `ino`
+async fn list<F: VirtualFs>(fs: &F, ino: u64) -> DatenLordResult<Vec<String>> {
+    let mut names: Vec<String> = fs
+        .readdir(&ctx(), ino, 0, 0)
+        .await?
+        .into_iter()
+        .map(|entry| entry.name.to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// The inode of `path`
+async fn ino<F: VirtualFs>(fs: &F, path: &str) -> DatenLordResult<u64> {
+    Ok(fs.lookup(&ctx(), ROOT_ID, OsStr::new(path)).await?.1.ino)
+}
+
+/// Run `op` on `fs`
+async fn run_op<F: VirtualFs>(fs: &F, op: &Op) -> DatenLordResult<Option<Observed>> {
+    let ctx = ctx();
+    match *op {
+        Op::Mkdir(ref path) => {
+            fs.mkdir(&ctx, create_param(path, SFlag::S_IFDIR)).await?;
+        }
+        Op::Create(ref path) => {
+            fs.mknod(&ctx, create_param(path, SFlag::S_IFREG)).await?;
+        }
+        Op::Write(ref path, offset, ref data) => {
+            let ino = ino(fs, path).await?;
+            let fh = fs.open(&ctx, ino, OFlag::O_WRONLY.bits() as u32).await?;
+            let result = fs.write(&ctx, ino, fh, offset as i64, data, 0).await;
+            fs.release(&ctx, ino, fh, 0, 0, true).await?;
+            result?;
+        }
+        Op::Truncate(ref path, size) => {
+            let param = SetAttrParam {
+                size: Some(size),
+                ..SetAttrParam::default()
+            };
+            fs.setattr(&ctx, ino(fs, path).await?, param).await?;
+        }
+        Op::Read(ref path) => {
+            let content = read_all(fs, ino(fs, path).await?).await?;
+            return Ok(Some(Observed::Data(content)));
+        }
+        Op::Unlink(ref path) => fs.unlink(&ctx, ROOT_ID, OsStr::new(path)).await?,
+        Op::Rmdir(ref path) => {
+            fs.rmdir(&ctx, ROOT_ID, OsStr::new(path)).await?;
+        }
+        Op::Rename(ref from, ref to) => {
+            let param = RenameParam {
+                old_parent: ROOT_ID,
+                old_name: from.clone().into(),
+                new_parent: ROOT_ID,
+                new_name: to.clone().into(),
+                flags: 0,
+            };
+            fs.rename(&ctx, param).await?;
+        }
+        Op::List(ref path) => {
+            let names = list(fs, ino(fs, path).await?).await?;
+            return Ok(Some(Observed::Names(names)));
+        }
+    }
+    Ok(None)
+}
+
+/// The tree of `fs` as a model, reading every file and directory
+async fn snapshot<F: VirtualFs>(fs: &F) -> Model {
+    let mut model = Model::default();
+    let mut pending = vec![(String::new(), ROOT_ID)];
+    while let Some((dir, dir_ino)) = pending.pop() {
+        for name in list(fs, dir_ino).await.unwrap() {
+            let path = if dir.is_empty() { name } else { format!("{dir}/{name}") };
+            let name = path.rsplit('/').next().unwrap();
+            let (_, attr, _) = fs.lookup(&ctx(), dir_ino, OsStr::new(name)).await.unwrap();
+            if attr.kind == SFlag::S_IFDIR {
+                model.0.insert(path.clone(), Node::Dir);
+                pending.push((path, attr.ino));
+            } else {
+                let content = read_all(fs, attr.ino).await.unwrap();
+                assert_eq!(attr.size, content.len() as u64, "size of {path}");
+                model.0.insert(path, Node::File(content));
+            }
+        }
+    }
+    model
+}
+
+/// The filesystems checked against the model
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    /// `LocalFS` alone
+    Local,
+    /// The middleware stack of the SDKs, caching attributes
+    Sdk,
+}
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "datenlord-model-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    fn config(&self) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        }
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Run every sequence of `tasks` concurrently on a fresh `target`, the
+/// sequence of task `i` under the directory `t{i}`, each also writing its
+/// `shared` blocks of the file `shared`, and check them against the model
+fn check(target: Target, tasks: Vec<Vec<Op>>, shared: &[u8]) -> Result<(), TestCaseError> {
+    let root = Root::new();
+    let runtime = Runtime::new().unwrap();
+    let config = root.config();
+    match target {
+        Target::Local => {
+            let fs = Arc::new(LocalFS::new(&config).unwrap());
+            check_on(&runtime, fs, tasks, shared)
+        }
+        Target::Sdk => {
+            let fs = Arc::new(sdk::open_fs(&config).unwrap());
+            check_on(&runtime, fs, tasks, shared)
+        }
+    }
+}
+
+fn check_on<F: VirtualFs + 'static>(
+    runtime: &Runtime,
+    fs: Arc<F>,
+    tasks: Vec<Vec<Op>>,
+    shared: &[u8],
+) -> Result<(), TestCaseError> {
+    let mut expected = Model::default();
+    let block = shared.len().div_ceil(tasks.len().max(1)).max(1);
+    runtime.block_on(run_op(&*fs, &Op::Create("shared".to_owned()))).unwrap();
+    expected.0.insert("shared".to_owned(), Node::File(shared.to_vec()));
+
+    let mut handles = Vec::new();
+    for (i, ops) in tasks.into_iter().enumerate() {
+        let dir = format!("t{i}");
+        runtime.block_on(run_op(&*fs, &Op::Mkdir(dir.clone()))).unwrap();
+        expected.0.insert(dir.clone(), Node::Dir);
+        let ops: Vec<Op> = ops.into_iter().map(|op| op.under(&dir)).collect();
+        let mut model = expected.clone();
+        let outcomes: Vec<Outcome> = ops.iter().map(|op| model.apply(op)).collect();
+        for (path, node) in model.0 {
+            expected.0.entry(path).or_insert(node);
+        }
+        let start = (i * block).min(shared.len());
+        let write = Op::Write(
+            "shared".to_owned(),
+            start as u64,
+            shared[start..(start + block).min(shared.len())].to_vec(),
+        );
+        let fs = Arc::clone(&fs);
+        handles.push(runtime.spawn(async move {
+            let mut observed = Vec::new();
+            for (n, op) in ops.iter().enumerate() {
+                if n == ops.len() / 2 {
+                    run_op(&*fs, &write).await.unwrap();
+                }
+                observed.push(run_op(&*fs, op).await.map_err(|_| ()));
+            }
+            if ops.is_empty() {
+                run_op(&*fs, &write).await.unwrap();
+            }
+            (ops, outcomes, observed)
+        }));
+    }
+    for handle in handles {
+        let (ops, outcomes, observed) = runtime.block_on(handle).unwrap();
+        for ((op, outcome), observed) in ops.iter().zip(outcomes).zip(observed) {
+            prop_assert_eq!(observed, outcome, "{:?}", op);
+        }
+    }
+    // A task may have removed its directory
+    let actual = runtime.block_on(snapshot(&*fs));
+    expected.0.retain(|path, _| {
+        let top = path.split('/').next().unwrap();
+        top == "shared" || actual.0.contains_key(top)
+    });
+    prop_assert_eq!(actual, expected);
+    Ok(())
+}
+
+/// Paths of one to three components among a few names, so operations
+/// collide often
+fn path() -> impl Strategy<Value = String> {
+    prop::collection::vec(prop::sample::select(vec!["a", "b", "c"]), 1..=3)
+        .prop_map(|names| names.join("/"))
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => path().prop_map(Op::Mkdir),
+        3 => path().prop_map(Op::Create),
+        3 => (path(), 0..64_u64, prop::collection::vec(any::<u8>(), 0..32))
+            .prop_map(|(path, offset, data)| Op::Write(path, offset, data)),
+        1 => (path(), 0..64_u64).prop_map(|(path, size)| Op::Truncate(path, size)),
+        2 => path().prop_map(Op::Read),
+        1 => path().prop_map(Op::Unlink),
+        1 => path().prop_map(Op::Rmdir),
+        2 => (path(), path()).prop_map(|(from, to)| Op::Rename(from, to)),
+        2 => path().prop_map(Op::List),
+    ]
+}
+
+proptest! {
+    // Persisting failures creates directories with libc's `mkdir`, which
+    // the library exports a symbol of its own for
+    #![proptest_config(ProptestConfig {
+        cases: 64,
+        failure_persistence: None,
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn localfs_matches_the_model(ops in prop::collection::vec(op(), 0..40)) {
+        check(Target::Local, vec![ops], &[])?;
+    }
+
+    #[test]
+    fn sdk_stack_matches_the_model(ops in prop::collection::vec(op(), 0..40)) {
+        check(Target::Sdk, vec![ops], &[])?;
+    }
+
+    #[test]
+    fn concurrent_sequences_match_the_model(
+        tasks in prop::collection::vec(prop::collection::vec(op(), 0..20), 1..4),
+        shared in prop::collection::vec(any::<u8>(), 0..256),
+    ) {
+        check(Target::Sdk, tasks.clone(), &shared)?;
+        check(Target::Local, tasks, &shared)?;
+    }
+}
@@ -0,0 +1,215 @@
+//! Several backends composed under one namespace
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::fs_util::{RenameParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::mount::{MountBackend, MountConfig, MountTable};
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+
+/// A fresh temporary directory named after `name`
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("datenlord-mount-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn local_fs(root: &Path) -> LocalFS {
+    let config = DatenLordConfig {
+        root: root.to_owned(),
+        ..DatenLordConfig::default()
+    };
+    LocalFS::new(&config).unwrap()
+}
+
+async fn write(client: &Client, path: &str, data: &[u8]) {
+    let file = client.create(path).await.unwrap();
+    file.write_at(data, 0).await.unwrap();
+    file.close().await.unwrap();
+}
+
+async fn read(client: &Client, path: &str) -> Vec<u8> {
+    let file = client.open(path, OFlag::O_RDONLY).await.unwrap();
+    let mut buf = vec![0; 4096];
+    let read = file.read_at(&mut buf, 0).await.unwrap();
+    file.close().await.unwrap();
+    buf.truncate(read);
+    buf
+}
+
+async fn names(client: &Client, path: &str) -> Vec<String> {
+    let mut names: Vec<_> = client
+        .read_dir(path)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name.to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+fn is_errno<T>(result: Result<T, DatenLordError>, errno: Errno) -> bool {
+    matches!(result, Err(ref e) if e.errno() == Some(errno))
+}
+
+#[tokio::test]
+async fn mounts_of_the_config_serve_their_paths() {
+    let (root, hot) = (temp_dir("config-root"), temp_dir("config-hot"));
+    let config = DatenLordConfig::parse(&format!(
+        r#"{{"root": {root:?}, "mounts": [
+            {{"path": "data/hot", "backend": {{"type": "local", "root": {hot:?}}}}},
+            {{"path": "archive", "backend": {{"type": "shared", "data_scheme": "memory"}}}}
+        ]}}"#
+    ));
+    assert_eq!(config.mounts.len(), 2);
+    let client = Client::new(&config).unwrap();
+
+    client.create_dir_all("data/hot/run").await.unwrap();
+    write(&client, "data/hot/run/out.bin", b"hot data").await;
+    write(&client, "archive/old.bin", b"archived").await;
+    write(&client, "data/cold.bin", b"root data").await;
+    assert_eq!(std::fs::read(hot.join("run/out.bin")).unwrap(), b"hot data");
+    assert!(!root.join("data/hot/run").exists());
+    assert_eq!(
+        std::fs::read(root.join("data/cold.bin")).unwrap(),
+        b"root data"
+    );
+    assert_eq!(read(&client, "archive/old.bin").await, b"archived");
+    assert_eq!(
+        read(&client, "data/hot/../hot/run/out.bin").await,
+        b"hot data"
+    );
+
+    assert_eq!(names(&client, "data").await, ["cold.bin", "hot"]);
+    let listed = client.read_dir("data").await.unwrap();
+    let hot_entry = listed.iter().find(|entry| entry.name == "hot").unwrap();
+    assert_eq!(
+        hot_entry.ino,
+        client.metadata("data/hot").await.unwrap().ino
+    );
+    assert_ne!(client.metadata("archive").await.unwrap().ino, ROOT_ID);
+
+    // Each mount reports the space of its own backend
+    assert!(client.statfs("").await.unwrap().blocks > 0);
+    assert_eq!(client.statfs("archive").await.unwrap().blocks, 0);
+    assert_eq!(client.statfs("archive/old.bin").await.unwrap().blocks, 0);
+
+    // Mount points and the directories holding them stay
+    assert!(is_errno(client.remove("archive").await, Errno::EBUSY));
+    assert!(is_errno(client.remove("data/hot").await, Errno::EBUSY));
+    drop(client);
+    let _ = std::fs::remove_dir_all(&root);
+    let _ = std::fs::remove_dir_all(&hot);
+}
+
+#[tokio::test]
+async fn renames_across_mounts_copy_then_remove() {
+    let (root, hot) = (temp_dir("rename-root"), temp_dir("rename-hot"));
+    let mounts: Vec<(PathBuf, Box<dyn VirtualFs>)> =
+        vec![(PathBuf::from("hot"), Box::new(local_fs(&hot)))];
+    let fs = MountTable::with_backends(local_fs(&root), mounts).unwrap();
+    let ctx = RequestContext::current();
+    let mkdir = |name: &str| datenlord::storage::fs_util::CreateParam {
+        parent: ROOT_ID,
+        name: name.into(),
+        mode: 0o750,
+        rdev: 0,
+        node_type: nix::sys::stat::SFlag::S_IFDIR,
+        link: None,
+    };
+    fs.mkdir(&ctx, mkdir("run")).await.unwrap();
+    fs.mkdir(&ctx, mkdir("run/logs")).await.unwrap();
+    std::fs::write(root.join("run/logs/0.log"), b"log").unwrap();
+    std::fs::write(root.join("run/model.bin"), vec![7; 100_000]).unwrap();
+    std::os::unix::fs::symlink("model.bin", root.join("run/latest")).unwrap();
+
+    let rename = |old: &str, new: &str| RenameParam {
+        old_parent: ROOT_ID,
+        old_name: old.into(),
+        new_parent: ROOT_ID,
+        new_name: new.into(),
+        flags: 0,
+    };
+    fs.rename(&ctx, rename("run", "hot/run")).await.unwrap();
+    assert!(!root.join("run").exists());
+    assert_eq!(std::fs::read(hot.join("run/logs/0.log")).unwrap(), b"log");
+    assert_eq!(
+        std::fs::read(hot.join("run/model.bin")).unwrap(),
+        vec![7; 100_000]
+    );
+    assert_eq!(
+        std::fs::read_link(hot.join("run/latest")).unwrap(),
+        PathBuf::from("model.bin")
+    );
+    let mode = std::os::unix::fs::PermissionsExt::mode(
+        &std::fs::metadata(hot.join("run")).unwrap().permissions(),
+    );
+    assert_eq!(mode & 0o777, 0o750);
+    // No temporary copy is left behind
+    assert!(std::fs::read_dir(&hot).unwrap().all(|entry| !entry
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .contains("moving")));
+
+    // Back again, over an existing file
+    std::fs::write(root.join("log"), b"old").unwrap();
+    fs.rename(&ctx, rename("hot/run/logs/0.log", "log"))
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(root.join("log")).unwrap(), b"log");
+    assert!(!hot.join("run/logs/0.log").exists());
+    let mut noreplace = rename("hot/run/model.bin", "log");
+    noreplace.flags = nix::fcntl::RenameFlags::RENAME_NOREPLACE.bits();
+    assert!(matches!(
+        fs.rename(&ctx, noreplace).await,
+        Err(DatenLordError::AlreadyExists { .. })
+    ));
+    assert!(hot.join("run/model.bin").exists());
+
+    assert!(is_errno(
+        fs.rename(&ctx, rename("hot", "elsewhere")).await,
+        Errno::EBUSY
+    ));
+    assert!(fs
+        .lookup(&ctx, ROOT_ID, OsStr::new("hot/run"))
+        .await
+        .is_ok());
+    let _ = std::fs::remove_dir_all(&root);
+    let _ = std::fs::remove_dir_all(&hot);
+}
+
+#[test]
+fn mount_points_must_not_nest() {
+    let root = temp_dir("nest-root");
+    let local = |path: &str| MountConfig {
+        path: path.to_owned(),
+        backend: MountBackend::Local {
+            root: root.join(path.replace('/', "-")),
+        },
+    };
+    for mounts in [
+        vec![local("a"), local("a/b")],
+        vec![local("a/b"), local("a")],
+        vec![local("a"), local("./a/")],
+        vec![local("")],
+        vec![local("../a")],
+    ] {
+        let config = DatenLordConfig {
+            mounts,
+            ..DatenLordConfig::default()
+        };
+        let result = MountTable::new(local_fs(&root), &config);
+        assert!(matches!(
+            result,
+            Err(DatenLordError::InvalidArgument { .. })
+        ));
+    }
+    let _ = std::fs::remove_dir_all(&root);
+}
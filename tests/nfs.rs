@@ -0,0 +1,427 @@
+//! Serves a local namespace over NFSv3 to a client speaking the protocol
+#![cfg(feature = "nfs")]
+use std::ffi::OsString;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::gateway::nfs::rpc;
+use datenlord::gateway::nfs::xdr::{XdrReader, XdrWriter};
+use datenlord::gateway::nfs::NfsServer;
+use datenlord::gateway::{NfsConfig, NfsExport, Squash};
+use datenlord::storage::fs_util::{CreateParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::sys::stat::SFlag;
+use tokio::net::{TcpListener, TcpStream};
+
+const CALL: u32 = 0;
+const REPLY: u32 = 1;
+const MSG_ACCEPTED: u32 = 0;
+const AUTH_NONE: u32 = 0;
+const AUTH_UNIX: u32 = 1;
+const MOUNT_PROGRAM: u32 = 100_005;
+const NFS_PROGRAM: u32 = 100_003;
+const MNT: u32 = 1;
+const EXPORT: u32 = 5;
+const GETATTR: u32 = 1;
+const LOOKUP: u32 = 3;
+const READ: u32 = 6;
+const WRITE: u32 = 7;
+const CREATE: u32 = 8;
+const REMOVE: u32 = 12;
+const RMDIR: u32 = 13;
+const RENAME: u32 = 14;
+const READDIR: u32 = 16;
+const READDIRPLUS: u32 = 17;
+const FILE_SYNC: u32 = 2;
+const GUARDED: u32 = 1;
+
+const NFS3_OK: u32 = 0;
+const NFS3ERR_NOENT: u32 = 2;
+const NFS3ERR_ACCES: u32 = 13;
+const NFS3ERR_EXIST: u32 = 17;
+const NFS3ERR_ROFS: u32 = 30;
+const NFS3ERR_BADHANDLE: u32 = 10001;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("datenlord-nfs-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    /// The local filesystem of the root with the directories `dirs` made in
+    /// it, the mode of each following its name
+    async fn open(&self, dirs: &[(&str, u32)]) -> Arc<LocalFS> {
+        let fs = LocalFS::new(&DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        })
+        .unwrap();
+        let ctx = RequestContext::current();
+        for &(name, mode) in dirs {
+            let param = CreateParam {
+                parent: ROOT_ID,
+                name: OsString::from(name),
+                mode,
+                rdev: 0,
+                node_type: SFlag::S_IFDIR,
+                link: None,
+            };
+            fs.mkdir(&ctx, param).await.unwrap();
+            // Free of the umask of the process
+            std::fs::set_permissions(self.0.join(name), std::fs::Permissions::from_mode(mode))
+                .unwrap();
+        }
+        Arc::new(fs)
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Serve `exports` of `fs` on a local port, returning a client of it
+async fn serve(fs: Arc<LocalFS>, exports: Vec<NfsExport>) -> Client {
+    let config = NfsConfig {
+        listen: "127.0.0.1:0".to_owned(),
+        exports,
+    };
+    let server = Arc::new(NfsServer::new(fs, &config).await.unwrap());
+    let listener = TcpListener::bind(&config.listen).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(server.serve(listener));
+    Client {
+        stream: TcpStream::connect(addr).await.unwrap(),
+        xid: 0,
+        uid: 0,
+    }
+}
+
+/// An export of `path` squashing as `squash`
+fn export(path: &str, squash: Squash) -> NfsExport {
+    NfsExport {
+        path: path.to_owned(),
+        squash,
+        ..NfsExport::default()
+    }
+}
+
+/// A client calling with the `AUTH_UNIX` credentials of `uid`
+struct Client {
+    stream: TcpStream,
+    xid: u32,
+    uid: u32,
+}
+
+impl Client {
+    /// The accept status and the results of calling `procedure`
+    async fn call(
+        &mut self,
+        program: u32,
+        version: u32,
+        procedure: u32,
+        args: XdrWriter,
+    ) -> (u32, Vec<u8>) {
+        self.xid += 1;
+        let mut cred = XdrWriter::new();
+        cred.u32(0)
+            .opaque(b"client")
+            .u32(self.uid)
+            .u32(self.uid)
+            .u32(0);
+        let mut call = XdrWriter::new();
+        call.u32(self.xid)
+            .u32(CALL)
+            .u32(2)
+            .u32(program)
+            .u32(version)
+            .u32(procedure)
+            .u32(AUTH_UNIX)
+            .opaque(&cred.into_inner())
+            .u32(AUTH_NONE)
+            .opaque(&[])
+            .append(args);
+        rpc::write_record(&mut self.stream, &call.into_inner())
+            .await
+            .unwrap();
+        let reply = rpc::read_record(&mut self.stream).await.unwrap().unwrap();
+        let mut reader = XdrReader::new(&reply);
+        assert_eq!(reader.u32().unwrap(), self.xid);
+        assert_eq!(reader.u32().unwrap(), REPLY);
+        assert_eq!(reader.u32().unwrap(), MSG_ACCEPTED);
+        reader.u32().unwrap();
+        reader.opaque(400).unwrap();
+        let stat = reader.u32().unwrap();
+        (stat, reader.remaining().to_vec())
+    }
+
+    /// The status and the rest of the results of the NFS `procedure`
+    async fn nfs(&mut self, procedure: u32, args: XdrWriter) -> (u32, Vec<u8>) {
+        let (stat, results) = self.call(NFS_PROGRAM, 3, procedure, args).await;
+        assert_eq!(stat, 0, "procedure {procedure} was not accepted");
+        let status = XdrReader::new(&results).u32().unwrap();
+        (status, results[4..].to_vec())
+    }
+
+    /// The root handle of the export `path`, `None` if it is not exported
+    async fn mount(&mut self, path: &str) -> Option<Vec<u8>> {
+        let mut args = XdrWriter::new();
+        args.opaque(path.as_bytes());
+        let (stat, results) = self.call(MOUNT_PROGRAM, 3, MNT, args).await;
+        assert_eq!(stat, 0);
+        let mut reader = XdrReader::new(&results);
+        (reader.u32().unwrap() == 0).then(|| reader.opaque(64).unwrap().to_vec())
+    }
+
+    /// The status of creating the regular file `name` in `dir` with `mode`,
+    /// and its handle and attributes on success
+    async fn create(
+        &mut self,
+        dir: &[u8],
+        name: &str,
+        mode: u32,
+    ) -> (u32, Option<(Vec<u8>, Attr)>) {
+        let mut args = XdrWriter::new();
+        args.opaque(dir).opaque(name.as_bytes()).u32(GUARDED);
+        // The mode and nothing else
+        args.bool(true)
+            .u32(mode)
+            .bool(false)
+            .bool(false)
+            .bool(false)
+            .u32(0)
+            .u32(0);
+        let (status, results) = self.nfs(CREATE, args).await;
+        if status != NFS3_OK {
+            return (status, None);
+        }
+        let mut reader = XdrReader::new(&results);
+        assert!(reader.bool().unwrap());
+        let handle = reader.opaque(64).unwrap().to_vec();
+        assert!(reader.bool().unwrap());
+        (status, Some((handle, Attr::decode(&mut reader))))
+    }
+
+    /// The status of looking up `name` in `dir`, and its handle on success
+    async fn lookup(&mut self, dir: &[u8], name: &str) -> (u32, Option<Vec<u8>>) {
+        let mut args = XdrWriter::new();
+        args.opaque(dir).opaque(name.as_bytes());
+        let (status, results) = self.nfs(LOOKUP, args).await;
+        let handle =
+            (status == NFS3_OK).then(|| XdrReader::new(&results).opaque(64).unwrap().to_vec());
+        (status, handle)
+    }
+
+    async fn getattr(&mut self, handle: &[u8]) -> (u32, Option<Attr>) {
+        let mut args = XdrWriter::new();
+        args.opaque(handle);
+        let (status, results) = self.nfs(GETATTR, args).await;
+        let attr = (status == NFS3_OK).then(|| Attr::decode(&mut XdrReader::new(&results)));
+        (status, attr)
+    }
+
+    /// The names listed in `dir`, by `READDIRPLUS` if `plus`
+    async fn list(&mut self, dir: &[u8], plus: bool) -> Vec<String> {
+        let mut args = XdrWriter::new();
+        args.opaque(dir).u64(0).fixed(&[0; 8]);
+        if plus {
+            args.u32(4096);
+        }
+        args.u32(8192);
+        let (status, results) = self
+            .nfs(if plus { READDIRPLUS } else { READDIR }, args)
+            .await;
+        assert_eq!(status, NFS3_OK);
+        let mut reader = XdrReader::new(&results);
+        assert!(reader.bool().unwrap());
+        Attr::decode(&mut reader);
+        reader.fixed(8).unwrap();
+        let mut names = Vec::new();
+        while reader.bool().unwrap() {
+            reader.u64().unwrap();
+            names.push(String::from_utf8(reader.opaque(255).unwrap().to_vec()).unwrap());
+            reader.u64().unwrap();
+            if plus {
+                assert!(reader.bool().unwrap());
+                Attr::decode(&mut reader);
+                assert!(reader.bool().unwrap());
+                reader.opaque(64).unwrap();
+            }
+        }
+        assert!(reader.bool().unwrap(), "the listing ended early");
+        names.sort();
+        names
+    }
+}
+
+/// The fields of a `fattr3` the tests check
+#[derive(Debug, PartialEq, Eq)]
+struct Attr {
+    mode: u32,
+    uid: u32,
+    size: u64,
+}
+
+impl Attr {
+    fn decode(reader: &mut XdrReader<'_>) -> Self {
+        reader.u32().unwrap();
+        let mode = reader.u32().unwrap();
+        reader.u32().unwrap();
+        let uid = reader.u32().unwrap();
+        reader.u32().unwrap();
+        let size = reader.u64().unwrap();
+        // used, rdev, fsid, fileid and the three times
+        reader.fixed(8 + 8 + 8 + 8 + 3 * 8).unwrap();
+        Self { mode, uid, size }
+    }
+}
+
+#[tokio::test]
+async fn files_round_trip_through_an_export() {
+    let root = Root::new("round-trip");
+    let fs = root.open(&[("data", 0o755)]).await;
+    let mut client = serve(fs, vec![export("data/", Squash::None)]).await;
+
+    let (stat, exports) = client
+        .call(MOUNT_PROGRAM, 3, EXPORT, XdrWriter::new())
+        .await;
+    assert_eq!(stat, 0);
+    let mut reader = XdrReader::new(&exports);
+    assert!(reader.bool().unwrap());
+    assert_eq!(reader.opaque(1024).unwrap(), b"/data");
+    assert!(client.mount("/nope").await.is_none());
+    let dir = client.mount("/data").await.unwrap();
+
+    let (status, created) = client.create(&dir, "f", 0o640).await;
+    assert_eq!(status, NFS3_OK);
+    let (file, attr) = created.unwrap();
+    assert_eq!(attr.mode, 0o640);
+    assert_eq!(client.create(&dir, "f", 0o640).await.0, NFS3ERR_EXIST);
+    assert_eq!(
+        client.lookup(&dir, "f").await,
+        (NFS3_OK, Some(file.clone()))
+    );
+
+    let mut args = XdrWriter::new();
+    args.opaque(&file)
+        .u64(0)
+        .u32(5)
+        .u32(FILE_SYNC)
+        .opaque(b"hello");
+    let (status, results) = client.nfs(WRITE, args).await;
+    assert_eq!(status, NFS3_OK);
+    let mut reader = XdrReader::new(&results);
+    // The attributes before and after the write
+    assert!(reader.bool().unwrap());
+    reader.fixed(8 + 8 + 8).unwrap();
+    assert!(reader.bool().unwrap());
+    assert_eq!(Attr::decode(&mut reader).size, 5);
+    assert_eq!(
+        (reader.u32().unwrap(), reader.u32().unwrap()),
+        (5, FILE_SYNC)
+    );
+
+    let mut args = XdrWriter::new();
+    args.opaque(&file).u64(1).u32(100);
+    let (status, results) = client.nfs(READ, args).await;
+    assert_eq!(status, NFS3_OK);
+    let mut reader = XdrReader::new(&results);
+    assert!(reader.bool().unwrap());
+    Attr::decode(&mut reader);
+    assert_eq!(reader.u32().unwrap(), 4);
+    assert!(reader.bool().unwrap());
+    assert_eq!(reader.opaque(100).unwrap(), b"ello");
+    assert_eq!(std::fs::read(root.0.join("data/f")).unwrap(), b"hello");
+
+    assert_eq!(client.list(&dir, false).await, ["f"]);
+    assert_eq!(client.list(&dir, true).await, ["f"]);
+
+    let mut args = XdrWriter::new();
+    args.opaque(&dir).opaque(b"f").opaque(&dir).opaque(b"g");
+    assert_eq!(client.nfs(RENAME, args).await.0, NFS3_OK);
+    assert_eq!(client.lookup(&dir, "f").await.0, NFS3ERR_NOENT);
+    let mut args = XdrWriter::new();
+    args.opaque(&dir).opaque(b"g");
+    assert_eq!(client.nfs(REMOVE, args).await.0, NFS3_OK);
+    assert!(client.list(&dir, true).await.is_empty());
+    let mut args = XdrWriter::new();
+    args.opaque(&dir).opaque(b"g");
+    assert_eq!(client.nfs(REMOVE, args).await.0, NFS3ERR_NOENT);
+}
+
+#[tokio::test]
+async fn read_only_exports_refuse_changes() {
+    let root = Root::new("read-only");
+    let fs = root.open(&[("data", 0o755)]).await;
+    let read_only = NfsExport {
+        read_only: true,
+        ..export("", Squash::None)
+    };
+    let mut client = serve(fs, vec![read_only]).await;
+    let dir = client.mount("/").await.unwrap();
+    assert_eq!(client.create(&dir, "f", 0o644).await.0, NFS3ERR_ROFS);
+    let (status, data) = client.lookup(&dir, "data").await;
+    assert_eq!(status, NFS3_OK);
+    let mut args = XdrWriter::new();
+    args.opaque(&dir).opaque(b"data");
+    assert_eq!(client.nfs(RMDIR, args).await.0, NFS3ERR_ROFS);
+    assert_eq!(
+        client.list(&data.unwrap(), false).await,
+        Vec::<String>::new()
+    );
+}
+
+#[tokio::test]
+async fn callers_are_squashed_as_configured() {
+    // Creating files owned by other users needs root
+    if !nix::unistd::geteuid().is_root() {
+        return;
+    }
+    let root = Root::new("squash");
+    let fs = root.open(&[("private", 0o755), ("public", 0o777)]).await;
+    let everyone = NfsExport {
+        anon_uid: 1234,
+        anon_gid: 1234,
+        ..export("public", Squash::All)
+    };
+    let mut client = serve(fs, vec![export("private", Squash::Root), everyone]).await;
+
+    // The superuser acts as `nobody`, who may not write to the directory
+    let private = client.mount("/private").await.unwrap();
+    assert_eq!(client.create(&private, "f", 0o644).await.0, NFS3ERR_ACCES);
+
+    let public = client.mount("/public").await.unwrap();
+    client.uid = 1000;
+    let (status, created) = client.create(&public, "f", 0o644).await;
+    assert_eq!(status, NFS3_OK);
+    assert_eq!(created.unwrap().1.uid, 1234);
+}
+
+#[tokio::test]
+async fn foreign_handles_and_programs_are_refused() {
+    let root = Root::new("refused");
+    let fs = root.open(&[]).await;
+    let mut client = serve(fs, vec![export("", Squash::None)]).await;
+    let dir = client.mount("/").await.unwrap();
+    assert_eq!(client.getattr(&dir).await.0, NFS3_OK);
+    assert_eq!(client.getattr(b"bogus").await, (NFS3ERR_BADHANDLE, None));
+    let mut other = dir.clone();
+    other[3] = 1;
+    assert_eq!(client.getattr(&other).await, (NFS3ERR_BADHANDLE, None));
+
+    // PROG_UNAVAIL, and PROG_MISMATCH with the version served
+    assert_eq!(client.call(100_000, 2, 0, XdrWriter::new()).await.0, 1);
+    let (stat, versions) = client.call(NFS_PROGRAM, 4, 0, XdrWriter::new()).await;
+    assert_eq!(stat, 2);
+    let mut reader = XdrReader::new(&versions);
+    assert_eq!((reader.u32().unwrap(), reader.u32().unwrap()), (3, 3));
+}
@@ -0,0 +1,280 @@
+//! Checks the events reported to notification sinks
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::fs_util::{CreateParam, RenameParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::notify::{Event, EventKind, NotifyFs, SinkConfig, Watch, EVENT_SCHEMA_VERSION};
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::sys::stat::SFlag;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UnixListener};
+
+/// A fresh root and a client reporting to `sinks`
+fn client(name: &str, sinks: Vec<SinkConfig>) -> (Client, PathBuf) {
+    let root = std::env::temp_dir().join(format!("datenlord-notify-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let config = DatenLordConfig {
+        root: root.clone(),
+        notify_sinks: sinks,
+        ..DatenLordConfig::default()
+    };
+    (Client::new(&config).unwrap(), root)
+}
+
+#[tokio::test]
+async fn unix_socket_sink_receives_every_change_in_order() {
+    let socket = std::env::temp_dir().join(format!("datenlord-notify-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).unwrap();
+    let (client, root) = client("unix", vec![SinkConfig::UnixSocket { path: socket.clone() }]);
+
+    client.create_dir_all("dir").await.unwrap();
+    let file = client.create("dir/a.txt").await.unwrap();
+    file.write_at(b"hello", 0).await.unwrap();
+    file.close().await.unwrap();
+    client.remove("dir/a.txt").await.unwrap();
+
+    let (stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut lines = BufReader::new(stream).lines();
+    let mut events = Vec::new();
+    while events.len() < 5 {
+        let line = tokio::time::timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        events.push(serde_json::from_str::<Event>(&line).unwrap());
+    }
+    let kinds: Vec<_> = events.iter().map(|event| event.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            EventKind::Mkdir,
+            EventKind::Create,
+            EventKind::Attrib,
+            EventKind::CloseWrite,
+            EventKind::Delete
+        ]
+    );
+    assert!(events.iter().all(|event| event.version == EVENT_SCHEMA_VERSION
+        && event.namespace == root.display().to_string()));
+    assert_eq!(events[1].name.as_deref(), Some("dir/a.txt"));
+    assert_eq!((events[3].ino, events[3].size), (events[1].ino, 5));
+    assert_eq!(events[4].ino, events[1].ino);
+
+    drop(client);
+    let _ = std::fs::remove_dir_all(&root);
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[tokio::test]
+async fn webhook_sink_posts_events() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hooks/datenlord", listener.local_addr().unwrap());
+    let (client, root) = client("webhook", vec![SinkConfig::Webhook { url }]);
+
+    client.create_dir_all("dir").await.unwrap();
+
+    let (mut stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut request = Vec::new();
+    let mut buf = [0; 4096];
+    while !String::from_utf8_lossy(&request).contains("\"kind\":\"mkdir\"") {
+        let read = stream.read(&mut buf).await.unwrap();
+        assert_ne!(read, 0, "request ended early: {}", String::from_utf8_lossy(&request));
+        request.extend_from_slice(&buf[..read]);
+    }
+    stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").await.unwrap();
+    let request = String::from_utf8(request).unwrap();
+    assert!(request.starts_with("POST /hooks/datenlord HTTP/1.1\r\n"));
+    let body = request.split("\r\n\r\n").nth(1).unwrap();
+    let event: Event = serde_json::from_str(body).unwrap();
+    assert_eq!((event.kind, event.name.as_deref()), (EventKind::Mkdir, Some("dir")));
+
+    drop(client);
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[tokio::test]
+async fn nats_sink_publishes_to_the_subject() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let subject = "datenlord.events".to_owned();
+    let (client, root) = client("nats", vec![SinkConfig::Nats { address, subject }]);
+
+    client.create_dir_all("dir").await.unwrap();
+
+    let (mut stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    stream.write_all(b"INFO {\"server_id\":\"test\"}\r\n").await.unwrap();
+    let mut lines = BufReader::new(stream).lines();
+    let connect = lines.next_line().await.unwrap().unwrap();
+    assert!(connect.starts_with("CONNECT {"));
+    let publish = lines.next_line().await.unwrap().unwrap();
+    let payload = lines.next_line().await.unwrap().unwrap();
+    assert_eq!(publish, format!("PUB datenlord.events {}", payload.len()));
+    let event: Event = serde_json::from_str(&payload).unwrap();
+    assert_eq!(event.kind, EventKind::Mkdir);
+
+    drop(client);
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+/// The next change reported to `watch`
+async fn next(watch: &mut Watch) -> Event {
+    tokio::time::timeout(Duration::from_secs(5), watch.next())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn watches_report_changes_under_their_path() {
+    let (client, root) = client("watch", Vec::new());
+    client.create_dir_all("dir/sub").await.unwrap();
+    assert!(client.watch("missing", false).await.is_err());
+    let mut shallow = client.watch("dir", false).await.unwrap();
+    let mut deep = client.watch("/dir/", true).await.unwrap();
+
+    client
+        .create("other.txt")
+        .await
+        .unwrap()
+        .close()
+        .await
+        .unwrap();
+    let file = client.create("dir/sub/a.txt").await.unwrap();
+    file.write_at(b"hello", 0).await.unwrap();
+    file.close().await.unwrap();
+    client
+        .create("dir/b.txt")
+        .await
+        .unwrap()
+        .close()
+        .await
+        .unwrap();
+    client.remove("dir/sub/a.txt").await.unwrap();
+
+    let mut changes = Vec::new();
+    for _ in 0..6 {
+        let event = next(&mut deep).await;
+        changes.push((event.kind, event.path.unwrap()));
+    }
+    assert_eq!(
+        changes,
+        [
+            (EventKind::Create, "dir/sub/a.txt".to_owned()),
+            (EventKind::Attrib, "dir/sub/a.txt".to_owned()),
+            (EventKind::CloseWrite, "dir/sub/a.txt".to_owned()),
+            (EventKind::Create, "dir/b.txt".to_owned()),
+            (EventKind::Attrib, "dir/b.txt".to_owned()),
+            (EventKind::Delete, "dir/sub/a.txt".to_owned()),
+        ]
+    );
+    // Without `recursive` only the children of the path are reported
+    let event = next(&mut shallow).await;
+    assert_eq!(
+        (event.kind, event.path.as_deref()),
+        (EventKind::Create, Some("dir/b.txt"))
+    );
+    assert_eq!(next(&mut shallow).await.kind, EventKind::Attrib);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), shallow.next())
+            .await
+            .is_err()
+    );
+
+    drop(client);
+    assert!(deep.next().await.is_none());
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[tokio::test]
+async fn watches_follow_renamed_directories() {
+    let root = std::env::temp_dir().join(format!("datenlord-notify-rename-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let config = DatenLordConfig {
+        root: root.clone(),
+        ..DatenLordConfig::default()
+    };
+    let fs = NotifyFs::new(LocalFS::new(&config).unwrap(), String::new(), Vec::new()).unwrap();
+    let ctx = RequestContext::current();
+    let mkdir = CreateParam {
+        parent: ROOT_ID,
+        name: "old".into(),
+        mode: 0o755,
+        rdev: 0,
+        node_type: SFlag::S_IFDIR,
+        link: None,
+    };
+    assert!(fs.watch(&ctx, Path::new("old"), true).await.is_err());
+    let mut watch = fs.watch(&ctx, Path::new(""), true).await.unwrap();
+    let (_, dir, _) = fs.mkdir(&ctx, mkdir).await.unwrap();
+    assert_eq!(next(&mut watch).await.kind, EventKind::Mkdir);
+    let rename = RenameParam {
+        old_parent: ROOT_ID,
+        old_name: "old".into(),
+        new_parent: ROOT_ID,
+        new_name: "new".into(),
+        flags: 0,
+    };
+    fs.rename(&ctx, rename).await.unwrap();
+    let event = next(&mut watch).await;
+    assert_eq!(
+        (event.kind, event.path.as_deref(), event.new_path.as_deref()),
+        (EventKind::Rename, Some("old"), Some("new"))
+    );
+
+    // Entries of the directory are reported under its new path
+    let mknod = CreateParam {
+        parent: dir.ino,
+        name: "a.txt".into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    fs.mknod(&ctx, mknod).await.unwrap();
+    let event = next(&mut watch).await;
+    assert_eq!(
+        (event.kind, event.path.as_deref()),
+        (EventKind::Create, Some("new/a.txt"))
+    );
+
+    drop(fs);
+    assert!(watch.next().await.is_none());
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn invalid_sinks_fail_the_client() {
+    for sink in [
+        SinkConfig::Webhook {
+            url: "https://example.com/hook".to_owned(),
+        },
+        SinkConfig::Nats {
+            address: "127.0.0.1:4222".to_owned(),
+            subject: "has space".to_owned(),
+        },
+    ] {
+        let root = std::env::temp_dir().join(format!("datenlord-notify-invalid-{}", std::process::id()));
+        let config = DatenLordConfig {
+            root: root.clone(),
+            notify_sinks: vec![sink],
+            ..DatenLordConfig::default()
+        };
+        assert!(Client::new(&config).is_err());
+        // Sinks are checked before the namespace is touched
+        assert!(!root.exists());
+    }
+}
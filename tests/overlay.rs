@@ -0,0 +1,246 @@
+//! Writable layers over read-only ones
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::storage::fs_util::{CreateParam, RenameParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::overlay::{OverlayFs, OPAQUE_MARKER};
+use datenlord::storage::virtualfs::{INum, VirtualFs};
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+/// A `LocalFS` over an empty temporary directory named after `name`
+fn local_fs(name: &str) -> (LocalFS, PathBuf) {
+    let root =
+        std::env::temp_dir().join(format!("datenlord-overlay-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let config = DatenLordConfig {
+        root: root.clone(),
+        ..DatenLordConfig::default()
+    };
+    (LocalFS::new(&config).unwrap(), root)
+}
+
+fn param(parent: INum, name: &str, node_type: SFlag) -> CreateParam {
+    CreateParam {
+        parent,
+        name: name.into(),
+        mode: if node_type == SFlag::S_IFDIR {
+            0o755
+        } else {
+            0o644
+        },
+        rdev: 0,
+        node_type,
+        link: None,
+    }
+}
+
+/// Create the file `path` holding `data`
+async fn write_file<F: VirtualFs>(fs: &F, ctx: &RequestContext, path: &str, data: &[u8]) {
+    let ino = fs
+        .mknod(ctx, param(ROOT_ID, path, SFlag::S_IFREG))
+        .await
+        .unwrap()
+        .1
+        .ino;
+    let fh = fs
+        .open(ctx, ino, OFlag::O_WRONLY.bits() as u32)
+        .await
+        .unwrap();
+    fs.write(ctx, ino, fh, 0, data, 0).await.unwrap();
+    fs.release(ctx, ino, fh, 0, 0, true).await.unwrap();
+}
+
+/// The contents of the file `path`
+async fn read_file<F: VirtualFs>(fs: &F, ctx: &RequestContext, path: &str) -> Vec<u8> {
+    let ino = fs
+        .lookup(ctx, ROOT_ID, OsStr::new(path))
+        .await
+        .unwrap()
+        .1
+        .ino;
+    let fh = fs
+        .open(ctx, ino, OFlag::O_RDONLY.bits() as u32)
+        .await
+        .unwrap();
+    let mut buf = vec![0; 4096];
+    let read = fs.read(ctx, ino, fh, 0, 4096, &mut buf).await.unwrap();
+    fs.release(ctx, ino, fh, 0, 0, false).await.unwrap();
+    buf.truncate(read);
+    buf
+}
+
+/// The names in the directory `path`, sorted
+async fn list<F: VirtualFs>(fs: &F, ctx: &RequestContext, path: &str) -> Vec<String> {
+    let ino = if path.is_empty() {
+        ROOT_ID
+    } else {
+        fs.lookup(ctx, ROOT_ID, OsStr::new(path))
+            .await
+            .unwrap()
+            .1
+            .ino
+    };
+    let fh = fs.opendir(ctx, ino, 0).await.unwrap();
+    let entries = fs.readdir(ctx, ino, fh, 0).await.unwrap();
+    fs.releasedir(ctx, ino, fh, 0).await.unwrap();
+    let mut names: Vec<_> = entries
+        .into_iter()
+        .map(|entry| entry.name.to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+fn is_errno(result: Result<impl std::fmt::Debug, DatenLordError>, errno: Errno) -> bool {
+    matches!(result, Err(ref e) if e.errno() == Some(errno))
+}
+
+/// An overlay of a scratch layer over a lower layer holding `data/a`,
+/// `data/b` and `base`, with the roots of both
+async fn overlay(name: &str) -> (OverlayFs<LocalFS>, PathBuf, PathBuf) {
+    let ctx = RequestContext::current();
+    let (lower, lower_root) = local_fs(&format!("{name}-lower"));
+    lower
+        .mkdir(&ctx, param(ROOT_ID, "data", SFlag::S_IFDIR))
+        .await
+        .unwrap();
+    write_file(&lower, &ctx, "data/a", b"lower a").await;
+    write_file(&lower, &ctx, "data/b", b"lower b").await;
+    write_file(&lower, &ctx, "base", b"base").await;
+    let (upper, upper_root) = local_fs(&format!("{name}-upper"));
+    (OverlayFs::new(upper, vec![lower]), lower_root, upper_root)
+}
+
+#[tokio::test]
+async fn writes_are_copied_up_leaving_the_lower_layer_unchanged() {
+    let (fs, lower_root, upper_root) = overlay("copy-up").await;
+    let ctx = RequestContext::current();
+    assert_eq!(list(&fs, &ctx, "").await, ["base", "data"]);
+    assert_eq!(list(&fs, &ctx, "data").await, ["a", "b"]);
+    assert_eq!(read_file(&fs, &ctx, "data/a").await, b"lower a");
+    // An empty path is the directory itself
+    let root = fs.lookup(&ctx, ROOT_ID, OsStr::new("")).await.unwrap().1;
+    assert_eq!((root.ino, root.kind), (ROOT_ID, SFlag::S_IFDIR));
+
+    let a = fs
+        .lookup(&ctx, ROOT_ID, OsStr::new("data/a"))
+        .await
+        .unwrap()
+        .1
+        .ino;
+    let fh = fs
+        .open(&ctx, a, OFlag::O_WRONLY.bits() as u32)
+        .await
+        .unwrap();
+    fs.write(&ctx, a, fh, 0, b"UPPER", 0).await.unwrap();
+    fs.release(&ctx, a, fh, 0, 0, true).await.unwrap();
+    // Same inode, new contents, the lower file untouched
+    assert_eq!(
+        fs.lookup(&ctx, ROOT_ID, OsStr::new("data/a"))
+            .await
+            .unwrap()
+            .1
+            .ino,
+        a
+    );
+    assert_eq!(read_file(&fs, &ctx, "data/a").await, b"UPPER a");
+    assert_eq!(
+        std::fs::read(lower_root.join("data/a")).unwrap(),
+        b"lower a"
+    );
+    assert_eq!(
+        std::fs::read(upper_root.join("data/a")).unwrap(),
+        b"UPPER a"
+    );
+    assert!(!upper_root.join("data/b").exists());
+
+    // New files land in the upper layer, merged with the lower ones
+    write_file(&fs, &ctx, "data/c", b"new").await;
+    assert_eq!(list(&fs, &ctx, "data").await, ["a", "b", "c"]);
+    assert!(!lower_root.join("data/c").exists());
+    let _ = std::fs::remove_dir_all(&lower_root);
+    let _ = std::fs::remove_dir_all(&upper_root);
+}
+
+#[tokio::test]
+async fn removals_leave_whiteouts_hiding_the_lower_layer() {
+    let (fs, lower_root, upper_root) = overlay("whiteout").await;
+    let ctx = RequestContext::current();
+    fs.unlink(&ctx, ROOT_ID, OsStr::new("data/a"))
+        .await
+        .unwrap();
+    assert_eq!(list(&fs, &ctx, "data").await, ["b"]);
+    assert!(matches!(
+        fs.lookup(&ctx, ROOT_ID, OsStr::new("data/a")).await,
+        Err(DatenLordError::NotFound { .. })
+    ));
+    assert!(lower_root.join("data/a").exists());
+    assert!(upper_root.join("data/.wh.a").exists());
+    // Whiteouts are not entries of the overlay
+    assert!(fs
+        .lookup(&ctx, ROOT_ID, OsStr::new("data/.wh.a"))
+        .await
+        .is_err());
+
+    // A file made in place of a removed one replaces its whiteout
+    write_file(&fs, &ctx, "data/a", b"again").await;
+    assert_eq!(read_file(&fs, &ctx, "data/a").await, b"again");
+    assert!(!upper_root.join("data/.wh.a").exists());
+
+    // A directory made in place of a removed one shows nothing below it
+    let rmdir = fs.rmdir(&ctx, ROOT_ID, OsStr::new("data")).await;
+    assert!(is_errno(rmdir, Errno::ENOTEMPTY));
+    for name in ["data/a", "data/b"] {
+        fs.unlink(&ctx, ROOT_ID, OsStr::new(name)).await.unwrap();
+    }
+    fs.rmdir(&ctx, ROOT_ID, OsStr::new("data")).await.unwrap();
+    assert_eq!(list(&fs, &ctx, "").await, ["base"]);
+    fs.mkdir(&ctx, param(ROOT_ID, "data", SFlag::S_IFDIR))
+        .await
+        .unwrap();
+    assert!(list(&fs, &ctx, "data").await.is_empty());
+    assert!(upper_root.join("data").join(OPAQUE_MARKER).exists());
+    assert!(lower_root.join("data/b").exists());
+    let _ = std::fs::remove_dir_all(&lower_root);
+    let _ = std::fs::remove_dir_all(&upper_root);
+}
+
+#[tokio::test]
+async fn renames_copy_files_up_and_refuse_lower_directories() {
+    let (fs, lower_root, upper_root) = overlay("rename").await;
+    let ctx = RequestContext::current();
+    let rename = |old: &str, new: &str| RenameParam {
+        old_parent: ROOT_ID,
+        old_name: old.into(),
+        new_parent: ROOT_ID,
+        new_name: new.into(),
+        flags: 0,
+    };
+    fs.rename(&ctx, rename("data/a", "moved")).await.unwrap();
+    assert_eq!(list(&fs, &ctx, "").await, ["base", "data", "moved"]);
+    assert_eq!(list(&fs, &ctx, "data").await, ["b"]);
+    assert_eq!(read_file(&fs, &ctx, "moved").await, b"lower a");
+    assert!(lower_root.join("data/a").exists());
+
+    // Onto a lower file, replacing it
+    fs.rename(&ctx, rename("moved", "base")).await.unwrap();
+    assert_eq!(read_file(&fs, &ctx, "base").await, b"lower a");
+    assert_eq!(list(&fs, &ctx, "").await, ["base", "data"]);
+
+    let result = fs.rename(&ctx, rename("data", "elsewhere")).await;
+    assert!(is_errno(result, Errno::EXDEV));
+    // Directories of the upper layer alone move
+    fs.mkdir(&ctx, param(ROOT_ID, "new", SFlag::S_IFDIR))
+        .await
+        .unwrap();
+    write_file(&fs, &ctx, "new/f", b"f").await;
+    fs.rename(&ctx, rename("new", "renamed")).await.unwrap();
+    assert_eq!(read_file(&fs, &ctx, "renamed/f").await, b"f");
+    let _ = std::fs::remove_dir_all(&lower_root);
+    let _ = std::fs::remove_dir_all(&upper_root);
+}
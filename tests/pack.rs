@@ -0,0 +1,183 @@
+//! Packs small files into segments in namespaces with the `packing` feature
+use std::path::PathBuf;
+use std::time::Duration;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::packing::{PackStats, PackingConfig};
+use datenlord::storage::superblock::Feature;
+use nix::fcntl::OFlag;
+
+/// A root removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("datenlord-pack-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+
+    fn config(&self, features: Vec<Feature>, segment_size: u64) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            features,
+            packing: PackingConfig {
+                max_file_size: 100,
+                segment_size,
+            },
+            ..DatenLordConfig::default()
+        }
+    }
+
+    fn client(&self, segment_size: u64) -> Client {
+        Client::new(&self.config(vec![Feature::Packing], segment_size)).unwrap()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+async fn write(client: &Client, path: &str, data: &[u8]) {
+    let file = client.create(path).await.unwrap();
+    file.write_at(data, 0).await.unwrap();
+    file.close().await.unwrap();
+}
+
+async fn read(client: &Client, path: &str) -> Vec<u8> {
+    let file = client.open(path, OFlag::O_RDONLY).await.unwrap();
+    let mut data = vec![0; file.metadata().await.unwrap().size as usize];
+    let n = file.read_at(&mut data, 0).await.unwrap();
+    data.truncate(n);
+    file.close().await.unwrap();
+    data
+}
+
+fn stats(client: &Client) -> PackStats {
+    client.pack_stats().unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn small_files_are_packed_until_they_grow() {
+    let root = Root::new("grow");
+    let client = root.client(1 << 20);
+    write(&client, "a", b"first file").await;
+    write(&client, "b", b"second file").await;
+    let file = client.open("b", OFlag::O_WRONLY).await.unwrap();
+    file.write_at(b"!!", 20).await.unwrap();
+    file.close().await.unwrap();
+    assert_eq!(read(&client, "a").await, b"first file");
+    assert_eq!(read(&client, "b").await, b"second file\0\0\0\0\0\0\0\0\0!!");
+    assert_eq!(client.metadata("b").await.unwrap().size, 22);
+    let packed = stats(&client);
+    assert_eq!(
+        (packed.files, packed.segments, packed.live_bytes),
+        (2, 1, 32)
+    );
+    // The root only keeps the sizes
+    assert!(std::fs::read(root.0.join("a"))
+        .unwrap()
+        .iter()
+        .all(|&b| b == 0));
+
+    drop(client);
+
+    let client = root.client(1 << 20);
+    assert_eq!(read(&client, "a").await, b"first file");
+    // Growing past the limit unpacks the file
+    let file = client.open("a", OFlag::O_WRONLY).await.unwrap();
+    file.write_at(&[7; 200], 10).await.unwrap();
+    file.close().await.unwrap();
+    let mut expected = b"first file".to_vec();
+    expected.extend_from_slice(&[7; 200]);
+    assert_eq!(read(&client, "a").await, expected);
+    assert_eq!(std::fs::read(root.0.join("a")).unwrap(), expected);
+    assert_eq!(stats(&client).files, 1);
+
+    client.remove("b").await.unwrap();
+    assert_eq!(stats(&client).files, 0);
+}
+
+#[tokio::test]
+async fn dead_segments_are_compacted() {
+    let root = Root::new("compact");
+    let client = root.client(64);
+    for i in 0..8 {
+        write(&client, &format!("f{i}"), &[i; 40]).await;
+    }
+    assert_eq!(stats(&client).segments, 4);
+    // Rewriting the even files leaves half of every sealed segment dead
+    for i in (0..8).step_by(2) {
+        write(&client, &format!("f{i}"), &[i + 100; 40]).await;
+    }
+    let packed = stats(&client);
+    assert_eq!((packed.files, packed.live_bytes), (8, 320));
+    assert!(packed.dead_bytes <= 2 * 40, "{packed:?}");
+
+    drop(client);
+
+    let client = root.client(64);
+    for i in 0..8 {
+        let expected = if i % 2 == 0 { i + 100 } else { i };
+        assert_eq!(read(&client, &format!("f{i}")).await, [expected; 40]);
+    }
+    for i in 0..8 {
+        client.remove(&format!("f{i}")).await.unwrap();
+    }
+    let packed = stats(&client);
+    assert_eq!(
+        (packed.files, packed.segments, packed.live_bytes),
+        (0, 1, 0)
+    );
+}
+
+#[tokio::test]
+async fn garbage_collection_drops_lost_packed_files() {
+    let root = Root::new("gc");
+    let client = root.client(1 << 20);
+    write(&client, "kept", b"kept").await;
+    write(&client, "lost", b"lost").await;
+    let lost = client.metadata("lost").await.unwrap().ino;
+    // Removed behind the SDK
+    std::fs::remove_file(root.0.join("lost")).unwrap();
+
+    let report = client.collect_garbage(Duration::ZERO, true).await.unwrap();
+    assert_eq!(report.orphans, [format!("packed inode {lost}")]);
+    assert_eq!(stats(&client).files, 2);
+    let report = client.collect_garbage(Duration::ZERO, false).await.unwrap();
+    assert_eq!((report.removed, report.bytes), (1, 4));
+    assert_eq!(stats(&client).files, 1);
+    assert_eq!(read(&client, "kept").await, b"kept");
+}
+
+#[tokio::test]
+async fn enabling_the_feature_keeps_existing_files() {
+    let root = Root::new("enable");
+    let client = Client::new(&root.config(Vec::new(), 1 << 20)).unwrap();
+    write(&client, "old", b"written before").await;
+    assert!(client.pack_stats().unwrap().is_none());
+    drop(client);
+
+    LocalFS::new(&root.config(Vec::new(), 1 << 20))
+        .unwrap()
+        .enable_feature(Feature::Packing)
+        .unwrap();
+    let client = Client::new(&root.config(Vec::new(), 1 << 20)).unwrap();
+    assert_eq!(read(&client, "old").await, b"written before");
+    assert_eq!(stats(&client).files, 0);
+
+    // Truncating the file packs it
+    let file = client
+        .open("old", OFlag::O_WRONLY | OFlag::O_TRUNC)
+        .await
+        .unwrap();
+    file.write_at(b"written after", 0).await.unwrap();
+    file.close().await.unwrap();
+    assert_eq!(read(&client, "old").await, b"written after");
+    assert_eq!(stats(&client).files, 1);
+}
@@ -0,0 +1,195 @@
+//! Replays captured audit logs against fresh local namespaces
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::replay::{self, ReplayOptions};
+use datenlord::sdk::rust::Client;
+use datenlord::storage::audit::{AuditConfig, AuditRecord};
+use datenlord::storage::fs_util::{RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+
+/// Fresh paths for a test, removed on drop
+struct Paths {
+    /// The root operations are captured on
+    source: PathBuf,
+    /// The root they are replayed on
+    target: PathBuf,
+    /// The audit log of the source
+    log: PathBuf,
+}
+
+impl Paths {
+    fn new(name: &str) -> Self {
+        let tmp = std::env::temp_dir();
+        let path = |suffix: &str| {
+            tmp.join(format!(
+                "datenlord-replay-{name}-{}{suffix}",
+                std::process::id()
+            ))
+        };
+        let paths = Self {
+            source: path("-source"),
+            target: path("-target"),
+            log: path(".log"),
+        };
+        paths.clean();
+        paths
+    }
+
+    fn clean(&self) {
+        let _ = std::fs::remove_dir_all(&self.source);
+        let _ = std::fs::remove_dir_all(&self.target);
+        let _ = std::fs::remove_file(&self.log);
+    }
+
+    /// The config of `root`, with the audit log on for the source
+    fn config(&self, root: &Path) -> DatenLordConfig {
+        let enabled = root == self.source;
+        DatenLordConfig {
+            root: root.to_owned(),
+            audit: AuditConfig {
+                enabled,
+                path: self.log.clone(),
+                ..AuditConfig::default()
+            },
+            ..DatenLordConfig::default()
+        }
+    }
+
+    /// Capture a small session on the source
+    async fn capture(&self) {
+        let client = Client::new(&self.config(&self.source)).unwrap();
+        client.create_dir_all("dir/sub").await.unwrap();
+        let file = client.create("dir/sub/a.txt").await.unwrap();
+        file.write_at(&[7; 100], 0).await.unwrap();
+        file.write_at(&[7; 50], 200).await.unwrap();
+        file.close().await.unwrap();
+        let file = client.create("b.txt").await.unwrap();
+        file.write_at(b"data", 0).await.unwrap();
+        file.close().await.unwrap();
+        client.remove("b.txt").await.unwrap();
+    }
+}
+
+impl Drop for Paths {
+    fn drop(&mut self) {
+        self.clean();
+    }
+}
+
+/// Replay as fast as possible
+const FAST: ReplayOptions = ReplayOptions {
+    speed: 0.0,
+    as_recorded: false,
+};
+
+#[tokio::test]
+async fn captured_sessions_are_reproduced() {
+    let paths = Paths::new("session");
+    paths.capture().await;
+    let records = replay::read_log(&paths.log).unwrap();
+    assert!(records.len() >= 8, "{records:?}");
+
+    let fs = LocalFS::new(&paths.config(&paths.target)).unwrap();
+    let report = replay::replay(&fs, RequestContext::current(), &records, &FAST)
+        .await
+        .unwrap();
+    assert_eq!(report.replayed, records.len() as u64);
+    assert_eq!(report.skipped, 0);
+    assert!(report.mismatches.is_empty(), "{:?}", report.mismatches);
+    assert!(report.percentile(50.0) <= report.percentile(100.0));
+    let data = std::fs::read(paths.target.join("dir/sub/a.txt")).unwrap();
+    assert_eq!(data.len(), 250);
+    assert!(!paths.target.join("b.txt").exists());
+
+    // Replaying again diverges, the entries already being there
+    let report = replay::replay(&fs, RequestContext::current(), &records, &FAST)
+        .await
+        .unwrap();
+    let mismatch = &report.mismatches[0];
+    assert_eq!((mismatch.index, mismatch.op.as_str()), (0, "mkdir"));
+    assert!(mismatch.recorded_ok);
+    assert!(mismatch.error.is_some());
+}
+
+/// A record of creating `name` in the root as `ino`, `ms` milliseconds in
+fn mkdir(name: &str, ino: u64, ms: i128) -> AuditRecord {
+    let line = format!(
+        r#"{{"timestamp_ns": {}, "uid": 0, "gid": 0, "pid": 1, "op": "mkdir",
+            "ino": {ino}, "parent": {ROOT_ID}, "name": "{name}", "mode": 493,
+            "ok": true}}"#,
+        ms * 1_000_000
+    );
+    serde_json::from_str(&line).unwrap()
+}
+
+#[tokio::test]
+async fn replays_keep_the_captured_pace() {
+    let paths = Paths::new("pace");
+    let fs = LocalFS::new(&paths.config(&paths.target)).unwrap();
+    let records = [mkdir("a", 10, 0), mkdir("b", 11, 200), mkdir("c", 12, 400)];
+    let options = ReplayOptions {
+        speed: 2.0,
+        ..ReplayOptions::default()
+    };
+    let start = Instant::now();
+    let report = replay::replay(&fs, RequestContext::current(), &records, &options)
+        .await
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(report.replayed, 3);
+    assert!(paths.target.join("c").is_dir());
+
+    // Operations on entries created before the capture are skipped
+    let write: AuditRecord = serde_json::from_str(
+        r#"{"timestamp_ns": 0, "uid": 0, "gid": 0, "pid": 1, "op": "write",
+            "ino": 99, "offset": 0, "len": 4, "ok": true}"#,
+    )
+    .unwrap();
+    let report = replay::replay(&fs, RequestContext::current(), &[write], &FAST)
+        .await
+        .unwrap();
+    assert_eq!((report.replayed, report.skipped), (0, 1));
+
+    let backwards = ReplayOptions {
+        speed: -1.0,
+        ..ReplayOptions::default()
+    };
+    assert!(
+        replay::replay(&fs, RequestContext::current(), &records, &backwards)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn replay_binary_reports_mismatches() {
+    let paths = Paths::new("binary");
+    paths.capture().await;
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_datenlord-replay"))
+            .arg("--backend")
+            .arg(format!("file://{}", paths.target.display()))
+            .args(["--speed", "0", "--bare"])
+            .arg(&paths.log)
+            .output()
+            .unwrap()
+    };
+    let output = run();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("0 skipped, 0 mismatched"), "{stdout}");
+    assert_eq!(
+        std::fs::read(paths.target.join("dir/sub/a.txt"))
+            .unwrap()
+            .len(),
+        250
+    );
+    let output = run();
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+
+    std::fs::write(&paths.log, "not json\n").unwrap();
+    assert_eq!(run().status.code(), Some(2));
+}
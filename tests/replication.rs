@@ -0,0 +1,197 @@
+//! Mirrors the namespace of the SDKs to secondary backends
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::replication::{ReplicationConfig, ReplicationStatus};
+
+/// Fresh directories for a primary and its secondaries, removed on drop
+struct Roots(Vec<PathBuf>);
+
+impl Roots {
+    fn new(name: &str, count: usize) -> Self {
+        Self(
+            (0..count)
+                .map(|i| {
+                    let root = std::env::temp_dir().join(format!(
+                        "datenlord-replication-{name}-{i}-{}",
+                        std::process::id()
+                    ));
+                    let _ = std::fs::remove_dir_all(&root);
+                    let _ = std::fs::remove_file(&root);
+                    root
+                })
+                .collect(),
+        )
+    }
+
+    fn client(&self, replication: ReplicationConfig) -> Client {
+        let config = DatenLordConfig {
+            root: self.0[0].clone(),
+            replication: ReplicationConfig {
+                secondaries: self.0[1..]
+                    .iter()
+                    .map(|root| root.display().to_string())
+                    .collect(),
+                ..replication
+            },
+            ..DatenLordConfig::default()
+        };
+        Client::new(&config).unwrap()
+    }
+}
+
+impl Drop for Roots {
+    fn drop(&mut self) {
+        for root in &self.0 {
+            let _ = std::fs::remove_dir_all(root);
+            let _ = std::fs::remove_file(root);
+        }
+    }
+}
+
+/// The files, directories and links below `root`, with the contents of
+/// files and the targets of links, leaving out the inode table
+fn tree(root: &Path) -> BTreeMap<PathBuf, String> {
+    let mut entries = BTreeMap::new();
+    let mut dirs = vec![root.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let relative = path.strip_prefix(root).unwrap().to_owned();
+            if relative.starts_with(".datenlord_fs_info.inodes") {
+                continue;
+            }
+            let metadata = std::fs::symlink_metadata(&path).unwrap();
+            let value = if metadata.is_dir() {
+                dirs.push(path);
+                "dir".to_owned()
+            } else if metadata.is_symlink() {
+                format!("-> {}", std::fs::read_link(&path).unwrap().display())
+            } else {
+                String::from_utf8_lossy(&std::fs::read(&path).unwrap()).into_owned()
+            };
+            entries.insert(relative, value);
+        }
+    }
+    entries
+}
+
+async fn synced(client: &Client) -> ReplicationStatus {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let status = client.replication_status().unwrap();
+        if status.synced() {
+            return status;
+        }
+        assert!(Instant::now() < deadline, "not synced: {status:?}");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+async fn write(client: &Client, path: &str, data: &[u8]) {
+    let file = client.create(path).await.unwrap();
+    file.write_at(data, 0).await.unwrap();
+    file.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn changes_reach_every_secondary() {
+    let roots = Roots::new("changes", 3);
+    // Left over in a secondary, removed by the first resync
+    nix::sys::stat::mkdirat(
+        None,
+        &roots.0[2],
+        nix::sys::stat::Mode::from_bits_truncate(0o755),
+    )
+    .unwrap();
+    std::fs::write(roots.0[2].join("stale"), b"old").unwrap();
+
+    let client = roots.client(ReplicationConfig::default());
+    client.create_dir_all("a/b").await.unwrap();
+    write(&client, "a/b/f", b"first").await;
+    write(&client, "a/g", b"second").await;
+    client.set_tag("a/g", "team", "data").await.unwrap();
+    client.remove("a/g").await.unwrap();
+    write(&client, "h", b"third").await;
+
+    let status = synced(&client).await;
+    let primary = tree(&roots.0[0]);
+    assert_eq!(
+        primary.get(Path::new("a/b/f")).map(String::as_str),
+        Some("first")
+    );
+    for (secondary, root) in status.secondaries.iter().zip(&roots.0[1..]) {
+        assert_eq!(&secondary.root, root);
+        assert!(secondary.resyncs >= 1 && secondary.replicated > 0);
+        assert_eq!(secondary.last_error, None);
+        assert_eq!(tree(root), primary);
+    }
+
+    // Tags travel with the files
+    write(&client, "tagged", b"x").await;
+    client.set_tag("tagged", "team", "ml").await.unwrap();
+    synced(&client).await;
+    for root in &roots.0[1..] {
+        let secondary = Client::new(&DatenLordConfig {
+            root: root.clone(),
+            ..DatenLordConfig::default()
+        })
+        .unwrap();
+        let tags = secondary.tags("tagged").await.unwrap();
+        assert_eq!(tags.get("team").map(String::as_str), Some("ml"));
+    }
+}
+
+#[tokio::test]
+async fn overflows_resync_the_secondaries() {
+    let roots = Roots::new("overflow", 2);
+    let client = roots.client(ReplicationConfig {
+        queue_capacity: 1,
+        ..ReplicationConfig::default()
+    });
+    synced(&client).await;
+    for i in 0..50 {
+        write(&client, &format!("f{i}"), format!("data {i}").as_bytes()).await;
+    }
+    let status = synced(&client).await;
+    assert!(status.overflows > 0, "{status:?}");
+    assert!(status.secondaries[0].resyncs >= 2, "{status:?}");
+    assert_eq!(tree(&roots.0[1]), tree(&roots.0[0]));
+}
+
+#[tokio::test]
+async fn unreachable_secondaries_catch_up() {
+    let roots = Roots::new("unreachable", 2);
+    // A file where the secondary should be
+    std::fs::write(&roots.0[1], b"in the way").unwrap();
+    let client = roots.client(ReplicationConfig {
+        retry_interval_ms: 50,
+        ..ReplicationConfig::default()
+    });
+    write(&client, "f", b"kept").await;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let failed = loop {
+        let status = client.replication_status().unwrap();
+        if status.secondaries[0].failures > 0 {
+            break status;
+        }
+        assert!(Instant::now() < deadline);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert!(!failed.synced());
+    assert!(failed.secondaries[0].last_error.is_some());
+
+    std::fs::remove_file(&roots.0[1]).unwrap();
+    nix::sys::stat::mkdirat(
+        None,
+        &roots.0[1],
+        nix::sys::stat::Mode::from_bits_truncate(0o755),
+    )
+    .unwrap();
+    let status = synced(&client).await;
+    assert_eq!(status.secondaries[0].last_error, None);
+    assert_eq!(tree(&roots.0[1]), tree(&roots.0[0]));
+}
@@ -0,0 +1,226 @@
+//! Keeps a search index current through the Rust client
+#![cfg(feature = "search")]
+use std::ffi::OsStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::fs_util::{FileKind, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::notify::{Event, EventKind, EventSink, EVENT_SCHEMA_VERSION};
+use datenlord::storage::search::{IndexSink, SearchIndex};
+use datenlord::storage::tags::TagFilter;
+use datenlord::storage::walk;
+use tokio::runtime::Handle;
+
+/// The config of a fresh root with an index next to it, both removed on drop
+///
+/// Directories are left to the crate to create, the `mkdir` symbol of the C
+/// SDK interposes the libc one `std::fs` uses.
+struct Namespace {
+    config: DatenLordConfig,
+}
+
+impl Namespace {
+    fn new(name: &str) -> Self {
+        let base = format!("datenlord-search-{name}-{}", std::process::id());
+        let root = std::env::temp_dir().join(&base);
+        let index = std::env::temp_dir().join(format!("{base}-index"));
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_dir_all(&index);
+        let config = DatenLordConfig {
+            root,
+            search_index: Some(index),
+            ..DatenLordConfig::default()
+        };
+        Self { config }
+    }
+}
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.config.root);
+        if let Some(ref index) = self.config.search_index {
+            let _ = std::fs::remove_dir_all(index);
+        }
+    }
+}
+
+/// The paths matching `query` once they are `expected`, events being applied in the background
+async fn eventually(index: &SearchIndex, query: &str, expected: &[&str]) {
+    let mut paths = Vec::new();
+    for _ in 0..100 {
+        paths = index.search(query, 10).unwrap().into_iter().map(|hit| hit.path).collect();
+        paths.sort();
+        if paths == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{query:?} found {paths:?} instead of {expected:?}");
+}
+
+#[tokio::test]
+async fn index_follows_namespace_changes() {
+    let ns = Namespace::new("events");
+    let client = Client::new(&ns.config).unwrap();
+    let index = SearchIndex::open(ns.config.search_index.as_ref().unwrap()).unwrap();
+
+    client.create_dir_all("projects/alpha").await.unwrap();
+    let file = client.create("projects/alpha/q3_report.pdf").await.unwrap();
+    file.write_at(&[0; 2048], 0).await.unwrap();
+    file.close().await.unwrap();
+    client.create("projects/alpha/notes.txt").await.unwrap().close().await.unwrap();
+
+    eventually(&index, "report", &["projects/alpha/q3_report.pdf"]).await;
+    eventually(&index, "kind:directory", &["projects", "projects/alpha"]).await;
+    eventually(&index, "kind:file size:>1000", &["projects/alpha/q3_report.pdf"]).await;
+    let hit = index.search("report", 1).unwrap().remove(0);
+    assert_eq!((hit.kind, hit.size), (Some(FileKind::RegularFile), 2048));
+
+    client.remove("projects/alpha/notes.txt").await.unwrap();
+    eventually(&index, "notes", &[]).await;
+    assert!(index.search("size:>", 10).is_err());
+}
+
+/// A rename event of `ino` from `from` to `to`, both in the root
+fn rename_event(ino: u64, from: &str, to: &str) -> String {
+    let event = Event {
+        version: EVENT_SCHEMA_VERSION,
+        namespace: String::new(),
+        kind: EventKind::Rename,
+        ino,
+        file_kind: Some(FileKind::Directory),
+        parent: Some(ROOT_ID),
+        name: Some(from.to_owned()),
+        new_parent: Some(ROOT_ID),
+        new_name: Some(to.to_owned()),
+        path: Some(from.to_owned()),
+        new_path: Some(to.to_owned()),
+        size: 0,
+        timestamp_ns: 0,
+        tags: None,
+    };
+    serde_json::to_string(&event).unwrap()
+}
+
+#[tokio::test]
+async fn renaming_a_directory_moves_the_files_below_it() {
+    let ns = Namespace::new("rename");
+    let client = Client::new(&DatenLordConfig {
+        search_index: None,
+        ..ns.config.clone()
+    })
+    .unwrap();
+    client.create_dir_all("projects/alpha/deep").await.unwrap();
+    client.create("projects/alpha/deep/plan.md").await.unwrap().close().await.unwrap();
+    client.create("projects/beta").await.unwrap().close().await.unwrap();
+    let alpha = client.metadata("projects/alpha").await.unwrap().ino;
+    let beta = client.metadata("projects/beta").await.unwrap().ino;
+
+    let localfs = Arc::new(LocalFS::new(&ns.config).unwrap());
+    let mut sink = IndexSink::open(ns.config.search_index.as_ref().unwrap()).unwrap();
+    let ctx = ns.config.request_context();
+    let concurrency = walk::DEFAULT_WALK_CONCURRENCY;
+    let walk = walk::walk(Arc::clone(&localfs), ctx, &Handle::current(), OsStr::new(""), concurrency);
+    sink.rebuild(&*localfs, &ctx, walk).await.unwrap();
+    // The replaced file drops out, the files below the directory follow it
+    sink.send(&rename_event(alpha, "/projects/alpha", "projects/beta")).await.unwrap();
+    sink.flush().await.unwrap();
+
+    let index = SearchIndex::open(ns.config.search_index.as_ref().unwrap()).unwrap();
+    eventually(&index, "plan", &["projects/beta/deep/plan.md"]).await;
+    eventually(&index, "path:projects/beta/deep", &["projects/beta/deep"]).await;
+    assert!(index.search("beta", 10).unwrap().iter().all(|hit| hit.ino != beta));
+}
+
+#[tokio::test]
+async fn reindex_covers_existing_files() {
+    let ns = Namespace::new("rebuild");
+    let client = Client::new(&DatenLordConfig {
+        search_index: None,
+        ..ns.config.clone()
+    })
+    .unwrap();
+    client.create_dir_all("old/data").await.unwrap();
+    client.create("old/data/archive.tar").await.unwrap().close().await.unwrap();
+
+    let localfs = Arc::new(LocalFS::new(&ns.config).unwrap());
+    let dir = ns.config.search_index.clone().unwrap();
+    let mut sink = IndexSink::open(&dir).unwrap();
+    let ctx = ns.config.request_context();
+    let concurrency = walk::DEFAULT_WALK_CONCURRENCY;
+    let walk = walk::walk(Arc::clone(&localfs), ctx, &Handle::current(), OsStr::new(""), concurrency);
+    assert!(sink.rebuild(&*localfs, &ctx, walk).await.unwrap() >= 3);
+    drop(sink);
+
+    let index = SearchIndex::open(&dir).unwrap();
+    eventually(&index, "archive", &["old/data/archive.tar"]).await;
+}
+
+/// The paths tagged as `filter` says once they are `expected`
+async fn eventually_tagged(index: &SearchIndex, filter: &str, expected: &[&str]) {
+    let filter: TagFilter = filter.parse().unwrap();
+    let mut paths = Vec::new();
+    for _ in 0..100 {
+        paths = index.find_by_tags(&filter, 10).unwrap().into_iter().map(|hit| hit.path).collect();
+        paths.sort();
+        if paths == expected {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{filter:?} found {paths:?} instead of {expected:?}");
+}
+
+#[tokio::test]
+async fn files_are_found_by_tags() {
+    let ns = Namespace::new("tags");
+    let client = Client::new(&ns.config).unwrap();
+    let index = SearchIndex::open(ns.config.search_index.as_ref().unwrap()).unwrap();
+
+    client.create_dir_all("lake").await.unwrap();
+    for name in ["lake/events.parquet", "lake/users.parquet", "lake/old.csv"] {
+        client.create(name).await.unwrap().close().await.unwrap();
+    }
+    client.set_tag("lake/events.parquet", "team", "data").await.unwrap();
+    client.set_tag("lake/users.parquet", "team", "growth").await.unwrap();
+    client.set_tag("lake/old.csv", "team", "data").await.unwrap();
+    client.set_tag("lake/old.csv", "cold", "").await.unwrap();
+
+    eventually_tagged(&index, "team=data", &["lake/events.parquet", "lake/old.csv"]).await;
+    eventually_tagged(&index, "team=data,cold", &["lake/old.csv"]).await;
+    eventually_tagged(&index, "team", &["lake/events.parquet", "lake/old.csv", "lake/users.parquet"])
+        .await;
+    let hit = index.find_by_tags(&"cold".parse().unwrap(), 1).unwrap().remove(0);
+    assert_eq!(hit.tags.get("team").map(String::as_str), Some("data"));
+
+    client.remove_tag("lake/old.csv", "cold").await.unwrap();
+    eventually_tagged(&index, "cold", &[]).await;
+    assert!("=data".parse::<TagFilter>().is_err());
+}
+
+#[tokio::test]
+async fn reindex_reads_tags() {
+    let ns = Namespace::new("retag");
+    let client = Client::new(&DatenLordConfig {
+        search_index: None,
+        ..ns.config.clone()
+    })
+    .unwrap();
+    client.create("archive.tar").await.unwrap().close().await.unwrap();
+    client.set_tag("archive.tar", "tier", "cold").await.unwrap();
+
+    let localfs = Arc::new(LocalFS::new(&ns.config).unwrap());
+    let dir = ns.config.search_index.clone().unwrap();
+    let mut sink = IndexSink::open(&dir).unwrap();
+    let ctx = ns.config.request_context();
+    let concurrency = walk::DEFAULT_WALK_CONCURRENCY;
+    let walk = walk::walk(Arc::clone(&localfs), ctx, &Handle::current(), OsStr::new(""), concurrency);
+    sink.rebuild(&*localfs, &ctx, walk).await.unwrap();
+    drop(sink);
+
+    let index = SearchIndex::open(&dir).unwrap();
+    eventually_tagged(&index, "tier=cold", &["archive.tar"]).await;
+}
@@ -0,0 +1,431 @@
+//! Serves a local namespace to SFTP sessions, in process and to OpenSSH
+#![cfg(feature = "sftp")]
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::gateway::sftp::wire::{self, Reader, Writer};
+use datenlord::gateway::sftp::SftpServer;
+use datenlord::gateway::SftpConfig;
+use datenlord::storage::fs_util::RequestContext;
+use datenlord::storage::localfs::LocalFS;
+use tokio::io::DuplexStream;
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_FSTAT: u8 = 8;
+const FXP_SETSTAT: u8 = 9;
+const FXP_MKDIR: u8 = 14;
+const FXP_REALPATH: u8 = 16;
+const FXP_STAT: u8 = 17;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+const FXF_READ: u32 = 0x1;
+const FXF_WRITE: u32 = 0x2;
+const FXF_CREAT: u32 = 0x8;
+const FXF_EXCL: u32 = 0x20;
+
+const ATTR_SIZE: u32 = 0x1;
+const ATTR_PERMISSIONS: u32 = 0x4;
+const ATTR_ACMODTIME: u32 = 0x8;
+
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+const FX_NO_SUCH_FILE: u32 = 2;
+const FX_PERMISSION_DENIED: u32 = 3;
+const FX_FAILURE: u32 = 4;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("datenlord-sftp-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    fn config(&self) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        }
+    }
+
+    /// A server of the directory of `config` on the root
+    async fn serve(&self, config: &SftpConfig) -> Arc<SftpServer<LocalFS>> {
+        let fs = Arc::new(LocalFS::new(&self.config()).unwrap());
+        Arc::new(SftpServer::new(fs, config).await.unwrap())
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The attributes of a reply the tests check
+#[derive(Debug)]
+struct Attrs {
+    size: u64,
+    permissions: u32,
+    mtime: u32,
+}
+
+impl Attrs {
+    fn decode(reader: &mut Reader<'_>) -> Self {
+        assert_eq!(
+            reader.u32().unwrap(),
+            0xf,
+            "all but extended attributes are sent"
+        );
+        let size = reader.u64().unwrap();
+        reader.u32().unwrap();
+        reader.u32().unwrap();
+        let permissions = reader.u32().unwrap();
+        reader.u32().unwrap();
+        let mtime = reader.u32().unwrap();
+        Self {
+            size,
+            permissions,
+            mtime,
+        }
+    }
+}
+
+/// A client of a session served in process
+struct Client {
+    stream: DuplexStream,
+    id: u32,
+}
+
+impl Client {
+    /// Start a session of `server` acting as the current process
+    async fn connect(server: &Arc<SftpServer<LocalFS>>) -> Self {
+        let (stream, served) = tokio::io::duplex(1 << 20);
+        let server = Arc::clone(server);
+        tokio::spawn(async move {
+            let (reader, writer) = tokio::io::split(served);
+            server
+                .serve(RequestContext::current(), reader, writer)
+                .await
+                .unwrap();
+        });
+        let mut client = Self { stream, id: 0 };
+        let mut init = Writer::new(FXP_INIT);
+        init.u32(3);
+        wire::write_packet(&mut client.stream, &init.into_inner())
+            .await
+            .unwrap();
+        let version = wire::read_packet(&mut client.stream)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut reader = Reader::new(&version);
+        assert_eq!(reader.u8().unwrap(), FXP_VERSION);
+        assert_eq!(reader.u32().unwrap(), 3);
+        client
+    }
+
+    /// The type and the body of the reply to the request of type `kind`
+    /// with the arguments `args` writes
+    async fn request(&mut self, kind: u8, args: impl FnOnce(&mut Writer)) -> (u8, Vec<u8>) {
+        self.id += 1;
+        let mut request = Writer::new(kind);
+        request.u32(self.id);
+        args(&mut request);
+        wire::write_packet(&mut self.stream, &request.into_inner())
+            .await
+            .unwrap();
+        let reply = wire::read_packet(&mut self.stream).await.unwrap().unwrap();
+        let mut reader = Reader::new(&reply);
+        let kind = reader.u8().unwrap();
+        assert_eq!(reader.u32().unwrap(), self.id);
+        (kind, reader.remaining().to_vec())
+    }
+
+    /// The status code of a request replied to with a status
+    async fn status(&mut self, kind: u8, args: impl FnOnce(&mut Writer)) -> u32 {
+        let (reply, body) = self.request(kind, args).await;
+        assert_eq!(reply, FXP_STATUS);
+        Reader::new(&body).u32().unwrap()
+    }
+
+    /// The handle of the file at `path` opened with `flags` and the
+    /// permissions of new files, or the status opening it failed with
+    async fn open(&mut self, path: &str, flags: u32, permissions: u32) -> Result<Vec<u8>, u32> {
+        let (reply, body) = self
+            .request(FXP_OPEN, |args| {
+                args.string(path.as_bytes())
+                    .u32(flags)
+                    .u32(ATTR_PERMISSIONS)
+                    .u32(permissions);
+            })
+            .await;
+        let mut reader = Reader::new(&body);
+        match reply {
+            FXP_HANDLE => Ok(reader.string().unwrap().to_vec()),
+            FXP_STATUS => Err(reader.u32().unwrap()),
+            _ => panic!("unexpected reply {reply} to OPEN"),
+        }
+    }
+
+    /// Write `data` at the start of the file at `path`, creating it
+    async fn put(&mut self, path: &str, data: &[u8]) {
+        let handle = self.open(path, FXF_WRITE | FXF_CREAT, 0o644).await.unwrap();
+        let status = self
+            .status(FXP_WRITE, |args| {
+                args.string(&handle).u64(0).string(data);
+            })
+            .await;
+        assert_eq!(status, FX_OK);
+        assert_eq!(
+            self.status(FXP_CLOSE, |args| {
+                args.string(&handle);
+            })
+            .await,
+            FX_OK
+        );
+    }
+
+    /// The whole content of the file at `path`
+    async fn get(&mut self, path: &str) -> Vec<u8> {
+        let handle = self.open(path, FXF_READ, 0).await.unwrap();
+        let mut content = Vec::new();
+        loop {
+            let offset = content.len() as u64;
+            let (reply, body) = self
+                .request(FXP_READ, |args| {
+                    args.string(&handle).u64(offset).u32(4);
+                })
+                .await;
+            let mut reader = Reader::new(&body);
+            if reply == FXP_STATUS {
+                assert_eq!(reader.u32().unwrap(), FX_EOF);
+                break;
+            }
+            assert_eq!(reply, FXP_DATA);
+            content.extend_from_slice(reader.string().unwrap());
+        }
+        assert_eq!(
+            self.status(FXP_CLOSE, |args| {
+                args.string(&handle);
+            })
+            .await,
+            FX_OK
+        );
+        content
+    }
+
+    /// The attributes of the entry at `path`, or the status failing
+    async fn stat(&mut self, path: &str) -> Result<Attrs, u32> {
+        let (reply, body) = self
+            .request(FXP_STAT, |args| {
+                args.string(path.as_bytes());
+            })
+            .await;
+        let mut reader = Reader::new(&body);
+        match reply {
+            FXP_ATTRS => Ok(Attrs::decode(&mut reader)),
+            _ => Err(reader.u32().unwrap()),
+        }
+    }
+}
+
+#[tokio::test]
+async fn sessions_share_the_server() {
+    let root = Root::new("sessions");
+    let server = root.serve(&SftpConfig::default()).await;
+    let (mut a, mut b) = tokio::join!(Client::connect(&server), Client::connect(&server));
+    tokio::join!(a.put("a.txt", b"written by a"), b.put("b.txt", b"by b"));
+    assert_eq!(b.get("a.txt").await, b"written by a");
+    assert_eq!(a.get("b.txt").await, b"by b");
+    assert_eq!(
+        a.open("a.txt", FXF_WRITE | FXF_CREAT | FXF_EXCL, 0o644)
+            .await,
+        Err(FX_FAILURE)
+    );
+    assert_eq!(a.open("missing", FXF_READ, 0).await, Err(FX_NO_SUCH_FILE));
+    // Sessions ending with open handles release them
+    a.open("a.txt", FXF_READ, 0).await.unwrap();
+    drop(a);
+    assert_eq!(b.get("a.txt").await, b"written by a");
+}
+
+#[tokio::test]
+async fn attributes_map_to_file_attributes() {
+    let root = Root::new("attrs");
+    let server = root.serve(&SftpConfig::default()).await;
+    let mut client = Client::connect(&server).await;
+    client.put("f", b"0123456789").await;
+    let handle = client.open("f", FXF_READ, 0).await.unwrap();
+    let (reply, body) = client
+        .request(FXP_FSTAT, |args| {
+            args.string(&handle);
+        })
+        .await;
+    assert_eq!(reply, FXP_ATTRS);
+    let attrs = Attrs::decode(&mut Reader::new(&body));
+    assert_eq!(attrs.size, 10);
+    // The type is part of the permissions, like `st_mode`
+    assert_eq!(attrs.permissions & 0o170_000, 0o100_000);
+
+    let status = client
+        .status(FXP_SETSTAT, |args| {
+            args.string(b"f")
+                .u32(ATTR_SIZE | ATTR_PERMISSIONS | ATTR_ACMODTIME)
+                .u64(4)
+                .u32(0o600)
+                .u32(1_000_000_000)
+                .u32(1_000_000_000);
+        })
+        .await;
+    assert_eq!(status, FX_OK);
+    let attrs = client.stat("f").await.unwrap();
+    assert_eq!((attrs.size, attrs.permissions & 0o7777), (4, 0o600));
+    assert_eq!(attrs.mtime, 1_000_000_000);
+    let local = std::fs::metadata(root.0.join("f")).unwrap();
+    assert_eq!(
+        local.modified().unwrap(),
+        UNIX_EPOCH + Duration::from_secs(1_000_000_000)
+    );
+    assert_eq!(client.get("f").await, b"0123");
+
+    let status = client
+        .status(FXP_MKDIR, |args| {
+            args.string(b"d").u32(ATTR_PERMISSIONS).u32(0o750);
+        })
+        .await;
+    assert_eq!(status, FX_OK);
+    let attrs = client.stat("d").await.unwrap();
+    assert_eq!(attrs.permissions, 0o040_750);
+}
+
+#[tokio::test]
+async fn paths_stay_under_the_served_directory() {
+    let root = Root::new("confined");
+    let server = root.serve(&SftpConfig::default()).await;
+    Client::connect(&server).await.put("outside", b"").await;
+    let mut client = Client::connect(&server).await;
+    let status = client
+        .status(FXP_MKDIR, |args| {
+            args.string(b"data").u32(0);
+        })
+        .await;
+    assert_eq!(status, FX_OK);
+
+    let server = root
+        .serve(&SftpConfig {
+            path: "data".to_owned(),
+            read_only: false,
+        })
+        .await;
+    let mut client = Client::connect(&server).await;
+    let (reply, body) = client
+        .request(FXP_REALPATH, |args| {
+            args.string(b"a/./../../../b");
+        })
+        .await;
+    assert_eq!(reply, FXP_NAME);
+    let mut reader = Reader::new(&body);
+    assert_eq!(reader.u32().unwrap(), 1);
+    assert_eq!(reader.string().unwrap(), b"/b");
+    assert_eq!(
+        client.stat("../outside").await.unwrap_err(),
+        FX_NO_SUCH_FILE
+    );
+    client.put("../../inside", b"here").await;
+    assert_eq!(std::fs::read(root.0.join("data/inside")).unwrap(), b"here");
+}
+
+#[tokio::test]
+async fn read_only_sessions_refuse_changes() {
+    let root = Root::new("read-only");
+    Client::connect(&root.serve(&SftpConfig::default()).await)
+        .await
+        .put("f", b"kept")
+        .await;
+    let server = root
+        .serve(&SftpConfig {
+            path: String::new(),
+            read_only: true,
+        })
+        .await;
+    let mut client = Client::connect(&server).await;
+    assert_eq!(client.get("f").await, b"kept");
+    assert_eq!(
+        client.open("f", FXF_WRITE, 0).await,
+        Err(FX_PERMISSION_DENIED)
+    );
+    assert_eq!(
+        client.open("g", FXF_CREAT | FXF_WRITE, 0o644).await,
+        Err(FX_PERMISSION_DENIED)
+    );
+    let status = client
+        .status(FXP_MKDIR, |args| {
+            args.string(b"d").u32(0);
+        })
+        .await;
+    assert_eq!(status, FX_PERMISSION_DENIED);
+}
+
+#[test]
+fn openssh_client_moves_files() {
+    // `sftp -D` runs the server in place of `sshd` running the subsystem
+    if Command::new("sftp").arg("-V").output().is_err() {
+        return;
+    }
+    let root = Root::new("openssh");
+    let dir = std::env::temp_dir().join(format!("datenlord-sftp-local-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let (config, batch) = (dir.with_extension("json"), dir.with_extension("batch"));
+    let (local, back) = (dir.with_extension("in"), dir.with_extension("out"));
+    std::fs::write(&config, format!("{{\"root\": {:?}}}", root.0)).unwrap();
+    std::fs::write(&local, b"hello over sftp\n").unwrap();
+    let commands = [
+        "mkdir data".to_owned(),
+        format!("put {} data/a.txt", local.display()),
+        "rename data/a.txt data/b.txt".to_owned(),
+        "chmod 600 data/b.txt".to_owned(),
+        "ls -l data".to_owned(),
+        format!("get data/b.txt {}", back.display()),
+    ];
+    std::fs::write(&batch, commands.join("\n")).unwrap();
+    let server = format!(
+        "{} --config @{}",
+        env!("CARGO_BIN_EXE_datenlord-sftp"),
+        config.display()
+    );
+    let output = Command::new("sftp")
+        .arg("-b")
+        .arg(&batch)
+        .arg("-D")
+        .arg(server)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    for path in [&config, &batch, &local, &back] {
+        let _ = std::fs::remove_file(path);
+    }
+    let content = std::fs::read(root.0.join("data/b.txt"));
+    assert!(
+        output.status.success(),
+        "{stdout}{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(content.unwrap(), b"hello over sftp\n");
+    assert!(stdout.contains("-rw-------"), "{stdout}");
+    assert!(stdout.contains(" b.txt"), "{stdout}");
+}
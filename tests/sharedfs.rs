@@ -0,0 +1,461 @@
+//! Shares a namespace between instances through a metadata store and an
+//! object store
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use datenlord::common::{DatenLordError, DatenLordResult};
+use datenlord::storage::fs_util::{
+    CreateParam, FileAttr, RenameParam, RequestContext, SetAttrParam, ROOT_ID,
+};
+use datenlord::storage::meta::{MemoryMeta, MetaConfig, MetaStore};
+use datenlord::storage::sharedfs::{SharedConfig, SharedFs};
+use datenlord::storage::virtualfs::{INum, VirtualFs};
+use nix::errno::Errno;
+use nix::fcntl::{OFlag, RenameFlags};
+use nix::sys::stat::SFlag;
+use opendal::{Operator, Scheme};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// Keeps the data in memory in blocks of 4 bytes
+fn config(meta: MetaConfig) -> SharedConfig {
+    SharedConfig {
+        meta,
+        data_scheme: "memory".to_owned(),
+        block_size: 4,
+        ..SharedConfig::default()
+    }
+}
+
+/// An object store in memory, shared by its clones
+fn memory() -> Operator {
+    Operator::via_map(Scheme::Memory, HashMap::new()).unwrap()
+}
+
+/// The objects holding the data of `ino`
+async fn blocks(data: &Operator, ino: INum) -> usize {
+    let prefix = format!("{ino}.");
+    data.list("/")
+        .await
+        .unwrap()
+        .iter()
+        .filter(|entry| entry.name().starts_with(&prefix))
+        .count()
+}
+
+fn ctx() -> RequestContext {
+    RequestContext {
+        uid: 0,
+        gid: 0,
+        pid: 1,
+        umask: 0o022,
+        root: ROOT_ID,
+    }
+}
+
+fn file(name: &str) -> CreateParam {
+    CreateParam {
+        parent: ROOT_ID,
+        name: name.into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    }
+}
+
+fn rename(from: &str, to: &str, flags: RenameFlags) -> RenameParam {
+    RenameParam {
+        old_parent: ROOT_ID,
+        old_name: from.into(),
+        new_parent: ROOT_ID,
+        new_name: to.into(),
+        flags: flags.bits(),
+    }
+}
+
+async fn ino(fs: &SharedFs, name: &str) -> DatenLordResult<INum> {
+    Ok(fs.lookup(&ctx(), ROOT_ID, OsStr::new(name)).await?.1.ino)
+}
+
+async fn write(fs: &SharedFs, name: &str, offset: i64, data: &[u8]) {
+    let ino = ino(fs, name).await.unwrap();
+    let fh = fs
+        .open(&ctx(), ino, OFlag::O_WRONLY.bits() as u32)
+        .await
+        .unwrap();
+    fs.write(&ctx(), ino, fh, offset, data, 0).await.unwrap();
+    fs.release(&ctx(), ino, fh, 0, 0, false).await.unwrap();
+}
+
+async fn read(fs: &SharedFs, name: &str, offset: u64) -> Vec<u8> {
+    let ino = ino(fs, name).await.unwrap();
+    let fh = fs
+        .open(&ctx(), ino, OFlag::O_RDONLY.bits() as u32)
+        .await
+        .unwrap();
+    let mut buf = vec![0; 64];
+    let read = fs
+        .read(&ctx(), ino, fh, offset, 64, &mut buf)
+        .await
+        .unwrap();
+    fs.release(&ctx(), ino, fh, 0, 0, false).await.unwrap();
+    buf.truncate(read);
+    buf
+}
+
+#[tokio::test]
+async fn instances_share_the_tree_and_the_data() {
+    let (meta, data): (Arc<dyn MetaStore>, _) = (Arc::new(MemoryMeta::default()), memory());
+    let config = config(MetaConfig::Memory);
+    let first = SharedFs::with_stores(&config, Arc::clone(&meta), data.clone())
+        .await
+        .unwrap();
+    let second = SharedFs::with_stores(&config, meta, data.clone())
+        .await
+        .unwrap();
+
+    first
+        .mkdir_all(&ctx(), ROOT_ID, OsStr::new("dir/sub"), 0o755)
+        .await
+        .unwrap();
+    first.mknod(&ctx(), file("dir/file")).await.unwrap();
+    write(&first, "dir/file", 0, b"hello world!").await;
+    // Writes spanning blocks, past the end and into a hole
+    write(&second, "dir/file", 10, b"?!").await;
+    write(&second, "dir/file", 16, b"end").await;
+    assert_eq!(
+        read(&first, "dir/file", 0).await,
+        b"hello worl?!\0\0\0\0end"
+    );
+    assert_eq!(read(&second, "dir/file", 6).await, b"worl?!\0\0\0\0end");
+    let file_ino = ino(&first, "dir/file").await.unwrap();
+    assert_eq!(ino(&second, "dir/file").await.unwrap(), file_ino);
+    // The hole takes no block
+    assert_eq!(blocks(&data, file_ino).await, 4);
+
+    // A rename by one instance shows in the other
+    second
+        .rename(&ctx(), rename("dir", "moved", RenameFlags::empty()))
+        .await
+        .unwrap();
+    assert_eq!(ino(&first, "moved/file").await.unwrap(), file_ino);
+    assert!(ino(&first, "dir").await.is_err());
+    let param = RenameParam {
+        new_parent: ino(&first, "moved/sub").await.unwrap(),
+        ..rename("moved", "inside", RenameFlags::empty())
+    };
+    assert!(matches!(
+        first.rename(&ctx(), param).await,
+        Err(DatenLordError::InvalidArgument { .. })
+    ));
+    assert!(first
+        .rmdir(&ctx(), ROOT_ID, OsStr::new("moved"))
+        .await
+        .is_err());
+    let names: Vec<_> = second
+        .readdir(&ctx(), ino(&second, "moved").await.unwrap(), 0, 0)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    assert_eq!(names, ["file", "sub"]);
+
+    // Truncating and removing drop the blocks
+    let param = SetAttrParam {
+        size: Some(3),
+        ..SetAttrParam::default()
+    };
+    second.setattr(&ctx(), file_ino, param).await.unwrap();
+    assert_eq!(read(&first, "moved/file", 0).await, b"hel");
+    assert_eq!(blocks(&data, file_ino).await, 1);
+    first
+        .unlink(&ctx(), ROOT_ID, OsStr::new("moved/file"))
+        .await
+        .unwrap();
+    assert_eq!(blocks(&data, file_ino).await, 0);
+    assert!(second.getattr(&ctx(), file_ino).await.is_err());
+    first
+        .rmdir(&ctx(), ROOT_ID, OsStr::new("moved/sub"))
+        .await
+        .unwrap();
+    first
+        .rmdir(&ctx(), ROOT_ID, OsStr::new("moved"))
+        .await
+        .unwrap();
+    assert!(second
+        .readdir(&ctx(), ROOT_ID, 0, 0)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn one_of_concurrent_creates_wins() {
+    let (meta, data): (Arc<dyn MetaStore>, _) = (Arc::new(MemoryMeta::default()), memory());
+    let config = config(MetaConfig::Memory);
+    let mut tasks = Vec::new();
+    for _ in 0..8 {
+        let fs = SharedFs::with_stores(&config, Arc::clone(&meta), data.clone())
+            .await
+            .unwrap();
+        tasks.push(tokio::spawn(async move {
+            fs.mknod(&ctx(), file("same")).await.map(|_| ())
+        }));
+    }
+    let mut created = 0;
+    for task in tasks {
+        match task.await.unwrap() {
+            Ok(()) => created += 1,
+            Err(DatenLordError::AlreadyExists { .. }) => {}
+            Err(e) => panic!("{e}"),
+        }
+    }
+    assert_eq!(created, 1);
+}
+
+#[tokio::test]
+async fn opens_follow_their_flags() {
+    async fn truncate(fs: &SharedFs, ino: INum) -> FileAttr {
+        let flags = (OFlag::O_WRONLY | OFlag::O_TRUNC).bits() as u32;
+        let fh = fs.open(&ctx(), ino, flags).await.unwrap();
+        fs.release(&ctx(), ino, fh, flags, 0, false).await.unwrap();
+        fs.getattr(&ctx(), ino).await.unwrap().1
+    }
+    let fs = SharedFs::new(&config(MetaConfig::Memory)).await.unwrap();
+    fs.mknod(&ctx(), file("opened")).await.unwrap();
+    let opened = ino(&fs, "opened").await.unwrap();
+
+    // Truncating updates the times even of an empty file
+    let (_, created) = fs.getattr(&ctx(), opened).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let truncated = truncate(&fs, opened).await;
+    assert!(truncated.mtime > created.mtime);
+    assert!(truncated.ctime > created.ctime);
+    write(&fs, "opened", 0, b"some data").await;
+    assert_eq!(truncate(&fs, opened).await.size, 0);
+    assert!(read(&fs, "opened", 0).await.is_empty());
+
+    let target = Path::new("opened");
+    fs.symlink(&ctx(), ROOT_ID, OsStr::new("opened-link"), target)
+        .await
+        .unwrap();
+    let link = ino(&fs, "opened-link").await.unwrap();
+    let flags = (OFlag::O_RDONLY | OFlag::O_NOFOLLOW).bits() as u32;
+    let result = fs.open(&ctx(), link, flags).await;
+    assert!(matches!(result, Err(ref e) if e.errno() == Some(Errno::ELOOP)));
+}
+
+#[tokio::test]
+async fn renames_follow_their_flags() {
+    let fs = SharedFs::new(&config(MetaConfig::Memory)).await.unwrap();
+    fs.mknod(&ctx(), file("a")).await.unwrap();
+    fs.mknod(&ctx(), file("b")).await.unwrap();
+    let (a, b) = (ino(&fs, "a").await.unwrap(), ino(&fs, "b").await.unwrap());
+    assert!(matches!(
+        fs.rename(&ctx(), rename("a", "b", RenameFlags::RENAME_NOREPLACE))
+            .await,
+        Err(DatenLordError::AlreadyExists { .. })
+    ));
+    assert!(matches!(
+        fs.rename(&ctx(), rename("c", "d", RenameFlags::empty())).await,
+        Err(DatenLordError::NotFound { .. })
+    ));
+    fs.rename(&ctx(), rename("a", "b", RenameFlags::RENAME_EXCHANGE))
+        .await
+        .unwrap();
+    assert_eq!(
+        (ino(&fs, "a").await.unwrap(), ino(&fs, "b").await.unwrap()),
+        (b, a)
+    );
+    // Replacing drops the former target
+    fs.rename(&ctx(), rename("a", "b", RenameFlags::empty()))
+        .await
+        .unwrap();
+    assert_eq!(ino(&fs, "b").await.unwrap(), b);
+    assert!(fs.getattr(&ctx(), a).await.is_err());
+}
+
+#[tokio::test]
+async fn locks_expire_with_their_lease() {
+    let meta = MemoryMeta::default();
+    let lease = Duration::from_millis(50);
+    assert!(meta.try_lock("dir:1", "dead", lease).await.unwrap());
+    assert!(!meta.try_lock("dir:1", "alive", lease).await.unwrap());
+    tokio::time::sleep(lease * 2).await;
+    assert!(meta.try_lock("dir:1", "alive", lease).await.unwrap());
+    // A late unlock by the former owner leaves the new one alone
+    meta.unlock("dir:1", "dead").await.unwrap();
+    assert!(!meta.try_lock("dir:1", "other", lease).await.unwrap());
+    meta.unlock("dir:1", "alive").await.unwrap();
+    assert!(meta.try_lock("dir:1", "other", lease).await.unwrap());
+}
+
+/// The values of a fake redis server
+#[derive(Debug, Default)]
+struct Redis {
+    strings: HashMap<Vec<u8>, Vec<u8>>,
+    hashes: HashMap<Vec<u8>, HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+fn bulk(value: Option<&Vec<u8>>) -> Vec<u8> {
+    match value {
+        Some(value) => {
+            let mut reply = format!("${}\r\n", value.len()).into_bytes();
+            reply.extend_from_slice(value);
+            reply.extend_from_slice(b"\r\n");
+            reply
+        }
+        None => b"$-1\r\n".to_vec(),
+    }
+}
+
+impl Redis {
+    /// Run the commands `RedisMeta` sends, `EVAL` being its unlock script
+    fn run(&mut self, args: &[Vec<u8>]) -> Vec<u8> {
+        let command = String::from_utf8_lossy(&args[0]).to_uppercase();
+        match (command.as_str(), &args[1..]) {
+            ("INCR", [key]) => {
+                let value = self.strings.entry(key.clone()).or_insert(b"0".to_vec());
+                let next = String::from_utf8_lossy(value).parse::<i64>().unwrap() + 1;
+                *value = next.to_string().into_bytes();
+                format!(":{next}\r\n").into_bytes()
+            }
+            ("GET", [key]) => bulk(self.strings.get(key)),
+            ("SET", [key, value, options @ ..]) => {
+                if options.iter().any(|option| option == b"NX") && self.strings.contains_key(key) {
+                    return bulk(None);
+                }
+                self.strings.insert(key.clone(), value.clone());
+                b"+OK\r\n".to_vec()
+            }
+            ("DEL", keys) => {
+                let deleted = keys
+                    .iter()
+                    .filter(|key| {
+                        self.strings.remove(*key).is_some() || self.hashes.remove(*key).is_some()
+                    })
+                    .count();
+                format!(":{deleted}\r\n").into_bytes()
+            }
+            ("HGET", [key, field]) => bulk(self.hashes.get(key).and_then(|hash| hash.get(field))),
+            ("HSET" | "HSETNX", [key, field, value]) => {
+                let hash = self.hashes.entry(key.clone()).or_default();
+                if command == "HSETNX" && hash.contains_key(field) {
+                    return b":0\r\n".to_vec();
+                }
+                let added = hash.insert(field.clone(), value.clone()).is_none();
+                format!(":{}\r\n", u8::from(added)).into_bytes()
+            }
+            ("HDEL", [key, field]) => {
+                let removed = self
+                    .hashes
+                    .get_mut(key)
+                    .and_then(|hash| hash.remove(field))
+                    .is_some();
+                format!(":{}\r\n", u8::from(removed)).into_bytes()
+            }
+            ("HGETALL", [key]) => {
+                let hash = self.hashes.get(key).cloned().unwrap_or_default();
+                let mut reply = format!("*{}\r\n", hash.len() * 2).into_bytes();
+                for (field, value) in &hash {
+                    reply.extend(bulk(Some(field)));
+                    reply.extend(bulk(Some(value)));
+                }
+                reply
+            }
+            ("EVAL", [_script, _, key, owner]) => {
+                let held = self.strings.get(key) == Some(owner);
+                if held {
+                    self.strings.remove(key);
+                }
+                format!(":{}\r\n", u8::from(held)).into_bytes()
+            }
+            _ => format!("-ERR unknown command {command}\r\n").into_bytes(),
+        }
+    }
+}
+
+/// Serve the fake redis at a local address
+async fn fake_redis() -> (String, Arc<Mutex<Redis>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let redis = Arc::new(Mutex::new(Redis::default()));
+    let shared = Arc::clone(&redis);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let redis = Arc::clone(&shared);
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+                while stream.read_line(&mut line).await.unwrap_or(0) > 0 {
+                    let count: usize = line.trim()[1..].parse().unwrap();
+                    let mut args = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        line.clear();
+                        stream.read_line(&mut line).await.unwrap();
+                        let len: usize = line.trim()[1..].parse().unwrap();
+                        let mut arg = vec![0; len + 2];
+                        stream.read_exact(&mut arg).await.unwrap();
+                        arg.truncate(len);
+                        args.push(arg);
+                    }
+                    let reply = redis.lock().unwrap().run(&args);
+                    stream.get_mut().write_all(&reply).await.unwrap();
+                    line.clear();
+                }
+            });
+        }
+    });
+    (address, redis)
+}
+
+#[tokio::test]
+async fn redis_keeps_the_namespace_under_its_prefix() {
+    let (address, redis) = fake_redis().await;
+    let data = memory();
+    let config = config(MetaConfig::Redis {
+        address,
+        prefix: "ns".to_owned(),
+        password: None,
+    });
+    let first = SharedFs::with_stores(&config, config.meta.build(), data.clone())
+        .await
+        .unwrap();
+    first
+        .mkdir_all(&ctx(), ROOT_ID, OsStr::new("dir"), 0o755)
+        .await
+        .unwrap();
+    first.mknod(&ctx(), file("dir/file")).await.unwrap();
+    write(&first, "dir/file", 0, b"shared").await;
+
+    // Another host connecting to the same stores
+    let second = SharedFs::with_stores(&config, config.meta.build(), data)
+        .await
+        .unwrap();
+    assert_eq!(read(&second, "dir/file", 0).await, b"shared");
+    let attr = second
+        .lookup(&ctx(), ROOT_ID, OsStr::new("dir/file"))
+        .await
+        .unwrap()
+        .1;
+    assert_eq!((attr.size, attr.perm), (6, 0o644));
+    second
+        .rename(&ctx(), rename("dir", "renamed", RenameFlags::empty()))
+        .await
+        .unwrap();
+    assert_eq!(read(&first, "renamed/file", 0).await, b"shared");
+
+    let redis = redis.lock().unwrap();
+    assert!(redis.strings.contains_key(b"ns:next".as_slice()));
+    let root = &redis.hashes[&b"ns:dir:1".to_vec()];
+    assert!(root.contains_key(&b"renamed".to_vec()));
+    assert!(!root.contains_key(&b"dir".to_vec()));
+    // Every lock was released
+    assert!(!redis.strings.keys().any(|key| key.starts_with(b"ns:lock:")));
+}
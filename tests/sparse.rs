@@ -0,0 +1,171 @@
+//! Finds the data and holes of sparse files, and counts only the blocks
+//! holding data
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::fs_util::{CreateParam, RequestContext, SeekWhence, SetAttrParam, ROOT_ID};
+use datenlord::storage::meta::{MemoryMeta, MetaStore};
+use datenlord::storage::sharedfs::{SharedConfig, SharedFs};
+use datenlord::storage::superblock::Feature;
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+use opendal::{Operator, Scheme};
+
+const MIB: u64 = 1024 * 1024;
+
+fn ctx() -> RequestContext {
+    RequestContext {
+        uid: 0,
+        gid: 0,
+        pid: 1,
+        umask: 0o022,
+        root: ROOT_ID,
+    }
+}
+
+/// A root removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("datenlord-sparse-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+
+    fn client(&self, features: Vec<Feature>) -> Client {
+        Client::new(&DatenLordConfig {
+            root: self.0.clone(),
+            features,
+            ..DatenLordConfig::default()
+        })
+        .unwrap()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn local_checkpoints_keep_their_holes() {
+    let root = Root::new("local");
+    let client = root.client(Vec::new());
+    let file = client.create("ckpt").await.unwrap();
+    file.write_at(b"header", 0).await.unwrap();
+    file.write_at(&[7; 4096], 64 * MIB).await.unwrap();
+    let attr = file.metadata().await.unwrap();
+    assert_eq!(attr.size, 64 * MIB + 4096);
+    assert!(attr.blocks < 1024, "{} blocks allocated", attr.blocks);
+
+    assert_eq!(file.lseek(0, SeekWhence::Data).await.unwrap(), Some(0));
+    let hole = file.lseek(0, SeekWhence::Hole).await.unwrap().unwrap();
+    assert!(hole > 0 && hole < 64 * MIB, "hole at {hole}");
+    assert_eq!(
+        file.lseek(hole, SeekWhence::Data).await.unwrap(),
+        Some(64 * MIB)
+    );
+    assert_eq!(
+        file.lseek(64 * MIB, SeekWhence::Hole).await.unwrap(),
+        Some(attr.size)
+    );
+    assert_eq!(file.lseek(attr.size, SeekWhence::Data).await.unwrap(), None);
+    file.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn deduplicated_files_skip_missing_blocks() {
+    const BLOCK: u64 = 128 * 1024;
+    let root = Root::new("dedup");
+    let client = root.client(vec![Feature::Dedup]);
+    let file = client.create("ckpt").await.unwrap();
+    file.write_at(&[1; 100], 0).await.unwrap();
+    file.write_at(&[2; 100], 3 * BLOCK).await.unwrap();
+
+    assert_eq!(file.lseek(0, SeekWhence::Hole).await.unwrap(), Some(BLOCK));
+    assert_eq!(
+        file.lseek(BLOCK, SeekWhence::Data).await.unwrap(),
+        Some(3 * BLOCK)
+    );
+    assert_eq!(
+        file.lseek(3 * BLOCK + 10, SeekWhence::Hole).await.unwrap(),
+        Some(3 * BLOCK + 100)
+    );
+    file.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn shared_files_count_the_blocks_stored() {
+    let config = SharedConfig {
+        data_scheme: "memory".to_owned(),
+        block_size: 512,
+        ..SharedConfig::default()
+    };
+    let meta: Arc<dyn MetaStore> = Arc::new(MemoryMeta::default());
+    let data = Operator::via_map(Scheme::Memory, HashMap::new()).unwrap();
+    let fs = SharedFs::with_stores(&config, meta, data).await.unwrap();
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: "f".into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let ino = fs.mknod(&ctx(), param).await.unwrap().1.ino;
+    let fh = fs
+        .open(&ctx(), ino, OFlag::O_RDWR.bits() as u32)
+        .await
+        .unwrap();
+    fs.write(&ctx(), ino, fh, 0, &[1; 100], 0).await.unwrap();
+    fs.write(&ctx(), ino, fh, 10 * 512, &[2; 512], 0)
+        .await
+        .unwrap();
+    fs.write(&ctx(), ino, fh, 20 * 512, &[3; 600], 0)
+        .await
+        .unwrap();
+    let (_, attr) = fs.getattr(&ctx(), ino).await.unwrap();
+    assert_eq!((attr.size, attr.blocks), (20 * 512 + 600, 4));
+
+    let ctx = ctx();
+    let seek = |offset, whence| fs.lseek(&ctx, ino, fh, offset, whence);
+    assert_eq!(seek(0, SeekWhence::Hole).await.unwrap(), Some(100));
+    assert_eq!(seek(100, SeekWhence::Data).await.unwrap(), Some(10 * 512));
+    assert_eq!(
+        seek(10 * 512, SeekWhence::Hole).await.unwrap(),
+        Some(11 * 512)
+    );
+    assert_eq!(
+        seek(11 * 512, SeekWhence::Hole).await.unwrap(),
+        Some(11 * 512)
+    );
+    assert_eq!(
+        seek(20 * 512 + 5, SeekWhence::Hole).await.unwrap(),
+        Some(attr.size)
+    );
+    assert_eq!(seek(attr.size, SeekWhence::Hole).await.unwrap(), None);
+
+    // Growing leaves a hole, shrinking frees the blocks dropped
+    let grow = SetAttrParam {
+        size: Some(100 * 512),
+        ..SetAttrParam::default()
+    };
+    assert_eq!(fs.setattr(&ctx, ino, grow).await.unwrap().1.blocks, 4);
+    let shrink = SetAttrParam {
+        size: Some(10 * 512 + 1),
+        ..SetAttrParam::default()
+    };
+    assert_eq!(fs.setattr(&ctx, ino, shrink).await.unwrap().1.blocks, 2);
+    assert_eq!(
+        seek(10 * 512, SeekWhence::Data).await.unwrap(),
+        Some(10 * 512)
+    );
+    fs.release(&ctx, ino, fh, 0, 0, false).await.unwrap();
+}
@@ -0,0 +1,56 @@
+//! Counts the calls made through the SDK and reports what it holds
+use std::path::PathBuf;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use nix::fcntl::OFlag;
+
+/// A root removed on drop
+struct Root(PathBuf);
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn calls_bytes_and_handles_are_counted() {
+    let root = Root(std::env::temp_dir().join(format!("datenlord-stats-{}", std::process::id())));
+    let _ = std::fs::remove_dir_all(&root.0);
+    let client = Client::new(&DatenLordConfig {
+        root: root.0.clone(),
+        ..DatenLordConfig::default()
+    })
+    .unwrap();
+    let before = client.stats();
+
+    let file = client.create("data").await.unwrap();
+    file.write_at(b"0123456789", 0).await.unwrap();
+    assert_eq!(client.stats().open_files, 1);
+    file.close().await.unwrap();
+    let file = client.open("data", OFlag::O_RDONLY).await.unwrap();
+    let mut buf = [0u8; 10];
+    assert_eq!(file.read_at(&mut buf, 0).await.unwrap(), 10);
+    assert_eq!(file.read_at(&mut buf, 5).await.unwrap(), 5);
+    file.close().await.unwrap();
+    client.metadata("data").await.unwrap();
+    assert!(client.metadata("missing").await.is_err());
+
+    let stats = client.stats();
+    assert_eq!(stats.open_files, 0);
+    assert_eq!(stats.open_dirs, 0);
+    assert_eq!(stats.fs.in_flight, 0);
+    assert_eq!(stats.fs.bytes_written - before.fs.bytes_written, 10);
+    assert_eq!(stats.fs.bytes_read - before.fs.bytes_read, 15);
+    let writes = stats.fs.ops["write"];
+    assert_eq!((writes.calls, writes.errors), (1, 0));
+    assert!(stats.fs.ops["lookup"].errors >= 1);
+    // The last lookup of the file found it cached
+    assert!(stats.cache_hits > before.cache_hits);
+    assert!(stats.cache_hit_ratio > 0.0 && stats.cache_hit_ratio <= 1.0);
+
+    let json = serde_json::to_value(&stats).unwrap();
+    assert_eq!(json["ops"]["write"]["calls"], 1);
+    assert_eq!(json["open_files"], 0);
+}
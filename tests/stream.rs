@@ -0,0 +1,96 @@
+//! Reads files as streams of chunks
+use std::path::PathBuf;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use futures::{StreamExt, TryStreamExt};
+
+/// A root removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("datenlord-stream-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+
+    fn client(&self) -> Client {
+        Client::new(&DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        })
+        .unwrap()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+async fn chunks(client: &Client, offset: u64, len: u64, chunk_size: usize) -> Vec<Vec<u8>> {
+    client
+        .read_stream("data", offset, len, chunk_size)
+        .await
+        .unwrap()
+        .map_ok(|chunk| chunk.to_vec())
+        .try_collect()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn files_are_read_in_chunks() {
+    let root = Root::new("chunks");
+    let client = root.client();
+    let data: Vec<u8> = (0..250).collect();
+    let file = client.create("data").await.unwrap();
+    file.write_at(&data, 0).await.unwrap();
+    file.close().await.unwrap();
+
+    let read = chunks(&client, 0, u64::MAX, 100).await;
+    assert_eq!(
+        read.iter().map(Vec::len).collect::<Vec<_>>(),
+        [100, 100, 50]
+    );
+    assert_eq!(read.concat(), data);
+    // A window of the file
+    assert_eq!(chunks(&client, 10, 25, 10).await.concat(), &data[10..35]);
+    // Past the end
+    assert!(chunks(&client, 250, 10, 10).await.is_empty());
+}
+
+#[tokio::test]
+async fn invalid_streams_fail() {
+    let root = Root::new("invalid");
+    let client = root.client();
+    assert!(client.read_stream("data", 0, 10, 10).await.is_err());
+    client.create("data").await.unwrap().close().await.unwrap();
+    let mut stream = Box::pin(client.read_stream("data", 0, 10, 0).await.unwrap());
+    assert!(stream.next().await.unwrap().is_err());
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn many_files_are_read_at_once() {
+    let root = Root::new("many");
+    let client = root.client();
+    client.create_dir_all("a/b").await.unwrap();
+    for (path, data) in [("a/1", &b"one"[..]), ("a/b/2", b"two"), ("3", b"")] {
+        let file = client.create(path).await.unwrap();
+        file.write_at(data, 0).await.unwrap();
+        file.close().await.unwrap();
+    }
+
+    let read = client
+        .read_many(&["a/b/2", "missing", "a/1", "3", "a"], 2)
+        .await;
+    assert_eq!(read[0].as_deref().unwrap(), b"two");
+    assert!(read[1].is_err());
+    assert_eq!(read[2].as_deref().unwrap(), b"one");
+    assert!(read[3].as_ref().unwrap().is_empty());
+    assert!(read[4].is_err());
+}
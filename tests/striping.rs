@@ -0,0 +1,176 @@
+//! Erasure codes the file data of the SDKs across several directories
+use std::path::PathBuf;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::striping::StripingConfig;
+use nix::fcntl::OFlag;
+
+/// A root and the stripe paths next to it, removed on drop
+struct Roots {
+    root: PathBuf,
+    paths: Vec<PathBuf>,
+}
+
+impl Roots {
+    fn new(name: &str, paths: usize) -> Self {
+        let dir = |suffix: String| {
+            let dir = std::env::temp_dir().join(format!(
+                "datenlord-striping-{name}-{suffix}-{}",
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            dir
+        };
+        Self {
+            root: dir("root".to_owned()),
+            paths: (0..paths).map(|i| dir(i.to_string())).collect(),
+        }
+    }
+
+    fn config(&self, parity_shards: usize) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.root.clone(),
+            striping: StripingConfig {
+                paths: self
+                    .paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect(),
+                parity_shards,
+                chunk_size: 4096,
+            },
+            ..DatenLordConfig::default()
+        }
+    }
+
+    fn client(&self, parity_shards: usize) -> Client {
+        Client::new(&self.config(parity_shards)).unwrap()
+    }
+
+    /// The chunk files of every path
+    fn chunks(&self) -> Vec<PathBuf> {
+        let mut chunks = Vec::new();
+        for path in &self.paths {
+            for dir in std::fs::read_dir(path).into_iter().flatten().flatten() {
+                chunks.extend(
+                    std::fs::read_dir(dir.path())
+                        .unwrap()
+                        .flatten()
+                        .map(|entry| entry.path()),
+                );
+            }
+        }
+        chunks
+    }
+}
+
+impl Drop for Roots {
+    fn drop(&mut self) {
+        for dir in self.paths.iter().chain([&self.root]) {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+async fn read(client: &Client, path: &str) -> datenlord::common::DatenLordResult<Vec<u8>> {
+    let file = client.open(path, OFlag::O_RDONLY).await?;
+    let mut data = vec![0; file.metadata().await?.size as usize];
+    let n = file.read_at(&mut data, 0).await?;
+    data.truncate(n);
+    file.close().await?;
+    Ok(data)
+}
+
+#[tokio::test]
+async fn files_survive_losing_parity_paths() {
+    let roots = Roots::new("lose", 4);
+    let client = roots.client(2);
+    let data = pattern(50_000);
+    let file = client.create("f").await.unwrap();
+    // Unaligned writes spanning several stripes, and a hole
+    file.write_at(&data[..10_000], 0).await.unwrap();
+    file.write_at(&data[10_000..30_001], 10_000).await.unwrap();
+    file.write_at(&data[40_000..], 40_000).await.unwrap();
+    file.close().await.unwrap();
+    let mut expected = data.clone();
+    expected[30_001..40_000].fill(0);
+    assert_eq!(read(&client, "f").await.unwrap(), expected);
+
+    // The root only keeps the size
+    let local = std::fs::read(roots.root.join("f")).unwrap();
+    assert_eq!(local.len(), expected.len());
+    assert!(local.iter().all(|&b| b == 0));
+
+    for lost in &roots.paths[..2] {
+        std::fs::remove_dir_all(lost).unwrap();
+    }
+    assert_eq!(read(&client, "f").await.unwrap(), expected);
+
+    let report = client.rebuild_stripes().await.unwrap();
+    assert_eq!(report.inodes, 1);
+    assert!(report.stripes >= 6, "{report:?}");
+    assert_eq!(report.repaired, report.stripes * 2);
+    assert!(report.lost.is_empty());
+    for lost in &roots.paths[2..] {
+        std::fs::remove_dir_all(lost).unwrap();
+    }
+    assert_eq!(read(&client, "f").await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn corrupt_chunks_are_rewritten() {
+    let roots = Roots::new("corrupt", 3);
+    let client = roots.client(1);
+    let data = pattern(8192);
+    let file = client.create("f").await.unwrap();
+    file.write_at(&data, 0).await.unwrap();
+    file.close().await.unwrap();
+
+    let chunks = roots.chunks();
+    assert_eq!(chunks.len(), 3);
+    let mut bytes = std::fs::read(&chunks[0]).unwrap();
+    bytes[100] ^= 0xff;
+    std::fs::write(&chunks[0], bytes).unwrap();
+    assert_eq!(read(&client, "f").await.unwrap(), data);
+    let report = client.rebuild_stripes().await.unwrap();
+    assert_eq!((report.stripes, report.repaired), (1, 1));
+
+    // Beyond the parity, the stripe is gone
+    std::fs::remove_file(&chunks[1]).unwrap();
+    std::fs::remove_file(&chunks[2]).unwrap();
+    assert!(read(&client, "f").await.is_err());
+    let report = client.rebuild_stripes().await.unwrap();
+    assert_eq!(report.lost.len(), 1);
+}
+
+#[tokio::test]
+async fn truncating_and_removing_drop_chunks() {
+    let roots = Roots::new("truncate", 3);
+    let client = roots.client(1);
+    let file = client.create("f").await.unwrap();
+    file.write_at(&pattern(100_000), 0).await.unwrap();
+    file.close().await.unwrap();
+    assert_eq!(roots.chunks().len(), 13 * 3);
+
+    // Creating the file again truncates it
+    let file = client.create("f").await.unwrap();
+    file.write_at(b"short", 0).await.unwrap();
+    file.close().await.unwrap();
+    assert_eq!(read(&client, "f").await.unwrap(), b"short");
+    assert_eq!(roots.chunks().len(), 3);
+
+    client.remove("f").await.unwrap();
+    assert!(roots.chunks().is_empty());
+}
+
+#[test]
+fn invalid_layouts_are_rejected() {
+    let roots = Roots::new("invalid", 2);
+    assert!(Client::new(&roots.config(0)).is_err());
+    assert!(Client::new(&roots.config(2)).is_err());
+}
@@ -0,0 +1,45 @@
+//! Exports the spans of the filesystem operations with the `otel` feature
+use datenlord::common::logging::{self, TracingConfig};
+use datenlord::common::DatenLordError;
+
+/// A collector nothing listens on, spans failing to export are dropped
+fn config() -> TracingConfig {
+    TracingConfig {
+        otlp_endpoint: Some("http://127.0.0.1:9/v1/traces".to_owned()),
+        ..TracingConfig::default()
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+#[test]
+fn exporting_needs_the_otel_feature() {
+    let err = logging::init(None, &config()).unwrap_err();
+    assert!(matches!(err, DatenLordError::Unimplemented { .. }), "{err:?}");
+    // Nothing is installed without an endpoint
+    logging::init(None, &TracingConfig::default()).unwrap();
+}
+
+#[cfg(feature = "otel")]
+#[test]
+fn spans_continue_remote_traces() {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    logging::init(Some("off"), &config()).unwrap();
+    // The subscriber is installed once per process
+    let err = logging::init(None, &config()).unwrap_err();
+    assert!(matches!(err, DatenLordError::Unimplemented { .. }), "{err:?}");
+
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+    let traceparent = format!("00-{trace_id}-00f067aa0ba902b7-01");
+    let span = tracing::info_span!(target: "datenlord::gateway", "request");
+    logging::continue_trace(&span, Some(&traceparent), Some("vendor=value"));
+    let context = span.context();
+    assert_eq!(context.span().span_context().trace_id().to_string(), trace_id);
+
+    // Headers that are not a trace context leave the span a root
+    let span = tracing::info_span!(target: "datenlord::gateway", "request");
+    logging::continue_trace(&span, Some("garbage"), None);
+    assert_ne!(span.context().span().span_context().trace_id().to_string(), trace_id);
+    logging::flush_traces();
+}
@@ -0,0 +1,174 @@
+//! Copies local files into a namespace in concurrent ranges, and files
+//! within a namespace
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use datenlord::common::buffer_pool::BufferPool;
+use datenlord::common::config::DatenLordConfig;
+use datenlord::common::DatenLordError;
+use datenlord::storage::fs_util::{CopyRangeParam, CreateParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::transfer::{self, CopyConfig};
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::fcntl::OFlag;
+use nix::sys::stat::SFlag;
+
+/// A fresh root and a local file beside it, both removed on drop
+struct Root {
+    root: PathBuf,
+    local: PathBuf,
+}
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("datenlord-transfer-{name}-{}", std::process::id()));
+        let local = root.with_extension("local");
+        let _ = std::fs::remove_dir_all(&root);
+        Self { root, local }
+    }
+
+    fn open(&self) -> Arc<LocalFS> {
+        let config = DatenLordConfig {
+            root: self.root.clone(),
+            ..DatenLordConfig::default()
+        };
+        Arc::new(LocalFS::new(&config).unwrap())
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+        let _ = std::fs::remove_file(&self.local);
+    }
+}
+
+/// Copy `data` through a local file into `name` of `root` with `config`,
+/// returning the bytes copied
+async fn copy(root: &Root, name: &str, data: &[u8], config: &CopyConfig) -> u64 {
+    std::fs::write(&root.local, data).unwrap();
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    let param = CreateParam {
+        parent: ROOT_ID,
+        name: name.into(),
+        mode: 0o644,
+        rdev: 0,
+        node_type: SFlag::S_IFREG,
+        link: None,
+    };
+    let ino = fs.mknod(&ctx, param).await.unwrap().1.ino;
+    let fh = fs
+        .open(&ctx, ino, OFlag::O_WRONLY.bits() as u32)
+        .await
+        .unwrap();
+    let file = std::fs::File::open(&root.local).unwrap();
+    let copied = transfer::copy_from_local(&fs, ctx, file, ino, fh, config, &BufferPool::new())
+        .await
+        .unwrap();
+    fs.release(&ctx, ino, fh, 0, 0, true).await.unwrap();
+    copied
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn large_files_are_copied_in_concurrent_ranges() {
+    let root = Root::new("ranges");
+    // Five full ranges and a short one, each range larger than a buffer
+    let chunk_size = 3 << 20;
+    let data: Vec<u8> = (0..5 * chunk_size + 12345)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    let config = CopyConfig {
+        threads: 3,
+        chunk_size: chunk_size as u64,
+    };
+    let copied = copy(&root, "large.bin", &data, &config).await;
+    assert_eq!(copied, data.len() as u64);
+    assert_eq!(std::fs::read(root.root.join("large.bin")).unwrap(), data);
+}
+
+#[tokio::test]
+async fn small_files_and_single_threads_copy_sequentially() {
+    let root = Root::new("sequential");
+    let config = CopyConfig::default();
+    assert_eq!(copy(&root, "small", b"small file", &config).await, 10);
+    assert_eq!(
+        std::fs::read(root.root.join("small")).unwrap(),
+        b"small file"
+    );
+
+    let data = vec![7; 3 << 20];
+    let config = CopyConfig {
+        threads: 1,
+        chunk_size: 1 << 20,
+    };
+    assert_eq!(copy(&root, "single", &data, &config).await, 3 << 20);
+    assert_eq!(std::fs::read(root.root.join("single")).unwrap(), data);
+
+    assert_eq!(copy(&root, "empty", b"", &CopyConfig::default()).await, 0);
+    assert!(std::fs::read(root.root.join("empty")).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn files_are_copied_over_the_destination() {
+    let root = Root::new("within");
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    let data: Vec<u8> = (0..(1 << 20) + 77).map(|i| (i % 253) as u8).collect();
+    std::fs::write(root.root.join("src"), &data).unwrap();
+    std::fs::write(root.root.join("dst"), vec![1; 3 << 20]).unwrap();
+
+    let copy = transfer::copy_file(fs.as_ref(), &ctx, "src".as_ref(), "dst".as_ref())
+        .await
+        .unwrap();
+    assert_eq!(copy.copied, data.len() as u64);
+    assert_eq!(std::fs::read(root.root.join("dst")).unwrap(), data);
+    let copy = transfer::copy_file(fs.as_ref(), &ctx, "src".as_ref(), "new".as_ref())
+        .await
+        .unwrap();
+    assert_eq!(copy.copied, data.len() as u64);
+    assert_eq!(std::fs::read(root.root.join("new")).unwrap(), data);
+
+    assert!(matches!(
+        transfer::copy_file(fs.as_ref(), &ctx, "src".as_ref(), "src".as_ref()).await,
+        Err(DatenLordError::InvalidArgument { .. })
+    ));
+    assert_eq!(std::fs::read(root.root.join("src")).unwrap(), data);
+}
+
+#[tokio::test]
+async fn ranges_are_copied_at_their_offsets() {
+    let root = Root::new("range");
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    std::fs::write(root.root.join("src"), b"0123456789").unwrap();
+    std::fs::write(root.root.join("dst"), b"abcdef").unwrap();
+    let ino = |name: &'static str| {
+        let fs = Arc::clone(&fs);
+        async move { fs.lookup(&ctx, ROOT_ID, name.as_ref()).await.unwrap().1.ino }
+    };
+    let (ino_in, ino_out) = (ino("src").await, ino("dst").await);
+    let fh_in = fs.open(&ctx, ino_in, OFlag::O_RDONLY.bits() as u32).await.unwrap();
+    let fh_out = fs.open(&ctx, ino_out, OFlag::O_WRONLY.bits() as u32).await.unwrap();
+    let param = CopyRangeParam {
+        ino_in,
+        fh_in,
+        offset_in: 6,
+        ino_out,
+        fh_out,
+        offset_out: 4,
+        len: 100,
+    };
+    let copy = fs.copy_file_range(&ctx, param).await.unwrap();
+    // Only whole files share their blocks
+    assert_eq!((copy.copied, copy.reflinked), (4, false));
+    let past_end = CopyRangeParam {
+        offset_in: 10,
+        ..param
+    };
+    assert_eq!(fs.copy_file_range(&ctx, past_end).await.unwrap().copied, 0);
+    fs.release(&ctx, ino_in, fh_in, 0, 0, false).await.unwrap();
+    fs.release(&ctx, ino_out, fh_out, 0, 0, true).await.unwrap();
+    assert_eq!(std::fs::read(root.root.join("dst")).unwrap(), b"abcd6789");
+}
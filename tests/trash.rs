@@ -0,0 +1,231 @@
+//! Moves the entries removed from a local namespace to a trash
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk;
+use datenlord::storage::fs_util::{CreateParam, RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::trash::{self, PurgeTask, TrashConfig, TrashFs, TRASH_DIR};
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::sys::stat::SFlag;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("datenlord-trash-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    fn config(&self, trash: TrashConfig) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            trash,
+            ..DatenLordConfig::default()
+        }
+    }
+
+    /// The local filesystem of the root with the trash on
+    fn open(&self) -> TrashFs<LocalFS> {
+        let config = enabled();
+        let local = LocalFS::new(&self.config(config.clone())).unwrap();
+        TrashFs::new(local, config)
+    }
+
+    /// The names of the directories in the trash
+    fn trashed(&self) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(self.0.join(TRASH_DIR)) else {
+            return Vec::new();
+        };
+        entries
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The trash on, keeping entries until purged explicitly
+fn enabled() -> TrashConfig {
+    TrashConfig {
+        enabled: true,
+        retention_secs: 0,
+        ..TrashConfig::default()
+    }
+}
+
+/// Create the directory `name` in `parent`, returning its inode
+async fn mkdir<F: VirtualFs>(fs: &F, parent: u64, name: &str) -> u64 {
+    let param = CreateParam {
+        parent,
+        name: name.into(),
+        mode: 0o755,
+        rdev: 0,
+        node_type: SFlag::S_IFDIR,
+        link: None,
+    };
+    let ctx = RequestContext::current();
+    fs.mkdir(&ctx, param).await.unwrap().1.ino
+}
+
+/// The paths of the entries in the trash, oldest removal first
+async fn listed<F: VirtualFs>(fs: &F) -> Vec<PathBuf> {
+    let ctx = RequestContext::current();
+    trash::list_trash(fs, &ctx)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.path)
+        .collect()
+}
+
+#[tokio::test]
+async fn removed_entries_are_restored() {
+    let root = Root::new("restore");
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    mkdir(&fs, ROOT_ID, "dir").await;
+    std::fs::write(root.0.join("dir/file"), b"first").unwrap();
+    fs.unlink(&ctx, ROOT_ID, OsStr::new("dir/file"))
+        .await
+        .unwrap();
+    std::fs::write(root.0.join("dir/file"), b"second").unwrap();
+    fs.unlink(&ctx, ROOT_ID, OsStr::new("dir/file"))
+        .await
+        .unwrap();
+    assert!(!root.0.join("dir/file").exists());
+    let entries = trash::list_trash(&fs, &ctx).await.unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries[0].deleted_at <= entries[1].deleted_at);
+    assert_eq!(entries[1].attr.size, 6);
+
+    // Directories go to the trash once empty, their parents are recreated
+    // on restore
+    fs.rmdir(&ctx, ROOT_ID, OsStr::new("dir")).await.unwrap();
+    assert_eq!(
+        listed(&fs).await,
+        [
+            Path::new("dir/file"),
+            Path::new("dir/file"),
+            Path::new("dir")
+        ]
+    );
+    let attr = trash::restore(&fs, &ctx, Path::new("dir/file"))
+        .await
+        .unwrap();
+    assert_eq!(attr.size, 6);
+    assert_eq!(std::fs::read(root.0.join("dir/file")).unwrap(), b"second");
+    assert!(trash::restore(&fs, &ctx, Path::new("dir/file"))
+        .await
+        .is_err());
+    std::fs::remove_file(root.0.join("dir/file")).unwrap();
+    trash::restore(&fs, &ctx, Path::new("dir/file"))
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(root.0.join("dir/file")).unwrap(), b"first");
+    assert_eq!(listed(&fs).await, [Path::new("dir")]);
+    assert!(trash::restore(&fs, &ctx, Path::new("missing"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn paths_come_from_looked_up_directories() {
+    let root = Root::new("paths");
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    let outer = mkdir(&fs, ROOT_ID, "outer").await;
+    let inner = mkdir(&fs, outer, "inner").await;
+    std::fs::write(root.0.join("outer/inner/file"), b"data").unwrap();
+    fs.unlink(&ctx, inner, OsStr::new("file")).await.unwrap();
+    assert!(fs.rmdir(&ctx, ROOT_ID, OsStr::new("outer")).await.is_err());
+    fs.rmdir(&ctx, outer, OsStr::new("inner")).await.unwrap();
+    assert_eq!(
+        listed(&fs).await,
+        [Path::new("outer/inner/file"), Path::new("outer/inner")]
+    );
+
+    // Removals inside the trash are final
+    let (_, trash_dir, _) = fs
+        .lookup(&ctx, ROOT_ID, OsStr::new(TRASH_DIR))
+        .await
+        .unwrap();
+    let id = &root.trashed()[0];
+    fs.unlink(&ctx, trash_dir.ino, OsStr::new(&format!("{id}/info")))
+        .await
+        .unwrap();
+    assert_eq!(listed(&fs).await.len(), 1);
+    assert_eq!(root.trashed().len(), 2);
+}
+
+#[tokio::test]
+async fn purges_remove_old_entries() {
+    let root = Root::new("purge");
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    let dir = mkdir(&fs, ROOT_ID, "dir").await;
+    mkdir(&fs, dir, "nested").await;
+    std::fs::write(root.0.join("dir/nested/file"), b"data").unwrap();
+    std::fs::write(root.0.join("file"), b"data").unwrap();
+    fs.unlink(&ctx, ROOT_ID, OsStr::new("file")).await.unwrap();
+    // Renamed into the trash whole by hand, the way `rmdir` never does
+    std::fs::rename(root.0.join("dir"), root.0.join(TRASH_DIR).join("dir")).unwrap();
+    assert_eq!(listed(&fs).await.len(), 1);
+
+    let hour = Duration::from_secs(3600);
+    assert_eq!(trash::purge(&fs, &ctx, hour).await.unwrap(), 0);
+    assert_eq!(trash::purge(&fs, &ctx, Duration::ZERO).await.unwrap(), 1);
+    assert_eq!(root.trashed(), ["dir"]);
+
+    // The scheduled purge keeps the entries younger than the retention
+    std::fs::write(root.0.join("file"), b"data").unwrap();
+    fs.unlink(&ctx, ROOT_ID, OsStr::new("file")).await.unwrap();
+    let fs = Arc::new(fs);
+    let config = TrashConfig {
+        retention_secs: 1,
+        purge_interval_secs: 1,
+        ..enabled()
+    };
+    let task = PurgeTask::start(Arc::clone(&fs), ctx, config).unwrap();
+    assert!(task.is_some());
+    assert_eq!(listed(fs.as_ref()).await.len(), 1);
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert!(listed(fs.as_ref()).await.is_empty());
+    assert!(PurgeTask::start(fs, ctx, enabled()).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn sdk_stack_hides_the_trash() {
+    let root = Root::new("sdk");
+    let ctx = RequestContext::current();
+    let off = sdk::open_fs(&root.config(TrashConfig::default())).unwrap();
+    std::fs::write(root.0.join("file"), b"data").unwrap();
+    off.unlink(&ctx, ROOT_ID, OsStr::new("file")).await.unwrap();
+    assert!(!root.0.join(TRASH_DIR).exists());
+
+    let fs = sdk::open_fs(&root.config(enabled())).unwrap();
+    std::fs::write(root.0.join("file"), b"data").unwrap();
+    fs.unlink(&ctx, ROOT_ID, OsStr::new("file")).await.unwrap();
+    assert_eq!(listed(&fs).await, [Path::new("file")]);
+    let fh = fs.opendir(&ctx, ROOT_ID, 0).await.unwrap();
+    let names: Vec<_> = fs
+        .readdir(&ctx, ROOT_ID, fh, 0)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    assert!(names.is_empty(), "{names:?}");
+    trash::restore(&fs, &ctx, Path::new("file")).await.unwrap();
+    assert_eq!(std::fs::read(root.0.join("file")).unwrap(), b"data");
+}
@@ -0,0 +1,234 @@
+//! Uploads files in parts, resumed across restarts of the client
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk::rust::Client;
+use datenlord::storage::fs_util::{RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::trash::TrashConfig;
+use datenlord::storage::upload::{self, UPLOADS_DIR};
+use datenlord::storage::virtualfs::VirtualFs;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("datenlord-upload-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    fn config(&self) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            ..DatenLordConfig::default()
+        }
+    }
+
+    fn open(&self) -> LocalFS {
+        LocalFS::new(&self.config()).unwrap()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn parts_are_assembled_in_order() {
+    let root = Root::new("assemble");
+    let fs = root.open();
+    let ctx = RequestContext::current();
+    let path = Path::new("models/model.bin");
+    assert!(upload::start_upload(&fs, &ctx, path).await.is_err());
+    assert!(upload::start_upload(&fs, &ctx, Path::new("../escape"))
+        .await
+        .is_err());
+    fs.mkdir_all(&ctx, ROOT_ID, OsStr::new("models"), 0o755)
+        .await
+        .unwrap();
+
+    let id = upload::start_upload(&fs, &ctx, path).await.unwrap();
+    upload::upload_part(&fs, &ctx, &id, 2, b"tail")
+        .await
+        .unwrap();
+    upload::upload_part(&fs, &ctx, &id, 0, b"head-")
+        .await
+        .unwrap();
+    upload::upload_part(&fs, &ctx, &id, 1, b"garbage")
+        .await
+        .unwrap();
+    // Uploading an index again replaces its part
+    let part = upload::upload_part(&fs, &ctx, &id, 1, b"body-")
+        .await
+        .unwrap();
+    assert_eq!((part.index, part.size), (1, 5));
+    assert_eq!(part.checksum, upload::checksum(b"body-"));
+    assert!(upload::upload_part(&fs, &ctx, &id, upload::MAX_PARTS, b"")
+        .await
+        .is_err());
+    assert!(upload::upload_part(&fs, &ctx, "upload-missing", 0, b"")
+        .await
+        .is_err());
+
+    let parts = upload::upload_parts(&fs, &ctx, &id).await.unwrap();
+    let indexes: Vec<_> = parts.iter().map(|part| part.index).collect();
+    assert_eq!(indexes, [0, 1, 2]);
+    assert_eq!(parts[1], part);
+    let uploads = upload::list_uploads(&fs, &ctx).await.unwrap();
+    assert_eq!(uploads.len(), 1);
+    assert_eq!(
+        (uploads[0].id.as_str(), uploads[0].path.as_path()),
+        (id.as_str(), path)
+    );
+
+    let attr = upload::complete_upload(&fs, &ctx, &id).await.unwrap();
+    assert_eq!(attr.size, 14);
+    assert_eq!(std::fs::read(root.0.join(path)).unwrap(), b"head-body-tail");
+    assert!(upload::list_uploads(&fs, &ctx).await.unwrap().is_empty());
+    assert!(upload::complete_upload(&fs, &ctx, &id).await.is_err());
+}
+
+#[tokio::test]
+async fn uploads_resume_and_check_their_parts() {
+    let root = Root::new("resume");
+    let ctx = RequestContext::current();
+    let id = {
+        let fs = root.open();
+        let id = upload::start_upload(&fs, &ctx, Path::new("big.bin"))
+            .await
+            .unwrap();
+        upload::upload_part(&fs, &ctx, &id, 0, &[1; 1000])
+            .await
+            .unwrap();
+        upload::upload_part(&fs, &ctx, &id, 2, &[3; 10])
+            .await
+            .unwrap();
+        id
+    };
+
+    // A new client finds the upload and what it lacks
+    let fs = root.open();
+    let uploads = upload::list_uploads(&fs, &ctx).await.unwrap();
+    assert_eq!(uploads[0].id, id);
+    let parts = upload::upload_parts(&fs, &ctx, &id).await.unwrap();
+    assert_eq!(parts.len(), 2);
+    let missing = upload::complete_upload(&fs, &ctx, &id).await.unwrap_err();
+    assert!(missing.to_string().contains("part 1"), "{missing}");
+    upload::upload_part(&fs, &ctx, &id, 1, &[2; 100])
+        .await
+        .unwrap();
+
+    // Parts damaged at rest are refused
+    let part_file = root
+        .0
+        .join(UPLOADS_DIR)
+        .join(&id)
+        .join(format!("00000.{}", parts[0].checksum));
+    std::fs::write(&part_file, [9; 1000]).unwrap();
+    assert!(upload::complete_upload(&fs, &ctx, &id).await.is_err());
+    assert!(!root.0.join("big.bin").exists());
+    upload::upload_part(&fs, &ctx, &id, 0, &[1; 1000])
+        .await
+        .unwrap();
+    let attr = upload::complete_upload(&fs, &ctx, &id).await.unwrap();
+    assert_eq!(attr.size, 1110);
+    let data = std::fs::read(root.0.join("big.bin")).unwrap();
+    assert_eq!((data[999], data[1000], data[1109]), (1, 2, 3));
+
+    let aborted = upload::start_upload(&fs, &ctx, Path::new("big.bin"))
+        .await
+        .unwrap();
+    upload::upload_part(&fs, &ctx, &aborted, 0, b"new")
+        .await
+        .unwrap();
+    upload::abort_upload(&fs, &ctx, &aborted).await.unwrap();
+    assert!(upload::list_uploads(&fs, &ctx).await.unwrap().is_empty());
+    assert!(upload::abort_upload(&fs, &ctx, &aborted).await.is_err());
+    assert_eq!(std::fs::read(root.0.join("big.bin")).unwrap().len(), 1110);
+}
+
+#[tokio::test]
+async fn client_uploads_stay_out_of_listings_and_the_trash() {
+    let root = Root::new("client");
+    let config = DatenLordConfig {
+        trash: TrashConfig {
+            enabled: true,
+            ..TrashConfig::default()
+        },
+        ..root.config()
+    };
+    let client = Client::new(&config).unwrap();
+    let id = client.start_upload("file").await.unwrap();
+    client.upload_part(&id, 0, b"first").await.unwrap();
+    client.upload_part(&id, 0, b"again").await.unwrap();
+    assert_eq!(client.upload_parts(&id).await.unwrap().len(), 1);
+    assert!(client
+        .read_dir("")
+        .await
+        .unwrap()
+        .iter()
+        .all(|entry| entry.name != UPLOADS_DIR));
+    assert_eq!(client.list_uploads().await.unwrap().len(), 1);
+    client.complete_upload(&id).await.unwrap();
+    assert_eq!(std::fs::read(root.0.join("file")).unwrap(), b"again");
+    assert!(client.list_trash().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn atomic_writes_are_never_seen_in_part() {
+    let root = Root::new("atomic");
+    let client = Client::new(&root.config()).unwrap();
+    client.create_dir_all("dir").await.unwrap();
+    let contents = [vec![b'a'; 300_000], vec![b'b'; 100_000]];
+    let attr = client
+        .write_file_atomic("dir/file", &contents[0])
+        .await
+        .unwrap();
+    assert_eq!(attr.size, 300_000);
+    let path = root.0.join("dir/file");
+    std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o600)).unwrap();
+
+    let (fs, ctx) = (root.open(), RequestContext::current());
+    let reader = {
+        let (path, contents) = (path.clone(), contents.clone());
+        std::thread::spawn(move || {
+            for _ in 0..200 {
+                let read = std::fs::read(&path).unwrap();
+                assert!(
+                    contents.contains(&read),
+                    "read {} bytes in part",
+                    read.len()
+                );
+            }
+        })
+    };
+    for content in contents.iter().cycle().take(40) {
+        upload::write_file_atomic(&fs, &ctx, OsStr::new("dir/file"), content)
+            .await
+            .unwrap();
+    }
+    reader.join().unwrap();
+
+    // The replaced file keeps its permissions and no temporary file is left
+    let (_, attr, _) = fs
+        .lookup(&ctx, ROOT_ID, OsStr::new("dir/file"))
+        .await
+        .unwrap();
+    assert_eq!(attr.perm & 0o777, 0o600);
+    assert_eq!(std::fs::read_dir(root.0.join("dir")).unwrap().count(), 1);
+    for path in ["missing/file", "", "dir"] {
+        let written = upload::write_file_atomic(&fs, &ctx, OsStr::new(path), b"").await;
+        assert!(written.is_err(), "{path:?}");
+    }
+    assert!(std::fs::read_dir(&root.0).unwrap().all(|entry| !entry
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .contains("atomic")));
+}
@@ -0,0 +1,237 @@
+//! Keeps the former contents of overwritten files of a local namespace
+use std::ffi::OsStr;
+use std::path::PathBuf;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::sdk;
+use datenlord::storage::fs_util::{RenameParam, RequestContext, SetAttrParam, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::versioning::{VersioningConfig, VersioningFs, VERSIONS_DIR};
+use datenlord::storage::virtualfs::VirtualFs;
+use nix::fcntl::OFlag;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "datenlord-versioning-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    fn config(&self, versioning: VersioningConfig) -> DatenLordConfig {
+        DatenLordConfig {
+            root: self.0.clone(),
+            versioning,
+            ..DatenLordConfig::default()
+        }
+    }
+
+    /// The local filesystem of the root keeping `retention` versions
+    fn open(&self, retention: usize) -> VersioningFs<LocalFS> {
+        let config = VersioningConfig {
+            enabled: true,
+            retention,
+        };
+        let local = LocalFS::new(&self.config(config.clone())).unwrap();
+        VersioningFs::new(local, config)
+    }
+
+    /// The names of the files holding the versions of `ino`
+    fn stored(&self, ino: u64) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(self.0.join(VERSIONS_DIR).join(ino.to_string())) else {
+            return Vec::new();
+        };
+        entries
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect()
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The inode of the file `name` of `root`, created with `content`
+async fn create<F: VirtualFs>(root: &Root, fs: &F, name: &str, content: &[u8]) -> u64 {
+    std::fs::write(root.0.join(name), content).unwrap();
+    let ctx = RequestContext::current();
+    fs.lookup(&ctx, ROOT_ID, OsStr::new(name))
+        .await
+        .unwrap()
+        .1
+        .ino
+}
+
+/// Replace the content of the file `ino` through one handle, truncating
+/// it and writing `content` in two halves
+async fn overwrite<F: VirtualFs>(fs: &F, ino: u64, content: &[u8]) {
+    let ctx = RequestContext::current();
+    let flags = OFlag::O_WRONLY.bits() as u32;
+    let fh = fs.open(&ctx, ino, flags).await.unwrap();
+    let truncate = SetAttrParam {
+        fh: Some(fh),
+        size: Some(0),
+        ..SetAttrParam::default()
+    };
+    fs.setattr(&ctx, ino, truncate).await.unwrap();
+    let (first, second) = content.split_at(content.len() / 2);
+    fs.write(&ctx, ino, fh, 0, first, flags).await.unwrap();
+    let offset = i64::try_from(first.len()).unwrap();
+    fs.write(&ctx, ino, fh, offset, second, flags)
+        .await
+        .unwrap();
+    fs.release(&ctx, ino, fh, flags, 0, true).await.unwrap();
+}
+
+/// The ids and contents of the versions of `ino`
+async fn versions<F: VirtualFs>(fs: &VersioningFs<F>, ino: u64) -> Vec<(u64, Vec<u8>)> {
+    let ctx = RequestContext::current();
+    let mut contents = Vec::new();
+    for version in fs.list_versions(&ctx, ino).await.unwrap() {
+        let content = fs.read_version(&ctx, ino, version.id).await.unwrap();
+        assert_eq!(version.size, content.len() as u64);
+        contents.push((version.id, content));
+    }
+    contents
+}
+
+#[tokio::test]
+async fn overwrites_keep_the_latest_versions() {
+    let root = Root::new("overwrites");
+    let fs = root.open(3);
+    let ctx = RequestContext::current();
+    let ino = create(&root, &fs, "file", b"one").await;
+    let mtime = fs.getattr(&ctx, ino).await.unwrap().1.mtime;
+    overwrite(&fs, ino, b"two").await;
+    assert_eq!(versions(&fs, ino).await, [(1, b"one".to_vec())]);
+    assert_eq!(fs.list_versions(&ctx, ino).await.unwrap()[0].mtime, mtime);
+
+    // Truncating saves the content, writing into the empty file does not
+    let shrink = SetAttrParam {
+        size: Some(1),
+        ..SetAttrParam::default()
+    };
+    fs.setattr(&ctx, ino, shrink).await.unwrap();
+    let grow = SetAttrParam {
+        size: Some(8),
+        ..SetAttrParam::default()
+    };
+    fs.setattr(&ctx, ino, grow).await.unwrap();
+    overwrite(&fs, ino, b"").await;
+    overwrite(&fs, ino, b"three").await;
+    overwrite(&fs, ino, b"four").await;
+    assert_eq!(
+        versions(&fs, ino).await,
+        [
+            (2, b"two".to_vec()),
+            (3, b"t\0\0\0\0\0\0\0".to_vec()),
+            (4, b"three".to_vec())
+        ]
+    );
+    assert_eq!(std::fs::read(root.0.join("file")).unwrap(), b"four");
+    assert!(fs.read_version(&ctx, ino, 1).await.is_err());
+
+    // Opening with `O_TRUNC` saves the content once for the handle
+    let flags = (OFlag::O_WRONLY | OFlag::O_TRUNC).bits() as u32;
+    let fh = fs.open(&ctx, ino, flags).await.unwrap();
+    fs.write(&ctx, ino, fh, 0, b"five", flags).await.unwrap();
+    fs.release(&ctx, ino, fh, flags, 0, true).await.unwrap();
+    let kept = versions(&fs, ino).await;
+    assert_eq!(kept.len(), 3);
+    assert_eq!(kept[2], (5, b"four".to_vec()));
+    assert_eq!(std::fs::read(root.0.join("file")).unwrap(), b"five");
+}
+
+#[tokio::test]
+async fn restores_keep_the_replaced_content() {
+    let root = Root::new("restores");
+    let fs = root.open(2);
+    let ctx = RequestContext::current();
+    let ino = create(&root, &fs, "file", b"first content").await;
+    overwrite(&fs, ino, b"second").await;
+    overwrite(&fs, ino, b"third").await;
+    assert_eq!(
+        versions(&fs, ino).await,
+        [(1, b"first content".to_vec()), (2, b"second".to_vec())]
+    );
+
+    // The oldest version is restored although saving `third` is past the
+    // retention, which drops the next oldest instead
+    fs.restore_version(&ctx, ino, 1).await.unwrap();
+    assert_eq!(
+        std::fs::read(root.0.join("file")).unwrap(),
+        b"first content"
+    );
+    assert_eq!(
+        versions(&fs, ino).await,
+        [(1, b"first content".to_vec()), (3, b"third".to_vec())]
+    );
+    fs.restore_version(&ctx, ino, 3).await.unwrap();
+    assert_eq!(std::fs::read(root.0.join("file")).unwrap(), b"third");
+    assert_eq!(versions(&fs, ino).await.len(), 2);
+    assert!(fs.restore_version(&ctx, ino, 2).await.is_err());
+}
+
+#[tokio::test]
+async fn versions_follow_renames_and_go_with_the_last_link() {
+    let root = Root::new("links");
+    let fs = root.open(10);
+    let ctx = RequestContext::current();
+    let ino = create(&root, &fs, "old", b"one").await;
+    overwrite(&fs, ino, b"two").await;
+    let rename = |old: &str, new: &str| RenameParam {
+        old_parent: ROOT_ID,
+        old_name: old.into(),
+        new_parent: ROOT_ID,
+        new_name: new.into(),
+        flags: 0,
+    };
+    fs.rename(&ctx, rename("old", "new")).await.unwrap();
+    let (_, attr, _) = fs.lookup(&ctx, ROOT_ID, OsStr::new("new")).await.unwrap();
+    assert_eq!(versions(&fs, attr.ino).await, [(1, b"one".to_vec())]);
+
+    // Replacing and removing files drops their versions
+    let other = create(&root, &fs, "other", b"one").await;
+    overwrite(&fs, other, b"two").await;
+    fs.rename(&ctx, rename("other", "new")).await.unwrap();
+    assert!(root.stored(ino).is_empty());
+    assert_eq!(root.stored(other).len(), 1);
+    fs.unlink(&ctx, ROOT_ID, OsStr::new("new")).await.unwrap();
+    assert!(root.stored(other).is_empty());
+}
+
+#[tokio::test]
+async fn sdk_stack_hides_the_store() {
+    let root = Root::new("sdk");
+    let ctx = RequestContext::current();
+    let off = sdk::open_fs(&root.config(VersioningConfig::default())).unwrap();
+    let ino = create(&root, &off, "file", b"one").await;
+    overwrite(&off, ino, b"two").await;
+    assert!(!root.0.join(VERSIONS_DIR).exists());
+
+    let config = VersioningConfig {
+        enabled: true,
+        ..VersioningConfig::default()
+    };
+    let fs = sdk::open_fs(&root.config(config)).unwrap();
+    let (_, attr, _) = fs.lookup(&ctx, ROOT_ID, OsStr::new("file")).await.unwrap();
+    overwrite(&fs, attr.ino, b"three").await;
+    assert_eq!(root.stored(attr.ino).len(), 1);
+    let fh = fs.opendir(&ctx, ROOT_ID, 0).await.unwrap();
+    let names: Vec<_> = fs
+        .readdir(&ctx, ROOT_ID, fh, 0)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    assert!(names.iter().all(|name| name != VERSIONS_DIR), "{names:?}");
+    assert!(names.iter().any(|name| name == "file"), "{names:?}");
+}
@@ -0,0 +1,154 @@
+//! Attributes, entries and parameters through their wire representations
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use datenlord::common::DatenLordError;
+use datenlord::storage::fs_util::{DirEntry, FileAttr, FileKind, SetAttrParam, StatFsParam};
+use datenlord::storage::wire::{self, Attr, Entry, SetAttr, StatFs, Timestamp};
+use nix::sys::stat::SFlag;
+use prost::Message;
+
+fn attr() -> FileAttr {
+    FileAttr {
+        ino: 42,
+        size: 4096,
+        blocks: 8,
+        // Before the epoch, which `SystemTime` serializes to no number
+        atime: UNIX_EPOCH - Duration::new(86_400, 250),
+        mtime: UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+        ctime: SystemTime::now(),
+        kind: SFlag::S_IFREG,
+        perm: 0o4755,
+        nlink: 2,
+        uid: 1000,
+        gid: 100,
+        rdev: 0,
+        generation: 7,
+    }
+}
+
+/// `message` decoded from its protobuf encoding and from its JSON
+fn round_trips<M>(message: &M) -> (M, M)
+where
+    M: Message + Default + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let decoded = wire::decode(&message.encode_to_vec()).unwrap();
+    let json = serde_json::to_string(message).unwrap();
+    (decoded, serde_json::from_str(&json).unwrap())
+}
+
+#[test]
+fn attributes_and_entries_round_trip() {
+    let attr = attr();
+    let (decoded, from_json) = round_trips(&Attr::from(&attr));
+    assert_eq!(FileAttr::try_from(decoded).unwrap(), attr);
+    assert_eq!(FileAttr::try_from(from_json).unwrap(), attr);
+
+    let entries = [
+        DirEntry {
+            name: OsString::from_vec(b"caf\xe9".to_vec()),
+            ino: attr.ino,
+            kind: FileKind::RegularFile,
+            attr: Some(attr),
+        },
+        DirEntry {
+            name: "dir".into(),
+            ino: 43,
+            kind: FileKind::Directory,
+            attr: None,
+        },
+    ];
+    for entry in entries {
+        let (decoded, from_json) = round_trips(&Entry::from(&entry));
+        assert_eq!(DirEntry::try_from(decoded).unwrap(), entry);
+        assert_eq!(DirEntry::try_from(from_json).unwrap(), entry);
+    }
+}
+
+#[test]
+fn parameters_round_trip() {
+    let param = SetAttrParam {
+        valid: 0x31,
+        fh: Some(3),
+        mode: Some(0o640),
+        size: Some(0),
+        m_time: Some(UNIX_EPOCH - Duration::from_nanos(1)),
+        ..SetAttrParam::default()
+    };
+    let (decoded, from_json) = round_trips(&SetAttr::from(&param));
+    for wire in [decoded, from_json] {
+        let back = SetAttrParam::try_from(wire).unwrap();
+        assert_eq!(format!("{back:?}"), format!("{param:?}"));
+    }
+
+    let stat = StatFsParam {
+        blocks: 1 << 40,
+        bfree: 1 << 20,
+        bavail: 1 << 19,
+        files: 1000,
+        f_free: 10,
+        bsize: 4096,
+        namelen: 255,
+        frsize: 4096,
+    };
+    let (decoded, from_json) = round_trips(&StatFs::from(&stat));
+    for wire in [decoded, from_json] {
+        assert_eq!(
+            format!("{:?}", StatFsParam::from(wire)),
+            format!("{stat:?}")
+        );
+    }
+
+    // The parameters serialize as they are too, with times after the epoch
+    let param = SetAttrParam {
+        m_time: Some(UNIX_EPOCH + Duration::from_nanos(1)),
+        ..param
+    };
+    let json = serde_json::to_string(&param).unwrap();
+    let back: SetAttrParam = serde_json::from_str(&json).unwrap();
+    assert_eq!(format!("{back:?}"), format!("{param:?}"));
+    let json = serde_json::to_string(&stat).unwrap();
+    let back: StatFsParam = serde_json::from_str(&json).unwrap();
+    assert_eq!(format!("{back:?}"), format!("{stat:?}"));
+}
+
+#[test]
+fn malformed_messages_are_corrupted() {
+    let corrupted =
+        |result: Result<_, DatenLordError>| matches!(result, Err(DatenLordError::Corrupted { .. }));
+    assert!(corrupted(wire::decode::<Attr>(&[0xff; 4]).map(|_| ())));
+
+    let valid = Attr::from(&attr());
+    let invalid = [
+        Attr {
+            kind: 0,
+            ..valid.clone()
+        },
+        Attr {
+            perm: 0o10000,
+            ..valid.clone()
+        },
+        Attr {
+            atime: None,
+            ..valid.clone()
+        },
+        Attr {
+            mtime: Some(Timestamp {
+                sec: 0,
+                nsec: 1_000_000_000,
+            }),
+            ..valid.clone()
+        },
+    ];
+    for attr in invalid {
+        assert!(corrupted(FileAttr::try_from(attr).map(|_| ())));
+    }
+    let entry = Entry {
+        name: b"x".to_vec(),
+        ino: 1,
+        kind: "unknown".to_owned(),
+        attr: None,
+    };
+    assert!(corrupted(DirEntry::try_from(entry).map(|_| ())));
+}
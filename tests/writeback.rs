@@ -0,0 +1,146 @@
+//! Writes back the data written through open files of a local namespace
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use datenlord::common::config::DatenLordConfig;
+use datenlord::storage::fs_util::{RequestContext, ROOT_ID};
+use datenlord::storage::localfs::LocalFS;
+use datenlord::storage::virtualfs::VirtualFs;
+use datenlord::storage::writeback::{self, WritebackConfig, WritebackTask};
+use nix::fcntl::OFlag;
+
+/// A fresh root, removed on drop
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("datenlord-writeback-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        Self(root)
+    }
+
+    /// The local filesystem of the root writing back as `writeback` says
+    fn open(&self, writeback: WritebackConfig) -> Arc<LocalFS> {
+        Arc::new(
+            LocalFS::new(&DatenLordConfig {
+                root: self.0.clone(),
+                writeback,
+                ..DatenLordConfig::default()
+            })
+            .unwrap(),
+        )
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Create the file `name` under `root` and open it for writing, returning
+/// its inode and handle
+async fn create(root: &Root, fs: &LocalFS, ctx: &RequestContext, name: &str) -> (u64, u64) {
+    std::fs::write(root.0.join(name), b"").unwrap();
+    let (_, attr, _) = fs.lookup(ctx, ROOT_ID, OsStr::new(name)).await.unwrap();
+    let fh = fs.open(ctx, attr.ino, OFlag::O_WRONLY.bits() as u32).await.unwrap();
+    (attr.ino, fh)
+}
+
+/// Start the writeback of `fs` as `config` says
+fn start(fs: &Arc<LocalFS>, config: WritebackConfig) -> WritebackTask {
+    let local = Arc::clone(fs);
+    WritebackTask::start(fs.dirty_bytes(), move || local.flush_dirty(), config).unwrap()
+}
+
+/// Wait until `fs` has no unsynced bytes left, false if it takes too long
+async fn drained(fs: &LocalFS) -> bool {
+    for _ in 0..100 {
+        if fs.dirty_bytes().get() == 0 {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn writes_are_dirty_until_synced() {
+    let root = Root::new("dirty");
+    let disabled = WritebackConfig {
+        interval_ms: 0,
+        dirty_high_watermark: 0,
+    };
+    let fs = root.open(disabled);
+    let ctx = RequestContext::current();
+    let (a, a_fh) = create(&root, &fs, &ctx, "a").await;
+    let (b, b_fh) = create(&root, &fs, &ctx, "b").await;
+    fs.write(&ctx, a, a_fh, 0, b"hello", 0).await.unwrap();
+    fs.write(&ctx, b, b_fh, 0, b"world!", 0).await.unwrap();
+    assert_eq!(fs.dirty_bytes().get(), 11);
+
+    fs.fsync(&ctx, a, a_fh, true).await.unwrap();
+    assert_eq!(fs.dirty_bytes().get(), 6);
+    assert_eq!(fs.flush_dirty().unwrap(), 6);
+    assert_eq!(fs.flush_dirty().unwrap(), 0);
+
+    // Released handles are left to the page cache
+    fs.write(&ctx, b, b_fh, 6, b"!", 0).await.unwrap();
+    fs.release(&ctx, b, b_fh, 0, 0, false).await.unwrap();
+    assert_eq!(fs.dirty_bytes().get(), 0);
+
+    // Synchronous handles are never dirty
+    let (_, attr, _) = fs.lookup(&ctx, ROOT_ID, OsStr::new("b")).await.unwrap();
+    let flags = (OFlag::O_WRONLY | OFlag::O_SYNC).bits() as u32;
+    let fh = fs.open(&ctx, attr.ino, flags).await.unwrap();
+    fs.write(&ctx, attr.ino, fh, 0, b"sync", 0).await.unwrap();
+    assert_eq!(fs.dirty_bytes().get(), 0);
+}
+
+#[tokio::test]
+async fn writeback_runs_periodically_and_past_the_watermark() {
+    let root = Root::new("task");
+    let ctx = RequestContext::current();
+
+    let periodic = WritebackConfig {
+        interval_ms: 50,
+        dirty_high_watermark: 0,
+    };
+    let fs = root.open(periodic);
+    let _task = start(&fs, periodic);
+    let (ino, fh) = create(&root, &fs, &ctx, "periodic").await;
+    fs.write(&ctx, ino, fh, 0, b"data", 0).await.unwrap();
+    assert!(drained(&fs).await);
+
+    let watermark = WritebackConfig {
+        interval_ms: 0,
+        dirty_high_watermark: 8,
+    };
+    let fs = root.open(watermark);
+    let task = start(&fs, watermark);
+    let (ino, fh) = create(&root, &fs, &ctx, "watermark").await;
+    fs.write(&ctx, ino, fh, 0, b"1234", 0).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(fs.dirty_bytes().get(), 4);
+    fs.write(&ctx, ino, fh, 4, b"5678", 0).await.unwrap();
+    assert!(drained(&fs).await);
+
+    // Stopping the task flushes a last time
+    fs.write(&ctx, ino, fh, 8, b"9", 0).await.unwrap();
+    drop(task);
+    assert_eq!(fs.dirty_bytes().get(), 0);
+
+    // And so does flushing every task before exiting
+    let disabled = WritebackConfig {
+        interval_ms: 0,
+        dirty_high_watermark: 0,
+    };
+    let fs = root.open(disabled);
+    let _task = start(&fs, disabled);
+    let (ino, fh) = create(&root, &fs, &ctx, "exit").await;
+    fs.write(&ctx, ino, fh, 0, b"bye", 0).await.unwrap();
+    writeback::flush_all();
+    assert_eq!(fs.dirty_bytes().get(), 0);
+}